@@ -131,6 +131,7 @@ fn run_client_thread(tid: usize, server_tid: usize, ctx: Context) -> Result<(),
         &phoenix_syscalls::transport::SchedulingHint {
             mode: Default::default(),
             numa_node_affinity: Some((tid % num_numa_nodes) as u8),
+            cpu_affinity: None,
         },
     );
 
@@ -265,6 +266,7 @@ fn run_server_thread(tid: usize, ctx: Context) -> Result<(), Error> {
         &phoenix_syscalls::transport::SchedulingHint {
             mode: Default::default(),
             numa_node_affinity: Some((tid % num_numa_nodes) as u8),
+            cpu_affinity: None,
         },
     );
 
@@ -0,0 +1,6 @@
+const PROTO: &str = "../proto/conformance/conformance.proto";
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed={PROTO}");
+    mrpc_build::compile_protos(PROTO)?;
+    Ok(())
+}
@@ -0,0 +1,531 @@
+//! The conformance cases and the field-by-field comparison of messages.
+use crate::conformance::{AllTypes, Inner, Leaf};
+
+/// The number of pseudo-random cases that follow the fixed ones.
+const NUM_RANDOM: u32 = 16;
+
+/// The number of mismatches that are reported for a message.
+const MAX_MISMATCHES: usize = 32;
+
+const FIXED: &[(&str, fn() -> AllTypes)] = &[
+    ("default", AllTypes::default),
+    ("max", max),
+    ("min", min),
+    ("special_floats", special_floats),
+    ("strings", strings),
+    ("bytes", bytes),
+    ("empty_repeated", empty_repeated),
+    ("nested", nested),
+    ("large", large),
+];
+
+/// Returns the number of cases. The ids of the cases are `0..num_cases()`.
+pub fn num_cases() -> u32 {
+    FIXED.len() as u32 + NUM_RANDOM
+}
+
+/// Returns the name of the case `id`.
+pub fn name(id: u32) -> Option<String> {
+    match FIXED.get(id as usize) {
+        Some((name, _)) => Some((*name).to_owned()),
+        None if id < num_cases() => Some(format!("random_{}", id as usize - FIXED.len())),
+        None => None,
+    }
+}
+
+/// Builds the message of the case `id`.
+pub fn build(id: u32) -> Option<AllTypes> {
+    match FIXED.get(id as usize) {
+        Some((_, build)) => Some(build()),
+        None if id < num_cases() => Some(random(id as u64)),
+        None => None,
+    }
+}
+
+/// Returns the fields of `actual` that do not match `expected`, at most [`MAX_MISMATCHES`] of
+/// them. Floats are compared by their bits, so that NaN payloads and the sign of zero are checked.
+pub fn diff(expected: &AllTypes, actual: &AllTypes) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    expected.compare(actual, "", &mut mismatches);
+    if mismatches.len() > MAX_MISMATCHES {
+        let more = mismatches.len() - MAX_MISMATCHES;
+        mismatches.truncate(MAX_MISMATCHES);
+        mismatches.push(format!("... and {} more", more));
+    }
+    mismatches
+}
+
+/// Compares a field with the expected value.
+trait Conform {
+    fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>);
+}
+
+macro_rules! conform_scalar {
+    ($($ty:ty),*) => {$(
+        impl Conform for $ty {
+            fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+                if self != actual {
+                    mismatches.push(format!("{}: expected {:?}, got {:?}", path, self, actual));
+                }
+            }
+        }
+    )*};
+}
+
+conform_scalar!(bool, i32, i64, u32, u64, u8);
+
+impl Conform for str {
+    fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+        if self == actual {
+            return;
+        }
+        if self.len() + actual.len() <= 64 {
+            mismatches.push(format!("{}: expected {:?}, got {:?}", path, self, actual));
+        } else {
+            let at = self
+                .bytes()
+                .zip(actual.bytes())
+                .take_while(|(e, a)| e == a)
+                .count();
+            mismatches.push(format!(
+                "{}: expected {} bytes, got {}, differ at byte {}",
+                path,
+                self.len(),
+                actual.len(),
+                at
+            ));
+        }
+    }
+}
+
+macro_rules! conform_float {
+    ($($ty:ty),*) => {$(
+        impl Conform for $ty {
+            fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+                if self.to_bits() != actual.to_bits() {
+                    mismatches.push(format!(
+                        "{}: expected {:?} ({:#x}), got {:?} ({:#x})",
+                        path,
+                        self,
+                        self.to_bits(),
+                        actual,
+                        actual.to_bits()
+                    ));
+                }
+            }
+        }
+    )*};
+}
+
+conform_float!(f32, f64);
+
+impl<T: Conform> Conform for [T] {
+    fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+        if self.len() != actual.len() {
+            mismatches.push(format!(
+                "{}: expected {} elements, got {}",
+                path,
+                self.len(),
+                actual.len()
+            ));
+            return;
+        }
+        for (i, (e, a)) in self.iter().zip(actual).enumerate() {
+            e.compare(a, &format!("{}[{}]", path, i), mismatches);
+        }
+    }
+}
+
+impl<T: Conform> Conform for mrpc::alloc::Vec<T> {
+    fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+        self[..].compare(&actual[..], path, mismatches);
+    }
+}
+
+impl Conform for mrpc::alloc::String {
+    fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+        self.as_str().compare(actual.as_str(), path, mismatches);
+    }
+}
+
+impl<T: Conform> Conform for Option<T> {
+    fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+        match (self, actual) {
+            (Some(e), Some(a)) => e.compare(a, path, mismatches),
+            (None, None) => {}
+            (Some(_), None) => mismatches.push(format!("{}: expected a message, got none", path)),
+            (None, Some(_)) => mismatches.push(format!("{}: expected none, got a message", path)),
+        }
+    }
+}
+
+fn field_path(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_owned()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+macro_rules! conform_message {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl Conform for $ty {
+            fn compare(&self, actual: &Self, path: &str, mismatches: &mut Vec<String>) {
+                $(
+                    self.$field.compare(
+                        &actual.$field,
+                        &field_path(path, stringify!($field)),
+                        mismatches,
+                    );
+                )*
+            }
+        }
+    };
+}
+
+conform_message!(Leaf { data, flag });
+conform_message!(Inner {
+    id,
+    label,
+    values,
+    leaf,
+    leaves
+});
+conform_message!(AllTypes {
+    f_double,
+    f_float,
+    f_int32,
+    f_int64,
+    f_uint32,
+    f_uint64,
+    f_sint32,
+    f_sint64,
+    f_fixed32,
+    f_fixed64,
+    f_sfixed32,
+    f_sfixed64,
+    f_bool,
+    f_string,
+    f_bytes,
+    f_inner,
+    r_double,
+    r_float,
+    r_int32,
+    r_int64,
+    r_uint32,
+    r_uint64,
+    r_sint32,
+    r_sint64,
+    r_fixed32,
+    r_fixed64,
+    r_sfixed32,
+    r_sfixed64,
+    r_bool,
+    r_string,
+    r_bytes,
+    r_inner,
+});
+
+fn leaf(data: &[u8], flag: bool) -> Leaf {
+    Leaf {
+        data: data.into(),
+        flag,
+    }
+}
+
+fn max() -> AllTypes {
+    AllTypes {
+        f_double: f64::MAX,
+        f_float: f32::MAX,
+        f_int32: i32::MAX,
+        f_int64: i64::MAX,
+        f_uint32: u32::MAX,
+        f_uint64: u64::MAX,
+        f_sint32: i32::MAX,
+        f_sint64: i64::MAX,
+        f_fixed32: u32::MAX,
+        f_fixed64: u64::MAX,
+        f_sfixed32: i32::MAX,
+        f_sfixed64: i64::MAX,
+        f_bool: true,
+        f_string: "max".into(),
+        f_bytes: [u8::MAX; 8][..].into(),
+        f_inner: Some(Inner {
+            id: i64::MAX,
+            label: "max".into(),
+            values: [u32::MAX; 3][..].into(),
+            leaf: Some(leaf(&[u8::MAX], true)),
+            leaves: Default::default(),
+        }),
+        r_double: [f64::MAX, 0.0, f64::MIN_POSITIVE][..].into(),
+        r_float: [f32::MAX, 0.0, f32::MIN_POSITIVE][..].into(),
+        r_int32: [i32::MAX, 0, 1][..].into(),
+        r_int64: [i64::MAX, 0, 1][..].into(),
+        r_uint32: [u32::MAX, 0, 1][..].into(),
+        r_uint64: [u64::MAX, 0, 1][..].into(),
+        r_sint32: [i32::MAX, 0, 1][..].into(),
+        r_sint64: [i64::MAX, 0, 1][..].into(),
+        r_fixed32: [u32::MAX, 0, 1][..].into(),
+        r_fixed64: [u64::MAX, 0, 1][..].into(),
+        r_sfixed32: [i32::MAX, 0, 1][..].into(),
+        r_sfixed64: [i64::MAX, 0, 1][..].into(),
+        r_bool: [true, false, true][..].into(),
+        r_string: ["max", "", "max"].iter().map(|&s| s.into()).collect(),
+        r_bytes: [&[u8::MAX][..], &[], &[u8::MAX; 2]]
+            .iter()
+            .map(|&b| b.into())
+            .collect(),
+        r_inner: Default::default(),
+    }
+}
+
+fn min() -> AllTypes {
+    AllTypes {
+        f_double: f64::MIN,
+        f_float: f32::MIN,
+        f_int32: i32::MIN,
+        f_int64: i64::MIN,
+        f_uint32: 1,
+        f_uint64: 1,
+        f_sint32: i32::MIN,
+        f_sint64: i64::MIN,
+        f_fixed32: 1,
+        f_fixed64: 1,
+        f_sfixed32: i32::MIN,
+        f_sfixed64: i64::MIN,
+        f_bool: false,
+        f_string: "min".into(),
+        f_bytes: [0u8; 8][..].into(),
+        f_inner: Some(Inner {
+            id: i64::MIN,
+            label: Default::default(),
+            values: [0u32; 3][..].into(),
+            leaf: Some(leaf(&[], false)),
+            leaves: Default::default(),
+        }),
+        r_double: [f64::MIN, -1.0][..].into(),
+        r_float: [f32::MIN, -1.0][..].into(),
+        r_int32: [i32::MIN, -1][..].into(),
+        r_int64: [i64::MIN, -1][..].into(),
+        r_uint32: [0, 1][..].into(),
+        r_uint64: [0, 1][..].into(),
+        r_sint32: [i32::MIN, -1][..].into(),
+        r_sint64: [i64::MIN, -1][..].into(),
+        r_fixed32: [0, 1][..].into(),
+        r_fixed64: [0, 1][..].into(),
+        r_sfixed32: [i32::MIN, -1][..].into(),
+        r_sfixed64: [i64::MIN, -1][..].into(),
+        r_bool: [false, false][..].into(),
+        r_string: ["", "min"].iter().map(|&s| s.into()).collect(),
+        r_bytes: [&[][..], &[0u8]].iter().map(|&b| b.into()).collect(),
+        r_inner: [Inner::default()][..].into(),
+    }
+}
+
+fn special_floats() -> AllTypes {
+    let doubles = [
+        f64::NAN,
+        -f64::NAN,
+        f64::from_bits(0x7ff0_0000_0000_0001),
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        -0.0,
+        f64::MIN_POSITIVE / 2.0,
+        f64::EPSILON,
+    ];
+    let floats = [
+        f32::NAN,
+        -f32::NAN,
+        f32::from_bits(0x7f80_0001),
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        -0.0,
+        f32::MIN_POSITIVE / 2.0,
+        f32::EPSILON,
+    ];
+    AllTypes {
+        f_double: f64::NAN,
+        f_float: -0.0,
+        r_double: doubles[..].into(),
+        r_float: floats[..].into(),
+        ..Default::default()
+    }
+}
+
+fn strings() -> AllTypes {
+    let strings = [
+        "",
+        "ascii",
+        "nul\0in the middle",
+        "é ü ß 中文 日本語 한국어",
+        "🦀 emoji and 𝄞",
+        "\u{7f}\u{80}\u{7ff}\u{800}\u{ffff}\u{10000}\u{10ffff}",
+    ];
+    let long = "mrpc".repeat(4096);
+    AllTypes {
+        f_string: long.as_str().into(),
+        r_string: strings.iter().map(|&s| s.into()).collect(),
+        f_inner: Some(Inner {
+            label: strings[3].into(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn bytes() -> AllTypes {
+    let all: Vec<u8> = (0..=u8::MAX).collect();
+    AllTypes {
+        f_bytes: all[..].into(),
+        r_bytes: [&all[..], &[], &[0u8], &all[128..]]
+            .iter()
+            .map(|&b| b.into())
+            .collect(),
+        f_inner: Some(Inner {
+            leaf: Some(leaf(&all, true)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn empty_repeated() -> AllTypes {
+    AllTypes {
+        f_int32: 1,
+        f_string: "only scalars".into(),
+        f_inner: Some(Inner::default()),
+        ..Default::default()
+    }
+}
+
+fn nested() -> AllTypes {
+    let inner = |i: i64| Inner {
+        id: i,
+        label: format!("inner {}", i).into(),
+        values: (0..i as u32).collect(),
+        leaf: (i % 2 == 0).then(|| leaf(&i.to_le_bytes(), i % 4 == 0)),
+        leaves: (0..i)
+            .map(|j| leaf(&j.to_be_bytes()[..j as usize % 8], j % 3 == 0))
+            .collect(),
+    };
+    AllTypes {
+        f_inner: Some(inner(7)),
+        r_inner: (0..16).map(inner).collect(),
+        ..Default::default()
+    }
+}
+
+fn large() -> AllTypes {
+    const N: usize = 100_000;
+    AllTypes {
+        f_bytes: (0..1 << 20).map(|i| i as u8).collect(),
+        r_double: (0..N).map(|i| i as f64 / 3.0).collect(),
+        r_float: (0..N).map(|i| i as f32 / 3.0).collect(),
+        r_int32: (0..N).map(|i| -(i as i32)).collect(),
+        r_int64: (0..N).map(|i| -(i as i64) << 20).collect(),
+        r_uint32: (0..N).map(|i| i as u32).collect(),
+        r_uint64: (0..N).map(|i| (i as u64) << 32).collect(),
+        r_bool: (0..N).map(|i| i % 3 == 0).collect(),
+        r_string: (0..1000).map(|i| format!("string {}", i).into()).collect(),
+        r_inner: (0..1000)
+            .map(|i| Inner {
+                id: i,
+                values: [i as u32; 4][..].into(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// A xorshift generator, so that both ends derive the same message from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn len(&mut self, max: u64) -> usize {
+        (self.next() % (max + 1)) as usize
+    }
+
+    fn repeated<T, F: FnMut(&mut Self) -> T>(&mut self, max: u64, mut f: F) -> mrpc::alloc::Vec<T> {
+        let len = self.len(max);
+        (0..len).map(|_| f(self)).collect()
+    }
+
+    fn string(&mut self) -> mrpc::alloc::String {
+        const CHARS: &[char] = &['a', 'Z', '0', ' ', '\0', 'é', '中', '🦀'];
+        let len = self.len(32);
+        (0..len)
+            .map(|_| CHARS[self.next() as usize % CHARS.len()])
+            .collect()
+    }
+
+    fn bytes(&mut self) -> mrpc::alloc::Vec<u8> {
+        self.repeated(64, |r| r.next() as u8)
+    }
+
+    fn leaf(&mut self) -> Leaf {
+        Leaf {
+            data: self.bytes(),
+            flag: self.next() % 2 == 0,
+        }
+    }
+
+    fn inner(&mut self) -> Inner {
+        Inner {
+            id: self.next() as i64,
+            label: self.string(),
+            values: self.repeated(16, |r| r.next() as u32),
+            leaf: (self.next() % 2 == 0).then(|| self.leaf()),
+            leaves: self.repeated(4, |r| r.leaf()),
+        }
+    }
+}
+
+fn random(seed: u64) -> AllTypes {
+    let mut r = Rng::new(seed);
+    AllTypes {
+        f_double: f64::from_bits(r.next()),
+        f_float: f32::from_bits(r.next() as u32),
+        f_int32: r.next() as i32,
+        f_int64: r.next() as i64,
+        f_uint32: r.next() as u32,
+        f_uint64: r.next(),
+        f_sint32: r.next() as i32,
+        f_sint64: r.next() as i64,
+        f_fixed32: r.next() as u32,
+        f_fixed64: r.next(),
+        f_sfixed32: r.next() as i32,
+        f_sfixed64: r.next() as i64,
+        f_bool: r.next() % 2 == 0,
+        f_string: r.string(),
+        f_bytes: r.bytes(),
+        f_inner: (r.next() % 4 != 0).then(|| r.inner()),
+        r_double: r.repeated(64, |r| f64::from_bits(r.next())),
+        r_float: r.repeated(64, |r| f32::from_bits(r.next() as u32)),
+        r_int32: r.repeated(64, |r| r.next() as i32),
+        r_int64: r.repeated(64, |r| r.next() as i64),
+        r_uint32: r.repeated(64, |r| r.next() as u32),
+        r_uint64: r.repeated(64, |r| r.next()),
+        r_sint32: r.repeated(64, |r| r.next() as i32),
+        r_sint64: r.repeated(64, |r| r.next() as i64),
+        r_fixed32: r.repeated(64, |r| r.next() as u32),
+        r_fixed64: r.repeated(64, |r| r.next()),
+        r_sfixed32: r.repeated(64, |r| r.next() as i32),
+        r_sfixed64: r.repeated(64, |r| r.next() as i64),
+        r_bool: r.repeated(64, |r| r.next() % 2 == 0),
+        r_string: r.repeated(8, |r| r.string()),
+        r_bytes: r.repeated(8, |r| r.bytes()),
+        r_inner: r.repeated(8, |r| r.inner()),
+    }
+}
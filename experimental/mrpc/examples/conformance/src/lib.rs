@@ -0,0 +1,12 @@
+//! Conformance cases of the mRPC wire format.
+//!
+//! A case is a message of every scalar, repeated, and nested field type, derived from the case id
+//! alone. The server and the runner each build the message of a case and check the message they
+//! receive against it, so a change in the layout that the codegen, the marshalling, or the engine
+//! expects shows up as a field that does not match.
+pub mod conformance {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("conformance");
+}
+
+pub mod cases;
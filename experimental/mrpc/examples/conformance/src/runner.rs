@@ -0,0 +1,157 @@
+//! The runner of the conformance harness.
+//!
+//! It spawns a conformance server, unless told to connect to one that is already running, and
+//! runs every case in three ways: the client sends the message of the case for the server to
+//! check, the server sends the message of the case for the client to check, and the server echoes
+//! back the message that the client sends. It exits with a non-zero status if any case fails.
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+
+use conformance::cases;
+use conformance::conformance::conformance_client::ConformanceClient;
+use conformance::conformance::{CaseId, CaseMessage};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "mRPC conformance runner")]
+struct Args {
+    /// The address of the server.
+    #[structopt(short, long, default_value = "127.0.0.1:5000")]
+    server_addr: String,
+
+    /// The server binary to spawn. Defaults to `conformance_server` next to this binary.
+    #[structopt(long)]
+    server_bin: Option<PathBuf>,
+
+    /// Connect to a server that is already running instead of spawning one, e.g., a server built
+    /// from another version of the codegen.
+    #[structopt(long)]
+    no_spawn: bool,
+
+    /// Run only the cases whose name contains this string.
+    #[structopt(long)]
+    filter: Option<String>,
+
+    /// Seconds to wait for the server to accept connections.
+    #[structopt(long, default_value = "10")]
+    connect_timeout: f64,
+}
+
+/// Kills the spawned server on drop.
+struct Server(Child);
+
+impl Server {
+    fn spawn(args: &Args) -> std::io::Result<Self> {
+        let bin = match &args.server_bin {
+            Some(bin) => bin.clone(),
+            None => std::env::current_exe()?.with_file_name("conformance_server"),
+        };
+        let port = args.server_addr.rsplit(':').next().unwrap_or("5000");
+        let child = Command::new(bin)
+            .arg("--listen")
+            .arg(format!("0.0.0.0:{}", port))
+            .spawn()?;
+        Ok(Server(child))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn connect(args: &Args) -> Result<ConformanceClient, mrpc::Error> {
+    let deadline = Instant::now() + Duration::from_secs_f64(args.connect_timeout);
+    loop {
+        match ConformanceClient::connect(&args.server_addr) {
+            Ok(client) => return Ok(client),
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => std::thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// Runs the case `id` in each way, and returns the ways that fail with their mismatches.
+async fn run_case(client: &ConformanceClient, id: u32) -> Vec<(&'static str, Vec<String>)> {
+    let mut failures = Vec::new();
+
+    let request = CaseMessage {
+        id,
+        message: cases::build(id),
+    };
+    let mismatches = match client.check(request).await {
+        Ok(reply) => reply.mismatches.iter().map(|m| m.to_string()).collect(),
+        Err(status) => vec![status.to_string()],
+    };
+    if !mismatches.is_empty() {
+        failures.push(("client -> server", mismatches));
+    }
+
+    let expected = cases::build(id).unwrap();
+    let mismatches = match client.fetch(CaseId { id }).await {
+        Ok(reply) => cases::diff(&expected, &reply),
+        Err(status) => vec![status.to_string()],
+    };
+    if !mismatches.is_empty() {
+        failures.push(("server -> client", mismatches));
+    }
+
+    let mismatches = match client.echo(cases::build(id).unwrap()).await {
+        Ok(reply) => cases::diff(&expected, &reply),
+        Err(status) => vec![status.to_string()],
+    };
+    if !mismatches.is_empty() {
+        failures.push(("echo", mismatches));
+    }
+
+    failures
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+
+    let server = if args.no_spawn {
+        None
+    } else {
+        Some(Server::spawn(&args)?)
+    };
+    let client = connect(&args)?;
+
+    let ids: Vec<u32> = (0..cases::num_cases())
+        .filter(|&id| match &args.filter {
+            Some(filter) => cases::name(id).unwrap().contains(filter.as_str()),
+            None => true,
+        })
+        .collect();
+
+    let mut failed = 0;
+    smol::block_on(async {
+        for &id in &ids {
+            let name = cases::name(id).unwrap();
+            let failures = run_case(&client, id).await;
+            if failures.is_empty() {
+                println!("ok      {}", name);
+                continue;
+            }
+            failed += 1;
+            println!("FAILED  {}", name);
+            for (way, mismatches) in failures {
+                println!("    {}:", way);
+                for m in mismatches {
+                    println!("        {}", m);
+                }
+            }
+        }
+    });
+
+    println!("{} passed, {} failed", ids.len() - failed, failed);
+    drop(server);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
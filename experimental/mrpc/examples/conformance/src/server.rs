@@ -0,0 +1,60 @@
+//! The server of the conformance harness.
+//!
+//! It checks the message of each case it receives against the message it builds from the case
+//! id, sends the message of a case on request, and echoes back what it receives.
+use structopt::StructOpt;
+
+use mrpc::{RRef, WRef};
+
+use conformance::cases;
+use conformance::conformance::conformance_server::{Conformance, ConformanceServer};
+use conformance::conformance::{AllTypes, CaseId, CaseMessage, CheckResult};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "mRPC conformance server")]
+struct Args {
+    /// The address to listen on.
+    #[structopt(short, long, default_value = "0.0.0.0:5000")]
+    listen: String,
+}
+
+fn build(id: u32) -> Result<AllTypes, mrpc::Status> {
+    cases::build(id).ok_or_else(|| mrpc::Status::invalid_argument(format!("no case {}", id)))
+}
+
+#[derive(Debug, Default)]
+struct ConformanceService;
+
+#[mrpc::async_trait]
+impl Conformance for ConformanceService {
+    async fn check(&self, request: RRef<CaseMessage>) -> Result<WRef<CheckResult>, mrpc::Status> {
+        let expected = build(request.id)?;
+        let mismatches = match request.message.as_ref() {
+            Some(actual) => cases::diff(&expected, actual),
+            None => vec!["message: expected a message, got none".to_owned()],
+        };
+        Ok(WRef::new(CheckResult {
+            mismatches: mismatches.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn fetch(&self, request: RRef<CaseId>) -> Result<WRef<AllTypes>, mrpc::Status> {
+        Ok(WRef::new(build(request.id)?))
+    }
+
+    async fn echo(&self, request: RRef<AllTypes>) -> Result<WRef<AllTypes>, mrpc::Status> {
+        Ok(WRef::new(AllTypes::clone(&request)))
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    smol::block_on(async {
+        let mut server = mrpc::stub::LocalServer::bind(&args.listen)?;
+        server
+            .add_service(ConformanceServer::new(ConformanceService::default()))
+            .serve()
+            .await?;
+        Ok(())
+    })
+}
@@ -5,6 +5,8 @@ const PROTOS: &[&str] = &[
     "../proto/hotel_microservices/rate.proto",
     "../proto/hotel_microservices/search.proto",
     "../proto/hotel_microservices/profile.proto",
+    "../proto/hotel_microservices/reservation.proto",
+    "../proto/hotel_microservices/user.proto",
 ];
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
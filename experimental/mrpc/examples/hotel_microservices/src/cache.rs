@@ -0,0 +1,127 @@
+//! An in-process cache that sits in front of memcached and MongoDB.
+//!
+//! Entries are kept in an LRU with a TTL. Concurrent misses on the same key are coalesced, so
+//! that only one of them fetches the value while the others wait for its result.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use futures::channel::oneshot;
+use lru::LruCache;
+
+type Waiter<V> = oneshot::Sender<Result<V, String>>;
+
+pub struct LocalCache<V> {
+    // None if the LRU is disabled
+    entries: Option<RefCell<LruCache<String, (V, Instant)>>>,
+    ttl: Duration,
+    // The waiters of the keys being fetched
+    inflight: RefCell<HashMap<String, Vec<Waiter<V>>>>,
+}
+
+impl<V: Clone> LocalCache<V> {
+    /// Creates a cache of at most `capacity` entries that expire `ttl` after insertion. A
+    /// `capacity` of 0 disables the LRU but still coalesces the fetches.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        LocalCache {
+            entries: NonZeroUsize::new(capacity).map(|cap| RefCell::new(LruCache::new(cap))),
+            ttl,
+            inflight: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the value of `key`, calling `fetch` on a miss unless a fetch of the same key is
+    /// already in flight.
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> Result<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V>>,
+    {
+        if let Some(value) = self.lookup(key) {
+            return Ok(value);
+        }
+
+        let waiter = {
+            let mut inflight = self.inflight.borrow_mut();
+            match inflight.get_mut(key) {
+                Some(waiters) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    Some(rx)
+                }
+                None => {
+                    inflight.insert(key.to_owned(), Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(rx) = waiter {
+            return match rx.await {
+                Ok(result) => result.map_err(|e| anyhow!(e)),
+                // The fetching request was dropped, fetch by ourselves.
+                Err(oneshot::Canceled) => fetch().await,
+            };
+        }
+
+        let flight = Flight {
+            inflight: &self.inflight,
+            key: Some(key),
+        };
+        let result = fetch().await;
+        let waiters = flight.finish();
+        if let Ok(value) = &result {
+            self.insert(key, value.clone());
+        }
+        for tx in waiters {
+            let _ = tx.send(match &result {
+                Ok(value) => Ok(value.clone()),
+                Err(e) => Err(e.to_string()),
+            });
+        }
+        result
+    }
+
+    fn lookup(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.as_ref()?.borrow_mut();
+        let (value, inserted) = entries.get(key)?;
+        if inserted.elapsed() < self.ttl {
+            return Some(value.clone());
+        }
+        entries.pop(key);
+        None
+    }
+
+    fn insert(&self, key: &str, value: V) {
+        if let Some(entries) = &self.entries {
+            entries
+                .borrow_mut()
+                .put(key.to_owned(), (value, Instant::now()));
+        }
+    }
+}
+
+/// Removes the in-flight entry of a key when the fetch completes or is dropped.
+struct Flight<'a, V> {
+    inflight: &'a RefCell<HashMap<String, Vec<Waiter<V>>>>,
+    key: Option<&'a str>,
+}
+
+impl<'a, V> Flight<'a, V> {
+    fn finish(mut self) -> Vec<Waiter<V>> {
+        let key = self.key.take().unwrap();
+        self.inflight.borrow_mut().remove(key).unwrap_or_default()
+    }
+}
+
+impl<'a, V> Drop for Flight<'a, V> {
+    fn drop(&mut self) {
+        // Dropping the senders wakes up the waiters.
+        if let Some(key) = self.key {
+            self.inflight.borrow_mut().remove(key);
+        }
+    }
+}
@@ -33,6 +33,22 @@ pub struct Config {
     #[serde(rename = "RateMemcAddress")]
     pub rate_memc_addr: String,
 
+    #[serde(rename = "ReservationAddress")]
+    pub reservation_addr: String,
+    #[serde(rename = "ReservationPort")]
+    pub reservation_port: u16,
+    #[serde(rename = "ReservationMongoAddress")]
+    pub reservation_mongo_addr: String,
+    #[serde(rename = "ReservationMemcAddress")]
+    pub reservation_memc_addr: String,
+
+    #[serde(rename = "UserAddress")]
+    pub user_addr: String,
+    #[serde(rename = "UserPort")]
+    pub user_port: u16,
+    #[serde(rename = "UserMongoAddress")]
+    pub user_mongo_addr: String,
+
     #[serde(rename = "SearchAddress")]
     pub search_addr: String,
     #[serde(rename = "SearchPort")]
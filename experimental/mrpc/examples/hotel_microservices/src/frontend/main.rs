@@ -4,6 +4,7 @@ use std::io::BufReader;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
@@ -14,12 +15,12 @@ pub mod config;
 #[path = "../logging.rs"]
 pub mod logging;
 pub mod server;
-#[path = "../tracer.rs"]
-pub mod tracer;
 
 use config::Config;
 use server::hotel_microservices::profile::profile_client::ProfileClient;
+use server::hotel_microservices::reservation::reservation_client::ReservationClient;
 use server::hotel_microservices::search::search_client::SearchClient;
+use server::hotel_microservices::user::user_client::UserClient;
 use server::{dispatch_fn, FrontendService};
 
 #[derive(StructOpt, Debug, Clone)]
@@ -36,10 +37,26 @@ pub struct Args {
     pub profile_addr: String,
     #[structopt(long, default_value = "5000")]
     pub profile_port: u16,
+    #[structopt(long, default_value = "reservation")]
+    pub reservation_addr: String,
+    #[structopt(long, default_value = "5000")]
+    pub reservation_port: u16,
+    #[structopt(long, default_value = "user")]
+    pub user_addr: String,
+    #[structopt(long, default_value = "5000")]
+    pub user_port: u16,
     #[structopt(short, long)]
     pub config: Option<PathBuf>,
     #[structopt(long)]
     pub log_path: Option<PathBuf>,
+    /// The deadline budget in milliseconds of the backend calls of the HTTP requests of each
+    /// urgency (the `priority` header), from 0, the most urgent, to 7. 0 means no budget.
+    #[structopt(long)]
+    pub urgency_budget_ms: Vec<u64>,
+    /// Answer `503 Service Unavailable` to the HTTP requests while a backend they go to asks to
+    /// slow down.
+    #[structopt(long)]
+    pub shed_on_pushback: bool,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -54,7 +71,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         args.search_port = config.search_port;
         args.profile_addr = config.profile_addr;
         args.profile_port = config.profile_port;
-        args.log_path = Some(config.log_path.join("frontend.csv"));
+        args.reservation_addr = config.reservation_addr;
+        args.reservation_port = config.reservation_port;
+        args.user_addr = config.user_addr;
+        args.user_port = config.user_port;
+        args.log_path = Some(config.log_path.join("frontend.json"));
     }
     eprintln!("args: {:?}", args);
     logging::init_env_log("RUST_LOG", "info");
@@ -64,9 +85,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         SearchClient::connect(format!("{}:{}", args.search_addr, args.search_port))?;
     let profile_client =
         ProfileClient::connect(format!("{}:{}", args.profile_addr, args.profile_port))?;
+    let reservation_client = ReservationClient::connect(format!(
+        "{}:{}",
+        args.reservation_addr, args.reservation_port
+    ))?;
+    let user_client = UserClient::connect(format!("{}:{}", args.user_addr, args.user_port))?;
+    let urgency_budgets = args
+        .urgency_budget_ms
+        .iter()
+        .map(|&ms| (ms > 0).then(|| Duration::from_millis(ms)))
+        .collect();
     let frontend = Arc::new(FrontendService::new(
         search_client,
         profile_client,
+        reservation_client,
+        user_client,
+        urgency_budgets,
+        args.shed_on_pushback,
         args.log_path,
     ));
 
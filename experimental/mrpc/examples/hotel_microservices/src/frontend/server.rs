@@ -1,8 +1,10 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::future::Future;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use futures::poll;
@@ -10,9 +12,9 @@ use hyper::{Body, Request, Response, StatusCode};
 use minstant::Instant;
 use serde_json::json;
 
-use mrpc::RRef;
-
-use super::tracer::Tracer;
+use mrpc::stub::RpcData;
+use mrpc::{RRef, WRef};
+use phoenix_bench::Tracer;
 
 pub mod hotel_microservices {
     pub mod search {
@@ -23,16 +25,34 @@ pub mod hotel_microservices {
         // The string specified here must match the proto package name
         mrpc::include_proto!("profile");
     }
+    pub mod reservation {
+        // The string specified here must match the proto package name
+        mrpc::include_proto!("reservation");
+    }
+    pub mod user {
+        // The string specified here must match the proto package name
+        mrpc::include_proto!("user");
+    }
 }
 
 use hotel_microservices::profile::profile_client::ProfileClient;
 use hotel_microservices::profile::{Request as ProfileRequest, Result as ProfileResult};
+use hotel_microservices::reservation::reservation_client::ReservationClient;
+use hotel_microservices::reservation::Request as ReservationRequest;
 use hotel_microservices::search::search_client::SearchClient;
 use hotel_microservices::search::NearbyRequest as SearchRequest;
+use hotel_microservices::user::user_client::UserClient;
+use hotel_microservices::user::Request as UserRequest;
 
 pub struct FrontendService {
     search_client: SearchClient,
     profile_client: ProfileClient,
+    reservation_client: ReservationClient,
+    user_client: UserClient,
+    // the deadline budget of each urgency of the HTTP requests, see `call_deadline`
+    urgency_budgets: Vec<Option<Duration>>,
+    // whether to fail the HTTP requests early while their backend asks to slow down
+    shed_on_pushback: bool,
     log_path: Option<PathBuf>,
     tracer: RefCell<Tracer>,
 }
@@ -42,13 +62,28 @@ unsafe impl Send for FrontendService {}
 unsafe impl Sync for FrontendService {}
 
 impl FrontendService {
-    pub fn new(search: SearchClient, profile: ProfileClient, log_path: Option<PathBuf>) -> Self {
+    pub fn new(
+        search: SearchClient,
+        profile: ProfileClient,
+        reservation: ReservationClient,
+        user: UserClient,
+        urgency_budgets: Vec<Option<Duration>>,
+        shed_on_pushback: bool,
+        log_path: Option<PathBuf>,
+    ) -> Self {
         let mut tracer = Tracer::new();
         tracer.new_end_to_end_entry("search");
+        tracer.new_end_to_end_entry("check_availability");
         tracer.new_end_to_end_entry("profile");
+        tracer.new_end_to_end_entry("user");
+        tracer.new_end_to_end_entry("make_reservation");
         FrontendService {
             search_client: search,
             profile_client: profile,
+            reservation_client: reservation,
+            user_client: user,
+            urgency_budgets,
+            shed_on_pushback,
             log_path,
             tracer: RefCell::new(tracer),
         }
@@ -57,14 +92,14 @@ impl FrontendService {
 
 impl Drop for FrontendService {
     fn drop(&mut self) {
-        let mut tracer = self.tracer.borrow_mut();
+        let tracer = self.tracer.borrow();
         if let Some(path) = &self.log_path {
             if let Some(parent) = path.parent() {
                 if let Err(err) = std::fs::create_dir_all(parent) {
                     log::error!("Error create logging dir: {}", err);
                 }
             }
-            if let Err(err) = tracer.to_csv(path) {
+            if let Err(err) = tracer.write_json(path) {
                 log::error!("Error writting logs: {}", err);
             }
         }
@@ -72,7 +107,52 @@ impl Drop for FrontendService {
 }
 
 impl FrontendService {
+    /// Returns the deadline the calls made for `request` inherit: the earlier of the time its
+    /// client waits for it, `x-request-timeout-ms`, and the budget of its urgency, the `priority`
+    /// header of RFC 9218, 3 if not given. The backend sends the calls with earlier deadlines
+    /// first on the connections that send in earliest-deadline-first order.
+    fn call_deadline(&self, request: &Request<Body>) -> Option<std::time::Instant> {
+        let now = std::time::Instant::now();
+        let header = |name| request.headers().get(name).and_then(|v| v.to_str().ok());
+        let urgency = header("priority")
+            .and_then(|v| {
+                v.split(',')
+                    .find_map(|param| param.trim().strip_prefix("u=")?.parse::<usize>().ok())
+            })
+            .unwrap_or(3);
+        let budget = self
+            .urgency_budgets
+            .get(urgency)
+            .copied()
+            .flatten()
+            .map(|budget| now + budget);
+        let timeout = header("x-request-timeout-ms")
+            .and_then(|v| v.trim().parse().ok())
+            .map(|ms| now + Duration::from_millis(ms));
+        match (budget, timeout) {
+            (Some(budget), Some(timeout)) => Some(budget.min(timeout)),
+            (budget, timeout) => budget.or(timeout),
+        }
+    }
+
+    /// Returns whether to fail a request to `path` early, as a backend it goes to has asked to
+    /// slow down.
+    fn shed(&self, path: &str) -> bool {
+        if !self.shed_on_pushback {
+            return false;
+        }
+        match path {
+            "/hotels" => self.search_client.pushed_back(),
+            "/user" => self.user_client.pushed_back(),
+            "/reservation" => {
+                self.user_client.pushed_back() || self.reservation_client.pushed_back()
+            }
+            _ => false,
+        }
+    }
+
     async fn handle_search(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let deadline = self.call_deadline(&request);
         let params = request
             .uri()
             .query()
@@ -106,33 +186,37 @@ impl FrontendService {
         log::trace!("SEARCH {:?}", search_req);
 
         let start = Instant::now();
-        let mut resp_fut = self.search_client.nearby(search_req);
-        let result = loop {
-            let result = poll!(&mut resp_fut);
-            match result {
-                Poll::Ready(resp) => break resp,
-                Poll::Pending => {}
-            }
-        }?;
+        let search_req = with_deadline(search_req, deadline);
+        let result = spin(self.search_client.nearby(search_req)).await?;
         self.tracer
             .borrow_mut()
             .record_end_to_end("search", start.elapsed())?;
         log::trace!("SearchHandler gets searchResp");
 
+        let reservation_req = ReservationRequest {
+            customer_name: mrpc::alloc::String::default(),
+            hotel_id: result.hotel_ids.clone(),
+            in_date: in_date.as_ref().into(),
+            out_date: out_date.as_ref().into(),
+            room_number: 1,
+        };
+
+        let start = Instant::now();
+        let reservation_req = with_deadline(reservation_req, deadline);
+        let result = spin(self.reservation_client.check_availability(reservation_req)).await?;
+        self.tracer
+            .borrow_mut()
+            .record_end_to_end("check_availability", start.elapsed())?;
+        log::trace!("SearchHandler gets reserveResp");
+
         let profile_req = ProfileRequest {
-            hotel_ids: result.hotel_ids.clone(),
+            hotel_ids: result.hotel_id.clone(),
             locale: locale.into(),
         };
 
         let start = Instant::now();
-        let mut resp_fut = self.profile_client.get_profiles(profile_req);
-        let result = loop {
-            let result = poll!(&mut resp_fut);
-            match result {
-                Poll::Ready(resp) => break resp,
-                Poll::Pending => {}
-            }
-        }?;
+        let profile_req = with_deadline(profile_req, deadline);
+        let result = spin(self.profile_client.get_profiles(profile_req)).await?;
         self.tracer
             .borrow_mut()
             .record_end_to_end("profile", start.elapsed())?;
@@ -145,6 +229,134 @@ impl FrontendService {
             .body(response_json.into())?;
         Ok(response)
     }
+
+    async fn handle_user(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let deadline = self.call_deadline(&request);
+        let params = request
+            .uri()
+            .query()
+            .map(|v| url::form_urlencoded::parse(v.as_bytes()).collect::<HashMap<_, _>>())
+            .ok_or(anyhow!("no query in request"))?;
+
+        let username = params
+            .get("username")
+            .ok_or(anyhow!("username param not found in query"))?;
+        let password = params
+            .get("password")
+            .ok_or(anyhow!("password param not found in query"))?;
+
+        let message = if self.check_user(username, password, deadline).await? {
+            "Login successfully!"
+        } else {
+            "Failed. Please check your username and password. "
+        };
+        message_response(message)
+    }
+
+    async fn handle_reservation(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let deadline = self.call_deadline(&request);
+        let params = request
+            .uri()
+            .query()
+            .map(|v| url::form_urlencoded::parse(v.as_bytes()).collect::<HashMap<_, _>>())
+            .ok_or(anyhow!("no query in request"))?;
+
+        let in_date = params
+            .get("inDate")
+            .ok_or(anyhow!("inDate param not found in query"))?;
+        let out_date = params
+            .get("outDate")
+            .ok_or(anyhow!("outDate param not found in query"))?;
+        let hotel_id = params
+            .get("hotelId")
+            .ok_or(anyhow!("hotelId param not found in query"))?;
+        let customer_name = params
+            .get("customerName")
+            .ok_or(anyhow!("customerName param not found in query"))?;
+        let username = params
+            .get("username")
+            .ok_or(anyhow!("username param not found in query"))?;
+        let password = params
+            .get("password")
+            .ok_or(anyhow!("password param not found in query"))?;
+        let room_number = match params.get("number") {
+            Some(number) => number.parse()?,
+            None => 1,
+        };
+
+        if !self.check_user(username, password, deadline).await? {
+            return message_response("Failed. Please check your username and password. ");
+        }
+
+        let mut hotel_ids = mrpc::alloc::Vec::with_capacity(1);
+        hotel_ids.push(hotel_id.as_ref().into());
+        let reservation_req = ReservationRequest {
+            customer_name: customer_name.as_ref().into(),
+            hotel_id: hotel_ids,
+            in_date: in_date.as_ref().into(),
+            out_date: out_date.as_ref().into(),
+            room_number,
+        };
+        log::trace!("RESERVE {:?}", reservation_req);
+
+        let start = Instant::now();
+        let reservation_req = with_deadline(reservation_req, deadline);
+        let result = spin(self.reservation_client.make_reservation(reservation_req)).await?;
+        self.tracer
+            .borrow_mut()
+            .record_end_to_end("make_reservation", start.elapsed())?;
+
+        let message = if result.hotel_id.is_empty() {
+            "Failed. Already reserved. "
+        } else {
+            "Reserve successfully!"
+        };
+        message_response(message)
+    }
+
+    async fn check_user(
+        &self,
+        username: &str,
+        password: &str,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<bool> {
+        let user_req = UserRequest {
+            username: username.into(),
+            password: password.into(),
+        };
+        let user_req = with_deadline(user_req, deadline);
+
+        let start = Instant::now();
+        let result = spin(self.user_client.check_user(user_req)).await?;
+        self.tracer
+            .borrow_mut()
+            .record_end_to_end("user", start.elapsed())?;
+        Ok(result.correct)
+    }
+}
+
+/// Puts `msg` on the shared heap, to be sent by the deadline of the HTTP request it is made for.
+fn with_deadline<T: RpcData>(msg: T, deadline: Option<std::time::Instant>) -> WRef<T> {
+    let mut msg = WRef::new(msg);
+    msg.set_deadline(deadline);
+    msg
+}
+
+/// Polls the future until it completes without yielding to the executor.
+async fn spin<F: Future + Unpin>(mut fut: F) -> F::Output {
+    loop {
+        if let Poll::Ready(output) = poll!(&mut fut) {
+            break output;
+        }
+    }
+}
+
+fn message_response(message: &str) -> Result<Response<Body>> {
+    let response = Response::builder()
+        .status(200)
+        .header("Access-Control-Allow-Origin", "*")
+        .body(json!({ "message": message }).to_string().into())?;
+    Ok(response)
 }
 
 fn geo_json_response(res: RRef<ProfileResult>) -> Result<String> {
@@ -189,18 +401,26 @@ pub async fn dispatch_fn(
     frontend: Arc<FrontendService>,
     request: Request<Body>,
 ) -> Result<Response<Body>, hyper::Error> {
+    if frontend.shed(request.uri().path()) {
+        let mut unavailable = Response::new(Body::empty());
+        *unavailable.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        unavailable
+            .headers_mut()
+            .insert("Retry-After", hyper::header::HeaderValue::from_static("1"));
+        return Ok(unavailable);
+    }
     match request.uri().path() {
         "/hotels" => {
             let response = frontend.handle_search(request).await;
-            match response {
-                Ok(resp) => Ok(resp),
-                Err(err) => {
-                    let body = format!("Internal error: {}", err.to_string()).into();
-                    let mut resp = Response::new(body);
-                    *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-                    Ok(resp)
-                }
-            }
+            Ok(internal_error_response(response))
+        }
+        "/user" => {
+            let response = frontend.handle_user(request).await;
+            Ok(internal_error_response(response))
+        }
+        "/reservation" => {
+            let response = frontend.handle_reservation(request).await;
+            Ok(internal_error_response(response))
         }
         _ => {
             let mut not_found = Response::new(Body::empty());
@@ -209,3 +429,15 @@ pub async fn dispatch_fn(
         }
     }
 }
+
+fn internal_error_response(response: Result<Response<Body>>) -> Response<Body> {
+    match response {
+        Ok(resp) => resp,
+        Err(err) => {
+            let body = format!("Internal error: {}", err.to_string()).into();
+            let mut resp = Response::new(body);
+            *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            resp
+        }
+    }
+}
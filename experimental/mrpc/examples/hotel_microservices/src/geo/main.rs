@@ -1,7 +1,10 @@
 use std::fs::File;
 use std::io::BufReader;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
+use mrpc::stub::PushbackPolicy;
+use mrpc_gateway::{ClientPool, Gateway, GrpcRoute, Router};
 use structopt::StructOpt;
 
 #[path = "../config.rs"]
@@ -10,11 +13,10 @@ pub mod db;
 #[path = "../logging.rs"]
 pub mod logging;
 pub mod server;
-#[path = "../tracer.rs"]
-pub mod tracer;
 
 use config::Config;
 use db::initialize_database;
+use server::hotel_microservices::geo::geo_client::GeoClient;
 use server::hotel_microservices::geo::geo_server::GeoServer;
 use server::GeoService;
 
@@ -30,6 +32,13 @@ pub struct Args {
     pub config: Option<PathBuf>,
     #[structopt(long)]
     pub log_path: Option<PathBuf>,
+    /// Ask the clients to slow down when more requests than this are being handled.
+    #[structopt(long)]
+    pub pushback_concurrency: Option<usize>,
+    /// Also serve the gRPC clients that are not on phoenix on this port, through a gateway that
+    /// calls this server at the loopback address.
+    #[structopt(long)]
+    pub grpc_port: Option<u16>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -41,7 +50,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let config: Config = serde_json::from_reader(reader)?;
         args.db = config.geo_mongo_addr;
         args.port = config.geo_port;
-        args.log_path = Some(config.log_path.join("geo.csv"));
+        args.log_path = Some(config.log_path.join("geo.json"));
     }
     eprintln!("args: {:?}", args);
     logging::init_env_log("RUST_LOG", "info");
@@ -51,10 +60,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     log::info!("Successful");
 
     let service = GeoService::new(database, args.log_path).await?;
+    if let Some(grpc_port) = args.grpc_port {
+        spawn_grpc_gateway(grpc_port, args.port)?;
+    }
     let signal = async_ctrlc::CtrlC::new()?;
+    let mut pushback = PushbackPolicy::new();
+    if let Some(concurrency) = args.pushback_concurrency {
+        pushback = pushback.concurrency(concurrency);
+    }
     mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
+        .set_pushback(pushback)
         .add_service(GeoServer::new(service))
         .serve_with_graceful_shutdown(signal)
         .await?;
     Ok(())
 }
+
+/// Serves the gRPC calls on `grpc_port` by calling the server on `port` over mRPC, on a thread of
+/// its own, as the clients of the gateway are bound to the thread that creates them.
+fn spawn_grpc_gateway(grpc_port: u16, port: u16) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("grpc-gateway".to_owned())
+        .spawn(move || {
+            let pool =
+                ClientPool::new(1, move || GeoClient::connect(format!("127.0.0.1:{}", port)));
+            let route =
+                GrpcRoute::unary(pool, |client, req| async move { client.nearby(req).await });
+            let router = Router::new().grpc("/geo.Geo/Nearby", route);
+            let addr = SocketAddr::from(([0, 0, 0, 0], grpc_port));
+            if let Err(e) = Gateway::new(router).run(addr, futures::future::pending()) {
+                log::error!("gRPC gateway failed: {}", e);
+            }
+        })?;
+    Ok(())
+}
@@ -11,9 +11,9 @@ use mongodb::Database;
 
 use mrpc::alloc::{String as MrpcString, Vec};
 use mrpc::{RRef, WRef};
+use phoenix_bench::Tracer;
 
 use super::db::Point;
-use super::tracer::Tracer;
 
 pub mod hotel_microservices {
     pub mod geo {
@@ -38,14 +38,14 @@ unsafe impl Sync for GeoService {}
 
 impl Drop for GeoService {
     fn drop(&mut self) {
-        let mut tracer = self.tracer.borrow_mut();
+        let tracer = self.tracer.borrow();
         if let Some(path) = &self.log_path {
             if let Some(parent) = path.parent() {
                 if let Err(err) = std::fs::create_dir_all(parent) {
                     log::error!("Error create logging dir: {}", err);
                 }
             }
-            if let Err(err) = tracer.to_csv(path) {
+            if let Err(err) = tracer.write_json(path) {
                 log::error!("Error writting logs: {}", err);
             }
         }
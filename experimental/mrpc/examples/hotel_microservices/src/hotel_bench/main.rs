@@ -0,0 +1,236 @@
+//! A workload generator for the hotel reservation frontend.
+//!
+//! It sends a mix of search, recommendation, login, and reservation requests to the frontend
+//! over HTTP, and reports the latency distribution of each type of request.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use hyper::client::HttpConnector;
+use hyper::Client;
+use minstant::Instant;
+use serde::Serialize;
+use structopt::StructOpt;
+
+use phoenix_bench::{Arrival, Bench, BenchConfig, BenchResult, LatencyRecorder, LatencySummary};
+
+pub mod workload;
+
+use workload::{Endpoint, Mix, Workload};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Hotel microservices workload generator")]
+pub struct Args {
+    /// The URL of the frontend.
+    #[structopt(short = "c", long, default_value = "http://127.0.0.1:5000")]
+    pub frontend: String,
+
+    /// The maximal number of concurrenty outstanding requests.
+    #[structopt(long, default_value = "64")]
+    pub concurrency: usize,
+
+    /// Total number of iterations.
+    #[structopt(short, long, default_value = "16384")]
+    pub total_iters: usize,
+
+    /// Number of warmup iterations.
+    #[structopt(short, long, default_value = "1000")]
+    pub warmup: usize,
+
+    /// Run test for a customized period of seconds.
+    #[structopt(short = "D", long)]
+    pub duration: Option<f64>,
+
+    /// Seconds between periodic throughput reports.
+    #[structopt(short, long)]
+    pub interval: Option<f64>,
+
+    /// Send requests at this rate (requests per second) in an open loop rather than keeping
+    /// `concurrency` requests outstanding.
+    #[structopt(long)]
+    pub rate: Option<f64>,
+
+    /// Use Poisson arrivals in the open loop.
+    #[structopt(long)]
+    pub poisson: bool,
+
+    /// The ratio of search requests.
+    #[structopt(long, default_value = "0.6")]
+    pub search_ratio: f64,
+
+    /// The ratio of recommendation requests. This requires a frontend that serves
+    /// `/recommendations`.
+    #[structopt(long, default_value = "0")]
+    pub recommend_ratio: f64,
+
+    /// The ratio of user login requests.
+    #[structopt(long, default_value = "0.005")]
+    pub login_ratio: f64,
+
+    /// The ratio of reservation requests.
+    #[structopt(long, default_value = "0.005")]
+    pub reserve_ratio: f64,
+
+    /// The exponent of the Zipfian popularity of hotels, 0 for uniform.
+    #[structopt(long, default_value = "0.99")]
+    pub zipf: f64,
+
+    /// The number of users to log in as.
+    #[structopt(long, default_value = "500")]
+    pub num_users: usize,
+
+    /// Write the overall and per-endpoint results as JSON to this file.
+    #[structopt(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl Args {
+    fn bench_config(&self) -> BenchConfig {
+        BenchConfig {
+            concurrency: self.concurrency,
+            warmup: self.warmup,
+            total_iters: self.total_iters,
+            duration_secs: self.duration,
+            report_interval_secs: self.interval,
+            rate: self.rate,
+            arrival: if self.poisson {
+                Arrival::Poisson
+            } else {
+                Arrival::Uniform
+            },
+            ..Default::default()
+        }
+    }
+
+    fn mix(&self) -> Result<Mix, String> {
+        Mix::new(&[
+            (Endpoint::Search, self.search_ratio),
+            (Endpoint::Recommend, self.recommend_ratio),
+            (Endpoint::Login, self.login_ratio),
+            (Endpoint::Reserve, self.reserve_ratio),
+        ])
+    }
+}
+
+#[derive(Default)]
+struct EndpointStats {
+    completed: u64,
+    errors: u64,
+    recorder: LatencyRecorder,
+}
+
+#[derive(Serialize)]
+struct EndpointSummary {
+    completed: u64,
+    errors: u64,
+    latency: LatencySummary,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    overall: &'a BenchResult,
+    endpoints: BTreeMap<&'static str, EndpointSummary>,
+}
+
+/// Sends a GET request and returns the size of the response body.
+async fn send(client: &Client<HttpConnector>, uri: &str) -> Result<usize> {
+    let resp = client.get(uri.parse()?).await?;
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+    if !status.is_success() {
+        bail!("{}: {}", status, String::from_utf8_lossy(&body));
+    }
+    Ok(body.len())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::from_args();
+    eprintln!("args: {:?}", args);
+
+    let workload = Workload::new(args.mix()?, args.zipf, args.num_users);
+    let client = Client::new();
+    let frontend = args.frontend.trim_end_matches('/');
+    // Requests issued during warmup are not recorded by endpoint.
+    let warmup = args.warmup;
+    let stats: RefCell<BTreeMap<Endpoint, EndpointStats>> = RefCell::new(BTreeMap::new());
+
+    let mut bench = Bench::new(args.bench_config()).on_interval(|interval| {
+        println!(
+            "{} rps, p50: {:?}, p99: {:?}",
+            interval.rps, interval.p50, interval.p99
+        );
+    });
+
+    let make_call = |scnt| {
+        let (endpoint, path) = workload.next_request();
+        let uri = format!("{}{}", frontend, path);
+        let client = &client;
+        let stats = &stats;
+        async move {
+            let start = Instant::now();
+            let result = send(client, &uri).await;
+            if scnt >= warmup {
+                let mut stats = stats.borrow_mut();
+                let entry = stats.entry(endpoint).or_default();
+                match &result {
+                    Ok(_) => {
+                        entry.completed += 1;
+                        entry.recorder.record(start.elapsed());
+                    }
+                    Err(_) => entry.errors += 1,
+                }
+            }
+            result
+        }
+    };
+
+    let result = if args.rate.is_some() {
+        bench.open_loop(make_call).await
+    } else {
+        bench.closed_loop(make_call).await
+    };
+
+    println!("overall, {}", result);
+    let stats = stats.into_inner();
+    let ns = Duration::from_nanos;
+    for endpoint in Endpoint::ALL {
+        if let Some(s) = stats.get(&endpoint) {
+            let latency = s.recorder.summary();
+            println!(
+                "{}, completed: {}, errors: {}, avg: {:?}, p50: {:?}, p90: {:?}, p99: {:?}, \
+                p999: {:?}, max: {:?}",
+                endpoint.name(),
+                s.completed,
+                s.errors,
+                ns(latency.mean_ns),
+                ns(latency.p50_ns),
+                ns(latency.p90_ns),
+                ns(latency.p99_ns),
+                ns(latency.p999_ns),
+                ns(latency.max_ns),
+            );
+        }
+    }
+
+    if let Some(path) = &args.output {
+        let report = Report {
+            overall: &result,
+            endpoints: stats
+                .iter()
+                .map(|(endpoint, s)| {
+                    let summary = EndpointSummary {
+                        completed: s.completed,
+                        errors: s.errors,
+                        latency: s.recorder.summary(),
+                    };
+                    (endpoint.name(), summary)
+                })
+                .collect(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    Ok(())
+}
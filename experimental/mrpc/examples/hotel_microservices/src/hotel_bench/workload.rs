@@ -0,0 +1,240 @@
+//! The mixed workload of the hotel reservation application.
+//!
+//! It follows the mixed workload of DeathStarBench: a request is a search, a recommendation, a
+//! user login, or a reservation, picked by the given ratios. The hotels that are searched around
+//! and reserved follow a Zipfian popularity.
+use std::fmt::Write;
+
+/// The number of hotels in the databases.
+pub const NUM_HOTELS: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Endpoint {
+    Search,
+    Recommend,
+    Login,
+    Reserve,
+}
+
+impl Endpoint {
+    pub const ALL: [Endpoint; 4] = [
+        Endpoint::Search,
+        Endpoint::Recommend,
+        Endpoint::Login,
+        Endpoint::Reserve,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Endpoint::Search => "search",
+            Endpoint::Recommend => "recommend",
+            Endpoint::Login => "login",
+            Endpoint::Reserve => "reserve",
+        }
+    }
+}
+
+/// Samples ranks in `[0, n)` with probability proportional to `1 / (rank + 1)^s`. An exponent
+/// of 0 gives a uniform distribution.
+pub struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    pub fn new(n: usize, s: f64) -> Self {
+        assert!(n > 0, "Zipf requires at least one item");
+        let mut cdf = Vec::with_capacity(n);
+        let mut sum = 0.0;
+        for k in 1..=n {
+            sum += 1.0 / (k as f64).powf(s);
+            cdf.push(sum);
+        }
+        cdf.iter_mut().for_each(|x| *x /= sum);
+        Zipf { cdf }
+    }
+
+    #[inline]
+    pub fn sample(&self) -> usize {
+        self.sample_with(fastrand::f64())
+    }
+
+    /// Returns the rank at `u` in `[0, 1)` of the inverse CDF.
+    fn sample_with(&self, u: f64) -> usize {
+        self.cdf
+            .partition_point(|&p| p <= u)
+            .min(self.cdf.len() - 1)
+    }
+}
+
+/// Picks the type of the next request by ratio.
+pub struct Mix {
+    // (endpoint, cumulative ratio)
+    cdf: Vec<(Endpoint, f64)>,
+}
+
+impl Mix {
+    /// Creates a mix from the ratio of each endpoint. The ratios are normalized, and endpoints of
+    /// zero ratio are never picked.
+    pub fn new(ratios: &[(Endpoint, f64)]) -> Result<Self, String> {
+        if ratios.iter().any(|(_, r)| *r < 0.0 || !r.is_finite()) {
+            return Err(format!("invalid ratios: {:?}", ratios));
+        }
+        let total: f64 = ratios.iter().map(|(_, r)| r).sum();
+        if total <= 0.0 {
+            return Err("at least one ratio must be positive".to_owned());
+        }
+        let mut sum = 0.0;
+        let cdf = ratios
+            .iter()
+            .filter(|(_, r)| *r > 0.0)
+            .map(|(endpoint, r)| {
+                sum += r / total;
+                (*endpoint, sum)
+            })
+            .collect();
+        Ok(Mix { cdf })
+    }
+
+    #[inline]
+    pub fn sample(&self) -> Endpoint {
+        self.sample_with(fastrand::f64())
+    }
+
+    fn sample_with(&self, u: f64) -> Endpoint {
+        self.cdf
+            .iter()
+            .find(|(_, p)| u < *p)
+            .unwrap_or_else(|| self.cdf.last().unwrap())
+            .0
+    }
+}
+
+pub struct Workload {
+    mix: Mix,
+    hotels: Zipf,
+    num_users: usize,
+}
+
+impl Workload {
+    pub fn new(mix: Mix, zipf_exponent: f64, num_users: usize) -> Self {
+        Workload {
+            mix,
+            hotels: Zipf::new(NUM_HOTELS, zipf_exponent),
+            num_users,
+        }
+    }
+
+    /// Returns the endpoint and the path and query of the next request.
+    pub fn next_request(&self) -> (Endpoint, String) {
+        let endpoint = self.mix.sample();
+        let hotel_id = self.hotels.sample() + 1;
+        let (lat, lon) = hotel_location(hotel_id);
+        // Search around the hotel.
+        let lat = lat + (fastrand::f64() - 0.5) * 0.01;
+        let lon = lon + (fastrand::f64() - 0.5) * 0.01;
+
+        let mut uri = String::new();
+        match endpoint {
+            Endpoint::Search => {
+                let (in_date, out_date) = random_dates();
+                write!(
+                    uri,
+                    "/hotels?inDate={}&outDate={}&lat={:.4}&lon={:.4}",
+                    in_date, out_date, lat, lon
+                )
+            }
+            Endpoint::Recommend => {
+                let require = ["dis", "rate", "price"][fastrand::usize(..3)];
+                write!(
+                    uri,
+                    "/recommendations?require={}&lat={:.4}&lon={:.4}",
+                    require, lat, lon
+                )
+            }
+            Endpoint::Login => {
+                let (username, password) = self.random_user();
+                write!(uri, "/user?username={}&password={}", username, password)
+            }
+            Endpoint::Reserve => {
+                let (in_date, out_date) = random_dates();
+                let (username, password) = self.random_user();
+                write!(
+                    uri,
+                    "/reservation?inDate={}&outDate={}&lat={:.4}&lon={:.4}&hotelId={}\
+                    &customerName={}&username={}&password={}&number=1",
+                    in_date, out_date, lat, lon, hotel_id, username, username, password
+                )
+            }
+        }
+        .unwrap();
+        (endpoint, uri)
+    }
+
+    /// Picks one of the users that the user service generates.
+    fn random_user(&self) -> (String, String) {
+        let suffix = fastrand::usize(..self.num_users).to_string();
+        (format!("Cornell_{}", suffix), suffix.repeat(10))
+    }
+}
+
+/// The location of a hotel in the geo database.
+fn hotel_location(hotel_id: usize) -> (f64, f64) {
+    match hotel_id {
+        1 => (37.7867, -122.4112),
+        2 => (37.7854, -122.4005),
+        3 => (37.7854, -122.4071),
+        4 => (37.7936, -122.3930),
+        5 => (37.7831, -122.4181),
+        6 => (37.7863, -122.4015),
+        i => (
+            37.7835 + i as f64 / 500.0 * 3.0,
+            -122.41 + i as f64 / 500.0 * 4.0,
+        ),
+    }
+}
+
+/// A stay of 1 to 5 nights checking in between 2015-04-09 and 2015-04-23.
+fn random_dates() -> (String, String) {
+    let in_day = fastrand::u32(9..=23);
+    let out_day = in_day + fastrand::u32(1..=5);
+    (
+        format!("2015-04-{:02}", in_day),
+        format!("2015-04-{:02}", out_day),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zipf_skew() {
+        let uniform = Zipf::new(4, 0.0);
+        assert_eq!(uniform.sample_with(0.0), 0);
+        assert_eq!(uniform.sample_with(0.3), 1);
+        assert_eq!(uniform.sample_with(0.99), 3);
+
+        // P(rank 0) = 1 / (1 + 1/2 + 1/3 + 1/4) = 0.48
+        let zipf = Zipf::new(4, 1.0);
+        assert_eq!(zipf.sample_with(0.47), 0);
+        assert_eq!(zipf.sample_with(0.49), 1);
+        assert_eq!(zipf.sample_with(0.9999), 3);
+    }
+
+    #[test]
+    fn mix_ratios() {
+        let mix = Mix::new(&[
+            (Endpoint::Search, 3.0),
+            (Endpoint::Recommend, 0.0),
+            (Endpoint::Login, 1.0),
+        ])
+        .unwrap();
+        assert_eq!(mix.sample_with(0.0), Endpoint::Search);
+        assert_eq!(mix.sample_with(0.74), Endpoint::Search);
+        assert_eq!(mix.sample_with(0.76), Endpoint::Login);
+        assert_eq!(mix.sample_with(0.9999), Endpoint::Login);
+
+        assert!(Mix::new(&[(Endpoint::Search, 0.0)]).is_err());
+        assert!(Mix::new(&[(Endpoint::Search, -1.0)]).is_err());
+    }
+}
@@ -63,10 +63,6 @@ pub struct Args {
     #[structopt(long, default_value = "1")]
     pub num_client_threads: usize,
 
-    /// Number of server threads.
-    #[structopt(long, default_value = "1")]
-    pub num_server_threads: usize,
-
     /// Whether use a synchronized client that busy polls responses
     #[structopt(long)]
     pub polling: bool,
@@ -239,10 +235,7 @@ fn run_client_thread(
     tid: usize,
     args: &Args,
 ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
-    let client = GeoClient::connect((
-        args.ip.as_str(),
-        args.port + (tid % args.num_server_threads) as u16,
-    ))?;
+    let client = GeoClient::connect((args.ip.as_str(), args.port))?;
     eprintln!("connection setup for thread {tid}");
 
     smol::block_on(async {
@@ -290,7 +283,6 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
     let args = Args::from_args();
     eprintln!("args: {:?}", args);
 
-    assert!(args.num_client_threads % args.num_server_threads == 0);
     std::thread::scope(|s| {
         let mut handles = Vec::new();
         for tid in 1..args.num_client_threads {
@@ -48,9 +48,9 @@ pub struct Args {
     pub num_server_threads: usize,
 }
 
-fn run_server(tid: usize, args: Args) -> Result<(), mrpc::Error> {
+fn run_server(_tid: usize, args: Args) -> Result<(), mrpc::Error> {
     smol::block_on(async {
-        mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port + tid as u16))?
+        mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
             .add_service(GeoServer::new(GeoService))
             .serve()
             .await
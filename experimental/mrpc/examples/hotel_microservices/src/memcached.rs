@@ -0,0 +1,164 @@
+//! A minimal asynchronous memcached client that speaks the text protocol.
+//!
+//! The client keeps a small pool of connections. A connection is established lazily and is
+//! dropped on any error, so that the next request on it reconnects.
+use std::cell::Cell;
+use std::io;
+use std::time::Duration;
+
+use futures::lock::{Mutex, MutexGuard};
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid memcached address: {0}")]
+    Address(String),
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Unexpected response: {0:?}")]
+    Protocol(String),
+}
+
+pub struct Client {
+    conns: Vec<Mutex<Connection>>,
+    next: Cell<usize>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Connects to the memcached server at `addr` (e.g., `memcache://localhost:11211`) with
+    /// `pool_size` connections.
+    pub async fn connect(addr: &str, pool_size: usize, timeout: Duration) -> Result<Self, Error> {
+        let addr = addr
+            .strip_prefix("memcache://")
+            .or_else(|| addr.strip_prefix("tcp://"))
+            .unwrap_or(addr)
+            .trim_end_matches('/');
+        if addr.is_empty() || pool_size == 0 {
+            return Err(Error::Address(addr.to_owned()));
+        }
+        let mut conns = Vec::with_capacity(pool_size);
+        for _ in 0..pool_size {
+            let mut conn = Connection {
+                addr: addr.to_owned(),
+                stream: None,
+            };
+            // Fail fast if the server is not reachable.
+            conn.stream().await?;
+            conns.push(Mutex::new(conn));
+        }
+        Ok(Client {
+            conns,
+            next: Cell::new(0),
+            timeout,
+        })
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let mut conn = self.conn().await;
+        let result = tokio::time::timeout(self.timeout, conn.get(key))
+            .await
+            .unwrap_or(Err(Error::Timeout));
+        if result.is_err() {
+            conn.stream = None;
+        }
+        result
+    }
+
+    /// Stores `value` under `key`. The item expires after `expiration` seconds, or never if
+    /// `expiration` is 0.
+    pub async fn set(&self, key: &str, value: &str, expiration: u32) -> Result<(), Error> {
+        let mut conn = self.conn().await;
+        let result = tokio::time::timeout(self.timeout, conn.set(key, value, expiration))
+            .await
+            .unwrap_or(Err(Error::Timeout));
+        if result.is_err() {
+            conn.stream = None;
+        }
+        result
+    }
+
+    /// Picks an idle connection, or waits for one in round-robin if all of them are busy.
+    async fn conn(&self) -> MutexGuard<'_, Connection> {
+        for conn in self.conns.iter() {
+            if let Some(guard) = conn.try_lock() {
+                return guard;
+            }
+        }
+        let next = self.next.get();
+        self.next.set(next.wrapping_add(1));
+        self.conns[next % self.conns.len()].lock().await
+    }
+}
+
+struct Connection {
+    addr: String,
+    stream: Option<BufStream<TcpStream>>,
+}
+
+impl Connection {
+    async fn stream(&mut self) -> Result<&mut BufStream<TcpStream>, Error> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect(&self.addr).await?;
+            stream.set_nodelay(true)?;
+            self.stream = Some(BufStream::new(stream));
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    async fn get(&mut self, key: &str) -> Result<Option<String>, Error> {
+        let stream = self.stream().await?;
+        stream
+            .write_all(format!("get {}\r\n", key).as_bytes())
+            .await?;
+        stream.flush().await?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        if line == "END\r\n" {
+            return Ok(None);
+        }
+        // VALUE <key> <flags> <bytes>\r\n
+        let len = match line.trim_end().split(' ').collect::<Vec<_>>()[..] {
+            ["VALUE", k, _, len] if k == key => len.parse::<usize>().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| Error::Protocol(line.clone()))?;
+
+        let mut data = vec![0; len + 2];
+        stream.read_exact(&mut data).await?;
+        if !data.ends_with(b"\r\n") {
+            return Err(Error::Protocol(String::from_utf8_lossy(&data).into_owned()));
+        }
+        data.truncate(len);
+
+        line.clear();
+        stream.read_line(&mut line).await?;
+        if line != "END\r\n" {
+            return Err(Error::Protocol(line));
+        }
+        String::from_utf8(data)
+            .map(Some)
+            .map_err(|e| Error::Protocol(e.to_string()))
+    }
+
+    async fn set(&mut self, key: &str, value: &str, expiration: u32) -> Result<(), Error> {
+        let stream = self.stream().await?;
+        let header = format!("set {} 0 {} {}\r\n", key, expiration, value.len());
+        stream.write_all(header.as_bytes()).await?;
+        stream.write_all(value.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        if line != "STORED\r\n" {
+            return Err(Error::Protocol(line));
+        }
+        Ok(())
+    }
+}
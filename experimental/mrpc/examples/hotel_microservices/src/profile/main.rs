@@ -3,17 +3,21 @@ use std::io::BufReader;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use mrpc::stub::PushbackPolicy;
 use structopt::StructOpt;
 
+#[path = "../cache.rs"]
+pub mod cache;
 #[path = "../config.rs"]
 pub mod config;
 pub mod db;
 #[path = "../logging.rs"]
 pub mod logging;
+#[path = "../memcached.rs"]
+pub mod memcached;
 pub mod server;
-#[path = "../tracer.rs"]
-pub mod tracer;
 
+use cache::LocalCache;
 use config::Config;
 use db::initialize_database;
 use server::hotel_microservices::profile::profile_server::ProfileServer;
@@ -29,10 +33,22 @@ pub struct Args {
     pub db: String,
     #[structopt(long, default_value = "memcache://localhost:11211")]
     pub memc: String,
+    /// The number of connections to memcached.
+    #[structopt(long, default_value = "8")]
+    pub memc_pool_size: usize,
+    /// The capacity of the in-process cache, 0 to disable it.
+    #[structopt(long, default_value = "4096")]
+    pub cache_capacity: usize,
+    /// The time-to-live of the entries in the in-process cache, in milliseconds.
+    #[structopt(long, default_value = "10000")]
+    pub cache_ttl_ms: u64,
     #[structopt(short, long)]
     pub config: Option<PathBuf>,
     #[structopt(long)]
     pub log_path: Option<PathBuf>,
+    /// Ask the clients to slow down when more requests than this are being handled.
+    #[structopt(long)]
+    pub pushback_concurrency: Option<usize>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -45,7 +61,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         args.db = config.profile_mongo_addr;
         args.memc = config.profile_memc_addr;
         args.port = config.profile_port;
-        args.log_path = Some(config.log_path.join("profile.csv"));
+        args.log_path = Some(config.log_path.join("profile.json"));
     }
     eprintln!("args: {:?}", args);
     logging::init_env_log("RUST_LOG", "info");
@@ -55,14 +71,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     log::info!("Successful");
 
     log::info!("Initializing memcached client...");
-    let memc_client = memcache::Client::with_pool_size(&*args.memc, 512)?;
-    memc_client.set_read_timeout(Some(Duration::from_secs(2)))?;
-    memc_client.set_write_timeout(Some(Duration::from_secs(2)))?;
+    let memc_client =
+        memcached::Client::connect(&args.memc, args.memc_pool_size, Duration::from_secs(2)).await?;
     log::info!("Successful");
 
-    let service = ProfileService::new(database, memc_client, args.log_path);
+    let cache = LocalCache::new(
+        args.cache_capacity,
+        Duration::from_millis(args.cache_ttl_ms),
+    );
+    let service = ProfileService::new(database, memc_client, cache, args.log_path);
     let signal = async_ctrlc::CtrlC::new()?;
+    let mut pushback = PushbackPolicy::new();
+    if let Some(concurrency) = args.pushback_concurrency {
+        pushback = pushback.concurrency(concurrency);
+    }
     mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
+        .set_pushback(pushback)
         .add_service(ProfileServer::new(service))
         .serve_with_graceful_shutdown(signal)
         .await?;
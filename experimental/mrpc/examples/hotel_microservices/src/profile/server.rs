@@ -2,16 +2,17 @@ use std::cell::RefCell;
 use std::path::PathBuf;
 
 use anyhow::{bail, Result};
-use memcache::Client as MemcacheClient;
 use minstant::Instant;
 use mongodb::bson::doc;
 use mongodb::Database;
 
 use mrpc::alloc::Vec;
 use mrpc::{RRef, WRef};
+use phoenix_bench::Tracer;
 
+use super::cache::LocalCache;
 use super::db;
-use super::tracer::Tracer;
+use super::memcached::Client as MemcacheClient;
 
 pub mod hotel_microservices {
     pub mod profile {
@@ -55,6 +56,7 @@ impl From<db::Address> for Address {
 pub struct ProfileService {
     memc_client: MemcacheClient,
     db_handle: Database,
+    cache: LocalCache<db::Hotel>,
     log_path: Option<PathBuf>,
     tracer: RefCell<Tracer>,
 }
@@ -65,14 +67,14 @@ unsafe impl Sync for ProfileService {}
 
 impl Drop for ProfileService {
     fn drop(&mut self) {
-        let mut tracer = self.tracer.borrow_mut();
+        let tracer = self.tracer.borrow();
         if let Some(path) = &self.log_path {
             if let Some(parent) = path.parent() {
                 if let Err(err) = std::fs::create_dir_all(parent) {
                     log::error!("Error create logging dir: {}", err);
                 }
             }
-            if let Err(err) = tracer.to_csv(path) {
+            if let Err(err) = tracer.write_json(path) {
                 log::error!("Error writting logs: {}", err);
             }
         }
@@ -103,42 +105,59 @@ impl Profile for ProfileService {
 impl ProfileService {
     #[inline]
     async fn get_profiles_internal(&self, request: RRef<ProfileRequest>) -> Result<ProfileResult> {
-        let mut hotels = Vec::with_capacity(request.hotel_ids.len());
-        for hotel_id in request.hotel_ids.iter() {
-            let item: Option<String> = self.memc_client.get(hotel_id.as_str())?;
-            if let Some(item) = item {
-                log::trace!("memc hit with {}", item);
-                let hotel_prof: db::Hotel = serde_json::from_str(&item)?;
-                let hotel_proto = hotel_prof.into();
-                hotels.push(hotel_proto);
-            } else {
-                let collection = self.db_handle.collection::<db::Hotel>("hotels");
-                let hotel = collection
-                    .find_one(doc! { "id": hotel_id.as_str() }, None)
-                    .await?;
-                if let Some(hotel) = hotel {
-                    let hotel_json = serde_json::to_string(&hotel)?;
-                    let hotel_proto = hotel.into();
-                    hotels.push(hotel_proto);
-                    self.memc_client.set(hotel_id.as_str(), hotel_json, 0)?;
-                } else {
-                    bail!("hotel {} not found", hotel_id);
-                }
-            }
+        let profiles = futures::future::try_join_all(
+            request
+                .hotel_ids
+                .iter()
+                .map(|hotel_id| self.get_profile(hotel_id.as_str())),
+        )
+        .await?;
+        let mut hotels = Vec::with_capacity(profiles.len());
+        for hotel in profiles {
+            hotels.push(hotel.into());
         }
 
         let result = ProfileResult { hotels };
         Ok(result)
     }
+
+    async fn get_profile(&self, hotel_id: &str) -> Result<db::Hotel> {
+        self.cache
+            .get_or_fetch(hotel_id, || self.fetch_profile(hotel_id))
+            .await
+    }
+
+    async fn fetch_profile(&self, hotel_id: &str) -> Result<db::Hotel> {
+        if let Some(item) = self.memc_client.get(hotel_id).await? {
+            log::trace!("memc hit with {}", item);
+            return Ok(serde_json::from_str(&item)?);
+        }
+
+        let collection = self.db_handle.collection::<db::Hotel>("hotels");
+        let hotel = collection.find_one(doc! { "id": hotel_id }, None).await?;
+        if let Some(hotel) = hotel {
+            let hotel_json = serde_json::to_string(&hotel)?;
+            self.memc_client.set(hotel_id, &hotel_json, 0).await?;
+            Ok(hotel)
+        } else {
+            bail!("hotel {} not found", hotel_id);
+        }
+    }
 }
 
 impl ProfileService {
-    pub fn new(db: Database, memc: MemcacheClient, log_path: Option<PathBuf>) -> Self {
+    pub fn new(
+        db: Database,
+        memc: MemcacheClient,
+        cache: LocalCache<db::Hotel>,
+        log_path: Option<PathBuf>,
+    ) -> Self {
         let mut tracer = Tracer::new();
         tracer.new_proc_entry("profile");
         ProfileService {
             memc_client: memc,
             db_handle: db,
+            cache,
             log_path,
             tracer: RefCell::new(tracer),
         }
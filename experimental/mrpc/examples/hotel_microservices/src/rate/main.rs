@@ -1,21 +1,28 @@
 use std::fs::File;
 use std::io::BufReader;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use mrpc::stub::PushbackPolicy;
+use mrpc_gateway::{ClientPool, Gateway, GrpcRoute, Router};
 use structopt::StructOpt;
 
+#[path = "../cache.rs"]
+pub mod cache;
 #[path = "../config.rs"]
 pub mod config;
 pub mod db;
 #[path = "../logging.rs"]
 pub mod logging;
+#[path = "../memcached.rs"]
+pub mod memcached;
 pub mod server;
-#[path = "../tracer.rs"]
-pub mod tracer;
 
+use cache::LocalCache;
 use config::Config;
 use db::initialize_database;
+use server::hotel_microservices::rate::rate_client::RateClient;
 use server::hotel_microservices::rate::rate_server::RateServer;
 use server::RateService;
 
@@ -29,10 +36,26 @@ pub struct Args {
     pub db: String,
     #[structopt(long, default_value = "memcache://localhost:11211")]
     pub memc: String,
+    /// The number of connections to memcached.
+    #[structopt(long, default_value = "8")]
+    pub memc_pool_size: usize,
+    /// The capacity of the in-process cache, 0 to disable it.
+    #[structopt(long, default_value = "4096")]
+    pub cache_capacity: usize,
+    /// The time-to-live of the entries in the in-process cache, in milliseconds.
+    #[structopt(long, default_value = "10000")]
+    pub cache_ttl_ms: u64,
     #[structopt(short, long)]
     pub config: Option<PathBuf>,
     #[structopt(long)]
     pub log_path: Option<PathBuf>,
+    /// Ask the clients to slow down when more requests than this are being handled.
+    #[structopt(long)]
+    pub pushback_concurrency: Option<usize>,
+    /// Also serve the gRPC clients that are not on phoenix on this port, through a gateway that
+    /// calls this server at the loopback address.
+    #[structopt(long)]
+    pub grpc_port: Option<u16>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -45,7 +68,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         args.db = config.rate_mongo_addr;
         args.memc = config.rate_memc_addr;
         args.port = config.rate_port;
-        args.log_path = Some(config.log_path.join("rate.csv"));
+        args.log_path = Some(config.log_path.join("rate.json"));
     }
     eprintln!("args: {:?}", args);
     logging::init_env_log("RUST_LOG", "info");
@@ -55,16 +78,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     log::info!("Successful");
 
     log::info!("Initializing memcached client...");
-    let memc_client = memcache::Client::with_pool_size(&*args.memc, 512)?;
-    memc_client.set_read_timeout(Some(Duration::from_secs(2)))?;
-    memc_client.set_write_timeout(Some(Duration::from_secs(2)))?;
+    let memc_client =
+        memcached::Client::connect(&args.memc, args.memc_pool_size, Duration::from_secs(2)).await?;
     log::info!("Successful");
 
-    let service = RateService::new(database, memc_client, args.log_path);
+    let cache = LocalCache::new(
+        args.cache_capacity,
+        Duration::from_millis(args.cache_ttl_ms),
+    );
+    let service = RateService::new(database, memc_client, cache, args.log_path);
+    if let Some(grpc_port) = args.grpc_port {
+        spawn_grpc_gateway(grpc_port, args.port)?;
+    }
     let signal = async_ctrlc::CtrlC::new()?;
+    let mut pushback = PushbackPolicy::new();
+    if let Some(concurrency) = args.pushback_concurrency {
+        pushback = pushback.concurrency(concurrency);
+    }
     mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
+        .set_pushback(pushback)
         .add_service(RateServer::new(service))
         .serve_with_graceful_shutdown(signal)
         .await?;
     Ok(())
 }
+
+/// Serves the gRPC calls on `grpc_port` by calling the server on `port` over mRPC, on a thread of
+/// its own, as the clients of the gateway are bound to the thread that creates them.
+fn spawn_grpc_gateway(grpc_port: u16, port: u16) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("grpc-gateway".to_owned())
+        .spawn(move || {
+            let pool = ClientPool::new(1, move || {
+                RateClient::connect(format!("127.0.0.1:{}", port))
+            });
+            let route = GrpcRoute::unary(
+                pool,
+                |client, req| async move { client.get_rates(req).await },
+            );
+            let router = Router::new().grpc("/rate.Rate/GetRates", route);
+            let addr = SocketAddr::from(([0, 0, 0, 0], grpc_port));
+            if let Err(e) = Gateway::new(router).run(addr, futures::future::pending()) {
+                log::error!("gRPC gateway failed: {}", e);
+            }
+        })?;
+    Ok(())
+}
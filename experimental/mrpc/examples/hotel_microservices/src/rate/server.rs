@@ -3,16 +3,17 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use futures::StreamExt;
-use memcache::Client as MemcacheClient;
 use minstant::Instant;
 use mongodb::bson::doc;
 use mongodb::Database;
 
 use mrpc::alloc::Vec;
 use mrpc::{RRef, WRef};
+use phoenix_bench::Tracer;
 
+use super::cache::LocalCache;
 use super::db;
-use super::tracer::Tracer;
+use super::memcached::Client as MemcacheClient;
 
 pub mod hotel_microservices {
     pub mod rate {
@@ -53,6 +54,7 @@ impl From<db::RatePlan> for RatePlan {
 pub struct RateService {
     memc_client: MemcacheClient,
     db_handle: Database,
+    cache: LocalCache<std::vec::Vec<db::RatePlan>>,
     log_path: Option<PathBuf>,
     tracer: RefCell<Tracer>,
 }
@@ -63,14 +65,14 @@ unsafe impl Sync for RateService {}
 
 impl Drop for RateService {
     fn drop(&mut self) {
-        let mut tracer = self.tracer.borrow_mut();
+        let tracer = self.tracer.borrow();
         if let Some(path) = &self.log_path {
             if let Some(parent) = path.parent() {
                 if let Err(err) = std::fs::create_dir_all(parent) {
                     log::error!("Error create logging dir: {}", err);
                 }
             }
-            if let Err(err) = tracer.to_csv(path) {
+            if let Err(err) = tracer.write_json(path) {
                 log::error!("Error writting logs: {}", err);
             }
         }
@@ -100,49 +102,70 @@ impl Rate for RateService {
 
 impl RateService {
     async fn get_rates_internal(&self, request: RRef<RateRequest>) -> Result<RateResult> {
+        let plans = futures::future::try_join_all(
+            request
+                .hotel_ids
+                .iter()
+                .map(|hotel_id| self.get_rate_plans(hotel_id.as_str())),
+        )
+        .await?;
         let mut rate_plans = Vec::new();
-        for hotel_id in request.hotel_ids.iter() {
-            let item: Option<String> = self.memc_client.get(hotel_id.as_str())?;
-            if let Some(item) = item {
-                log::trace!("memc hit, hotelId = {}", hotel_id);
-                let rate_strs = item.split("\n");
-                for rate_str in rate_strs {
-                    if !rate_str.is_empty() {
-                        let rate_plan: db::RatePlan = serde_json::from_str(rate_str)?;
-                        let proto_plan = rate_plan.into();
-                        rate_plans.push(proto_plan);
-                    }
-                }
-            } else {
-                log::trace!("memc miss, hotelId = {}", hotel_id);
-                let mut memc_str = String::new();
-                let collections = self.db_handle.collection::<db::RatePlan>("inventory");
-                let mut plans = collections
-                    .find(doc! { "hotelId" : hotel_id.as_str() }, None)
-                    .await?;
-                while let Some(plan) = plans.next().await {
-                    let plan = plan?;
-                    let plan_json = serde_json::to_string(&plan)?;
-                    memc_str += plan_json.as_str();
-                    memc_str += "\n";
-                    let proto_plan = plan.into();
-                    rate_plans.push(proto_plan);
-                }
-                self.memc_client.set(hotel_id.as_str(), memc_str, 0)?;
-            }
+        for plan in plans.into_iter().flatten() {
+            rate_plans.push(plan.into());
         }
         let result = RateResult { rate_plans };
         Ok(result)
     }
+
+    async fn get_rate_plans(&self, hotel_id: &str) -> Result<std::vec::Vec<db::RatePlan>> {
+        self.cache
+            .get_or_fetch(hotel_id, || self.fetch_rate_plans(hotel_id))
+            .await
+    }
+
+    async fn fetch_rate_plans(&self, hotel_id: &str) -> Result<std::vec::Vec<db::RatePlan>> {
+        let mut rate_plans = std::vec::Vec::new();
+        if let Some(item) = self.memc_client.get(hotel_id).await? {
+            log::trace!("memc hit, hotelId = {}", hotel_id);
+            for rate_str in item.split('\n') {
+                if !rate_str.is_empty() {
+                    rate_plans.push(serde_json::from_str(rate_str)?);
+                }
+            }
+            return Ok(rate_plans);
+        }
+
+        log::trace!("memc miss, hotelId = {}", hotel_id);
+        let mut memc_str = String::new();
+        let collections = self.db_handle.collection::<db::RatePlan>("inventory");
+        let mut plans = collections
+            .find(doc! { "hotelId" : hotel_id }, None)
+            .await?;
+        while let Some(plan) = plans.next().await {
+            let plan = plan?;
+            let plan_json = serde_json::to_string(&plan)?;
+            memc_str += plan_json.as_str();
+            memc_str += "\n";
+            rate_plans.push(plan);
+        }
+        self.memc_client.set(hotel_id, &memc_str, 0).await?;
+        Ok(rate_plans)
+    }
 }
 
 impl RateService {
-    pub fn new(db: Database, memc: MemcacheClient, log_path: Option<PathBuf>) -> Self {
+    pub fn new(
+        db: Database,
+        memc: MemcacheClient,
+        cache: LocalCache<std::vec::Vec<db::RatePlan>>,
+        log_path: Option<PathBuf>,
+    ) -> Self {
         let mut tracer = Tracer::new();
         tracer.new_proc_entry("rate");
         RateService {
             memc_client: memc,
             db_handle: db,
+            cache,
             log_path,
             tracer: RefCell::new(tracer),
         }
@@ -0,0 +1,81 @@
+use mongodb::bson::doc;
+use mongodb::error::Result;
+use mongodb::IndexModel;
+use mongodb::{Client, Database};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reservation {
+    #[serde(rename = "hotelId")]
+    pub hotel_id: String,
+    #[serde(rename = "customerName")]
+    pub customer_name: String,
+    #[serde(rename = "inDate")]
+    pub in_date: String,
+    #[serde(rename = "outDate")]
+    pub out_date: String,
+    pub number: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Number {
+    #[serde(rename = "hotelId")]
+    pub hotel_id: String,
+    #[serde(rename = "numberOfRoom")]
+    pub number_of_room: i32,
+}
+
+pub async fn initialize_database(uri: impl AsRef<str>) -> Result<Database> {
+    let client = Client::with_uri_str(uri).await?;
+
+    log::info!("New session successful...");
+
+    log::info!("Generating test data...");
+    let db = client.database("reservation-db");
+    let reservations = db.collection::<Reservation>("reservation");
+
+    let count = reservations
+        .count_documents(doc! { "hotelId": "4" }, None)
+        .await?;
+    if count == 0 {
+        let reservation = Reservation {
+            hotel_id: "4".to_string(),
+            customer_name: "Alice".to_string(),
+            in_date: "2015-04-09".to_string(),
+            out_date: "2015-04-10".to_string(),
+            number: 1,
+        };
+        reservations.insert_one(reservation, None).await?;
+    }
+
+    let numbers = db.collection::<Number>("number");
+    for i in 1..=80 {
+        let hotel_id = i.to_string();
+        let count = numbers
+            .count_documents(doc! { "hotelId": hotel_id.as_str() }, None)
+            .await?;
+        if count == 0 {
+            let number_of_room = if i <= 6 || i % 3 == 0 {
+                200
+            } else if i % 3 == 1 {
+                300
+            } else {
+                250
+            };
+            let number = Number {
+                hotel_id,
+                number_of_room,
+            };
+            numbers.insert_one(number, None).await?;
+        }
+    }
+
+    let index = IndexModel::builder()
+        .keys(doc! { "hotelId": 1, "inDate": 1, "outDate": 1 })
+        .build();
+    reservations.create_index(index, None).await?;
+    let index = IndexModel::builder().keys(doc! { "hotelId": 1 }).build();
+    numbers.create_index(index, None).await?;
+
+    Ok(db)
+}
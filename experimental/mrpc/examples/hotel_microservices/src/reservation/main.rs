@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use mrpc::stub::PushbackPolicy;
+use structopt::StructOpt;
+
+#[path = "../config.rs"]
+pub mod config;
+pub mod db;
+#[path = "../logging.rs"]
+pub mod logging;
+#[path = "../memcached.rs"]
+pub mod memcached;
+pub mod server;
+
+use config::Config;
+use db::initialize_database;
+use server::hotel_microservices::reservation::reservation_server::ReservationServer;
+use server::ReservationService;
+
+#[derive(StructOpt, Debug, Clone)]
+#[structopt(about = "Hotel reservation reservation server")]
+pub struct Args {
+    /// The port number to listen on.
+    #[structopt(short, long, default_value = "5000")]
+    pub port: u16,
+    #[structopt(long, default_value = "mongodb://localhost:27017")]
+    pub db: String,
+    #[structopt(long, default_value = "memcache://localhost:11211")]
+    pub memc: String,
+    /// The number of connections to memcached.
+    #[structopt(long, default_value = "8")]
+    pub memc_pool_size: usize,
+    #[structopt(short, long)]
+    pub config: Option<PathBuf>,
+    #[structopt(long)]
+    pub log_path: Option<PathBuf>,
+    /// Ask the clients to slow down when more requests than this are being handled.
+    #[structopt(long)]
+    pub pushback_concurrency: Option<usize>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = Args::from_args();
+    if let Some(path) = &args.config {
+        let file = File::open(path).unwrap();
+        let reader = BufReader::new(file);
+        let config: Config = serde_json::from_reader(reader)?;
+        args.db = config.reservation_mongo_addr;
+        args.memc = config.reservation_memc_addr;
+        args.port = config.reservation_port;
+        args.log_path = Some(config.log_path.join("reservation.json"));
+    }
+    eprintln!("args: {:?}", args);
+    logging::init_env_log("RUST_LOG", "info");
+
+    log::info!("Initializing DB connection...");
+    let database = initialize_database(args.db).await?;
+    log::info!("Successful");
+
+    log::info!("Initializing memcached client...");
+    let memc_client =
+        memcached::Client::connect(&args.memc, args.memc_pool_size, Duration::from_secs(2)).await?;
+    log::info!("Successful");
+
+    let service = ReservationService::new(database, memc_client, args.log_path);
+    let signal = async_ctrlc::CtrlC::new()?;
+    let mut pushback = PushbackPolicy::new();
+    if let Some(concurrency) = args.pushback_concurrency {
+        pushback = pushback.concurrency(concurrency);
+    }
+    mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
+        .set_pushback(pushback)
+        .add_service(ReservationServer::new(service))
+        .serve_with_graceful_shutdown(signal)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,255 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{Duration, NaiveDate};
+use futures::StreamExt;
+use minstant::Instant;
+use mongodb::bson::doc;
+use mongodb::Database;
+
+use mrpc::alloc::Vec;
+use mrpc::{RRef, WRef};
+use phoenix_bench::Tracer;
+
+use super::db;
+use super::memcached::Client as MemcacheClient;
+
+pub mod hotel_microservices {
+    pub mod reservation {
+        // The string specified here must match the proto package name
+        mrpc::include_proto!("reservation");
+    }
+}
+
+use hotel_microservices::reservation::reservation_server::Reservation;
+use hotel_microservices::reservation::{
+    Request as ReservationRequest, Result as ReservationResult,
+};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+pub struct ReservationService {
+    memc_client: MemcacheClient,
+    db_handle: Database,
+    log_path: Option<PathBuf>,
+    tracer: RefCell<Tracer>,
+}
+
+// SAFETY: This is unsafe
+unsafe impl Send for ReservationService {}
+unsafe impl Sync for ReservationService {}
+
+impl Drop for ReservationService {
+    fn drop(&mut self) {
+        let tracer = self.tracer.borrow();
+        if let Some(path) = &self.log_path {
+            if let Some(parent) = path.parent() {
+                if let Err(err) = std::fs::create_dir_all(parent) {
+                    log::error!("Error create logging dir: {}", err);
+                }
+            }
+            if let Err(err) = tracer.write_json(path) {
+                log::error!("Error writting logs: {}", err);
+            }
+        }
+    }
+}
+
+#[mrpc::async_trait]
+impl Reservation for ReservationService {
+    async fn make_reservation(
+        &self,
+        request: RRef<ReservationRequest>,
+    ) -> Result<WRef<ReservationResult>, mrpc::Status> {
+        let start = Instant::now();
+        let result = self
+            .make_reservation_internal(request)
+            .await
+            .map_err(|err| mrpc::Status::internal(err.to_string()))?;
+        self.tracer
+            .borrow_mut()
+            .record_proc("make_reservation", start.elapsed())
+            .map_err(|err| mrpc::Status::internal(err.to_string()))?;
+
+        let wref = WRef::new(result);
+        Ok(wref)
+    }
+
+    async fn check_availability(
+        &self,
+        request: RRef<ReservationRequest>,
+    ) -> Result<WRef<ReservationResult>, mrpc::Status> {
+        let start = Instant::now();
+        let result = self
+            .check_availability_internal(request)
+            .await
+            .map_err(|err| mrpc::Status::internal(err.to_string()))?;
+        self.tracer
+            .borrow_mut()
+            .record_proc("check_availability", start.elapsed())
+            .map_err(|err| mrpc::Status::internal(err.to_string()))?;
+
+        let wref = WRef::new(result);
+        Ok(wref)
+    }
+}
+
+/// Returns the (inDate, outDate) of each night between `in_date` and `out_date`.
+fn nights(in_date: &str, out_date: &str) -> Result<std::vec::Vec<(String, String)>> {
+    let mut date = NaiveDate::parse_from_str(in_date, DATE_FORMAT)?;
+    let out_date = NaiveDate::parse_from_str(out_date, DATE_FORMAT)?;
+    let mut nights = std::vec::Vec::new();
+    while date < out_date {
+        let next = date + Duration::days(1);
+        nights.push((
+            date.format(DATE_FORMAT).to_string(),
+            next.format(DATE_FORMAT).to_string(),
+        ));
+        date = next;
+    }
+    Ok(nights)
+}
+
+impl ReservationService {
+    async fn make_reservation_internal(
+        &self,
+        request: RRef<ReservationRequest>,
+    ) -> Result<ReservationResult> {
+        let hotel_id = request
+            .hotel_id
+            .first()
+            .ok_or(anyhow!("hotelId not found in request"))?
+            .as_str();
+        let nights = nights(request.in_date.as_str(), request.out_date.as_str())?;
+
+        // Check the availability of all nights before reserving any of them.
+        let capacity = self.get_capacity(hotel_id).await?;
+        let mut reserved = std::vec::Vec::with_capacity(nights.len());
+        for (in_date, out_date) in nights.iter() {
+            let count = self.get_reserved(hotel_id, in_date, out_date).await?;
+            if count + request.room_number > capacity {
+                return Ok(ReservationResult {
+                    hotel_id: Vec::new(),
+                });
+            }
+            reserved.push(count);
+        }
+
+        let collection = self.db_handle.collection::<db::Reservation>("reservation");
+        for ((in_date, out_date), count) in nights.into_iter().zip(reserved) {
+            let memc_key = format!("{}_{}_{}", hotel_id, in_date, out_date);
+            self.memc_client
+                .set(&memc_key, &(count + request.room_number).to_string(), 0)
+                .await?;
+            let reservation = db::Reservation {
+                hotel_id: hotel_id.to_string(),
+                customer_name: request.customer_name.as_str().to_string(),
+                in_date,
+                out_date,
+                number: request.room_number,
+            };
+            collection.insert_one(reservation, None).await?;
+        }
+
+        let mut hotel_ids = Vec::with_capacity(1);
+        hotel_ids.push(hotel_id.into());
+        Ok(ReservationResult {
+            hotel_id: hotel_ids,
+        })
+    }
+
+    async fn check_availability_internal(
+        &self,
+        request: RRef<ReservationRequest>,
+    ) -> Result<ReservationResult> {
+        let nights = nights(request.in_date.as_str(), request.out_date.as_str())?;
+
+        let mut hotel_ids = Vec::new();
+        for hotel_id in request.hotel_id.iter() {
+            let capacity = self.get_capacity(hotel_id.as_str()).await?;
+            let mut available = true;
+            for (in_date, out_date) in nights.iter() {
+                let count = self
+                    .get_reserved(hotel_id.as_str(), in_date, out_date)
+                    .await?;
+                if count + request.room_number > capacity {
+                    available = false;
+                    break;
+                }
+            }
+            if available {
+                hotel_ids.push(hotel_id.as_str().into());
+            }
+        }
+
+        Ok(ReservationResult {
+            hotel_id: hotel_ids,
+        })
+    }
+
+    /// Returns the number of rooms of a hotel.
+    async fn get_capacity(&self, hotel_id: &str) -> Result<i32> {
+        let memc_key = format!("{}_cap", hotel_id);
+        let item = self.memc_client.get(&memc_key).await?;
+        if let Some(item) = item {
+            log::trace!("memc hit, key = {}", memc_key);
+            return Ok(item.parse()?);
+        }
+
+        log::trace!("memc miss, key = {}", memc_key);
+        let collection = self.db_handle.collection::<db::Number>("number");
+        let number = collection
+            .find_one(doc! { "hotelId": hotel_id }, None)
+            .await?;
+        if let Some(number) = number {
+            self.memc_client
+                .set(&memc_key, &number.number_of_room.to_string(), 0)
+                .await?;
+            Ok(number.number_of_room)
+        } else {
+            bail!("hotel {} not found", hotel_id);
+        }
+    }
+
+    /// Returns the number of rooms of a hotel reserved for the night starting on `in_date`.
+    async fn get_reserved(&self, hotel_id: &str, in_date: &str, out_date: &str) -> Result<i32> {
+        let memc_key = format!("{}_{}_{}", hotel_id, in_date, out_date);
+        let item = self.memc_client.get(&memc_key).await?;
+        if let Some(item) = item {
+            log::trace!("memc hit, key = {}", memc_key);
+            return Ok(item.parse()?);
+        }
+
+        log::trace!("memc miss, key = {}", memc_key);
+        let collection = self.db_handle.collection::<db::Reservation>("reservation");
+        let mut reservations = collection
+            .find(
+                doc! { "hotelId": hotel_id, "inDate": in_date, "outDate": out_date },
+                None,
+            )
+            .await?;
+        let mut count = 0;
+        while let Some(reservation) = reservations.next().await {
+            count += reservation?.number;
+        }
+        self.memc_client
+            .set(&memc_key, &count.to_string(), 0)
+            .await?;
+        Ok(count)
+    }
+}
+
+impl ReservationService {
+    pub fn new(db: Database, memc: MemcacheClient, log_path: Option<PathBuf>) -> Self {
+        let mut tracer = Tracer::new();
+        tracer.new_proc_entry("make_reservation");
+        tracer.new_proc_entry("check_availability");
+        ReservationService {
+            memc_client: memc,
+            db_handle: db,
+            log_path,
+            tracer: RefCell::new(tracer),
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
 
+use mrpc::stub::PushbackPolicy;
 use structopt::StructOpt;
 
 #[path = "../config.rs"]
@@ -9,8 +10,6 @@ pub mod config;
 #[path = "../logging.rs"]
 pub mod logging;
 pub mod server;
-#[path = "../tracer.rs"]
-pub mod tracer;
 
 use config::Config;
 use server::hotel_microservices::geo::geo_client::GeoClient;
@@ -36,6 +35,9 @@ pub struct Args {
     pub config: Option<PathBuf>,
     #[structopt(long)]
     pub log_path: Option<PathBuf>,
+    /// Ask the clients to slow down when more requests than this are being handled.
+    #[structopt(long)]
+    pub pushback_concurrency: Option<usize>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -50,7 +52,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         args.geo_port = config.geo_port;
         args.rate_addr = config.rate_addr;
         args.rate_port = config.rate_port;
-        args.log_path = Some(config.log_path.join("search.csv"));
+        args.log_path = Some(config.log_path.join("search.json"));
     }
     eprintln!("args: {:?}", args);
     logging::init_env_log("RUST_LOG", "info");
@@ -62,7 +64,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let service = SearchService::new(geo_client, rate_client, args.log_path);
     let signal = async_ctrlc::CtrlC::new()?;
+    let mut pushback = PushbackPolicy::new();
+    if let Some(concurrency) = args.pushback_concurrency {
+        pushback = pushback.concurrency(concurrency);
+    }
     mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
+        .set_pushback(pushback)
         .add_service(SearchServer::new(service))
         .serve_with_graceful_shutdown(signal)
         .await?;
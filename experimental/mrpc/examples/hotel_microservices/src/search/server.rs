@@ -2,14 +2,14 @@ use std::cell::RefCell;
 use std::path::PathBuf;
 use std::task::Poll;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use futures::poll;
 use minstant::Instant;
 
 use mrpc::alloc::Vec;
+use mrpc::stub::RequestContext;
 use mrpc::{RRef, WRef};
-
-use super::tracer::Tracer;
+use phoenix_bench::Tracer;
 
 pub mod hotel_microservices {
     pub mod geo {
@@ -48,14 +48,14 @@ unsafe impl Sync for SearchService {}
 
 impl Drop for SearchService {
     fn drop(&mut self) {
-        let mut tracer = self.tracer.borrow_mut();
+        let tracer = self.tracer.borrow();
         if let Some(path) = &self.log_path {
             if let Some(parent) = path.parent() {
                 if let Err(err) = std::fs::create_dir_all(parent) {
                     log::error!("Error create logging dir: {}", err);
                 }
             }
-            if let Err(err) = tracer.to_csv(path) {
+            if let Err(err) = tracer.write_json(path) {
                 log::error!("Error writting logs: {}", err);
             }
         }
@@ -68,17 +68,44 @@ impl Search for SearchService {
         &self,
         request: RRef<SearchRequest>,
     ) -> Result<WRef<SearchResult>, mrpc::Status> {
-        let result = self
-            .nearby_internal(request)
-            .await
-            .map_err(|err| mrpc::Status::internal(err.to_string()))?;
+        let ctx = request.context().cloned();
+        if let Some(ctx) = &ctx {
+            log::debug!(
+                "Search Nearby from peer {:?}, conn {:?}",
+                ctx.peer_addr(),
+                ctx.conn_handle()
+            );
+        }
+        let result =
+            self.nearby_internal(request, ctx.as_ref())
+                .await
+                .map_err(|err| match &ctx {
+                    Some(ctx) if ctx.is_expired() => {
+                        mrpc::Status::deadline_exceeded(err.to_string())
+                    }
+                    Some(ctx) if ctx.cancellation_token().is_cancelled() => {
+                        mrpc::Status::cancelled(err.to_string())
+                    }
+                    _ => mrpc::Status::internal(err.to_string()),
+                })?;
+        if let Some(ctx) = &ctx {
+            log::debug!(
+                "Search Nearby from peer {:?} finished in {:?}",
+                ctx.peer_addr(),
+                ctx.arrival().elapsed()
+            );
+        }
         let wref = WRef::new(result);
         Ok(wref)
     }
 }
 
 impl SearchService {
-    async fn nearby_internal(&self, request: RRef<SearchRequest>) -> Result<SearchResult> {
+    async fn nearby_internal(
+        &self,
+        request: RRef<SearchRequest>,
+        ctx: Option<&RequestContext>,
+    ) -> Result<SearchResult> {
         log::trace!("in Search Nearby");
 
         log::trace!("nearby lat = {:.4}", request.lat);
@@ -94,7 +121,7 @@ impl SearchService {
             let result = poll!(&mut resp_fut);
             match result {
                 Poll::Ready(resp) => break resp,
-                Poll::Pending => {}
+                Poll::Pending => Self::check_abort(ctx)?,
             }
         }?;
         self.tracer
@@ -114,7 +141,7 @@ impl SearchService {
             let result = poll!(&mut resp_fut);
             match result {
                 Poll::Ready(resp) => break resp,
-                Poll::Pending => {}
+                Poll::Pending => Self::check_abort(ctx)?,
             }
         }?;
         self.tracer
@@ -129,6 +156,16 @@ impl SearchService {
         let result = SearchResult { hotel_ids };
         Ok(result)
     }
+
+    #[inline]
+    fn check_abort(ctx: Option<&RequestContext>) -> Result<()> {
+        if let Some(ctx) = ctx {
+            if ctx.should_abort() {
+                bail!("Search Nearby aborted, peer {:?}", ctx.peer_addr());
+            }
+        }
+        Ok(())
+    }
 }
 
 impl SearchService {
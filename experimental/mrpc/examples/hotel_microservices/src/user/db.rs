@@ -0,0 +1,49 @@
+use mongodb::bson::doc;
+use mongodb::error::Result;
+use mongodb::IndexModel;
+use mongodb::{Client, Database};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    /// The SHA-256 digest of the password in hex.
+    pub password: String,
+}
+
+/// Returns the SHA-256 digest of `password` in hex.
+pub fn hash_password(password: &str) -> String {
+    format!("{:x}", Sha256::digest(password.as_bytes()))
+}
+
+pub async fn initialize_database(uri: impl AsRef<str>) -> Result<Database> {
+    let client = Client::with_uri_str(uri).await?;
+
+    log::info!("New session successful...");
+
+    log::info!("Generating test data...");
+    let db = client.database("user-db");
+    let collections = db.collection::<User>("user");
+
+    // The users are Cornell_0 to Cornell_500, whose password is the suffix repeated 10 times.
+    for i in 0..=500 {
+        let suffix = i.to_string();
+        let username = format!("Cornell_{}", suffix);
+        let count = collections
+            .count_documents(doc! { "username": username.as_str() }, None)
+            .await?;
+        if count == 0 {
+            let user = User {
+                username,
+                password: hash_password(&suffix.repeat(10)),
+            };
+            collections.insert_one(user, None).await?;
+        }
+    }
+
+    let index = IndexModel::builder().keys(doc! { "username": 1 }).build();
+    collections.create_index(index, None).await?;
+
+    Ok(db)
+}
@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use futures::StreamExt;
+use minstant::Instant;
+use mongodb::Database;
+
+use mrpc::{RRef, WRef};
+use phoenix_bench::Tracer;
+
+use super::db;
+
+pub mod hotel_microservices {
+    pub mod user {
+        // The string specified here must match the proto package name
+        mrpc::include_proto!("user");
+    }
+}
+
+use hotel_microservices::user::user_server::User;
+use hotel_microservices::user::{Request as UserRequest, Result as UserResult};
+
+pub struct UserService {
+    // username -> password digest
+    users: HashMap<String, String>,
+    log_path: Option<PathBuf>,
+    tracer: RefCell<Tracer>,
+}
+
+// SAFETY: This is unsafe
+unsafe impl Send for UserService {}
+unsafe impl Sync for UserService {}
+
+impl Drop for UserService {
+    fn drop(&mut self) {
+        let tracer = self.tracer.borrow();
+        if let Some(path) = &self.log_path {
+            if let Some(parent) = path.parent() {
+                if let Err(err) = std::fs::create_dir_all(parent) {
+                    log::error!("Error create logging dir: {}", err);
+                }
+            }
+            if let Err(err) = tracer.write_json(path) {
+                log::error!("Error writting logs: {}", err);
+            }
+        }
+    }
+}
+
+#[mrpc::async_trait]
+impl User for UserService {
+    async fn check_user(
+        &self,
+        request: RRef<UserRequest>,
+    ) -> Result<WRef<UserResult>, mrpc::Status> {
+        let start = Instant::now();
+        let result = self.check_user_internal(request);
+        self.tracer
+            .borrow_mut()
+            .record_proc("user", start.elapsed())
+            .map_err(|err| mrpc::Status::internal(err.to_string()))?;
+
+        let wref = WRef::new(result);
+        Ok(wref)
+    }
+}
+
+impl UserService {
+    fn check_user_internal(&self, request: RRef<UserRequest>) -> UserResult {
+        let digest = db::hash_password(request.password.as_str());
+        let correct = self
+            .users
+            .get(request.username.as_str())
+            .map_or(false, |password| *password == digest);
+        UserResult { correct }
+    }
+}
+
+impl UserService {
+    /// Loads all users from the database.
+    pub async fn new(db: &Database, log_path: Option<PathBuf>) -> Result<Self> {
+        let collection = db.collection::<db::User>("user");
+        let mut cursor = collection.find(None, None).await?;
+        let mut users = HashMap::new();
+        while let Some(user) = cursor.next().await {
+            let user = user?;
+            users.insert(user.username, user.password);
+        }
+
+        let mut tracer = Tracer::new();
+        tracer.new_proc_entry("user");
+        Ok(UserService {
+            users,
+            log_path,
+            tracer: RefCell::new(tracer),
+        })
+    }
+}
@@ -34,9 +34,6 @@ extern "C" fn handle_sigint(sig: i32) {
 
 #[derive(Debug, StructOpt)]
 struct Opt {
-    /// Process ID
-    #[structopt(long, default_value = "0")]
-    process_id: usize,
     /// Test milliseconds
     #[structopt(long)]
     test_ms: u64,
@@ -63,9 +60,6 @@ struct Opt {
     /// Server port
     #[structopt(long)]
     server_port: u16,
-    /// Number of server foreground threads
-    #[structopt(long)]
-    num_server_fg_threads: usize,
 }
 
 pub mod masstree_analytics {
@@ -226,13 +220,11 @@ fn run_client_thread(
         println!("{}", Stats::header_str());
     }
 
-    // TODO(cjr): eRPC sets up a dedicate connection to a server thread from each
-    // client to balance the load. This is not a common operation in other RPC
-    // frameworks (establishing connections to a particular server thread).
+    // eRPC sets up a dedicate connection to a server thread from each client to balance the load.
+    // Here the server threads share the port, and the connections are spread among them.
     let stub = MasstreeAnalyticsClient::connect((
         opt.server_addr[tid % opt.server_addr.len()].as_str(),
-        opt.server_port
-            + ((opt.process_id * opt.num_client_threads + tid) % opt.num_server_fg_threads) as u16,
+        opt.server_port,
     ))?;
     log::info!("main: Thread {}: Connected. Sending requests.", tid);
 
@@ -158,7 +158,7 @@ fn run_server(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
                     let service = MasstreeAnalyticsService::new(mti, tid, ti_vec);
                     mrpc::stub::LocalServer::bind((
                         opt.server_addr.as_deref().unwrap_or("0.0.0.0"),
-                        opt.server_port + tid as u16,
+                        opt.server_port,
                     ))?
                     .add_service(MasstreeAnalyticsServer::new(service))
                     .serve()
@@ -82,10 +82,6 @@ pub struct Args {
     /// Number of client threads. Each client thread is mapped to one server threads.
     #[structopt(long, default_value = "1")]
     pub num_client_threads: usize,
-
-    /// Number of server threads.
-    #[structopt(long, default_value = "1")]
-    pub num_server_threads: usize,
 }
 
 // mod bench_app;
@@ -233,10 +229,7 @@ fn run_client_thread(
         }
     }
 
-    let client = GreeterClient::connect((
-        args.ip.as_str(),
-        args.port + (tid % args.num_server_threads) as u16,
-    ))?;
+    let client = GreeterClient::connect((args.ip.as_str(), args.port))?;
     eprintln!("connection setup for thread {tid}");
 
     smol::block_on(async {
@@ -280,8 +273,6 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
     let args = Args::from_args();
     eprintln!("args: {:?}", args);
 
-    assert!(args.num_client_threads % args.num_server_threads == 0);
-
     let _guard = init_tokio_tracing(&args.log_level, &args.log_dir);
 
     std::thread::scope(|s| {
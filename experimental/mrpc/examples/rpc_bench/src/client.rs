@@ -1,16 +1,10 @@
-use std::future::Future;
 use std::path::PathBuf;
-use std::time::Duration;
 
-use futures::select;
-use futures::stream::FuturesUnordered;
-use futures::stream::StreamExt;
-use hdrhistogram::Histogram;
-use minstant::Instant;
 use structopt::StructOpt;
 
 use mrpc::stub::TransportType;
 use mrpc::WRef;
+use phoenix_bench::{Arrival, Bench, BenchConfig, BenchResult};
 
 pub mod rpc_hello {
     // The string specified here must match the proto package name
@@ -18,7 +12,7 @@ pub mod rpc_hello {
     // include!("../../../mrpc/src/codegen.rs");
 }
 use rpc_hello::greeter_client::GreeterClient;
-use rpc_hello::{HelloReply, HelloRequest};
+use rpc_hello::HelloRequest;
 
 #[derive(StructOpt, Debug)]
 #[structopt(about = "mRPC benchmark client")]
@@ -76,178 +70,86 @@ pub struct Args {
     #[structopt(long, default_value = "1")]
     pub num_client_threads: usize,
 
-    /// Number of server threads.
-    #[structopt(long, default_value = "1")]
-    pub num_server_threads: usize,
-
     /// Which transport to use, rdma or tcp
     #[structopt(long, default_value = "rdma")]
     pub transport: TransportType,
+
+    /// Send requests at this rate (requests per second) in an open loop rather than keeping
+    /// `concurrency` requests outstanding.
+    #[structopt(long)]
+    pub rate: Option<f64>,
+
+    /// Use Poisson arrivals in the open loop.
+    #[structopt(long)]
+    pub poisson: bool,
+
+    /// Write the merged result of all threads as JSON to this file.
+    #[structopt(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// The largest message copied into the message header instead of sent without a copy, in
+    /// bytes. The backend default if not set.
+    #[structopt(long)]
+    pub eager_copy_threshold: Option<usize>,
 }
 
 // mod bench_app;
 // include!("./bench_app.rs");
 
-#[derive(Debug)]
-struct Call {
-    ts: Instant,
-    req_size: usize,
-    result: Result<mrpc::RRef<HelloReply>, mrpc::Status>,
-}
-
-fn make_rpc_call<'c>(
-    client: &'c GreeterClient,
-    workload: &'c Workload,
-    scnt: usize,
-) -> impl Future<Output = Call> + 'c {
-    let ts = Instant::now();
-    let (req, req_size) = workload.next_request(scnt);
-    let fut = client.say_hello(req);
-    async move {
-        Call {
-            ts,
-            req_size,
-            result: fut.await,
+impl Args {
+    fn bench_config(&self) -> BenchConfig {
+        BenchConfig {
+            concurrency: self.concurrency,
+            warmup: self.warmup,
+            total_iters: self.total_iters,
+            duration_secs: self.duration,
+            report_interval_secs: self.interval,
+            rate: self.rate,
+            arrival: if self.poisson {
+                Arrival::Poisson
+            } else {
+                Arrival::Uniform
+            },
+            ..Default::default()
         }
     }
 }
 
-#[allow(unused)]
 async fn run_bench(
     args: &Args,
     client: &GreeterClient,
     workload: &Workload,
     tid: usize,
-) -> Result<(Duration, usize, usize, Histogram<u64>), mrpc::Status> {
-    macro_rules! my_print {
-        ($($arg:tt)*) => {
-            if args.log_level == "info" {
-                tracing::info!($($arg)*);
-            } else {
-                println!($($arg)*);
-            }
+) -> BenchResult {
+    let log_latency = args.log_latency;
+    let use_tracing = args.log_level == "info";
+    let mut bench = Bench::new(args.bench_config()).on_interval(move |stats| {
+        let msg = if log_latency {
+            format!(
+                "Thread {}, {} rps, {} Gb/s, p50: {:?}, p99: {:?}",
+                tid, stats.rps, stats.gbps, stats.p50, stats.p99,
+            )
+        } else {
+            format!("Thread {}, {} rps, {} Gb/s", tid, stats.rps, stats.gbps)
+        };
+        if use_tracing {
+            tracing::info!("{}", msg);
+        } else {
+            println!("{}", msg);
         }
-    }
-
-    let mut hist = hdrhistogram::Histogram::<u64>::new_with_max(60_000_000_000, 5).unwrap();
-
-    let mut reply_futures = FuturesUnordered::new();
+    });
 
-    let (total_iters, timeout) = if let Some(dura) = args.duration {
-        (usize::MAX / 2, Duration::from_secs_f64(dura))
-    } else {
-        (args.total_iters, Duration::from_millis(u64::MAX))
+    let make_call = |scnt| {
+        let (req, req_size) = workload.next_request(scnt);
+        let fut = client.say_hello(req);
+        async move { fut.await.map(|_| req_size) }
     };
 
-    // report the rps every several milliseconds
-    let tput_interval = args.interval.map(Duration::from_secs_f64);
-
-    // start sending
-    let mut last_ts = Instant::now();
-    let start = Instant::now();
-
-    let mut warmup_end = Instant::now();
-    let mut nbytes = 0;
-    let mut last_nbytes = 0;
-
-    let mut scnt = 0;
-    let mut rcnt = 0;
-    let mut last_rcnt = 0;
-
-    while scnt < args.concurrency && scnt < total_iters + args.warmup {
-        let fut = make_rpc_call(client, workload, scnt);
-        reply_futures.push(fut);
-        scnt += 1;
-    }
-
-    loop {
-        select! {
-            resp = reply_futures.next() => {
-                if rcnt >= total_iters + args.warmup || start.elapsed() > timeout {
-                    break;
-                }
-
-                let Call { ts, req_size, result: resp } = resp.unwrap();
-                if let Err(status) = resp {
-                    tracing::warn!("failed request with: {}", status);
-                }
-
-                if rcnt >= args.warmup {
-                    let dura = ts.elapsed();
-                    let _ = hist.record(dura.as_nanos() as u64);
-                }
-
-                nbytes += req_size;
-                rcnt += 1;
-                if rcnt == args.warmup {
-                    warmup_end = Instant::now();
-                }
-
-                if scnt < total_iters + args.warmup {
-                    let fut = make_rpc_call(client, workload, scnt);
-                    reply_futures.push(fut);
-                    scnt += 1;
-                }
-
-                let last_dura = last_ts.elapsed();
-                if tput_interval.is_some() && last_dura > tput_interval.unwrap() {
-                    let rps = (rcnt - last_rcnt) as f64 / last_dura.as_secs_f64();
-                    let bw = 8e-9 * (nbytes - last_nbytes) as f64 / last_dura.as_secs_f64();
-                    if rcnt>args.warmup{
-                        if args.log_latency {
-                            my_print!(
-                                "Thread {}, {} rps, {} Gb/s, p95: {:?}, p99: {:?}",
-                                tid,
-                                rps,
-                                bw,
-                                Duration::from_nanos(hist.value_at_percentile(95.0)),
-                                Duration::from_nanos(hist.value_at_percentile(99.0)),
-                            );
-                            hist.clear();
-                        } else {
-                            my_print!("Thread {}, {} rps, {} Gb/s", tid, rps, bw);
-                        }
-                    }
-                    last_ts = Instant::now();
-                    last_rcnt = rcnt;
-                    last_nbytes = nbytes;
-                }
-            }
-            complete => break,
-            default => {
-                // no futures is ready
-                if rcnt >= total_iters + args.warmup || start.elapsed() > timeout {
-                    break;
-                }
-                let last_dura = last_ts.elapsed();
-                if tput_interval.is_some() && last_dura > tput_interval.unwrap() {
-                    let rps = (rcnt - last_rcnt) as f64 / last_dura.as_secs_f64();
-                    let bw = 8e-9 * (nbytes - last_nbytes) as f64 / last_dura.as_secs_f64();
-                    if rcnt>args.warmup{
-                        if args.log_latency {
-                            my_print!(
-                                "Thread {}, {} rps, {} Gb/s, p95: {:?}, p99: {:?}",
-                                tid,
-                                rps,
-                                bw,
-                                Duration::from_nanos(hist.value_at_percentile(95.0)),
-                                Duration::from_nanos(hist.value_at_percentile(99.0)),
-                            );
-                            hist.clear();
-                        } else {
-                            my_print!("Thread {}, {} rps, {} Gb/s", tid, rps, bw);
-                        }
-                    }
-                    last_ts = Instant::now();
-                    last_rcnt = rcnt;
-                    last_nbytes = nbytes;
-                }
-            }
-        }
+    if args.rate.is_some() {
+        bench.open_loop(make_call).await
+    } else {
+        bench.closed_loop(make_call).await
     }
-
-    let dura = warmup_end.elapsed();
-    Ok((dura, nbytes, rcnt, hist))
 }
 
 struct Workload {
@@ -277,7 +179,7 @@ impl Workload {
     }
 }
 
-fn run_client_thread(tid: usize, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+fn run_client_thread(tid: usize, args: &Args) -> Result<BenchResult, Box<dyn std::error::Error>> {
     macro_rules! my_print {
         ($($arg:tt)*) => {
             if args.log_level == "info" {
@@ -298,60 +200,47 @@ fn run_client_thread(tid: usize, args: &Args) -> Result<(), Box<dyn std::error::
 
     // choose a server
     let host = args.connects[tid % args.connects.len()].as_str();
-    let port = args.port + (tid % args.num_server_threads) as u16;
-
-    let client = GreeterClient::connect((host, port))?;
+    let client = GreeterClient::connect((host, args.port))?;
+    if args.eager_copy_threshold.is_some() {
+        client.set_eager_copy_threshold(args.eager_copy_threshold)?;
+    }
     eprintln!("connection setup for thread {tid}");
 
-    smol::block_on(async {
+    let result = smol::block_on(async {
         // initialize workload
         let workload = Workload::new(args);
+        run_bench(args, &client, &workload, tid).await
+    });
 
-        let (dura, total_bytes, rcnt, hist) = run_bench(args, &client, &workload, tid).await?;
-
-        my_print!(
-            "Thread {tid}, duration: {:?}, bandwidth: {:?} Gb/s, rate: {:.5} Mrps",
-            dura,
-            8e-9 * (total_bytes - args.warmup * args.req_size) as f64 / dura.as_secs_f64(),
-            1e-6 * (rcnt - args.warmup) as f64 / dura.as_secs_f64(),
-        );
-        // print latencies
-        my_print!(
-            "Thread {tid}, duration: {:?}, avg: {:?}, min: {:?}, median: {:?}, p95: {:?}, p99: {:?}, max: {:?}",
-            dura,
-            Duration::from_nanos(hist.mean() as u64),
-            Duration::from_nanos(hist.min()),
-            Duration::from_nanos(hist.value_at_percentile(50.0)),
-            Duration::from_nanos(hist.value_at_percentile(95.0)),
-            Duration::from_nanos(hist.value_at_percentile(99.0)),
-            Duration::from_nanos(hist.max()),
-        );
-
-        Result::<(), mrpc::Status>::Ok(())
-    })?;
-
-    Ok(())
+    my_print!("Thread {tid}, {}", result);
+    Ok(result)
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::from_args();
     eprintln!("args: {:?}", args);
 
-    assert!(args.num_client_threads % args.num_server_threads == 0);
-
     let _guard = init_tokio_tracing(&args.log_level, &args.log_dir);
 
-    std::thread::scope(|s| {
+    let results = std::thread::scope(|s| {
         let mut handles = Vec::new();
         for tid in 1..args.num_client_threads {
             let args = &args;
-            handles.push(s.spawn(move || {
-                run_client_thread(tid, args).unwrap();
-            }));
+            handles.push(s.spawn(move || run_client_thread(tid, args).unwrap()));
         }
-        run_client_thread(0, &args).unwrap();
+        let mut results = vec![run_client_thread(0, &args).unwrap()];
+        results.extend(handles.into_iter().map(|h| h.join().unwrap()));
+        results
     });
 
+    let result = BenchResult::merge(&results);
+    if results.len() > 1 {
+        println!("Total, {}", result);
+    }
+    if let Some(path) = &args.output {
+        result.write_json(path)?;
+    }
+
     Ok(())
 }
 
@@ -84,7 +84,7 @@ fn run_server(tid: usize, args: Args) -> Result<(), mrpc::Error> {
             replies.push(msg);
         }
 
-        mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port + tid as u16))?
+        mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
             .add_service(GreeterServer::new(MyGreeter {
                 replies,
                 count: AtomicUsize::new(0),
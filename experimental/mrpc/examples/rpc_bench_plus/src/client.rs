@@ -83,10 +83,6 @@ pub struct Args {
     #[structopt(long, default_value = "1")]
     pub num_client_threads: usize,
 
-    /// Number of server threads.
-    #[structopt(long, default_value = "1")]
-    pub num_server_threads: usize,
-
     /// overwrite with hardcoded test case
     #[structopt(long)]
     pub overwrite: Option<ModelType>,
@@ -286,10 +282,7 @@ fn run_client_thread(
     tid: usize,
     args_o: &Args,
 ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
-    let client = GreeterClient::connect((
-        args_o.ip.as_str(),
-        args_o.port + (tid % args_o.num_server_threads) as u16,
-    ))?;
+    let client = GreeterClient::connect((args_o.ip.as_str(), args_o.port))?;
     eprintln!("connection setup for thread {tid}");
     let mut args = args_o.clone();
 
@@ -361,8 +354,6 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
     let args = Args::from_args();
     eprintln!("args: {:?}", args);
 
-    assert!(args.num_client_threads % args.num_server_threads == 0);
-
     let _guard = init_tokio_tracing(&args.log_level, &args.log_dir);
 
     std::thread::scope(|s| {
@@ -64,7 +64,7 @@ impl Greeter for MyGreeter {
     }
 }
 
-fn run_server(tid: usize, args: Args) -> Result<(), mrpc::Error> {
+fn run_server(_tid: usize, args: Args) -> Result<(), mrpc::Error> {
     smol::block_on(async {
         let mut replies = Vec::new();
         for _ in 0..args.provision_count {
@@ -74,7 +74,7 @@ fn run_server(tid: usize, args: Args) -> Result<(), mrpc::Error> {
             replies.push(msg);
         }
 
-        mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port + tid as u16))?
+        mrpc::stub::LocalServer::bind(format!("0.0.0.0:{}", args.port))?
             .add_service(GreeterServer::new(MyGreeter {
                 replies,
                 count: AtomicUsize::new(0),
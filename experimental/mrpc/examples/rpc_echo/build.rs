@@ -1,6 +1,8 @@
 const PROTO: &str = "../proto/rpc_hello/rpc_hello.proto";
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed={PROTO}");
-    mrpc_build::compile_protos(PROTO)?;
+    mrpc_build::configure()
+        .build_blocking_client("Greeter")
+        .compile(&[PROTO], &["../proto/rpc_hello"])?;
     Ok(())
 }
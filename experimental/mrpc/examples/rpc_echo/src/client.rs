@@ -4,7 +4,7 @@ pub mod rpc_hello {
     // include!("../../../mrpc/src/codegen.rs");
 }
 
-use rpc_hello::greeter_client::GreeterClient;
+use rpc_hello::greeter_client::blocking::GreeterClient;
 use rpc_hello::HelloRequest;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -12,7 +12,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let req = HelloRequest {
         name: "mRPC".into(),
     };
-    let reply = smol::block_on(client.say_hello(req))?;
+    let reply = client.say_hello(req)?;
     println!("reply: {}", String::from_utf8_lossy(&reply.message));
     Ok(())
 }
@@ -0,0 +1,35 @@
+//! The echo client on the multi-thread tokio runtime. The client lives on the thread of a
+//! dispatcher, and the tasks of the runtime send their calls through it.
+pub mod rpc_hello {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("rpc_hello");
+}
+
+use mrpc::stub::Dispatcher;
+use rpc_hello::greeter_client::GreeterClient;
+use rpc_hello::HelloRequest;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let greeter = Dispatcher::spawn(|| GreeterClient::connect("localhost:5000"))?;
+
+    let mut tasks = Vec::new();
+    for i in 0..4 {
+        let greeter = greeter.clone();
+        tasks.push(tokio::spawn(async move {
+            greeter
+                .call(move |client| async move {
+                    let req = HelloRequest {
+                        name: format!("mRPC {}", i).as_str().into(),
+                    };
+                    let reply = client.say_hello(req).await?;
+                    Ok::<_, mrpc::Status>(String::from_utf8_lossy(&reply.message).into_owned())
+                })
+                .await
+        }));
+    }
+    for task in tasks {
+        println!("reply: {}", task.await???);
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! Serves the Greeter with a concurrency budget, see `LocalServer::add_service_bounded`.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use mrpc::{RRef, WRef};
+
+pub mod rpc_hello {
+    mrpc::include_proto!("rpc_hello");
+}
+
+use rpc_hello::greeter_client::blocking::GreeterClient;
+use rpc_hello::greeter_server::{Greeter, GreeterServer};
+use rpc_hello::{HelloReply, HelloRequest};
+
+const ADDR: &str = "127.0.0.1:5137";
+const MAX_CONCURRENCY: usize = 2;
+const CLIENTS: usize = 6;
+// more than the receive buffers of a connection, so the requests must be reclaimed
+const CALLS: usize = 64;
+
+/// Answers after a while, and records how many requests it handles at a time.
+#[derive(Default)]
+struct SlowGreeter {
+    running: Arc<AtomicUsize>,
+    max_running: Arc<AtomicUsize>,
+}
+
+#[mrpc::async_trait]
+impl Greeter for SlowGreeter {
+    async fn say_hello(
+        &self,
+        request: RRef<HelloRequest>,
+    ) -> Result<WRef<HelloReply>, mrpc::Status> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        smol::Timer::after(Duration::from_millis(1)).await;
+        let message = format!("Hello {}!", String::from_utf8_lossy(&request.name));
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        Ok(WRef::new(HelloReply {
+            message: message.as_bytes().into(),
+        }))
+    }
+}
+
+#[test]
+fn bounded_concurrency() {
+    phoenix_testing::TestDaemon::start();
+
+    let greeter = SlowGreeter::default();
+    let max_running = Arc::clone(&greeter.max_running);
+    let (ready_tx, ready_rx) = mpsc::channel();
+    // the server runs until the test process exits
+    thread::spawn(move || {
+        smol::block_on(async {
+            let mut server = mrpc::stub::LocalServer::bind(ADDR)?;
+            server.add_service_bounded(GreeterServer::new(greeter), MAX_CONCURRENCY);
+            ready_tx.send(()).unwrap();
+            server.serve().await
+        })
+    });
+    ready_rx.recv().expect("the server failed to bind");
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|i| {
+            thread::spawn(move || {
+                let client = GreeterClient::connect(ADDR).unwrap();
+                for j in 0..CALLS {
+                    let name = format!("client {} call {}", i, j);
+                    let reply = client
+                        .say_hello(HelloRequest {
+                            name: name.as_bytes().into(),
+                        })
+                        .unwrap();
+                    assert_eq!(
+                        String::from_utf8_lossy(&reply.message),
+                        format!("Hello {}!", name)
+                    );
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    let max_running = max_running.load(Ordering::SeqCst);
+    assert!(
+        (1..=MAX_CONCURRENCY).contains(&max_running),
+        "{} requests were handled at a time",
+        max_running
+    );
+}
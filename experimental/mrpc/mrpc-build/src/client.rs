@@ -1,31 +1,51 @@
+use std::time::Duration;
+
 use proc_macro2::TokenStream;
 
-use crate::attribute::Attributes;
+use crate::attribute::{match_name, Attributes};
 use crate::{
-    generate_doc_comments, get_method_path, get_proto_packages, get_service_path, mrpc_get_func_id,
-    mrpc_get_service_id, naive_snake_case, Method, Service,
+    generate_doc_comments, generate_method_ids, get_method_path, get_proto_packages,
+    get_service_path, mrpc_get_func_id, mrpc_get_service_id, naive_snake_case, Method, Service,
 };
 
 /// Generate service for client.
 ///
 /// This takes some `Service` and will generate a `TokenStream` that contains
 /// a public module with the generated client.
+#[allow(clippy::too_many_arguments)]
 pub fn generate<T: Service>(
     service: &T,
     emit_package: bool,
     proto_path: &str,
     compile_well_known_types: bool,
     attributes: &Attributes,
+    blocking_clients: &[String],
+    oneshot_methods: &[String],
+    cacheable_methods: &[(String, Duration)],
+    compat: bool,
 ) -> TokenStream {
     let service_ident = quote::format_ident!("{}Client", service.name());
     let client_mod = quote::format_ident!("{}_client", naive_snake_case(service.name()));
-    let methods = generate_methods(service, emit_package, proto_path, compile_well_known_types);
 
     let service_doc = generate_doc_comments(service.comment());
 
     let package = if emit_package { service.package() } else { "" };
     let path = get_service_path(package, service);
-    let service_id = mrpc_get_service_id(&path);
+    let methods = generate_methods(
+        service,
+        emit_package,
+        proto_path,
+        compile_well_known_types,
+        oneshot_methods,
+        cacheable_methods,
+        compat,
+    );
+    let service_id = mrpc_get_service_id(&path, service.comment());
+    let method_ids = generate_method_ids(package, service);
+    let caches = service
+        .methods()
+        .iter()
+        .any(|method| cache_ttl(&path, method, cacheable_methods).is_some());
 
     let mod_attributes = attributes.for_mod(package);
     let struct_attributes = attributes.for_struct(&path);
@@ -36,6 +56,112 @@ pub fn generate<T: Service>(
         .into_iter()
         .map(|x| syn::parse_str::<syn::Path>(&x).unwrap());
 
+    let compat_methods = if compat {
+        quote::quote! {
+            /// Sends the requests of the unary calls in the protobuf wire format, see
+            /// [`ClientStub::set_compat`].
+            pub fn set_compat(&self, enable: bool) {
+                self.stub.set_compat(enable)
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
+    let blocking = if blocking_clients.iter().any(|p| match_name(p, &path)) {
+        let blocking_methods = generate_blocking_methods(
+            service,
+            &path,
+            proto_path,
+            compile_well_known_types,
+            oneshot_methods,
+        );
+        let compat_methods = if compat {
+            quote::quote! {
+                /// Sends the requests in the protobuf wire format, see
+                /// [`ClientStub::set_compat`](::mrpc::stub::ClientStub::set_compat).
+                pub fn set_compat(&self, enable: bool) {
+                    self.inner.set_compat(enable)
+                }
+            }
+        } else {
+            TokenStream::new()
+        };
+        let cache_methods = if caches {
+            quote::quote! {
+                /// Returns the counters of the cached replies.
+                pub fn cache_stats(&self) -> ::mrpc::stub::CacheStats {
+                    self.inner.cache_stats()
+                }
+                /// Drops the cached replies.
+                pub fn flush_cache(&self) {
+                    self.inner.flush_cache()
+                }
+            }
+        } else {
+            TokenStream::new()
+        };
+        quote::quote! {
+            /// Generate blocking client implementations.
+            pub mod blocking {
+                #service_doc
+                #(#struct_attributes)*
+                #[derive(Debug)]
+                pub struct #service_ident {
+                    inner: super::#service_ident,
+                }
+
+                impl #service_ident {
+                    pub fn connect<A: std::net::ToSocketAddrs>(dst: A) -> Result<Self, ::mrpc::Error> {
+                        let inner = super::#service_ident::connect(dst)?;
+                        Ok(Self { inner })
+                    }
+                    pub fn multi_connect<A: std::net::ToSocketAddrs>(dsts: impl IntoIterator<Item=A>) -> Result<Self, ::mrpc::Error> {
+                        let inner = super::#service_ident::multi_connect(dsts)?;
+                        Ok(Self { inner })
+                    }
+                    /// Enables automatic reconnection when the connection is lost.
+                    pub fn set_reconnect_policy(&self, policy: ::mrpc::stub::ReconnectPolicy) {
+                        self.inner.set_reconnect_policy(policy)
+                    }
+                    /// Returns the handles of the connections that are alive.
+                    pub fn connections(&self) -> Vec<::mrpc::stub::Handle> {
+                        self.inner.connections()
+                    }
+                    /// Returns the async client.
+                    pub fn into_inner(self) -> super::#service_ident {
+                        self.inner
+                    }
+                    #compat_methods
+                    #cache_methods
+                    #blocking_methods
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
+    let cache_methods = if caches {
+        quote::quote! {
+            /// Sets the most replies of the cacheable methods to keep, see
+            /// [`ClientStub::set_cache_capacity`].
+            pub fn set_cache_capacity(&self, capacity: usize) {
+                self.stub.set_cache_capacity(capacity)
+            }
+            /// Returns the counters of the cached replies.
+            pub fn cache_stats(&self) -> ::mrpc::stub::CacheStats {
+                self.stub.cache_stats()
+            }
+            /// Drops the cached replies, so the calls issued afterwards go to the server.
+            pub fn flush_cache(&self) {
+                self.stub.flush_cache()
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
     quote::quote! {
         /// Generate client implementations.
         #(#mod_attributes)*
@@ -51,7 +177,8 @@ pub fn generate<T: Service>(
 
             impl #service_ident {
                 fn update_protos() -> Result<(), ::mrpc::Error> {
-                    let srcs = [#(#proto_srcs),*].concat();
+                    ::mrpc::stub::ids::register::<Self>()?;
+                    let srcs = <Self as NamedService>::proto_srcs();
                     ::mrpc::stub::update_protos(srcs.as_slice())
                 }
 
@@ -80,22 +207,113 @@ pub fn generate<T: Service>(
                         stub,
                     })
                 }
+                /// Connects from the local address, device or ports set in `options`.
+                pub fn connect_with<A: std::net::ToSocketAddrs>(dst: A, options: ::mrpc::stub::ConnectOptions) -> Result<Self, ::mrpc::Error> {
+                    Self::update_protos()?;
+                    let stub = ClientStub::connect_with(dst, options)?;
+                    Ok(Self {
+                        stub,
+                    })
+                }
+                /// Connects over unreliable datagrams, for small calls that tolerate loss.
+                pub fn connect_datagram<A: std::net::ToSocketAddrs>(dst: A) -> Result<Self, ::mrpc::Error> {
+                    Self::update_protos()?;
+                    let stub = ClientStub::connect_datagram(dst)?;
+                    Ok(Self {
+                        stub,
+                    })
+                }
+                /// Establishes and checks `n` connections to each of `dsts` up front, see
+                /// [`ClientStub::warm_up`].
+                pub fn warm_up<A: std::net::ToSocketAddrs>(dsts: impl IntoIterator<Item=A>, n: usize) -> Result<Vec<::mrpc::stub::WarmUp<Self>>, ::mrpc::Error> {
+                    Self::update_protos()?;
+                    let targets = ClientStub::warm_up(dsts, n)?;
+                    Ok(targets.into_iter().map(|target| target.map(|stub| Self { stub })).collect())
+                }
+                /// Connects to an endpoint of the service registered in the name service of the
+                /// daemons, see [`::mrpc::registry`].
+                pub fn connect_by_name() -> Result<Self, ::mrpc::Error> {
+                    let endpoint = ::mrpc::registry::resolve::<Self>()?;
+                    Self::connect(endpoint.addr())
+                }
+                /// Enables automatic reconnection when the connection is lost.
+                pub fn set_reconnect_policy(&self, policy: ::mrpc::stub::ReconnectPolicy) {
+                    self.stub.set_reconnect_policy(policy)
+                }
+                /// Enables retransmitting the requests that get no reply in time.
+                pub fn set_retry_policy(&self, policy: ::mrpc::stub::RetryPolicy) {
+                    self.stub.set_retry_policy(policy)
+                }
+                /// Sets the largest message copied into the message header instead of sent
+                /// without a copy, see [`ClientStub::set_eager_copy_threshold`].
+                pub fn set_eager_copy_threshold(&self, threshold: Option<usize>) -> Result<(), ::mrpc::Error> {
+                    self.stub.set_eager_copy_threshold(threshold)
+                }
+                /// Returns the handles of the connections that are alive, to broadcast to.
+                pub fn connections(&self) -> Vec<::mrpc::stub::Handle> {
+                    self.stub.connections()
+                }
+                /// Returns whether the server has asked to slow down recently, see
+                /// [`ClientStub::pushed_back`].
+                pub fn pushed_back(&self) -> bool {
+                    self.stub.pushed_back()
+                }
+                #compat_methods
+                #cache_methods
                 #methods
             }
 
             impl NamedService for #service_ident {
                 const SERVICE_ID: u32 = #service_id;
                 const NAME: &'static str = #path;
+                const METHODS: &'static [(&'static str, u32)] = #method_ids;
+
+                fn proto_srcs() -> Vec<&'static str> {
+                    [#(#proto_srcs),*].concat()
+                }
             }
+
+            #blocking
         }
     }
 }
 
+// Returns whether the method is a oneshot call, which must return `google.protobuf.Empty`.
+fn is_oneshot<M: Method>(service_path: &str, method: &M, oneshot_methods: &[String]) -> bool {
+    let path = format!("{}.{}", service_path, method.identifier());
+    if !oneshot_methods.iter().any(|p| match_name(p, &path)) {
+        return false;
+    }
+    assert_eq!(
+        method.response_proto_type(),
+        ".google.protobuf.Empty",
+        "oneshot method {} must return google.protobuf.Empty",
+        path
+    );
+    true
+}
+
+// Returns the TTL of the replies of the method if they are cached.
+fn cache_ttl<M: Method>(
+    service_path: &str,
+    method: &M,
+    cacheable_methods: &[(String, Duration)],
+) -> Option<Duration> {
+    let path = format!("{}.{}", service_path, method.identifier());
+    cacheable_methods
+        .iter()
+        .find(|(p, _)| match_name(p, &path))
+        .map(|(_, ttl)| *ttl)
+}
+
 fn generate_methods<T: Service>(
     service: &T,
     emit_package: bool,
     proto_path: &str,
     compile_well_known_types: bool,
+    oneshot_methods: &[String],
+    cacheable_methods: &[(String, Duration)],
+    compat: bool,
 ) -> TokenStream {
     let mut stream = TokenStream::new();
     let package = if emit_package { service.package() } else { "" };
@@ -103,18 +321,74 @@ fn generate_methods<T: Service>(
     // println!("cargo:warning={}", "================generate_methods================");
     for method in service.methods() {
         let path = get_method_path(package, service, method);
-        let func_id = mrpc_get_func_id(&path);
-        let service_id = mrpc_get_service_id(&get_service_path(package, service));
+        let func_id = mrpc_get_func_id(&path, method.comment());
+        let service_id =
+            mrpc_get_service_id(&get_service_path(package, service), service.comment());
 
         stream.extend(generate_doc_comments(method.comment()));
 
         // mRPC current doesn't not support streaming
         // Generate unary
         let ident = quote::format_ident!("{}", method.name());
+        let broadcast_ident = quote::format_ident!("{}_broadcast", method.name());
 
         let (request, response) =
             method.request_response_name(proto_path, compile_well_known_types);
 
+        if is_oneshot(&get_service_path(package, service), method, oneshot_methods) {
+            let post = if compat {
+                quote::quote!(post_compat)
+            } else {
+                quote::quote!(post)
+            };
+            stream.extend(quote::quote! {
+                /// Posts the request without waiting for a reply. The request is delivered at
+                /// most once.
+                pub fn #ident(
+                    &self,
+                    req: impl ::mrpc::IntoWRef<#request>
+                ) -> Result<(), ::mrpc::Status> {
+                    self.stub.#post(#service_id, #func_id, req.into_wref())
+                }
+            });
+            continue;
+        }
+
+        let service_path = get_service_path(package, service);
+        let unary = match cache_ttl(&service_path, method, cacheable_methods) {
+            Some(ttl) => {
+                let ttl = ttl.as_nanos() as u64;
+                let unary_cached = if compat {
+                    quote::quote!(unary_cached_compat)
+                } else {
+                    quote::quote!(unary_cached)
+                };
+                quote::quote! {
+                    let req = req.into_wref();
+                    // identical requests encode to the same bytes
+                    let key = ::prost::Message::encode_to_vec(&*req);
+                    let ttl = std::time::Duration::from_nanos(#ttl);
+                    self.stub.#unary_cached(#service_id, #func_id, key, ttl, req)
+                }
+            }
+            None if compat => quote::quote! {
+                let call_id = self.stub.initiate_call();
+
+                self.stub.unary_compat(#service_id, #func_id, call_id, req.into_wref())
+            },
+            None => quote::quote! {
+                let call_id = self.stub.initiate_call();
+
+                self.stub.unary(#service_id, #func_id, call_id, req.into_wref())
+            },
+        };
+
+        let broadcast = if compat {
+            quote::quote!(broadcast_compat)
+        } else {
+            quote::quote!(broadcast)
+        };
+
         let method = quote::quote! {
             pub fn #ident(
                 &self,
@@ -122,9 +396,64 @@ fn generate_methods<T: Service>(
             ) -> impl std::future::Future<
                 Output = Result<::mrpc::RRef<#response>, ::mrpc::Status>
             > + '_ {
-                let call_id = self.stub.initiate_call();
+                #unary
+            }
 
-                self.stub.unary(#service_id, #func_id, call_id, req.into_wref())
+            pub fn #broadcast_ident(
+                &self,
+                req: impl ::mrpc::IntoWRef<#request>,
+                targets: &[::mrpc::stub::Handle],
+            ) -> ::mrpc::stub::BroadcastStream<'_, #response> {
+                self.stub.#broadcast(#service_id, #func_id, req.into_wref(), targets)
+            }
+        };
+
+        stream.extend(method);
+    }
+
+    stream
+}
+
+fn generate_blocking_methods<T: Service>(
+    service: &T,
+    service_path: &str,
+    proto_path: &str,
+    compile_well_known_types: bool,
+    oneshot_methods: &[String],
+) -> TokenStream {
+    let mut stream = TokenStream::new();
+    // the blocking module is nested in the client module
+    let proto_path = if proto_path.starts_with("crate") || proto_path.starts_with("::") {
+        proto_path.to_string()
+    } else {
+        format!("super::{}", proto_path)
+    };
+
+    for method in service.methods() {
+        stream.extend(generate_doc_comments(method.comment()));
+
+        let ident = quote::format_ident!("{}", method.name());
+        let (request, response) =
+            method.request_response_name(&proto_path, compile_well_known_types);
+
+        if is_oneshot(service_path, method, oneshot_methods) {
+            stream.extend(quote::quote! {
+                pub fn #ident(
+                    &self,
+                    req: impl ::mrpc::IntoWRef<#request>
+                ) -> Result<(), ::mrpc::Status> {
+                    self.inner.#ident(req)
+                }
+            });
+            continue;
+        }
+
+        let method = quote::quote! {
+            pub fn #ident(
+                &self,
+                req: impl ::mrpc::IntoWRef<#request>
+            ) -> Result<::mrpc::RRef<#response>, ::mrpc::Status> {
+                ::mrpc::stub::block_on(self.inner.#ident(req))
             }
         };
 
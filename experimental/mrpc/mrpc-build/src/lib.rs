@@ -107,6 +107,8 @@ pub trait Method {
     ) -> (TokenStream, TokenStream);
     /// Proto pacakge name of request and response
     fn request_response_package(&self, proto_path: &str) -> (Option<String>, Option<String>);
+    /// Fully qualified proto type of the response, e.g., `.google.protobuf.Empty`.
+    fn response_proto_type(&self) -> &str;
 }
 
 // Returns a full path of a service compatible to gRPC.
@@ -134,14 +136,41 @@ fn get_method_path<S: Service<Method = M>, M: Method>(
     )
 }
 
-// Calculate SERVICE_ID for mRPC NamedService.
-fn mrpc_get_service_id(path: &str) -> u32 {
-    crc32fast::hash(path.as_bytes())
+// Calculate SERVICE_ID for mRPC NamedService, `mrpc.service_id = <id>` in the comments of the
+// service overrides the hash of its path.
+fn mrpc_get_service_id<C: AsRef<str>>(path: &str, comments: &[C]) -> u32 {
+    explicit_id(comments, "mrpc.service_id").unwrap_or_else(|| crc32fast::hash(path.as_bytes()))
 }
 
-// Calculate FUNC_ID for mRPC remote procedures.
-fn mrpc_get_func_id(path: &str) -> u32 {
-    crc32fast::hash(path.as_bytes())
+// Calculate FUNC_ID for mRPC remote procedures, `mrpc.func_id = <id>` in the comments of the
+// method overrides the hash of its path.
+fn mrpc_get_func_id<C: AsRef<str>>(path: &str, comments: &[C]) -> u32 {
+    explicit_id(comments, "mrpc.func_id").unwrap_or_else(|| crc32fast::hash(path.as_bytes()))
+}
+
+// Generates the `(path, func_id)` of each method of the service, for `NamedService::METHODS`.
+fn generate_method_ids<T: Service>(package: &str, service: &T) -> TokenStream {
+    let ids = service.methods().iter().map(|method| {
+        let path = get_method_path(package, service, method);
+        let func_id = mrpc_get_func_id(&path, method.comment());
+        quote::quote! { (#path, #func_id) }
+    });
+    quote::quote! { &[#(#ids),*] }
+}
+
+// Finds a `<key> = <id>` line in the leading comments. prost drops custom options, so the IDs
+// are overridden in the comments, which the backend reads the same way.
+fn explicit_id<C: AsRef<str>>(comments: &[C], key: &str) -> Option<u32> {
+    comments.iter().find_map(|line| {
+        let (k, v) = line.as_ref().trim().split_once('=')?;
+        if k.trim() != key {
+            return None;
+        }
+        match v.trim().trim_end_matches(';').trim().parse() {
+            Ok(id) => Some(id),
+            Err(_) => panic!("invalid {} in the comments: {:?}", key, line.as_ref()),
+        }
+    })
 }
 
 // Generate a singular line of a doc comment
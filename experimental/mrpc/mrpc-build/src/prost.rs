@@ -1,13 +1,18 @@
+use std::collections::HashMap;
 use std::ffi::OsString;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use proc_macro2::TokenStream;
 use prost_build::Config;
 use quote::quote;
 
 use crate::attribute::Attributes;
-use crate::{client, server};
+use crate::{
+    client, get_method_path, get_service_path, mrpc_get_func_id, mrpc_get_service_id, server,
+    Service as _,
+};
 
 /// Simple `.proto` compiling. Use [`configure`] instead if you need more options.
 ///
@@ -33,6 +38,11 @@ pub fn configure() -> Builder {
     Builder {
         build_client: true,
         build_server: true,
+        blocking_clients: Vec::new(),
+        oneshot_methods: Vec::new(),
+        cacheable_methods: Vec::new(),
+        serde: false,
+        compat: false,
         server_attributes: Attributes::default(),
         client_attributes: Attributes::default(),
         proto_path: "super".to_string(),
@@ -54,6 +64,16 @@ pub struct Builder {
     // Switches
     pub(crate) build_client: bool,
     pub(crate) build_server: bool,
+    // patterns of the services to generate blocking clients for
+    pub(crate) blocking_clients: Vec<String>,
+    // patterns of the methods to generate oneshot calls for
+    pub(crate) oneshot_methods: Vec<String>,
+    // patterns of the methods whose replies the clients cache, with the TTL of the replies
+    pub(crate) cacheable_methods: Vec<(String, Duration)>,
+    // derive serde for the messages
+    pub(crate) serde: bool,
+    // serve and issue the calls in the protobuf wire format between proto versions
+    pub(crate) compat: bool,
     // client/server service settings
     pub(crate) server_attributes: Attributes,
     pub(crate) client_attributes: Attributes,
@@ -108,6 +128,12 @@ impl Builder {
         for (prost_path, attr) in self.type_attributes.iter() {
             config.type_attribute(prost_path, attr);
         }
+        if self.serde {
+            config.type_attribute(".", "#[derive(::serde::Serialize, ::serde::Deserialize)]");
+        }
+        if self.compat {
+            config.type_attribute(".", "#[derive(::mrpc::compat::FieldTags)]");
+        }
         if self.compile_well_known_types {
             config.compile_well_known_types();
         }
@@ -144,6 +170,72 @@ impl Builder {
         self
     }
 
+    /// Generate a blocking client for the services matching the given pattern, in addition to the
+    /// async one. Matches on the service name like [`client_attribute`](Builder::client_attribute),
+    /// use `"."` to match all services.
+    ///
+    /// The blocking client lives in the `blocking` module inside the client module, e.g.,
+    /// `greeter_client::blocking::GreeterClient`, and its methods return once the reply arrives.
+    pub fn build_blocking_client<P: AsRef<str>>(mut self, path: P) -> Self {
+        self.blocking_clients.push(path.as_ref().to_string());
+        self
+    }
+
+    /// Generate a oneshot call for the methods matching the given pattern, which posts the request
+    /// and expects no reply. Matches on the method name qualified by the service name, e.g.,
+    /// `"Telemetry.Report"`.
+    ///
+    /// The matching methods must return `google.protobuf.Empty`. Their client methods return once
+    /// the request is handed to the backend, and the server drops the replies of their handlers.
+    /// The requests are delivered at most once, they are never retransmitted, and a failure to
+    /// send one is not reported to the caller.
+    pub fn oneshot_method<P: AsRef<str>>(mut self, path: P) -> Self {
+        self.oneshot_methods.push(path.as_ref().to_string());
+        self
+    }
+
+    /// Let the clients cache the replies of the methods matching the given pattern for `ttl`.
+    /// Matches on the method name qualified by the service name like
+    /// [`oneshot_method`](Builder::oneshot_method), e.g., `"Profile.GetProfiles"`.
+    ///
+    /// A call whose encoded request is the same as that of a reply cached within `ttl` is
+    /// answered by the client without going to the server. Only methods without side effects
+    /// should be cached. The generated clients get `cache_stats` and `flush_cache` to inspect and
+    /// drop the cached replies, see [`ClientStub::unary_cached`].
+    ///
+    /// [`ClientStub::unary_cached`]: ../mrpc/stub/struct.ClientStub.html#method.unary_cached
+    pub fn cacheable_method<P: AsRef<str>>(mut self, path: P, ttl: Duration) -> Self {
+        self.cacheable_methods
+            .push((path.as_ref().to_string(), ttl));
+        self
+    }
+
+    /// Derive `serde::Serialize` and `serde::Deserialize` for all the generated messages, enums,
+    /// and oneofs, e.g., to log them as JSON or to compare them with golden files.
+    ///
+    /// The crate that includes the generated code must depend on `serde` with the `derive`
+    /// feature, and enable the `serde` feature of `mrpc` for the collections of
+    /// `mrpc::alloc`. They serialize like their counterparts in `std`, and are deserialized onto
+    /// the shared heap, which needs the mRPC backend like any other message.
+    pub fn serde(mut self, enable: bool) -> Self {
+        self.serde = enable;
+        self
+    }
+
+    /// Generate the servers and the clients to also exchange the messages in the protobuf wire
+    /// format, so that the peers built from different versions of the protos interoperate, e.g.,
+    /// during a rolling upgrade. The clients send their requests so once `set_compat` is called,
+    /// the servers reply in the format of the request.
+    ///
+    /// The fields unknown to the receiver are kept, see [`mrpc::compat`]. The unary, cached and
+    /// oneshot calls are sent so, while the broadcast calls fail with `FailedPrecondition`.
+    ///
+    /// [`mrpc::compat`]: ../mrpc/compat/index.html
+    pub fn compat(mut self, enable: bool) -> Self {
+        self.compat = enable;
+        self
+    }
+
     /// Generate a file containing the encoded `prost_types::FileDescriptorSet` for protocol buffers
     /// modules. This is required for implementing gRPC Server Reflection.
     pub fn file_descriptor_set_path(mut self, path: impl AsRef<Path>) -> Self {
@@ -276,6 +368,9 @@ struct ServiceGenerator {
     builder: Builder,
     clients: TokenStream,
     servers: TokenStream,
+    // the path of each service and func ID generated so far
+    service_ids: HashMap<u32, String>,
+    func_ids: HashMap<u32, String>,
 }
 
 impl ServiceGenerator {
@@ -284,12 +379,42 @@ impl ServiceGenerator {
             builder,
             clients: TokenStream::default(),
             servers: TokenStream::default(),
+            service_ids: HashMap::new(),
+            func_ids: HashMap::new(),
+        }
+    }
+
+    /// Panics if an ID of `service` or its methods is taken by another path, the calls of both
+    /// would be dispatched to the same handler.
+    fn check_ids(&mut self, service: &prost_build::Service) {
+        let package = &service.package[..];
+        let path = get_service_path(package, service);
+        let service_id = mrpc_get_service_id(&path, service.comment());
+        check_id(&mut self.service_ids, service_id, path, "mrpc.service_id");
+        for method in service.methods.iter() {
+            let path = get_method_path(package, service, method);
+            let func_id = mrpc_get_func_id(&path, method.comment());
+            check_id(&mut self.func_ids, func_id, path, "mrpc.func_id");
         }
     }
 }
 
+fn check_id(ids: &mut HashMap<u32, String>, id: u32, path: String, key: &str) {
+    if let Some(other) = ids.get(&id) {
+        if *other != path {
+            panic!(
+                "{} and {} have the same ID {}, add `{} = <id>` to the comments of either",
+                other, path, id, key
+            );
+        }
+    }
+    ids.insert(id, path);
+}
+
 impl prost_build::ServiceGenerator for ServiceGenerator {
     fn generate(&mut self, service: prost_build::Service, _buf: &mut String) {
+        self.check_ids(&service);
+
         if self.builder.build_server {
             let server = server::generate(
                 &service,
@@ -297,6 +422,7 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
                 &self.builder.proto_path,
                 self.builder.compile_well_known_types,
                 &self.builder.server_attributes,
+                self.builder.compat,
             );
             self.servers.extend(server);
         }
@@ -308,6 +434,10 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
                 &self.builder.proto_path,
                 self.builder.compile_well_known_types,
                 &self.builder.client_attributes,
+                &self.builder.blocking_clients,
+                &self.builder.oneshot_methods,
+                &self.builder.cacheable_methods,
+                self.builder.compat,
             );
             self.clients.extend(client);
         }
@@ -434,6 +564,10 @@ impl crate::Method for prost_build::Method {
         (request, response)
     }
 
+    fn response_proto_type(&self) -> &str {
+        &self.output_proto_type
+    }
+
     // TODO: figure out whether we need to specially handle compile_well_known_types
     fn request_response_package(&self, proto_path: &str) -> (Option<String>, Option<String>) {
         let input_package = self.input_package.as_ref().map(|pkg| {
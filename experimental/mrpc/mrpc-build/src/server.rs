@@ -2,8 +2,8 @@ use proc_macro2::TokenStream;
 
 use crate::attribute::Attributes;
 use crate::{
-    generate_doc_comments, get_method_path, get_proto_packages, get_service_path, mrpc_get_func_id,
-    mrpc_get_service_id, naive_snake_case, Method, Service,
+    generate_doc_comments, generate_method_ids, get_method_path, get_proto_packages,
+    get_service_path, mrpc_get_func_id, mrpc_get_service_id, naive_snake_case, Method, Service,
 };
 
 /// Generate service for server.
@@ -16,8 +16,9 @@ pub fn generate<T: Service>(
     proto_path: &str,
     compile_well_known_types: bool,
     attributes: &Attributes,
+    compat: bool,
 ) -> TokenStream {
-    let methods = generate_methods(service, proto_path, compile_well_known_types);
+    let methods = generate_methods(service, proto_path, compile_well_known_types, compat);
 
     let server_service = quote::format_ident!("{}Server", service.name());
     let server_trait = quote::format_ident!("{}", service.name());
@@ -34,7 +35,9 @@ pub fn generate<T: Service>(
     let package = if emit_package { service.package() } else { "" };
 
     let path = get_service_path(package, service);
-    let service_id = mrpc_get_service_id(&path);
+    let service_id = mrpc_get_service_id(&path, service.comment());
+    // the func IDs of the dispatch are hashed with the package, see `generate_methods`
+    let method_ids = generate_method_ids(service.package(), service);
 
     let mod_attributes = attributes.for_mod(package);
     let struct_attributes = attributes.for_struct(&path);
@@ -64,7 +67,8 @@ pub fn generate<T: Service>(
 
             impl<T: #server_trait> #server_service<T> {
                 fn update_protos() -> Result<(), ::mrpc::Error> {
-                    let srcs = [#(#proto_srcs),*].concat();
+                    ::mrpc::stub::ids::register::<Self>()?;
+                    let srcs = <Self as NamedService>::proto_srcs();
                     ::mrpc::stub::update_protos(srcs.as_slice())
                 }
 
@@ -78,6 +82,12 @@ pub fn generate<T: Service>(
             impl<T: #server_trait> NamedService for #server_service<T> {
                 const SERVICE_ID: u32 = #service_id;
                 const NAME: &'static str = #path;
+                const METHODS: &'static [(&'static str, u32)] = #method_ids;
+                const COMPAT: bool = #compat;
+
+                fn proto_srcs() -> Vec<&'static str> {
+                    [#(#proto_srcs),*].concat()
+                }
             }
 
             #[mrpc::async_trait]
@@ -86,14 +96,13 @@ pub fn generate<T: Service>(
                     &self,
                     req_opaque: ::mrpc::MessageErased,
                     read_heap: std::sync::Arc<::mrpc::ReadHeap>,
+                    ctx: ::mrpc::stub::RequestContext,
                 ) -> (::mrpc::WRefOpaque, ::mrpc::MessageErased) {
                     let func_id = req_opaque.meta.func_id;
 
                     match func_id {
                         #methods
-                        _ => {
-                            todo!("error handling for unknown func_id: {}", func_id);
-                        }
+                        _ => ::mrpc::stub::service_unimplemented(&req_opaque, read_heap),
                     }
                 }
             }
@@ -151,31 +160,53 @@ fn generate_methods<T: Service>(
     service: &T,
     proto_path: &str,
     compile_well_known_types: bool,
+    compat: bool,
 ) -> TokenStream {
     let mut stream = TokenStream::new();
     let package = service.package();
 
     for method in service.methods() {
-        let func_id = mrpc_get_func_id(&get_method_path(package, service, method));
+        let func_id =
+            mrpc_get_func_id(&get_method_path(package, service, method), method.comment());
         let func_ident = quote::format_ident!("{}", method.name());
 
         let (_req_type, _res_type) =
             method.request_response_name(proto_path, compile_well_known_types);
 
-        let match_branch = quote::quote! {
-            #func_id => {
-                // let req_view = ::mrpc::stub::service_pre_handler(&req, reclaim_buffer);
-                let req = ::mrpc::RRef::new(&req_opaque, read_heap);
-                let res = self.inner.#func_ident(req).await;
-                match res {
-                    Ok(reply) => {
-                        ::mrpc::stub::service_post_handler(reply, &req_opaque)
+        let match_branch = if compat {
+            quote::quote! {
+                #func_id => {
+                    let req = match ::mrpc::stub::compat_pre_handler(&req_opaque, read_heap, ctx) {
+                        Ok(req) => req,
+                        Err(reply) => return reply,
+                    };
+                    let res = self.inner.#func_ident(req).await;
+                    match res {
+                        Ok(reply) => {
+                            ::mrpc::stub::compat_post_handler(reply, &req_opaque)
+                        }
+                        Err(status) => {
+                            ::mrpc::stub::service_error(status, &req_opaque)
+                        }
                     }
-                    Err(_status) => {
-                        todo!();
+                },
+            }
+        } else {
+            quote::quote! {
+                #func_id => {
+                    // let req_view = ::mrpc::stub::service_pre_handler(&req, reclaim_buffer);
+                    let req = ::mrpc::RRef::with_context(&req_opaque, read_heap, ctx);
+                    let res = self.inner.#func_ident(req).await;
+                    match res {
+                        Ok(reply) => {
+                            ::mrpc::stub::service_post_handler(reply, &req_opaque)
+                        }
+                        Err(status) => {
+                            ::mrpc::stub::service_error(status, &req_opaque)
+                        }
                     }
-                }
-            },
+                },
+            }
         };
 
         stream.extend(match_branch);
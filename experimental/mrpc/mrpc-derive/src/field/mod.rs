@@ -71,6 +71,23 @@ impl Field {
     }
 }
 
+/// Returns the tags of a field from its attributes, or `inferred_tag` if it has none. Unlike
+/// [`Field::new`], it takes any field, including a oneof and its `tags` attribute.
+pub fn tags(attrs: Vec<Attribute>, inferred_tag: u32) -> Result<Vec<u32>, Error> {
+    let mut tags = Vec::new();
+    for attr in prost_attrs(attrs) {
+        if let Some(tag) = tag_attr(&attr)? {
+            tags.push(tag);
+        } else if let Some(oneof_tags) = tags_attr(&attr)? {
+            tags.extend(oneof_tags);
+        }
+    }
+    if tags.is_empty() {
+        tags.push(inferred_tag);
+    }
+    Ok(tags)
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Label {
     /// An optional field.
@@ -227,3 +244,22 @@ pub(super) fn tag_attr(attr: &Meta) -> Result<Option<u32>, Error> {
         _ => bail!("invalid tag attribute: {:?}", attr),
     }
 }
+
+fn tags_attr(attr: &Meta) -> Result<Option<Vec<u32>>, Error> {
+    // parse the tags of a oneof field, e.g., `tags = "1, 2"`
+    if !attr.path().is_ident("tags") {
+        return Ok(None);
+    }
+    match *attr {
+        Meta::NameValue(MetaNameValue {
+            lit: Lit::Str(ref lit),
+            ..
+        }) => lit
+            .value()
+            .split(',')
+            .map(|tag| tag.trim().parse::<u32>().map_err(Error::from))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+        _ => bail!("invalid tags attribute: {:?}", attr),
+    }
+}
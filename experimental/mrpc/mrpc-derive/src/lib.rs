@@ -161,6 +161,46 @@ pub fn message(input: TokenStream) -> TokenStream {
     try_message(input).unwrap()
 }
 
+fn try_field_tags(input: TokenStream) -> Result<TokenStream, Error> {
+    let input: DeriveInput = syn::parse(input)?;
+    let ident = input.ident;
+
+    // prost-build puts the attributes of the messages on their enums and oneofs too, which have
+    // no fields of their own
+    let fields = match input.data {
+        Data::Struct(DataStruct { fields, .. }) => fields,
+        Data::Enum(..) | Data::Union(..) => return Ok(TokenStream::new()),
+    };
+
+    let generics = &input.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let mut next_tag: u32 = 1;
+    let mut tags = Vec::new();
+    for field in fields {
+        let field_tags = field::tags(field.attrs, next_tag)
+            .map_err(|err| err.context(format!("invalid message field of {}", ident)))?;
+        next_tag = field_tags.iter().max().unwrap() + 1;
+        tags.extend(field_tags);
+    }
+    tags.sort_unstable();
+
+    let expanded = quote! {
+        impl #impl_generics ::mrpc::compat::FieldTags for #ident #ty_generics #where_clause {
+            const FIELD_TAGS: &'static [u32] = &[#(#tags),*];
+        }
+    };
+
+    Ok(expanded.into())
+}
+
+/// Derives `mrpc::compat::FieldTags` for a message generated by prost, to receive it from the
+/// peers of other versions of the proto. It derives nothing for an enum.
+#[proc_macro_derive(FieldTags, attributes(prost))]
+pub fn field_tags(input: TokenStream) -> TokenStream {
+    try_field_tags(input).unwrap()
+}
+
 fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
     let input: DeriveInput = syn::parse(input)?;
     let ident = input.ident;
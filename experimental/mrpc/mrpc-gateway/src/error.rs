@@ -0,0 +1,58 @@
+//! Errors of the gateway, and the HTTP status codes they are answered with.
+use hyper::StatusCode;
+use thiserror::Error;
+
+use mrpc::{Code, Status};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Missing field: {0}")]
+    MissingField(String),
+    #[error("Invalid value of field {0}: {1:?}")]
+    InvalidField(String, String),
+    #[error("Invalid body: {0}")]
+    Body(String),
+    #[error("Connect failed: {0}")]
+    Connect(#[from] mrpc::Error),
+    #[error("RPC failed: {0}")]
+    Status(#[from] Status),
+    #[error("HTTP error: {0}")]
+    Hyper(#[from] hyper::Error),
+    #[error("IO Error {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    /// Returns the HTTP status code to answer the request with.
+    pub fn http_status(&self) -> StatusCode {
+        match self {
+            Error::MissingField(_) | Error::InvalidField(..) | Error::Body(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Error::Connect(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Status(status) => status_code(status.code()),
+            Error::Hyper(_) | Error::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Maps the code of a failed call to an HTTP status code, in the same way as the gRPC gateways.
+fn status_code(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        // client closed request, nginx's non-standard code
+        Code::Cancelled => StatusCode::from_u16(499).unwrap(),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -0,0 +1,193 @@
+//! gRPC in front of mRPC services.
+//!
+//! A server on phoenix only accepts mRPC clients. For the clients that are not on phoenix yet,
+//! the gateway also speaks gRPC: a [`GrpcRoute`] binds a gRPC method, e.g.,
+//! `/geo.Geo/Nearby`, to the unary method of a generated client that calls the same method over
+//! mRPC. The gateway decodes the protobuf request, makes the call, and encodes the reply, so the
+//! requests of both kinds of clients end up in the same handler of the server, and the clients on
+//! phoenix keep their fast path to it.
+//!
+//! Only unary calls with uncompressed messages are supported, over cleartext HTTP/2 with prior
+//! knowledge. The `grpc-timeout` header of a call becomes the deadline of the mRPC call, and the
+//! [`Code`] of a failed call is answered as is.
+use std::future::Future;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::future::LocalBoxFuture;
+use hyper::body::Bytes;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Request, Response};
+
+use mrpc::stub::RpcData;
+use mrpc::{Code, RRef, Status, WRef};
+
+use crate::pool::ClientPool;
+
+/// The content type of gRPC, and the prefix of its variants, e.g., `application/grpc+proto`.
+pub const CONTENT_TYPE_GRPC: &str = "application/grpc";
+
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+// the flag and the length that prefix each message
+const FRAME_HEADER_LEN: usize = 5;
+
+// takes the encoded request, and the deadline of the call
+type GrpcHandler =
+    Rc<dyn Fn(Bytes, Option<Instant>) -> LocalBoxFuture<'static, Result<Vec<u8>, Status>>>;
+
+/// A unary method of a service that serves a gRPC method.
+pub struct GrpcRoute {
+    handler: GrpcHandler,
+}
+
+impl GrpcRoute {
+    /// Serves the gRPC method by a unary method. `call` issues the request on a client of
+    /// `pool`, e.g., `|client, req| async move { client.nearby(req).await }`.
+    pub fn unary<C, Req, Res, F, Fut>(pool: Rc<ClientPool<C>>, call: F) -> Self
+    where
+        C: 'static,
+        Req: prost::Message + Default + RpcData,
+        Res: prost::Message + 'static,
+        F: Fn(Rc<C>, WRef<Req>) -> Fut + 'static,
+        Fut: Future<Output = Result<RRef<Res>, Status>> + 'static,
+    {
+        let call = Rc::new(call);
+        let handler: GrpcHandler = Rc::new(move |message, deadline| {
+            let pool = Rc::clone(&pool);
+            let call = Rc::clone(&call);
+            Box::pin(async move {
+                let req = Req::decode(message)
+                    .map_err(|e| Status::invalid_argument(format!("invalid request: {}", e)))?;
+                let mut req = WRef::new(req);
+                req.set_deadline(deadline);
+                let (slot, client) = pool.get().map_err(|e| Status::unavailable(e.to_string()))?;
+                match call(client, req).await {
+                    Ok(reply) => Ok(prost::Message::encode_to_vec(&*reply)),
+                    Err(status) => {
+                        if status.code() == Code::Unavailable {
+                            pool.evict(slot);
+                        }
+                        Err(status)
+                    }
+                }
+            })
+        });
+        GrpcRoute { handler }
+    }
+}
+
+/// Returns whether `request` is a gRPC call.
+pub(crate) fn is_grpc(request: &Request<Body>) -> bool {
+    request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.starts_with(CONTENT_TYPE_GRPC))
+}
+
+/// Answers a gRPC call by `route`, or with `Unimplemented` if there is none.
+pub(crate) async fn dispatch(route: Option<&GrpcRoute>, request: Request<Body>) -> Response<Body> {
+    let Some(route) = route else {
+        let status = Status::unimplemented(format!("unknown method {}", request.uri().path()));
+        return trailers_only(&status);
+    };
+    match call(route, request).await {
+        Ok(reply) => reply_response(reply),
+        Err(status) => {
+            log::debug!("gRPC call failed: {}", status);
+            trailers_only(&status)
+        }
+    }
+}
+
+async fn call(route: &GrpcRoute, request: Request<Body>) -> Result<Vec<u8>, Status> {
+    let (parts, body) = request.into_parts();
+    let deadline = parts
+        .headers
+        .get(GRPC_TIMEOUT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_timeout)
+        .map(|timeout| Instant::now() + timeout);
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?;
+    let message = unframe(body)?;
+    (route.handler)(message, deadline).await
+}
+
+/// Returns the single message of the body of a unary call.
+fn unframe(body: Bytes) -> Result<Bytes, Status> {
+    if body.len() < FRAME_HEADER_LEN {
+        return Err(Status::internal("truncated message"));
+    }
+    if body[0] != 0 {
+        return Err(Status::unimplemented(
+            "compressed messages are not supported",
+        ));
+    }
+    let len = u32::from_be_bytes(body[1..FRAME_HEADER_LEN].try_into().unwrap()) as usize;
+    if body.len() != FRAME_HEADER_LEN + len {
+        return Err(Status::internal("a unary call takes a single message"));
+    }
+    Ok(body.slice(FRAME_HEADER_LEN..))
+}
+
+/// Returns the duration of a `grpc-timeout` header, e.g., `100m`.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = amount.parse().ok()?;
+    let timeout = match unit {
+        "H" => Duration::from_secs(amount.saturating_mul(3600)),
+        "M" => Duration::from_secs(amount.saturating_mul(60)),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    };
+    Some(timeout)
+}
+
+fn grpc_response(body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_GRPC));
+    response
+}
+
+/// Answers with the reply message, followed by the trailers of a successful call.
+fn reply_response(reply: Vec<u8>) -> Response<Body> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + reply.len());
+    frame.push(0);
+    frame.extend_from_slice(&(reply.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&reply);
+
+    let (mut sender, body) = Body::channel();
+    tokio::task::spawn_local(async move {
+        let mut trailers = HeaderMap::new();
+        trailers.insert(GRPC_STATUS, HeaderValue::from(Code::Ok as i32));
+        if sender.send_data(frame.into()).await.is_ok() {
+            let _ = sender.send_trailers(trailers).await;
+        }
+    });
+    grpc_response(body)
+}
+
+/// Answers a failed call with its status in the headers and no body.
+fn trailers_only(status: &Status) -> Response<Body> {
+    let mut response = grpc_response(Body::empty());
+    let headers = response.headers_mut();
+    headers.insert(GRPC_STATUS, HeaderValue::from(status.code() as i32));
+    // the message is dropped if it is not a valid header value, rather than percent-encoded
+    if let Ok(message) = HeaderValue::from_str(status.message()) {
+        headers.insert(GRPC_MESSAGE, message);
+    }
+    response
+}
@@ -0,0 +1,69 @@
+//! HTTP/JSON gateway to mRPC services.
+//!
+//! Instead of hand-writing a hyper server that translates each HTTP request into mRPC calls, an
+//! application describes the translation in a [`Router`]: each route binds a method and a path to
+//! a unary method of a generated client. The gateway then
+//!
+//! - collects the fields of the request message from the query string and the JSON body, with the
+//!   query parameters renamed to the fields they fill by the [`FieldMap`] of the route;
+//! - builds the request with [`FromParams`] and renders the reply with [`ToJson`], which the
+//!   application implements for its message types;
+//! - spreads the calls over a [`ClientPool`], reconnecting the clients whose connections are lost;
+//! - gives the calls the deadline of the priority and the timeout in the headers of the request,
+//!   as set by the [`Qos`] of the router;
+//! - maps the [`Status`](mrpc::Status) of a failed call to an HTTP status code;
+//! - stops accepting requests when the shutdown signal resolves, and returns once the in-flight
+//!   requests are answered.
+//!
+//! The gateway also serves gRPC calls by the [`GrpcRoute`]s of the router, for the clients that
+//! are not on phoenix, see [`grpc`].
+//!
+//! mRPC clients are bound to the thread that creates them, so the gateway serves all the requests
+//! on a single thread.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! impl FromParams for NearbyRequest {
+//!     fn from_params(params: &Params) -> Result<Self, Error> {
+//!         Ok(NearbyRequest {
+//!             lat: params.require("lat")?,
+//!             lon: params.require("lon")?,
+//!             in_date: params.require::<String>("in_date")?.as_str().into(),
+//!             out_date: params.require::<String>("out_date")?.as_str().into(),
+//!         })
+//!     }
+//! }
+//!
+//! let search = ClientPool::new(4, move || SearchClient::connect(search_addr));
+//! let router = Router::new().route(
+//!     Method::GET,
+//!     "/hotels",
+//!     Route::unary(search, |client, req| async move { client.nearby(req).await })
+//!         .param("inDate", "in_date")
+//!         .param("outDate", "out_date"),
+//! );
+//! Gateway::new(router).run(addr, async_ctrlc::CtrlC::new()?)?;
+//! ```
+pub mod error;
+pub use error::Error;
+
+pub mod grpc;
+pub use grpc::GrpcRoute;
+
+pub mod params;
+pub use params::{FieldMap, FromParams, Params, ToJson};
+
+pub mod pool;
+pub use pool::ClientPool;
+
+pub mod qos;
+pub use qos::Qos;
+
+pub mod router;
+pub use router::{Route, Router};
+
+pub mod server;
+pub use server::Gateway;
+
+pub use hyper::Method;
@@ -0,0 +1,167 @@
+//! Transcoding between HTTP/JSON and the messages of a service.
+//!
+//! The fields of a request message are collected from the query string and the JSON object in the
+//! body of an HTTP request, by name. A field in the body overrides the same field in the query
+//! string. A field can be repeated in the query string (`?id=1&id=2`) or given as a JSON array to
+//! fill a repeated field of the message. Nested objects are not supported, the routes of such
+//! messages have to flatten them into fields of their own.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::Error;
+
+/// Builds a request message from the fields of an HTTP request.
+pub trait FromParams: Sized {
+    fn from_params(params: &Params) -> Result<Self, Error>;
+}
+
+/// Renders a reply message as JSON.
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+/// Maps the query parameters and the keys of the JSON body to the fields of a request message. A
+/// name that is not mapped fills the field of the same name.
+#[derive(Debug, Clone, Default)]
+pub struct FieldMap {
+    // param -> field
+    fields: HashMap<String, String>,
+}
+
+impl FieldMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps the parameter `param` to the field `field`.
+    pub fn insert(&mut self, param: impl Into<String>, field: impl Into<String>) {
+        self.fields.insert(param.into(), field.into());
+    }
+
+    /// Returns the field that `param` fills.
+    pub fn field<'a>(&'a self, param: &'a str) -> &'a str {
+        self.fields.get(param).map_or(param, |field| field.as_str())
+    }
+}
+
+/// The fields of a request, by the names of the fields of the message.
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Params {
+    /// Collects the fields from the query string and the JSON body of a request.
+    pub fn parse(query: Option<&str>, body: &[u8], fields: &FieldMap) -> Result<Self, Error> {
+        let mut values: HashMap<String, Vec<String>> = HashMap::new();
+        if let Some(query) = query {
+            for (param, value) in url::form_urlencoded::parse(query.as_bytes()) {
+                values
+                    .entry(fields.field(&param).to_owned())
+                    .or_default()
+                    .push(value.into_owned());
+            }
+        }
+
+        if !body.iter().all(u8::is_ascii_whitespace) {
+            let object = match serde_json::from_slice(body) {
+                Ok(Value::Object(object)) => object,
+                Ok(_) => return Err(Error::Body("not a JSON object".to_owned())),
+                Err(e) => return Err(Error::Body(e.to_string())),
+            };
+            for (param, value) in object {
+                let field = fields.field(&param).to_owned();
+                let items = match value {
+                    Value::Null => continue,
+                    Value::Array(items) => items,
+                    value => vec![value],
+                };
+                let items = items
+                    .into_iter()
+                    .map(|item| match item {
+                        Value::String(s) => Ok(s),
+                        Value::Number(n) => Ok(n.to_string()),
+                        Value::Bool(b) => Ok(b.to_string()),
+                        item => Err(Error::InvalidField(field.clone(), item.to_string())),
+                    })
+                    .collect::<Result<_, _>>()?;
+                values.insert(field, items);
+            }
+        }
+
+        Ok(Params { values })
+    }
+
+    /// Returns the value of `field`, or `None` if it is absent.
+    pub fn get<T: FromStr>(&self, field: &str) -> Result<Option<T>, Error> {
+        match self.values.get(field).and_then(|values| values.first()) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| Error::InvalidField(field.to_owned(), value.clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the value of `field`, which must be present.
+    pub fn require<T: FromStr>(&self, field: &str) -> Result<T, Error> {
+        self.get(field)?
+            .ok_or_else(|| Error::MissingField(field.to_owned()))
+    }
+
+    /// Returns all the values of a repeated `field`, which may be empty.
+    pub fn get_all<T: FromStr>(&self, field: &str) -> Result<Vec<T>, Error> {
+        self.values.get(field).map_or(Ok(Vec::new()), |values| {
+            values
+                .iter()
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| Error::InvalidField(field.to_owned(), value.clone()))
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_and_body() {
+        let mut fields = FieldMap::new();
+        fields.insert("inDate", "in_date");
+        let body = br#"{"lat": 37.5, "ids": [1, 2], "locale": null}"#;
+        let params = Params::parse(Some("inDate=2015-04-09&lat=0&id=3"), body, &fields).unwrap();
+        assert_eq!(params.require::<String>("in_date").unwrap(), "2015-04-09");
+        // the body overrides the query
+        assert_eq!(params.require::<f32>("lat").unwrap(), 37.5);
+        assert_eq!(params.get_all::<u32>("ids").unwrap(), vec![1, 2]);
+        assert_eq!(params.get::<String>("locale").unwrap(), None);
+        assert!(matches!(
+            params.require::<String>("out_date"),
+            Err(Error::MissingField(_))
+        ));
+        assert!(matches!(
+            params.require::<u32>("in_date"),
+            Err(Error::InvalidField(..))
+        ));
+    }
+
+    #[test]
+    fn reject_nested_body() {
+        let fields = FieldMap::new();
+        assert!(matches!(
+            Params::parse(None, br#"{"address": {"lat": 0}}"#, &fields),
+            Err(Error::InvalidField(..))
+        ));
+        assert!(matches!(
+            Params::parse(None, b"[1]", &fields),
+            Err(Error::Body(_))
+        ));
+        assert!(Params::parse(None, b" \n", &fields).is_ok());
+    }
+}
@@ -0,0 +1,67 @@
+//! A pool of clients of a service.
+//!
+//! A generated client holds a single connection, so the calls of a busy route queue up behind each
+//! other on the same queue pair. The pool keeps up to `size` clients, each on its own connection,
+//! and hands them out in turn. The clients are connected on first use. A client whose call fails
+//! with `Unavailable` is dropped, and the next request that picks its slot connects a new one.
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::Error;
+
+type ConnectFn<C> = Box<dyn Fn() -> Result<C, mrpc::Error>>;
+
+pub struct ClientPool<C> {
+    connect: ConnectFn<C>,
+    clients: RefCell<Vec<Option<Rc<C>>>>,
+    next: Cell<usize>,
+}
+
+impl<C> ClientPool<C> {
+    /// Creates a pool of up to `size` clients, connected by `connect`.
+    pub fn new<F>(size: usize, connect: F) -> Rc<Self>
+    where
+        F: Fn() -> Result<C, mrpc::Error> + 'static,
+    {
+        assert!(size > 0, "a client pool needs at least one client");
+        Rc::new(ClientPool {
+            connect: Box::new(connect),
+            clients: RefCell::new(vec![None; size]),
+            next: Cell::new(0),
+        })
+    }
+
+    /// Returns the next client and its slot, connecting it if the slot is empty.
+    pub fn get(&self) -> Result<(usize, Rc<C>), Error> {
+        let slot = self.next.get();
+        self.next.set((slot + 1) % self.len());
+
+        if let Some(client) = &self.clients.borrow()[slot] {
+            return Ok((slot, Rc::clone(client)));
+        }
+        let client = Rc::new((self.connect)()?);
+        self.clients.borrow_mut()[slot] = Some(Rc::clone(&client));
+        Ok((slot, client))
+    }
+
+    /// Drops the client in `slot`, e.g., because its connection is lost. Calls that are still
+    /// running on it are not affected.
+    pub fn evict(&self, slot: usize) {
+        self.clients.borrow_mut()[slot] = None;
+    }
+
+    /// Returns the number of slots.
+    pub fn len(&self) -> usize {
+        self.clients.borrow().len()
+    }
+
+    /// Returns whether the pool has no slots.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of connected clients.
+    pub fn num_connected(&self) -> usize {
+        self.clients.borrow().iter().flatten().count()
+    }
+}
@@ -0,0 +1,81 @@
+//! The QoS of the calls made for an HTTP request.
+//!
+//! An HTTP request may carry its urgency in the `priority` header of RFC 9218, e.g.,
+//! `priority: u=1`, from 0, the most urgent, to 7, and 3 if not given, and how long its client
+//! waits for it in milliseconds in the `x-request-timeout-ms` header. The calls the gateway makes
+//! for the request inherit both as their deadline (see [`WRef::set_deadline`]): the backend sends
+//! the calls with the earlier deadlines first on the connections that send in
+//! earliest-deadline-first order, and fails the calls still queued at their deadlines, which the
+//! gateway answers with `504 Gateway Timeout`.
+//!
+//! mRPC orders the calls by deadline only, so an urgency is translated into a deadline by the
+//! budget [`Qos::urgency`] gives it. The deadline of a call is the earlier of the two.
+//!
+//! [`WRef::set_deadline`]: mrpc::WRef::set_deadline
+use std::time::{Duration, Instant};
+
+use hyper::header::HeaderMap;
+
+/// The header of the urgency of a request, see RFC 9218.
+pub const PRIORITY: &str = "priority";
+/// The header of how long the client waits for a request, in milliseconds.
+pub const REQUEST_TIMEOUT_MS: &str = "x-request-timeout-ms";
+
+/// The urgency of a request without one.
+pub const DEFAULT_URGENCY: u8 = 3;
+const MAX_URGENCY: u8 = 7;
+
+/// How the QoS headers of the HTTP requests translate into the deadlines of their calls.
+#[derive(Debug, Clone, Default)]
+pub struct Qos {
+    // the budget of each urgency
+    budgets: [Option<Duration>; MAX_URGENCY as usize + 1],
+}
+
+impl Qos {
+    /// The calls take only the timeouts of their requests as deadlines, and ignore the urgency.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The calls made for the requests of `urgency` must leave the send queue of the backend
+    /// within `budget`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `urgency` is greater than 7.
+    pub fn urgency(mut self, urgency: u8, budget: Duration) -> Self {
+        assert!(urgency <= MAX_URGENCY, "urgency must be within 0..=7");
+        self.budgets[urgency as usize] = Some(budget);
+        self
+    }
+
+    /// Returns the deadline of the calls made for a request with `headers` that arrives now.
+    /// Malformed headers are ignored.
+    pub fn deadline(&self, headers: &HeaderMap) -> Option<Instant> {
+        let now = Instant::now();
+        let urgency = headers
+            .get(PRIORITY)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_urgency)
+            .unwrap_or(DEFAULT_URGENCY);
+        let budget = self.budgets[urgency as usize].map(|budget| now + budget);
+        let timeout = headers
+            .get(REQUEST_TIMEOUT_MS)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .map(|ms| now + Duration::from_millis(ms));
+        match (budget, timeout) {
+            (Some(budget), Some(timeout)) => Some(budget.min(timeout)),
+            (budget, timeout) => budget.or(timeout),
+        }
+    }
+}
+
+/// Returns the urgency of the `priority` header `value`, e.g., `u=1, i`.
+fn parse_urgency(value: &str) -> Option<u8> {
+    value.split(',').find_map(|param| {
+        let urgency: u8 = param.trim().strip_prefix("u=")?.parse().ok()?;
+        (urgency <= MAX_URGENCY).then_some(urgency)
+    })
+}
@@ -0,0 +1,156 @@
+//! The routing table from HTTP requests to the methods of the services.
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Instant;
+
+use futures::future::LocalBoxFuture;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+
+use mrpc::stub::RpcData;
+use mrpc::{Code, RRef, Status, WRef};
+
+use crate::grpc::{self, GrpcRoute};
+use crate::params::{FieldMap, FromParams, Params, ToJson};
+use crate::pool::ClientPool;
+use crate::qos::Qos;
+use crate::Error;
+
+// takes the fields of the request, and the deadline of the calls
+type Handler = Rc<dyn Fn(Params, Option<Instant>) -> LocalBoxFuture<'static, Result<Value, Error>>>;
+
+/// A method of a service that serves an HTTP route.
+pub struct Route {
+    fields: FieldMap,
+    handler: Handler,
+}
+
+impl Route {
+    /// Serves the route by a unary method. `call` issues the request on a client of `pool`, e.g.,
+    /// `|client, req| async move { client.say_hello(req).await }`. The request carries the
+    /// deadline of the HTTP request, see [`Qos`].
+    pub fn unary<C, Req, Res, F, Fut>(pool: Rc<ClientPool<C>>, call: F) -> Self
+    where
+        C: 'static,
+        Req: FromParams + RpcData,
+        Res: ToJson + 'static,
+        F: Fn(Rc<C>, WRef<Req>) -> Fut + 'static,
+        Fut: Future<Output = Result<RRef<Res>, Status>> + 'static,
+    {
+        let call = Rc::new(call);
+        let handler: Handler = Rc::new(move |params, deadline| {
+            let pool = Rc::clone(&pool);
+            let call = Rc::clone(&call);
+            Box::pin(async move {
+                let mut req = WRef::new(Req::from_params(&params)?);
+                req.set_deadline(deadline);
+                let (slot, client) = pool.get()?;
+                match call(client, req).await {
+                    Ok(reply) => Ok(reply.to_json()),
+                    Err(status) => {
+                        if status.code() == Code::Unavailable {
+                            pool.evict(slot);
+                        }
+                        Err(status.into())
+                    }
+                }
+            })
+        });
+        Route {
+            fields: FieldMap::new(),
+            handler,
+        }
+    }
+
+    /// Fills the field `field` of the request with the query parameter `param`.
+    pub fn param(mut self, param: impl Into<String>, field: impl Into<String>) -> Self {
+        self.fields.insert(param, field);
+        self
+    }
+}
+
+/// The routes of a gateway, by method and path.
+#[derive(Default)]
+pub struct Router {
+    // path -> method -> route
+    routes: HashMap<String, HashMap<Method, Route>>,
+    // path of the gRPC method, `/<package>.<Service>/<Method>` -> route
+    grpc_routes: HashMap<String, GrpcRoute>,
+    qos: Qos,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the requests of `method` to `path` by `route`.
+    pub fn route(mut self, method: Method, path: impl Into<String>, route: Route) -> Self {
+        self.routes
+            .entry(path.into())
+            .or_default()
+            .insert(method, route);
+        self
+    }
+
+    /// Serves the gRPC calls of the method at `path`, e.g., `/geo.Geo/Nearby`, by `route`.
+    pub fn grpc(mut self, path: impl Into<String>, route: GrpcRoute) -> Self {
+        self.grpc_routes.insert(path.into(), route);
+        self
+    }
+
+    /// Translates the QoS headers of the requests into the deadlines of their calls by `qos`.
+    pub fn qos(mut self, qos: Qos) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Answers an HTTP request.
+    pub async fn dispatch(&self, request: Request<Body>) -> Response<Body> {
+        if grpc::is_grpc(&request) {
+            let route = self.grpc_routes.get(request.uri().path());
+            return grpc::dispatch(route, request).await;
+        }
+
+        let route = match self.routes.get(request.uri().path()) {
+            Some(methods) => match methods.get(request.method()) {
+                Some(route) => route,
+                None => return empty_response(StatusCode::METHOD_NOT_ALLOWED),
+            },
+            None => return empty_response(StatusCode::NOT_FOUND),
+        };
+
+        match self.call(route, request).await {
+            Ok(reply) => json_response(StatusCode::OK, reply),
+            Err(e) => {
+                log::debug!("Request failed: {}", e);
+                json_response(e.http_status(), json!({ "error": e.to_string() }))
+            }
+        }
+    }
+
+    async fn call(&self, route: &Route, request: Request<Body>) -> Result<Value, Error> {
+        let (parts, body) = request.into_parts();
+        let deadline = self.qos.deadline(&parts.headers);
+        let body = hyper::body::to_bytes(body).await?;
+        let params = Params::parse(parts.uri.query(), &body, &route.fields)?;
+        (route.handler)(params, deadline).await
+    }
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn json_response(status: StatusCode, value: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(value.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
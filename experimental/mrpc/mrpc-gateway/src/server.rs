@@ -0,0 +1,72 @@
+//! The HTTP server of the gateway.
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::Server;
+
+use crate::router::Router;
+use crate::Error;
+
+/// Runs the connections of the HTTP server on the current thread, where the clients live.
+#[derive(Debug, Clone, Copy)]
+struct LocalExec;
+
+impl<F> hyper::rt::Executor<F> for LocalExec
+where
+    F: Future + 'static,
+{
+    fn execute(&self, fut: F) {
+        tokio::task::spawn_local(fut);
+    }
+}
+
+pub struct Gateway {
+    router: Rc<Router>,
+}
+
+impl Gateway {
+    pub fn new(router: Router) -> Self {
+        Gateway {
+            router: Rc::new(router),
+        }
+    }
+
+    /// Serves HTTP on `addr` until `shutdown` resolves, then waits for the requests in flight to
+    /// be answered. Must be called within a [`tokio::task::LocalSet`].
+    pub async fn serve<S>(self, addr: SocketAddr, shutdown: S) -> Result<(), Error>
+    where
+        S: Future<Output = ()>,
+    {
+        let router = self.router;
+        let make_service = make_service_fn(move |_conn| {
+            let router = Rc::clone(&router);
+            let service = service_fn(move |request| {
+                let router = Rc::clone(&router);
+                async move { Ok::<_, Infallible>(router.dispatch(request).await) }
+            });
+            async move { Ok::<_, Infallible>(service) }
+        });
+
+        let server = Server::try_bind(&addr)?
+            .executor(LocalExec)
+            .serve(make_service);
+        log::info!("Gateway listening on {}", addr);
+        server.with_graceful_shutdown(shutdown).await?;
+        Ok(())
+    }
+
+    /// Serves HTTP on `addr` on the current thread until `shutdown` resolves.
+    pub fn run<S>(self, addr: SocketAddr, shutdown: S) -> Result<(), Error>
+    where
+        S: Future<Output = ()>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&runtime, self.serve(addr, shutdown))
+    }
+}
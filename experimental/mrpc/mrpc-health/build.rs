@@ -0,0 +1,6 @@
+const PROTO: &str = "proto/health.proto";
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed={PROTO}");
+    mrpc_build::configure().compile(&[PROTO], &["proto"])?;
+    Ok(())
+}
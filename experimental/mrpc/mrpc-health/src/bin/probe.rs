@@ -0,0 +1,46 @@
+//! Probes the health of an mRPC server, in the manner of `grpc_health_probe`.
+//!
+//! Exits with 0 if the service is serving, and with 1 otherwise or if the server cannot be
+//! reached, so it can serve as the liveness or readiness probe of an orchestrator.
+use std::process;
+
+use structopt::StructOpt;
+
+use mrpc_health::pb::health_client::HealthClient;
+use mrpc_health::pb::HealthCheckRequest;
+use mrpc_health::ServingStatus;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Health probe of mRPC servers")]
+struct Args {
+    /// The address of the server.
+    #[structopt(long, default_value = "localhost:5000")]
+    addr: String,
+    /// The service to check, e.g., `rpc_hello.Greeter`. Checks the whole server if empty.
+    #[structopt(long, default_value = "")]
+    service: String,
+}
+
+fn check(args: &Args) -> Result<ServingStatus, Box<dyn std::error::Error>> {
+    let client = HealthClient::connect(&args.addr)?;
+    let req = HealthCheckRequest {
+        service: args.service.as_str().into(),
+    };
+    let reply = smol::block_on(client.check(req))?;
+    Ok(ServingStatus::from_i32(reply.status).unwrap_or(ServingStatus::Unknown))
+}
+
+fn main() {
+    let args = Args::from_args();
+    match check(&args) {
+        Ok(ServingStatus::Serving) => println!("status: SERVING"),
+        Ok(status) => {
+            println!("status: {:?}", status);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("health check of {} failed: {}", args.addr, e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! gRPC-compatible health checking for mRPC servers.
+//!
+//! This crate provides the `grpc.health.v1.Health` service, so that orchestrators can probe every
+//! mRPC server in the same way, e.g., with the `mrpc_health_probe` binary. A server registers it
+//! in one line:
+//!
+//! ```ignore
+//! use mrpc_health::HealthServiceExt;
+//!
+//! let mut server = mrpc::stub::LocalServer::bind("0.0.0.0:5000")?;
+//! server
+//!     .add_service(GreeterServer::new(MyGreeter::default()))
+//!     .add_health_service()
+//!     .serve()
+//!     .await?;
+//! ```
+//!
+//! The serving status is kept per process in the [`reporter`], shared by the servers of all the
+//! threads. The overall status of the server, queried with an empty service name, is `SERVING`
+//! from the start. The statuses of the individual services are set by the application:
+//!
+//! ```ignore
+//! mrpc_health::reporter().set_serving::<GreeterServer<MyGreeter>>();
+//! // e.g., when the database behind the service goes away
+//! mrpc_health::reporter().set_not_serving::<GreeterServer<MyGreeter>>();
+//! ```
+//!
+//! A service that has no status set is reported as `SERVICE_UNKNOWN`.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+
+use mrpc::stub::{LocalServer, NamedService};
+use mrpc::{RRef, WRef};
+
+pub mod pb {
+    // The string specified here must match the proto package name
+    mrpc::include_proto!("grpc.health.v1");
+}
+
+pub use pb::health_check_response::ServingStatus;
+use pb::health_server::{Health, HealthServer};
+use pb::{HealthCheckRequest, HealthCheckResponse};
+
+lazy_static! {
+    static ref REPORTER: HealthReporter = HealthReporter::new();
+}
+
+/// Returns the reporter of the serving statuses of this process.
+pub fn reporter() -> &'static HealthReporter {
+    &REPORTER
+}
+
+/// The serving statuses of the services, by their names.
+#[derive(Debug, Clone)]
+pub struct HealthReporter {
+    statuses: Arc<spin::RwLock<HashMap<String, ServingStatus>>>,
+}
+
+impl Default for HealthReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthReporter {
+    /// Creates a reporter where only the overall status of the server is set, to `SERVING`.
+    pub fn new() -> Self {
+        let mut statuses = HashMap::new();
+        statuses.insert(String::new(), ServingStatus::Serving);
+        HealthReporter {
+            statuses: Arc::new(spin::RwLock::new(statuses)),
+        }
+    }
+
+    /// Marks the service `S` as serving.
+    pub fn set_serving<S: NamedService>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::Serving);
+    }
+
+    /// Marks the service `S` as not serving.
+    pub fn set_not_serving<S: NamedService>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::NotServing);
+    }
+
+    /// Sets the status of the service named `service`. The empty name is the overall status of
+    /// the server.
+    pub fn set_service_status(&self, service: impl Into<String>, status: ServingStatus) {
+        self.statuses.write().insert(service.into(), status);
+    }
+
+    /// Forgets the status of the service named `service`, which is then reported as
+    /// `SERVICE_UNKNOWN`.
+    pub fn clear_service_status(&self, service: &str) {
+        self.statuses.write().remove(service);
+    }
+
+    /// Returns the status of the service named `service`.
+    pub fn service_status(&self, service: &str) -> ServingStatus {
+        self.statuses
+            .read()
+            .get(service)
+            .copied()
+            .unwrap_or(ServingStatus::ServiceUnknown)
+    }
+}
+
+/// The `grpc.health.v1.Health` service, which answers from a [`HealthReporter`].
+#[derive(Debug, Clone)]
+pub struct HealthService {
+    reporter: HealthReporter,
+}
+
+impl HealthService {
+    pub fn new(reporter: HealthReporter) -> Self {
+        HealthService { reporter }
+    }
+}
+
+#[mrpc::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: RRef<HealthCheckRequest>,
+    ) -> Result<WRef<HealthCheckResponse>, mrpc::Status> {
+        let status = self.reporter.service_status(request.service.as_str());
+        Ok(WRef::new(HealthCheckResponse {
+            status: status as i32,
+        }))
+    }
+}
+
+/// Registers the health service on a server.
+pub trait HealthServiceExt {
+    /// Adds the `grpc.health.v1.Health` service that answers from the [`reporter`] of the process.
+    fn add_health_service(&mut self) -> &mut Self;
+}
+
+impl HealthServiceExt for LocalServer {
+    fn add_health_service(&mut self) -> &mut Self {
+        self.add_service(HealthServer::new(HealthService::new(reporter().clone())))
+    }
+}
@@ -0,0 +1,252 @@
+//! Messages in the protobuf wire format, for peers built from different versions of a proto.
+//!
+//! A message is normally sent in its in-memory representation, which only a peer with the very
+//! same definition of the message can take in place. During a rolling upgrade, the peers can send
+//! their messages encoded in the protobuf wire format instead, as raw messages flagged with
+//! `MessageMeta::COMPAT`. The receiver decodes a message into its own version of the type: the
+//! fields the sender does not know are left to their defaults, and the fields the receiver does
+//! not know are kept as [`UnknownFields`]. Attached to a message sent on, e.g., the reply or a
+//! request forwarded to another service, they are appended to its encoding as they were received,
+//! so a peer of the newer version gets them back.
+//!
+//! Only the unknown fields of the message itself are kept, those of the nested messages are
+//! dropped when the nested messages are decoded. The receiver refuses such a message rather than
+//! losing them.
+use thiserror::Error;
+
+/// The tags of the fields of a message, including those of its oneofs. Derived for the
+/// generated messages by `mrpc_derive::FieldTags`.
+pub trait FieldTags {
+    const FIELD_TAGS: &'static [u32];
+}
+
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_FIXED64: u8 = 1;
+const WIRE_TYPE_LENGTH_DELIMITED: u8 = 2;
+const WIRE_TYPE_START_GROUP: u8 = 3;
+const WIRE_TYPE_END_GROUP: u8 = 4;
+const WIRE_TYPE_FIXED32: u8 = 5;
+
+const MAX_TAG: u64 = (1 << 29) - 1;
+// the same limit as prost
+const RECURSION_LIMIT: u32 = 100;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    #[error("buffer underflow")]
+    Truncated,
+    #[error("invalid varint")]
+    InvalidVarint,
+    #[error("invalid tag: {0}")]
+    InvalidTag(u64),
+    #[error("invalid wire type: {0}")]
+    InvalidWireType(u8),
+    #[error("unexpected end group of field {0}")]
+    UnexpectedEndGroup(u32),
+    #[error("recursion limit reached")]
+    RecursionLimit,
+}
+
+/// The fields of a received message that are unknown to the receiver, in the wire format they
+/// were received in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnknownFields {
+    records: Vec<u8>,
+}
+
+impl UnknownFields {
+    /// Returns the fields of the encoded message `buf` whose tags are not in `known`.
+    pub fn split(buf: &[u8], known: &[u32]) -> Result<Self, WireError> {
+        let mut records = Vec::new();
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let record = rest;
+            let (tag, wire_type) = decode_key(&mut rest)?;
+            if wire_type == WIRE_TYPE_END_GROUP {
+                return Err(WireError::UnexpectedEndGroup(tag));
+            }
+            skip_value(tag, wire_type, &mut rest, 0)?;
+            if !known.contains(&tag) {
+                records.extend_from_slice(&record[..record.len() - rest.len()]);
+            }
+        }
+        Ok(UnknownFields { records })
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns the fields as they were received.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.records
+    }
+
+    /// Appends the fields to the encoded message in `buf`.
+    #[inline]
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.records);
+    }
+}
+
+fn decode_varint(buf: &mut &[u8]) -> Result<u64, WireError> {
+    let mut value = 0;
+    for (i, &byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (i * 7);
+        if byte < 0x80 {
+            // the 10th byte only holds the highest bit
+            if i == 9 && byte > 1 {
+                return Err(WireError::InvalidVarint);
+            }
+            *buf = &buf[i + 1..];
+            return Ok(value);
+        }
+    }
+    if buf.len() < 10 {
+        Err(WireError::Truncated)
+    } else {
+        Err(WireError::InvalidVarint)
+    }
+}
+
+fn decode_key(buf: &mut &[u8]) -> Result<(u32, u8), WireError> {
+    let key = decode_varint(buf)?;
+    let wire_type = (key & 0x7) as u8;
+    let tag = key >> 3;
+    if tag == 0 || tag > MAX_TAG {
+        return Err(WireError::InvalidTag(tag));
+    }
+    Ok((tag as u32, wire_type))
+}
+
+fn advance(buf: &mut &[u8], len: u64) -> Result<(), WireError> {
+    if len > buf.len() as u64 {
+        return Err(WireError::Truncated);
+    }
+    *buf = &buf[len as usize..];
+    Ok(())
+}
+
+/// Skips the value of the field `tag`, the rest of the group if it starts one.
+fn skip_value(tag: u32, wire_type: u8, buf: &mut &[u8], depth: u32) -> Result<(), WireError> {
+    match wire_type {
+        WIRE_TYPE_VARINT => decode_varint(buf).map(|_| ()),
+        WIRE_TYPE_FIXED64 => advance(buf, 8),
+        WIRE_TYPE_LENGTH_DELIMITED => {
+            let len = decode_varint(buf)?;
+            advance(buf, len)
+        }
+        WIRE_TYPE_START_GROUP => {
+            if depth == RECURSION_LIMIT {
+                return Err(WireError::RecursionLimit);
+            }
+            loop {
+                let (inner_tag, inner_wire_type) = decode_key(buf)?;
+                if inner_wire_type == WIRE_TYPE_END_GROUP {
+                    if inner_tag != tag {
+                        return Err(WireError::UnexpectedEndGroup(inner_tag));
+                    }
+                    return Ok(());
+                }
+                skip_value(inner_tag, inner_wire_type, buf, depth + 1)?;
+            }
+        }
+        WIRE_TYPE_FIXED32 => advance(buf, 4),
+        _ => Err(WireError::InvalidWireType(wire_type)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(tag: u32, wire_type: u8) -> u8 {
+        assert!(tag < 16);
+        (tag << 3) as u8 | wire_type
+    }
+
+    #[test]
+    fn split_keeps_unknown_records() {
+        let known = [
+            key(1, WIRE_TYPE_VARINT),
+            0x96,
+            0x01,
+            key(2, WIRE_TYPE_LENGTH_DELIMITED),
+            3,
+            b'a',
+            b'b',
+            b'c',
+        ];
+        let fixed64 = [key(3, WIRE_TYPE_FIXED64), 1, 2, 3, 4, 5, 6, 7, 8];
+        let fixed32 = [key(4, WIRE_TYPE_FIXED32), 1, 2, 3, 4];
+        let group = [
+            key(5, WIRE_TYPE_START_GROUP),
+            key(1, WIRE_TYPE_VARINT),
+            7,
+            key(5, WIRE_TYPE_END_GROUP),
+        ];
+        let buf = [
+            &fixed64[..],
+            &known[..3],
+            &fixed32[..],
+            &known[3..],
+            &group[..],
+        ]
+        .concat();
+
+        let unknown = UnknownFields::split(&buf, &[1, 2]).unwrap();
+        assert_eq!(
+            unknown.as_bytes(),
+            [&fixed64[..], &fixed32, &group].concat()
+        );
+
+        let all = UnknownFields::split(&buf, &[]).unwrap();
+        assert_eq!(all.as_bytes(), &buf[..]);
+        assert!(UnknownFields::split(&buf, &[1, 2, 3, 4, 5])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn encode_appends_records() {
+        let buf = [key(1, WIRE_TYPE_VARINT), 1, key(9, WIRE_TYPE_VARINT), 2];
+        let unknown = UnknownFields::split(&buf, &[1]).unwrap();
+        let mut encoded = vec![key(1, WIRE_TYPE_VARINT), 5];
+        unknown.encode(&mut encoded);
+        assert_eq!(
+            encoded,
+            [key(1, WIRE_TYPE_VARINT), 5, key(9, WIRE_TYPE_VARINT), 2]
+        );
+    }
+
+    #[test]
+    fn split_rejects_malformed_messages() {
+        let truncated = [key(2, WIRE_TYPE_LENGTH_DELIMITED), 4, b'a'];
+        assert_eq!(
+            UnknownFields::split(&truncated, &[]),
+            Err(WireError::Truncated)
+        );
+        let bad_wire_type = [key(1, 6), 0];
+        assert_eq!(
+            UnknownFields::split(&bad_wire_type, &[]),
+            Err(WireError::InvalidWireType(6))
+        );
+        let zero_tag = [0, 0];
+        assert_eq!(
+            UnknownFields::split(&zero_tag, &[]),
+            Err(WireError::InvalidTag(0))
+        );
+        let unmatched_group = [key(5, WIRE_TYPE_START_GROUP), key(6, WIRE_TYPE_END_GROUP)];
+        assert_eq!(
+            UnknownFields::split(&unmatched_group, &[]),
+            Err(WireError::UnexpectedEndGroup(6))
+        );
+        let long_varint = [&[key(1, WIRE_TYPE_VARINT)][..], &[0xff; 9], &[0x02]].concat();
+        assert_eq!(
+            UnknownFields::split(&long_varint, &[]),
+            Err(WireError::InvalidVarint)
+        );
+    }
+}
@@ -1,6 +1,14 @@
 //! There are extensive FFI in this module. However, these foreign function only interface between
 //! the backend compiler and the backend's plugin compiler, which we can guarantee to be the exact
 //! same. Therefore, types such as SgList, ExcavateContext, MarshalError do not need to be #[repr(C)].
+//!
+//! Messages are not encoded on the wire. The SgList of a message points to its in-memory
+//! representation, and the receiver takes the received segments in place as the message. As a
+//! result, a message can only be unmarshaled with the very same definition it was marshaled with.
+//! A mismatch in the size of the message is reported as [`UnmarshalError::SgELengthMismatch`],
+//! but a change of the layout that keeps the size is not detected. Peers built from different
+//! versions of a proto exchange their messages in the protobuf wire format instead, see
+//! [`compat`].
 #![feature(strict_provenance)]
 #![feature(core_intrinsics)]
 #![feature(allocator_api)]
@@ -13,7 +21,9 @@ use thiserror::Error;
 
 use shm::ptr::ShmPtr;
 
+pub mod compat;
 pub mod emplacement;
+pub mod raw;
 pub mod shadow {
     use crate::alloc::PrivateHeap;
 
@@ -62,7 +72,8 @@ pub mod alloc {
 
 #[derive(Error, Debug)]
 pub enum MarshalError {
-    // TBD
+    #[error("unknown func_id: {0}")]
+    UnknownFuncId(u32),
 }
 
 #[derive(Error, Debug)]
@@ -73,6 +84,8 @@ pub enum UnmarshalError {
     SgListUnderflow,
     #[error("query app addr failed: {0}")]
     QueryAppAddr(#[from] AddressNotFound),
+    #[error("unknown func_id: {0}")]
+    UnknownFuncId(u32),
 }
 
 #[derive(Debug)]
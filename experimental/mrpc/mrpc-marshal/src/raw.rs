@@ -0,0 +1,52 @@
+//! Raw messages, byte strings sent as they are without a dispatch library.
+//!
+//! A raw message is a `Vec<u8>` on the shared heap. It is marshaled to the segment of its header,
+//! followed by its bytes as a single opaque segment, and the receiver takes the received bytes in
+//! place, so raw calls keep the zero-copy path of the messages of a proto.
+use std::mem;
+
+use shm::ptr::ShmPtr;
+
+use crate::emplacement::bytes;
+use crate::shadow::Vec;
+use crate::{AddressArbiter, ExcavateContext, MarshalError, SgE, SgList, UnmarshalError};
+
+/// Marshals the raw message at `addr_backend`.
+pub fn marshal(addr_backend: usize) -> Result<SgList, MarshalError> {
+    let ptr_backend = addr_backend as *const Vec<u8>;
+    assert_eq!(ptr_backend.align_offset(mem::align_of::<Vec<u8>>()), 0);
+    // SAFETY: the frontend hands a raw message at the address
+    let msg_ref = unsafe { &*ptr_backend };
+    let mut sgl = SgList(std::vec::Vec::with_capacity(2));
+    sgl.0.push(SgE {
+        ptr: addr_backend,
+        len: mem::size_of::<Vec<u8>>(),
+    });
+    bytes::emplace(msg_ref, &mut sgl)?;
+    Ok(sgl)
+}
+
+/// Unmarshals a raw message from the received segments, and returns its addresses in the app
+/// and in the backend.
+///
+/// # Safety
+///
+/// The message is unmarshaled in place, the received segments must remain valid as long as the
+/// message is in use.
+pub unsafe fn unmarshal<A: AddressArbiter>(
+    ctx: &mut ExcavateContext<A>,
+) -> Result<(usize, usize), UnmarshalError> {
+    let header_sge = ctx.sgl.next().ok_or(UnmarshalError::SgListUnderflow)?;
+    if header_sge.len != mem::size_of::<Vec<u8>>() {
+        return Err(UnmarshalError::SgELengthMismatch {
+            expected: mem::size_of::<Vec<u8>>(),
+            actual: header_sge.len,
+        });
+    }
+
+    let backend_addr = header_sge.ptr;
+    let app_addr = ctx.addr_arbiter.query_app_addr(backend_addr)?;
+    let mut message = ShmPtr::new(app_addr as *mut Vec<u8>, backend_addr as *mut Vec<u8>).unwrap();
+    bytes::excavate(message.as_mut_backend(), ctx)?;
+    Ok((app_addr, backend_addr))
+}
@@ -1,5 +1,9 @@
 //! mRPC control path commands.
-use std::{net::SocketAddr, os::unix::prelude::RawFd, path::PathBuf};
+use std::{
+    net::{IpAddr, SocketAddr},
+    os::unix::prelude::RawFd,
+    path::PathBuf,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -11,15 +15,61 @@ type IResult<T> = Result<T, phoenix_api::Error>;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
     SetTransport(TransportType),
-    Connect(SocketAddr),
+    Connect(SocketAddr, ConnectOptions),
+    // Connect over unreliable datagrams, for small RPCs that tolerate loss
+    ConnectDatagram(SocketAddr),
     // MultiConnect tells lb to map a vector of connections to a virtual connection
     MultiConnect(Vec<Handle>),
-    Bind(SocketAddr),
+    Bind(SocketAddr, BindOptions),
     // The app notifies the backend with its mapped addresses
     // conn_handle, [mr_handle, addr]
     NewMappedAddrs(Handle, Vec<(Handle, usize)>),
     UpdateProtos(Vec<String>),
-    UpdateProtosInner(PathBuf),
+    // The dispatch library to load and its version, 0 if the frontend does not number them. The
+    // messages in flight are marshaled with the library of their version until it is retired.
+    UpdateProtosInner(PathBuf, u32),
+    // The messages marshaled with the dispatch library of the version have all been
+    // acknowledged, the backend unloads it
+    RetireProtosInner(u32),
+    // Query the send credits of the connections
+    QueryCredits(Vec<Handle>),
+    // Enable or disable the timing records of the calls on the connections
+    SetCallTiming(Vec<Handle>, bool),
+    // Set the largest message copied into the meta buffer on the connections, None for the
+    // default of the backend
+    SetEagerCopyThreshold(Vec<Handle>, Option<usize>),
+    // Connect to a host by name, the name is resolved by the backend, host:port
+    ConnectHost(String, u16),
+    // Stop accepting connections on a listener returned by Bind, the accepted connections are
+    // kept
+    Unbind(Handle),
+}
+
+/// Settings of a listener. The settings left `None` take the defaults of the backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BindOptions {
+    /// The length of the queue of connections waiting to be accepted
+    pub accept_backlog: Option<u32>,
+    /// The most connections of the listener open at a time, more are rejected
+    pub max_connections: Option<usize>,
+    /// The number of receive buffers of each accepted connection, and their size in bytes
+    pub recv_buffers: Option<(usize, usize)>,
+    /// Accept connections over unreliable datagrams instead, see `Command::ConnectDatagram`
+    pub datagram: bool,
+}
+
+/// Settings of the local side of a connection, for hosts with more than one address or device.
+/// The settings left `None` are chosen by the backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectOptions {
+    /// The local address to connect from, takes precedence over `nic_index`
+    pub local_addr: Option<IpAddr>,
+    /// The index of the device to connect from, among the devices of the backend
+    pub nic_index: Option<usize>,
+    /// The range of local ports to take one from, both ends included
+    pub port_range: Option<(u16, u16)>,
+    /// The type of service of the packets of the connection
+    pub tos: Option<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +84,8 @@ pub struct ReadHeapRegion {
 pub struct ConnectResponse {
     pub conn_handle: Handle,
     pub read_regions: Vec<ReadHeapRegion>,
+    // the address of the remote end, if the transport knows it
+    pub peer_addr: Option<SocketAddr>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -53,6 +105,12 @@ pub enum CompletionKind {
     // the acknowledgement
     NewMappedAddrs,
     UpdateProtos,
+    RetireProtosInner,
+    // the send credits of each connection, None if the transport does not use credits
+    QueryCredits(Vec<(Handle, Option<usize>)>),
+    SetCallTiming,
+    SetEagerCopyThreshold,
+    Unbind,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
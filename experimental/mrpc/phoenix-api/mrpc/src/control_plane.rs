@@ -10,6 +10,9 @@ pub enum TransportType {
     #[default]
     #[serde(alias = "Tcp")]
     Tcp,
+    /// RDMA, and TCP for the connections RDMA fails to make, e.g., to the peers without RDMA.
+    #[serde(alias = "Auto")]
+    Auto,
 }
 
 impl std::str::FromStr for TransportType {
@@ -19,7 +22,8 @@ impl std::str::FromStr for TransportType {
         match s.to_uppercase().as_str() {
             "RDMA" => Ok(Self::Rdma),
             "TCP" => Ok(Self::Tcp),
-            _ => Err("Expect RDMA or TCP"),
+            "AUTO" => Ok(Self::Auto),
+            _ => Err("Expect RDMA, TCP or AUTO"),
         }
     }
 }
@@ -48,4 +52,8 @@ pub struct Setting {
     pub core_id: Option<usize>,
 
     pub module_config: Option<String>,
+    /// The profile of the backend to use, i.e., the chain of addon engines the messages go
+    /// through, see `profiles` in the backend config. No addon engines if [`None`].
+    #[serde(default)]
+    pub profile: Option<String>,
 }
@@ -1,10 +1,12 @@
 //! mRPC data path operations.
 use serde::{Deserialize, Serialize};
 
-use phoenix_api::rpc::{CallId, MessageErased, RpcId, TransportStatus};
+use phoenix_api::rpc::{
+    CallId, ConnectionState, MessageErased, RpcId, TransportStatus, TransportTiming,
+};
 use phoenix_api::Handle;
 
-pub type WorkRequestSlot = [u8; 64];
+pub type WorkRequestSlot = [u8; 128];
 
 pub const RECV_RECLAIM_BS: usize = 4;
 
@@ -16,9 +18,15 @@ pub enum WorkRequest {
     Reply(MessageErased),
     // conn_id and an array of call_id
     ReclaimRecvBuf(Handle, [CallId; RECV_RECLAIM_BS]),
+    // the deadline of the Call or Reply of the RPC that follows, in nanoseconds of monotonic_ns,
+    // the message is dropped if it is still queued in the backend by then
+    Deadline(RpcId, u64),
+    // the Call or Reply of the RPC that follows is sent in bulk, its payload is read by the peer
+    // with RDMA READs
+    Bulk(RpcId),
 }
 
-pub type CompletionSlot = [u8; 64];
+pub type CompletionSlot = [u8; 128];
 
 // Avoid using too much `Send`/`Recv` in the code.
 #[repr(C, align(64))]
@@ -28,6 +36,11 @@ pub enum Completion {
     Outgoing(RpcId, TransportStatus),
     // (conn_id, status)
     RecvError(Handle, TransportStatus),
+    // (conn_id, state)
+    ConnectionState(Handle, ConnectionState),
+    // the timestamps taken by the transport, sent before the reply of a call that has timing
+    // enabled
+    CallTiming(RpcId, TransportTiming),
 }
 
 mod sa {
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use phoenix_api::rpc::{CallId, MessageErased, RpcId, TransportStatus};
 use phoenix_api::Handle;
 
-pub type WorkRequestSlot = [u8; 64];
+pub type WorkRequestSlot = [u8; 128];
 
 pub const RECV_RECLAIM_BS: usize = 4;
 
@@ -18,7 +18,7 @@ pub enum WorkRequest {
     ReclaimRecvBuf(Handle, [CallId; RECV_RECLAIM_BS]),
 }
 
-pub type CompletionSlot = [u8; 64];
+pub type CompletionSlot = [u8; 128];
 
 // Avoid using too much `Send`/`Recv` in the code.
 #[repr(C, align(64))]
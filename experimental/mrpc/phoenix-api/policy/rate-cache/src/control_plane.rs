@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// ttl_ms, capacity
+    NewConfig(u64, usize),
+    /// Drop all cached replies.
+    Clear,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
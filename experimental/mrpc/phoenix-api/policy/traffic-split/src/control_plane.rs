@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+/// What to do with the requests selected for the destination connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitMode {
+    /// Send the requests to the destination instead, the replies come from the destination.
+    Canary,
+    /// Send a copy of the requests to the destination as well, its replies are discarded.
+    Shadow,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// service, mode, percentage, destination
+    NewConfig(String, SplitMode, f64, Option<u64>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
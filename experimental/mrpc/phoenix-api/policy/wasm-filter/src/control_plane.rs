@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+type IResult<T> = Result<T, phoenix_api::Error>;
+
+/// What to do with a message when the filter traps, e.g., runs out of fuel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnTrap {
+    /// Pass the message on as if the filter accepted it.
+    Accept,
+    /// Reject the message as if the filter rejected it.
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Loads the filter from the module at the path, and starts a new instance of it. The
+    /// current filter is kept if the module fails to load.
+    LoadModule(PathBuf),
+    /// fuel, on_trap
+    NewConfig(u64, OnTrap),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseKind {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response(pub IResult<ResponseKind>);
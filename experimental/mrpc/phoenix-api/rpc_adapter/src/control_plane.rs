@@ -1,12 +1,57 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 
+use phoenix_api::Handle;
+
 type IResult<T> = Result<T, phoenix_api::Error>;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
     ListConnection,
+    // Log the counters of the send queue of the engine
+    TxQueueStats,
+    // Send the events of a connection, or of all the connections if none is given, to the
+    // datagram socket at the path as they happen, starting with the ones kept by the engine. The
+    // events are sent as bincode-encoded `ConnEvent`s until the socket is gone.
+    TailConnLog(Option<Handle>, PathBuf),
+    // Log the usage of each slab of receive buffers, and the buffers held by the applications for
+    // the duration or longer with the connections they are delivered on
+    BufferUsage(Duration),
+    // Move a connection accepted from a shared listener, with its credits, buffers, and the
+    // messages in flight, to the engine of the application with the rpc_adapter_id, as listed
+    // by `ListConnection`. The engine must have joined the listener.
+    MigrateConnection(Handle, usize),
+}
+
+/// An event of a connection, see `Request::TailConnLog`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnEvent {
+    pub conn_id: Handle,
+    pub at: SystemTime,
+    pub kind: ConnEventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ConnEventKind {
+    /// The connection to the peer is established
+    Connected(SocketAddr),
+    /// The connection from the peer is accepted
+    Accepted(SocketAddr),
+    /// Sending is held back until the peer returns credits, with the credits left
+    CreditStall(usize),
+    /// Sending resumes after a credit stall
+    CreditResumed,
+    /// A work completion failed, with its status and opcode
+    WcError(String),
+    /// Receive buffers are returned to the connection
+    Reclaimed(usize),
+    /// The connection is moved to another engine of the application, with its rpc_adapter_id
+    Migrated(usize),
+    /// The connection is gone
+    Disconnected,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
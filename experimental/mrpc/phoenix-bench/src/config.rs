@@ -0,0 +1,80 @@
+//! Benchmark configuration.
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How the open-loop generator spaces the requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arrival {
+    /// Requests are sent at a fixed interval.
+    Uniform,
+    /// Inter-arrival times are exponentially distributed.
+    Poisson,
+}
+
+impl Default for Arrival {
+    fn default() -> Self {
+        Arrival::Uniform
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BenchConfig {
+    /// The maximal number of outstanding requests. The closed-loop generator keeps exactly this
+    /// many requests in flight. The open-loop generator delays requests beyond this limit, and
+    /// the delay counts towards their latency.
+    pub concurrency: usize,
+    /// The number of requests that are completed before measuring.
+    pub warmup: usize,
+    /// The minimal time spent in warmup, in seconds.
+    pub warmup_secs: Option<f64>,
+    /// The number of requests to measure. Ignored when `duration_secs` is set.
+    pub total_iters: usize,
+    /// Measure for a period of seconds rather than a number of requests.
+    pub duration_secs: Option<f64>,
+    /// Seconds between periodic reports.
+    pub report_interval_secs: Option<f64>,
+    /// The request rate of the open-loop generator, in requests per second.
+    pub rate: Option<f64>,
+    /// The arrival process of the open-loop generator.
+    pub arrival: Arrival,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            concurrency: 32,
+            warmup: 1000,
+            warmup_secs: None,
+            total_iters: 16384,
+            duration_secs: None,
+            report_interval_secs: None,
+            rate: None,
+            arrival: Arrival::Uniform,
+        }
+    }
+}
+
+impl BenchConfig {
+    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
+        let config = toml::from_str(config.unwrap_or(""))?;
+        Ok(config)
+    }
+
+    #[inline]
+    pub(crate) fn warmup_duration(&self) -> Option<Duration> {
+        self.warmup_secs.map(Duration::from_secs_f64)
+    }
+
+    #[inline]
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        self.duration_secs.map(Duration::from_secs_f64)
+    }
+
+    #[inline]
+    pub(crate) fn report_interval(&self) -> Option<Duration> {
+        self.report_interval_secs.map(Duration::from_secs_f64)
+    }
+}
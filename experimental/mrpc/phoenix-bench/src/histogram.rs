@@ -0,0 +1,124 @@
+//! Latency recording with HDR histograms.
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+
+/// The largest latency that can be recorded, larger values are clamped.
+const MAX_LATENCY_NS: u64 = 60_000_000_000;
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+/// Records latencies in nanoseconds.
+#[derive(Debug, Clone)]
+pub struct LatencyRecorder {
+    hist: Histogram<u64>,
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        LatencyRecorder {
+            hist: Histogram::new_with_max(MAX_LATENCY_NS, SIGNIFICANT_DIGITS).unwrap(),
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, dura: Duration) {
+        self.hist.saturating_record(dura.as_nanos() as u64);
+    }
+
+    /// Adds all samples of `other` to this recorder.
+    pub fn merge(&mut self, other: &LatencyRecorder) {
+        // Both histograms share the same bounds, so this never fails.
+        self.hist.add(&other.hist).unwrap();
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.hist.clear();
+    }
+
+    /// Returns the number of samples.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.hist.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.hist.is_empty()
+    }
+
+    /// Returns the latency at a percentile in `[0, 100]`.
+    #[inline]
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        Duration::from_nanos(self.hist.value_at_percentile(percentile))
+    }
+
+    #[inline]
+    pub fn histogram(&self) -> &Histogram<u64> {
+        &self.hist
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.hist.len(),
+            mean_ns: self.hist.mean() as u64,
+            min_ns: self.hist.min(),
+            p50_ns: self.hist.value_at_percentile(50.0),
+            p90_ns: self.hist.value_at_percentile(90.0),
+            p99_ns: self.hist.value_at_percentile(99.0),
+            p999_ns: self.hist.value_at_percentile(99.9),
+            max_ns: self.hist.max(),
+        }
+    }
+}
+
+/// A summary of the recorded latencies, in nanoseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_ns: u64,
+    pub min_ns: u64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p99_ns: u64,
+    pub p999_ns: u64,
+    pub max_ns: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_and_merge() {
+        let mut a = LatencyRecorder::new();
+        let mut b = LatencyRecorder::new();
+        for i in 1..=1000 {
+            a.record(Duration::from_micros(i));
+            b.record(Duration::from_micros(1000 + i));
+        }
+        a.merge(&b);
+        let summary = a.summary();
+        assert_eq!(summary.count, 2000);
+        assert_eq!(summary.min_ns, 1_000);
+        // 3 significant digits
+        assert!(summary.p50_ns.abs_diff(1_000_000) <= 1_000);
+        assert!(summary.p999_ns.abs_diff(1_998_000) <= 2_000);
+        assert!(summary.max_ns.abs_diff(2_000_000) <= 2_000);
+    }
+
+    #[test]
+    fn clamp_large_latency() {
+        let mut recorder = LatencyRecorder::new();
+        recorder.record(Duration::from_secs(3600));
+        assert_eq!(recorder.len(), 1);
+        assert!(recorder.percentile(100.0) <= Duration::from_secs(61));
+    }
+}
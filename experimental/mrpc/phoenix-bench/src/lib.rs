@@ -0,0 +1,39 @@
+//! Benchmark harness for mRPC applications.
+//!
+//! This crate provides the pieces that every benchmark client used to carry its own copy of:
+//!
+//! - [`Bench`], a closed-loop and an open-loop load generator that works with any generated
+//!   client, since a call is just a closure that returns a future;
+//! - [`LatencyRecorder`], an HDR histogram of latencies that reports p50/p99/p999;
+//! - warmup handling, periodic reports, and [`BenchResult`], a machine-readable JSON result;
+//! - [`Tracer`], named latency recorders for instrumenting services.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! let config = BenchConfig {
+//!     concurrency: 32,
+//!     ..Default::default()
+//! };
+//! let req = WRef::new(HelloRequest { name });
+//! let result = smol::block_on(Bench::new(config).closed_loop(|_| {
+//!     let fut = client.say_hello(WRef::clone(&req));
+//!     async move { fut.await.map(|_| req_size) }
+//! }));
+//! println!("{}", result);
+//! result.write_json("result.json")?;
+//! ```
+pub mod config;
+pub use config::{Arrival, BenchConfig};
+
+pub mod histogram;
+pub use histogram::{LatencyRecorder, LatencySummary};
+
+pub mod load;
+pub use load::{Bench, IntervalStats};
+
+pub mod report;
+pub use report::BenchResult;
+
+pub mod tracer;
+pub use tracer::Tracer;
@@ -0,0 +1,321 @@
+//! Closed-loop and open-loop load generators.
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use minstant::Instant;
+
+use crate::config::{Arrival, BenchConfig};
+use crate::histogram::LatencyRecorder;
+use crate::report::BenchResult;
+
+/// Statistics of one reporting interval.
+#[derive(Debug, Clone, Copy)]
+pub struct IntervalStats {
+    /// The length of the interval.
+    pub elapsed: Duration,
+    /// Requests per second.
+    pub rps: f64,
+    /// Bandwidth in Gb/s.
+    pub gbps: f64,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+type Observer = Box<dyn FnMut(&IntervalStats)>;
+
+/// A load generator.
+///
+/// A request is issued by calling the closure passed to [`closed_loop`] or [`open_loop`] with
+/// the sequence number of the request. The returned future resolves to the number of bytes
+/// transferred, or to an error which is counted and logged.
+///
+/// [`closed_loop`]: Bench::closed_loop
+/// [`open_loop`]: Bench::open_loop
+pub struct Bench {
+    config: BenchConfig,
+    observer: Option<Observer>,
+}
+
+impl Bench {
+    pub fn new(config: BenchConfig) -> Self {
+        Bench {
+            config,
+            observer: None,
+        }
+    }
+
+    /// Sets a callback that is called every `report_interval_secs` after warmup. It prints the
+    /// statistics with `tracing::info!` by default.
+    pub fn on_interval<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&IntervalStats) + 'static,
+    {
+        self.observer = Some(Box::new(f));
+        self
+    }
+
+    #[inline]
+    pub fn config(&self) -> &BenchConfig {
+        &self.config
+    }
+
+    /// Keeps `concurrency` requests in flight. A new request is issued as soon as one completes.
+    pub async fn closed_loop<F, Fut, E>(&mut self, mut make_call: F) -> BenchResult
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = Result<usize, E>>,
+        E: Display,
+    {
+        let mut progress = Progress::new(&self.config, self.observer.as_mut());
+        let mut inflight = FuturesUnordered::new();
+        let mut issued = 0;
+
+        while inflight.len() < self.config.concurrency && progress.wants_more(issued) {
+            inflight.push(timed(Instant::now(), make_call(issued)));
+            issued += 1;
+        }
+
+        while let Some((start, result)) = inflight.next().await {
+            progress.complete(start, result);
+            if progress.is_done() {
+                break;
+            }
+            if progress.wants_more(issued) {
+                inflight.push(timed(Instant::now(), make_call(issued)));
+                issued += 1;
+            }
+        }
+
+        progress.finish("closed_loop")
+    }
+
+    /// Issues requests at `rate` regardless of the completions.
+    ///
+    /// The latency of a request is measured from the time it is scheduled rather than the time
+    /// it is actually issued, so that queueing delays are not hidden (coordinated omission).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not set in the config.
+    pub async fn open_loop<F, Fut, E>(&mut self, mut make_call: F) -> BenchResult
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = Result<usize, E>>,
+        E: Display,
+    {
+        let rate = self.config.rate.expect("open_loop requires a rate");
+        assert!(rate > 0.0, "rate must be positive");
+        let arrival = self.config.arrival;
+        let concurrency = self.config.concurrency;
+
+        let mut progress = Progress::new(&self.config, self.observer.as_mut());
+        let mut inflight = FuturesUnordered::new();
+        let mut issued = 0;
+        let mut next_send = Instant::now();
+
+        loop {
+            let now = Instant::now();
+            while next_send <= now && inflight.len() < concurrency && progress.wants_more(issued) {
+                inflight.push(timed(next_send, make_call(issued)));
+                issued += 1;
+                next_send = next_send + inter_arrival(arrival, rate);
+            }
+
+            match inflight.next().now_or_never() {
+                Some(Some((start, result))) => {
+                    progress.complete(start, result);
+                    if progress.is_done() {
+                        break;
+                    }
+                }
+                Some(None) if !progress.wants_more(issued) => break,
+                _ => YieldNow(false).await,
+            }
+        }
+
+        progress.finish("open_loop")
+    }
+}
+
+fn inter_arrival(arrival: Arrival, rate: f64) -> Duration {
+    match arrival {
+        Arrival::Uniform => Duration::from_secs_f64(1.0 / rate),
+        Arrival::Poisson => Duration::from_secs_f64(-(1.0 - fastrand::f64()).ln() / rate),
+    }
+}
+
+fn timed<Fut: Future>(start: Instant, fut: Fut) -> impl Future<Output = (Instant, Fut::Output)> {
+    async move { (start, fut.await) }
+}
+
+/// Yields to the executor once.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Tracks warmup, termination, and the measurements of a run.
+struct Progress<'a> {
+    warmup: usize,
+    warmup_duration: Option<Duration>,
+    total_iters: usize,
+    duration: Option<Duration>,
+    report_interval: Option<Duration>,
+    observer: Option<&'a mut Observer>,
+
+    start: Instant,
+    // Set when the warmup finishes.
+    measure_start: Option<Instant>,
+    // The number of completions during warmup.
+    warmup_done: usize,
+
+    recorder: LatencyRecorder,
+    completed: u64,
+    errors: u64,
+    bytes: u64,
+
+    last_report: Instant,
+    interval_recorder: LatencyRecorder,
+    interval_completed: u64,
+    interval_bytes: u64,
+}
+
+impl<'a> Progress<'a> {
+    fn new(config: &BenchConfig, observer: Option<&'a mut Observer>) -> Self {
+        let now = Instant::now();
+        let no_warmup = config.warmup == 0 && config.warmup_secs.is_none();
+        Progress {
+            warmup: config.warmup,
+            warmup_duration: config.warmup_duration(),
+            total_iters: config.total_iters,
+            duration: config.duration(),
+            report_interval: config.report_interval(),
+            observer,
+            start: now,
+            measure_start: no_warmup.then_some(now),
+            warmup_done: 0,
+            recorder: LatencyRecorder::new(),
+            completed: 0,
+            errors: 0,
+            bytes: 0,
+            last_report: now,
+            interval_recorder: LatencyRecorder::new(),
+            interval_completed: 0,
+            interval_bytes: 0,
+        }
+    }
+
+    /// Whether the request with sequence number `issued` should be issued.
+    #[inline]
+    fn wants_more(&self, issued: usize) -> bool {
+        if self.measure_start.is_none() || self.duration.is_some() {
+            return !self.is_done();
+        }
+        issued < self.warmup_done + self.total_iters
+    }
+
+    #[inline]
+    fn is_done(&self) -> bool {
+        match (self.measure_start, self.duration) {
+            (None, _) => false,
+            (Some(start), Some(duration)) => start.elapsed() >= duration,
+            (Some(_), None) => (self.completed + self.errors) as usize >= self.total_iters,
+        }
+    }
+
+    fn complete<E: Display>(&mut self, start: Instant, result: Result<usize, E>) {
+        let latency = start.elapsed();
+
+        if self.measure_start.is_none() {
+            self.warmup_done += 1;
+            let warmup_elapsed = self
+                .warmup_duration
+                .map_or(true, |d| self.start.elapsed() >= d);
+            if self.warmup_done >= self.warmup && warmup_elapsed {
+                let now = Instant::now();
+                self.measure_start = Some(now);
+                self.last_report = now;
+            }
+            return;
+        }
+
+        match result {
+            Ok(nbytes) => {
+                self.recorder.record(latency);
+                self.completed += 1;
+                self.bytes += nbytes as u64;
+                if self.report_interval.is_some() {
+                    self.interval_recorder.record(latency);
+                    self.interval_completed += 1;
+                    self.interval_bytes += nbytes as u64;
+                }
+            }
+            Err(e) => {
+                tracing::warn!("failed request with: {}", e);
+                self.errors += 1;
+            }
+        }
+
+        self.maybe_report();
+    }
+
+    fn maybe_report(&mut self) {
+        let interval = match self.report_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let elapsed = self.last_report.elapsed();
+        if elapsed < interval {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        let stats = IntervalStats {
+            elapsed,
+            rps: self.interval_completed as f64 / secs,
+            gbps: 8e-9 * self.interval_bytes as f64 / secs,
+            p50: self.interval_recorder.percentile(50.0),
+            p99: self.interval_recorder.percentile(99.0),
+        };
+        match self.observer.as_mut() {
+            Some(observer) => (**observer)(&stats),
+            None => tracing::info!(
+                "{} rps, {} Gb/s, p50: {:?}, p99: {:?}",
+                stats.rps,
+                stats.gbps,
+                stats.p50,
+                stats.p99
+            ),
+        }
+        self.last_report = Instant::now();
+        self.interval_recorder.clear();
+        self.interval_completed = 0;
+        self.interval_bytes = 0;
+    }
+
+    fn finish(self, mode: &str) -> BenchResult {
+        let dura = self.measure_start.map_or(Duration::ZERO, |s| s.elapsed());
+        BenchResult::new(
+            mode,
+            dura,
+            self.completed,
+            self.errors,
+            self.bytes,
+            self.recorder,
+        )
+    }
+}
@@ -0,0 +1,113 @@
+//! Benchmark results.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::histogram::{LatencyRecorder, LatencySummary};
+
+/// The result of a benchmark run. All numbers exclude the warmup.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchResult {
+    /// `closed_loop` or `open_loop`.
+    pub mode: String,
+    /// The time spent measuring, in seconds.
+    pub duration_secs: f64,
+    /// The number of successful requests.
+    pub completed: u64,
+    /// The number of failed requests.
+    pub errors: u64,
+    /// The number of bytes reported by the successful requests.
+    pub bytes: u64,
+    /// Requests per second.
+    pub rps: f64,
+    /// Bandwidth in Gb/s.
+    pub gbps: f64,
+    pub latency: LatencySummary,
+    #[serde(skip)]
+    recorder: LatencyRecorder,
+}
+
+impl BenchResult {
+    pub(crate) fn new(
+        mode: &str,
+        dura: Duration,
+        completed: u64,
+        errors: u64,
+        bytes: u64,
+        recorder: LatencyRecorder,
+    ) -> Self {
+        let secs = dura.as_secs_f64();
+        let per_sec = |x: f64| if secs > 0.0 { x / secs } else { 0.0 };
+        BenchResult {
+            mode: mode.to_owned(),
+            duration_secs: secs,
+            completed,
+            errors,
+            bytes,
+            rps: per_sec(completed as f64),
+            gbps: per_sec(8e-9 * bytes as f64),
+            latency: recorder.summary(),
+            recorder,
+        }
+    }
+
+    /// Combines the results of concurrent runs, e.g., one for each client thread.
+    pub fn merge(results: &[BenchResult]) -> BenchResult {
+        let mut merged = BenchResult::default();
+        for result in results {
+            if merged.mode.is_empty() {
+                merged.mode = result.mode.clone();
+            }
+            merged.duration_secs = merged.duration_secs.max(result.duration_secs);
+            merged.completed += result.completed;
+            merged.errors += result.errors;
+            merged.bytes += result.bytes;
+            merged.rps += result.rps;
+            merged.gbps += result.gbps;
+            merged.recorder.merge(&result.recorder);
+        }
+        merged.latency = merged.recorder.summary();
+        merged
+    }
+
+    /// Returns the latency recorder of the measured requests. This is empty for results that
+    /// are deserialized.
+    #[inline]
+    pub fn recorder(&self) -> &LatencyRecorder {
+        &self.recorder
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("BenchResult is serializable")
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ns = Duration::from_nanos;
+        write!(
+            f,
+            "duration: {:?}, completed: {}, errors: {}, rate: {:.5} Mrps, bandwidth: {:.5} Gb/s, \
+            avg: {:?}, min: {:?}, p50: {:?}, p99: {:?}, p999: {:?}, max: {:?}",
+            Duration::from_secs_f64(self.duration_secs),
+            self.completed,
+            self.errors,
+            1e-6 * self.rps,
+            self.gbps,
+            ns(self.latency.mean_ns),
+            ns(self.latency.min_ns),
+            ns(self.latency.p50_ns),
+            ns(self.latency.p99_ns),
+            ns(self.latency.p999_ns),
+            ns(self.latency.max_ns),
+        )
+    }
+}
@@ -0,0 +1,96 @@
+//! Named latency recorders for instrumenting services.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::histogram::{LatencyRecorder, LatencySummary};
+
+#[derive(Debug, Clone, Copy, Error)]
+pub enum Error {
+    #[error("Entry not found")]
+    EntryNotFound,
+}
+
+/// Records the processing latency of handlers and the end-to-end latency of calls to other
+/// services, both by entry name.
+#[derive(Debug, Default)]
+pub struct Tracer {
+    proc_latency: BTreeMap<String, LatencyRecorder>,
+    end_to_end_latency: BTreeMap<String, LatencyRecorder>,
+}
+
+#[derive(Serialize)]
+struct TracerSummary<'a> {
+    proc: BTreeMap<&'a str, LatencySummary>,
+    end_to_end: BTreeMap<&'a str, LatencySummary>,
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Self::default()
+    }
+
+    pub fn new_proc_entry(&mut self, entry: impl AsRef<str>) {
+        self.proc_latency
+            .insert(entry.as_ref().to_owned(), LatencyRecorder::new());
+    }
+
+    pub fn new_end_to_end_entry(&mut self, entry: impl AsRef<str>) {
+        self.end_to_end_latency
+            .insert(entry.as_ref().to_owned(), LatencyRecorder::new());
+    }
+
+    pub fn record_proc(&mut self, entry: impl AsRef<str>, dura: Duration) -> Result<(), Error> {
+        self.proc_latency
+            .get_mut(entry.as_ref())
+            .ok_or(Error::EntryNotFound)?
+            .record(dura);
+        Ok(())
+    }
+
+    pub fn record_end_to_end(
+        &mut self,
+        entry: impl AsRef<str>,
+        dura: Duration,
+    ) -> Result<(), Error> {
+        self.end_to_end_latency
+            .get_mut(entry.as_ref())
+            .ok_or(Error::EntryNotFound)?
+            .record(dura);
+        Ok(())
+    }
+
+    pub fn proc_entry(&self, entry: impl AsRef<str>) -> Option<&LatencyRecorder> {
+        self.proc_latency.get(entry.as_ref())
+    }
+
+    pub fn end_to_end_entry(&self, entry: impl AsRef<str>) -> Option<&LatencyRecorder> {
+        self.end_to_end_latency.get(entry.as_ref())
+    }
+
+    /// Returns the latency summaries of all entries as JSON.
+    pub fn to_json(&self) -> String {
+        let summary = TracerSummary {
+            proc: self
+                .proc_latency
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.summary()))
+                .collect(),
+            end_to_end: self
+                .end_to_end_latency
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.summary()))
+                .collect(),
+        };
+        serde_json::to_string_pretty(&summary).expect("TracerSummary is serializable")
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
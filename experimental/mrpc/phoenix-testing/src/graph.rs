@@ -0,0 +1,246 @@
+//! Data path graphs of engines built by hand, for the integration tests of engines that work
+//! together, e.g., a policy engine between a test client and an engine it throttles, without
+//! the modules and services that build the graphs in the runtime.
+//!
+//! A [`GraphBuilder`] takes the channels between the engines as [`ChannelDescriptor`]s, like the
+//! `tx_channels` and `rx_channels` of a service, and creates the [`DataPathNode`] of each engine
+//! on the ends. The nodes of the engines under test are built into engines and polled by a
+//! [`Runner`]; the nodes left over are the ends of the test, which sends and receives on their
+//! queues directly.
+//!
+//! ```ignore
+//! const CLIENT: EngineType = EngineType("Client");
+//! const POLICY: EngineType = EngineType("RateLimitEngine");
+//! const SINK: EngineType = EngineType("Sink");
+//!
+//! let mut nodes = GraphBuilder::new()
+//!     .tx(ChannelDescriptor(CLIENT, POLICY, 0, 0))
+//!     .tx(ChannelDescriptor(POLICY, SINK, 0, 0))
+//!     .rx(ChannelDescriptor(SINK, POLICY, 0, 0))
+//!     .rx(ChannelDescriptor(POLICY, CLIENT, 0, 0))
+//!     .build()?;
+//! let mut runner = Runner::new();
+//! runner.spawn(build_policy(nodes.take(POLICY)));
+//! let mut client = nodes.take(CLIENT);
+//! client.tx_outputs[0].send(EngineTxMessage::RpcMessage(msg))?;
+//! runner.run(10);
+//! ```
+//!
+//! All the channels are sequential, as the engines of a scheduling group, so the engines and the
+//! test run on the same thread.
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use futures::task::noop_waker_ref;
+
+use phoenix_common::engine::datapath::{
+    create_channel, ChannelDescriptor, ChannelFlavor, DataPathNode,
+};
+use phoenix_common::engine::{Engine, EngineResult, EngineType};
+
+/// The channels between the engines of a graph.
+#[derive(Debug, Clone, Default)]
+pub struct GraphBuilder {
+    tx_channels: Vec<ChannelDescriptor>,
+    rx_channels: Vec<ChannelDescriptor>,
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a channel on the tx path, from a tx output of the sender to a tx input of the
+    /// receiver.
+    pub fn tx(mut self, channel: ChannelDescriptor) -> Self {
+        self.tx_channels.push(channel);
+        self
+    }
+
+    /// Adds a channel on the rx path, from an rx output of the sender to an rx input of the
+    /// receiver.
+    pub fn rx(mut self, channel: ChannelDescriptor) -> Self {
+        self.rx_channels.push(channel);
+        self
+    }
+
+    /// Creates the channels, and the data path node of each engine on their ends.
+    ///
+    /// Fails if the indices of the queues of an engine are taken twice, or leave a gap, as the
+    /// runtime does.
+    pub fn build(self) -> Result<Nodes> {
+        let mut tx_inputs = HashMap::new();
+        let mut tx_outputs = HashMap::new();
+        for ChannelDescriptor(sender, receiver, output, input) in self.tx_channels {
+            let (tx, rx) = create_channel(ChannelFlavor::Sequential);
+            add_endpoint(&mut tx_outputs, sender, output, tx);
+            add_endpoint(&mut tx_inputs, receiver, input, rx);
+        }
+        let mut rx_inputs = HashMap::new();
+        let mut rx_outputs = HashMap::new();
+        for ChannelDescriptor(sender, receiver, output, input) in self.rx_channels {
+            let (tx, rx) = create_channel(ChannelFlavor::Sequential);
+            add_endpoint(&mut rx_outputs, sender, output, tx);
+            add_endpoint(&mut rx_inputs, receiver, input, rx);
+        }
+
+        let mut nodes: HashMap<EngineType, DataPathNode> = HashMap::new();
+        for (engine, queues) in tx_inputs {
+            nodes
+                .entry(engine)
+                .or_insert_with(DataPathNode::new)
+                .tx_inputs = sorted("tx input", engine, queues)?;
+        }
+        for (engine, queues) in tx_outputs {
+            nodes
+                .entry(engine)
+                .or_insert_with(DataPathNode::new)
+                .tx_outputs = sorted("tx output", engine, queues)?;
+        }
+        for (engine, queues) in rx_inputs {
+            nodes
+                .entry(engine)
+                .or_insert_with(DataPathNode::new)
+                .rx_inputs = sorted("rx input", engine, queues)?;
+        }
+        for (engine, queues) in rx_outputs {
+            nodes
+                .entry(engine)
+                .or_insert_with(DataPathNode::new)
+                .rx_outputs = sorted("rx output", engine, queues)?;
+        }
+        Ok(Nodes(nodes))
+    }
+}
+
+fn add_endpoint<T>(
+    endpoints: &mut HashMap<EngineType, Vec<(usize, T)>>,
+    engine: EngineType,
+    index: usize,
+    queue: T,
+) {
+    endpoints.entry(engine).or_default().push((index, queue));
+}
+
+/// Orders the queues of an engine by their indices, which must be 0, 1, 2, ...
+fn sorted<T>(what: &str, engine: EngineType, mut queues: Vec<(usize, T)>) -> Result<Vec<T>> {
+    queues.sort_by_key(|(index, _)| *index);
+    for (expected, (index, _)) in queues.iter().enumerate() {
+        if *index != expected {
+            bail!(
+                "the {} indices of {:?} are not contiguous, expected {}, found {}",
+                what,
+                engine,
+                expected,
+                index
+            );
+        }
+    }
+    Ok(queues.into_iter().map(|(_, queue)| queue).collect())
+}
+
+/// The data path nodes of a graph, by their engines.
+pub struct Nodes(HashMap<EngineType, DataPathNode>);
+
+impl Nodes {
+    /// Takes the node of `engine`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `engine` is on none of the channels, or its node is taken already.
+    pub fn take(&mut self, engine: EngineType) -> DataPathNode {
+        self.0
+            .remove(&engine)
+            .unwrap_or_else(|| panic!("no data path node of {:?}", engine))
+    }
+
+    /// The engines whose nodes are not taken yet.
+    pub fn engines(&self) -> impl Iterator<Item = EngineType> + '_ {
+        self.0.keys().copied()
+    }
+}
+
+struct Running {
+    // NOTE: the future borrows the engine, and must be dropped first.
+    future: BoxFuture<'static, EngineResult>,
+    _engine: Pin<Box<dyn Engine>>,
+    result: Option<EngineResult>,
+}
+
+/// Polls a set of engines on the calling thread, in the order they are spawned, like the
+/// runtime polls the engines of a scheduling group.
+#[derive(Default)]
+pub struct Runner {
+    engines: Vec<Running>,
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an engine, which is polled from the next round on. Returns its index in the runner.
+    pub fn spawn<E: Engine>(&mut self, engine: E) -> usize {
+        let mut engine: Pin<Box<dyn Engine>> = Box::pin(engine);
+        let future = engine.as_mut().activate();
+        // SAFETY: the engine is pinned on the heap, and the future is dropped before it, like in
+        // the containers of the runtime.
+        let future = unsafe {
+            std::mem::transmute::<BoxFuture<'_, EngineResult>, BoxFuture<'static, EngineResult>>(
+                future,
+            )
+        };
+        self.engines.push(Running {
+            future,
+            _engine: engine,
+            result: None,
+        });
+        self.engines.len() - 1
+    }
+
+    /// Polls each engine that has not finished once. Returns false if all of them have
+    /// finished.
+    pub fn poll(&mut self) -> bool {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut running = false;
+        for e in self.engines.iter_mut().filter(|e| e.result.is_none()) {
+            match e.future.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => e.result = Some(result),
+                Poll::Pending => running = true,
+            }
+        }
+        running
+    }
+
+    /// Polls the engines for `rounds` rounds, or until they have all finished.
+    pub fn run(&mut self, rounds: usize) {
+        for _ in 0..rounds {
+            if !self.poll() {
+                break;
+            }
+        }
+    }
+
+    /// Polls the engines until `done` returns true, for at most `rounds` rounds. Returns whether
+    /// `done` did.
+    pub fn run_until(&mut self, rounds: usize, mut done: impl FnMut() -> bool) -> bool {
+        for _ in 0..rounds {
+            if done() {
+                return true;
+            }
+            if !self.poll() {
+                break;
+            }
+        }
+        done()
+    }
+
+    /// The result of the engine at `index` if it has finished.
+    pub fn result(&self, index: usize) -> Option<&EngineResult> {
+        self.engines[index].result.as_ref()
+    }
+}
@@ -0,0 +1,199 @@
+//! An in-process phoenix daemon for the end-to-end tests of mRPC applications.
+//!
+//! [`TestDaemon::start`] runs the phoenix runtime with the salloc, mrpc and TCP adapter plugins
+//! on a thread of the test process, under a prefix directory of its own. Servers and clients of
+//! the same daemon talk over the loopback transport of the TCP adapter, so the tests need
+//! neither root, RDMA NICs nor a phoenix daemon started beforehand.
+//!
+//! ```ignore
+//! #[test]
+//! fn echo() {
+//!     phoenix_testing::TestDaemon::start();
+//!     // the stubs created from now on connect to the test daemon
+//!     let server = Server::bind("127.0.0.1:5000")?;
+//!     let client = GreeterClient::connect("127.0.0.1:5000")?;
+//! }
+//! ```
+//!
+//! The plugins are loaded from `PHOENIX_TEST_PLUGINS`, `/tmp/phoenix/plugins` by default, where
+//! `cargo make` deploys them. The dispatch libraries of the applications are built into
+//! `PHOENIX_TEST_BUILD_CACHE`, which is kept across the runs.
+//!
+//! With the `replay` feature, [`replay`] drives a single engine from a scripted trace instead,
+//! in virtual time, and with the `graph` feature, [`graph`] connects several engines by hand to
+//! test them together.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use phoenixos::Config;
+
+#[cfg(feature = "graph")]
+pub mod graph;
+#[cfg(feature = "replay")]
+pub mod replay;
+
+const DEFAULT_PLUGIN_DIR: &str = "/tmp/phoenix/plugins";
+const CONTROL_SOCK: &str = "control.sock";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The test daemon is never asked to exit, it goes away with the process.
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+/// The plugins of the test daemon, by their module names.
+const MODULES: &[(&str, &str)] = &[
+    ("TcpTransport", "libphoenix_transport_tcp.rlib"),
+    ("Salloc", "libphoenix_salloc.rlib"),
+    ("Mrpc", "libphoenix_mrpc.rlib"),
+    ("TcpRpcAdapter", "libphoenix_tcp_rpc_adapter.rlib"),
+];
+
+lazy_static::lazy_static! {
+    static ref DAEMON: TestDaemon = TestDaemon::launch();
+}
+
+/// A phoenix daemon running in the test process.
+///
+/// The clients find the daemon by `PHOENIX_PREFIX`, which they read once, so there is a single
+/// daemon for all tests of a process, started by the first call of [`TestDaemon::start`]. It
+/// runs until the process exits, and its prefix directory is left for inspection.
+#[derive(Debug)]
+pub struct TestDaemon {
+    prefix: PathBuf,
+    handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl TestDaemon {
+    /// Starts the daemon of the process, or returns it if it is already running. It must be
+    /// called before any mRPC stub is created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a plugin is missing or the daemon fails to start.
+    pub fn start() -> &'static TestDaemon {
+        &DAEMON
+    }
+
+    /// The prefix directory of the daemon, where the control socket and the shared memory files
+    /// are created.
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    /// Whether the daemon is still running.
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished()
+    }
+
+    fn launch() -> TestDaemon {
+        let prefix = env::temp_dir().join(format!("phoenix-test-{}", std::process::id()));
+        // the prefix of a previous process with the same pid
+        let _ = fs::remove_dir_all(&prefix);
+        fs::create_dir_all(&prefix)
+            .unwrap_or_else(|e| panic!("Cannot create the prefix {:?}: {}", prefix, e));
+
+        let plugin_dir = env::var_os("PHOENIX_TEST_PLUGINS")
+            .map_or_else(|| PathBuf::from(DEFAULT_PLUGIN_DIR), PathBuf::from);
+        let build_cache = env::var_os("PHOENIX_TEST_BUILD_CACHE").map_or_else(
+            || env::temp_dir().join("phoenix-test-build-cache"),
+            PathBuf::from,
+        );
+
+        let config_path = prefix.join("phoenix.toml");
+        fs::write(
+            &config_path,
+            config_string(&prefix, &plugin_dir, &build_cache),
+        )
+        .unwrap_or_else(|e| panic!("Cannot write {:?}: {}", config_path, e));
+        let config = Config::from_path(&config_path)
+            .unwrap_or_else(|e| panic!("Invalid test daemon config: {}", e));
+
+        // the clients of this process connect to the daemon
+        env::set_var("PHOENIX_PREFIX", &prefix);
+        env::set_var("PHOENIX_CONTROL", CONTROL_SOCK);
+
+        let log_filter = phoenixos::init_test_log(&config);
+        let handle = thread::Builder::new()
+            .name("phoenix-test-daemon".to_owned())
+            .spawn(move || phoenixos::run(config, config_path, log_filter, &TERMINATE))
+            .expect("Cannot spawn the test daemon");
+
+        let daemon = TestDaemon { prefix, handle };
+        daemon.wait_ready();
+        daemon
+    }
+
+    /// Waits for the control socket to be bound. The requests sent after that are queued in the
+    /// socket until the plugins are loaded.
+    fn wait_ready(&self) {
+        let sock = self.prefix.join(CONTROL_SOCK);
+        let start = Instant::now();
+        while !sock.exists() {
+            assert!(
+                !self.handle.is_finished(),
+                "The test daemon exited during startup, see the log above"
+            );
+            assert!(
+                start.elapsed() < STARTUP_TIMEOUT,
+                "The test daemon did not start in {:?}",
+                STARTUP_TIMEOUT
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn config_string(prefix: &Path, plugin_dir: &Path, build_cache: &Path) -> String {
+    let mut config = format!(
+        r#"log_level = "info"
+
+[tracing]
+enable = false
+min_event_level = "trace"
+max_event_level = "trace"
+span_level = "info"
+output_dir = "{prefix}/trace"
+
+[profiling]
+enable_on_new_client = false
+duration_ms = 1000
+
+[control]
+prefix = "{prefix}"
+path = "{CONTROL_SOCK}"
+
+[linker]
+workdir = "{prefix}/linker"
+"#,
+        prefix = prefix.display(),
+    );
+    for (name, file) in MODULES {
+        let lib_path = plugin_dir.join(file);
+        assert!(
+            lib_path.is_file(),
+            "Plugin {} is not found at {:?}, build and deploy the plugins with `cargo make`, \
+             or set PHOENIX_TEST_PLUGINS to where they are",
+            name,
+            lib_path
+        );
+        config.push_str(&format!(
+            "\n[[modules]]\nname = \"{}\"\nlib_path = \"{}\"\n",
+            name,
+            lib_path.display()
+        ));
+    }
+    config.push_str(&format!(
+        r#"
+[module_config.Mrpc]
+prefix = "{}"
+build_cache = "{}"
+transport = "Tcp"
+"#,
+        prefix.display(),
+        build_cache.display(),
+    ));
+    config
+}
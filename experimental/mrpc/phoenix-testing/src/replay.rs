@@ -0,0 +1,364 @@
+//! A harness to replay a scripted trace against a single engine, and check its outputs step by
+//! step, without a runtime, other engines or hardware.
+//!
+//! The [`Harness`] connects the data path queues of the engine to its own ends, and polls the
+//! future of the engine on the calling thread, each poll running one iteration of the mainloop.
+//! A [`Step`] of the trace feeds a message to an input queue, advances the virtual time, polls the
+//! engine, or expects the next message on an output queue. The inputs that are specific to an
+//! engine, e.g., its commands or the completions of a simulated transport, are fed by
+//! [`Step::Inject`] through the handles the test kept when it built the engine.
+//!
+//! ```ignore
+//! let mut harness = Harness::new(Ports::default(), |node| build_engine(node));
+//! let msg = harness.message(meta, 0, harness.now() + 1000);
+//! harness.run_trace([
+//!     Step::Advance(Duration::from_micros(2)),
+//!     Step::Tx(0, EngineTxMessage::RpcMessage(msg)),
+//!     Step::expect_rx(0, "an ack of the expired call", |m| {
+//!         matches!(m, EngineRxMessage::Ack(_, s) if *s == TransportStatus::DEADLINE_EXCEEDED)
+//!     }),
+//!     Step::ExpectIdle,
+//! ]);
+//! ```
+//!
+//! Runs are deterministic as long as the engine reads the time through `monotonic_ns`, which
+//! returns the virtual time of the harness, and draws its random numbers from `fastrand`, which
+//! is seeded with the same value for each harness. An engine that reads `Instant` directly still
+//! sees the real time.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::task::noop_waker_ref;
+
+use phoenix_api::rpc::{virtual_clock, CallId, MessageMeta, RpcId};
+use phoenix_common::engine::datapath::channel::{
+    create_channel, ChannelFlavor, Receiver, Sender, TryRecvError,
+};
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+use phoenix_common::engine::datapath::node::{RxIQueue, RxOQueue, TxIQueue, TxOQueue};
+use phoenix_common::engine::datapath::RpcMessageTx;
+use phoenix_common::engine::datapath::{DataPathNode, EngineRxMessage, EngineTxMessage};
+use phoenix_common::engine::{Engine, EngineResult};
+
+/// The virtual time when a harness starts, in nanoseconds. It is not 0, which means no deadline.
+pub const START_NS: u64 = 1_000_000_000;
+
+/// The seed of `fastrand` for each harness.
+pub const SEED: u64 = 0x5eed;
+
+/// How many times an expectation polls the engine for the message to come.
+pub const EXPECT_POLLS: usize = 100_000;
+
+/// The number of meta buffers [`Harness::message`] can hand out.
+const META_BUFFER_POOL_CAP: usize = 128;
+
+/// The number of the data path queues of the engine.
+#[derive(Debug, Clone, Copy)]
+pub struct Ports {
+    pub tx_inputs: usize,
+    pub tx_outputs: usize,
+    pub rx_inputs: usize,
+    pub rx_outputs: usize,
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Ports {
+            tx_inputs: 1,
+            tx_outputs: 1,
+            rx_inputs: 1,
+            rx_outputs: 1,
+        }
+    }
+}
+
+/// What an output message is expected to be.
+pub struct Expect<T> {
+    what: String,
+    matches: Box<dyn FnOnce(&T) -> bool>,
+}
+
+impl<T> Expect<T> {
+    /// Describes the expected message with `what`, for the failures.
+    pub fn new(what: impl Into<String>, matches: impl FnOnce(&T) -> bool + 'static) -> Self {
+        Expect {
+            what: what.into(),
+            matches: Box::new(matches),
+        }
+    }
+}
+
+/// A step of a trace.
+pub enum Step {
+    /// Sends a message to a tx input queue.
+    Tx(usize, EngineTxMessage),
+    /// Sends a message to an rx input queue.
+    Rx(usize, EngineRxMessage),
+    /// Feeds the inputs specific to the engine.
+    Inject(Box<dyn FnOnce()>),
+    /// Advances the virtual time.
+    Advance(Duration),
+    /// Polls the engine the number of times, or until it finishes.
+    Run(usize),
+    /// Expects the next message of a tx output queue, polling the engine until it comes.
+    ExpectTx(usize, Expect<EngineTxMessage>),
+    /// Expects the next message of an rx output queue, polling the engine until it comes.
+    ExpectRx(usize, Expect<EngineRxMessage>),
+    /// Expects all the output queues to be empty.
+    ExpectIdle,
+}
+
+impl Step {
+    pub fn inject(f: impl FnOnce() + 'static) -> Self {
+        Step::Inject(Box::new(f))
+    }
+
+    pub fn expect_tx(
+        port: usize,
+        what: impl Into<String>,
+        matches: impl FnOnce(&EngineTxMessage) -> bool + 'static,
+    ) -> Self {
+        Step::ExpectTx(port, Expect::new(what, matches))
+    }
+
+    pub fn expect_rx(
+        port: usize,
+        what: impl Into<String>,
+        matches: impl FnOnce(&EngineRxMessage) -> bool + 'static,
+    ) -> Self {
+        Step::ExpectRx(port, Expect::new(what, matches))
+    }
+}
+
+/// An engine driven by a trace. The virtual time is set on the calling thread while the harness
+/// lives, so a thread runs one harness at a time.
+pub struct Harness<E: Engine> {
+    // NOTE: the future borrows the engine, and must be dropped first.
+    future: BoxFuture<'static, EngineResult>,
+    _engine: Pin<Box<E>>,
+    tx_inputs: Vec<TxOQueue>,
+    tx_outputs: Vec<TxIQueue>,
+    rx_inputs: Vec<RxOQueue>,
+    rx_outputs: Vec<RxIQueue>,
+    meta_buf_pool: MetaBufferPool,
+    next_call_id: u64,
+    // the number of steps run
+    steps: usize,
+    result: Option<EngineResult>,
+}
+
+impl<E: Engine> Harness<E> {
+    /// Builds the engine on a data path node with `ports`, and starts the virtual time.
+    pub fn new(ports: Ports, build: impl FnOnce(DataPathNode) -> E) -> Self {
+        virtual_clock::set(Some(START_NS));
+        fastrand::seed(SEED);
+
+        let (tx_inputs, engine_tx_inputs) = (0..ports.tx_inputs).map(|_| channel()).unzip();
+        let (engine_tx_outputs, tx_outputs) = (0..ports.tx_outputs).map(|_| channel()).unzip();
+        let (rx_inputs, engine_rx_inputs) = (0..ports.rx_inputs).map(|_| channel()).unzip();
+        let (engine_rx_outputs, rx_outputs) = (0..ports.rx_outputs).map(|_| channel()).unzip();
+        let node = DataPathNode {
+            tx_inputs: engine_tx_inputs,
+            tx_outputs: engine_tx_outputs,
+            rx_inputs: engine_rx_inputs,
+            rx_outputs: engine_rx_outputs,
+        };
+
+        let mut engine = Box::pin(build(node));
+        let future = engine.as_mut().activate();
+        // SAFETY: the engine is pinned on the heap, and the future is dropped before it, like in
+        // the containers of the runtime.
+        let future = unsafe {
+            std::mem::transmute::<BoxFuture<'_, EngineResult>, BoxFuture<'static, EngineResult>>(
+                future,
+            )
+        };
+
+        Harness {
+            future,
+            _engine: engine,
+            tx_inputs,
+            tx_outputs,
+            rx_inputs,
+            rx_outputs,
+            meta_buf_pool: MetaBufferPool::new(META_BUFFER_POOL_CAP),
+            next_call_id: 0,
+            steps: 0,
+            result: None,
+        }
+    }
+
+    /// The virtual time, in nanoseconds of `monotonic_ns`.
+    pub fn now(&self) -> u64 {
+        virtual_clock::now().expect("the virtual clock is set by the harness")
+    }
+
+    /// Returns whether the engine has finished, e.g., after its input queues are closed.
+    pub fn is_finished(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Makes a message carrying `meta` with a new call ID, whose meta buffer is owned by the
+    /// harness. `deadline` is 0 for never.
+    ///
+    /// # Panics
+    ///
+    /// Panics if all the meta buffers are given out.
+    pub fn message(
+        &mut self,
+        meta: MessageMeta,
+        addr_backend: usize,
+        deadline: u64,
+    ) -> RpcMessageTx {
+        let call_id = CallId(self.next_call_id);
+        self.next_call_id += 1;
+        let meta_buf_ptr = self
+            .meta_buf_pool
+            .obtain(RpcId::new(meta.conn_id, call_id))
+            .expect("the meta buffers of the harness are used up");
+        // SAFETY: the buffer is owned by the harness, and not used by any other message
+        unsafe {
+            meta_buf_ptr
+                .as_meta_ptr()
+                .write(MessageMeta { call_id, ..meta })
+        };
+        RpcMessageTx {
+            meta_buf_ptr,
+            addr_backend,
+            deadline,
+            bulk: false,
+            dispatch_version: 0,
+        }
+    }
+
+    /// Runs the steps of `trace` in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics at the first step that fails, with its number in the trace so far.
+    pub fn run_trace(&mut self, trace: impl IntoIterator<Item = Step>) {
+        for step in trace {
+            self.step(step);
+        }
+    }
+
+    /// Runs a step.
+    pub fn step(&mut self, step: Step) {
+        let n = self.steps;
+        self.steps += 1;
+        match step {
+            Step::Tx(port, msg) => {
+                if self.tx_inputs[port].send(msg).is_err() {
+                    panic!("step {}: tx input {} is closed", n, port);
+                }
+            }
+            Step::Rx(port, msg) => {
+                if self.rx_inputs[port].send(msg).is_err() {
+                    panic!("step {}: rx input {} is closed", n, port);
+                }
+            }
+            Step::Inject(f) => f(),
+            Step::Advance(duration) => virtual_clock::advance(duration.as_nanos() as u64),
+            Step::Run(polls) => {
+                for _ in 0..polls {
+                    if !self.poll() {
+                        break;
+                    }
+                }
+            }
+            Step::ExpectTx(port, expect) => {
+                let msg = self.next_output(n, |h| h.tx_outputs[port].try_recv(), &expect.what);
+                if !(expect.matches)(&msg) {
+                    panic!(
+                        "step {}: expected {} on tx output {}, got {:?}",
+                        n, expect.what, port, msg
+                    );
+                }
+            }
+            Step::ExpectRx(port, expect) => {
+                let msg = self.next_output(n, |h| h.rx_outputs[port].try_recv(), &expect.what);
+                if !(expect.matches)(&msg) {
+                    panic!(
+                        "step {}: expected {} on rx output {}, got {:?}",
+                        n, expect.what, port, msg
+                    );
+                }
+            }
+            Step::ExpectIdle => {
+                for (port, queue) in self.tx_outputs.iter_mut().enumerate() {
+                    if let Ok(msg) = queue.try_recv() {
+                        panic!(
+                            "step {}: expected no output, got {:?} on tx output {}",
+                            n, msg, port
+                        );
+                    }
+                }
+                for (port, queue) in self.rx_outputs.iter_mut().enumerate() {
+                    if let Ok(msg) = queue.try_recv() {
+                        panic!(
+                            "step {}: expected no output, got {:?} on rx output {}",
+                            n, msg, port
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Polls the engine once. Returns false if it has finished.
+    pub fn poll(&mut self) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match self.future.as_mut().poll(&mut cx) {
+            Poll::Ready(result) => {
+                self.result = Some(result);
+                false
+            }
+            Poll::Pending => true,
+        }
+    }
+
+    /// Returns the next message of an output queue, polling the engine until there is one.
+    fn next_output<T>(
+        &mut self,
+        n: usize,
+        mut try_recv: impl FnMut(&mut Self) -> Result<T, TryRecvError>,
+        what: &str,
+    ) -> T {
+        for _ in 0..EXPECT_POLLS {
+            match try_recv(self) {
+                Ok(msg) => return msg,
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    panic!("step {}: expected {}, the output is closed", n, what)
+                }
+            }
+            if !self.poll() {
+                panic!(
+                    "step {}: expected {}, the engine finished with {:?}",
+                    n, what, self.result
+                );
+            }
+        }
+        panic!(
+            "step {}: expected {}, got nothing in {} polls",
+            n, what, EXPECT_POLLS
+        );
+    }
+}
+
+impl<E: Engine> Drop for Harness<E> {
+    fn drop(&mut self) {
+        virtual_clock::set(None);
+    }
+}
+
+fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    // the engine runs on the thread of the harness
+    create_channel(ChannelFlavor::Sequential)
+}
@@ -0,0 +1,113 @@
+//! Connects an engine between the two ends of a test by hand.
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{CallId, RpcId, TransportStatus};
+use phoenix_api::Handle;
+use phoenix_common::engine::datapath::node::{ChannelDescriptor, DataPathNode};
+use phoenix_common::engine::datapath::EngineRxMessage;
+use phoenix_common::engine::{
+    future, Decompose, Engine, EngineResult, EngineType, Indicator, Vertex,
+};
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+use phoenix_testing::graph::{GraphBuilder, Runner};
+
+const CLIENT: EngineType = EngineType("Client");
+const COUNTER: EngineType = EngineType("CounterEngine");
+const BACKEND: EngineType = EngineType("Backend");
+
+/// Passes the rx messages on, and counts them.
+struct CounterEngine {
+    node: DataPathNode,
+    indicator: Indicator,
+    count: Arc<AtomicUsize>,
+}
+
+impl_vertex_for_engine!(CounterEngine, node);
+
+impl Engine for CounterEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move {
+            let this = self.get_mut();
+            loop {
+                while let Ok(msg) = this.rx_inputs()[0].try_recv() {
+                    this.count.fetch_add(1, Ordering::Relaxed);
+                    this.rx_outputs()[0]
+                        .send(msg)
+                        .expect("the rx output is open");
+                }
+                future::yield_now().await;
+            }
+        })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "CounterEngine".to_owned()
+    }
+
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+}
+
+impl Decompose for CounterEngine {
+    fn flush(&mut self) -> anyhow::Result<usize> {
+        Ok(0)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        (ResourceCollection::new(), self.node)
+    }
+}
+
+#[test]
+fn messages_pass_through_the_engine() {
+    let mut nodes = GraphBuilder::new()
+        .tx(ChannelDescriptor(CLIENT, BACKEND, 0, 0))
+        .rx(ChannelDescriptor(BACKEND, COUNTER, 0, 0))
+        .rx(ChannelDescriptor(COUNTER, CLIENT, 0, 0))
+        .build()
+        .unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut runner = Runner::new();
+    runner.spawn(CounterEngine {
+        node: nodes.take(COUNTER),
+        indicator: Default::default(),
+        count: Arc::clone(&count),
+    });
+    let mut backend = nodes.take(BACKEND);
+    let mut client = nodes.take(CLIENT);
+    assert_eq!(client.tx_outputs.len(), 1);
+    assert_eq!(backend.tx_inputs.len(), 1);
+
+    for call_id in 0..3 {
+        let rpc_id = RpcId::new(Handle(1), CallId(call_id));
+        let ack = EngineRxMessage::Ack(rpc_id, TransportStatus::Success);
+        backend.rx_outputs[0].send(ack).unwrap();
+    }
+    assert!(runner.run_until(10, || count.load(Ordering::Relaxed) == 3));
+    for call_id in 0..3 {
+        match client.rx_inputs[0].try_recv() {
+            Ok(EngineRxMessage::Ack(rpc_id, _)) => assert_eq!(rpc_id.1, CallId(call_id)),
+            other => panic!("expected the ack of call {}, got {:?}", call_id, other),
+        }
+    }
+    assert!(runner.result(0).is_none());
+}
+
+#[test]
+fn gaps_in_the_indices_are_rejected() {
+    let result = GraphBuilder::new()
+        .tx(ChannelDescriptor(CLIENT, COUNTER, 0, 0))
+        .tx(ChannelDescriptor(CLIENT, BACKEND, 2, 0))
+        .build();
+    assert!(result.is_err());
+}
@@ -0,0 +1,118 @@
+//! Replays traces against a small engine that drops the expired requests.
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{
+    monotonic_ns, CallId, MessageMeta, RpcId, RpcMsgType, StatusCode, TransportStatus,
+};
+use phoenix_api::Handle;
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::datapath::{EngineRxMessage, EngineTxMessage};
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+use phoenix_testing::replay::{Harness, Ports, Step};
+
+/// Passes the requests on, and acks those past their deadlines with an error instead.
+struct ExpiryEngine {
+    node: DataPathNode,
+    indicator: Indicator,
+}
+
+impl_vertex_for_engine!(ExpiryEngine, node);
+
+impl Engine for ExpiryEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move {
+            let this = self.get_mut();
+            loop {
+                while let Ok(msg) = this.tx_inputs()[0].try_recv() {
+                    match msg {
+                        EngineTxMessage::RpcMessage(msg)
+                            if msg.deadline != 0 && msg.deadline <= monotonic_ns() =>
+                        {
+                            let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+                            let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                            let ack =
+                                EngineRxMessage::Ack(rpc_id, TransportStatus::DEADLINE_EXCEEDED);
+                            this.rx_outputs()[0]
+                                .send(ack)
+                                .expect("the rx output is open");
+                        }
+                        m => this.tx_outputs()[0].send(m).expect("the tx output is open"),
+                    }
+                }
+                future::yield_now().await;
+            }
+        })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        "ExpiryEngine".to_owned()
+    }
+
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+}
+
+impl Decompose for ExpiryEngine {
+    fn flush(&mut self) -> anyhow::Result<usize> {
+        Ok(0)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        (ResourceCollection::new(), self.node)
+    }
+}
+
+fn request_meta() -> MessageMeta {
+    MessageMeta {
+        conn_id: Handle(1),
+        service_id: 0,
+        func_id: 0,
+        call_id: CallId(0),
+        token: 0,
+        correlation_id: 0,
+        request_id: [0; 16],
+        msg_type: RpcMsgType::Request,
+        status_code: StatusCode::Success,
+        flags: 0,
+    }
+}
+
+#[test]
+fn expired_requests_are_acked() {
+    let mut harness = Harness::new(Ports::default(), |node| ExpiryEngine {
+        node,
+        indicator: Default::default(),
+    });
+    let deadline = harness.now() + 1000;
+    let fresh = harness.message(request_meta(), 0, deadline);
+    let stale = harness.message(request_meta(), 0, deadline);
+    let stale_call_id = unsafe { (*stale.meta_buf_ptr.as_meta_ptr()).call_id };
+
+    harness.run_trace([
+        Step::Tx(0, EngineTxMessage::RpcMessage(fresh)),
+        Step::expect_tx(0, "the request before its deadline", |m| {
+            matches!(m, EngineTxMessage::RpcMessage(_))
+        }),
+        Step::Advance(Duration::from_micros(1)),
+        Step::Tx(0, EngineTxMessage::RpcMessage(stale)),
+        Step::expect_rx(0, "an ack of the expired request", move |m| {
+            matches!(
+                m,
+                EngineRxMessage::Ack(rpc_id, status)
+                    if *status == TransportStatus::DEADLINE_EXCEEDED && rpc_id.1 == stale_call_id
+            )
+        }),
+        Step::Run(10),
+        Step::ExpectIdle,
+    ]);
+}
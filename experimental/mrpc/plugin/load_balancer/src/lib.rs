@@ -86,6 +86,8 @@ pub(crate) enum DatapathError {
 
 use crate::module::LoadBalancerModule;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(_config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let module = LoadBalancerModule::new();
@@ -0,0 +1,58 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+// The sources the dispatch libraries are built from besides the protos. A dispatch library is
+// only compatible with a backend built from the same sources.
+const DISPATCH_SOURCES: &[&str] = &[
+    "../../mrpc-derive",
+    "../../mrpc-marshal",
+    "../../../../src/shm",
+    "../../../../src/phoenix-api",
+    "../../../../rust-toolchain",
+    "src/builder/compiler",
+];
+
+fn collect(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        files.push(path.to_owned());
+        return;
+    }
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.file_name().map_or(false, |name| name == "target") {
+            continue;
+        }
+        collect(&path, files);
+    }
+}
+
+fn main() {
+    // The target triple that prebuilt dispatch libraries are looked up by.
+    println!(
+        "cargo:rustc-env=MRPC_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+
+    // The fingerprint of the sources, see `builder::abi_fingerprint`.
+    println!("cargo:rerun-if-changed=build.rs");
+    let mut files = Vec::new();
+    for source in DISPATCH_SOURCES {
+        println!("cargo:rerun-if-changed={}", source);
+        collect(Path::new(source), &mut files);
+    }
+    files.sort();
+    let mut hasher = DefaultHasher::new();
+    for file in files {
+        hasher.write(file.to_string_lossy().as_bytes());
+        hasher.write(&std::fs::read(&file).unwrap());
+    }
+    println!(
+        "cargo:rustc-env=MRPC_SOURCES_FINGERPRINT={:016x}",
+        hasher.finish()
+    );
+}
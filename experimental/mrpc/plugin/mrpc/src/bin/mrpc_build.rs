@@ -0,0 +1,74 @@
+//! Builds the dispatch libraries of mRPC services ahead of time, so that the backend does not
+//! need to build them when an application starts.
+//!
+//! The protos must be given in the same order as the application sends them to the backend,
+//! i.e., the proto files of each package imported by the service in order.
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use phoenix_mrpc::builder::{build_prebuilt, cache};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "mRPC dispatch library builder")]
+enum Opts {
+    /// Build the dispatch library of a set of protos into a prebuilt directory.
+    Dispatch {
+        /// The proto files.
+        #[structopt(short, long = "proto", required = true)]
+        protos: Vec<PathBuf>,
+        /// The prebuilt directory to install the library to.
+        #[structopt(short, long)]
+        out_dir: PathBuf,
+        /// The target triple to build for. Default to the host.
+        #[structopt(long)]
+        target: Option<String>,
+        /// The directory to build the library in.
+        #[structopt(long, default_value = "build_cache")]
+        build_cache: PathBuf,
+    },
+    /// Remove entries from a build cache.
+    Gc {
+        /// The build cache.
+        build_cache: PathBuf,
+        /// Keep at most this many entries.
+        #[structopt(long)]
+        max_entries: Option<usize>,
+        /// Remove entries that have not been used for this many seconds.
+        #[structopt(long)]
+        max_age_secs: Option<u64>,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    match Opts::from_args() {
+        Opts::Dispatch {
+            protos,
+            out_dir,
+            target,
+            build_cache,
+        } => {
+            let protos = protos
+                .iter()
+                .map(std::fs::read_to_string)
+                .collect::<Result<Vec<_>, _>>()?;
+            let identifier = cache::entry_key(&protos);
+            let dylib_path = build_prebuilt(protos, &build_cache, &out_dir, target.as_deref())?;
+            println!("{} {}", identifier, dylib_path.display());
+        }
+        Opts::Gc {
+            build_cache,
+            max_entries,
+            max_age_secs,
+        } => {
+            let policy = cache::GcPolicy {
+                max_entries,
+                max_age_secs,
+            };
+            for identifier in cache::gc(&build_cache, &policy, "")? {
+                println!("removed {}", identifier);
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,90 @@
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use serde::{Deserialize, Serialize};
+
+use super::abi_fingerprint;
+
+/// The file in an entry of the build cache that records when the entry was last used.
+const LAST_USED_FILE: &str = "last_used";
+
+/// The directory in the build cache that holds the lock files of the entries. The lock files are
+/// kept outside of the entries, so that removing an entry does not remove its lock.
+const LOCK_DIR: &str = ".locks";
+
+/// When to remove entries from the build cache. Nothing is removed by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GcPolicy {
+    /// Keep at most this many entries, removing the least recently used ones first
+    pub max_entries: Option<usize>,
+    /// Remove entries that have not been used for this many seconds
+    pub max_age_secs: Option<u64>,
+}
+
+impl GcPolicy {
+    #[inline]
+    pub fn is_disabled(&self) -> bool {
+        self.max_entries.is_none() && self.max_age_secs.is_none()
+    }
+}
+
+/// Returns the identifier of a set of protos, i.e., the hash of their content.
+pub fn proto_identifier(protos: &[String]) -> String {
+    let mut checksum_ctx = md5::Context::new();
+    for proto in protos.iter() {
+        checksum_ctx.consume(proto.as_bytes());
+    }
+    format!("{:0x}", checksum_ctx.compute())
+}
+
+/// Returns the key of the dispatch library of a set of protos, i.e., the name of its entry in the
+/// build cache or a prebuilt directory. A library built against other sources gets another key,
+/// see [`abi_fingerprint`].
+pub fn entry_key(protos: &[String]) -> String {
+    let mut checksum_ctx = md5::Context::new();
+    checksum_ctx.consume(proto_identifier(protos).as_bytes());
+    checksum_ctx.consume(abi_fingerprint().as_bytes());
+    format!("{:0x}", checksum_ctx.compute())
+}
+
+/// An exclusive lock on an entry of the build cache, held while the entry is built or removed.
+/// It is released when dropped, or when the process exits.
+pub struct EntryLock {
+    _file: File,
+}
+
+impl EntryLock {
+    fn open(cache_dir: &Path, key: &str) -> std::io::Result<File> {
+        let lock_dir = cache_dir.join(LOCK_DIR);
+        std::fs::create_dir_all(&lock_dir)?;
+        File::options()
+            .create(true)
+            .write(true)
+            .open(lock_dir.join(key))
+    }
+
+    /// Waits for the lock of the entry `key` in `cache_dir`.
+    pub fn acquire<P: AsRef<Path>>(cache_dir: P, key: &str) -> std::io::Result<Self> {
+        let file = Self::open(cache_dir.as_ref(), key)?;
+        flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
+        Ok(EntryLock { _file: file })
+    }
+
+    /// Takes the lock of the entry `key` in `cache_dir`, or returns `None` if it is held.
+    pub fn try_acquire<P: AsRef<Path>>(cache_dir: P, key: &str) -> std::io::Result<Option<Self>> {
+        let file = Self::open(cache_dir.as_ref(), key)?;
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => Ok(Some(EntryLock { _file: file })),
+            Err(Errno::EWOULDBLOCK) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
 
 pub fn check_cache<P: AsRef<Path>>(
     protos: &[String],
@@ -9,11 +95,7 @@ pub fn check_cache<P: AsRef<Path>>(
     // relative to `cache_dir`
     proto_dir: &str,
 ) -> std::io::Result<(String, bool)> {
-    let mut checksum_ctx = md5::Context::new();
-    for proto in protos.iter() {
-        checksum_ctx.consume(proto.as_bytes());
-    }
-    let app_identifier = format!("{:0x}", checksum_ctx.compute());
+    let app_identifier = entry_key(protos);
 
     // protos are stored in cache_dir/proto_dir
     let cached_proto_dir = cache_dir.as_ref().join(&app_identifier).join(proto_dir);
@@ -59,3 +141,154 @@ pub fn write_protos_to_cache<P: AsRef<Path>>(
 
     Ok(())
 }
+
+/// Records that the entry `identifier` in `cache_dir` is used now.
+pub fn touch<P: AsRef<Path>>(identifier: &str, cache_dir: P) -> std::io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let filename = cache_dir.as_ref().join(identifier).join(LAST_USED_FILE);
+    std::fs::write(filename, now.to_string())
+}
+
+fn last_used(entry: &Path) -> std::io::Result<SystemTime> {
+    // Entries built before the marker was introduced fall back to the mtime of the directory.
+    match std::fs::read_to_string(entry.join(LAST_USED_FILE)) {
+        Ok(secs) => match secs.trim().parse() {
+            Ok(secs) => Ok(UNIX_EPOCH + Duration::from_secs(secs)),
+            Err(_) => entry.metadata()?.modified(),
+        },
+        Err(_) => entry.metadata()?.modified(),
+    }
+}
+
+/// Removes the entries in `cache_dir` according to `policy`, except for `keep` and the entries
+/// being built. Returns the identifiers of the removed entries.
+pub fn gc<P: AsRef<Path>>(
+    cache_dir: P,
+    policy: &GcPolicy,
+    keep: &str,
+) -> std::io::Result<Vec<String>> {
+    if policy.is_disabled() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(cache_dir.as_ref())? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let identifier = entry.file_name().to_string_lossy().into_owned();
+        if identifier.starts_with('.') {
+            continue;
+        }
+        entries.push((identifier, last_used(&entry.path())?));
+    }
+
+    let mut removed = select_victims(entries, policy, keep, SystemTime::now());
+    removed.retain(|identifier| {
+        // an entry being built is left for a later collection
+        match EntryLock::try_acquire(cache_dir.as_ref(), identifier) {
+            Ok(Some(_lock)) => std::fs::remove_dir_all(cache_dir.as_ref().join(identifier)).is_ok(),
+            _ => false,
+        }
+    });
+    Ok(removed)
+}
+
+fn select_victims(
+    mut entries: Vec<(String, SystemTime)>,
+    policy: &GcPolicy,
+    keep: &str,
+    now: SystemTime,
+) -> Vec<String> {
+    // Most recently used first.
+    entries.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let mut victims = Vec::new();
+    // The entry in use is always kept, and counts towards `max_entries`.
+    let mut kept = entries.iter().filter(|(id, _)| id == keep).count();
+    for (identifier, last_used) in entries {
+        if identifier == keep {
+            continue;
+        }
+        let expired = policy.max_age_secs.map_or(false, |max_age| {
+            now.duration_since(last_used).unwrap_or_default() > Duration::from_secs(max_age)
+        });
+        let overflow = policy
+            .max_entries
+            .map_or(false, |max_entries| kept >= max_entries);
+        if expired || overflow {
+            victims.push(identifier);
+        } else {
+            kept += 1;
+        }
+    }
+    victims
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(now: SystemTime, ages: &[(&str, u64)]) -> Vec<(String, SystemTime)> {
+        ages.iter()
+            .map(|(id, age)| (id.to_string(), now - Duration::from_secs(*age)))
+            .collect()
+    }
+
+    #[test]
+    fn gc_disabled_by_default() {
+        let now = SystemTime::now();
+        let policy = GcPolicy::default();
+        assert!(policy.is_disabled());
+        let victims = select_victims(entries(now, &[("a", 1), ("b", 1 << 30)]), &policy, "", now);
+        assert!(victims.is_empty());
+    }
+
+    #[test]
+    fn gc_max_entries() {
+        let now = SystemTime::now();
+        let policy = GcPolicy {
+            max_entries: Some(2),
+            max_age_secs: None,
+        };
+        let cached = entries(now, &[("a", 30), ("b", 10), ("c", 20), ("d", 40)]);
+        assert_eq!(select_victims(cached.clone(), &policy, "", now), ["a", "d"]);
+        assert_eq!(select_victims(cached, &policy, "d", now), ["c", "a"]);
+    }
+
+    #[test]
+    fn gc_max_age() {
+        let now = SystemTime::now();
+        let policy = GcPolicy {
+            max_entries: None,
+            max_age_secs: Some(15),
+        };
+        let cached = entries(now, &[("a", 30), ("b", 10), ("c", 20)]);
+        assert_eq!(select_victims(cached.clone(), &policy, "", now), ["c", "a"]);
+        assert_eq!(select_victims(cached, &policy, "a", now), ["c"]);
+    }
+
+    #[test]
+    fn gc_skips_locked_entries() {
+        let cache_dir = std::env::temp_dir().join(format!("mrpc-gc-test-{}", std::process::id()));
+        for identifier in ["a", "b", "c"] {
+            std::fs::create_dir_all(cache_dir.join(identifier)).unwrap();
+        }
+        let policy = GcPolicy {
+            max_entries: Some(1),
+            max_age_secs: None,
+        };
+        let lock = EntryLock::try_acquire(&cache_dir, "b").unwrap().unwrap();
+        assert!(EntryLock::try_acquire(&cache_dir, "b").unwrap().is_none());
+        assert_eq!(gc(&cache_dir, &policy, "c").unwrap(), ["a"]);
+        assert!(cache_dir.join("b").is_dir());
+        drop(lock);
+        assert_eq!(gc(&cache_dir, &policy, "c").unwrap(), ["b"]);
+        assert!(cache_dir.join("c").is_dir());
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
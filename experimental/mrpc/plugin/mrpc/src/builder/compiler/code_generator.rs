@@ -77,16 +77,16 @@ pub fn generate(
             addr_backend: usize,
         ) -> Result<SgList, MarshalError> {
             match meta.msg_type {
-                RpcMsgType::Request => {
+                RpcMsgType::Request | RpcMsgType::Post => {
                     match meta.func_id {
                         #(#requests_marshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => Err(MarshalError::UnknownFuncId(meta.func_id)),
                     }
                 },
                 RpcMsgType::Response => {
                     match meta.func_id {
                         #(#responses_marshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => Err(MarshalError::UnknownFuncId(meta.func_id)),
                     }
                 }
             }
@@ -98,16 +98,16 @@ pub fn generate(
             ctx: &mut ExcavateContext<AddressMap>,
         ) -> Result<(usize, usize), UnmarshalError> {
             let addr_shm = match meta.msg_type {
-                RpcMsgType::Request => {
+                RpcMsgType::Request | RpcMsgType::Post => {
                     match meta.func_id {
                         #(#requests_unmarshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => return Err(UnmarshalError::UnknownFuncId(meta.func_id)),
                     }
                 },
                 RpcMsgType::Response => {
                     match meta.func_id {
                         #(#response_unmarshal)*
-                        _ => panic!("unknown func_id: {}, meta: {:?}", meta.func_id, meta),
+                        _ => return Err(UnmarshalError::UnknownFuncId(meta.func_id)),
                     }
                 }
             };
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use thiserror::Error;
@@ -14,7 +14,7 @@ const MRPC_MARSHAL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../mrpc-mars
 const SHM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../src/shm");
 const PHOENIX_API: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../src/phoenix-api");
 // Make sure the compiler for plugins is the same as the compiler for the backend.
-const TOOLCHAIN: &str = include_str!(concat!(
+pub(crate) const TOOLCHAIN: &str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/../../../../rust-toolchain"
 ));
@@ -44,6 +44,31 @@ pub struct Builder {
     /// default to "_include.rs"
     pub(crate) include_filename: Option<String>,
     pub(crate) method_type_mapping: HashMap<MethodIdentifier, RpcMethodInfo>,
+    /// the target triple to build for
+    /// default to the host
+    pub(crate) target: Option<String>,
+}
+
+/// Runs `cargo build --release` in `crate_dir` and returns the path to the dispatch library.
+pub(crate) fn cargo_build(crate_dir: &Path, target: Option<&str>) -> Result<PathBuf, Error> {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("build").arg("--release");
+    if let Some(target) = target {
+        cmd.arg("--target").arg(target);
+    }
+    cmd.current_dir(crate_dir);
+
+    let status = cmd.status()?;
+    if !status.success() {
+        // failed to run cargo build
+        return Err(Error::Cargo);
+    }
+
+    let target_dir = match target {
+        Some(target) => crate_dir.join("target").join(target),
+        None => crate_dir.join("target"),
+    };
+    Ok(target_dir.join(format!("release/{}", DYLIB_FILENAME)))
 }
 
 impl Builder {
@@ -82,20 +107,6 @@ impl Builder {
 
         fs::write(self.emit_crate_dir.join("rust-toolchain"), TOOLCHAIN).unwrap();
 
-        let mut cmd = Command::new("cargo");
-        cmd.arg("build").arg("--release");
-        cmd.current_dir(&self.emit_crate_dir);
-
-        let status = cmd.status()?;
-        if !status.success() {
-            // failed to run cargo build
-            return Err(Error::Cargo);
-        }
-
-        let dylib_path = self
-            .emit_crate_dir
-            .join(format!("target/release/{}", DYLIB_FILENAME));
-
-        Ok(dylib_path)
+        cargo_build(&self.emit_crate_dir, self.target.as_deref())
     }
 }
@@ -1,18 +1,38 @@
-use std::path::PathBuf;
-use std::process::Command;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use phoenix_common::log;
+
 pub mod cache;
 pub mod compiler;
+pub mod namespace;
 pub mod prost;
 
 const PROTO_DIR: &str = "proto";
 const PROST_DIR: &str = "prost";
 const LIBRARY_DIR: &str = "marshal";
 const PROST_INCLUDE_FILE: &str = "_include.rs";
+const MANIFEST_FILE: &str = "manifest.json";
+const METHOD_INFO_FILE: &str = "method_info.json";
+
+/// The target triple of the backend. Prebuilt dispatch libraries are looked up by it.
+pub const TARGET: &str = env!("MRPC_TARGET");
+
+/// Returns the fingerprint of what a dispatch library must agree on with the backend besides the
+/// protos: the sources of the crates it links, the code generator, the toolchain, and the layout
+/// of `MessageMeta`. It is part of the key of a library in the build cache, and of the manifest
+/// of a prebuilt library.
+pub fn abi_fingerprint() -> String {
+    format!(
+        "{}-meta{}",
+        env!("MRPC_SOURCES_FINGERPRINT"),
+        std::mem::size_of::<phoenix_api::rpc::MessageMeta>()
+    )
+}
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub struct MethodIdentifier(u32, u32);
@@ -88,6 +108,10 @@ impl<'de> Deserialize<'de> for MethodIdentifier {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RpcMethodInfo {
+    // fully qualified path of the service, e.g., `rpc_hello.Greeter`, empty in the method tables
+    // saved before it is recorded
+    #[serde(default)]
+    pub service: String,
     pub service_id: u32,
     pub func_id: u32,
     // fully qualified path for the method's input rust type
@@ -100,6 +124,20 @@ pub struct RpcMethodInfo {
     pub output_type: String,
 }
 
+/// Describes a prebuilt dispatch library.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DispatchManifest {
+    /// key of the library, see [`cache::entry_key`]
+    pub identifier: String,
+    /// target triple the library is built for
+    pub target: String,
+    /// toolchain the library is built with
+    pub toolchain: String,
+    /// see [`abi_fingerprint`], empty for the libraries built before it is recorded
+    #[serde(default)]
+    pub abi: String,
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("IO Error: {0}")]
@@ -108,20 +146,187 @@ pub enum Error {
     ProstBuild(#[from] prost::Error),
     #[error("Marshal Library Compile Error: {0}")]
     LibraryCompile(#[from] compiler::Error),
+    #[error("Invalid Manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("No prebuilt dispatch library for protos {0}, and building is disabled")]
+    NotPrebuilt(String),
+    #[error(
+        "Service {service} (service ID {service_id}) conflicts with {previous} registered before"
+    )]
+    ServiceConflict {
+        service_id: u32,
+        service: String,
+        previous: String,
+    },
+    #[error("Service {1} (service ID {0}) registered before is missing from the protos")]
+    ServiceMissing(u32, String),
+}
+
+/// A dispatch library and the methods it dispatches.
+#[derive(Clone, Debug)]
+pub struct DispatchLibrary {
+    /// key of the library, see [`cache::entry_key`]
+    pub identifier: String,
+    pub path: PathBuf,
+    /// `None` if the library is prebuilt without the method table
+    pub methods: Option<HashMap<MethodIdentifier, RpcMethodInfo>>,
+}
+
+/// Reads the method table saved in an entry of the build cache or the prebuilt directory.
+fn read_method_info(
+    entry: &Path,
+) -> Result<Option<HashMap<MethodIdentifier, RpcMethodInfo>>, Error> {
+    let path = entry.join(METHOD_INFO_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
 }
 
 pub fn build_serializer_lib(protos: Vec<String>, cache_dir: PathBuf) -> Result<PathBuf, Error> {
+    let (_identifier, dylib_path) = build_in_cache(protos, &cache_dir, None)?;
+    Ok(dylib_path)
+}
+
+/// Where to find and build the dispatch libraries.
+#[derive(Clone, Debug)]
+pub struct DispatchCache {
+    /// directory to build dispatch libraries in
+    pub build_cache: PathBuf,
+    /// directories of prebuilt dispatch libraries, searched in order
+    pub prebuilt: Vec<PathBuf>,
+    /// whether to build the dispatch library if it is not prebuilt
+    pub build_on_miss: bool,
+    pub gc: cache::GcPolicy,
+}
+
+impl DispatchCache {
+    /// Returns the dispatch library for `protos`. A prebuilt library is preferred.
+    pub fn get_or_build(&self, protos: Vec<String>) -> Result<DispatchLibrary, Error> {
+        if let Some(dylib_path) = find_prebuilt(&protos, &self.prebuilt)? {
+            log::debug!("using prebuilt dispatch library: {:?}", dylib_path);
+            let entry = dylib_path
+                .parent()
+                .expect("prebuilt library resides in an entry");
+            return Ok(DispatchLibrary {
+                identifier: cache::entry_key(&protos),
+                methods: read_method_info(entry)?,
+                path: dylib_path,
+            });
+        }
+        if !self.build_on_miss {
+            return Err(Error::NotPrebuilt(cache::entry_key(&protos)));
+        }
+
+        let (identifier, dylib_path) = build_in_cache(protos, &self.build_cache, None)?;
+        cache::touch(&identifier, &self.build_cache)?;
+        let removed = cache::gc(&self.build_cache, &self.gc, &identifier)?;
+        if !removed.is_empty() {
+            log::info!("removed from build cache: {:?}", removed);
+        }
+        let methods = read_method_info(&self.build_cache.join(&identifier))?;
+        Ok(DispatchLibrary {
+            identifier,
+            path: dylib_path,
+            methods,
+        })
+    }
+}
+
+/// Looks up the dispatch library for `protos` in `prebuilt_dirs`. Only libraries built for the
+/// target, with the toolchain, and against the sources of the backend are returned.
+pub fn find_prebuilt(
+    protos: &[String],
+    prebuilt_dirs: &[PathBuf],
+) -> Result<Option<PathBuf>, Error> {
+    for prebuilt_dir in prebuilt_dirs {
+        let target_dir = prebuilt_dir.join(TARGET);
+        let (identifier, found) = cache::check_cache(protos, &target_dir, PROTO_DIR)?;
+        if !found {
+            continue;
+        }
+
+        let entry = target_dir.join(identifier);
+        let manifest: DispatchManifest =
+            serde_json::from_slice(&std::fs::read(entry.join(MANIFEST_FILE))?)?;
+        // Rust has no stable ABI, the library must be built by the same compiler and against the
+        // same crates.
+        if manifest.target != TARGET
+            || manifest.toolchain.trim() != compiler::TOOLCHAIN.trim()
+            || manifest.abi != abi_fingerprint()
+        {
+            log::warn!(
+                "skipping prebuilt dispatch library in {:?}, built for {} with {}, ABI {:?}",
+                entry,
+                manifest.target,
+                manifest.toolchain.trim(),
+                manifest.abi
+            );
+            continue;
+        }
+        let dylib_path = entry.join(compiler::DYLIB_FILENAME);
+        if dylib_path.is_file() {
+            return Ok(Some(dylib_path));
+        }
+    }
+    Ok(None)
+}
+
+/// Builds the dispatch library for `protos` in `cache_dir`, and installs it to `out_dir` as a
+/// prebuilt library for `target`, default to the host. Returns the path to the installed library.
+pub fn build_prebuilt(
+    protos: Vec<String>,
+    cache_dir: &Path,
+    out_dir: &Path,
+    target: Option<&str>,
+) -> Result<PathBuf, Error> {
+    let (identifier, dylib_path) = build_in_cache(protos.clone(), cache_dir, target)?;
+
+    let target = target.unwrap_or(TARGET);
+    let target_dir = out_dir.join(target);
+    std::fs::create_dir_all(&target_dir)?;
+    cache::write_protos_to_cache(&identifier, &protos, &target_dir, PROTO_DIR)?;
+
+    let entry = target_dir.join(&identifier);
+    let installed = entry.join(compiler::DYLIB_FILENAME);
+    std::fs::copy(&dylib_path, &installed)?;
+    std::fs::copy(
+        cache_dir.join(&identifier).join(METHOD_INFO_FILE),
+        entry.join(METHOD_INFO_FILE),
+    )?;
+    let manifest = DispatchManifest {
+        identifier,
+        target: target.to_owned(),
+        toolchain: compiler::TOOLCHAIN.trim().to_owned(),
+        abi: abi_fingerprint(),
+    };
+    std::fs::write(
+        entry.join(MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(installed)
+}
+
+/// Builds the dispatch library for `protos` in `cache_dir`. Returns the identifier of the protos
+/// and the path to the library.
+fn build_in_cache(
+    protos: Vec<String>,
+    cache_dir: &Path,
+    target: Option<&str>,
+) -> Result<(String, PathBuf), Error> {
     // Create cache dir if it does not exists
-    std::fs::create_dir_all(cache_dir.as_path())?;
-    let (identifier, cached) = cache::check_cache(&protos, &cache_dir, PROTO_DIR)?;
+    std::fs::create_dir_all(cache_dir)?;
+    // keep the entry from being built twice at once or removed while it is built
+    let _lock = cache::EntryLock::acquire(cache_dir, &cache::entry_key(&protos))?;
+    let (identifier, cached) = cache::check_cache(&protos, cache_dir, PROTO_DIR)?;
     if !cached {
-        cache::write_protos_to_cache(&identifier, &protos, &cache_dir, PROTO_DIR)?;
+        cache::write_protos_to_cache(&identifier, &protos, cache_dir, PROTO_DIR)?;
         let prost_out_dir = cache_dir.join(&identifier).join(PROST_DIR);
         if !prost_out_dir.is_dir() {
             std::fs::create_dir(&prost_out_dir)?;
         }
 
-        let method_info_out_path = cache_dir.join(&identifier).join("method_info.json");
+        let method_info_out_path = cache_dir.join(&identifier).join(METHOD_INFO_FILE);
 
         let prost_builder = prost::configure()
             .include_file(PROST_INCLUDE_FILE)
@@ -148,26 +353,17 @@ pub fn build_serializer_lib(protos: Vec<String>, cache_dir: PathBuf) -> Result<P
             prost_out_dir: Some(prost_out_dir),
             include_filename: Some(PROST_INCLUDE_FILE.to_string()),
             method_type_mapping: method_info,
+            target: target.map(str::to_owned),
         };
         let dylib_path = builder.compile()?;
-        Ok(dylib_path)
+        Ok((identifier, dylib_path))
     } else {
         // Check whether dependencies have changed,
         // hence requiring rebuilding the shared library
         // TODO(wyj): use a built-in fingerprint mechanism to check changes
         // instead of directly using cargo
         let emit_crate_dir = cache_dir.join(&identifier).join(LIBRARY_DIR);
-        let mut cmd = Command::new("cargo");
-        cmd.arg("build").arg("--release");
-        cmd.current_dir(&emit_crate_dir);
-        // let status = cmd.stdout(Stdio::null()).stderr(Stdio::null()).status()?;
-        let status = cmd.status()?;
-        if !status.success() {
-            // failed to run cargo build
-            return Err(Error::LibraryCompile(compiler::Error::Cargo));
-        }
-        let dylib_path =
-            emit_crate_dir.join(format!("target/release/{}", compiler::DYLIB_FILENAME));
-        Ok(dylib_path)
+        let dylib_path = compiler::cargo_build(&emit_crate_dir, target)?;
+        Ok((identifier, dylib_path))
     }
 }
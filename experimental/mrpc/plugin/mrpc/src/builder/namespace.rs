@@ -0,0 +1,122 @@
+//! The services registered by a client.
+//!
+//! A client registers its protos with `update_protos` once for each generated client or server,
+//! and every time the dispatch library of the client is replaced by the one built from all the
+//! protos registered so far. The dispatch library routes messages by the service ID, so a new
+//! library must dispatch the services loaded before to the same services. Otherwise, the messages
+//! of a service could be handed to the (un)marshal functions of another service with the same ID,
+//! so the update is rejected instead. The methods of a service may change, which updates the
+//! schema of the service on the live connections: the messages sent before are still marshaled
+//! with the old library (see `crate::dispatch`).
+//!
+//! The method tables saved before they recorded the paths of the services have no path for any
+//! service. Such a service is only known to be the same if its methods are the same, otherwise its
+//! ID cannot be checked.
+use std::collections::{BTreeMap, HashMap};
+
+use phoenix_common::log;
+
+use super::{DispatchLibrary, Error, MethodIdentifier, RpcMethodInfo};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ServiceDef {
+    // `None` if the method table does not record it
+    path: Option<String>,
+    // func_id -> (input type, output type)
+    methods: BTreeMap<u32, (String, String)>,
+}
+
+fn group_by_service(
+    methods: &HashMap<MethodIdentifier, RpcMethodInfo>,
+) -> HashMap<u32, ServiceDef> {
+    let mut services: HashMap<u32, ServiceDef> = HashMap::new();
+    for info in methods.values() {
+        let service = services
+            .entry(info.service_id)
+            .or_insert_with(|| ServiceDef {
+                path: (!info.service.is_empty()).then(|| info.service.clone()),
+                methods: BTreeMap::new(),
+            });
+        service.methods.insert(
+            info.func_id,
+            (info.input_type.clone(), info.output_type.clone()),
+        );
+    }
+    services
+}
+
+impl ServiceDef {
+    fn name(&self) -> &str {
+        self.path.as_deref().unwrap_or("<unknown>")
+    }
+}
+
+/// The services of the dispatch library loaded by a client.
+#[derive(Debug, Clone, Default)]
+pub struct ProtoNamespace {
+    // identifier of the protos of the loaded library
+    identifier: Option<String>,
+    services: HashMap<u32, ServiceDef>,
+}
+
+impl ProtoNamespace {
+    /// Returns the identifier of the protos of the loaded library.
+    pub fn identifier(&self) -> Option<&str> {
+        self.identifier.as_deref()
+    }
+
+    /// Checks that `library` dispatches the services loaded so far to the same services, and
+    /// records it as the loaded library.
+    pub fn update(&mut self, library: &DispatchLibrary) -> Result<(), Error> {
+        let services = match library.methods.as_ref() {
+            Some(methods) => group_by_service(methods),
+            None => {
+                log::warn!(
+                    "dispatch library {:?} has no method table, service IDs are not checked",
+                    library.path
+                );
+                self.identifier = Some(library.identifier.clone());
+                return Ok(());
+            }
+        };
+
+        for (service_id, previous) in self.services.iter() {
+            match services.get(service_id) {
+                Some(service) if service == previous => {}
+                Some(service) if service.path.is_none() || previous.path.is_none() => {
+                    log::warn!(
+                        "service ID {} is updated in dispatch library {:?}, not checked as the \
+                         path of the service is not recorded",
+                        service_id,
+                        library.path
+                    );
+                }
+                Some(service) if service.path == previous.path => {
+                    log::info!(
+                        "service {} ({}) is updated in dispatch library {:?}",
+                        service.name(),
+                        service_id,
+                        library.path
+                    );
+                }
+                Some(service) => {
+                    return Err(Error::ServiceConflict {
+                        service_id: *service_id,
+                        service: service.name().to_owned(),
+                        previous: previous.name().to_owned(),
+                    })
+                }
+                None => {
+                    return Err(Error::ServiceMissing(
+                        *service_id,
+                        previous.name().to_owned(),
+                    ));
+                }
+            }
+        }
+
+        self.identifier = Some(library.identifier.clone());
+        self.services = services;
+        Ok(())
+    }
+}
@@ -8,7 +8,15 @@ use super::super::{MethodIdentifier, RpcMethodInfo};
 
 pub struct ServiceRecorder {
     pub mapping: Rc<RefCell<HashMap<MethodIdentifier, RpcMethodInfo>>>,
+    /// (service_id, service path, another service path) of the services whose IDs collide
+    pub collisions: Rc<RefCell<Vec<(u32, String, String)>>>,
     pub compile_well_known_types: bool,
+    /// service_id -> the path of the service seen so far
+    pub service_paths: HashMap<u32, String>,
+    /// (func_id, method path, another method path) of the methods whose IDs collide
+    pub func_collisions: Rc<RefCell<Vec<(u32, String, String)>>>,
+    /// func_id -> the path of the method seen so far, the dispatch matches func_id alone
+    pub func_paths: HashMap<u32, String>,
 }
 
 impl prost_build::ServiceGenerator for ServiceRecorder {
@@ -16,10 +24,35 @@ impl prost_build::ServiceGenerator for ServiceRecorder {
         let mut mapping = self.mapping.borrow_mut();
         let package = &service.package[..];
         let service_path = get_service_path(package, &service);
-        let service_id = get_mrpc_service_id(&service_path);
+        let service_id = get_mrpc_service_id(&service_path, &service.comments.leading);
+        match self.service_paths.get(&service_id) {
+            Some(other) if *other != service_path => {
+                // the dispatch table would mix the methods of both services
+                self.collisions
+                    .borrow_mut()
+                    .push((service_id, service_path, other.clone()));
+                return;
+            }
+            Some(_) => {}
+            None => {
+                self.service_paths.insert(service_id, service_path.clone());
+            }
+        }
         for method in service.methods.iter() {
             let method_path = get_method_path(package, &service, method);
-            let func_id = get_mrpc_func_id(&method_path);
+            let func_id = get_mrpc_func_id(&method_path, &method.comments.leading);
+            match self.func_paths.get(&func_id) {
+                Some(other) if *other != method_path => {
+                    self.func_collisions
+                        .borrow_mut()
+                        .push((func_id, method_path, other.clone()));
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    self.func_paths.insert(func_id, method_path.clone());
+                }
+            }
             let input_type_canonical = resolve_ident(
                 package,
                 &method.input_proto_type,
@@ -33,6 +66,7 @@ impl prost_build::ServiceGenerator for ServiceRecorder {
                 self.compile_well_known_types,
             );
             let method_info = RpcMethodInfo {
+                service: service_path.clone(),
                 service_id,
                 func_id,
                 input_type: input_type_canonical,
@@ -101,12 +135,26 @@ fn get_method_path(
     )
 }
 
-// Calculate SERVICE_ID for mRPC NamedService.
-fn get_mrpc_service_id(path: &str) -> u32 {
-    crc32fast::hash(path.as_bytes())
+// Calculate SERVICE_ID for mRPC NamedService, overridden by `mrpc.service_id = <id>` in the
+// comments of the service as mrpc-build does.
+fn get_mrpc_service_id(path: &str, comments: &[String]) -> u32 {
+    explicit_id(comments, "mrpc.service_id").unwrap_or_else(|| crc32fast::hash(path.as_bytes()))
+}
+
+// Calculate FUNC_ID for mRPC remote procedures, overridden by `mrpc.func_id = <id>` in the
+// comments of the method as mrpc-build does.
+fn get_mrpc_func_id(path: &str, comments: &[String]) -> u32 {
+    explicit_id(comments, "mrpc.func_id").unwrap_or_else(|| crc32fast::hash(path.as_bytes()))
 }
 
-// Calculate FUNC_ID for mRPC remote procedures.
-fn get_mrpc_func_id(path: &str) -> u32 {
-    crc32fast::hash(path.as_bytes())
+// Finds a `<key> = <id>` line in the comments, a malformed one is ignored and mrpc-build rejects
+// it.
+fn explicit_id(comments: &[String], key: &str) -> Option<u32> {
+    comments.iter().find_map(|line| {
+        let (k, v) = line.trim().split_once('=')?;
+        if k.trim() != key {
+            return None;
+        }
+        v.trim().trim_end_matches(';').trim().parse().ok()
+    })
 }
@@ -3,6 +3,9 @@ use std::path::PathBuf;
 use phoenix_api_mrpc::control_plane::TransportType;
 use serde::{Deserialize, Serialize};
 
+use crate::builder::cache::GcPolicy;
+use crate::resolver::ResolverConfig;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MrpcConfig {
@@ -15,11 +18,24 @@ pub struct MrpcConfig {
     /// The directory to store the build cache
     #[serde(default = "default_build_cache")]
     pub build_cache: PathBuf,
-    /// Transport to use
+    /// When to remove entries from the build cache
+    #[serde(default)]
+    pub build_cache_gc: GcPolicy,
+    /// Directories of prebuilt dispatch libraries produced by `mrpc-build dispatch`,
+    /// searched in order before the build cache. Relative paths are resolved like `build_cache`.
+    #[serde(default)]
+    pub prebuilt_dispatch: Vec<PathBuf>,
+    /// Build the dispatch library if it is not prebuilt. This requires a Rust toolchain.
+    #[serde(default = "default_build_dispatch")]
+    pub build_dispatch: bool,
+    /// Transport to use, `Auto` connects over RDMA and falls back to TCP
     pub transport: TransportType,
     /// Use NIC 0 by default
     #[serde(default)]
     pub nic_index: usize,
+    /// How the host names of the applications are resolved
+    #[serde(default)]
+    pub resolver: ResolverConfig,
 }
 
 impl MrpcConfig {
@@ -34,6 +50,10 @@ fn default_build_cache() -> PathBuf {
     PathBuf::from("build_cache")
 }
 
+fn default_build_dispatch() -> bool {
+    true
+}
+
 fn default_engine_basename() -> String {
     "mrpc-engine".to_owned()
 }
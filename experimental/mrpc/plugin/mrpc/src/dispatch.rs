@@ -0,0 +1,104 @@
+//! The versions of the dispatch library of a client.
+//!
+//! Every `update_protos` loads a new version of the dispatch library in the backend, while the
+//! connections stay up. The messages handed to the backend before are still marshaled with the
+//! library of the version they are sent in, which the backend keeps loaded until they have all
+//! been acknowledged. A message that is never acknowledged, e.g., dropped by a policy without an
+//! ack, keeps the library of its version loaded.
+use std::collections::BTreeMap;
+
+use fnv::FnvHashSet;
+
+use phoenix_api::rpc::RpcId;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DispatchVersions {
+    // the version of the library loaded last, 0 if none is loaded
+    current: u32,
+    // the earlier versions that are still used, and the messages in flight that use them
+    draining: BTreeMap<u32, FnvHashSet<RpcId>>,
+}
+
+impl DispatchVersions {
+    /// The version to send the messages in.
+    #[inline]
+    pub(crate) fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Moves to the next version. The messages in flight, `in_flight`, are sent in the current
+    /// version, and keep it. Returns the new version, and the current one if it can be unloaded
+    /// right away.
+    pub(crate) fn advance<I>(&mut self, in_flight: I) -> (u32, Option<u32>)
+    where
+        I: IntoIterator<Item = RpcId>,
+    {
+        let previous = self.current;
+        self.current += 1;
+        if previous == 0 {
+            return (self.current, None);
+        }
+        // the messages of older versions in flight are tracked already
+        let in_flight: FnvHashSet<RpcId> = in_flight
+            .into_iter()
+            .filter(|rpc_id| !self.draining.values().any(|msgs| msgs.contains(rpc_id)))
+            .collect();
+        if in_flight.is_empty() {
+            return (self.current, Some(previous));
+        }
+        self.draining.insert(previous, in_flight);
+        (self.current, None)
+    }
+
+    /// Records that the message `rpc_id` has been acknowledged. Returns the version that it was
+    /// the last message in flight of, which can be unloaded.
+    #[inline]
+    pub(crate) fn acked(&mut self, rpc_id: RpcId) -> Option<u32> {
+        if self.draining.is_empty() {
+            return None;
+        }
+        let (version, msgs) = self
+            .draining
+            .iter_mut()
+            .find(|(_, msgs)| msgs.contains(&rpc_id))?;
+        let version = *version;
+        msgs.remove(&rpc_id);
+        if msgs.is_empty() {
+            self.draining.remove(&version);
+            return Some(version);
+        }
+        None
+    }
+
+    /// The earlier versions that are still used, and the numbers of their messages in flight.
+    pub(crate) fn draining(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+        self.draining
+            .iter()
+            .map(|(version, msgs)| (*version, msgs.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phoenix_api::rpc::CallId;
+    use phoenix_api::Handle;
+
+    fn rpc_id(call_id: u64) -> RpcId {
+        RpcId::new(Handle(1), CallId(call_id))
+    }
+
+    #[test]
+    fn retire_after_acked() {
+        let mut versions = DispatchVersions::default();
+        assert_eq!(versions.advance(vec![rpc_id(0)]), (1, None));
+        assert_eq!(versions.advance(vec![rpc_id(1), rpc_id(2)]), (2, None));
+        assert_eq!(versions.acked(rpc_id(1)), None);
+        // the message 2 is still counted in version 1
+        assert_eq!(versions.advance(vec![rpc_id(2), rpc_id(3)]), (3, None));
+        assert_eq!(versions.acked(rpc_id(2)), Some(1));
+        assert_eq!(versions.acked(rpc_id(3)), Some(2));
+        assert_eq!(versions.advance(None), (4, Some(3)));
+        assert_eq!(versions.current(), 4);
+    }
+}
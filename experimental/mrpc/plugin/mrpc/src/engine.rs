@@ -1,18 +1,22 @@
+use std::collections::BTreeMap;
 use std::mem;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::pin::Pin;
 
 use anyhow::{anyhow, Result};
 use futures::future::BoxFuture;
-use std::num::NonZeroU32;
 
 use phoenix_api::engine::SchedulingMode;
-use phoenix_api::rpc::{MessageErased, RpcId, StatusCode};
+use phoenix_api::rpc::{ConnectionState, MessageErased, RpcId, StatusCode};
+use phoenix_api::Handle;
 use phoenix_api_mrpc::{cmd, control_plane, dp};
+use tokio::sync::mpsc::error::SendError;
 
 use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
 use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
-use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::datapath::{DataPathNode, TryRecvError};
+use phoenix_common::engine::profile::{Phase, Profiler};
 use phoenix_common::engine::{
     future, Decompose, DecomposeResult, Engine, EngineResult, Indicator, Vertex,
 };
@@ -22,8 +26,12 @@ use phoenix_common::module::{ModuleCollection, Version};
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 use phoenix_common::{log, tracing};
 
-use super::builder::build_serializer_lib;
+use super::builder::namespace::ProtoNamespace;
+use super::builder::DispatchCache;
+use super::dispatch::DispatchVersions;
+use super::fallback::{self, Fallback};
 use super::module::CustomerType;
+use super::resolver::Resolver;
 use super::state::State;
 use super::{DatapathError, Error};
 
@@ -42,12 +50,27 @@ pub struct MrpcEngine {
 
     pub(crate) _mode: SchedulingMode,
 
-    pub(crate) dispatch_build_cache: PathBuf,
+    pub(crate) dispatch_cache: DispatchCache,
+    /// The services in the dispatch library loaded for the client
+    pub(crate) proto_namespace: ProtoNamespace,
+    /// The versions of the dispatch library loaded in the backend
+    pub(crate) dispatch_versions: DispatchVersions,
+    /// Resolves the host names to connect to
+    pub(crate) resolver: Resolver,
 
     pub(crate) transport_type: Option<control_plane::TransportType>,
+    /// The connections over TCP when RDMA fails, if the transport of the module is `Auto`
+    pub(crate) fallback: Option<Fallback>,
 
     pub(crate) indicator: Indicator,
+    pub(crate) profiler: Profiler,
     pub(crate) wr_read_buffer: Vec<dp::WorkRequest>,
+    /// The deadline of the message that follows, see `WorkRequest::Deadline`. It is not kept
+    /// across upgrades, the message is sent without a deadline then.
+    pub(crate) next_deadline: Option<(RpcId, u64)>,
+    /// The message that follows is sent in bulk, see `WorkRequest::Bulk`. It is not kept across
+    /// upgrades either.
+    pub(crate) next_bulk: Option<RpcId>,
 }
 
 impl_vertex_for_engine!(MrpcEngine, node);
@@ -55,11 +78,11 @@ impl_vertex_for_engine!(MrpcEngine, node);
 impl Decompose for MrpcEngine {
     #[inline]
     fn flush(&mut self) -> DecomposeResult<usize> {
-        // mRPC engine has a single receiver on data path,
-        // i.e., rx_inputs()[0]
+        // mRPC engine has a receiver on data path for each adapter,
+        // i.e., rx_inputs()[0], and rx_inputs()[1] if it falls back to TCP
         // each call to `check_input_queue()` processes at most one message
         let mut work = 0;
-        while !self.rx_inputs()[0].is_empty() {
+        while self.rx_inputs().iter().any(|rx| !rx.is_empty()) {
             if let Progress(n) = self.check_input_queue()? {
                 work += n;
             }
@@ -87,13 +110,23 @@ impl Decompose for MrpcEngine {
         collections.insert("cmd_rx".to_string(), Box::new(engine.cmd_rx));
         collections.insert("meta_buf_pool".to_string(), Box::new(engine.meta_buf_pool));
         collections.insert(
-            "dispatch_build_cache".to_string(),
-            Box::new(engine.dispatch_build_cache),
+            "dispatch_cache".to_string(),
+            Box::new(engine.dispatch_cache),
         );
+        collections.insert(
+            "proto_namespace".to_string(),
+            Box::new(engine.proto_namespace),
+        );
+        collections.insert(
+            "dispatch_versions".to_string(),
+            Box::new(engine.dispatch_versions),
+        );
+        collections.insert("resolver".to_string(), Box::new(engine.resolver));
         collections.insert(
             "transport_type".to_string(),
             Box::new(engine.transport_type),
         );
+        collections.insert("fallback".to_string(), Box::new(engine.fallback));
         collections.insert(
             "wr_read_buffer".to_string(),
             Box::new(engine.wr_read_buffer),
@@ -143,16 +176,56 @@ impl MrpcEngine {
             .unwrap()
             .downcast::<MetaBufferPool>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
-        let dispatch_build_cache = *local
-            .remove("dispatch_build_cache")
-            .unwrap()
-            .downcast::<PathBuf>()
-            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let dispatch_cache = match local.remove("dispatch_build_cache") {
+            // Upgraded from a version that only has the build cache.
+            Some(build_cache) => DispatchCache {
+                build_cache: *build_cache
+                    .downcast::<PathBuf>()
+                    .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+                prebuilt: Vec::new(),
+                build_on_miss: true,
+                gc: Default::default(),
+            },
+            None => *local
+                .remove("dispatch_cache")
+                .unwrap()
+                .downcast::<DispatchCache>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+        };
+        let proto_namespace = match local.remove("proto_namespace") {
+            Some(proto_namespace) => *proto_namespace
+                .downcast::<ProtoNamespace>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that does not track the services, start over.
+            None => ProtoNamespace::default(),
+        };
+        let dispatch_versions = match local.remove("dispatch_versions") {
+            Some(dispatch_versions) => *dispatch_versions
+                .downcast::<DispatchVersions>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that does not number the dispatch libraries, the backend
+            // only keeps the latest one.
+            None => DispatchVersions::default(),
+        };
+        let resolver = match local.remove("resolver") {
+            Some(resolver) => *resolver
+                .downcast::<Resolver>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that does not resolve host names.
+            None => Resolver::default(),
+        };
         let transport_type = *local
             .remove("transport_type")
             .unwrap()
             .downcast::<Option<control_plane::TransportType>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let fallback = match local.remove("fallback") {
+            Some(fallback) => *fallback
+                .downcast::<Option<Fallback>>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that only connects over a single transport.
+            None => None,
+        };
         let wr_read_buffer = *local
             .remove("wr_read_buffer")
             .unwrap()
@@ -167,10 +240,17 @@ impl MrpcEngine {
             node,
             meta_buf_pool,
             _mode: mode,
-            dispatch_build_cache,
+            dispatch_cache,
+            proto_namespace,
+            dispatch_versions,
+            resolver,
             transport_type,
+            fallback,
             indicator: Default::default(),
+            profiler: Profiler::new(),
             wr_read_buffer,
+            next_deadline: None,
+            next_bulk: None,
         };
         Ok(engine)
     }
@@ -197,39 +277,109 @@ impl Engine for MrpcEngine {
     fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
         &mut self.get_mut().indicator
     }
+
+    #[inline]
+    fn profiler(&self) -> Option<&Profiler> {
+        Some(&self.profiler)
+    }
+
+    fn dump(self: Pin<&Self>) -> Option<String> {
+        Some(self.get_ref().dump_state())
+    }
+
+    fn on_fault(&mut self, reason: &str) {
+        let err = phoenix_api::Error::Generic(format!("mRPC service failed: {}", reason));
+        if let Err(e) = self.customer.send_comp(cmd::Completion(Err(err))) {
+            log::warn!("failed to notify the client of the failure: {}", e);
+        }
+    }
 }
 
 impl MrpcEngine {
+    fn dump_state(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let _ = writeln!(out, "transport: {:?}", self.transport_type);
+        if let Some(fallback) = self.fallback.as_ref() {
+            let stats = fallback.stats();
+            let _ = writeln!(
+                out,
+                "connections: {} over RDMA, {} over TCP, {} fell back to TCP",
+                stats.rdma, stats.tcp, stats.fell_back
+            );
+            for (conn_id, transport) in fallback.conns() {
+                let _ = writeln!(out, "  connection {:#x}: {:?}", conn_id.0, transport);
+            }
+        }
+        let _ = writeln!(
+            out,
+            "protos: {}, dispatch version {}",
+            self.proto_namespace.identifier().unwrap_or("none"),
+            self.dispatch_versions.current()
+        );
+        for (version, n) in self.dispatch_versions.draining() {
+            let _ = writeln!(out, "  draining version {}: {} in flight", version, n);
+        }
+
+        // the messages sent and not acknowledged yet, by connection
+        let mut in_flight: BTreeMap<u64, usize> = BTreeMap::new();
+        for rpc_id in self.meta_buf_pool.in_use() {
+            *in_flight.entry(rpc_id.0 .0).or_default() += 1;
+        }
+        let _ = writeln!(
+            out,
+            "meta buffers: {} in flight, {} free",
+            in_flight.values().sum::<usize>(),
+            self.meta_buf_pool.free.len()
+        );
+        for (conn_id, n) in in_flight {
+            let _ = writeln!(out, "  connection {:#x}: {} in flight", conn_id, n);
+        }
+        out
+    }
+
     async fn mainloop(&mut self) -> EngineResult {
         loop {
             // let mut timer = utils::timer::Timer::new();
             let mut nwork = 0;
+            let budget = self.indicator.budget();
 
             // no work 80ns
             // has work: <1us for a batch of 30
+            let start = self.profiler.start();
             loop {
                 // no work: 40ns
                 if let Progress(n) = self.check_customer()? {
                     nwork += n;
-                    if n == 0 {
+                    if n == 0 || nwork >= budget {
                         break;
                     }
                 }
             }
+            self.profiler.record(Phase::Customer, start);
             // timer.tick();
 
             // no work: 20ns
             // has work: <2us for a batch of 30
+            let start = self.profiler.start();
             loop {
                 match self.check_input_queue()? {
                     Progress(0) => break,
-                    Progress(n) => nwork += n,
+                    Progress(n) => {
+                        nwork += n;
+                        if nwork >= budget {
+                            break;
+                        }
+                    }
                     Status::Disconnected => break,
                 }
             }
+            self.profiler.record(Phase::InputQueue, start);
             // timer.tick();
 
             if fastrand::usize(..100) < 1 {
+                let start = self.profiler.start();
                 // 80-100ns, sometimes 200ns
                 if let Status::Disconnected = self.check_cmd().await? {
                     break;
@@ -238,6 +388,10 @@ impl MrpcEngine {
 
                 // 50ns
                 self.check_input_cmd_queue()?;
+                if let Some(result) = self.resolver.poll() {
+                    self.on_resolved(result)?;
+                }
+                self.profiler.record(Phase::Command, start);
                 // timer.tick();
             }
 
@@ -257,16 +411,18 @@ impl MrpcEngine {
     // (whether successful or not), to release message meta pool and shutdown mRPC engine.
     // However, we cannot indefinitely wait for it in case of wc errors.
     async fn wait_outstanding_complete(&mut self) -> Result<(), DatapathError> {
-        use phoenix_common::engine::datapath::TryRecvError;
         while !self.meta_buf_pool.is_full() {
-            match self.rx_inputs()[0].try_recv() {
+            match self.try_recv_input() {
                 Ok(msg) => match msg {
                     EngineRxMessage::Ack(rpc_id, _status) => {
                         // release the buffer whatever the status is.
                         self.meta_buf_pool.release(rpc_id)?;
+                        self.retire_acked(rpc_id);
                     }
                     EngineRxMessage::RpcMessage(_) => {}
                     EngineRxMessage::RecvError(..) => {}
+                    EngineRxMessage::ConnectionState(..) => {}
+                    EngineRxMessage::CallTiming(..) => {}
                 },
                 Err(TryRecvError::Disconnected) => return Ok(()),
                 Err(TryRecvError::Empty) => {}
@@ -276,6 +432,43 @@ impl MrpcEngine {
         Ok(())
     }
 
+    /// Unloads the previous dispatch library that `rpc_id` was the last message in flight of.
+    fn retire_acked(&mut self, rpc_id: RpcId) {
+        if let Some(version) = self.dispatch_versions.acked(rpc_id) {
+            log::debug!("retiring dispatch library version {}", version);
+            // the backend may have shut down already
+            let _ = self.send_cmd(cmd::Command::RetireProtosInner(version));
+        }
+    }
+
+    /// Sends a command to the adapter, or to the adapters if the engine falls back to TCP.
+    fn send_cmd(&mut self, cmd: cmd::Command) -> Result<(), SendError<cmd::Command>> {
+        match self.fallback.as_mut() {
+            Some(fallback) => fallback.submit(&self.cmd_tx, cmd),
+            None => self.cmd_tx.send(cmd),
+        }
+    }
+
+    /// Returns the index of the data path queues of the adapter of a connection.
+    #[inline]
+    fn output_of(&self, conn_id: Handle) -> usize {
+        match self.fallback {
+            Some(_) => fallback::adapter_of(conn_id),
+            None => 0,
+        }
+    }
+
+    /// Receives a message from the adapters, from the one over RDMA first.
+    fn try_recv_input(&mut self) -> Result<EngineRxMessage, TryRecvError> {
+        let received = self.rx_inputs()[0].try_recv();
+        match received {
+            Err(TryRecvError::Empty) if self.fallback.is_some() => {
+                self.rx_inputs()[fallback::TCP].try_recv()
+            }
+            received => received,
+        }
+    }
+
     async fn check_cmd(&mut self) -> Result<Status, Error> {
         match self.customer.try_recv_cmd() {
             // handle request
@@ -299,6 +492,29 @@ impl MrpcEngine {
 
     fn create_transport(&mut self, transport_type: control_plane::TransportType) {
         self.transport_type = Some(transport_type);
+        if let Some(fallback) = self.fallback.as_mut() {
+            fallback.set_transport(transport_type);
+        }
+    }
+
+    /// Connects to the address a host name is resolved to, or fails the `ConnectHost` command.
+    fn on_resolved(
+        &mut self,
+        result: Result<SocketAddr, super::resolver::Error>,
+    ) -> Result<(), Error> {
+        match result {
+            Ok(addr) => self
+                .send_cmd(cmd::Command::Connect(addr, Default::default()))
+                .unwrap(),
+            Err(e) => {
+                log::debug!("ConnectHost failed: {}", e);
+                // the client waits for the fds of the connection before the completion
+                self.customer.send_fd(&[])?;
+                let err = Error::from(e).into();
+                self.customer.send_comp(cmd::Completion(Err(err)))?;
+            }
+        }
+        Ok(())
     }
 
     async fn process_cmd(
@@ -315,34 +531,72 @@ impl MrpcEngine {
                     Ok(Some(CompletionKind::SetTransport))
                 }
             }
-            Command::Connect(addr) => {
-                self.cmd_tx.send(Command::Connect(*addr)).unwrap();
+            Command::Connect(addr, options) => {
+                self.send_cmd(Command::Connect(*addr, *options)).unwrap();
+                Ok(None)
+            }
+            Command::ConnectDatagram(addr) => {
+                self.send_cmd(Command::ConnectDatagram(*addr)).unwrap();
+                Ok(None)
+            }
+            Command::ConnectHost(host, port) => {
+                // the lookup may take a while, the connection is made when it completes
+                if let Some(result) = self.resolver.resolve(host, *port) {
+                    self.on_resolved(result)?;
+                }
+                Ok(None)
+            }
+            Command::Bind(addr, options) => {
+                self.send_cmd(Command::Bind(*addr, *options)).unwrap();
                 Ok(None)
             }
-            Command::Bind(addr) => {
-                self.cmd_tx.send(Command::Bind(*addr)).unwrap();
+            Command::Unbind(listener) => {
+                self.send_cmd(Command::Unbind(*listener)).unwrap();
                 Ok(None)
             }
             Command::NewMappedAddrs(conn_handle, app_vaddrs) => {
-                self.cmd_tx
-                    .send(Command::NewMappedAddrs(*conn_handle, app_vaddrs.clone()))
+                self.send_cmd(Command::NewMappedAddrs(*conn_handle, app_vaddrs.clone()))
+                    .unwrap();
+                Ok(None)
+            }
+            Command::QueryCredits(handles) => {
+                self.send_cmd(Command::QueryCredits(handles.clone()))
+                    .unwrap();
+                Ok(None)
+            }
+            Command::SetCallTiming(handles, enable) => {
+                self.send_cmd(Command::SetCallTiming(handles.clone(), *enable))
+                    .unwrap();
+                Ok(None)
+            }
+            Command::SetEagerCopyThreshold(handles, threshold) => {
+                self.send_cmd(Command::SetEagerCopyThreshold(handles.clone(), *threshold))
                     .unwrap();
                 Ok(None)
             }
             Command::UpdateProtos(protos) => {
-                let dylib_path =
-                    build_serializer_lib(protos.clone(), self.dispatch_build_cache.clone())?;
-                self.cmd_tx
-                    .send(Command::UpdateProtosInner(dylib_path))
+                let library = self.dispatch_cache.get_or_build(protos.clone())?;
+                self.proto_namespace.update(&library)?;
+                // the messages in flight are still marshaled with the current library, which is
+                // retired once they are all acknowledged
+                let (version, retired) =
+                    self.dispatch_versions.advance(self.meta_buf_pool.in_use());
+                self.send_cmd(Command::UpdateProtosInner(library.path, version))
                     .unwrap();
+                if let Some(retired) = retired {
+                    self.send_cmd(Command::RetireProtosInner(retired)).unwrap();
+                }
                 Ok(None)
             }
             Command::MultiConnect(_) => {
                 panic!("MultiConnect is only used in mrpclb")
             }
-            Command::UpdateProtosInner(_) => {
+            Command::UpdateProtosInner(..) => {
                 panic!("UpdateProtosInner is only used in backend")
             }
+            Command::RetireProtosInner(_) => {
+                panic!("RetireProtosInner is only used in backend")
+            }
         }
     }
 
@@ -438,16 +692,25 @@ impl MrpcEngine {
                     std::ptr::write(meta_buf_ptr.as_meta_ptr(), erased.meta);
                 }
 
+                let deadline = match self.next_deadline.take() {
+                    Some((id, deadline)) if id == rpc_id => deadline,
+                    _ => 0,
+                };
+                let bulk = self.next_bulk.take() == Some(rpc_id);
                 let msg = RpcMessageTx {
                     meta_buf_ptr,
                     addr_backend: erased.shm_addr_backend,
+                    deadline,
+                    bulk,
+                    dispatch_version: self.dispatch_versions.current(),
                 };
 
                 // timer.tick();
 
                 // if access to message's data fields are desired,
                 // typed message can be conjured up here via matching func_id
-                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                let output = self.output_of(erased.meta.conn_id);
+                self.tx_outputs()[output].send(EngineTxMessage::RpcMessage(msg))?;
 
                 // timer.tick();
                 // log::info!("process_dp call/reply: {}", timer);
@@ -456,19 +719,25 @@ impl MrpcEngine {
                 // let mut timer = crate::timer::Timer::new();
 
                 // 10-40ns, mostly 10ns
-                self.tx_outputs()[0]
+                let output = self.output_of(*conn_id);
+                self.tx_outputs()[output]
                     .send(EngineTxMessage::ReclaimRecvBuf(*conn_id, *msg_call_ids))?;
 
                 // timer.tick();
                 // log::info!("process_dp reclaim recv buf: {}", timer);
             }
+            WorkRequest::Deadline(rpc_id, deadline) => {
+                self.next_deadline = Some((*rpc_id, *deadline));
+            }
+            WorkRequest::Bulk(rpc_id) => {
+                self.next_bulk = Some(*rpc_id);
+            }
         }
         Ok(())
     }
 
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
-        use phoenix_common::engine::datapath::TryRecvError;
-        match self.rx_inputs()[0].try_recv() {
+        match self.try_recv_input() {
             Ok(msg) => {
                 match msg {
                     EngineRxMessage::RpcMessage(msg) => {
@@ -486,13 +755,19 @@ impl MrpcEngine {
                         };
                         // timer.tick();
                         match meta.status_code {
-                            StatusCode::AccessDenied => {
-                                tracing::debug!("Status code: Access denied, meta={:?}", meta);
+                            StatusCode::AccessDenied
+                            | StatusCode::Unimplemented
+                            | StatusCode::DataLoss
+                            | StatusCode::Failed => {
+                                tracing::debug!(
+                                    "Status code: {:?}, meta={:?}",
+                                    meta.status_code,
+                                    meta
+                                );
                                 let mut sent = false;
                                 let rpc_id = RpcId(meta.conn_id, meta.call_id);
-                                let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
-                                    NonZeroU32::new_unchecked(402)
-                                });
+                                // only `Unknown` has no transport status
+                                let status = meta.status_code.transport_status().unwrap();
                                 while !sent {
                                     self.customer.enqueue_wc_with(|ptr, _count| unsafe {
                                         // self.customer.notify_wc_with(|ptr, _count| unsafe {
@@ -504,7 +779,8 @@ impl MrpcEngine {
                                 }
                                 let msg_call_ids =
                                     [meta.call_id, meta.call_id, meta.call_id, meta.call_id];
-                                self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
+                                let output = self.output_of(meta.conn_id);
+                                self.tx_outputs()[output].send(EngineTxMessage::ReclaimRecvBuf(
                                     meta.conn_id,
                                     msg_call_ids,
                                 ))?;
@@ -533,6 +809,7 @@ impl MrpcEngine {
                     EngineRxMessage::Ack(rpc_id, status) => {
                         // release message meta buffer
                         self.meta_buf_pool.release(rpc_id)?;
+                        self.retire_acked(rpc_id);
                         let mut sent = false;
                         while !sent {
                             self.customer.enqueue_wc_with(|ptr, _count| unsafe {
@@ -556,6 +833,35 @@ impl MrpcEngine {
                             })?;
                         }
                     }
+                    EngineRxMessage::ConnectionState(conn_id, state) => {
+                        if let (
+                            Some(fallback),
+                            ConnectionState::Closed | ConnectionState::Migrated,
+                        ) = (self.fallback.as_mut(), state)
+                        {
+                            fallback.close(conn_id);
+                        }
+                        let mut sent = false;
+                        while !sent {
+                            self.customer.enqueue_wc_with(|ptr, _count| unsafe {
+                                sent = true;
+                                ptr.cast::<dp::Completion>()
+                                    .write(dp::Completion::ConnectionState(conn_id, state));
+                                1
+                            })?;
+                        }
+                    }
+                    EngineRxMessage::CallTiming(rpc_id, timing) => {
+                        let mut sent = false;
+                        while !sent {
+                            self.customer.enqueue_wc_with(|ptr, _count| unsafe {
+                                sent = true;
+                                ptr.cast::<dp::Completion>()
+                                    .write(dp::Completion::CallTiming(rpc_id, timing));
+                                1
+                            })?;
+                        }
+                    }
                 }
                 Ok(Progress(1))
             }
@@ -567,8 +873,12 @@ impl MrpcEngine {
     fn check_input_cmd_queue(&mut self) -> Result<Status, Error> {
         use phoenix_api_mrpc::cmd::{Completion, CompletionKind};
         use tokio::sync::mpsc::error::TryRecvError;
-        match self.cmd_rx.try_recv() {
-            Ok(Completion(comp)) => {
+        let received = match self.fallback.as_mut() {
+            Some(fallback) => fallback.try_recv(&self.cmd_tx, &mut self.cmd_rx),
+            None => self.cmd_rx.try_recv().map(|comp| (comp, false)),
+        };
+        match received {
+            Ok((Completion(comp), connect)) => {
                 match comp {
                     // server new incoming connection
                     Ok(CompletionKind::NewConnectionInternal(conn_resp, fds)) => {
@@ -588,12 +898,24 @@ impl MrpcEngine {
                     // server bind response
                     c @ Ok(
                         CompletionKind::Bind(..)
+                        | CompletionKind::Unbind
                         | CompletionKind::NewMappedAddrs
-                        | CompletionKind::UpdateProtos,
+                        | CompletionKind::UpdateProtos
+                        | CompletionKind::QueryCredits(..)
+                        | CompletionKind::SetCallTiming
+                        | CompletionKind::SetEagerCopyThreshold,
                     ) => {
                         self.customer.send_comp(cmd::Completion(c))?;
                         Ok(Status::Progress(1))
                     }
+                    // a previous dispatch library is unloaded, the application does not wait for it
+                    Ok(CompletionKind::RetireProtosInner) => Ok(Status::Progress(1)),
+                    // a connection failed over both transports
+                    Err(e) if connect => {
+                        self.customer.send_fd(&[])?;
+                        self.customer.send_comp(cmd::Completion(Err(e)))?;
+                        Ok(Status::Progress(1))
+                    }
                     other => panic!("unexpected: {:?}", other),
                 }
             }
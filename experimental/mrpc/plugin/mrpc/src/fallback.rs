@@ -0,0 +1,354 @@
+//! Connections over TCP for the peers RDMA cannot reach, with `transport = "Auto"`.
+//!
+//! The engine is connected to both the RpcAdapterEngine and the TcpRpcAdapterEngine, on the data
+//! path queues [`RDMA`] and [`TCP`]. A connection is made over RDMA first, and over TCP if that
+//! fails, e.g., the route to the peer cannot be resolved, or the peer has no RDMA device. The
+//! state that belongs to no connection, i.e., the listeners and the dispatch libraries, is
+//! mirrored to both adapters. The application gets one completion of each command, in the order
+//! of the commands, as if there were a single adapter.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+
+use tokio::sync::mpsc::error::{SendError, TryRecvError};
+
+use phoenix_api::{Handle, HandleNamespace};
+use phoenix_api_mrpc::cmd::{self, Command, CompletionKind, ConnectOptions};
+use phoenix_api_mrpc::control_plane::TransportType;
+use phoenix_common::log;
+
+pub(crate) type CmdTx = tokio::sync::mpsc::UnboundedSender<cmd::Command>;
+pub(crate) type CmdRx = tokio::sync::mpsc::UnboundedReceiver<cmd::Completion>;
+
+/// The index of the queues of the RpcAdapterEngine.
+pub(crate) const RDMA: usize = 0;
+/// The index of the queues of the TcpRpcAdapterEngine.
+pub(crate) const TCP: usize = 1;
+
+/// Returns the adapter of a connection. The handles of the RDMA connections are tagged, the ones
+/// of the TCP connections are file descriptors.
+pub(crate) fn adapter_of(conn_id: Handle) -> usize {
+    match conn_id.namespace() {
+        Some(HandleNamespace::CmId | HandleNamespace::Datagram) => RDMA,
+        _ => TCP,
+    }
+}
+
+/// What to do with the next completion of an adapter.
+#[derive(Debug)]
+enum Expect {
+    /// Pass it to the application, as the completion of the command of this sequence number.
+    Forward(u64),
+    /// A connection over RDMA, which is made over TCP if it fails.
+    Connect(u64, SocketAddr, ConnectOptions),
+    /// A listener over RDMA, forwarded and paired with the one over TCP.
+    Bind(u64),
+    /// The listener over TCP bound along with the one over RDMA.
+    MirrorBind(u64),
+    /// A command mirrored to the adapter, the other adapter answers it.
+    Mirror,
+}
+
+/// The listeners bound by a `Bind` command, `None` if the adapter has failed it.
+#[derive(Debug, Default)]
+struct BindPair {
+    rdma: Option<Option<Handle>>,
+    tcp: Option<Option<Handle>>,
+}
+
+/// The number of connections made over each transport.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TransportStats {
+    pub(crate) rdma: usize,
+    pub(crate) tcp: usize,
+    /// The connections made over TCP after RDMA failed.
+    pub(crate) fell_back: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Fallback {
+    /// The command queues of the TcpRpcAdapterEngine, the ones of the RpcAdapterEngine are the
+    /// engine's own.
+    cmd_tx: CmdTx,
+    cmd_rx: CmdRx,
+    /// The transport set by the application, the connections are made over RDMA and then TCP if
+    /// it is not set.
+    prefer: Option<TransportType>,
+    expects: [VecDeque<Expect>; 2],
+    /// The sequence numbers of the commands waiting for their completions, in order.
+    order: VecDeque<u64>,
+    next_seq: u64,
+    done: HashMap<u64, cmd::Completion>,
+    /// The sequence numbers of the `Connect` commands, the application waits for the file
+    /// descriptors of the connection before their completions, even if they fail.
+    connects: HashSet<u64>,
+    /// The completions to pass to the application, and whether they are of `Connect` commands.
+    ready: VecDeque<(cmd::Completion, bool)>,
+    binds: HashMap<u64, BindPair>,
+    /// The listener over TCP of each listener over RDMA.
+    listeners: HashMap<Handle, Handle>,
+    /// The adapter of each connection open.
+    conns: HashMap<Handle, usize>,
+    stats: TransportStats,
+}
+
+impl Fallback {
+    pub(crate) fn new(cmd_tx: CmdTx, cmd_rx: CmdRx, transport: TransportType) -> Self {
+        let mut fallback = Fallback {
+            cmd_tx,
+            cmd_rx,
+            prefer: None,
+            expects: Default::default(),
+            order: VecDeque::new(),
+            next_seq: 0,
+            done: HashMap::new(),
+            connects: HashSet::new(),
+            ready: VecDeque::new(),
+            binds: HashMap::new(),
+            listeners: HashMap::new(),
+            conns: HashMap::new(),
+            stats: TransportStats::default(),
+        };
+        fallback.set_transport(transport);
+        fallback
+    }
+
+    /// Sets the transport of the connections made from now on.
+    pub(crate) fn set_transport(&mut self, transport: TransportType) {
+        self.prefer = match transport {
+            TransportType::Auto => None,
+            transport => Some(transport),
+        };
+    }
+
+    /// Sends a command to the adapters.
+    pub(crate) fn submit(
+        &mut self,
+        rdma_tx: &CmdTx,
+        cmd: Command,
+    ) -> Result<(), SendError<Command>> {
+        match cmd {
+            Command::Connect(addr, options) => {
+                let seq = self.next_order();
+                self.connects.insert(seq);
+                match self.prefer {
+                    Some(TransportType::Tcp) => self.send(rdma_tx, TCP, cmd, Expect::Forward(seq)),
+                    Some(TransportType::Rdma) => {
+                        self.send(rdma_tx, RDMA, cmd, Expect::Forward(seq))
+                    }
+                    _ => self.send(rdma_tx, RDMA, cmd, Expect::Connect(seq, addr, options)),
+                }
+            }
+            Command::Bind(_, options) if !options.datagram => {
+                let seq = self.next_order();
+                self.binds.insert(seq, BindPair::default());
+                self.send(rdma_tx, TCP, cmd.clone(), Expect::MirrorBind(seq))?;
+                self.send(rdma_tx, RDMA, cmd, Expect::Bind(seq))
+            }
+            Command::Unbind(listener) => {
+                if let Some(tcp_listener) = self.listeners.remove(&listener) {
+                    self.send(rdma_tx, TCP, Command::Unbind(tcp_listener), Expect::Mirror)?;
+                }
+                let seq = self.next_order();
+                self.send(rdma_tx, RDMA, cmd, Expect::Forward(seq))
+            }
+            Command::UpdateProtosInner(..) => {
+                let seq = self.next_order();
+                self.send(rdma_tx, TCP, cmd.clone(), Expect::Mirror)?;
+                self.send(rdma_tx, RDMA, cmd, Expect::Forward(seq))
+            }
+            // the application does not wait for the retired libraries
+            Command::RetireProtosInner(_) => {
+                self.send(rdma_tx, TCP, cmd.clone(), Expect::Mirror)?;
+                self.send(rdma_tx, RDMA, cmd, Expect::Mirror)
+            }
+            // the commands of connections go to their adapters, the ones of several connections
+            // are assumed to name connections of one adapter
+            Command::NewMappedAddrs(conn_id, _) => {
+                let seq = self.next_order();
+                self.send(rdma_tx, adapter_of(conn_id), cmd, Expect::Forward(seq))
+            }
+            Command::QueryCredits(ref handles)
+            | Command::SetCallTiming(ref handles, _)
+            | Command::SetEagerCopyThreshold(ref handles, _) => {
+                let adapter = handles.first().map_or(RDMA, |&h| adapter_of(h));
+                let seq = self.next_order();
+                self.send(rdma_tx, adapter, cmd, Expect::Forward(seq))
+            }
+            cmd => {
+                let seq = self.next_order();
+                self.send(rdma_tx, RDMA, cmd, Expect::Forward(seq))
+            }
+        }
+    }
+
+    fn next_order(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.push_back(seq);
+        seq
+    }
+
+    fn send(
+        &mut self,
+        rdma_tx: &CmdTx,
+        adapter: usize,
+        cmd: Command,
+        expect: Expect,
+    ) -> Result<(), SendError<Command>> {
+        if adapter == RDMA {
+            rdma_tx.send(cmd)?;
+        } else {
+            self.cmd_tx.send(cmd)?;
+        }
+        self.expects[adapter].push_back(expect);
+        Ok(())
+    }
+
+    /// Takes the next completion to pass to the application, and whether it is of a `Connect`
+    /// command.
+    pub(crate) fn try_recv(
+        &mut self,
+        rdma_tx: &CmdTx,
+        rdma_rx: &mut CmdRx,
+    ) -> Result<(cmd::Completion, bool), TryRecvError> {
+        if let Some(comp) = self.ready.pop_front() {
+            return Ok(comp);
+        }
+        match rdma_rx.try_recv() {
+            Ok(comp) => self.on_completion(rdma_tx, RDMA, comp),
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Err(TryRecvError::Disconnected),
+        }
+        match self.cmd_rx.try_recv() {
+            Ok(comp) => self.on_completion(rdma_tx, TCP, comp),
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Err(TryRecvError::Disconnected),
+        }
+        while let Some(&seq) = self.order.front() {
+            let Some(comp) = self.done.remove(&seq) else {
+                break;
+            };
+            self.order.pop_front();
+            self.ready.push_back((comp, self.connects.remove(&seq)));
+        }
+        self.ready.pop_front().ok_or(TryRecvError::Empty)
+    }
+
+    fn on_completion(&mut self, rdma_tx: &CmdTx, adapter: usize, comp: cmd::Completion) {
+        // the connections accepted by the listeners come unasked
+        if let Ok(CompletionKind::NewConnectionInternal(conn_resp, _)) = &comp.0 {
+            self.open(conn_resp.conn_handle, adapter);
+            self.ready.push_back((comp, false));
+            return;
+        }
+        let Some(expect) = self.expects[adapter].pop_front() else {
+            log::warn!("Unexpected completion from adapter {}: {:?}", adapter, comp);
+            return;
+        };
+        match expect {
+            Expect::Forward(seq) => {
+                if let Ok(CompletionKind::ConnectInternal(conn_resp, _)) = &comp.0 {
+                    self.open(conn_resp.conn_handle, adapter);
+                }
+                self.done.insert(seq, comp);
+            }
+            Expect::Connect(seq, addr, options) => match comp.0 {
+                Err(e) => {
+                    log::info!("Connect to {} over RDMA failed: {}, trying TCP", addr, e);
+                    self.stats.fell_back += 1;
+                    let cmd = Command::Connect(addr, options);
+                    if self.send(rdma_tx, TCP, cmd, Expect::Forward(seq)).is_err() {
+                        let err = phoenix_api::Error::Generic(format!(
+                            "Failed to connect to {} over RDMA: {}, TCP is gone",
+                            addr, e
+                        ));
+                        self.done.insert(seq, cmd::Completion(Err(err)));
+                    }
+                }
+                Ok(kind) => {
+                    if let CompletionKind::ConnectInternal(conn_resp, _) = &kind {
+                        self.open(conn_resp.conn_handle, RDMA);
+                    }
+                    self.done.insert(seq, cmd::Completion(Ok(kind)));
+                }
+            },
+            Expect::Bind(seq) => {
+                let listener = match &comp.0 {
+                    Ok(CompletionKind::Bind(listener)) => Some(*listener),
+                    _ => None,
+                };
+                self.binds.entry(seq).or_default().rdma = Some(listener);
+                self.pair_listeners(rdma_tx, seq);
+                self.done.insert(seq, comp);
+            }
+            Expect::MirrorBind(seq) => {
+                let listener = match comp.0 {
+                    Ok(CompletionKind::Bind(listener)) => Some(listener),
+                    other => {
+                        log::warn!("Failed to listen over TCP: {:?}", other);
+                        None
+                    }
+                };
+                self.binds.entry(seq).or_default().tcp = Some(listener);
+                self.pair_listeners(rdma_tx, seq);
+            }
+            Expect::Mirror => {
+                if let Err(e) = comp.0 {
+                    log::warn!("Adapter {} failed a mirrored command: {}", adapter, e);
+                }
+            }
+        }
+    }
+
+    fn pair_listeners(&mut self, rdma_tx: &CmdTx, seq: u64) {
+        let Some(BindPair {
+            rdma: Some(rdma),
+            tcp: Some(tcp),
+        }) = self.binds.get(&seq)
+        else {
+            return;
+        };
+        match (*rdma, *tcp) {
+            (Some(rdma), Some(tcp)) => {
+                self.listeners.insert(rdma, tcp);
+            }
+            // the application does not know the listener over TCP alone
+            (None, Some(tcp)) => {
+                let _ = self.send(rdma_tx, TCP, Command::Unbind(tcp), Expect::Mirror);
+            }
+            _ => {}
+        }
+        self.binds.remove(&seq);
+    }
+
+    fn open(&mut self, conn_id: Handle, adapter: usize) {
+        if adapter == RDMA {
+            self.stats.rdma += 1;
+        } else {
+            self.stats.tcp += 1;
+        }
+        self.conns.insert(conn_id, adapter);
+    }
+
+    /// Forgets a connection that is closed.
+    pub(crate) fn close(&mut self, conn_id: Handle) {
+        self.conns.remove(&conn_id);
+    }
+
+    /// Returns the number of connections made over each transport.
+    pub(crate) fn stats(&self) -> TransportStats {
+        self.stats
+    }
+
+    /// Returns the connections open over each transport.
+    pub(crate) fn conns(&self) -> impl Iterator<Item = (Handle, TransportType)> + '_ {
+        self.conns.iter().map(|(&conn_id, &adapter)| {
+            let transport = if adapter == RDMA {
+                TransportType::Rdma
+            } else {
+                TransportType::Tcp
+            };
+            (conn_id, transport)
+        })
+    }
+}
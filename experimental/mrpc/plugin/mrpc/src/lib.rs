@@ -8,10 +8,13 @@ pub use phoenix_common::{InitFnResult, PhoenixModule};
 
 pub mod builder;
 pub mod config;
+pub(crate) mod dispatch;
 pub(crate) mod engine;
+pub(crate) mod fallback;
 // pub mod message;
 // pub mod meta_pool;
 pub mod module;
+pub mod resolver;
 pub mod state;
 pub mod unpack;
 
@@ -22,6 +25,8 @@ pub(crate) enum Error {
     TransportType,
     #[error("Resource error: {0}")]
     Resource(#[from] ResourceError),
+    #[error("{0}")]
+    Resolve(#[from] resolver::Error),
 
     // Below are errors that does not return to the user.
     #[error("ipc-channel TryRecvError")]
@@ -70,6 +75,8 @@ impl<T> From<SendError<T>> for DatapathError {
 use crate::config::MrpcConfig;
 use crate::module::MrpcModule;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let config = MrpcConfig::new(config_string)?;
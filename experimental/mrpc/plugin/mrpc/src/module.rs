@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Result};
@@ -20,10 +20,15 @@ use phoenix_common::module::{
     Version,
 };
 use phoenix_common::state_mgr::{Pid, SharedStateManager};
-use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
+use phoenix_common::storage::{
+    get_default_prefix, get_user_dir, ResourceCollection, SharedStorage,
+};
 use phoenix_common::PhoenixResult;
 
+use crate::builder::DispatchCache;
 use crate::config::MrpcConfig;
+use crate::fallback::{self, Fallback};
+use crate::resolver::Resolver;
 
 use super::engine::MrpcEngine;
 use super::state::{Shared, State};
@@ -38,8 +43,10 @@ pub(crate) struct MrpcEngineBuilder {
     cmd_tx: tokio::sync::mpsc::UnboundedSender<cmd::Command>,
     cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Completion>,
     node: DataPathNode,
-    serializer_build_cache: PathBuf,
+    dispatch_cache: DispatchCache,
+    resolver: Resolver,
     shared: Arc<Shared>,
+    fallback: Option<Fallback>,
 }
 
 impl MrpcEngineBuilder {
@@ -51,7 +58,8 @@ impl MrpcEngineBuilder {
         cmd_tx: tokio::sync::mpsc::UnboundedSender<cmd::Command>,
         cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Completion>,
         node: DataPathNode,
-        serializer_build_cache: PathBuf,
+        dispatch_cache: DispatchCache,
+        resolver: Resolver,
         shared: Arc<Shared>,
     ) -> Self {
         MrpcEngineBuilder {
@@ -61,11 +69,19 @@ impl MrpcEngineBuilder {
             node,
             _client_pid: client_pid,
             mode,
-            serializer_build_cache,
+            dispatch_cache,
+            resolver,
             shared,
+            fallback: None,
         }
     }
 
+    /// Connects over TCP when RDMA fails, see [`Fallback`].
+    fn with_fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
     fn build(self) -> Result<MrpcEngine> {
         const META_BUFFER_POOL_CAP: usize = 128;
         const BUF_LEN: usize = 32;
@@ -80,10 +96,17 @@ impl MrpcEngineBuilder {
             node: self.node,
             meta_buf_pool: MetaBufferPool::new(META_BUFFER_POOL_CAP),
             _mode: self.mode,
-            dispatch_build_cache: self.serializer_build_cache,
+            dispatch_cache: self.dispatch_cache,
+            proto_namespace: Default::default(),
+            dispatch_versions: Default::default(),
+            resolver: self.resolver,
             transport_type: None,
+            fallback: self.fallback,
             indicator: Default::default(),
+            profiler: Default::default(),
             wr_read_buffer: Vec::with_capacity(BUF_LEN),
+            next_deadline: None,
+            next_bulk: None,
         })
     }
 }
@@ -127,6 +150,39 @@ impl MrpcModule {
         0,
         0,
     )];
+
+    pub const AUTO_DEPENDENCIES: &'static [EnginePair] = &[
+        (MrpcModule::MRPC_ENGINE, EngineType("RpcAdapterEngine")),
+        (MrpcModule::MRPC_ENGINE, EngineType("TcpRpcAdapterEngine")),
+    ];
+    pub const AUTO_TX_CHANNELS: &'static [ChannelDescriptor] = &[
+        ChannelDescriptor(
+            MrpcModule::MRPC_ENGINE,
+            EngineType("RpcAdapterEngine"),
+            fallback::RDMA,
+            0,
+        ),
+        ChannelDescriptor(
+            MrpcModule::MRPC_ENGINE,
+            EngineType("TcpRpcAdapterEngine"),
+            fallback::TCP,
+            0,
+        ),
+    ];
+    pub const AUTO_RX_CHANNELS: &'static [ChannelDescriptor] = &[
+        ChannelDescriptor(
+            EngineType("RpcAdapterEngine"),
+            MrpcModule::MRPC_ENGINE,
+            0,
+            fallback::RDMA,
+        ),
+        ChannelDescriptor(
+            EngineType("TcpRpcAdapterEngine"),
+            MrpcModule::MRPC_ENGINE,
+            0,
+            fallback::TCP,
+        ),
+    ];
 }
 
 impl MrpcModule {
@@ -137,14 +193,27 @@ impl MrpcModule {
         }
     }
 
-    // Returns build_cache if it's already an absolute path. Otherwise returns the path relative
-    // to the engine's prefix.
-    fn get_build_cache_directory(&self, engine_prefix: &PathBuf) -> PathBuf {
-        let build_cache = self.config.build_cache.clone();
-        if build_cache.is_absolute() {
-            build_cache
+    // Returns path if it's already an absolute path. Otherwise returns the path relative to the
+    // engine's prefix.
+    fn resolve_directory(path: &Path, engine_prefix: &Path) -> PathBuf {
+        if path.is_absolute() {
+            path.to_path_buf()
         } else {
-            engine_prefix.join(build_cache)
+            engine_prefix.join(path)
+        }
+    }
+
+    fn get_dispatch_cache(&self, engine_prefix: &Path) -> DispatchCache {
+        DispatchCache {
+            build_cache: Self::resolve_directory(&self.config.build_cache, engine_prefix),
+            prebuilt: self
+                .config
+                .prebuilt_dispatch
+                .iter()
+                .map(|dir| Self::resolve_directory(dir, engine_prefix))
+                .collect(),
+            build_on_miss: self.config.build_dispatch,
+            gc: self.config.build_cache_gc.clone(),
         }
     }
 }
@@ -160,6 +229,19 @@ impl PhoenixModule for MrpcModule {
                 rx_channels: MrpcModule::TCP_RX_CHANNELS,
                 scheduling_groups: vec![group],
             }
+        } else if self.config.transport == TransportType::Auto {
+            let group = vec![
+                Self::MRPC_ENGINE,
+                EngineType("RpcAdapterEngine"),
+                EngineType("TcpRpcAdapterEngine"),
+            ];
+            ServiceInfo {
+                service: MrpcModule::SERVICE,
+                engine: MrpcModule::MRPC_ENGINE,
+                tx_channels: MrpcModule::AUTO_TX_CHANNELS,
+                rx_channels: MrpcModule::AUTO_RX_CHANNELS,
+                scheduling_groups: vec![group],
+            }
         } else {
             let group = vec![Self::MRPC_ENGINE, EngineType("RpcAdapterEngine")];
             ServiceInfo {
@@ -180,6 +262,8 @@ impl PhoenixModule for MrpcModule {
     fn dependencies(&self) -> &[EnginePair] {
         if self.config.transport == TransportType::Tcp {
             MrpcModule::TCP_DEPENDENCIES
+        } else if self.config.transport == TransportType::Auto {
+            MrpcModule::AUTO_DEPENDENCIES
         } else {
             MrpcModule::DEPENDENCIES
         }
@@ -222,6 +306,7 @@ impl PhoenixModule for MrpcModule {
             mode,
             cred,
             config_string,
+            protocol,
         } = request
         {
             // generate a path and bind a unix domain socket to it
@@ -231,13 +316,15 @@ impl PhoenixModule for MrpcModule {
             // use the phoenix_prefix if not otherwise specified
             let phoenix_prefix = get_default_prefix(global)?;
             let engine_prefix = self.config.prefix.as_ref().unwrap_or(phoenix_prefix);
-            let engine_path = engine_prefix.join(instance_name);
+            // the user has a directory of its own if the daemon isolates the users
+            let socket_dir = get_user_dir(global).unwrap_or(engine_prefix);
+            let engine_path = socket_dir.join(instance_name);
 
-            // get the directory of build cache
-            let build_cache = self.get_build_cache_directory(engine_prefix);
+            // get the directories of build cache and prebuilt dispatch libraries
+            let dispatch_cache = self.get_dispatch_cache(engine_prefix);
 
             // create customer stub
-            let customer = ShmCustomer::accept(sock, client_path, mode, engine_path)?;
+            let customer = ShmCustomer::accept(sock, client_path, mode, engine_path, protocol)?;
 
             let client_pid = Pid::from_raw(cred.pid.unwrap());
             let shared_state = self.state_mgr.get_or_create(client_pid)?;
@@ -250,13 +337,18 @@ impl PhoenixModule for MrpcModule {
                     nic_index: self.config.nic_index,
                     core_id: None,
                     module_config: None,
+                    profile: None,
                 }
             };
             log::debug!("mRPC service setting: {:?}", setting);
 
-            let engine_type = match setting.transport {
-                TransportType::Tcp => EngineType("TcpRpcAdapterEngine"),
-                TransportType::Rdma => EngineType("RpcAdapterEngine"),
+            // the engine is connected to both adapters if the module falls back to TCP, the
+            // transport of the setting is then preferred for each connection
+            let engine_type = match (self.config.transport, setting.transport) {
+                (TransportType::Auto, _) | (_, TransportType::Rdma | TransportType::Auto) => {
+                    EngineType("RpcAdapterEngine")
+                }
+                (_, TransportType::Tcp) => EngineType("TcpRpcAdapterEngine"),
             };
 
             // obtain senders/receivers of command queues with RpcAdapterEngine
@@ -266,17 +358,25 @@ impl PhoenixModule for MrpcModule {
             let cmd_tx = shared.command_path.get_sender(&engine_type)?;
             let cmd_rx = shared.command_path.get_receiver(&engine_type)?;
 
-            let builder = MrpcEngineBuilder::new(
+            let mut builder = MrpcEngineBuilder::new(
                 customer,
                 client_pid,
                 mode,
                 cmd_tx,
                 cmd_rx,
                 node,
-                build_cache,
+                dispatch_cache,
+                Resolver::new(self.config.resolver.clone()),
                 shared_state,
                 // TODO(cjr): store the setting, not necessary now.
             );
+            if self.config.transport == TransportType::Auto {
+                let engine_type = EngineType("TcpRpcAdapterEngine");
+                let tcp_cmd_tx = shared.command_path.get_sender(&engine_type)?;
+                let tcp_cmd_rx = shared.command_path.get_receiver(&engine_type)?;
+                let fallback = Fallback::new(tcp_cmd_tx, tcp_cmd_rx, setting.transport);
+                builder = builder.with_fallback(fallback);
+            }
             let engine = builder.build()?;
 
             Ok(Some(Box::new(engine)))
@@ -0,0 +1,220 @@
+//! Resolves the host names of `Command::ConnectHost` for the applications.
+//!
+//! The lookup of the system resolver may block for seconds, so it runs on a thread of its own,
+//! and the engine polls for the result in its command path. Names in the static hosts table and
+//! IP literals are resolved right away.
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+use phoenix_common::log;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Failed to resolve {0}: {1}")]
+    Lookup(String, io::Error),
+    #[error("No {1} address is found for {0}")]
+    NoAddress(String, AddressFamily),
+    #[error("Resolving {0} timed out after {1:?}")]
+    Timeout(String, Duration),
+    #[error("Another host name is being resolved")]
+    Busy,
+}
+
+/// The addresses to connect to when a name resolves to both IPv4 and IPv6 ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamily {
+    /// The first address returned by the resolver
+    #[default]
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+    /// IPv4 addresses only
+    Ipv4,
+    /// IPv6 addresses only
+    Ipv6,
+}
+
+impl std::fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressFamily::Any | AddressFamily::PreferIpv4 | AddressFamily::PreferIpv6 => {
+                write!(f, "usable")
+            }
+            AddressFamily::Ipv4 => write!(f, "IPv4"),
+            AddressFamily::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+impl AddressFamily {
+    /// Picks the address to connect to.
+    fn select(self, addrs: &[SocketAddr]) -> Option<SocketAddr> {
+        let find = |v4: bool| addrs.iter().find(|addr| addr.is_ipv4() == v4).copied();
+        match self {
+            AddressFamily::Any => addrs.first().copied(),
+            AddressFamily::PreferIpv4 => find(true).or_else(|| find(false)),
+            AddressFamily::PreferIpv6 => find(false).or_else(|| find(true)),
+            AddressFamily::Ipv4 => find(true),
+            AddressFamily::Ipv6 => find(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResolverConfig {
+    /// Static addresses of host names, looked up before the system resolver
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+    /// Which addresses to connect to
+    pub family: AddressFamily,
+    /// How long to wait for the system resolver
+    pub timeout_ms: u64,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            hosts: HashMap::new(),
+            family: AddressFamily::Any,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Lookup {
+    host: String,
+    deadline: Instant,
+    result: oneshot::Receiver<io::Result<Vec<SocketAddr>>>,
+}
+
+/// The resolver of an mRPC engine. An application thread waits for each command to complete, so
+/// at most one name is being resolved at a time.
+#[derive(Debug, Default)]
+pub(crate) struct Resolver {
+    config: ResolverConfig,
+    pending: Option<Lookup>,
+}
+
+impl Resolver {
+    pub(crate) fn new(config: ResolverConfig) -> Self {
+        Resolver {
+            config,
+            pending: None,
+        }
+    }
+
+    /// Starts resolving `host`. Returns the address to connect to if it is known without a
+    /// lookup, otherwise the result is returned by [`Resolver::poll`] later.
+    pub(crate) fn resolve(&mut self, host: &str, port: u16) -> Option<Result<SocketAddr, Error>> {
+        if self.pending.is_some() {
+            return Some(Err(Error::Busy));
+        }
+        // IPv6 literals may come in brackets, as in URLs
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            return Some(Ok(SocketAddr::new(ip, port)));
+        }
+        if let Some(ips) = self.config.hosts.get(host) {
+            let addrs: Vec<_> = ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect();
+            return Some(self.select(host, addrs));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let name = (host.to_owned(), port);
+        let spawned = std::thread::Builder::new()
+            .name("mrpc-resolver".to_owned())
+            .spawn(move || {
+                let result = name.to_socket_addrs().map(|addrs| addrs.collect());
+                // the engine may have given up
+                let _ = tx.send(result);
+            });
+        if let Err(e) = spawned {
+            return Some(Err(Error::Lookup(host.to_owned(), e)));
+        }
+        self.pending = Some(Lookup {
+            host: host.to_owned(),
+            deadline: Instant::now() + Duration::from_millis(self.config.timeout_ms),
+            result: rx,
+        });
+        None
+    }
+
+    /// Returns the result of the pending lookup if it has finished or timed out.
+    pub(crate) fn poll(&mut self) -> Option<Result<SocketAddr, Error>> {
+        let lookup = self.pending.as_mut()?;
+        let result = match lookup.result.try_recv() {
+            Ok(Ok(addrs)) => Ok(addrs),
+            Ok(Err(e)) => Err(Error::Lookup(lookup.host.clone(), e)),
+            Err(oneshot::error::TryRecvError::Empty) => {
+                if Instant::now() < lookup.deadline {
+                    return None;
+                }
+                // the thread is left behind, its result is dropped
+                let timeout = Duration::from_millis(self.config.timeout_ms);
+                Err(Error::Timeout(lookup.host.clone(), timeout))
+            }
+            Err(oneshot::error::TryRecvError::Closed) => Err(Error::Lookup(
+                lookup.host.clone(),
+                io::Error::new(io::ErrorKind::Other, "the resolver thread panicked"),
+            )),
+        };
+        let lookup = self.pending.take().unwrap();
+        Some(result.and_then(|addrs| self.select(&lookup.host, addrs)))
+    }
+
+    fn select(&self, host: &str, addrs: Vec<SocketAddr>) -> Result<SocketAddr, Error> {
+        let addr = self
+            .config
+            .family
+            .select(&addrs)
+            .ok_or_else(|| Error::NoAddress(host.to_owned(), self.config.family))?;
+        log::debug!("Resolved {} to {}", host, addr);
+        Ok(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![
+            "10.0.0.1:5000".parse().unwrap(),
+            "[fd00::1]:5000".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn select_family() {
+        let addrs = addrs();
+        assert_eq!(AddressFamily::Any.select(&addrs), Some(addrs[0]));
+        assert_eq!(AddressFamily::PreferIpv6.select(&addrs), Some(addrs[1]));
+        assert_eq!(AddressFamily::Ipv4.select(&addrs[1..]), None);
+        assert_eq!(
+            AddressFamily::PreferIpv4.select(&addrs[1..]),
+            Some(addrs[1])
+        );
+    }
+
+    #[test]
+    fn resolve_without_lookup() {
+        let mut config = ResolverConfig::default();
+        config
+            .hosts
+            .insert("server".to_owned(), vec!["fd00::1".parse().unwrap()]);
+        let mut resolver = Resolver::new(config);
+        let addr = resolver.resolve("[fd00::2]", 5000).unwrap().unwrap();
+        assert_eq!(addr, "[fd00::2]:5000".parse().unwrap());
+        let addr = resolver.resolve("server", 5000).unwrap().unwrap();
+        assert_eq!(addr, "[fd00::1]:5000".parse().unwrap());
+        assert!(resolver.poll().is_none());
+    }
+}
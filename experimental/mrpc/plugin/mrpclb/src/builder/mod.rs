@@ -88,6 +88,9 @@ impl<'de> Deserialize<'de> for MethodIdentifier {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RpcMethodInfo {
+    // fully qualified path of the service, e.g., `rpc_hello.Greeter`
+    #[serde(default)]
+    pub service: String,
     pub service_id: u32,
     pub func_id: u32,
     // fully qualified path for the method's input rust type
@@ -13,4 +13,8 @@ pub enum Error {
     IO(#[from] std::io::Error),
     #[error("Serde JSON Error: {0}")]
     SerdeJSON(#[from] serde_json::Error),
+    #[error("Service {1} and {2} have the same service ID {0}, rename one of them")]
+    ServiceIdCollision(u32, String, String),
+    #[error("Methods {1} and {2} have the same func ID {0}, override either with `mrpc.func_id`")]
+    FuncIdCollision(u32, String, String),
 }
@@ -196,9 +196,15 @@ impl Builder {
         }
 
         let method_info = Rc::new(RefCell::new(HashMap::new()));
+        let collisions = Rc::new(RefCell::new(Vec::new()));
+        let func_collisions = Rc::new(RefCell::new(Vec::new()));
         let recorder = ServiceRecorder {
             mapping: method_info.clone(),
+            collisions: collisions.clone(),
             compile_well_known_types: self.compile_well_known_types,
+            service_paths: HashMap::new(),
+            func_collisions: func_collisions.clone(),
+            func_paths: HashMap::new(),
         };
         config.service_generator(Box::new(recorder));
 
@@ -207,6 +213,12 @@ impl Builder {
         std::mem::drop(config);
 
         let method_info = Rc::try_unwrap(method_info).unwrap().into_inner();
+        if let Some((service_id, service, other)) = collisions.take().into_iter().next() {
+            return Err(Error::ServiceIdCollision(service_id, service, other));
+        }
+        if let Some((func_id, method, other)) = func_collisions.take().into_iter().next() {
+            return Err(Error::FuncIdCollision(func_id, method, other));
+        }
 
         if let Some(out_method_info_path) = self.method_info_out_path {
             let json = serde_json::to_string_pretty(&method_info)?;
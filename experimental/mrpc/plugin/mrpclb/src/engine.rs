@@ -5,7 +5,6 @@ use std::pin::Pin;
 use anyhow::{anyhow, Result};
 use futures::future::BoxFuture;
 use itertools::Itertools;
-use std::num::NonZeroU32;
 
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::rpc::{MessageErased, RpcId, StatusCode};
@@ -49,6 +48,12 @@ pub struct MrpcLBEngine {
 
     pub(crate) indicator: Indicator,
     pub(crate) wr_read_buffer: Vec<dp::WorkRequest>,
+    /// The deadline of the message that follows, see `WorkRequest::Deadline`. It is not kept
+    /// across upgrades, the message is sent without a deadline then.
+    pub(crate) next_deadline: Option<(RpcId, u64)>,
+    /// The message that follows is sent in bulk, see `WorkRequest::Bulk`. It is not kept across
+    /// upgrades either.
+    pub(crate) next_bulk: Option<RpcId>,
 }
 
 impl_vertex_for_engine!(MrpcLBEngine, node);
@@ -172,6 +177,8 @@ impl MrpcLBEngine {
             transport_type,
             indicator: Default::default(),
             wr_read_buffer,
+            next_deadline: None,
+            next_bulk: None,
         };
         Ok(engine)
     }
@@ -198,6 +205,13 @@ impl Engine for MrpcLBEngine {
     fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
         &mut self.get_mut().indicator
     }
+
+    fn on_fault(&mut self, reason: &str) {
+        let err = phoenix_api::Error::Generic(format!("mRPC service failed: {}", reason));
+        if let Err(e) = self.customer.send_comp(cmd::Completion(Err(err))) {
+            log::warn!("failed to notify the client of the failure: {}", e);
+        }
+    }
 }
 
 impl MrpcLBEngine {
@@ -268,6 +282,8 @@ impl MrpcLBEngine {
                     }
                     EngineRxMessage::RpcMessage(_) => {}
                     EngineRxMessage::RecvError(..) => {}
+                    EngineRxMessage::ConnectionState(..) => {}
+                    EngineRxMessage::CallTiming(..) => {}
                 },
                 Err(TryRecvError::Disconnected) => return Ok(()),
                 Err(TryRecvError::Empty) => {}
@@ -316,10 +332,19 @@ impl MrpcLBEngine {
                     Ok(Some(CompletionKind::SetTransport))
                 }
             }
-            Command::Connect(addr) => {
-                self.cmd_tx.send(Command::Connect(*addr)).unwrap();
+            Command::Connect(addr, options) => {
+                self.cmd_tx.send(Command::Connect(*addr, *options)).unwrap();
                 Ok(None)
             }
+            Command::ConnectDatagram(addr) => {
+                self.cmd_tx.send(Command::ConnectDatagram(*addr)).unwrap();
+                Ok(None)
+            }
+            Command::ConnectHost(..) => {
+                // the client waits for the fds of the connection before the completion
+                self.customer.send_fd(&[])?;
+                Err(Error::Unsupported("ConnectHost"))
+            }
             Command::MultiConnect(handles) => {
                 let copy_handle = handles.clone();
                 self.cmd_tx
@@ -327,8 +352,12 @@ impl MrpcLBEngine {
                     .unwrap();
                 Ok(None)
             }
-            Command::Bind(addr) => {
-                self.cmd_tx.send(Command::Bind(*addr)).unwrap();
+            Command::Bind(addr, options) => {
+                self.cmd_tx.send(Command::Bind(*addr, *options)).unwrap();
+                Ok(None)
+            }
+            Command::Unbind(listener) => {
+                self.cmd_tx.send(Command::Unbind(*listener)).unwrap();
                 Ok(None)
             }
             Command::NewMappedAddrs(conn_handle, app_vaddrs) => {
@@ -337,17 +366,39 @@ impl MrpcLBEngine {
                     .unwrap();
                 Ok(None)
             }
+            Command::QueryCredits(handles) => {
+                self.cmd_tx
+                    .send(Command::QueryCredits(handles.clone()))
+                    .unwrap();
+                Ok(None)
+            }
+            Command::SetCallTiming(handles, enable) => {
+                self.cmd_tx
+                    .send(Command::SetCallTiming(handles.clone(), *enable))
+                    .unwrap();
+                Ok(None)
+            }
+            Command::SetEagerCopyThreshold(handles, threshold) => {
+                self.cmd_tx
+                    .send(Command::SetEagerCopyThreshold(handles.clone(), *threshold))
+                    .unwrap();
+                Ok(None)
+            }
             Command::UpdateProtos(protos) => {
                 let dylib_path =
                     build_serializer_lib(protos.clone(), self.dispatch_build_cache.clone())?;
+                // the versions are not numbered, the previous library is unloaded right away
                 self.cmd_tx
-                    .send(Command::UpdateProtosInner(dylib_path))
+                    .send(Command::UpdateProtosInner(dylib_path, 0))
                     .unwrap();
                 Ok(None)
             }
-            Command::UpdateProtosInner(_) => {
+            Command::UpdateProtosInner(..) => {
                 panic!("UpdateProtosInner is only used in backend")
             }
+            Command::RetireProtosInner(_) => {
+                panic!("RetireProtosInner is only used in backend")
+            }
         }
     }
 
@@ -444,9 +495,17 @@ impl MrpcLBEngine {
                     std::ptr::write(meta_buf_ptr.as_meta_ptr(), erased.meta);
                 }
 
+                let deadline = match self.next_deadline.take() {
+                    Some((id, deadline)) if id == rpc_id => deadline,
+                    _ => 0,
+                };
+                let bulk = self.next_bulk.take() == Some(rpc_id);
                 let msg = RpcMessageTx {
                     meta_buf_ptr,
                     addr_backend: erased.shm_addr_backend,
+                    deadline,
+                    bulk,
+                    dispatch_version: 0,
                 };
 
                 // timer.tick();
@@ -468,6 +527,12 @@ impl MrpcLBEngine {
                 // timer.tick();
                 // log::info!("process_dp reclaim recv buf: {}", timer);
             }
+            WorkRequest::Deadline(rpc_id, deadline) => {
+                self.next_deadline = Some((*rpc_id, *deadline));
+            }
+            WorkRequest::Bulk(rpc_id) => {
+                self.next_bulk = Some(*rpc_id);
+            }
         }
         Ok(())
     }
@@ -493,13 +558,19 @@ impl MrpcLBEngine {
                         };
                         // timer.tick();
                         match meta.status_code {
-                            StatusCode::AccessDenied => {
-                                tracing::debug!("Status code: Access denied, meta={:?}", meta);
+                            StatusCode::AccessDenied
+                            | StatusCode::Unimplemented
+                            | StatusCode::DataLoss
+                            | StatusCode::Failed => {
+                                tracing::debug!(
+                                    "Status code: {:?}, meta={:?}",
+                                    meta.status_code,
+                                    meta
+                                );
                                 let mut sent = false;
                                 let rpc_id = RpcId(meta.conn_id, meta.call_id);
-                                let status = phoenix_api::rpc::TransportStatus::Error(unsafe {
-                                    NonZeroU32::new_unchecked(402)
-                                });
+                                // only `Unknown` has no transport status
+                                let status = meta.status_code.transport_status().unwrap();
                                 while !sent {
                                     self.customer.enqueue_wc_with(|ptr, _count| unsafe {
                                         // self.customer.notify_wc_with(|ptr, _count| unsafe {
@@ -563,6 +634,28 @@ impl MrpcLBEngine {
                             })?;
                         }
                     }
+                    EngineRxMessage::ConnectionState(conn_id, state) => {
+                        let mut sent = false;
+                        while !sent {
+                            self.customer.enqueue_wc_with(|ptr, _count| unsafe {
+                                sent = true;
+                                ptr.cast::<dp::Completion>()
+                                    .write(dp::Completion::ConnectionState(conn_id, state));
+                                1
+                            })?;
+                        }
+                    }
+                    EngineRxMessage::CallTiming(rpc_id, timing) => {
+                        let mut sent = false;
+                        while !sent {
+                            self.customer.enqueue_wc_with(|ptr, _count| unsafe {
+                                sent = true;
+                                ptr.cast::<dp::Completion>()
+                                    .write(dp::Completion::CallTiming(rpc_id, timing));
+                                1
+                            })?;
+                        }
+                    }
                 }
                 Ok(Progress(1))
             }
@@ -600,8 +693,12 @@ impl MrpcLBEngine {
                     // server bind response
                     c @ Ok(
                         CompletionKind::Bind(..)
+                        | CompletionKind::Unbind
                         | CompletionKind::NewMappedAddrs
-                        | CompletionKind::UpdateProtos,
+                        | CompletionKind::UpdateProtos
+                        | CompletionKind::QueryCredits(..)
+                        | CompletionKind::SetCallTiming
+                        | CompletionKind::SetEagerCopyThreshold,
                     ) => {
                         self.customer.send_comp(cmd::Completion(c))?;
                         Ok(Status::Progress(1))
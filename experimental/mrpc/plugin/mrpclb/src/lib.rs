@@ -22,6 +22,8 @@ pub(crate) enum Error {
     TransportType,
     #[error("Resource error: {0}")]
     Resource(#[from] ResourceError),
+    #[error("{0} is not supported by mRPC-LB")]
+    Unsupported(&'static str),
 
     // Below are errors that does not return to the user.
     #[error("ipc-channel TryRecvError")]
@@ -70,6 +72,8 @@ impl<T> From<SendError<T>> for DatapathError {
 use crate::config::MrpcLBConfig;
 use crate::module::MrpcLBModule;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let config = MrpcLBConfig::new(config_string)?;
@@ -20,7 +20,9 @@ use phoenix_common::module::{
     Version,
 };
 use phoenix_common::state_mgr::{Pid, SharedStateManager};
-use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
+use phoenix_common::storage::{
+    get_default_prefix, get_user_dir, ResourceCollection, SharedStorage,
+};
 use phoenix_common::PhoenixResult;
 
 use crate::config::MrpcLBConfig;
@@ -84,6 +86,8 @@ impl MrpcLBEngineBuilder {
             transport_type: Some(TransportType::Tcp),
             indicator: Default::default(),
             wr_read_buffer: Vec::with_capacity(BUF_LEN),
+            next_deadline: None,
+            next_bulk: None,
         })
     }
 }
@@ -258,6 +262,7 @@ impl PhoenixModule for MrpcLBModule {
             mode,
             cred,
             config_string,
+            protocol,
         } = request
         {
             // generate a path and bind a unix domain socket to it
@@ -267,13 +272,15 @@ impl PhoenixModule for MrpcLBModule {
             // use the phoenix_prefix if not otherwise specified
             let phoenix_prefix = get_default_prefix(global)?;
             let engine_prefix = self.config.prefix.as_ref().unwrap_or(phoenix_prefix);
-            let engine_path = engine_prefix.join(instance_name);
+            // the user has a directory of its own if the daemon isolates the users
+            let socket_dir = get_user_dir(global).unwrap_or(engine_prefix);
+            let engine_path = socket_dir.join(instance_name);
 
             // get the directory of build cache
             let build_cache = self.get_build_cache_directory(engine_prefix);
 
             // create customer stub
-            let customer = ShmCustomer::accept(sock, client_path, mode, engine_path)?;
+            let customer = ShmCustomer::accept(sock, client_path, mode, engine_path, protocol)?;
 
             let client_pid = Pid::from_raw(cred.pid.unwrap());
             let shared_state = self.state_mgr.get_or_create(client_pid)?;
@@ -286,6 +293,7 @@ impl PhoenixModule for MrpcLBModule {
                     nic_index: self.config.nic_index,
                     core_id: None,
                     module_config: None,
+                    profile: None,
                 }
             };
             log::debug!("mRPCLB service setting: {:?}", setting);
@@ -214,6 +214,9 @@ impl HelloAclReceiverEngine {
                             let rpc_msg = RpcMessageTx {
                                 meta_buf_ptr: meta_ptr,
                                 addr_backend: 0,
+                                deadline: 0,
+                                bulk: false,
+                                dispatch_version: 0,
                             };
                             let new_msg = EngineTxMessage::RpcMessage(rpc_msg);
                             self.tx_outputs()[0]
@@ -229,7 +232,9 @@ impl HelloAclReceiverEngine {
                             self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
                         }
                     }
-                    EngineRxMessage::RecvError(_, _) => {
+                    EngineRxMessage::RecvError(_, _)
+                    | EngineRxMessage::ConnectionState(_, _)
+                    | EngineRxMessage::CallTiming(_, _) => {
                         self.rx_outputs()[0].send(m)?;
                     }
                 }
@@ -23,12 +23,16 @@ impl<T> From<SendError<T>> for DatapathError {
     }
 }
 
+use phoenix_common::config::PluginConfig;
+
 use crate::config::HelloAclReceiverConfig;
 use crate::module::HelloAclReceiverAddon;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
-    let config = HelloAclReceiverConfig::new(config_string)?;
+    let config = HelloAclReceiverConfig::parse(config_string)?;
     let addon = HelloAclReceiverAddon::new(config);
     Ok(Box::new(addon))
 }
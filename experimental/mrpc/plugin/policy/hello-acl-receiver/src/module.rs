@@ -2,6 +2,7 @@ use anyhow::{bail, Result};
 use nix::unistd::Pid;
 
 use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::config::PluginConfig;
 use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EngineType};
@@ -71,7 +72,7 @@ impl PhoenixAddon for HelloAclReceiverAddon {
     }
 
     fn update_config(&mut self, config: &str) -> Result<()> {
-        self.config = toml::from_str(config)?;
+        self.config = HelloAclReceiverConfig::parse(Some(config))?;
         Ok(())
     }
 
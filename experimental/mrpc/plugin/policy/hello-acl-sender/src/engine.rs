@@ -205,6 +205,9 @@ impl HelloAclSenderEngine {
                             let new_msg = RpcMessageTx {
                                 meta_buf_ptr: msg.meta_buf_ptr,
                                 addr_backend: raw_ptr.addr(),
+                                deadline: msg.deadline,
+                                bulk: msg.bulk,
+                                dispatch_version: msg.dispatch_version,
                             };
                             self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(new_msg))?;
                         }
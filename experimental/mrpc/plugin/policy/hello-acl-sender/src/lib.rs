@@ -23,12 +23,16 @@ impl<T> From<SendError<T>> for DatapathError {
     }
 }
 
+use phoenix_common::config::PluginConfig;
+
 use crate::config::HelloAclSenderConfig;
 use crate::module::HelloAclSenderAddon;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
-    let config = HelloAclSenderConfig::new(config_string)?;
+    let config = HelloAclSenderConfig::parse(config_string)?;
     let addon = HelloAclSenderAddon::new(config);
     Ok(Box::new(addon))
 }
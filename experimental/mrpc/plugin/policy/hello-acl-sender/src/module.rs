@@ -3,6 +3,7 @@ use fnv::FnvHashMap as HashMap;
 use nix::unistd::Pid;
 
 use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::config::PluginConfig;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EngineType};
 use phoenix_common::storage::ResourceCollection;
@@ -65,7 +66,7 @@ impl PhoenixAddon for HelloAclSenderAddon {
     }
 
     fn update_config(&mut self, config: &str) -> Result<()> {
-        self.config = toml::from_str(config)?;
+        self.config = HelloAclSenderConfig::parse(Some(config))?;
         Ok(())
     }
 
@@ -23,12 +23,16 @@ impl<T> From<SendError<T>> for DatapathError {
     }
 }
 
+use phoenix_common::config::PluginConfig;
+
 use crate::config::HotelAclConfig;
 use crate::module::HotelAclAddon;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
-    let config = HotelAclConfig::new(config_string)?;
+    let config = HotelAclConfig::parse(config_string)?;
     let addon = HotelAclAddon::new(config);
     Ok(Box::new(addon))
 }
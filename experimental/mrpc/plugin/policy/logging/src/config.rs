@@ -1,17 +1,14 @@
 use chrono::{Datelike, Timelike, Utc};
+use phoenix_common::config::PluginConfig;
 use phoenix_common::log;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
+#[serde(default)]
 pub struct LoggingConfig {}
 
-impl LoggingConfig {
-    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
-        Ok(config)
-    }
-}
+impl PluginConfig for LoggingConfig {}
 
 pub fn create_log_file() -> std::fs::File {
     std::fs::create_dir_all("/tmp/phoenix/log").expect("mkdir failed");
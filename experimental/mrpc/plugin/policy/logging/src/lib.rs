@@ -21,12 +21,16 @@ impl<T> From<SendError<T>> for DatapathError {
     }
 }
 
+use phoenix_common::config::PluginConfig;
+
 use crate::config::LoggingConfig;
 use crate::module::LoggingAddon;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
-    let config = LoggingConfig::new(config_string)?;
+    let config = LoggingConfig::parse(config_string)?;
     let addon = LoggingAddon::new(config);
     Ok(Box::new(addon))
 }
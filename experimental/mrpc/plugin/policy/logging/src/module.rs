@@ -2,6 +2,7 @@ use anyhow::{bail, Result};
 use nix::unistd::Pid;
 
 use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::config::PluginConfig;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EngineType};
 use phoenix_common::storage::ResourceCollection;
@@ -66,7 +67,7 @@ impl PhoenixAddon for LoggingAddon {
     }
 
     fn update_config(&mut self, config: &str) -> Result<()> {
-        self.config = toml::from_str(config)?;
+        self.config = LoggingConfig::parse(Some(config))?;
         Ok(())
     }
 
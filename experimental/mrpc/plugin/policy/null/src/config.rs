@@ -1,12 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use phoenix_common::config::PluginConfig;
+
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
+#[serde(default)]
 pub struct NullConfig {}
 
-impl NullConfig {
-    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
-        Ok(config)
-    }
-}
+impl PluginConfig for NullConfig {}
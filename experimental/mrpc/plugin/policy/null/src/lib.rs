@@ -21,12 +21,16 @@ impl<T> From<SendError<T>> for DatapathError {
     }
 }
 
+use phoenix_common::config::PluginConfig;
+
 use crate::config::NullConfig;
 use crate::module::NullAddon;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
-    let config = NullConfig::new(config_string)?;
+    let config = NullConfig::parse(config_string)?;
     let addon = NullAddon::new(config);
     Ok(Box::new(addon))
 }
@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use phoenix_common::config::PluginConfig;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
+#[serde(default)]
 pub struct QosConfig {
     // (non-strict) latency budget
     pub latency_budget_microsecs: u64,
@@ -15,9 +18,4 @@ impl Default for QosConfig {
     }
 }
 
-impl QosConfig {
-    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
-        Ok(config)
-    }
-}
+impl PluginConfig for QosConfig {}
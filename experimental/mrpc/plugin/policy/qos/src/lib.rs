@@ -23,12 +23,16 @@ impl<T> From<SendError<T>> for DatapathError {
     }
 }
 
+use phoenix_common::config::PluginConfig;
+
 use crate::config::QosConfig;
 use crate::module::QosAddon;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
-    let config = QosConfig::new(config_string)?;
+    let config = QosConfig::parse(config_string)?;
     let addon = QosAddon::new(config);
     Ok(Box::new(addon))
 }
@@ -1,7 +1,9 @@
 use anyhow::{bail, Result};
 use nix::unistd::Pid;
 
+use phoenix_api_policy_qos::control_plane;
 use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::config::PluginConfig;
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EngineType};
 use phoenix_common::storage::ResourceCollection;
@@ -69,10 +71,15 @@ impl PhoenixAddon for QosAddon {
     }
 
     fn update_config(&mut self, config: &str) -> Result<()> {
-        self.config = toml::from_str(config)?;
+        self.config = QosConfig::parse(Some(config))?;
         Ok(())
     }
 
+    fn config_request(&self) -> Option<Vec<u8>> {
+        let request = control_plane::Request::NewConfig(self.config.latency_budget_microsecs);
+        bincode::serialize(&request).ok()
+    }
+
     fn create_engine(
         &mut self,
         ty: EngineType,
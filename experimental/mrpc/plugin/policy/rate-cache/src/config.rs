@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+use phoenix_common::config::PluginConfig;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct RateCacheConfig {
+    /// How long a cached reply can be used to answer the same request, in milliseconds.
+    pub ttl_ms: u64,
+    /// The maximum number of cached replies.
+    pub capacity: usize,
+}
+
+impl Default for RateCacheConfig {
+    fn default() -> Self {
+        RateCacheConfig {
+            ttl_ms: 1000,
+            capacity: 4096,
+        }
+    }
+}
+
+impl PluginConfig for RateCacheConfig {}
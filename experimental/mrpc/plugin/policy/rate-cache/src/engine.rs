@@ -0,0 +1,348 @@
+//! This engine can only be placed at the receiver side of the Rate service for now.
+//!
+//! It answers the repeated `GetRates` requests from the replies it has seen, so the cache lookup
+//! and the memcached round trip in the Rate service are bypassed for the hot keys. A reply is
+//! cached for `ttl_ms` after the service sends it. The application never sees the requests
+//! answered from the cache.
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+use std::ptr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap as HashMap;
+use futures::future::BoxFuture;
+use minstant::Instant;
+
+use phoenix_api::rpc::{MessageMeta, RpcId, RpcMsgType, StatusCode};
+use phoenix_api_mrpc::dp::RECV_RECLAIM_BS;
+use phoenix_api_policy_rate_cache::control_plane;
+
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::log;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::config::RateCacheConfig;
+
+pub mod rate {
+    // The string specified here must match the proto package name
+    include!("rate.rs");
+}
+
+/// FUNC_ID of `/rate.Rate/GetRates`.
+const GET_RATES_FUNC_ID: u32 = 754185907;
+
+/// The number of replies that can be sent from the cache at the same time.
+pub(crate) const META_BUFFER_POOL_CAP: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    service_id: u32,
+    func_id: u32,
+    // the length-prefixed fields of the request
+    payload: Vec<u8>,
+}
+
+pub(crate) struct CacheEntry {
+    reply: Box<rate::Result>,
+    expires: Instant,
+}
+
+pub(crate) struct RateCacheEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+
+    pub(crate) config: RateCacheConfig,
+    pub(crate) cache: HashMap<CacheKey, CacheEntry>,
+    // Requests passed to the application that have not been replied.
+    pub(crate) pending: HashMap<RpcId, CacheKey>,
+    // Replies sent from the cache, released after the RPC adapter passes us an Ack.
+    pub(crate) replying: HashMap<RpcId, Box<rate::Result>>,
+    // The meta buffers of the replies sent from the cache.
+    pub(crate) meta_buf_pool: MetaBufferPool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for RateCacheEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        format!("RateCacheEngine, {} replies cached", self.cache.len())
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::NewConfig(ttl_ms, capacity) => {
+                self.config = RateCacheConfig { ttl_ms, capacity };
+            }
+            control_plane::Request::Clear => {
+                self.cache.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_vertex_for_engine!(RateCacheEngine, node);
+
+impl Decompose for RateCacheEngine {
+    fn flush(&mut self) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        let mut collections = ResourceCollection::with_capacity(5);
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("cache".to_string(), Box::new(engine.cache));
+        collections.insert("pending".to_string(), Box::new(engine.pending));
+        collections.insert("replying".to_string(), Box::new(engine.replying));
+        collections.insert("meta_buf_pool".to_string(), Box::new(engine.meta_buf_pool));
+        (collections, engine.node)
+    }
+}
+
+impl RateCacheEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<RateCacheConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let cache = *local
+            .remove("cache")
+            .unwrap()
+            .downcast::<HashMap<CacheKey, CacheEntry>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let pending = *local
+            .remove("pending")
+            .unwrap()
+            .downcast::<HashMap<RpcId, CacheKey>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let replying = *local
+            .remove("replying")
+            .unwrap()
+            .downcast::<HashMap<RpcId, Box<rate::Result>>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let meta_buf_pool = *local
+            .remove("meta_buf_pool")
+            .unwrap()
+            .downcast::<MetaBufferPool>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = RateCacheEngine {
+            node,
+            indicator: Default::default(),
+            config,
+            cache,
+            pending,
+            replying,
+            meta_buf_pool,
+        };
+        Ok(engine)
+    }
+}
+
+impl RateCacheEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+/// Returns the key of a `GetRates` request.
+#[inline]
+fn cache_key(meta: &MessageMeta, req: &rate::Request) -> CacheKey {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&req.hotel_ids.len().to_le_bytes());
+    for field in req.hotel_ids.iter().chain([&req.in_date, &req.out_date]) {
+        payload.extend_from_slice(&field.len().to_le_bytes());
+        payload.extend_from_slice(field.as_bytes());
+    }
+    CacheKey {
+        service_id: meta.service_id,
+        func_id: meta.func_id,
+        payload,
+    }
+}
+
+impl RateCacheEngine {
+    /// Returns whether the request is answered from the cache.
+    fn try_reply_from_cache(
+        &mut self,
+        meta: &MessageMeta,
+        key: &CacheKey,
+    ) -> Result<bool, DatapathError> {
+        let reply = match self.cache.get(key) {
+            Some(entry) if entry.expires > Instant::now() => Box::new((*entry.reply).clone()),
+            Some(_) => {
+                self.cache.remove(key);
+                return Ok(false);
+            }
+            None => return Ok(false),
+        };
+
+        let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+        let meta_buf_ptr = match self.meta_buf_pool.obtain(rpc_id) {
+            Some(meta_buf_ptr) => meta_buf_ptr,
+            None => return Ok(false),
+        };
+        let reply_meta = MessageMeta {
+            msg_type: RpcMsgType::Response,
+            status_code: StatusCode::Success,
+            ..*meta
+        };
+        unsafe {
+            ptr::write(meta_buf_ptr.as_meta_ptr(), reply_meta);
+        }
+
+        let raw_ptr: *const rate::Result = &*reply;
+        self.replying.insert(rpc_id, reply);
+        let msg = RpcMessageTx {
+            meta_buf_ptr,
+            addr_backend: raw_ptr.addr(),
+            deadline: 0,
+            bulk: false,
+            dispatch_version: 0,
+        };
+        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        // The application never sees the request, release its receive buffers here.
+        self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
+            meta.conn_id,
+            [meta.call_id; RECV_RECLAIM_BS],
+        ))?;
+        Ok(true)
+    }
+
+    /// Caches the reply to a request passed to the application.
+    fn cache_reply(&mut self, msg: &RpcMessageTx, key: CacheKey) {
+        let now = Instant::now();
+        if self.cache.len() >= self.config.capacity {
+            self.cache.retain(|_, entry| entry.expires > now);
+            if self.cache.len() >= self.config.capacity {
+                log::trace!("rate cache is full, reply not cached");
+                return;
+            }
+        }
+        // Copy the reply to a private heap
+        let reply = unsafe { &*(msg.addr_backend as *const rate::Result) };
+        let entry = CacheEntry {
+            reply: Box::new(reply.clone()),
+            expires: now + Duration::from_millis(self.config.ttl_ms),
+        };
+        self.cache.insert(key, entry);
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        match self.tx_inputs()[0].try_recv() {
+            Ok(msg) => {
+                if let EngineTxMessage::RpcMessage(ref msg) = msg {
+                    let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+                    let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                    if meta.msg_type == RpcMsgType::Response {
+                        if let Some(key) = self.pending.remove(&rpc_id) {
+                            if meta.status_code == StatusCode::Success {
+                                self.cache_reply(msg, key);
+                            }
+                        }
+                    }
+                }
+                self.tx_outputs()[0].send(msg)?;
+                return Ok(Progress(1));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+        }
+
+        match self.rx_inputs()[0].try_recv() {
+            Ok(m) => {
+                match m {
+                    EngineRxMessage::RpcMessage(msg) => {
+                        let meta = unsafe { *msg.meta.as_ref() };
+                        if meta.msg_type == RpcMsgType::Request
+                            && meta.func_id == GET_RATES_FUNC_ID
+                            && self.config.ttl_ms > 0
+                        {
+                            let req = unsafe { &*(msg.addr_backend as *const rate::Request) };
+                            let key = cache_key(&meta, req);
+                            if self.try_reply_from_cache(&meta, &key)? {
+                                return Ok(Progress(1));
+                            }
+                            self.pending
+                                .insert(RpcId::new(meta.conn_id, meta.call_id), key);
+                        }
+                        self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                    }
+                    EngineRxMessage::Ack(rpc_id, status) => {
+                        if self.replying.remove(&rpc_id).is_some() {
+                            // a reply sent from the cache
+                            self.meta_buf_pool.release(rpc_id).unwrap_or_else(|e| {
+                                log::warn!("failed to release meta buffer of {:?}: {}", rpc_id, e)
+                            });
+                        } else {
+                            self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                        }
+                    }
+                    EngineRxMessage::RecvError(conn_id, status) => {
+                        self.pending.retain(|rpc_id, _| rpc_id.0 != conn_id);
+                        self.rx_outputs()[0].send(EngineRxMessage::RecvError(conn_id, status))?;
+                    }
+                    m => self.rx_outputs()[0].send(m)?,
+                }
+                return Ok(Progress(1));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+        }
+
+        Ok(Progress(0))
+    }
+}
@@ -0,0 +1,38 @@
+#![feature(peer_credentials_unix_socket)]
+#![feature(ptr_internals)]
+#![feature(strict_provenance)]
+
+use thiserror::Error;
+
+pub use phoenix_common::{InitFnResult, PhoenixAddon};
+
+pub mod config;
+pub(crate) mod engine;
+pub mod module;
+
+#[derive(Error, Debug)]
+pub(crate) enum DatapathError {
+    #[error("Internal queue send error")]
+    InternalQueueSend,
+}
+
+use phoenix_common::engine::datapath::SendError;
+impl<T> From<SendError<T>> for DatapathError {
+    fn from(_other: SendError<T>) -> Self {
+        DatapathError::InternalQueueSend
+    }
+}
+
+use phoenix_common::config::PluginConfig;
+
+use crate::config::RateCacheConfig;
+use crate::module::RateCacheAddon;
+
+phoenix_common::export_abi!();
+
+#[no_mangle]
+pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
+    let config = RateCacheConfig::parse(config_string)?;
+    let addon = RateCacheAddon::new(config);
+    Ok(Box::new(addon))
+}
@@ -0,0 +1,113 @@
+use anyhow::{bail, Result};
+use fnv::FnvHashMap as HashMap;
+use nix::unistd::Pid;
+
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+
+use phoenix_api_policy_rate_cache::control_plane;
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::config::PluginConfig;
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::{RateCacheEngine, META_BUFFER_POOL_CAP};
+use crate::config::RateCacheConfig;
+
+pub(crate) struct RateCacheEngineBuilder {
+    node: DataPathNode,
+    config: RateCacheConfig,
+}
+
+impl RateCacheEngineBuilder {
+    fn new(node: DataPathNode, config: RateCacheConfig) -> Self {
+        RateCacheEngineBuilder { node, config }
+    }
+
+    fn build(self) -> Result<RateCacheEngine> {
+        Ok(RateCacheEngine {
+            node: self.node,
+            indicator: Default::default(),
+            config: self.config,
+            cache: HashMap::default(),
+            pending: HashMap::default(),
+            replying: HashMap::default(),
+            meta_buf_pool: MetaBufferPool::new(META_BUFFER_POOL_CAP),
+        })
+    }
+}
+
+pub struct RateCacheAddon {
+    config: RateCacheConfig,
+}
+
+impl RateCacheAddon {
+    pub const RATE_CACHE_ENGINE: EngineType = EngineType("RateCacheEngine");
+    pub const ENGINES: &'static [EngineType] = &[RateCacheAddon::RATE_CACHE_ENGINE];
+}
+
+impl RateCacheAddon {
+    pub fn new(config: RateCacheConfig) -> Self {
+        RateCacheAddon { config }
+    }
+}
+
+impl PhoenixAddon for RateCacheAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        RateCacheAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = RateCacheConfig::parse(Some(config))?;
+        Ok(())
+    }
+
+    fn config_request(&self) -> Option<Vec<u8>> {
+        let request = control_plane::Request::NewConfig(self.config.ttl_ms, self.config.capacity);
+        bincode::serialize(&request).ok()
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        _pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != RateCacheAddon::RATE_CACHE_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = RateCacheEngineBuilder::new(node, self.config);
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != RateCacheAddon::RATE_CACHE_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = RateCacheEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
@@ -0,0 +1,42 @@
+#[derive(Debug, Clone, ::mrpc_derive::Message)]
+pub struct Request {
+    #[prost(string, repeated, tag = "1")]
+    pub hotel_ids: ::mrpc_marshal::shadow::Vec<::mrpc_marshal::shadow::String>,
+    #[prost(string, tag = "2")]
+    pub in_date: ::mrpc_marshal::shadow::String,
+    #[prost(string, tag = "3")]
+    pub out_date: ::mrpc_marshal::shadow::String,
+}
+#[derive(Debug, Clone, ::mrpc_derive::Message)]
+pub struct Result {
+    #[prost(message, repeated, tag = "1")]
+    pub rate_plans: ::mrpc_marshal::shadow::Vec<RatePlan>,
+}
+#[derive(Debug, Clone, ::mrpc_derive::Message)]
+pub struct RatePlan {
+    #[prost(string, tag = "1")]
+    pub hotel_id: ::mrpc_marshal::shadow::String,
+    #[prost(string, tag = "2")]
+    pub code: ::mrpc_marshal::shadow::String,
+    #[prost(string, tag = "3")]
+    pub in_date: ::mrpc_marshal::shadow::String,
+    #[prost(string, tag = "4")]
+    pub out_date: ::mrpc_marshal::shadow::String,
+    #[prost(message, optional, tag = "5")]
+    pub room_type: ::core::option::Option<RoomType>,
+}
+#[derive(Debug, Clone, ::mrpc_derive::Message)]
+pub struct RoomType {
+    #[prost(double, tag = "1")]
+    pub bookable_rate: f64,
+    #[prost(double, tag = "2")]
+    pub total_rate: f64,
+    #[prost(double, tag = "3")]
+    pub total_rate_inclusive: f64,
+    #[prost(string, tag = "4")]
+    pub code: ::mrpc_marshal::shadow::String,
+    #[prost(string, tag = "5")]
+    pub currency: ::mrpc_marshal::shadow::String,
+    #[prost(string, tag = "6")]
+    pub room_description: ::mrpc_marshal::shadow::String,
+}
@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use phoenix_common::config::PluginConfig;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
+#[serde(default)]
 pub struct RateLimitConfig {
     pub requests_per_sec: u64,
     pub bucket_size: u64,
@@ -16,9 +19,4 @@ impl Default for RateLimitConfig {
     }
 }
 
-impl RateLimitConfig {
-    pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
-        Ok(config)
-    }
-}
+impl PluginConfig for RateLimitConfig {}
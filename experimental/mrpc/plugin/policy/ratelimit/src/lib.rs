@@ -21,12 +21,16 @@ impl<T> From<SendError<T>> for DatapathError {
     }
 }
 
+use phoenix_common::config::PluginConfig;
+
 use crate::config::RateLimitConfig;
 use crate::module::RateLimitAddon;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_addon(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixAddon>> {
-    let config = RateLimitConfig::new(config_string)?;
+    let config = RateLimitConfig::parse(config_string)?;
     let addon = RateLimitAddon::new(config);
     Ok(Box::new(addon))
 }
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+use phoenix_common::config::{check_range, ConfigError, PluginConfig};
+
+pub use phoenix_api_policy_traffic_split::control_plane::SplitMode;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct TrafficSplitConfig {
+    /// The full name of the service whose requests are split, e.g., `rpc_hello.Greeter`. The
+    /// requests of all services are split if empty.
+    pub service: String,
+    pub mode: SplitMode,
+    /// The percentage of the requests to send to the destination, from 0 to 100.
+    pub percentage: f64,
+    /// The handle of the destination connection, as listed by `phoenixctl listconn`. Nothing
+    /// is split if not set.
+    pub destination: Option<u64>,
+}
+
+impl Default for TrafficSplitConfig {
+    fn default() -> Self {
+        TrafficSplitConfig {
+            service: String::new(),
+            mode: SplitMode::Canary,
+            percentage: 0.0,
+            destination: None,
+        }
+    }
+}
+
+impl PluginConfig for TrafficSplitConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        check_range("percentage", self.percentage, 0.0..=100.0)
+    }
+}
+
+impl TrafficSplitConfig {
+    /// Returns the `SERVICE_ID` of the split service, or `None` if all services are split.
+    pub fn service_id(&self) -> Option<u32> {
+        (!self.service.is_empty()).then(|| crc32fast::hash(self.service.as_bytes()))
+    }
+}
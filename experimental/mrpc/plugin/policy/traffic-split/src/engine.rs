@@ -0,0 +1,432 @@
+//! This engine can only be placed at the sender side for now.
+//!
+//! It selects `percentage` of the requests of a service and either sends them to the destination
+//! connection instead of their own (canary), or sends a copy of them to the destination as well
+//! (shadow). The destination must be a connection of the same client process, e.g., opened by
+//! `ClientStub::multi_connect`.
+//!
+//! A redirected request gets a new call ID on the destination connection. Its reply, its ack,
+//! and the reclaim of its receive buffers are translated back and forth, so the application
+//! sees the reply on the connection it sent the request to. A shadow copy carries its own
+//! metadata and call ID. Its reply is discarded, and the ack of the original request is held back
+//! until the copy is sent, so the application does not free the request under the copy.
+//!
+//! Requests still redirected when the engine is detached will not find their callers.
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{CallId, RpcId, RpcMsgType, TransportStatus};
+use phoenix_api::Handle;
+use phoenix_api_mrpc::dp::RECV_RECLAIM_BS;
+use phoenix_api_policy_traffic_split::control_plane;
+
+use phoenix_common::config::PluginConfig;
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
+use phoenix_common::engine::datapath::meta_pool::{MetaBuffer, MetaBufferPool};
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::log;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::config::{SplitMode, TrafficSplitConfig};
+
+/// The number of shadow copies that can be in flight at the same time.
+pub(crate) const META_BUFFER_POOL_CAP: usize = 128;
+
+/// The call IDs given by this engine have the highest bit set, so they never collide with the
+/// ones given by the application.
+const CALL_ID_BASE: u64 = 1 << 63;
+
+pub(crate) struct TrafficSplitEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+
+    pub(crate) config: TrafficSplitConfig,
+    // The `SERVICE_ID` of the split service, or None to split all services.
+    pub(crate) service_id: Option<u32>,
+    // Accumulates `percentage` for each request, a request is split when it reaches 100.
+    pub(crate) credit: f64,
+    pub(crate) next_call_id: u64,
+    // Redirected requests, from their RpcId on the destination to the original RpcId.
+    pub(crate) redirected: HashMap<RpcId, RpcId>,
+    // Replies of redirected requests passed to the application, from the original RpcId to the
+    // RpcId on the destination, to translate the reclaim of their receive buffers.
+    pub(crate) unreclaimed: HashMap<RpcId, RpcId>,
+    // Shadow copies that are not sent yet, to the original RpcId.
+    pub(crate) shadows: HashMap<RpcId, RpcId>,
+    // Shadow copies that are sent, whose replies are to be discarded.
+    pub(crate) shadow_replies: HashSet<RpcId>,
+    // Original requests whose shadow copies are not sent yet, with their acks if they come
+    // first.
+    pub(crate) held_acks: HashMap<RpcId, Option<TransportStatus>>,
+    // The meta buffers of the shadow copies.
+    pub(crate) meta_buf_pool: MetaBufferPool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for TrafficSplitEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        format!(
+            "TrafficSplitEngine, {:?} {}% to {:?}",
+            self.config.mode, self.config.percentage, self.config.destination
+        )
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::NewConfig(service, mode, percentage, destination) => {
+                let config = TrafficSplitConfig {
+                    service,
+                    mode,
+                    percentage,
+                    destination,
+                };
+                config.validate()?;
+                self.service_id = config.service_id();
+                self.config = config;
+                self.credit = 0.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_vertex_for_engine!(TrafficSplitEngine, node);
+
+impl Decompose for TrafficSplitEngine {
+    fn flush(&mut self) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        let mut collections = ResourceCollection::with_capacity(9);
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("credit".to_string(), Box::new(engine.credit));
+        collections.insert("next_call_id".to_string(), Box::new(engine.next_call_id));
+        collections.insert("redirected".to_string(), Box::new(engine.redirected));
+        collections.insert("unreclaimed".to_string(), Box::new(engine.unreclaimed));
+        collections.insert("shadows".to_string(), Box::new(engine.shadows));
+        collections.insert(
+            "shadow_replies".to_string(),
+            Box::new(engine.shadow_replies),
+        );
+        collections.insert("held_acks".to_string(), Box::new(engine.held_acks));
+        collections.insert("meta_buf_pool".to_string(), Box::new(engine.meta_buf_pool));
+        (collections, engine.node)
+    }
+}
+
+impl TrafficSplitEngine {
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<TrafficSplitConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let credit = *local
+            .remove("credit")
+            .unwrap()
+            .downcast::<f64>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let next_call_id = *local
+            .remove("next_call_id")
+            .unwrap()
+            .downcast::<u64>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let redirected = *local
+            .remove("redirected")
+            .unwrap()
+            .downcast::<HashMap<RpcId, RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let unreclaimed = *local
+            .remove("unreclaimed")
+            .unwrap()
+            .downcast::<HashMap<RpcId, RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let shadows = *local
+            .remove("shadows")
+            .unwrap()
+            .downcast::<HashMap<RpcId, RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let shadow_replies = *local
+            .remove("shadow_replies")
+            .unwrap()
+            .downcast::<HashSet<RpcId>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let held_acks = *local
+            .remove("held_acks")
+            .unwrap()
+            .downcast::<HashMap<RpcId, Option<TransportStatus>>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let meta_buf_pool = *local
+            .remove("meta_buf_pool")
+            .unwrap()
+            .downcast::<MetaBufferPool>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let engine = TrafficSplitEngine {
+            node,
+            indicator: Default::default(),
+            service_id: config.service_id(),
+            config,
+            credit,
+            next_call_id,
+            redirected,
+            unreclaimed,
+            shadows,
+            shadow_replies,
+            held_acks,
+            meta_buf_pool,
+        };
+        Ok(engine)
+    }
+}
+
+impl TrafficSplitEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+impl TrafficSplitEngine {
+    /// Returns the destination if the request should be split.
+    fn select(&mut self, msg: &RpcMessageTx) -> Option<Handle> {
+        let destination = Handle(self.config.destination?);
+        let meta = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+        if meta.msg_type != RpcMsgType::Request
+            || meta.conn_id == destination
+            || self.service_id.map_or(false, |id| id != meta.service_id)
+        {
+            return None;
+        }
+        self.credit += self.config.percentage;
+        if self.credit < 100.0 {
+            return None;
+        }
+        self.credit -= 100.0;
+        Some(destination)
+    }
+
+    #[inline]
+    fn new_call_id(&mut self) -> CallId {
+        let call_id = CallId(CALL_ID_BASE | self.next_call_id);
+        self.next_call_id = (self.next_call_id + 1) % CALL_ID_BASE;
+        call_id
+    }
+
+    /// Sends the request to `destination` instead.
+    fn redirect(&mut self, msg: RpcMessageTx, destination: Handle) -> Result<(), DatapathError> {
+        let call_id = self.new_call_id();
+        let meta = unsafe { &mut *msg.meta_buf_ptr.as_meta_ptr() };
+        let original = RpcId::new(meta.conn_id, meta.call_id);
+        meta.conn_id = destination;
+        meta.call_id = call_id;
+        self.redirected
+            .insert(RpcId::new(destination, call_id), original);
+        self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+        Ok(())
+    }
+
+    /// Sends the request, and a copy of it to `destination`.
+    fn shadow(&mut self, msg: RpcMessageTx, destination: Handle) -> Result<(), DatapathError> {
+        let call_id = self.new_call_id();
+        let shadow_id = RpcId::new(destination, call_id);
+        match self.meta_buf_pool.obtain(shadow_id) {
+            Some(meta_buf_ptr) => {
+                let original = unsafe {
+                    let src: *const MetaBuffer = msg.meta_buf_ptr.0.as_ptr();
+                    let dst: *mut MetaBuffer = meta_buf_ptr.0.as_ptr();
+                    ptr::copy_nonoverlapping(src.cast::<u8>(), dst.cast::<u8>(), (*src).len());
+                    let meta = &mut (*dst).meta;
+                    let original = RpcId::new(meta.conn_id, meta.call_id);
+                    meta.conn_id = destination;
+                    meta.call_id = call_id;
+                    original
+                };
+                self.shadows.insert(shadow_id, original);
+                self.held_acks.insert(original, None);
+                let copy = RpcMessageTx {
+                    meta_buf_ptr,
+                    addr_backend: msg.addr_backend,
+                    deadline: msg.deadline,
+                    bulk: msg.bulk,
+                    dispatch_version: msg.dispatch_version,
+                };
+                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(copy))?;
+            }
+            None => {
+                log::trace!("too many shadow copies in flight, request not copied");
+                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the ack of a sent message, returns the ack to pass to the application, if any.
+    fn on_ack(
+        &mut self,
+        rpc_id: RpcId,
+        status: TransportStatus,
+    ) -> Option<(RpcId, TransportStatus)> {
+        if let Some(original) = self.shadows.remove(&rpc_id) {
+            self.meta_buf_pool.release(rpc_id).unwrap_or_else(|e| {
+                log::warn!("failed to release meta buffer of {:?}: {}", rpc_id, e)
+            });
+            if status == TransportStatus::Success {
+                self.shadow_replies.insert(rpc_id);
+            }
+            // pass the ack of the original request if it was held back for the copy
+            return match self.held_acks.remove(&original) {
+                Some(Some(original_status)) => Some((original, original_status)),
+                _ => None,
+            };
+        }
+
+        if let Some(original) = self.redirected.get(&rpc_id).copied() {
+            if status != TransportStatus::Success {
+                // no reply will come
+                self.redirected.remove(&rpc_id);
+            }
+            return Some((original, status));
+        }
+
+        match self.held_acks.get_mut(&rpc_id) {
+            Some(held) => {
+                *held = Some(status);
+                None
+            }
+            None => Some((rpc_id, status)),
+        }
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        match self.tx_inputs()[0].try_recv() {
+            Ok(msg) => {
+                match msg {
+                    EngineTxMessage::RpcMessage(msg) => match self.select(&msg) {
+                        Some(destination) => match self.config.mode {
+                            SplitMode::Canary => self.redirect(msg, destination)?,
+                            SplitMode::Shadow => self.shadow(msg, destination)?,
+                        },
+                        None => self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?,
+                    },
+                    EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
+                        // the RPC adapter only reclaims the first call, so does the translation
+                        match self.unreclaimed.remove(&RpcId::new(conn_id, call_ids[0])) {
+                            Some(rpc_id) => {
+                                self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
+                                    rpc_id.0,
+                                    [rpc_id.1; RECV_RECLAIM_BS],
+                                ))?
+                            }
+                            None => self.tx_outputs()[0]
+                                .send(EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids))?,
+                        }
+                    }
+                }
+                return Ok(Progress(1));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+        }
+
+        match self.rx_inputs()[0].try_recv() {
+            Ok(m) => {
+                match m {
+                    EngineRxMessage::RpcMessage(mut msg) => {
+                        let meta = unsafe { msg.meta.as_mut() };
+                        let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                        if self.shadow_replies.remove(&rpc_id) {
+                            // discard the reply of a shadow copy
+                            self.tx_outputs()[0].send(EngineTxMessage::ReclaimRecvBuf(
+                                rpc_id.0,
+                                [rpc_id.1; RECV_RECLAIM_BS],
+                            ))?;
+                            return Ok(Progress(1));
+                        }
+                        if let Some(original) = self.redirected.remove(&rpc_id) {
+                            meta.conn_id = original.0;
+                            meta.call_id = original.1;
+                            self.unreclaimed.insert(original, rpc_id);
+                        }
+                        self.rx_outputs()[0].send(EngineRxMessage::RpcMessage(msg))?;
+                    }
+                    EngineRxMessage::Ack(rpc_id, status) => {
+                        if let Some((rpc_id, status)) = self.on_ack(rpc_id, status) {
+                            self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                        }
+                    }
+                    EngineRxMessage::CallTiming(rpc_id, timing) => {
+                        // the timing comes before the reply, so the call is still tracked
+                        if !self.shadow_replies.contains(&rpc_id) {
+                            let rpc_id = self.redirected.get(&rpc_id).copied().unwrap_or(rpc_id);
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::CallTiming(rpc_id, timing))?;
+                        }
+                    }
+                    m => self.rx_outputs()[0].send(m)?,
+                }
+                return Ok(Progress(1));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+        }
+
+        Ok(Progress(0))
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{bail, Result};
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+use nix::unistd::Pid;
+
+use phoenix_common::engine::datapath::meta_pool::MetaBufferPool;
+
+use phoenix_api_policy_traffic_split::control_plane;
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::config::PluginConfig;
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::{TrafficSplitEngine, META_BUFFER_POOL_CAP};
+use crate::config::TrafficSplitConfig;
+
+pub(crate) struct TrafficSplitEngineBuilder {
+    node: DataPathNode,
+    config: TrafficSplitConfig,
+}
+
+impl TrafficSplitEngineBuilder {
+    fn new(node: DataPathNode, config: TrafficSplitConfig) -> Self {
+        TrafficSplitEngineBuilder { node, config }
+    }
+
+    fn build(self) -> Result<TrafficSplitEngine> {
+        Ok(TrafficSplitEngine {
+            node: self.node,
+            indicator: Default::default(),
+            service_id: self.config.service_id(),
+            config: self.config,
+            credit: 0.0,
+            next_call_id: 0,
+            redirected: HashMap::default(),
+            unreclaimed: HashMap::default(),
+            shadows: HashMap::default(),
+            shadow_replies: HashSet::default(),
+            held_acks: HashMap::default(),
+            meta_buf_pool: MetaBufferPool::new(META_BUFFER_POOL_CAP),
+        })
+    }
+}
+
+pub struct TrafficSplitAddon {
+    config: TrafficSplitConfig,
+}
+
+impl TrafficSplitAddon {
+    pub const TRAFFIC_SPLIT_ENGINE: EngineType = EngineType("TrafficSplitEngine");
+    pub const ENGINES: &'static [EngineType] = &[TrafficSplitAddon::TRAFFIC_SPLIT_ENGINE];
+}
+
+impl TrafficSplitAddon {
+    pub fn new(config: TrafficSplitConfig) -> Self {
+        TrafficSplitAddon { config }
+    }
+}
+
+impl PhoenixAddon for TrafficSplitAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        TrafficSplitAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        self.config = TrafficSplitConfig::parse(Some(config))?;
+        Ok(())
+    }
+
+    fn config_request(&self) -> Option<Vec<u8>> {
+        let config = self.config.clone();
+        let request = control_plane::Request::NewConfig(
+            config.service,
+            config.mode,
+            config.percentage,
+            config.destination,
+        );
+        bincode::serialize(&request).ok()
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        _pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != TrafficSplitAddon::TRAFFIC_SPLIT_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = TrafficSplitEngineBuilder::new(node, self.config.clone());
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != TrafficSplitAddon::TRAFFIC_SPLIT_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = TrafficSplitEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
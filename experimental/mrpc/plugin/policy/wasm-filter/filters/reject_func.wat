@@ -0,0 +1,17 @@
+;; Rejects the requests of one function, and tags the others with a token.
+;;
+;; The func_id of a method is the CRC32 of its full path, e.g., `/rpc_hello.Greeter/SayHello`.
+(module
+  (import "phoenix" "meta_get" (func $meta_get (param i32) (result i64)))
+  (import "phoenix" "meta_set" (func $meta_set (param i32 i64) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "filter") (result i32)
+    ;; only the requests, field 5 is the message type
+    (if (i64.ne (call $meta_get (i32.const 5)) (i64.const 0))
+      (then (return (i32.const 0))))
+    ;; field 2 is the func_id
+    (if (i64.eq (call $meta_get (i32.const 2)) (i64.const 3687134534))
+      (then (return (i32.const 1))))
+    ;; field 4 is the token
+    (drop (call $meta_set (i32.const 4) (i64.const 42)))
+    (i32.const 0)))
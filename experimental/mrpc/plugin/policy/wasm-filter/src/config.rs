@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use phoenix_common::config::{check_range, ConfigError, PluginConfig};
+
+pub use phoenix_api_policy_wasm_filter::control_plane::OnTrap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct WasmFilterConfig {
+    /// The filter, a WebAssembly module in the binary or the text format.
+    pub module: PathBuf,
+    /// The fuel the filter is given for each message, roughly the number of instructions it may
+    /// run. The filter traps when it runs out.
+    pub fuel: u64,
+    /// The most memory the filter may grow to, in 64 KiB pages.
+    pub max_memory_pages: u32,
+    pub on_trap: OnTrap,
+    /// The transport error code of the messages rejected by the filter.
+    pub reject_code: u32,
+    /// The dispatch library of the services whose payloads the filter reads, as built by mRPC in
+    /// its `build_cache`. The filter sees no payload if not set, nor the payloads in device memory.
+    pub marshal_library: Option<PathBuf>,
+}
+
+impl Default for WasmFilterConfig {
+    fn default() -> Self {
+        WasmFilterConfig {
+            module: PathBuf::new(),
+            fuel: 100_000,
+            max_memory_pages: 16,
+            on_trap: OnTrap::Reject,
+            reject_code: 403,
+            marshal_library: None,
+        }
+    }
+}
+
+impl PluginConfig for WasmFilterConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.module.as_os_str().is_empty() {
+            return Err(ConfigError::invalid(
+                "module",
+                "a filter module is required",
+            ));
+        }
+        check_range("fuel", self.fuel, 1..)?;
+        check_range("max_memory_pages", self.max_memory_pages, 1..=65536)?;
+        check_range("reject_code", self.reject_code, 1..)
+    }
+}
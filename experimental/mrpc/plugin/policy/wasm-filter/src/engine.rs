@@ -0,0 +1,259 @@
+//! This engine can only be placed at the sender side for now.
+//!
+//! It runs a user-supplied WebAssembly filter on each message sent (see [`crate::filter`] for what
+//! the filter can do). An accepted message is passed on with the changes the filter made to its
+//! metadata. A rejected message is not sent, and the application gets an ack with `reject_code`
+//! as the error. A filter that traps, e.g., runs out of fuel, decides as `on_trap` says.
+use std::num::NonZeroU32;
+use std::os::unix::ucred::UCred;
+use std::pin::Pin;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+
+use phoenix_api::rpc::{RpcId, TransportStatus};
+use phoenix_api_policy_wasm_filter::control_plane;
+
+use phoenix_common::config::PluginConfig;
+use phoenix_common::engine::datapath::message::{EngineRxMessage, EngineTxMessage};
+use phoenix_common::engine::datapath::node::DataPathNode;
+use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
+use phoenix_common::envelop::ResourceDowncast;
+use phoenix_common::impl_vertex_for_engine;
+use phoenix_common::log;
+use phoenix_common::module::Version;
+use phoenix_common::storage::{ResourceCollection, SharedStorage};
+
+use super::DatapathError;
+use crate::config::{OnTrap, WasmFilterConfig};
+use crate::filter::{Filter, FilterModule, MarshalLibrary, Verdict};
+
+/// The number of messages the filter has decided on.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FilterStats {
+    pub(crate) accepted: u64,
+    pub(crate) rejected: u64,
+    // included in the above, as decided by `on_trap`
+    pub(crate) trapped: u64,
+}
+
+pub(crate) struct WasmFilterEngine {
+    pub(crate) node: DataPathNode,
+
+    pub(crate) indicator: Indicator,
+
+    pub(crate) config: WasmFilterConfig,
+    pub(crate) filter: Filter,
+    pub(crate) marshal_library: Option<MarshalLibrary>,
+    pub(crate) stats: FilterStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Progress(usize),
+    Disconnected,
+}
+
+use Status::Progress;
+
+impl Engine for WasmFilterEngine {
+    fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult> {
+        Box::pin(async move { self.get_mut().mainloop().await })
+    }
+
+    fn description(self: Pin<&Self>) -> String {
+        format!(
+            "WasmFilterEngine, {:?}, accepted {}, rejected {}, trapped {}",
+            self.config.module, self.stats.accepted, self.stats.rejected, self.stats.trapped
+        )
+    }
+
+    #[inline]
+    fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
+        &mut self.get_mut().indicator
+    }
+
+    fn handle_request(&mut self, request: Vec<u8>, _cred: UCred) -> Result<()> {
+        let request: control_plane::Request = bincode::deserialize(&request[..])?;
+
+        match request {
+            control_plane::Request::LoadModule(path) => {
+                let module = FilterModule::load(&path)?;
+                self.filter = module.instantiate(&self.config)?;
+                self.config.module = path;
+            }
+            control_plane::Request::NewConfig(fuel, on_trap) => {
+                let config = WasmFilterConfig {
+                    fuel,
+                    on_trap,
+                    ..self.config.clone()
+                };
+                config.validate()?;
+                self.config = config;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_vertex_for_engine!(WasmFilterEngine, node);
+
+impl Decompose for WasmFilterEngine {
+    fn flush(&mut self) -> Result<usize> {
+        let mut work = 0;
+        while !self.tx_inputs()[0].is_empty() || !self.rx_inputs()[0].is_empty() {
+            if let Progress(n) = self.check_input_queue()? {
+                work += n;
+            }
+        }
+        Ok(work)
+    }
+
+    fn decompose(
+        self: Box<Self>,
+        _shared: &mut SharedStorage,
+        _global: &mut ResourceCollection,
+    ) -> (ResourceCollection, DataPathNode) {
+        let engine = *self;
+
+        // The instance of the filter is not carried over, a new one is started on restore.
+        let mut collections = ResourceCollection::with_capacity(2);
+        collections.insert("config".to_string(), Box::new(engine.config));
+        collections.insert("stats".to_string(), Box::new(engine.stats));
+        (collections, engine.node)
+    }
+}
+
+impl WasmFilterEngine {
+    pub(crate) fn new(
+        node: DataPathNode,
+        config: WasmFilterConfig,
+        module: &FilterModule,
+    ) -> Result<Self> {
+        let filter = module.instantiate(&config)?;
+        let marshal_library = config
+            .marshal_library
+            .as_deref()
+            .map(MarshalLibrary::new)
+            .transpose()?;
+        Ok(WasmFilterEngine {
+            node,
+            indicator: Default::default(),
+            config,
+            filter,
+            marshal_library,
+            stats: Default::default(),
+        })
+    }
+
+    pub(crate) fn restore(
+        mut local: ResourceCollection,
+        node: DataPathNode,
+        _prev_version: Version,
+    ) -> Result<Self> {
+        let config = *local
+            .remove("config")
+            .unwrap()
+            .downcast::<WasmFilterConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let stats = *local
+            .remove("stats")
+            .unwrap()
+            .downcast::<FilterStats>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+
+        let module = FilterModule::load(&config.module)?;
+        let mut engine = WasmFilterEngine::new(node, config, &module)?;
+        engine.stats = stats;
+        Ok(engine)
+    }
+}
+
+impl WasmFilterEngine {
+    async fn mainloop(&mut self) -> EngineResult {
+        loop {
+            let mut work = 0;
+            // check input queue, ~100ns
+            loop {
+                match self.check_input_queue()? {
+                    Progress(0) => break,
+                    Progress(n) => work += n,
+                    Status::Disconnected => return Ok(()),
+                }
+            }
+
+            self.indicator.set_nwork(work);
+
+            future::yield_now().await;
+        }
+    }
+}
+
+impl WasmFilterEngine {
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        match self.tx_inputs()[0].try_recv() {
+            Ok(msg) => {
+                match msg {
+                    EngineTxMessage::RpcMessage(msg) => {
+                        let meta = unsafe { &mut *msg.meta_buf_ptr.as_meta_ptr() };
+                        let rpc_id = RpcId::new(meta.conn_id, meta.call_id);
+                        let payload = self
+                            .marshal_library
+                            .as_ref()
+                            .and_then(|library| library.payload(meta, msg.addr_backend));
+                        let verdict = match self.filter.run(meta, payload, self.config.fuel) {
+                            Ok(verdict) => verdict,
+                            Err(e) => {
+                                log::debug!("filter trapped on {:?}: {}", rpc_id, e);
+                                self.stats.trapped += 1;
+                                match self.config.on_trap {
+                                    OnTrap::Accept => Verdict::Accept(*meta),
+                                    OnTrap::Reject => Verdict::Reject,
+                                }
+                            }
+                        };
+                        match verdict {
+                            Verdict::Accept(new_meta) => {
+                                self.stats.accepted += 1;
+                                *meta = new_meta;
+                                self.tx_outputs()[0].send(EngineTxMessage::RpcMessage(msg))?;
+                            }
+                            Verdict::Reject => {
+                                self.stats.rejected += 1;
+                                // checked by `validate`
+                                let code = NonZeroU32::new(self.config.reject_code).unwrap();
+                                let error =
+                                    EngineRxMessage::Ack(rpc_id, TransportStatus::Error(code));
+                                self.rx_outputs()[0].send(error).unwrap_or_else(|e| {
+                                    log::warn!(
+                                        "error when bubbling up the error, send failed e: {}",
+                                        e
+                                    )
+                                });
+                            }
+                        }
+                    }
+                    // XXX TODO(cjr): it is best not to reorder the message
+                    m => self.tx_outputs()[0].send(m)?,
+                }
+                return Ok(Progress(1));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+        }
+
+        // forward all rx msgs
+        match self.rx_inputs()[0].try_recv() {
+            Ok(m) => {
+                self.rx_outputs()[0].send(m)?;
+                return Ok(Progress(1));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(Status::Disconnected),
+        }
+
+        Ok(Progress(0))
+    }
+}
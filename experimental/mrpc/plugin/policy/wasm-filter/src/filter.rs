@@ -0,0 +1,290 @@
+//! The host of the WebAssembly filters.
+//!
+//! A filter is a module that exports its `memory` and a function `filter: () -> i32`, which is
+//! called once for each message, and returns [`ACCEPT`] or [`REJECT`]. It may import the
+//! following functions from the module `phoenix`, which only see the message being filtered:
+//!
+//! - `meta_get(field: i32) -> i64` returns a field of the metadata (see [`field`]), or -1 for an
+//!   unknown field.
+//! - `meta_set(field: i32, value: i64) -> i32` changes a field of the metadata, and returns 0, or
+//!   -1 if the field cannot be changed. Only the token and the status code can be.
+//! - `payload_len() -> i64` returns the size of the payload, or -1 if it cannot be read.
+//! - `payload_read(offset: i64, ptr: i32, len: i32) -> i32` copies at most `len` bytes of the
+//!   payload from `offset` to `ptr` in the memory of the filter, and returns the number of bytes
+//!   copied, or -1 if they are out of the memory or the payload cannot be read.
+//!
+//! The payload is the message as marshaled by the dispatch library, the concatenation of its
+//! segments, so it can only be read if the library is configured. An instance of a filter lives as
+//! long as its engine: its globals and memory persist across the messages, but not across the
+//! upgrades of the engine.
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::slice;
+
+use anyhow::{anyhow, bail, Result};
+use wasmtime::{Caller, Extern, Linker, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use mrpc_marshal::{MarshalError, SgE, SgList};
+use phoenix_api::rpc::{MessageMeta, RpcMsgType, StatusCode};
+
+use crate::config::WasmFilterConfig;
+
+/// The message is passed on, with the changes to its metadata.
+pub(crate) const ACCEPT: i32 = 0;
+/// The message is not sent, and the application gets an error.
+pub(crate) const REJECT: i32 = 1;
+
+/// The fields of the metadata, as passed to `meta_get` and `meta_set`.
+pub(crate) mod field {
+    pub(crate) const CONN_ID: i32 = 0;
+    pub(crate) const SERVICE_ID: i32 = 1;
+    pub(crate) const FUNC_ID: i32 = 2;
+    pub(crate) const CALL_ID: i32 = 3;
+    pub(crate) const TOKEN: i32 = 4;
+    /// 0 for a request, 1 for a response, 2 for a post.
+    pub(crate) const MSG_TYPE: i32 = 5;
+    /// 0 for success, 1 for access denied, 2 for unknown.
+    pub(crate) const STATUS_CODE: i32 = 6;
+    pub(crate) const CORRELATION_ID: i32 = 7;
+    /// The advisory flags, e.g., 1 if the server asks the client to slow down.
+    pub(crate) const FLAGS: i32 = 8;
+}
+
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+type MarshalFn = fn(&MessageMeta, usize) -> Result<SgList, MarshalError>;
+
+/// The dispatch library, to find the payloads of the messages.
+pub(crate) struct MarshalLibrary {
+    _library: libloading::Library,
+    // NOTE: Symbol here shall not outlive library.
+    marshal_fn: libloading::os::unix::Symbol<MarshalFn>,
+}
+
+impl MarshalLibrary {
+    pub(crate) fn new(path: &Path) -> Result<Self, libloading::Error> {
+        let library = unsafe { libloading::Library::new(path) }?;
+        let marshal_fn = unsafe {
+            let symbol: libloading::Symbol<MarshalFn> = library.get(b"marshal")?;
+            symbol.into_raw()
+        };
+        Ok(MarshalLibrary {
+            _library: library,
+            marshal_fn,
+        })
+    }
+
+    /// Returns the segments of the payload, or `None` if the library fails or panics on it.
+    pub(crate) fn payload(&self, meta: &MessageMeta, addr_backend: usize) -> Option<SgList> {
+        panic::catch_unwind(AssertUnwindSafe(|| (self.marshal_fn)(meta, addr_backend)))
+            .ok()?
+            .ok()
+    }
+}
+
+/// A compiled filter, shared by the engines.
+#[derive(Clone)]
+pub(crate) struct FilterModule {
+    engine: wasmtime::Engine,
+    module: wasmtime::Module,
+}
+
+impl FilterModule {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = wasmtime::Engine::new(&config)?;
+        let module = wasmtime::Module::from_file(&engine, path)
+            .map_err(|e| anyhow!("failed to load filter {:?}: {}", path, e))?;
+        Ok(FilterModule { engine, module })
+    }
+
+    /// Starts an instance of the filter.
+    pub(crate) fn instantiate(&self, config: &WasmFilterConfig) -> Result<Filter> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(config.max_memory_pages as usize * WASM_PAGE_SIZE)
+            .build();
+        let state = HostState {
+            limits,
+            meta: None,
+            payload: None,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+
+        let mut linker = Linker::new(&self.engine);
+        link_host(&mut linker)?;
+        let instance = linker.instantiate(&mut store, &self.module)?;
+        if instance.get_memory(&mut store, "memory").is_none() {
+            bail!("the filter does not export its memory");
+        }
+        let filter_fn = instance.get_typed_func::<(), i32, _>(&mut store, "filter")?;
+        Ok(Filter { store, filter_fn })
+    }
+}
+
+struct HostState {
+    limits: StoreLimits,
+    // the metadata of the message being filtered
+    meta: Option<MessageMeta>,
+    // the segments of its payload, if they can be read
+    payload: Option<SgList>,
+}
+
+/// What the filter decides for a message.
+pub(crate) enum Verdict {
+    Accept(MessageMeta),
+    Reject,
+}
+
+/// An instance of a filter.
+pub(crate) struct Filter {
+    store: Store<HostState>,
+    filter_fn: TypedFunc<(), i32>,
+}
+
+impl Filter {
+    /// Runs the filter on a message with `fuel`. Returns an error if the filter traps.
+    pub(crate) fn run(
+        &mut self,
+        meta: &MessageMeta,
+        payload: Option<SgList>,
+        fuel: u64,
+    ) -> Result<Verdict> {
+        let remaining = self.store.consume_fuel(0)?;
+        if remaining < fuel {
+            self.store.add_fuel(fuel - remaining)?;
+        }
+
+        let state = self.store.data_mut();
+        state.meta = Some(*meta);
+        state.payload = payload;
+        let verdict = self.filter_fn.call(&mut self.store, ());
+        let state = self.store.data_mut();
+        // the segments are only valid while the message is filtered
+        state.payload = None;
+        let meta = state.meta.take().unwrap();
+        match verdict? {
+            ACCEPT => Ok(Verdict::Accept(meta)),
+            REJECT => Ok(Verdict::Reject),
+            v => bail!("unknown verdict {}", v),
+        }
+    }
+}
+
+fn link_host(linker: &mut Linker<HostState>) -> Result<()> {
+    linker.func_wrap(
+        "phoenix",
+        "meta_get",
+        |caller: Caller<'_, HostState>, field: i32| -> i64 {
+            caller
+                .data()
+                .meta
+                .as_ref()
+                .and_then(|meta| meta_get(meta, field))
+                .unwrap_or(-1)
+        },
+    )?;
+    linker.func_wrap(
+        "phoenix",
+        "meta_set",
+        |mut caller: Caller<'_, HostState>, field: i32, value: i64| -> i32 {
+            match caller.data_mut().meta.as_mut() {
+                Some(meta) if meta_set(meta, field, value) => 0,
+                _ => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "phoenix",
+        "payload_len",
+        |caller: Caller<'_, HostState>| -> i64 {
+            match caller.data().payload.as_ref() {
+                Some(sglist) => sglist.0.iter().map(|sge| sge.len as i64).sum(),
+                None => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "phoenix",
+        "payload_read",
+        |mut caller: Caller<'_, HostState>, offset: i64, ptr: i32, len: i32| -> i32 {
+            let memory = match caller.get_export("memory") {
+                Some(Extern::Memory(memory)) => memory,
+                _ => return -1,
+            };
+            if offset < 0 || ptr < 0 || len < 0 {
+                return -1;
+            }
+            let (ptr, len) = (ptr as usize, len as usize);
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            match (data.get_mut(ptr..ptr + len), state.payload.as_ref()) {
+                (Some(dst), Some(sglist)) => copy_payload(&sglist.0, offset as usize, dst) as i32,
+                _ => -1,
+            }
+        },
+    )?;
+    Ok(())
+}
+
+fn meta_get(meta: &MessageMeta, field: i32) -> Option<i64> {
+    let value = match field {
+        field::CONN_ID => meta.conn_id.0 as i64,
+        field::SERVICE_ID => meta.service_id as i64,
+        field::FUNC_ID => meta.func_id as i64,
+        field::CALL_ID => meta.call_id.0 as i64,
+        field::TOKEN => meta.token as i64,
+        field::MSG_TYPE => match meta.msg_type {
+            RpcMsgType::Request => 0,
+            RpcMsgType::Response => 1,
+            RpcMsgType::Post => 2,
+        },
+        field::STATUS_CODE => meta.status_code as i64,
+        field::CORRELATION_ID => meta.correlation_id as i64,
+        field::FLAGS => meta.flags as i64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Returns false if the field cannot be set to `value`.
+fn meta_set(meta: &mut MessageMeta, field: i32, value: i64) -> bool {
+    match field {
+        field::TOKEN => meta.token = value as u64,
+        field::STATUS_CODE => {
+            meta.status_code = match value {
+                0 => StatusCode::Success,
+                1 => StatusCode::AccessDenied,
+                2 => StatusCode::Unknown,
+                3 => StatusCode::Unimplemented,
+                4 => StatusCode::DataLoss,
+                5 => StatusCode::Failed,
+                _ => return false,
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Copies the payload from `offset` into `dst`, and returns the number of bytes copied.
+fn copy_payload(segments: &[SgE], mut offset: usize, dst: &mut [u8]) -> usize {
+    let mut copied = 0;
+    for sge in segments {
+        if copied == dst.len() {
+            break;
+        }
+        if offset >= sge.len {
+            offset -= sge.len;
+            continue;
+        }
+        let n = (sge.len - offset).min(dst.len() - copied);
+        // SAFETY: the segments are on the shared heaps, and stay valid while the message is
+        // filtered
+        let src = unsafe { slice::from_raw_parts((sge.ptr + offset) as *const u8, n) };
+        dst[copied..copied + n].copy_from_slice(src);
+        copied += n;
+        offset = 0;
+    }
+    copied
+}
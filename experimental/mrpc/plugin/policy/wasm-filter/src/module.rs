@@ -0,0 +1,114 @@
+use anyhow::{bail, Result};
+use nix::unistd::Pid;
+
+use phoenix_api_policy_wasm_filter::control_plane;
+use phoenix_common::addon::{PhoenixAddon, Version};
+use phoenix_common::config::PluginConfig;
+use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::{Engine, EngineType};
+use phoenix_common::storage::ResourceCollection;
+
+use super::engine::WasmFilterEngine;
+use crate::config::WasmFilterConfig;
+use crate::filter::FilterModule;
+
+pub(crate) struct WasmFilterEngineBuilder<'a> {
+    node: DataPathNode,
+    config: WasmFilterConfig,
+    module: &'a FilterModule,
+}
+
+impl<'a> WasmFilterEngineBuilder<'a> {
+    fn new(node: DataPathNode, config: WasmFilterConfig, module: &'a FilterModule) -> Self {
+        WasmFilterEngineBuilder {
+            node,
+            config,
+            module,
+        }
+    }
+
+    fn build(self) -> Result<WasmFilterEngine> {
+        WasmFilterEngine::new(self.node, self.config, self.module)
+    }
+}
+
+pub struct WasmFilterAddon {
+    config: WasmFilterConfig,
+    // the filter of `config`, compiled once for all the engines
+    module: FilterModule,
+}
+
+impl WasmFilterAddon {
+    pub const WASM_FILTER_ENGINE: EngineType = EngineType("WasmFilterEngine");
+    pub const ENGINES: &'static [EngineType] = &[WasmFilterAddon::WASM_FILTER_ENGINE];
+}
+
+impl WasmFilterAddon {
+    pub fn new(config: WasmFilterConfig) -> Result<Self> {
+        let module = FilterModule::load(&config.module)?;
+        Ok(WasmFilterAddon { config, module })
+    }
+}
+
+impl PhoenixAddon for WasmFilterAddon {
+    fn check_compatibility(&self, _prev: Option<&Version>) -> bool {
+        true
+    }
+
+    fn decompose(self: Box<Self>) -> ResourceCollection {
+        let addon = *self;
+        let mut collections = ResourceCollection::new();
+        collections.insert("config".to_string(), Box::new(addon.config));
+        collections
+    }
+
+    #[inline]
+    fn migrate(&mut self, _prev_addon: Box<dyn PhoenixAddon>) {}
+
+    fn engines(&self) -> &[EngineType] {
+        WasmFilterAddon::ENGINES
+    }
+
+    fn update_config(&mut self, config: &str) -> Result<()> {
+        let config = WasmFilterConfig::parse(Some(config))?;
+        self.module = FilterModule::load(&config.module)?;
+        self.config = config;
+        Ok(())
+    }
+
+    fn config_request(&self) -> Option<Vec<u8>> {
+        // the running engines keep their filter module, which is replaced by `LoadModule`
+        let request = control_plane::Request::NewConfig(self.config.fuel, self.config.on_trap);
+        bincode::serialize(&request).ok()
+    }
+
+    fn create_engine(
+        &mut self,
+        ty: EngineType,
+        _pid: Pid,
+        node: DataPathNode,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != WasmFilterAddon::WASM_FILTER_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let builder = WasmFilterEngineBuilder::new(node, self.config.clone(), &self.module);
+        let engine = builder.build()?;
+        Ok(Box::new(engine))
+    }
+
+    fn restore_engine(
+        &mut self,
+        ty: EngineType,
+        local: ResourceCollection,
+        node: DataPathNode,
+        prev_version: Version,
+    ) -> Result<Box<dyn Engine>> {
+        if ty != WasmFilterAddon::WASM_FILTER_ENGINE {
+            bail!("invalid engine type {:?}", ty)
+        }
+
+        let engine = WasmFilterEngine::restore(local, node, prev_version)?;
+        Ok(Box::new(engine))
+    }
+}
@@ -5,7 +5,7 @@ use anyhow::{anyhow, Result};
 use futures::future::BoxFuture;
 
 use super::super::engine::{TlStorage, ELS};
-use super::super::state::State;
+use super::super::state::{IncomingConnection, State};
 use super::super::ControlPathError;
 
 use phoenix_common::engine::datapath::DataPathNode;
@@ -144,20 +144,44 @@ impl AcceptorEngine {
             return Ok(Status::Progress(0));
         }
         let mut nwork = 0;
+        // take at most one request from each listener at a time, so a busy listener does not
+        // starve the others
         for entry in table.iter() {
             let listener = entry.data();
-            if let Some(builder) = listener.1.try_get_request()? {
+            if let Some(builder) = listener.listener.try_get_request()? {
+                nwork += 1;
                 // choose an rpc_adapter evenly
-                let rpc_adapter_id = listener.0;
-
+                let rpc_adapter_id = match listener.pick() {
+                    Some(rpc_adapter_id) => rpc_adapter_id,
+                    // all engines have unbound it, it is about to be closed
+                    None => {
+                        builder.reject();
+                        continue;
+                    }
+                };
+                let slot = match listener.admit() {
+                    Some(slot) => slot,
+                    None => {
+                        log::warn!(
+                            "{} has reached max_connections, rejecting a new connection",
+                            listener.addr
+                        );
+                        builder.reject();
+                        continue;
+                    }
+                };
                 // RpcAdapter please check for new pre_cmid
                 self.state
                     .resource()
                     .builder_table
                     .entry(rpc_adapter_id)
                     .or_insert_with(VecDeque::new)
-                    .push_back(builder);
-                nwork += 1;
+                    .push_back(IncomingConnection {
+                        listener: *entry.key(),
+                        builder,
+                        options: listener.options,
+                        slot,
+                    });
             }
         }
         Ok(Status::Progress(nwork))
@@ -0,0 +1,586 @@
+//! Bulk transfers of the messages the application marks with `WRef::as_bulk`.
+//!
+//! Such a message is marshaled as usual, but its segments are not sent. The sender posts a bulk
+//! offer instead, a send marked by `BULK_OFFER_IMM` that carries the `MessageMeta` followed by the
+//! address, the length and the remote key of each segment. The receiver reads the segments with
+//! RDMA READs into a gather buffer of the connection, tells the sender the outcome in a bulk
+//! completion marked by `BULK_DONE_IMM`, and delivers the message as if it was received in the
+//! gather buffer. The sender completes the message only when the bulk completion arrives, so the
+//! application keeps the payload untouched until the peer has read it.
+//!
+//! An offer takes a single receive buffer of the peer however large the message is, and the
+//! payload is not copied by either CPU. The payload carries no checksum. Bulk transfers are only
+//! used if both ends support `FEATURE_BULK`, i.e., they run on RDMA NICs; otherwise the message is
+//! sent in the standard way.
+//!
+//! Each segment is registered for the offer in a memory region that the peer may only read, so the
+//! peer cannot access anything but the payload. The regions are deregistered when the bulk
+//! completion arrives, when the connection closes, or when the peer has not read the message
+//! within the offer timeout, in which case the message fails and later reads of the peer fail too.
+use std::mem;
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+
+use mrpc_marshal::{SgE, SgList};
+use phoenix_api::net::RemoteKey;
+use phoenix_api::rpc::{CallId, MessageMeta, RpcId, TransportStatus, TransportTiming};
+use phoenix_api::Handle;
+
+use phoenix_common::engine::datapath::meta_pool::MetaBuffer;
+
+use super::config::default_bulk_offer_timeout_ms;
+use super::state::RecvContext;
+use super::ulib::uverbs::RemoteReadable;
+
+/// The immediate value that marks a bulk offer.
+pub(crate) const BULK_OFFER_IMM: u32 = 0x62750001;
+/// The immediate value that marks a bulk completion.
+pub(crate) const BULK_DONE_IMM: u32 = 0x62750002;
+/// The size of an encoded bulk completion.
+pub(crate) const BULK_DONE_LEN: usize = 16;
+
+// Written to `MetaBuffer::value_len` of an offer. A fused message never gets close to it.
+const OFFER_MARKER: u32 = u32::MAX;
+
+// The segments are read into the gather buffer at this alignment.
+const SEGMENT_ALIGN: usize = 8;
+
+const OFFER_HEADER_LEN: usize = mem::size_of::<MessageMeta>() + 2 * mem::size_of::<u32>();
+
+/// A segment of a message in the memory of the sender.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RemoteSegment {
+    pub(crate) addr: u64,
+    pub(crate) len: u32,
+    pub(crate) rkey: u32,
+}
+
+impl RemoteSegment {
+    #[inline]
+    pub(crate) fn remote_key(&self) -> RemoteKey {
+        RemoteKey {
+            addr: self.addr,
+            rkey: self.rkey,
+        }
+    }
+}
+
+/// Returns the offsets of the segments of `lens` in the gather buffer they are read into, and the
+/// length of the buffer they take.
+pub(crate) fn layout(lens: impl IntoIterator<Item = usize>) -> (Vec<usize>, usize) {
+    let mut offsets = Vec::new();
+    let mut total = 0;
+    for len in lens {
+        offsets.push(total);
+        total += (len + SEGMENT_ALIGN - 1) & !(SEGMENT_ALIGN - 1);
+    }
+    (offsets, total)
+}
+
+/// Returns whether the segments of `sglist` can be described in one offer and read into one
+/// gather buffer of the peer, which is expected to be of `gather_buffer_size` bytes like ours.
+pub(crate) fn fits(sglist: &SgList, gather_buffer_size: usize) -> bool {
+    sglist.0.len() * mem::size_of::<RemoteSegment>() <= MetaBuffer::capacity()
+        && layout(sglist.0.iter().map(|sge| sge.len)).1 <= gather_buffer_size
+}
+
+/// Writes the offer of `segments` after the meta in `meta_buf`. Returns the number of bytes to
+/// send from `meta_buf`. The segments must fit, see `fits`.
+pub(crate) fn write_offer(meta_buf: &mut MetaBuffer, segments: &[RemoteSegment]) -> usize {
+    let table_len = segments.len() * mem::size_of::<RemoteSegment>();
+    assert!(table_len <= MetaBuffer::capacity());
+
+    meta_buf.num_sge = segments.len() as u32;
+    meta_buf.value_len = OFFER_MARKER;
+    let table = meta_buf
+        .length_delimited
+        .as_mut_ptr()
+        .cast::<RemoteSegment>();
+    for (i, segment) in segments.iter().enumerate() {
+        // SAFETY: the table fits in the buffer
+        unsafe { table.add(i).write_unaligned(*segment) };
+    }
+    OFFER_HEADER_LEN + table_len
+}
+
+/// Reads the segments from the first segment of a message if it is a bulk offer, and trims the
+/// segment to the `MessageMeta`. Returns `None` and leaves the segment as it is otherwise.
+///
+/// # Safety
+///
+/// The segment must point to a receive buffer.
+pub(crate) unsafe fn take_offer(meta_sge: &mut SgE) -> Option<Vec<RemoteSegment>> {
+    let received = meta_sge.len;
+    if received < OFFER_HEADER_LEN {
+        return None;
+    }
+    let meta_buf = &*(meta_sge.ptr as *const MetaBuffer);
+    let num_segments = meta_buf.num_sge as usize;
+    let table_len = num_segments * mem::size_of::<RemoteSegment>();
+    if meta_buf.value_len != OFFER_MARKER
+        || table_len > MetaBuffer::capacity()
+        || received != OFFER_HEADER_LEN + table_len
+    {
+        return None;
+    }
+
+    let table = meta_buf.length_delimited.as_ptr().cast::<RemoteSegment>();
+    let segments = (0..num_segments)
+        .map(|i| table.add(i).read_unaligned())
+        .collect();
+    meta_sge.len = mem::size_of::<MessageMeta>();
+    Some(segments)
+}
+
+/// Encodes the bulk completion of the message of `call_id`.
+pub(crate) fn encode_done(call_id: CallId, status: TransportStatus) -> [u8; BULK_DONE_LEN] {
+    let mut buf = [0u8; BULK_DONE_LEN];
+    buf[0..8].copy_from_slice(&call_id.0.to_le_bytes());
+    buf[8..12].copy_from_slice(&status.code().to_le_bytes());
+    buf
+}
+
+/// Decodes a bulk completion. Returns `None` if `buf` is not one.
+pub(crate) fn decode_done(buf: &[u8]) -> Option<(CallId, TransportStatus)> {
+    if buf.len() != BULK_DONE_LEN {
+        return None;
+    }
+    let call_id = CallId(u64::from_le_bytes(buf[0..8].try_into().unwrap()));
+    let status = match NonZeroU32::new(u32::from_le_bytes(buf[8..12].try_into().unwrap())) {
+        Some(code) => TransportStatus::Error(code),
+        None => TransportStatus::Success,
+    };
+    Some((call_id, status))
+}
+
+#[derive(Debug)]
+struct Offer<R> {
+    // whether the send of the offer has completed
+    sent: bool,
+    // the status to complete the message with, from the bulk completion of the peer, or the
+    // failure of the offer
+    done: Option<TransportStatus>,
+    // whether the bulk completion of the peer can still arrive
+    awaiting_peer: bool,
+    // the regions the peer reads the segments from, dropped to revoke its access
+    regions: Vec<R>,
+    expires: Instant,
+}
+
+impl<R> Offer<R> {
+    /// Fails the offer with `status` unless it is done already, and revokes the access of the
+    /// peer. Returns whether the message is to be completed now.
+    fn fail(&mut self, status: TransportStatus) -> bool {
+        self.regions.clear();
+        if self.done.is_some() {
+            return false;
+        }
+        self.done = Some(status);
+        self.sent
+    }
+}
+
+/// A message whose segments are being read from the peer.
+#[derive(Debug)]
+pub(crate) struct Fetch {
+    pub(crate) conn_id: Handle,
+    pub(crate) call_id: CallId,
+    /// The meta in the receive buffer of the offer, followed by the segments in the gather buffer
+    pub(crate) recv_ctx: RecvContext,
+    /// The timing of the call if the message is a reply
+    pub(crate) timing: Option<TransportTiming>,
+    /// The first error of the reads
+    pub(crate) status: TransportStatus,
+    // the reads that have not completed
+    remaining: usize,
+}
+
+impl Fetch {
+    pub(crate) fn new(
+        conn_id: Handle,
+        call_id: CallId,
+        recv_ctx: RecvContext,
+        timing: Option<TransportTiming>,
+    ) -> Self {
+        Fetch {
+            conn_id,
+            call_id,
+            recv_ctx,
+            timing,
+            status: TransportStatus::Success,
+            remaining: 0,
+        }
+    }
+}
+
+/// The bulk transfers on the connections of an engine, in both directions.
+#[derive(Debug)]
+pub(crate) struct BulkTransfersT<R> {
+    // how long the peer has to read an offered message
+    offer_timeout: Duration,
+    // the messages offered to the peers
+    offers: FnvHashMap<RpcId, Offer<R>>,
+    // the handle of the gather buffer, which the reads of the fetch are posted with as wr_id ->
+    // the fetch
+    fetches: FnvHashMap<u64, Fetch>,
+}
+
+pub(crate) type BulkTransfers = BulkTransfersT<RemoteReadable>;
+
+impl<R> Default for BulkTransfersT<R> {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(default_bulk_offer_timeout_ms()))
+    }
+}
+
+impl<R> BulkTransfersT<R> {
+    pub(crate) fn new(offer_timeout: Duration) -> Self {
+        BulkTransfersT {
+            offer_timeout,
+            offers: FnvHashMap::default(),
+            fetches: FnvHashMap::default(),
+        }
+    }
+
+    /// The numbers of the messages offered to the peers, and of the messages being read.
+    pub(crate) fn in_flight(&self) -> (usize, usize) {
+        (self.offers.len(), self.fetches.len())
+    }
+
+    /// Records that the message of `rpc_id` is offered to the peer, which reads it from `regions`.
+    pub(crate) fn offer(&mut self, rpc_id: RpcId, regions: Vec<R>) {
+        let offer = Offer {
+            sent: false,
+            done: None,
+            awaiting_peer: true,
+            regions,
+            expires: Instant::now() + self.offer_timeout,
+        };
+        self.offers.insert(rpc_id, offer);
+    }
+
+    #[inline]
+    pub(crate) fn is_offered(&self, rpc_id: &RpcId) -> bool {
+        self.offers.contains_key(rpc_id)
+    }
+
+    /// The send of the offer of `rpc_id` has completed. Returns the status to complete the message
+    /// with if the offer is done.
+    pub(crate) fn offer_sent(&mut self, rpc_id: RpcId) -> Option<TransportStatus> {
+        let offer = self.offers.get_mut(&rpc_id)?;
+        offer.sent = true;
+        let status = offer.done?;
+        if !offer.awaiting_peer {
+            self.offers.remove(&rpc_id);
+        }
+        Some(status)
+    }
+
+    /// The bulk completion of `rpc_id` has arrived with `status`. Returns the status to complete
+    /// the message with if the send of the offer has completed, the completion of the send can
+    /// come later than the bulk completion. The message of an expired offer has been completed
+    /// already.
+    pub(crate) fn offer_done(
+        &mut self,
+        rpc_id: RpcId,
+        status: TransportStatus,
+    ) -> Option<TransportStatus> {
+        let offer = self.offers.get_mut(&rpc_id)?;
+        offer.awaiting_peer = false;
+        offer.regions.clear();
+        if offer.done.is_some() {
+            if offer.sent {
+                self.offers.remove(&rpc_id);
+            }
+            return None;
+        }
+        if !offer.sent {
+            offer.done = Some(status);
+            return None;
+        }
+        self.offers.remove(&rpc_id);
+        Some(status)
+    }
+
+    /// Forgets the offer of `rpc_id`, whose send has failed.
+    pub(crate) fn withdraw(&mut self, rpc_id: &RpcId) {
+        self.offers.remove(rpc_id);
+    }
+
+    /// Fails the offers on `conn_id`, which the peer will never read. Returns the messages to
+    /// complete with `status` now, the others are completed when the sends of their offers do.
+    pub(crate) fn fail_offers(&mut self, conn_id: Handle, status: TransportStatus) -> Vec<RpcId> {
+        let mut failed = Vec::new();
+        self.offers.retain(|rpc_id, offer| {
+            if rpc_id.0 != conn_id {
+                return true;
+            }
+            offer.awaiting_peer = false;
+            if offer.fail(status) {
+                failed.push(*rpc_id);
+            }
+            !offer.sent
+        });
+        failed
+    }
+
+    /// Fails the offers that the peers have not read in time, and revokes their access. Returns
+    /// the messages to complete with `BULK_READ_FAILED` now, the others are completed when the
+    /// sends of their offers do. An expired offer is kept until its bulk completion arrives, so
+    /// the completion is not taken as a message.
+    pub(crate) fn expire_offers(&mut self, now: Instant) -> Vec<RpcId> {
+        let mut expired = Vec::new();
+        for (rpc_id, offer) in &mut self.offers {
+            if offer.expires <= now && offer.fail(TransportStatus::BULK_READ_FAILED) {
+                expired.push(*rpc_id);
+            }
+        }
+        expired
+    }
+
+    /// Returns the number of transfers in progress.
+    #[inline]
+    pub(crate) fn pending(&self) -> usize {
+        self.offers.len() + self.fetches.len()
+    }
+
+    /// Returns whether any message of `conn_id` is offered to the peer or being read from it.
+    pub(crate) fn involves(&self, conn_id: &Handle) -> bool {
+        self.offers.keys().any(|rpc_id| rpc_id.0 == *conn_id)
+            || self.fetches.values().any(|fetch| fetch.conn_id == *conn_id)
+    }
+
+    /// Tracks `fetch` until the reads posted with `wr_id` complete.
+    pub(crate) fn start_fetch(&mut self, wr_id: u64, mut fetch: Fetch, num_reads: usize) {
+        fetch.remaining = num_reads;
+        self.fetches.insert(wr_id, fetch);
+    }
+
+    #[inline]
+    pub(crate) fn owns(&self, wr_id: u64) -> bool {
+        self.fetches.contains_key(&wr_id)
+    }
+
+    /// A read posted with `wr_id` has completed with `status`. Returns the fetch once all its
+    /// reads have completed.
+    pub(crate) fn read_completed(&mut self, wr_id: u64, status: TransportStatus) -> Option<Fetch> {
+        let fetch = self.fetches.get_mut(&wr_id)?;
+        if fetch.status == TransportStatus::Success {
+            fetch.status = status;
+        }
+        fetch.remaining -= 1;
+        if fetch.remaining > 0 {
+            return None;
+        }
+        self.fetches.remove(&wr_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn rpc_id(conn: u64, call: u64) -> RpcId {
+        RpcId(Handle(conn), CallId(call))
+    }
+
+    fn meta_buffer() -> Box<MetaBuffer> {
+        // SAFETY: all zeros is a valid MetaBuffer
+        Box::new(unsafe { mem::zeroed() })
+    }
+
+    #[test]
+    fn aligned_layout() {
+        let (offsets, len) = layout([3, 8, 0, 9]);
+        assert_eq!(offsets, [0, 8, 16, 16]);
+        assert_eq!(len, 32);
+    }
+
+    #[test]
+    fn offer_round_trip() {
+        let segments = [
+            RemoteSegment {
+                addr: 0x1000,
+                len: 100,
+                rkey: 7,
+            },
+            RemoteSegment {
+                addr: 0x2000,
+                len: 0,
+                rkey: 0,
+            },
+        ];
+        let mut meta_buf = meta_buffer();
+        let post_len = write_offer(&mut meta_buf, &segments);
+        let mut sge = SgE {
+            ptr: &*meta_buf as *const MetaBuffer as usize,
+            len: post_len,
+        };
+        let taken = unsafe { take_offer(&mut sge) };
+        assert_eq!(taken.as_deref(), Some(&segments[..]));
+        assert_eq!(sge.len, mem::size_of::<MessageMeta>());
+    }
+
+    #[test]
+    fn not_an_offer() {
+        let segments = [RemoteSegment {
+            addr: 0x1000,
+            len: 100,
+            rkey: 7,
+        }];
+        let mut meta_buf = meta_buffer();
+        let post_len = write_offer(&mut meta_buf, &segments);
+        let ptr = &*meta_buf as *const MetaBuffer as usize;
+        // truncated
+        let mut sge = SgE {
+            ptr,
+            len: post_len - 1,
+        };
+        assert!(unsafe { take_offer(&mut sge) }.is_none());
+        assert_eq!(sge.len, post_len - 1);
+        // a fused message
+        meta_buf.value_len = 0;
+        let mut sge = SgE { ptr, len: post_len };
+        assert!(unsafe { take_offer(&mut sge) }.is_none());
+        assert_eq!(sge.len, post_len);
+    }
+
+    #[test]
+    fn done_round_trip() {
+        let done = encode_done(CallId(42), TransportStatus::Success);
+        assert_eq!(
+            decode_done(&done),
+            Some((CallId(42), TransportStatus::Success))
+        );
+        let done = encode_done(CallId(43), TransportStatus::BULK_READ_FAILED);
+        assert_eq!(
+            decode_done(&done),
+            Some((CallId(43), TransportStatus::BULK_READ_FAILED))
+        );
+        assert_eq!(decode_done(&done[1..]), None);
+    }
+
+    #[test]
+    fn involved_connections() {
+        let mut bulk = BulkTransfersT::new(Duration::from_secs(60));
+        bulk.offer(rpc_id(1, 1), vec![()]);
+        assert!(bulk.involves(&Handle(1)));
+        assert!(!bulk.involves(&Handle(2)));
+        bulk.withdraw(&rpc_id(1, 1));
+        assert!(!bulk.involves(&Handle(1)));
+    }
+
+    #[test]
+    fn done_after_sent() {
+        let mut bulk = BulkTransfersT::new(Duration::from_secs(60));
+        let region = Rc::new(());
+        bulk.offer(rpc_id(1, 1), vec![Rc::clone(&region)]);
+        assert_eq!(bulk.offer_sent(rpc_id(1, 1)), None);
+        assert_eq!(Rc::strong_count(&region), 2);
+        assert_eq!(
+            bulk.offer_done(rpc_id(1, 1), TransportStatus::Success),
+            Some(TransportStatus::Success)
+        );
+        assert_eq!(Rc::strong_count(&region), 1);
+        assert!(!bulk.is_offered(&rpc_id(1, 1)));
+    }
+
+    #[test]
+    fn done_before_sent() {
+        let mut bulk = BulkTransfersT::new(Duration::from_secs(60));
+        let region = Rc::new(());
+        bulk.offer(rpc_id(1, 1), vec![Rc::clone(&region)]);
+        assert_eq!(
+            bulk.offer_done(rpc_id(1, 1), TransportStatus::BULK_READ_FAILED),
+            None
+        );
+        // the peer is done reading
+        assert_eq!(Rc::strong_count(&region), 1);
+        assert_eq!(
+            bulk.offer_sent(rpc_id(1, 1)),
+            Some(TransportStatus::BULK_READ_FAILED)
+        );
+        assert_eq!(bulk.pending(), 0);
+    }
+
+    #[test]
+    fn expire_sent() {
+        let mut bulk = BulkTransfersT::new(Duration::ZERO);
+        let region = Rc::new(());
+        bulk.offer(rpc_id(1, 1), vec![Rc::clone(&region)]);
+        assert_eq!(bulk.offer_sent(rpc_id(1, 1)), None);
+        assert_eq!(bulk.expire_offers(Instant::now()), [rpc_id(1, 1)]);
+        assert_eq!(Rc::strong_count(&region), 1);
+        // reported once
+        assert!(bulk.expire_offers(Instant::now()).is_empty());
+        // kept for the late completion, which completes nothing
+        assert!(bulk.is_offered(&rpc_id(1, 1)));
+        assert_eq!(
+            bulk.offer_done(rpc_id(1, 1), TransportStatus::Success),
+            None
+        );
+        assert!(!bulk.is_offered(&rpc_id(1, 1)));
+    }
+
+    #[test]
+    fn expire_unsent() {
+        let mut bulk = BulkTransfersT::new(Duration::ZERO);
+        let region = Rc::new(());
+        bulk.offer(rpc_id(1, 1), vec![Rc::clone(&region)]);
+        assert!(bulk.expire_offers(Instant::now()).is_empty());
+        assert_eq!(Rc::strong_count(&region), 1);
+        assert_eq!(
+            bulk.offer_sent(rpc_id(1, 1)),
+            Some(TransportStatus::BULK_READ_FAILED)
+        );
+        assert_eq!(
+            bulk.offer_done(rpc_id(1, 1), TransportStatus::Success),
+            None
+        );
+        assert!(!bulk.is_offered(&rpc_id(1, 1)));
+    }
+
+    #[test]
+    fn not_expired() {
+        let mut bulk = BulkTransfersT::new(Duration::from_secs(60));
+        let region = Rc::new(());
+        bulk.offer(rpc_id(1, 1), vec![Rc::clone(&region)]);
+        assert!(bulk.expire_offers(Instant::now()).is_empty());
+        assert_eq!(Rc::strong_count(&region), 2);
+    }
+
+    #[test]
+    fn fail_connection() {
+        let mut bulk = BulkTransfersT::new(Duration::from_secs(60));
+        let region = Rc::new(());
+        bulk.offer(rpc_id(1, 1), vec![Rc::clone(&region)]);
+        bulk.offer(rpc_id(1, 2), vec![Rc::clone(&region)]);
+        bulk.offer(rpc_id(2, 1), vec![Rc::clone(&region)]);
+        assert_eq!(bulk.offer_sent(rpc_id(1, 1)), None);
+
+        let status = TransportStatus::BULK_READ_FAILED;
+        assert_eq!(bulk.fail_offers(Handle(1), status), [rpc_id(1, 1)]);
+        // the other connection keeps its access
+        assert_eq!(Rc::strong_count(&region), 2);
+        assert_eq!(bulk.offer_sent(rpc_id(1, 2)), Some(status));
+        assert_eq!(bulk.in_flight(), (1, 0));
+    }
+
+    #[test]
+    fn fetch() {
+        let mut bulk = BulkTransfersT::<()>::default();
+        let fetch = Fetch::new(Handle(1), CallId(1), RecvContext::default(), None);
+        bulk.start_fetch(9, fetch, 2);
+        assert!(bulk.owns(9));
+        let error = TransportStatus::Error(NonZeroU32::new(5).unwrap());
+        assert!(bulk.read_completed(9, error).is_none());
+        let fetch = bulk.read_completed(9, TransportStatus::Success).unwrap();
+        // the first error is kept
+        assert_eq!(fetch.status, error);
+        assert!(!bulk.owns(9));
+    }
+}
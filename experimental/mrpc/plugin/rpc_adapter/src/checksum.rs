@@ -0,0 +1,129 @@
+//! CRC32C (Castagnoli) of the message payloads.
+//!
+//! The checksum is computed by the sender over the marshaled SgList and carried in the immediate
+//! value of the last send of a message. The receiver recomputes it over the received segments, so
+//! that corruption of the buffers in between (e.g., by a faulty DMA or a stray write to the shared
+//! memory) is caught before the message is unmarshaled. An immediate value of 0 means the message
+//! carries no checksum.
+use std::slice;
+
+use mrpc_marshal::SgE;
+
+// reversed polynomial of CRC32C
+const POLY: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn update_sw(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn update_hw(crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut chunks = data.chunks_exact(8);
+    let mut crc = crc as u64;
+    for chunk in &mut chunks {
+        crc = _mm_crc32_u64(crc, u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    chunks
+        .remainder()
+        .iter()
+        .fold(crc as u32, |crc, &b| _mm_crc32_u8(crc, b))
+}
+
+#[inline]
+fn update(crc: u32, data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the CPU supports the instructions
+        return unsafe { update_hw(crc, data) };
+    }
+    update_sw(crc, data)
+}
+
+/// Returns the CRC32C over the concatenation of the segments.
+///
+/// # Safety
+///
+/// The segments must point to readable memory.
+pub(crate) unsafe fn crc32c<'a, I>(sgl: I) -> u32
+where
+    I: IntoIterator<Item = &'a SgE>,
+{
+    let crc = sgl
+        .into_iter()
+        .filter(|sge| sge.len > 0)
+        .fold(!0, |crc, sge| {
+            update(crc, slice::from_raw_parts(sge.ptr as *const u8, sge.len))
+        });
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sge(data: &[u8]) -> SgE {
+        SgE {
+            ptr: data.as_ptr() as usize,
+            len: data.len(),
+        }
+    }
+
+    #[test]
+    fn known_vector() {
+        let data = b"123456789";
+        assert_eq!(!update_sw(!0, data), 0xe306_9283);
+        assert_eq!(unsafe { crc32c(&[sge(data)]) }, 0xe306_9283);
+        // the segments are checksummed as one buffer, the empty ones are skipped
+        let sgl = [sge(&data[..4]), sge(&[]), sge(&data[4..])];
+        assert_eq!(unsafe { crc32c(&sgl) }, 0xe306_9283);
+        assert_eq!(unsafe { crc32c(&[]) }, 0);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn hw_matches_sw() {
+        if !is_x86_feature_detected!("sse4.2") {
+            return;
+        }
+        let buf: Vec<u8> = (0..4096).map(|_| fastrand::u8(..)).collect();
+        for _ in 0..1000 {
+            // unaligned starts and lengths that leave remainders of every size
+            let start = fastrand::usize(..16);
+            let len = fastrand::usize(..buf.len() - start);
+            let data = &buf[start..start + len];
+            let crc = fastrand::u32(..);
+            assert_eq!(
+                unsafe { update_hw(crc, data) },
+                update_sw(crc, data),
+                "start {}, len {}",
+                start,
+                len
+            );
+        }
+    }
+}
@@ -1,14 +1,366 @@
+use std::net::{IpAddr, SocketAddr};
+
 use serde::{Deserialize, Serialize};
 
+use phoenix_common::engine::datapath::meta_pool::MetaBuffer;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct RpcAdapterConfig {
     pub enable_scheduler: bool,
+    /// Transport-level keep-alive on idle connections
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    /// Run on the simulated in-memory transport instead of RDMA NICs, e.g., in CI
+    #[serde(default)]
+    pub sim: Option<SimConfig>,
+    /// Software pacing of sends on each connection
+    #[serde(default)]
+    pub congestion: CongestionConfig,
+    /// Verify a CRC32C of each message payload to catch memory corruption
+    #[serde(default)]
+    pub checksum: bool,
+    /// The number of receive buffers posted on each connection
+    #[serde(default)]
+    pub recv_buffers: RecvBufferConfig,
+    /// The buffers of each connection that the fragmented segments and the payloads read in bulk
+    /// are gathered into
+    #[serde(default)]
+    pub gather_buffers: GatherBufferConfig,
+    /// The largest message accepted from the peer of a connection, in bytes
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: u64,
+    /// Connections over unreliable datagrams, for small RPCs that tolerate loss
+    #[serde(default)]
+    pub datagram: DatagramConfig,
+    /// Post the receive buffers to a queue shared by the connections of an engine
+    #[serde(default)]
+    pub srq: SrqConfig,
+    /// What to do when the dispatch library fails on a message, e.g., for an unknown func_id
+    #[serde(default)]
+    pub on_dispatch_error: DispatchErrorPolicy,
+    /// The largest message copied into its meta buffer and sent in one piece, in bytes of the
+    /// segments and their lengths. Larger messages are sent from the shared heap without a copy.
+    /// Capped by the capacity of a meta buffer, the default. The application can change it for
+    /// its connections.
+    #[serde(default = "default_eager_copy_threshold")]
+    pub eager_copy_threshold: usize,
+    /// The order the messages queued in an engine are sent in
+    #[serde(default)]
+    pub tx_queue: TxQueueConfig,
+    /// How long the peer has to read a message sent in bulk before the message fails and the
+    /// peer loses access to it, in milliseconds
+    #[serde(default = "default_bulk_offer_timeout_ms")]
+    pub bulk_offer_timeout_ms: u64,
+}
+
+fn default_max_message_size() -> u64 {
+    1 << 30
+}
+
+pub(crate) fn default_eager_copy_threshold() -> usize {
+    MetaBuffer::capacity()
+}
+
+pub(crate) fn default_bulk_offer_timeout_ms() -> u64 {
+    10_000
 }
 
 impl RpcAdapterConfig {
     pub fn new(config: Option<&str>) -> anyhow::Result<Self> {
-        let config = toml::from_str(config.unwrap_or(""))?;
+        let config: Self = toml::from_str(config.unwrap_or(""))?;
+        let recv_buffers = &config.recv_buffers;
+        anyhow::ensure!(
+            0 < recv_buffers.low_watermark
+                && recv_buffers.low_watermark <= recv_buffers.high_watermark,
+            "invalid recv_buffers watermarks: low {}, high {}",
+            recv_buffers.low_watermark,
+            recv_buffers.high_watermark
+        );
+        let gather_buffers = &config.gather_buffers;
+        anyhow::ensure!(
+            gather_buffers.count > 0,
+            "invalid number of gather buffers: {}",
+            gather_buffers.count
+        );
+        anyhow::ensure!(
+            gather_buffers.size.is_power_of_two() && gather_buffers.size >= 4096,
+            "invalid gather buffer size: {}",
+            gather_buffers.size
+        );
+        let datagram = &config.datagram;
+        anyhow::ensure!(
+            datagram.mtu.is_power_of_two() && (256..=4096).contains(&datagram.mtu),
+            "invalid datagram mtu: {}",
+            datagram.mtu
+        );
+        let srq = &config.srq;
+        anyhow::ensure!(
+            srq.buffers > 0 && srq.buffers <= u32::MAX as usize,
+            "invalid number of srq buffers: {}",
+            srq.buffers
+        );
+        // a receive buffer must hold a message meta with its fragment table
+        anyhow::ensure!(
+            srq.buffer_size.is_power_of_two() && srq.buffer_size >= 16 * 1024,
+            "invalid srq buffer size: {}",
+            srq.buffer_size
+        );
         Ok(config)
     }
 }
+
+/// The order an engine sends the messages it has queued in, e.g., while they wait for credits.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TxQueueConfig {
+    /// The order of the connections whose peers are not listed in `peers`
+    pub order: TxOrder,
+    /// The peers whose connections are given another order
+    pub peers: Vec<PeerTxOrder>,
+}
+
+impl TxQueueConfig {
+    /// Returns the order of a connection to or from `peer_addr`.
+    pub(crate) fn order_of(&self, peer_addr: Option<SocketAddr>) -> TxOrder {
+        peer_addr
+            .and_then(|addr| self.peers.iter().find(|peer| peer.addr == addr.ip()))
+            .map_or(self.order, |peer| peer.order)
+    }
+
+    /// Returns whether every connection is FIFO.
+    pub(crate) fn is_fifo(&self) -> bool {
+        self.order == TxOrder::Fifo && self.peers.iter().all(|peer| peer.order == TxOrder::Fifo)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxOrder {
+    /// In the order the messages arrive
+    #[default]
+    Fifo,
+    /// Earliest deadline first. The messages without a deadline go after those with one, in the
+    /// order they arrive.
+    Edf,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerTxOrder {
+    /// The address of the peer, connections from any port match
+    pub addr: IpAddr,
+    pub order: TxOrder,
+}
+
+/// How an engine handles a message the dispatch library fails to marshal or unmarshal, or panics
+/// on. A message that cannot be sent is completed with the error in all cases.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DispatchErrorPolicy {
+    /// Only drop the message
+    Drop,
+    /// Also drop the later messages of the connection in both directions, and report the error
+    /// to the application as a receive error of the connection, which closes it
+    #[default]
+    Quarantine,
+    /// Fail the engine, which shuts down the engines of the client unless a restart policy is set
+    Fault,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecvBufferConfig {
+    /// The number of buffers posted when a connection is set up, and kept posted when it is idle
+    pub low_watermark: usize,
+    /// The most buffers posted on a connection
+    pub high_watermark: usize,
+    /// How often the number of posted buffers is adjusted to the load
+    pub adjust_interval_ms: u64,
+}
+
+impl Default for RecvBufferConfig {
+    fn default() -> Self {
+        RecvBufferConfig {
+            low_watermark: 16,
+            high_watermark: 128,
+            adjust_interval_ms: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatherBufferConfig {
+    /// The number of gather buffers of a connection, i.e., the fragmented messages or the messages
+    /// read in bulk that the application can hold at a time
+    pub count: usize,
+    /// The size of a gather buffer, i.e., the largest segment that can be received fragmented or
+    /// read in bulk. The ends of a connection are expected to use the same size.
+    pub size: usize,
+}
+
+impl Default for GatherBufferConfig {
+    fn default() -> Self {
+        GatherBufferConfig {
+            count: 2,
+            size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatagramConfig {
+    /// The MTU of the path, in bytes. A message must fit in one datagram.
+    pub mtu: usize,
+    /// The number of receive buffers posted for all the datagram connections of an engine
+    pub recv_buffers: usize,
+    /// The number of received messages of a connection the application can hold at a time
+    pub peer_buffers: usize,
+    /// The most datagrams an engine can have in flight
+    pub max_send_wr: usize,
+    /// How long to wait for the peer to answer when connecting
+    pub handshake_timeout_ms: u64,
+    /// How often the handshake is sent again when the peer does not answer
+    pub handshake_retry_ms: u64,
+}
+
+impl Default for DatagramConfig {
+    fn default() -> Self {
+        DatagramConfig {
+            mtu: 1024,
+            recv_buffers: 512,
+            peer_buffers: 64,
+            max_send_wr: 256,
+            handshake_timeout_ms: 1000,
+            handshake_retry_ms: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SrqConfig {
+    /// Whether the connections share the receive buffers instead of posting their own
+    pub enable: bool,
+    /// The number of receive buffers posted to the shared queue of an engine
+    pub buffers: usize,
+    /// The size of each receive buffer in bytes, larger messages are received in fragments
+    pub buffer_size: usize,
+}
+
+impl Default for SrqConfig {
+    fn default() -> Self {
+        SrqConfig {
+            enable: false,
+            buffers: 1024,
+            buffer_size: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepaliveConfig {
+    /// Whether to probe idle connections
+    pub enable: bool,
+    /// A connection that has not received anything for this long is probed with a ping
+    pub interval_ms: u64,
+    /// A connection that has not received anything for this long is considered dead
+    pub timeout_ms: u64,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        KeepaliveConfig {
+            enable: false,
+            interval_ms: 1000,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    /// The one-way latency of a message
+    pub latency_us: u64,
+    /// The probability that an RPC message is lost
+    pub loss: f64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            latency_us: 0,
+            loss: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CongestionConfig {
+    /// Whether to pace the sends on each connection
+    pub enable: bool,
+    /// The sending rate of a connection in Mbps, 0 means unlimited
+    pub rate_mbps: u64,
+    /// The number of bytes that can be sent back-to-back at the line rate
+    pub burst_bytes: u64,
+    /// Services paced at their own rate, each on a separate budget
+    pub services: Vec<ServicePacing>,
+    /// Cuts the sending rate when the NIC reports congestion
+    pub dcqcn: DcqcnConfig,
+}
+
+impl Default for CongestionConfig {
+    fn default() -> Self {
+        CongestionConfig {
+            enable: false,
+            rate_mbps: 0,
+            burst_bytes: 64 * 1024,
+            services: Vec::new(),
+            dcqcn: DcqcnConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServicePacing {
+    pub service_id: u32,
+    /// The sending rate of the service on a connection in Mbps, 0 means unlimited
+    pub rate_mbps: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DcqcnConfig {
+    /// Whether to adjust the sending rates by the congestion notifications
+    pub enable: bool,
+    /// The counter of handled CNPs, e.g.,
+    /// /sys/class/infiniband/mlx5_0/ports/1/hw_counters/rp_cnp_handled
+    pub cnp_counter: Option<String>,
+    /// How often the counter is sampled
+    pub interval_us: u64,
+    /// The gain of the congestion estimate
+    pub g: f64,
+    /// The rate regained in each interval without congestion, in Mbps
+    pub rate_ai_mbps: u64,
+    /// The sending rate is never cut below this, in Mbps
+    pub min_rate_mbps: u64,
+}
+
+impl Default for DcqcnConfig {
+    fn default() -> Self {
+        DcqcnConfig {
+            enable: false,
+            cnp_counter: None,
+            interval_us: 55,
+            g: 1.0 / 256.0,
+            rate_ai_mbps: 40,
+            min_rate_mbps: 100,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Congestion control of the RDMA path.
+//!
+//! Sends are paced by a token bucket on each connection. A service can be paced at its own rate,
+//! on a budget separate from the rest of the connection. With DCQCN enabled, the rates are cut
+//! multiplicatively when the NIC reports congestion notification packets (CNPs), and regained
+//! additively otherwise, like the reaction point of DCQCN. The CNP counters of the NIC are per
+//! port, so a CNP slows down all the connections of an engine.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+
+use phoenix_api::Handle;
+use phoenix_common::log;
+
+use super::config::CongestionConfig;
+
+// bytes per second in one Mbps
+const BYTES_PER_MBPS: f64 = 1_000_000.0 / 8.0;
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        TokenBucket {
+            tokens: burst,
+            last_refill: now,
+        }
+    }
+
+    fn try_consume(&mut self, bytes: usize, rate: f64, burst: f64, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        // A message larger than the burst is let go as long as the bucket is not in debt, and
+        // leaves the bucket in debt.
+        if self.tokens < 0.0 {
+            return false;
+        }
+        self.tokens -= bytes as f64;
+        true
+    }
+}
+
+/// Reads the number of CNPs handled by the NIC from a hardware counter.
+struct CnpCounter {
+    path: PathBuf,
+    last: u64,
+}
+
+impl CnpCounter {
+    fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let last = Self::read(&path)?;
+        Ok(CnpCounter { path, last })
+    }
+
+    fn read(path: &Path) -> io::Result<u64> {
+        fs::read_to_string(path)?
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns the number of CNPs since the last poll.
+    fn poll(&mut self) -> io::Result<u64> {
+        let value = Self::read(&self.path)?;
+        let delta = value.saturating_sub(self.last);
+        self.last = value;
+        Ok(delta)
+    }
+}
+
+pub(crate) struct CongestionControl {
+    config: CongestionConfig,
+    // indexed by connection and the service with its own rate, if any
+    buckets: FnvHashMap<(Handle, Option<u32>), TokenBucket>,
+    cnp_counter: Option<CnpCounter>,
+    // the fraction of the configured rates currently allowed
+    ratio: f64,
+    // the estimate of congestion
+    alpha: f64,
+    last_check: Instant,
+}
+
+impl CongestionControl {
+    pub(crate) fn new(config: CongestionConfig) -> Self {
+        let cnp_counter = match config.dcqcn.cnp_counter.as_ref() {
+            Some(path) if config.enable && config.dcqcn.enable => CnpCounter::open(path)
+                .map_err(|e| log::warn!("cannot read CNP counter {}: {}", path, e))
+                .ok(),
+            _ => None,
+        };
+        CongestionControl {
+            config,
+            buckets: FnvHashMap::default(),
+            cnp_counter,
+            ratio: 1.0,
+            alpha: 1.0,
+            last_check: Instant::now(),
+        }
+    }
+
+    #[inline]
+    fn rate(&self, rate_mbps: u64) -> f64 {
+        let min_rate_mbps = self.config.dcqcn.min_rate_mbps.min(rate_mbps) as f64;
+        (rate_mbps as f64 * self.ratio).max(min_rate_mbps) * BYTES_PER_MBPS
+    }
+
+    /// Returns whether a message of `bytes` to `service_id` can be sent on `conn_id` now.
+    pub(crate) fn admit(&mut self, conn_id: Handle, service_id: u32, bytes: usize) -> bool {
+        if !self.config.enable {
+            return true;
+        }
+
+        let (key, rate_mbps) = match self
+            .config
+            .services
+            .iter()
+            .find(|s| s.service_id == service_id)
+        {
+            Some(s) => (Some(service_id), s.rate_mbps),
+            None => (None, self.config.rate_mbps),
+        };
+        if rate_mbps == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let rate = self.rate(rate_mbps);
+        let burst = self.config.burst_bytes as f64;
+        self.buckets
+            .entry((conn_id, key))
+            .or_insert_with(|| TokenBucket::new(burst, now))
+            .try_consume(bytes, rate, burst, now)
+    }
+
+    /// Adjusts the rates by the CNPs received since the last check. Returns whether the rates
+    /// are adjusted.
+    pub(crate) fn check_congestion(&mut self) -> bool {
+        if self.cnp_counter.is_none() {
+            return false;
+        }
+
+        let now = Instant::now();
+        let interval = Duration::from_micros(self.config.dcqcn.interval_us);
+        if now.saturating_duration_since(self.last_check) < interval {
+            return false;
+        }
+        self.last_check = now;
+
+        match self.cnp_counter.as_mut().unwrap().poll() {
+            Ok(cnps) => {
+                self.on_congestion_notification(cnps);
+                true
+            }
+            Err(e) => {
+                log::warn!("cannot read CNP counter, stop adjusting rates: {}", e);
+                self.cnp_counter = None;
+                self.ratio = 1.0;
+                false
+            }
+        }
+    }
+
+    /// Cuts the rates if any CNP is received, regains the rates otherwise.
+    pub(crate) fn on_congestion_notification(&mut self, cnps: u64) {
+        let g = self.config.dcqcn.g;
+        if cnps > 0 {
+            self.alpha = (1.0 - g) * self.alpha + g;
+            self.ratio *= 1.0 - self.alpha / 2.0;
+        } else {
+            self.alpha *= 1.0 - g;
+            let step = if self.config.rate_mbps > 0 {
+                self.config.dcqcn.rate_ai_mbps as f64 / self.config.rate_mbps as f64
+            } else {
+                1.0
+            };
+            self.ratio = (self.ratio + step).min(1.0);
+        }
+        // rates are floored at min_rate_mbps anyway, do not go further below
+        if self.config.rate_mbps > 0 {
+            let floor = self.config.dcqcn.min_rate_mbps as f64 / self.config.rate_mbps as f64;
+            self.ratio = self.ratio.max(floor.min(1.0));
+        }
+    }
+}
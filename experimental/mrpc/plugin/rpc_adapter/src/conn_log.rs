@@ -0,0 +1,134 @@
+//! The last events of each connection, and the tools tailing them from the control plane.
+//!
+//! Each connection keeps its last events in a [`ConnLog`], so the history of a misbehaving
+//! connection is there when someone starts looking. A `TailConnLog` request adds a [`Tail`],
+//! which gets the events kept so far and then every new one as a datagram, like `tcpdump` does
+//! for packets.
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use phoenix_api::Handle;
+use phoenix_api_rpc_adapter::control_plane::{ConnEvent, ConnEventKind};
+
+/// The number of events kept per connection, the older ones are dropped.
+const CAPACITY: usize = 64;
+
+/// The last events of a connection.
+#[derive(Debug, Default)]
+pub(crate) struct ConnLog {
+    events: VecDeque<ConnEvent>,
+}
+
+impl ConnLog {
+    fn push(&mut self, event: ConnEvent) {
+        // consecutive reclaims are merged, so they do not push the other events out
+        if let (Some(last), ConnEventKind::Reclaimed(n)) = (self.events.back_mut(), &event.kind) {
+            if let ConnEventKind::Reclaimed(total) = &mut last.kind {
+                *total += n;
+                last.at = event.at;
+                return;
+            }
+        }
+        if self.events.len() == CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+/// A tool tailing the events of a connection, or of all the connections.
+#[derive(Debug)]
+struct Tail {
+    conn_id: Option<Handle>,
+    path: PathBuf,
+}
+
+/// The tails of the connections of an engine.
+#[derive(Debug, Default)]
+pub(crate) struct Tails {
+    // unbound and non-blocking, a slow tool loses events rather than stalls the engine
+    socket: Option<UnixDatagram>,
+    tails: Vec<Tail>,
+}
+
+impl Tails {
+    /// Records an event of the connection `conn_id` in `log`, and sends it to the tails of the
+    /// connection.
+    pub(crate) fn record(&mut self, log: &mut ConnLog, conn_id: Handle, kind: ConnEventKind) {
+        let event = ConnEvent {
+            conn_id,
+            at: SystemTime::now(),
+            kind,
+        };
+        if !self.tails.is_empty() {
+            self.send(&event);
+        }
+        log.push(event);
+    }
+
+    /// Records an event of a connection whose log is gone, e.g., it is disconnected.
+    pub(crate) fn record_gone(&mut self, conn_id: Handle, kind: ConnEventKind) {
+        self.record(&mut ConnLog::default(), conn_id, kind);
+    }
+
+    /// Adds a tail at `path`, and sends it the events in `logs` of the connections it tails.
+    pub(crate) fn add<'a>(
+        &mut self,
+        conn_id: Option<Handle>,
+        path: PathBuf,
+        logs: impl IntoIterator<Item = &'a ConnLog>,
+    ) -> io::Result<()> {
+        if self.socket.is_none() {
+            let socket = UnixDatagram::unbound()?;
+            socket.set_nonblocking(true)?;
+            self.socket = Some(socket);
+        }
+        let tail = Tail { conn_id, path };
+        let socket = self.socket.as_ref().unwrap();
+        let mut backlog: Vec<_> = logs
+            .into_iter()
+            .flat_map(|log| log.events.iter())
+            .filter(|event| {
+                tail.conn_id
+                    .map_or(true, |conn_id| conn_id == event.conn_id)
+            })
+            .collect();
+        backlog.sort_by_key(|event| event.at);
+        for event in backlog {
+            send_to(socket, &tail, event)?;
+        }
+        self.tails.push(tail);
+        Ok(())
+    }
+
+    fn send(&mut self, event: &ConnEvent) {
+        let Some(socket) = self.socket.as_ref() else {
+            return;
+        };
+        self.tails.retain(|tail| {
+            if tail
+                .conn_id
+                .map_or(false, |conn_id| conn_id != event.conn_id)
+            {
+                return true;
+            }
+            match send_to(socket, tail, event) {
+                Ok(()) => true,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => true,
+                Err(e) => {
+                    log::info!("Stop tailing connection events to {:?}: {}", tail.path, e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+fn send_to(socket: &UnixDatagram, tail: &Tail, event: &ConnEvent) -> io::Result<()> {
+    let buf = bincode::serialize(event).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    socket.send_to(&buf, &tail.path)?;
+    Ok(())
+}
@@ -0,0 +1,641 @@
+//! Connections over unreliable datagrams, for small RPCs that tolerate loss.
+//!
+//! An engine opens at most one UD QP, the endpoint, on its first `ConnectDatagram` or datagram
+//! bind, and all its datagram connections share it. A connection is set up by exchanging a
+//! [`Hello`] over UDP with the address the server is bound to. The hello tells the other end the
+//! GID and the QP to send to, and the ID to put in the messages it sends, since the messages of
+//! all the connections arrive on the same QP.
+//!
+//! A message is sent in a single datagram laid out like a fused message of a reliable
+//! connection, so only the messages that fit in `datagram.mtu` can be sent. There are no credits
+//! and no retransmissions: a message that is lost, or that arrives when the application holds
+//! all the buffers of its connection, is gone.
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use serde::{Deserialize, Serialize};
+
+use phoenix_api::net;
+use phoenix_api::rpc::MessageMeta;
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
+use phoenix_common::engine::datapath::meta_pool::MetaBuffer;
+use phoenix_common::engine::future;
+use phoenix_common::log;
+use phoenix_common::resource::Error as ResourceError;
+use phoenix_salloc::region::AddressMediator;
+use rdma::ibv;
+
+use super::config::DatagramConfig;
+use super::pool::{BufferSlab, RecvBuffer};
+use super::ulib;
+use super::{ControlPathError, DatapathError};
+
+/// The global routing header that precedes every datagram received.
+pub(crate) const GRH_LEN: usize = 40;
+
+/// The Q_Key librdmacm sets on the QPs of `RDMA_PS_UDP`.
+const UDP_QKEY: u32 = 0x01234567;
+
+/// The largest encoded hello.
+const MAX_HELLO_LEN: usize = 128;
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a new ID for a datagram connection, unique in the process.
+pub(crate) fn new_conn_id() -> Handle {
+    Handle::new(
+        HandleNamespace::Datagram,
+        NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Hello {
+    /// The GID of the port of the endpoint
+    pub(crate) gid: [u8; 16],
+    pub(crate) qpn: u32,
+    pub(crate) qkey: u32,
+    /// The ID the other end puts in the messages to the sender
+    pub(crate) conn_id: Handle,
+    /// The optional features the sender supports, see `settings`
+    pub(crate) features: u32,
+}
+
+pub(crate) struct DatagramPeer {
+    ah: ulib::uverbs::AddressHandle,
+    remote_qpn: u32,
+    remote_qkey: u32,
+    /// The ID of the connection at the other end
+    pub(crate) remote_conn_id: Handle,
+    pub(crate) features: u32,
+    /// The buffers the messages from the peer are copied into, mapped by the application
+    slab: Arc<BufferSlab>,
+}
+
+/// What to do with a hello received by the listener.
+#[derive(Debug, PartialEq, Eq)]
+enum Received {
+    /// The hello of a new peer, to be accepted by the engine
+    New,
+    /// Sent again by a peer that has connected, whose answer may have been lost
+    Answer(Hello),
+    /// Sent again by a peer the application is not ready to receive from yet
+    Pending,
+}
+
+/// The peers that connected to this end, and the answers to their hellos.
+#[derive(Debug, Default)]
+struct Accepted {
+    // the connections of the peers, by the GID, QP and ID in their hellos
+    conns: FnvHashMap<([u8; 16], u32, Handle), Handle>,
+    // where to answer the hellos of each connection, and the answer once it is sent
+    replies: FnvHashMap<Handle, (SocketAddr, Option<Hello>)>,
+}
+
+impl Accepted {
+    fn receive(&self, hello: &Hello) -> Received {
+        let conn_id = match self.conns.get(&(hello.gid, hello.qpn, hello.conn_id)) {
+            Some(conn_id) => conn_id,
+            None => return Received::New,
+        };
+        match self.replies[conn_id] {
+            (_, Some(reply)) => Received::Answer(reply),
+            (_, None) => Received::Pending,
+        }
+    }
+
+    fn insert(&mut self, conn_id: Handle, remote: &Hello, reply_to: SocketAddr) {
+        self.conns
+            .insert((remote.gid, remote.qpn, remote.conn_id), conn_id);
+        self.replies.insert(conn_id, (reply_to, None));
+    }
+
+    /// Records `reply` as the answer to the peer of `conn_id`. Returns where to send it, or
+    /// `None` if the peer did not connect to this end or has been answered already.
+    fn reply(&mut self, conn_id: &Handle, reply: Hello) -> Option<SocketAddr> {
+        match self.replies.get_mut(conn_id)? {
+            (_, Some(_)) => None,
+            (to, sent) => {
+                *sent = Some(reply);
+                Some(*to)
+            }
+        }
+    }
+}
+
+pub(crate) struct DatagramEndpoint {
+    // NOTE: The address handles must be destroyed before the QP.
+    peers: FnvHashMap<Handle, DatagramPeer>,
+    accepted: Accepted,
+    listener: Option<UdpSocket>,
+    // the receive buffers posted on the QP, by wr_id
+    posted: FnvHashMap<u64, RecvBuffer>,
+    recv_slab: BufferSlab,
+    // the number of sends that have not completed
+    outstanding: usize,
+    max_send_wr: usize,
+    mtu: usize,
+    gid: ibv::Gid,
+    qpn: u32,
+    pre_id: ulib::ucm::PreparedCmId,
+}
+
+impl DatagramEndpoint {
+    /// Opens the endpoint on the UD QP of `pre_id`, and posts all its receive buffers.
+    pub(crate) fn new(
+        pre_id: ulib::ucm::PreparedCmId,
+        config: &DatagramConfig,
+        odp_mr: &mut ulib::uverbs::MemoryRegion<u8>,
+        addr_mediator: &AddressMediator,
+    ) -> Result<Self, ControlPathError> {
+        let recv_slab = BufferSlab::new(
+            config.recv_buffers,
+            GRH_LEN + config.mtu,
+            4096,
+            addr_mediator,
+        )?;
+        let mut endpoint = DatagramEndpoint {
+            peers: FnvHashMap::default(),
+            accepted: Accepted::default(),
+            listener: None,
+            posted: FnvHashMap::default(),
+            recv_slab,
+            outstanding: 0,
+            max_send_wr: config.max_send_wr,
+            mtu: config.mtu,
+            gid: pre_id.sgid()?,
+            qpn: pre_id.qp_num()?,
+            pre_id,
+        };
+        while let Some(recv_buffer) = endpoint.recv_slab.obtain() {
+            let wr_id = recv_buffer.as_handle().0;
+            endpoint.post_recv(odp_mr, wr_id, recv_buffer)?;
+        }
+        Ok(endpoint)
+    }
+
+    #[inline]
+    pub(crate) fn qpn(&self) -> u32 {
+        self.qpn
+    }
+
+    #[inline]
+    pub(crate) fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    #[inline]
+    pub(crate) fn peer(&self, conn_id: &Handle) -> Option<&DatagramPeer> {
+        self.peers.get(conn_id)
+    }
+
+    /// Returns whether another datagram can be sent.
+    #[inline]
+    pub(crate) fn can_send(&self) -> bool {
+        self.outstanding < self.max_send_wr
+    }
+
+    fn hello(&self, conn_id: Handle, features: u32) -> Hello {
+        Hello {
+            gid: self.gid.raw(),
+            qpn: self.qpn,
+            qkey: UDP_QKEY,
+            conn_id,
+            features,
+        }
+    }
+
+    /// Sends the hello of connection `conn_id` to the server at `addr` until it answers. Returns
+    /// the hello of the server.
+    pub(crate) async fn handshake(
+        &self,
+        addr: &SocketAddr,
+        conn_id: Handle,
+        features: u32,
+        config: &DatagramConfig,
+    ) -> Result<Hello, ControlPathError> {
+        let unspecified = match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let sock = UdpSocket::bind((unspecified, 0)).map_err(ulib::Error::from)?;
+        sock.set_nonblocking(true).map_err(ulib::Error::from)?;
+        let hello = bincode::serialize(&self.hello(conn_id, features)).expect("serialize hello");
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(config.handshake_timeout_ms);
+        let retry = Duration::from_millis(config.handshake_retry_ms);
+        let mut last_sent: Option<Instant> = None;
+        let mut buf = [0u8; MAX_HELLO_LEN];
+        loop {
+            if last_sent.map_or(true, |t| t.elapsed() >= retry) {
+                sock.send_to(&hello, addr).map_err(ulib::Error::from)?;
+                last_sent = Some(Instant::now());
+            }
+            match sock.recv_from(&mut buf) {
+                Ok((size, from)) if from == *addr => {
+                    match bincode::deserialize::<Hello>(&buf[..size]) {
+                        Ok(reply) => return Ok(reply),
+                        Err(e) => log::warn!("Invalid hello from {}: {}", from, e),
+                    }
+                }
+                Ok((_, from)) => log::debug!("Ignored a hello from {}", from),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(ulib::Error::from(e).into()),
+            }
+            if start.elapsed() >= timeout {
+                return Err(ControlPathError::HandshakeTimeout(*addr));
+            }
+            future::yield_now().await;
+        }
+    }
+
+    /// Accepts the hellos sent to `addr`.
+    pub(crate) fn listen(&mut self, addr: &SocketAddr) -> Result<(), ControlPathError> {
+        if self.listener.is_some() {
+            return Err(ControlPathError::BindOptions(
+                "the engine already accepts datagram connections".to_owned(),
+            ));
+        }
+        let sock = UdpSocket::bind(addr).map_err(ulib::Error::from)?;
+        sock.set_nonblocking(true).map_err(ulib::Error::from)?;
+        self.listener = Some(sock);
+        Ok(())
+    }
+
+    /// Stops accepting hellos. Returns false if the endpoint does not accept them. The peers
+    /// that have connected are kept.
+    pub(crate) fn unlisten(&mut self) -> bool {
+        self.listener.take().is_some()
+    }
+
+    /// Returns the hellos of the new peers. The hellos of the peers that have connected are
+    /// answered again, in case the answer was lost.
+    pub(crate) fn poll_hellos(&mut self) -> Vec<(SocketAddr, Hello)> {
+        let mut hellos = Vec::new();
+        let sock = match self.listener.as_ref() {
+            Some(sock) => sock,
+            None => return hellos,
+        };
+        let mut buf = [0u8; MAX_HELLO_LEN];
+        loop {
+            let (size, from) = match sock.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Datagram listener recv failed: {}", e);
+                    break;
+                }
+            };
+            let hello = match bincode::deserialize::<Hello>(&buf[..size]) {
+                Ok(hello) => hello,
+                Err(e) => {
+                    log::warn!("Invalid hello from {}: {}", from, e);
+                    continue;
+                }
+            };
+            match self.accepted.receive(&hello) {
+                Received::New => hellos.push((from, hello)),
+                Received::Answer(reply) => Self::send_hello(sock, &reply, &from),
+                // not answered until the application has mapped the buffers
+                Received::Pending => {}
+            }
+        }
+        hellos
+    }
+
+    fn send_hello(sock: &UdpSocket, hello: &Hello, to: &SocketAddr) {
+        let buf = bincode::serialize(hello).expect("serialize hello");
+        if let Err(e) = sock.send_to(&buf, to) {
+            log::debug!("Failed to send hello to {}: {}", to, e);
+        }
+    }
+
+    /// Adds the connection `conn_id` to the peer that sent `remote`. The hello of a peer that
+    /// connected to this end is answered by `reply`.
+    pub(crate) fn add_peer(
+        &mut self,
+        conn_id: Handle,
+        remote: &Hello,
+        slab: Arc<BufferSlab>,
+        reply_to: Option<SocketAddr>,
+    ) -> Result<(), ulib::Error> {
+        let ah = self.pre_id.create_ah(&ibv::Gid::from_raw(remote.gid))?;
+        if let Some(reply_to) = reply_to {
+            self.accepted.insert(conn_id, remote, reply_to);
+        }
+        let peer = DatagramPeer {
+            ah,
+            remote_qpn: remote.qpn,
+            remote_qkey: remote.qkey,
+            remote_conn_id: remote.conn_id,
+            features: remote.features,
+            slab,
+        };
+        self.peers.insert(conn_id, peer);
+        Ok(())
+    }
+
+    /// Answers the hello of the peer of `conn_id` if it connected to this end, once the
+    /// application is ready to receive from it.
+    pub(crate) fn reply(&mut self, conn_id: &Handle, features: u32) {
+        let reply = self.hello(*conn_id, features);
+        let sock = match self.listener.as_ref() {
+            Some(sock) => sock,
+            None => return,
+        };
+        if let Some(to) = self.accepted.reply(conn_id, reply) {
+            Self::send_hello(sock, &reply, &to);
+        }
+    }
+
+    /// Sends `range` of `mr` to the peer of `conn_id`. The message in the range must carry the ID
+    /// of the connection at the peer, see [`DatagramPeer::remote_conn_id`].
+    pub(crate) fn post_send(
+        &mut self,
+        conn_id: &Handle,
+        mr: &ulib::uverbs::MemoryRegion<u8>,
+        range: std::ops::Range<usize>,
+        wr_id: u64,
+        flags: ulib::uverbs::SendFlags,
+        imm: u32,
+    ) -> Result<(), DatapathError> {
+        use ulib::uverbs::SendFlags;
+
+        let peer = self.peers.get(conn_id).ok_or(ResourceError::NotFound)?;
+        unsafe {
+            self.pre_id.post_ud_send_with_imm(
+                mr,
+                range,
+                wr_id,
+                flags | SendFlags::SIGNALED,
+                imm,
+                &peer.ah,
+                peer.remote_qpn,
+                peer.remote_qkey,
+            )?;
+        }
+        self.outstanding += 1;
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn send_completed(&mut self) {
+        self.outstanding -= 1;
+    }
+
+    /// Copies the message received by `wc` into a buffer of its connection, and posts the
+    /// receive buffer again. Returns the connection and the buffer with the length of the
+    /// message, or `None` if the message is dropped.
+    pub(crate) fn take_recv(
+        &mut self,
+        wc: &net::WorkCompletion,
+        odp_mr: &mut ulib::uverbs::MemoryRegion<u8>,
+    ) -> Result<Option<(Handle, RecvBuffer, usize)>, DatapathError> {
+        let recv_buffer = self
+            .posted
+            .remove(&wc.wr_id)
+            .ok_or(ResourceError::NotFound)?;
+        let addr = recv_buffer.addr() + GRH_LEN;
+        let len = (wc.byte_len as usize).saturating_sub(GRH_LEN);
+        let taken = self.copy_to_peer(wc, addr, len);
+        self.post_recv(odp_mr, wc.wr_id, recv_buffer)?;
+        Ok(taken.map(|(conn_id, buffer)| (conn_id, buffer, len)))
+    }
+
+    fn copy_to_peer(
+        &self,
+        wc: &net::WorkCompletion,
+        addr: usize,
+        len: usize,
+    ) -> Option<(Handle, RecvBuffer)> {
+        if !is_fused_message(addr, len) {
+            log::debug!("Dropped a malformed datagram of {} bytes", len);
+            return None;
+        }
+        // SAFETY: the receive buffer holds a message meta
+        let conn_id =
+            unsafe { ptr::addr_of!((*(addr as *const MessageMeta)).conn_id).read_unaligned() };
+        let peer = match self.peers.get(&conn_id) {
+            // the QP number cannot be forged, unlike the ID in the message
+            Some(peer) if peer.remote_qpn == wc.ud_src_qp => peer,
+            _ => {
+                log::debug!(
+                    "Dropped a datagram for {:?} from QP {}",
+                    conn_id,
+                    wc.ud_src_qp
+                );
+                return None;
+            }
+        };
+        let buffer = match peer.slab.obtain() {
+            Some(buffer) => buffer,
+            None => {
+                log::debug!("Dropped a datagram for {:?}, no buffer is free", conn_id);
+                return None;
+            }
+        };
+        // SAFETY: the buffers of the peer are larger than the MTU
+        unsafe { ptr::copy_nonoverlapping(addr as *const u8, buffer.addr() as *mut u8, len) };
+        Some((conn_id, buffer))
+    }
+
+    /// Gives a buffer taken by `take_recv` back to its connection.
+    pub(crate) fn release(&self, conn_id: &Handle, buffer: Arc<RecvBuffer>) {
+        if let Some(peer) = self.peers.get(conn_id) {
+            match Arc::try_unwrap(buffer) {
+                Ok(buffer) => peer.slab.release(buffer),
+                Err(buffer) => peer.slab.retire(&buffer),
+            }
+        }
+    }
+
+    /// Drops the receive buffer of a failed receive.
+    pub(crate) fn discard_recv(&mut self, wr_id: u64) -> bool {
+        match self.posted.remove(&wr_id) {
+            Some(recv_buffer) => {
+                self.recv_slab.release(recv_buffer);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn post_recv(
+        &mut self,
+        odp_mr: &mut ulib::uverbs::MemoryRegion<u8>,
+        wr_id: u64,
+        recv_buffer: RecvBuffer,
+    ) -> Result<(), ulib::Error> {
+        let off = recv_buffer.addr();
+        let len = GRH_LEN + self.mtu;
+        unsafe {
+            self.pre_id.post_recv(odp_mr, off..off + len, wr_id)?;
+        }
+        self.posted.insert(wr_id, recv_buffer);
+        Ok(())
+    }
+}
+
+/// Returns whether the `len` bytes at `addr` are laid out like a fused message, so it can be
+/// unpacked without reading past the end.
+fn is_fused_message(addr: usize, len: usize) -> bool {
+    let header = mem::size_of::<MessageMeta>() + 2 * mem::size_of::<u32>();
+    if len < header {
+        return false;
+    }
+    let meta_buf = addr as *const MetaBuffer;
+    // SAFETY: the header is within the datagram
+    let (num_sge, value_len) = unsafe {
+        (
+            ptr::addr_of!((*meta_buf).num_sge).read_unaligned() as usize,
+            ptr::addr_of!((*meta_buf).value_len).read_unaligned() as usize,
+        )
+    };
+    let lens_len = num_sge.saturating_mul(mem::size_of::<u32>());
+    if header.saturating_add(lens_len).saturating_add(value_len) != len {
+        return false;
+    }
+    // SAFETY: the lengths are within the datagram, as checked above
+    let lens = unsafe { ptr::addr_of!((*meta_buf).length_delimited).cast::<u32>() };
+    let total = (0..num_sge)
+        .map(|i| unsafe { lens.add(i).read_unaligned() } as usize)
+        .sum::<usize>();
+    total == value_len
+}
+
+impl AsHandle for DatagramEndpoint {
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        self.pre_id.as_handle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: usize = mem::size_of::<MessageMeta>() + 2 * mem::size_of::<u32>();
+
+    fn hello(qpn: u32, conn_id: u64) -> Hello {
+        Hello {
+            gid: [0xfe; 16],
+            qpn,
+            qkey: UDP_QKEY,
+            conn_id: Handle::new(HandleNamespace::Datagram, conn_id),
+            features: 0b101,
+        }
+    }
+
+    // a datagram with a fused message of `lens`, and the length of the message
+    fn fused(lens: &[u32]) -> (Box<MetaBuffer>, usize) {
+        // SAFETY: all zeros is a valid MetaBuffer
+        let mut meta_buf: Box<MetaBuffer> = Box::new(unsafe { mem::zeroed() });
+        meta_buf.num_sge = lens.len() as u32;
+        meta_buf.value_len = lens.iter().sum();
+        for (i, len) in lens.iter().enumerate() {
+            let at = i * mem::size_of::<u32>();
+            meta_buf.length_delimited[at..at + 4].copy_from_slice(&len.to_ne_bytes());
+        }
+        let len = HEADER + mem::size_of_val(lens) + meta_buf.value_len as usize;
+        (meta_buf, len)
+    }
+
+    fn addr(meta_buf: &MetaBuffer) -> usize {
+        meta_buf as *const MetaBuffer as usize
+    }
+
+    #[test]
+    fn hello_round_trip() {
+        let hello = hello(7, 3);
+        let buf = bincode::serialize(&hello).unwrap();
+        assert!(buf.len() <= MAX_HELLO_LEN);
+        assert_eq!(bincode::deserialize::<Hello>(&buf).unwrap(), hello);
+        assert!(bincode::deserialize::<Hello>(&buf[..buf.len() - 1]).is_err());
+        assert!(bincode::deserialize::<Hello>(&[]).is_err());
+    }
+
+    #[test]
+    fn fused_message() {
+        let (meta_buf, len) = fused(&[3, 0, 5]);
+        assert!(is_fused_message(addr(&meta_buf), len));
+        let (meta_buf, len) = fused(&[]);
+        assert_eq!(len, HEADER);
+        assert!(is_fused_message(addr(&meta_buf), len));
+    }
+
+    #[test]
+    fn malformed_datagram() {
+        let (mut meta_buf, len) = fused(&[3, 5]);
+        let ptr = addr(&meta_buf);
+        // truncated, padded, or shorter than the header
+        assert!(!is_fused_message(ptr, len - 1));
+        assert!(!is_fused_message(ptr, len + 1));
+        assert!(!is_fused_message(ptr, HEADER - 1));
+        assert!(!is_fused_message(ptr, 0));
+        // the lengths do not add up to the body
+        meta_buf.length_delimited[0] = 4;
+        assert!(!is_fused_message(ptr, len));
+        meta_buf.length_delimited[0] = 3;
+        meta_buf.value_len += 1;
+        assert!(!is_fused_message(ptr, len + 1));
+        // more lengths than the datagram holds
+        meta_buf.value_len -= 1;
+        meta_buf.num_sge = u32::MAX;
+        assert!(!is_fused_message(ptr, len));
+    }
+
+    #[test]
+    fn conn_ids_are_unique() {
+        let a = new_conn_id();
+        let b = new_conn_id();
+        assert_ne!(a, b);
+        assert_eq!(a.namespace(), Some(HandleNamespace::Datagram));
+    }
+
+    #[test]
+    fn accepted_peer() {
+        let mut accepted = Accepted::default();
+        let from: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let remote = hello(7, 3);
+        let conn_id = Handle::new(HandleNamespace::Datagram, 42);
+        assert_eq!(accepted.receive(&remote), Received::New);
+
+        accepted.insert(conn_id, &remote, from);
+        // the application has not mapped the buffers yet
+        assert_eq!(accepted.receive(&remote), Received::Pending);
+
+        let reply = hello(9, 42);
+        assert_eq!(accepted.reply(&conn_id, reply), Some(from));
+        assert_eq!(accepted.receive(&remote), Received::Answer(reply));
+        // answered once, then only when the peer sends its hello again
+        assert_eq!(accepted.reply(&conn_id, reply), None);
+        assert_eq!(accepted.receive(&remote), Received::Answer(reply));
+    }
+
+    #[test]
+    fn peers_told_apart() {
+        let mut accepted = Accepted::default();
+        let from: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let remote = hello(7, 3);
+        accepted.insert(Handle::new(HandleNamespace::Datagram, 42), &remote, from);
+
+        // another connection of the same QP, or the same ID from another QP or port
+        assert_eq!(accepted.receive(&hello(7, 4)), Received::New);
+        assert_eq!(accepted.receive(&hello(8, 3)), Received::New);
+        let other_port = Hello {
+            gid: [0xfd; 16],
+            ..remote
+        };
+        assert_eq!(accepted.receive(&other_port), Received::New);
+        // a connection made by this end is never answered
+        let dialed = Handle::new(HandleNamespace::Datagram, 43);
+        assert_eq!(accepted.reply(&dialed, hello(9, 43)), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! MRs over the device memory regions.
+//!
+//! The payload of a message may be in GPU memory that the application allocated with salloc (see
+//! `phoenix_salloc::device`). An MR is registered over such a region the first time a message is
+//! sent from it, and kept for the next messages. The MR holds a reference to the region, which
+//! keeps the memory allocated until the MR is deregistered. The MRs of the regions that the app
+//! has freed are deregistered at the next send, so an MR is never used for a new region allocated
+//! at the same address.
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use mrpc_marshal::{SgE, SgList};
+use phoenix_salloc::device::DeviceRegion;
+use phoenix_salloc::state::Resource;
+
+use super::ulib;
+use super::DatapathError;
+
+struct DeviceMr {
+    // declared before `region` so that the MR is deregistered before the region is freed
+    mr: ulib::uverbs::MemoryRegion<u8>,
+    region: Arc<DeviceRegion>,
+}
+
+#[derive(Default)]
+pub(crate) struct DeviceMrs {
+    // indexed by the start address of the region
+    mrs: BTreeMap<usize, DeviceMr>,
+    // the number of the device regions freed by the app when the MRs were last checked
+    frees: usize,
+}
+
+impl DeviceMrs {
+    /// Registers MRs over the device memory regions referenced by `sglist` that have no MR yet.
+    /// Returns whether any segment is in device memory.
+    pub(crate) fn register(
+        &mut self,
+        cmid: &ulib::ucm::CmId,
+        resource: &Resource,
+        sglist: &SgList,
+    ) -> Result<bool, DatapathError> {
+        // Drop the MRs of the regions that have been freed by the app.
+        let frees = resource.device_frees();
+        if frees != self.frees {
+            self.mrs
+                .retain(|_, mr| resource.is_device_region_allocated(&mr.region));
+            self.frees = frees;
+        }
+
+        let mut on_device = false;
+        for sge in &sglist.0 {
+            let region = match resource.lookup_device_region(sge.ptr) {
+                Some(region) => region,
+                None => continue,
+            };
+            on_device = true;
+            if self
+                .mrs
+                .get(&region.addr())
+                .map_or(false, |mr| Arc::ptr_eq(&mr.region, &region))
+            {
+                continue;
+            }
+            let pd = cmid.get_pd()?;
+            let mr = pd.register_with_addr(region.addr(), region.len())?;
+            self.mrs.insert(region.addr(), DeviceMr { mr, region });
+        }
+        Ok(on_device)
+    }
+
+    /// Returns the MR and the range within the MR to post `sge` with, if `sge` is in a device
+    /// memory region.
+    #[inline]
+    pub(crate) fn select(
+        &self,
+        sge: &SgE,
+    ) -> Option<(&ulib::uverbs::MemoryRegion<u8>, Range<usize>)> {
+        let (&start, mr) = self.mrs.range(..=sge.ptr).next_back()?;
+        if sge.ptr + sge.len > start + mr.mr.len() {
+            return None;
+        }
+        let off = sge.ptr - start;
+        Some((&mr.mr, off..off + sge.len))
+    }
+}
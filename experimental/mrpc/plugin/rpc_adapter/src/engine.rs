@@ -1,49 +1,99 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::mem;
+use std::net::SocketAddr;
 use std::os::unix::prelude::{AsRawFd, RawFd};
 use std::pin::Pin;
 use std::ptr;
+use std::slice;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use futures::future::BoxFuture;
 use slab::Slab;
 
 use mrpc_marshal::{ExcavateContext, SgE, SgList};
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::net;
-use phoenix_api::rpc::{MessageMeta, RpcId, RpcMsgType, TransportStatus};
-use phoenix_api::{AsHandle, Handle};
+use phoenix_api::rpc::{
+    monotonic_ns, CallId, ConnectionState, MessageMeta, RpcId, RpcMsgType, StatusCode,
+    TransportStatus, TransportTiming,
+};
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
 use phoenix_api_mrpc::cmd;
-use phoenix_api_mrpc::cmd::{ConnectResponse, ReadHeapRegion};
-use phoenix_api_rpc_adapter::control_plane;
+use phoenix_api_mrpc::cmd::{BindOptions, ConnectResponse, ReadHeapRegion};
+use phoenix_api_rpc_adapter::control_plane::{self, ConnEventKind};
 use phoenix_mrpc::unpack::UnpackFromSgE;
 use phoenix_salloc::state::State as SallocState;
-use transport_rdma::ops::Ops;
 
+use phoenix_common::checkpoint::{ConnectionDescriptor, EngineCheckpoint};
 use phoenix_common::engine::datapath::message::{
     EngineRxMessage, EngineTxMessage, RpcMessageRx, RpcMessageTx,
 };
 use phoenix_common::engine::datapath::meta_pool::{MetaBuffer, MetaBufferPtr};
 use phoenix_common::engine::datapath::DataPathNode;
+use phoenix_common::engine::events::{EventKind, EventRecorder};
+use phoenix_common::engine::profile::{Phase, Profiler};
 use phoenix_common::engine::{future, Decompose, Engine, EngineResult, Indicator, Vertex};
 use phoenix_common::envelop::ResourceDowncast;
 use phoenix_common::impl_vertex_for_engine;
 use phoenix_common::module::{ModuleCollection, Version};
+use phoenix_common::resource::Error as ResourceError;
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 use phoenix_common::{log, tracing};
 
-use super::pool::BufferSlab;
-use super::serialization::SerializationEngine;
-use super::state::{ConnectionContext, ReqContext, State, WrContext};
+use super::bulk::{
+    self, BulkTransfers, Fetch, RemoteSegment, BULK_DONE_IMM, BULK_DONE_LEN, BULK_OFFER_IMM,
+};
+use super::checksum;
+use super::config::{
+    default_eager_copy_threshold, DatagramConfig, DispatchErrorPolicy, GatherBufferConfig,
+    KeepaliveConfig, RecvBufferConfig, SrqConfig, TxOrder, TxQueueConfig,
+};
+use super::congestion::CongestionControl;
+use super::conn_log::Tails;
+use super::datagram::{self, DatagramEndpoint};
+use super::device_mr::DeviceMrs;
+use super::gather;
+use super::migrate::{
+    Handoff, MigrateError, MigratedConnection, Migration, OrphanCq, Outgoing, Quiesce,
+};
+use super::pool::{BufferSlab, RecvBuffer};
+use super::recent_errors::RecentErrors;
+use super::recv_window::{self, CREDITS_IMM, CREDITS_LEN};
+use super::serialization::{self, DispatchError, DispatchTables, SerializationEngine};
+use super::settings::{
+    Settings, FEATURE_BULK, FEATURE_CHECKSUM, FEATURE_CREDITS, SETTINGS_IMM, SETTINGS_LEN,
+};
+use super::srq::SharedRecvQueue;
+use super::state::{
+    ConnectionContext, IncomingConnection, RecvContext, ReqContext, SharedListener,
+    StagedConnection, State, WrContext,
+};
+use super::tx_queue::TxQueue;
 use super::ulib;
+use super::ulib::uverbs::RemoteReadable;
+use super::user_mr::UserMrs;
+use super::validate;
 use super::{ControlPathError, DatapathError};
 
 pub(crate) const MAX_INLINE_DATA: usize = 128;
 
+/// The most memory of the receive buffers of a connection a listener can ask for.
+const MAX_RECV_BUFFER_BYTES: usize = 1 << 30;
+
+// Immediate values that mark keep-alive probes. Ordinary RPC messages carry 0, or their checksum.
+const KEEPALIVE_PING_IMM: u32 = 0x6b610001;
+const KEEPALIVE_PONG_IMM: u32 = 0x6b610002;
+// Keep-alive probes and settings frames are unsignaled, this wr_id can only show up in error
+// completions.
+const KEEPALIVE_WR_ID: u64 = u64::MAX;
+// Keep-alive probes carry no payload, this only serves as a valid address to post.
+static KEEPALIVE_PAYLOAD: [u8; 8] = [0; 8];
+
 thread_local! {
     /// To emulate a thread local storage (TLS). This should be called engine-local-storage (ELS).
     pub(crate) static ELS: RefCell<Option<&'static TlStorage>> = RefCell::new(None);
@@ -51,17 +101,21 @@ thread_local! {
 
 // Must be `Send`.
 pub(crate) struct TlStorage {
-    pub(crate) ops: Ops,
+    pub(crate) transport: ulib::Transport,
 }
 
 pub(crate) struct RpcAdapterEngine {
     // NOTE(cjr): The drop order here is important. objects in ulib first, objects in transport later.
     pub(crate) state: State,
     pub(crate) odp_mr: Option<ulib::uverbs::MemoryRegion<u8>>,
+    // MRs of the device memory regions that have been sent from
+    pub(crate) device_mrs: DeviceMrs,
+    // MRs of the user memory regions that have been sent from
+    pub(crate) user_mrs: UserMrs,
     pub(crate) tls: Box<TlStorage>,
 
     // shared completion queue model
-    pub(crate) local_buffer: VecDeque<RpcMessageTx>,
+    pub(crate) local_buffer: TxQueue,
 
     // the number of pending receives that are going on. this can avoid the runtime from sleeping
     pub(crate) pending_recv: usize,
@@ -73,7 +127,8 @@ pub(crate) struct RpcAdapterEngine {
     // just change Handle here to usize
     pub(crate) recv_mr_usage: FnvHashMap<RpcId, Vec<Handle>>,
 
-    pub(crate) serialization_engine: Option<SerializationEngine>,
+    /// The versions of the dispatch library
+    pub(crate) dispatch_tables: DispatchTables,
 
     pub(crate) cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Command>,
     pub(crate) cmd_tx: tokio::sync::mpsc::UnboundedSender<cmd::Completion>,
@@ -81,6 +136,8 @@ pub(crate) struct RpcAdapterEngine {
 
     pub(crate) _mode: SchedulingMode,
     pub(crate) indicator: Indicator,
+    pub(crate) profiler: Profiler,
+    pub(crate) events: EventRecorder,
 
     pub(crate) rpc_ctx: Slab<RpcId>,
 
@@ -89,6 +146,52 @@ pub(crate) struct RpcAdapterEngine {
 
     // NOTE: Hold salloc State to prevent early dropping of send heap.
     pub(crate) salloc: SallocState,
+
+    pub(crate) keepalive: KeepaliveConfig,
+    pub(crate) congestion: CongestionControl,
+    // whether to verify the checksum of the received messages, and compute it for the messages
+    // sent to the peers that verify it
+    pub(crate) checksum: bool,
+    // the watermarks of the posted receive buffers of each connection
+    pub(crate) recv_buffers: RecvBufferConfig,
+    // the settings sent to the peer of each connection
+    pub(crate) settings: Settings,
+    // the UD QP shared by the datagram connections, opened on the first one
+    pub(crate) datagram: Option<DatagramEndpoint>,
+    pub(crate) datagram_config: DatagramConfig,
+    // NOTE: The QPs in `state` must be destroyed before the shared receive queue they take their
+    // receives from.
+    pub(crate) srq: Option<SharedRecvQueue>,
+    pub(crate) srq_config: SrqConfig,
+    pub(crate) tx_stats: TxQueueStats,
+    // what to do when the dispatch library fails on a message
+    pub(crate) dispatch_policy: DispatchErrorPolicy,
+    // the connections whose messages are dropped since the dispatch library failed on one, with
+    // the status the messages are dropped with
+    pub(crate) quarantined: FnvHashMap<Handle, TransportStatus>,
+    // the shared listeners the application has bound through this engine
+    pub(crate) listeners: FnvHashSet<Handle>,
+    // the messages offered to the peers to read, and the messages being read from the peers
+    pub(crate) bulk: BulkTransfers,
+    // the largest message sent fused, unless the application sets another for the connection
+    pub(crate) eager_copy_threshold: usize,
+    // the gather buffers allocated for each connection
+    pub(crate) gather_config: GatherBufferConfig,
+    // the order of the messages in `local_buffer` of each connection
+    pub(crate) tx_queue_config: TxQueueConfig,
+    // the last errors, shown in the dumps of the engine
+    pub(crate) recent_errors: RecentErrors,
+    // the tools tailing the events of the connections
+    pub(crate) conn_tails: Tails,
+    // the connections being moved from or to this engine
+    pub(crate) migration: Migration,
+}
+
+/// Counters of the messages that go through `local_buffer`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TxQueueStats {
+    /// The messages dropped because their deadlines passed while they were queued
+    pub(crate) expired: u64,
 }
 
 impl_vertex_for_engine!(RpcAdapterEngine, node);
@@ -115,7 +218,7 @@ impl Decompose for RpcAdapterEngine {
         // then the channels must be recreated
         let engine = *self;
 
-        let mut collections = ResourceCollection::with_capacity(14);
+        let mut collections = ResourceCollection::with_capacity(16);
         tracing::trace!("dumping RpcAdapterEngine states...");
 
         let node = unsafe {
@@ -123,6 +226,14 @@ impl Decompose for RpcAdapterEngine {
             collections.insert("tls".to_string(), Box::new(ptr::read(&engine.tls)));
             collections.insert("mode".to_string(), Box::new(ptr::read(&engine._mode)));
             collections.insert("odp_mr".to_string(), Box::new(ptr::read(&engine.odp_mr)));
+            collections.insert(
+                "device_mrs".to_string(),
+                Box::new(ptr::read(&engine.device_mrs)),
+            );
+            collections.insert(
+                "user_mrs".to_string(),
+                Box::new(ptr::read(&engine.user_mrs)),
+            );
             collections.insert(
                 "local_buffer".to_string(),
                 Box::new(ptr::read(&engine.local_buffer)),
@@ -136,8 +247,8 @@ impl Decompose for RpcAdapterEngine {
                 Box::new(ptr::read(&engine.recv_mr_usage)),
             );
             collections.insert(
-                "serialization_engine".to_string(),
-                Box::new(ptr::read(&engine.serialization_engine)),
+                "dispatch_tables".to_string(),
+                Box::new(ptr::read(&engine.dispatch_tables)),
             );
             collections.insert("cmd_tx".to_string(), Box::new(ptr::read(&engine.cmd_tx)));
             collections.insert("cmd_rx".to_string(), Box::new(ptr::read(&engine.cmd_rx)));
@@ -147,6 +258,76 @@ impl Decompose for RpcAdapterEngine {
                 Box::new(ptr::read(&engine.wc_read_buffer)),
             );
             collections.insert("salloc".to_string(), Box::new(ptr::read(&engine.salloc)));
+            collections.insert(
+                "keepalive".to_string(),
+                Box::new(ptr::read(&engine.keepalive)),
+            );
+            collections.insert(
+                "congestion".to_string(),
+                Box::new(ptr::read(&engine.congestion)),
+            );
+            collections.insert(
+                "checksum".to_string(),
+                Box::new(ptr::read(&engine.checksum)),
+            );
+            collections.insert(
+                "recv_buffers".to_string(),
+                Box::new(ptr::read(&engine.recv_buffers)),
+            );
+            collections.insert(
+                "settings".to_string(),
+                Box::new(ptr::read(&engine.settings)),
+            );
+            collections.insert(
+                "datagram".to_string(),
+                Box::new(ptr::read(&engine.datagram)),
+            );
+            collections.insert(
+                "datagram_config".to_string(),
+                Box::new(ptr::read(&engine.datagram_config)),
+            );
+            collections.insert("srq".to_string(), Box::new(ptr::read(&engine.srq)));
+            collections.insert(
+                "srq_config".to_string(),
+                Box::new(ptr::read(&engine.srq_config)),
+            );
+            collections.insert(
+                "tx_stats".to_string(),
+                Box::new(ptr::read(&engine.tx_stats)),
+            );
+            collections.insert(
+                "dispatch_policy".to_string(),
+                Box::new(ptr::read(&engine.dispatch_policy)),
+            );
+            collections.insert(
+                "quarantined".to_string(),
+                Box::new(ptr::read(&engine.quarantined)),
+            );
+            collections.insert(
+                "listeners".to_string(),
+                Box::new(ptr::read(&engine.listeners)),
+            );
+            collections.insert("bulk".to_string(), Box::new(ptr::read(&engine.bulk)));
+            collections.insert(
+                "eager_copy_threshold".to_string(),
+                Box::new(ptr::read(&engine.eager_copy_threshold)),
+            );
+            collections.insert(
+                "gather_config".to_string(),
+                Box::new(ptr::read(&engine.gather_config)),
+            );
+            collections.insert(
+                "tx_queue_config".to_string(),
+                Box::new(ptr::read(&engine.tx_queue_config)),
+            );
+            collections.insert(
+                "migration".to_string(),
+                Box::new(ptr::read(&engine.migration)),
+            );
+            // the recent errors are not carried over
+            drop(ptr::read(&engine.recent_errors));
+            // the tools tail the connections of the engine, not of its successor
+            drop(ptr::read(&engine.conn_tails));
             // don't call the drop function
             ptr::read(&engine.node)
         };
@@ -187,21 +368,43 @@ impl RpcAdapterEngine {
             .unwrap()
             .downcast::<Option<ulib::uverbs::MemoryRegion<u8>>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
-        let local_buffer = *local
-            .remove("local_buffer")
+        let device_mrs = *local
+            .remove("device_mrs")
+            .unwrap()
+            .downcast::<DeviceMrs>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let user_mrs = *local
+            .remove("user_mrs")
             .unwrap()
-            .downcast::<VecDeque<RpcMessageTx>>()
+            .downcast::<UserMrs>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let local_buffer = match local.remove("local_buffer").unwrap().downcast::<TxQueue>() {
+            Ok(local_buffer) => *local_buffer,
+            // Upgraded from a version that queues the messages in a VecDeque, keep their order.
+            Err(local_buffer) => TxQueue::from(
+                *local_buffer
+                    .downcast::<VecDeque<RpcMessageTx>>()
+                    .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            ),
+        };
         let recv_mr_usage = *local
             .remove("recv_mr_usage")
             .unwrap()
             .downcast::<FnvHashMap<RpcId, Vec<Handle>>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
-        let serialization_engine = *local
-            .remove("serialization_engine")
-            .unwrap()
-            .downcast::<Option<SerializationEngine>>()
-            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let dispatch_tables = match local.remove("dispatch_tables") {
+            Some(dispatch_tables) => *dispatch_tables
+                .downcast::<DispatchTables>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that only loads the latest library.
+            None => DispatchTables::from(
+                *local
+                    .remove("serialization_engine")
+                    .unwrap()
+                    .downcast::<Option<SerializationEngine>>()
+                    .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            ),
+        };
         let cmd_tx = *local
             .remove("cmd_tx")
             .unwrap()
@@ -232,24 +435,163 @@ impl RpcAdapterEngine {
             .unwrap()
             .downcast::<SallocState>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let keepalive = *local
+            .remove("keepalive")
+            .unwrap()
+            .downcast::<KeepaliveConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let congestion = *local
+            .remove("congestion")
+            .unwrap()
+            .downcast::<CongestionControl>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let checksum = *local
+            .remove("checksum")
+            .unwrap()
+            .downcast::<bool>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let recv_buffers = *local
+            .remove("recv_buffers")
+            .unwrap()
+            .downcast::<RecvBufferConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let settings = *local
+            .remove("settings")
+            .unwrap()
+            .downcast::<Settings>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let datagram = *local
+            .remove("datagram")
+            .unwrap()
+            .downcast::<Option<DatagramEndpoint>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let datagram_config = *local
+            .remove("datagram_config")
+            .unwrap()
+            .downcast::<DatagramConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let srq = *local
+            .remove("srq")
+            .unwrap()
+            .downcast::<Option<SharedRecvQueue>>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let srq_config = *local
+            .remove("srq_config")
+            .unwrap()
+            .downcast::<SrqConfig>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let tx_stats = match local.remove("tx_stats") {
+            Some(tx_stats) => *tx_stats
+                .downcast::<TxQueueStats>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that does not count, start over.
+            None => TxQueueStats::default(),
+        };
+        // Both are missing if upgraded from a version that lets the dispatch library panic.
+        let dispatch_policy = match local.remove("dispatch_policy") {
+            Some(dispatch_policy) => *dispatch_policy
+                .downcast::<DispatchErrorPolicy>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            None => DispatchErrorPolicy::default(),
+        };
+        let quarantined = match local.remove("quarantined") {
+            Some(quarantined) => *quarantined
+                .downcast::<FnvHashMap<Handle, TransportStatus>>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            None => FnvHashMap::default(),
+        };
+        // Missing if upgraded from a version that does not unbind, the engine is still a member
+        // of the listeners it has bound.
+        let listeners = match local.remove("listeners") {
+            Some(listeners) => *listeners
+                .downcast::<FnvHashSet<Handle>>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            None => state
+                .resource()
+                .listeners_of(state.rpc_adapter_id)
+                .into_iter()
+                .collect(),
+        };
+        // Missing if upgraded from a version without bulk transfers, nothing is in progress.
+        let bulk = match local.remove("bulk") {
+            Some(bulk) => *bulk
+                .downcast::<BulkTransfers>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            None => BulkTransfers::default(),
+        };
+        // Upgraded from a version that sends fused whatever fits.
+        let eager_copy_threshold = match local.remove("eager_copy_threshold") {
+            Some(eager_copy_threshold) => *eager_copy_threshold
+                .downcast::<usize>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            None => default_eager_copy_threshold(),
+        };
+        // Upgraded from a version with four gather buffers of 64 MiB on each connection.
+        let gather_config = match local.remove("gather_config") {
+            Some(gather_config) => *gather_config
+                .downcast::<GatherBufferConfig>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            None => GatherBufferConfig {
+                count: 4,
+                size: 64 * 1024 * 1024,
+            },
+        };
+        let tx_queue_config = match local.remove("tx_queue_config") {
+            Some(tx_queue_config) => *tx_queue_config
+                .downcast::<TxQueueConfig>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that sends the messages in the order they arrive.
+            None => TxQueueConfig::default(),
+        };
+        // Upgraded from a version that does not migrate connections.
+        let migration = match local.remove("migration") {
+            Some(migration) => *migration
+                .downcast::<Migration>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            None => Migration::default(),
+        };
 
         let engine = RpcAdapterEngine {
             state,
             odp_mr,
+            device_mrs,
+            user_mrs,
             tls,
             local_buffer,
             pending_recv,
             recv_mr_usage,
-            serialization_engine,
+            dispatch_tables,
             cmd_tx,
             cmd_rx,
             node,
             _mode: mode,
             indicator: Default::default(),
+            profiler: Profiler::new(),
+            events: EventRecorder::default(),
             // TODO(cjr)
             rpc_ctx,
             wc_read_buffer,
             salloc,
+            keepalive,
+            congestion,
+            checksum,
+            recv_buffers,
+            settings,
+            datagram,
+            datagram_config,
+            srq,
+            srq_config,
+            tx_stats,
+            dispatch_policy,
+            quarantined,
+            listeners,
+            bulk,
+            eager_copy_threshold,
+            gather_config,
+            tx_queue_config,
+            recent_errors: Default::default(),
+            conn_tails: Default::default(),
+            migration,
         };
         Ok(engine)
     }
@@ -275,11 +617,50 @@ impl Engine for RpcAdapterEngine {
         Box::pin(async move { self.get_mut().mainloop().await })
     }
 
+    fn dump(self: Pin<&Self>) -> Option<String> {
+        Some(self.get_ref().dump_state())
+    }
+
+    // The queue pairs do not survive the daemon, so only the connections are saved, for the
+    // restarted daemon to tell which ones the client has lost. There is no state to resume from:
+    // the connection tables, the receive buffers and the outstanding calls all belong to the
+    // queue pairs, and the dispatch tables are loaded again when the client reattaches.
+    fn checkpoint(self: Pin<&Self>) -> Option<EngineCheckpoint> {
+        let this = self.get_ref();
+        let cmid_table = this.state.local_resource().cmid_table.inner().borrow();
+        let connections = cmid_table
+            .iter()
+            .map(|(_, conn_ctx)| {
+                let cmid = &conn_ctx.data().cmid;
+                ConnectionDescriptor {
+                    handle: cmid.inner.handle.0 .0,
+                    local: cmid.get_local_addr().ok(),
+                    peer: cmid.get_peer_addr().ok(),
+                    listener: conn_ctx.data().listener.map(|listener| listener.0),
+                }
+            })
+            .collect();
+        Some(EngineCheckpoint {
+            state: Vec::new(),
+            connections,
+        })
+    }
+
     #[inline]
     fn tracker(self: Pin<&mut Self>) -> &mut Indicator {
         &mut self.get_mut().indicator
     }
 
+    #[inline]
+    fn profiler(&self) -> Option<&Profiler> {
+        Some(&self.profiler)
+    }
+
+    #[inline]
+    fn events(&self) -> Option<&EventRecorder> {
+        Some(&self.events)
+    }
+
     #[inline]
     fn set_els(self: Pin<&mut Self>) {
         let tls = self.get_mut().tls.as_ref() as *const TlStorage;
@@ -297,6 +678,13 @@ impl Engine for RpcAdapterEngine {
 
         // TODO: send result to userland
         match request {
+            control_plane::Request::TxQueueStats => {
+                log::info!(
+                    "RpcAdapter send queue, queued={}, expired={}",
+                    self.local_buffer.len(),
+                    self.tx_stats.expired
+                );
+            }
             control_plane::Request::ListConnection => {
                 let mut connections = Vec::with_capacity(
                     self.state
@@ -327,13 +715,64 @@ impl Engine for RpcAdapterEngine {
 
                 for conn in connections {
                     log::info!(
-                        "RpcAdapter connection, CmId={:?}, local_addr={:?}, peer_addr={:?}",
+                        "RpcAdapter {} connection, CmId={:?}, local_addr={:?}, peer_addr={:?}",
+                        self.state.rpc_adapter_id,
                         conn.cmid,
                         conn.local,
                         conn.peer
                     );
                 }
             }
+            control_plane::Request::TailConnLog(conn_id, path) => {
+                log::info!("Tail the events of {:?} to {:?}", conn_id, path);
+                let conns: Vec<_> = self
+                    .state
+                    .local_resource()
+                    .cmid_table
+                    .inner()
+                    .borrow()
+                    .values()
+                    .map(|entry| entry.data())
+                    .collect();
+                let logs: Vec<_> = conns.iter().map(|conn_ctx| conn_ctx.log.lock()).collect();
+                self.conn_tails
+                    .add(conn_id, path, logs.iter().map(|log| &**log))?;
+            }
+            control_plane::Request::BufferUsage(threshold) => {
+                let pool = &self.state.resource().recv_buffer_pool;
+                for (storage, usage) in pool.usage() {
+                    log::info!(
+                        "RpcAdapter recv slab {:?}, capacity={}, in_use={}, high_water={}, lent={}",
+                        storage,
+                        usage.capacity,
+                        usage.in_use,
+                        usage.high_water,
+                        usage.lent
+                    );
+                }
+                let held = pool.held_longer_than(threshold);
+                log::info!(
+                    "RpcAdapter {} recv buffers held for {:?} or longer",
+                    held.len(),
+                    threshold
+                );
+                for buf in held {
+                    log::info!(
+                        "RpcAdapter recv buffer {:?} held by {:?} for {:?}",
+                        buf.handle,
+                        buf.conn_id,
+                        buf.held_for
+                    );
+                }
+            }
+            control_plane::Request::MigrateConnection(conn_id, to) => {
+                match self.start_migration(conn_id, to) {
+                    Ok(()) => log::info!("Migrating {:?} to RpcAdapter {}", conn_id, to),
+                    Err(e) => {
+                        log::warn!("Cannot migrate {:?} to RpcAdapter {}: {}", conn_id, to, e)
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -344,44 +783,158 @@ impl Drop for RpcAdapterEngine {
         let this = Pin::new(self);
         let desc = this.as_ref().description();
         log::debug!("{} is being dropped", desc);
-        this.get_mut().state.stop_acceptor(true);
+        let this = this.get_mut();
+        let rpc_adapter_id = this.state.rpc_adapter_id;
+        // leave the CQ to the engines the connections have moved to
+        if !this.migration.forwarding.is_empty() {
+            let orphan = OrphanCq {
+                id: rpc_adapter_id,
+                cq: this.state.take_cq(),
+                forwarding: mem::take(&mut this.migration.forwarding),
+            };
+            this.state.resource().migrations.leave_cq(orphan);
+        }
+        for orphan in this.migration.orphans.drain(..) {
+            this.state.resource().migrations.leave_cq(orphan);
+        }
+        this.state.resource().migrations.forget(rpc_adapter_id);
+        // hand the connections of the shared listeners to the engines left
+        for listener in this.listeners.drain() {
+            this.state
+                .resource()
+                .leave_listener(&listener, rpc_adapter_id);
+        }
+        this.state.stop_acceptor(true);
         log::debug!("stop acceptor bit set");
     }
 }
 
 impl RpcAdapterEngine {
+    fn dump_state(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let (version, previous) = self.dispatch_tables.versions();
+        let _ = writeln!(
+            out,
+            "dispatch library: {}, version {}, earlier versions loaded {:?}",
+            if self.dispatch_tables.latest().is_some() {
+                "loaded"
+            } else {
+                "none"
+            },
+            version,
+            previous
+        );
+        let (offered, fetching) = self.bulk.in_flight();
+        let _ = writeln!(
+            out,
+            "queued: {}, expired: {}, pending recvs: {}, recvs in use: {}, rpc contexts: {}, \
+             bulk offered: {}, bulk reading: {}",
+            self.local_buffer.len(),
+            self.tx_stats.expired,
+            self.pending_recv,
+            self.recv_mr_usage.len(),
+            self.rpc_ctx.len(),
+            offered,
+            fetching
+        );
+        let _ = writeln!(
+            out,
+            "rpc adapter: {}, migrating out: {}, taking over: {}, moved out: {}, adopted: {}, \
+             orphan cqs: {}",
+            self.state.rpc_adapter_id,
+            self.migration.outgoing.len(),
+            self.migration.incoming.len(),
+            self.migration.moved.len(),
+            self.migration.adopted.len(),
+            self.migration.orphans.len()
+        );
+
+        let table = self.state.local_resource().cmid_table.inner().borrow();
+        let mut conns: Vec<_> = table.values().map(|entry| entry.data()).collect();
+        conns.sort_by_key(|conn_ctx| conn_ctx.cmid.as_handle().0);
+        let _ = writeln!(out, "connections: {}", conns.len());
+        for conn_ctx in conns {
+            let conn_id = conn_ctx.cmid.as_handle();
+            let _ = write!(
+                out,
+                "  {:?}: {:?}, credit {}, outstanding {}, peer settings {}",
+                conn_id,
+                conn_ctx.keepalive.lock().state,
+                conn_ctx.credit.load(Ordering::Relaxed),
+                self.state
+                    .local_resource()
+                    .rx_contexts
+                    .with(&conn_id, |rx| rx.outstanding_req.len())
+                    .unwrap_or(0),
+                if conn_ctx.peer_settings.lock().is_some() {
+                    "received"
+                } else {
+                    "pending"
+                }
+            );
+            match self.quarantined.get(&conn_id) {
+                Some(status) => {
+                    let _ = writeln!(out, ", quarantined with {:?}", status);
+                }
+                None => {
+                    let _ = writeln!(out);
+                }
+            }
+        }
+
+        let _ = writeln!(out, "recent errors:");
+        let _ = write!(out, "{}", self.recent_errors);
+        out
+    }
+
     async fn mainloop(&mut self) -> EngineResult {
         loop {
             // let mut timer = crate::timer::Timer::new();
             let mut work = 0;
+            let budget = self.indicator.budget();
             // let mut work2 = 0;
             // no work: 10-100ns
             // has work: ~150-180ns each req on avg
+            let start = self.profiler.start();
             loop {
                 // check input queue, no work 10ns, otherwise 250-350ns
                 match self.check_input_queue()? {
                     Progress(0) => break,
-                    Progress(n) => work += n,
+                    Progress(n) => {
+                        work += n;
+                        if work >= budget {
+                            break;
+                        }
+                    }
                     Status::Disconnected => return Ok(()),
                 }
             }
+            self.profiler.record(Phase::InputQueue, start);
             // timer.tick();
 
             // no work: 80-130ns
             // has work: ~320ns each wc on avg
+            let start = self.profiler.start();
             loop {
                 // ibv_poll_cq, no work: 100-150ns, otherwise 400ns
                 if let Progress(n) = self.check_transport_service()? {
                     work += n;
                     // work2 += n;
-                    if n == 0 {
+                    if n == 0 || work >= budget {
                         break;
                     }
                 }
             }
+            self.profiler.record(Phase::Transport, start);
             // timer.tick();
 
+            // adjust the sending rates by the congestion notifications from the NIC
+            self.congestion.check_congestion();
+
             if fastrand::usize(..1000) < 1 {
+                let start = self.profiler.start();
                 // check input command queue, ~50ns
                 match self.check_input_cmd_queue().await? {
                     Progress(n) => work += n,
@@ -391,11 +944,38 @@ impl RpcAdapterEngine {
 
                 // TODO(cjr): check incoming connect request, ~200ns
                 self.check_incoming_connection().await?;
+                self.check_datagram_hellos()?;
                 // timer.tick();
+
+                // hand over the connections quiesced, and take over those handed to this engine
+                if let Progress(n) = self.check_migrations()? {
+                    work += n;
+                }
+                if let Progress(n) = self.announce_migrated()? {
+                    work += n;
+                }
+
+                // probe idle connections
+                if let Progress(n) = self.check_keepalive()? {
+                    work += n;
+                }
+
+                // revoke the bulk offers the peers have not read in time
+                if let Progress(n) = self.check_bulk_offers() {
+                    work += n;
+                }
+
+                // resize the posted receive buffers to the load
+                if let Progress(n) = self.check_recv_windows()? {
+                    work += n;
+                }
+                self.profiler.record(Phase::Command, start);
             }
 
-            // If there's pending receives, there will always be future work to do.
-            self.indicator.set_nwork(work + self.pending_recv);
+            // If there's pending receives or bulk transfers, there will always be future work to do.
+            self.indicator.set_nwork(
+                work + self.pending_recv + self.bulk.pending() + self.migration.pending(),
+            );
 
             // log::info!("RpcAdapter mainloop: {} {} {} {}", work - work2, work2, self.pending_recv, timer);
             future::yield_now().await;
@@ -419,54 +999,54 @@ impl RpcAdapterEngine {
         // this function is not supposed to be called concurrently.
         if self.odp_mr.is_none() {
             let pd = pre_id.get_pd().unwrap();
-            let odp_mr = self.tls.ops.create_mr_on_demand_paging(&pd.inner).unwrap();
-            self.odp_mr = Some(ulib::uverbs::MemoryRegion::<u8>::new(odp_mr).unwrap());
+            self.odp_mr = Some(pd.register_on_demand_paging().unwrap());
         }
         self.odp_mr.as_mut().unwrap()
     }
 
+    /// Returns the MR and the range within the MR to post `sge` with.
+    #[inline]
+    fn select_mr<'a>(
+        odp_mr: &'a ulib::uverbs::MemoryRegion<u8>,
+        device_mrs: &'a DeviceMrs,
+        user_mrs: &'a UserMrs,
+        sge: &SgE,
+    ) -> (&'a ulib::uverbs::MemoryRegion<u8>, std::ops::Range<usize>) {
+        if let Some(selected) = device_mrs.select(sge) {
+            return selected;
+        }
+        if let Some(selected) = user_mrs.select(sge) {
+            return selected;
+        }
+        (odp_mr, sge.ptr..sge.ptr + sge.len)
+    }
+
     #[inline]
-    fn choose_strategy(sglist: &SgList) -> RpcStrategy {
+    fn choose_strategy(
+        sglist: &SgList,
+        on_device: bool,
+        eager_copy_threshold: usize,
+    ) -> RpcStrategy {
+        // Device memory cannot be copied into the meta buffer by the CPU.
+        if on_device {
+            return RpcStrategy::Standard;
+        }
         // See if the total length can fit into a meta buffer
         let serialized_size: usize = sglist
             .0
             .iter()
             .map(|sge| mem::size_of::<u32>() + sge.len)
             .sum();
-        if serialized_size < MetaBuffer::capacity() {
+        if serialized_size < MetaBuffer::capacity() && serialized_size <= eager_copy_threshold {
             RpcStrategy::Fused
         } else {
             RpcStrategy::Standard
         }
     }
 
-    fn send_fused(
-        &mut self,
-        conn_ctx: &ConnectionContext,
-        mut meta_buf_ptr: MetaBufferPtr,
-        sglist: &SgList,
-    ) -> Result<Status, DatapathError> {
-        use ulib::uverbs::SendFlags;
-
-        let call_id = unsafe { &*meta_buf_ptr.as_meta_ptr() }.call_id;
-        let msg_type = unsafe { &*meta_buf_ptr.as_meta_ptr() }.msg_type;
-        let cmid = &conn_ctx.cmid;
-        // let ctx = RpcId::new(cmid.as_handle(), call_id).encode_u64();
-        let ctx = self.rpc_ctx.insert(RpcId::new(cmid.as_handle(), call_id));
-
-        // TODO(cjr): XXX, this credit implementation has big flaws
-        if msg_type == RpcMsgType::Request {
-            conn_ctx.credit.fetch_sub(1, Ordering::AcqRel);
-            self.pending_recv += 1;
-            conn_ctx
-                .outstanding_req
-                .lock()
-                .push_back(ReqContext { call_id, sg_len: 1 });
-        }
-
-        let off = meta_buf_ptr.0.as_ptr().expose_addr();
-        let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
-
+    /// Writes the segments of `sglist` after the meta in `meta_buf`, so the message is sent in one
+    /// piece.
+    fn write_fused(meta_buf: &mut MetaBuffer, sglist: &SgList) {
         // TODO(cjr): impl Serialize for SgList
         // Serialize the sglist
         // write the lens to MetaBuffer
@@ -487,23 +1067,65 @@ impl RpcAdapterEngine {
 
         // write the values to MetaBuffer
         meta_buf.value_len = value_len as u32;
+    }
+
+    /// Takes the credits of a request sent on the connection, and waits for its reply, which
+    /// takes `sg_len` receive buffers of the peer.
+    fn track_request(&mut self, conn_ctx: &ConnectionContext, call_id: CallId, sg_len: usize) {
+        conn_ctx.credit.fetch_sub(sg_len as isize, Ordering::AcqRel);
+        let req_ctx = ReqContext {
+            call_id,
+            sg_len,
+            sent_at: Self::timestamp(conn_ctx),
+            issued_at: self.events.timestamp(),
+        };
+        let tracked = self
+            .state
+            .local_resource()
+            .rx_contexts
+            .with(&conn_ctx.cmid.as_handle(), |rx| {
+                rx.outstanding_req.push_back(req_ctx)
+            });
+        // no reply arrives on a connection torn down
+        if tracked.is_some() {
+            self.pending_recv += sg_len;
+        }
+    }
+
+    fn send_fused(
+        &mut self,
+        conn_ctx: &ConnectionContext,
+        mut meta_buf_ptr: MetaBufferPtr,
+        sglist: &SgList,
+        imm: u32,
+    ) -> Result<Status, DatapathError> {
+        let call_id = unsafe { &*meta_buf_ptr.as_meta_ptr() }.call_id;
+        let msg_type = unsafe { &*meta_buf_ptr.as_meta_ptr() }.msg_type;
+        let cmid = &conn_ctx.cmid;
+        // let ctx = RpcId::new(cmid.as_handle(), call_id).encode_u64();
+        let ctx = self.rpc_ctx.insert(RpcId::new(cmid.as_handle(), call_id));
+
+        // TODO(cjr): XXX, this credit implementation has big flaws
+        if msg_type == RpcMsgType::Request {
+            self.track_request(conn_ctx, call_id, 1);
+        }
+
+        let off = meta_buf_ptr.0.as_ptr().expose_addr();
+        let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
+        Self::write_fused(meta_buf, sglist);
 
         let odp_mr = self.odp_mr.as_mut().unwrap();
 
         // post send with imm
         // tracing::trace!("send_fused, meta_buf={:?}, post_len: {}", meta_buf, meta_buf.len());
-        let send_flags = if meta_buf.len() <= MAX_INLINE_DATA {
-            SendFlags::INLINE
-        } else {
-            SendFlags::empty()
-        };
+        let send_flags = conn_ctx.send_flags(meta_buf.len(), true, true);
         unsafe {
             cmid.post_send_with_imm(
                 odp_mr,
                 off..off + meta_buf.len(),
                 ctx as u64,
-                send_flags | SendFlags::SIGNALED,
-                0,
+                send_flags,
+                imm,
             )?;
         }
 
@@ -513,68 +1135,103 @@ impl RpcAdapterEngine {
     fn send_standard(
         &mut self,
         conn_ctx: &ConnectionContext,
-        meta_ref: &MessageMeta,
+        mut meta_buf_ptr: MetaBufferPtr,
         sglist: &SgList,
+        imm: u32,
+        fragment_size: usize,
     ) -> Result<Status, DatapathError> {
-        use ulib::uverbs::SendFlags;
-
-        let call_id = meta_ref.call_id;
+        let call_id = unsafe { &*meta_buf_ptr.as_meta_ptr() }.call_id;
+        let msg_type = unsafe { &*meta_buf_ptr.as_meta_ptr() }.msg_type;
         let cmid = &conn_ctx.cmid;
 
+        // Segments larger than a receive buffer of the peer are sent in fragments, described by
+        // the fragment table sent along with the meta.
+        let mut meta_len = mem::size_of::<MessageMeta>();
+        let mut fragmented = false;
+        if gather::is_fragmented(sglist, fragment_size) {
+            let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
+            match gather::write_fragment_table(meta_buf, sglist) {
+                Some(len) => {
+                    meta_len = len;
+                    fragmented = true;
+                }
+                None => log::error!(
+                    "fragment table of {} segments does not fit in the meta buffer",
+                    sglist.0.len()
+                ),
+            }
+        }
+        let num_sends = if fragmented {
+            1 + sglist
+                .0
+                .iter()
+                .map(|sge| gather::num_fragments(sge.len, fragment_size))
+                .sum::<usize>()
+        } else {
+            1 + sglist.0.len()
+        };
+
         // TODO(cjr): XXX, this credit implementation has some issues
-        if meta_ref.msg_type == RpcMsgType::Request {
-            conn_ctx
-                .credit
-                .fetch_sub(sglist.0.len() + 1, Ordering::AcqRel);
-            self.pending_recv += sglist.0.len() + 1;
-            conn_ctx.outstanding_req.lock().push_back(ReqContext {
-                call_id,
-                sg_len: sglist.0.len() + 1,
-            });
+        if msg_type == RpcMsgType::Request {
+            self.track_request(conn_ctx, call_id, num_sends);
         }
 
         // Sender posts send requests from the SgList
         // let ctx = RpcId::new(cmid.as_handle(), call_id).encode_u64();
         let ctx = self.rpc_ctx.insert(RpcId::new(cmid.as_handle(), call_id));
+        // the user memory must stay registered until the last send completes
+        self.user_mrs.pin(ctx, sglist);
 
         let meta_sge = SgE {
-            ptr: (meta_ref as *const MessageMeta).expose_addr(),
-            len: mem::size_of::<MessageMeta>(),
+            ptr: meta_buf_ptr.0.as_ptr().expose_addr(),
+            len: meta_len,
         };
 
         // TODO(cjr): credit handle logic for response
-        let odp_mr = self.odp_mr.as_mut().unwrap();
+        let odp_mr = self.odp_mr.as_ref().unwrap();
         // timer.tick();
 
-        // post send message meta
+        // post send message meta, only the last send of the message is signaled
+        let send_flags = conn_ctx.send_flags(meta_sge.len, true, false);
         unsafe {
             cmid.post_send(
                 odp_mr,
                 meta_sge.ptr..meta_sge.ptr + meta_sge.len,
                 ctx as u64,
-                SendFlags::SIGNALED,
+                send_flags,
             )?;
         }
 
         // post the remaining data
-        for (i, &sge) in sglist.0.iter().enumerate() {
-            let off = sge.ptr;
-            if i + 1 < sglist.0.len() {
-                // post send
-                unsafe {
-                    cmid.post_send(odp_mr, off..off + sge.len, ctx as u64, SendFlags::SIGNALED)?;
+        let mut posted = 1;
+        for sge in sglist.0.iter() {
+            let (mr, range) = Self::select_mr(odp_mr, &self.device_mrs, &self.user_mrs, sge);
+            let mut start = range.start;
+            loop {
+                let end = if fragmented {
+                    range.end.min(start + fragment_size)
+                } else {
+                    range.end
+                };
+                posted += 1;
+                // the CPU copies the inline data, which must not be on a device
+                let inline = ptr::eq(mr, odp_mr);
+                let send_flags = conn_ctx.send_flags(end - start, inline, posted == num_sends);
+                if posted < num_sends {
+                    // post send
+                    unsafe {
+                        cmid.post_send(mr, start..end, ctx as u64, send_flags)?;
+                    }
+                } else {
+                    // post send with imm
+                    tracing::trace!("post_send_imm, len={}", end - start);
+                    unsafe {
+                        cmid.post_send_with_imm(mr, start..end, ctx as u64, send_flags, imm)?;
+                    }
                 }
-            } else {
-                // post send with imm
-                tracing::trace!("post_send_imm, len={}", sge.len);
-                unsafe {
-                    cmid.post_send_with_imm(
-                        odp_mr,
-                        off..off + sge.len,
-                        ctx as u64,
-                        SendFlags::SIGNALED,
-                        0,
-                    )?;
+                start = end;
+                if start >= range.end {
+                    break;
                 }
             }
         }
@@ -582,24 +1239,219 @@ impl RpcAdapterEngine {
         Ok(Progress(1))
     }
 
-    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
-        use phoenix_common::engine::datapath::TryRecvError;
+    /// Registers each segment of `sglist` in a region that only the peer of `cmid` may read, and
+    /// only with RDMA READs. Returns the segments to offer the peer, and the regions, which revoke
+    /// the access of the peer when dropped.
+    fn expose_segments(
+        cmid: &ulib::ucm::CmId,
+        sglist: &SgList,
+    ) -> Result<(Vec<RemoteSegment>, Vec<RemoteReadable>), DatapathError> {
+        let pd = cmid.get_pd()?;
+        let mut segments = Vec::with_capacity(sglist.0.len());
+        let mut regions = Vec::with_capacity(sglist.0.len());
+        for sge in &sglist.0 {
+            // the peer does not read empty segments
+            if sge.len == 0 {
+                segments.push(RemoteSegment {
+                    addr: sge.ptr as u64,
+                    len: 0,
+                    rkey: 0,
+                });
+                continue;
+            }
+            let region = pd.register_remote_readable(sge.ptr, sge.len)?;
+            let rkey = region.remote_key();
+            segments.push(RemoteSegment {
+                addr: rkey.addr,
+                len: sge.len as u32,
+                rkey: rkey.rkey,
+            });
+            regions.push(region);
+        }
+        Ok((segments, regions))
+    }
 
-        match self.tx_inputs()[0].try_recv() {
-            Ok(msg) => {
-                match msg {
-                    EngineTxMessage::RpcMessage(msg) => self.local_buffer.push_back(msg),
-                    EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
-                        // let mut timer = crate::timer::Timer::new();
-                        let conn_ctx = self.state.local_resource().cmid_table.get(&conn_id)?;
-                        let cmid = &conn_ctx.cmid;
-                        // timer.tick();
+    /// Offers a message to the peer, which reads the segments with RDMA READs from `regions`, see
+    /// `bulk`. The message is acknowledged when the peer tells it has read them.
+    fn send_bulk(
+        &mut self,
+        conn_ctx: &ConnectionContext,
+        mut meta_buf_ptr: MetaBufferPtr,
+        segments: &[RemoteSegment],
+        regions: Vec<RemoteReadable>,
+    ) -> Result<Status, DatapathError> {
+        let call_id = unsafe { &*meta_buf_ptr.as_meta_ptr() }.call_id;
+        let msg_type = unsafe { &*meta_buf_ptr.as_meta_ptr() }.msg_type;
+        let cmid = &conn_ctx.cmid;
+        let rpc_id = RpcId::new(cmid.as_handle(), call_id);
 
-                        // TODO(cjr): only handle the first element, fix it later
-                        for call_id in &call_ids[..1] {
-                            let recv_buffer_handles = self
-                                .recv_mr_usage
-                                .remove(&RpcId(conn_id, *call_id))
+        // the offer takes a single receive buffer of the peer
+        if msg_type == RpcMsgType::Request {
+            self.track_request(conn_ctx, call_id, 1);
+        }
+
+        let odp_mr = self.odp_mr.as_ref().unwrap();
+        let off = meta_buf_ptr.0.as_ptr().expose_addr();
+        let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
+        let post_len = bulk::write_offer(meta_buf, segments);
+
+        let ctx = self.rpc_ctx.insert(rpc_id);
+        self.bulk.offer(rpc_id, regions);
+        let send_flags = conn_ctx.send_flags(post_len, true, true);
+        unsafe {
+            cmid.post_send_with_imm(
+                odp_mr,
+                off..off + post_len,
+                ctx as u64,
+                send_flags,
+                BULK_OFFER_IMM,
+            )?;
+        }
+
+        Ok(Progress(1))
+    }
+
+    /// Sends a message of a datagram connection in a single datagram.
+    fn send_datagram(&mut self, msg: RpcMessageTx) -> Result<Status, DatapathError> {
+        use ulib::uverbs::SendFlags;
+
+        let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+        let conn_id = meta_ref.conn_id;
+        let rpc_id = RpcId(conn_id, meta_ref.call_id);
+
+        let endpoint = self.datagram.as_ref().ok_or(ResourceError::NotFound)?;
+        let peer = endpoint.peer(&conn_id).ok_or(ResourceError::NotFound)?;
+        let (remote_conn_id, peer_features) = (peer.remote_conn_id, peer.features);
+        let mtu = endpoint.mtu();
+        if !endpoint.can_send() {
+            self.local_buffer.push_front(msg);
+            return Ok(Progress(0));
+        }
+
+        let sglist = if meta_ref.status_code != StatusCode::Success {
+            // a reply carrying only its status, e.g., to an unimplemented method
+            SgList(Vec::new())
+        } else {
+            match validate::marshal(
+                self.dispatch_tables.get(msg.dispatch_version),
+                meta_ref,
+                msg.addr_backend,
+                self.salloc.resource(),
+                &self.state.resource().recv_buffer_pool,
+            ) {
+                Ok(sglist) => sglist,
+                Err(e) => return self.marshal_failed(rpc_id, e),
+            }
+        };
+
+        // the message is laid out as a fused one, see `write_fused`
+        let len = mem::size_of::<MessageMeta>()
+            + 2 * mem::size_of::<u32>()
+            + sglist
+                .0
+                .iter()
+                .map(|sge| mem::size_of::<u32>() + sge.len)
+                .sum::<usize>();
+        let resource = self.salloc.resource();
+        let on_device = sglist
+            .0
+            .iter()
+            .any(|sge| resource.lookup_device_region(sge.ptr).is_some());
+        if len > mtu || on_device {
+            log::error!(
+                "Message of {} bytes does not fit in a datagram of {:?}, mtu: {}",
+                len,
+                conn_id,
+                mtu
+            );
+            self.rx_outputs()[0]
+                .send(EngineRxMessage::Ack(
+                    rpc_id,
+                    TransportStatus::MESSAGE_TOO_LARGE,
+                ))
+                .unwrap();
+            return Ok(Progress(1));
+        }
+        if !self.congestion.admit(conn_id, meta_ref.service_id, len) {
+            // paced, try again later
+            self.local_buffer.push_front(msg);
+            return Ok(Progress(0));
+        }
+        self.events.record(EventKind::Sent, len as u64);
+
+        let imm = if self.settings.features & peer_features & FEATURE_CHECKSUM != 0 {
+            // SAFETY: the SgList points to the send heap
+            unsafe { checksum::crc32c(&sglist.0) }
+        } else {
+            0
+        };
+
+        let mut meta_buf_ptr = msg.meta_buf_ptr;
+        let off = meta_buf_ptr.0.as_ptr().expose_addr();
+        let meta_buf = unsafe { meta_buf_ptr.0.as_mut() };
+        Self::write_fused(meta_buf, &sglist);
+        // the peer tells the connections apart by their IDs at its end
+        meta_buf.meta.conn_id = remote_conn_id;
+        let post_len = meta_buf.len();
+
+        let ctx = self.rpc_ctx.insert(rpc_id);
+        let send_flags = if post_len <= MAX_INLINE_DATA {
+            SendFlags::INLINE
+        } else {
+            SendFlags::empty()
+        };
+        let odp_mr = self.odp_mr.as_ref().unwrap();
+        self.datagram.as_mut().unwrap().post_send(
+            &conn_id,
+            odp_mr,
+            off..off + post_len,
+            ctx as u64,
+            send_flags,
+            imm,
+        )?;
+        Ok(Progress(1))
+    }
+
+    fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
+        use phoenix_common::engine::datapath::TryRecvError;
+
+        match self.tx_inputs()[0].try_recv() {
+            Ok(msg) => {
+                match msg {
+                    EngineTxMessage::RpcMessage(msg) => {
+                        let order = self.tx_order(&msg);
+                        self.local_buffer.push_back(msg, order);
+                    }
+                    EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids)
+                        if conn_id.namespace() == Some(HandleNamespace::Datagram) =>
+                    {
+                        let recv_buffer_handles = self
+                            .recv_mr_usage
+                            .remove(&RpcId(conn_id, call_ids[0]))
+                            .expect("invalid WR identifier");
+                        self.reclaim_datagram_buffers(&conn_id, &recv_buffer_handles)?;
+                    }
+                    EngineTxMessage::ReclaimRecvBuf(conn_id, _)
+                        if self.migration.moved.contains_key(&conn_id) =>
+                    {
+                        // a connection is handed over only when the application holds none of
+                        // its buffers
+                        log::warn!(
+                            "{:?} reclaims recv buffers after it has moved, skipped",
+                            conn_id
+                        );
+                    }
+                    EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
+                        // let mut timer = crate::timer::Timer::new();
+                        let conn_ctx = self.state.local_resource().cmid_table.get(&conn_id)?;
+                        let cmid = &conn_ctx.cmid;
+                        // timer.tick();
+
+                        // TODO(cjr): only handle the first element, fix it later
+                        for call_id in &call_ids[..1] {
+                            let recv_buffer_handles = self
+                                .recv_mr_usage
+                                .remove(&RpcId(conn_id, *call_id))
                                 .expect("invalid WR identifier");
                             self.reclaim_recv_buffers(cmid, &recv_buffer_handles)?;
                         }
@@ -614,31 +1466,163 @@ impl RpcAdapterEngine {
         }
 
         if let Some(msg) = self.local_buffer.pop_front() {
+            // the message may have waited for credits or pacing
+            if msg.deadline != 0 && monotonic_ns() > msg.deadline {
+                return self.drop_expired(msg);
+            }
             // SAFETY: don't know what kind of UB can be triggered
             let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
             let cmid_handle = meta_ref.conn_id;
+            if let Some(&status) = self.quarantined.get(&cmid_handle) {
+                let rpc_id = RpcId(cmid_handle, meta_ref.call_id);
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::Ack(rpc_id, status))
+                    .unwrap();
+                return Ok(Progress(1));
+            }
+            if self.migration.moved.contains_key(&cmid_handle) {
+                // the application has not seen the connection move yet
+                let rpc_id = RpcId(cmid_handle, meta_ref.call_id);
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::Ack(rpc_id, TransportStatus::MIGRATED))
+                    .unwrap();
+                return Ok(Progress(1));
+            }
+            if cmid_handle.namespace() == Some(HandleNamespace::Datagram) {
+                return self.send_datagram(msg);
+            }
 
             // get cmid from conn_id
             let conn_ctx = self.state.local_resource().cmid_table.get(&cmid_handle)?;
 
-            if conn_ctx.credit.load(Ordering::Acquire) <= 5 {
+            let peer_settings = match *conn_ctx.peer_settings.lock() {
+                Some(peer_settings) => peer_settings,
+                None => {
+                    // the settings of the peer have not arrived
+                    self.local_buffer.push_front(msg);
+                    return Ok(Progress(0));
+                }
+            };
+
+            let credit = conn_ctx.credit.load(Ordering::Acquire);
+            if credit <= 5 {
                 // some random number for now TODO(cjr): update this
+                if !conn_ctx.credit_stalled.swap(true, Ordering::Relaxed) {
+                    self.conn_tails.record(
+                        &mut conn_ctx.log.lock(),
+                        cmid_handle,
+                        ConnEventKind::CreditStall(credit.max(0) as usize),
+                    );
+                }
                 self.local_buffer.push_front(msg);
                 return Ok(Progress(0));
             }
+            if conn_ctx.credit_stalled.swap(false, Ordering::Relaxed) {
+                self.conn_tails.record(
+                    &mut conn_ctx.log.lock(),
+                    cmid_handle,
+                    ConnEventKind::CreditResumed,
+                );
+            }
             // let mut timer = crate::timer::Timer::new();
 
-            let sglist = if let Some(ref module) = self.serialization_engine {
-                module.marshal(meta_ref, msg.addr_backend).unwrap()
+            let sglist = if meta_ref.status_code != StatusCode::Success {
+                // a reply carrying only its status, e.g., to an unimplemented method
+                SgList(Vec::new())
             } else {
-                panic!("dispatch module not loaded");
+                match validate::marshal(
+                    self.dispatch_tables.get(msg.dispatch_version),
+                    meta_ref,
+                    msg.addr_backend,
+                    self.salloc.resource(),
+                    &self.state.resource().recv_buffer_pool,
+                ) {
+                    Ok(sglist) => sglist,
+                    Err(e) => {
+                        let rpc_id = RpcId(cmid_handle, meta_ref.call_id);
+                        return self.marshal_failed(rpc_id, e);
+                    }
+                }
             };
             // timer.tick();
 
-            // TODO(cjr): Examine the SgList and optimize for small messages
-            let status = match Self::choose_strategy(&sglist) {
-                RpcStrategy::Fused => self.send_fused(&conn_ctx, msg.meta_buf_ptr, &sglist)?,
-                RpcStrategy::Standard => self.send_standard(&conn_ctx, meta_ref, &sglist)?,
+            let len =
+                mem::size_of::<MessageMeta>() + sglist.0.iter().map(|sge| sge.len).sum::<usize>();
+            if len as u64 > peer_settings.max_message_size {
+                log::error!(
+                    "Message of {} bytes exceeds the limit of the peer of {:?}: {}",
+                    len,
+                    cmid_handle,
+                    peer_settings.max_message_size
+                );
+                let rpc_id = RpcId(cmid_handle, meta_ref.call_id);
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::Ack(
+                        rpc_id,
+                        TransportStatus::MESSAGE_TOO_LARGE,
+                    ))
+                    .unwrap();
+                return Ok(Progress(1));
+            }
+            if !self.congestion.admit(cmid_handle, meta_ref.service_id, len) {
+                // paced, try again later
+                self.local_buffer.push_front(msg);
+                return Ok(Progress(0));
+            }
+            self.events.record(EventKind::Sent, len as u64);
+
+            let on_device =
+                self.device_mrs
+                    .register(&conn_ctx.cmid, self.salloc.resource(), &sglist)?;
+            self.user_mrs
+                .register(&conn_ctx.cmid, self.salloc.resource(), &sglist)?;
+
+            // The user memory regions are not registered for remote access.
+            if msg.bulk
+                && self.settings.negotiated(&peer_settings, FEATURE_BULK)
+                && bulk::fits(&sglist, self.gather_config.size)
+                && !sglist
+                    .0
+                    .iter()
+                    .any(|sge| self.user_mrs.select(sge).is_some())
+            {
+                match Self::expose_segments(&conn_ctx.cmid, &sglist) {
+                    Ok((segments, regions)) => {
+                        return self.send_bulk(&conn_ctx, msg.meta_buf_ptr, &segments, regions);
+                    }
+                    Err(e) => {
+                        // e.g., the pages cannot be pinned, send it the standard way instead
+                        log::warn!(
+                            "Failed to register {:?} for the peer to read: {}",
+                            RpcId(cmid_handle, meta_ref.call_id),
+                            e
+                        );
+                    }
+                }
+            }
+
+            // Device memory cannot be read by the CPU, such messages go without a checksum.
+            let with_checksum = self.settings.negotiated(&peer_settings, FEATURE_CHECKSUM);
+            let imm = if with_checksum && !on_device {
+                // SAFETY: the SgList points to the send heap
+                unsafe { checksum::crc32c(&sglist.0) }
+            } else {
+                0
+            };
+
+            let eager_copy_threshold = conn_ctx
+                .eager_copy_threshold
+                .lock()
+                .unwrap_or(self.eager_copy_threshold);
+            let status = match Self::choose_strategy(&sglist, on_device, eager_copy_threshold) {
+                RpcStrategy::Fused => self.send_fused(&conn_ctx, msg.meta_buf_ptr, &sglist, imm)?,
+                RpcStrategy::Standard => self.send_standard(
+                    &conn_ctx,
+                    msg.meta_buf_ptr,
+                    &sglist,
+                    imm,
+                    peer_settings.recv_buffer_size as usize,
+                )?,
             };
 
             // timer.tick();
@@ -649,6 +1633,90 @@ impl RpcAdapterEngine {
         Ok(Progress(0))
     }
 
+    /// Returns the order of the connection of a message in `local_buffer`.
+    fn tx_order(&self, msg: &RpcMessageTx) -> TxOrder {
+        if self.tx_queue_config.is_fifo() {
+            return TxOrder::Fifo;
+        }
+        // SAFETY: the meta buffer is held until the message is acknowledged
+        let conn_id = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() }.conn_id;
+        match self.state.local_resource().cmid_table.get(&conn_id) {
+            Ok(conn_ctx) => conn_ctx.tx_order,
+            Err(_) => self.tx_queue_config.order,
+        }
+    }
+
+    /// Drops a message whose deadline has passed before it is sent, and fails it with
+    /// `DEADLINE_EXCEEDED`.
+    fn drop_expired(&mut self, msg: RpcMessageTx) -> Result<Status, DatapathError> {
+        // SAFETY: the meta buffer is held until the message is acknowledged
+        let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+        let rpc_id = RpcId(meta_ref.conn_id, meta_ref.call_id);
+        log::debug!("Drop {:?}, its deadline passed in the send queue", rpc_id);
+        self.tx_stats.expired += 1;
+        self.events.record(EventKind::Dropped, 0);
+        self.rx_outputs()[0]
+            .send(EngineRxMessage::Ack(
+                rpc_id,
+                TransportStatus::DEADLINE_EXCEEDED,
+            ))
+            .unwrap();
+        Ok(Progress(1))
+    }
+
+    /// Drops a message the dispatch library fails to marshal, and completes it with the error.
+    fn marshal_failed(&mut self, rpc_id: RpcId, e: DispatchError) -> Result<Status, DatapathError> {
+        self.rx_outputs()[0]
+            .send(EngineRxMessage::Ack(rpc_id, e.status()))
+            .unwrap();
+        self.dispatch_failed(rpc_id.0, e)?;
+        Ok(Progress(1))
+    }
+
+    /// Applies the `on_dispatch_error` policy to the connection of a message the dispatch library
+    /// fails on. The message has been dropped.
+    fn dispatch_failed(&mut self, conn_id: Handle, e: DispatchError) -> Result<(), DatapathError> {
+        log::error!("Dispatch failed on a message of {:?}: {}", conn_id, e);
+        self.recent_errors.record(format!(
+            "dispatch failed on a message of {:?}: {}",
+            conn_id, e
+        ));
+        match self.dispatch_policy {
+            DispatchErrorPolicy::Drop => {}
+            DispatchErrorPolicy::Quarantine => {
+                let status = e.status();
+                log::warn!("Quarantine connection {:?}", conn_id);
+                self.quarantined.insert(conn_id, status);
+                self.tear_down_rx(&conn_id);
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::RecvError(conn_id, status))
+                    .unwrap();
+            }
+            DispatchErrorPolicy::Fault => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Drops the states of a connection torn down from the datapath. The segments received on it
+    /// afterwards are dropped, and the requests waiting for their replies are not answered. The
+    /// buffers of a message cut off stay with the connection, as do those posted on it.
+    fn tear_down_rx(&mut self, conn_id: &Handle) {
+        if let Some(rx) = self.state.local_resource().rx_contexts.remove(conn_id) {
+            let sg_len: usize = rx.outstanding_req.iter().map(|req| req.sg_len).sum();
+            self.pending_recv -= sg_len;
+        }
+    }
+
+    /// Returns the current time if the connection has call timing enabled, or zero.
+    #[inline]
+    fn timestamp(conn_ctx: &ConnectionContext) -> u64 {
+        if conn_ctx.call_timing.load(Ordering::Relaxed) {
+            monotonic_ns()
+        } else {
+            0
+        }
+    }
+
     fn reshape_fused_sg_list(sg_list: &mut SgList) {
         use std::ptr::Unique;
 
@@ -675,208 +1743,1578 @@ impl RpcAdapterEngine {
                 len: lens[i] as usize,
             });
 
-            value_offset += lens[i] as usize;
+            value_offset += lens[i] as usize;
+        }
+
+        // tracing::trace!("reshape_fused_sg_list: sg_list: {:?}", sg_list);
+    }
+
+    /// Returns `None` if the message is dropped because its fragmented segments cannot be gathered
+    /// or it fails the checksum verification.
+    fn unmarshal_and_deliver_up(
+        &mut self,
+        recv_ctx: &mut RecvContext,
+        conn_ctx: Arc<ConnectionContext>,
+        checksum: u32,
+    ) -> Result<Option<RpcId>, DatapathError> {
+        // log::debug!("unmarshal_and_deliver_up, sgl: {:0x?}", recv_ctx.sg_list);
+
+        // let mut timer = crate::timer::Timer::new();
+
+        // the first segment carries a fragment table if the message is fragmented
+        let gathered = if recv_ctx.sg_list.0[0].len > mem::size_of::<MessageMeta>() {
+            self.state.local_resource().gather_buffers.gather_message(
+                &conn_ctx.cmid.as_handle(),
+                recv_ctx,
+                conn_ctx.settings.recv_buffer_size as usize,
+            )
+        } else {
+            Ok(Vec::new())
+        };
+        let sgl = &recv_ctx.sg_list;
+
+        // the first segment still holds the fragment table if the gathering has failed
+        let meta_sge = SgE {
+            ptr: sgl.0[0].ptr,
+            len: mem::size_of::<MessageMeta>(),
+        };
+        let meta_ptr = unsafe { MessageMeta::unpack(&meta_sge) }.unwrap();
+        let meta = unsafe { meta_ptr.as_ref() };
+        let conn_id = conn_ctx.cmid.as_handle();
+
+        let recv_id = RpcId(conn_id, meta.call_id);
+
+        // timer.tick();
+        let timing = self.replenish_credits(&conn_ctx, meta, recv_ctx.first_recv_at);
+        // timer.tick();
+
+        match gathered {
+            Ok(reposts) => self.reclaim_recv_buffers(&conn_ctx.cmid, &reposts)?,
+            Err(e) => {
+                log::error!("Failed to gather {:?}: {}", recv_id, e);
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::RecvError(
+                        recv_id.0,
+                        TransportStatus::GATHER_FAILED,
+                    ))
+                    .unwrap();
+                return Ok(None);
+            }
+        }
+
+        self.deliver_up(sgl, conn_id, checksum, timing)
+    }
+
+    /// Gives back the credits of the request a received response answers. Returns the timing of
+    /// the call if it is timed.
+    fn replenish_credits(
+        &mut self,
+        conn_ctx: &ConnectionContext,
+        meta: &MessageMeta,
+        first_recv_at: u64,
+    ) -> Option<TransportTiming> {
+        if meta.msg_type != RpcMsgType::Response {
+            return None;
+        }
+        let req_ctx = self
+            .state
+            .local_resource()
+            .rx_contexts
+            .with(&conn_ctx.cmid.as_handle(), |rx| {
+                rx.outstanding_req.pop_front()
+            })
+            .flatten()
+            .unwrap();
+        assert_eq!(meta.call_id, req_ctx.call_id);
+        conn_ctx
+            .credit
+            .fetch_add(req_ctx.sg_len as isize, Ordering::AcqRel);
+        self.pending_recv -= req_ctx.sg_len;
+        self.events
+            .record_since(EventKind::Completed, req_ctx.issued_at);
+        (req_ctx.sent_at != 0 && first_recv_at != 0).then_some(TransportTiming {
+            sent: req_ctx.sent_at,
+            first_recv: first_recv_at,
+        })
+    }
+
+    /// Verifies the checksum of a received message, and passes it to the upper engine as a
+    /// message of `conn_id`. Returns `None` if the message is dropped.
+    fn deliver_up(
+        &mut self,
+        sgl: &SgList,
+        conn_id: Handle,
+        checksum: u32,
+        timing: Option<TransportTiming>,
+    ) -> Result<Option<RpcId>, DatapathError> {
+        let mut meta_ptr = unsafe { MessageMeta::unpack(&sgl.0[0]) }.unwrap();
+        let meta = unsafe { meta_ptr.as_mut() };
+        meta.conn_id = conn_id;
+
+        let recv_id = RpcId(meta.conn_id, meta.call_id);
+
+        if self.quarantined.contains_key(&conn_id) {
+            return Ok(None);
+        }
+
+        if self.checksum && checksum != 0 {
+            // SAFETY: the SgList points to the receive buffers
+            let actual = unsafe { checksum::crc32c(&sgl.0[1..]) };
+            if actual != checksum {
+                log::error!(
+                    "Checksum mismatch for {:?}, expected: {:#x}, found: {:#x}",
+                    recv_id,
+                    checksum,
+                    actual
+                );
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::RecvError(
+                        recv_id.0,
+                        TransportStatus::CHECKSUM_MISMATCH,
+                    ))
+                    .unwrap();
+                return Ok(None);
+            }
+        }
+
+        let mut excavate_ctx = ExcavateContext {
+            sgl: sgl.0[1..].iter(),
+            addr_arbiter: &self.state.local_resource().addr_map,
+        };
+
+        let unmarshaled = if meta.status_code != StatusCode::Success {
+            Ok((0, 0))
+        } else if meta.raw() {
+            // SAFETY: the SgList points to the receive buffers
+            unsafe { serialization::unmarshal_raw(&mut excavate_ctx) }
+        } else if let Some(module) = self.dispatch_tables.latest() {
+            module.unmarshal(meta, &mut excavate_ctx)
+        } else {
+            panic!("dispatch module not loaded");
+        };
+        let (addr_app, addr_backend) = match unmarshaled {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                self.dispatch_failed(conn_id, e)?;
+                return Ok(None);
+            }
+        };
+        // timer.tick();
+
+        let msg = RpcMessageRx {
+            meta: meta_ptr,
+            addr_backend,
+            addr_app,
+        };
+
+        if self.events.is_enabled() {
+            let len = sgl.0[1..].iter().map(|sge| sge.len as u64).sum();
+            self.events.record(EventKind::Received, len);
+        }
+        if let Some(timing) = timing {
+            self.rx_outputs()[0]
+                .send(EngineRxMessage::CallTiming(recv_id, timing))
+                .unwrap();
+        }
+        self.rx_outputs()[0]
+            .send(EngineRxMessage::RpcMessage(msg))
+            .unwrap();
+
+        // timer.tick();
+        // log::info!("unmarshal_and_deliver_up {}", timer);
+
+        Ok(Some(recv_id))
+    }
+
+    fn check_transport_service(&mut self) -> Result<Status, DatapathError> {
+        // check completion, and replenish some recv requests
+        let mut progress = 0;
+        if self.migration.expects_forwarded() {
+            progress += self.check_forwarded()?;
+        }
+
+        if self.state.local_resource().cq.as_ref().is_none() {
+            return Ok(Progress(progress));
+        }
+
+        let cq = self.state.local_resource().cq.as_ref().unwrap();
+
+        // SAFETY: dp::WorkRequest is Copy and zerocopy
+        unsafe {
+            self.wc_read_buffer.set_len(0);
+        }
+
+        cq.poll(&mut self.wc_read_buffer)?;
+
+        let comps = mem::take(&mut self.wc_read_buffer);
+
+        for wc in &comps {
+            if self.migration.diverts() && self.divert_wc(wc) {
+                continue;
+            }
+            progress += self.handle_wc(wc)?;
+        }
+
+        self.wc_read_buffer = comps;
+
+        // COMMENT(cjr): Progress(0) here is okay for now because we haven't use the progress as
+        // any indicator.
+        Ok(Status::Progress(progress))
+    }
+
+    /// Handles a completion polled from the CQ of the engine, or forwarded to it. Returns the
+    /// progress made.
+    fn handle_wc(&mut self, wc: &net::WorkCompletion) -> Result<usize, DatapathError> {
+        use net::{WcFlags, WcOpcode, WcStatus};
+
+        if self
+            .datagram
+            .as_ref()
+            .map_or(false, |endpoint| endpoint.qpn() == wc.qp_num)
+        {
+            self.handle_datagram_wc(wc)?;
+            return Ok(1);
+        }
+        if self.srq.as_ref().map_or(false, |srq| srq.owns(wc.wr_id)) {
+            self.handle_srq_wc(wc)?;
+            return Ok(1);
+        }
+        let mut progress = 0;
+        match wc.status {
+            WcStatus::Success => {
+                match wc.opcode {
+                    WcOpcode::Send => {
+                        // send completed,  do nothing
+                        if wc.wc_flags.contains(WcFlags::WITH_IMM) {
+                            tracing::trace!("post_send_imm completed, wr_id={}", wc.wr_id);
+                            // let rpc_id = RpcId::decode_u64(wc.wr_id);
+                            let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
+                            self.user_mrs.unpin(wc.wr_id as usize);
+                            let status = if self.bulk.is_offered(&rpc_id) {
+                                // done only when the peer has read the message
+                                self.bulk.offer_sent(rpc_id)
+                            } else {
+                                Some(TransportStatus::Success)
+                            };
+                            if let Some(status) = status {
+                                self.rx_outputs()[0]
+                                    .send(EngineRxMessage::Ack(rpc_id, status))
+                                    .unwrap();
+                            }
+                        }
+                    }
+                    WcOpcode::RdmaRead => {
+                        self.read_completed(wc.wr_id, TransportStatus::Success)?;
+                        progress += 1;
+                    }
+                    WcOpcode::Recv => {
+                        self.handle_recv(wc)?;
+                        progress += 1;
+                    }
+                    // The below two are probably errors in impl logic, so assert them
+                    WcOpcode::Invalid => panic!("invalid wc: {:?}", wc),
+                    _ => panic!("Unhandled wc opcode: {:?}", wc),
+                }
+            }
+            WcStatus::Error(_) if wc.wr_id == KEEPALIVE_WR_ID => {
+                // the peer will be reported by check_keepalive if it is really gone
+                log::debug!("keep-alive probe failed: {:?}", wc);
+            }
+            WcStatus::Error(code) if self.bulk.owns(wc.wr_id) => {
+                log::debug!("bulk read failed: {:?}", wc);
+                self.recent_errors
+                    .record(format!("bulk read failed: {:?}", wc));
+                self.read_completed(wc.wr_id, TransportStatus::Error(code))?;
+            }
+            WcStatus::Error(code) => {
+                log::debug!("wc failed: {:?}", wc);
+                self.events.record(EventKind::Failed, 0);
+                self.recent_errors.record(format!("wc failed: {:?}", wc));
+                // TODO(cjr): bubble up the error, close the connection, and return an error
+                // to the user.
+                let error = ConnEventKind::WcError(format!("{:?} of {:?}", wc.status, wc.opcode));
+                let msg = if let Ok(wr_ctx) = self.state.local_resource().wr_contexts.get(&wc.wr_id)
+                {
+                    // this is a recv operation. don't know the rpc_id
+                    let conn_id = wr_ctx.conn_id;
+                    self.log_conn_event(conn_id, error);
+                    // the QP is in the error state
+                    self.tear_down_rx(&conn_id);
+                    EngineRxMessage::RecvError(conn_id, TransportStatus::Error(code))
+                } else {
+                    // let rpc_id = RpcId::decode_u64(wc.wr_id);
+                    let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
+                    self.user_mrs.unpin(wc.wr_id as usize);
+                    self.bulk.withdraw(&rpc_id);
+                    self.log_conn_event(rpc_id.0, error);
+                    self.tear_down_rx(&rpc_id.0);
+                    EngineRxMessage::Ack(rpc_id, TransportStatus::Error(code))
+                };
+                self.rx_outputs()[0].send(msg).unwrap_or_else(|e| {
+                    log::warn!("error when bubbling up the error, send failed e: {}", e)
+                });
+                // the error is caused by unexpected shutdown of mrpc engine
+            }
+        }
+
+        Ok(progress)
+    }
+
+    /// Handles a receive on the QP of a connection, or a segment copied out of the shared
+    /// receive queue.
+    fn handle_recv(&mut self, wc: &net::WorkCompletion) -> Result<(), DatapathError> {
+        use net::WcFlags;
+
+        // keep-alive probes carry no payload, a checksum of a message may coincide with the
+        // immediate values of the probes
+        if wc.wc_flags.contains(WcFlags::WITH_IMM)
+            && wc.byte_len == 0
+            && (wc.imm_data == KEEPALIVE_PING_IMM || wc.imm_data == KEEPALIVE_PONG_IMM)
+        {
+            self.handle_keepalive_recv(wc)?;
+            return Ok(());
+        }
+        if wc.wc_flags.contains(WcFlags::WITH_IMM)
+            && wc.imm_data == SETTINGS_IMM
+            && wc.byte_len as usize == SETTINGS_LEN
+            && self.handle_settings_recv(wc)?
+        {
+            return Ok(());
+        }
+        if wc.wc_flags.contains(WcFlags::WITH_IMM)
+            && wc.imm_data == BULK_DONE_IMM
+            && wc.byte_len as usize == BULK_DONE_LEN
+            && self.handle_bulk_done(wc)?
+        {
+            return Ok(());
+        }
+        if wc.wc_flags.contains(WcFlags::WITH_IMM)
+            && wc.imm_data == CREDITS_IMM
+            && wc.byte_len as usize == CREDITS_LEN
+            && self.handle_credits_recv(wc)?
+        {
+            return Ok(());
+        }
+        let conn_ctx = {
+            let wr_ctx = self.state.local_resource().wr_contexts.get(&wc.wr_id)?;
+            let cmid_handle = wr_ctx.conn_id;
+            self.state
+                .local_resource()
+                .recv_windows
+                .consume(&cmid_handle);
+            let conn_ctx = self.state.local_resource().cmid_table.get(&cmid_handle)?;
+            if self.keepalive.enable {
+                conn_ctx.keepalive.lock().last_recv = Instant::now();
+            }
+            // received a segment of RPC message
+            let sge = SgE {
+                ptr: wr_ctx.buffer_addr,
+                len: wc.byte_len as _, // note this byte_len is only valid for recv request
+            };
+            let now = Self::timestamp(&conn_ctx);
+            let received = self
+                .state
+                .local_resource()
+                .rx_contexts
+                .with(&cmid_handle, |rx| {
+                    let recv_ctx = &mut rx.receiving_ctx;
+                    if recv_ctx.sg_list.0.is_empty() {
+                        recv_ctx.first_recv_at = now;
+                    }
+                    recv_ctx.sg_list.0.push(sge);
+                    recv_ctx.recv_buffer_handles.push(Handle(wc.wr_id as u64));
+                });
+            if received.is_none() {
+                // the connection is torn down, the segment is dropped
+                self.reclaim_recv_buffers(&conn_ctx.cmid, &[Handle(wc.wr_id as u64)])?;
+                return Ok(());
+            }
+            conn_ctx
+        };
+
+        if wc.wc_flags.contains(WcFlags::WITH_IMM) {
+            // received an entire RPC message
+            tracing::trace!("post_recv received complete message, wr_id={}", wc.wr_id);
+            // let mut timer = crate::timer::Timer::new();
+
+            let mut recv_ctx = self
+                .state
+                .local_resource()
+                .rx_contexts
+                .take_message(&conn_ctx.cmid.as_handle());
+
+            if wc.imm_data == BULK_OFFER_IMM && recv_ctx.sg_list.0.len() == 1 {
+                // SAFETY: the segment is in a receive buffer
+                if let Some(segments) = unsafe { bulk::take_offer(&mut recv_ctx.sg_list.0[0]) } {
+                    return self.fetch_bulk(recv_ctx, &conn_ctx, segments);
+                }
+            }
+
+            // check if it is an eager message
+            if recv_ctx.sg_list.0.len() == 1 {
+                // got an eager message
+                Self::reshape_fused_sg_list(&mut recv_ctx.sg_list);
+            }
+
+            // timer.tick();
+            // 200-500ns
+            let recv_id =
+                self.unmarshal_and_deliver_up(&mut recv_ctx, Arc::clone(&conn_ctx), wc.imm_data)?;
+            // timer.tick();
+
+            match recv_id {
+                Some(recv_id) => {
+                    let local_resource = self.state.local_resource();
+                    let pool = &self.state.resource().recv_buffer_pool;
+                    for handle in recv_ctx.recv_buffer_handles.iter() {
+                        if let Ok(recv_buffer) = local_resource.recv_buffer_table.get(handle) {
+                            pool.lend(&recv_buffer, recv_id.0);
+                        }
+                    }
+                    // 60-70ns
+                    // keep them outstanding because they will be used by the user
+                    self.recv_mr_usage
+                        .insert(recv_id, recv_ctx.recv_buffer_handles);
+                }
+                None => {
+                    // the message is dropped, give the buffers back
+                    self.reclaim_recv_buffers(&conn_ctx.cmid, &recv_ctx.recv_buffer_handles)?;
+                }
+            }
+            // timer.tick();
+            // log::info!("check_transport_service: {}", timer);
+        }
+        Ok(())
+    }
+
+    /// Handles a receive from the shared receive queue.
+    fn handle_srq_wc(&mut self, wc: &net::WorkCompletion) -> Result<(), DatapathError> {
+        use net::WcStatus;
+
+        let srq = self.srq.as_mut().unwrap();
+        match wc.status {
+            WcStatus::Success => {
+                let odp_mr = self.odp_mr.as_mut().unwrap();
+                if let Some(taken) = srq.take_recv(wc, odp_mr)? {
+                    self.deliver_srq_recv(taken)?;
+                }
+            }
+            WcStatus::Error(code) => {
+                log::debug!("srq wc failed: {:?}", wc);
+                self.recent_errors
+                    .record(format!("srq wc failed: {:?}", wc));
+                if let Some(conn_id) = srq.discard_recv(wc) {
+                    self.tear_down_rx(&conn_id);
+                    self.rx_outputs()[0]
+                        .send(EngineRxMessage::RecvError(
+                            conn_id,
+                            TransportStatus::Error(code),
+                        ))
+                        .unwrap_or_else(|e| {
+                            log::warn!("error when bubbling up the error, send failed e: {}", e)
+                        });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes a segment copied out of the shared receive queue to the receive path of its
+    /// connection, as if it was received in `recv_buffer`.
+    fn deliver_srq_recv(
+        &mut self,
+        (conn_id, recv_buffer, wc): (Handle, RecvBuffer, net::WorkCompletion),
+    ) -> Result<(), DatapathError> {
+        let wr_ctx = WrContext {
+            conn_id,
+            buffer_addr: recv_buffer.addr(),
+        };
+        self.state
+            .local_resource()
+            .wr_contexts
+            .insert(wc.wr_id, wr_ctx)?;
+        self.state
+            .local_resource()
+            .recv_buffer_table
+            .insert(recv_buffer.as_handle(), recv_buffer)?;
+        self.handle_recv(&wc)
+    }
+
+    /// Gives the buffers of a connection on the shared receive queue back to it, and passes on
+    /// the segments that were waiting for them.
+    fn reclaim_srq_buffers(
+        &mut self,
+        conn_id: &Handle,
+        mr_handles: &[Handle],
+    ) -> Result<(), DatapathError> {
+        let local_resource = self.state.local_resource();
+        let srq = self.srq.as_mut().unwrap();
+        for handle in mr_handles {
+            if local_resource.gather_buffers.release(handle) {
+                continue;
+            }
+            if !local_resource.owns_recv_buffer(conn_id, handle) {
+                continue;
+            }
+            local_resource.wr_contexts.close_resource(&handle.0)?;
+            if let Some(recv_buffer) = local_resource.recv_buffer_table.close_resource(handle)? {
+                srq.release(conn_id, recv_buffer);
+            }
+        }
+        for taken in srq.resume(conn_id) {
+            self.deliver_srq_recv(taken)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a completion on the UD QP of the datagram connections.
+    fn handle_datagram_wc(&mut self, wc: &net::WorkCompletion) -> Result<(), DatapathError> {
+        use net::{WcFlags, WcOpcode, WcStatus};
+
+        let endpoint = self.datagram.as_mut().unwrap();
+        match wc.status {
+            WcStatus::Success => match wc.opcode {
+                WcOpcode::Send => {
+                    endpoint.send_completed();
+                    let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
+                    self.rx_outputs()[0]
+                        .send(EngineRxMessage::Ack(rpc_id, TransportStatus::Success))
+                        .unwrap();
+                }
+                WcOpcode::Recv => {
+                    let odp_mr = self.odp_mr.as_mut().unwrap();
+                    let (conn_id, recv_buffer, len) = match endpoint.take_recv(wc, odp_mr)? {
+                        Some(taken) => taken,
+                        None => return Ok(()),
+                    };
+                    let handle = recv_buffer.as_handle();
+                    let mut sg_list = SgList(vec![SgE {
+                        ptr: recv_buffer.addr(),
+                        len,
+                    }]);
+                    Self::reshape_fused_sg_list(&mut sg_list);
+                    self.state
+                        .local_resource()
+                        .recv_buffer_table
+                        .insert(handle, recv_buffer)?;
+
+                    let checksum = if wc.wc_flags.contains(WcFlags::WITH_IMM) {
+                        wc.imm_data
+                    } else {
+                        0
+                    };
+                    match self.deliver_up(&sg_list, conn_id, checksum, None)? {
+                        Some(recv_id) => {
+                            self.recv_mr_usage.insert(recv_id, vec![handle]);
+                        }
+                        None => self.reclaim_datagram_buffers(&conn_id, &[handle])?,
+                    }
+                }
+                _ => panic!("Unhandled wc opcode: {:?}", wc),
+            },
+            WcStatus::Error(code) => {
+                log::debug!("datagram wc failed: {:?}", wc);
+                self.recent_errors
+                    .record(format!("datagram wc failed: {:?}", wc));
+                if !endpoint.discard_recv(wc.wr_id) {
+                    endpoint.send_completed();
+                    let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
+                    self.rx_outputs()[0]
+                        .send(EngineRxMessage::Ack(rpc_id, TransportStatus::Error(code)))
+                        .unwrap_or_else(|e| {
+                            log::warn!("error when bubbling up the error, send failed e: {}", e)
+                        });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives the buffers of the messages received on a datagram connection back to it.
+    fn reclaim_datagram_buffers(
+        &mut self,
+        conn_id: &Handle,
+        mr_handles: &[Handle],
+    ) -> Result<(), DatapathError> {
+        let endpoint = self.datagram.as_ref().ok_or(ResourceError::NotFound)?;
+        for handle in mr_handles {
+            if let Some(recv_buffer) = self
+                .state
+                .local_resource()
+                .recv_buffer_table
+                .close_resource(handle)?
+            {
+                endpoint.release(conn_id, recv_buffer);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_keepalive_recv(&mut self, wc: &net::WorkCompletion) -> Result<(), DatapathError> {
+        let wr_ctx = self.state.local_resource().wr_contexts.get(&wc.wr_id)?;
+        self.state
+            .local_resource()
+            .recv_windows
+            .consume(&wr_ctx.conn_id);
+        let conn_ctx = self
+            .state
+            .local_resource()
+            .cmid_table
+            .get(&wr_ctx.conn_id)?;
+        conn_ctx.keepalive.lock().last_recv = Instant::now();
+
+        // the probe carries no payload, give the buffer back to the receive queue
+        self.reclaim_recv_buffers(&conn_ctx.cmid, &[Handle(wc.wr_id)])?;
+
+        if wc.imm_data == KEEPALIVE_PING_IMM {
+            self.post_keepalive(&conn_ctx.cmid, KEEPALIVE_PONG_IMM)?;
+        }
+        Ok(())
+    }
+
+    /// Takes the settings of the peer from the first receive of a connection. Returns `false` if
+    /// the settings of the peer have arrived before, so the receive is part of a message.
+    fn handle_settings_recv(&mut self, wc: &net::WorkCompletion) -> Result<bool, DatapathError> {
+        let wr_ctx = self.state.local_resource().wr_contexts.get(&wc.wr_id)?;
+        let conn_ctx = self
+            .state
+            .local_resource()
+            .cmid_table
+            .get(&wr_ctx.conn_id)?;
+        let mut peer_settings = conn_ctx.peer_settings.lock();
+        if peer_settings.is_some() {
+            return Ok(false);
+        }
+
+        // SAFETY: the receive buffer holds the frame
+        let frame =
+            unsafe { slice::from_raw_parts(wr_ctx.buffer_addr as *const u8, wc.byte_len as usize) };
+        match Settings::decode(frame) {
+            Ok(settings) => {
+                log::debug!(
+                    "Settings of the peer of {:?}: {:?}",
+                    wr_ctx.conn_id,
+                    settings
+                );
+                conn_ctx
+                    .credit
+                    .store(settings.initial_credits as isize, Ordering::Release);
+                *peer_settings = Some(settings);
+            }
+            Err(e) => {
+                // nothing is ever sent on the connection
+                log::error!(
+                    "Invalid settings from the peer of {:?}: {}",
+                    wr_ctx.conn_id,
+                    e
+                );
+                self.recent_errors.record(format!(
+                    "invalid settings from the peer of {:?}: {}",
+                    wr_ctx.conn_id, e
+                ));
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::RecvError(
+                        wr_ctx.conn_id,
+                        TransportStatus::INCOMPATIBLE_PEER,
+                    ))
+                    .unwrap();
+                self.tear_down_rx(&wr_ctx.conn_id);
+            }
+        }
+        drop(peer_settings);
+
+        self.state
+            .local_resource()
+            .recv_windows
+            .consume(&wr_ctx.conn_id);
+        self.reclaim_recv_buffers(&conn_ctx.cmid, &[Handle(wc.wr_id)])?;
+        Ok(true)
+    }
+
+    /// Takes the credits the peer grants or takes back as its receive window changes. Returns
+    /// `false` if the receive is not a credits frame but part of a message.
+    fn handle_credits_recv(&mut self, wc: &net::WorkCompletion) -> Result<bool, DatapathError> {
+        let wr_ctx = self.state.local_resource().wr_contexts.get(&wc.wr_id)?;
+        let conn_ctx = self
+            .state
+            .local_resource()
+            .cmid_table
+            .get(&wr_ctx.conn_id)?;
+        let negotiated = match *conn_ctx.peer_settings.lock() {
+            Some(peer_settings) => conn_ctx
+                .settings
+                .negotiated(&peer_settings, FEATURE_CREDITS),
+            None => false,
+        };
+        // a message starts with its meta, which never fits in a credits frame
+        if !negotiated
+            || self
+                .state
+                .local_resource()
+                .rx_contexts
+                .is_receiving(&wr_ctx.conn_id)
+        {
+            return Ok(false);
+        }
+        // SAFETY: the receive buffer holds the frame
+        let frame =
+            unsafe { slice::from_raw_parts(wr_ctx.buffer_addr as *const u8, wc.byte_len as usize) };
+        let delta = match recv_window::decode_credits(frame) {
+            Some(delta) => delta,
+            None => return Ok(false),
+        };
+        conn_ctx.credit.fetch_add(delta, Ordering::AcqRel);
+
+        self.state
+            .local_resource()
+            .recv_windows
+            .consume(&wr_ctx.conn_id);
+        if self.keepalive.enable {
+            conn_ctx.keepalive.lock().last_recv = Instant::now();
+        }
+        self.reclaim_recv_buffers(&conn_ctx.cmid, &[Handle(wc.wr_id)])?;
+        Ok(true)
+    }
+
+    /// Takes a bulk completion from the peer, and acknowledges the message it has read. Returns
+    /// `false` if the receive is not a bulk completion but part of a message.
+    fn handle_bulk_done(&mut self, wc: &net::WorkCompletion) -> Result<bool, DatapathError> {
+        let wr_ctx = self.state.local_resource().wr_contexts.get(&wc.wr_id)?;
+        let conn_ctx = self
+            .state
+            .local_resource()
+            .cmid_table
+            .get(&wr_ctx.conn_id)?;
+        // SAFETY: the receive buffer holds the frame
+        let frame =
+            unsafe { slice::from_raw_parts(wr_ctx.buffer_addr as *const u8, wc.byte_len as usize) };
+        let (call_id, status) = match bulk::decode_done(frame) {
+            Some(done) => done,
+            None => return Ok(false),
+        };
+        let rpc_id = RpcId(wr_ctx.conn_id, call_id);
+        // a bulk completion never comes in the middle of a message
+        if !self.bulk.is_offered(&rpc_id)
+            || self
+                .state
+                .local_resource()
+                .rx_contexts
+                .is_receiving(&wr_ctx.conn_id)
+        {
+            return Ok(false);
+        }
+
+        self.state
+            .local_resource()
+            .recv_windows
+            .consume(&wr_ctx.conn_id);
+        if self.keepalive.enable {
+            conn_ctx.keepalive.lock().last_recv = Instant::now();
+        }
+        self.reclaim_recv_buffers(&conn_ctx.cmid, &[Handle(wc.wr_id)])?;
+
+        if let Some(status) = self.bulk.offer_done(rpc_id, status) {
+            self.rx_outputs()[0]
+                .send(EngineRxMessage::Ack(rpc_id, status))
+                .unwrap();
+        }
+        Ok(true)
+    }
+
+    /// Reads the segments of a message offered by the peer into a gather buffer, see `bulk`. The
+    /// message is delivered when the reads complete.
+    fn fetch_bulk(
+        &mut self,
+        mut recv_ctx: RecvContext,
+        conn_ctx: &ConnectionContext,
+        segments: Vec<RemoteSegment>,
+    ) -> Result<(), DatapathError> {
+        use ulib::uverbs::SendFlags;
+
+        let conn_id = conn_ctx.cmid.as_handle();
+        let meta_ptr = unsafe { MessageMeta::unpack(&recv_ctx.sg_list.0[0]) }.unwrap();
+        let meta = unsafe { meta_ptr.as_ref() };
+        let call_id = meta.call_id;
+        // the responses give back the credits in the order of the requests, whenever the reads
+        // complete
+        let timing = self.replenish_credits(conn_ctx, meta, recv_ctx.first_recv_at);
+
+        let (offsets, len) = bulk::layout(segments.iter().map(|segment| segment.len as usize));
+        let gathered = self
+            .state
+            .local_resource()
+            .gather_buffers
+            .obtain(&conn_id, len);
+        let (addr, handle) = match gathered {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                log::error!("Failed to fetch {:?}: {}", RpcId(conn_id, call_id), e);
+                let mut fetch = Fetch::new(conn_id, call_id, recv_ctx, timing);
+                fetch.status = TransportStatus::BULK_READ_FAILED;
+                return self.finish_fetch(fetch);
+            }
+        };
+        recv_ctx.recv_buffer_handles.push(handle);
+
+        let odp_mr = self.odp_mr.as_mut().unwrap();
+        let mut num_reads = 0;
+        for (segment, off) in segments.iter().zip(offsets) {
+            let ptr = addr + off;
+            let len = segment.len as usize;
+            recv_ctx.sg_list.0.push(SgE { ptr, len });
+            if len == 0 {
+                continue;
+            }
+            unsafe {
+                conn_ctx.cmid.post_read(
+                    odp_mr,
+                    ptr..ptr + len,
+                    handle.0,
+                    SendFlags::SIGNALED,
+                    segment.remote_key(),
+                    0,
+                )?;
+            }
+            num_reads += 1;
+        }
+
+        let fetch = Fetch::new(conn_id, call_id, recv_ctx, timing);
+        if num_reads == 0 {
+            return self.finish_fetch(fetch);
+        }
+        self.bulk.start_fetch(handle.0, fetch, num_reads);
+        Ok(())
+    }
+
+    /// Completes a read posted by `fetch_bulk`, and finishes the fetch once all its reads
+    /// complete.
+    fn read_completed(&mut self, wr_id: u64, status: TransportStatus) -> Result<(), DatapathError> {
+        match self.bulk.read_completed(wr_id, status) {
+            Some(fetch) => self.finish_fetch(fetch),
+            None => Ok(()),
+        }
+    }
+
+    /// Tells the peer the outcome of a fetch, and delivers the message if it is read.
+    fn finish_fetch(&mut self, fetch: Fetch) -> Result<(), DatapathError> {
+        let recv_ctx = fetch.recv_ctx;
+        let conn_ctx = match self.state.local_resource().cmid_table.get(&fetch.conn_id) {
+            Ok(conn_ctx) => conn_ctx,
+            Err(_) => {
+                // the reads are flushed as the connection goes away
+                let gather_buffers = &self.state.local_resource().gather_buffers;
+                for handle in &recv_ctx.recv_buffer_handles {
+                    gather_buffers.release(handle);
+                }
+                return Ok(());
+            }
+        };
+
+        let recv_id = if fetch.status == TransportStatus::Success {
+            self.post_bulk_done(&conn_ctx.cmid, fetch.call_id, TransportStatus::Success)?;
+            self.deliver_up(&recv_ctx.sg_list, fetch.conn_id, 0, fetch.timing)?
+        } else {
+            log::error!(
+                "Failed to read {:?} sent in bulk: {:?}",
+                RpcId(fetch.conn_id, fetch.call_id),
+                fetch.status
+            );
+            self.post_bulk_done(
+                &conn_ctx.cmid,
+                fetch.call_id,
+                TransportStatus::BULK_READ_FAILED,
+            )?;
+            self.rx_outputs()[0]
+                .send(EngineRxMessage::RecvError(
+                    fetch.conn_id,
+                    TransportStatus::BULK_READ_FAILED,
+                ))
+                .unwrap();
+            None
+        };
+
+        match recv_id {
+            Some(recv_id) => {
+                self.recv_mr_usage
+                    .insert(recv_id, recv_ctx.recv_buffer_handles);
+            }
+            None => self.reclaim_recv_buffers(&conn_ctx.cmid, &recv_ctx.recv_buffer_handles)?,
+        }
+        Ok(())
+    }
+
+    /// Tells the peer whether the message of `call_id` it has offered is read.
+    fn post_bulk_done(
+        &mut self,
+        cmid: &ulib::ucm::CmId,
+        call_id: CallId,
+        status: TransportStatus,
+    ) -> Result<(), DatapathError> {
+        use ulib::uverbs::SendFlags;
+
+        let frame = bulk::encode_done(call_id, status);
+        let odp_mr = self.odp_mr.as_mut().unwrap();
+        let off = frame.as_ptr().expose_addr();
+        // inlined and unsignaled like the settings frame
+        unsafe {
+            cmid.post_send_with_imm(
+                odp_mr,
+                off..off + frame.len(),
+                KEEPALIVE_WR_ID,
+                SendFlags::INLINE,
+                BULK_DONE_IMM,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Grants the peer `delta` credits, or takes them back if `delta` is negative.
+    fn post_credits(&mut self, cmid: &ulib::ucm::CmId, delta: isize) -> Result<(), DatapathError> {
+        use ulib::uverbs::SendFlags;
+
+        let frame = recv_window::encode_credits(delta);
+        let odp_mr = self.odp_mr.as_mut().unwrap();
+        let off = frame.as_ptr().expose_addr();
+        // inlined and unsignaled like the settings frame
+        unsafe {
+            cmid.post_send_with_imm(
+                odp_mr,
+                off..off + frame.len(),
+                KEEPALIVE_WR_ID,
+                SendFlags::INLINE,
+                CREDITS_IMM,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sends the settings of this end to the peer of a new connection.
+    fn post_settings(
+        &mut self,
+        cmid: &ulib::ucm::CmId,
+        settings: &Settings,
+    ) -> Result<(), ulib::Error> {
+        use ulib::uverbs::SendFlags;
+
+        // only the posted buffers are granted, the rest as the window grows; with a shared
+        // receive queue the connection has no window of its own
+        let mut settings = *settings;
+        if let Some(credits) = self
+            .state
+            .local_resource()
+            .recv_windows
+            .credits(&cmid.as_handle())
+        {
+            settings.initial_credits = credits as u32;
+        }
+        let frame = settings.encode();
+        let odp_mr = self.odp_mr.as_mut().unwrap();
+        let off = frame.as_ptr().expose_addr();
+        // inlined and unsignaled, so the frame can go out of scope once it is posted
+        unsafe {
+            cmid.post_send_with_imm(
+                odp_mr,
+                off..off + frame.len(),
+                KEEPALIVE_WR_ID,
+                SendFlags::INLINE,
+                SETTINGS_IMM,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn post_keepalive(&mut self, cmid: &ulib::ucm::CmId, imm: u32) -> Result<(), DatapathError> {
+        use ulib::uverbs::SendFlags;
+
+        let odp_mr = match self.odp_mr.as_mut() {
+            Some(odp_mr) => odp_mr,
+            None => return Ok(()),
+        };
+        let off = KEEPALIVE_PAYLOAD.as_ptr().expose_addr();
+        // unsignaled, so it does not occupy any rpc_ctx
+        unsafe {
+            cmid.post_send_with_imm(odp_mr, off..off, KEEPALIVE_WR_ID, SendFlags::INLINE, imm)?;
+        }
+        Ok(())
+    }
+
+    /// Fails the messages offered to the peers that have not been read within the offer timeout.
+    fn check_bulk_offers(&mut self) -> Status {
+        let expired = self.bulk.expire_offers(Instant::now());
+        for &rpc_id in &expired {
+            log::warn!("{:?} has not been read by the peer in time", rpc_id);
+            self.rx_outputs()[0]
+                .send(EngineRxMessage::Ack(
+                    rpc_id,
+                    TransportStatus::BULK_READ_FAILED,
+                ))
+                .unwrap();
+        }
+        Progress(expired.len())
+    }
+
+    /// Sends pings on idle connections and reports the connections whose health changes.
+    fn check_keepalive(&mut self) -> Result<Status, DatapathError> {
+        if !self.keepalive.enable {
+            return Ok(Progress(0));
+        }
+
+        let now = Instant::now();
+        let interval = Duration::from_millis(self.keepalive.interval_ms);
+        let timeout = Duration::from_millis(self.keepalive.timeout_ms);
+
+        let conns: Vec<_> = self
+            .state
+            .local_resource()
+            .cmid_table
+            .inner()
+            .borrow()
+            .values()
+            .map(|entry| entry.data())
+            .collect();
+
+        let mut work = 0;
+        for conn_ctx in conns {
+            let mut keepalive = conn_ctx.keepalive.lock();
+            if keepalive.state == ConnectionState::Closed {
+                continue;
+            }
+
+            let idle = now.saturating_duration_since(keepalive.last_recv);
+            let state = if idle >= timeout {
+                ConnectionState::Closed
+            } else if idle >= interval * 2 {
+                // at least one ping has gone unanswered
+                ConnectionState::Degraded
+            } else {
+                ConnectionState::Connected
+            };
+
+            let should_ping = idle >= interval
+                && keepalive
+                    .last_ping
+                    .map_or(true, |t| now.saturating_duration_since(t) >= interval);
+            if should_ping && state != ConnectionState::Closed {
+                keepalive.last_ping = Some(now);
+                self.post_keepalive(&conn_ctx.cmid, KEEPALIVE_PING_IMM)?;
+                work += 1;
+            }
+
+            if state != keepalive.state {
+                keepalive.state = state;
+                let conn_id = conn_ctx.cmid.as_handle();
+                log::debug!(
+                    "connection {:?} becomes {:?}, idle for {:?}",
+                    conn_id,
+                    state,
+                    idle
+                );
+                self.rx_outputs()[0]
+                    .send(EngineRxMessage::ConnectionState(conn_id, state))
+                    .unwrap_or_else(|e| {
+                        log::warn!("error when reporting connection state, e: {}", e)
+                    });
+                if state == ConnectionState::Closed {
+                    self.tear_down_rx(&conn_id);
+                    self.recent_errors.record(format!(
+                        "connection {:?} closed, idle for {:?}",
+                        conn_id, idle
+                    ));
+                    self.conn_tails.record(
+                        &mut conn_ctx.log.lock(),
+                        conn_id,
+                        ConnEventKind::Disconnected,
+                    );
+                    // the peer will never read the messages offered to it
+                    let status = TransportStatus::BULK_READ_FAILED;
+                    for rpc_id in self.bulk.fail_offers(conn_id, status) {
+                        self.rx_outputs()[0]
+                            .send(EngineRxMessage::Ack(rpc_id, status))
+                            .unwrap();
+                    }
+                }
+                work += 1;
+            }
+        }
+
+        Ok(Progress(work))
+    }
+
+    /// Records an event of the connection `conn_id`, and sends it to the tools tailing it.
+    fn log_conn_event(&mut self, conn_id: Handle, kind: ConnEventKind) {
+        match self.state.local_resource().cmid_table.get(&conn_id) {
+            Ok(conn_ctx) => self
+                .conn_tails
+                .record(&mut conn_ctx.log.lock(), conn_id, kind),
+            Err(_) => self.conn_tails.record_gone(conn_id, kind),
+        }
+    }
+
+    fn reclaim_recv_buffers(
+        &mut self,
+        cmid: &ulib::ucm::CmId,
+        mr_handles: &[Handle],
+    ) -> Result<(), DatapathError> {
+        let conn_id = cmid.as_handle();
+        self.log_conn_event(conn_id, ConnEventKind::Reclaimed(mr_handles.len()));
+        if self.srq.is_some() {
+            return self.reclaim_srq_buffers(&conn_id, mr_handles);
+        }
+        let local_resource = self.state.local_resource();
+        for handle in mr_handles {
+            if local_resource.gather_buffers.release(handle) {
+                continue;
+            }
+            if !local_resource.owns_recv_buffer(&conn_id, handle) {
+                continue;
+            }
+            if !local_resource.recv_windows.repost(&conn_id) {
+                // the connection has enough buffers posted
+                if let Some(recv_buffer) =
+                    local_resource.recv_buffer_table.close_resource(handle)?
+                {
+                    local_resource.wr_contexts.close_resource(&handle.0)?;
+                    local_resource.recv_windows.retire(&conn_id, &recv_buffer);
+                }
+                continue;
+            }
+            let recv_buffer = local_resource.recv_buffer_table.get(handle)?;
+            self.state
+                .resource()
+                .recv_buffer_pool
+                .give_back(&recv_buffer);
+            let off = recv_buffer.addr();
+            let len = recv_buffer.len();
+
+            let odp_mr = self.odp_mr.as_mut().unwrap();
+            unsafe {
+                cmid.post_recv(odp_mr, off..off + len, handle.0 as u64)?;
+            }
+        }
+
+        // take more buffers if the reclaimed ones do not suffice
+        let buffers = self.state.local_resource().recv_windows.grow(&conn_id);
+        self.post_recv_buffers(cmid, buffers)
+    }
+
+    /// Posts the buffers newly taken from the slab of the connection.
+    fn post_recv_buffers(
+        &mut self,
+        cmid: &ulib::ucm::CmId,
+        buffers: Vec<RecvBuffer>,
+    ) -> Result<(), DatapathError> {
+        for recv_buffer in buffers {
+            let handle = recv_buffer.as_handle();
+            let wr_id = handle.0 as u64;
+            let wr_ctx = WrContext {
+                conn_id: cmid.as_handle(),
+                buffer_addr: recv_buffer.addr(),
+            };
+
+            let off = recv_buffer.addr();
+            let len = recv_buffer.len();
+
+            let odp_mr = self.odp_mr.as_mut().unwrap();
+            unsafe {
+                cmid.post_recv(odp_mr, off..off + len, wr_id)?;
+            }
+            self.state
+                .local_resource()
+                .wr_contexts
+                .insert(wr_id, wr_ctx)?;
+            self.state
+                .local_resource()
+                .recv_buffer_table
+                .insert(handle, recv_buffer)?;
         }
-
-        // tracing::trace!("reshape_fused_sg_list: sg_list: {:?}", sg_list);
+        Ok(())
     }
 
-    fn unmarshal_and_deliver_up(
-        &mut self,
-        sgl: SgList,
-        conn_ctx: Arc<ConnectionContext>,
-    ) -> Result<RpcId, DatapathError> {
-        // log::debug!("unmarshal_and_deliver_up, sgl: {:0x?}", sgl);
+    /// Adjusts the number of posted receive buffers of each connection to the load, and the
+    /// credits of the peer to the posted buffers.
+    fn check_recv_windows(&mut self) -> Result<Status, DatapathError> {
+        let pending = self
+            .state
+            .local_resource()
+            .recv_windows
+            .adjust(&self.recv_buffers);
 
-        // let mut timer = crate::timer::Timer::new();
+        let mut work = 0;
+        for conn_id in pending {
+            // the connection may still be being established
+            let conn_ctx = match self.state.local_resource().cmid_table.get(&conn_id) {
+                Ok(conn_ctx) => conn_ctx,
+                Err(_) => continue,
+            };
+            let buffers = self.state.local_resource().recv_windows.grow(&conn_id);
+            work += buffers.len();
+            self.post_recv_buffers(&conn_ctx.cmid, buffers)?;
+
+            // the credits are granted once the peer is known to take them, until then they are
+            // left for a later adjustment
+            let negotiated = match *conn_ctx.peer_settings.lock() {
+                Some(peer_settings) => conn_ctx
+                    .settings
+                    .negotiated(&peer_settings, FEATURE_CREDITS),
+                None => continue,
+            };
+            if !negotiated {
+                continue;
+            }
+            let delta = self.state.local_resource().recv_windows.grant(&conn_id);
+            if delta != 0 {
+                self.post_credits(&conn_ctx.cmid, delta)?;
+                work += 1;
+            }
+        }
+        Ok(Progress(work))
+    }
 
-        let mut meta_ptr = unsafe { MessageMeta::unpack(&sgl.0[0]) }.unwrap();
-        let meta = unsafe { meta_ptr.as_mut() };
-        meta.conn_id = conn_ctx.cmid.as_handle();
+    /// Starts to move the connection `conn_id` to the engine `to`. It is handed over once it is
+    /// quiesced, see [`migrate`].
+    fn start_migration(&mut self, conn_id: Handle, to: usize) -> Result<(), MigrateError> {
+        if to == self.state.rpc_adapter_id {
+            return Err(MigrateError::SameEngine);
+        }
+        let conn_ctx = self
+            .state
+            .local_resource()
+            .cmid_table
+            .get(&conn_id)
+            .map_err(|_| MigrateError::NotFound)?;
+        if self.migration.outgoing.contains_key(&conn_id) {
+            return Err(MigrateError::InProgress);
+        }
+        // the buffers of a shared receive queue are not owned by a connection
+        if self.srq.is_some() {
+            return Err(MigrateError::SharedRecvQueue);
+        }
+        // only an engine that accepts the connections of the listener can take it over
+        let listener = conn_ctx.listener.ok_or(MigrateError::NotAccepted)?;
+        match self.state.resource().listener_table.get(&listener) {
+            Ok(shared) if shared.has_member(to) => {}
+            _ => return Err(MigrateError::NotListening(to)),
+        }
+        if conn_ctx.peer_settings.lock().is_none() {
+            return Err(MigrateError::NotEstablished);
+        }
 
-        let recv_id = RpcId(meta.conn_id, meta.call_id);
+        let qp_num = conn_ctx.cmid.qp_num()?;
+        self.migration.outgoing.insert(
+            conn_id,
+            Outgoing {
+                to,
+                qp_num,
+                started: Instant::now(),
+                idle_since: None,
+                held: Vec::new(),
+            },
+        );
+        Ok(())
+    }
 
-        // timer.tick();
-        // replenish the credits
-        if meta.msg_type == RpcMsgType::Response {
-            let call_id = meta.call_id;
-            let mut outstanding_req = conn_ctx.outstanding_req.lock();
-            let req_ctx = outstanding_req.pop_front().unwrap();
-            assert_eq!(call_id, req_ctx.call_id);
-            conn_ctx.credit.fetch_add(req_ctx.sg_len, Ordering::AcqRel);
-            self.pending_recv -= req_ctx.sg_len;
-            drop(outstanding_req);
+    /// Returns whether anything of the connection is in flight.
+    fn conn_busy(&self, conn_ctx: &ConnectionContext) -> bool {
+        let conn_id = conn_ctx.cmid.as_handle();
+        self.state
+            .local_resource()
+            .rx_contexts
+            .with(&conn_id, |rx| !rx.outstanding_req.is_empty())
+            .unwrap_or(false)
+            || self
+                .local_buffer
+                .any(|msg| unsafe { &*msg.meta_buf_ptr.as_meta_ptr() }.conn_id == conn_id)
+            || self.rpc_ctx.iter().any(|(_, rpc_id)| rpc_id.0 == conn_id)
+            || self.recv_mr_usage.keys().any(|rpc_id| rpc_id.0 == conn_id)
+            || self.bulk.involves(&conn_id)
+    }
+
+    /// Hands over the connections quiesced, and abandons the migrations of those busy for too
+    /// long. Takes what is handed to this engine, and the CQs left by the engines that are gone.
+    fn check_migrations(&mut self) -> Result<Status, DatapathError> {
+        let mut work = 0;
+        let orphans = self.state.resource().migrations.take_orphans();
+        work += orphans.len();
+        self.migration.orphans.extend(orphans);
+
+        let now = Instant::now();
+        let conn_ids: Vec<_> = self.migration.outgoing.keys().copied().collect();
+        for conn_id in conn_ids {
+            let busy = match self.state.local_resource().cmid_table.get(&conn_id) {
+                Ok(conn_ctx) => Some(self.conn_busy(&conn_ctx)),
+                // closed in the meantime
+                Err(_) => None,
+            };
+            let outgoing = self.migration.outgoing.get_mut(&conn_id).unwrap();
+            match outgoing.check(busy, now) {
+                Quiesce::Wait => {}
+                Quiesce::HandOver => {
+                    let outgoing = self.migration.outgoing.remove(&conn_id).unwrap();
+                    self.hand_over(conn_id, outgoing)?;
+                    work += 1;
+                }
+                Quiesce::Abandon => {
+                    // the receives held back are handled here after all
+                    let outgoing = self.migration.abandon(&conn_id).unwrap();
+                    log::warn!(
+                        "Migration of {:?} to RpcAdapter {} abandoned, the connection is {}",
+                        conn_id,
+                        outgoing.to,
+                        if busy.is_some() { "busy" } else { "gone" }
+                    );
+                    self.recent_errors.record(format!(
+                        "migration of {:?} to RpcAdapter {} abandoned",
+                        conn_id, outgoing.to
+                    ));
+                    work += 1;
+                }
+            }
         }
-        // timer.tick();
 
-        let mut excavate_ctx = ExcavateContext {
-            sgl: sgl.0[1..].iter(),
-            addr_arbiter: &self.state.local_resource().addr_map,
-        };
+        // the fast path takes them once the engine expects completions forwarded
+        if !self.migration.expects_forwarded() {
+            work += self.check_forwarded()?;
+        }
+        Ok(Progress(work))
+    }
 
-        let (addr_app, addr_backend) = if let Some(ref module) = self.serialization_engine {
-            module.unmarshal(meta, &mut excavate_ctx).unwrap()
-        } else {
-            panic!("dispatch module not loaded");
+    /// Hands a quiesced connection over to the engine it moves to, with the receive buffers
+    /// posted on it and the receives held back.
+    fn hand_over(&mut self, conn_id: Handle, outgoing: Outgoing) -> Result<(), DatapathError> {
+        let Outgoing {
+            to, qp_num, held, ..
+        } = outgoing;
+        self.log_conn_event(conn_id, ConnEventKind::Migrated(to));
+
+        let local_resource = self.state.local_resource();
+        let conn_ctx = match local_resource.cmid_table.close_resource(&conn_id)? {
+            Some(conn_ctx) => Arc::try_unwrap(conn_ctx).expect("the connection is in use"),
+            None => return Ok(()),
         };
-        // timer.tick();
+        let rx = local_resource
+            .rx_contexts
+            .remove(&conn_id)
+            .unwrap_or_default();
+        let wr_ids: Vec<u64> = local_resource
+            .wr_contexts
+            .inner()
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| entry.data().conn_id == conn_id)
+            .map(|(wr_id, _)| *wr_id)
+            .collect();
+        let mut recv_buffers = Vec::with_capacity(wr_ids.len());
+        for wr_id in wr_ids {
+            let handle = Handle(wr_id);
+            let recv_buffer = match local_resource.recv_buffer_table.close_resource(&handle)? {
+                Some(recv_buffer) => match Arc::try_unwrap(recv_buffer) {
+                    Ok(recv_buffer) => recv_buffer,
+                    Err(_) => panic!("recv buffer {:?} is in use", handle),
+                },
+                None => continue,
+            };
+            if let Some(wr_ctx) = local_resource.wr_contexts.close_resource(&wr_id)? {
+                let wr_ctx = WrContext {
+                    conn_id: wr_ctx.conn_id,
+                    buffer_addr: wr_ctx.buffer_addr,
+                };
+                recv_buffers.push((wr_ctx, recv_buffer));
+            }
+        }
 
-        let msg = RpcMessageRx {
-            meta: meta_ptr,
-            addr_backend,
-            addr_app,
+        let conn = MigratedConnection {
+            from: self.state.rpc_adapter_id,
+            qp_num,
+            conn_ctx,
+            rx,
+            recv_buffers,
+            window: local_resource.recv_windows.take(&conn_id),
+            gather: local_resource.gather_buffers.take(&conn_id),
+            quarantined: self.quarantined.remove(&conn_id),
+            wcs: held,
         };
+        self.state.resource().migrations.hand(to, conn);
+        self.migration.adopted.remove(&qp_num);
+        self.migration.forwarding.insert(qp_num, to);
+        self.migration.moved.insert(conn_id, to);
+        log::info!("Handed {:?} over to RpcAdapter {}", conn_id, to);
 
         self.rx_outputs()[0]
-            .send(EngineRxMessage::RpcMessage(msg))
-            .unwrap();
-
-        // timer.tick();
-        // log::info!("unmarshal_and_deliver_up {}", timer);
-
-        Ok(recv_id)
+            .send(EngineRxMessage::ConnectionState(
+                conn_id,
+                ConnectionState::Migrated,
+            ))
+            .unwrap_or_else(|e| log::warn!("error when reporting connection state, e: {}", e));
+        Ok(())
     }
 
-    fn check_transport_service(&mut self) -> Result<Status, DatapathError> {
-        // check completion, and replenish some recv requests
-        use net::{WcFlags, WcOpcode, WcStatus};
-
-        if self.state.local_resource().cq.as_ref().is_none() {
-            return Ok(Progress(0));
+    /// Holds back a completion of a connection being quiesced or taken over, or forwards a
+    /// completion of a connection that has moved to the engine owning it. Returns whether the
+    /// completion is diverted.
+    fn divert_wc(&mut self, wc: &net::WorkCompletion) -> bool {
+        if let Some(&to) = self.migration.forwarding.get(&wc.qp_num) {
+            self.state.resource().migrations.forward(to, *wc);
+            return true;
         }
+        let local_resource = self.state.local_resource();
+        self.migration.hold(wc, |wc| {
+            wc.opcode == net::WcOpcode::Recv || local_resource.wr_contexts.get(&wc.wr_id).is_ok()
+        })
+    }
 
-        let cq = self.state.local_resource().cq.as_ref().unwrap();
-
-        // SAFETY: dp::WorkRequest is Copy and zerocopy
-        unsafe {
-            self.wc_read_buffer.set_len(0);
+    /// Handles a completion taken from the CQ of another engine.
+    fn handle_forwarded(&mut self, wc: &net::WorkCompletion) -> Result<usize, DatapathError> {
+        if self.migration.diverts() && self.divert_wc(wc) {
+            return Ok(0);
         }
+        self.handle_wc(wc)
+    }
 
-        cq.poll(&mut self.wc_read_buffer)?;
-
-        let comps = mem::take(&mut self.wc_read_buffer);
-
+    /// Handles the completions held back by the migrations, polls the CQs left by the engines
+    /// that are gone, and takes what the other engines have handed to this one.
+    fn check_forwarded(&mut self) -> Result<usize, DatapathError> {
         let mut progress = 0;
-        for wc in &comps {
-            match wc.status {
-                WcStatus::Success => {
-                    match wc.opcode {
-                        WcOpcode::Send => {
-                            // send completed,  do nothing
-                            if wc.wc_flags.contains(WcFlags::WITH_IMM) {
-                                tracing::trace!("post_send_imm completed, wr_id={}", wc.wr_id);
-                                // let rpc_id = RpcId::decode_u64(wc.wr_id);
-                                let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
-                                self.rx_outputs()[0]
-                                    .send(EngineRxMessage::Ack(rpc_id, TransportStatus::Success))
-                                    .unwrap();
-                            }
-                        }
-                        WcOpcode::Recv => {
-                            let conn_ctx = {
-                                let wr_ctx =
-                                    self.state.local_resource().wr_contexts.get(&wc.wr_id)?;
-                                let cmid_handle = wr_ctx.conn_id;
-                                let conn_ctx =
-                                    self.state.local_resource().cmid_table.get(&cmid_handle)?;
-                                // received a segment of RPC message
-                                let sge = SgE {
-                                    ptr: wr_ctx.buffer_addr,
-                                    len: wc.byte_len as _, // note this byte_len is only valid for
-                                                           // recv request
-                                };
-                                let mut recv_ctx = conn_ctx.receiving_ctx.lock();
-                                recv_ctx.sg_list.0.push(sge);
-                                recv_ctx.recv_buffer_handles.push(Handle(wc.wr_id as u64));
-                                drop(recv_ctx);
-                                conn_ctx
-                            };
+        while let Some(wc) = self.migration.replay.pop_front() {
+            progress += self.handle_wc(&wc)?;
+        }
 
-                            if wc.wc_flags.contains(WcFlags::WITH_IMM) {
-                                // received an entire RPC message
-                                tracing::trace!(
-                                    "post_recv received complete message, wr_id={}",
-                                    wc.wr_id
-                                );
-                                // let mut timer = crate::timer::Timer::new();
-
-                                use std::ops::DerefMut;
-                                let mut recv_ctx =
-                                    mem::take(conn_ctx.receiving_ctx.lock().deref_mut());
-
-                                // check if it is an eager message
-                                if recv_ctx.sg_list.0.len() == 1 {
-                                    // got an eager message
-                                    Self::reshape_fused_sg_list(&mut recv_ctx.sg_list);
-                                }
-
-                                // timer.tick();
-                                // 200-500ns
-                                let recv_id = self.unmarshal_and_deliver_up(
-                                    recv_ctx.sg_list,
-                                    Arc::clone(&conn_ctx),
-                                )?;
-                                // timer.tick();
-
-                                // 60-70ns
-                                // keep them outstanding because they will be used by the user
-                                self.recv_mr_usage
-                                    .insert(recv_id, recv_ctx.recv_buffer_handles);
-                                // timer.tick();
-                                // log::info!("check_transport_service: {}", timer);
-                            }
-                            progress += 1;
-                        }
-                        // The below two are probably errors in impl logic, so assert them
-                        WcOpcode::Invalid => panic!("invalid wc: {:?}", wc),
-                        _ => panic!("Unhandled wc opcode: {:?}", wc),
-                    }
-                }
-                WcStatus::Error(code) => {
-                    log::debug!("wc failed: {:?}", wc);
-                    // TODO(cjr): bubble up the error, close the connection, and return an error
-                    // to the user.
-                    let msg = if let Ok(wr_ctx) =
-                        self.state.local_resource().wr_contexts.get(&wc.wr_id)
-                    {
-                        // this is a recv operation. don't know the rpc_id
-                        let conn_id = wr_ctx.conn_id;
-                        EngineRxMessage::RecvError(conn_id, TransportStatus::Error(code))
-                    } else {
-                        // let rpc_id = RpcId::decode_u64(wc.wr_id);
-                        let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
-                        EngineRxMessage::Ack(rpc_id, TransportStatus::Error(code))
-                    };
-                    self.rx_outputs()[0].send(msg).unwrap_or_else(|e| {
-                        log::warn!("error when bubbling up the error, send failed e: {}", e)
-                    });
-                    // the error is caused by unexpected shutdown of mrpc engine
+        let rpc_adapter_id = self.state.rpc_adapter_id;
+        let mut polled = Vec::new();
+        for orphan in &self.migration.orphans {
+            let cq = match orphan.cq.as_ref() {
+                Some(cq) => cq,
+                None => continue,
+            };
+            // SAFETY: dp::WorkRequest is Copy and zerocopy
+            unsafe {
+                self.wc_read_buffer.set_len(0);
+            }
+            cq.poll(&mut self.wc_read_buffer)?;
+            for wc in &self.wc_read_buffer {
+                match orphan.route(wc) {
+                    Some(to) => polled.push((to, *wc)),
+                    // the connection has been closed
+                    None => log::debug!("dropping wc of RpcAdapter {}: {:?}", orphan.id, wc),
                 }
             }
         }
+        for (to, wc) in polled {
+            if to == rpc_adapter_id {
+                progress += self.handle_forwarded(&wc)?;
+            } else {
+                self.state.resource().migrations.forward(to, wc);
+                progress += 1;
+            }
+        }
 
-        self.wc_read_buffer = comps;
+        for handoff in self.state.resource().migrations.take(rpc_adapter_id) {
+            match handoff {
+                Handoff::Connection(conn) => {
+                    self.receive_migrated(conn);
+                    progress += 1;
+                }
+                Handoff::Completion(wc) => progress += self.handle_forwarded(&wc)?,
+            }
+        }
+        Ok(progress)
+    }
 
-        // COMMENT(cjr): Progress(0) here is okay for now because we haven't use the progress as
-        // any indicator.
-        Ok(Status::Progress(progress))
+    /// Takes a connection handed to this engine. It is announced to the application in the slow
+    /// path, and its completions are held back until the application has mapped its buffers.
+    fn receive_migrated(&mut self, conn: MigratedConnection) {
+        let conn_id = conn.conn_ctx.cmid.as_handle();
+        self.migration.adopted.insert(conn.qp_num);
+        self.migration.forwarding.remove(&conn.qp_num);
+        self.migration.moved.remove(&conn_id);
+        self.migration.arrived.push(conn_id);
+        self.migration.incoming.insert(conn_id, conn);
     }
 
-    fn reclaim_recv_buffers(
-        &mut self,
-        cmid: &ulib::ucm::CmId,
-        mr_handles: &[Handle],
-    ) -> Result<(), DatapathError> {
-        for handle in mr_handles {
-            let recv_buffer = self.state.local_resource().recv_buffer_table.get(handle)?;
-            let off = recv_buffer.addr();
-            let len = recv_buffer.len();
+    /// Announces the connections taken over from the other engines to the application like the
+    /// accepted ones, with the receive buffers they have had.
+    fn announce_migrated(&mut self) -> Result<Status, ControlPathError> {
+        let arrived = mem::take(&mut self.migration.arrived);
+        let mut work = 0;
+        for conn_id in arrived {
+            let conn = match self.migration.incoming.get_mut(&conn_id) {
+                Some(conn) => conn,
+                None => continue,
+            };
+            if self.odp_mr.is_none() {
+                let pd = conn.conn_ctx.cmid.get_pd()?;
+                self.odp_mr = Some(pd.register_on_demand_paging()?);
+            }
 
-            let odp_mr = self.odp_mr.as_mut().unwrap();
-            unsafe {
-                cmid.post_recv(odp_mr, off..off + len, handle.0 as u64)?;
+            let local_resource = self.state.local_resource();
+            for (wr_ctx, recv_buffer) in mem::take(&mut conn.recv_buffers) {
+                let handle = recv_buffer.as_handle();
+                local_resource.wr_contexts.insert(handle.0, wr_ctx)?;
+                local_resource
+                    .recv_buffer_table
+                    .insert(handle, recv_buffer)?;
             }
+            if let Some(window) = conn.window.take() {
+                local_resource.recv_windows.put(conn_id, window);
+            }
+            let gather = conn.gather.take();
+            if let Some(gather) = gather.as_ref() {
+                local_resource
+                    .gather_buffers
+                    .put(conn_id, Arc::clone(gather));
+            }
+            if let Some(status) = conn.quarantined {
+                self.quarantined.insert(conn_id, status);
+            }
+
+            // the gather buffers are read by the application like the receive buffers
+            let regions: Vec<_> = local_resource
+                .recv_windows
+                .slab(&conn_id)
+                .into_iter()
+                .chain(gather)
+                .map(|slab| slab.storage())
+                .collect();
+            let read_regions = regions
+                .iter()
+                .map(|region| ReadHeapRegion {
+                    handle: region.as_handle(),
+                    addr: region.as_ptr().addr(),
+                    len: region.len(),
+                    file_off: 0,
+                })
+                .collect();
+            let fds = regions
+                .iter()
+                .map(|region| region.memfd().as_raw_fd())
+                .collect();
+
+            let conn_resp = ConnectResponse {
+                conn_handle: conn_id,
+                read_regions,
+                peer_addr: conn.conn_ctx.cmid.get_peer_addr().ok(),
+            };
+            let comp = cmd::Completion(Ok(cmd::CompletionKind::NewConnectionInternal(
+                conn_resp, fds,
+            )));
+            self.cmd_tx.send(comp)?;
+            log::info!("Took over {:?} from RpcAdapter {}", conn_id, conn.from);
+            work += 1;
         }
+        Ok(Progress(work))
+    }
+
+    /// Puts a connection taken over in the tables of the engine once the application has mapped
+    /// its buffers, and handles the completions held back.
+    fn activate_migrated(&mut self, conn: MigratedConnection) -> Result<(), ControlPathError> {
+        let MigratedConnection {
+            conn_ctx, rx, wcs, ..
+        } = conn;
+        let conn_id = conn_ctx.cmid.as_handle();
+        // nothing is received from the peer while the connection is moved
+        conn_ctx.keepalive.lock().last_recv = Instant::now();
+        self.state.local_resource().adopt_cmid(conn_ctx, rx)?;
+        self.migration.replay.extend(wcs);
+        self.log_conn_event(conn_id, ConnEventKind::Migrated(self.state.rpc_adapter_id));
         Ok(())
     }
 
@@ -891,28 +3329,46 @@ impl RpcAdapterEngine {
             .pop_front();
         match ret {
             None => Ok(Status::Progress(0)),
-            Some(mut builder) => {
+            Some(IncomingConnection {
+                listener,
+                mut builder,
+                options,
+                slot,
+            }) => {
+                self.get_or_init_srq(&builder)?;
+                let settings = self.connection_settings(&options);
                 let cq = self.state.get_or_init_cq(2048, 0, &builder)?;
+                if let Some(srq) = self.srq.as_ref() {
+                    builder.set_srq(srq.srq());
+                }
                 let mut pre_id = builder
                     .set_send_cq(cq)
                     .set_recv_cq(cq)
                     .set_max_send_wr(128)
-                    .set_max_recv_wr(128)
+                    .set_max_recv_wr(settings.initial_credits)
                     .set_max_inline_data(MAX_INLINE_DATA as _)
                     .build()?;
 
                 // prepare and post receive buffers
-                let (read_regions, fds) = self.prepare_recv_buffers(&mut pre_id)?;
+                let (read_regions, fds) = self.prepare_recv_buffers(&mut pre_id, &settings)?;
                 let handle = pre_id.as_handle();
+                let peer_addr = pre_id.get_peer_addr().ok();
                 // move pre_cm_id to staging
+                let staged = StagedConnection {
+                    pre_id,
+                    settings,
+                    listener,
+                    slot,
+                };
                 self.state
                     .resource()
                     .staging_pre_cmid_table
-                    .insert(handle, pre_id)?;
+                    .insert(handle, staged)?;
                 // pass these resources back to the user
                 let conn_resp = ConnectResponse {
                     conn_handle: handle,
                     read_regions,
+                    peer_addr,
                 };
                 let comp = cmd::Completion(Ok(cmd::CompletionKind::NewConnectionInternal(
                     conn_resp, fds,
@@ -923,23 +3379,189 @@ impl RpcAdapterEngine {
         }
     }
 
+    /// Accepts the datagram connections whose hellos have arrived. The hellos are answered once
+    /// the application has mapped the receive buffers.
+    fn check_datagram_hellos(&mut self) -> Result<Status, ControlPathError> {
+        let hellos = match self.datagram.as_mut() {
+            Some(endpoint) => endpoint.poll_hellos(),
+            None => return Ok(Progress(0)),
+        };
+        let mut work = 0;
+        for (from, remote) in hellos {
+            let conn_id = datagram::new_conn_id();
+            let (slab, read_regions, fds) = self.prepare_datagram_buffers()?;
+            self.datagram
+                .as_mut()
+                .unwrap()
+                .add_peer(conn_id, &remote, slab, Some(from))?;
+            let conn_resp = ConnectResponse {
+                conn_handle: conn_id,
+                read_regions,
+                peer_addr: Some(from),
+            };
+            let comp = cmd::Completion(Ok(cmd::CompletionKind::NewConnectionInternal(
+                conn_resp, fds,
+            )));
+            self.cmd_tx.send(comp)?;
+            work += 1;
+        }
+        Ok(Progress(work))
+    }
+
+    /// Opens the UD QP shared by the datagram connections, on the device that owns `addr` if
+    /// `bind` is set, or on the device that routes to `addr` otherwise.
+    async fn open_datagram(
+        &mut self,
+        addr: &SocketAddr,
+        bind: bool,
+    ) -> Result<(), ControlPathError> {
+        let config = self.datagram_config;
+        let mut builder = ulib::ucm::CmIdBuilder::new();
+        builder
+            .set_max_send_wr(config.max_send_wr as u32)
+            .set_max_recv_wr(config.recv_buffers as u32)
+            .set_max_inline_data(MAX_INLINE_DATA as u32);
+        let mut builder = if bind {
+            builder.bind_datagram(addr).await?
+        } else {
+            builder.resolve_datagram(addr).await?
+        };
+        let cq = self.state.get_or_init_cq(2048, 0, &builder)?;
+        builder.set_send_cq(cq).set_recv_cq(cq);
+        let pre_id = builder.build()?;
+        self.get_or_init_odp_mr(&pre_id);
+        let odp_mr = self.odp_mr.as_mut().unwrap();
+        let endpoint = DatagramEndpoint::new(pre_id, &config, odp_mr, &self.salloc.addr_mediator)?;
+        self.datagram = Some(endpoint);
+        Ok(())
+    }
+
+    /// Allocates the buffers the messages of a new datagram connection are copied into.
+    fn prepare_datagram_buffers(
+        &mut self,
+    ) -> Result<(Arc<BufferSlab>, Vec<ReadHeapRegion>, Vec<RawFd>), ControlPathError> {
+        let slab = Arc::new(BufferSlab::new(
+            self.datagram_config.peer_buffers,
+            self.datagram_config.mtu,
+            4096,
+            &self.salloc.addr_mediator,
+        )?);
+        let region = slab.storage();
+        let read_regions = vec![ReadHeapRegion {
+            handle: region.as_handle(),
+            addr: region.as_ptr().addr(),
+            len: region.len(),
+            file_off: 0,
+        }];
+        let fds = vec![region.memfd().as_raw_fd()];
+        self.state
+            .resource()
+            .recv_buffer_pool
+            .replenish(Arc::clone(&slab));
+        Ok((slab, read_regions, fds))
+    }
+
+    /// Returns the settings of a connection accepted by a listener bound with `options`.
+    fn connection_settings(&self, options: &BindOptions) -> Settings {
+        let mut settings = self.settings;
+        if let Some((count, size)) = options.recv_buffers {
+            settings.initial_credits = count as u32;
+            // the segments are copied out of the buffers of the shared receive queue
+            if self.srq.is_none() {
+                settings.recv_buffer_size = size as u32;
+            }
+        }
+        settings
+    }
+
+    /// Creates the shared receive queue on the device of `builder` on the first connection, if
+    /// `srq.enable` is set. Falls back to posting the receive buffers on each QP if the transport
+    /// does not support it.
+    fn get_or_init_srq(
+        &mut self,
+        builder: &ulib::ucm::CmIdBuilder,
+    ) -> Result<(), ControlPathError> {
+        if !self.srq_config.enable || self.srq.is_some() {
+            return Ok(());
+        }
+        let pd = builder.get_default_pd()?;
+        match pd.create_srq(self.srq_config.buffers as u32) {
+            Ok(srq) => {
+                let srq = SharedRecvQueue::new(srq, &self.srq_config, &self.salloc.addr_mediator)?;
+                self.srq = Some(srq);
+            }
+            Err(ulib::Error::SrqUnsupported) => {
+                log::warn!("Shared receive queues are not supported, falling back to per-QP mode");
+                self.srq_config.enable = false;
+                self.settings.recv_buffer_size = gather::RECV_BUFFER_SIZE as u32;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Checks the options of a new listener.
+    fn validate_bind_options(options: &BindOptions) -> Result<(), ControlPathError> {
+        if let Some((count, size)) = options.recv_buffers {
+            if count == 0 || count > u32::MAX as usize {
+                return Err(ControlPathError::BindOptions(format!(
+                    "invalid number of receive buffers: {}",
+                    count
+                )));
+            }
+            // a receive buffer must hold a message meta with its fragment table, and the buffers
+            // are page-aligned
+            let min_size = mem::size_of::<MetaBuffer>().max(4096);
+            if !size.is_power_of_two() || size < min_size || size > u32::MAX as usize {
+                return Err(ControlPathError::BindOptions(format!(
+                    "receive buffer size {} is not a power of two in [{}, {}]",
+                    size,
+                    min_size,
+                    u32::MAX
+                )));
+            }
+            if count.saturating_mul(size) > MAX_RECV_BUFFER_BYTES {
+                return Err(ControlPathError::BindOptions(format!(
+                    "{} receive buffers of {} bytes exceed {} bytes per connection",
+                    count, size, MAX_RECV_BUFFER_BYTES
+                )));
+            }
+        }
+        if options.max_connections == Some(0) {
+            return Err(ControlPathError::BindOptions(
+                "max_connections must be positive".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
     fn prepare_recv_buffers(
         &mut self,
         pre_id: &mut ulib::ucm::PreparedCmId,
+        settings: &Settings,
     ) -> Result<(Vec<ReadHeapRegion>, Vec<RawFd>), ControlPathError> {
-        // create 128 receive mrs, post recv requests
-        let slab = BufferSlab::new(
-            128,
-            8 * 1024 * 1024,
-            8 * 1024 * 1024,
+        // create `initial_credits` receive mrs, and post up to low_watermark recv requests, the
+        // rest are posted as the load grows
+        let num_buffers = settings.initial_credits as usize;
+        let buffer_size = settings.recv_buffer_size as usize;
+        let slab = Arc::new(BufferSlab::new(
+            num_buffers,
+            buffer_size,
+            buffer_size,
             &self.salloc.addr_mediator,
-        )?;
+        )?);
+        // with a shared receive queue, the segments are copied into the buffers when they arrive
+        let posted = if self.srq.is_some() {
+            0
+        } else {
+            self.recv_buffers.low_watermark.min(num_buffers)
+        };
 
         // post receives
-        for _ in 0..128 {
+        for _ in 0..posted {
             let odp_mr = self.get_or_init_odp_mr(pre_id);
 
-            // This is fine because we just allocated 128 buffers there
+            // This is fine because we just allocated `num_buffers` buffers there
             let recv_buffer = slab.obtain().unwrap();
 
             let handle = recv_buffer.as_handle();
@@ -965,15 +3587,41 @@ impl RpcAdapterEngine {
                 .insert(handle, recv_buffer)?;
         }
 
-        let region = slab.storage();
-        let read_regions = vec![ReadHeapRegion {
-            handle: region.as_handle(),
-            addr: region.as_ptr().addr(),
-            len: region.len(),
-            file_off: 0,
-        }];
-        let fds = vec![region.memfd().as_raw_fd()];
+        // the gather buffers are read by the application like the receive buffers
+        let gather_region = self.state.local_resource().gather_buffers.allocate(
+            pre_id.as_handle(),
+            &self.gather_config,
+            &self.salloc.addr_mediator,
+        )?;
 
+        let region = slab.storage();
+        let read_regions = [&region, &gather_region]
+            .into_iter()
+            .map(|region| ReadHeapRegion {
+                handle: region.as_handle(),
+                addr: region.as_ptr().addr(),
+                len: region.len(),
+                file_off: 0,
+            })
+            .collect();
+        let fds = vec![
+            region.memfd().as_raw_fd(),
+            gather_region.memfd().as_raw_fd(),
+        ];
+
+        if self.srq.is_some() {
+            let qpn = pre_id.qp_num()?;
+            self.get_or_init_odp_mr(pre_id);
+            let srq = self.srq.as_mut().unwrap();
+            srq.open(pre_id.as_handle(), qpn, Arc::clone(&slab));
+            srq.fill(self.odp_mr.as_mut().unwrap())?;
+        } else {
+            self.state.local_resource().recv_windows.open(
+                pre_id.as_handle(),
+                Arc::clone(&slab),
+                posted,
+            );
+        }
         // don't forget this
         self.state.resource().recv_buffer_pool.replenish(slab);
 
@@ -1002,51 +3650,164 @@ impl RpcAdapterEngine {
     ) -> Result<cmd::CompletionKind, ControlPathError> {
         match req {
             cmd::Command::SetTransport(_) => {
-                unreachable!();
+                unreachable!("SetTransport is handled by the mRPC engine");
             }
-            cmd::Command::Connect(addr) => {
-                log::debug!("Connect, addr: {:?}", addr);
+            cmd::Command::Connect(addr, options) => {
+                log::debug!("Connect, addr: {:?}, options: {:?}", addr, options);
                 // create CmIdBuilder
-                let mut builder = ulib::ucm::CmIdBuilder::new()
+                let mut builder = ulib::ucm::CmIdBuilder::new();
+                builder
                     .set_max_send_wr(128)
-                    .set_max_recv_wr(128)
-                    .set_max_inline_data(MAX_INLINE_DATA as u32)
-                    .resolve_route(addr)
-                    .await?;
+                    .set_max_recv_wr(self.recv_buffers.high_watermark as u32)
+                    .set_max_inline_data(MAX_INLINE_DATA as u32);
+                if let Some(local_addr) = options.local_addr {
+                    builder.set_local_addr(local_addr);
+                }
+                if let Some(nic_index) = options.nic_index {
+                    builder.set_nic_index(nic_index);
+                }
+                if let Some((first, last)) = options.port_range {
+                    builder.set_port_range(first, last);
+                }
+                if let Some(tos) = options.tos {
+                    builder.set_tos(tos);
+                }
+                let mut builder = builder.resolve_route(addr).await?;
 
                 // create or get CQ
+                self.get_or_init_srq(&builder)?;
                 let cq = self.state.get_or_init_cq(2048, 0, &builder)?;
 
                 builder.set_send_cq(cq).set_recv_cq(cq);
+                if let Some(srq) = self.srq.as_ref() {
+                    builder.set_srq(srq.srq());
+                }
                 let mut pre_id = builder.build()?;
 
                 // prepare and post receive buffers
-                let (read_regions, fds) = self.prepare_recv_buffers(&mut pre_id)?;
+                let settings = self.settings;
+                let (read_regions, fds) = self.prepare_recv_buffers(&mut pre_id, &settings)?;
                 // connect
                 let id = pre_id.connect(None).await?;
                 let handle = id.as_handle();
+                let peer_addr = id.get_peer_addr().ok();
+                let tx_order = self.tx_queue_config.order_of(peer_addr);
+                let max_inline_data = id.max_inline_data()? as usize;
 
                 // insert resources after connection establishment
-                self.state.local_resource().insert_cmid(id, 128)?;
+                self.post_settings(&id, &settings)?;
+                self.state.local_resource().insert_cmid(
+                    id,
+                    settings,
+                    tx_order,
+                    max_inline_data,
+                    None,
+                )?;
+                if let Some(peer_addr) = peer_addr {
+                    self.log_conn_event(handle, ConnEventKind::Connected(peer_addr));
+                }
                 let conn_resp = ConnectResponse {
                     conn_handle: handle,
                     read_regions,
+                    peer_addr,
+                };
+                Ok(cmd::CompletionKind::ConnectInternal(conn_resp, fds))
+            }
+            cmd::Command::ConnectDatagram(addr) => {
+                log::debug!("ConnectDatagram, addr: {:?}", addr);
+                if self.datagram.is_none() {
+                    self.open_datagram(addr, false).await?;
+                }
+                let conn_id = datagram::new_conn_id();
+                let remote = self
+                    .datagram
+                    .as_ref()
+                    .unwrap()
+                    .handshake(addr, conn_id, self.settings.features, &self.datagram_config)
+                    .await?;
+                let (slab, read_regions, fds) = self.prepare_datagram_buffers()?;
+                self.datagram
+                    .as_mut()
+                    .unwrap()
+                    .add_peer(conn_id, &remote, slab, None)?;
+                let conn_resp = ConnectResponse {
+                    conn_handle: conn_id,
+                    read_regions,
+                    peer_addr: Some(*addr),
                 };
                 Ok(cmd::CompletionKind::ConnectInternal(conn_resp, fds))
             }
-            cmd::Command::Bind(addr) => {
+            cmd::Command::Bind(addr, options) if options.datagram => {
+                Self::validate_bind_options(options)?;
+                // the address picks the device of the endpoint
+                if addr.ip().is_unspecified() {
+                    return Err(ControlPathError::BindOptions(
+                        "a datagram listener must bind to a specific address".to_owned(),
+                    ));
+                }
+                if self.datagram.is_none() {
+                    self.open_datagram(addr, true).await?;
+                }
+                let endpoint = self.datagram.as_mut().unwrap();
+                endpoint.listen(addr)?;
+                Ok(cmd::CompletionKind::Bind(endpoint.as_handle()))
+            }
+            cmd::Command::Bind(addr, options) => {
+                Self::validate_bind_options(options)?;
+                // Engines of the same process that bind to the same address share the listener.
+                let rpc_adapter_id = self.state.rpc_adapter_id;
+                let resource = self.state.resource();
+                if let Some(handle) = resource.join_listener(addr, options, rpc_adapter_id) {
+                    self.listeners.insert(handle);
+                    return Ok(cmd::CompletionKind::Bind(handle));
+                }
                 // create CmIdBuilder
-                let listener = ulib::ucm::CmIdBuilder::new().bind(addr).await?;
+                let mut builder = ulib::ucm::CmIdBuilder::new();
+                if let Some(backlog) = options.accept_backlog {
+                    builder.set_backlog(backlog.min(i32::MAX as u32) as i32);
+                }
+                let listener = match builder.bind(addr).await {
+                    Ok(listener) => listener,
+                    // another engine may have bound the address in the meantime
+                    Err(e) => match resource.join_listener(addr, options, rpc_adapter_id) {
+                        Some(handle) => {
+                            self.listeners.insert(handle);
+                            return Ok(cmd::CompletionKind::Bind(handle));
+                        }
+                        None => return Err(e.into()),
+                    },
+                };
                 let handle = listener.as_handle();
-                self.state
-                    .resource()
-                    .listener_table
-                    .insert(handle, (self.state.rpc_adapter_id, listener))?;
+                let listener = SharedListener::new(*addr, *options, rpc_adapter_id, listener);
+                resource.listener_table.insert(handle, listener)?;
+                self.listeners.insert(handle);
                 Ok(cmd::CompletionKind::Bind(handle))
             }
+            cmd::Command::Unbind(listener) => {
+                let datagram = self
+                    .datagram
+                    .as_mut()
+                    .filter(|endpoint| endpoint.as_handle() == *listener);
+                if let Some(endpoint) = datagram {
+                    if !endpoint.unlisten() {
+                        return Err(ResourceError::NotFound.into());
+                    }
+                } else if self.listeners.remove(listener) {
+                    let rpc_adapter_id = self.state.rpc_adapter_id;
+                    self.state
+                        .resource()
+                        .leave_listener(listener, rpc_adapter_id);
+                } else {
+                    return Err(ResourceError::NotFound.into());
+                }
+                Ok(cmd::CompletionKind::Unbind)
+            }
             cmd::Command::NewMappedAddrs(conn_handle, app_vaddrs) => {
                 for (mr_handle, app_vaddr) in app_vaddrs.iter() {
-                    let region = self.state.resource().recv_buffer_pool.find(mr_handle)?;
+                    let region = match self.state.local_resource().gather_buffers.find(mr_handle) {
+                        Some(region) => region,
+                        None => self.state.resource().recv_buffer_pool.find(mr_handle)?,
+                    };
                     let mr_local_addr = region.as_ptr().expose_addr();
                     let mr_remote_mapped = mrpc_marshal::ShmRecvMr {
                         ptr: *app_vaddr,
@@ -1059,31 +3820,99 @@ impl RpcAdapterEngine {
                         .insert_addr_map(mr_local_addr, mr_remote_mapped)?;
                 }
                 // finish the last step to establish a connection
-                if let Ok(Some(pre_id)) = self
+                if let Ok(Some(staged)) = self
                     .state
                     .resource()
                     .staging_pre_cmid_table
                     .close_resource(conn_handle)
                 {
+                    let StagedConnection {
+                        pre_id,
+                        settings,
+                        listener,
+                        slot,
+                    } = Arc::try_unwrap(staged).unwrap();
                     // accept connection after we get the AddrMap updated
-                    let id = Arc::try_unwrap(pre_id).unwrap().accept(None).await?;
+                    let id = pre_id.accept(None).await?;
+                    let peer_addr = id.get_peer_addr().ok();
+                    let tx_order = self.tx_queue_config.order_of(peer_addr);
+                    let max_inline_data = id.max_inline_data()? as usize;
                     // insert resources after connection establishment
-                    self.state.local_resource().insert_cmid(id, 128)?;
+                    self.post_settings(&id, &settings)?;
+                    self.state.local_resource().insert_cmid(
+                        id,
+                        settings,
+                        tx_order,
+                        max_inline_data,
+                        Some((listener, slot)),
+                    )?;
+                    if let Some(peer_addr) = peer_addr {
+                        self.log_conn_event(*conn_handle, ConnEventKind::Accepted(peer_addr));
+                    }
+                }
+                // a connection taken over from another engine is ready once its buffers are
+                // mapped
+                if let Some(conn) = self.migration.incoming.remove(conn_handle) {
+                    self.activate_migrated(conn)?;
+                }
+                // the peer of a datagram connection starts sending once it is answered
+                if let Some(endpoint) = self.datagram.as_mut() {
+                    endpoint.reply(conn_handle, self.settings.features);
                 }
                 Ok(cmd::CompletionKind::NewMappedAddrs)
             }
-            cmd::Command::UpdateProtosInner(dylib) => {
-                log::debug!("Loading dispatch library: {:?}", dylib);
+            cmd::Command::UpdateProtosInner(dylib, version) => {
+                log::debug!("Loading dispatch library: {:?}, version {}", dylib, version);
                 let module = SerializationEngine::new(dylib)?;
-                self.serialization_engine = Some(module);
+                self.dispatch_tables.update(module, *version);
                 Ok(cmd::CompletionKind::UpdateProtos)
             }
+            cmd::Command::RetireProtosInner(version) => {
+                if !self.dispatch_tables.retire(*version) {
+                    log::warn!("dispatch library version {} is not loaded", version);
+                }
+                Ok(cmd::CompletionKind::RetireProtosInner)
+            }
+            cmd::Command::QueryCredits(handles) => {
+                let cmid_table = &self.state.local_resource().cmid_table;
+                let credits = handles
+                    .iter()
+                    .map(|handle| {
+                        let credit = cmid_table.get(handle).ok().map(|conn_ctx| {
+                            conn_ctx.credit.load(Ordering::Acquire).max(0) as usize
+                        });
+                        (*handle, credit)
+                    })
+                    .collect();
+                Ok(cmd::CompletionKind::QueryCredits(credits))
+            }
+            cmd::Command::SetCallTiming(handles, enable) => {
+                let cmid_table = &self.state.local_resource().cmid_table;
+                for handle in handles {
+                    if let Ok(conn_ctx) = cmid_table.get(handle) {
+                        conn_ctx.call_timing.store(*enable, Ordering::Relaxed);
+                    }
+                }
+                Ok(cmd::CompletionKind::SetCallTiming)
+            }
+            cmd::Command::SetEagerCopyThreshold(handles, threshold) => {
+                let cmid_table = &self.state.local_resource().cmid_table;
+                for handle in handles {
+                    if let Ok(conn_ctx) = cmid_table.get(handle) {
+                        *conn_ctx.eager_copy_threshold.lock() = *threshold;
+                    }
+                }
+                Ok(cmd::CompletionKind::SetEagerCopyThreshold)
+            }
             cmd::Command::UpdateProtos(_) => {
                 unreachable!();
             }
             cmd::Command::MultiConnect(_) => {
                 unreachable!();
             }
+            cmd::Command::ConnectHost(..) => {
+                unreachable!();
+            }
         }
     }
 }
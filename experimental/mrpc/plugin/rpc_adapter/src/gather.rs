@@ -0,0 +1,343 @@
+//! Gathering of the fragmented segments of the received messages.
+//!
+//! A receive buffer holds at most `RECV_BUFFER_SIZE` bytes, unless set otherwise for the listener
+//! that accepts the connection, and the ends tell each other the size of their receive buffers in
+//! the settings. The sender splits a segment larger than the receive buffers of the peer into
+//! fragments, each received into its own buffer, and describes the original
+//! segments in a fragment table that follows the `MessageMeta` in the first send. The unmarshaller
+//! expects every field buffer in a single segment, so before a fragmented message is delivered,
+//! the fragments of each segment are copied into a gather buffer, a contiguous buffer in a region
+//! mapped into the application like the receive buffers. The receive buffers of the fragments are
+//! reposted right away, and the gather buffer is released when the application reclaims the
+//! message.
+//!
+//! Segments that fit in a receive buffer stay where they are received, so the messages that are
+//! not fragmented are delivered without copying. The payloads of the messages sent in bulk are
+//! read into the gather buffers too, see `bulk`.
+use std::cell::RefCell;
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+
+use fnv::FnvHashMap;
+use thiserror::Error;
+
+use mrpc_marshal::{SgE, SgList};
+use phoenix_api::rpc::MessageMeta;
+use phoenix_api::{AsHandle, Handle};
+use phoenix_salloc::region::{AddressMediator, SharedRegion};
+
+use phoenix_common::engine::datapath::meta_pool::MetaBuffer;
+
+use super::config::GatherBufferConfig;
+use super::pool::{BufferSlab, RecvBuffer};
+use super::state::RecvContext;
+use super::ControlPathError;
+
+/// The default size of a receive buffer, i.e., the largest segment that is received without
+/// fragmentation.
+pub(crate) const RECV_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Error)]
+pub(crate) enum GatherError {
+    #[error("segment of {0} bytes exceeds the gather buffer")]
+    TooLarge(usize),
+    #[error("no gather buffer available")]
+    NoBuffer,
+    #[error("fragment table does not match the received segments")]
+    Malformed,
+}
+
+/// Returns the number of sends a segment of `len` bytes takes with receive buffers of
+/// `fragment_size` bytes.
+#[inline]
+pub(crate) fn num_fragments(len: usize, fragment_size: usize) -> usize {
+    ((len + fragment_size - 1) / fragment_size).max(1)
+}
+
+/// Returns whether any segment of `sglist` has to be fragmented.
+#[inline]
+pub(crate) fn is_fragmented(sglist: &SgList, fragment_size: usize) -> bool {
+    sglist.0.iter().any(|sge| sge.len > fragment_size)
+}
+
+/// Writes the fragment table of `sglist`, i.e., the lengths of its segments, after the meta in
+/// `meta_buf`. Returns the number of bytes to send from `meta_buf`, or `None` if the table does
+/// not fit.
+pub(crate) fn write_fragment_table(meta_buf: &mut MetaBuffer, sglist: &SgList) -> Option<usize> {
+    if sglist.0.len() * mem::size_of::<u32>() > MetaBuffer::capacity()
+        || sglist.0.iter().any(|sge| sge.len > u32::MAX as usize)
+    {
+        return None;
+    }
+
+    meta_buf.num_sge = sglist.0.len() as u32;
+    meta_buf.value_len = 0;
+    let lens_buf = meta_buf.length_delimited.as_mut_ptr().cast::<u32>();
+    for (i, sge) in sglist.0.iter().enumerate() {
+        // SAFETY: the table fits in the buffer
+        unsafe { lens_buf.add(i).write(sge.len as u32) };
+    }
+    Some(meta_buf.len())
+}
+
+/// Reads the fragment table from the first segment of a fragmented message.
+///
+/// # Safety
+///
+/// The segment must point to a receive buffer.
+unsafe fn read_fragment_table(meta_sge: &SgE) -> Result<Vec<usize>, GatherError> {
+    let received = meta_sge.len;
+    let header = mem::size_of::<MessageMeta>() + 2 * mem::size_of::<u32>();
+    if received < header {
+        return Err(GatherError::Malformed);
+    }
+    let meta_buf = &*(meta_sge.ptr as *const MetaBuffer);
+    let table_len = meta_buf.num_sge as usize * mem::size_of::<u32>();
+    if table_len > MetaBuffer::capacity() || received != header + table_len {
+        return Err(GatherError::Malformed);
+    }
+
+    let (_prefix, lens, _suffix): (_, &[u32], _) = meta_buf.lens_buffer().align_to();
+    debug_assert!(_prefix.is_empty() && _suffix.is_empty());
+    Ok(lens.iter().map(|&len| len as usize).collect())
+}
+
+/// The gather buffers of the connections of an engine.
+#[derive(Default)]
+pub(crate) struct GatherBuffers {
+    // conn_id -> the gather buffers of the connection
+    slabs: RefCell<FnvHashMap<Handle, Arc<BufferSlab>>>,
+    // the gather buffers held by the received messages
+    in_use: RefCell<FnvHashMap<Handle, (Arc<BufferSlab>, RecvBuffer)>>,
+}
+
+impl GatherBuffers {
+    /// Allocates the gather buffers of a connection. Returns the region to map into the
+    /// application.
+    pub(crate) fn allocate(
+        &self,
+        conn_id: Handle,
+        config: &GatherBufferConfig,
+        addr_mediator: &AddressMediator,
+    ) -> Result<Arc<SharedRegion>, ControlPathError> {
+        let slab = BufferSlab::new(config.count, config.size, config.size, addr_mediator)?;
+        let region = slab.storage();
+        self.slabs.borrow_mut().insert(conn_id, Arc::new(slab));
+        Ok(region)
+    }
+
+    /// Takes the gather buffers of a connection that moves to another engine. The buffers must
+    /// not be in use.
+    pub(crate) fn take(&self, conn_id: &Handle) -> Option<Arc<BufferSlab>> {
+        self.slabs.borrow_mut().remove(conn_id)
+    }
+
+    /// Puts the gather buffers of a connection taken over from another engine.
+    pub(crate) fn put(&self, conn_id: Handle, slab: Arc<BufferSlab>) {
+        self.slabs.borrow_mut().insert(conn_id, slab);
+    }
+
+    /// Returns the region of gather buffers with the handle.
+    pub(crate) fn find(&self, handle: &Handle) -> Option<Arc<SharedRegion>> {
+        self.slabs
+            .borrow()
+            .values()
+            .map(|slab| slab.storage())
+            .find(|region| &region.as_handle() == handle)
+    }
+
+    /// Releases a gather buffer. Returns `false` if `handle` is not a gather buffer in use.
+    pub(crate) fn release(&self, handle: &Handle) -> bool {
+        match self.in_use.borrow_mut().remove(handle) {
+            Some((slab, buffer)) => {
+                slab.release(buffer);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes a gather buffer of the connection to hold `len` bytes. Returns the address and the
+    /// handle of the buffer, which is in use until it is released.
+    pub(crate) fn obtain(
+        &self,
+        conn_id: &Handle,
+        len: usize,
+    ) -> Result<(usize, Handle), GatherError> {
+        let slab = self
+            .slabs
+            .borrow()
+            .get(conn_id)
+            .cloned()
+            .ok_or(GatherError::NoBuffer)?;
+        if len > slab.buffer_size() {
+            return Err(GatherError::TooLarge(len));
+        }
+        let buffer = slab.obtain().ok_or(GatherError::NoBuffer)?;
+        let (addr, handle) = (buffer.addr(), buffer.as_handle());
+        self.in_use.borrow_mut().insert(handle, (slab, buffer));
+        Ok((addr, handle))
+    }
+
+    /// Copies `fragments` into a gather buffer of the connection. Returns the gathered segment and
+    /// the handle of the buffer.
+    fn gather(&self, conn_id: &Handle, fragments: &[SgE]) -> Result<(SgE, Handle), GatherError> {
+        let len = fragments.iter().map(|sge| sge.len).sum();
+        let (addr, handle) = self.obtain(conn_id, len)?;
+
+        let mut off = addr;
+        for sge in fragments {
+            // SAFETY: the fragments are in the receive buffers, and they fit in the gather buffer
+            unsafe { ptr::copy_nonoverlapping(sge.ptr as *const u8, off as *mut u8, sge.len) };
+            off += sge.len;
+        }
+
+        Ok((SgE { ptr: addr, len }, handle))
+    }
+
+    /// Replaces the fragments of each segment of a fragmented message with a gathered segment.
+    /// `fragment_size` is the size of the receive buffers of the connection. Returns the handles
+    /// of the receive buffers of the gathered fragments, which can be reposted. The received
+    /// segments are left as they are on error.
+    pub(crate) fn gather_message(
+        &self,
+        conn_id: &Handle,
+        recv_ctx: &mut RecvContext,
+        fragment_size: usize,
+    ) -> Result<Vec<Handle>, GatherError> {
+        let RecvContext {
+            sg_list,
+            recv_buffer_handles: handles,
+            ..
+        } = recv_ctx;
+
+        // SAFETY: the first segment of a fragmented message holds the fragment table
+        let lens = unsafe { read_fragment_table(&sg_list.0[0]) }?;
+        let num_sends: usize = 1 + lens
+            .iter()
+            .map(|&len| num_fragments(len, fragment_size))
+            .sum::<usize>();
+        if num_sends != sg_list.0.len() || num_sends != handles.len() {
+            return Err(GatherError::Malformed);
+        }
+
+        // the first segment is trimmed to the meta
+        let meta_sge = SgE {
+            ptr: sg_list.0[0].ptr,
+            len: mem::size_of::<MessageMeta>(),
+        };
+        let mut gathered_sgl = vec![meta_sge];
+        let mut gathered_handles = vec![handles[0]];
+        let mut reposts = Vec::new();
+        let mut i = 1;
+        for len in lens {
+            let n = num_fragments(len, fragment_size);
+            let fragments = &sg_list.0[i..i + n];
+            let result = if fragments.iter().map(|sge| sge.len).sum::<usize>() != len {
+                Err(GatherError::Malformed)
+            } else if n == 1 {
+                Ok((fragments[0], handles[i]))
+            } else {
+                self.gather(conn_id, fragments)
+            };
+            match result {
+                Ok((sge, handle)) => {
+                    gathered_sgl.push(sge);
+                    gathered_handles.push(handle);
+                    if n > 1 {
+                        reposts.extend_from_slice(&handles[i..i + n]);
+                    }
+                }
+                Err(e) => {
+                    for handle in &gathered_handles {
+                        self.release(handle);
+                    }
+                    return Err(e);
+                }
+            }
+            i += n;
+        }
+
+        sg_list.0 = gathered_sgl;
+        *handles = gathered_handles;
+        Ok(reposts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta_buffer() -> Box<MetaBuffer> {
+        // SAFETY: all zeros is a valid MetaBuffer
+        Box::new(unsafe { mem::zeroed() })
+    }
+
+    fn sglist(lens: &[usize]) -> SgList {
+        SgList(lens.iter().map(|&len| SgE { ptr: 0, len }).collect())
+    }
+
+    #[test]
+    fn fragments() {
+        assert_eq!(num_fragments(0, 4096), 1);
+        assert_eq!(num_fragments(4096, 4096), 1);
+        assert_eq!(num_fragments(4097, 4096), 2);
+        assert_eq!(num_fragments(3 * 4096, 4096), 3);
+        assert!(!is_fragmented(&sglist(&[10, 4096]), 4096));
+        assert!(is_fragmented(&sglist(&[10, 4097]), 4096));
+    }
+
+    #[test]
+    fn table_round_trip() {
+        let lens = [10, 0, 1 << 20];
+        let mut meta_buf = meta_buffer();
+        let post_len = write_fragment_table(&mut meta_buf, &sglist(&lens)).unwrap();
+        let sge = SgE {
+            ptr: &*meta_buf as *const MetaBuffer as usize,
+            len: post_len,
+        };
+        let table = unsafe { read_fragment_table(&sge) }.unwrap();
+        assert_eq!(table, lens);
+    }
+
+    #[test]
+    fn table_too_large() {
+        let mut meta_buf = meta_buffer();
+        let num_sge = MetaBuffer::capacity() / mem::size_of::<u32>() + 1;
+        assert!(write_fragment_table(&mut meta_buf, &sglist(&vec![1; num_sge])).is_none());
+        assert!(write_fragment_table(&mut meta_buf, &sglist(&[u32::MAX as usize + 1])).is_none());
+    }
+
+    #[test]
+    fn malformed_table() {
+        let mut meta_buf = meta_buffer();
+        let post_len = write_fragment_table(&mut meta_buf, &sglist(&[10, 20])).unwrap();
+        let ptr = &*meta_buf as *const MetaBuffer as usize;
+        // truncated
+        let sge = SgE {
+            ptr,
+            len: post_len - 1,
+        };
+        assert!(matches!(
+            unsafe { read_fragment_table(&sge) },
+            Err(GatherError::Malformed)
+        ));
+        // shorter than the header
+        let sge = SgE {
+            ptr,
+            len: mem::size_of::<MessageMeta>(),
+        };
+        assert!(matches!(
+            unsafe { read_fragment_table(&sge) },
+            Err(GatherError::Malformed)
+        ));
+        // a table larger than the buffer
+        meta_buf.num_sge = u32::MAX;
+        let sge = SgE { ptr, len: post_len };
+        assert!(matches!(
+            unsafe { read_fragment_table(&sge) },
+            Err(GatherError::Malformed)
+        ));
+    }
+}
@@ -2,6 +2,7 @@
 #![feature(strict_provenance)]
 #![feature(local_key_cell_methods)]
 #![feature(peer_credentials_unix_socket)]
+#![cfg_attr(test, feature(test))]
 
 //! this engine translate RPC messages into transport-level work requests / completions
 use std::alloc::LayoutError;
@@ -10,6 +11,9 @@ use thiserror::Error;
 
 use phoenix_salloc::region;
 
+#[cfg(test)]
+extern crate test;
+
 // Re-export PhoenixModule
 use phoenix_common::resource::Error as ResourceError;
 pub use phoenix_common::{InitFnResult, PhoenixModule};
@@ -18,10 +22,25 @@ pub mod module;
 pub mod state;
 
 pub(crate) mod acceptor;
+pub(crate) mod bulk;
+pub(crate) mod checksum;
 pub mod config;
+pub(crate) mod congestion;
+pub(crate) mod conn_log;
+pub(crate) mod datagram;
+pub(crate) mod device_mr;
 pub(crate) mod engine;
+pub(crate) mod gather;
+pub(crate) mod migrate;
+pub(crate) mod recent_errors;
+pub(crate) mod recv_window;
 pub(crate) mod serialization;
+pub(crate) mod settings;
+pub(crate) mod srq;
+pub(crate) mod tx_queue;
 pub(crate) mod ulib;
+pub(crate) mod user_mr;
+pub(crate) mod validate;
 
 #[allow(unused)]
 pub(crate) mod pool;
@@ -40,6 +59,10 @@ pub(crate) enum ControlPathError {
     SharedRegion(#[from] region::Error),
     #[error("{0}")]
     InsertAddrMap(#[from] mrpc_marshal::AddressExists),
+    #[error("Invalid bind options: {0}")]
+    BindOptions(String),
+    #[error("No answer to the datagram handshake from {0}")]
+    HandshakeTimeout(std::net::SocketAddr),
 
     // Below are errors that does not return to the user.
     #[error("Send command error")]
@@ -74,11 +97,15 @@ pub(crate) enum DatapathError {
     Ulib(#[from] ulib::Error),
     #[error("Tx queue send error: {0}")]
     Tx(#[from] phoenix_common::engine::datapath::SendError<EngineTxMessage>),
+    #[error("Dispatch error: {0}")]
+    Dispatch(#[from] serialization::DispatchError),
 }
 
 use crate::config::RpcAdapterConfig;
 use crate::module::RpcAdapterModule;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let config = RpcAdapterConfig::new(config_string)?;
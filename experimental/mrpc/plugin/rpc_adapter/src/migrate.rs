@@ -0,0 +1,444 @@
+//! Moving connections between the RpcAdapter engines of an application.
+//!
+//! The threads of a server that bind to the same address share a listener, which spreads the
+//! connections among their engines as they arrive. A `MigrateConnection` request moves an
+//! established connection to another engine that has joined the listener, e.g., to balance the
+//! load of the threads, without the peer noticing. The move takes three steps:
+//!
+//! 1. The source quiesces the connection. The receives completed on it are held back rather than
+//!    delivered, and the source waits until nothing of the connection is in flight: no message
+//!    queued or waiting for its send to complete, no reply awaited, no receive buffer held by the
+//!    application, and no bulk transfer. It waits for [`GRACE`] more, so a handler that has just
+//!    returned its request sends the reply from the source. The migration is abandoned, and the
+//!    held receives are handled, if the connection is still busy after [`TIMEOUT`].
+//! 2. The source hands the state of the connection over in a [`MigratedConnection`]: the
+//!    connection context with its credits and settings, the posted receive buffers, the gather
+//!    buffers, and the held receives. It reports the connection as `Migrated` to its application,
+//!    and answers the messages that still come for it with `TransportStatus::MIGRATED`.
+//! 3. The destination takes the connection over in its slow path, and announces it to its
+//!    application like an accepted connection, with the same receive buffers. Once they are
+//!    mapped, the connection is put in the tables of the engine, and the held receives are
+//!    handled in the order they have arrived.
+//!
+//! A QP cannot be moved to another CQ, so the completions of a migrated connection still come out
+//! of the CQ of the engine that has created it. That engine forwards them to the engine owning the
+//! connection, and an engine that is dropped leaves its CQ to be polled by one of the others.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fnv::{FnvHashMap, FnvHashSet};
+use thiserror::Error;
+
+use phoenix_api::net::WorkCompletion;
+use phoenix_api::rpc::TransportStatus;
+use phoenix_api::Handle;
+
+use super::pool::{BufferSlab, RecvBuffer};
+use super::recv_window::RecvWindow;
+use super::state::{ConnectionContext, ConnectionRx, WrContext};
+use super::ulib;
+
+/// How long nothing of a connection must be in flight before it is handed over.
+pub(crate) const GRACE: Duration = Duration::from_millis(10);
+
+/// How long a connection can stay busy before its migration is abandoned.
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub(crate) enum MigrateError {
+    #[error("no such connection")]
+    NotFound,
+    #[error("the connection is already being migrated")]
+    InProgress,
+    #[error("the connection is on the engine already")]
+    SameEngine,
+    #[error("the connection is not accepted from a shared listener")]
+    NotAccepted,
+    #[error("engine {0} has not joined the listener of the connection")]
+    NotListening(usize),
+    #[error("the receives are taken from a shared receive queue")]
+    SharedRecvQueue,
+    #[error("the settings of the peer have not arrived")]
+    NotEstablished,
+    #[error("Ulib error {0}")]
+    Ulib(#[from] ulib::Error),
+}
+
+/// A connection being quiesced to move to another engine.
+#[derive(Debug)]
+pub(crate) struct Outgoing {
+    /// The rpc_adapter_id of the engine to move to
+    pub(crate) to: usize,
+    pub(crate) qp_num: u32,
+    pub(crate) started: Instant,
+    /// Since when nothing of the connection is in flight
+    pub(crate) idle_since: Option<Instant>,
+    /// The receives completed on the connection, held back until it is handed over
+    pub(crate) held: Vec<WorkCompletion>,
+}
+
+/// What to do with a connection being quiesced, see [`Outgoing::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Quiesce {
+    /// Something of the connection is in flight, or has been until less than [`GRACE`] ago
+    Wait,
+    /// Nothing of the connection has been in flight for [`GRACE`]
+    HandOver,
+    /// The connection is gone, or still busy after [`TIMEOUT`]
+    Abandon,
+}
+
+impl Outgoing {
+    /// Records whether anything of the connection is in flight at `now`, `busy` being `None` if
+    /// the connection is gone, and returns what to do with it.
+    pub(crate) fn check(&mut self, busy: Option<bool>, now: Instant) -> Quiesce {
+        match busy {
+            Some(true) => self.idle_since = None,
+            Some(false) if self.idle_since.is_none() => self.idle_since = Some(now),
+            _ => {}
+        }
+        let quiesced = self
+            .idle_since
+            .map_or(false, |t| now.saturating_duration_since(t) >= GRACE);
+        if busy.is_some() && quiesced {
+            Quiesce::HandOver
+        } else if busy.is_none() || now.saturating_duration_since(self.started) >= TIMEOUT {
+            Quiesce::Abandon
+        } else {
+            Quiesce::Wait
+        }
+    }
+}
+
+/// The state of a connection handed from one engine to another.
+pub(crate) struct MigratedConnection {
+    pub(crate) from: usize,
+    pub(crate) qp_num: u32,
+    pub(crate) conn_ctx: ConnectionContext,
+    pub(crate) rx: ConnectionRx,
+    /// The receive buffers posted on the connection, with the wr_ids they are posted with
+    pub(crate) recv_buffers: Vec<(WrContext, RecvBuffer)>,
+    pub(crate) window: Option<RecvWindow>,
+    pub(crate) gather: Option<Arc<BufferSlab>>,
+    /// The status the messages of the connection are dropped with, see `quarantined`
+    pub(crate) quarantined: Option<TransportStatus>,
+    /// The completions of the connection yet to be handled, in order
+    pub(crate) wcs: Vec<WorkCompletion>,
+}
+
+/// The CQ of an engine that is gone, and the completions still forwarded to it, with where to
+/// forward them.
+pub(crate) struct OrphanCq {
+    /// The rpc_adapter_id of the engine that is gone
+    pub(crate) id: usize,
+    pub(crate) cq: Option<ulib::uverbs::CompletionQueue>,
+    // qp_num -> the rpc_adapter_id of the engine owning the connection of the QP
+    pub(crate) forwarding: FnvHashMap<u32, usize>,
+}
+
+impl OrphanCq {
+    /// Returns the rpc_adapter_id of the engine owning the connection of a completion polled from
+    /// the CQ, or `None` if the connection has been closed.
+    #[inline]
+    pub(crate) fn route(&self, wc: &WorkCompletion) -> Option<usize> {
+        self.forwarding.get(&wc.qp_num).copied()
+    }
+}
+
+/// What an engine hands to another.
+pub(crate) enum Handoff {
+    Connection(MigratedConnection),
+    /// A completion of a connection the other engine owns, polled from the CQ of this one
+    Completion(WorkCompletion),
+}
+
+/// The connections and the completions handed between the engines of an application.
+#[derive(Default)]
+pub(crate) struct Migrations {
+    // rpc_adapter_id -> what is handed to the engine, in order, so a connection is taken over
+    // before the completions forwarded after it. A connection is not `Sync`, so this is not a
+    // `DashMap`.
+    inbox: spin::Mutex<FnvHashMap<usize, VecDeque<Handoff>>>,
+    // the CQs left by the engines that are gone
+    orphans: spin::Mutex<Vec<OrphanCq>>,
+}
+
+impl Migrations {
+    /// Hands a connection to the engine `to`.
+    pub(crate) fn hand(&self, to: usize, conn: MigratedConnection) {
+        self.inbox
+            .lock()
+            .entry(to)
+            .or_default()
+            .push_back(Handoff::Connection(conn));
+    }
+
+    /// Forwards a completion to the engine `to`.
+    pub(crate) fn forward(&self, to: usize, wc: WorkCompletion) {
+        self.inbox
+            .lock()
+            .entry(to)
+            .or_default()
+            .push_back(Handoff::Completion(wc));
+    }
+
+    /// Takes what is handed to the engine `id`.
+    pub(crate) fn take(&self, id: usize) -> VecDeque<Handoff> {
+        match self.inbox.lock().get_mut(&id) {
+            Some(queue) => std::mem::take(queue),
+            None => VecDeque::new(),
+        }
+    }
+
+    /// Drops what is handed to the engine `id`, which is gone.
+    pub(crate) fn forget(&self, id: usize) {
+        // the connections are closed out of the lock
+        let gone = self.inbox.lock().remove(&id);
+        drop(gone);
+    }
+
+    /// Leaves the CQ of an engine that is gone to the others.
+    pub(crate) fn leave_cq(&self, orphan: OrphanCq) {
+        self.orphans.lock().push(orphan);
+    }
+
+    /// Takes the CQs left by the engines that are gone.
+    pub(crate) fn take_orphans(&self) -> Vec<OrphanCq> {
+        std::mem::take(&mut *self.orphans.lock())
+    }
+}
+
+/// The migrations an engine takes part in.
+#[derive(Default)]
+pub(crate) struct Migration {
+    // conn_id -> the connections being moved out
+    pub(crate) outgoing: FnvHashMap<Handle, Outgoing>,
+    // conn_id -> the connections taken over, waiting for the application to map their buffers
+    pub(crate) incoming: FnvHashMap<Handle, MigratedConnection>,
+    // the connections taken over, yet to be announced to the application
+    pub(crate) arrived: Vec<Handle>,
+    // qp_num -> the rpc_adapter_id of the engine the completions of the QP are forwarded to
+    pub(crate) forwarding: FnvHashMap<u32, usize>,
+    // conn_id -> the rpc_adapter_id of the engine the connection has moved to
+    pub(crate) moved: FnvHashMap<Handle, usize>,
+    // the QPs of the connections taken over from the other engines
+    pub(crate) adopted: FnvHashSet<u32>,
+    // the CQs of the engines that are gone, polled by this one
+    pub(crate) orphans: Vec<OrphanCq>,
+    // the completions of the connections taken over or kept, handled before any other
+    pub(crate) replay: VecDeque<WorkCompletion>,
+}
+
+impl Migration {
+    /// Returns whether any completion polled by this engine may be held or forwarded.
+    #[inline]
+    pub(crate) fn diverts(&self) -> bool {
+        !self.outgoing.is_empty() || !self.incoming.is_empty() || !self.forwarding.is_empty()
+    }
+
+    /// Returns the number of the migrations in progress and the completions to replay.
+    #[inline]
+    pub(crate) fn pending(&self) -> usize {
+        self.outgoing.len() + self.incoming.len() + self.replay.len()
+    }
+
+    /// Returns whether any completion may be forwarded to this engine.
+    #[inline]
+    pub(crate) fn expects_forwarded(&self) -> bool {
+        !self.adopted.is_empty() || !self.orphans.is_empty() || !self.replay.is_empty()
+    }
+
+    /// Holds back a completion of a connection taken over, or a receive of a connection being
+    /// quiesced, as told by `is_recv`. The sends of a connection being quiesced still complete
+    /// here. Returns whether the completion is held.
+    pub(crate) fn hold<F>(&mut self, wc: &WorkCompletion, is_recv: F) -> bool
+    where
+        F: FnOnce(&WorkCompletion) -> bool,
+    {
+        if let Some(conn) = self
+            .incoming
+            .values_mut()
+            .find(|conn| conn.qp_num == wc.qp_num)
+        {
+            conn.wcs.push(*wc);
+            return true;
+        }
+        if let Some(outgoing) = self
+            .outgoing
+            .values_mut()
+            .find(|outgoing| outgoing.qp_num == wc.qp_num)
+        {
+            if is_recv(wc) {
+                outgoing.held.push(*wc);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Abandons the migration of `conn_id`. The receives held back are replayed after the
+    /// completions already to replay.
+    pub(crate) fn abandon(&mut self, conn_id: &Handle) -> Option<Outgoing> {
+        let mut outgoing = self.outgoing.remove(conn_id)?;
+        self.replay.extend(outgoing.held.drain(..));
+        Some(outgoing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phoenix_api::net::{WcOpcode, WcStatus};
+
+    fn wc(wr_id: u64, qp_num: u32, opcode: WcOpcode) -> WorkCompletion {
+        let mut wc = WorkCompletion::new_vendor_err(wr_id, WcStatus::Success, 0);
+        wc.opcode = opcode;
+        wc.qp_num = qp_num;
+        wc
+    }
+
+    fn quiescing(qp_num: u32, started: Instant) -> Outgoing {
+        Outgoing {
+            to: 2,
+            qp_num,
+            started,
+            idle_since: None,
+            held: Vec::new(),
+        }
+    }
+
+    fn wr_ids<'a>(wcs: impl IntoIterator<Item = &'a WorkCompletion>) -> Vec<u64> {
+        wcs.into_iter().map(|wc| wc.wr_id).collect()
+    }
+
+    #[test]
+    fn handed_over_after_grace() {
+        let start = Instant::now();
+        let mut outgoing = quiescing(7, start);
+        assert_eq!(outgoing.check(Some(false), start), Quiesce::Wait);
+        assert_eq!(
+            outgoing.check(Some(false), start + GRACE / 2),
+            Quiesce::Wait
+        );
+        assert_eq!(
+            outgoing.check(Some(false), start + GRACE),
+            Quiesce::HandOver
+        );
+    }
+
+    #[test]
+    fn busy_restarts_grace() {
+        let start = Instant::now();
+        let mut outgoing = quiescing(7, start);
+        assert_eq!(outgoing.check(Some(false), start), Quiesce::Wait);
+        assert_eq!(outgoing.check(Some(true), start + GRACE / 2), Quiesce::Wait);
+        assert_eq!(outgoing.idle_since, None);
+        assert_eq!(outgoing.check(Some(false), start + GRACE), Quiesce::Wait);
+        assert_eq!(
+            outgoing.check(Some(false), start + GRACE * 2),
+            Quiesce::HandOver
+        );
+    }
+
+    #[test]
+    fn abandoned_on_timeout() {
+        let start = Instant::now();
+        let mut outgoing = quiescing(7, start);
+        assert_eq!(
+            outgoing.check(Some(true), start + TIMEOUT / 2),
+            Quiesce::Wait
+        );
+        assert_eq!(
+            outgoing.check(Some(true), start + TIMEOUT),
+            Quiesce::Abandon
+        );
+        // a connection that turns idle only just before the timeout is not handed over
+        let mut outgoing = quiescing(7, start);
+        assert_eq!(
+            outgoing.check(Some(false), start + TIMEOUT),
+            Quiesce::Abandon
+        );
+    }
+
+    #[test]
+    fn abandoned_when_gone() {
+        let start = Instant::now();
+        let mut outgoing = quiescing(7, start);
+        assert_eq!(outgoing.check(Some(false), start), Quiesce::Wait);
+        assert_eq!(outgoing.check(None, start + GRACE), Quiesce::Abandon);
+    }
+
+    #[test]
+    fn held_receives_replayed_in_order() {
+        let start = Instant::now();
+        let mut migration = Migration::default();
+        migration.outgoing.insert(Handle(1), quiescing(7, start));
+        migration.replay.push_back(wc(100, 9, WcOpcode::Recv));
+
+        // the receives are held back, the sends complete here
+        assert!(migration.hold(&wc(1, 7, WcOpcode::Recv), |_| true));
+        assert!(!migration.hold(&wc(2, 7, WcOpcode::Send), |_| false));
+        assert!(migration.hold(&wc(3, 7, WcOpcode::Recv), |_| true));
+        // the completions of other QPs are not held
+        assert!(!migration.hold(&wc(4, 8, WcOpcode::Recv), |_| true));
+        assert_eq!(wr_ids(&migration.outgoing[&Handle(1)].held), [1, 3]);
+
+        let outgoing = migration.abandon(&Handle(1)).unwrap();
+        assert_eq!(outgoing.to, 2);
+        assert!(migration.outgoing.is_empty());
+        assert!(!migration.diverts());
+        assert_eq!(wr_ids(&migration.replay), [100, 1, 3]);
+        assert_eq!(migration.pending(), 3);
+        assert!(migration.abandon(&Handle(1)).is_none());
+    }
+
+    #[test]
+    fn orphan_cq_forwarded() {
+        let migrations = Migrations::default();
+        migrations.leave_cq(OrphanCq {
+            id: 0,
+            cq: None,
+            forwarding: [(7, 1), (8, 2)].into_iter().collect(),
+        });
+
+        let orphans = migrations.take_orphans();
+        assert_eq!(orphans.len(), 1);
+        assert!(migrations.take_orphans().is_empty());
+
+        // engine 1 polls the CQ left by engine 0 and forwards the completions of engine 2
+        let orphan = &orphans[0];
+        let polled = [
+            wc(1, 8, WcOpcode::Recv),
+            wc(2, 7, WcOpcode::Recv),
+            wc(3, 9, WcOpcode::Recv),
+            wc(4, 8, WcOpcode::Send),
+        ];
+        let mut handled = Vec::new();
+        for wc in &polled {
+            match orphan.route(wc) {
+                Some(1) => handled.push(wc.wr_id),
+                Some(to) => migrations.forward(to, *wc),
+                None => {}
+            }
+        }
+        assert_eq!(handled, [2]);
+        assert!(migrations.take(1).is_empty());
+
+        let forwarded: Vec<_> = migrations
+            .take(2)
+            .into_iter()
+            .map(|handoff| match handoff {
+                Handoff::Completion(wc) => wc.wr_id,
+                Handoff::Connection(_) => panic!("unexpected connection"),
+            })
+            .collect();
+        assert_eq!(forwarded, [1, 4]);
+        assert!(migrations.take(2).is_empty());
+
+        // what is handed to an engine that is gone is dropped
+        migrations.forward(3, polled[0]);
+        migrations.forget(3);
+        assert!(migrations.take(3).is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, bail, Result};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::Duration;
 
 use nix::unistd::Pid;
 
@@ -11,7 +12,6 @@ use phoenix_salloc::module::SallocModule;
 use phoenix_salloc::region::AddressMediator;
 use phoenix_salloc::state::{Shared as SallocShared, State as SallocState};
 use transport_rdma::module::RdmaTransportModule;
-use transport_rdma::ops::Ops;
 
 use phoenix_common::engine::datapath::DataPathNode;
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
@@ -23,30 +23,46 @@ use phoenix_common::state_mgr::SharedStateManager;
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 
 use crate::acceptor::engine::AcceptorEngine;
-use crate::config::RpcAdapterConfig;
+use crate::bulk::BulkTransfers;
+use crate::config::{
+    CongestionConfig, DatagramConfig, DispatchErrorPolicy, GatherBufferConfig, KeepaliveConfig,
+    RecvBufferConfig, RpcAdapterConfig, SrqConfig, TxQueueConfig,
+};
+use crate::congestion::CongestionControl;
 use crate::engine::{RpcAdapterEngine, TlStorage};
+use crate::gather::RECV_BUFFER_SIZE;
+use crate::settings::{Settings, FEATURE_BULK, FEATURE_CHECKSUM, FEATURE_CREDITS};
 use crate::state::{Shared, State};
+use crate::tx_queue::TxQueue;
+use crate::ulib::sim::SimTransport;
+use crate::ulib::Transport;
 
 pub(crate) struct AcceptorEngineBuilder {
     _client_pid: Pid,
     shared: Arc<Shared>,
     node: DataPathNode,
-    ops: Ops,
+    transport: Transport,
 }
 
 impl AcceptorEngineBuilder {
-    fn new(shared: Arc<Shared>, ops: Ops, client_pid: Pid, node: DataPathNode) -> Self {
+    fn new(shared: Arc<Shared>, transport: Transport, client_pid: Pid, node: DataPathNode) -> Self {
         AcceptorEngineBuilder {
             _client_pid: client_pid,
             shared,
             node,
-            ops,
+            transport,
         }
     }
 
     fn build(self) -> Result<AcceptorEngine> {
         let state = State::new(self.shared);
-        let engine = AcceptorEngine::new(self.node, state, Box::new(TlStorage { ops: self.ops }));
+        let engine = AcceptorEngine::new(
+            self.node,
+            state,
+            Box::new(TlStorage {
+                transport: self.transport,
+            }),
+        );
         Ok(engine)
     }
 }
@@ -57,10 +73,22 @@ pub(crate) struct RpcAdapterEngineBuilder {
     cmd_tx: tokio::sync::mpsc::UnboundedSender<phoenix_api_mrpc::cmd::Completion>,
     cmd_rx: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
     node: DataPathNode,
-    ops: Ops,
+    transport: Transport,
     shared: Arc<Shared>,
     salloc_shared: Arc<SallocShared>,
     addr_mediator: Arc<AddressMediator>,
+    keepalive: KeepaliveConfig,
+    congestion: CongestionConfig,
+    checksum: bool,
+    recv_buffers: RecvBufferConfig,
+    max_message_size: u64,
+    datagram: DatagramConfig,
+    srq: SrqConfig,
+    dispatch_policy: DispatchErrorPolicy,
+    eager_copy_threshold: usize,
+    tx_queue: TxQueueConfig,
+    bulk_offer_timeout: Duration,
+    gather_buffers: GatherBufferConfig,
 }
 
 impl RpcAdapterEngineBuilder {
@@ -68,11 +96,23 @@ impl RpcAdapterEngineBuilder {
     fn new(
         client_pid: Pid,
         _enable_scheduler: bool,
+        keepalive: KeepaliveConfig,
+        congestion: CongestionConfig,
+        checksum: bool,
+        recv_buffers: RecvBufferConfig,
+        max_message_size: u64,
+        datagram: DatagramConfig,
+        srq: SrqConfig,
+        dispatch_policy: DispatchErrorPolicy,
+        eager_copy_threshold: usize,
+        tx_queue: TxQueueConfig,
+        bulk_offer_timeout: Duration,
+        gather_buffers: GatherBufferConfig,
         mode: SchedulingMode,
         cmd_tx: tokio::sync::mpsc::UnboundedSender<phoenix_api_mrpc::cmd::Completion>,
         cmd_rx: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
         node: DataPathNode,
-        ops: Ops,
+        transport: Transport,
         shared: Arc<Shared>,
         salloc_shared: Arc<SallocShared>,
         addr_mediator: Arc<AddressMediator>,
@@ -83,10 +123,22 @@ impl RpcAdapterEngineBuilder {
             cmd_tx,
             cmd_rx,
             node,
-            ops,
+            transport,
             shared,
             salloc_shared,
             addr_mediator,
+            keepalive,
+            congestion,
+            checksum,
+            recv_buffers,
+            max_message_size,
+            datagram,
+            srq,
+            dispatch_policy,
+            eager_copy_threshold,
+            tx_queue,
+            bulk_offer_timeout,
+            gather_buffers,
         }
     }
 
@@ -94,23 +146,66 @@ impl RpcAdapterEngineBuilder {
         const BUF_LEN: usize = 32;
         let state = State::new(self.shared);
         let salloc_state = SallocState::new(self.salloc_shared, self.addr_mediator);
+        let mut features = FEATURE_CREDITS;
+        if self.checksum {
+            features |= FEATURE_CHECKSUM;
+        }
+        // only an RDMA NIC can read the payloads from the peer
+        if matches!(self.transport, Transport::Rdma(_)) {
+            features |= FEATURE_BULK;
+        }
 
         Ok(RpcAdapterEngine {
             state,
             odp_mr: None,
-            tls: Box::new(TlStorage { ops: self.ops }),
+            device_mrs: Default::default(),
+            user_mrs: Default::default(),
+            tls: Box::new(TlStorage {
+                transport: self.transport,
+            }),
             pending_recv: 0,
-            local_buffer: VecDeque::new(),
+            local_buffer: TxQueue::default(),
             cmd_tx: self.cmd_tx,
             cmd_rx: self.cmd_rx,
             node: self.node,
             _mode: self.mode,
             indicator: Default::default(),
+            profiler: Default::default(),
+            events: Default::default(),
             recv_mr_usage: fnv::FnvHashMap::default(),
-            serialization_engine: None,
+            dispatch_tables: Default::default(),
             rpc_ctx: slab::Slab::with_capacity(128),
             wc_read_buffer: Vec::with_capacity(BUF_LEN),
             salloc: salloc_state,
+            keepalive: self.keepalive,
+            congestion: CongestionControl::new(self.congestion),
+            checksum: self.checksum,
+            recv_buffers: self.recv_buffers,
+            settings: Settings {
+                max_message_size: self.max_message_size,
+                initial_credits: self.recv_buffers.high_watermark as u32,
+                recv_buffer_size: if self.srq.enable {
+                    self.srq.buffer_size as u32
+                } else {
+                    RECV_BUFFER_SIZE as u32
+                },
+                features,
+            },
+            datagram: None,
+            datagram_config: self.datagram,
+            srq: None,
+            srq_config: self.srq,
+            tx_stats: Default::default(),
+            dispatch_policy: self.dispatch_policy,
+            eager_copy_threshold: self.eager_copy_threshold,
+            gather_config: self.gather_buffers,
+            tx_queue_config: self.tx_queue,
+            quarantined: Default::default(),
+            listeners: Default::default(),
+            bulk: BulkTransfers::new(self.bulk_offer_timeout),
+            recent_errors: Default::default(),
+            conn_tails: Default::default(),
+            migration: Default::default(),
         })
     }
 }
@@ -142,6 +237,12 @@ impl RpcAdapterModule {
         ),
     ];
 
+    /// The dependencies on the simulated transport, which needs no RDMA CM engine.
+    pub const SIM_DEPENDENCIES: &'static [EnginePair] = &[(
+        RpcAdapterModule::RPC_ADAPTER_ENGINE,
+        RpcAdapterModule::RPC_ACCEPTOR_ENGINE,
+    )];
+
     pub const SCHEDULING_SPECS: &'static [(EngineType, SchedulingMode)] = &[(
         RpcAdapterModule::RPC_ACCEPTOR_ENGINE,
         SchedulingMode::Compact,
@@ -171,13 +272,36 @@ impl PhoenixModule for RpcAdapterModule {
     }
 
     fn dependencies(&self) -> &[EnginePair] {
-        Self::DEPENDENCIES
+        if self.config.sim.is_some() {
+            Self::SIM_DEPENDENCIES
+        } else {
+            Self::DEPENDENCIES
+        }
     }
 
     fn check_compatibility(&self, _prev: Option<&Version>, _curr: &HashMap<&str, Version>) -> bool {
         true
     }
 
+    fn capabilities(&self) -> BTreeMap<String, String> {
+        let mut caps = BTreeMap::new();
+        caps.insert(
+            "max_message_size".to_owned(),
+            self.config.max_message_size.to_string(),
+        );
+        caps.insert("checksum".to_owned(), self.config.checksum.to_string());
+        caps.insert(
+            "eager_copy_threshold".to_owned(),
+            self.config.eager_copy_threshold.to_string(),
+        );
+        caps.insert(
+            "datagram_mtu".to_owned(),
+            self.config.datagram.mtu.to_string(),
+        );
+        caps.insert("srq".to_owned(), self.config.srq.enable.to_string());
+        caps
+    }
+
     fn decompose(self: Box<Self>) -> ResourceCollection {
         let module = *self;
         let mut collections = ResourceCollection::new();
@@ -200,12 +324,6 @@ impl PhoenixModule for RpcAdapterModule {
         node: DataPathNode,
         plugged: &ModuleCollection,
     ) -> Result<Option<Box<dyn Engine>>> {
-        let mut rdma_transport_module = plugged
-            .get_mut("RdmaTransport")
-            .ok_or_else(|| anyhow!("fail to get RdmaTransport module"))?;
-        let rdma_transport = rdma_transport_module
-            .downcast_mut()
-            .ok_or_else(|| anyhow!("fail to downcast RdmaTransport module"))?;
         let mut salloc_module = plugged
             .get_mut("Salloc")
             .ok_or_else(|| anyhow!("fail to get Salloc module"))?;
@@ -221,10 +339,11 @@ impl PhoenixModule for RpcAdapterModule {
                     config_string,
                 } = request
                 {
+                    let transport = self.create_transport(client_pid, plugged)?;
                     let engine = self.create_acceptor_engine(
                         client_pid,
                         salloc,
-                        rdma_transport,
+                        transport,
                         node,
                         config_string,
                     )?;
@@ -250,6 +369,7 @@ impl PhoenixModule for RpcAdapterModule {
                         .command_path
                         .put_receiver(Self::RPC_ADAPTER_ENGINE, comp_receiver)?;
 
+                    let transport = self.create_transport(client_pid, plugged)?;
                     let engine = self.create_rpc_adapter_engine(
                         mode,
                         client_pid,
@@ -257,7 +377,7 @@ impl PhoenixModule for RpcAdapterModule {
                         cmd_receiver,
                         node,
                         salloc,
-                        rdma_transport,
+                        transport,
                         config_string,
                     )?;
                     Ok(Some(Box::new(engine)))
@@ -296,6 +416,20 @@ impl PhoenixModule for RpcAdapterModule {
 }
 
 impl RpcAdapterModule {
+    /// Creates the simulated transport if configured, or the RDMA transport of the client.
+    fn create_transport(&self, client_pid: Pid, plugged: &ModuleCollection) -> Result<Transport> {
+        if let Some(sim) = self.config.sim {
+            return Ok(Transport::Sim(SimTransport::new(sim)));
+        }
+        let mut rdma_transport_module = plugged
+            .get_mut("RdmaTransport")
+            .ok_or_else(|| anyhow!("fail to get RdmaTransport module"))?;
+        let rdma_transport: &mut RdmaTransportModule = rdma_transport_module
+            .downcast_mut()
+            .ok_or_else(|| anyhow!("fail to downcast RdmaTransport module"))?;
+        Ok(Transport::Rdma(rdma_transport.create_ops(client_pid)?))
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn create_rpc_adapter_engine(
         &mut self,
@@ -305,12 +439,10 @@ impl RpcAdapterModule {
         cmd_rx: tokio::sync::mpsc::UnboundedReceiver<cmd::Command>,
         node: DataPathNode,
         salloc: &mut SallocModule,
-        rdma_transport: &mut RdmaTransportModule,
+        transport: Transport,
         _config_string: Option<String>,
     ) -> Result<RpcAdapterEngine> {
         // Acceptor engine should already been created at this moment
-        let ops = rdma_transport.create_ops(client_pid)?;
-
         // Get salloc state
         let addr_mediator = salloc.get_addr_mediator();
         let addr_mediator_clone = Arc::clone(&addr_mediator);
@@ -322,11 +454,23 @@ impl RpcAdapterModule {
         let builder = RpcAdapterEngineBuilder::new(
             client_pid,
             self.config.enable_scheduler,
+            self.config.keepalive,
+            self.config.congestion.clone(),
+            self.config.checksum,
+            self.config.recv_buffers,
+            self.config.max_message_size,
+            self.config.datagram,
+            self.config.srq,
+            self.config.on_dispatch_error,
+            self.config.eager_copy_threshold,
+            self.config.tx_queue.clone(),
+            Duration::from_millis(self.config.bulk_offer_timeout_ms),
+            self.config.gather_buffers,
             mode,
             cmd_tx,
             cmd_rx,
             node,
-            ops,
+            transport,
             shared,
             salloc_shared,
             addr_mediator,
@@ -339,12 +483,11 @@ impl RpcAdapterModule {
         &mut self,
         client_pid: Pid,
         salloc: &mut SallocModule,
-        rdma_transport: &mut RdmaTransportModule,
+        transport: Transport,
         node: DataPathNode,
         _config_string: Option<String>,
     ) -> Result<Option<AcceptorEngine>> {
         log::warn!("create_acceptor_engine");
-        let ops = rdma_transport.create_ops(client_pid)?;
 
         let addr_mediator = salloc.get_addr_mediator();
         let shared = self.state_mgr.get_or_create_with(client_pid, move || {
@@ -355,7 +498,7 @@ impl RpcAdapterModule {
             return Ok(None);
         }
 
-        let builder = AcceptorEngineBuilder::new(shared, ops, client_pid, node);
+        let builder = AcceptorEngineBuilder::new(shared, transport, client_pid, node);
         let engine = builder.build()?;
 
         Ok(Some(engine))
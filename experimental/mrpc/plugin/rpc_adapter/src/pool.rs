@@ -1,14 +1,22 @@
 //! A pool of receive buffers. The buffers are shared among connections.
+//!
+//! Each slab counts the buffers in use and the most ever in use, and remembers the connection
+//! whose application holds each buffer delivered to it, so a connection that never reclaims its
+//! buffers is found before it exhausts the pool.
 use std::alloc::Layout;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use bitvec::bitvec;
 use bitvec::vec::BitVec;
 
-use phoenix_api::{AsHandle, Handle};
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
 
 use phoenix_salloc::region::{AddressMediator, SharedRegion};
 
+use phoenix_common::log;
 use phoenix_common::resource::Error as ResourceError;
 
 use super::ControlPathError;
@@ -19,17 +27,39 @@ pub(crate) struct RecvBuffer {
     offset: usize,
     len: usize,
     align: usize,
+    /// The number of times the memory segment has been obtained, see [`RecvBuffer::as_handle`].
+    generation: u8,
     /// The backing storage. A `RecvBuffer` can only belong to one SharedRegion.
     storage: Arc<SharedRegion>,
 }
 
+/// The number of bits of the buffer index in the handle of a `RecvBuffer`.
+const RECV_BUFFER_INDEX_BITS: u32 = 24;
+/// The number of bits of the generation in the handle of a `RecvBuffer`, above the index. The
+/// rest of the id holds the handle of the backing storage.
+const RECV_BUFFER_GENERATION_BITS: u32 = 8;
+
 impl AsHandle for RecvBuffer {
+    /// The handle tells apart the times the memory segment is obtained, so the handle of a buffer
+    /// released and obtained again, e.g., by another connection, is no longer found in the tables
+    /// keyed by the handles. A late reclaim of the former owner is then dropped, rather than
+    /// reposts a buffer it no longer owns.
     fn as_handle(&self) -> Handle {
         let high = self.storage.as_handle().0;
-        let low = self.offset / self.len;
-        assert!(high < (1 << 16), "Please consider reduce the number of underlying storage or widen the Handle type to 64-bit");
-        assert!(low < (1 << 16), "Please consider reduce the number of recv buffers inside a slab or widen the Handle type to 64-bit");
-        Handle(((high * (1 << 16)) as u32 + low as u32) as u64)
+        let low = (self.offset / self.len) as u64;
+        assert!(
+            high < (1 << (Handle::ID_BITS - RECV_BUFFER_INDEX_BITS - RECV_BUFFER_GENERATION_BITS)),
+            "Please consider reduce the number of underlying storage"
+        );
+        assert!(
+            low < (1 << RECV_BUFFER_INDEX_BITS),
+            "Please consider reduce the number of recv buffers inside a slab"
+        );
+        let generation = self.generation as u64;
+        Handle::new(
+            HandleNamespace::RecvBuf,
+            (high << RECV_BUFFER_GENERATION_BITS | generation) << RECV_BUFFER_INDEX_BITS | low,
+        )
     }
 }
 
@@ -50,6 +80,31 @@ impl RecvBuffer {
     }
 }
 
+/// A buffer delivered to the application, until it is reclaimed.
+#[derive(Debug, Clone, Copy)]
+struct Lent {
+    conn_id: Handle,
+    since: Instant,
+}
+
+/// The usage of the buffers of a slab.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SlabUsage {
+    pub(crate) capacity: usize,
+    pub(crate) in_use: usize,
+    pub(crate) high_water: usize,
+    /// The buffers held by the application.
+    pub(crate) lent: usize,
+}
+
+/// A buffer held by the application, see [`BufferPool::held_longer_than`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HeldBuffer {
+    pub(crate) handle: Handle,
+    pub(crate) conn_id: Handle,
+    pub(crate) held_for: Duration,
+}
+
 /// A thread-safe buffer slab.
 pub(crate) struct BufferSlab {
     num_buffers: usize,
@@ -59,6 +114,12 @@ pub(crate) struct BufferSlab {
     storage: Arc<SharedRegion>,
     /// Record which index is borrowed. 1 used, 0 unused.
     bitmap: spin::Mutex<BitVec>,
+    /// The generation of each buffer, bumped each time it is obtained.
+    generations: Vec<AtomicU8>,
+    in_use: AtomicUsize,
+    high_water: AtomicUsize,
+    /// The buffers held by the application, by their indices.
+    lent: spin::Mutex<HashMap<usize, Lent>>,
 }
 
 impl BufferSlab {
@@ -89,6 +150,10 @@ impl BufferSlab {
             buffer_align,
             storage: region,
             bitmap: spin::Mutex::new(bitvec![0; num_buffers]),
+            generations: (0..num_buffers).map(|_| AtomicU8::new(0)).collect(),
+            in_use: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+            lent: spin::Mutex::new(HashMap::new()),
         })
     }
 
@@ -97,16 +162,33 @@ impl BufferSlab {
         Arc::clone(&self.storage)
     }
 
+    /// Returns the number of buffers in the slab.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.num_buffers
+    }
+
+    /// Returns the size of each buffer in the slab.
+    #[inline]
+    pub(crate) fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
     pub(crate) fn obtain(&self) -> Option<RecvBuffer> {
         let mut bitmap = self.bitmap.lock();
         if let Some(unused) = bitmap.iter_zeros().next() {
             let offset = unused * self.buffer_size;
             let len = self.buffer_size;
             bitmap.set(offset / len, true);
+            let in_use = self.in_use.fetch_add(1, Ordering::Relaxed) + 1;
+            self.high_water.fetch_max(in_use, Ordering::Relaxed);
+            // wraps around, a handle is only kept for that long by a misbehaving application
+            let generation = self.generations[unused].fetch_add(1, Ordering::Relaxed);
             Some(RecvBuffer {
                 offset,
                 len,
                 align: self.buffer_align,
+                generation: generation.wrapping_add(1),
                 storage: Arc::clone(&self.storage),
             })
         } else {
@@ -115,15 +197,79 @@ impl BufferSlab {
     }
 
     pub(crate) fn release(&self, recv_buf: RecvBuffer) {
-        self.bitmap
+        self.free(&recv_buf);
+    }
+
+    /// Releases a buffer that is still referenced elsewhere, and returns its memory to the
+    /// system. The buffer must not be used until it is obtained again.
+    pub(crate) fn retire(&self, recv_buf: &RecvBuffer) {
+        if let Err(e) = self.storage.discard(recv_buf.offset, recv_buf.len) {
+            log::warn!(
+                "failed to discard recv buffer at {:#x}: {}",
+                recv_buf.addr(),
+                e
+            );
+        }
+        self.free(recv_buf);
+    }
+
+    fn free(&self, recv_buf: &RecvBuffer) {
+        let index = recv_buf.offset / recv_buf.len;
+        self.lent.lock().remove(&index);
+        self.bitmap.lock().set(index, false);
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Records that the buffer is delivered to the application of `conn_id`.
+    pub(crate) fn lend(&self, recv_buf: &RecvBuffer, conn_id: Handle) {
+        let lent = Lent {
+            conn_id,
+            since: Instant::now(),
+        };
+        self.lent
             .lock()
-            .set(recv_buf.offset / recv_buf.len, false);
+            .insert(recv_buf.offset / recv_buf.len, lent);
+    }
+
+    /// Records that the application reclaims the buffer, which stays in use, e.g., posted again.
+    pub(crate) fn give_back(&self, recv_buf: &RecvBuffer) {
+        self.lent.lock().remove(&(recv_buf.offset / recv_buf.len));
+    }
+
+    pub(crate) fn usage(&self) -> SlabUsage {
+        SlabUsage {
+            capacity: self.num_buffers,
+            in_use: self.in_use.load(Ordering::Relaxed),
+            high_water: self.high_water.load(Ordering::Relaxed),
+            lent: self.lent.lock().len(),
+        }
+    }
+
+    fn held_longer_than(&self, threshold: Duration, now: Instant, held: &mut Vec<HeldBuffer>) {
+        for (&index, lent) in self.lent.lock().iter() {
+            let held_for = now.saturating_duration_since(lent.since);
+            if held_for < threshold {
+                continue;
+            }
+            let recv_buf = RecvBuffer {
+                offset: index * self.buffer_size,
+                len: self.buffer_size,
+                align: self.buffer_align,
+                generation: self.generations[index].load(Ordering::Relaxed),
+                storage: Arc::clone(&self.storage),
+            };
+            held.push(HeldBuffer {
+                handle: recv_buf.as_handle(),
+                conn_id: lent.conn_id,
+                held_for,
+            });
+        }
     }
 }
 
 /// A thread-safe buffer slab.
 pub(crate) struct BufferPool {
-    slabs: spin::Mutex<Vec<BufferSlab>>,
+    slabs: spin::Mutex<Vec<Arc<BufferSlab>>>,
     addr_mediator: Arc<AddressMediator>,
 }
 
@@ -135,7 +281,7 @@ impl BufferPool {
         }
     }
 
-    pub(crate) fn replenish(&self, slab: BufferSlab) {
+    pub(crate) fn replenish(&self, slab: Arc<BufferSlab>) {
         self.slabs.lock().push(slab);
     }
 
@@ -147,9 +293,9 @@ impl BufferPool {
         }
 
         // replenish a slab
-        self.replenish(
+        self.replenish(Arc::new(
             BufferSlab::new(128, 8 * 1024 * 1024, 8 * 1024 * 1024, &self.addr_mediator).unwrap(),
-        );
+        ));
         self.obtain()
     }
 
@@ -164,6 +310,60 @@ impl BufferPool {
         unreachable!()
     }
 
+    /// Records that the buffer is delivered to the application of `conn_id`.
+    pub(crate) fn lend(&self, recv_buf: &RecvBuffer, conn_id: Handle) {
+        if let Some(slab) = self.slab_of(recv_buf) {
+            slab.lend(recv_buf, conn_id);
+        }
+    }
+
+    /// Records that the application reclaims the buffer.
+    pub(crate) fn give_back(&self, recv_buf: &RecvBuffer) {
+        if let Some(slab) = self.slab_of(recv_buf) {
+            slab.give_back(recv_buf);
+        }
+    }
+
+    fn slab_of(&self, recv_buf: &RecvBuffer) -> Option<Arc<BufferSlab>> {
+        self.slabs
+            .lock()
+            .iter()
+            .find(|slab| Arc::ptr_eq(&slab.storage, &recv_buf.storage))
+            .cloned()
+    }
+
+    /// Returns the handle of the storage and the usage of each slab.
+    pub(crate) fn usage(&self) -> Vec<(Handle, SlabUsage)> {
+        self.slabs
+            .lock()
+            .iter()
+            .map(|slab| (slab.storage.as_handle(), slab.usage()))
+            .collect()
+    }
+
+    /// Returns the buffers held by the application for `threshold` or longer, the longest held
+    /// first.
+    pub(crate) fn held_longer_than(&self, threshold: Duration) -> Vec<HeldBuffer> {
+        let now = Instant::now();
+        let mut held = Vec::new();
+        for slab in self.slabs.lock().iter() {
+            slab.held_longer_than(threshold, now, &mut held);
+        }
+        held.sort_by(|a, b| b.held_for.cmp(&a.held_for));
+        held
+    }
+
+    /// Returns whether `[addr, addr + len)` lies in a buffer of the pool.
+    pub(crate) fn contains(&self, addr: usize, len: usize) -> bool {
+        let Some(end) = addr.checked_add(len) else {
+            return false;
+        };
+        self.slabs.lock().iter().any(|s| {
+            let start = s.storage.as_ptr().expose_addr();
+            addr >= start && end <= start + s.storage.len()
+        })
+    }
+
     pub(crate) fn find(&self, handle: &Handle) -> Result<Arc<SharedRegion>, ControlPathError> {
         self.slabs
             .lock()
@@ -0,0 +1,39 @@
+//! The last errors of an engine, kept to be shown in its dumps.
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Instant;
+
+/// The number of errors kept, the older ones are dropped.
+const CAPACITY: usize = 16;
+
+/// The errors are not carried over upgrades.
+#[derive(Debug, Default)]
+pub(crate) struct RecentErrors {
+    errors: VecDeque<(Instant, String)>,
+}
+
+impl RecentErrors {
+    /// Records an error. It should not contain any payload of the messages.
+    pub(crate) fn record(&mut self, error: String) {
+        if self.errors.len() == CAPACITY {
+            self.errors.pop_front();
+        }
+        self.errors.push_back((Instant::now(), error));
+    }
+}
+
+impl fmt::Display for RecentErrors {
+    /// Writes the errors from the oldest, one per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = Instant::now();
+        for (at, error) in &self.errors {
+            writeln!(
+                f,
+                "{:.1?} ago: {}",
+                now.saturating_duration_since(*at),
+                error
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+//! Load-adaptive posting of the receive buffers.
+//!
+//! Each connection gets a slab of `high_watermark` receive buffers, which is mapped into the
+//! application when the connection is set up, but only `low_watermark` of them are posted at
+//! first. The pages of a buffer are only backed by memory once it receives something, so the
+//! buffers that are never posted cost nothing but address space.
+//!
+//! The number of buffers to keep posted on a connection, the target, follows the demand of the
+//! connection. The demand is counted in buffers rather than messages, as a large message takes a
+//! buffer for each of its fragments. A reclaimed buffer is posted again only if the connection is
+//! below its target, otherwise it is retired to the slab and its memory is returned to the
+//! system. Every `adjust_interval_ms`, the target is doubled if the posted buffers of the
+//! connection ran low during the interval, and halved if less than a quarter of the target was
+//! consumed. A connection below its target takes more buffers from its slab.
+//!
+//! The sender only gets credits for the buffers that are posted. The settings frame grants the
+//! buffers posted when the connection is set up. Whenever the target changes, a credits frame, an
+//! inline send marked by `CREDITS_IMM`, grants the buffers newly posted up to the target, or takes
+//! back the credits above a lowered target. The grants are only sent if both ends support
+//! `FEATURE_CREDITS`; an earlier peer keeps the credits of the settings frame.
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+
+use phoenix_api::Handle;
+
+use super::config::RecvBufferConfig;
+use super::pool::{BufferSlab, RecvBuffer};
+
+/// The immediate value that marks a credits frame.
+pub(crate) const CREDITS_IMM: u32 = 0x63720001;
+/// The size of an encoded credits frame.
+pub(crate) const CREDITS_LEN: usize = 8;
+
+/// A window over the buffers of a slab `S`, see [`RecvWindow`].
+pub(crate) struct RecvWindowT<S> {
+    slab: S,
+    // the number of buffers posted on the connection
+    posted: usize,
+    // the number of buffers to keep posted
+    target: usize,
+    // the bounds of the target
+    low_watermark: usize,
+    high_watermark: usize,
+    // the number of buffers consumed in the current interval
+    consumed: usize,
+    // the fewest buffers posted during the current interval
+    min_posted: usize,
+    // the credits granted to the sender
+    granted: usize,
+}
+
+pub(crate) type RecvWindow = RecvWindowT<Arc<BufferSlab>>;
+
+/// The posted receive buffers of the connections of an engine.
+pub(crate) struct RecvWindowsT<S> {
+    // conn_id -> the window of the connection
+    windows: RefCell<FnvHashMap<Handle, RecvWindowT<S>>>,
+    last_adjust: Cell<Instant>,
+}
+
+pub(crate) type RecvWindows = RecvWindowsT<Arc<BufferSlab>>;
+
+impl<S> Default for RecvWindowsT<S> {
+    fn default() -> Self {
+        RecvWindowsT {
+            windows: RefCell::new(FnvHashMap::default()),
+            last_adjust: Cell::new(Instant::now()),
+        }
+    }
+}
+
+impl<S> RecvWindowsT<S> {
+    /// Starts to track a connection that has `posted` buffers of `slab` posted, and grants the
+    /// sender as many credits. The target of the connection stays between `posted` and
+    /// `high_watermark`.
+    fn open_with(&self, conn_id: Handle, slab: S, posted: usize, high_watermark: usize) {
+        let window = RecvWindowT {
+            slab,
+            posted,
+            target: posted,
+            low_watermark: posted,
+            high_watermark,
+            consumed: 0,
+            min_posted: posted,
+            granted: posted,
+        };
+        self.windows.borrow_mut().insert(conn_id, window);
+    }
+
+    /// Stops tracking a connection, which moves to another engine with its window.
+    pub(crate) fn take(&self, conn_id: &Handle) -> Option<RecvWindowT<S>> {
+        self.windows.borrow_mut().remove(conn_id)
+    }
+
+    /// Tracks a connection taken over with its window from another engine.
+    pub(crate) fn put(&self, conn_id: Handle, window: RecvWindowT<S>) {
+        self.windows.borrow_mut().insert(conn_id, window);
+    }
+
+    /// Returns the credits granted to the sender of the connection.
+    pub(crate) fn credits(&self, conn_id: &Handle) -> Option<usize> {
+        self.windows.borrow().get(conn_id).map(|w| w.granted)
+    }
+
+    /// Records that a posted buffer of the connection has received something.
+    pub(crate) fn consume(&self, conn_id: &Handle) {
+        if let Some(w) = self.windows.borrow_mut().get_mut(conn_id) {
+            w.posted -= 1;
+            w.consumed += 1;
+            w.min_posted = w.min_posted.min(w.posted);
+        }
+    }
+
+    /// Returns whether a reclaimed buffer of the connection should be posted again. Otherwise,
+    /// the buffer should be retired.
+    pub(crate) fn repost(&self, conn_id: &Handle) -> bool {
+        match self.windows.borrow_mut().get_mut(conn_id) {
+            Some(w) if w.posted >= w.target => false,
+            Some(w) => {
+                w.posted += 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Adjusts the targets by the demand, once every `adjust_interval_ms`. Returns the
+    /// connections below their targets or whose credits are not in line with their targets.
+    pub(crate) fn adjust(&self, config: &RecvBufferConfig) -> Vec<Handle> {
+        let now = Instant::now();
+        let interval = Duration::from_millis(config.adjust_interval_ms);
+        if now.saturating_duration_since(self.last_adjust.get()) < interval {
+            return Vec::new();
+        }
+        self.last_adjust.set(now);
+
+        let mut below = Vec::new();
+        for (conn_id, w) in self.windows.borrow_mut().iter_mut() {
+            if w.min_posted <= w.target / 4 {
+                w.target = (w.target * 2).min(w.high_watermark);
+            } else if w.consumed < w.target / 4 {
+                w.target = (w.target / 2).max(w.low_watermark);
+            }
+            w.consumed = 0;
+            w.min_posted = w.posted;
+            if w.posted < w.target || w.granted != w.target {
+                below.push(*conn_id);
+            }
+        }
+        below
+    }
+
+    /// Brings the credits of the connection in line with its target, as far as the buffers are
+    /// posted. Returns the credits to grant to the sender, negative if some are taken back.
+    pub(crate) fn grant(&self, conn_id: &Handle) -> isize {
+        let mut windows = self.windows.borrow_mut();
+        let w = match windows.get_mut(conn_id) {
+            Some(w) => w,
+            None => return 0,
+        };
+        let granted = if w.target > w.granted {
+            // the buffers still held by the application are posted once they are reclaimed
+            w.target.min(w.posted).max(w.granted)
+        } else {
+            w.target
+        };
+        let delta = granted as isize - w.granted as isize;
+        w.granted = granted;
+        delta
+    }
+}
+
+impl RecvWindows {
+    /// Starts to track a connection that has `posted` buffers of `slab` posted. The target of the
+    /// connection stays between `posted` and the number of buffers in `slab`.
+    pub(crate) fn open(&self, conn_id: Handle, slab: Arc<BufferSlab>, posted: usize) {
+        let high_watermark = slab.capacity();
+        self.open_with(conn_id, slab, posted, high_watermark);
+    }
+
+    /// Returns the slab of the receive buffers of the connection.
+    pub(crate) fn slab(&self, conn_id: &Handle) -> Option<Arc<BufferSlab>> {
+        self.windows
+            .borrow()
+            .get(conn_id)
+            .map(|w| Arc::clone(&w.slab))
+    }
+
+    /// Retires a buffer of the connection to its slab.
+    pub(crate) fn retire(&self, conn_id: &Handle, recv_buffer: &RecvBuffer) {
+        if let Some(w) = self.windows.borrow().get(conn_id) {
+            w.slab.retire(recv_buffer);
+        }
+    }
+
+    /// Takes the buffers from the slab of the connection to bring it up to its target. The
+    /// buffers are counted as posted.
+    pub(crate) fn grow(&self, conn_id: &Handle) -> Vec<RecvBuffer> {
+        let mut windows = self.windows.borrow_mut();
+        let w = match windows.get_mut(conn_id) {
+            Some(w) => w,
+            None => return Vec::new(),
+        };
+        let mut buffers = Vec::new();
+        while w.posted < w.target {
+            match w.slab.obtain() {
+                Some(buffer) => buffers.push(buffer),
+                None => break,
+            }
+            w.posted += 1;
+        }
+        buffers
+    }
+}
+
+/// Encodes a credits frame that grants `delta` credits.
+pub(crate) fn encode_credits(delta: isize) -> [u8; CREDITS_LEN] {
+    (delta as i64).to_le_bytes()
+}
+
+/// Decodes a credits frame. Returns `None` if `buf` is not one.
+pub(crate) fn decode_credits(buf: &[u8]) -> Option<isize> {
+    let buf: [u8; CREDITS_LEN] = buf.try_into().ok()?;
+    Some(i64::from_le_bytes(buf) as isize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONN: Handle = Handle(1);
+
+    fn config() -> RecvBufferConfig {
+        RecvBufferConfig {
+            adjust_interval_ms: 0,
+            ..Default::default()
+        }
+    }
+
+    fn windows(posted: usize, high_watermark: usize) -> RecvWindowsT<()> {
+        let windows = RecvWindowsT::default();
+        windows.open_with(CONN, (), posted, high_watermark);
+        windows
+    }
+
+    // posts the buffers up to the target, like `grow` does from the slab
+    fn fill(windows: &RecvWindowsT<()>) {
+        while windows.repost(&CONN) {}
+    }
+
+    fn target(windows: &RecvWindowsT<()>) -> usize {
+        windows.windows.borrow()[&CONN].target
+    }
+
+    #[test]
+    fn idle_window_is_unchanged() {
+        let windows = windows(16, 128);
+        // a quarter of the target consumed keeps it
+        for _ in 0..4 {
+            windows.consume(&CONN);
+            assert!(windows.repost(&CONN));
+        }
+        assert!(windows.adjust(&config()).is_empty());
+        assert_eq!(target(&windows), 16);
+        assert_eq!(windows.grant(&CONN), 0);
+        assert_eq!(windows.credits(&CONN), Some(16));
+    }
+
+    #[test]
+    fn busy_window_grows_to_high_watermark() {
+        let windows = windows(16, 128);
+        for expected in [32, 64, 128, 128] {
+            // the posted buffers run out
+            while windows.windows.borrow()[&CONN].posted > 0 {
+                windows.consume(&CONN);
+            }
+            let previous = windows.credits(&CONN).unwrap();
+            let pending = windows.adjust(&config());
+            assert_eq!(target(&windows), expected);
+            assert_eq!(pending, vec![CONN]);
+            // no credits for the buffers not yet posted
+            assert_eq!(windows.grant(&CONN), 0);
+            fill(&windows);
+            assert_eq!(windows.grant(&CONN), expected as isize - previous as isize);
+            assert_eq!(windows.credits(&CONN), Some(expected));
+        }
+    }
+
+    #[test]
+    fn credits_follow_partially_posted_buffers() {
+        let windows = windows(16, 128);
+        while windows.windows.borrow()[&CONN].posted > 0 {
+            windows.consume(&CONN);
+        }
+        windows.adjust(&config());
+        for _ in 0..20 {
+            assert!(windows.repost(&CONN));
+        }
+        assert_eq!(windows.grant(&CONN), 4);
+        // the rest is granted once the buffers are posted
+        fill(&windows);
+        assert_eq!(windows.grant(&CONN), 12);
+        assert_eq!(windows.credits(&CONN), Some(32));
+    }
+
+    #[test]
+    fn quiet_window_shrinks_to_low_watermark() {
+        let windows = windows(16, 128);
+        while windows.windows.borrow()[&CONN].posted > 0 {
+            windows.consume(&CONN);
+        }
+        windows.adjust(&config());
+        fill(&windows);
+        windows.grant(&CONN);
+        windows.adjust(&config());
+        fill(&windows);
+        windows.grant(&CONN);
+        assert_eq!(windows.credits(&CONN), Some(64));
+
+        for expected in [32, 16, 16] {
+            let previous = windows.credits(&CONN).unwrap();
+            windows.adjust(&config());
+            assert_eq!(target(&windows), expected);
+            // the credits above the target are taken back at once
+            assert_eq!(windows.grant(&CONN), expected as isize - previous as isize);
+            assert_eq!(windows.credits(&CONN), Some(expected));
+        }
+        // the buffers above the target are retired when they are reclaimed
+        windows.consume(&CONN);
+        assert!(!windows.repost(&CONN));
+    }
+
+    #[test]
+    fn adjust_waits_for_interval() {
+        let windows = windows(16, 128);
+        while windows.windows.borrow()[&CONN].posted > 0 {
+            windows.consume(&CONN);
+        }
+        let config = RecvBufferConfig {
+            adjust_interval_ms: 60_000,
+            ..Default::default()
+        };
+        assert!(windows.adjust(&config).is_empty());
+        assert_eq!(target(&windows), 16);
+    }
+
+    #[test]
+    fn credits_frame_roundtrip() {
+        for delta in [0, 16, -48, isize::MAX] {
+            assert_eq!(decode_credits(&encode_credits(delta)), Some(delta));
+        }
+        assert_eq!(decode_credits(&[0u8; CREDITS_LEN + 1]), None);
+    }
+}
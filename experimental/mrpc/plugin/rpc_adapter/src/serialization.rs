@@ -1,8 +1,13 @@
+use std::any::Any;
 use std::ffi::OsStr;
+use std::panic::{self, AssertUnwindSafe};
+
+use fnv::FnvHashMap;
+use thiserror::Error;
 
 use mrpc_marshal::{ExcavateContext, SgList};
 use mrpc_marshal::{MarshalError, UnmarshalError};
-use phoenix_api::rpc::MessageMeta;
+use phoenix_api::rpc::{MessageMeta, TransportStatus};
 
 pub(crate) use mrpc_marshal::AddressMap;
 
@@ -10,6 +15,63 @@ pub(crate) type MarshalFn = fn(&MessageMeta, usize) -> Result<SgList, MarshalErr
 pub(crate) type UnmarshalFn =
     fn(&MessageMeta, &mut ExcavateContext<AddressMap>) -> Result<(usize, usize), UnmarshalError>;
 
+/// The dispatch library fails on a message. A panic of the library is caught, so that it only
+/// affects the connection of the message.
+#[derive(Debug, Error)]
+pub(crate) enum DispatchError {
+    #[error("marshal: {0}")]
+    Marshal(#[from] MarshalError),
+    #[error("unmarshal: {0}")]
+    Unmarshal(#[from] UnmarshalError),
+    #[error("dispatch library panicked: {0}")]
+    Panicked(String),
+    #[error("{1} bytes at {0:#x} are outside the memory of the app")]
+    InvalidAddress(usize, usize),
+}
+
+impl DispatchError {
+    /// The status reported to the application.
+    pub(crate) fn status(&self) -> TransportStatus {
+        match self {
+            DispatchError::Marshal(_)
+            | DispatchError::Unmarshal(_)
+            | DispatchError::InvalidAddress(..) => TransportStatus::BAD_MESSAGE,
+            DispatchError::Panicked(_) => TransportStatus::DISPATCH_PANICKED,
+        }
+    }
+
+    fn panicked(payload: Box<dyn Any + Send>) -> Self {
+        let msg = if let Some(s) = payload.downcast_ref::<&str>() {
+            (*s).to_owned()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic payload".to_owned()
+        };
+        DispatchError::Panicked(msg)
+    }
+}
+
+/// Marshals the raw message at `addr_backend`, see [`MessageMeta::RAW`].
+pub(crate) fn marshal_raw(addr_backend: usize) -> Result<SgList, DispatchError> {
+    panic::catch_unwind(|| mrpc_marshal::raw::marshal(addr_backend))
+        .map_err(DispatchError::panicked)?
+        .map_err(DispatchError::from)
+}
+
+/// Unmarshals a raw message, see [`MessageMeta::RAW`].
+///
+/// # Safety
+///
+/// The message is unmarshaled in place in the receive buffers of `ctx`.
+pub(crate) unsafe fn unmarshal_raw(
+    ctx: &mut ExcavateContext<AddressMap>,
+) -> Result<(usize, usize), DispatchError> {
+    panic::catch_unwind(AssertUnwindSafe(|| mrpc_marshal::raw::unmarshal(ctx)))
+        .map_err(DispatchError::panicked)?
+        .map_err(DispatchError::from)
+}
+
 pub(crate) struct SerializationEngine {
     _library: libloading::Library,
     // NOTE: Symbol here shall not outlive library.
@@ -50,8 +112,10 @@ impl SerializationEngine {
         &self,
         meta: &MessageMeta,
         addr_backend: usize,
-    ) -> Result<SgList, MarshalError> {
-        (self.marshal_fn)(meta, addr_backend)
+    ) -> Result<SgList, DispatchError> {
+        panic::catch_unwind(AssertUnwindSafe(|| (self.marshal_fn)(meta, addr_backend)))
+            .map_err(DispatchError::panicked)?
+            .map_err(DispatchError::from)
     }
 
     #[inline]
@@ -59,7 +123,71 @@ impl SerializationEngine {
         &self,
         meta: &MessageMeta,
         ctx: &mut ExcavateContext<AddressMap>,
-    ) -> Result<(usize, usize), UnmarshalError> {
-        (self.unmarshal_fn)(meta, ctx)
+    ) -> Result<(usize, usize), DispatchError> {
+        panic::catch_unwind(AssertUnwindSafe(|| (self.unmarshal_fn)(meta, ctx)))
+            .map_err(DispatchError::panicked)?
+            .map_err(DispatchError::from)
+    }
+}
+
+/// The dispatch libraries loaded. Each `update_protos` loads a new version of the library, the
+/// previous one is kept for the messages sent before, until the frontend retires it. Unnumbered
+/// versions (0) are not kept, the latest library is used for everything.
+#[derive(Default)]
+pub(crate) struct DispatchTables {
+    latest: Option<SerializationEngine>,
+    // the version of `latest`
+    version: u32,
+    previous: FnvHashMap<u32, SerializationEngine>,
+}
+
+impl From<Option<SerializationEngine>> for DispatchTables {
+    fn from(latest: Option<SerializationEngine>) -> Self {
+        DispatchTables {
+            latest,
+            version: 0,
+            previous: FnvHashMap::default(),
+        }
+    }
+}
+
+impl DispatchTables {
+    /// The library to marshal a message sent in `version` with.
+    #[inline]
+    pub(crate) fn get(&self, version: u32) -> Option<&SerializationEngine> {
+        if version != self.version {
+            if let Some(module) = self.previous.get(&version) {
+                return Some(module);
+            }
+        }
+        self.latest.as_ref()
+    }
+
+    /// The library to unmarshal the messages received with.
+    #[inline]
+    pub(crate) fn latest(&self) -> Option<&SerializationEngine> {
+        self.latest.as_ref()
+    }
+
+    /// Makes `module` the latest library.
+    pub(crate) fn update(&mut self, module: SerializationEngine, version: u32) {
+        if let Some(previous) = self.latest.replace(module) {
+            if self.version != 0 && version != 0 {
+                self.previous.insert(self.version, previous);
+            }
+        }
+        self.version = version;
+    }
+
+    /// The version of the latest library, and the earlier versions still loaded.
+    pub(crate) fn versions(&self) -> (u32, Vec<u32>) {
+        let mut previous: Vec<u32> = self.previous.keys().copied().collect();
+        previous.sort_unstable();
+        (self.version, previous)
+    }
+
+    /// Unloads the library of `version`. Returns `false` if it is not loaded.
+    pub(crate) fn retire(&mut self, version: u32) -> bool {
+        self.previous.remove(&version).is_some()
     }
 }
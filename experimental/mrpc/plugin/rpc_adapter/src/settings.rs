@@ -0,0 +1,92 @@
+//! The settings exchanged by the two ends of a connection.
+//!
+//! Right after a connection is established, each end sends a settings frame to the other, an
+//! inline send marked by `SETTINGS_IMM`. The frame is always the first thing an end sends on a
+//! connection, so the first receive of a connection is taken as the settings of the peer. Until
+//! they arrive, no message is sent on the connection.
+//!
+//! The settings tell the sender how large a message the peer accepts, how many sends it can have
+//! outstanding, i.e., the number of receive buffers the peer has posted, how large the receive
+//! buffers are, and which optional features the peer supports. A feature is only used if both
+//! ends support it.
+use thiserror::Error;
+
+use super::gather::RECV_BUFFER_SIZE;
+
+/// The immediate value that marks a settings frame.
+pub(crate) const SETTINGS_IMM: u32 = 0x73650001;
+
+/// The version of the settings frame sent by this build.
+const SETTINGS_VERSION: u32 = 1;
+
+/// The size of an encoded settings frame.
+pub(crate) const SETTINGS_LEN: usize = 24;
+
+/// The message payloads carry a CRC32C, see `checksum`.
+pub(crate) const FEATURE_CHECKSUM: u32 = 1 << 0;
+
+/// The payloads can be read with RDMA READs, see `bulk`.
+pub(crate) const FEATURE_BULK: u32 = 1 << 1;
+
+/// The credits follow the posted receive buffers, see `recv_window`.
+pub(crate) const FEATURE_CREDITS: u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, Error)]
+pub(crate) enum SettingsError {
+    #[error("settings frame of {0} bytes, expect {SETTINGS_LEN}")]
+    Length(usize),
+    #[error("unsupported settings version {0}")]
+    Version(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Settings {
+    /// The largest message the end accepts, in bytes
+    pub(crate) max_message_size: u64,
+    /// The number of sends the other end can have outstanding at first, more are granted as the
+    /// receive window grows, see `recv_window`
+    pub(crate) initial_credits: u32,
+    /// The size of the receive buffers of the end, i.e., the largest send the other end can post
+    pub(crate) recv_buffer_size: u32,
+    /// The optional features the end supports
+    pub(crate) features: u32,
+}
+
+impl Settings {
+    /// Returns whether both `self` and `peer` support `feature`.
+    #[inline]
+    pub(crate) fn negotiated(&self, peer: &Settings, feature: u32) -> bool {
+        self.features & peer.features & feature == feature
+    }
+
+    pub(crate) fn encode(&self) -> [u8; SETTINGS_LEN] {
+        let mut buf = [0u8; SETTINGS_LEN];
+        buf[0..4].copy_from_slice(&SETTINGS_VERSION.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.features.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.max_message_size.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.initial_credits.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.recv_buffer_size.to_le_bytes());
+        buf
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self, SettingsError> {
+        if buf.len() != SETTINGS_LEN {
+            return Err(SettingsError::Length(buf.len()));
+        }
+        let u32_at = |off: usize| u32::from_le_bytes(buf[off..off + 4].try_into().unwrap());
+        let version = u32_at(0);
+        if version != SETTINGS_VERSION {
+            return Err(SettingsError::Version(version));
+        }
+        Ok(Settings {
+            features: u32_at(4),
+            max_message_size: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            initial_credits: u32_at(16),
+            // the field was reserved and left zero by the earlier builds
+            recv_buffer_size: match u32_at(20) {
+                0 => RECV_BUFFER_SIZE as u32,
+                size => size,
+            },
+        })
+    }
+}
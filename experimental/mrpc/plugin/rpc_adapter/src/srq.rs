@@ -0,0 +1,228 @@
+//! Receive buffers shared by the connections of an engine.
+//!
+//! With `srq.enable`, an engine posts `srq.buffers` receive buffers to one shared receive queue,
+//! and the QPs of all its connections take their receives from it, so the memory posted does not
+//! grow with the number of connections.
+//!
+//! The shared buffers are private to the engine. A segment received in one of them is copied into
+//! a buffer from the slab of its connection, which the application maps as in the per-QP mode, and
+//! the shared buffer is posted again right away. When the application holds all the buffers of a
+//! connection, the completions of the connection wait, in order, until it gives some back.
+use std::collections::VecDeque;
+use std::ptr;
+use std::sync::Arc;
+
+use fnv::FnvHashMap;
+
+use phoenix_api::net;
+use phoenix_api::{AsHandle, Handle};
+use phoenix_common::log;
+use phoenix_common::resource::Error as ResourceError;
+use phoenix_salloc::region::AddressMediator;
+
+use super::config::SrqConfig;
+use super::pool::{BufferSlab, RecvBuffer};
+use super::ulib;
+use super::{ControlPathError, DatapathError};
+
+struct SrqConnection {
+    /// The buffers the segments of the connection are copied into, mapped by the application
+    slab: Arc<BufferSlab>,
+    /// The number of buffers of the slab the connection holds
+    held: usize,
+    /// The number of segments received on the connection
+    received: u64,
+    /// The segments waiting for a free buffer of the slab, with their completions
+    stalled: VecDeque<(net::WorkCompletion, Vec<u8>)>,
+}
+
+pub(crate) struct SharedRecvQueue {
+    // the receive buffers posted to the queue, by wr_id
+    posted: FnvHashMap<u64, RecvBuffer>,
+    slab: BufferSlab,
+    buffer_size: usize,
+    // the connections, by the number of their QPs
+    qpns: FnvHashMap<u32, Handle>,
+    conns: FnvHashMap<Handle, SrqConnection>,
+    srq: ulib::uverbs::SharedReceiveQueue,
+}
+
+impl SharedRecvQueue {
+    pub(crate) fn new(
+        srq: ulib::uverbs::SharedReceiveQueue,
+        config: &SrqConfig,
+        addr_mediator: &AddressMediator,
+    ) -> Result<Self, ControlPathError> {
+        let slab = BufferSlab::new(config.buffers, config.buffer_size, 4096, addr_mediator)?;
+        Ok(SharedRecvQueue {
+            posted: FnvHashMap::default(),
+            slab,
+            buffer_size: config.buffer_size,
+            qpns: FnvHashMap::default(),
+            conns: FnvHashMap::default(),
+            srq,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn srq(&self) -> &ulib::uverbs::SharedReceiveQueue {
+        &self.srq
+    }
+
+    #[inline]
+    pub(crate) fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Returns whether the receive of `wr_id` was posted to the queue.
+    #[inline]
+    pub(crate) fn owns(&self, wr_id: u64) -> bool {
+        self.posted.contains_key(&wr_id)
+    }
+
+    /// Posts the buffers that are not posted yet.
+    pub(crate) fn fill(
+        &mut self,
+        odp_mr: &mut ulib::uverbs::MemoryRegion<u8>,
+    ) -> Result<(), ulib::Error> {
+        while let Some(recv_buffer) = self.slab.obtain() {
+            let wr_id = recv_buffer.as_handle().0;
+            self.post_recv(odp_mr, wr_id, recv_buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the connection `conn_id` on the QP `qpn`, whose segments are copied into `slab`.
+    pub(crate) fn open(&mut self, conn_id: Handle, qpn: u32, slab: Arc<BufferSlab>) {
+        self.qpns.insert(qpn, conn_id);
+        self.conns.insert(
+            conn_id,
+            SrqConnection {
+                slab,
+                held: 0,
+                received: 0,
+                stalled: VecDeque::new(),
+            },
+        );
+    }
+
+    /// Copies the segment received by `wc` into a buffer of its connection, and posts the
+    /// receive buffer again. Returns the connection, the buffer, and the completion to pass to
+    /// the receive path of the connection, whose `wr_id` is the handle of the buffer. Returns
+    /// `None` if the segment is dropped or has to wait for a buffer.
+    pub(crate) fn take_recv(
+        &mut self,
+        wc: &net::WorkCompletion,
+        odp_mr: &mut ulib::uverbs::MemoryRegion<u8>,
+    ) -> Result<Option<(Handle, RecvBuffer, net::WorkCompletion)>, DatapathError> {
+        let recv_buffer = self
+            .posted
+            .remove(&wc.wr_id)
+            .ok_or(ResourceError::NotFound)?;
+        let conn_id = match self.qpns.get(&wc.qp_num) {
+            Some(conn_id) => *conn_id,
+            None => {
+                log::debug!("Dropped a segment received on QP {}", wc.qp_num);
+                self.post_recv(odp_mr, wc.wr_id, recv_buffer)?;
+                return Ok(None);
+            }
+        };
+        let conn = self.conns.get_mut(&conn_id).unwrap();
+        let len = (wc.byte_len as usize).min(recv_buffer.len());
+        // SAFETY: the receive buffer holds the segment
+        let segment = unsafe { std::slice::from_raw_parts(recv_buffer.addr() as *const u8, len) };
+        let taken = if conn.stalled.is_empty() {
+            Self::copy_to_conn(conn, wc, segment)
+        } else {
+            None
+        };
+        if taken.is_none() {
+            if conn.stalled.is_empty() {
+                log::debug!(
+                    "{:?} holds all its {} buffers after {} segments",
+                    conn_id,
+                    conn.held,
+                    conn.received
+                );
+            }
+            // keep the segment until the application gives a buffer back
+            conn.stalled.push_back((*wc, segment.to_vec()));
+        }
+        self.post_recv(odp_mr, wc.wr_id, recv_buffer)?;
+        Ok(taken.map(|(buffer, wc)| (conn_id, buffer, wc)))
+    }
+
+    fn copy_to_conn(
+        conn: &mut SrqConnection,
+        wc: &net::WorkCompletion,
+        segment: &[u8],
+    ) -> Option<(RecvBuffer, net::WorkCompletion)> {
+        let buffer = conn.slab.obtain()?;
+        // SAFETY: the buffers of the connection are as large as the shared ones
+        unsafe {
+            ptr::copy_nonoverlapping(segment.as_ptr(), buffer.addr() as *mut u8, segment.len())
+        };
+        conn.held += 1;
+        conn.received += 1;
+        let mut wc = *wc;
+        wc.wr_id = buffer.as_handle().0;
+        Some((buffer, wc))
+    }
+
+    /// Gives a buffer taken by `take_recv` back to its connection.
+    pub(crate) fn release(&mut self, conn_id: &Handle, recv_buffer: Arc<RecvBuffer>) {
+        if let Some(conn) = self.conns.get_mut(conn_id) {
+            conn.held -= 1;
+            match Arc::try_unwrap(recv_buffer) {
+                Ok(recv_buffer) => conn.slab.release(recv_buffer),
+                Err(recv_buffer) => conn.slab.retire(&recv_buffer),
+            }
+        }
+    }
+
+    /// Copies the segments waiting for a buffer of `conn_id` into the free buffers, in the order
+    /// they were received.
+    pub(crate) fn resume(
+        &mut self,
+        conn_id: &Handle,
+    ) -> Vec<(Handle, RecvBuffer, net::WorkCompletion)> {
+        let mut resumed = Vec::new();
+        let conn = match self.conns.get_mut(conn_id) {
+            Some(conn) => conn,
+            None => return resumed,
+        };
+        while let Some((wc, segment)) = conn.stalled.pop_front() {
+            match Self::copy_to_conn(conn, &wc, &segment) {
+                Some((buffer, wc)) => resumed.push((*conn_id, buffer, wc)),
+                None => {
+                    conn.stalled.push_front((wc, segment));
+                    break;
+                }
+            }
+        }
+        resumed
+    }
+
+    /// Drops the receive buffer of a failed receive. Returns the connection of the receive.
+    pub(crate) fn discard_recv(&mut self, wc: &net::WorkCompletion) -> Option<Handle> {
+        if let Some(recv_buffer) = self.posted.remove(&wc.wr_id) {
+            self.slab.release(recv_buffer);
+        }
+        self.qpns.get(&wc.qp_num).copied()
+    }
+
+    fn post_recv(
+        &mut self,
+        odp_mr: &mut ulib::uverbs::MemoryRegion<u8>,
+        wr_id: u64,
+        recv_buffer: RecvBuffer,
+    ) -> Result<(), ulib::Error> {
+        let off = recv_buffer.addr();
+        let len = recv_buffer.len();
+        unsafe {
+            self.srq.post_recv(odp_mr, off..off + len, wr_id)?;
+        }
+        self.posted.insert(wr_id, recv_buffer);
+        Ok(())
+    }
+}
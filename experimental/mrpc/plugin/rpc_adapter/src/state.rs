@@ -1,25 +1,42 @@
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::io;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use dashmap::DashMap;
-use fnv::FnvBuildHasher;
+use fnv::{FnvBuildHasher, FnvHashMap};
 use nix::unistd::Pid;
 
 use mrpc_marshal::SgList;
-use phoenix_api::rpc::CallId;
-use phoenix_api::AsHandle;
+use phoenix_api::rpc::{CallId, ConnectionState};
+use phoenix_api::{AsHandle, Handle};
+use phoenix_api_mrpc::cmd::BindOptions;
 
 use phoenix_salloc::region::AddressMediator;
 
 use phoenix_common::local_resource::{LocalResourceTable, LocalResourceTableGeneric};
+use phoenix_common::log;
 use phoenix_common::resource::{Error as ResourceError, ResourceTable};
 use phoenix_common::state_mgr::ProcessShared;
 
+use super::config::TxOrder;
+use super::conn_log::ConnLog;
+use super::gather::GatherBuffers;
+use super::migrate::Migrations;
 use super::pool::{BufferPool, RecvBuffer};
+use super::recv_window::RecvWindows;
 use super::serialization::AddressMap;
+use super::settings::Settings;
 use super::ulib;
+use super::ulib::uverbs::SendFlags;
+
+// The sends of a connection are signaled at least this often, so that the completed unsignaled
+// ones leave the send queue. It is well below the depth of the send queue.
+pub(crate) const SIGNAL_INTERVAL: usize = 32;
 
 // TODO(cjr): Currently we do not have concurrent access to State while upgrading. But we need to
 // be careful when this assumption does not hold in the future.
@@ -87,6 +104,10 @@ pub(crate) struct WrContext {
 pub(crate) struct ReqContext {
     pub(crate) call_id: CallId,
     pub(crate) sg_len: usize,
+    // when the request is sent, zero if the connection has call timing disabled
+    pub(crate) sent_at: u64,
+    // when the request is sent, zero if the events are not recorded
+    pub(crate) issued_at: u64,
 }
 
 #[derive(Debug, Default)]
@@ -95,34 +116,179 @@ pub(crate) struct RecvContext {
     pub(crate) sg_list: SgList,
     // recv mrs that received sges are on
     pub(crate) recv_buffer_handles: Vec<phoenix_api::Handle>,
+    // when the first segment is received, zero if the connection has call timing disabled
+    pub(crate) first_recv_at: u64,
+}
+
+/// The states of a connection that only the engine owning the connection reads and writes on the
+/// datapath. They are kept out of the `ConnectionContext`, which is shared, so that they need no
+/// locks, see [`RxContexts`].
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionRx {
+    // call_id, sg_len
+    pub(crate) outstanding_req: VecDeque<ReqContext>,
+    pub(crate) receiving_ctx: RecvContext,
+}
+
+/// The [`ConnectionRx`] of the connections of an engine. A completed message is handed off by
+/// taking its `RecvContext` out.
+#[derive(Debug, Default)]
+pub(crate) struct RxContexts {
+    // conn_id -> the states of the connection
+    table: RefCell<FnvHashMap<Handle, ConnectionRx>>,
+}
+
+impl RxContexts {
+    pub(crate) fn insert(&self, conn_id: Handle, rx: ConnectionRx) {
+        self.table.borrow_mut().insert(conn_id, rx);
+    }
+
+    pub(crate) fn remove(&self, conn_id: &Handle) -> Option<ConnectionRx> {
+        self.table.borrow_mut().remove(conn_id)
+    }
+
+    /// Runs `f` on the states of the connection, or returns `None` if the connection is not
+    /// established or is torn down. `f` must not touch the `RxContexts` again.
+    #[inline]
+    pub(crate) fn with<T>(
+        &self,
+        conn_id: &Handle,
+        f: impl FnOnce(&mut ConnectionRx) -> T,
+    ) -> Option<T> {
+        let mut table = self.table.borrow_mut();
+        table.get_mut(conn_id).map(f)
+    }
+
+    /// Takes the segments received so far on the connection.
+    #[inline]
+    pub(crate) fn take_message(&self, conn_id: &Handle) -> RecvContext {
+        self.with(conn_id, |rx| mem::take(&mut rx.receiving_ctx))
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the connection is in the middle of receiving a message.
+    #[inline]
+    pub(crate) fn is_receiving(&self, conn_id: &Handle) -> bool {
+        self.with(conn_id, |rx| !rx.receiving_ctx.sg_list.0.is_empty())
+            .unwrap_or(false)
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct ConnectionContext {
     pub(crate) cmid: ulib::ucm::CmId,
-    pub(crate) credit: AtomicUsize,
-    // call_id, sg_len
-    pub(crate) outstanding_req: spin::Mutex<VecDeque<ReqContext>>,
-    pub(crate) receiving_ctx: spin::Mutex<RecvContext>,
+    // the settings sent to the peer
+    pub(crate) settings: Settings,
+    // negative while the peer takes back more credits than the outstanding sends return
+    pub(crate) credit: AtomicIsize,
+    pub(crate) keepalive: spin::Mutex<KeepaliveContext>,
+    // the settings of the peer, nothing is sent before they arrive
+    pub(crate) peer_settings: spin::Mutex<Option<Settings>>,
+    // whether to report the timestamps of the calls, set by the application
+    pub(crate) call_timing: AtomicBool,
+    // the eager copy threshold set by the application, the one of the engine if not set
+    pub(crate) eager_copy_threshold: spin::Mutex<Option<usize>>,
+    // the order the messages of the connection are sent in
+    pub(crate) tx_order: TxOrder,
+    // the most bytes a send can carry inline, queried from the QP
+    pub(crate) max_inline_data: usize,
+    // the sends posted unsignaled since the last signaled one
+    unsignaled: AtomicUsize,
+    // the last events of the connection
+    pub(crate) log: spin::Mutex<ConnLog>,
+    // whether sending waits for credits, so the stall is logged once
+    pub(crate) credit_stalled: AtomicBool,
+    // the shared listener that accepts the connection, if any
+    pub(crate) listener: Option<Handle>,
+    // held until the connection is gone if it is accepted by a listener
+    _slot: Option<ConnectionSlot>,
 }
 
 impl ConnectionContext {
-    pub(crate) fn new(cmid: ulib::ucm::CmId, credit: usize) -> Self {
+    pub(crate) fn new(
+        cmid: ulib::ucm::CmId,
+        settings: Settings,
+        tx_order: TxOrder,
+        max_inline_data: usize,
+        accepted: Option<(Handle, ConnectionSlot)>,
+    ) -> Self {
+        let (listener, slot) = match accepted {
+            Some((listener, slot)) => (Some(listener), Some(slot)),
+            None => (None, None),
+        };
         Self {
             cmid,
-            credit: AtomicUsize::new(credit),
-            outstanding_req: spin::Mutex::new(VecDeque::new()),
-            receiving_ctx: spin::Mutex::new(RecvContext::default()),
+            settings,
+            credit: AtomicIsize::new(settings.initial_credits as isize),
+            keepalive: spin::Mutex::new(KeepaliveContext::new()),
+            peer_settings: spin::Mutex::new(None),
+            call_timing: AtomicBool::new(false),
+            eager_copy_threshold: spin::Mutex::new(None),
+            tx_order,
+            max_inline_data,
+            unsignaled: AtomicUsize::new(0),
+            log: spin::Mutex::new(ConnLog::default()),
+            credit_stalled: AtomicBool::new(false),
+            listener,
+            _slot: slot,
+        }
+    }
+
+    /// Returns the flags of a send of `len` bytes, inline if `inline` allows and it fits.
+    ///
+    /// Only the send that ends a message must be signaled, as its completion acks the message,
+    /// and those of the sends before it on the QP are implied. The others are signaled every
+    /// [`SIGNAL_INTERVAL`] sends.
+    pub(crate) fn send_flags(&self, len: usize, inline: bool, last: bool) -> SendFlags {
+        let mut flags = if inline && len <= self.max_inline_data {
+            SendFlags::INLINE
+        } else {
+            SendFlags::empty()
+        };
+        // only the engine owning the connection sends on it
+        let unsignaled = self.unsignaled.load(Ordering::Relaxed);
+        if last || unsignaled + 1 >= SIGNAL_INTERVAL {
+            flags |= SendFlags::SIGNALED;
+            self.unsignaled.store(0, Ordering::Relaxed);
+        } else {
+            self.unsignaled.store(unsignaled + 1, Ordering::Relaxed);
+        }
+        flags
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct KeepaliveContext {
+    // the last time anything is received from the peer
+    pub(crate) last_recv: Instant,
+    // the last time a ping is sent to the peer
+    pub(crate) last_ping: Option<Instant>,
+    // the last state reported to the upper layer
+    pub(crate) state: ConnectionState,
+}
+
+impl KeepaliveContext {
+    fn new() -> Self {
+        KeepaliveContext {
+            last_recv: Instant::now(),
+            last_ping: None,
+            state: ConnectionState::Connected,
         }
     }
 }
 
 pub struct LocalResource {
     pub(crate) cmid_table: LocalResourceTable<ConnectionContext>,
+    // the states of the connections on the datapath
+    pub(crate) rx_contexts: RxContexts,
     // wr_id -> WrContext
     pub(crate) wr_contexts: LocalResourceTableGeneric<u64, WrContext>,
     // TODO(wyj): redesign these states
     pub(crate) recv_buffer_table: LocalResourceTable<RecvBuffer>,
+    // buffers to gather the fragmented segments of the received messages into
+    pub(crate) gather_buffers: GatherBuffers,
+    // the number of receive buffers posted on each connection
+    pub(crate) recv_windows: RecvWindows,
     // map from recv buffer's local addr (backend) to app addr (frontend)
     pub(crate) addr_map: AddressMap,
     // Per-thread CQ
@@ -133,38 +299,85 @@ impl LocalResource {
     fn new() -> Self {
         Self {
             cmid_table: LocalResourceTable::default(),
+            rx_contexts: RxContexts::default(),
             wr_contexts: LocalResourceTableGeneric::default(),
             recv_buffer_table: LocalResourceTable::default(),
+            gather_buffers: GatherBuffers::default(),
+            recv_windows: RecvWindows::default(),
             addr_map: AddressMap::new(),
             cq: None,
         }
     }
 
+    /// Puts back a connection taken over from another engine.
+    pub(crate) fn adopt_cmid(
+        &self,
+        conn_ctx: ConnectionContext,
+        rx: ConnectionRx,
+    ) -> Result<(), ResourceError> {
+        self.rx_contexts.insert(conn_ctx.cmid.as_handle(), rx);
+        self.cmid_table.insert(conn_ctx.cmid.as_handle(), conn_ctx)
+    }
+
     #[inline]
     pub(crate) fn insert_cmid(
         &self,
         cmid: ulib::ucm::CmId,
-        credit: usize,
+        settings: Settings,
+        tx_order: TxOrder,
+        max_inline_data: usize,
+        accepted: Option<(Handle, ConnectionSlot)>,
     ) -> Result<(), ResourceError> {
-        self.cmid_table
-            .insert(cmid.as_handle(), ConnectionContext::new(cmid, credit))
+        self.rx_contexts
+            .insert(cmid.as_handle(), ConnectionRx::default());
+        self.cmid_table.insert(
+            cmid.as_handle(),
+            ConnectionContext::new(cmid, settings, tx_order, max_inline_data, accepted),
+        )
+    }
+
+    /// Returns whether the receive buffer of `handle` is posted on, or delivered from, the
+    /// connection `conn_id`.
+    ///
+    /// A buffer released and obtained again has a handle of another generation, so a stale
+    /// handle of it is not found, and neither is a buffer of another connection. These are
+    /// logged and skipped, the buffer stays with its owner.
+    pub(crate) fn owns_recv_buffer(&self, conn_id: &Handle, handle: &Handle) -> bool {
+        match self.wr_contexts.get(&handle.0) {
+            Ok(wr_ctx) if wr_ctx.conn_id == *conn_id => true,
+            Ok(wr_ctx) => {
+                log::warn!(
+                    "{:?} reclaims recv buffer {:?} of {:?}, skipped",
+                    conn_id,
+                    handle,
+                    wr_ctx.conn_id
+                );
+                false
+            }
+            Err(_) => {
+                log::warn!(
+                    "{:?} reclaims stale recv buffer {:?}, skipped",
+                    conn_id,
+                    handle
+                );
+                false
+            }
+        }
     }
 }
 
 // NOTE: Pay attention to the drop order.
 pub struct Resource {
     // rpc_adapter_id -> Queue of pre_cmid
-    pub(crate) builder_table: DashMap<
-        usize,
-        VecDeque<ulib::ucm::CmIdBuilder<'static, 'static, 'static, 'static, 'static>>,
-        FnvBuildHasher,
-    >,
-    pub(crate) staging_pre_cmid_table: ResourceTable<ulib::ucm::PreparedCmId>,
-    // (rpc_adapter_id, CmIdListener)
-    pub(crate) listener_table: ResourceTable<(usize, ulib::ucm::CmIdListener)>,
+    pub(crate) builder_table: DashMap<usize, VecDeque<IncomingConnection>, FnvBuildHasher>,
+    pub(crate) staging_pre_cmid_table: ResourceTable<StagedConnection>,
+    pub(crate) listener_table: ResourceTable<SharedListener>,
 
     // receive buffer pool
     pub(crate) recv_buffer_pool: BufferPool,
+
+    // the connections being moved between the engines
+    pub(crate) migrations: Migrations,
 }
 
 impl Resource {
@@ -174,8 +387,180 @@ impl Resource {
             staging_pre_cmid_table: ResourceTable::default(),
             listener_table: ResourceTable::default(),
             recv_buffer_pool: BufferPool::new(addr_mediator),
+            migrations: Migrations::default(),
+        }
+    }
+}
+
+impl Resource {
+    /// Joins the listener bound to `addr` if any. Returns the handle of the listener. The
+    /// listener keeps the options it is created with.
+    pub(crate) fn join_listener(
+        &self,
+        addr: &SocketAddr,
+        options: &BindOptions,
+        rpc_adapter_id: usize,
+    ) -> Option<Handle> {
+        self.listener_table
+            .inner()
+            .iter()
+            .find(|entry| entry.data().addr == *addr)
+            .map(|entry| {
+                let listener = entry.data();
+                if listener.options != *options {
+                    log::warn!(
+                        "{} is bound with {:?}, {:?} are ignored",
+                        addr,
+                        listener.options,
+                        options
+                    );
+                }
+                listener.join(rpc_adapter_id);
+                *entry.key()
+            })
+    }
+
+    /// Returns the listeners that `rpc_adapter_id` has joined.
+    pub(crate) fn listeners_of(&self, rpc_adapter_id: usize) -> Vec<Handle> {
+        self.listener_table
+            .inner()
+            .iter()
+            .filter(|entry| entry.data().has_member(rpc_adapter_id))
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    /// Leaves the listener `handle`. The listener is closed when no engine is left. The
+    /// connections taken from it for this engine go to the engines left, or are rejected.
+    pub(crate) fn leave_listener(&self, handle: &Handle, rpc_adapter_id: usize) {
+        // an engine binding to the address in the meantime waits for the shard lock, so it
+        // either joins before the listener is closed or creates a new one
+        self.listener_table
+            .inner()
+            .remove_if(handle, |_, entry| entry.data().leave(rpc_adapter_id));
+
+        let stale: Vec<_> = match self.builder_table.get_mut(&rpc_adapter_id) {
+            Some(mut queue) => {
+                let (stale, rest) = mem::take(&mut *queue)
+                    .into_iter()
+                    .partition(|conn| conn.listener == *handle);
+                *queue = rest;
+                stale
+            }
+            None => Vec::new(),
+        };
+        let listener = self.listener_table.get(handle).ok();
+        for conn in stale {
+            match listener.as_ref().and_then(|l| l.pick()) {
+                Some(id) => self
+                    .builder_table
+                    .entry(id)
+                    .or_insert_with(VecDeque::new)
+                    .push_back(conn),
+                None => conn.builder.reject(),
+            }
+        }
+    }
+}
+
+/// A connection request taken from a listener, waiting for an engine to set it up.
+pub(crate) struct IncomingConnection {
+    pub(crate) listener: Handle,
+    pub(crate) builder: ulib::ucm::CmIdBuilder<'static, 'static, 'static, 'static, 'static>,
+    pub(crate) options: BindOptions,
+    pub(crate) slot: ConnectionSlot,
+}
+
+/// A connection whose receive buffers are being mapped into the application before it is
+/// accepted.
+#[derive(Debug)]
+pub(crate) struct StagedConnection {
+    pub(crate) pre_id: ulib::ucm::PreparedCmId,
+    pub(crate) settings: Settings,
+    pub(crate) listener: Handle,
+    pub(crate) slot: ConnectionSlot,
+}
+
+/// Counts a connection of a listener until it is dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A listener shared by the RpcAdapter engines of a process that bind to the same address.
+/// Incoming connections are distributed among the engines in round-robin.
+pub(crate) struct SharedListener {
+    pub(crate) addr: SocketAddr,
+    pub(crate) options: BindOptions,
+    pub(crate) listener: ulib::ucm::CmIdListener,
+    // the connections of the listener that are not gone
+    connections: Arc<AtomicUsize>,
+    // rpc_adapter_ids of the engines sharing the listener
+    members: spin::Mutex<Vec<usize>>,
+    next: AtomicUsize,
+}
+
+impl SharedListener {
+    pub(crate) fn new(
+        addr: SocketAddr,
+        options: BindOptions,
+        rpc_adapter_id: usize,
+        listener: ulib::ucm::CmIdListener,
+    ) -> Self {
+        SharedListener {
+            addr,
+            options,
+            listener,
+            connections: Arc::new(AtomicUsize::new(0)),
+            members: spin::Mutex::new(vec![rpc_adapter_id]),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns whether the engine `rpc_adapter_id` has joined the listener.
+    pub(crate) fn has_member(&self, rpc_adapter_id: usize) -> bool {
+        self.members.lock().contains(&rpc_adapter_id)
+    }
+
+    pub(crate) fn join(&self, rpc_adapter_id: usize) {
+        let mut members = self.members.lock();
+        if !members.contains(&rpc_adapter_id) {
+            members.push(rpc_adapter_id);
         }
     }
+
+    /// Removes an engine from the listener. Returns true if no engine is left.
+    pub(crate) fn leave(&self, rpc_adapter_id: usize) -> bool {
+        let mut members = self.members.lock();
+        members.retain(|&id| id != rpc_adapter_id);
+        members.is_empty()
+    }
+
+    /// Takes a slot for a new connection. Returns `None` if the listener has `max_connections`
+    /// connections already.
+    pub(crate) fn admit(&self) -> Option<ConnectionSlot> {
+        let max = self.options.max_connections.unwrap_or(usize::MAX);
+        self.connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot(Arc::clone(&self.connections)))
+    }
+
+    /// Picks the engine to handle the next incoming connection. Returns `None` if all engines
+    /// have left.
+    pub(crate) fn pick(&self) -> Option<usize> {
+        let members = self.members.lock();
+        if members.is_empty() {
+            return None;
+        }
+        Some(members[self.next.fetch_add(1, Ordering::Relaxed) % members.len()])
+    }
 }
 
 impl State {
@@ -199,6 +584,11 @@ impl State {
         self.shared.stop_acceptor.store(stop, Ordering::Relaxed);
     }
 
+    /// Takes the CQ of the engine, which is polled by another engine once this one is gone.
+    pub(crate) fn take_cq(&mut self) -> Option<ulib::uverbs::CompletionQueue> {
+        self.local_resource.cq.take()
+    }
+
     pub(crate) fn get_or_init_cq(
         &mut self,
         cq_size: i32,
@@ -223,3 +613,97 @@ impl State {
         Ok(cq)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mrpc_marshal::SgE;
+    use test::Bencher;
+
+    const CONN: Handle = Handle(1);
+
+    // the segments of a message received in 4 buffers
+    fn segments() -> impl Iterator<Item = (SgE, Handle)> {
+        (0..4).map(|i| {
+            (
+                SgE {
+                    ptr: 0x1000 * i,
+                    len: 64,
+                },
+                Handle(i as u64),
+            )
+        })
+    }
+
+    #[test]
+    fn rx_contexts_of_established_connections() {
+        let rx_contexts = RxContexts::default();
+        assert_eq!(rx_contexts.with(&CONN, |rx| rx.outstanding_req.len()), None);
+        assert!(!rx_contexts.is_receiving(&CONN));
+
+        rx_contexts.insert(CONN, ConnectionRx::default());
+        let (sge, handle) = segments().next().unwrap();
+        rx_contexts.with(&CONN, |rx| {
+            rx.receiving_ctx.sg_list.0.push(sge);
+            rx.receiving_ctx.recv_buffer_handles.push(handle);
+        });
+        assert!(rx_contexts.is_receiving(&CONN));
+        assert_eq!(
+            rx_contexts.take_message(&CONN).recv_buffer_handles,
+            [handle]
+        );
+        assert!(!rx_contexts.is_receiving(&CONN));
+
+        // nothing is kept for a connection torn down
+        assert!(rx_contexts.remove(&CONN).is_some());
+        assert_eq!(rx_contexts.with(&CONN, |_| ()), None);
+        assert!(rx_contexts.take_message(&CONN).sg_list.0.is_empty());
+    }
+
+    #[bench]
+    fn rx_contexts_message(b: &mut Bencher) {
+        let rx_contexts = RxContexts::default();
+        rx_contexts.insert(CONN, ConnectionRx::default());
+        b.iter(|| {
+            rx_contexts.with(&CONN, |rx| {
+                rx.outstanding_req.push_back(ReqContext {
+                    call_id: CallId(1),
+                    sg_len: 4,
+                    sent_at: 0,
+                    issued_at: 0,
+                })
+            });
+            for (sge, handle) in segments() {
+                rx_contexts.with(&CONN, |rx| {
+                    rx.receiving_ctx.sg_list.0.push(sge);
+                    rx.receiving_ctx.recv_buffer_handles.push(handle);
+                });
+            }
+            test::black_box(rx_contexts.take_message(&CONN));
+            test::black_box(rx_contexts.with(&CONN, |rx| rx.outstanding_req.pop_front()));
+        });
+    }
+
+    // the states behind spin locks, as they were kept before
+    #[bench]
+    fn spin_locked_message(b: &mut Bencher) {
+        let outstanding_req = spin::Mutex::new(VecDeque::new());
+        let receiving_ctx = spin::Mutex::new(RecvContext::default());
+        b.iter(|| {
+            outstanding_req.lock().push_back(ReqContext {
+                call_id: CallId(1),
+                sg_len: 4,
+                sent_at: 0,
+                issued_at: 0,
+            });
+            for (sge, handle) in segments() {
+                let mut recv_ctx = receiving_ctx.lock();
+                recv_ctx.sg_list.0.push(sge);
+                recv_ctx.recv_buffer_handles.push(handle);
+            }
+            test::black_box(mem::take(&mut *receiving_ctx.lock()));
+            test::black_box(outstanding_req.lock().pop_front());
+        });
+    }
+}
@@ -0,0 +1,200 @@
+//! The messages an engine has yet to send, e.g., while their connections wait for credits.
+//!
+//! The messages are sent in the order they arrive, unless their connection uses
+//! earliest-deadline-first (see `TxQueueConfig`): the messages of such connections that carry a
+//! deadline go before all others, the earliest deadline first, and the rest follow in the order
+//! they arrive. A message that cannot be sent yet is put back in its place with `push_front`.
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+
+use phoenix_common::engine::datapath::message::RpcMessageTx;
+
+use super::config::TxOrder;
+
+// The messages that go in the order they arrive.
+const NO_DEADLINE: u64 = u64::MAX;
+
+#[derive(Debug)]
+struct Entry {
+    // the deadline, and the arrival order
+    key: (u64, u64),
+    msg: RpcMessageTx,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // the smallest key is on the top of the heap
+        other.key.cmp(&self.key)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TxQueue {
+    heap: BinaryHeap<Entry>,
+    next_seq: u64,
+    // the key of the message popped last, for `push_front`
+    popped: Option<(u64, u64)>,
+}
+
+impl From<VecDeque<RpcMessageTx>> for TxQueue {
+    fn from(queue: VecDeque<RpcMessageTx>) -> Self {
+        let mut tx_queue = TxQueue::default();
+        for msg in queue {
+            tx_queue.push_back(msg, TxOrder::Fifo);
+        }
+        tx_queue
+    }
+}
+
+impl TxQueue {
+    /// Queues a message of a connection that uses `order`.
+    pub(crate) fn push_back(&mut self, msg: RpcMessageTx, order: TxOrder) {
+        let deadline = match order {
+            TxOrder::Edf if msg.deadline != 0 => msg.deadline,
+            _ => NO_DEADLINE,
+        };
+        self.heap.push(Entry {
+            key: (deadline, self.next_seq),
+            msg,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Takes the message to send next.
+    pub(crate) fn pop_front(&mut self) -> Option<RpcMessageTx> {
+        let entry = self.heap.pop()?;
+        self.popped = Some(entry.key);
+        Some(entry.msg)
+    }
+
+    /// Puts back the message popped last, which is sent next again.
+    pub(crate) fn push_front(&mut self, msg: RpcMessageTx) {
+        let key = self
+            .popped
+            .take()
+            .expect("push_front without a popped message");
+        self.heap.push(Entry { key, msg });
+    }
+
+    /// Returns whether any queued message satisfies `f`.
+    pub(crate) fn any(&self, mut f: impl FnMut(&RpcMessageTx) -> bool) -> bool {
+        self.heap.iter().any(|entry| f(&entry.msg))
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::Unique;
+
+    use phoenix_common::engine::datapath::meta_pool::MetaBufferPtr;
+
+    // a message told apart by `id`, whose meta buffer is never read
+    fn msg(id: usize, deadline: u64) -> RpcMessageTx {
+        RpcMessageTx {
+            meta_buf_ptr: MetaBufferPtr(Unique::dangling()),
+            addr_backend: id,
+            deadline,
+            bulk: false,
+            dispatch_version: 0,
+        }
+    }
+
+    fn drain(queue: &mut TxQueue) -> Vec<usize> {
+        std::iter::from_fn(|| queue.pop_front())
+            .map(|msg| msg.addr_backend)
+            .collect()
+    }
+
+    #[test]
+    fn send_order() {
+        use TxOrder::{Edf, Fifo};
+        // the messages queued as (id, deadline, order), and the ids in the order they are sent
+        let cases: &[(&str, &[(usize, u64, TxOrder)], &[usize])] = &[
+            (
+                "fifo ignores deadlines",
+                &[(1, 30, Fifo), (2, 10, Fifo), (3, 20, Fifo)],
+                &[1, 2, 3],
+            ),
+            (
+                "earliest deadline first",
+                &[(1, 30, Edf), (2, 10, Edf), (3, 20, Edf)],
+                &[2, 3, 1],
+            ),
+            (
+                "ties in arrival order",
+                &[(1, 20, Edf), (2, 10, Edf), (3, 20, Edf), (4, 10, Edf)],
+                &[2, 4, 1, 3],
+            ),
+            (
+                "no deadline after the deadlines",
+                &[(1, 0, Edf), (2, 50, Edf), (3, 0, Edf), (4, 40, Edf)],
+                &[4, 2, 1, 3],
+            ),
+            (
+                "fifo connections after the edf ones",
+                &[(1, 5, Fifo), (2, 50, Edf), (3, 0, Edf), (4, 1, Fifo)],
+                &[2, 1, 3, 4],
+            ),
+        ];
+        for &(name, queued, sent) in cases {
+            let mut queue = TxQueue::default();
+            for &(id, deadline, order) in queued {
+                queue.push_back(msg(id, deadline), order);
+            }
+            assert_eq!(queue.len(), queued.len(), "{name}");
+            assert_eq!(drain(&mut queue), sent, "{name}");
+        }
+    }
+
+    #[test]
+    fn push_front_keeps_the_place() {
+        let mut queue = TxQueue::default();
+        queue.push_back(msg(1, 20), TxOrder::Edf);
+        queue.push_back(msg(2, 10), TxOrder::Edf);
+        let first = queue.pop_front().unwrap();
+        assert_eq!(first.addr_backend, 2);
+
+        // an earlier deadline arrives while the message waits, it goes first
+        queue.push_back(msg(3, 5), TxOrder::Edf);
+        queue.push_front(first);
+        assert_eq!(drain(&mut queue), [3, 2, 1]);
+
+        // a tie keeps the message put back ahead of the later arrivals
+        queue.push_back(msg(4, 10), TxOrder::Edf);
+        let first = queue.pop_front().unwrap();
+        queue.push_back(msg(5, 10), TxOrder::Edf);
+        queue.push_front(first);
+        assert_eq!(drain(&mut queue), [4, 5]);
+    }
+
+    #[test]
+    fn restored_queue_keeps_its_order() {
+        let restored: VecDeque<_> = [(1, 30), (2, 10), (3, 0)]
+            .into_iter()
+            .map(|(id, deadline)| msg(id, deadline))
+            .collect();
+        let mut queue = TxQueue::from(restored);
+        queue.push_back(msg(4, 5), TxOrder::Edf);
+        assert_eq!(drain(&mut queue), [4, 1, 2, 3]);
+    }
+}
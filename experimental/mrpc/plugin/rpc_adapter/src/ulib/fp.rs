@@ -3,7 +3,8 @@ use std::slice::SliceIndex;
 
 use phoenix_api::buf;
 
-use super::{get_ops, Error};
+use super::sim;
+use super::{get_rdma_ops, get_transport, Error, Transport};
 
 use super::ucm;
 use super::ucm::{CmId, PreparedCmId};
@@ -21,22 +22,27 @@ impl ucm::Inner {
     where
         R: SliceIndex<[T], Output = [T]>,
     {
-        get_ops().post_recv(
-            self.handle.0,
-            &mr.inner.mr,
-            buf::Range::new(mr, range),
-            context,
-        )?;
+        match get_transport() {
+            Transport::Rdma(ops) => ops.post_recv(
+                self.handle.0,
+                mr.inner.rdma(),
+                buf::Range::new(mr, range),
+                context,
+            )?,
+            Transport::Sim(sim) => {
+                sim.post_recv(self.handle.0, sim::as_bytes_mut(&mut mr[range]), context)?
+            }
+        }
         Ok(())
     }
 }
 
-impl PreparedCmId {
+impl uverbs::SharedReceiveQueue {
     /// # Safety
     ///
     /// The memory region can only be safely reused or dropped after the request is fully executed
     /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
-    /// until `CompletionQueue::poll_cq` returns a completion for this send).
+    /// until `CompletionQueue::poll_cq` returns a completion for this receive).
     #[inline]
     pub(crate) unsafe fn post_recv<T, R>(
         &self,
@@ -47,11 +53,20 @@ impl PreparedCmId {
     where
         R: SliceIndex<[T], Output = [T]>,
     {
-        self.inner.post_recv(mr, range, context)
+        match get_transport() {
+            Transport::Rdma(ops) => ops.post_srq_recv(
+                &self.inner,
+                mr.inner.rdma(),
+                buf::Range::new(mr, range),
+                context,
+            )?,
+            Transport::Sim(_) => return Err(Error::SrqUnsupported),
+        }
+        Ok(())
     }
 }
 
-impl CmId {
+impl PreparedCmId {
     /// # Safety
     ///
     /// The memory region can only be safely reused or dropped after the request is fully executed
@@ -69,32 +84,101 @@ impl CmId {
     {
         self.inner.post_recv(mr, range, context)
     }
+}
 
+impl PreparedCmId {
+    /// Sends a datagram to the QP `remote_qpn` at the port addressed by `ah`. The CmId must be
+    /// created for unreliable datagrams.
+    ///
     /// # Safety
     ///
     /// The memory region can only be safely reused or dropped after the request is fully executed
     /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
     /// until `CompletionQueue::poll_cq` returns a completion for this send).
     #[inline]
-    pub(crate) unsafe fn post_send<T, R>(
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) unsafe fn post_ud_send_with_imm<T, R>(
         &self,
         mr: &uverbs::MemoryRegion<T>,
         range: R,
         context: u64,
         flags: uverbs::SendFlags,
+        imm: u32,
+        ah: &uverbs::AddressHandle,
+        remote_qpn: u32,
+        remote_qkey: u32,
     ) -> Result<(), Error>
     where
         R: SliceIndex<[T], Output = [T]>,
     {
-        get_ops().post_send(
+        get_rdma_ops()?.post_ud_send_with_imm(
             self.inner.handle.0,
-            &mr.inner.mr,
+            mr.inner.rdma(),
             buf::Range::new(mr, range),
             context,
             flags,
+            imm,
+            &ah.inner,
+            remote_qpn,
+            remote_qkey,
         )?;
         Ok(())
     }
+}
+
+impl CmId {
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `CompletionQueue::poll_cq` returns a completion for this send).
+    #[inline]
+    pub(crate) unsafe fn post_recv<T, R>(
+        &self,
+        mr: &mut uverbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        self.inner.post_recv(mr, range, context)
+    }
+
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `CompletionQueue::poll_cq` returns a completion for this send).
+    #[inline]
+    pub(crate) unsafe fn post_send<T, R>(
+        &self,
+        mr: &uverbs::MemoryRegion<T>,
+        range: R,
+        context: u64,
+        flags: uverbs::SendFlags,
+    ) -> Result<(), Error>
+    where
+        R: SliceIndex<[T], Output = [T]>,
+    {
+        match get_transport() {
+            Transport::Rdma(ops) => ops.post_send(
+                self.inner.handle.0,
+                mr.inner.rdma(),
+                buf::Range::new(mr, range),
+                context,
+                flags,
+            )?,
+            Transport::Sim(sim) => sim.post_send(
+                self.inner.handle.0,
+                sim::as_bytes(&mr[range]),
+                context,
+                flags,
+                None,
+            )?,
+        }
+        Ok(())
+    }
 
     /// # Safety
     ///
@@ -113,14 +197,23 @@ impl CmId {
     where
         R: SliceIndex<[T], Output = [T]>,
     {
-        get_ops().post_send_with_imm(
-            self.inner.handle.0,
-            &mr.inner.mr,
-            buf::Range::new(mr, range),
-            context,
-            flags,
-            imm,
-        )?;
+        match get_transport() {
+            Transport::Rdma(ops) => ops.post_send_with_imm(
+                self.inner.handle.0,
+                mr.inner.rdma(),
+                buf::Range::new(mr, range),
+                context,
+                flags,
+                imm,
+            )?,
+            Transport::Sim(sim) => sim.post_send(
+                self.inner.handle.0,
+                sim::as_bytes(&mr[range]),
+                context,
+                flags,
+                Some(imm),
+            )?,
+        }
         Ok(())
     }
 
@@ -142,15 +235,18 @@ impl CmId {
     where
         R: SliceIndex<[T], Output = [T]>,
     {
-        get_ops().post_write(
-            self.inner.handle.0,
-            &mr.inner.mr,
-            buf::Range::new(mr, range),
-            context,
-            rkey,
-            remote_offset,
-            flags,
-        )?;
+        match get_transport() {
+            Transport::Rdma(ops) => ops.post_write(
+                self.inner.handle.0,
+                mr.inner.rdma(),
+                buf::Range::new(mr, range),
+                context,
+                rkey,
+                remote_offset,
+                flags,
+            )?,
+            Transport::Sim(sim) => sim.post_one_sided()?,
+        }
         Ok(())
     }
 
@@ -172,15 +268,18 @@ impl CmId {
     where
         R: SliceIndex<[T], Output = [T]>,
     {
-        get_ops().post_read(
-            self.inner.handle.0,
-            &mr.inner.mr,
-            buf::Range::new(mr, range),
-            context,
-            rkey,
-            remote_offset,
-            flags,
-        )?;
+        match get_transport() {
+            Transport::Rdma(ops) => ops.post_read(
+                self.inner.handle.0,
+                mr.inner.rdma(),
+                buf::Range::new(mr, range),
+                context,
+                rkey,
+                remote_offset,
+                flags,
+            )?,
+            Transport::Sim(sim) => sim.post_one_sided()?,
+        }
         Ok(())
     }
 
@@ -214,7 +313,7 @@ impl CmId {
 impl CompletionQueue {
     #[inline]
     pub(crate) fn poll(&self, wc: &mut Vec<WorkCompletion>) -> Result<(), Error> {
-        get_ops().poll_cq(&self.inner, wc)?;
+        transport!(poll_cq(&self.inner, wc))?;
         Ok(())
     }
 }
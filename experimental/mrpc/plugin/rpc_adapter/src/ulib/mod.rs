@@ -6,15 +6,39 @@ use thiserror::Error;
 use transport_rdma::ops::Ops;
 use transport_rdma::{ApiError, DatapathError};
 
+/// Calls the method of the transport in use, which has the same signature in both transports.
+macro_rules! transport {
+    ($method:ident($($arg:expr),* $(,)?).await) => {
+        match $crate::ulib::get_transport() {
+            $crate::ulib::Transport::Rdma(ops) => ops.$method($($arg),*).await,
+            $crate::ulib::Transport::Sim(sim) => sim.$method($($arg),*).await,
+        }
+    };
+    ($method:ident($($arg:expr),* $(,)?)) => {
+        match $crate::ulib::get_transport() {
+            $crate::ulib::Transport::Rdma(ops) => ops.$method($($arg),*),
+            $crate::ulib::Transport::Sim(sim) => sim.$method($($arg),*),
+        }
+    };
+}
+
 #[allow(dead_code)]
 pub(crate) mod fp;
 
+pub(crate) mod sim;
+
 #[allow(dead_code)]
 pub(crate) mod ucm;
 
 #[allow(dead_code)]
 pub(crate) mod uverbs;
 
+/// The transport that the ulib runs on.
+pub(crate) enum Transport {
+    Rdma(Ops),
+    Sim(sim::SimTransport),
+}
+
 #[derive(Error, Debug)]
 pub(crate) enum Error {
     #[error("Error in RDMA API: {0}")]
@@ -27,6 +51,14 @@ pub(crate) enum Error {
     NoAddrResolved,
     #[error("Connect failed: {0}")]
     Connect(ApiError),
+    #[error("No local port in {0}..={1} is free")]
+    NoLocalPort(u16, u16),
+    #[error("Unreliable datagrams are not supported by the simulated transport")]
+    DatagramUnsupported,
+    #[error("Shared receive queues are not supported by the simulated transport")]
+    SrqUnsupported,
+    #[error("Remote reads are not supported by the simulated transport")]
+    RemoteReadUnsupported,
 }
 
 // Get an owned structure from a borrow
@@ -35,7 +67,17 @@ pub(crate) trait FromBorrow<Borrowed> {
 }
 
 #[inline]
-fn get_ops() -> &'static Ops {
+fn get_transport() -> &'static Transport {
     use super::engine::ELS;
-    ELS.with(|els| &els.borrow().as_ref().unwrap().ops)
+    ELS.with(|els| &els.borrow().as_ref().unwrap().transport)
+}
+
+/// Returns the RDMA transport, for the operations of unreliable datagrams that the simulated
+/// transport does not have.
+#[inline]
+fn get_rdma_ops() -> Result<&'static Ops, Error> {
+    match get_transport() {
+        Transport::Rdma(ops) => Ok(ops),
+        Transport::Sim(_) => Err(Error::DatagramUnsupported),
+    }
 }
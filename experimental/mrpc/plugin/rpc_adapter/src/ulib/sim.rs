@@ -0,0 +1,881 @@
+//! A simulated transport that runs the RPC adapter without RDMA NICs.
+//!
+//! It mirrors the subset of [`Ops`] used by the ulib, backed by in-memory queues shared by all
+//! engines in the daemon. A send copies the bytes to the inbox of the peer when it is posted,
+//! and the peer delivers them into its posted receives when its receive CQ is polled, after the
+//! configured latency. Unlike RC, the link is unreliable: a lost RPC message is dropped silently
+//! while the sender still gets a successful completion. RDMA read and write are not supported.
+//!
+//! [`Ops`]: transport_rdma::ops::Ops
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+
+use phoenix_api::addrinfo::PortSpace;
+use phoenix_api::net::{self, returned, SendFlags, WcFlags, WcOpcode, WcStatus, WorkCompletion};
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
+use phoenix_common::engine::future;
+use phoenix_common::log;
+use transport_rdma::{ApiError, DatapathError};
+
+use crate::config::SimConfig;
+
+type Result<T> = std::result::Result<T, ApiError>;
+
+// Error codes of ibv_wc_status.
+const WC_LOC_LEN_ERR: u32 = 1;
+const WC_WR_FLUSH_ERR: u32 = 5;
+const WC_RETRY_EXC_ERR: u32 = 12;
+
+/// The verbs context and the protection domain of the simulated NIC.
+const SIM_VERBS_CONTEXT: net::VerbsContext =
+    net::VerbsContext(Handle::new(HandleNamespace::Untyped, 0));
+const SIM_PD: net::ProtectionDomain =
+    net::ProtectionDomain(Handle::new(HandleNamespace::Untyped, 0));
+
+lazy_static! {
+    static ref FABRIC: Fabric = Fabric::default();
+}
+
+#[derive(Default)]
+struct Fabric {
+    // listening address -> the listening endpoint
+    listeners: DashMap<SocketAddr, Handle>,
+    endpoints: DashMap<Handle, Arc<Endpoint>>,
+    cqs: DashMap<Handle, Arc<Cq>>,
+    next_id: AtomicU64,
+    next_port: AtomicU16,
+}
+
+impl Fabric {
+    fn new_handle(&self, ns: HandleNamespace) -> Handle {
+        // id 0 is taken by the verbs context and the PD
+        Handle::new(ns, self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn endpoint(&self, handle: Handle) -> Result<Arc<Endpoint>> {
+        self.endpoints
+            .get(&handle)
+            .map(|e| Arc::clone(&e))
+            .ok_or(ApiError::NotFound)
+    }
+
+    fn add_endpoint(&self, local_addr: SocketAddr) -> Arc<Endpoint> {
+        let endpoint = Arc::new(Endpoint {
+            handle: self.new_handle(HandleNamespace::CmId),
+            local_addr: spin::Mutex::new(local_addr),
+            peer_addr: spin::Mutex::new(None),
+            peer: spin::Mutex::new(Weak::new()),
+            connected: AtomicBool::new(false),
+            cqs: spin::Mutex::new(None),
+            max_inline_data: AtomicU32::new(0),
+            requests: spin::Mutex::new(VecDeque::new()),
+            recvs: spin::Mutex::new(VecDeque::new()),
+            inbox: spin::Mutex::new(VecDeque::new()),
+            in_message: AtomicBool::new(false),
+            losing: AtomicBool::new(false),
+        });
+        self.endpoints
+            .insert(endpoint.handle, Arc::clone(&endpoint));
+        endpoint
+    }
+
+    fn ephemeral_addr(&self) -> SocketAddr {
+        let port = 32768 + self.next_port.fetch_add(1, Ordering::Relaxed) % 28232;
+        SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+    }
+}
+
+struct PostedRecv {
+    wr_id: u64,
+    addr: usize,
+    len: usize,
+}
+
+struct Message {
+    deliver_at: Instant,
+    data: Vec<u8>,
+    imm: Option<u32>,
+}
+
+/// A communication identifier.
+struct Endpoint {
+    handle: Handle,
+    local_addr: spin::Mutex<SocketAddr>,
+    peer_addr: spin::Mutex<Option<SocketAddr>>,
+    peer: spin::Mutex<Weak<Endpoint>>,
+    connected: AtomicBool,
+    // send CQ and recv CQ, set when the QP is created
+    cqs: spin::Mutex<Option<(Arc<Cq>, Arc<Cq>)>>,
+    // as asked for when the QP is created
+    max_inline_data: AtomicU32,
+    // connection requests, if listening
+    requests: spin::Mutex<VecDeque<Handle>>,
+    recvs: spin::Mutex<VecDeque<PostedRecv>>,
+    inbox: spin::Mutex<VecDeque<Message>>,
+    // whether the last send did not finish an RPC message
+    in_message: AtomicBool,
+    // whether the current RPC message is lost
+    losing: AtomicBool,
+}
+
+impl Endpoint {
+    fn peer(&self) -> Option<Arc<Endpoint>> {
+        self.peer.lock().upgrade()
+    }
+
+    fn send_cq(&self) -> Option<Arc<Cq>> {
+        self.cqs
+            .lock()
+            .as_ref()
+            .map(|(send_cq, _)| Arc::clone(send_cq))
+    }
+
+    /// Moves the messages that are due into the posted receives.
+    fn deliver(&self, cq: &Cq, now: Instant) {
+        let mut inbox = self.inbox.lock();
+        let mut recvs = self.recvs.lock();
+        while inbox.front().map_or(false, |m| m.deliver_at <= now) && !recvs.is_empty() {
+            let msg = inbox.pop_front().unwrap();
+            let recv = recvs.pop_front().unwrap();
+            if msg.data.len() > recv.len {
+                cq.push(completion(
+                    recv.wr_id,
+                    WcStatus::Error(NonZeroU32::new(WC_LOC_LEN_ERR).unwrap()),
+                    WcOpcode::Recv,
+                    0,
+                    None,
+                ));
+                continue;
+            }
+            // SAFETY: the buffer of a posted receive stays valid until its completion is polled.
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    msg.data.as_ptr(),
+                    ptr::from_exposed_addr_mut(recv.addr),
+                    msg.data.len(),
+                );
+            }
+            cq.push(completion(
+                recv.wr_id,
+                WcStatus::Success,
+                WcOpcode::Recv,
+                msg.data.len() as u32,
+                msg.imm,
+            ));
+        }
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        // Flush the receives that will never complete.
+        let recvs = self.recvs.get_mut();
+        if recvs.is_empty() {
+            return;
+        }
+        if let Some((_, recv_cq)) = self.cqs.get_mut() {
+            for recv in recvs.drain(..) {
+                recv_cq.push(WorkCompletion::new_vendor_err(
+                    recv.wr_id,
+                    WcStatus::Error(NonZeroU32::new(WC_WR_FLUSH_ERR).unwrap()),
+                    0,
+                ));
+            }
+        }
+    }
+}
+
+/// A completion queue.
+#[derive(Default)]
+struct Cq {
+    completions: spin::Mutex<VecDeque<WorkCompletion>>,
+    // the endpoints whose receives complete in this CQ
+    endpoints: spin::Mutex<Vec<Weak<Endpoint>>>,
+    refcnt: AtomicUsize,
+}
+
+impl Cq {
+    fn push(&self, wc: WorkCompletion) {
+        self.completions.lock().push_back(wc);
+    }
+}
+
+fn completion(
+    wr_id: u64,
+    status: WcStatus,
+    opcode: WcOpcode,
+    byte_len: u32,
+    imm: Option<u32>,
+) -> WorkCompletion {
+    WorkCompletion {
+        wr_id,
+        status,
+        opcode,
+        vendor_err: 0,
+        byte_len,
+        imm_data: imm.unwrap_or(0),
+        qp_num: 0,
+        ud_src_qp: 0,
+        wc_flags: if imm.is_some() {
+            WcFlags::WITH_IMM
+        } else {
+            WcFlags::empty()
+        },
+        pkey_index: 0,
+        slid: 0,
+        sl: 0,
+        dlid_path_bits: 0,
+    }
+}
+
+/// The memory region of the simulated transport. Any address of the process is accessible, so
+/// registration only records the range.
+#[derive(Debug)]
+pub(crate) struct MemoryRegion {
+    addr: usize,
+    len: usize,
+    handle: Handle,
+}
+
+impl MemoryRegion {
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        ptr::from_exposed_addr(self.addr)
+    }
+
+    #[inline]
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u8 {
+        ptr::from_exposed_addr_mut(self.addr)
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl AsHandle for MemoryRegion {
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        self.handle
+    }
+}
+
+/// The per-client handle of the simulated transport.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SimTransport {
+    latency: Duration,
+    loss: f64,
+}
+
+impl SimTransport {
+    pub(crate) fn new(config: SimConfig) -> Self {
+        SimTransport {
+            latency: Duration::from_micros(config.latency_us),
+            loss: config.loss.clamp(0.0, 1.0),
+        }
+    }
+
+    pub(crate) fn default_verbs_context(&self) -> returned::VerbsContext {
+        returned::VerbsContext {
+            handle: SIM_VERBS_CONTEXT,
+        }
+    }
+
+    pub(crate) fn default_pd(&self) -> returned::ProtectionDomain {
+        returned::ProtectionDomain { handle: SIM_PD }
+    }
+
+    pub(crate) fn get_default_contexts(&self) -> Result<Vec<returned::VerbsContext>> {
+        Ok(vec![self.default_verbs_context()])
+    }
+
+    pub(crate) fn get_default_pds(&self) -> Result<Vec<returned::ProtectionDomain>> {
+        Ok(vec![self.default_pd()])
+    }
+
+    /// The fabric has a single device, on the loopback address.
+    pub(crate) fn get_device_addr(&self, nic_index: usize) -> Result<IpAddr> {
+        match nic_index {
+            0 => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            _ => Err(ApiError::NotFound),
+        }
+    }
+
+    pub(crate) fn register_on_demand_paging(&self) -> MemoryRegion {
+        MemoryRegion {
+            addr: 0,
+            len: isize::MAX as usize,
+            handle: FABRIC.new_handle(HandleNamespace::Mr),
+        }
+    }
+
+    pub(crate) fn register_with_addr(&self, _addr: usize, _len: usize) -> Result<MemoryRegion> {
+        Err(ApiError::Ibv(io::ErrorKind::Unsupported.into()))
+    }
+
+    // Control path
+
+    pub(crate) async fn create_id_with_event_channel(
+        &self,
+        _port_space: PortSpace,
+    ) -> Result<(returned::CmId, returned::EventChannel)> {
+        let endpoint = FABRIC.add_endpoint(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+        let cmid = returned::CmId {
+            handle: net::CmId(endpoint.handle),
+            qp: None,
+        };
+        let event_channel = returned::EventChannel {
+            handle: net::EventChannel(FABRIC.new_handle(HandleNamespace::Untyped)),
+        };
+        Ok((cmid, event_channel))
+    }
+
+    pub(crate) fn set_tos(&self, _cmid_handle: Handle, _tos: u8) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn set_afonly(&self, _cmid_handle: Handle, _afonly: bool) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn set_rnr_timeout(&self, _cmid_handle: Handle, _min_rnr_timer: u8) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn bind_addr(&self, cmid_handle: Handle, sockaddr: &SocketAddr) -> Result<()> {
+        let endpoint = FABRIC.endpoint(cmid_handle)?;
+        *endpoint.local_addr.lock() = *sockaddr;
+        Ok(())
+    }
+
+    pub(crate) fn listen(&self, cmid_handle: Handle, _backlog: i32) -> Result<()> {
+        let endpoint = FABRIC.endpoint(cmid_handle)?;
+        let addr = *endpoint.local_addr.lock();
+        match FABRIC.listeners.entry(addr) {
+            dashmap::mapref::entry::Entry::Occupied(_) => {
+                Err(ApiError::RdmaCm(io::ErrorKind::AddrInUse.into()))
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(cmid_handle);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) async fn resolve_addr(
+        &self,
+        cmid_handle: Handle,
+        sockaddr: &SocketAddr,
+    ) -> Result<()> {
+        let endpoint = FABRIC.endpoint(cmid_handle)?;
+        *endpoint.peer_addr.lock() = Some(*sockaddr);
+        // the address and port the CmId is bound to are kept
+        let ephemeral = FABRIC.ephemeral_addr();
+        let mut local_addr = endpoint.local_addr.lock();
+        if local_addr.ip().is_unspecified() {
+            local_addr.set_ip(ephemeral.ip());
+        }
+        if local_addr.port() == 0 {
+            local_addr.set_port(ephemeral.port());
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn resolve_route(&self, _cmid_handle: Handle, _timeout_ms: i32) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn try_get_request(
+        &self,
+        listener_handle: Handle,
+    ) -> Result<Option<returned::CmId>> {
+        let listener = FABRIC.endpoint(listener_handle)?;
+        let request = listener.requests.lock().pop_front();
+        Ok(request.map(|handle| returned::CmId {
+            handle: net::CmId(handle),
+            qp: None,
+        }))
+    }
+
+    pub(crate) async fn get_request(&self, listener_handle: Handle) -> Result<returned::CmId> {
+        loop {
+            if let Some(cmid) = self.try_get_request(listener_handle)? {
+                return Ok(cmid);
+            }
+            future::yield_now().await;
+        }
+    }
+
+    pub(crate) async fn accept(
+        &self,
+        cmid_handle: Handle,
+        _conn_param: Option<&net::ConnParam>,
+    ) -> Result<()> {
+        let endpoint = FABRIC.endpoint(cmid_handle)?;
+        let peer = endpoint
+            .peer()
+            .ok_or_else(|| ApiError::RdmaCm(io::ErrorKind::ConnectionAborted.into()))?;
+        endpoint.connected.store(true, Ordering::Release);
+        peer.connected.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub(crate) async fn connect(
+        &self,
+        cmid_handle: Handle,
+        _conn_param: Option<&net::ConnParam>,
+    ) -> Result<()> {
+        let endpoint = FABRIC.endpoint(cmid_handle)?;
+        let peer_addr = endpoint
+            .peer_addr
+            .lock()
+            .ok_or_else(|| ApiError::RdmaCm(io::ErrorKind::NotConnected.into()))?;
+        let refused = || ApiError::RdmaCm(io::ErrorKind::ConnectionRefused.into());
+        let listener = FABRIC
+            .listeners
+            .get(&peer_addr)
+            .map(|l| *l)
+            .ok_or_else(refused)?;
+        let listener = FABRIC.endpoint(listener).map_err(|_| refused())?;
+
+        let server = FABRIC.add_endpoint(peer_addr);
+        *server.peer_addr.lock() = Some(*endpoint.local_addr.lock());
+        *server.peer.lock() = Arc::downgrade(&endpoint);
+        *endpoint.peer.lock() = Arc::downgrade(&server);
+        listener.requests.lock().push_back(server.handle);
+        let server = Arc::downgrade(&server);
+
+        while !endpoint.connected.load(Ordering::Acquire) {
+            if server.strong_count() == 0 {
+                return Err(refused());
+            }
+            future::yield_now().await;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn cm_create_qp(
+        &self,
+        cmid_handle: Handle,
+        pd: Option<&net::ProtectionDomain>,
+        qp_init_attr: &net::QpInitAttr,
+    ) -> Result<returned::QueuePair> {
+        let endpoint = FABRIC.endpoint(cmid_handle)?;
+        let find_cq =
+            |cq: Option<net::CompletionQueue>| -> Result<(Arc<Cq>, net::CompletionQueue)> {
+                let cq = cq.ok_or(ApiError::NotFound)?;
+                let sim_cq = FABRIC.cqs.get(&cq.0).map(|c| Arc::clone(&c));
+                Ok((sim_cq.ok_or(ApiError::NotFound)?, cq))
+            };
+        let (send_cq, send_cq_handle) = find_cq(qp_init_attr.send_cq)?;
+        let (recv_cq, recv_cq_handle) = find_cq(qp_init_attr.recv_cq)?;
+        recv_cq.endpoints.lock().push(Arc::downgrade(&endpoint));
+        *endpoint.cqs.lock() = Some((send_cq, recv_cq));
+        endpoint
+            .max_inline_data
+            .store(qp_init_attr.cap.max_inline_data, Ordering::Relaxed);
+        Ok(returned::QueuePair {
+            handle: net::QueuePair(FABRIC.new_handle(HandleNamespace::Untyped)),
+            pd: returned::ProtectionDomain {
+                handle: pd.copied().unwrap_or(SIM_PD),
+            },
+            send_cq: returned::CompletionQueue {
+                handle: send_cq_handle,
+            },
+            recv_cq: returned::CompletionQueue {
+                handle: recv_cq_handle,
+            },
+        })
+    }
+
+    pub(crate) fn get_max_inline_data(&self, cmid_handle: Handle) -> Result<u32> {
+        let endpoint = FABRIC.endpoint(cmid_handle)?;
+        Ok(endpoint.max_inline_data.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn get_local_addr(&self, cmid: &net::CmId) -> Result<SocketAddr> {
+        let endpoint = FABRIC.endpoint(cmid.0)?;
+        let addr = *endpoint.local_addr.lock();
+        Ok(addr)
+    }
+
+    pub(crate) fn get_peer_addr(&self, cmid: &net::CmId) -> Result<SocketAddr> {
+        let endpoint = FABRIC.endpoint(cmid.0)?;
+        let addr = *endpoint.peer_addr.lock();
+        addr.ok_or_else(|| ApiError::RdmaCm(io::ErrorKind::NotConnected.into()))
+    }
+
+    pub(crate) fn disconnect(&self, cmid: &net::CmId) -> Result<()> {
+        let endpoint = FABRIC.endpoint(cmid.0)?;
+        endpoint.connected.store(false, Ordering::Release);
+        if let Some(peer) = endpoint.peer() {
+            peer.connected.store(false, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn destroy_id(&self, cmid: &net::CmId) -> Result<()> {
+        let (_, endpoint) = FABRIC.endpoints.remove(&cmid.0).ok_or(ApiError::NotFound)?;
+        let addr = *endpoint.local_addr.lock();
+        FABRIC
+            .listeners
+            .remove_if(&addr, |_, &listener| listener == endpoint.handle);
+        // Refuse the pending connection requests.
+        for request in endpoint.requests.lock().drain(..) {
+            FABRIC.endpoints.remove(&request);
+        }
+        endpoint.connected.store(false, Ordering::Release);
+        if let Some(peer) = endpoint.peer() {
+            peer.connected.store(false, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn create_cq(
+        &self,
+        _ctx: &net::VerbsContext,
+        _min_cq_entries: i32,
+        _cq_context: u64,
+    ) -> Result<returned::CompletionQueue> {
+        let handle = FABRIC.new_handle(HandleNamespace::Untyped);
+        FABRIC.cqs.insert(handle, Arc::new(Cq::default()));
+        Ok(returned::CompletionQueue {
+            handle: net::CompletionQueue(handle),
+        })
+    }
+
+    pub(crate) fn open_cq(&self, cq: &net::CompletionQueue) -> Result<u32> {
+        let sim_cq = FABRIC.cqs.get(&cq.0).ok_or(ApiError::NotFound)?;
+        sim_cq.refcnt.fetch_add(1, Ordering::Relaxed);
+        Ok(u32::MAX)
+    }
+
+    pub(crate) fn destroy_cq(&self, cq: &net::CompletionQueue) -> Result<()> {
+        FABRIC
+            .cqs
+            .remove_if(&cq.0, |_, c| c.refcnt.fetch_sub(1, Ordering::Relaxed) == 1);
+        Ok(())
+    }
+
+    pub(crate) fn get_verbs_for_cq(
+        &self,
+        _cq: &net::CompletionQueue,
+    ) -> Result<returned::VerbsContext> {
+        Ok(self.default_verbs_context())
+    }
+
+    pub(crate) fn open_pd(&self, _pd: &net::ProtectionDomain) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn dealloc_pd(&self, _pd: &net::ProtectionDomain) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn open_qp(&self, _qp: &net::QueuePair) -> Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn destroy_qp(&self, _qp: &net::QueuePair) -> Result<()> {
+        Ok(())
+    }
+
+    // Datapath
+
+    /// # Safety
+    ///
+    /// `buf` can only be reused after the receive completes.
+    pub(crate) unsafe fn post_recv(
+        &self,
+        cmid_handle: Handle,
+        buf: &mut [u8],
+        wr_id: u64,
+    ) -> std::result::Result<(), DatapathError> {
+        let endpoint = FABRIC
+            .endpoint(cmid_handle)
+            .map_err(|_| DatapathError::NotFound)?;
+        endpoint.recvs.lock().push_back(PostedRecv {
+            wr_id,
+            addr: buf.as_mut_ptr().expose_addr(),
+            len: buf.len(),
+        });
+        Ok(())
+    }
+
+    /// Sends `buf`. The RPC message ends with the send that carries `imm`.
+    pub(crate) fn post_send(
+        &self,
+        cmid_handle: Handle,
+        buf: &[u8],
+        wr_id: u64,
+        send_flags: SendFlags,
+        imm: Option<u32>,
+    ) -> std::result::Result<(), DatapathError> {
+        let endpoint = FABRIC
+            .endpoint(cmid_handle)
+            .map_err(|_| DatapathError::NotFound)?;
+        if !endpoint.connected.load(Ordering::Acquire) {
+            return Err(DatapathError::RdmaCm(io::ErrorKind::NotConnected.into()));
+        }
+        let send_cq = endpoint.send_cq().ok_or(DatapathError::NotFound)?;
+
+        if !endpoint.in_message.swap(imm.is_none(), Ordering::Relaxed) {
+            let lost = self.loss > 0.0 && fastrand::f64() < self.loss;
+            endpoint.losing.store(lost, Ordering::Relaxed);
+        }
+
+        let status = match endpoint.peer() {
+            Some(_) if endpoint.losing.load(Ordering::Relaxed) => {
+                log::trace!("sim: dropped wr_id {} on {:?}", wr_id, cmid_handle);
+                WcStatus::Success
+            }
+            Some(peer) => {
+                peer.inbox.lock().push_back(Message {
+                    deliver_at: Instant::now() + self.latency,
+                    data: buf.to_vec(),
+                    imm,
+                });
+                WcStatus::Success
+            }
+            None => WcStatus::Error(NonZeroU32::new(WC_RETRY_EXC_ERR).unwrap()),
+        };
+
+        if send_flags.contains(SendFlags::SIGNALED) || status != WcStatus::Success {
+            send_cq.push(completion(
+                wr_id,
+                status,
+                WcOpcode::Send,
+                buf.len() as u32,
+                imm,
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn post_one_sided(&self) -> std::result::Result<(), DatapathError> {
+        Err(DatapathError::Ibv(io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Fills `wc` with up to its capacity of completions.
+    pub(crate) fn poll_cq(
+        &self,
+        cq_handle: &net::CompletionQueue,
+        wc: &mut Vec<WorkCompletion>,
+    ) -> std::result::Result<(), DatapathError> {
+        let cq = FABRIC
+            .cqs
+            .get(&cq_handle.0)
+            .map(|c| Arc::clone(&c))
+            .ok_or(DatapathError::NotFound)?;
+        wc.clear();
+        if wc.capacity() == 0 {
+            log::warn!("wc capacity is zero");
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        cq.endpoints
+            .lock()
+            .retain(|endpoint| match endpoint.upgrade() {
+                Some(endpoint) => {
+                    endpoint.deliver(&cq, now);
+                    true
+                }
+                None => false,
+            });
+
+        let mut completions = cq.completions.lock();
+        let n = completions.len().min(wc.capacity());
+        wc.extend(completions.drain(..n));
+        Ok(())
+    }
+}
+
+/// Returns the bytes of `buf`.
+///
+/// # Safety
+///
+/// `T` must have no padding.
+#[inline]
+pub(crate) unsafe fn as_bytes<T>(buf: &[T]) -> &[u8] {
+    slice::from_raw_parts(buf.as_ptr().cast(), std::mem::size_of_val(buf))
+}
+
+/// Returns the bytes of `buf`.
+///
+/// # Safety
+///
+/// `T` must have no padding.
+#[inline]
+pub(crate) unsafe fn as_bytes_mut<T>(buf: &mut [T]) -> &mut [u8] {
+    slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), std::mem::size_of_val(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two connected endpoints, each with a CQ of its own
+    fn connect_pair(
+        sim: &SimTransport,
+    ) -> (Handle, Handle, net::CompletionQueue, net::CompletionQueue) {
+        let ctx = sim.default_verbs_context().handle;
+        let client = FABRIC.add_endpoint(FABRIC.ephemeral_addr());
+        let server = FABRIC.add_endpoint(FABRIC.ephemeral_addr());
+        *client.peer.lock() = Arc::downgrade(&server);
+        *server.peer.lock() = Arc::downgrade(&client);
+        let mut cqs = Vec::new();
+        for endpoint in [&client, &server] {
+            let cq = sim.create_cq(&ctx, 64, 0).unwrap().handle;
+            let sim_cq = Arc::clone(&FABRIC.cqs.get(&cq.0).unwrap());
+            sim_cq.endpoints.lock().push(Arc::downgrade(endpoint));
+            *endpoint.cqs.lock() = Some((Arc::clone(&sim_cq), sim_cq));
+            endpoint.connected.store(true, Ordering::Release);
+            cqs.push(cq);
+        }
+        (client.handle, server.handle, cqs[0], cqs[1])
+    }
+
+    fn poll(sim: &SimTransport, cq: &net::CompletionQueue) -> Vec<WorkCompletion> {
+        let mut wcs = Vec::with_capacity(16);
+        sim.poll_cq(cq, &mut wcs).unwrap();
+        wcs
+    }
+
+    #[test]
+    fn round_trip() {
+        let sim = SimTransport::new(SimConfig::default());
+        let (client, server, client_cq, server_cq) = connect_pair(&sim);
+        let mut buf = [0u8; 64];
+        unsafe { sim.post_recv(server, &mut buf, 7).unwrap() };
+
+        sim.post_send(client, b"hello", 1, SendFlags::SIGNALED, Some(42))
+            .unwrap();
+        let sent = poll(&sim, &client_cq);
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].wr_id, sent[0].status), (1, WcStatus::Success));
+        assert_eq!(sent[0].opcode, WcOpcode::Send);
+
+        let received = poll(&sim, &server_cq);
+        assert_eq!(received.len(), 1);
+        let wc = &received[0];
+        assert_eq!(
+            (wc.wr_id, wc.status, wc.opcode),
+            (7, WcStatus::Success, WcOpcode::Recv)
+        );
+        assert_eq!(wc.byte_len, 5);
+        assert!(wc.wc_flags.contains(WcFlags::WITH_IMM));
+        assert_eq!(wc.imm_data, 42);
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    #[test]
+    fn unsignaled_send_waits_for_posted_recv() {
+        let sim = SimTransport::new(SimConfig::default());
+        let (client, server, client_cq, server_cq) = connect_pair(&sim);
+
+        sim.post_send(client, b"ping", 1, SendFlags::empty(), None)
+            .unwrap();
+        assert!(poll(&sim, &client_cq).is_empty());
+        // nothing is delivered without a posted receive
+        assert!(poll(&sim, &server_cq).is_empty());
+
+        let mut buf = [0u8; 64];
+        unsafe { sim.post_recv(server, &mut buf, 7).unwrap() };
+        let received = poll(&sim, &server_cq);
+        assert_eq!(received.len(), 1);
+        assert!(!received[0].wc_flags.contains(WcFlags::WITH_IMM));
+        assert_eq!(&buf[..4], b"ping");
+    }
+
+    #[test]
+    fn oversized_send_fails_recv() {
+        let sim = SimTransport::new(SimConfig::default());
+        let (client, server, _, server_cq) = connect_pair(&sim);
+        let mut buf = [0u8; 4];
+        unsafe { sim.post_recv(server, &mut buf, 7).unwrap() };
+
+        sim.post_send(client, b"too long", 1, SendFlags::empty(), Some(0))
+            .unwrap();
+        let received = poll(&sim, &server_cq);
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].status,
+            WcStatus::Error(NonZeroU32::new(WC_LOC_LEN_ERR).unwrap())
+        );
+    }
+
+    #[test]
+    fn latency_delays_delivery() {
+        let sim = SimTransport::new(SimConfig {
+            latency_us: 20_000,
+            loss: 0.0,
+        });
+        let (client, server, client_cq, server_cq) = connect_pair(&sim);
+        let mut buf = [0u8; 64];
+        unsafe { sim.post_recv(server, &mut buf, 7).unwrap() };
+
+        let start = Instant::now();
+        sim.post_send(client, b"hello", 1, SendFlags::SIGNALED, Some(0))
+            .unwrap();
+        // the send completes at once
+        assert_eq!(poll(&sim, &client_cq).len(), 1);
+        let received = loop {
+            let received = poll(&sim, &server_cq);
+            if !received.is_empty() {
+                break received;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(received[0].byte_len, 5);
+    }
+
+    #[test]
+    fn lost_messages_complete_but_never_arrive() {
+        let sim = SimTransport::new(SimConfig {
+            latency_us: 0,
+            loss: 1.0,
+        });
+        let (client, server, client_cq, server_cq) = connect_pair(&sim);
+        let mut buf = [0u8; 64];
+        unsafe { sim.post_recv(server, &mut buf, 7).unwrap() };
+
+        // a message of two sends is lost as a whole
+        sim.post_send(client, b"head", 1, SendFlags::SIGNALED, None)
+            .unwrap();
+        sim.post_send(client, b"tail", 2, SendFlags::SIGNALED, Some(0))
+            .unwrap();
+        let sent = poll(&sim, &client_cq);
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|wc| wc.status == WcStatus::Success));
+        assert!(poll(&sim, &server_cq).is_empty());
+    }
+
+    #[test]
+    fn send_to_gone_peer_fails() {
+        let sim = SimTransport::new(SimConfig::default());
+        let (client, server, client_cq, _) = connect_pair(&sim);
+        FABRIC.endpoints.remove(&server);
+
+        sim.post_send(client, b"hello", 1, SendFlags::empty(), Some(0))
+            .unwrap();
+        let sent = poll(&sim, &client_cq);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].status,
+            WcStatus::Error(NonZeroU32::new(WC_RETRY_EXC_ERR).unwrap())
+        );
+    }
+}
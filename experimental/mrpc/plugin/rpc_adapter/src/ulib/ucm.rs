@@ -1,13 +1,15 @@
 use std::any::Any;
+use std::io;
 use std::mem;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 
 use phoenix_api::net;
 use phoenix_api::{AsHandle, Handle};
 use phoenix_common::log;
-use rdma::rdmacm;
+use rdma::{ibv, rdmacm};
+use transport_rdma::ApiError;
 
-use super::get_ops;
+use super::{get_rdma_ops, get_transport, Transport};
 use super::{uverbs, Error, FromBorrow};
 use uverbs::AccessFlags;
 use uverbs::{ConnParam, ProtectionDomain, QpInitAttr};
@@ -15,6 +17,16 @@ use uverbs::{ConnParam, ProtectionDomain, QpInitAttr};
 // Re-exports
 pub use phoenix_api::addrinfo::{AddrFamily, AddrInfo, AddrInfoFlags, AddrInfoHints, PortSpace};
 
+/// The length of the queue of pending connections of a listener, if not set.
+const DEFAULT_BACKLOG: i32 = 512;
+
+/// Turns the IPv4-mapped IPv6 addresses of the IPv4 connections taken by a dual-stack listener
+/// back into IPv4 addresses.
+#[inline]
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
 #[derive(Clone)]
 pub(crate) struct CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
     handle: net::CmId,
@@ -22,6 +34,11 @@ pub(crate) struct CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
     pd: Option<&'pd ProtectionDomain>,
     qp_init_attr: QpInitAttr<'ctx, 'scq, 'rcq, 'srq>,
     tos: Option<u8>,
+    // the local side of a connection, see `resolve_route`
+    local_addr: Option<IpAddr>,
+    nic_index: Option<usize>,
+    port_range: Option<(u16, u16)>,
+    backlog: i32,
 }
 
 impl<'pd, 'ctx, 'scq, 'rcq, 'srq> Default for CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
@@ -38,6 +55,10 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
             pd: None,
             qp_init_attr: Default::default(),
             tos: None,
+            local_addr: None,
+            nic_index: None,
+            port_range: None,
+            backlog: DEFAULT_BACKLOG,
         }
     }
 
@@ -72,6 +93,12 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
         self
     }
 
+    /// Takes the receive requests of the QP from `srq` rather than its own receive queue.
+    pub(crate) fn set_srq(&mut self, srq: &'srq uverbs::SharedReceiveQueue) -> &mut Self {
+        self.qp_init_attr.srq = Some(srq);
+        self
+    }
+
     pub(crate) fn set_cap(&mut self, cap: uverbs::QpCapability) -> &mut Self {
         self.qp_init_attr.cap = cap;
         self
@@ -102,6 +129,11 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
         self
     }
 
+    pub(crate) fn set_qp_type(&mut self, qp_type: uverbs::QpType) -> &mut Self {
+        self.qp_init_attr.qp_type = qp_type;
+        self
+    }
+
     pub(crate) fn signal_all(&mut self) -> &mut Self {
         self.qp_init_attr.sq_sig_all = true;
         self
@@ -112,25 +144,58 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
         self
     }
 
+    /// Connects from `local_addr`, which takes precedence over the device set by
+    /// `set_nic_index`.
+    pub(crate) fn set_local_addr(&mut self, local_addr: IpAddr) -> &mut Self {
+        self.local_addr = Some(local_addr);
+        self
+    }
+
+    /// Connects from the address of the device `nic_index`.
+    pub(crate) fn set_nic_index(&mut self, nic_index: usize) -> &mut Self {
+        self.nic_index = Some(nic_index);
+        self
+    }
+
+    /// Connects from the first free port of `first..=last`.
+    pub(crate) fn set_port_range(&mut self, first: u16, last: u16) -> &mut Self {
+        self.port_range = Some((first, last));
+        self
+    }
+
+    pub(crate) fn set_backlog(&mut self, backlog: i32) -> &mut Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Rejects the connection request this builder is created from.
+    pub(crate) fn reject(self) {
+        let _drop_cmid = DropCmId(self.handle);
+    }
+
     pub(crate) async fn bind<A: ToSocketAddrs>(&self, addr: A) -> Result<CmIdListener, Error> {
         let listen_addr = addr
             .to_socket_addrs()?
             .next()
             .ok_or(Error::NoAddrResolved)?;
-        let ops = get_ops();
         // create_id
-        let (cmid, event_channel) = ops.create_id_with_event_channel(PortSpace::TCP).await?;
+        let (cmid, event_channel) = transport!(create_id_with_event_channel(PortSpace::TCP).await)?;
         assert!(cmid.qp.is_none());
         // drop guard, automatically drop if any of the following step fails
         let drop_cmid = DropCmId(cmid.handle);
         // Set TOS, haven't tested
         if let Some(tos) = self.tos {
-            ops.set_tos(self.handle.0, tos)?;
+            transport!(set_tos(cmid.handle.0, tos))?;
+        }
+        // a listener on the unspecified IPv6 address takes IPv4 connections too, whatever
+        // net.ipv6.bindv6only says
+        if let SocketAddr::V6(addr) = listen_addr {
+            transport!(set_afonly(cmid.handle.0, !addr.ip().is_unspecified()))?;
         }
         // bind_addr
-        ops.bind_addr(cmid.handle.0, &listen_addr)?;
+        transport!(bind_addr(cmid.handle.0, &listen_addr))?;
         // listen
-        ops.listen(cmid.handle.0, 512)?;
+        transport!(listen(cmid.handle.0, self.backlog))?;
         mem::forget(drop_cmid);
         Ok(CmIdListener {
             handle: cmid.handle,
@@ -146,20 +211,23 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
             .to_socket_addrs()?
             .next()
             .ok_or(Error::NoAddrResolved)?;
-        let ops = get_ops();
         // create_id
-        let (cmid, event_channel) = ops.create_id_with_event_channel(PortSpace::TCP).await?;
+        let (cmid, event_channel) = transport!(create_id_with_event_channel(PortSpace::TCP).await)?;
         assert!(cmid.qp.is_none());
         // drop guard, automatically drop if any of the following step fails
         let drop_cmid = DropCmId(cmid.handle);
         // Set TOS, haven't tested
         if let Some(tos) = self.tos {
-            ops.set_tos(self.handle.0, tos)?;
+            transport!(set_tos(cmid.handle.0, tos))?;
+        }
+        // bind the local side, if it is chosen
+        if let Some(local_ip) = self.local_ip(&connect_addr)? {
+            self.bind_local(cmid.handle.0, local_ip)?;
         }
         // resolve_addr
-        ops.resolve_addr(cmid.handle.0, &connect_addr).await?;
+        transport!(resolve_addr(cmid.handle.0, &connect_addr).await)?;
         // resolve_route
-        ops.resolve_route(cmid.handle.0, 2000).await?;
+        transport!(resolve_route(cmid.handle.0, 2000).await)?;
         assert!(cmid.qp.is_none());
         let mut builder = self.clone();
         builder.handle = cmid.handle;
@@ -168,13 +236,98 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
         Ok(builder)
     }
 
+    /// Returns the local address to connect to `connect_addr` from, or `None` to leave it to
+    /// `resolve_addr`.
+    fn local_ip(&self, connect_addr: &SocketAddr) -> Result<Option<IpAddr>, Error> {
+        if let Some(local_addr) = self.local_addr {
+            return Ok(Some(local_addr));
+        }
+        if let Some(nic_index) = self.nic_index {
+            return Ok(Some(transport!(get_device_addr(nic_index))?));
+        }
+        // only the port is chosen
+        Ok(self.port_range.map(|_| match connect_addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }))
+    }
+
+    /// Binds the CmId to `ip`, on the first free port of the port range if it is set.
+    fn bind_local(&self, cmid_handle: Handle, ip: IpAddr) -> Result<(), Error> {
+        let (first, last) = match self.port_range {
+            Some(port_range) => port_range,
+            None => {
+                transport!(bind_addr(cmid_handle, &SocketAddr::new(ip, 0)))?;
+                return Ok(());
+            }
+        };
+        for port in first..=last {
+            match transport!(bind_addr(cmid_handle, &SocketAddr::new(ip, port))) {
+                Ok(()) => return Ok(()),
+                Err(ApiError::RdmaCm(e)) if e.kind() == io::ErrorKind::AddrInUse => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(Error::NoLocalPort(first, last))
+    }
+
+    /// Creates a CmId for unreliable datagrams bound to `addr`. The address must be a specific
+    /// one, so that the CmId is bound to the device that owns it.
+    pub(crate) async fn bind_datagram<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq>, Error> {
+        get_rdma_ops()?;
+        let bind_addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or(Error::NoAddrResolved)?;
+        let (cmid, event_channel) = transport!(create_id_with_event_channel(PortSpace::UDP).await)?;
+        assert!(cmid.qp.is_none());
+        let drop_cmid = DropCmId(cmid.handle);
+        transport!(bind_addr(cmid.handle.0, &bind_addr))?;
+        let mut builder = self.clone();
+        builder.handle = cmid.handle;
+        builder.ec_handle = event_channel.handle;
+        builder.qp_init_attr.qp_type = uverbs::QpType::UD;
+        mem::forget(drop_cmid);
+        Ok(builder)
+    }
+
+    /// Creates a CmId for unreliable datagrams on the device that routes to `addr`.
+    pub(crate) async fn resolve_datagram<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq>, Error> {
+        get_rdma_ops()?;
+        let connect_addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or(Error::NoAddrResolved)?;
+        let (cmid, event_channel) = transport!(create_id_with_event_channel(PortSpace::UDP).await)?;
+        assert!(cmid.qp.is_none());
+        let drop_cmid = DropCmId(cmid.handle);
+        // the peer is addressed by its GID, which is exchanged out of band, so the address only
+        // has to be resolved to bind the CmId to a device
+        transport!(resolve_addr(cmid.handle.0, &connect_addr).await)?;
+        let mut builder = self.clone();
+        builder.handle = cmid.handle;
+        builder.ec_handle = event_channel.handle;
+        builder.qp_init_attr.qp_type = uverbs::QpType::UD;
+        mem::forget(drop_cmid);
+        Ok(builder)
+    }
+
     /// Can only be called after resolve_route.
     pub(crate) fn get_default_verbs_context(&self) -> Result<uverbs::VerbsContext, Error> {
-        let ops = get_ops();
         assert!(
             self.handle.0 != Handle::INVALID,
             "Something is being used wrongly, get_verbs_context must be called after resolve_route/get_request."
         );
+        let ops = match get_transport() {
+            Transport::Rdma(ops) => ops,
+            Transport::Sim(sim) => return uverbs::VerbsContext::new(sim.default_verbs_context()),
+        };
         let cmid_handle = self.handle;
         let sgid = ops.get_sgid(cmid_handle.0)?;
         match ops.find_verbs_by_sgid(&sgid)? {
@@ -185,11 +338,14 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
 
     /// Can only be called after resolve_route.
     pub(crate) fn get_default_pd(&self) -> Result<uverbs::ProtectionDomain, Error> {
-        let ops = get_ops();
         assert!(
             self.handle.0 != Handle::INVALID,
             "Something is being used wrongly, get_default_pd must be called after resolve_route/get_request."
         );
+        let ops = match get_transport() {
+            Transport::Rdma(ops) => ops,
+            Transport::Sim(sim) => return uverbs::ProtectionDomain::open(sim.default_pd()),
+        };
         let cmid_handle = self.handle;
         let sgid = ops.get_sgid(cmid_handle.0)?;
         match ops.find_pd_by_sgid(&sgid)? {
@@ -201,11 +357,11 @@ impl<'pd, 'ctx, 'scq, 'rcq, 'srq> CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq> {
     pub(crate) fn build(&self) -> Result<PreparedCmId, Error> {
         // create_qp
         let pd = self.pd.map(|pd| pd.inner);
-        let qp = get_ops().cm_create_qp(
+        let qp = transport!(cm_create_qp(
             self.handle.0,
             pd.as_ref(),
             &net::QpInitAttr::from_borrow(&self.qp_init_attr),
-        )?;
+        ))?;
 
         Ok(PreparedCmId {
             inner: Inner {
@@ -238,7 +394,12 @@ impl EventChannel {
         &self,
         event_type: rdma::ffi::rdma_cm_event_type::Type,
     ) -> Option<Result<rdmacm::CmEvent, Error>> {
-        match get_ops().try_get_cm_event(&self.as_handle(), event_type) {
+        let ops = match get_transport() {
+            Transport::Rdma(ops) => ops,
+            // The simulated transport does not generate CM events.
+            Transport::Sim(_) => return None,
+        };
+        match ops.try_get_cm_event(&self.as_handle(), event_type) {
             Some(Ok(res)) => Some(Ok(res)),
             Some(Err(e)) => Some(Err(e.into())),
             None => None,
@@ -250,9 +411,7 @@ struct DropCmId(net::CmId);
 
 impl Drop for DropCmId {
     fn drop(&mut self) {
-        get_ops()
-            .destroy_id(&self.0)
-            .unwrap_or_else(|e| eprintln!("Destroying CmId: {}", e));
+        transport!(destroy_id(&self.0)).unwrap_or_else(|e| eprintln!("Destroying CmId: {}", e));
     }
 }
 
@@ -284,7 +443,7 @@ impl CmIdListener {
     pub(crate) async fn get_request<'pd, 'ctx, 'scq, 'rcq, 'srq>(
         &self,
     ) -> Result<CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq>, Error> {
-        let cmid = get_ops().get_request(self.handle.0).await?;
+        let cmid = transport!(get_request(self.handle.0).await)?;
         assert!(cmid.qp.is_none());
         let mut builder = CmIdBuilder::new();
         builder.handle = cmid.handle;
@@ -294,7 +453,7 @@ impl CmIdListener {
     pub(crate) fn try_get_request<'pd, 'ctx, 'scq, 'rcq, 'srq>(
         &self,
     ) -> Result<Option<CmIdBuilder<'pd, 'ctx, 'scq, 'rcq, 'srq>>, Error> {
-        let maybe_cmid = get_ops().try_get_request(self.handle.0)?;
+        let maybe_cmid = transport!(try_get_request(self.handle.0))?;
         if let Some(cmid) = maybe_cmid.as_ref() {
             assert!(cmid.qp.is_none());
             let mut builder = CmIdBuilder::new();
@@ -319,15 +478,39 @@ impl AsHandle for PreparedCmId {
 }
 
 impl PreparedCmId {
+    pub(crate) fn get_peer_addr(&self) -> Result<SocketAddr, Error> {
+        let addr = transport!(get_peer_addr(&self.inner.handle))?;
+        Ok(canonical(addr))
+    }
+
+    /// Returns the number of the QP, which addresses the QP in datagrams.
+    pub(crate) fn qp_num(&self) -> Result<u32, Error> {
+        Ok(get_rdma_ops()?.get_qp_num(self.inner.handle.0)?)
+    }
+
+    /// Returns the most bytes a send on the QP can carry inline.
+    pub(crate) fn max_inline_data(&self) -> Result<u32, Error> {
+        Ok(transport!(get_max_inline_data(self.inner.handle.0))?)
+    }
+
+    /// Returns the GID of the port the CmId is bound to.
+    pub(crate) fn sgid(&self) -> Result<ibv::Gid, Error> {
+        Ok(get_rdma_ops()?.get_sgid(self.inner.handle.0)?)
+    }
+
+    /// Creates an address handle to send datagrams from the QP to the port with `dgid`.
+    pub(crate) fn create_ah(&self, dgid: &ibv::Gid) -> Result<uverbs::AddressHandle, Error> {
+        let inner = get_rdma_ops()?.create_ah(self.inner.handle.0, dgid)?;
+        Ok(uverbs::AddressHandle { inner })
+    }
+
     pub(crate) async fn accept<'a>(
         self,
         conn_param: Option<&'a ConnParam<'a>>,
     ) -> Result<CmId, Error> {
         let conn_param = conn_param.map(|param| net::ConnParam::from_borrow(&param));
-        get_ops()
-            .accept(self.inner.handle.0, conn_param.as_ref())
-            .await?;
-        get_ops().set_rnr_timeout(self.inner.handle.0, 1)?;
+        transport!(accept(self.inner.handle.0, conn_param.as_ref()).await)?;
+        transport!(set_rnr_timeout(self.inner.handle.0, 1))?;
         Ok(CmId { inner: self.inner })
     }
 
@@ -336,11 +519,9 @@ impl PreparedCmId {
         conn_param: Option<&'a ConnParam<'a>>,
     ) -> Result<CmId, Error> {
         let conn_param = conn_param.map(|param| net::ConnParam::from_borrow(&param));
-        get_ops()
-            .connect(self.inner.handle.0, conn_param.as_ref())
-            .await
+        transport!(connect(self.inner.handle.0, conn_param.as_ref()).await)
             .map_err(Error::Connect)?;
-        get_ops().set_rnr_timeout(self.inner.handle.0, 1)?;
+        transport!(set_rnr_timeout(self.inner.handle.0, 1))?;
         Ok(CmId { inner: self.inner })
     }
 }
@@ -359,18 +540,23 @@ impl AsHandle for CmId {
 
 impl CmId {
     pub(crate) fn get_local_addr(&self) -> Result<SocketAddr, Error> {
-        let addr = get_ops().get_local_addr(&self.inner.handle)?;
-        Ok(addr)
+        let addr = transport!(get_local_addr(&self.inner.handle))?;
+        Ok(canonical(addr))
     }
 
     pub(crate) fn get_peer_addr(&self) -> Result<SocketAddr, Error> {
-        let addr = get_ops().get_peer_addr(&self.inner.handle)?;
-        Ok(addr)
+        let addr = transport!(get_peer_addr(&self.inner.handle))?;
+        Ok(canonical(addr))
+    }
+
+    /// Returns the number of the QP, which the work completions of the QP carry.
+    pub(crate) fn qp_num(&self) -> Result<u32, Error> {
+        Ok(get_rdma_ops()?.get_qp_num(self.inner.handle.0)?)
     }
 }
 impl CmId {
     pub(crate) fn disconnect(&self) -> Result<(), Error> {
-        get_ops().disconnect(&self.inner.handle)?;
+        transport!(disconnect(&self.inner.handle))?;
         Ok(())
     }
 }
@@ -479,7 +665,10 @@ impl Inner {
     }
 
     pub(crate) fn get_pd(&self) -> Result<uverbs::ProtectionDomain, Error> {
-        let ops = get_ops();
+        let ops = match get_transport() {
+            Transport::Rdma(ops) => ops,
+            Transport::Sim(sim) => return uverbs::ProtectionDomain::open(sim.default_pd()),
+        };
         let cmid_handle = self.handle;
         let sgid = ops.get_sgid(cmid_handle.0)?;
         match ops.find_pd_by_sgid(&sgid)? {
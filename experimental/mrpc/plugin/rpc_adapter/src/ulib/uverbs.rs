@@ -12,14 +12,15 @@ use phoenix_common::log;
 use rdma::mr::OdpMemoryRegion;
 use rdma::rdmacm;
 
-use super::{get_ops, Error, FromBorrow};
+use super::sim;
+use super::{get_transport, Error, FromBorrow, Transport};
 
 // Re-exports
 pub use phoenix_api::net::{AccessFlags, SendFlags, WcFlags, WcOpcode, WcStatus, WorkCompletion};
 pub use phoenix_api::net::{QpCapability, QpType, RemoteKey};
 
 pub(crate) fn get_default_verbs_contexts() -> Result<Vec<VerbsContext>, Error> {
-    let ctx_list = get_ops().get_default_contexts()?;
+    let ctx_list = transport!(get_default_contexts())?;
     ctx_list
         .into_iter()
         .map(VerbsContext::new)
@@ -29,7 +30,7 @@ pub(crate) fn get_default_verbs_contexts() -> Result<Vec<VerbsContext>, Error> {
 pub(crate) fn get_default_pds() -> Result<Vec<ProtectionDomain>, Error> {
     // This should only be called when it is first initialized. At that time, hopefully KL_CTX has
     // already been initialized.
-    let pds = get_ops().get_default_pds()?;
+    let pds = transport!(get_default_pds())?;
     pds.into_iter()
         .map(ProtectionDomain::open)
         .collect::<Result<Vec<_>, Error>>()
@@ -60,7 +61,7 @@ impl VerbsContext {
         min_cq_entries: i32,
         cq_context: u64,
     ) -> Result<CompletionQueue, Error> {
-        let cq = get_ops().create_cq(&self.inner, min_cq_entries, cq_context)?;
+        let cq = transport!(create_cq(&self.inner, min_cq_entries, cq_context))?;
         CompletionQueue::open(cq)
     }
 }
@@ -72,8 +73,7 @@ pub struct ProtectionDomain {
 
 impl Drop for ProtectionDomain {
     fn drop(&mut self) {
-        get_ops()
-            .dealloc_pd(&self.inner)
+        transport!(dealloc_pd(&self.inner))
             .unwrap_or_else(|e| eprintln!("Dropping ProtectionDomain: {}", e));
     }
 }
@@ -81,7 +81,7 @@ impl Drop for ProtectionDomain {
 impl ProtectionDomain {
     pub(crate) fn open(pd: returned::ProtectionDomain) -> Result<Self, Error> {
         let inner = pd.handle;
-        get_ops().open_pd(&inner)?;
+        transport!(open_pd(&inner))?;
         Ok(ProtectionDomain { inner })
     }
 
@@ -98,6 +98,96 @@ impl ProtectionDomain {
         // assert_eq!(nbytes, mr.len());
         // Ok(MemoryRegion::new(mr)?)
     }
+
+    /// Registers the whole address space with on-demand paging.
+    pub(crate) fn register_on_demand_paging(&self) -> Result<MemoryRegion<u8>, Error> {
+        match get_transport() {
+            Transport::Rdma(ops) => MemoryRegion::new(ops.create_mr_on_demand_paging(&self.inner)?),
+            Transport::Sim(sim) => Ok(MemoryRegion::from_inner(MrInner::Sim(
+                sim.register_on_demand_paging(),
+            ))),
+        }
+    }
+
+    /// Registers a buffer that is allocated elsewhere, e.g., a GPU buffer.
+    pub(crate) fn register_with_addr(
+        &self,
+        addr: usize,
+        len: usize,
+    ) -> Result<MemoryRegion<u8>, Error> {
+        match get_transport() {
+            Transport::Rdma(ops) => {
+                MemoryRegion::new(ops.create_mr_with_addr(&self.inner, addr, len)?)
+            }
+            Transport::Sim(sim) => Ok(MemoryRegion::from_inner(MrInner::Sim(
+                sim.register_with_addr(addr, len)?,
+            ))),
+        }
+    }
+
+    /// Creates a shared receive queue of up to `max_wr` receive requests.
+    pub(crate) fn create_srq(&self, max_wr: u32) -> Result<SharedReceiveQueue, Error> {
+        match get_transport() {
+            Transport::Rdma(ops) => Ok(SharedReceiveQueue {
+                inner: ops.create_srq(&self.inner, max_wr, 1)?,
+            }),
+            Transport::Sim(_) => Err(Error::SrqUnsupported),
+        }
+    }
+
+    /// Registers a buffer that is allocated elsewhere and only sent from, e.g., a read-only
+    /// mapping of a file.
+    pub(crate) fn register_read_only_with_addr(
+        &self,
+        addr: usize,
+        len: usize,
+    ) -> Result<MemoryRegion<u8>, Error> {
+        match get_transport() {
+            Transport::Rdma(ops) => {
+                MemoryRegion::new(ops.create_read_only_mr_with_addr(&self.inner, addr, len)?)
+            }
+            Transport::Sim(sim) => Ok(MemoryRegion::from_inner(MrInner::Sim(
+                sim.register_with_addr(addr, len)?,
+            ))),
+        }
+    }
+
+    /// Registers a buffer that the peer may only read, with RDMA READs.
+    pub(crate) fn register_remote_readable(
+        &self,
+        addr: usize,
+        len: usize,
+    ) -> Result<RemoteReadable, Error> {
+        match get_transport() {
+            Transport::Rdma(ops) => Ok(RemoteReadable {
+                inner: OdpMemoryRegion::new(ops.create_remote_read_mr_with_addr(
+                    &self.inner,
+                    addr,
+                    len,
+                )?),
+            }),
+            Transport::Sim(_) => Err(Error::RemoteReadUnsupported),
+        }
+    }
+}
+
+/// A buffer registered for the peer to read with RDMA READs, and for nothing else. The access of
+/// the peer is revoked when it is dropped.
+#[derive(Debug)]
+pub(crate) struct RemoteReadable {
+    inner: OdpMemoryRegion,
+}
+
+impl RemoteReadable {
+    /// Returns the key for the peer to read the buffer with.
+    #[inline]
+    pub(crate) fn remote_key(&self) -> RemoteKey {
+        let mr = unsafe { &*self.inner.mr.0 };
+        RemoteKey {
+            addr: mr.addr as u64,
+            rkey: mr.rkey,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -113,8 +203,7 @@ impl AsHandle for CompletionQueue {
 
 impl Drop for CompletionQueue {
     fn drop(&mut self) {
-        get_ops()
-            .destroy_cq(&self.inner)
+        transport!(destroy_cq(&self.inner))
             .unwrap_or_else(|e| eprintln!("Dropping CompletionQueue: {}", e));
     }
 }
@@ -122,26 +211,114 @@ impl Drop for CompletionQueue {
 impl CompletionQueue {
     pub(crate) fn open(returned_cq: returned::CompletionQueue) -> Result<Self, Error> {
         let inner = returned_cq.handle;
-        get_ops().open_cq(&inner)?;
+        transport!(open_cq(&inner))?;
         Ok(CompletionQueue { inner })
     }
 
     pub(crate) fn get_verbs_context(&self) -> Result<VerbsContext, Error> {
-        let returned_ctx = get_ops().get_verbs_for_cq(&self.inner)?;
+        let returned_ctx = transport!(get_verbs_for_cq(&self.inner))?;
         VerbsContext::new(returned_ctx)
     }
 }
 
+/// The address of a remote port to send datagrams to.
+#[derive(Debug)]
+pub struct AddressHandle {
+    pub(crate) inner: net::AddressHandle,
+}
+
+impl AsHandle for AddressHandle {
+    fn as_handle(&self) -> Handle {
+        self.inner.0
+    }
+}
+
+impl Drop for AddressHandle {
+    fn drop(&mut self) {
+        if let Transport::Rdma(ops) = get_transport() {
+            ops.destroy_ah(&self.inner)
+                .unwrap_or_else(|e| eprintln!("Dropping AddressHandle: {}", e));
+        }
+    }
+}
+
+/// A receive queue shared by the QPs created with it.
+#[derive(Debug)]
 pub struct SharedReceiveQueue {
     pub(crate) inner: net::SharedReceiveQueue,
 }
 
+impl AsHandle for SharedReceiveQueue {
+    fn as_handle(&self) -> Handle {
+        self.inner.0
+    }
+}
+
+impl Drop for SharedReceiveQueue {
+    fn drop(&mut self) {
+        if let Transport::Rdma(ops) = get_transport() {
+            ops.destroy_srq(&self.inner)
+                .unwrap_or_else(|e| eprintln!("Dropping SharedReceiveQueue: {}", e));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MemoryRegion<T> {
-    pub(crate) inner: OdpMemoryRegion,
+    pub(crate) inner: MrInner,
     _marker: PhantomData<T>,
 }
 
+#[derive(Debug)]
+pub(crate) enum MrInner {
+    Rdma(OdpMemoryRegion),
+    Sim(sim::MemoryRegion),
+}
+
+impl MrInner {
+    /// Returns the MR registered with the NIC.
+    #[inline]
+    pub(crate) fn rdma(&self) -> &rdmacm::MemoryRegion<'static> {
+        match self {
+            MrInner::Rdma(odp_mr) => &odp_mr.mr,
+            MrInner::Sim(_) => panic!("the memory region is not registered with an RDMA NIC"),
+        }
+    }
+
+    #[inline]
+    fn as_ptr(&self) -> *const u8 {
+        match self {
+            MrInner::Rdma(odp_mr) => odp_mr.as_ptr(),
+            MrInner::Sim(sim_mr) => sim_mr.as_ptr(),
+        }
+    }
+
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        match self {
+            MrInner::Rdma(odp_mr) => odp_mr.as_mut_ptr(),
+            MrInner::Sim(sim_mr) => sim_mr.as_mut_ptr(),
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            MrInner::Rdma(odp_mr) => odp_mr.len(),
+            MrInner::Sim(sim_mr) => sim_mr.len(),
+        }
+    }
+}
+
+impl AsHandle for MrInner {
+    fn as_handle(&self) -> Handle {
+        match self {
+            MrInner::Rdma(odp_mr) => odp_mr.as_handle(),
+            MrInner::Sim(sim_mr) => sim_mr.as_handle(),
+        }
+    }
+}
+
 impl<T> Deref for MemoryRegion<T> {
     type Target = [T];
     fn deref(&self) -> &Self::Target {
@@ -173,10 +350,14 @@ impl<T> AsHandle for MemoryRegion<T> {
 
 impl<T: Sized + Copy> MemoryRegion<T> {
     pub(crate) fn new(mr: rdmacm::MemoryRegion<'static>) -> Result<Self, Error> {
-        Ok(MemoryRegion {
-            inner: OdpMemoryRegion::new(mr),
+        Ok(Self::from_inner(MrInner::Rdma(OdpMemoryRegion::new(mr))))
+    }
+
+    fn from_inner(inner: MrInner) -> Self {
+        MemoryRegion {
+            inner,
             _marker: PhantomData,
-        })
+        }
     }
 
     #[inline]
@@ -221,7 +402,7 @@ pub struct QueuePair {
 impl QueuePair {
     pub(crate) fn open(returned_qp: returned::QueuePair) -> Result<Self, Error> {
         let inner = returned_qp.handle;
-        get_ops().open_qp(&inner)?;
+        transport!(open_qp(&inner))?;
         Ok(QueuePair {
             inner,
             pd: ProtectionDomain::open(returned_qp.pd)?,
@@ -249,8 +430,7 @@ impl QueuePair {
 impl Drop for QueuePair {
     fn drop(&mut self) {
         log::debug!("dropping QueuePair");
-        get_ops()
-            .destroy_qp(&self.inner)
+        transport!(destroy_qp(&self.inner))
             .unwrap_or_else(|e| eprintln!("Dropping QueuePair: {}", e));
         log::debug!("dropped QueuePair");
     }
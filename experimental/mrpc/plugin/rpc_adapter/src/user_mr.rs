@@ -0,0 +1,117 @@
+//! MRs over the user memory regions.
+//!
+//! The payload of a message may be in memory that the application allocated and registered with
+//! salloc (see `phoenix_salloc::user`). An MR is registered over such a region the first time a
+//! message is sent from it. The application may unregister the region at any time, so a message
+//! sent in the standard way pins the MRs it is posted over until its last send completes. An MR is
+//! deregistered once its region is unregistered and no message in flight is sent over it. The MR
+//! holds a reference to the region, which keeps the backend mapping alive until then.
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use fnv::FnvHashMap;
+
+use mrpc_marshal::{SgE, SgList};
+use phoenix_salloc::state::Resource;
+use phoenix_salloc::user::UserRegion;
+
+use super::ulib;
+use super::DatapathError;
+
+struct UserMr {
+    // declared before `_region` so that the MR is deregistered before the region is unmapped
+    mr: ulib::uverbs::MemoryRegion<u8>,
+    _region: Arc<UserRegion>,
+    // the number of the messages in flight that are sent over the MR
+    inflight: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct UserMrs {
+    // indexed by the start address of the region
+    mrs: BTreeMap<usize, UserMr>,
+    // rpc_ctx -> the start addresses of the MRs pinned by the message
+    pinned: FnvHashMap<usize, Vec<usize>>,
+}
+
+impl UserMrs {
+    /// Registers MRs over the user memory regions referenced by `sglist` that have no MR yet.
+    pub(crate) fn register(
+        &mut self,
+        cmid: &ulib::ucm::CmId,
+        resource: &Resource,
+        sglist: &SgList,
+    ) -> Result<(), DatapathError> {
+        for sge in &sglist.0 {
+            if self.find(sge).is_some() {
+                continue;
+            }
+            let region = match resource.lookup_user_region(sge.ptr) {
+                Some(region) => region,
+                None => continue,
+            };
+            // Drop the idle MRs of the regions that have been unregistered by the app.
+            self.mrs
+                .retain(|&addr, mr| mr.inflight > 0 || resource.is_user_region_registered(addr));
+            let pd = cmid.get_pd()?;
+            let mr = pd.register_read_only_with_addr(region.addr(), region.len())?;
+            self.mrs.insert(
+                region.addr(),
+                UserMr {
+                    mr,
+                    _region: region,
+                    inflight: 0,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the start address of the MR that contains `sge`.
+    #[inline]
+    fn find(&self, sge: &SgE) -> Option<usize> {
+        self.mrs
+            .range(..=sge.ptr)
+            .next_back()
+            .filter(|(&start, mr)| sge.ptr + sge.len <= start + mr.mr.len())
+            .map(|(&start, _)| start)
+    }
+
+    /// Returns the MR and the range within the MR to post `sge` with, if `sge` is in a user
+    /// memory region.
+    #[inline]
+    pub(crate) fn select(
+        &self,
+        sge: &SgE,
+    ) -> Option<(&ulib::uverbs::MemoryRegion<u8>, Range<usize>)> {
+        let start = self.find(sge)?;
+        let off = sge.ptr - start;
+        Some((&self.mrs[&start].mr, off..off + sge.len))
+    }
+
+    /// Pins the MRs that the message of `rpc_ctx` is sent over.
+    pub(crate) fn pin(&mut self, rpc_ctx: usize, sglist: &SgList) {
+        let mut starts: Vec<usize> = sglist.0.iter().filter_map(|sge| self.find(sge)).collect();
+        if starts.is_empty() {
+            return;
+        }
+        starts.sort_unstable();
+        starts.dedup();
+        for start in &starts {
+            self.mrs.get_mut(start).unwrap().inflight += 1;
+        }
+        self.pinned.insert(rpc_ctx, starts);
+    }
+
+    /// Unpins the MRs of the message of `rpc_ctx` after its last send completes.
+    pub(crate) fn unpin(&mut self, rpc_ctx: usize) {
+        if let Some(starts) = self.pinned.remove(&rpc_ctx) {
+            for start in starts {
+                if let Some(mr) = self.mrs.get_mut(&start) {
+                    mr.inflight -= 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Checks of the addresses an app hands to the adapter, with the `validate-abi` feature.
+//!
+//! A message of the app points into its shared memory, and the adapter reads it as is: a bad
+//! address, e.g., from a bug of the user library, has the dispatch library read, and the adapter
+//! send, whatever is mapped there. With the feature, the address of a message is checked against
+//! the memory of the app before the dispatch library reads it, and so are the segments it
+//! marshals the message to, before the adapter reads them. A message that fails the checks is
+//! acked with `BAD_MESSAGE`. The memory of the app is what salloc allocated or registered for it,
+//! and the receive buffers, which the messages it forwards point into.
+use mrpc_marshal::SgList;
+use phoenix_api::rpc::MessageMeta;
+use phoenix_salloc::state::Resource as SallocResource;
+
+use super::pool::BufferPool;
+use super::serialization::{self, DispatchError, SerializationEngine};
+
+/// Marshals the message at `addr_backend` with `module`, or as is if it is a raw message,
+/// checking the addresses with the `validate-abi` feature.
+///
+/// # Panics
+///
+/// Panics if the message is not raw and no dispatch library is loaded.
+pub(crate) fn marshal(
+    module: Option<&SerializationEngine>,
+    meta: &MessageMeta,
+    addr_backend: usize,
+    salloc: &SallocResource,
+    recv_buffers: &BufferPool,
+) -> Result<SgList, DispatchError> {
+    let marshal = || {
+        if meta.raw() {
+            serialization::marshal_raw(addr_backend)
+        } else {
+            let module = module.expect("dispatch module not loaded");
+            module.marshal(meta, addr_backend)
+        }
+    };
+    if !cfg!(feature = "validate-abi") {
+        return marshal();
+    }
+    let check = |addr: usize, len: usize| {
+        if salloc.contains(addr, len) || recv_buffers.contains(addr, len) {
+            Ok(())
+        } else {
+            Err(DispatchError::InvalidAddress(addr, len))
+        }
+    };
+    // the size of the message is only known to the dispatch library
+    check(addr_backend, 1)?;
+    let sglist = marshal()?;
+    for sge in sglist.0.iter().filter(|sge| sge.len > 0) {
+        check(sge.ptr, sge.len)?;
+    }
+    Ok(sglist)
+}
@@ -1,6 +1,8 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::io;
 use std::mem;
+use std::num::NonZeroU32;
 use std::os::unix::prelude::{AsRawFd, RawFd};
 use std::pin::Pin;
 use std::ptr;
@@ -18,7 +20,7 @@ use phoenix_api::net::{MappedAddrStatus, WcOpcode, WcStatus};
 use phoenix_api::rpc::{MessageMeta, RpcId, StatusCode, TransportStatus};
 use phoenix_api::transport::tcp::dp::Completion;
 use phoenix_api::{AsHandle, Handle};
-use phoenix_api_mrpc::cmd::{ConnectResponse, ReadHeapRegion};
+use phoenix_api_mrpc::cmd::{BindOptions, ConnectOptions, ConnectResponse, ReadHeapRegion};
 use phoenix_api_tcp_rpc_adapter::control_plane;
 use phoenix_mrpc::unpack::UnpackFromSgE;
 use phoenix_salloc::state::State as SallocState;
@@ -39,9 +41,11 @@ use phoenix_common::resource::Error as ResourceError;
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 
 use super::get_ops;
+use super::loopback::{LoopbackMessage, LoopbackState};
 use super::pool::BufferSlab;
-use super::serialization::SerializationEngine;
+use super::serialization::{self, DispatchError, DispatchTables, SerializationEngine};
 use super::state::{ConnectionContext, State};
+use super::validate;
 use super::{ControlPathError, DatapathError};
 
 thread_local! {
@@ -70,7 +74,8 @@ pub(crate) struct TcpRpcAdapterEngine {
     // just change Handle here to usize
     pub(crate) recv_mr_usage: FnvHashMap<RpcId, Vec<Handle>>,
 
-    pub(crate) serialization_engine: Option<SerializationEngine>,
+    /// The versions of the dispatch library
+    pub(crate) dispatch_tables: DispatchTables,
 
     pub(crate) node: DataPathNode,
     pub(crate) cmd_rx: tokio::sync::mpsc::UnboundedReceiver<phoenix_api_mrpc::cmd::Command>,
@@ -80,6 +85,12 @@ pub(crate) struct TcpRpcAdapterEngine {
     pub(crate) indicator: Indicator,
     // pub(crate) start: std::time::Instant,
     pub(crate) rpc_ctx: Slab<RpcId>,
+
+    // connections to other clients of this phoenix instance
+    pub(crate) loopback: LoopbackState,
+    // the connections whose messages are dropped since the dispatch library failed on one, with
+    // the status the messages are dropped with
+    pub(crate) quarantined: FnvHashMap<Handle, TransportStatus>,
 }
 
 impl_vertex_for_engine!(TcpRpcAdapterEngine, node);
@@ -122,13 +133,21 @@ impl Decompose for TcpRpcAdapterEngine {
                 Box::new(ptr::read(&engine.recv_mr_usage)),
             );
             collections.insert(
-                "serialization_engine".to_string(),
-                Box::new(ptr::read(&engine.serialization_engine)),
+                "dispatch_tables".to_string(),
+                Box::new(ptr::read(&engine.dispatch_tables)),
             );
             collections.insert("cmd_tx".to_string(), Box::new(ptr::read(&engine.cmd_tx)));
             collections.insert("cmd_rx".to_string(), Box::new(ptr::read(&engine.cmd_rx)));
             collections.insert("salloc".to_string(), Box::new(ptr::read(&engine.salloc)));
             collections.insert("rpc_ctx".to_string(), Box::new(ptr::read(&engine.rpc_ctx)));
+            collections.insert(
+                "loopback".to_string(),
+                Box::new(ptr::read(&engine.loopback)),
+            );
+            collections.insert(
+                "quarantined".to_string(),
+                Box::new(ptr::read(&engine.quarantined)),
+            );
             // don't call the drop function
             ptr::read(&engine.node)
         };
@@ -174,11 +193,19 @@ impl TcpRpcAdapterEngine {
             .unwrap()
             .downcast::<FnvHashMap<RpcId, Vec<Handle>>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
-        let serialization_engine = *local
-            .remove("serialization_engine")
-            .unwrap()
-            .downcast::<Option<SerializationEngine>>()
-            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let dispatch_tables = match local.remove("dispatch_tables") {
+            Some(dispatch_tables) => *dispatch_tables
+                .downcast::<DispatchTables>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that only loads the latest library.
+            None => DispatchTables::from(
+                *local
+                    .remove("serialization_engine")
+                    .unwrap()
+                    .downcast::<Option<SerializationEngine>>()
+                    .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            ),
+        };
         let cmd_tx = *local
             .remove("cmd_tx")
             .unwrap()
@@ -199,13 +226,25 @@ impl TcpRpcAdapterEngine {
             .unwrap()
             .downcast::<Slab<RpcId>>()
             .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let loopback = *local
+            .remove("loopback")
+            .unwrap()
+            .downcast::<LoopbackState>()
+            .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?;
+        let quarantined = match local.remove("quarantined") {
+            Some(quarantined) => *quarantined
+                .downcast::<FnvHashMap<Handle, TransportStatus>>()
+                .map_err(|x| anyhow!("fail to downcast, type_name={:?}", x.type_name()))?,
+            // Upgraded from a version that lets the dispatch library panic.
+            None => FnvHashMap::default(),
+        };
 
         let engine = TcpRpcAdapterEngine {
             state,
             tls,
             local_buffer,
             recv_mr_usage,
-            serialization_engine,
+            dispatch_tables,
             cmd_tx,
             cmd_rx,
             node,
@@ -214,6 +253,8 @@ impl TcpRpcAdapterEngine {
             salloc,
             // start: std::time::Instant::now(),
             rpc_ctx,
+            loopback,
+            quarantined,
         };
         Ok(engine)
     }
@@ -281,6 +322,12 @@ impl Engine for TcpRpcAdapterEngine {
                         conn.peer
                     );
                 }
+                for endpoint in self.loopback.endpoints.values() {
+                    log::info!(
+                        "TcpRpcAdapter loopback connection, handle={:?}",
+                        endpoint.handle()
+                    );
+                }
             }
         }
 
@@ -346,6 +393,17 @@ enum RpcStrategy {
     Standard,
 }
 
+/// The status of a work completion of the TCP transport that fails with `code`, either an errno,
+/// or a code of the transport itself from 1024 on.
+fn socket_status(code: NonZeroU32) -> TransportStatus {
+    match code.get() {
+        errno @ 1..=1023 => TransportStatus::from_errno(errno as i32),
+        // the socket is disconnected
+        1027 => TransportStatus::from_errno(nix::errno::Errno::ECONNRESET as i32),
+        _ => TransportStatus::TRANSPORT_FAILED,
+    }
+}
+
 impl TcpRpcAdapterEngine {
     #[inline]
     fn choose_strategy(sglist: &SgList) -> RpcStrategy {
@@ -464,12 +522,194 @@ impl TcpRpcAdapterEngine {
         Ok(Progress(1))
     }
 
+    fn send_loopback(
+        &mut self,
+        meta_ref: &MessageMeta,
+        sglist: SgList,
+    ) -> Result<Status, DatapathError> {
+        let endpoint = self
+            .loopback
+            .endpoints
+            .get(&meta_ref.conn_id)
+            .ok_or(ResourceError::NotFound)?;
+        // the send completes when the receiver consumes the message
+        let wr_id = self
+            .rpc_ctx
+            .insert(RpcId::new(meta_ref.conn_id, meta_ref.call_id));
+        endpoint.send(LoopbackMessage::Data {
+            meta: *meta_ref,
+            sgl: sglist,
+            wr_id: wr_id as u64,
+        });
+        Ok(Progress(1))
+    }
+
+    /// Puts a message from the sender's heap into one of our receive buffers. If `lend` is set,
+    /// only the meta is copied, and the payload stays where it is. Returns the sgl of the
+    /// received message, with the meta as the first element, and the buffer handle, or `None` if
+    /// the message does not fit in a receive buffer.
+    fn receive_loopback_message(
+        &mut self,
+        handle: Handle,
+        meta: &MessageMeta,
+        sgl: &SgList,
+        lend: bool,
+    ) -> Result<Option<(SgList, Handle)>, DatapathError> {
+        let endpoint = self
+            .loopback
+            .endpoints
+            .get_mut(&handle)
+            .ok_or(ResourceError::NotFound)?;
+        let buffer_handle = endpoint.free_buffers.pop().ok_or(ResourceError::NotFound)?;
+        let table = self.state.recv_buffer_table.borrow();
+        let recv_buffer = table.get(&buffer_handle).ok_or(ResourceError::NotFound)?;
+
+        let meta_sge = SgE {
+            ptr: (meta as *const MessageMeta).expose_addr(),
+            len: mem::size_of::<MessageMeta>(),
+        };
+        let copies = if lend { &sgl.0[..0] } else { &sgl.0[..] };
+        // keep each segment 8-byte aligned
+        let size = std::iter::once(&meta_sge)
+            .chain(copies)
+            .fold(0, |offset, sge| ((offset + 7) & !7) + sge.len);
+        if size > recv_buffer.len() {
+            endpoint.free_buffers.push(buffer_handle);
+            return Ok(None);
+        }
+
+        let base = recv_buffer.addr();
+        let mut received = SgList(Vec::with_capacity(sgl.0.len() + 1));
+        let mut offset = 0;
+        for sge in std::iter::once(&meta_sge).chain(copies) {
+            offset = (offset + 7) & !7;
+            unsafe {
+                ptr::copy_nonoverlapping(sge.ptr as *const u8, (base + offset) as *mut u8, sge.len);
+            }
+            received.0.push(SgE {
+                ptr: base + offset,
+                len: sge.len,
+            });
+            offset += sge.len;
+        }
+        if lend {
+            received.0.extend_from_slice(&sgl.0);
+        }
+        Ok(Some((received, buffer_handle)))
+    }
+
+    fn check_loopback(&mut self) -> Result<usize, DatapathError> {
+        let mut progress = 0;
+
+        for handle in self.loopback.accept() {
+            progress += self.process_new_connection(&handle);
+        }
+
+        let handles: Vec<Handle> = self.loopback.endpoints.keys().copied().collect();
+        for handle in handles {
+            loop {
+                let endpoint = &self.loopback.endpoints[&handle];
+                // wait until the receive buffers are mapped by the application
+                if !endpoint.mapped {
+                    break;
+                }
+                let msg = match endpoint.try_recv() {
+                    Some(msg) => msg,
+                    None => {
+                        if endpoint.is_closed() {
+                            // the peer has gone, report to the upper layer
+                            self.loopback.endpoints.remove(&handle);
+                            let status =
+                                TransportStatus::from_errno(nix::errno::Errno::ECONNRESET as i32);
+                            self.rx_outputs()[0]
+                                .send(EngineRxMessage::RecvError(handle, status))?;
+                        }
+                        break;
+                    }
+                };
+                match msg {
+                    LoopbackMessage::Data { .. } if endpoint.free_buffers.is_empty() => {
+                        // all receive buffers are in use, retry after some are reclaimed
+                        endpoint.requeue(msg);
+                        break;
+                    }
+                    LoopbackMessage::Data { meta, sgl, wr_id } => {
+                        // the payload of an engine of the same app is on the shared heap that
+                        // the app maps too
+                        let salloc = self.salloc.resource();
+                        let lend = sgl
+                            .0
+                            .iter()
+                            .all(|sge| sge.len == 0 || salloc.in_shared_heap(sge.ptr, sge.len));
+                        let Some((received, buffer_handle)) =
+                            self.receive_loopback_message(handle, &meta, &sgl, lend)?
+                        else {
+                            let status =
+                                TransportStatus::from_errno(nix::errno::Errno::EMSGSIZE as i32);
+                            self.loopback.endpoints[&handle]
+                                .send(LoopbackMessage::Consumed { wr_id, status });
+                            progress += 1;
+                            continue;
+                        };
+                        let delivered = self.unmarshal_and_deliver_up(received, handle);
+                        let endpoint = self.loopback.endpoints.get_mut(&handle).unwrap();
+                        match delivered {
+                            Some(recv_id) => {
+                                self.recv_mr_usage.insert(recv_id, vec![buffer_handle]);
+                            }
+                            None => endpoint.free_buffers.push(buffer_handle),
+                        }
+                        match delivered {
+                            // the sender releases the message when the app releases it
+                            Some(recv_id) if lend => {
+                                endpoint.lent.insert(recv_id, wr_id);
+                            }
+                            // the sender can release the message now
+                            _ => endpoint.send(LoopbackMessage::Consumed {
+                                wr_id,
+                                status: TransportStatus::Success,
+                            }),
+                        }
+                        progress += 1;
+                    }
+                    LoopbackMessage::Consumed { wr_id, status } => {
+                        let rpc_id = self.rpc_ctx.remove(wr_id as usize);
+                        self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                        progress += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(progress)
+    }
+
     fn check_input_queue(&mut self) -> Result<Status, DatapathError> {
         use phoenix_common::engine::datapath::TryRecvError;
 
         match self.tx_inputs()[0].try_recv() {
             Ok(msg) => match msg {
                 EngineTxMessage::RpcMessage(msg) => self.local_buffer.push_back(msg),
+                EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids)
+                    if self.loopback.contains(&conn_id) =>
+                {
+                    for call_id in &call_ids[..1] {
+                        let recv_id = RpcId::new(conn_id, *call_id);
+                        let recv_mrs = self
+                            .recv_mr_usage
+                            .remove(&recv_id)
+                            .expect("invalid WR identifier");
+                        if let Some(endpoint) = self.loopback.endpoints.get_mut(&conn_id) {
+                            endpoint.free_buffers.extend(recv_mrs);
+                            if let Some(wr_id) = endpoint.lent.remove(&recv_id) {
+                                endpoint.send(LoopbackMessage::Consumed {
+                                    wr_id,
+                                    status: TransportStatus::Success,
+                                });
+                            }
+                        }
+                    }
+                }
                 EngineTxMessage::ReclaimRecvBuf(conn_id, call_ids) => {
                     let sock_handle = {
                         let table = self.state.conn_table.borrow_mut();
@@ -493,23 +733,35 @@ impl TcpRpcAdapterEngine {
         if let Some(msg) = self.local_buffer.pop_front() {
             // SAFETY: don't know what kind of UB can be triggered
             let meta_ref = unsafe { &*msg.meta_buf_ptr.as_meta_ptr() };
+            let rpc_id = RpcId::new(meta_ref.conn_id, meta_ref.call_id);
+            if let Some(&status) = self.quarantined.get(&meta_ref.conn_id) {
+                self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, status))?;
+                return Ok(Progress(1));
+            }
             // let table = self.state.conn_table.borrow_mut();
             // let conn_ctx = table
             //     .get(&meta_ref.conn_id)
             //     .ok_or(ResourceError::NotFound)?;
             // log::info!("dispatching message: {:?}", meta_ref);
             let sglist = match meta_ref.status_code {
-                StatusCode::AccessDenied => SgList { 0: Vec::new() },
+                StatusCode::AccessDenied
+                | StatusCode::Unimplemented
+                | StatusCode::DataLoss
+                | StatusCode::Failed => SgList { 0: Vec::new() },
                 StatusCode::Success => {
-                    if let Some(ref module) = self.serialization_engine {
-                        match module.marshal(meta_ref, msg.addr_backend) {
-                            Ok(sglist) => sglist,
-                            Err(e) => {
-                                panic!("marshal error: {:?}", e);
-                            }
+                    match validate::marshal(
+                        self.dispatch_tables.get(msg.dispatch_version),
+                        meta_ref,
+                        msg.addr_backend,
+                        self.salloc.resource(),
+                        &self.state.resource().recv_buffer_pool,
+                    ) {
+                        Ok(sglist) => sglist,
+                        Err(e) => {
+                            self.rx_outputs()[0].send(EngineRxMessage::Ack(rpc_id, e.status()))?;
+                            self.quarantine(rpc_id.0, e);
+                            return Ok(Progress(1));
                         }
-                    } else {
-                        panic!("dispatch module not loaded");
                     }
                 }
                 _ => {
@@ -517,6 +769,10 @@ impl TcpRpcAdapterEngine {
                 }
             };
 
+            if self.loopback.contains(&meta_ref.conn_id) {
+                return self.send_loopback(meta_ref, sglist);
+            }
+
             let status = match Self::choose_strategy(&sglist) {
                 RpcStrategy::Fused => self.send_fused(msg.meta_buf_ptr, &sglist)?,
                 RpcStrategy::Standard => self.send_standard(meta_ref, &sglist)?,
@@ -556,12 +812,28 @@ impl TcpRpcAdapterEngine {
         // tracing::trace!("reshape_fused_sg_list: sg_list: {:?}", sg_list);
     }
 
-    fn unmarshal_and_deliver_up(&mut self, sgl: SgList, sock_handle: Handle) -> RpcId {
+    /// Quarantines the connection of a message the dispatch library fails on. The message has
+    /// been dropped, and the later messages of the connection are dropped as well.
+    fn quarantine(&mut self, conn_id: Handle, e: DispatchError) {
+        log::error!("Dispatch failed on a message of {:?}: {}", conn_id, e);
+        log::warn!("Quarantine connection {:?}", conn_id);
+        let status = e.status();
+        self.quarantined.insert(conn_id, status);
+        self.rx_outputs()[0]
+            .send(EngineRxMessage::RecvError(conn_id, status))
+            .unwrap();
+    }
+
+    /// Returns `None` if the message is dropped, and its receive buffers should be reused.
+    fn unmarshal_and_deliver_up(&mut self, sgl: SgList, sock_handle: Handle) -> Option<RpcId> {
         let mut meta_ptr = unsafe { MessageMeta::unpack(&sgl.0[0]) }.unwrap();
         let meta = unsafe { meta_ptr.as_mut() };
         meta.conn_id = sock_handle;
 
         let recv_id = RpcId::new(meta.conn_id, meta.call_id);
+        if self.quarantined.contains_key(&sock_handle) {
+            return None;
+        }
         let mut excavate_ctx = ExcavateContext {
             sgl: sgl.0[1..].iter(),
             addr_arbiter: &self.state.resource().addr_map,
@@ -569,13 +841,26 @@ impl TcpRpcAdapterEngine {
 
         let (addr_app, addr_backend) = match meta.status_code {
             StatusCode::Success => {
-                if let Some(ref module) = self.serialization_engine {
-                    module.unmarshal(meta, &mut excavate_ctx).unwrap()
+                let unmarshaled = if meta.raw() {
+                    // SAFETY: the SgList points to the receive buffers
+                    unsafe { serialization::unmarshal_raw(&mut excavate_ctx) }
+                } else if let Some(module) = self.dispatch_tables.latest() {
+                    module.unmarshal(meta, &mut excavate_ctx)
                 } else {
                     panic!("dispatch module not loaded");
+                };
+                match unmarshaled {
+                    Ok(addrs) => addrs,
+                    Err(e) => {
+                        self.quarantine(sock_handle, e);
+                        return None;
+                    }
                 }
             }
-            StatusCode::AccessDenied => (0usize, 0usize),
+            StatusCode::AccessDenied
+            | StatusCode::Unimplemented
+            | StatusCode::DataLoss
+            | StatusCode::Failed => (0usize, 0usize),
             _ => {
                 panic!("unexpected status code: {:?}", meta.status_code);
             }
@@ -591,15 +876,26 @@ impl TcpRpcAdapterEngine {
             .send(EngineRxMessage::RpcMessage(msg))
             .unwrap();
 
-        recv_id
+        Some(recv_id)
     }
 
     fn process_new_connection(&mut self, handle: &Handle) -> usize {
         (|| -> Result<(), ControlPathError> {
-            let (read_regions, fds) = self.prepare_recv_buffers(*handle)?;
+            let (read_regions, fds) = if self.loopback.contains(handle) {
+                self.prepare_loopback_buffers(*handle)?
+            } else {
+                self.prepare_recv_buffers(*handle)?
+            };
+            let peer_addr = get_ops()
+                .state
+                .sock_table
+                .borrow()
+                .get(handle)
+                .and_then(|(sock, _status)| sock.peer_addr().ok());
             let conn_resp = ConnectResponse {
                 conn_handle: *handle,
                 read_regions,
+                peer_addr,
             };
             let comp = phoenix_api_mrpc::cmd::Completion(Ok(
                 phoenix_api_mrpc::cmd::CompletionKind::NewConnectionInternal(conn_resp, fds),
@@ -651,11 +947,20 @@ impl TcpRpcAdapterEngine {
                                 Self::reshape_fused_sg_list(&mut recv_ctx.sg_list);
                             }
 
-                            let recv_id =
-                                self.unmarshal_and_deliver_up(recv_ctx.sg_list, sock_handle);
-
-                            // keep them outstanding because they will be used by the user
-                            self.recv_mr_usage.insert(recv_id, recv_ctx.recv_mrs);
+                            match self.unmarshal_and_deliver_up(recv_ctx.sg_list, sock_handle) {
+                                Some(recv_id) => {
+                                    // keep them outstanding because they will be used by the user
+                                    self.recv_mr_usage.insert(recv_id, recv_ctx.recv_mrs);
+                                }
+                                None => {
+                                    // the message is dropped, post the buffers again
+                                    if let Err(e) =
+                                        self.reclaim_recv_buffers(sock_handle, &recv_ctx.recv_mrs)
+                                    {
+                                        log::error!("Failed to reclaim the receive buffers: {}", e);
+                                    }
+                                }
+                            }
                         }
                     }
                     // The below two are probably errors in impl logic, so assert them
@@ -672,9 +977,9 @@ impl TcpRpcAdapterEngine {
                 let msg = if wc.opcode == WcOpcode::Send {
                     // let rpc_id = RpcId::decode_u64(wc.wr_id);
                     let rpc_id = self.rpc_ctx.remove(wc.wr_id as usize);
-                    EngineRxMessage::Ack(rpc_id, TransportStatus::Error(code))
+                    EngineRxMessage::Ack(rpc_id, socket_status(code))
                 } else if wc.opcode == WcOpcode::Recv {
-                    EngineRxMessage::RecvError(handle, TransportStatus::Error(code))
+                    EngineRxMessage::RecvError(handle, socket_status(code))
                 } else {
                     panic!("invalid wc: {:?}", wc);
                 };
@@ -698,6 +1003,7 @@ impl TcpRpcAdapterEngine {
         for wc in &wcs {
             progress += self.process_completion(wc);
         }
+        progress += self.check_loopback()?;
 
         // COMMENT(cjr): Progress(0) here is okay for now because we haven't use the progress as
         // any indicator.
@@ -725,6 +1031,33 @@ impl TcpRpcAdapterEngine {
         Ok(())
     }
 
+    fn prepare_loopback_buffers(
+        &mut self,
+        handle: Handle,
+    ) -> Result<(Vec<ReadHeapRegion>, Vec<RawFd>), ControlPathError> {
+        // a loopback message takes one buffer, so fewer buffers are needed
+        let slab = BufferSlab::new(
+            32,
+            8 * 1024 * 1024,
+            8 * 1024 * 1024,
+            &self.salloc.addr_mediator,
+        )?;
+        let endpoint = self
+            .loopback
+            .endpoints
+            .get_mut(&handle)
+            .ok_or(ResourceError::NotFound)?;
+        for _ in 0..32 {
+            let recv_buffer = slab.obtain().unwrap();
+            endpoint.free_buffers.push(recv_buffer.as_handle());
+            self.state
+                .recv_buffer_table
+                .borrow_mut()
+                .insert(recv_buffer.as_handle(), recv_buffer);
+        }
+        Ok(self.export_recv_buffers(slab))
+    }
+
     fn prepare_recv_buffers(
         &mut self,
         sock_handle: Handle,
@@ -748,6 +1081,10 @@ impl TcpRpcAdapterEngine {
                 .insert(recv_buffer.as_handle(), recv_buffer);
         }
 
+        Ok(self.export_recv_buffers(slab))
+    }
+
+    fn export_recv_buffers(&mut self, slab: BufferSlab) -> (Vec<ReadHeapRegion>, Vec<RawFd>) {
         let region = slab.storage();
         let read_regions = vec![ReadHeapRegion {
             handle: region.as_handle(),
@@ -759,7 +1096,7 @@ impl TcpRpcAdapterEngine {
 
         // don't forget this
         self.state.resource().recv_buffer_pool.replenish(slab);
-        Ok((read_regions, fds))
+        (read_regions, fds)
     }
 
     fn check_input_cmd_queue(&mut self) -> Result<Status, ControlPathError> {
@@ -789,7 +1126,7 @@ impl TcpRpcAdapterEngine {
         use phoenix_api_mrpc::cmd::{Command, CompletionKind};
         match req {
             Command::SetTransport(_) => {
-                unreachable!();
+                unreachable!("SetTransport is handled by the mRPC engine");
             }
             Command::NewMappedAddrs(sock_handle, app_vaddrs) => {
                 for (mr_handle, app_vaddr) in app_vaddrs.iter() {
@@ -806,6 +1143,11 @@ impl TcpRpcAdapterEngine {
                         .insert_addr_map(mr_local_addr, mr_remote_mapped)?;
                 }
 
+                if let Some(endpoint) = self.loopback.endpoints.get_mut(sock_handle) {
+                    endpoint.mapped = true;
+                    return Ok(CompletionKind::NewMappedAddrs);
+                }
+
                 //Marked socket as addresses mapped
                 let mut table = get_ops().state.sock_table.borrow_mut();
                 let value = table.get_mut(sock_handle).ok_or(ApiError::NotFound)?;
@@ -818,9 +1160,48 @@ impl TcpRpcAdapterEngine {
 
                 Ok(CompletionKind::NewMappedAddrs)
             }
-            Command::Connect(addr) => {
+            Command::Connect(addr, _) | Command::ConnectDatagram(addr) => {
                 log::debug!("Connect, addr: {:?}", addr);
-                let sock_handle = get_ops().connect(addr)?;
+                if matches!(req, Command::ConnectDatagram(_)) {
+                    log::warn!(
+                        "Datagram connections are not supported by the TCP transport, connecting to {:?} over TCP",
+                        addr
+                    );
+                }
+                if let Some(handle) = self.loopback.connect(addr) {
+                    log::debug!(
+                        "Connect to {:?} through loopback, handle: {:?}",
+                        addr,
+                        handle
+                    );
+                    let (read_regions, fds) = self.prepare_loopback_buffers(handle)?;
+                    let conn_resp = ConnectResponse {
+                        conn_handle: handle,
+                        read_regions,
+                        peer_addr: Some(*addr),
+                    };
+                    return Ok(CompletionKind::ConnectInternal(conn_resp, fds));
+                }
+                let options = match req {
+                    Command::Connect(_, options) => *options,
+                    _ => ConnectOptions::default(),
+                };
+                let sock_handle = if options == ConnectOptions::default() {
+                    get_ops().connect(addr)?
+                } else {
+                    if options.local_addr.is_none() && options.nic_index.is_some() {
+                        log::warn!(
+                            "The TCP transport cannot choose a device, connecting to {:?} from any",
+                            addr
+                        );
+                    }
+                    get_ops().connect_from(
+                        addr,
+                        options.local_addr,
+                        options.port_range,
+                        options.tos,
+                    )?
+                };
                 let (read_regions, fds) = self.prepare_recv_buffers(sock_handle)?;
                 self.state
                     .conn_table
@@ -829,27 +1210,87 @@ impl TcpRpcAdapterEngine {
                 let conn_resp = ConnectResponse {
                     conn_handle: sock_handle,
                     read_regions,
+                    peer_addr: Some(*addr),
                 };
                 Ok(CompletionKind::ConnectInternal(conn_resp, fds))
             }
 
-            Command::Bind(addr) => {
+            Command::Bind(addr, options) => {
                 log::debug!("Bind, addr: {:?}", addr);
-                let handle = get_ops().bind(addr)?;
+                if *options != BindOptions::default() {
+                    log::warn!(
+                        "{:?} are not supported by the TCP transport, ignored",
+                        options
+                    );
+                }
+                // Engines of the same process can bind to the same address, and the kernel
+                // distributes the connections among them. Other processes cannot share it.
+                let pid = self.state.shared.pid;
+                if !self.loopback.can_listen(addr, pid) {
+                    return Err(ApiError::Socket(io::ErrorKind::AddrInUse.into()).into());
+                }
+                let handle = get_ops().bind_reuse_port(addr)?;
+                let local_addr = get_ops()
+                    .state
+                    .listener_table
+                    .borrow()
+                    .get(&handle)
+                    .and_then(|l| l.local_addr().ok())
+                    .unwrap_or(*addr);
+                if !self.loopback.listen(local_addr, pid, handle) {
+                    // another process has bound the address in the meantime
+                    get_ops().state.listener_table.borrow_mut().remove(&handle);
+                    return Err(ApiError::Socket(io::ErrorKind::AddrInUse.into()).into());
+                }
                 Ok(CompletionKind::Bind(handle))
             }
-            Command::UpdateProtosInner(dylib) => {
-                log::debug!("Loading dispatch library: {:?}", dylib);
+            Command::Unbind(listener) => {
+                // closing the socket resets the connections the kernel has not handed over
+                let removed = get_ops().state.listener_table.borrow_mut().remove(listener);
+                if removed.is_none() {
+                    return Err(ResourceError::NotFound.into());
+                }
+                self.loopback.unlisten(*listener);
+                Ok(CompletionKind::Unbind)
+            }
+            Command::UpdateProtosInner(dylib, version) => {
+                log::debug!("Loading dispatch library: {:?}, version {}", dylib, version);
                 let module = SerializationEngine::new(dylib)?;
-                self.serialization_engine = Some(module);
+                self.dispatch_tables.update(module, *version);
                 Ok(CompletionKind::UpdateProtos)
             }
+            Command::RetireProtosInner(version) => {
+                if !self.dispatch_tables.retire(*version) {
+                    log::warn!("dispatch library version {} is not loaded", version);
+                }
+                Ok(CompletionKind::RetireProtosInner)
+            }
+            Command::QueryCredits(handles) => {
+                // TCP has its own flow control
+                let credits = handles.iter().map(|handle| (*handle, None)).collect();
+                Ok(CompletionKind::QueryCredits(credits))
+            }
+            Command::SetCallTiming(_, enable) => {
+                // the calls only get the timestamps taken by the application
+                if *enable {
+                    log::debug!("Call timing is not recorded by the TCP transport");
+                }
+                Ok(CompletionKind::SetCallTiming)
+            }
+            Command::SetEagerCopyThreshold(..) => {
+                // the messages are copied into the socket anyway
+                log::debug!("The eager copy threshold is ignored by the TCP transport");
+                Ok(CompletionKind::SetEagerCopyThreshold)
+            }
             Command::UpdateProtos(_) => {
                 unreachable!();
             }
             Command::MultiConnect(_) => {
                 unreachable!();
             }
+            Command::ConnectHost(..) => {
+                unreachable!();
+            }
         }
     }
 }
@@ -10,9 +10,11 @@ use phoenix_salloc::ControlPathError as SallocError;
 use thiserror::Error;
 use transport_tcp::{ops, ApiError, TransportError};
 
+use phoenix_common::engine::datapath::message::EngineRxMessage;
 use phoenix_common::resource::Error as ResourceError;
 pub use phoenix_common::{InitFnResult, PhoenixModule};
 
+pub mod loopback;
 pub mod module;
 pub mod state;
 
@@ -20,6 +22,7 @@ pub(crate) mod engine;
 #[allow(unused)]
 pub(crate) mod pool;
 pub(crate) mod serialization;
+pub(crate) mod validate;
 
 #[inline]
 fn get_ops() -> &'static ops::Ops {
@@ -75,10 +78,15 @@ pub(crate) enum DatapathError {
 
     #[error("TCP transport error: {0}")]
     TransportError(#[from] TransportError),
+
+    #[error("Rx queue send error: {0}")]
+    Rx(#[from] phoenix_common::engine::datapath::SendError<EngineRxMessage>),
 }
 
 use crate::module::TcpRpcAdapterModule;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(_config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let module = TcpRpcAdapterModule::new();
@@ -0,0 +1,341 @@
+//! Shared-memory transport for RPCs between two clients of the same phoenix instance.
+//!
+//! When a client connects to an address that is bound by another engine of this module, the
+//! connection bypasses the TCP stack entirely. The sender hands the scatter-gather list of the
+//! marshaled message (pointing to its own shared heap) to the receiver. The receiver puts the
+//! meta in one of its receive buffers, which also bounds the messages in flight. If the sender
+//! is an engine of the same application, the payload is on the shared heap that the receiving
+//! app maps too, and it is lent to the receiver as is, until the app releases the message.
+//! Otherwise, the receiver copies the segments straight into the receive buffer, and the message
+//! fails with `EMSGSIZE` if it does not fit. The sender gets the send completion only after the
+//! receiver has consumed the message, which keeps the message alive until then.
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use fnv::FnvHashMap as HashMap;
+use nix::unistd::Pid;
+
+use mrpc_marshal::SgList;
+use phoenix_api::rpc::{MessageMeta, RpcId, TransportStatus};
+use phoenix_api::Handle;
+
+/// Loopback connection handles start from here to not collide with socket handles.
+const LOOPBACK_HANDLE_BASE: u64 = 1 << 32;
+
+pub(crate) enum LoopbackMessage {
+    /// An RPC message. `sgl` points to the shared heap of the sender.
+    Data {
+        meta: MessageMeta,
+        sgl: SgList,
+        wr_id: u64,
+    },
+    /// The receiver has copied or released the message with `wr_id`, or failed to receive it.
+    Consumed { wr_id: u64, status: TransportStatus },
+}
+
+/// One direction of a loopback connection.
+#[derive(Default)]
+struct Channel {
+    queue: spin::Mutex<VecDeque<LoopbackMessage>>,
+}
+
+/// A loopback connection shared by the two endpoints.
+pub(crate) struct Pipe {
+    client: Handle,
+    server: Handle,
+    to_client: Channel,
+    to_server: Channel,
+    closed: AtomicBool,
+}
+
+impl Pipe {
+    #[inline]
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
+/// A listener that accepts loopback connections.
+#[derive(Default)]
+pub(crate) struct Listener {
+    pending: spin::Mutex<VecDeque<Arc<Pipe>>>,
+}
+
+/// The listeners of the engines of a process that bind to the same address. Connections are
+/// distributed among them in round-robin.
+struct ListenerGroup {
+    pid: Pid,
+    listeners: Vec<Arc<Listener>>,
+    next: usize,
+}
+
+impl ListenerGroup {
+    fn pick(&mut self) -> Arc<Listener> {
+        let listener = &self.listeners[self.next % self.listeners.len()];
+        self.next = self.next.wrapping_add(1);
+        Arc::clone(listener)
+    }
+}
+
+/// Shared by all TcpRpcAdapter engines to find each other's listeners.
+#[derive(Default)]
+pub struct LoopbackHub {
+    listeners: spin::Mutex<HashMap<SocketAddr, ListenerGroup>>,
+    next_handle: AtomicU64,
+}
+
+impl LoopbackHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn new_handle(&self) -> Handle {
+        Handle(LOOPBACK_HANDLE_BASE + self.next_handle.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Whether `pid` can listen on `addr`. Only engines of the same process can share an address.
+    fn can_register(&self, addr: &SocketAddr, pid: Pid) -> bool {
+        self.listeners
+            .lock()
+            .get(addr)
+            .map_or(true, |group| group.pid == pid)
+    }
+
+    fn register(&self, addr: SocketAddr, pid: Pid) -> Option<Arc<Listener>> {
+        let mut listeners = self.listeners.lock();
+        let group = listeners.entry(addr).or_insert_with(|| ListenerGroup {
+            pid,
+            listeners: Vec::new(),
+            next: 0,
+        });
+        if group.pid != pid {
+            return None;
+        }
+        let listener = Arc::new(Listener::default());
+        group.listeners.push(Arc::clone(&listener));
+        Some(listener)
+    }
+
+    fn unregister(&self, listener: &Arc<Listener>) {
+        let mut listeners = self.listeners.lock();
+        for group in listeners.values_mut() {
+            group.listeners.retain(|l| !Arc::ptr_eq(l, listener));
+        }
+        listeners.retain(|_, group| !group.listeners.is_empty());
+    }
+
+    /// Finds the listener that `addr` reaches if `addr` is an address of this host.
+    fn lookup(&self, addr: &SocketAddr) -> Option<Arc<Listener>> {
+        let mut listeners = self.listeners.lock();
+        if let Some(group) = listeners.get_mut(addr) {
+            return Some(group.pick());
+        }
+        if !is_local_ip(addr.ip()) {
+            return None;
+        }
+        listeners
+            .iter_mut()
+            .find(|(bound, _)| {
+                bound.port() == addr.port()
+                    && (bound.ip().is_unspecified()
+                        || (bound.ip().is_loopback() && addr.ip().is_loopback()))
+            })
+            .map(|(_, group)| group.pick())
+    }
+}
+
+fn is_local_ip(ip: IpAddr) -> bool {
+    if ip.is_loopback() || ip.is_unspecified() {
+        return true;
+    }
+    match nix::ifaddrs::getifaddrs() {
+        Ok(mut ifaddrs) => ifaddrs.any(|ifaddr| {
+            ifaddr.address.map_or(false, |sa| {
+                sa.as_sockaddr_in()
+                    .map(|sin| IpAddr::from(std::net::Ipv4Addr::from(sin.ip())) == ip)
+                    .or_else(|| {
+                        sa.as_sockaddr_in6()
+                            .map(|sin6| IpAddr::from(sin6.ip()) == ip)
+                    })
+                    .unwrap_or(false)
+            })
+        }),
+        Err(_) => false,
+    }
+}
+
+/// The local end of a loopback connection.
+pub(crate) struct Endpoint {
+    pipe: Arc<Pipe>,
+    is_client: bool,
+    /// Receive buffers not holding any message.
+    pub(crate) free_buffers: Vec<Handle>,
+    /// Whether the application has mapped the receive buffers.
+    pub(crate) mapped: bool,
+    /// The `wr_id` of the messages lent by the sender, until the application releases them
+    pub(crate) lent: HashMap<RpcId, u64>,
+}
+
+impl Endpoint {
+    #[inline]
+    pub(crate) fn handle(&self) -> Handle {
+        if self.is_client {
+            self.pipe.client
+        } else {
+            self.pipe.server
+        }
+    }
+
+    #[inline]
+    pub(crate) fn send(&self, msg: LoopbackMessage) {
+        let channel = if self.is_client {
+            &self.pipe.to_server
+        } else {
+            &self.pipe.to_client
+        };
+        channel.queue.lock().push_back(msg);
+    }
+
+    #[inline]
+    pub(crate) fn try_recv(&self) -> Option<LoopbackMessage> {
+        let channel = if self.is_client {
+            &self.pipe.to_client
+        } else {
+            &self.pipe.to_server
+        };
+        channel.queue.lock().pop_front()
+    }
+
+    /// Puts a received message back to the front of the queue.
+    #[inline]
+    pub(crate) fn requeue(&self, msg: LoopbackMessage) {
+        let channel = if self.is_client {
+            &self.pipe.to_client
+        } else {
+            &self.pipe.to_server
+        };
+        channel.queue.lock().push_front(msg);
+    }
+
+    #[inline]
+    pub(crate) fn is_closed(&self) -> bool {
+        self.pipe.is_closed()
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        self.pipe.closed.store(true, Ordering::Release);
+    }
+}
+
+/// Per engine state of the loopback transport.
+pub(crate) struct LoopbackState {
+    hub: Arc<LoopbackHub>,
+    // the listeners of this engine, by the handles of the sockets they are bound along with
+    listeners: Vec<(Handle, Arc<Listener>)>,
+    pub(crate) endpoints: HashMap<Handle, Endpoint>,
+}
+
+impl LoopbackState {
+    pub(crate) fn new(hub: Arc<LoopbackHub>) -> Self {
+        LoopbackState {
+            hub,
+            listeners: Vec::new(),
+            endpoints: HashMap::default(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn contains(&self, handle: &Handle) -> bool {
+        self.endpoints.contains_key(handle)
+    }
+
+    /// Whether the process `pid` can listen on `addr`, i.e., `addr` is not bound by the engines
+    /// of another process.
+    pub(crate) fn can_listen(&self, addr: &SocketAddr, pid: Pid) -> bool {
+        self.hub.can_register(addr, pid)
+    }
+
+    /// Makes `addr` reachable by loopback connections. Returns false if `addr` is bound by the
+    /// engines of another process.
+    pub(crate) fn listen(&mut self, addr: SocketAddr, pid: Pid, handle: Handle) -> bool {
+        match self.hub.register(addr, pid) {
+            Some(listener) => {
+                self.listeners.push((handle, listener));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops accepting loopback connections on the listener bound along with `handle`. The
+    /// connections not accepted yet are closed.
+    pub(crate) fn unlisten(&mut self, handle: Handle) {
+        if let Some(pos) = self.listeners.iter().position(|(h, _)| *h == handle) {
+            let (_, listener) = self.listeners.swap_remove(pos);
+            self.hub.unregister(&listener);
+            for pipe in listener.pending.lock().drain(..) {
+                pipe.closed.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Connects to a listener of this phoenix instance. Returns `None` if no engine listens on
+    /// `addr` or `addr` is not local.
+    pub(crate) fn connect(&mut self, addr: &SocketAddr) -> Option<Handle> {
+        let listener = self.hub.lookup(addr)?;
+        let pipe = Arc::new(Pipe {
+            client: self.hub.new_handle(),
+            server: self.hub.new_handle(),
+            to_client: Channel::default(),
+            to_server: Channel::default(),
+            closed: AtomicBool::new(false),
+        });
+        let handle = pipe.client;
+        listener.pending.lock().push_back(Arc::clone(&pipe));
+        self.endpoints.insert(
+            handle,
+            Endpoint {
+                pipe,
+                is_client: true,
+                free_buffers: Vec::new(),
+                mapped: false,
+                lent: HashMap::default(),
+            },
+        );
+        Some(handle)
+    }
+
+    /// Accepts the loopback connections pending on this engine's listeners.
+    pub(crate) fn accept(&mut self) -> Vec<Handle> {
+        let mut accepted = Vec::new();
+        for (_, listener) in &self.listeners {
+            while let Some(pipe) = listener.pending.lock().pop_front() {
+                let handle = pipe.server;
+                self.endpoints.insert(
+                    handle,
+                    Endpoint {
+                        pipe,
+                        is_client: false,
+                        free_buffers: Vec::new(),
+                        mapped: false,
+                        lent: HashMap::default(),
+                    },
+                );
+                accepted.push(handle);
+            }
+        }
+        accepted
+    }
+}
+
+impl Drop for LoopbackState {
+    fn drop(&mut self) {
+        for (_, listener) in &self.listeners {
+            self.hub.unregister(listener);
+        }
+    }
+}
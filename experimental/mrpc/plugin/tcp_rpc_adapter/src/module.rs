@@ -22,6 +22,7 @@ use phoenix_common::state_mgr::SharedStateManager;
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 
 use crate::engine::{TcpRpcAdapterEngine, TlStorage};
+use crate::loopback::{LoopbackHub, LoopbackState};
 use crate::state::{Shared, State};
 
 pub(crate) struct RpcAdapterEngineBuilder {
@@ -34,6 +35,7 @@ pub(crate) struct RpcAdapterEngineBuilder {
     shared: Arc<Shared>,
     salloc_shared: Arc<SallocShared>,
     addr_mediator: Arc<AddressMediator>,
+    loopback_hub: Arc<LoopbackHub>,
 }
 
 impl RpcAdapterEngineBuilder {
@@ -48,6 +50,7 @@ impl RpcAdapterEngineBuilder {
         shared: Arc<Shared>,
         salloc_shared: Arc<SallocShared>,
         addr_mediator: Arc<AddressMediator>,
+        loopback_hub: Arc<LoopbackHub>,
     ) -> Self {
         RpcAdapterEngineBuilder {
             _client_pid: client_pid,
@@ -59,6 +62,7 @@ impl RpcAdapterEngineBuilder {
             shared,
             salloc_shared,
             addr_mediator,
+            loopback_hub,
         }
     }
 
@@ -76,16 +80,19 @@ impl RpcAdapterEngineBuilder {
             _mode: self.mode,
             indicator: Default::default(),
             recv_mr_usage: fnv::FnvHashMap::default(),
-            serialization_engine: None,
+            dispatch_tables: Default::default(),
             salloc: salloc_state,
             // start: std::time::Instant::now(),
             rpc_ctx: Default::default(),
+            loopback: LoopbackState::new(self.loopback_hub),
+            quarantined: Default::default(),
         })
     }
 }
 
 pub struct TcpRpcAdapterModule {
     pub state_mgr: SharedStateManager<Shared>,
+    pub loopback_hub: Arc<LoopbackHub>,
 }
 
 impl TcpRpcAdapterModule {
@@ -104,6 +111,7 @@ impl TcpRpcAdapterModule {
     pub fn new() -> Self {
         TcpRpcAdapterModule {
             state_mgr: SharedStateManager::new(),
+            loopback_hub: Arc::new(LoopbackHub::new()),
         }
     }
 }
@@ -129,6 +137,7 @@ impl PhoenixModule for TcpRpcAdapterModule {
         let module = *self;
         let mut collections = ResourceCollection::new();
         collections.insert("state_mgr".to_string(), Box::new(module.state_mgr));
+        collections.insert("loopback_hub".to_string(), Box::new(module.loopback_hub));
         collections
     }
 
@@ -136,6 +145,7 @@ impl PhoenixModule for TcpRpcAdapterModule {
         // NOTE(wyj): we may better call decompose here
         let prev_concrete = unsafe { *prev_module.downcast_unchecked::<Self>() };
         self.state_mgr = prev_concrete.state_mgr;
+        self.loopback_hub = prev_concrete.loopback_hub;
     }
 
     fn create_engine(
@@ -257,6 +267,7 @@ impl TcpRpcAdapterModule {
             shared,
             salloc_shared,
             addr_mediator,
+            Arc::clone(&self.loopback_hub),
         );
         let engine = builder.build()?;
         Ok(engine)
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use bitvec::bitvec;
 use bitvec::vec::BitVec;
 
-use phoenix_api::{AsHandle, Handle};
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
 
 use phoenix_salloc::region::{AddressMediator, SharedRegion};
 
@@ -23,13 +23,26 @@ pub(crate) struct RecvBuffer {
     storage: Arc<SharedRegion>,
 }
 
+/// The number of bits of the buffer index in the handle of a `RecvBuffer`. The rest of the id
+/// holds the handle of the backing storage.
+const RECV_BUFFER_INDEX_BITS: u32 = 24;
+
 impl AsHandle for RecvBuffer {
     fn as_handle(&self) -> Handle {
         let high = self.storage.as_handle().0;
-        let low = self.offset / self.len;
-        assert!(high < (1 << 16), "Please consider reduce the number of underlying storage or widen the Handle type to 64-bit");
-        assert!(low < (1 << 16), "Please consider reduce the number of recv buffers inside a slab or widen the Handle type to 64-bit");
-        Handle(((high * (1 << 16)) as u32 + low as u32) as u64)
+        let low = (self.offset / self.len) as u64;
+        assert!(
+            high < (1 << (Handle::ID_BITS - RECV_BUFFER_INDEX_BITS)),
+            "Please consider reduce the number of underlying storage"
+        );
+        assert!(
+            low < (1 << RECV_BUFFER_INDEX_BITS),
+            "Please consider reduce the number of recv buffers inside a slab"
+        );
+        Handle::new(
+            HandleNamespace::RecvBuf,
+            high << RECV_BUFFER_INDEX_BITS | low,
+        )
     }
 }
 
@@ -164,6 +177,17 @@ impl BufferPool {
         unreachable!()
     }
 
+    /// Returns whether `[addr, addr + len)` lies in a buffer of the pool.
+    pub(crate) fn contains(&self, addr: usize, len: usize) -> bool {
+        let Some(end) = addr.checked_add(len) else {
+            return false;
+        };
+        self.slabs.lock().iter().any(|s| {
+            let start = s.storage.as_ptr().expose_addr();
+            addr >= start && end <= start + s.storage.len()
+        })
+    }
+
     pub(crate) fn find(&self, handle: &Handle) -> Result<Arc<SharedRegion>, ControlPathError> {
         self.slabs
             .lock()
@@ -0,0 +1,81 @@
+//! What the phoenix daemon serves to this library.
+//!
+//! The transports, the message size limit and the policy engines depend on the plugins the daemon
+//! loads. An application or a framework on top of mRPC calls [`capabilities`] to adapt to them up
+//! front, instead of failing deep inside a call.
+use ipc::control::{Request, ResponseKind};
+use ipc::service::request_control;
+use ipc::version::ProtocolVersion;
+use phoenix_api_mrpc::control_plane::TransportType;
+use phoenix_syscalls::{PHOENIX_CONTROL_SOCK, PHOENIX_PREFIX};
+
+use crate::Error;
+
+const RDMA_ADAPTER: &str = "RpcAdapter";
+const TCP_ADAPTER: &str = "TcpRpcAdapter";
+
+/// The features the daemon supports.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// The control path protocol version this library and the daemon agree on
+    pub protocol_version: ProtocolVersion,
+    /// The transports that can be selected in the [`Setting`](crate::current_setting)
+    pub transports: Vec<TransportType>,
+    /// The largest message accepted over RDMA, in bytes, if it is limited
+    pub max_message_size: Option<u64>,
+    /// The policy engines that can be attached to the datapath
+    pub policy_engines: Vec<String>,
+    /// The datapath profiles that can be selected at connect time
+    pub profiles: Vec<String>,
+}
+
+impl Capabilities {
+    /// Returns whether the daemon serves the `transport`.
+    #[inline]
+    pub fn supports_transport(&self, transport: TransportType) -> bool {
+        self.transports.contains(&transport)
+    }
+
+    /// Returns whether the daemon provides the policy engine `engine`.
+    #[inline]
+    pub fn supports_policy(&self, engine: &str) -> bool {
+        self.policy_engines.iter().any(|e| e == engine)
+    }
+}
+
+/// Queries the daemon for the features it supports.
+pub fn capabilities() -> Result<Capabilities, Error> {
+    let caps = match request_control(
+        &*PHOENIX_PREFIX,
+        &*PHOENIX_CONTROL_SOCK,
+        &Request::Capabilities,
+    )? {
+        ResponseKind::Capabilities(caps) => caps,
+        kind => panic!("unexpected response: {:?}", kind),
+    };
+    let protocol_version = ipc::version::negotiate(caps.protocol_version)?;
+
+    let mut transports = Vec::new();
+    if caps.modules.contains_key(RDMA_ADAPTER) {
+        transports.push(TransportType::Rdma);
+    }
+    if caps.modules.contains_key(TCP_ADAPTER) {
+        transports.push(TransportType::Tcp);
+    }
+    if transports.len() == 2 {
+        transports.push(TransportType::Auto);
+    }
+    let max_message_size = caps
+        .modules
+        .get(RDMA_ADAPTER)
+        .and_then(|m| m.get("max_message_size"))
+        .and_then(|s| s.parse().ok());
+
+    Ok(Capabilities {
+        protocol_version,
+        transports,
+        max_message_size,
+        policy_engines: caps.addon_engines,
+        profiles: caps.profiles,
+    })
+}
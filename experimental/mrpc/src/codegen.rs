@@ -47,6 +47,14 @@ pub mod greeter_client {
             Ok(Self { stub })
         }
 
+        pub fn set_reconnect_policy(&self, policy: ::mrpc::stub::ReconnectPolicy) {
+            self.stub.set_reconnect_policy(policy)
+        }
+
+        pub fn connections(&self) -> Vec<::mrpc::stub::Handle> {
+            self.stub.connections()
+        }
+
         pub fn say_hello(
             &self,
             req: impl mrpc::IntoWRef<HelloRequest>,
@@ -59,6 +67,18 @@ pub mod greeter_client {
             self.stub
                 .unary(Self::SERVICE_ID, func_id, call_id, req.into_wref())
         }
+
+        pub fn say_hello_broadcast(
+            &self,
+            req: impl mrpc::IntoWRef<HelloRequest>,
+            targets: &[::mrpc::stub::Handle],
+        ) -> ::mrpc::stub::BroadcastStream<'_, HelloReply> {
+            // Fill this with the right func_id
+            let func_id = 3687134534u32;
+
+            self.stub
+                .broadcast(Self::SERVICE_ID, func_id, req.into_wref(), targets)
+        }
     }
 
     impl NamedService for GreeterClient {
@@ -112,18 +132,17 @@ pub mod greeter_server {
             &self,
             req_opaque: mrpc::MessageErased,
             read_heap: std::sync::Arc<mrpc::ReadHeap>,
+            ctx: mrpc::stub::RequestContext,
         ) -> (mrpc::WRefOpaque, mrpc::MessageErased) {
             let func_id = req_opaque.meta.func_id;
             match func_id {
                 // TODO(cjr): fill this with the right func_id
                 3687134534u32 => {
-                    let req = ::mrpc::RRef::new(&req_opaque, read_heap);
+                    let req = ::mrpc::RRef::with_context(&req_opaque, read_heap, ctx);
                     let res = self.inner.say_hello(req).await;
                     match res {
                         Ok(reply) => ::mrpc::stub::service_post_handler(reply, &req_opaque),
-                        Err(_status) => {
-                            todo!();
-                        }
+                        Err(status) => ::mrpc::stub::service_error(status, &req_opaque),
                     }
                 }
                 _ => {
@@ -0,0 +1,43 @@
+//! Calls between peers built from different versions of a proto, e.g., during a rolling upgrade.
+//!
+//! A message is normally sent in its in-memory representation, which only a peer with the very
+//! same definition of the message can take in place. The clients and servers generated with
+//! `mrpc_build::Builder::compat` can send their messages in the protobuf wire format instead,
+//! once a client enables it by [`ClientStub::set_compat`]. A server decodes such a request into
+//! its own version of the request, and encodes its reply likewise.
+//!
+//! A message decoded by the receiver is tolerant of the fields the two versions do not share.
+//! The fields the sender does not know are left to their defaults, and those the receiver does not
+//! know are kept by the [`RRef`], see [`RRef::unknown_fields`]. Attached to a message sent on by
+//! [`WRef::set_unknown_fields`], they are sent along with it, so a peer of the newer version gets
+//! them back, e.g., from a server of the older version that updates a record and returns it.
+//!
+//! Only the unknown fields of the message itself are kept. A message whose nested messages have
+//! fields unknown to the receiver is refused with `Code::DataLoss` rather than losing them, so
+//! new fields should be added to the messages of the requests and the replies themselves. A server
+//! replies `Code::DataLoss` to a request it cannot decode, and `Code::Unknown` when its handler
+//! fails the call. The unary, cached and oneshot calls are sent in the protobuf wire format once
+//! enabled, while the broadcast calls fail with `Code::FailedPrecondition`.
+//!
+//! The messages are copied to be encoded and decoded, so a client should only enable it while
+//! the servers may be of another version.
+//!
+//! [`ClientStub::set_compat`]: crate::stub::ClientStub::set_compat
+use crate::stub::{RawMessage, RpcData};
+use crate::WRef;
+
+pub use mrpc_derive::FieldTags;
+pub use mrpc_marshal::compat::{FieldTags, UnknownFields};
+
+/// Encodes the message in the protobuf wire format, followed by its unknown fields, into a raw
+/// message that keeps the token, the correlation ID and the deadline of `msg`.
+pub(crate) fn encode<T: RpcData + prost::Message>(msg: &WRef<T>) -> WRef<RawMessage> {
+    let mut buf = prost::Message::encode_to_vec(&**msg);
+    msg.unknown_fields().encode(&mut buf);
+    let mut payload = RawMessage::with_capacity(buf.len());
+    payload.extend_from_slice(&buf);
+    let mut encoded = WRef::with_token(msg.token(), payload);
+    encoded.set_correlation_id(msg.correlation_id());
+    encoded.set_deadline(msg.deadline());
+    encoded
+}
@@ -0,0 +1,74 @@
+//! Fork handling.
+//!
+//! The queues to the backend, the shared heaps, and the connections of a process are bound to
+//! its PID. A child process created by `fork` inherits all of them from its parent, so any use
+//! of them in the child would corrupt the state of the parent.
+//!
+//! The library registers a `pthread_atfork` handler when it first talks to the backend. The
+//! handler bumps a generation counter in the child, which invalidates the [`ClientStub`]s,
+//! [`LocalServer`]s, and [`RRef`]s inherited from the parent: their operations fail with
+//! [`Error::Forked`], and dropping them does not notify the backend. The child must call
+//! [`reinit_after_fork`] before using mRPC again.
+//!
+//! [`ClientStub`]: crate::stub::ClientStub
+//! [`LocalServer`]: crate::stub::LocalServer
+//! [`RRef`]: crate::RRef
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+use crate::stub::{Reactor, LOCAL_REACTOR};
+use crate::{Error, MRPC_CTX};
+
+/// The number of forks that lead to the current process.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+static WATCH: Once = Once::new();
+
+extern "C" fn on_fork_child() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Installs the fork handler once per process.
+pub(crate) fn watch() {
+    WATCH.call_once(|| {
+        let ret = unsafe { libc::pthread_atfork(None, None, Some(on_fork_child)) };
+        if ret != 0 {
+            log::warn!("pthread_atfork failed: {}", ret);
+        }
+    });
+}
+
+#[inline]
+pub(crate) fn generation() -> usize {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Returns an error if an object created in generation `gen` is used in a forked child.
+#[inline]
+pub(crate) fn check(gen: usize) -> Result<(), Error> {
+    if gen == generation() {
+        Ok(())
+    } else {
+        Err(Error::Forked)
+    }
+}
+
+/// Re-registers the calling thread with the mRPC service and the shared memory allocator under
+/// the PID of a forked child.
+///
+/// This must be called in the child before it uses any other mRPC API. The objects inherited from
+/// the parent, such as [`ClientStub`]s, [`LocalServer`]s, [`WRef`]s, and [`RRef`]s, must not be
+/// used by the child. Stubs and servers must be created again.
+///
+/// [`ClientStub`]: crate::stub::ClientStub
+/// [`LocalServer`]: crate::stub::LocalServer
+/// [`WRef`]: crate::WRef
+/// [`RRef`]: crate::RRef
+pub fn reinit_after_fork() -> Result<(), Error> {
+    shmalloc::backend::reinit_after_fork()?;
+    MRPC_CTX.with(|ctx| ctx.reregister())?;
+    // Forget the stubs inherited from the parent.
+    LOCAL_REACTOR.with(|r| r.replace(Reactor::new()));
+    // The request IDs of exactly-once calls are random, do not draw the same ones as the parent.
+    fastrand::seed(fastrand::u64(..) ^ u64::from(std::process::id()));
+    Ok(())
+}
@@ -0,0 +1,221 @@
+//! Issuing many calls at once and waiting for all of them.
+//!
+//! A unary call is posted to the backend when it is made, not when its future is first polled,
+//! so the calls given to [`join_all`] are already in flight together over the connections they
+//! share. The replies are the [`RRef`]s the calls return, nothing is copied. Dropping the future
+//! of a call, e.g., when it times out, cancels the call, its reply is discarded when it arrives.
+//!
+//! ```ignore
+//! let calls = hotel_ids.iter().map(|id| client.get_profile(req(id)));
+//! let profiles = mrpc::join_all(calls).timeout(Duration::from_millis(10)).await?;
+//! ```
+//!
+//! [`RRef`]: crate::RRef
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::Status;
+
+/// Waits for all the `calls`, see [`JoinAll`].
+pub fn join_all<I, F, T>(calls: I) -> JoinAll<F, T>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = Result<T, Status>>,
+{
+    let calls: Vec<_> = calls.into_iter().map(|call| Some(Box::pin(call))).collect();
+    let results = calls.iter().map(|_| None).collect();
+    JoinAll {
+        calls: Calls {
+            calls,
+            results,
+            timeout: None,
+            deadline: None,
+        },
+    }
+}
+
+/// Fails the `call` with [`Code::DeadlineExceeded`] if it does not finish within `timeout`
+/// since first polled, for calls of different types in [`futures::join!`] or
+/// [`futures::try_join!`].
+///
+/// [`Code::DeadlineExceeded`]: crate::Code::DeadlineExceeded
+pub fn timeout<F, T>(call: F, timeout: Duration) -> Timeout<F>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    Timeout {
+        call: Some(Box::pin(call)),
+        timeout,
+        deadline: None,
+    }
+}
+
+struct Calls<F, T> {
+    calls: Vec<Option<Pin<Box<F>>>>,
+    results: Vec<Option<Result<T, Status>>>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl<F, T> Calls<F, T>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    /// Polls the calls not finished yet. Returns the first error if `first_error` is set, or
+    /// `Ready(None)` once all the calls finish.
+    fn poll(&mut self, cx: &mut Context<'_>, first_error: bool) -> Poll<Option<Status>> {
+        let now = self.timeout.map(|timeout| {
+            let now = Instant::now();
+            (now, *self.deadline.get_or_insert(now + timeout))
+        });
+        let mut pending = false;
+        for (slot, result) in self.calls.iter_mut().zip(self.results.iter_mut()) {
+            let Some(call) = slot else {
+                continue;
+            };
+            let ret = match call.as_mut().poll(cx) {
+                Poll::Ready(ret) => ret,
+                Poll::Pending => match now {
+                    Some((now, deadline)) if now >= deadline => {
+                        Err(Status::deadline_exceeded("call timed out in join_all"))
+                    }
+                    _ => {
+                        pending = true;
+                        continue;
+                    }
+                },
+            };
+            // the call is cancelled if it is still pending
+            *slot = None;
+            match ret {
+                Err(status) if first_error => return Poll::Ready(Some(status)),
+                ret => *result = Some(ret),
+            }
+        }
+        if pending {
+            if now.is_some() {
+                // the calls may not wake us before the deadline
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        } else {
+            Poll::Ready(None)
+        }
+    }
+
+    fn take_results(&mut self) -> impl Iterator<Item = Result<T, Status>> + '_ {
+        self.results
+            .iter_mut()
+            .map(|result| result.take().expect("JoinAll polled after completion"))
+    }
+}
+
+/// The future of [`join_all`]. Resolves to the replies in the order of the calls, or to the
+/// first error, in which case the other calls are cancelled.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct JoinAll<F, T> {
+    calls: Calls<F, T>,
+}
+
+impl<F, T> JoinAll<F, T> {
+    /// Fails each call with [`Code::DeadlineExceeded`] if it does not finish within `timeout`
+    /// since the calls are first polled.
+    ///
+    /// [`Code::DeadlineExceeded`]: crate::Code::DeadlineExceeded
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.calls.timeout = Some(timeout);
+        self
+    }
+
+    /// Waits for all the calls even if some fail, and resolves to the result of each call.
+    pub fn best_effort(self) -> JoinAllSettled<F, T> {
+        JoinAllSettled { calls: self.calls }
+    }
+}
+
+// the calls are boxed
+impl<F, T> Unpin for JoinAll<F, T> {}
+
+impl<F, T> Future for JoinAll<F, T>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    type Output = Result<Vec<T>, Status>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.calls.poll(cx, true) {
+            Poll::Ready(Some(status)) => {
+                this.calls.calls.clear();
+                Poll::Ready(Err(status))
+            }
+            Poll::Ready(None) => Poll::Ready(
+                this.calls
+                    .take_results()
+                    .map(|ret| Ok(ret.expect("no error is kept")))
+                    .collect(),
+            ),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The future of [`JoinAll::best_effort`]. Resolves to the result of each call in the order of
+/// the calls.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct JoinAllSettled<F, T> {
+    calls: Calls<F, T>,
+}
+
+impl<F, T> Unpin for JoinAllSettled<F, T> {}
+
+impl<F, T> Future for JoinAllSettled<F, T>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    type Output = Vec<Result<T, Status>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.calls.poll(cx, false) {
+            Poll::Ready(_) => Poll::Ready(this.calls.take_results().collect()),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The future of [`timeout`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Timeout<F> {
+    call: Option<Pin<Box<F>>>,
+    timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl<F> Unpin for Timeout<F> {}
+
+impl<F, T> Future for Timeout<F>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    type Output = Result<T, Status>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let call = this.call.as_mut().expect("Timeout polled after completion");
+        if let Poll::Ready(ret) = call.as_mut().poll(cx) {
+            this.call = None;
+            return Poll::Ready(ret);
+        }
+        let now = Instant::now();
+        if now >= *this.deadline.get_or_insert(now + this.timeout) {
+            // cancel the call
+            this.call = None;
+            return Poll::Ready(Err(Status::deadline_exceeded("call timed out")));
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -27,7 +27,8 @@
 //! # Max Message Size
 //!
 //! Currently, both servers and clients are using a fixed `8MB` as the limit for maximal message size.
-//! This fixed limit will be removed or made configurable in the future.
+//! This fixed limit will be removed or made configurable in the future. The limit of the backend
+//! is reported by [`capabilities`].
 //!
 //! [`mRPC`]: https://github.com/phoenix-dataplane/phoenix/tree/main/experimental/mrpc
 //! [`Phoenix`]: https://github.com/phoenix-dataplane/phoenix
@@ -50,9 +51,11 @@
 // WRef
 #![feature(get_mut_unchecked)]
 
-use std::cell::RefCell;
+use std::cell::{Cell, Ref, RefCell};
 use std::collections::BTreeSet;
 use std::io;
+use std::mem;
+use std::sync::Arc;
 
 use thiserror::Error;
 
@@ -107,18 +110,37 @@ thread_local! {
         SA_CTX.with(|_ctx| {
             // do nothing, just to ensure SA_CTX is initialized before MRPC_CTX
         });
-        Context::register(&current_setting()).expect("phoenix mRPC register failed")
+        // e.g., the daemon rejects the client for its limits
+        Context::register(&current_setting())
+            .unwrap_or_else(|e| panic!("phoenix mRPC register failed: {}", e))
     }
 }
 
+type Service = ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>;
+
 pub(crate) struct Context {
     protos: RefCell<BTreeSet<String>>,
-    service: ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>,
+    service: RefCell<Service>,
+    // The fork generation that the service is registered in.
+    generation: Cell<usize>,
+    // The received messages whose last views are dropped on other threads.
+    pub(crate) released: Arc<rref::Released>,
 }
 
 impl Context {
     fn register(setting: &Setting) -> Result<Context, Error> {
+        fork::watch();
         let protos = RefCell::new(BTreeSet::new());
+        let service = Self::register_service(setting)?;
+        Ok(Self {
+            protos,
+            service: RefCell::new(service),
+            generation: Cell::new(fork::generation()),
+            released: Default::default(),
+        })
+    }
+
+    fn register_service(setting: &Setting) -> Result<Service, Error> {
         println!("mrpc register: {:?}", setting);
         let setting_str = serde_json::to_string(setting)?;
         let mut service = "Mrpc".to_string();
@@ -132,23 +154,80 @@ impl Context {
             SCHEDULING_HINT.with_borrow(|h| *h),
             Some(&setting_str),
         )?;
-        Ok(Self { protos, service })
+        Ok(service)
+    }
+
+    /// Returns the service, or [`Error::Forked`] if the service is inherited from the parent
+    /// process.
+    #[inline]
+    pub(crate) fn service(&self) -> Result<Ref<'_, Service>, Error> {
+        fork::check(self.generation.get())?;
+        Ok(self.service.borrow())
+    }
+
+    /// Registers with the backend again in a forked child, see [`reinit_after_fork`].
+    fn reregister(&self) -> Result<(), Error> {
+        let service = Self::register_service(&current_setting())?;
+        // Dropping the inherited service would remove the socket file of the parent.
+        mem::forget(self.service.replace(service));
+        self.generation.set(fork::generation());
+        self.reload_protos()
+    }
+
+    /// Registers with a restarted backend, see [`reattach`].
+    fn reattach(&self) -> Result<(), Error> {
+        let service = Self::register_service(&current_setting())?;
+        drop(self.service.replace(service));
+        self.reload_protos()
     }
 
     fn update_protos(&self, protos: &[&str]) -> Result<(), Error> {
         let mut used_protos = self.protos.borrow_mut();
-        let orig = used_protos.len();
-        used_protos.extend(protos.iter().copied().map(String::from));
-        if used_protos.len() > orig {
+        let mut new_protos = used_protos.clone();
+        new_protos.extend(protos.iter().copied().map(String::from));
+        if new_protos.len() > used_protos.len() {
+            let protos = new_protos.iter().cloned().collect::<Vec<_>>();
+            let req = cmd::Command::UpdateProtos(protos);
+            let service = self.service()?;
+            service.send_cmd(req)?;
+            // the backend rejects protos whose services collide with the ones used before, keep
+            // the used protos unchanged then
+            rx_recv_impl!(service, cmd::CompletionKind::UpdateProtos)?;
+            *used_protos = new_protos;
+        }
+        Ok(())
+    }
+
+    /// Sends all protos used so far to the backend again, e.g., after the backend restarts.
+    fn reload_protos(&self) -> Result<(), Error> {
+        let used_protos = self.protos.borrow();
+        if !used_protos.is_empty() {
             let protos = used_protos.iter().cloned().collect::<Vec<_>>();
             let req = cmd::Command::UpdateProtos(protos);
-            self.service.send_cmd(req)?;
-            rx_recv_impl!(self.service, cmd::CompletionKind::UpdateProtos)?;
+            let service = self.service()?;
+            service.send_cmd(req)?;
+            rx_recv_impl!(service, cmd::CompletionKind::UpdateProtos)?;
         }
         Ok(())
     }
 }
 
+/// Registers the calling thread with the backend again after the backend restarts, keeping the
+/// shared heap and the messages on it.
+///
+/// The backend must have been restarted from a checkpoint (see `[checkpoint]` in `phoenix.toml`),
+/// which takes back the shared memory regions of the process. The connections do not survive the
+/// backend: the [`ClientStub`]s with a [`ReconnectPolicy`] reconnect on their own, the other stubs
+/// and the [`LocalServer`]s must be created again.
+///
+/// [`ClientStub`]: crate::stub::ClientStub
+/// [`ReconnectPolicy`]: crate::stub::ReconnectPolicy
+/// [`LocalServer`]: crate::stub::LocalServer
+pub fn reattach() -> Result<(), Error> {
+    shmalloc::backend::reattach()?;
+    MRPC_CTX.with(|ctx| ctx.reattach())
+}
+
 /// Re-exports shared memory collections and data types.
 #[doc(inline)]
 pub use shm::collections;
@@ -162,10 +241,21 @@ pub mod alloc {
     pub type Vec<T> = shm::vec::Vec<T, SharedHeapAllocator>;
     /// Shared memory String whose memory is managed by [`SharedHeapAllocator`].
     pub type String = shm::string::String<SharedHeapAllocator>;
+    /// GPU memory that can be sent as a message payload, see [`WRef::from_device_ptr`].
+    ///
+    /// [`WRef::from_device_ptr`]: crate::WRef::from_device_ptr
+    pub use shmalloc::DeviceBuffer;
+    /// Memory allocated by the application that can be sent as a message payload, see
+    /// [`WRef::from_user_ptr`].
+    ///
+    /// [`WRef::from_user_ptr`]: crate::WRef::from_user_ptr
+    pub use shmalloc::UserMemory;
 }
 
 pub mod stub;
 
+pub mod compat;
+
 #[macro_use]
 mod macros;
 
@@ -176,14 +266,14 @@ pub use phoenix_api::rpc::Token;
 pub use phoenix_api::rpc::MessageErased;
 
 mod rref;
-pub use rref::RRef;
+pub use rref::{RRef, RRefView};
 
 mod wref;
-pub use wref::{IntoWRef, WRef, WRefOpaque};
+pub use wref::{IntoWRef, Sent, WRef, WRefBuilder, WRefOpaque};
 
 mod status;
 #[doc(inline)]
-pub use status::{Code, Status};
+pub use status::{Code, Status, TransportCode};
 
 #[cfg(feature = "timing")]
 pub(crate) mod timing;
@@ -192,6 +282,17 @@ pub mod sched;
 #[doc(inline)]
 pub use sched::{bind_to_node, num_numa_nodes};
 
+mod fork;
+pub use fork::reinit_after_fork;
+
+pub mod registry;
+
+mod capabilities;
+pub use capabilities::{capabilities, Capabilities};
+
+mod join;
+pub use join::{join_all, timeout, JoinAll, JoinAllSettled, Timeout};
+
 /// A re-export of [`async-trait`](https://docs.rs/async-trait) for use with codegen.
 pub use async_trait::async_trait;
 
@@ -219,4 +320,21 @@ pub enum Error {
     /// Connection has been closed.
     #[error("Connection closed.")]
     ConnectionClosed,
+    /// The object is inherited from the parent process across fork.
+    #[error("Inherited from the parent process across fork, see reinit_after_fork")]
+    Forked,
+    /// Errors of the shared memory allocator.
+    #[error("Shared heap error: {0}")]
+    SharedHeap(#[from] shmalloc::backend::Error),
+    /// No compatible endpoint of the service is registered in the name service.
+    #[error("No endpoint of {0} is registered")]
+    NoEndpoint(String),
+    /// The thread of a [`stub::Dispatcher`] or a server spawned by [`stub::spawn_server`] has
+    /// exited.
+    #[error("The dispatcher thread has exited")]
+    DispatcherExited,
+    /// The ID of a service or method collides with the one of another path, see
+    /// [`stub::ids::register`].
+    #[error("ID {0} of {2} collides with {1}, override either in the comments of the proto")]
+    IdCollision(u32, String, String),
 }
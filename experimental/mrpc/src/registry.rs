@@ -0,0 +1,93 @@
+//! Service discovery through the name service of the phoenix daemons.
+//!
+//! A [`LocalServer`] registers an endpoint in the daemon for each service it serves, with the
+//! address it is bound to and a hash of the protos of the service. The endpoint is removed when
+//! the last server of the process that serves it is dropped, or when the process exits. If the
+//! daemons are federated (see `[registry]` in `phoenix.toml`), the endpoints registered on the
+//! other hosts are found as well.
+//!
+//! A generated client connects to a registered endpoint of its service with:
+//!
+//! ```ignore
+//! let client = GreeterClient::connect_by_name()?;
+//! ```
+//!
+//! [`LocalServer`]: crate::stub::LocalServer
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+use ipc::control::{Request, ResponseKind};
+use ipc::service::{notify_control, request_control};
+use phoenix_syscalls::{PHOENIX_CONTROL_SOCK, PHOENIX_PREFIX};
+
+#[doc(inline)]
+pub use ipc::control::ServiceEndpoint;
+
+use crate::stub::NamedService;
+use crate::Error;
+
+lazy_static! {
+    // The number of servers of this process that serve each endpoint.
+    static ref REGISTERED: spin::Mutex<HashMap<ServiceEndpoint, usize>> =
+        spin::Mutex::new(HashMap::new());
+}
+
+/// Returns the hash of the protos of the service `S`. Endpoints with the same hash serve the same
+/// protos.
+pub fn proto_hash<S: NamedService>() -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    for src in S::proto_srcs() {
+        hasher.update(src.as_bytes());
+    }
+    format!("{:08x}", hasher.finalize())
+}
+
+/// Returns the endpoints of the service named `service`, the ones on this host first.
+pub fn lookup(service: &str) -> Result<Vec<ServiceEndpoint>, Error> {
+    let req = Request::LookupEndpoint(service.to_owned());
+    match request_control(&*PHOENIX_PREFIX, &*PHOENIX_CONTROL_SOCK, &req)? {
+        ResponseKind::LookupEndpoint(endpoints) => Ok(endpoints),
+        kind => panic!("unexpected response: {:?}", kind),
+    }
+}
+
+/// Returns an endpoint of the service `S` built from the same protos, preferring the ones on this
+/// host.
+pub fn resolve<S: NamedService>() -> Result<ServiceEndpoint, Error> {
+    let hash = proto_hash::<S>();
+    lookup(S::NAME)?
+        .into_iter()
+        .find(|e| e.proto_hash == hash)
+        .ok_or_else(|| Error::NoEndpoint(S::NAME.to_owned()))
+}
+
+/// Registers the endpoint unless another server of this process already did.
+pub(crate) fn register(endpoint: &ServiceEndpoint) -> Result<(), Error> {
+    let mut registered = REGISTERED.lock();
+    let count = registered.entry(endpoint.clone()).or_insert(0);
+    if *count == 0 {
+        let req = Request::RegisterEndpoint(endpoint.clone());
+        if let Err(e) = notify_control(&*PHOENIX_PREFIX, &*PHOENIX_CONTROL_SOCK, &req) {
+            registered.remove(endpoint);
+            return Err(e.into());
+        }
+    }
+    *count += 1;
+    Ok(())
+}
+
+/// Unregisters the endpoint when the last server of this process that serves it is gone.
+pub(crate) fn unregister(endpoint: &ServiceEndpoint) -> Result<(), Error> {
+    let mut registered = REGISTERED.lock();
+    match registered.get_mut(endpoint) {
+        Some(count) if *count > 1 => *count -= 1,
+        Some(_) => {
+            registered.remove(endpoint);
+            let req = Request::UnregisterEndpoint(endpoint.clone());
+            notify_control(&*PHOENIX_PREFIX, &*PHOENIX_CONTROL_SOCK, &req)?;
+        }
+        None => {}
+    }
+    Ok(())
+}
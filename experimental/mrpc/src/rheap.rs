@@ -1,10 +1,14 @@
 //! Read-only shared memory heap.
+use std::collections::HashMap;
 use std::io;
+use std::mem::ManuallyDrop;
 use std::ops::Deref;
 use std::os::unix::io::RawFd;
 use std::slice;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
 
+use lazy_static::lazy_static;
 use memfd::Memfd;
 use mmap::MmapFixed;
 
@@ -13,13 +17,23 @@ use phoenix_api_mrpc::cmd::ConnectResponse;
 
 use super::Error;
 
+lazy_static! {
+    // The regions mapped in this process by the fork generation they are mapped in and their
+    // address. A connection moved to another thread by the backend comes with the regions the
+    // thread it leaves has mapped, they are mapped once and shared.
+    static ref MAPPED: spin::Mutex<HashMap<(usize, usize), Weak<ReadRegion>>> =
+        spin::Mutex::new(HashMap::new());
+}
+
 /// A collection of read-only memory-mapped regions that are guarded by the same reference counter
 /// (the regions are bounded to the same connection).
 #[derive(Debug)]
 pub struct ReadHeap {
     /// The number of [`RRef<T>`](crate::rref::RRef<T>)s pointing to this heap.
     pub(crate) rref_cnt: AtomicUsize,
-    pub(crate) rbufs: Vec<ReadRegion>,
+    pub(crate) rbufs: Vec<Arc<ReadRegion>>,
+    /// The fork generation that the heap is mapped in.
+    pub(crate) generation: usize,
 }
 
 impl Drop for ReadHeap {
@@ -35,20 +49,44 @@ impl Drop for ReadHeap {
 
 impl ReadHeap {
     /// Creates a [`ReadHeap`] from a connection establishment event and a list of memfds.
+    ///
+    /// The regions already mapped in the process, by another thread the connection is moved
+    /// from, are shared instead of mapped again.
     pub fn new(conn_resp: &ConnectResponse, fds: &[RawFd]) -> Self {
+        let generation = crate::fork::generation();
         let mut rbufs = Vec::new();
         for (rbuf, &fd) in conn_resp.read_regions.iter().zip(fds) {
             let memfd = Memfd::try_from_fd(fd)
                 .map_err(|_| io::Error::last_os_error())
                 .unwrap();
-            let m =
-                ReadRegion::new(rbuf.handle, rbuf.addr, rbuf.len, rbuf.file_off, memfd).unwrap();
+            let key = (generation, rbuf.addr);
+            let m = loop {
+                let mut mapped = MAPPED.lock();
+                match mapped.get(&key).map(Weak::upgrade) {
+                    // the memfd sent along is not needed
+                    Some(Some(m)) => break m,
+                    // being unmapped by the last heap holding it, wait until it is gone
+                    Some(None) => {
+                        drop(mapped);
+                        std::hint::spin_loop();
+                    }
+                    None => {
+                        let m =
+                            ReadRegion::new(rbuf.handle, rbuf.addr, rbuf.len, rbuf.file_off, memfd)
+                                .map(Arc::new)
+                                .unwrap();
+                        mapped.insert(key, Arc::downgrade(&m));
+                        break m;
+                    }
+                }
+            };
             // vaddrs.push((mr.0, m.as_ptr().expose_addr()));
             rbufs.push(m);
         }
         ReadHeap {
             rref_cnt: AtomicUsize::new(0),
             rbufs,
+            generation,
         }
     }
 
@@ -56,6 +94,7 @@ impl ReadHeap {
         ReadHeap {
             rref_cnt: AtomicUsize::new(0),
             rbufs: Vec::new(),
+            generation: crate::fork::generation(),
         }
     }
 
@@ -78,12 +117,27 @@ impl ReadHeap {
 /// On destruction, the internal `memfd` will be automatically released.
 #[derive(Debug)]
 pub(crate) struct ReadRegion {
-    mmap: MmapFixed,
+    mmap: ManuallyDrop<MmapFixed>,
     handle: Handle,
-    _remote_addr: usize,
+    remote_addr: usize,
+    generation: usize,
     _memfd: Memfd,
 }
 
+impl Drop for ReadRegion {
+    fn drop(&mut self) {
+        // Unmapped under the lock, so that another thread mapping the same region again does not
+        // find it still mapped.
+        let mut mapped = MAPPED.lock();
+        let key = (self.generation, self.remote_addr);
+        if mapped.get(&key).map_or(false, |m| m.strong_count() == 0) {
+            mapped.remove(&key);
+        }
+        // SAFETY: the mmap is not used afterwards
+        unsafe { ManuallyDrop::drop(&mut self.mmap) };
+    }
+}
+
 impl AsHandle for ReadRegion {
     #[inline]
     fn as_handle(&self) -> Handle {
@@ -114,9 +168,10 @@ impl ReadRegion {
         // NOTE(wyj): align is not needed for shared recv buffer
         // as we don't need to query backend addr for shared recv buffer
         Ok(ReadRegion {
-            mmap,
+            mmap: ManuallyDrop::new(mmap),
             handle,
-            _remote_addr: remote_addr,
+            remote_addr,
+            generation: crate::fork::generation(),
             _memfd: memfd,
         })
     }
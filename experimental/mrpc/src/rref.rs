@@ -2,15 +2,22 @@
 use std::cmp::Ordering;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::mem::MaybeUninit;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
 use std::ops::Deref;
+use std::ptr::NonNull;
 use std::sync::Arc;
+use std::thread::{self, ThreadId};
 
 use phoenix_api::rpc::{CallId, MessageErased, RpcId, Token};
 use phoenix_api_mrpc::dp::{WorkRequest, RECV_RECLAIM_BS};
 use shm::ptr::ShmPtr;
 
+use crate::alloc::Box as ShmBox;
+use crate::compat::{FieldTags, UnknownFields};
+use crate::fork;
+use crate::stub::{CallTiming, RawMessage, RequestContext};
 use crate::ReadHeap;
+use crate::Status;
 use crate::MRPC_CTX;
 
 #[derive(Debug)]
@@ -18,8 +25,26 @@ struct RRefInner<T> {
     rpc_id: RpcId,
     /// User associated context.
     token: Token,
+    /// The application correlation ID of the call.
+    correlation_id: u64,
     read_heap: Arc<ReadHeap>,
     data: ShmPtr<T>,
+    /// The server side context of an incoming request.
+    context: Option<RequestContext>,
+    /// The timing of the call if this is a timed reply.
+    timing: Option<CallTiming>,
+    /// Whether the server asked the client to slow down in this reply.
+    pushback: bool,
+    /// The message decoded from the protobuf wire format, which `data` points to, see
+    /// [`RRef::decode`].
+    decoded: Option<Decoded<T>>,
+}
+
+#[derive(Debug)]
+struct Decoded<T> {
+    // owns the message that `data` points to
+    _message: ShmBox<T>,
+    unknown_fields: UnknownFields,
 }
 
 /// A thread-safe reference-counting pointer to objects on the read-only shared memory heap.
@@ -29,6 +54,16 @@ pub struct RRef<T>(Arc<RRefInner<T>>);
 // but the shared memory should be properly recycled by the backend
 impl<T> Drop for RRefInner<T> {
     fn drop(&mut self) {
+        // the receive buffer of a decoded message is reclaimed once it is decoded
+        if self.decoded.is_some() {
+            return;
+        }
+        self.read_heap.decrement_refcnt();
+        // The receive buffer belongs to the parent process.
+        if fork::check(self.read_heap.generation).is_err() {
+            return;
+        }
+
         let msgs: [MaybeUninit<CallId>; RECV_RECLAIM_BS] = MaybeUninit::uninit_array();
         let mut msgs = unsafe { MaybeUninit::array_assume_init(msgs) };
         msgs[0] = self.rpc_id.1;
@@ -36,9 +71,10 @@ impl<T> Drop for RRefInner<T> {
         let conn_id = self.rpc_id.0;
         let reclaim_wr = WorkRequest::ReclaimRecvBuf(conn_id, msgs);
         MRPC_CTX.with(move |ctx| {
+            let service = ctx.service().expect("mRPC context inherited across fork");
             let mut sent = false;
             while !sent {
-                ctx.service
+                service
                     .enqueue_wr_with(|ptr, _count| unsafe {
                         ptr.cast::<WorkRequest>().write(reclaim_wr);
                         sent = true;
@@ -47,8 +83,6 @@ impl<T> Drop for RRefInner<T> {
                     .expect("channel to backend corrupted");
             }
         });
-
-        self.read_heap.decrement_refcnt();
     }
 }
 
@@ -58,6 +92,38 @@ impl<T> RRef<T> {
     #[must_use]
     #[inline]
     pub fn new(msg: &MessageErased, read_heap: Arc<ReadHeap>) -> Self {
+        Self::new_inner(msg, read_heap, None, None)
+    }
+
+    /// Constructs an `RRef<T>` for an incoming request and attaches the
+    /// [`RequestContext`] to it.
+    #[must_use]
+    #[inline]
+    pub fn with_context(
+        msg: &MessageErased,
+        read_heap: Arc<ReadHeap>,
+        context: RequestContext,
+    ) -> Self {
+        Self::new_inner(msg, read_heap, Some(context), None)
+    }
+
+    /// Constructs an `RRef<T>` for a reply and attaches the timing of its call.
+    #[inline]
+    pub(crate) fn with_timing(
+        msg: &MessageErased,
+        read_heap: Arc<ReadHeap>,
+        timing: Option<CallTiming>,
+    ) -> Self {
+        Self::new_inner(msg, read_heap, None, timing)
+    }
+
+    #[inline]
+    fn new_inner(
+        msg: &MessageErased,
+        read_heap: Arc<ReadHeap>,
+        context: Option<RequestContext>,
+        timing: Option<CallTiming>,
+    ) -> Self {
         let ptr_app = msg.shm_addr_app as *mut T;
         let ptr_backend = ptr_app.with_addr(msg.shm_addr_backend);
         let backend_owned = ShmPtr::new(ptr_app, ptr_backend).unwrap();
@@ -70,17 +136,112 @@ impl<T> RRef<T> {
         RRef(Arc::new(RRefInner {
             rpc_id,
             token: Token(msg.meta.token as usize),
+            correlation_id: msg.meta.correlation_id,
             read_heap,
             data: backend_owned,
+            context,
+            timing,
+            pushback: msg.meta.pushback(),
+            decoded: None,
         }))
     }
 
+    /// Decodes a message received in the protobuf wire format into this version of `T`, see
+    /// [`compat`](crate::compat). The receive buffer of the encoded message is reclaimed
+    /// once it is decoded.
+    ///
+    /// Fails with `Code::DataLoss` if the message cannot be decoded, or if its nested messages
+    /// have fields unknown to `T`, which would be lost.
+    pub(crate) fn decode(encoded: RRef<RawMessage>) -> Result<Self, Status>
+    where
+        T: prost::Message + Default + FieldTags,
+    {
+        let bytes: &[u8] = &encoded;
+        let message = T::decode(bytes)
+            .map_err(|e| Status::data_loss(format!("failed to decode the message: {}", e)))?;
+        let unknown_fields = UnknownFields::split(bytes, T::FIELD_TAGS)
+            .map_err(|e| Status::data_loss(format!("failed to decode the message: {}", e)))?;
+        // Only the unknown fields of the message itself are kept. Both peers encode with prost,
+        // so the decoded message encodes back to the bytes received unless the unknown fields
+        // of its nested messages are dropped.
+        let kept = message.encoded_len() + unknown_fields.as_bytes().len();
+        if kept < bytes.len() {
+            return Err(Status::data_loss(format!(
+                "the nested messages have {} bytes of fields unknown to the receiver",
+                bytes.len() - kept
+            )));
+        }
+        let message = ShmBox::new(message);
+        let (ptr_app, ptr_backend) = ShmBox::to_raw_parts(&message);
+        let encoded = &encoded.0;
+        Ok(RRef(Arc::new(RRefInner {
+            rpc_id: encoded.rpc_id,
+            token: encoded.token,
+            correlation_id: encoded.correlation_id,
+            read_heap: Arc::clone(&encoded.read_heap),
+            data: ShmPtr::new(ptr_app.as_ptr(), ptr_backend.as_ptr()).unwrap(),
+            context: encoded.context.clone(),
+            timing: encoded.timing,
+            pushback: encoded.pushback,
+            decoded: Some(Decoded {
+                _message: message,
+                unknown_fields,
+            }),
+        })))
+    }
+
     /// Returns the user associated token.
     #[must_use]
     #[inline]
     pub fn token(&self) -> Token {
         self.0.token
     }
+
+    /// Returns the application correlation ID of the call, as set on the [`WRef`] of the
+    /// request, both for the request received by a server and for its reply.
+    ///
+    /// [`WRef`]: crate::WRef
+    #[must_use]
+    #[inline]
+    pub fn correlation_id(&self) -> u64 {
+        self.0.correlation_id
+    }
+
+    /// Returns the [`RequestContext`] if this is a request received by a server.
+    #[must_use]
+    #[inline]
+    pub fn context(&self) -> Option<&RequestContext> {
+        self.0.context.as_ref()
+    }
+
+    /// Returns the [`CallTiming`] if this is the reply of a call issued with timing enabled, see
+    /// [`ClientStub::set_call_timing`](crate::stub::ClientStub::set_call_timing).
+    #[must_use]
+    #[inline]
+    pub fn timing(&self) -> Option<CallTiming> {
+        self.0.timing
+    }
+
+    /// Returns whether the server was congested when it sent this reply, and asked the client
+    /// to slow down, see [`LocalServer::set_pushback`](crate::stub::LocalServer::set_pushback).
+    /// The request is handled all the same, the flag is only advisory.
+    #[must_use]
+    #[inline]
+    pub fn pushback(&self) -> bool {
+        self.0.pushback
+    }
+
+    /// Returns the fields unknown to this version of the proto if the message is received in
+    /// the protobuf wire format, see [`compat`](crate::compat). They can be sent along with
+    /// another message by [`WRef::set_unknown_fields`](crate::WRef::set_unknown_fields).
+    #[must_use]
+    #[inline]
+    pub fn unknown_fields(&self) -> Option<&UnknownFields> {
+        self.0
+            .decoded
+            .as_ref()
+            .map(|decoded| &decoded.unknown_fields)
+    }
 }
 
 impl<T> Clone for RRef<T> {
@@ -106,6 +267,29 @@ impl<T> AsRef<T> for RRef<T> {
     }
 }
 
+impl<T: Send + Sync + 'static> RRef<T> {
+    /// Returns a view of a part of the message, e.g., `rref.project(|m| &m.rate_plans)`,
+    /// without copying it. The view keeps the message alive like an `RRef<T>`.
+    #[must_use]
+    #[inline]
+    pub fn project<U: ?Sized, F: FnOnce(&T) -> &U>(&self, f: F) -> RRefView<U> {
+        RRefView::new(NonNull::from(f(self.as_ref())), self.view_owner())
+    }
+
+    /// Returns a view of a part of the message, or `None` if `f` returns `None`, e.g., for an
+    /// out-of-bounds element.
+    #[must_use]
+    #[inline]
+    pub fn try_project<U: ?Sized, F: FnOnce(&T) -> Option<&U>>(&self, f: F) -> Option<RRefView<U>> {
+        f(self.as_ref()).map(|part| RRefView::new(NonNull::from(part), self.view_owner()))
+    }
+
+    fn view_owner(&self) -> Arc<dyn Owner + Send + Sync> {
+        let released = MRPC_CTX.with(|ctx| Arc::clone(&ctx.released));
+        Arc::new(ViewOwner::new(Received(Arc::clone(&self.0)), released))
+    }
+}
+
 impl<T: Clone> RRef<T> {
     /// Creates owned data from borrowed data, usually by cloning.
     pub fn into_owned(self) -> T {
@@ -157,3 +341,286 @@ impl<T: Hash> Hash for RRef<T> {
         Hash::hash(&**self, state)
     }
 }
+
+/// The message that an [`RRefView`] points into, with its type erased.
+trait Owner {
+    fn token(&self) -> Token;
+    fn correlation_id(&self) -> u64;
+}
+
+/// A received message shared with the views.
+struct Received<T>(Arc<RRefInner<T>>);
+
+// SAFETY: the views only read the message and the fields copied from its meta. The read heap
+// and the receive buffer are only released by dropping the message, which `ViewOwner` does on
+// the thread that received it.
+unsafe impl<T: Send + Sync> Send for Received<T> {}
+unsafe impl<T: Send + Sync> Sync for Received<T> {}
+
+impl<T> Owner for Received<T> {
+    fn token(&self) -> Token {
+        self.0.token
+    }
+
+    fn correlation_id(&self) -> u64 {
+        self.0.correlation_id
+    }
+}
+
+/// The messages whose last views are dropped on other threads, to be dropped by the thread that
+/// received them. The receive buffer of a message must be reclaimed through the service of that
+/// thread, each thread talks to its own backend engine.
+#[derive(Default)]
+pub(crate) struct Released {
+    messages: spin::Mutex<Vec<Box<dyn Send>>>,
+}
+
+impl Released {
+    fn push(&self, message: Box<dyn Send>) {
+        self.messages.lock().push(message);
+    }
+
+    /// Drops the messages released by the views on other threads. It must be called by the
+    /// thread that owns this list.
+    pub(crate) fn drop_all(&self) {
+        let messages = mem::take(&mut *self.messages.lock());
+        mem::drop(messages);
+    }
+}
+
+/// The owner shared by the views of a message. It remembers the thread that received the
+/// message, and hands the message back to that thread if the last view is dropped elsewhere.
+struct ViewOwner<O: Send + 'static> {
+    owner: ManuallyDrop<O>,
+    thread: ThreadId,
+    released: Arc<Released>,
+}
+
+impl<O: Send + 'static> ViewOwner<O> {
+    fn new(owner: O, released: Arc<Released>) -> Self {
+        ViewOwner {
+            owner: ManuallyDrop::new(owner),
+            thread: thread::current().id(),
+            released,
+        }
+    }
+}
+
+impl<O: Send + 'static> Drop for ViewOwner<O> {
+    fn drop(&mut self) {
+        // SAFETY: the owner is not used after this.
+        let owner = unsafe { ManuallyDrop::take(&mut self.owner) };
+        if thread::current().id() == self.thread {
+            mem::drop(owner);
+        } else {
+            self.released.push(Box::new(owner));
+        }
+    }
+}
+
+impl<O: Owner + Send + 'static> Owner for ViewOwner<O> {
+    fn token(&self) -> Token {
+        self.owner.token()
+    }
+
+    fn correlation_id(&self) -> u64 {
+        self.owner.correlation_id()
+    }
+}
+
+/// A read-only view of a part of a received message, such as a field or an element, obtained
+/// by [`RRef::project`].
+///
+/// The view shares the message with the [`RRef`] it comes from. The receive buffer of the
+/// message is reclaimed after the last `RRef` and the last view of the message are dropped.
+///
+/// Unlike an `RRef`, a view can be sent to other threads, e.g., to the threads of a proxy that
+/// forward a part of the message. If the last view is dropped on another thread, the buffer is
+/// reclaimed the next time the thread that received the message polls for completions.
+pub struct RRefView<U: ?Sized> {
+    ptr: NonNull<U>,
+    owner: Arc<dyn Owner + Send + Sync>,
+}
+
+// SAFETY: a view is a shared reference to a part of the message, and its owner is Send + Sync.
+unsafe impl<U: ?Sized + Sync> Send for RRefView<U> {}
+unsafe impl<U: ?Sized + Sync> Sync for RRefView<U> {}
+
+impl<U: ?Sized> RRefView<U> {
+    #[inline]
+    fn new(ptr: NonNull<U>, owner: Arc<dyn Owner + Send + Sync>) -> Self {
+        RRefView { ptr, owner }
+    }
+
+    /// Returns a view of a part of this view.
+    #[must_use]
+    #[inline]
+    pub fn project<V: ?Sized, F: FnOnce(&U) -> &V>(&self, f: F) -> RRefView<V> {
+        RRefView {
+            ptr: NonNull::from(f(self.as_ref())),
+            owner: Arc::clone(&self.owner),
+        }
+    }
+
+    /// Returns a view of a part of this view, or `None` if `f` returns `None`.
+    #[must_use]
+    #[inline]
+    pub fn try_project<V: ?Sized, F: FnOnce(&U) -> Option<&V>>(&self, f: F) -> Option<RRefView<V>> {
+        f(self.as_ref()).map(|part| RRefView {
+            ptr: NonNull::from(part),
+            owner: Arc::clone(&self.owner),
+        })
+    }
+
+    /// Returns the user associated token of the message.
+    #[must_use]
+    #[inline]
+    pub fn token(&self) -> Token {
+        self.owner.token()
+    }
+
+    /// Returns the application correlation ID of the message, see [`RRef::correlation_id`].
+    #[must_use]
+    #[inline]
+    pub fn correlation_id(&self) -> u64 {
+        self.owner.correlation_id()
+    }
+}
+
+impl<U: ?Sized> Clone for RRefView<U> {
+    fn clone(&self) -> Self {
+        RRefView {
+            ptr: self.ptr,
+            owner: Arc::clone(&self.owner),
+        }
+    }
+}
+
+impl<U: ?Sized> Deref for RRefView<U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_ref()
+    }
+}
+
+impl<U: ?Sized> AsRef<U> for RRefView<U> {
+    fn as_ref(&self) -> &U {
+        // SAFETY: ptr points into the message, which is kept alive by owner.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<U: ?Sized + PartialEq> PartialEq for RRefView<U> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        PartialEq::eq(&**self, &**other)
+    }
+}
+
+impl<U: ?Sized + Eq> Eq for RRefView<U> {}
+
+impl<U: ?Sized + fmt::Debug> fmt::Debug for RRefView<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<U: ?Sized + fmt::Display> fmt::Display for RRefView<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<U: ?Sized + Hash> Hash for RRefView<U> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Hash::hash(&**self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// A message that records the thread it is dropped on.
+    struct Probe {
+        data: Vec<u32>,
+        dropped_on: Arc<Mutex<Option<ThreadId>>>,
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            *self.dropped_on.lock().unwrap() = Some(thread::current().id());
+        }
+    }
+
+    impl Owner for Probe {
+        fn token(&self) -> Token {
+            Token(7)
+        }
+
+        fn correlation_id(&self) -> u64 {
+            42
+        }
+    }
+
+    fn view_of(
+        data: Vec<u32>,
+        released: &Arc<Released>,
+    ) -> (RRefView<[u32]>, Arc<Mutex<Option<ThreadId>>>) {
+        let dropped_on = Arc::new(Mutex::new(None));
+        let probe = Probe {
+            data,
+            dropped_on: Arc::clone(&dropped_on),
+        };
+        let ptr = NonNull::from(probe.data.as_slice());
+        let owner = Arc::new(ViewOwner::new(probe, Arc::clone(released)));
+        (RRefView::new(ptr, owner), dropped_on)
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn view_is_send_and_sync() {
+        assert_send_sync::<RRefView<[u32]>>();
+        assert_send_sync::<RRefView<str>>();
+    }
+
+    #[test]
+    fn view_keeps_message_alive() {
+        let released = Arc::default();
+        let (view, dropped_on) = view_of(vec![1, 2, 3], &released);
+        let second = view.project(|s| &s[1]);
+        let copy = second.clone();
+        assert!(view.try_project(|s| s.get(3)).is_none());
+        assert_eq!(second.token(), Token(7));
+        assert_eq!(second.correlation_id(), 42);
+
+        drop(view);
+        assert_eq!(*second, 2);
+        drop(second);
+        assert_eq!(*copy, 2);
+        assert!(dropped_on.lock().unwrap().is_none());
+
+        drop(copy);
+        assert_eq!(*dropped_on.lock().unwrap(), Some(thread::current().id()));
+    }
+
+    #[test]
+    fn view_released_on_receiving_thread() {
+        let released: Arc<Released> = Arc::default();
+        let (view, dropped_on) = view_of(vec![1, 2, 3], &released);
+        let sum = thread::spawn(move || view.iter().sum::<u32>())
+            .join()
+            .unwrap();
+        assert_eq!(sum, 6);
+        // the view is dropped on the other thread, the message waits for this thread
+        assert!(dropped_on.lock().unwrap().is_none());
+
+        released.drop_all();
+        assert_eq!(*dropped_on.lock().unwrap(), Some(thread::current().id()));
+    }
+}
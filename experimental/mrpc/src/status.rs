@@ -5,6 +5,8 @@
 //! [1]: https://github.com/hyperium/tonic/blob/master/tonic/src/status.rs
 use std::error::Error;
 use std::fmt;
+use std::io;
+use std::num::NonZeroU32;
 
 use phoenix_api::rpc::TransportStatus;
 
@@ -27,6 +29,151 @@ pub struct Status {
     message: String,
     /// Optional underlying error.
     source: Option<Box<dyn Error + Send + Sync + 'static>>,
+    /// The failure of the transport behind the status, if any.
+    transport: Option<TransportCode>,
+}
+
+/// The failure of the transport that a [`Status`] comes from.
+///
+/// It is attached to the calls that fail before the handler of the server answers them, so that
+/// the application can tell, e.g., a connection reset by the peer from a message lost in transit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransportCode {
+    /// A work completion of RDMA fails with this `ibv_wc_status`.
+    WorkCompletion(u32),
+    /// A socket operation of TCP fails with this errno.
+    Errno(i32),
+    /// mRPC or a policy fails the message with this status, e.g., 422 when the checksum of the
+    /// payload mismatches, or 503 when the connection is lost. See the constants of
+    /// `phoenix_api::rpc::TransportStatus`.
+    Rpc(u32),
+    /// The shared memory queues between the application and the backend fail.
+    SharedMemory,
+}
+
+impl TransportCode {
+    fn new(code: NonZeroU32) -> Self {
+        match code.get() {
+            code if code > TransportStatus::ERRNO_BASE => {
+                TransportCode::Errno((code - TransportStatus::ERRNO_BASE) as i32)
+            }
+            code @ 400..=599 => TransportCode::Rpc(code),
+            code => TransportCode::WorkCompletion(code),
+        }
+    }
+
+    /// The code of the [`Status`] for this failure, and what happened.
+    fn describe(self) -> (Code, String) {
+        match self {
+            TransportCode::WorkCompletion(status) => describe_wc_status(status),
+            TransportCode::Errno(errno) => {
+                let code = match errno {
+                    libc::ECONNRESET
+                    | libc::ECONNREFUSED
+                    | libc::ENOTCONN
+                    | libc::EPIPE
+                    | libc::ETIMEDOUT
+                    | libc::EHOSTDOWN
+                    | libc::EHOSTUNREACH
+                    | libc::ENETDOWN
+                    | libc::ENETUNREACH => Code::Unavailable,
+                    libc::ECONNABORTED => Code::Aborted,
+                    libc::ENOBUFS | libc::ENOMEM => Code::ResourceExhausted,
+                    _ => Code::Internal,
+                };
+                let err = io::Error::from_raw_os_error(errno);
+                (code, format!("Socket error: {err}"))
+            }
+            TransportCode::Rpc(status) => describe_rpc_status(status),
+            TransportCode::SharedMemory => (
+                Code::Internal,
+                "Shared memory queue to the backend failed".to_owned(),
+            ),
+        }
+    }
+}
+
+// The statuses of the work completions of RDMA, `ibv_wc_status`.
+const IBV_WC_LOC_LEN_ERR: u32 = 1;
+const IBV_WC_WR_FLUSH_ERR: u32 = 5;
+const IBV_WC_BAD_RESP_ERR: u32 = 7;
+const IBV_WC_REM_OP_ERR: u32 = 11;
+const IBV_WC_RETRY_EXC_ERR: u32 = 12;
+const IBV_WC_RNR_RETRY_EXC_ERR: u32 = 13;
+const IBV_WC_REM_ABORT_ERR: u32 = 16;
+const IBV_WC_RESP_TIMEOUT_ERR: u32 = 20;
+
+fn describe_wc_status(status: u32) -> (Code, String) {
+    let (code, msg) = match status {
+        IBV_WC_WR_FLUSH_ERR => (Code::Unavailable, "flushed as the connection goes down"),
+        IBV_WC_RETRY_EXC_ERR => (Code::Unavailable, "no ack from the peer after retries"),
+        IBV_WC_RNR_RETRY_EXC_ERR => (Code::Unavailable, "the peer is not ready to receive"),
+        IBV_WC_RESP_TIMEOUT_ERR => (Code::Unavailable, "no response from the peer in time"),
+        IBV_WC_REM_OP_ERR | IBV_WC_REM_ABORT_ERR => (Code::Aborted, "the peer fails the operation"),
+        IBV_WC_LOC_LEN_ERR => (Code::DataLoss, "the message exceeds the receive buffer"),
+        IBV_WC_BAD_RESP_ERR => (Code::DataLoss, "unexpected response from the peer"),
+        _ => (Code::Internal, "local or protection error"),
+    };
+    (code, format!("Work completion error {status}: {msg}"))
+}
+
+fn describe_rpc_status(status: u32) -> (Code, String) {
+    let (code, msg) = match status {
+        400 => (
+            Code::Internal,
+            "The backend cannot marshal or unmarshal the message",
+        ),
+        402 | 403 => (Code::PermissionDenied, "Access denied by the ACL policy"),
+        408 => (
+            Code::DeadlineExceeded,
+            "Deadline passed while queued in the backend",
+        ),
+        413 => (
+            Code::ResourceExhausted,
+            "Message exceeds the largest message the peer accepts",
+        ),
+        415 => (
+            Code::DataLoss,
+            "The server cannot decode the request in the protobuf wire format",
+        ),
+        421 => (
+            Code::Unavailable,
+            "The connection has moved to another thread of the server",
+        ),
+        422 => (
+            Code::DataLoss,
+            "Message corrupted in transit, checksum mismatch",
+        ),
+        500 => (
+            Code::Internal,
+            "The dispatch library of the backend panicked",
+        ),
+        501 => (
+            Code::Unimplemented,
+            "The server implements no such service or method",
+        ),
+        502 => (Code::DataLoss, "Failed to read the payload sent in bulk"),
+        503 => (
+            Code::Unavailable,
+            "Connection lost before the reply arrives",
+        ),
+        504 => (
+            Code::DeadlineExceeded,
+            "No reply after retransmitting the request",
+        ),
+        505 => (
+            Code::FailedPrecondition,
+            "The peer sent settings that are not understood",
+        ),
+        507 => (
+            Code::ResourceExhausted,
+            "No buffer to gather the fragments of the message",
+        ),
+        510 => (Code::Unknown, "The handler of the server failed the call"),
+        520 => (Code::Internal, "The transport of the backend failed"),
+        _ => return (Code::Unknown, format!("Transport error {status}")),
+    };
+    (code, msg.to_owned())
 }
 
 /// gRPC status codes used by [`Status`].
@@ -143,6 +290,7 @@ impl Status {
             code,
             message: message.into(),
             source: None,
+            transport: None,
         }
     }
 
@@ -309,23 +457,19 @@ impl Status {
         Err(err)
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn from_incoming_transport(transport_status: TransportStatus) -> Status {
+    /// Creates the `Status` of a call that fails in the transport.
+    pub(crate) fn from_transport(transport_status: TransportStatus) -> Status {
         match transport_status {
             TransportStatus::Success => Status::ok(""),
-            TransportStatus::Error(code) => match code.get() {
-                402 => Status::permission_denied("Access Denied from server ACL engine"),
-                _ => Status::data_loss(format!("receiving wc error: {code}")),
-            },
+            TransportStatus::Error(code) => Status::with_transport(TransportCode::new(code)),
         }
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn from_outgoing_transport(transport_status: TransportStatus) -> Status {
-        match transport_status {
-            TransportStatus::Success => Status::ok(""),
-            TransportStatus::Error(code) => Status::internal(format!("sending wc error: {code}")),
-        }
+    fn with_transport(transport: TransportCode) -> Status {
+        let (code, message) = transport.describe();
+        let mut status = Status::new(code, message);
+        status.transport = Some(transport);
+        status
     }
 
     #[allow(dead_code)]
@@ -346,6 +490,11 @@ impl Status {
     pub fn message(&self) -> &str {
         &self.message
     }
+
+    /// Get the failure of the transport behind this `Status`, if the call fails in the transport.
+    pub fn transport_code(&self) -> Option<TransportCode> {
+        self.transport
+    }
 }
 
 fn find_status_in_source_chain(err: &(dyn Error + 'static)) -> Option<Status> {
@@ -359,6 +508,7 @@ fn find_status_in_source_chain(err: &(dyn Error + 'static)) -> Option<Status> {
                 // Since `Status` is not `Clone`, any `source` on the original Status
                 // cannot be cloned so must remain with the original `Status`.
                 source: None,
+                transport: status.transport,
             });
         }
 
@@ -381,47 +531,68 @@ impl fmt::Debug for Status {
 
         builder.field("source", &self.source);
 
+        if let Some(transport) = self.transport {
+            builder.field("transport", &transport);
+        }
+
         builder.finish()
     }
 }
 
 impl From<std::io::Error> for Status {
     fn from(err: std::io::Error) -> Self {
-        use std::io::ErrorKind;
-        let code = match err.kind() {
-            ErrorKind::BrokenPipe
-            | ErrorKind::WouldBlock
-            | ErrorKind::WriteZero
-            | ErrorKind::Interrupted => Code::Internal,
-            ErrorKind::ConnectionRefused
-            | ErrorKind::ConnectionReset
-            | ErrorKind::NotConnected
-            | ErrorKind::AddrInUse
-            | ErrorKind::AddrNotAvailable => Code::Unavailable,
-            ErrorKind::AlreadyExists => Code::AlreadyExists,
-            ErrorKind::ConnectionAborted => Code::Aborted,
-            ErrorKind::InvalidData => Code::DataLoss,
-            ErrorKind::InvalidInput => Code::InvalidArgument,
-            ErrorKind::NotFound => Code::NotFound,
-            ErrorKind::PermissionDenied => Code::PermissionDenied,
-            ErrorKind::TimedOut => Code::DeadlineExceeded,
-            ErrorKind::UnexpectedEof => Code::OutOfRange,
-            _ => Code::Unknown,
-        };
-        Status::new(code, err.to_string())
+        Status::new(io_code(&err), err.to_string())
+    }
+}
+
+fn io_code(err: &io::Error) -> Code {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::BrokenPipe
+        | ErrorKind::WouldBlock
+        | ErrorKind::WriteZero
+        | ErrorKind::Interrupted => Code::Internal,
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::NotConnected
+        | ErrorKind::AddrInUse
+        | ErrorKind::AddrNotAvailable => Code::Unavailable,
+        ErrorKind::AlreadyExists => Code::AlreadyExists,
+        ErrorKind::ConnectionAborted => Code::Aborted,
+        ErrorKind::InvalidData => Code::DataLoss,
+        ErrorKind::InvalidInput => Code::InvalidArgument,
+        ErrorKind::NotFound => Code::NotFound,
+        ErrorKind::PermissionDenied => Code::PermissionDenied,
+        ErrorKind::TimedOut => Code::DeadlineExceeded,
+        ErrorKind::UnexpectedEof => Code::OutOfRange,
+        _ => Code::Unknown,
     }
 }
 
 impl From<crate::Error> for Status {
     fn from(err: crate::Error) -> Self {
         use crate::Error::*;
-        // TODO(cjr): This mapping doesn't make sense at all.
-        let code = match err {
-            Service(..) | Interface(..) | Io(..) => Code::Internal,
+        let code = match &err {
+            Service(ipc::Error::ShmIpc(_) | ipc::Error::ShmRingbuf(_)) => {
+                let mut status = Status::new(Code::Internal, err.to_string());
+                status.transport = Some(TransportCode::SharedMemory);
+                return status;
+            }
+            // the backend has gone
+            Service(
+                ipc::Error::IpcRecv(ipc::IpcRecvError::Disconnected)
+                | ipc::Error::TryRecv(ipc::TryRecvError::Disconnected),
+            ) => Code::Unavailable,
+            Service(ipc::Error::Io(e)) | Io(e) => io_code(e),
+            // the daemon has reached a limit on its clients
+            Service(ipc::Error::ControlPlane(_, phoenix_api::Error::Rejected(_))) => {
+                Code::ResourceExhausted
+            }
+            Service(..) | Interface(..) | SharedHeap(..) | DispatcherExited => Code::Internal,
             Serde(..) => Code::InvalidArgument,
             NoAddrResolved => Code::NotFound,
-            Connect(..) => Code::Unavailable,
-            ConnectionClosed => Code::Cancelled,
+            Connect(..) | ConnectionClosed | NoEndpoint(..) => Code::Unavailable,
+            Forked | IdCollision(..) => Code::FailedPrecondition,
         };
         Status::new(code, err.to_string())
     }
@@ -613,4 +784,74 @@ mod tests {
         assert_eq!(Status::data_loss("").code(), Code::DataLoss);
         assert_eq!(Status::unauthenticated("").code(), Code::Unauthenticated);
     }
+
+    #[test]
+    fn expired_in_queue() {
+        let status = Status::from_transport(TransportStatus::DEADLINE_EXCEEDED);
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+    }
+
+    #[test]
+    fn unknown_method() {
+        let status = Status::from_transport(TransportStatus::UNIMPLEMENTED);
+        assert_eq!(status.code(), Code::Unimplemented);
+    }
+
+    #[test]
+    fn error_replies() {
+        let status = Status::from_transport(TransportStatus::UNDECODABLE);
+        assert_eq!(status.code(), Code::DataLoss);
+        let status = Status::from_transport(TransportStatus::HANDLER_FAILED);
+        assert_eq!(status.code(), Code::Unknown);
+        assert_eq!(status.transport_code(), Some(TransportCode::Rpc(510)));
+    }
+
+    #[test]
+    fn dispatch_failed() {
+        let status = Status::from_transport(TransportStatus::DISPATCH_PANICKED);
+        assert_eq!(status.code(), Code::Internal);
+        assert!(status.message().contains("panicked"));
+        assert_eq!(status.transport_code(), Some(TransportCode::Rpc(500)));
+    }
+
+    #[test]
+    fn transport_failures() {
+        let wc_status = |code| TransportStatus::Error(NonZeroU32::new(code).unwrap());
+        let status = Status::from_transport(wc_status(IBV_WC_RETRY_EXC_ERR));
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            status.transport_code(),
+            Some(TransportCode::WorkCompletion(IBV_WC_RETRY_EXC_ERR))
+        );
+        let status = Status::from_transport(wc_status(IBV_WC_REM_ABORT_ERR));
+        assert_eq!(status.code(), Code::Aborted);
+
+        let status = Status::from_transport(TransportStatus::from_errno(libc::ECONNRESET));
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            status.transport_code(),
+            Some(TransportCode::Errno(libc::ECONNRESET))
+        );
+
+        let status = Status::from_transport(TransportStatus::CHECKSUM_MISMATCH);
+        assert_eq!(status.code(), Code::DataLoss);
+        let status = Status::from_transport(TransportStatus::CONNECTION_LOST);
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[test]
+    fn transport_code_kept_in_source_chain() {
+        let orig = Status::from_transport(TransportStatus::CONNECTION_LOST);
+        let found = Status::from_error(Box::new(Nested(Box::new(orig))));
+        assert_eq!(found.transport_code(), Some(TransportCode::Rpc(503)));
+    }
+
+    #[test]
+    fn rejected_client() {
+        let rejection = phoenix_api::error::Rejection::MaxClients { max: 64 };
+        let err = ipc::Error::ControlPlane("NewClient", phoenix_api::Error::Rejected(rejection));
+        let status = Status::from(crate::Error::Service(err));
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.message().contains("64 client processes"));
+    }
 }
@@ -1,5 +1,6 @@
 //! Client implementation.
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::future::Future;
 use std::hash::Hash;
 use std::marker::PhantomData;
@@ -7,19 +8,30 @@ use std::net::{SocketAddr, ToSocketAddrs};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
+use futures::future::Either;
+use futures::Stream;
 use ipc::channel::{Receiver, TryRecvError};
-use phoenix_api::rpc::{CallId, MessageErased, MessageMeta, RpcId, RpcMsgType, TransportStatus};
+use phoenix_api::rpc::{
+    monotonic_ns, CallId, ConnectionState, MessageErased, MessageMeta, RpcId, RpcMsgType,
+    TransportStatus,
+};
 use phoenix_api::{AsHandle, Handle};
-use phoenix_api_mrpc::cmd::{Command, CompletionKind};
+use phoenix_api_mrpc::cmd::{Command, CompletionKind, ConnectOptions};
 use phoenix_api_mrpc::dp;
 use phoenix_syscalls::_rx_recv_impl as rx_recv_impl;
 
 use super::conn::Connection;
+use super::raw::RawMessage;
+use super::reconnect::ReconnectPolicy;
 use super::reply_cache::ReplyCache;
+use super::response_cache::{CacheStats, ResponseCache};
+use super::retry::{RetryPolicy, DEFAULT_PUSHBACK_DELAY};
 use super::RpcData;
 use super::LOCAL_REACTOR;
-use crate::{Error, RRef, ReadHeap, Status, WRef, MRPC_CTX};
+use crate::compat::FieldTags;
+use crate::{fork, Error, RRef, ReadHeap, Status, WRef, WRefOpaque, MRPC_CTX};
 
 #[cfg(feature = "timing")]
 use crate::timing::{SampleKind, Timer};
@@ -31,9 +43,14 @@ thread_local! {
 
 /// Future that represents an ongoing RPC. Resolves to a read-only [`RRef<T>`] on success.
 /// Resolves to a [`Status`] on failure.
+///
+/// Dropping the future before it resolves cancels the call, its reply is discarded when it
+/// arrives.
 pub struct ReqFuture<'a, T> {
     rpc_id: RpcId,
     client: &'a ClientStub,
+    // whether the reply has been taken
+    done: bool,
     _marker: PhantomData<T>,
 }
 
@@ -43,35 +60,14 @@ impl<'a, T: Unpin> Future for ReqFuture<'a, T> {
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
 
+        fork::check(this.client.generation)?;
         futures::ready!(LOCAL_REACTOR.with_borrow_mut(|r| r.poll(cx)))?;
 
         this.client.dispatch()?;
-        // let inner = this.client.inner.borrow();
-        let inner = this.client.inner.lock();
 
         // Poll::Pending
-        if let Some(reply) = inner
-            .reply_cache
-            .get(this.rpc_id.1)
-            .expect("Expect an entry")
-        {
-            let ret = match reply {
-                Ok(reply) => {
-                    tracing::trace!(
-                        "ReqFuture receive reply from mRPC engine, rpc_id={:?}",
-                        this.rpc_id
-                    );
-                    let read_heap = this
-                        .client
-                        .conns
-                        .get(&reply.meta.conn_id)
-                        .unwrap()
-                        .map_alive(|alive| Arc::clone(&alive.read_heap))
-                        .expect("TODO: return an error when connection is dead rather than panic");
-                    Ok(RRef::new(reply, read_heap))
-                }
-                Err(status) => Err(Status::from_incoming_transport(*status)),
-            };
+        if let Some(ret) = this.client.take_reply(this.rpc_id) {
+            this.done = true;
             return Poll::Ready(ret);
         }
 
@@ -80,6 +76,177 @@ impl<'a, T: Unpin> Future for ReqFuture<'a, T> {
     }
 }
 
+impl<'a, T> Drop for ReqFuture<'a, T> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.client.abandon(self.rpc_id);
+        }
+    }
+}
+
+/// Stream of the replies of a broadcast RPC, see [`ClientStub::broadcast`]. Yields the result
+/// of each target once, in the order the replies arrive.
+///
+/// Dropping the stream cancels the calls of the targets that have not got a reply, their replies
+/// are discarded when they arrive.
+pub struct BroadcastStream<'a, T> {
+    // targets that have not got a reply
+    pending: Vec<RpcId>,
+    // targets that failed before the request is sent
+    failed: VecDeque<(Handle, Status)>,
+    client: &'a ClientStub,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T> BroadcastStream<'a, T> {
+    fn fail_pending(&mut self, err: Error) {
+        let status = Status::from(err);
+        for rpc_id in self.pending.drain(..) {
+            self.client.abandon(rpc_id);
+            let status = Status::new(status.code(), status.message());
+            self.failed.push_back((rpc_id.0, status));
+        }
+    }
+}
+
+impl<'a, T> Drop for BroadcastStream<'a, T> {
+    fn drop(&mut self) {
+        for rpc_id in self.pending.drain(..) {
+            self.client.abandon(rpc_id);
+        }
+    }
+}
+
+impl<'a, T: Unpin> Stream for BroadcastStream<'a, T> {
+    type Item = (Handle, Result<RRef<T>, Status>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some((conn_id, status)) = this.failed.pop_front() {
+            return Poll::Ready(Some((conn_id, Err(status))));
+        }
+        if this.pending.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        let polled = fork::check(this.client.generation).and_then(|_| {
+            match LOCAL_REACTOR.with_borrow_mut(|r| r.poll(cx)) {
+                Poll::Ready(Err(e)) => Err(e),
+                _ => this.client.dispatch(),
+            }
+        });
+        if let Err(e) = polled {
+            this.fail_pending(e);
+            let (conn_id, status) = this.failed.pop_front().unwrap();
+            return Poll::Ready(Some((conn_id, Err(status))));
+        }
+
+        for i in 0..this.pending.len() {
+            let rpc_id = this.pending[i];
+            if let Some(ret) = this.client.take_reply(rpc_id) {
+                this.pending.swap_remove(i);
+                return Poll::Ready(Some((rpc_id.0, ret)));
+            }
+        }
+
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.pending.len() + self.failed.len();
+        (remaining, Some(remaining))
+    }
+}
+
+/// A snapshot of a connection of a [`ClientStub`], see [`ClientStub::channel_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStatus {
+    /// The connection handle.
+    pub conn_id: Handle,
+    /// Requests posted to the backend that have not finished sending.
+    pub tx_queue_depth: usize,
+    /// Messages that can still be sent before the remote end runs out of receive buffers. `None`
+    /// if the transport does not use credits, or the connection is the virtual connection of
+    /// multiple connections.
+    pub credits: Option<usize>,
+    /// Calls that have not got a reply.
+    pub outstanding_calls: usize,
+}
+
+/// Where the time of a call goes, see [`ClientStub::set_call_timing`] and [`RRef::timing`].
+///
+/// The timestamps are in nanoseconds of `CLOCK_MONOTONIC`, which the application and the phoenix
+/// daemon on a host share. The timestamps of the daemon are `None` if the transport does not
+/// take them, e.g., the TCP transport.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallTiming {
+    /// When the request is posted to the daemon.
+    pub enqueued: u64,
+    /// When the daemon sends the request.
+    pub sent: Option<u64>,
+    /// When the daemon receives the first segment of the reply.
+    pub first_recv: Option<u64>,
+    /// When the reply is delivered to the application.
+    pub delivered: u64,
+}
+
+impl CallTiming {
+    /// The whole time of the call.
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.delivered.saturating_sub(self.enqueued))
+    }
+
+    /// The time from posting the request to sending it, spent in the daemon on the way out.
+    pub fn outbound(&self) -> Option<Duration> {
+        self.sent
+            .map(|sent| Duration::from_nanos(sent.saturating_sub(self.enqueued)))
+    }
+
+    /// The time from sending the request to receiving the reply, spent in the network and the
+    /// server.
+    pub fn remote(&self) -> Option<Duration> {
+        Some(Duration::from_nanos(
+            self.first_recv?.saturating_sub(self.sent?),
+        ))
+    }
+
+    /// The time from receiving the reply to delivering it, spent in the daemon on the way back
+    /// and in waiting for the application to poll.
+    pub fn inbound(&self) -> Option<Duration> {
+        self.first_recv
+            .map(|first_recv| Duration::from_nanos(self.delivered.saturating_sub(first_recv)))
+    }
+}
+
+/// The connections warmed up to a target, see [`ClientStub::warm_up`].
+#[derive(Debug)]
+pub struct WarmUp<C = ClientStub> {
+    /// The address of the target.
+    pub addr: SocketAddr,
+    /// The clients of the connections that are established and healthy, one per connection.
+    pub clients: Vec<C>,
+    /// Why the other connections are not.
+    pub errors: Vec<Error>,
+}
+
+impl<C> WarmUp<C> {
+    /// Returns whether all the connections to the target are ready.
+    pub fn is_ready(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Wraps the clients, e.g., into the generated clients of a service.
+    pub fn map<D, F: FnMut(C) -> D>(self, f: F) -> WarmUp<D> {
+        WarmUp {
+            addr: self.addr,
+            clients: self.clients.into_iter().map(f).collect(),
+            errors: self.errors,
+        }
+    }
+}
+
 impl !Send for ClientStub {}
 impl !Sync for ClientStub {}
 
@@ -88,12 +255,24 @@ impl !Sync for ClientStub {}
 /// [`mrpc-build`]: ../../../doc/mrpc_build/index.html
 #[derive(Debug)]
 pub struct ClientStub {
-    vconn: Connection,
+    vconn: RefCell<Connection>,
     // A connection could go into error state, in that case, all subsequent operations over this
     // connection would return an error.
-    conns: HashMap<Handle, Connection>,
+    conns: RefCell<HashMap<Handle, Connection>>,
+    // The address to reconnect to. Only set for stubs with a single connection whose peer
+    // address is known.
+    addr: Option<SocketAddr>,
+    // Whether the connection goes over unreliable datagrams.
+    datagram: bool,
+    // The settings of the local side of the connection, to reconnect with.
+    options: ConnectOptions,
+    stub_id: usize,
     // inner: RefCell<Inner>,
     inner: spin::Mutex<Inner>,
+    // The replies of the cacheable methods.
+    responses: RefCell<ResponseCache>,
+    // The fork generation that the stub is created in.
+    generation: usize,
 }
 
 #[derive(Debug)]
@@ -103,6 +282,114 @@ struct Inner {
     // Reply cache records whether a reply has been received for RPC client.  Each reply cache
     // should be assoicated to a connection.
     reply_cache: ReplyCache,
+    // The latest health state of the connection reported by the transport.
+    state: ConnectionState,
+    // Reconnect on connection loss if set.
+    reconnect: Option<ReconnectPolicy>,
+    // The next reconnection attempt and when it is due, while the connection is lost.
+    next_attempt: Option<(u32, Instant)>,
+    // Retransmit requests that get no reply in time if set.
+    retry: Option<RetryPolicy>,
+    // Attach a request ID to each call if set.
+    exactly_once: bool,
+    // Send the requests of the compat methods in the protobuf wire format if set.
+    compat: bool,
+    // Requests that have not got a reply, tracked only when reconnect or retry is enabled.
+    in_flight: HashMap<CallId, (MessageErased, WRefOpaque)>,
+    // The sends of each request in flight, tracked only when retry is enabled.
+    attempts: HashMap<CallId, Attempt>,
+    // The number of calls that have not got a reply on each connection.
+    outstanding: HashMap<Handle, usize>,
+    // The timing of the calls that have not been delivered, tracked only when call timing is
+    // enabled.
+    timing: Option<HashMap<CallId, CallTiming>>,
+    // Posted requests that have not been acknowledged by the backend.
+    posts: HashSet<CallId>,
+    // How long a request may wait in the send queue of the backend if set.
+    queue_timeout: Option<Duration>,
+    // Until when to slow down, as asked by the server in its latest flagged reply.
+    pushed_back: Option<Instant>,
+}
+
+impl Inner {
+    fn new(receiver: Receiver<dp::Completion>) -> Self {
+        Inner {
+            receiver,
+            reply_cache: ReplyCache::new(),
+            state: ConnectionState::Connected,
+            reconnect: None,
+            next_attempt: None,
+            retry: None,
+            exactly_once: false,
+            compat: false,
+            in_flight: HashMap::new(),
+            attempts: HashMap::new(),
+            outstanding: HashMap::new(),
+            timing: None,
+            posts: HashSet::new(),
+            queue_timeout: None,
+            pushed_back: None,
+        }
+    }
+
+    /// Records a pushback of the server received `now`.
+    fn push_back(&mut self, now: Instant) {
+        let until = match self.retry.as_ref() {
+            Some(policy) => policy.pushback_until(now),
+            None => now + DEFAULT_PUSHBACK_DELAY,
+        };
+        self.pushed_back = Some(until);
+    }
+
+    /// Returns the deadline of a request sent now to leave the send queue of the backend, or 0.
+    fn queue_deadline(&self) -> u64 {
+        self.queue_timeout
+            .map_or(0, |timeout| monotonic_ns() + timeout.as_nanos() as u64)
+    }
+
+    /// Returns the deadline of a request sent now with its own `deadline`, the earlier of it and
+    /// the queue timeout, or 0.
+    fn call_deadline(&self, deadline: Option<Instant>) -> u64 {
+        let queue_deadline = self.queue_deadline();
+        match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                let deadline = monotonic_ns() + left.as_nanos() as u64;
+                if queue_deadline == 0 {
+                    deadline
+                } else {
+                    deadline.min(queue_deadline)
+                }
+            }
+            None => queue_deadline,
+        }
+    }
+
+    fn call_started(&mut self, conn_id: Handle) {
+        *self.outstanding.entry(conn_id).or_insert(0) += 1;
+    }
+
+    fn call_finished(&mut self, conn_id: Handle) {
+        if let Some(n) = self.outstanding.get_mut(&conn_id) {
+            *n = n.saturating_sub(1);
+        }
+    }
+
+    /// Returns true if the call `call_id` has already been resolved, e.g., the reply is a
+    /// duplicate of a retransmitted request.
+    fn is_resolved(&self, call_id: CallId) -> bool {
+        matches!(self.reply_cache.get(call_id), Ok(Some(_)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Attempt {
+    // When to retransmit the request if no reply has arrived.
+    deadline: Instant,
+    // The number of times the request has been sent.
+    sent: u32,
+    // Whether the backend has acknowledged the latest send, so the message can be posted again.
+    acked: bool,
 }
 
 impl ClientStub {
@@ -118,7 +405,81 @@ impl ClientStub {
         Req: RpcData,
         Res: Unpin + RpcData,
     {
-        let conn_id = self.master_conn().handle();
+        self.call(service_id, func_id, call_id, req, 0)
+    }
+
+    /// Issue a unary RPC request of a method generated with `compat`. The request is sent in the
+    /// protobuf wire format and the reply decoded if compat calls are enabled by
+    /// [`ClientStub::set_compat`], otherwise the call is the same as [`ClientStub::unary`].
+    pub fn unary_compat<Req, Res>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        call_id: CallId,
+        req: WRef<Req>,
+    ) -> impl Future<Output = Result<RRef<Res>, Status>> + '_
+    where
+        Req: RpcData + prost::Message,
+        Res: Unpin + RpcData + prost::Message + Default + FieldTags,
+    {
+        // the request is sent here, the future only waits for the reply
+        let call = if self.inner.lock().compat {
+            let flags = MessageMeta::RAW | MessageMeta::COMPAT;
+            let req = crate::compat::encode(&req);
+            Either::Left(self.call::<_, RawMessage>(service_id, func_id, call_id, req, flags))
+        } else {
+            Either::Right(self.call(service_id, func_id, call_id, req, 0))
+        };
+        async move {
+            match call {
+                Either::Left(call) => RRef::decode(call.await?),
+                Either::Right(call) => call.await,
+            }
+        }
+    }
+
+    /// Issue a raw call of the method `func_id` of `service_id`, served by a [`RawService`] added
+    /// by [`LocalServer::add_raw_service`]. The request is a byte string of the application,
+    /// sent without the protos of the service. The `payload` is copied to the shared heap, use
+    /// [`ClientStub::raw_call_wref`] to send a [`RawMessage`] already there.
+    ///
+    /// [`RawService`]: super::RawService
+    /// [`LocalServer::add_raw_service`]: super::LocalServer::add_raw_service
+    pub fn raw_call(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        payload: &[u8],
+    ) -> impl Future<Output = Result<RRef<RawMessage>, Status>> + '_ {
+        let mut req = RawMessage::with_capacity(payload.len());
+        req.extend_from_slice(payload);
+        self.raw_call_wref(service_id, func_id, WRef::new(req))
+    }
+
+    /// Issue a raw call with a request on the shared heap, see [`ClientStub::raw_call`].
+    pub fn raw_call_wref(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        req: WRef<RawMessage>,
+    ) -> impl Future<Output = Result<RRef<RawMessage>, Status>> + '_ {
+        let call_id = self.initiate_call();
+        self.call(service_id, func_id, call_id, req, MessageMeta::RAW)
+    }
+
+    fn call<Req, Res>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        call_id: CallId,
+        req: WRef<Req>,
+        flags: u32,
+    ) -> ReqFuture<'_, Res>
+    where
+        Req: RpcData,
+        Res: Unpin + RpcData,
+    {
+        let conn_id = self.with_master_conn(|conn| conn.handle());
 
         // construct meta
         let meta = MessageMeta {
@@ -127,17 +488,308 @@ impl ClientStub {
             func_id,
             call_id,
             token: req.token().0 as u64,
+            correlation_id: req.correlation_id(),
+            request_id: self.request_id(),
             msg_type: RpcMsgType::Request,
             status_code: phoenix_api::rpc::StatusCode::Success,
+            flags,
         };
 
-        self.post_request(req, meta).unwrap();
+        // The call of an inherited stub fails when the future is polled.
+        if fork::check(self.generation).is_ok() {
+            self.post_request(req, meta).unwrap();
+        }
 
         ReqFuture {
             rpc_id: RpcId(conn_id, call_id),
             client: self,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Issue a unary RPC request of a cacheable method. The call is answered from the response
+    /// cache if a reply to the same `key`, the encoded request, has arrived within `ttl`.
+    /// Otherwise the request is sent and a successful reply is cached.
+    pub fn unary_cached<Req, Res>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        key: Vec<u8>,
+        ttl: Duration,
+        req: WRef<Req>,
+    ) -> impl Future<Output = Result<RRef<Res>, Status>> + '_
+    where
+        Req: RpcData,
+        Res: Unpin + RpcData,
+    {
+        self.cached(func_id, key, ttl, || {
+            self.unary(service_id, func_id, self.initiate_call(), req)
+        })
+    }
+
+    /// Issue a unary RPC request of a cacheable method generated with `compat`, see
+    /// [`ClientStub::unary_cached`] and [`ClientStub::unary_compat`]. The replies are cached
+    /// once decoded.
+    pub fn unary_cached_compat<Req, Res>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        key: Vec<u8>,
+        ttl: Duration,
+        req: WRef<Req>,
+    ) -> impl Future<Output = Result<RRef<Res>, Status>> + '_
+    where
+        Req: RpcData + prost::Message,
+        Res: Unpin + RpcData + prost::Message + Default + FieldTags,
+    {
+        self.cached(func_id, key, ttl, || {
+            self.unary_compat(service_id, func_id, self.initiate_call(), req)
+        })
+    }
+
+    // Answers the call from the response cache, or makes it by `call` and caches its reply.
+    fn cached<Res, F>(
+        &self,
+        func_id: u32,
+        key: Vec<u8>,
+        ttl: Duration,
+        call: impl FnOnce() -> F,
+    ) -> impl Future<Output = Result<RRef<Res>, Status>> + '_
+    where
+        Res: Unpin + RpcData,
+        F: Future<Output = Result<RRef<Res>, Status>> + '_,
+    {
+        let cached = self
+            .responses
+            .borrow_mut()
+            .get(func_id, key.clone())
+            .and_then(|reply| reply.downcast_ref::<RRef<Res>>())
+            .cloned();
+        let call = match cached {
+            Some(_) => None,
+            None => Some(call()),
+        };
+
+        async move {
+            let call = match call {
+                Some(call) => call,
+                None => return Ok(cached.unwrap()),
+            };
+            let reply = call.await?;
+            self.responses
+                .borrow_mut()
+                .insert(func_id, key, Box::new(reply.clone()), ttl);
+            Ok(reply)
+        }
+    }
+
+    /// Issue a request that expects no reply.
+    ///
+    /// Returns once the request is handed to the backend. The server runs the handler of the
+    /// request and drops its reply. The request is delivered at most once: it is neither
+    /// retransmitted nor replayed after reconnection, and a request that fails to send is only
+    /// logged.
+    pub fn post<Req: RpcData>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        req: WRef<Req>,
+    ) -> Result<(), Status> {
+        self.post_with_flags(service_id, func_id, req, 0)
+    }
+
+    /// Issue a request of a oneshot method generated with `compat` that expects no reply, see
+    /// [`ClientStub::post`]. The request is sent in the protobuf wire format if compat calls are
+    /// enabled by [`ClientStub::set_compat`].
+    pub fn post_compat<Req: RpcData + prost::Message>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        req: WRef<Req>,
+    ) -> Result<(), Status> {
+        if self.inner.lock().compat {
+            let flags = MessageMeta::RAW | MessageMeta::COMPAT;
+            self.post_with_flags(service_id, func_id, crate::compat::encode(&req), flags)
+        } else {
+            self.post_with_flags(service_id, func_id, req, 0)
+        }
+    }
+
+    fn post_with_flags<Req: RpcData>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        req: WRef<Req>,
+        flags: u32,
+    ) -> Result<(), Status> {
+        fork::check(self.generation)?;
+        let conn_id = self.with_master_conn(|conn| conn.handle());
+        let call_id = self.initiate_call();
+        let meta = MessageMeta {
+            conn_id,
+            service_id,
+            func_id,
+            call_id,
+            token: req.token().0 as u64,
+            correlation_id: req.correlation_id(),
+            request_id: [0; 16],
+            msg_type: RpcMsgType::Post,
+            status_code: phoenix_api::rpc::StatusCode::Success,
+            flags,
+        };
+
+        // the request is released once the backend has sent it
+        let bulk = req.is_bulk();
+        let call_deadline = req.deadline();
+        self.with_conn(conn_id, |conn| {
+            conn.map_alive(|alive| {
+                alive
+                    .pending
+                    .insert(RpcId::new(conn_id, call_id), WRef::clone(&req))
+            })
+        })?;
+        let (ptr_app, ptr_backend) = req.into_shmptr().to_raw_parts();
+        let erased = MessageErased {
+            meta,
+            shm_addr_app: ptr_app.addr().get(),
+            shm_addr_backend: ptr_backend.addr().get(),
+        };
+        let deadline = {
+            let mut inner = self.inner.lock();
+            inner.posts.insert(call_id);
+            inner.call_deadline(call_deadline)
+        };
+        Self::post_erased(erased, deadline, bulk)?;
+        Ok(())
+    }
+
+    /// Waits until the backend has completed all sends of `msg`, processing the completions of
+    /// this stub meanwhile, e.g., before reusing a message that has been [`post`]ed.
+    ///
+    /// [`post`]: ClientStub::post
+    pub async fn wait_sent<T: RpcData>(&self, msg: &WRef<T>) -> Result<(), Error> {
+        futures::future::poll_fn(|cx| {
+            if !msg.is_sending() {
+                return Poll::Ready(Ok(()));
+            }
+            futures::ready!(LOCAL_REACTOR.with_borrow_mut(|r| r.poll(cx)))?;
+            self.dispatch()?;
+            if !msg.is_sending() {
+                return Poll::Ready(Ok(()));
+            }
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Issue the same request to each connection in `targets`.
+    ///
+    /// The message is shared by all targets rather than marshalled for each of them, so it is
+    /// only released after every target acknowledges it. Targets that are not connections of
+    /// this stub, or that fail to send, get an error in the returned stream while the others
+    /// proceed.
+    pub fn broadcast<Req, Res>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        req: WRef<Req>,
+        targets: &[Handle],
+    ) -> BroadcastStream<'_, Res>
+    where
+        Req: RpcData,
+        Res: Unpin + RpcData,
+    {
+        let mut stream = BroadcastStream {
+            pending: Vec::with_capacity(targets.len()),
+            failed: VecDeque::new(),
+            client: self,
             _marker: PhantomData,
+        };
+        if let Err(e) = fork::check(self.generation) {
+            let status = Status::from(e);
+            for &conn_id in targets {
+                let status = Status::new(status.code(), status.message());
+                stream.failed.push_back((conn_id, status));
+            }
+            return stream;
         }
+
+        for &conn_id in targets {
+            if !self.conns.borrow().contains_key(&conn_id) {
+                let status = Status::not_found(format!("no connection {:?}", conn_id));
+                stream.failed.push_back((conn_id, status));
+                continue;
+            }
+            let call_id = self.initiate_call();
+            let meta = MessageMeta {
+                conn_id,
+                service_id,
+                func_id,
+                call_id,
+                token: req.token().0 as u64,
+                correlation_id: req.correlation_id(),
+                request_id: self.request_id(),
+                msg_type: RpcMsgType::Request,
+                status_code: phoenix_api::rpc::StatusCode::Success,
+                flags: 0,
+            };
+            match self.post_request(WRef::clone(&req), meta) {
+                Ok(()) => stream.pending.push(RpcId(conn_id, call_id)),
+                Err(e) => stream.failed.push_back((conn_id, e.into())),
+            }
+        }
+        stream
+    }
+
+    /// Issue the same request of a method generated with `compat` to each connection in
+    /// `targets`, see [`ClientStub::broadcast`]. Broadcast calls are only sent in place: while
+    /// compat calls are enabled by [`ClientStub::set_compat`], every target gets a
+    /// `FailedPrecondition` error instead, as the servers may be of another version.
+    pub fn broadcast_compat<Req, Res>(
+        &self,
+        service_id: u32,
+        func_id: u32,
+        req: WRef<Req>,
+        targets: &[Handle],
+    ) -> BroadcastStream<'_, Res>
+    where
+        Req: RpcData,
+        Res: Unpin + RpcData,
+    {
+        if !self.inner.lock().compat {
+            return self.broadcast(service_id, func_id, req, targets);
+        }
+        let failed = targets
+            .iter()
+            .map(|&conn_id| {
+                let status = Status::failed_precondition(
+                    "broadcast calls cannot be sent in the protobuf wire format",
+                );
+                (conn_id, status)
+            })
+            .collect();
+        BroadcastStream {
+            pending: Vec::new(),
+            failed,
+            client: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the handles of the connections of the stub that are alive, e.g., the targets to
+    /// [`broadcast`](ClientStub::broadcast) to.
+    pub fn connections(&self) -> Vec<Handle> {
+        let mut handles: Vec<_> = self
+            .conns
+            .borrow()
+            .values()
+            .filter(|conn| conn.is_alive())
+            .map(|conn| conn.handle())
+            .collect();
+        handles.sort_by_key(|handle| handle.0);
+        handles
     }
 
     /// Prepare to make an RPC.
@@ -148,21 +800,300 @@ impl ClientStub {
         // self.inner.borrow_mut().reply_cache.initiate_call()
         self.inner.lock().reply_cache.initiate_call()
     }
+
+    /// Returns a new request ID for a call if exactly-once calls are enabled, or all zeros.
+    fn request_id(&self) -> [u8; 16] {
+        if self.inner.lock().exactly_once {
+            // a zero ID marks the other calls
+            fastrand::u128(1..).to_le_bytes()
+        } else {
+            [0; 16]
+        }
+    }
+
+    /// Returns the latest health state of the connection.
+    ///
+    /// The state is reported by the transport when keep-alive is enabled for it. Without
+    /// keep-alive, the state stays [`ConnectionState::Connected`] until the connection
+    /// breaks.
+    pub fn state(&self) -> ConnectionState {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        if let Poll::Ready(Err(e)) = LOCAL_REACTOR.with_borrow_mut(|r| r.poll(&mut cx)) {
+            log::warn!("Failed to poll the reactor: {}", e);
+        }
+        if let Err(e) = self.dispatch() {
+            log::warn!("Failed to dispatch completions: {}", e);
+        }
+        self.inner.lock().state
+    }
+
+    /// Returns the status of each connection of the stub, so that applications can pick the
+    /// least loaded connection to call rather than going round-robin.
+    ///
+    /// The credits are queried from the backend, which takes a round trip on the control path.
+    /// For a stub of multiple connections, the calls issued by [`unary`](ClientStub::unary) go
+    /// through the virtual connection, which is reported as well.
+    pub fn channel_status(&self) -> Result<Vec<ChannelStatus>, Error> {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        if let Poll::Ready(Err(e)) = LOCAL_REACTOR.with_borrow_mut(|r| r.poll(&mut cx)) {
+            log::warn!("Failed to poll the reactor: {}", e);
+        }
+        self.dispatch()?;
+
+        let mut handles = self.connections();
+        let vconn_id = self.vconn.borrow().handle();
+        if !handles.contains(&vconn_id) && self.vconn.borrow().is_alive() {
+            handles.push(vconn_id);
+        }
+
+        let credits = MRPC_CTX.with(|ctx| -> Result<HashMap<_, _>, Error> {
+            let service = ctx.service()?;
+            service.send_cmd(Command::QueryCredits(handles.clone()))?;
+            rx_recv_impl!(service, CompletionKind::QueryCredits, credits, {
+                Ok(credits.into_iter().collect())
+            })
+        })?;
+
+        let inner = self.inner.lock();
+        let status = handles
+            .into_iter()
+            .map(|conn_id| ChannelStatus {
+                conn_id,
+                tx_queue_depth: self
+                    .with_conn(conn_id, |conn| conn.map_alive(|alive| alive.pending.len()))
+                    .unwrap_or(0),
+                credits: credits.get(&conn_id).copied().flatten(),
+                outstanding_calls: inner.outstanding.get(&conn_id).copied().unwrap_or(0),
+            })
+            .collect();
+        Ok(status)
+    }
+
+    /// Enables automatic reconnection with the given policy.
+    ///
+    /// Reconnection is only supported for stubs created by [`ClientStub::connect`], or by
+    /// [`ClientStub::connect_host`] when the address of the remote end is known. The
+    /// reconnection attempts run inline when the stub is polled, each once its backoff has
+    /// passed; the polling thread never waits for the backoff.
+    pub fn set_reconnect_policy(&self, policy: ReconnectPolicy) {
+        if self.addr.is_none() {
+            log::warn!(
+                "Reconnection is not supported for stubs with multiple connections or an unknown \
+                 peer address"
+            );
+            return;
+        }
+        self.inner.lock().reconnect = Some(policy);
+    }
+
+    /// Enables retransmitting the requests issued afterwards that get no reply in time, see
+    /// [`RetryPolicy`].
+    ///
+    /// Retransmission is only useful for stubs created by [`ClientStub::connect_datagram`], as
+    /// reliable connections do not lose messages silently.
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        if !self.datagram {
+            log::warn!("Retransmission is only useful for connections over datagrams");
+        }
+        self.inner.lock().retry = Some(policy);
+    }
+
+    /// Enables or disables exactly-once semantics for the calls issued afterwards, e.g., for
+    /// reservation-style calls that must not take effect twice.
+    ///
+    /// Each call carries a unique request ID, which its retransmissions keep (see
+    /// [`RetryPolicy`]). A server with a dedupe cache (see [`LocalServer::set_dedupe`]) answers
+    /// a request it has already handled with the cached reply instead of running the handler
+    /// again. Other servers ignore the ID, so the calls are only at-least-once with them.
+    ///
+    /// [`LocalServer::set_dedupe`]: super::LocalServer::set_dedupe
+    pub fn set_exactly_once(&self, enable: bool) {
+        self.inner.lock().exactly_once = enable;
+    }
+
+    /// Sets whether the calls of the methods generated with `compat` send their requests in the
+    /// protobuf wire format, so that they are served by the servers built from another version
+    /// of the proto, e.g., during a rolling upgrade. Disabled by default, as the messages are
+    /// copied to be encoded and decoded. See [`compat`](crate::compat).
+    pub fn set_compat(&self, enable: bool) {
+        self.inner.lock().compat = enable;
+    }
+
+    /// Sets how long each request issued afterwards may wait in the send queue of the backend,
+    /// or `None` to let them wait as long as it takes.
+    ///
+    /// Requests queue up in the backend when the connection runs out of credits or is paced by
+    /// congestion control. A request still queued when its timeout expires is dropped instead of
+    /// sent, and its call fails with [`Code::DeadlineExceeded`](crate::Code::DeadlineExceeded).
+    /// Only the RDMA transport queues requests.
+    pub fn set_queue_timeout(&self, timeout: Option<Duration>) {
+        self.inner.lock().queue_timeout = timeout;
+    }
+
+    /// Returns whether the server has asked to slow down recently, in a reply flagged by its
+    /// [`PushbackPolicy`](super::PushbackPolicy), for the pushback delay of the
+    /// [`RetryPolicy`], 100ms if it is not set.
+    ///
+    /// The application may shed its own load while it is pushed back, e.g., by failing the
+    /// requests of lower priority early.
+    pub fn pushed_back(&self) -> bool {
+        let until = self.inner.lock().pushed_back;
+        until.map_or(false, |until| until > Instant::now())
+    }
+
+    /// Sets the most replies of the cacheable methods to keep, 32 by default. The least recently
+    /// used replies are evicted if there are more.
+    ///
+    /// A cached reply holds its receive buffer, which the connection cannot receive another
+    /// message into until the reply is evicted, so the capacity should be well below the
+    /// receive buffers of the connection.
+    pub fn set_cache_capacity(&self, capacity: usize) {
+        self.responses.borrow_mut().set_capacity(capacity);
+    }
+
+    /// Returns the counters of the response cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.responses.borrow().stats()
+    }
+
+    /// Drops all cached replies, so the calls issued afterwards go to the server.
+    pub fn flush_cache(&self) {
+        self.responses.borrow_mut().flush();
+    }
+
+    /// Enables or disables the timing of the calls issued afterwards. The replies of the timed
+    /// calls carry a [`CallTiming`], see [`RRef::timing`].
+    ///
+    /// Timing takes a few clock reads for each call, and an extra completion from the daemon.
+    pub fn set_call_timing(&self, enable: bool) -> Result<(), Error> {
+        fork::check(self.generation)?;
+        let handles = self.connections();
+        MRPC_CTX.with(|ctx| -> Result<(), Error> {
+            let service = ctx.service()?;
+            service.send_cmd(Command::SetCallTiming(handles, enable))?;
+            rx_recv_impl!(service, CompletionKind::SetCallTiming)
+        })?;
+        self.inner.lock().timing = enable.then(HashMap::new);
+        Ok(())
+    }
+
+    /// Sets the largest message, in bytes, the backend copies into the message header and sends
+    /// in one piece on the connections of the client, `None` for the default of the backend.
+    /// Larger messages are sent from the shared heap without a copy.
+    ///
+    /// Copying is cheaper than the zero-copy path for tiny messages, the best threshold depends
+    /// on the NIC and the message sizes, see the `--eager-copy-threshold` of `rpc_bench`.
+    pub fn set_eager_copy_threshold(&self, threshold: Option<usize>) -> Result<(), Error> {
+        fork::check(self.generation)?;
+        let handles = self.connections();
+        MRPC_CTX.with(|ctx| -> Result<(), Error> {
+            let service = ctx.service()?;
+            service.send_cmd(Command::SetEagerCopyThreshold(handles, threshold))?;
+            rx_recv_impl!(service, CompletionKind::SetEagerCopyThreshold)
+        })
+    }
 }
 
 impl ClientStub {
+    /// Returns the result of the call `rpc_id` if the reply has arrived.
+    fn take_reply<T>(&self, rpc_id: RpcId) -> Option<Result<RRef<T>, Status>> {
+        // let inner = self.inner.borrow();
+        let mut inner = self.inner.lock();
+        let reply = *inner
+            .reply_cache
+            .get(rpc_id.1)
+            .expect("Expect an entry")
+            .as_ref()?;
+        let timing = inner
+            .timing
+            .as_mut()
+            .and_then(|timing| timing.remove(&rpc_id.1))
+            .map(|timing| CallTiming {
+                delivered: monotonic_ns(),
+                ..timing
+            });
+        let ret = match &reply {
+            Ok(reply) => {
+                tracing::trace!(
+                    "ReqFuture receive reply from mRPC engine, rpc_id={:?}",
+                    rpc_id
+                );
+                let read_heap = self
+                    .conns
+                    .borrow()
+                    .get(&reply.meta.conn_id)
+                    .unwrap()
+                    .map_alive(|alive| Arc::clone(&alive.read_heap))
+                    .expect("TODO: return an error when connection is dead rather than panic");
+                Ok(RRef::with_timing(reply, read_heap, timing))
+            }
+            Err(status) => Err(Status::from_transport(*status)),
+        };
+        Some(ret)
+    }
+
+    /// Gives up waiting for the call `rpc_id`, e.g., its future is dropped. The reply is
+    /// discarded, now if it has arrived, or when it arrives.
+    fn abandon(&self, rpc_id: RpcId) {
+        let mut inner = self.inner.lock();
+        let arrived = match inner.reply_cache.get(rpc_id.1).map(|slot| *slot) {
+            Ok(Some(Ok(reply))) => Some(reply),
+            Ok(None) => {
+                // resolve the call, so a late reply is taken as a duplicate and discarded
+                inner.in_flight.remove(&rpc_id.1);
+                inner.attempts.remove(&rpc_id.1);
+                inner.call_finished(rpc_id.0);
+                let status = TransportStatus::DEADLINE_EXCEEDED;
+                inner.reply_cache.update(rpc_id.1, Err(status)).unwrap();
+                None
+            }
+            _ => None,
+        };
+        if let Some(timing) = inner.timing.as_mut() {
+            timing.remove(&rpc_id.1);
+        }
+        drop(inner);
+        if let Some(reply) = arrived {
+            self.discard_reply(reply);
+        }
+    }
+
     /// Dispatch one completion from the Receiver, and update PendingWRef and ReplyCache.
     fn dispatch_one(&self, comp: &dp::Completion, inner: &mut Inner) -> Result<(), Error> {
+        let conn_id = match comp {
+            dp::Completion::Incoming(msg) => msg.meta.conn_id,
+            dp::Completion::Outgoing(rpc_id, _status) => rpc_id.0,
+            dp::Completion::RecvError(conn_id, _status) => *conn_id,
+            dp::Completion::ConnectionState(conn_id, _state) => *conn_id,
+            dp::Completion::CallTiming(rpc_id, _timing) => rpc_id.0,
+        };
+        if self.is_stale(conn_id) {
+            // Leftovers of a connection that has been replaced by reconnection.
+            log::debug!("Drop completion of stale connection {:?}", conn_id);
+            return Ok(());
+        }
+
         match *comp {
             dp::Completion::Incoming(msg) => {
                 let call_id = msg.meta.call_id;
                 match msg.meta.msg_type {
-                    RpcMsgType::Request => {
+                    RpcMsgType::Request | RpcMsgType::Post => {
                         // server receives requests
                         panic!("impossible, something is wrong")
                     }
                     RpcMsgType::Response => {
+                        if inner.is_resolved(call_id) {
+                            // late or duplicate reply of a retransmitted request
+                            self.discard_reply(msg);
+                            return Ok(());
+                        }
+                        if msg.meta.pushback() {
+                            inner.push_back(Instant::now());
+                        }
                         // client receives responses, update the ReplyCache
+                        inner.in_flight.remove(&call_id);
+                        inner.attempts.remove(&call_id);
+                        inner.call_finished(conn_id);
                         inner.reply_cache.update(call_id, Ok(msg)).unwrap();
                     }
                 }
@@ -175,22 +1106,43 @@ impl ClientStub {
                 // A success ack is returned by when the request is sent
                 // and 402 is returned when ACL denies the request
                 // in that case we must not remove the pending request twice!
+                // The same goes for 501, returned when the server implements no such method, 415
+                // when it cannot decode the request, and 510 when its handler fails the call.
                 match status {
                     TransportStatus::Error(code) => match code.get() {
-                        402 => {}
+                        402 | 415 | 501 | 510 => {}
                         _ => {
-                            self.master_conn()
-                                .map_alive(|alive| alive.pending.remove(&rpc_id))?;
+                            self.with_conn(rpc_id.0, |conn| {
+                                conn.map_alive(|alive| alive.pending.remove(&rpc_id))
+                            })?;
                         }
                     },
                     _ => {
-                        self.master_conn()
-                            .map_alive(|alive| alive.pending.remove(&rpc_id))?;
+                        self.with_conn(rpc_id.0, |conn| {
+                            conn.map_alive(|alive| alive.pending.remove(&rpc_id))
+                        })?;
                     }
                 }
 
+                if inner.posts.remove(&rpc_id.1) {
+                    // nobody waits for a posted request
+                    if let TransportStatus::Error(_) = status {
+                        log::debug!("Failed to send posted request {:?}: {:?}", rpc_id, status);
+                    }
+                    return Ok(());
+                }
+                if let Some(attempt) = inner.attempts.get_mut(&rpc_id.1) {
+                    attempt.acked = true;
+                }
                 if let TransportStatus::Error(_) = status {
+                    if inner.is_resolved(rpc_id.1) {
+                        // the reply to an earlier send has arrived
+                        return Ok(());
+                    }
                     // Update the ReplyCache with error
+                    inner.in_flight.remove(&rpc_id.1);
+                    inner.attempts.remove(&rpc_id.1);
+                    inner.call_finished(rpc_id.0);
                     inner.reply_cache.update(rpc_id.1, Err(status)).unwrap();
                 }
             }
@@ -203,7 +1155,36 @@ impl ClientStub {
                     conn_id,
                     status
                 );
-                self.master_conn().close();
+                inner.state = ConnectionState::Closed;
+                // The backend quarantined the connection after its dispatch library failed on a
+                // message, the calls on it will not be answered.
+                let quarantined = status == TransportStatus::BAD_MESSAGE
+                    || status == TransportStatus::DISPATCH_PANICKED;
+                if quarantined && inner.reconnect.is_none() {
+                    inner.outstanding.clear();
+                    inner.attempts.clear();
+                    for (call_id, _) in inner.in_flight.drain() {
+                        inner.reply_cache.update(call_id, Err(status)).unwrap();
+                    }
+                }
+                self.close_master_conn();
+            }
+            dp::Completion::ConnectionState(conn_id, state) => {
+                log::debug!("Connection {:?} changes state to {:?}", conn_id, state);
+                inner.state = state;
+                if state == ConnectionState::Closed {
+                    self.close_master_conn();
+                }
+            }
+            dp::Completion::CallTiming(rpc_id, timing) => {
+                if let Some(entry) = inner
+                    .timing
+                    .as_mut()
+                    .and_then(|calls| calls.get_mut(&rpc_id.1))
+                {
+                    entry.sent = Some(timing.sent);
+                    entry.first_recv = Some(timing.first_recv);
+                }
             }
         }
 
@@ -212,6 +1193,7 @@ impl ClientStub {
 
     /// Dispatch completions from the Receiver.
     pub(crate) fn dispatch(&self) -> Result<(), Error> {
+        fork::check(self.generation)?;
         // Because for client, each stub only has one connection, there is no real dispatch here.
         // let mut inner = self.inner.borrow_mut();
         let mut inner = self.inner.lock();
@@ -227,14 +1209,209 @@ impl ClientStub {
             }
         }
 
+        if inner.state == ConnectionState::Closed && inner.reconnect.is_some() {
+            self.reconnect(&mut inner)?;
+        }
+
+        if inner.retry.is_some() && !inner.attempts.is_empty() {
+            self.retransmit(&mut inner)?;
+        }
+
         Ok(())
     }
 
+    /// Sends the requests that have got no reply by their deadlines again, or fails them if
+    /// they have been sent too many times.
+    fn retransmit(&self, inner: &mut Inner) -> Result<(), Error> {
+        let policy = inner.retry.clone().unwrap();
+        let now = Instant::now();
+        let pushed_back = inner.pushed_back.filter(|until| *until > now);
+        let expired: Vec<CallId> = inner
+            .attempts
+            .iter()
+            .filter(|(_, attempt)| attempt.deadline <= now)
+            .map(|(call_id, _)| *call_id)
+            .collect();
+
+        for call_id in expired {
+            let Some((erased, wref)) = inner.in_flight.get(&call_id) else {
+                // failed by reconnection
+                inner.attempts.remove(&call_id);
+                continue;
+            };
+            let (erased, wref) = (*erased, wref.clone());
+            let attempt = inner.attempts.get_mut(&call_id).unwrap();
+            if !attempt.acked {
+                // The request is still queued in the backend, give it another period.
+                attempt.deadline = policy.deadline(now);
+                continue;
+            }
+            if let Some(until) = pushed_back {
+                // The server is congested, the request is more likely delayed than lost.
+                attempt.deadline = until;
+                continue;
+            }
+            if !policy.should_retry(attempt.sent) {
+                log::debug!(
+                    "No reply to call {:?} after {} attempts",
+                    call_id,
+                    attempt.sent
+                );
+                inner.attempts.remove(&call_id);
+                inner.in_flight.remove(&call_id);
+                inner.call_finished(erased.meta.conn_id);
+                let status = TransportStatus::NO_REPLY;
+                inner.reply_cache.update(call_id, Err(status)).unwrap();
+                continue;
+            }
+
+            attempt.sent += 1;
+            attempt.acked = false;
+            attempt.deadline = policy.deadline(now);
+            let rpc_id = RpcId::new(erased.meta.conn_id, call_id);
+            self.with_conn(rpc_id.0, |conn| {
+                conn.map_alive(|alive| alive.pending.insert_opaque(rpc_id, wref))
+            })?;
+            Self::post_erased(erased, inner.queue_deadline(), wref.is_bulk())?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops a reply that no call waits for, returning its receive buffer to the backend.
+    fn discard_reply(&self, msg: MessageErased) {
+        let read_heap = self
+            .conns
+            .borrow()
+            .get(&msg.meta.conn_id)
+            .and_then(|conn| conn.map_alive(|alive| Arc::clone(&alive.read_heap)).ok());
+        if let Some(read_heap) = read_heap {
+            drop(RRef::<()>::with_timing(&msg, read_heap, None));
+        }
+    }
+
+    fn reconnect(&self, inner: &mut Inner) -> Result<(), Error> {
+        let addr = self
+            .addr
+            .expect("reconnect policy is only set with an address");
+        let policy = inner.reconnect.clone().unwrap();
+
+        // The inner lock is held here, so the backoff is not waited out but checked again on the
+        // next poll.
+        let now = Instant::now();
+        let attempt = match inner.next_attempt {
+            Some((_, due)) if now < due => return Ok(()),
+            Some((attempt, _)) => attempt,
+            None => return self.schedule_reconnect(inner, &policy, 0, now),
+        };
+
+        // The backend may have been restarted, load the protos again.
+        let conn = match MRPC_CTX
+            .with(|ctx| ctx.reload_protos())
+            .and_then(|_| Self::establish(Self::connect_cmd(addr, self.datagram, self.options)))
+        {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::debug!(
+                    "Reconnect to {} attempt {} failed: {}",
+                    addr,
+                    attempt + 1,
+                    e
+                );
+                return self.schedule_reconnect(inner, &policy, attempt + 1, now);
+            }
+        };
+        inner.next_attempt = None;
+
+        // replace the broken connection
+        let conn_id = conn.handle();
+        LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(self.stub_id, &conn));
+        let old_conn_id = self.vconn.borrow().handle();
+        self.conns.borrow_mut().remove(&old_conn_id);
+        self.conns.borrow_mut().insert(conn_id, conn);
+        *self.vconn.borrow_mut() = Connection::vconn(conn_id);
+        inner.state = ConnectionState::Connected;
+        log::info!(
+            "Reconnected to {}, connection {:?} replaces {:?}",
+            addr,
+            conn_id,
+            old_conn_id
+        );
+
+        // replay idempotent calls and fail the others
+        inner.outstanding.clear();
+        let in_flight = std::mem::take(&mut inner.in_flight);
+        for (call_id, (mut erased, wref)) in in_flight {
+            if policy.is_replayable(erased.meta.func_id) {
+                erased.meta.conn_id = conn_id;
+                self.with_master_conn(|conn| {
+                    conn.map_alive(|alive| {
+                        alive
+                            .pending
+                            .insert_opaque(RpcId::new(conn_id, call_id), wref.clone())
+                    })
+                })?;
+                let bulk = wref.is_bulk();
+                inner.in_flight.insert(call_id, (erased, wref));
+                Self::post_erased(erased, inner.queue_deadline(), bulk)?;
+                inner.call_started(conn_id);
+            } else {
+                let status = TransportStatus::CONNECTION_LOST;
+                inner.reply_cache.update(call_id, Err(status)).unwrap();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Schedules the reconnection attempt `attempt` (counting from 0) after its backoff, or gives
+    /// up and fails the calls in flight if the policy allows no more attempts.
+    fn schedule_reconnect(
+        &self,
+        inner: &mut Inner,
+        policy: &ReconnectPolicy,
+        attempt: u32,
+        now: Instant,
+    ) -> Result<(), Error> {
+        if policy.should_retry(attempt) {
+            inner.next_attempt = Some((attempt, now + policy.backoff(attempt)));
+            return Ok(());
+        }
+        log::warn!(
+            "Give up reconnecting to {} after {} attempts",
+            self.addr.unwrap(),
+            attempt
+        );
+        inner.reconnect = None;
+        inner.next_attempt = None;
+        inner.outstanding.clear();
+        inner.attempts.clear();
+        for (call_id, _) in inner.in_flight.drain() {
+            let status = TransportStatus::CONNECTION_LOST;
+            inner.reply_cache.update(call_id, Err(status)).unwrap();
+        }
+        Err(Error::ConnectionClosed)
+    }
+
+    /// Returns true if `conn_id` does not belong to this stub anymore.
+    fn is_stale(&self, conn_id: Handle) -> bool {
+        self.vconn.borrow().handle() != conn_id && !self.conns.borrow().contains_key(&conn_id)
+    }
+
+    fn close_master_conn(&self) {
+        self.with_master_conn(|conn| {
+            if conn.is_alive() {
+                conn.close();
+            }
+        });
+    }
+
     pub(crate) fn post_request<T: RpcData>(
         &self,
         msg: WRef<T>,
         meta: MessageMeta,
     ) -> Result<(), Error> {
+        fork::check(self.generation)?;
         tracing::trace!(
             "client post request to mRPC engine, call_id={}",
             meta.call_id
@@ -244,14 +1421,18 @@ impl ClientStub {
         // self.conn
         //     .hold_rpc(RpcId::new(meta.conn_id, meta.call_id), WRef::clone(&msg))?;
 
-        self.master_conn()
-            .map_alive(|alive: &crate::stub::conn::AliveConnection| {
+        self.with_conn(meta.conn_id, |conn| {
+            conn.map_alive(|alive: &crate::stub::conn::AliveConnection| {
                 alive
                     .pending
                     .insert(RpcId::new(meta.conn_id, meta.call_id), WRef::clone(&msg))
-            })?;
+            })
+        })?;
 
         // construct the request
+        let wref = WRef::clone(&msg);
+        let bulk = msg.is_bulk();
+        let call_deadline = msg.deadline();
         let (ptr_app, ptr_backend) = msg.into_shmptr().to_raw_parts();
         let erased = MessageErased {
             meta,
@@ -259,50 +1440,189 @@ impl ClientStub {
             shm_addr_backend: ptr_backend.addr().get(),
         };
 
-        let req = dp::WorkRequest::Call(erased);
+        let deadline = {
+            let mut inner = self.inner.lock();
+            if let Some(timing) = inner.timing.as_mut() {
+                let enqueued = monotonic_ns();
+                timing.insert(
+                    meta.call_id,
+                    CallTiming {
+                        enqueued,
+                        ..Default::default()
+                    },
+                );
+            }
+            if let Some(policy) = inner.retry.as_ref() {
+                let attempt = Attempt {
+                    deadline: policy.deadline(Instant::now()),
+                    sent: 1,
+                    acked: false,
+                };
+                inner.attempts.insert(meta.call_id, attempt);
+            }
+            if inner.reconnect.is_some() || inner.retry.is_some() {
+                inner
+                    .in_flight
+                    .insert(meta.call_id, (erased, wref.into_opaque()));
+            }
+            inner.call_deadline(call_deadline)
+        };
+
+        Self::post_erased(erased, deadline, bulk)?;
+        self.inner.lock().call_started(meta.conn_id);
+        Ok(())
+    }
+
+    /// Hands a request to the backend. The request is dropped if it is still queued in the
+    /// backend at `deadline`, in nanoseconds of `monotonic_ns`, unless it is 0. `bulk` tells
+    /// whether it is sent in bulk, see [`WRef::as_bulk`].
+    fn post_erased(erased: MessageErased, deadline: u64, bulk: bool) -> Result<(), Error> {
+        let rpc_id = RpcId::new(erased.meta.conn_id, erased.meta.call_id);
+        let reqs = [
+            (deadline != 0).then_some(dp::WorkRequest::Deadline(rpc_id, deadline)),
+            bulk.then_some(dp::WorkRequest::Bulk(rpc_id)),
+            Some(dp::WorkRequest::Call(erased)),
+        ];
 
         #[cfg(feature = "timing")]
         TIMER.with_borrow_mut(|timer| {
-            timer.sample(
-                RpcId::new(meta.conn_id, meta.call_id),
-                SampleKind::ClientRequest,
-            );
+            timer.sample(rpc_id, SampleKind::ClientRequest);
         });
 
         // notify the backend
         MRPC_CTX.with(|ctx| {
-            let mut sent = false;
-            while !sent {
-                ctx.service.enqueue_wr_with(|ptr, _count| unsafe {
-                    ptr.cast::<dp::WorkRequest>().write(req);
-                    sent = true;
-                    1
-                })?;
+            let service = ctx.service()?;
+            for req in reqs.into_iter().flatten() {
+                let mut sent = false;
+                while !sent {
+                    service.enqueue_wr_with(|ptr, _count| unsafe {
+                        ptr.cast::<dp::WorkRequest>().write(req);
+                        sent = true;
+                        1
+                    })?;
+                }
             }
             Ok(())
         })
     }
 
-    fn master_conn(&self) -> &Connection {
-        if self.vconn.handle().is_master() {
-            &self.vconn
+    fn with_master_conn<T, F: FnOnce(&Connection) -> T>(&self, f: F) -> T {
+        let vconn = self.vconn.borrow();
+        if vconn.handle().is_master() {
+            f(&vconn)
         } else {
-            self.conns.get(&self.vconn.handle()).unwrap()
+            let conns = self.conns.borrow();
+            f(conns.get(&vconn.handle()).unwrap())
         }
     }
+
+    /// Runs `f` on the connection `conn_id`, or on the master connection if `conn_id` is not a
+    /// connection of the stub, e.g., the virtual connection of multiple connections.
+    fn with_conn<T, F: FnOnce(&Connection) -> T>(&self, conn_id: Handle, f: F) -> T {
+        if let Some(conn) = self.conns.borrow().get(&conn_id) {
+            return f(conn);
+        }
+        self.with_master_conn(f)
+    }
+
     /// Creates an RPC client by connecting to a given socket address.
     // TODO(cjr): Change this to async too
     pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        Self::connect_impl(addr, false, ConnectOptions::default())
+    }
+
+    /// Creates an RPC client by connecting to a given socket address, from the local address,
+    /// device or ports set in `options`, for hosts with more than one of them. The stub
+    /// reconnects with the same options.
+    ///
+    /// A local address takes precedence over a device. The RDMA transport binds the connection
+    /// to the address of the device, and tries the ports of the range in order until one is
+    /// free. The TCP transport cannot choose a device.
+    pub fn connect_with<A: ToSocketAddrs>(addr: A, options: ConnectOptions) -> Result<Self, Error> {
+        Self::connect_impl(addr, false, options)
+    }
+
+    /// Creates an RPC client by connecting to a given socket address over unreliable
+    /// datagrams.
+    ///
+    /// The connection takes no per-connection queue pair at either side, so a process can
+    /// keep many more of them than the reliable ones, but each message must fit in a single
+    /// datagram, and messages can be lost silently. Larger messages fail to send. Calls that
+    /// get no reply wait forever unless a [`RetryPolicy`] is set. The server must be bound
+    /// with [`LocalServerBuilder::datagram`](super::LocalServerBuilder::datagram), and only
+    /// the RDMA transport supports it.
+    pub fn connect_datagram<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        Self::connect_impl(addr, true, ConnectOptions::default())
+    }
+
+    /// Creates an RPC client by connecting to `host`, whose name is resolved by the backend
+    /// instead of the application. See the `resolver` settings of the mRPC plugin for the static
+    /// host table and which address family is preferred.
+    ///
+    /// The stub reconnects to the address the name was resolved to, if the transport reports
+    /// the address of the remote end.
+    pub fn connect_host(host: &str, port: u16) -> Result<Self, Error> {
+        let conn = Self::establish(Command::ConnectHost(host.to_owned(), port))?;
+        let peer_addr = conn.map_alive(|alive| alive.peer_addr)?;
+        Self::with_connection(conn, peer_addr, false, ConnectOptions::default())
+    }
+
+    fn connect_impl<A: ToSocketAddrs>(
+        addr: A,
+        datagram: bool,
+        options: ConnectOptions,
+    ) -> Result<Self, Error> {
         let connect_addr = addr
             .to_socket_addrs()?
             .next()
             .ok_or(Error::NoAddrResolved)?;
-        let req = Command::Connect(connect_addr);
 
+        let conn = Self::establish(Self::connect_cmd(connect_addr, datagram, options))?;
+        Self::with_connection(conn, Some(connect_addr), datagram, options)
+    }
+
+    fn with_connection(
+        conn: Connection,
+        addr: Option<SocketAddr>,
+        datagram: bool,
+        options: ConnectOptions,
+    ) -> Result<Self, Error> {
+        // register the stub with the reactor
+        let conn_handle = conn.handle();
+        let (stub_id, receiver) = LOCAL_REACTOR.with_borrow_mut(|r| r.register_stub());
+        LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(stub_id, &conn));
+
+        let mut conns = HashMap::new();
+        conns.insert(conn.handle().clone(), conn);
+        Ok(Self {
+            vconn: RefCell::new(Connection::vconn(conn_handle)),
+            conns: RefCell::new(conns),
+            addr,
+            datagram,
+            options,
+            stub_id,
+            // inner: RefCell::new(Inner {
+            inner: spin::Mutex::new(Inner::new(receiver)),
+            responses: RefCell::new(ResponseCache::default()),
+            generation: fork::generation(),
+        })
+    }
+
+    fn connect_cmd(connect_addr: SocketAddr, datagram: bool, options: ConnectOptions) -> Command {
+        if datagram {
+            Command::ConnectDatagram(connect_addr)
+        } else {
+            Command::Connect(connect_addr, options)
+        }
+    }
+
+    /// Sends the connect command `req` and maps the read regions of the new connection.
+    fn establish(req: Command) -> Result<Connection, Error> {
         MRPC_CTX.with(|ctx| {
-            ctx.service.send_cmd(req)?;
-            let fds = ctx.service.recv_fd()?;
-            rx_recv_impl!(ctx.service, CompletionKind::Connect, conn_resp, {
+            let service = ctx.service()?;
+            service.send_cmd(req)?;
+            let fds = service.recv_fd()?;
+            rx_recv_impl!(service, CompletionKind::Connect, conn_resp, {
                 // use memfd::Memfd;
                 assert_eq!(fds.len(), conn_resp.read_regions.len());
 
@@ -317,30 +1637,116 @@ impl ClientStub {
 
                 // return the mapped addr back
                 let req = Command::NewMappedAddrs(conn_handle, vaddrs);
-                ctx.service.send_cmd(req)?;
+                service.send_cmd(req)?;
                 // wait for the reply!
-                rx_recv_impl!(ctx.service, CompletionKind::NewMappedAddrs)?;
-
-                // register the stub with the reactor
-                let conn = Connection::new(conn_handle, read_heap);
-                let (stub_id, receiver) = LOCAL_REACTOR.with_borrow_mut(|r| r.register_stub());
-                LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(stub_id, &conn));
-
-                let mut conns = HashMap::new();
-                conns.insert(conn.handle().clone(), conn);
-                Ok(Self {
-                    vconn: Connection::vconn(conn_handle),
-                    conns: conns,
-                    // inner: RefCell::new(Inner {
-                    inner: spin::Mutex::new(Inner {
-                        receiver,
-                        reply_cache: ReplyCache::new(),
-                    }),
-                })
+                rx_recv_impl!(service, CompletionKind::NewMappedAddrs)?;
+
+                Ok(Connection::new(conn_handle, read_heap, conn_resp.peer_addr))
             })
         })
     }
 
+    /// Establishes `n` connections to each of `addrs` up front and checks that they are
+    /// healthy, so that the first calls of the application do not wait for the route
+    /// resolution, the memory registration and the receive buffers of a new connection.
+    ///
+    /// The connect commands of all the connections are sent to the backend at once, which sets
+    /// them up back to back rather than waiting for the application in between. A connection is
+    /// healthy if the transport still knows it and reports it as
+    /// [`ConnectionState::Connected`]. Each target gets a client per healthy connection and the
+    /// errors of the others; an address that does not resolve fails the whole call.
+    pub fn warm_up<A: ToSocketAddrs>(
+        addrs: impl IntoIterator<Item = A>,
+        n: usize,
+    ) -> Result<Vec<WarmUp>, Error> {
+        let mut targets = Vec::new();
+        for addr in addrs {
+            let addr = addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or(Error::NoAddrResolved)?;
+            targets.push(WarmUp {
+                addr,
+                clients: Vec::with_capacity(n),
+                errors: Vec::new(),
+            });
+        }
+
+        let conns = MRPC_CTX.with(|ctx| -> Result<Vec<Result<Connection, Error>>, Error> {
+            let service = ctx.service()?;
+            for target in &targets {
+                for _ in 0..n {
+                    let options = ConnectOptions::default();
+                    service.send_cmd(Command::Connect(target.addr, options))?;
+                }
+            }
+            // the completions come back in the order of the commands
+            let mut conns = Vec::with_capacity(targets.len() * n);
+            for _ in 0..targets.len() * n {
+                let fds = service.recv_fd()?;
+                let conn = rx_recv_impl!(service, CompletionKind::Connect, conn_resp, {
+                    assert_eq!(fds.len(), conn_resp.read_regions.len());
+                    let read_heap = ReadHeap::new(&conn_resp, &fds);
+                    let vaddrs: Vec<_> = read_heap
+                        .rbufs
+                        .iter()
+                        .map(|rbuf| (rbuf.as_handle(), rbuf.as_ptr().expose_addr()))
+                        .collect();
+                    let conn_handle = conn_resp.conn_handle;
+                    let conn = Connection::new(conn_handle, read_heap, conn_resp.peer_addr);
+                    Ok((conn, vaddrs))
+                });
+                conns.push(conn);
+            }
+            // return the mapped addrs of all the connections, then wait for the replies
+            let mut mapped = Vec::with_capacity(conns.len());
+            for conn in conns {
+                mapped.push(conn.and_then(|(conn, vaddrs)| {
+                    service.send_cmd(Command::NewMappedAddrs(conn.handle(), vaddrs))?;
+                    Ok(conn)
+                }));
+            }
+            for conn in mapped.iter_mut().filter(|conn| conn.is_ok()) {
+                if let Err(e) = rx_recv_impl!(service, CompletionKind::NewMappedAddrs) {
+                    *conn = Err(e);
+                }
+            }
+            Ok(mapped)
+        })?;
+
+        let mut conns = conns.into_iter();
+        for target in &mut targets {
+            for conn in conns.by_ref().take(n) {
+                let stub = conn.and_then(|conn| {
+                    let stub =
+                        Self::with_connection(conn, Some(target.addr), false, Default::default())?;
+                    stub.check_health()?;
+                    Ok(stub)
+                });
+                match stub {
+                    Ok(stub) => target.clients.push(stub),
+                    Err(e) => target.errors.push(e),
+                }
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Checks that the transport knows the connection, and has not reported it down.
+    fn check_health(&self) -> Result<(), Error> {
+        let status = self.channel_status()?;
+        let conn_id = self.vconn.borrow().handle();
+        if !status.iter().any(|status| status.conn_id == conn_id) {
+            return Err(Error::ConnectionClosed);
+        }
+        match self.state() {
+            ConnectionState::Connected => Ok(()),
+            ConnectionState::Degraded | ConnectionState::Closed | ConnectionState::Migrated => {
+                Err(Error::ConnectionClosed)
+            }
+        }
+    }
+
     /// Creates an RPC client by connecting to multiple socket address.
     pub fn multi_connect<A: ToSocketAddrs>(addrs: Vec<A>) -> Result<Self, Error> {
         let connect_addrs: Vec<SocketAddr> = addrs
@@ -354,11 +1760,12 @@ impl ClientStub {
         let mut handles = Vec::new();
         let mut vconn = None;
         for addr in connect_addrs {
-            let cmd = Command::Connect(addr);
+            let cmd = Command::Connect(addr, ConnectOptions::default());
             MRPC_CTX.with(|ctx| {
-                ctx.service.send_cmd(cmd).unwrap();
-                let fds = ctx.service.recv_fd().unwrap();
-                match ctx.service.recv_comp().unwrap().0 {
+                let service = ctx.service().unwrap();
+                service.send_cmd(cmd).unwrap();
+                let fds = service.recv_fd().unwrap();
+                match service.recv_comp().unwrap().0 {
                     Ok(CompletionKind::Connect(conn_resp)) => {
                         assert_eq!(fds.len(), conn_resp.read_regions.len());
 
@@ -373,16 +1780,16 @@ impl ClientStub {
 
                         // return the mapped addr back
                         let req = Command::NewMappedAddrs(conn_handle, vaddrs);
-                        ctx.service.send_cmd(req).unwrap();
+                        service.send_cmd(req).unwrap();
                         // wait for the reply!
-                        match ctx.service.recv_comp().unwrap().0 {
+                        match service.recv_comp().unwrap().0 {
                             Ok(CompletionKind::NewMappedAddrs) => {}
                             Err(e) => panic!("{:?}", e),
                             _ => panic!("unmatched branch"),
                         }
 
                         // register the stub with the reactor
-                        let conn = Connection::new(conn_handle, read_heap);
+                        let conn = Connection::new(conn_handle, read_heap, conn_resp.peer_addr);
                         handles.push(conn.handle().clone());
                         conns.push(conn);
                     }
@@ -394,9 +1801,10 @@ impl ClientStub {
             });
         }
         MRPC_CTX.with(|ctx| {
+            let service = ctx.service().unwrap();
             let cmd = Command::MultiConnect(handles);
-            ctx.service.send_cmd(cmd).unwrap();
-            match ctx.service.recv_comp().unwrap().0 {
+            service.send_cmd(cmd).unwrap();
+            match service.recv_comp().unwrap().0 {
                 Ok(CompletionKind::MultiConnect(handle)) => {
                     //assert!(handle == Handle::MASTER);
                     _ = vconn.insert(Connection::vconn(handle));
@@ -416,12 +1824,15 @@ impl ClientStub {
             conn_map.insert(conn.handle().clone(), conn);
         }
         Ok(Self {
-            vconn: vconn.unwrap(),
-            conns: conn_map,
-            inner: spin::Mutex::new(Inner {
-                receiver,
-                reply_cache: ReplyCache::new(),
-            }),
+            vconn: RefCell::new(vconn.unwrap()),
+            conns: RefCell::new(conn_map),
+            addr: None,
+            datagram: false,
+            options: ConnectOptions::default(),
+            stub_id,
+            inner: spin::Mutex::new(Inner::new(receiver)),
+            responses: RefCell::new(ResponseCache::default()),
+            generation: fork::generation(),
         })
     }
 }
@@ -1,8 +1,10 @@
 use std::cell::RefCell;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use phoenix_api::Handle;
 
+use super::context::CancellationToken;
 use super::pending::PendingWRef;
 use crate::{Error, ReadHeap};
 
@@ -24,6 +26,10 @@ pub(crate) struct AliveConnection {
     pub(crate) handle: Handle,
     pub(crate) read_heap: Arc<ReadHeap>,
     pub(crate) pending: PendingWRef,
+    /// The address of the remote end, if known.
+    pub(crate) peer_addr: Option<SocketAddr>,
+    /// Cancelled when the connection is closed.
+    pub(crate) cancel: CancellationToken,
 }
 
 #[derive(Debug)]
@@ -48,9 +54,11 @@ impl std::hash::Hash for Connection {
 
 impl Connection {
     #[inline]
-    pub(crate) fn new(handle: Handle, read_heap: ReadHeap) -> Self {
+    pub(crate) fn new(handle: Handle, read_heap: ReadHeap, peer_addr: Option<SocketAddr>) -> Self {
         Connection {
-            inner: RefCell::new(Inner::Alive(AliveConnection::new(handle, read_heap))),
+            inner: RefCell::new(Inner::Alive(AliveConnection::new(
+                handle, read_heap, peer_addr,
+            ))),
         }
     }
 
@@ -59,6 +67,7 @@ impl Connection {
             inner: RefCell::new(Inner::Alive(AliveConnection::new(
                 handle,
                 ReadHeap::default(),
+                None,
             ))),
         }
     }
@@ -71,6 +80,11 @@ impl Connection {
         }
     }
 
+    #[inline]
+    pub(crate) fn is_alive(&self) -> bool {
+        matches!(&*self.inner.borrow(), Inner::Alive(_))
+    }
+
     #[inline]
     pub(crate) fn handle(&self) -> Handle {
         let inner = self.inner.borrow();
@@ -92,15 +106,18 @@ impl Connection {
 
 impl AliveConnection {
     #[inline]
-    pub(crate) fn new(handle: Handle, read_heap: ReadHeap) -> Self {
+    pub(crate) fn new(handle: Handle, read_heap: ReadHeap, peer_addr: Option<SocketAddr>) -> Self {
         Self {
             handle,
             read_heap: Arc::new(read_heap),
             pending: PendingWRef::new(),
+            peer_addr,
+            cancel: CancellationToken::new(),
         }
     }
 
     pub(crate) fn close(&mut self) -> DeadConnection {
+        self.cancel.cancel();
         DeadConnection {
             handle: self.handle,
         }
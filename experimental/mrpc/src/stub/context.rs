@@ -0,0 +1,134 @@
+//! Per-request information exposed to server handlers.
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use phoenix_api::Handle;
+
+/// A token that signals a handler to stop working on a request early.
+///
+/// The token is shared by all requests arriving on the same connection. It gets cancelled when the
+/// connection is closed or when the server is shutting down.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Constructs a new token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signals cancellation to all clones of this token.
+    #[inline]
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Returns a [`Future`] that resolves once the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled { token: self }
+    }
+}
+
+/// Future returned by [`CancellationToken::cancelled`].
+#[derive(Debug)]
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+}
+
+impl<'a> Future for Cancelled<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        // The server is driven by busy polling, so we just ask to be polled again.
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// The context of an incoming request.
+///
+/// It carries the connection the request arrived on, the address of the peer, the time the
+/// request was dispatched, an optional deadline, and a [`CancellationToken`].
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    conn_handle: Handle,
+    peer_addr: Option<SocketAddr>,
+    arrival: Instant,
+    deadline: Option<Instant>,
+    cancel: CancellationToken,
+}
+
+impl RequestContext {
+    pub(crate) fn new(
+        conn_handle: Handle,
+        peer_addr: Option<SocketAddr>,
+        timeout: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Self {
+        let arrival = Instant::now();
+        RequestContext {
+            conn_handle,
+            peer_addr,
+            arrival,
+            deadline: timeout.map(|t| arrival + t),
+            cancel,
+        }
+    }
+
+    /// Returns the handle of the connection this request arrived on.
+    #[inline]
+    pub fn conn_handle(&self) -> Handle {
+        self.conn_handle
+    }
+
+    /// Returns the socket address of the remote peer, if the transport reports it.
+    #[inline]
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Returns the time when the request was dispatched to the handler.
+    #[inline]
+    pub fn arrival(&self) -> Instant {
+        self.arrival
+    }
+
+    /// Returns the deadline of this request, if the server sets a request timeout.
+    #[inline]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns `true` if the deadline of this request has passed.
+    #[inline]
+    pub fn is_expired(&self) -> bool {
+        self.deadline.map_or(false, |d| Instant::now() >= d)
+    }
+
+    /// Returns the cancellation token of this request.
+    #[inline]
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// Returns `true` if the handler should abort, either because the request is cancelled
+    /// or because its deadline has passed.
+    #[inline]
+    pub fn should_abort(&self) -> bool {
+        self.cancel.is_cancelled() || self.is_expired()
+    }
+}
@@ -0,0 +1,144 @@
+//! The replies of the exactly-once calls kept by a server, see
+//! [`LocalServer::set_dedupe`](super::LocalServer::set_dedupe).
+use std::collections::{hash_map, VecDeque};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap as HashMap;
+
+use phoenix_api::rpc::MessageErased;
+
+use crate::wref::WRefOpaque;
+
+/// The request ID of an exactly-once call, see `MessageMeta::request_id`.
+pub(crate) type RequestId = [u8; 16];
+
+#[derive(Debug)]
+enum Entry<V> {
+    // the handler is running
+    InProgress,
+    Done { reply: V, expires: Instant },
+}
+
+/// The result of looking up a request in the cache.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Lookup<'a, V> {
+    /// The request is new, and is now in progress.
+    New,
+    /// A copy of the request is being handled.
+    InProgress,
+    /// The request has been handled with this reply.
+    Done(&'a V),
+}
+
+#[derive(Debug)]
+pub(crate) struct DedupeCacheT<V> {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<RequestId, Entry<V>>,
+    // the handled requests from the earliest, which also expire first
+    done: VecDeque<RequestId>,
+}
+
+pub(crate) type DedupeCache = DedupeCacheT<(WRefOpaque, MessageErased)>;
+
+impl<V> DedupeCacheT<V> {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        DedupeCacheT {
+            capacity,
+            ttl,
+            entries: HashMap::default(),
+            done: VecDeque::new(),
+        }
+    }
+
+    /// Looks up the request `id`, and marks it in progress if it is new.
+    pub(crate) fn check(&mut self, id: RequestId) -> Lookup<'_, V> {
+        self.purge_expired(Instant::now());
+        match self.entries.entry(id) {
+            hash_map::Entry::Occupied(e) => match e.into_mut() {
+                Entry::Done { reply, .. } => Lookup::Done(reply),
+                Entry::InProgress => Lookup::InProgress,
+            },
+            hash_map::Entry::Vacant(e) => {
+                e.insert(Entry::InProgress);
+                Lookup::New
+            }
+        }
+    }
+
+    /// Keeps the `reply` of the request `id` in progress for the TTL. The earliest replies are
+    /// evicted if more than the capacity are kept. Does nothing if the request is not in
+    /// progress, e.g., the reply is a cached one sent again.
+    pub(crate) fn complete(&mut self, id: RequestId, reply: V) {
+        let Some(entry) = self.entries.get_mut(&id) else {
+            return;
+        };
+        if !matches!(entry, Entry::InProgress) {
+            return;
+        }
+        if self.capacity == 0 {
+            self.entries.remove(&id);
+            return;
+        }
+        *entry = Entry::Done {
+            reply,
+            expires: Instant::now() + self.ttl,
+        };
+        self.done.push_back(id);
+        while self.done.len() > self.capacity {
+            let id = self.done.pop_front().unwrap();
+            self.entries.remove(&id);
+        }
+    }
+
+    fn purge_expired(&mut self, now: Instant) {
+        while let Some(id) = self.done.front() {
+            match self.entries.get(id) {
+                Some(Entry::Done { expires, .. }) if *expires > now => break,
+                _ => {
+                    self.entries.remove(id);
+                    self.done.pop_front();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn id(n: u8) -> RequestId {
+        [n; 16]
+    }
+
+    #[test]
+    fn dedupe() {
+        let mut cache = DedupeCacheT::new(2, TTL);
+        assert_eq!(cache.check(id(1)), Lookup::New);
+        assert_eq!(cache.check(id(1)), Lookup::InProgress);
+        cache.complete(id(1), 'a');
+        assert_eq!(cache.check(id(1)), Lookup::Done(&'a'));
+        // a cached reply sent again is not kept twice
+        cache.complete(id(1), 'b');
+        assert_eq!(cache.check(id(1)), Lookup::Done(&'a'));
+        // the earliest reply is evicted
+        for n in 2..=3 {
+            assert_eq!(cache.check(id(n)), Lookup::New);
+            cache.complete(id(n), 'c');
+        }
+        assert_eq!(cache.check(id(1)), Lookup::New);
+        assert_eq!(cache.check(id(3)), Lookup::Done(&'c'));
+    }
+
+    #[test]
+    fn expire() {
+        let mut cache = DedupeCacheT::new(2, Duration::ZERO);
+        assert_eq!(cache.check(id(1)), Lookup::New);
+        cache.complete(id(1), 'a');
+        assert_eq!(cache.check(id(1)), Lookup::New);
+        assert_eq!(cache.done.len(), 0);
+    }
+}
@@ -0,0 +1,180 @@
+//! [`Send`] handles to the clients and servers that live on threads of their own, for the
+//! applications whose tasks move between threads, e.g., in a multi-thread tokio runtime.
+//!
+//! The connection to the backend, the shared memory heaps and the completion queues of mRPC are
+//! bound to the thread that opens them, so neither [`ClientStub`](super::ClientStub) nor the
+//! futures of its calls are [`Send`]. A [`Dispatcher`] connects a client on a dedicated thread,
+//! and runs the closures it is given there, each as a task of a local executor. Only what a
+//! closure returns crosses threads, which must be owned data rather than [`RRef`]s or [`WRef`]s:
+//! the requests are built and the replies are read on the dispatcher thread.
+//!
+//! ```ignore
+//! let greeter = Dispatcher::spawn(|| GreeterClient::connect("server:5000"))?;
+//! // in any task of a multi-thread runtime
+//! let message = greeter
+//!     .call(|client| async move {
+//!         let req = HelloRequest { name: "mRPC".into() };
+//!         let reply = client.say_hello(req).await?;
+//!         Ok::<_, Status>(reply.message.to_vec())
+//!     })
+//!     .await??;
+//! ```
+//!
+//! [`spawn_server`] does the same for a [`LocalServer`]. The dispatcher threads poll the backend
+//! while they have calls in flight, like any thread that awaits the stubs.
+//!
+//! [`RRef`]: crate::RRef
+//! [`WRef`]: crate::WRef
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{self, LocalPool};
+use futures::future::LocalBoxFuture;
+use futures::task::LocalSpawnExt;
+use futures::StreamExt;
+
+use super::LocalServer;
+use crate::Error;
+
+type Job<C> = Box<dyn FnOnce(Rc<C>) -> LocalBoxFuture<'static, ()> + Send>;
+
+/// A [`Send`] and [`Sync`] handle to a client on a dedicated thread. Clones share the client,
+/// which is dropped on its thread with the last clone, after the calls in flight complete.
+pub struct Dispatcher<C> {
+    jobs: mpsc::UnboundedSender<Job<C>>,
+}
+
+impl<C> Clone for Dispatcher<C> {
+    fn clone(&self) -> Self {
+        Dispatcher {
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl<C> std::fmt::Debug for Dispatcher<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dispatcher").finish_non_exhaustive()
+    }
+}
+
+impl<C: 'static> Dispatcher<C> {
+    /// Starts a thread, and creates the client on it with `connect`, e.g.,
+    /// `|| GreeterClient::connect(addr)`. Returns the error of `connect` if it fails.
+    pub fn spawn<F>(connect: F) -> Result<Self, Error>
+    where
+        F: FnOnce() -> Result<C, Error> + Send + 'static,
+    {
+        let (jobs, mut rx) = mpsc::unbounded::<Job<C>>();
+        let (ready_tx, ready_rx) = std_mpsc::channel();
+        thread::Builder::new()
+            .name("mrpc-dispatcher".to_owned())
+            .spawn(move || {
+                let client = match connect() {
+                    Ok(client) => Rc::new(client),
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                let mut pool = LocalPool::new();
+                let spawner = pool.spawner();
+                pool.run_until(async move {
+                    while let Some(job) = rx.next().await {
+                        // a job whose caller has gone still runs, to keep the stub consistent
+                        spawner
+                            .spawn_local(job(Rc::clone(&client)))
+                            .expect("the pool is running");
+                    }
+                });
+                // all handles are dropped, finish the calls in flight
+                pool.run();
+            })?;
+        ready_rx.recv().map_err(|_| Error::DispatcherExited)??;
+        Ok(Dispatcher { jobs })
+    }
+
+    /// Runs `f` on the thread of the client, and resolves to the output of the future it
+    /// returns. The returned future is [`Send`] as long as the output is.
+    pub fn call<F, Fut, R>(&self, f: F) -> impl Future<Output = Result<R, Error>> + Send
+    where
+        F: FnOnce(Rc<C>) -> Fut + Send + 'static,
+        Fut: Future<Output = R> + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job<C> = Box::new(move |client| {
+            Box::pin(async move {
+                let _ = tx.send(f(client).await);
+            })
+        });
+        let sent = self.jobs.unbounded_send(job).is_ok();
+        async move {
+            if !sent {
+                return Err(Error::DispatcherExited);
+            }
+            rx.await.map_err(|_| Error::DispatcherExited)
+        }
+    }
+}
+
+/// A [`Send`] handle to a server running on a dedicated thread, see [`spawn_server`].
+#[derive(Debug)]
+pub struct ServerHandle {
+    shutdown: Option<oneshot::Sender<()>>,
+    done: oneshot::Receiver<Result<(), Error>>,
+}
+
+impl ServerHandle {
+    /// Resolves when the server stops on its own, e.g., on an error.
+    pub async fn join(self) -> Result<(), Error> {
+        self.done.await.map_err(|_| Error::DispatcherExited)?
+    }
+
+    /// Shuts the server down gracefully, and resolves when it has stopped.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        self.join().await
+    }
+}
+
+/// Starts a thread, binds a server on it with `bind`, which also adds the services, and serves
+/// until the returned handle is shut down. The server is shut down if the handle is dropped.
+pub fn spawn_server<F>(bind: F) -> Result<ServerHandle, Error>
+where
+    F: FnOnce() -> Result<LocalServer, Error> + Send + 'static,
+{
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (done_tx, done_rx) = oneshot::channel();
+    let (ready_tx, ready_rx) = std_mpsc::channel();
+    thread::Builder::new()
+        .name("mrpc-server".to_owned())
+        .spawn(move || {
+            let mut server = match bind() {
+                Ok(server) => server,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+            let shutdown = async {
+                // a dropped handle cancels the channel, which shuts down the server as well
+                let _ = shutdown_rx.await;
+            };
+            let result =
+                executor::block_on(server.serve_with_graceful_shutdown(Box::pin(shutdown)));
+            let _ = done_tx.send(result);
+        })?;
+    ready_rx.recv().map_err(|_| Error::DispatcherExited)??;
+    Ok(ServerHandle {
+        shutdown: Some(shutdown_tx),
+        done: done_rx,
+    })
+}
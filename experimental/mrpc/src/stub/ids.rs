@@ -0,0 +1,55 @@
+//! The IDs of the services and methods used by this process.
+//!
+//! The service and func IDs generated by `mrpc-build` are hashes of their paths, which may
+//! collide across protos built separately. As the generated stubs send their protos to the
+//! backend, they register their IDs here first, and a collision fails the stub rather than
+//! misroutes its calls. A colliding ID is overridden by a line in the comments of the service or
+//! method in the proto, which both `mrpc-build` and the backend read:
+//!
+//! ```protobuf
+//! service Greeter {
+//!   // mrpc.func_id = 42
+//!   rpc SayHello(HelloRequest) returns (HelloReply) {}
+//! }
+//! ```
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+use super::NamedService;
+use crate::Error;
+
+lazy_static! {
+    // The path of each registered ID, services and methods are dispatched by separate IDs.
+    static ref SERVICE_IDS: spin::Mutex<HashMap<u32, &'static str>> =
+        spin::Mutex::new(HashMap::new());
+    static ref FUNC_IDS: spin::Mutex<HashMap<u32, &'static str>> =
+        spin::Mutex::new(HashMap::new());
+}
+
+/// Registers the IDs of the service `S` and its methods. Registering them again is a no-op.
+///
+/// Fails with [`Error::IdCollision`] if an ID is registered for another path already, in which
+/// case none of the IDs of `S` are registered.
+pub fn register<S: NamedService>() -> Result<(), Error> {
+    let mut service_ids = SERVICE_IDS.lock();
+    let mut func_ids = FUNC_IDS.lock();
+    check(&service_ids, S::SERVICE_ID, S::NAME)?;
+    for &(path, func_id) in S::METHODS {
+        check(&func_ids, func_id, path)?;
+    }
+    service_ids.insert(S::SERVICE_ID, S::NAME);
+    func_ids.extend(S::METHODS.iter().map(|&(path, func_id)| (func_id, path)));
+    Ok(())
+}
+
+fn check(ids: &HashMap<u32, &'static str>, id: u32, path: &'static str) -> Result<(), Error> {
+    match ids.get(&id) {
+        Some(&registered) if registered != path => Err(Error::IdCollision(
+            id,
+            registered.to_owned(),
+            path.to_owned(),
+        )),
+        _ => Ok(()),
+    }
+}
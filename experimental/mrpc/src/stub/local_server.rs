@@ -1,11 +1,14 @@
 //! A non-[`Send`] and non-[`Sync`] Server implementation.
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::future::Future;
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::rc::Rc;
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::Duration;
 
-use fnv::FnvHashMap as HashMap;
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
 use futures::future::poll_fn;
 use futures::select;
 use futures::stream::{FuturesUnordered, StreamExt};
@@ -13,17 +16,25 @@ use futures::task::LocalFutureObj;
 use futures::FutureExt;
 
 use ipc::channel::{Receiver, TryRecvError};
-use phoenix_api::rpc::{MessageErased, RpcId, RpcMsgType, TransportStatus};
+use phoenix_api::rpc::{
+    ConnectionState, MessageErased, MessageMeta, RpcId, RpcMsgType, TransportStatus,
+};
 use phoenix_api::{AsHandle, Handle};
-use phoenix_api_mrpc::cmd::{Command, CompletionKind, ConnectResponse};
+use phoenix_api_mrpc::cmd::{BindOptions, Command, CompletionKind, ConnectResponse};
 use phoenix_api_mrpc::dp;
 use phoenix_syscalls::_rx_recv_impl as rx_recv_impl;
 
 use super::conn::Connection;
-use super::service::{NamedService, Service};
+use super::context::RequestContext;
+use super::dedupe::{DedupeCache, Lookup};
+use super::pushback::PushbackPolicy;
+use super::raw::{RawServer, RawService};
+use super::service::{service_unimplemented, NamedService, Service};
 use super::LOCAL_REACTOR;
+use crate::fork;
+use crate::registry::{self, ServiceEndpoint};
 use crate::wref::WRefOpaque;
-use crate::{Error, ReadHeap, MRPC_CTX};
+use crate::{Error, RRef, ReadHeap, MRPC_CTX};
 
 #[cfg(feature = "timing")]
 use crate::timing::{SampleKind, Timer};
@@ -37,22 +48,110 @@ thread_local! {
 pub struct LocalServer {
     stub_id: usize,
     listener_handle: Handle,
-    routes: HashMap<u32, Box<dyn Service>>,
+    routes: HashMap<u32, Route>,
+    // The services added by `add_raw_service`, which only serve raw calls.
+    raw_services: HashSet<u32>,
+    // The services built with `compat`, which also serve the requests in the protobuf wire format.
+    compat_services: HashSet<u32>,
+    // The deadline of a request is set to its arrival time plus this timeout.
+    request_timeout: Option<Duration>,
+    // Flag the replies while the server is congested if set.
+    pushback: Option<PushbackPolicy>,
+    // The replies of the exactly-once calls handled recently if set.
+    dedupe: Option<RefCell<DedupeCache>>,
+    // The number of requests that arrived in the last batch.
+    arrived: Cell<usize>,
     inner: RefCell<Inner>,
+    // The fork generation that the server is bound in.
+    generation: usize,
+    bind_addr: SocketAddr,
+    // The endpoints registered in the name service of the daemon.
+    endpoints: Vec<ServiceEndpoint>,
 }
 
 impl Drop for LocalServer {
     fn drop(&mut self) {
-        eprintln!(
-            "do something with listener_handle {:?}",
-            self.listener_handle
-        );
+        if fork::check(self.generation).is_ok() {
+            for endpoint in &self.endpoints {
+                if let Err(e) = registry::unregister(endpoint) {
+                    log::warn!("Failed to unregister {:?}: {}", endpoint, e);
+                }
+            }
+            // Stop accepting connections, the other servers bound to the address keep
+            // accepting them. Nothing to do if the thread is exiting, the backend releases the
+            // listener along with the engine.
+            let unbound = MRPC_CTX.try_with(|ctx| -> Result<(), Error> {
+                let service = ctx.service()?;
+                service.send_cmd(Command::Unbind(self.listener_handle))?;
+                rx_recv_impl!(service, CompletionKind::Unbind)
+            });
+            if let Ok(Err(e)) = unbound {
+                log::warn!("Failed to unbind {}: {}", self.bind_addr, e);
+            }
+        }
     }
 }
 
 impl !Send for LocalServer {}
 impl !Sync for LocalServer {}
 
+enum Route {
+    // Handlers run on the serving thread.
+    Local(Box<dyn Service>),
+    // Handlers run on the serving thread, a limited number of them at a time.
+    Bounded(Bounded),
+}
+
+struct Bounded {
+    service: Box<dyn Service>,
+    max_concurrency: usize,
+    // Number of requests being handled.
+    in_flight: Rc<Cell<usize>>,
+    // Requests waiting for the concurrency budget, in arrival order.
+    backlog: RefCell<VecDeque<(MessageErased, Arc<ReadHeap>, RequestContext)>>,
+}
+
+impl Bounded {
+    fn spawn<'s>(
+        &'s self,
+        request: MessageErased,
+        read_heap: Arc<ReadHeap>,
+        ctx: RequestContext,
+        running: &mut FuturesUnordered<LocalFutureObj<'s, (WRefOpaque, MessageErased)>>,
+    ) {
+        if self.in_flight.get() >= self.max_concurrency {
+            self.backlog
+                .borrow_mut()
+                .push_back((request, read_heap, ctx));
+            return;
+        }
+
+        let in_flight = Rc::clone(&self.in_flight);
+        in_flight.set(in_flight.get() + 1);
+        let task = self
+            .service
+            .call(request, read_heap, ctx)
+            .map(move |reply| {
+                in_flight.set(in_flight.get() - 1);
+                reply
+            });
+        running.push(LocalFutureObj::new(Box::new(task)));
+    }
+
+    fn spawn_backlog<'s>(
+        &'s self,
+        running: &mut FuturesUnordered<LocalFutureObj<'s, (WRefOpaque, MessageErased)>>,
+    ) {
+        while self.in_flight.get() < self.max_concurrency {
+            let next = self.backlog.borrow_mut().pop_front();
+            match next {
+                Some((request, read_heap, ctx)) => self.spawn(request, read_heap, ctx, running),
+                None => break,
+            }
+        }
+    }
+}
+
 pub(crate) struct Inner {
     // Receiver.
     receiver: Receiver<dp::Completion>,
@@ -69,55 +168,241 @@ impl Inner {
     }
 
     fn close_connection(&mut self, conn_id: Handle) {
-        self.connections.remove(&conn_id);
+        if let Some(conn) = self.connections.remove(&conn_id) {
+            // notify the handlers still working on this connection
+            let _ = conn.map_alive(|alive| alive.cancel.cancel());
+        }
+    }
+
+    fn cancel_all(&self) {
+        for conn in self.connections.values() {
+            let _ = conn.map_alive(|alive| alive.cancel.cancel());
+        }
+    }
+}
+
+/// Builds a [`LocalServer`] provisioned for its expected fan-in.
+///
+/// ```ignore
+/// let mut server = LocalServer::builder()
+///     .max_connections(1024)
+///     .recv_buffers(32, 64 * 1024)
+///     .accept_backlog(2048)
+///     .bind("0.0.0.0:5000")?;
+/// ```
+///
+/// The settings that are not set take the defaults of the backend. When threads of the same
+/// process bind to the same address, the listener keeps the settings of the first of them.
+#[derive(Debug, Clone, Default)]
+pub struct LocalServerBuilder {
+    options: BindOptions,
+}
+
+impl LocalServerBuilder {
+    /// Constructs a builder with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the length of the queue of connections waiting to be accepted.
+    pub fn accept_backlog(mut self, backlog: u32) -> Self {
+        self.options.accept_backlog = Some(backlog);
+        self
+    }
+
+    /// Sets the most connections open at a time. More connections are rejected until some of
+    /// the open ones are gone.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.options.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Sets the number of receive buffers of each connection, i.e., the requests a client can
+    /// have in flight on it, and the size of each buffer in bytes. Larger segments of a message
+    /// are received in fragments and copied together, so the size should fit the common
+    /// messages. The size must be a power of two of at least 4096 bytes, and the buffers of a
+    /// connection take at most 1 GiB; otherwise binding fails.
+    pub fn recv_buffers(mut self, count: usize, size: usize) -> Self {
+        self.options.recv_buffers = Some((count, size));
+        self
+    }
+
+    /// Accepts connections over unreliable datagrams rather than reliable ones, see
+    /// [`ClientStub::connect_datagram`](super::ClientStub::connect_datagram). Only the
+    /// RDMA transport supports it.
+    pub fn datagram(mut self, enable: bool) -> Self {
+        self.options.datagram = enable;
+        self
+    }
+
+    /// Binds the server to the provided [socket address][ToSocketAddrs], see
+    /// [`LocalServer::bind`].
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> Result<LocalServer, Error> {
+        LocalServer::bind_with_options(addr, self.options)
     }
 }
 
 impl LocalServer {
+    /// Returns a builder to set up the listener of the server before binding it.
+    pub fn builder() -> LocalServerBuilder {
+        LocalServerBuilder::new()
+    }
+
     /// Bind to the provided [socket address][ToSocketAddrs].
     ///
+    /// Threads of the same process may bind to the same address, in which case the incoming
+    /// connections are distributed among them.
+    ///
     /// Construct itself on success. Returns an [`enum@Error`] otherwise.
     pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        Self::bind_with_options(addr, BindOptions::default())
+    }
+
+    fn bind_with_options<A: ToSocketAddrs>(addr: A, options: BindOptions) -> Result<Self, Error> {
         let bind_addr = addr
             .to_socket_addrs()?
             .next()
             .ok_or(Error::NoAddrResolved)?;
-        let req = Command::Bind(bind_addr);
+        let req = Command::Bind(bind_addr, options);
         MRPC_CTX.with(|ctx| {
-            ctx.service.send_cmd(req)?;
-            rx_recv_impl!(ctx.service, CompletionKind::Bind, listener_handle, {
+            let service = ctx.service()?;
+            service.send_cmd(req)?;
+            rx_recv_impl!(service, CompletionKind::Bind, listener_handle, {
                 let (stub_id, receiver) = LOCAL_REACTOR.with_borrow_mut(|r| r.register_stub());
 
                 Ok(Self {
                     stub_id,
                     listener_handle,
                     routes: HashMap::default(),
+                    raw_services: HashSet::default(),
+                    compat_services: HashSet::default(),
+                    request_timeout: None,
+                    pushback: None,
+                    dedupe: None,
+                    arrived: Cell::new(0),
                     inner: RefCell::new(Inner {
                         connections: HashMap::default(),
                         receiver,
                     }),
+                    generation: fork::generation(),
+                    bind_addr,
+                    endpoints: Vec::new(),
                 })
             })
         })
     }
 
-    /// Add an RPC [`Service`] to the server.
+    /// Add an RPC [`Service`] to the server. The service is registered in the name service of the
+    /// daemon, see [`registry`].
     ///
     /// # Panics
     ///
     /// Panics on duplicate [`NamedService::SERVICE_ID`].
     pub fn add_service<S: Service + NamedService + 'static>(&mut self, svc: S) -> &mut Self {
-        if self.routes.insert(S::SERVICE_ID, Box::new(svc)).is_some() {
-            panic!("Hash collisions in func_id: {}", S::SERVICE_ID);
+        self.add_route(S::SERVICE_ID, Route::Local(Box::new(svc)));
+        self.register_endpoint::<S>()
+    }
+
+    /// Add an RPC [`Service`] of which at most `max_concurrency` requests are handled at a
+    /// time. The other requests wait in arrival order, and are counted as running by the
+    /// [`PushbackPolicy`].
+    ///
+    /// Every request is handled as an independent task on the thread that polls the server,
+    /// like those of [`LocalServer::add_service`], so a handler awaiting something does not
+    /// delay the requests behind it. The handlers must not block the thread, since the requests
+    /// and the replies live in the shared memory heaps of this thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics on duplicate [`NamedService::SERVICE_ID`], or if `max_concurrency` is zero.
+    pub fn add_service_bounded<S>(&mut self, svc: S, max_concurrency: usize) -> &mut Self
+    where
+        S: Service + NamedService + 'static,
+    {
+        assert!(max_concurrency > 0, "max_concurrency must be positive");
+        let bounded = Bounded {
+            service: Box::new(svc),
+            max_concurrency,
+            in_flight: Rc::new(Cell::new(0)),
+            backlog: RefCell::new(VecDeque::new()),
+        };
+        self.add_route(S::SERVICE_ID, Route::Bounded(bounded));
+        self.register_endpoint::<S>()
+    }
+
+    /// Add a [`RawService`] serving the raw calls of `service_id`, see [`ClientStub::raw_call`].
+    /// The service is not registered in the name service, as it has no name.
+    ///
+    /// # Panics
+    ///
+    /// Panics on duplicate `service_id`.
+    ///
+    /// [`ClientStub::raw_call`]: super::ClientStub::raw_call
+    pub fn add_raw_service<S: RawService>(&mut self, service_id: u32, svc: S) -> &mut Self {
+        self.raw_services.insert(service_id);
+        self.add_route(service_id, Route::Local(Box::new(RawServer(svc))))
+    }
+
+    fn register_endpoint<S: NamedService>(&mut self) -> &mut Self {
+        if S::COMPAT {
+            self.compat_services.insert(S::SERVICE_ID);
+        }
+        let endpoint = ServiceEndpoint {
+            service: S::NAME.to_owned(),
+            host: self.bind_addr.ip().to_string(),
+            port: self.bind_addr.port(),
+            proto_hash: registry::proto_hash::<S>(),
+        };
+        // the service can still be reached by its address, so only warn on failures
+        match registry::register(&endpoint) {
+            Ok(()) => self.endpoints.push(endpoint),
+            Err(e) => log::warn!("Failed to register {:?}: {}", endpoint, e),
         }
         self
     }
 
+    fn add_route(&mut self, service_id: u32, route: Route) -> &mut Self {
+        if self.routes.insert(service_id, route).is_some() {
+            panic!("Hash collisions in func_id: {}", service_id);
+        }
+        self
+    }
+
+    /// Set a timeout for every request served by this server.
+    ///
+    /// Handlers can observe the resulting deadline through [`RequestContext::deadline`].
+    pub fn set_request_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Asks the clients to slow down while the server is congested, see [`PushbackPolicy`].
+    pub fn set_pushback(&mut self, policy: PushbackPolicy) -> &mut Self {
+        self.pushback = Some(policy);
+        self
+    }
+
+    /// Keeps the replies of the exactly-once calls (see [`ClientStub::set_exactly_once`]) for
+    /// `ttl` after they are sent, at most `capacity` of them, so that a retransmitted request
+    /// is answered with the reply of the first one instead of running the handler again.
+    ///
+    /// A copy of a request that arrives while the request is being handled is dropped, the
+    /// client gets the reply of the first one. The `ttl` should cover the retransmissions of the
+    /// clients, and the cached replies hold their memory on the shared heap until evicted.
+    ///
+    /// [`ClientStub::set_exactly_once`]: super::ClientStub::set_exactly_once
+    pub fn set_dedupe(&mut self, capacity: usize, ttl: Duration) -> &mut Self {
+        self.dedupe = Some(RefCell::new(DedupeCache::new(capacity, ttl)));
+        self
+    }
+
     /// Receive data from read shared heap and look up the routes and dispatch the erased message.
     ///
     /// Returns an [`Future`] that should be run by an `Executor`. The [`Future`] resolves to a
     /// `Result` indicating any error during serving.
     pub async fn serve(&mut self) -> Result<(), Error> {
+        fork::check(self.generation)?;
+
         // running tasks
         let mut running = FuturesUnordered::new();
         running.push(LocalFutureObj::new(Box::new(std::future::pending())));
@@ -141,10 +426,13 @@ impl LocalServer {
                     default => {
                         // TODO(cjr): Having the default branch is not cpu efficient
                         if !reply_buffer.is_empty() {
-                            self.post_replies(&mut reply_buffer)?;
+                            // the pending future is not a request
+                            self.post_replies(&mut reply_buffer, running.len() - 1)?;
                         }
                         // no futures is ready
                         self.check_cm_event()?;
+                        // spawn the requests waiting for the concurrency budget
+                        self.dispatch_backlog(&mut running);
                         // check new requests, dispatch them to the executor
                         match LOCAL_REACTOR.with_borrow_mut(|r| r.poll(cx)) {
                             Poll::Ready(Ok(n)) if n > 0 => self.dispatch_requests(&mut running)?,
@@ -167,6 +455,7 @@ impl LocalServer {
     where
         F: Future<Output = ()> + Unpin,
     {
+        fork::check(self.generation)?;
         let mut shutdown = shutdown.fuse();
 
         // running tasks
@@ -187,6 +476,8 @@ impl LocalServer {
                     //     self.dispatch_requests(&mut running)?;
                     // }
                     _ = shutdown => {
+                        // tell the running handlers to abort early
+                        self.inner.borrow().cancel_all();
                         break Poll::Ready(Ok(()));
                     },
                     complete => {
@@ -195,10 +486,13 @@ impl LocalServer {
                     default => {
                         // TODO(cjr): Having the default branch is not cpu efficient
                         if !reply_buffer.is_empty() {
-                            self.post_replies(&mut reply_buffer)?;
+                            // the pending future is not a request
+                            self.post_replies(&mut reply_buffer, running.len() - 1)?;
                         }
                         // no futures is ready
                         self.check_cm_event()?;
+                        // spawn the requests waiting for the concurrency budget
+                        self.dispatch_backlog(&mut running);
                         // check new requests, dispatch them to the executor
                         match LOCAL_REACTOR.with_borrow_mut(|r| r.poll(cx)) {
                             Poll::Ready(Ok(n)) if n > 0 => self.dispatch_requests(&mut running)?,
@@ -219,7 +513,7 @@ impl LocalServer {
         conn_resp: ConnectResponse,
         ctx: &crate::Context,
     ) -> Result<(), Error> {
-        match ctx.service.recv_fd() {
+        match ctx.service()?.recv_fd() {
             Ok(fds) => {
                 let conn_handle = conn_resp.conn_handle;
                 assert_eq!(fds.len(), conn_resp.read_regions.len());
@@ -232,7 +526,7 @@ impl LocalServer {
                     .collect();
 
                 // register connection to the reactor
-                let conn = Connection::new(conn_handle, read_heap);
+                let conn = Connection::new(conn_handle, read_heap, conn_resp.peer_addr);
                 LOCAL_REACTOR.with_borrow_mut(|r| r.register_connection(self.stub_id, &conn));
 
                 // update connection set
@@ -246,7 +540,7 @@ impl LocalServer {
 
                 // update backend addr mapping
                 let req = Command::NewMappedAddrs(conn_handle, vaddrs);
-                ctx.service.send_cmd(req)?;
+                ctx.service()?.send_cmd(req)?;
                 // NO NEED TO WAIT
                 Ok(())
             }
@@ -256,7 +550,7 @@ impl LocalServer {
 
     fn check_cm_event(&self) -> Result<(), Error> {
         MRPC_CTX.with(|ctx| {
-            match ctx.service.try_recv_comp().map(|comp| comp.0) {
+            match ctx.service()?.try_recv_comp().map(|comp| comp.0) {
                 Err(ipc::Error::TryRecv(ipc::TryRecvError::Empty)) => {}
                 Err(e) => return Err(e.into()),
                 Ok(compkind) => {
@@ -276,7 +570,46 @@ impl LocalServer {
         })
     }
 
-    fn post_replies(&self, msg_buffer: &mut Vec<(WRefOpaque, MessageErased)>) -> Result<(), Error> {
+    /// Returns whether the server is congested with `running` requests being handled, see
+    /// [`PushbackPolicy`].
+    fn congested(&self, running: usize) -> bool {
+        let Some(policy) = self.pushback.as_ref() else {
+            return false;
+        };
+        let waiting: usize = self
+            .routes
+            .values()
+            .map(|route| match route {
+                Route::Local(_) => 0,
+                Route::Bounded(s) => s.backlog.borrow().len(),
+            })
+            .sum();
+        policy.congested(self.arrived.get(), running + waiting)
+    }
+
+    fn post_replies(
+        &self,
+        msg_buffer: &mut Vec<(WRefOpaque, MessageErased)>,
+        running: usize,
+    ) -> Result<(), Error> {
+        // the replies to posted requests are not sent
+        msg_buffer.retain(|m| m.1.meta.msg_type != RpcMsgType::Post);
+
+        if let Some(dedupe) = self.dedupe.as_ref() {
+            let mut dedupe = dedupe.borrow_mut();
+            for (wref, erased) in msg_buffer.iter() {
+                if erased.meta.exactly_once() {
+                    dedupe.complete(erased.meta.request_id, (wref.clone(), *erased));
+                }
+            }
+        }
+
+        if self.congested(running) {
+            for (_, erased) in msg_buffer.iter_mut() {
+                erased.meta.flags |= MessageMeta::PUSHBACK;
+            }
+        }
+
         // track the msg as pending
 
         for m in msg_buffer.iter() {
@@ -290,15 +623,27 @@ impl LocalServer {
             })?;
         }
 
-        let num = msg_buffer.len();
+        // a reply sent in bulk is preceded by a notice of it
+        let wrs: Vec<_> = msg_buffer
+            .iter()
+            .flat_map(|(wref, erased)| {
+                let rpc_id = RpcId::new(erased.meta.conn_id, erased.meta.call_id);
+                [
+                    wref.is_bulk().then_some(dp::WorkRequest::Bulk(rpc_id)),
+                    Some(dp::WorkRequest::Reply(*erased)),
+                ]
+            })
+            .flatten()
+            .collect();
+        let num = wrs.len();
         let mut sent = 0;
         MRPC_CTX.with(|ctx| {
+            let service = ctx.service()?;
             while sent < num {
-                ctx.service.enqueue_wr_with(|ptr, count| unsafe {
+                service.enqueue_wr_with(|ptr, count| unsafe {
                     let to_send = (num - sent).min(count);
                     for i in 0..to_send {
-                        let wr = dp::WorkRequest::Reply(msg_buffer[sent + i].1);
-                        ptr.add(i).cast::<dp::WorkRequest>().write(wr);
+                        ptr.add(i).cast::<dp::WorkRequest>().write(wrs[sent + i]);
                     }
                     sent += to_send;
                     to_send
@@ -320,22 +665,50 @@ impl LocalServer {
         match *comp {
             dp::Completion::Incoming(request) => {
                 match request.meta.msg_type {
-                    RpcMsgType::Request => {
+                    RpcMsgType::Request | RpcMsgType::Post => {
                         // server receives requests
                         // todo!("do something with the request");
                         let service_id = request.meta.service_id;
-                        match self.routes.get(&service_id) {
-                            Some(s) => {
+                        // raw calls and the calls of a proto are not routed to each other, except
+                        // the encoded calls of a compat service
+                        let routable = if request.meta.compat() {
+                            self.compat_services.contains(&service_id)
+                        } else {
+                            self.raw_services.contains(&service_id) == request.meta.raw()
+                        };
+                        let route = self.routes.get(&service_id);
+                        match route.filter(|_| routable) {
+                            Some(_) if self.deduplicate(&request, inner, running)? => {}
+                            Some(route) => {
                                 let conn = inner.get_connection(request.meta.conn_id)?;
                                 // the connection has disappeared, do nothing
 
-                                let read_heap =
-                                    conn.map_alive(|alive| Arc::clone(&alive.read_heap))?;
-                                let task = LocalFutureObj::new(s.call(request, read_heap));
-                                running.push(task);
+                                let (read_heap, ctx) = conn.map_alive(|alive| {
+                                    let ctx = RequestContext::new(
+                                        alive.handle,
+                                        alive.peer_addr,
+                                        self.request_timeout,
+                                        alive.cancel.clone(),
+                                    );
+                                    (Arc::clone(&alive.read_heap), ctx)
+                                })?;
+                                match route {
+                                    Route::Local(s) => {
+                                        let task =
+                                            LocalFutureObj::new(s.call(request, read_heap, ctx));
+                                        running.push(task);
+                                    }
+                                    Route::Bounded(s) => s.spawn(request, read_heap, ctx, running),
+                                }
                             }
                             None => {
-                                log::warn!("unrecognized request: {:?}", request);
+                                // reply so the client does not wait for the call forever
+                                let read_heap = inner
+                                    .get_connection(request.meta.conn_id)?
+                                    .map_alive(|alive| Arc::clone(&alive.read_heap))?;
+                                let reply = service_unimplemented(&request, read_heap);
+                                let task = LocalFutureObj::new(Box::new(std::future::ready(reply)));
+                                running.push(task);
                             }
                         }
                     }
@@ -370,20 +743,98 @@ impl LocalServer {
                 );
                 inner.close_connection(conn_id);
             }
+            dp::Completion::ConnectionState(conn_id, state) => {
+                log::debug!("Connection {:?} changes state to {:?}", conn_id, state);
+                match state {
+                    ConnectionState::Closed => inner.close_connection(conn_id),
+                    // taken over by the server of another thread, the handlers still working on
+                    // the connection are not cancelled
+                    ConnectionState::Migrated => {
+                        inner.connections.remove(&conn_id);
+                    }
+                    ConnectionState::Connected | ConnectionState::Degraded => {}
+                }
+            }
+            dp::Completion::CallTiming(..) => {
+                // only the calls issued by clients are timed
+            }
         }
 
         Ok(())
     }
 
+    /// Answers a copy of an exactly-once request that has been handled with the cached reply,
+    /// see [`LocalServer::set_dedupe`]. Returns whether the request is a copy, whose handler is
+    /// not run again.
+    fn deduplicate<'s>(
+        &'s self,
+        request: &MessageErased,
+        inner: &Inner,
+        running: &mut FuturesUnordered<LocalFutureObj<'s, (WRefOpaque, MessageErased)>>,
+    ) -> Result<bool, Error> {
+        let Some(dedupe) = self.dedupe.as_ref() else {
+            return Ok(false);
+        };
+        if !request.meta.exactly_once() || request.meta.msg_type != RpcMsgType::Request {
+            return Ok(false);
+        }
+        let reply = match dedupe.borrow_mut().check(request.meta.request_id) {
+            Lookup::New => return Ok(false),
+            Lookup::InProgress => None,
+            // the cached reply goes to the call of the copy
+            Lookup::Done((wref, erased)) => Some((
+                wref.clone(),
+                MessageErased {
+                    meta: MessageMeta {
+                        msg_type: erased.meta.msg_type,
+                        status_code: erased.meta.status_code,
+                        flags: erased.meta.flags,
+                        ..request.meta
+                    },
+                    ..*erased
+                },
+            )),
+        };
+
+        // the copy is never read, release its receive buffer
+        let read_heap = inner
+            .get_connection(request.meta.conn_id)?
+            .map_alive(|alive| Arc::clone(&alive.read_heap))?;
+        drop(RRef::<()>::new(request, read_heap));
+        match reply {
+            Some(reply) => running.push(LocalFutureObj::new(Box::new(std::future::ready(reply)))),
+            None => log::debug!(
+                "Drop a copy of request {:?} in progress",
+                RpcId::new(request.meta.conn_id, request.meta.call_id)
+            ),
+        }
+        Ok(true)
+    }
+
+    fn dispatch_backlog<'s>(
+        &'s self,
+        running: &mut FuturesUnordered<LocalFutureObj<'s, (WRefOpaque, MessageErased)>>,
+    ) {
+        for route in self.routes.values() {
+            if let Route::Bounded(s) = route {
+                s.spawn_backlog(running);
+            }
+        }
+    }
+
     fn dispatch_requests<'s>(
         &'s self,
         running: &mut FuturesUnordered<LocalFutureObj<'s, (WRefOpaque, MessageErased)>>,
     ) -> Result<(), Error> {
         let mut inner = self.inner.borrow_mut();
+        let mut arrived = 0;
         loop {
             let item = inner.receiver.try_recv();
             match item {
                 Ok(ref comp) => {
+                    if let dp::Completion::Incoming(_) = comp {
+                        arrived += 1;
+                    }
                     self.dispatch_one_request(comp, &mut inner, running)?;
                 }
                 Err(TryRecvError::Empty) => break,
@@ -392,6 +843,7 @@ impl LocalServer {
                 }
             }
         }
+        self.arrived.set(arrived);
 
         Ok(())
     }
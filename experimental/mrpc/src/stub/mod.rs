@@ -4,23 +4,53 @@ use std::cell::RefCell;
 use crate::{Error, MRPC_CTX};
 
 // Re-exports
-pub use phoenix_api::rpc::{MessageErased, MessageMeta, RpcMsgType};
+pub use phoenix_api::rpc::{ConnectionState, MessageErased, MessageMeta, RpcMsgType};
+pub use phoenix_api::Handle;
+pub use phoenix_api_mrpc::cmd::ConnectOptions;
 pub use phoenix_api_mrpc::control_plane::TransportType;
 
 mod service;
-pub use service::{service_post_handler, service_pre_handler, NamedService, Service};
+pub use service::{
+    compat_post_handler, compat_pre_handler, service_error, service_post_handler,
+    service_pre_handler, service_unimplemented, NamedService, Service,
+};
+
+pub mod ids;
 
 mod client;
-pub use client::{ClientStub, ReqFuture};
+pub use client::{BroadcastStream, CallTiming, ChannelStatus, ClientStub, ReqFuture, WarmUp};
+
+mod context;
+pub use context::{CancellationToken, Cancelled, RequestContext};
+
+mod reconnect;
+pub use reconnect::ReconnectPolicy;
+
+mod retry;
+pub use retry::RetryPolicy;
+
+mod pushback;
+pub use pushback::PushbackPolicy;
+
+mod raw;
+pub use raw::{RawMessage, RawService};
 
 mod local_server;
 pub mod server;
-pub use local_server::LocalServer;
+pub use local_server::{LocalServer, LocalServerBuilder};
 
 pub(crate) mod conn;
 pub(crate) mod pending;
 pub(crate) mod reply_cache;
 
+mod response_cache;
+pub use response_cache::CacheStats;
+
+mod dedupe;
+
+mod dispatcher;
+pub use dispatcher::{spawn_server, Dispatcher, ServerHandle};
+
 // We can make RpcData a private trait, and only mark it for compiler generated types.
 // This seems impossible.
 /// Auto trait implmented for RPC request types that is safe to move to the writable shared memory heap.
@@ -38,3 +68,9 @@ thread_local! {
 pub fn update_protos(protos: &[&str]) -> Result<(), Error> {
     MRPC_CTX.with(|ctx| ctx.update_protos(protos))
 }
+
+/// Runs a future to completion on the current thread, used by the generated blocking clients.
+#[doc(hidden)]
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    futures::executor::block_on(future)
+}
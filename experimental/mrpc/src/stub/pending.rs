@@ -28,13 +28,34 @@ impl PendingWRef {
 
     #[inline]
     pub(crate) fn insert_opaque(&self, rpc_id: RpcId, wref_opaque: WRefOpaque) {
-        self.pool.borrow_mut().insert(rpc_id, wref_opaque);
+        wref_opaque.sends().begin();
+        let old = self.pool.borrow_mut().insert(rpc_id, wref_opaque);
+        if let Some(old) = old {
+            old.sends().end();
+        }
+    }
+
+    /// Returns the number of WRefs held.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.pool.borrow().len()
     }
 
     #[inline]
     pub(crate) fn remove(&self, rpc_id: &RpcId) {
-        if self.pool.borrow_mut().remove(rpc_id).is_none() {
-            panic!("PendingWRef::remove: rpc_id {:?} not found", rpc_id);
+        let wref_opaque = self.pool.borrow_mut().remove(rpc_id);
+        match wref_opaque {
+            Some(wref_opaque) => wref_opaque.sends().end(),
+            None => panic!("PendingWRef::remove: rpc_id {:?} not found", rpc_id),
+        }
+    }
+}
+
+impl Drop for PendingWRef {
+    fn drop(&mut self) {
+        // the connection is gone, so the backend no longer sends the messages
+        for (_rpc_id, wref_opaque) in self.pool.get_mut().drain() {
+            wref_opaque.sends().end();
         }
     }
 }
@@ -0,0 +1,50 @@
+//! Congestion signal of servers to their clients.
+
+/// Controls when a [`LocalServer`] asks its clients to slow down.
+///
+/// The server is congested when more requests than `queue_depth` arrive in a batch from the
+/// backend, or when more requests than `concurrency` are being handled or waiting for a handler.
+/// While it is congested, it flags its replies (see [`RRef::pushback`]), and the clients are
+/// expected to send less, e.g., a [`ClientStub`] holds its retransmissions back for the pushback
+/// delay of its [`RetryPolicy`], and an application may shed its own load by checking
+/// [`ClientStub::pushed_back`]. The flag is advisory, the server handles the requests all the
+/// same.
+///
+/// [`LocalServer`]: super::LocalServer
+/// [`RRef::pushback`]: crate::RRef::pushback
+/// [`ClientStub`]: super::ClientStub
+/// [`RetryPolicy`]: super::RetryPolicy
+/// [`ClientStub::pushed_back`]: super::ClientStub::pushed_back
+#[derive(Debug, Clone, Default)]
+pub struct PushbackPolicy {
+    queue_depth: Option<usize>,
+    concurrency: Option<usize>,
+}
+
+impl PushbackPolicy {
+    /// Constructs a policy that never pushes back.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pushes back when more than `queue_depth` requests arrive at once.
+    pub fn queue_depth(mut self, queue_depth: usize) -> Self {
+        self.queue_depth = Some(queue_depth);
+        self
+    }
+
+    /// Pushes back when more than `concurrency` requests are being handled or waiting for a
+    /// handler.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    /// Returns whether the server is congested with `queued` requests in the last batch and
+    /// `handling` requests being handled.
+    #[inline]
+    pub(crate) fn congested(&self, queued: usize, handling: usize) -> bool {
+        self.queue_depth.map_or(false, |max| queued > max)
+            || self.concurrency.map_or(false, |max| handling > max)
+    }
+}
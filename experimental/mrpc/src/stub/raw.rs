@@ -0,0 +1,48 @@
+//! Raw calls, whose requests and replies are byte strings defined by the application rather than
+//! the messages of a proto.
+//!
+//! The backend sends the bytes of a raw message as they are, without loading a dispatch library,
+//! and the receiver reads them in place, as it does for the messages of a proto. A raw call is
+//! issued by [`ClientStub::raw_call`] and served by a [`RawService`] added by
+//! [`LocalServer::add_raw_service`]. Raw calls are only routed to raw services, and the calls of
+//! a proto only to the services generated from it.
+//!
+//! [`ClientStub::raw_call`]: super::ClientStub::raw_call
+//! [`LocalServer::add_raw_service`]: super::LocalServer::add_raw_service
+use std::sync::Arc;
+
+use phoenix_api::rpc::{MessageErased, MessageMeta};
+
+use super::context::RequestContext;
+use super::service::{service_post_handler, Service};
+use crate::{RRef, ReadHeap, WRef, WRefOpaque};
+
+/// The request or reply of a raw call, a byte string on the shared heap.
+pub type RawMessage = crate::alloc::Vec<u8>;
+
+/// A service whose methods are called by raw calls.
+#[crate::async_trait]
+pub trait RawService: Send + Sync + 'static {
+    /// Handles a raw call of the method `func_id`, and returns the reply. Errors are up to the
+    /// application to encode in the reply.
+    async fn call(&self, func_id: u32, request: RRef<RawMessage>) -> WRef<RawMessage>;
+}
+
+/// Serves the raw calls of a service with a [`RawService`].
+pub(crate) struct RawServer<S>(pub(crate) S);
+
+#[crate::async_trait]
+impl<S: RawService> Service for RawServer<S> {
+    async fn call(
+        &self,
+        req_opaque: MessageErased,
+        read_heap: Arc<ReadHeap>,
+        ctx: RequestContext,
+    ) -> (WRefOpaque, MessageErased) {
+        let req = RRef::with_context(&req_opaque, read_heap, ctx);
+        let reply = self.0.call(req_opaque.meta.func_id, req).await;
+        let (reply_opaque, mut erased) = service_post_handler(reply, &req_opaque);
+        erased.meta.flags |= MessageMeta::RAW;
+        (reply_opaque, erased)
+    }
+}
@@ -64,8 +64,11 @@ impl Reactor {
 
             unsafe { self.buffer.set_len(0) };
 
+            // reclaim the receive buffers of the messages released by views on other threads
+            ctx.released.drop_all();
+
             // read completions into a local buffer
-            ctx.service
+            ctx.service()?
                 .dequeue_wc_with(|ptr, count| unsafe {
                     for i in 0..count {
                         let c = ptr.add(i).cast::<dp::Completion>().read();
@@ -84,6 +87,8 @@ impl Reactor {
                     dp::Completion::Incoming(msg) => msg.meta.conn_id,
                     dp::Completion::Outgoing(rpc_id, _status) => rpc_id.0,
                     dp::Completion::RecvError(conn_id, _status) => *conn_id,
+                    dp::Completion::ConnectionState(conn_id, _state) => *conn_id,
+                    dp::Completion::CallTiming(rpc_id, _timing) => rpc_id.0,
                 };
 
                 // find the stub and push the completion to that stub
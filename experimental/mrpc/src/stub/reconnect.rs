@@ -0,0 +1,109 @@
+//! Automatic reconnection policy for client stubs.
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Controls whether and how a [`ClientStub`] re-establishes a broken connection.
+///
+/// When the connection is lost, the stub reconnects to the same address with exponential
+/// backoff and jitter, reloads the protos at the backend, and maps the read regions of the new
+/// connection. In-flight calls to methods marked as idempotent are replayed on the new
+/// connection if `replay_idempotent` is set; all other in-flight calls fail with
+/// [`Code::Unavailable`].
+///
+/// [`ClientStub`]: super::ClientStub
+/// [`Code::Unavailable`]: crate::Code::Unavailable
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    max_retries: Option<u32>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    replay_idempotent: bool,
+    // func_ids of the idempotent methods
+    idempotent: HashSet<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_retries: Some(8),
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+            jitter: 0.2,
+            replay_idempotent: false,
+            idempotent: HashSet::new(),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Constructs a policy with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximal number of attempts before giving up. `None` retries forever.
+    pub fn max_retries(mut self, max_retries: Option<u32>) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the delay before the first attempt.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound of the delay between two attempts.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the factor the delay grows by after each failed attempt.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Sets the jitter as a fraction of the delay, e.g., `0.2` randomizes the delay by ±20%.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Enables or disables replaying in-flight calls to idempotent methods.
+    pub fn replay_idempotent(mut self, enable: bool) -> Self {
+        self.replay_idempotent = enable;
+        self
+    }
+
+    /// Marks a method as idempotent, so it is safe to be replayed after reconnection.
+    ///
+    /// The path takes the gRPC form, e.g., `/rpc_hello.Greeter/SayHello`.
+    pub fn idempotent_method(mut self, path: &str) -> Self {
+        // Must be consistent with how mrpc-build computes the func_id.
+        self.idempotent.insert(crc32fast::hash(path.as_bytes()));
+        self
+    }
+
+    #[inline]
+    pub(crate) fn should_retry(&self, attempt: u32) -> bool {
+        self.max_retries.map_or(true, |max| attempt < max)
+    }
+
+    #[inline]
+    pub(crate) fn is_replayable(&self, func_id: u32) -> bool {
+        self.replay_idempotent && self.idempotent.contains(&func_id)
+    }
+
+    /// Returns the delay before the given attempt (counting from 0).
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let base = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        let base = base.min(self.max_backoff.as_secs_f64());
+        let factor = 1.0 + self.jitter * (fastrand::f64() * 2.0 - 1.0);
+        Duration::from_secs_f64(base * factor)
+    }
+}
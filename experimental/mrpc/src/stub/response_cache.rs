@@ -0,0 +1,203 @@
+//! The replies of the cacheable methods kept by a client, see
+//! [`ClientStub::unary_cached`](super::ClientStub::unary_cached).
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// The number of replies a client caches by default.
+pub(crate) const DEFAULT_CAPACITY: usize = 32;
+
+/// Counters of the response cache of a client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The calls answered from the cache
+    pub hits: u64,
+    /// The calls of cacheable methods sent to the server
+    pub misses: u64,
+    /// The replies dropped to make room for new ones
+    pub evictions: u64,
+    /// The replies dropped since they have lived longer than their TTL
+    pub expirations: u64,
+    /// The replies in the cache
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    func_id: u32,
+    // the encoded request
+    request: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct CacheEntry<V> {
+    reply: V,
+    expires: Instant,
+    // the position in the LRU order
+    tick: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct ResponseCacheT<V> {
+    capacity: usize,
+    entries: HashMap<CacheKey, CacheEntry<V>>,
+    // the keys from the least recently used
+    lru: BTreeMap<u64, CacheKey>,
+    next_tick: u64,
+    stats: CacheStats,
+}
+
+pub(crate) type ResponseCache = ResponseCacheT<Box<dyn Any>>;
+
+impl<V> Default for ResponseCacheT<V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl<V> ResponseCacheT<V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ResponseCacheT {
+            capacity,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Returns the cached reply of `request` to `func_id` if it has not expired.
+    pub(crate) fn get(&mut self, func_id: u32, request: Vec<u8>) -> Option<&V> {
+        let key = CacheKey { func_id, request };
+        let (expires, tick) = match self.entries.get(&key) {
+            Some(entry) => (entry.expires, entry.tick),
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+        self.lru.remove(&tick);
+        if expires <= Instant::now() {
+            self.entries.remove(&key);
+            self.stats.expirations += 1;
+            self.stats.misses += 1;
+            return None;
+        }
+        self.stats.hits += 1;
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.tick = tick;
+        self.lru.insert(tick, key);
+        Some(&entry.reply)
+    }
+
+    /// Caches the reply of `request` to `func_id` for `ttl`.
+    pub(crate) fn insert(&mut self, func_id: u32, request: Vec<u8>, reply: V, ttl: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = CacheKey { func_id, request };
+        if let Some(old) = self.entries.remove(&key) {
+            // the same request may have been sent again before the first reply arrived
+            self.lru.remove(&old.tick);
+        }
+        if self.entries.len() >= self.capacity {
+            self.purge_expired();
+        }
+        while self.entries.len() >= self.capacity {
+            self.evict_lru();
+        }
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.lru.insert(tick, key.clone());
+        let expires = Instant::now() + ttl;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                reply,
+                expires,
+                tick,
+            },
+        );
+    }
+
+    fn purge_expired(&mut self) {
+        let now = Instant::now();
+        let lru = &mut self.lru;
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| {
+            let alive = entry.expires > now;
+            if !alive {
+                lru.remove(&entry.tick);
+            }
+            alive
+        });
+        self.stats.expirations += (before - self.entries.len()) as u64;
+    }
+
+    /// Sets the most replies to keep, the least recently used ones are evicted if there are more.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict_lru();
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        let tick = *self.lru.keys().next().unwrap();
+        let key = self.lru.remove(&tick).unwrap();
+        self.entries.remove(&key);
+        self.stats.evictions += 1;
+    }
+
+    /// Drops all cached replies.
+    pub(crate) fn flush(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            ..self.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn evict_lru() {
+        let mut cache = ResponseCacheT::new(2);
+        cache.insert(1, b"a".to_vec(), 'a', TTL);
+        cache.insert(1, b"b".to_vec(), 'b', TTL);
+        assert_eq!(cache.get(1, b"a".to_vec()), Some(&'a'));
+        cache.insert(1, b"c".to_vec(), 'c', TTL);
+        assert_eq!(cache.get(1, b"b".to_vec()), None);
+        assert_eq!(cache.get(1, b"a".to_vec()), Some(&'a'));
+        assert_eq!(cache.get(2, b"c".to_vec()), None);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 2, 1));
+        assert_eq!(stats.entries, 2);
+    }
+
+    #[test]
+    fn expire() {
+        let mut cache = ResponseCacheT::new(2);
+        cache.insert(1, b"a".to_vec(), 'a', Duration::ZERO);
+        assert_eq!(cache.get(1, b"a".to_vec()), None);
+        assert_eq!(cache.stats().expirations, 1);
+        cache.insert(1, b"a".to_vec(), 'a', Duration::ZERO);
+        cache.insert(1, b"b".to_vec(), 'b', TTL);
+        cache.insert(1, b"c".to_vec(), 'c', TTL);
+        let stats = cache.stats();
+        assert_eq!((stats.expirations, stats.evictions), (2, 0));
+        cache.flush();
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
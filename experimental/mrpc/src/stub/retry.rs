@@ -0,0 +1,82 @@
+//! Retransmission policy for client stubs over unreliable datagrams.
+use std::time::{Duration, Instant};
+
+/// Controls how a [`ClientStub`] retransmits the requests that get no reply in time.
+///
+/// It is meant for stubs created by [`ClientStub::connect_datagram`], where a request or its
+/// reply may be lost silently. A call that gets no reply within `timeout` after it is sent is
+/// sent again, up to `max_attempts` times in total, and then fails with
+/// [`Code::DeadlineExceeded`]. A lost reply cannot be told apart from a lost request, so the
+/// server may run a retransmitted request more than once, unless the call is exactly-once (see
+/// [`ClientStub::set_exactly_once`]). Late and duplicate replies are dropped.
+///
+/// A reply flagged by a congested server (see [`PushbackPolicy`]) holds the retransmissions
+/// back for `pushback_delay`, as the requests are more likely delayed than lost.
+///
+/// [`ClientStub`]: super::ClientStub
+/// [`ClientStub::connect_datagram`]: super::ClientStub::connect_datagram
+/// [`ClientStub::set_exactly_once`]: super::ClientStub::set_exactly_once
+/// [`Code::DeadlineExceeded`]: crate::Code::DeadlineExceeded
+/// [`PushbackPolicy`]: super::PushbackPolicy
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    timeout: Duration,
+    max_attempts: u32,
+    pushback_delay: Duration,
+}
+
+/// How long a client slows down after a server asks it to, unless a [`RetryPolicy`] says
+/// otherwise.
+pub(crate) const DEFAULT_PUSHBACK_DELAY: Duration = Duration::from_millis(100);
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_millis(20),
+            max_attempts: 3,
+            pushback_delay: DEFAULT_PUSHBACK_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Constructs a policy with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long to wait for the reply before sending the request again.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the maximal number of times a request is sent, including the first one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets how long to hold the retransmissions back after the server asks to slow down.
+    pub fn pushback_delay(mut self, delay: Duration) -> Self {
+        self.pushback_delay = delay;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn should_retry(&self, sent: u32) -> bool {
+        sent < self.max_attempts
+    }
+
+    /// Returns the time by which the reply to a request sent `now` is expected.
+    #[inline]
+    pub(crate) fn deadline(&self, now: Instant) -> Instant {
+        now + self.timeout
+    }
+
+    /// Returns the time until which to slow down after a pushback received `now`.
+    #[inline]
+    pub(crate) fn pushback_until(&self, now: Instant) -> Instant {
+        now + self.pushback_delay
+    }
+}
@@ -1,9 +1,12 @@
 use std::sync::Arc;
 
-use phoenix_api::rpc::{MessageErased, MessageMeta, RpcMsgType};
+use phoenix_api::rpc::{MessageErased, MessageMeta, RpcMsgType, StatusCode};
 
+use super::context::RequestContext;
+use super::raw::RawMessage;
 use super::RpcData;
-use crate::{RRef, ReadHeap, WRef, WRefOpaque};
+use crate::compat::{self, FieldTags};
+use crate::{RRef, ReadHeap, Status, WRef, WRefOpaque};
 
 /// A trait to provide a static reference to the service's name and ID.
 /// This is used for routing requests to service within the server.
@@ -16,16 +19,32 @@ pub trait NamedService {
     ///
     /// [here]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests
     const NAME: &'static str = "";
+    /// The path and the `Func-ID` of each method of the service, e.g.,
+    /// `("/rpc_hello.Greeter/SayHello", 3784353755)`.
+    const METHODS: &'static [(&'static str, u32)] = &[];
+    /// Whether the service takes the requests encoded in the protobuf wire format, see
+    /// [`compat`](crate::compat). The server replies `Unimplemented` to such requests for the
+    /// other services.
+    const COMPAT: bool = false;
+
+    /// The sources of the protos that the service is built from.
+    fn proto_srcs() -> Vec<&'static str> {
+        Vec::new()
+    }
 }
 
 /// A trait implemented by generated code.
 #[crate::async_trait]
 pub trait Service {
     /// Resolves to a type-erased [`WRef`] and the [type-erased RPC descriptor][MessageErased] for the reply.
+    ///
+    /// The [`RequestContext`] is attached to the request and can be retrieved by the handler
+    /// through [`RRef::context`].
     async fn call(
         &self,
         req: MessageErased,
         read_heap: Arc<ReadHeap>,
+        ctx: RequestContext,
     ) -> (WRefOpaque, MessageErased);
 }
 
@@ -34,14 +53,64 @@ pub fn service_pre_handler<T: Unpin>(req: &MessageErased, read_heap: Arc<ReadHea
     RRef::new(req, read_heap)
 }
 
+/// Takes a request of a service built with `compat`, and decodes it if it is encoded in the
+/// protobuf wire format. Returns the reply to send if the request cannot be decoded, with
+/// `StatusCode::DataLoss`. The client gets `Code::DataLoss`, as it does for a reply it cannot
+/// decode.
+#[doc(hidden)]
+pub fn compat_pre_handler<T>(
+    req: &MessageErased,
+    read_heap: Arc<ReadHeap>,
+    ctx: RequestContext,
+) -> Result<RRef<T>, (WRefOpaque, MessageErased)>
+where
+    T: Unpin + prost::Message + Default + FieldTags,
+{
+    if !req.meta.compat() {
+        return Ok(RRef::with_context(req, read_heap, ctx));
+    }
+    let encoded = RRef::<RawMessage>::with_context(req, read_heap, ctx);
+    RRef::decode(encoded).map_err(|status| {
+        log::warn!(
+            "{}, service_id={}, func_id={}",
+            status.message(),
+            req.meta.service_id,
+            req.meta.func_id
+        );
+        reject(req, StatusCode::DataLoss)
+    })
+}
+
+/// Replies to a request taken by [`compat_pre_handler`], encoding the reply in the protobuf wire
+/// format if the request is.
+#[doc(hidden)]
+pub fn compat_post_handler<T: RpcData + prost::Message>(
+    reply: WRef<T>,
+    req_opaque: &MessageErased,
+) -> (WRefOpaque, MessageErased) {
+    if !req_opaque.meta.compat() {
+        return service_post_handler(reply, req_opaque);
+    }
+    let (reply_opaque, mut erased) = service_post_handler(compat::encode(&reply), req_opaque);
+    erased.meta.flags |= MessageMeta::RAW | MessageMeta::COMPAT;
+    (reply_opaque, erased)
+}
+
 #[doc(hidden)]
 pub fn service_post_handler<T: RpcData>(
     reply: WRef<T>,
     req_opaque: &MessageErased,
 ) -> (WRefOpaque, MessageErased) {
-    // construct meta
+    // construct meta, the reply echoes the token and the correlation ID of the request, the reply
+    // to a posted request keeps its type and is dropped by the server. The server sets the flags
+    // when it sends the reply.
+    let msg_type = match req_opaque.meta.msg_type {
+        RpcMsgType::Post => RpcMsgType::Post,
+        _ => RpcMsgType::Response,
+    };
     let meta = MessageMeta {
-        msg_type: RpcMsgType::Response,
+        msg_type,
+        flags: 0,
         ..req_opaque.meta
     };
 
@@ -56,3 +125,57 @@ pub fn service_post_handler<T: RpcData>(
 
     (reply_opaque, erased)
 }
+
+/// Replies to a request whose handler returns `status`, with `StatusCode::Failed` and no payload.
+/// The status is logged by the server, the client gets `Code::Unknown`.
+#[doc(hidden)]
+pub fn service_error(status: Status, req_opaque: &MessageErased) -> (WRefOpaque, MessageErased) {
+    log::debug!(
+        "handler failed: {}, service_id={}, func_id={}",
+        status,
+        req_opaque.meta.service_id,
+        req_opaque.meta.func_id
+    );
+    reject(req_opaque, StatusCode::Failed)
+}
+
+/// Replies to a request whose service or method is not implemented by the server, with
+/// `StatusCode::Unimplemented` and no payload. The client gets `Code::Unimplemented`.
+#[doc(hidden)]
+pub fn service_unimplemented(
+    req_opaque: &MessageErased,
+    read_heap: Arc<ReadHeap>,
+) -> (WRefOpaque, MessageErased) {
+    // the request is never read, release its receive buffer
+    drop(RRef::<()>::new(req_opaque, read_heap));
+    log::warn!(
+        "unimplemented service or method, service_id={}, func_id={}",
+        req_opaque.meta.service_id,
+        req_opaque.meta.func_id
+    );
+    reject(req_opaque, StatusCode::Unimplemented)
+}
+
+/// Returns the reply with `status_code` and no payload to a request, whose receive buffer is
+/// released by its `RRef`.
+fn reject(req_opaque: &MessageErased, status_code: StatusCode) -> (WRefOpaque, MessageErased) {
+    let msg_type = match req_opaque.meta.msg_type {
+        RpcMsgType::Post => RpcMsgType::Post,
+        _ => RpcMsgType::Response,
+    };
+    let meta = MessageMeta {
+        msg_type,
+        status_code,
+        flags: 0,
+        ..req_opaque.meta
+    };
+    // the backend sends only the meta of the reply, the placeholder keeps the server's pending
+    // replies uniform
+    let reply_opaque = WRef::new(()).into_opaque();
+    let erased = MessageErased {
+        meta,
+        shm_addr_app: 0,
+        shm_addr_backend: 0,
+    };
+    (reply_opaque, erased)
+}
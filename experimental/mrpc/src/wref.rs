@@ -1,12 +1,21 @@
 //! An owned, writable reference on shared heap.
+use std::future::Future;
+use std::marker::PhantomData;
 use std::mem;
 use std::ops::Deref;
+use std::pin::Pin;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
 
 use phoenix_api::rpc::Token;
 use shm::ptr::ShmNonNull;
+use shmalloc::Arena;
 
 use crate::alloc::Box as ShmBox;
+use crate::compat::UnknownFields;
 use crate::stub::RpcData;
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
@@ -108,6 +117,25 @@ impl WRefOpaque {
 
         Self::new(data, vtable)
     }
+
+    #[inline]
+    fn header(&self) -> &WRefHeader {
+        // SAFETY: `data` comes from `WRef::into_raw`, which points to a `WRefInner<T>`. It is
+        // `repr(C)` with the header as its first field, and it lives as long as this opaque.
+        unsafe { &*(self.data as *const WRefHeader) }
+    }
+
+    /// Returns the sends of the shadowed [`WRef<T>`].
+    #[inline]
+    pub(crate) fn sends(&self) -> &SendTracker {
+        &self.header().sends
+    }
+
+    /// Returns whether the shadowed [`WRef<T>`] is sent in bulk, see [`WRef::as_bulk`].
+    #[inline]
+    pub(crate) fn is_bulk(&self) -> bool {
+        self.header().bulk.load(Ordering::Relaxed)
+    }
 }
 
 impl Clone for WRefOpaque {
@@ -155,8 +183,109 @@ impl<T: RpcData> IntoWRef<T> for &WRef<T> {
     }
 }
 
+enum SendWaiter {
+    Task(Waker),
+    Callback(Box<dyn FnOnce() + Send>),
+}
+
+impl SendWaiter {
+    fn notify(self) {
+        match self {
+            SendWaiter::Task(waker) => waker.wake(),
+            SendWaiter::Callback(f) => f(),
+        }
+    }
+}
+
+/// Counts the sends of a message that the backend has not completed, and notifies the waiters
+/// once they have all completed.
+#[derive(Default)]
+pub(crate) struct SendTracker {
+    outstanding: AtomicUsize,
+    waiters: spin::Mutex<Vec<SendWaiter>>,
+}
+
+impl std::fmt::Debug for SendTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendTracker")
+            .field("outstanding", &self.outstanding.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl SendTracker {
+    /// The message is handed to the backend.
+    #[inline]
+    pub(crate) fn begin(&self) {
+        self.outstanding.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// The backend has completed a send of the message.
+    #[inline]
+    pub(crate) fn end(&self) {
+        if self.outstanding.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let mut waiters = self.waiters.lock();
+            // the message may have been sent again in between
+            if self.outstanding.load(Ordering::Acquire) > 0 {
+                return;
+            }
+            let waiters = mem::take(&mut *waiters);
+            for waiter in waiters {
+                waiter.notify();
+            }
+        }
+    }
+
+    #[inline]
+    fn is_sending(&self) -> bool {
+        self.outstanding.load(Ordering::Acquire) > 0
+    }
+
+    /// Registers the waiter, or returns it if no send is outstanding.
+    fn register(&self, waiter: SendWaiter) -> Option<SendWaiter> {
+        let mut waiters = self.waiters.lock();
+        if !self.is_sending() {
+            return Some(waiter);
+        }
+        waiters.push(waiter);
+        None
+    }
+}
+
+/// Future that resolves once the backend has completed the sends of a [`WRef<T>`], see
+/// [`WRef::sent`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+#[derive(Debug)]
+pub struct Sent<'a> {
+    sends: &'a SendTracker,
+}
+
+impl<'a> Future for Sent<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if !self.sends.is_sending() {
+            return Poll::Ready(());
+        }
+        match self.sends.register(SendWaiter::Task(cx.waker().clone())) {
+            Some(_) => Poll::Ready(()),
+            None => Poll::Pending,
+        }
+    }
+}
+
+// The states of a message that do not depend on its type, see `WRefOpaque::header`.
+#[derive(Debug, Default)]
+struct WRefHeader {
+    sends: SendTracker,
+    bulk: AtomicBool,
+}
+
+// The header must be the first field, see `WRefOpaque::header`.
 #[derive(Debug)]
+#[repr(C)]
 struct WRefInner<T> {
+    header: WRefHeader,
     ptr: ShmBox<T>,
 }
 
@@ -165,6 +294,9 @@ struct WRefInner<T> {
 #[derive(Debug)]
 pub struct WRef<T: RpcData> {
     token: Token,
+    correlation_id: u64,
+    deadline: Option<Instant>,
+    unknown_fields: UnknownFields,
     inner: Arc<WRefInner<T>>,
 }
 
@@ -184,7 +316,11 @@ impl<T: RpcData> WRef<T> {
     pub fn with_token(token: Token, msg: T) -> Self {
         WRef {
             token,
+            correlation_id: 0,
+            deadline: None,
+            unknown_fields: UnknownFields::default(),
             inner: Arc::new(WRefInner {
+                header: WRefHeader::default(),
                 ptr: ShmBox::new(msg),
             }),
         }
@@ -203,6 +339,124 @@ impl<T: RpcData> WRef<T> {
         self.token = token;
     }
 
+    /// Returns the application correlation ID of the calls that send this message.
+    #[must_use]
+    #[inline]
+    pub fn correlation_id(&self) -> u64 {
+        self.correlation_id
+    }
+
+    /// Sets the application correlation ID, an opaque `u64` carried with the request and echoed
+    /// back in the metadata of its reply, see [`RRef::correlation_id`]. It is 0 if not set.
+    ///
+    /// [`RRef::correlation_id`]: crate::RRef::correlation_id
+    #[inline]
+    pub fn set_correlation_id(&mut self, correlation_id: u64) {
+        self.correlation_id = correlation_id;
+    }
+
+    /// Returns the deadline of the calls that send this message, see [`WRef::set_deadline`].
+    #[must_use]
+    #[inline]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Sets the time by which a call sending this message must leave the send queue of the
+    /// backend, or it fails with [`Code::DeadlineExceeded`]. It is combined with the queue
+    /// timeout of the client, the earlier one applies. The connections that send in
+    /// earliest-deadline-first order also send the calls with earlier deadlines first. A
+    /// retransmitted or replayed call only gets the queue timeout of the client.
+    ///
+    /// [`Code::DeadlineExceeded`]: crate::Code::DeadlineExceeded
+    #[inline]
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Returns the unknown fields sent along with this message, see
+    /// [`WRef::set_unknown_fields`].
+    #[must_use]
+    #[inline]
+    pub fn unknown_fields(&self) -> &UnknownFields {
+        &self.unknown_fields
+    }
+
+    /// Sets the fields to send along with this message when it is encoded in the protobuf wire
+    /// format, usually those of the message it is derived from, see [`RRef::unknown_fields`].
+    /// They are not sent when the message is sent in place. See [`compat`] for when the messages
+    /// are encoded.
+    ///
+    /// [`RRef::unknown_fields`]: crate::RRef::unknown_fields
+    /// [`compat`]: crate::compat
+    #[inline]
+    pub fn set_unknown_fields(&mut self, fields: UnknownFields) {
+        self.unknown_fields = fields;
+    }
+
+    /// Returns whether the message has been handed to the backend and some of its sends have
+    /// not completed. The message must not be modified until they complete.
+    #[must_use]
+    #[inline]
+    pub fn is_sending(&self) -> bool {
+        self.inner.header.sends.is_sending()
+    }
+
+    /// Returns a future that resolves once the backend has completed all sends of the message,
+    /// after which its buffers are no longer read by the backend.
+    ///
+    /// The sends complete when the stub that issued them processes their acknowledgements, which
+    /// happens while the stub is polled, e.g., by awaiting a reply. For a message that is never
+    /// replied to, see [`ClientStub::wait_sent`](crate::stub::ClientStub::wait_sent). A request
+    /// that is retransmitted is sent again from the same message.
+    #[inline]
+    pub fn sent(&self) -> Sent<'_> {
+        Sent {
+            sends: &self.inner.header.sends,
+        }
+    }
+
+    /// Runs `f` once the backend has completed all sends of the message, or right away if no
+    /// send is outstanding. See [`sent`](WRef::sent) for when the sends complete.
+    pub fn on_sent<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let waiter = SendWaiter::Callback(Box::new(f));
+        if let Some(waiter) = self.inner.header.sends.register(waiter) {
+            waiter.notify();
+        }
+    }
+
+    /// Marks the message to be sent in bulk, and returns a clone of the `WRef` to pass to the
+    /// stub, e.g., `client.load_weights(weights.as_bulk())`.
+    ///
+    /// The payload of a message sent in bulk does not go through the send queue of the
+    /// connection. The backend offers it to the peer, whose backend pulls it with RDMA READs
+    /// straight into its buffers, so the payload takes no send credits or receive buffers of the
+    /// peer. This pays off for large payloads, e.g., model weights or images. The receiver gets
+    /// the message as usual once the reads complete.
+    ///
+    /// The send of the message completes after the peer has read it, see [`sent`](WRef::sent),
+    /// so the message stays pinned until then. The peer is only allowed to read the payload, and
+    /// only until then; a message the peer has not read within `bulk_offer_timeout_ms` of the
+    /// backend fails with a transport error. The mark applies to the clones of the `WRef` and
+    /// to every later send of the message. It is ignored by the transports that cannot read
+    /// remote memory, and for payloads the peer cannot read, e.g., in a [`UserMemory`], which
+    /// are sent as usual.
+    ///
+    /// [`UserMemory`]: crate::alloc::UserMemory
+    #[must_use]
+    #[inline]
+    pub fn as_bulk(&self) -> Self {
+        self.inner.header.bulk.store(true, Ordering::Relaxed);
+        WRef::clone(self)
+    }
+
+    /// Returns whether the message is sent in bulk, see [`as_bulk`](WRef::as_bulk).
+    #[must_use]
+    #[inline]
+    pub fn is_bulk(&self) -> bool {
+        self.inner.header.bulk.load(Ordering::Relaxed)
+    }
+
     #[inline]
     pub(crate) fn into_opaque(self) -> WRefOpaque {
         WRefOpaque::from_wref(self)
@@ -233,15 +487,154 @@ impl<T: RpcData> WRef<T> {
     unsafe fn from_raw(ptr: *const WRefInner<T>) -> Self {
         WRef {
             token: Token::default(),
+            correlation_id: 0,
+            deadline: None,
+            unknown_fields: UnknownFields::default(),
             inner: Arc::from_raw(ptr),
         }
     }
 }
 
+impl<T: RpcData> WRef<T> {
+    /// Constructs a [`WRef<T>`] whose message carries a payload in GPU memory.
+    ///
+    /// `ptr` must point into a [`DeviceBuffer`] of this process. `f` receives a
+    /// [`Vec<u8>`] that refers to `[ptr, ptr + len)` and builds the message from it, usually by
+    /// setting it to a `bytes` field. The RDMA transport sends the payload directly from the GPU
+    /// (GPUDirect RDMA), and the receiver gets it in host memory. Other transports do not
+    /// support device payloads.
+    ///
+    /// # Safety
+    ///
+    /// * The [`DeviceBuffer`] must outlive the returned `WRef` and all its clones.
+    /// * The vector must not be read, written, or grown by the CPU.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `[ptr, ptr + len)` is not within a [`DeviceBuffer`].
+    ///
+    /// [`DeviceBuffer`]: crate::alloc::DeviceBuffer
+    /// [`Vec<u8>`]: crate::alloc::Vec
+    pub unsafe fn from_device_ptr<F>(ptr: NonNull<u8>, len: usize, f: F) -> Self
+    where
+        F: FnOnce(crate::alloc::Vec<u8>) -> T,
+    {
+        let addr_backend = shmalloc::device::query_backend_addr(ptr.addr().get(), len)
+            .expect("the payload is not within a DeviceBuffer");
+        let ptr_backend = ptr.as_ptr().with_addr(addr_backend);
+        // The vector never deallocates the buffer, the allocator skips device memory.
+        let payload = crate::alloc::Vec::from_raw_parts(ptr.as_ptr(), ptr_backend, len, len);
+        Self::new(f(payload))
+    }
+
+    /// Constructs a [`WRef<T>`] whose message carries a payload in memory allocated by the
+    /// application, e.g., a dataset mapped from a file.
+    ///
+    /// `ptr` must point into a [`UserMemory`] of this process. `f` receives a [`Vec<u8>`] that
+    /// refers to `[ptr, ptr + len)` and builds the message from it. The payload is sent in
+    /// place, without being copied to the shared heap. The backend keeps the memory mapped until
+    /// the sends from it complete, even if the [`UserMemory`] is dropped earlier.
+    ///
+    /// # Safety
+    ///
+    /// * The [`UserMemory`] must outlive the returned `WRef` and all its clones.
+    /// * The vector must not be written or grown.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `[ptr, ptr + len)` is not within a [`UserMemory`].
+    ///
+    /// [`UserMemory`]: crate::alloc::UserMemory
+    /// [`Vec<u8>`]: crate::alloc::Vec
+    pub unsafe fn from_user_ptr<F>(ptr: NonNull<u8>, len: usize, f: F) -> Self
+    where
+        F: FnOnce(crate::alloc::Vec<u8>) -> T,
+    {
+        let addr_backend = shmalloc::user::query_backend_addr(ptr.addr().get(), len)
+            .expect("the payload is not within a UserMemory");
+        let ptr_backend = ptr.as_ptr().with_addr(addr_backend);
+        // The vector never deallocates the memory, the allocator skips user memory.
+        let payload = crate::alloc::Vec::from_raw_parts(ptr.as_ptr(), ptr_backend, len, len);
+        Self::new(f(payload))
+    }
+}
+
+impl<T: RpcData> WRef<T> {
+    /// Returns a builder that constructs the message in an arena of `capacity` bytes on the
+    /// shared heap.
+    ///
+    /// The objects allocated while building the message, e.g., the strings and vectors in it,
+    /// are carved out of the arena, so a large message takes O(1) allocations to construct, and
+    /// the vector allocated last grows in place. Allocations beyond `capacity` are served by the
+    /// shared heap as usual. The arena is released after the message is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shared heap is out of memory.
+    pub fn builder_with_capacity(capacity: usize) -> WRefBuilder<T> {
+        WRefBuilder {
+            token: Token::default(),
+            correlation_id: 0,
+            arena: Arena::with_capacity(capacity).expect("shared heap out of memory"),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Constructs a [`WRef<T>`] in an arena on the shared heap, see
+/// [`WRef::builder_with_capacity`].
+#[derive(Debug)]
+pub struct WRefBuilder<T> {
+    token: Token,
+    correlation_id: u64,
+    arena: Arena,
+    _marker: PhantomData<T>,
+}
+
+impl<T: RpcData> WRefBuilder<T> {
+    /// Sets the user token of the message.
+    #[must_use]
+    pub fn token(mut self, token: Token) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Sets the application correlation ID of the message, see [`WRef::set_correlation_id`].
+    #[must_use]
+    pub fn correlation_id(mut self, correlation_id: u64) -> Self {
+        self.correlation_id = correlation_id;
+        self
+    }
+
+    /// Runs `f` with the allocations on the shared heap of this thread served by the arena,
+    /// e.g., to prepare the fields of the message in several steps.
+    ///
+    /// `f` should not yield to other tasks, whose allocations would be served by the arena too.
+    pub fn scope<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        self.arena.scope(f)
+    }
+
+    /// Returns the number of bytes allocated from the arena so far.
+    pub fn used(&self) -> usize {
+        self.arena.used()
+    }
+
+    /// Constructs the message with `f` in the arena.
+    pub fn build<F: FnOnce() -> T>(self, f: F) -> WRef<T> {
+        let (token, correlation_id) = (self.token, self.correlation_id);
+        let mut wref = self.arena.scope(|| WRef::with_token(token, f()));
+        wref.set_correlation_id(correlation_id);
+        wref
+    }
+}
+
 impl<T: RpcData> Clone for WRef<T> {
     fn clone(&self) -> Self {
         WRef {
             token: self.token,
+            correlation_id: self.correlation_id,
+            deadline: self.deadline,
+            unknown_fields: self.unknown_fields.clone(),
             inner: Arc::clone(&self.inner),
         }
     }
@@ -280,8 +673,14 @@ impl<T: RpcData> WRef<T> {
     /// for the duration of the returned borrow.
     /// This is trivially the case if no such pointers exist,
     /// for example immediately after `WRef::new`.
+    ///
+    /// In debug builds, panics if a send of the message is outstanding.
     #[inline]
     pub unsafe fn get_mut_unchecked(this: &mut Self) -> &mut T {
+        debug_assert!(
+            !this.is_sending(),
+            "WRef is mutated while a send of it is outstanding"
+        );
         // We are careful to *not* create a reference covering the "count" fields, as
         // this would alias with concurrent access to the reference counts (e.g. by `Weak`).
         // unsafe { &mut (*this.ptr.as_ptr()).data }
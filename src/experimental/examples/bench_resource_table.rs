@@ -0,0 +1,148 @@
+//! Looks up the resources of 10k+ connections in the resource tables of phoenix_common, as the
+//! engines do for every work completion, and checks that the handles of the closed connections
+//! are not found after their slots are reused.
+#![feature(scoped_threads)]
+use std::thread;
+use std::time::{Duration, Instant};
+
+use phoenix_api::Handle;
+use phoenix_common::local_resource::LocalResourceTable;
+use phoenix_common::resource::{ResourceSlab, ResourceTable};
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+#[structopt(about = "Resource table benchmark")]
+struct Opts {
+    /// The number of connections.
+    #[structopt(short, long, default_value = "10000")]
+    conns: usize,
+    /// The number of lookups of each thread.
+    #[structopt(short, long, default_value = "10000000")]
+    lookups: usize,
+    /// The most threads to look up from.
+    #[structopt(short, long, default_value = "8")]
+    threads: usize,
+}
+
+/// Stands for the context of a connection.
+struct Conn {
+    _credits: usize,
+}
+
+fn print(desc: &str, dura: Duration, num: usize) {
+    println!(
+        "{}, duration: {:?}, num: {}, latency: {} ns/ops, tput: {} Mops/s",
+        desc,
+        dura,
+        num,
+        dura.as_nanos() as usize / num,
+        num as f64 * 1e-6 / dura.as_secs_f64(),
+    );
+}
+
+fn bench_slab(opts: &Opts, nthreads: usize) {
+    let slab = ResourceSlab::default();
+    let keys: Vec<usize> = (0..opts.conns)
+        .map(|i| slab.insert(Conn { _credits: i }).unwrap())
+        .collect();
+
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..nthreads {
+            s.spawn(|| {
+                for _ in 0..opts.lookups {
+                    let key = keys[fastrand::usize(..keys.len())];
+                    let _conn = slab.get_dp(key).unwrap();
+                }
+            });
+        }
+    });
+    print(
+        &format!("ResourceSlab get_dp across {} threads", nthreads),
+        start.elapsed(),
+        opts.lookups * nthreads,
+    );
+
+    // close and reopen connections, the slots are reused with the next generations
+    let start = Instant::now();
+    for &key in &keys {
+        slab.close_resource_by_key(key).unwrap().unwrap();
+        let new_key = slab.insert(Conn { _credits: 0 }).unwrap();
+        assert!(
+            new_key <= Handle::MAX_ID as usize,
+            "key {:#x} overflows a handle",
+            new_key
+        );
+        assert!(
+            slab.get_dp(key).is_err(),
+            "the stale key {:#x} is found",
+            key
+        );
+    }
+    print("ResourceSlab close and reopen", start.elapsed(), keys.len());
+}
+
+fn bench_table(opts: &Opts, nthreads: usize) {
+    let table = ResourceTable::default();
+    for i in 0..opts.conns {
+        table
+            .insert(Handle(i as u64), Conn { _credits: i })
+            .unwrap();
+    }
+
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..nthreads {
+            s.spawn(|| {
+                for _ in 0..opts.lookups {
+                    let h = Handle(fastrand::u64(..opts.conns as u64));
+                    let _conn = table.get_dp(&h).unwrap();
+                }
+            });
+        }
+    });
+    print(
+        &format!("ResourceTable get_dp across {} threads", nthreads),
+        start.elapsed(),
+        opts.lookups * nthreads,
+    );
+}
+
+fn bench_local_table(opts: &Opts, nthreads: usize) {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..nthreads {
+            s.spawn(|| {
+                // each engine owns the connections of its own
+                let table = LocalResourceTable::default();
+                let conns = opts.conns / nthreads;
+                for i in 0..conns {
+                    table
+                        .insert(Handle(i as u64), Conn { _credits: i })
+                        .unwrap();
+                }
+                for _ in 0..opts.lookups {
+                    let h = Handle(fastrand::u64(..conns as u64));
+                    let _conn = table.get_dp(&h).unwrap();
+                }
+            });
+        }
+    });
+    print(
+        &format!("LocalResourceTable get_dp across {} threads", nthreads),
+        start.elapsed(),
+        opts.lookups * nthreads,
+    );
+}
+
+fn main() {
+    let opts = Opts::from_args();
+    println!("{} connections", opts.conns);
+    for nthreads in 1..=opts.threads {
+        println!("\nTesting across {} threads...", nthreads);
+        bench_slab(&opts, nthreads);
+        bench_table(&opts, nthreads);
+        bench_local_table(&opts, nthreads);
+    }
+}
@@ -1,7 +1,328 @@
-//! Concurrent channel, encapsulated over crossbeam channel.
+//! Concurrent channel, a bounded single producer single consumer ring.
+//!
+//! The ring has a fixed number of slots allocated up front, and the indices of the two ends are
+//! padded to their own cache lines. Each end caches the index of the other, so it only touches
+//! the shared cache line when the cached one says the ring is full or empty.
+//!
+//! A sender never blocks, as the engines of the two ends may be polled by the same runtime. When
+//! the ring is full, the messages spill into an overflow queue behind a lock, and keep going there
+//! until the receiver has taken all of them, so the messages are received in the order they are
+//! sent.
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-pub(crate) use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::utils::CachePadded;
+
+use super::super::{SendError, TryRecvError};
+
+/// The number of slots of a ring, a power of two.
+pub(crate) const RING_CAPACITY: usize = 4096;
+
+struct Ring<T> {
+    /// The index of the next slot to pop, only written by the receiver
+    head: CachePadded<AtomicUsize>,
+    /// The index of the next slot to push, only written by the sender
+    tail: CachePadded<AtomicUsize>,
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// The messages sent while the ring is full
+    overflow: Mutex<VecDeque<T>>,
+    /// The number of messages in `overflow`
+    spilled: CachePadded<AtomicUsize>,
+}
+
+// The slots between head and tail are only read by the receiver, and the others are only written
+// by the sender.
+unsafe impl<T: Send> Sync for Ring<T> {}
+unsafe impl<T: Send> Send for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn new(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two());
+        let slots = (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+        Ring {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            slots,
+            overflow: Mutex::new(VecDeque::new()),
+            spilled: CachePadded::new(AtomicUsize::new(0)),
+        }
+    }
+
+    #[inline]
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index & (self.slots.len() - 1)].get()
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        for index in head..tail {
+            // SAFETY: the slots between head and tail hold messages
+            unsafe { (*self.slot(index)).assume_init_drop() };
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Ring<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ring")
+            .field("head", &self.head.load(Ordering::Relaxed))
+            .field("tail", &self.tail.load(Ordering::Relaxed))
+            .field("capacity", &self.slots.len())
+            .field("spilled", &self.spilled.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Sender<T> {
+    ring: Arc<Ring<T>>,
+    /// The head last seen by the sender
+    head: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Receiver<T> {
+    ring: Arc<Ring<T>>,
+    /// The tail last seen by the receiver
+    tail: usize,
+}
+
+impl<T> Sender<T> {
+    pub(crate) fn send(&mut self, t: T) -> Result<(), SendError<T>> {
+        if Arc::strong_count(&self.ring) == 1 {
+            return Err(crossbeam::channel::SendError(t));
+        }
+        let ring = &*self.ring;
+        if ring.spilled.load(Ordering::Acquire) > 0 {
+            // keep the order behind the messages that have spilled
+            return Ok(Self::spill(ring, t));
+        }
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head) == ring.slots.len() {
+            self.head = ring.head.load(Ordering::Acquire);
+            if tail.wrapping_sub(self.head) == ring.slots.len() {
+                return Ok(Self::spill(ring, t));
+            }
+        }
+        // SAFETY: the slot at tail is not in use, and only the sender writes it
+        unsafe { (*ring.slot(tail)).write(t) };
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    #[cold]
+    fn spill(ring: &Ring<T>, t: T) {
+        let mut overflow = ring.overflow.lock().unwrap();
+        overflow.push_back(t);
+        ring.spilled.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Pops up to `max` messages from the ring. Returns the number of messages popped.
+    fn pop_ring(&mut self, max: usize, mut f: impl FnMut(T)) -> usize {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == self.tail {
+            self.tail = ring.tail.load(Ordering::Acquire);
+        }
+        let n = self.tail.wrapping_sub(head).min(max);
+        for i in 0..n {
+            // SAFETY: the slots between head and tail hold messages, and only the receiver
+            // reads them
+            f(unsafe { (*ring.slot(head.wrapping_add(i))).assume_init_read() });
+        }
+        if n > 0 {
+            ring.head.store(head.wrapping_add(n), Ordering::Release);
+        }
+        n
+    }
+
+    /// Pops a message that has spilled, if the ring is empty.
+    #[cold]
+    fn pop_overflow(&mut self) -> Option<T> {
+        let mut overflow = self.ring.overflow.lock().unwrap();
+        // the sender may have filled the ring after it was found empty and before the message
+        // spilled, those go first
+        let mut t = None;
+        if self.pop_ring(1, |x| t = Some(x)) > 0 {
+            return t;
+        }
+        let t = overflow.pop_front()?;
+        self.ring.spilled.fetch_sub(1, Ordering::Release);
+        Some(t)
+    }
+
+    #[inline]
+    fn is_disconnected(&self) -> bool {
+        if Arc::strong_count(&self.ring) == 1 {
+            // see the messages sent before the sender is dropped
+            fence(Ordering::Acquire);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let disconnected = self.is_disconnected();
+        let mut t = None;
+        if self.pop_ring(1, |x| t = Some(x)) > 0 {
+            return Ok(t.unwrap());
+        }
+        if self.ring.spilled.load(Ordering::Acquire) > 0 {
+            if let Some(t) = self.pop_overflow() {
+                return Ok(t);
+            }
+        }
+        if disconnected {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    pub(crate) fn try_recv_batch(
+        &mut self,
+        buf: &mut Vec<T>,
+        max: usize,
+    ) -> Result<usize, TryRecvError> {
+        let disconnected = self.is_disconnected();
+        let mut n = self.pop_ring(max, |x| buf.push(x));
+        while n < max && self.ring.spilled.load(Ordering::Acquire) > 0 {
+            match self.pop_overflow() {
+                Some(t) => {
+                    buf.push(t);
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        if n == 0 && disconnected {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Ok(n)
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head) + ring.spilled.load(Ordering::Acquire)
+    }
+}
 
 pub(crate) fn create_channel<T>() -> (Sender<T>, Receiver<T>) {
-    unbounded()
+    let ring = Arc::new(Ring::new(RING_CAPACITY));
+    (
+        Sender {
+            ring: Arc::clone(&ring),
+            head: 0,
+        },
+        Receiver { ring, tail: 0 },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_pong() {
+        let (mut tx, mut rx) = create_channel();
+        assert_eq!(tx.send(42), Ok(()));
+        assert_eq!(rx.try_recv(), Ok(42));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn spill_in_order() {
+        let (mut tx, mut rx) = create_channel();
+        let n = RING_CAPACITY * 2 + 3;
+        for i in 0..n {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        assert_eq!(rx.len(), n);
+        for i in 0..n {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn batch() {
+        let (mut tx, mut rx) = create_channel();
+        for i in 0..10 {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        let mut buf = Vec::new();
+        assert_eq!(rx.try_recv_batch(&mut buf, 4), Ok(4));
+        assert_eq!(rx.try_recv_batch(&mut buf, 32), Ok(6));
+        assert_eq!(buf, (0..10).collect::<Vec<_>>());
+        assert_eq!(rx.try_recv_batch(&mut buf, 32), Ok(0));
+    }
+
+    #[test]
+    fn closed_tx() {
+        let (mut tx, mut rx) = create_channel();
+        assert_eq!(tx.send(1), Ok(()));
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn closed_rx() {
+        let (mut tx, rx) = create_channel();
+        drop(rx);
+        assert_eq!(tx.send(42), Err(crossbeam::channel::SendError(42)));
+    }
+
+    #[test]
+    fn across_threads() {
+        let (mut tx, mut rx) = create_channel();
+        let n = RING_CAPACITY * 8;
+        let producer = std::thread::spawn(move || {
+            for i in 0..n {
+                tx.send(i).unwrap();
+            }
+        });
+        let mut expected = 0;
+        while expected < n {
+            match rx.try_recv() {
+                Ok(i) => {
+                    assert_eq!(i, expected);
+                    expected += 1;
+                }
+                Err(TryRecvError::Empty) => std::hint::spin_loop(),
+                Err(TryRecvError::Disconnected) => panic!("disconnected early"),
+            }
+        }
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn drop_unreceived() {
+        let (mut tx, rx) = create_channel();
+        let msg = Arc::new(());
+        for _ in 0..RING_CAPACITY + 1 {
+            tx.send(Arc::clone(&msg)).unwrap();
+        }
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&msg), 1);
+    }
 }
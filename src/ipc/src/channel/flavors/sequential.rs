@@ -60,10 +60,29 @@ impl<T> Receiver<T> {
         }
     }
 
+    pub(crate) fn try_recv_batch(
+        &mut self,
+        buf: &mut Vec<T>,
+        max: usize,
+    ) -> Result<usize, TryRecvError> {
+        if Rc::strong_count(&self.shared) == 1 {
+            return Err(TryRecvError::Disconnected);
+        }
+        let mut inner = self.shared.inner.borrow_mut();
+        let n = inner.queue.len().min(max);
+        buf.extend(inner.queue.drain(..n));
+        Ok(n)
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         let inner = self.shared.inner.borrow_mut();
         inner.queue.is_empty()
     }
+
+    pub(crate) fn len(&self) -> usize {
+        let inner = self.shared.inner.borrow_mut();
+        inner.queue.len()
+    }
 }
 
 pub(crate) fn create_channel<T>() -> (Sender<T>, Receiver<T>) {
@@ -92,6 +111,16 @@ mod tests {
         assert_eq!(rx.try_recv(), Ok(42));
     }
 
+    #[test]
+    fn len() {
+        let (mut tx, mut rx) = create_channel();
+        assert_eq!(tx.send(1), Ok(()));
+        assert_eq!(tx.send(2), Ok(()));
+        assert_eq!(rx.len(), 2);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.len(), 1);
+    }
+
     #[test]
     fn closed_tx() {
         let (tx, mut rx) = create_channel::<()>();
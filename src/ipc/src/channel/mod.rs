@@ -13,7 +13,7 @@ pub struct Sender<T> {
 
 #[derive(Debug)]
 pub(crate) enum SenderFlavor<T> {
-    /// Crossbeam MPMC channel.
+    /// Bounded SPSC ring, safe to use across threads.
     Concurrent(flavors::concurrent::Sender<T>),
     /// Sequential single-threaded queue. Not concurrent safe. Must be used with special
     /// scheduling policy.
@@ -44,7 +44,7 @@ pub struct Receiver<T> {
 
 #[derive(Debug)]
 pub(crate) enum ReceiverFlavor<T> {
-    /// Crossbeam MPMC channel.
+    /// Bounded SPSC ring, safe to use across threads.
     Concurrent(flavors::concurrent::Receiver<T>),
     /// Sequential single-threaded queue. Not concurrent safe. Must be used with special
     /// scheduling policy.
@@ -66,10 +66,23 @@ impl<T> Receiver<T> {
         choose_receiver_flavor!(&mut self.flavor, try_recv)
     }
 
+    /// Receives up to `max` messages at once, appending them to `buf`. Returns the number of
+    /// messages received, or an error if the channel is empty and disconnected.
+    #[inline]
+    pub fn try_recv_batch(&mut self, buf: &mut Vec<T>, max: usize) -> Result<usize, TryRecvError> {
+        choose_receiver_flavor!(&mut self.flavor, try_recv_batch, buf, max)
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         choose_receiver_flavor!(&self.flavor, is_empty)
     }
+
+    /// Returns the number of messages in the channel.
+    #[inline]
+    pub fn len(&self) -> usize {
+        choose_receiver_flavor!(&self.flavor, len)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
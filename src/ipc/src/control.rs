@@ -1,9 +1,12 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 pub use libc::pid_t;
 use serde::{Deserialize, Serialize};
 
-use phoenix_api::engine::{SchedulingHint, SchedulingMode};
+use phoenix_api::engine::{CpuSet, SchedulingHint, SchedulingMode};
+
+use crate::version::ProtocolVersion;
 
 type IResult<T> = Result<T, phoenix_api::Error>;
 
@@ -21,6 +24,35 @@ pub struct PluginDescriptor {
     pub config_path: Option<PathBuf>,
     /// The configuration string.
     pub config_string: Option<String>,
+    /// What to do when an engine of this plugin fails.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+}
+
+/// Restart policy for the engines of a plugin.
+///
+/// An engine fails when it returns an error or panics. A failed engine is restarted in place
+/// if the policy allows, otherwise, all engines of its service subscription are shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartPolicy {
+    /// Whether to restart failed engines.
+    pub enable: bool,
+    /// The maximal number of restarts of an engine within `window_secs`. The engine is shut
+    /// down when it fails again.
+    pub max_restarts: usize,
+    /// The length of the time window in seconds.
+    pub window_secs: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            enable: false,
+            max_restarts: 3,
+            window_secs: 60,
+        }
+    }
 }
 
 impl std::hash::Hash for PluginDescriptor {
@@ -50,6 +82,17 @@ pub struct UpgradeRequest {
     pub detach_subscription: bool,
 }
 
+/// Request for reloading the daemon config file and upgrading the plugins whose configs
+/// have changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadRequest {
+    /// whether to flush the shared queues
+    pub flush: bool,
+    /// whether to suspend all engines
+    /// within the same service subscription
+    pub detach_subscription: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PluginType {
     Module,
@@ -77,6 +120,53 @@ pub struct AddonRequest {
     pub config_string: Option<String>,
 }
 
+/// Request for moving the engines of a client to other cores.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AffinityRequest {
+    /// Target user process
+    pub pid: pid_t,
+    /// Target service subscription, or all subscriptions of the process if not given
+    pub sid: Option<u64>,
+    /// The cores to run the engines on
+    pub cpus: Option<CpuSet>,
+    /// The numa node to run the engines on, ignored if `cpus` is given. The engines may run on
+    /// any core if neither is given.
+    pub numa_node: Option<u8>,
+}
+
+/// Request for the datapath graphs of service subscriptions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphRequest {
+    /// Target user process, or all processes if not given
+    pub pid: Option<pid_t>,
+    /// Target service subscription, or all subscriptions of the process if not given
+    pub sid: Option<u64>,
+}
+
+/// An endpoint of a service, as kept by the name service of the daemons.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ServiceEndpoint {
+    /// The full name of the service, e.g., `rpc_hello.Greeter`
+    pub service: String,
+    /// The host name or IP address the service is reachable at. An unspecified address
+    /// (`0.0.0.0` or `::`) is replaced by the address of the daemon when registered.
+    pub host: String,
+    pub port: u16,
+    /// The hash of the protos the service is built from, to tell apart incompatible versions
+    pub proto_hash: String,
+}
+
+impl ServiceEndpoint {
+    /// Returns the endpoint as `host:port`.
+    pub fn addr(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
     /// New service subscription, scheduling mode, service name, and an optional config string
@@ -91,6 +181,30 @@ pub enum Request {
     DetachAddon(AddonRequest),
     /// Upgrade modules or plugins
     Upgrade(UpgradeRequest),
+    /// Reload the config file and apply the changed module and addon configs
+    ReloadConfig(ReloadRequest),
+    /// Replace the log filter of the daemon if a filter is given, and query the current filter
+    LogFilter(Option<String>),
+    /// Query the resources held by the daemon and the leaks reclaimed so far
+    Stats,
+    /// Pin the engines of a client to the given cores
+    SetAffinity(AffinityRequest),
+    /// Query the engines and channels on the datapath of service subscriptions
+    DataPathGraph(GraphRequest),
+    /// `NewClient` preceded by the protocol version of the client. New variants must be appended
+    /// so that the requests of older clients still decode.
+    NewVersionedClient(ProtocolVersion, SchedulingHint, String, Option<String>),
+    /// Set how often the batch engines are polled, in iterations of their runtimes, if an
+    /// interval is given, and query the current interval
+    BatchPollInterval(Option<u32>),
+    /// Register an endpoint of a service of the calling process in the name service
+    RegisterEndpoint(ServiceEndpoint),
+    /// Remove an endpoint registered by the calling process
+    UnregisterEndpoint(ServiceEndpoint),
+    /// Query the endpoints of a service, known locally or announced by the peer daemons
+    LookupEndpoint(String),
+    /// Query the services, modules, addons and profiles the daemon serves
+    Capabilities,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -102,11 +216,167 @@ pub struct ServiceSubscriptionInfo {
     pub addons: Vec<String>,
 }
 
+/// Resources held by a module for a client process, by the kind of resource.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceStats {
+    pub pid: pid_t,
+    pub module: String,
+    pub resources: BTreeMap<String, usize>,
+}
+
+/// Resources released by the sweeper after their client processes exited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LeakStats {
+    /// The number of client processes found exited with states left in the daemon
+    pub processes: usize,
+    /// The number of engines shut down because their client processes exited
+    pub engines: usize,
+    /// The number of reclaimed resources, by `<module>.<kind>`
+    pub resources: BTreeMap<String, usize>,
+}
+
+/// Where a scheduling group of a service subscription runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupPlacement {
+    pub pid: pid_t,
+    pub sid: u64,
+    pub gid: u64,
+    pub runtime: u64,
+    pub engines: Vec<String>,
+    /// The cores of the runtime, in the cpuset list format
+    pub cpus: String,
+}
+
+/// The time an engine spent in a phase of its mainloop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseStats {
+    pub phase: String,
+    /// The number of times the phase was run
+    pub count: u64,
+    pub total_ns: u64,
+    /// A log2 histogram, `buckets[i]` counts the runs that took `[2^i, 2^(i+1))` ns
+    pub buckets: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineProfile {
+    pub eid: u64,
+    pub pid: pid_t,
+    pub engine_type: String,
+    pub phases: Vec<PhaseStats>,
+}
+
+/// The events of a kind an engine recorded, rolled up over the recent window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventStats {
+    pub kind: String,
+    /// The events since the engine was registered or the events were enabled
+    pub total: u64,
+    /// The events in the window
+    pub window_count: u64,
+    /// The events per second over the window
+    pub rate: f64,
+    /// The 50th and 99th percentiles and the maximum of the values in the window, the sizes in
+    /// bytes or the latencies in ns; the percentiles are the upper bounds of log2 buckets
+    pub p50: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineEvents {
+    pub eid: u64,
+    pub pid: pid_t,
+    pub engine_type: String,
+    /// The length of the window in seconds
+    pub window_secs: f64,
+    pub kinds: Vec<EventStats>,
+    /// The events lost since the ring of the engine was full
+    pub overflows: u64,
+}
+
+/// What the daemon serves, for the user libraries to adapt to at runtime.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// The newest control path protocol version the daemon speaks
+    pub protocol_version: ProtocolVersion,
+    /// The services that can be subscribed to
+    pub services: Vec<String>,
+    /// The loaded modules with the limits and features each reports, e.g., `max_message_size`
+    pub modules: BTreeMap<String, BTreeMap<String, String>>,
+    /// The engine types of the loaded addons
+    pub addon_engines: Vec<String>,
+    /// The profiles defined in the daemon config
+    pub profiles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStats {
+    /// The number of client processes served
+    pub clients: usize,
+    pub subscriptions: usize,
+    pub engines: usize,
+    pub resources: Vec<ResourceStats>,
+    pub leaks: LeakStats,
+    pub placements: Vec<GroupPlacement>,
+    /// The phase profiles of the engines, empty unless `profiling.engine_phases` is set
+    pub profiles: Vec<EngineProfile>,
+    /// The event rollups of the engines, empty unless `profiling.engine_events` is set
+    pub events: Vec<EngineEvents>,
+}
+
+/// An engine on the datapath, as seen by its runtime.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphEngine {
+    pub eid: u64,
+    pub engine_type: String,
+    pub runtime: u64,
+    /// The description of the engine, missing if its runtime did not answer in time
+    pub description: Option<String>,
+    /// The number of messages queued in each tx input of the engine
+    pub tx_inputs: Vec<usize>,
+    /// The number of messages queued in each rx input of the engine
+    pub rx_inputs: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelDirection {
+    Tx,
+    Rx,
+}
+
+/// A channel between two engines of a service subscription.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphChannel {
+    pub direction: ChannelDirection,
+    pub sender: String,
+    pub receiver: String,
+    /// The index of the channel in the outputs of the sender
+    pub sender_index: usize,
+    /// The index of the channel in the inputs of the receiver
+    pub receiver_index: usize,
+    /// The number of messages queued, missing if the receiver is not known
+    pub queued: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriptionGraph {
+    pub pid: pid_t,
+    pub sid: u64,
+    pub service: String,
+    pub engines: Vec<GraphEngine>,
+    pub channels: Vec<GraphChannel>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ResponseKind {
     /// path of the engine's domain socket
     NewClient(PathBuf),
     ListSubscription(Vec<ServiceSubscriptionInfo>),
+    /// The current log filter
+    LogFilter(String),
+    Stats(DaemonStats),
+    DataPathGraph(Vec<SubscriptionGraph>),
     /// .0: the requested scheduling mode
     /// .1: name of the OneShotServer
     /// .2: data path work queue capacity in bytes
@@ -116,6 +386,13 @@ pub enum ResponseKind {
         wq_cap: usize,
         cq_cap: usize,
     },
+    /// The protocol version agreed on and the path of the engine's domain socket
+    NewVersionedClient(ProtocolVersion, PathBuf),
+    /// The current polling interval of the batch engines
+    BatchPollInterval(u32),
+    /// The endpoints of the service, the ones registered on this host first
+    LookupEndpoint(Vec<ServiceEndpoint>),
+    Capabilities(Capabilities),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
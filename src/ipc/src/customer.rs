@@ -16,6 +16,7 @@ use phoenix_api::engine::SchedulingMode;
 use crate::control;
 use crate::ipc_channel::{IpcReceiver, IpcSender, IpcSenderNotify};
 use crate::unix::DomainSocket;
+use crate::version::{CmdReceiver, CmdSender, ProtocolVersion, Tagged, PROTOCOL_V1};
 use crate::{Error, ShmObject, ShmReceiver, ShmSender, TryRecvError};
 
 // TODO(cjr): make these configurable, see phoenix.toml
@@ -36,8 +37,8 @@ pub struct Customer<Command, Completion, WorkRequest, WorkCompletion> {
     client_path: PathBuf,
     sock: DomainSocket,
     cmd_rx_entries: ShmObject<AtomicUsize>,
-    cmd_tx: IpcSenderNotify<Completion>,
-    cmd_rx: IpcReceiver<Command>,
+    cmd_tx: CmdSender<Completion>,
+    cmd_rx: CmdReceiver<Command>,
     dp_wq: ShmReceiver<WorkRequest>,
     dp_cq: ShmSender<WorkCompletion>,
     timer: Instant,
@@ -57,6 +58,7 @@ where
         client_path: P,
         mode: SchedulingMode,
         engine_path: Q,
+        protocol: ProtocolVersion,
     ) -> Result<Self, Error> {
         let engine_path = engine_path.as_ref();
         if engine_path.exists() {
@@ -65,10 +67,13 @@ where
         }
         let mut engine_sock = DomainSocket::bind(&engine_path)?;
 
-        // 2. tell the engine's path to the client
-        let mut buf = bincode::serialize(&control::Response(Ok(
-            control::ResponseKind::NewClient(engine_path.to_path_buf()),
-        )))?;
+        // 2. tell the engine's path and the agreed protocol version to the client
+        let res = if protocol == PROTOCOL_V1 {
+            control::ResponseKind::NewClient(engine_path.to_path_buf())
+        } else {
+            control::ResponseKind::NewVersionedClient(protocol, engine_path.to_path_buf())
+        };
+        let mut buf = bincode::serialize(&control::Response(Ok(res)))?;
         let nbytes = sock.send_to(buf.as_mut_slice(), &client_path)?;
         assert_eq!(
             nbytes,
@@ -109,15 +114,34 @@ where
         );
 
         // 6. the client should later connect to the oneshot server, and create these channels
-        // to communicate with its transport engine.
-        let (_, (cmd_tx, cmd_rx)): (_, (IpcSender<Completion>, IpcReceiver<Command>)) =
-            server.accept()?;
+        // to communicate with its transport engine. The messages on these channels are tagged with
+        // the protocol version unless the client speaks the untagged PROTOCOL_V1.
+        let cmd_tx_entries = ShmObject::new(AtomicUsize::new(0))?;
+        let (cmd_tx, cmd_rx) = if protocol == PROTOCOL_V1 {
+            let (_, (cmd_tx, cmd_rx)): (_, (IpcSender<Completion>, IpcReceiver<Command>)) =
+                server.accept()?;
+            (
+                CmdSender::Untagged(IpcSenderNotify::new(cmd_tx, cmd_tx_entries.clone())),
+                CmdReceiver::Untagged(cmd_rx),
+            )
+        } else {
+            let (_, (cmd_tx, cmd_rx)): (
+                _,
+                (IpcSender<Tagged<Completion>>, IpcReceiver<Tagged<Command>>),
+            ) = server.accept()?;
+            (
+                CmdSender::Tagged(
+                    IpcSenderNotify::new(cmd_tx, cmd_tx_entries.clone()),
+                    protocol,
+                ),
+                CmdReceiver::Tagged(cmd_rx, protocol),
+            )
+        };
 
         // 7. create data path shared memory queues
         let dp_wq = ShmReceiver::new(wq_cap)?;
         let dp_cq = ShmSender::new(cq_cap)?;
 
-        let cmd_tx_entries = ShmObject::new(AtomicUsize::new(0))?;
         let cmd_rx_entries = ShmObject::new(AtomicUsize::new(0))?;
         let fd_notifier = ShmObject::new(AtomicUsize::new(0))?;
 
@@ -142,7 +166,7 @@ where
             client_path: client_path.as_ref().to_path_buf(),
             sock: engine_sock,
             cmd_rx_entries,
-            cmd_tx: IpcSenderNotify::new(cmd_tx, cmd_tx_entries),
+            cmd_tx,
             cmd_rx,
             dp_wq,
             dp_cq,
@@ -169,6 +193,41 @@ where
             .map_err(|e| Error::SendFd(Box::new(e)))
     }
 
+    /// Receives the file descriptors sent by the client with `Service::send_fd`.
+    #[inline]
+    pub fn recv_fd(&self) -> Result<Vec<RawFd>, Error> {
+        let (fds, cred) = self.sock.recv_fd()?;
+        let peer_cred = self.sock.peer_cred()?;
+        match cred {
+            Some(cred) if peer_cred == cred => Ok(fds),
+            Some(cred) => Err(Error::CredentialMismatch(cred, peer_cred)),
+            None => Err(Error::EmptyCredential),
+        }
+    }
+
+    /// Receives the file descriptors sent by the client with `Service::send_fd` if they have
+    /// arrived, without waiting for them. The descriptors of a mismatched sender are closed.
+    pub fn try_recv_fd(&self) -> Result<Option<Vec<RawFd>>, Error> {
+        let (fds, cred) = match self.sock.try_recv_fd()? {
+            Some(received) => received,
+            None => return Ok(None),
+        };
+        let peer_cred = self.sock.peer_cred()?;
+        match cred {
+            Some(cred) if peer_cred == cred => Ok(Some(fds)),
+            cred => {
+                for fd in fds {
+                    // SAFETY: the fds are received and owned by us
+                    unsafe { libc::close(fd) };
+                }
+                match cred {
+                    Some(cred) => Err(Error::CredentialMismatch(cred, peer_cred)),
+                    None => Err(Error::EmptyCredential),
+                }
+            }
+        }
+    }
+
     #[inline]
     pub fn try_recv_cmd(&mut self) -> Result<Command, TryRecvError> {
         if !self.has_control_command() {
@@ -26,6 +26,9 @@ pub(crate) use shmobj::ShmObject;
 pub mod customer;
 pub mod service;
 
+/// Versioning of the control path protocol
+pub mod version;
+
 pub mod channel;
 
 #[derive(Debug, Error)]
@@ -98,6 +101,12 @@ pub enum Error {
     CredentialMismatch(UCred, UCred),
     #[error("Control plane error {0}: {1}")]
     ControlPlane(&'static str, phoenix_api::Error),
+    #[error("No response from the control plane in {0:?}")]
+    ControlTimeout(std::time::Duration),
+    #[error("Invalid environment variable {0}: {1}")]
+    InvalidEnv(&'static str, String),
+    #[error("Unsupported protocol version {0}, this build speaks version {1}")]
+    ProtocolVersion(u32, u32),
 }
 
 impl From<crate::ipc_channel::TryRecvError> for TryRecvError {
@@ -3,10 +3,11 @@ use std::cell::RefCell;
 use std::env;
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::os::unix::io::FromRawFd;
 use std::os::unix::io::RawFd;
 use std::os::unix::net::UCred;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
@@ -27,11 +28,100 @@ use phoenix_api::engine::SchedulingHint;
 use crate::control;
 use crate::ipc_channel::{IpcReceiver, IpcSender, IpcSenderNotify};
 use crate::unix::DomainSocket;
+use crate::version::{
+    CmdReceiver, CmdSender, Tagged, MIN_PROTOCOL_VERSION, PROTOCOL_V1, PROTOCOL_VERSION,
+};
 use crate::MAX_MSG_LEN;
 use crate::{Error, ShmObject, ShmReceiver, ShmSender, TryRecvError};
 
 pub type ShmService<A, B, C, D> = Service<A, B, C, D>;
 
+/// Fills in the placement that `hint` leaves unset from `PHOENIX_CPU_AFFINITY` (e.g., `0-3,8`)
+/// and `PHOENIX_NUMA_NODE`, so that the engines of an application can be pinned at launch.
+fn placement_from_env(mut hint: SchedulingHint) -> Result<SchedulingHint, Error> {
+    if hint.cpu_affinity.is_none() {
+        if let Ok(cpus) = env::var("PHOENIX_CPU_AFFINITY") {
+            let cpus = cpus
+                .parse()
+                .map_err(|e| Error::InvalidEnv("PHOENIX_CPU_AFFINITY", e))?;
+            hint.cpu_affinity = Some(cpus);
+        }
+    }
+    if hint.numa_node_affinity.is_none() {
+        if let Ok(node) = env::var("PHOENIX_NUMA_NODE") {
+            let node = node.parse().map_err(|e: std::num::ParseIntError| {
+                Error::InvalidEnv("PHOENIX_NUMA_NODE", e.to_string())
+            })?;
+            hint.numa_node_affinity = Some(node);
+        }
+    }
+    Ok(hint)
+}
+
+/// Binds a domain socket with a unique name under `phoenix_prefix` to talk to the daemon.
+fn bind_client_socket(phoenix_prefix: &Path) -> Result<DomainSocket, Error> {
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = phoenix_prefix.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+    if sock_path.exists() {
+        fs::remove_file(&sock_path).expect("remove_file");
+    }
+    Ok(DomainSocket::bind(sock_path)?)
+}
+
+/// Sends a request to the control plane of the daemon, without waiting for a response. For the
+/// requests that the daemon does not answer, e.g., [`control::Request::RegisterEndpoint`].
+pub fn notify_control<P: AsRef<Path>>(
+    phoenix_prefix: P,
+    control_path: P,
+    req: &control::Request,
+) -> Result<(), Error> {
+    let sock = bind_client_socket(phoenix_prefix.as_ref())?;
+    let buf = bincode::serialize(req)?;
+    assert!(buf.len() < MAX_MSG_LEN);
+    sock.send_to(&buf, phoenix_prefix.as_ref().join(control_path))?;
+    Ok(())
+}
+
+/// How long [`request_control`] waits for the response of the daemon.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a request to the control plane of the daemon and waits for the response, for at most
+/// 5 seconds.
+pub fn request_control<P: AsRef<Path>>(
+    phoenix_prefix: P,
+    control_path: P,
+    req: &control::Request,
+) -> Result<control::ResponseKind, Error> {
+    let sock = bind_client_socket(phoenix_prefix.as_ref())?;
+    let buf = bincode::serialize(req)?;
+    assert!(buf.len() < MAX_MSG_LEN);
+    let service_path = phoenix_prefix.as_ref().join(control_path);
+    sock.send_to(&buf, &service_path)?;
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    sock.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    let (_, sender) = match sock.recv_from(buf.as_mut_slice()) {
+        Ok(received) => received,
+        // the timeout is reported as either kind depending on the platform
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            return Err(Error::ControlTimeout(CONTROL_TIMEOUT));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+    let res: control::Response = bincode::deserialize(&buf)?;
+    res.0.map_err(|e| Error::ControlPlane("request_control", e))
+}
+
+/// The control path channels before the notifier of the commands is attached.
+enum CmdChannels<Command, Completion> {
+    Untagged(IpcSender<Command>, IpcReceiver<Completion>),
+    Tagged(IpcSender<Tagged<Command>>, IpcReceiver<Tagged<Completion>>),
+}
+
 unsafe impl<A: Sync, B: Sync, C: Sync, D: Sync> Sync for Service<A, B, C, D> {}
 
 /// A `Service` sends Command (contorl path) and WorkRequest (datapath)
@@ -40,8 +130,10 @@ unsafe impl<A: Sync, B: Sync, C: Sync, D: Sync> Sync for Service<A, B, C, D> {}
 /// The user must ensure that there is no concurrent access to this Service.
 pub struct Service<Command, Completion, WorkRequest, WorkCompletion> {
     sock: DomainSocket,
-    cmd_tx: IpcSenderNotify<Command>,
-    cmd_rx: IpcReceiver<Completion>,
+    // the path of the engine's domain socket, to send file descriptors to the engine
+    engine_path: PathBuf,
+    cmd_tx: CmdSender<Command>,
+    cmd_rx: CmdReceiver<Completion>,
     dp_wq: RefCell<ShmSender<WorkRequest>>,
     dp_cq: RefCell<ShmReceiver<WorkCompletion>>,
     timer: AtomicCell<Instant>,
@@ -75,27 +167,23 @@ where
         hint: SchedulingHint,
         config_str: Option<&str>,
     ) -> Result<Self, Error> {
-        let uuid = Uuid::new_v4();
-        let arg0 = env::args().next().unwrap();
-        let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
-
-        let sock_path = phoenix_prefix
-            .as_ref()
-            .join(format!("phoenix-client-{}_{}.sock", appname, uuid));
-        if sock_path.exists() {
-            fs::remove_file(&sock_path).expect("remove_file");
-        }
-        let mut sock = DomainSocket::bind(sock_path)?;
-
-        let req = control::Request::NewClient(hint, service, config_str.map(|s| s.to_string()));
+        let mut sock = bind_client_socket(phoenix_prefix.as_ref())?;
+
+        let hint = placement_from_env(hint)?;
+        let req = control::Request::NewVersionedClient(
+            PROTOCOL_VERSION,
+            hint,
+            service,
+            config_str.map(|s| s.to_string()),
+        );
         let buf = bincode::serialize(&req)?;
         assert!(buf.len() < MAX_MSG_LEN);
 
         let service_path = phoenix_prefix.as_ref().join(control_path);
         sock.send_to(&buf, &service_path)?;
 
-        // receive NewClient response
-        let mut buf = vec![0u8; 128];
+        // receive NewClient response, which may carry the reason the client is rejected
+        let mut buf = vec![0u8; MAX_MSG_LEN];
         let (_, sender) = sock.recv_from(buf.as_mut_slice())?;
         assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
         let res: control::Response = bincode::deserialize(&buf)?;
@@ -103,12 +191,20 @@ where
         // return the internal error
         let res = res.0.map_err(|e| Error::ControlPlane("NewClient", e))?;
 
-        match res {
-            control::ResponseKind::NewClient(engine_path) => {
-                sock.connect(engine_path)?;
+        let (protocol, engine_path) = match res {
+            control::ResponseKind::NewVersionedClient(protocol, engine_path) => {
+                if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol) {
+                    return Err(Error::ProtocolVersion(protocol, PROTOCOL_VERSION));
+                }
+                sock.connect(&engine_path)?;
+                (protocol, engine_path)
+            }
+            control::ResponseKind::NewClient(_) => {
+                // a daemon of the untagged protocol
+                return Err(Error::ProtocolVersion(PROTOCOL_V1, PROTOCOL_VERSION));
             }
             _ => panic!("unexpected response: {:?}", res),
-        }
+        };
 
         // connect to the engine, setup a bunch of channels and shared memory queues
         let mut buf = vec![0u8; 128];
@@ -127,12 +223,27 @@ where
                 cq_cap,
             } => {
                 // assert_eq!(mode, SchedulingMode::Dedicate);
-                let (cmd_tx1, cmd_rx1): (IpcSender<Command>, IpcReceiver<Command>) =
-                    crate::ipc_channel::channel()?;
-                let (cmd_tx2, cmd_rx2): (IpcSender<Completion>, IpcReceiver<Completion>) =
-                    crate::ipc_channel::channel()?;
-                let tx0 = IpcSender::connect(server_name)?;
-                tx0.send((cmd_tx2, cmd_rx1))?;
+                let cmd_channels = if protocol == PROTOCOL_V1 {
+                    let (cmd_tx1, cmd_rx1): (IpcSender<Command>, IpcReceiver<Command>) =
+                        crate::ipc_channel::channel()?;
+                    let (cmd_tx2, cmd_rx2): (IpcSender<Completion>, IpcReceiver<Completion>) =
+                        crate::ipc_channel::channel()?;
+                    let tx0 = IpcSender::connect(server_name)?;
+                    tx0.send((cmd_tx2, cmd_rx1))?;
+                    CmdChannels::Untagged(cmd_tx1, cmd_rx2)
+                } else {
+                    let (cmd_tx1, cmd_rx1): (
+                        IpcSender<Tagged<Command>>,
+                        IpcReceiver<Tagged<Command>>,
+                    ) = crate::ipc_channel::channel()?;
+                    let (cmd_tx2, cmd_rx2): (
+                        IpcSender<Tagged<Completion>>,
+                        IpcReceiver<Tagged<Completion>>,
+                    ) = crate::ipc_channel::channel()?;
+                    let tx0 = IpcSender::connect(server_name)?;
+                    tx0.send((cmd_tx2, cmd_rx1))?;
+                    CmdChannels::Tagged(cmd_tx1, cmd_rx2)
+                };
 
                 // receive file descriptors to attach to the shared memory queues
                 let (fds, cred) = sock.recv_fd()?;
@@ -174,13 +285,25 @@ where
                 let cmd_tx_entries = ShmObject::open(cmd_tx_notify_memfd)?;
                 let fd_notifier = ShmObject::open(fd_notifier_memfd)?;
 
+                let (cmd_tx, cmd_rx) = match cmd_channels {
+                    CmdChannels::Untagged(cmd_tx, cmd_rx) => (
+                        CmdSender::Untagged(IpcSenderNotify::new(cmd_tx, cmd_tx_entries)),
+                        CmdReceiver::Untagged(cmd_rx),
+                    ),
+                    CmdChannels::Tagged(cmd_tx, cmd_rx) => (
+                        CmdSender::Tagged(IpcSenderNotify::new(cmd_tx, cmd_tx_entries), protocol),
+                        CmdReceiver::Tagged(cmd_rx, protocol),
+                    ),
+                };
+
                 #[cfg(feature = "customer")]
                 let dp_cq_eventfd = async_io::Async::new(dp_cq.empty_signal().as_raw_fd())?;
 
                 Ok(Self {
                     sock,
-                    cmd_tx: IpcSenderNotify::new(cmd_tx1, cmd_tx_entries),
-                    cmd_rx: cmd_rx2,
+                    engine_path,
+                    cmd_tx,
+                    cmd_rx,
                     dp_wq: RefCell::new(dp_wq),
                     dp_cq: RefCell::new(dp_cq),
                     timer: AtomicCell::new(Instant::now()),
@@ -213,6 +336,14 @@ where
         }
     }
 
+    /// Sends file descriptors to the engine, which receives them with `Customer::recv_fd`.
+    #[inline]
+    pub fn send_fd(&self, fds: &[RawFd]) -> Result<(), Error> {
+        self.sock
+            .send_fd(&self.engine_path, fds)
+            .map_err(|e| Error::SendFd(Box::new(e)))
+    }
+
     #[inline]
     pub fn send_cmd(&self, cmd: Command) -> Result<(), Error> {
         Ok(self.cmd_tx.send(cmd)?)
@@ -25,6 +25,8 @@ pub enum Error {
     Truncated(usize),
     #[error("peer credential is empty, please make sure the socket is connected")]
     NotConnected,
+    #[error("more fds are sent than can be received at once")]
+    TooManyFds,
 }
 
 fn get_ucred() -> UCred {
@@ -214,8 +216,6 @@ impl DomainSocket {
         let (_size, truncated) = self.recv_vectored_with_ancillary(bufs, &mut ancillary)?;
         // TODO(cjr): sanity check the sender, and see if it is the correct phoenix transport engine
 
-        assert!(!truncated, "TODO: implement the logic to handle more fds");
-
         let mut cred = None;
         for ancillary_result in ancillary.messages() {
             match ancillary_result.unwrap() {
@@ -239,6 +239,14 @@ impl DomainSocket {
             }
         }
 
+        if truncated {
+            // the kernel has closed the fds that do not fit, close the others as well
+            for fd in fds {
+                // SAFETY: the fds are received and owned by us
+                unsafe { libc::close(fd) };
+            }
+            return Err(Error::TooManyFds);
+        }
         Ok((fds, cred))
     }
 
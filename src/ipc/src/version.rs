@@ -0,0 +1,146 @@
+//! Versioning of the control path protocol between the user library and the daemon.
+//!
+//! The library announces the protocol version it speaks in `Request::NewVersionedClient` when it
+//! subscribes to a service, and the daemon replies with the version both sides use for this
+//! subscription, which is the lower of the two. From `PROTOCOL_V2` on, every command and
+//! completion is tagged with the agreed version, so a message from a mismatched build is rejected
+//! with an error instead of being misread as another variant.
+//!
+//! The version is bumped whenever the encoding of a command, a completion, `MessageMeta` or
+//! `phoenix_api::Error` changes, even if the change is only a new field, because bincode cannot
+//! tell the old layout from the new one. A build decodes only the types of its own version, so
+//! it refuses the peers of an older one: the daemon answers the handshake, or the legacy
+//! `Request::NewClient` of `PROTOCOL_V1`, with an error naming the versions, and the library
+//! fails to register with [`Error::ProtocolVersion`](crate::Error::ProtocolVersion) when the
+//! daemon is older.
+use serde::{Deserialize, Serialize};
+
+use crate::ipc_channel::{IpcReceiver, IpcRecvError, IpcSenderNotify, TryRecvError};
+use crate::Error;
+
+pub type ProtocolVersion = u32;
+
+/// The bare bincode of the commands and completions, with no handshake.
+pub const PROTOCOL_V1: ProtocolVersion = 1;
+/// Adds the version handshake and tags the commands and completions with the version.
+pub const PROTOCOL_V2: ProtocolVersion = 2;
+/// Adds the fields and variants of the commands, completions, `MessageMeta` and errors since
+/// `PROTOCOL_V2`, e.g., the bind options, the correlation ID and the rejections by admission.
+pub const PROTOCOL_V3: ProtocolVersion = 3;
+
+/// The version spoken by this build.
+pub const PROTOCOL_VERSION: ProtocolVersion = PROTOCOL_V3;
+/// The oldest version this build can still speak. The commands of the older versions no longer
+/// decode as the commands of this one.
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = PROTOCOL_V3;
+
+/// Returns the version to speak with a peer of version `peer`.
+pub fn negotiate(peer: ProtocolVersion) -> Result<ProtocolVersion, Error> {
+    if peer < MIN_PROTOCOL_VERSION {
+        return Err(Error::ProtocolVersion(peer, PROTOCOL_VERSION));
+    }
+    Ok(peer.min(PROTOCOL_VERSION))
+}
+
+/// A command or completion tagged with the protocol version it is encoded in.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Tagged<T> {
+    version: ProtocolVersion,
+    msg: T,
+}
+
+fn mismatch(found: ProtocolVersion, expected: ProtocolVersion) -> IpcRecvError {
+    IpcRecvError::Bincode(Box::new(bincode::ErrorKind::Custom(format!(
+        "protocol version mismatch, expect {}, found {}",
+        expected, found
+    ))))
+}
+
+/// The sending half of the command channel, in the encoding of the agreed version.
+pub(crate) enum CmdSender<T> {
+    Untagged(IpcSenderNotify<T>),
+    Tagged(IpcSenderNotify<Tagged<T>>, ProtocolVersion),
+}
+
+impl<T: Serialize> CmdSender<T> {
+    pub(crate) fn send(&self, msg: T) -> Result<(), bincode::Error> {
+        match self {
+            CmdSender::Untagged(tx) => tx.send(msg),
+            CmdSender::Tagged(tx, version) => tx.send(Tagged {
+                version: *version,
+                msg,
+            }),
+        }
+    }
+}
+
+/// The receiving half of the command channel, in the encoding of the agreed version.
+pub(crate) enum CmdReceiver<T> {
+    Untagged(IpcReceiver<T>),
+    Tagged(IpcReceiver<Tagged<T>>, ProtocolVersion),
+}
+
+impl<T> CmdReceiver<T>
+where
+    T: for<'de> Deserialize<'de> + Serialize,
+{
+    pub(crate) fn recv(&self) -> Result<T, IpcRecvError> {
+        match self {
+            CmdReceiver::Untagged(rx) => rx.recv(),
+            CmdReceiver::Tagged(rx, version) => {
+                let tagged = rx.recv()?;
+                if tagged.version != *version {
+                    return Err(mismatch(tagged.version, *version));
+                }
+                Ok(tagged.msg)
+            }
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self {
+            CmdReceiver::Untagged(rx) => rx.try_recv(),
+            CmdReceiver::Tagged(rx, version) => {
+                let tagged = rx.try_recv()?;
+                if tagged.version != *version {
+                    return Err(TryRecvError::IpcError(mismatch(tagged.version, *version)));
+                }
+                Ok(tagged.msg)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_lower_version() {
+        assert_eq!(negotiate(PROTOCOL_VERSION).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1).unwrap(), PROTOCOL_VERSION);
+        assert!(negotiate(0).is_err());
+    }
+
+    #[test]
+    fn reject_older_versions() {
+        for version in [PROTOCOL_V1, PROTOCOL_V2] {
+            assert!(matches!(
+                negotiate(version),
+                Err(Error::ProtocolVersion(v, PROTOCOL_VERSION)) if v == version
+            ));
+        }
+    }
+
+    #[test]
+    fn tagged_encoding() {
+        let buf = bincode::serialize(&Tagged {
+            version: PROTOCOL_V2,
+            msg: String::from("cmd"),
+        })
+        .unwrap();
+        // the version comes first, followed by the untagged message
+        assert_eq!(&buf[..4], &PROTOCOL_V2.to_le_bytes());
+        assert_eq!(&buf[4..], &bincode::serialize("cmd").unwrap()[..]);
+    }
+}
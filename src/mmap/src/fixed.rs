@@ -33,7 +33,33 @@ impl MmapFixed {
     ) -> io::Result<Self> {
         let len = memfile.metadata()?.len() as usize;
         assert!(len >= map_len);
+        Self::map(
+            target_addr,
+            map_len,
+            file_off,
+            memfile,
+            libc::PROT_READ | libc::PROT_WRITE,
+        )
+    }
+
+    /// Maps `[file_off, file_off + map_len)` of `file` at `target_addr` for reading only, e.g.,
+    /// when the file is opened read-only. Writing to the memory causes SIGSEGV.
+    pub fn new_readonly(
+        target_addr: usize,
+        map_len: usize,
+        file_off: i64,
+        file: &fs::File,
+    ) -> io::Result<Self> {
+        Self::map(target_addr, map_len, file_off, file, libc::PROT_READ)
+    }
 
+    fn map(
+        target_addr: usize,
+        map_len: usize,
+        file_off: i64,
+        memfile: &fs::File,
+        prot: libc::c_int,
+    ) -> io::Result<Self> {
         // let hugetlb = if map_len & 0x1fffff == 0 && target_addr & 0x1fffff == 0 && len & 0x1fffff == 0 {
         //     libc::MAP_HUGETLB | libc::MAP_HUGE_2MB
         // } else {
@@ -53,7 +79,7 @@ impl MmapFixed {
             libc::mmap(
                 target_addr as *mut libc::c_void,
                 map_len,
-                libc::PROT_READ | libc::PROT_WRITE,
+                prot,
                 flags,
                 memfile.as_raw_fd(),
                 file_off,
@@ -68,6 +94,19 @@ impl MmapFixed {
         }
     }
 
+    /// Makes the pages in `[offset, offset + len)` of the mapping inaccessible, so that touching
+    /// them causes SIGSEGV, e.g., to guard the memory next to them. `offset` and `len` must be
+    /// multiples of the page size.
+    pub fn protect_none(&self, offset: usize, len: usize) -> io::Result<()> {
+        assert!(offset + len <= self.len);
+        let addr = unsafe { self.ptr.cast::<u8>().add(offset) };
+        let ret = unsafe { libc::mprotect(addr.cast(), len, libc::PROT_NONE) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
     /// Returns the number of bytes of this memory segment, also referred to as its 'length'.
     #[inline]
     pub fn len(&self) -> usize {
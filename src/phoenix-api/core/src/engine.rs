@@ -1,4 +1,7 @@
 //! Common date types for engine
+use std::fmt;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -46,4 +49,127 @@ pub struct SchedulingHint {
     pub mode: SchedulingMode,
     /// The numa node the user thread affinites to.
     pub numa_node_affinity: Option<u8>,
+    /// The cores to run the engines of the user on. It takes precedence over
+    /// `numa_node_affinity`.
+    pub cpu_affinity: Option<CpuSet>,
+}
+
+/// A set of CPU cores, written in the cpuset list format, e.g., `0-3,8`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CpuSet([u64; CpuSet::WORDS]);
+
+impl CpuSet {
+    const WORDS: usize = 16;
+    /// The largest number of cores that a set can hold.
+    pub const MAX_CPUS: usize = Self::WORDS * 64;
+
+    pub const fn new() -> Self {
+        CpuSet([0; Self::WORDS])
+    }
+
+    /// Adds `cpu` to the set. Returns false if `cpu` is out of range.
+    pub fn insert(&mut self, cpu: usize) -> bool {
+        if cpu >= Self::MAX_CPUS {
+            return false;
+        }
+        self.0[cpu / 64] |= 1 << (cpu % 64);
+        true
+    }
+
+    #[inline]
+    pub fn contains(&self, cpu: usize) -> bool {
+        cpu < Self::MAX_CPUS && self.0[cpu / 64] & (1 << (cpu % 64)) != 0
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|w| *w == 0)
+    }
+
+    /// Returns the cores in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..Self::MAX_CPUS).filter(|cpu| self.contains(*cpu))
+    }
+}
+
+impl Default for CpuSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for CpuSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = CpuSet::new();
+        for range in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let (lo, hi) = range.split_once('-').unwrap_or((range, range));
+            let parse = |x: &str| {
+                x.trim()
+                    .parse::<usize>()
+                    .map_err(|e| format!("invalid cpu {:?} in {:?}: {}", x, s, e))
+            };
+            let (lo, hi) = (parse(lo)?, parse(hi)?);
+            if lo > hi {
+                return Err(format!("invalid cpu range {:?}", range));
+            }
+            for cpu in lo..=hi {
+                if !set.insert(cpu) {
+                    return Err(format!("cpu {} exceeds {}", cpu, Self::MAX_CPUS));
+                }
+            }
+        }
+        if set.is_empty() {
+            return Err(format!("empty cpu set {:?}", s));
+        }
+        Ok(set)
+    }
+}
+
+impl fmt::Display for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cpus = self.iter().peekable();
+        let mut first = true;
+        while let Some(lo) = cpus.next() {
+            let mut hi = lo;
+            while cpus.peek() == Some(&(hi + 1)) {
+                hi = cpus.next().unwrap();
+            }
+            if !first {
+                f.write_str(",")?;
+            }
+            first = false;
+            if lo == hi {
+                write!(f, "{}", lo)?;
+            } else {
+                write!(f, "{}-{}", lo, hi)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CpuSet({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_set_list_format() {
+        let set: CpuSet = "0-3, 8,10-11".parse().unwrap();
+        assert_eq!(set.iter().collect::<Vec<_>>(), [0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(set.to_string(), "0-3,8,10-11");
+        assert_eq!("5".parse::<CpuSet>().unwrap().to_string(), "5");
+
+        assert!("".parse::<CpuSet>().is_err());
+        assert!("3-1".parse::<CpuSet>().is_err());
+        assert!("a".parse::<CpuSet>().is_err());
+        assert!("1024".parse::<CpuSet>().is_err());
+    }
 }
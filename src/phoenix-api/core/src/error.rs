@@ -5,4 +5,32 @@ use thiserror::Error;
 pub enum Error {
     #[error("{0}")]
     Generic(String),
+    /// The daemon refuses to admit a new client process, as one of its limits is reached.
+    #[error("client rejected: {0}")]
+    Rejected(Rejection),
+}
+
+/// The limit of the daemon that a new client process is rejected for.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+pub enum Rejection {
+    /// The daemon serves as many client processes as it admits.
+    #[error("the daemon serves {max} client processes, the most it admits")]
+    MaxClients { max: usize },
+    /// The engines of all clients reach the limit per core of the host.
+    #[error("{engines} engines run on {cores} cores, at most {max_per_core} per core")]
+    MaxEnginesPerCore {
+        engines: usize,
+        cores: usize,
+        max_per_core: usize,
+    },
+    /// The cgroup of the client has as many client processes as it admits.
+    #[error("cgroup {cgroup} has {max} client processes, the most it admits")]
+    CgroupClients { cgroup: String, max: usize },
+    /// The cgroup of the client, or one of its ancestors, uses more memory than its cap.
+    #[error("cgroup {cgroup} uses {usage} bytes of memory, over its cap of {cap} bytes")]
+    CgroupMemory {
+        cgroup: String,
+        usage: u64,
+        cap: u64,
+    },
 }
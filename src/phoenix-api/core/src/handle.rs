@@ -2,13 +2,84 @@ use std::os::unix::io::RawFd;
 
 use serde::{Deserialize, Serialize};
 
+/// An opaque 64-bit reference to a resource held by the backend.
+///
+/// The top 8 bits of a handle tell which kind of resource it refers to (see
+/// [`HandleNamespace`]), and the lower 56 bits identify the resource within that kind. Handles
+/// of different namespaces never compare equal even if their ids are the same. Handles created
+/// by `Handle(x)` directly fall in [`HandleNamespace::Untyped`] as long as `x` fits in 56 bits.
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Handle(pub u64);
 
+/// The kind of resource a [`Handle`] refers to.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HandleNamespace {
+    /// Handles that are not tagged, e.g., file descriptors and verbs handles.
+    Untyped = 0,
+    /// RDMA communication identifiers.
+    CmId = 1,
+    /// Memory regions.
+    Mr = 2,
+    /// Receive buffers of the RPC adapters.
+    RecvBuf = 3,
+    /// Listening sockets.
+    Listener = 4,
+    /// Connections over unreliable datagrams, which have no communication identifier of their own.
+    Datagram = 5,
+    /// Reserved for [`Handle::INVALID`] and [`Handle::MASTER`].
+    Reserved = 0xff,
+}
+
+impl HandleNamespace {
+    #[inline]
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(HandleNamespace::Untyped),
+            1 => Some(HandleNamespace::CmId),
+            2 => Some(HandleNamespace::Mr),
+            3 => Some(HandleNamespace::RecvBuf),
+            4 => Some(HandleNamespace::Listener),
+            5 => Some(HandleNamespace::Datagram),
+            0xff => Some(HandleNamespace::Reserved),
+            _ => None,
+        }
+    }
+}
+
 impl Handle {
     pub const INVALID: Handle = Handle(u64::MAX);
     pub const MASTER: Handle = Handle(u64::MAX - 1);
+
+    /// The number of bits of the id part.
+    pub const ID_BITS: u32 = 56;
+    /// The largest id of a handle.
+    pub const MAX_ID: u64 = (1 << Self::ID_BITS) - 1;
+
+    /// Creates a handle of `id` in namespace `ns`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is larger than [`Handle::MAX_ID`].
+    #[inline]
+    pub const fn new(ns: HandleNamespace, id: u64) -> Handle {
+        assert!(id <= Self::MAX_ID, "handle id exceeds 56 bits");
+        Handle((ns as u64) << Self::ID_BITS | id)
+    }
+
+    /// Returns the namespace of the handle, or `None` if the tag is unknown.
+    #[inline]
+    pub fn namespace(&self) -> Option<HandleNamespace> {
+        HandleNamespace::from_tag((self.0 >> Self::ID_BITS) as u8)
+    }
+
+    /// Returns the id of the handle within its namespace.
+    #[inline]
+    pub const fn id(&self) -> u64 {
+        self.0 & Self::MAX_ID
+    }
+
     pub fn is_master(&self) -> bool {
         self.0 == Self::MASTER.0
     }
@@ -29,3 +100,62 @@ impl AsHandle for RawFd {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_do_not_collide() {
+        let namespaces = [
+            HandleNamespace::Untyped,
+            HandleNamespace::CmId,
+            HandleNamespace::Mr,
+            HandleNamespace::RecvBuf,
+            HandleNamespace::Listener,
+            HandleNamespace::Datagram,
+        ];
+        for (i, a) in namespaces.iter().enumerate() {
+            for b in &namespaces[i + 1..] {
+                for id in [0, 1, 0xffff, 1 << 32, Handle::MAX_ID] {
+                    assert_ne!(Handle::new(*a, id), Handle::new(*b, id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        for id in [0, 42, u32::MAX as u64, Handle::MAX_ID] {
+            let h = Handle::new(HandleNamespace::RecvBuf, id);
+            assert_eq!(h.namespace(), Some(HandleNamespace::RecvBuf));
+            assert_eq!(h.id(), id);
+        }
+        // Plain handles are untyped.
+        assert_eq!(Handle(7).namespace(), Some(HandleNamespace::Untyped));
+        assert_eq!(Handle::new(HandleNamespace::Untyped, 7), Handle(7));
+    }
+
+    #[test]
+    fn reserved_handles() {
+        assert_eq!(Handle::INVALID.namespace(), Some(HandleNamespace::Reserved));
+        assert_eq!(Handle::MASTER.namespace(), Some(HandleNamespace::Reserved));
+        for ns in [
+            HandleNamespace::Untyped,
+            HandleNamespace::CmId,
+            HandleNamespace::Mr,
+            HandleNamespace::RecvBuf,
+            HandleNamespace::Listener,
+            HandleNamespace::Datagram,
+        ] {
+            assert_ne!(Handle::new(ns, Handle::MAX_ID), Handle::INVALID);
+            assert_ne!(Handle::new(ns, Handle::MAX_ID), Handle::MASTER);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn id_overflow() {
+        let _ = Handle::new(HandleNamespace::CmId, 1 << 56);
+    }
+}
@@ -2,7 +2,7 @@
 #![allow(missing_docs)]
 
 pub mod handle;
-pub use handle::{AsHandle, Handle};
+pub use handle::{AsHandle, Handle, HandleNamespace};
 
 pub mod error;
 pub use error::Error;
@@ -27,6 +27,9 @@ pub struct SharedReceiveQueue(pub Handle);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct QueuePair(pub Handle);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AddressHandle(pub Handle);
+
 pub mod returned {
     use serde::{Deserialize, Serialize};
 
@@ -203,6 +206,8 @@ bitflags! {
         const REMOTE_READ = 0b00000100;
         /// Enables Remote Atomic Operation Access.
         const REMOTE_ATOMIC = 0b00001000;
+        /// Registers the memory region with on-demand paging, its pages are not pinned.
+        const ON_DEMAND = 0b01000000;
     }
 }
 
@@ -30,6 +30,8 @@ impl From<Token> for usize {
 pub enum RpcMsgType {
     Request,
     Response,
+    /// A request that expects no reply. It is delivered at most once.
+    Post,
 }
 
 /// An `u64` associated with an RPC.
@@ -68,8 +70,13 @@ impl RpcId {
 
 /// Transport layer status.
 ///
-/// This transport error will be translated to mrpc::Status::internal("") on local send failure,
-/// or mrpc::Status::data_loss("") on local receive failure.
+/// The error codes come from different layers, in disjoint ranges:
+/// - below 400, the status of a failed work completion of RDMA (`ibv_wc_status`);
+/// - 400 to 599, the statuses of mRPC and the policies, e.g., [`TransportStatus::BAD_MESSAGE`];
+/// - [`TransportStatus::ERRNO_BASE`] and above, the errno of a failed socket operation.
+///
+/// The application translates them to a `mrpc::Status` with the code attached, see
+/// `mrpc::TransportCode`.
 //
 // NOTE(cjr): Do not annotate this structure with any repr. Use repr(Rust)
 // and static assertions.
@@ -81,6 +88,95 @@ pub enum TransportStatus {
 }
 
 impl TransportStatus {
+    /// The received message is dropped because its payload fails the checksum verification.
+    pub const CHECKSUM_MISMATCH: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(422) });
+
+    /// The received message is dropped because its fragmented segments cannot be gathered, either
+    /// a segment exceeds the gather buffer, or all the gather buffers are in use.
+    pub const GATHER_FAILED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(507) });
+
+    /// The message is not sent because its deadline passed while it waited in the send queue of
+    /// the backend.
+    pub const DEADLINE_EXCEEDED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(408) });
+
+    /// The message is not sent because it exceeds the largest message the peer accepts.
+    pub const MESSAGE_TOO_LARGE: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(413) });
+
+    /// The message is dropped because the dispatch library cannot marshal or unmarshal it, e.g.,
+    /// its func_id is unknown to the library.
+    pub const BAD_MESSAGE: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(400) });
+
+    /// The call fails because the server implements no service or method of its func_id.
+    pub const UNIMPLEMENTED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(501) });
+
+    /// The call fails because the server cannot decode its request sent in the protobuf wire
+    /// format.
+    pub const UNDECODABLE: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(415) });
+
+    /// The call fails because the handler of the server returns an error for it.
+    pub const HANDLER_FAILED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(510) });
+
+    /// The message is dropped because the dispatch library panicked on it.
+    pub const DISPATCH_PANICKED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(500) });
+
+    /// The connection cannot be used because the peer sent settings that are not understood.
+    pub const INCOMPATIBLE_PEER: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(505) });
+
+    /// The message sent in bulk is dropped because the receiver fails to read its payload from
+    /// the sender.
+    pub const BULK_READ_FAILED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(502) });
+
+    /// The call fails because its connection is lost before the reply arrives, and it is not
+    /// replayed on a new connection.
+    pub const CONNECTION_LOST: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(503) });
+
+    /// The call fails because no reply arrives after all the retransmissions of the request.
+    pub const NO_REPLY: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(504) });
+
+    /// The message is not sent because its connection has moved to the transport engine of
+    /// another thread of the application, and is sent from there.
+    pub const MIGRATED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(421) });
+
+    /// The message is dropped because the transport fails in a way that is not specific to its
+    /// connection, e.g., on its shared memory queues.
+    pub const TRANSPORT_FAILED: TransportStatus =
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(520) });
+
+    /// The errno of a failed socket operation is offset by this, see
+    /// [`TransportStatus::from_errno`].
+    pub const ERRNO_BASE: u32 = 0x1_0000;
+
+    /// The status of a socket operation that fails with `errno`.
+    #[inline]
+    pub fn from_errno(errno: i32) -> Self {
+        let code = Self::ERRNO_BASE + errno.max(1) as u32;
+        // SAFETY: the code is at least ERRNO_BASE + 1
+        TransportStatus::Error(unsafe { NonZeroU32::new_unchecked(code) })
+    }
+
+    /// Returns the errno of a failed socket operation, see [`TransportStatus::from_errno`].
+    #[inline]
+    pub fn errno(self) -> Option<i32> {
+        match self.code() {
+            code if code > Self::ERRNO_BASE => Some((code - Self::ERRNO_BASE) as i32),
+            _ => None,
+        }
+    }
+
     /// Converting a [`TransportStatus`] to a `u32`.
     ///
     /// Returns 0 for Success. Returns the underlying error code otherwise.
@@ -93,6 +189,84 @@ impl TransportStatus {
     }
 }
 
+/// The health of a connection as observed by the transport.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The peer is responsive.
+    Connected,
+    /// The peer has not responded to keep-alive probes for a while.
+    Degraded,
+    /// The peer is considered dead, or the connection has been shut down.
+    Closed,
+    /// The connection is moved to the transport engine of another thread of the application,
+    /// which takes it as a new connection. It is not closed.
+    Migrated,
+}
+
+/// The timestamps of a call taken by the transport of the client, in nanoseconds of
+/// [`monotonic_ns`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransportTiming {
+    /// When the request is sent.
+    pub sent: u64,
+    /// When the first segment of the reply is received.
+    pub first_recv: u64,
+}
+
+/// Returns the current time of `CLOCK_MONOTONIC` in nanoseconds. The clock is shared by all the
+/// processes on a host, so the timestamps taken by the applications and the backend compare.
+///
+/// With the `virtual-clock` feature, the time of a thread whose [`virtual_clock`] is set is
+/// returned instead.
+pub fn monotonic_ns() -> u64 {
+    #[cfg(feature = "virtual-clock")]
+    if let Some(now) = virtual_clock::now() {
+        return now;
+    }
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: ts is a valid timespec, and CLOCK_MONOTONIC is always supported on Linux
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// A clock of each thread that [`monotonic_ns`] reads while it is set, for the tests to run the
+/// engines in virtual time.
+#[cfg(feature = "virtual-clock")]
+pub mod virtual_clock {
+    use std::cell::Cell;
+
+    thread_local! {
+        static NOW: Cell<Option<u64>> = Cell::new(None);
+    }
+
+    /// Sets the time of the current thread, or goes back to the real time with `None`.
+    pub fn set(now: Option<u64>) {
+        NOW.with(|cell| cell.set(now));
+    }
+
+    /// Returns the time of the current thread, if it is set.
+    pub fn now() -> Option<u64> {
+        NOW.with(|cell| cell.get())
+    }
+
+    /// Moves the time of the current thread forward by `ns`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the time is not set.
+    pub fn advance(ns: u64) {
+        NOW.with(|cell| {
+            let now = cell.get().expect("the virtual clock is not set");
+            cell.set(Some(now + ns));
+        });
+    }
+}
+
 /// The metadata prepended to each RPC message.
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -100,6 +274,30 @@ pub enum StatusCode {
     Success = 0,
     AccessDenied = 1,
     Unknown = 2,
+    /// The server implements no service or method of the request.
+    Unimplemented = 3,
+    /// The server cannot decode the request sent in the protobuf wire format, or would lose
+    /// some of its fields.
+    DataLoss = 4,
+    /// The handler of the server returns an error for the request.
+    Failed = 5,
+}
+
+impl StatusCode {
+    /// Returns the status of the call that gets a reply with this status code, `None` for
+    /// `Unknown`.
+    pub fn transport_status(self) -> Option<TransportStatus> {
+        match self {
+            StatusCode::Success => Some(TransportStatus::Success),
+            StatusCode::AccessDenied => Some(TransportStatus::Error(unsafe {
+                NonZeroU32::new_unchecked(402)
+            })),
+            StatusCode::Unimplemented => Some(TransportStatus::UNIMPLEMENTED),
+            StatusCode::DataLoss => Some(TransportStatus::UNDECODABLE),
+            StatusCode::Failed => Some(TransportStatus::HANDLER_FAILED),
+            StatusCode::Unknown => None,
+        }
+    }
 }
 
 #[repr(C)]
@@ -117,10 +315,59 @@ pub struct MessageMeta {
     /// The token can be used to associate RPC reply with its request, or be used as an extra piece
     /// of information passed to the server.
     pub token: u64,
+    /// Application correlation ID, opaque to mRPC. Unlike `call_id`, it is chosen by the
+    /// application for each call, e.g., to correlate the RPC with external systems, and the
+    /// reply carries the one of its request.
+    pub correlation_id: u64,
+    /// The ID of an exactly-once call, a random 128-bit ID drawn by the client for the call and
+    /// kept by its retransmissions, so the server can tell them apart from new calls and runs the
+    /// call at most once. All zeros for the other calls, see [`MessageMeta::exactly_once`].
+    pub request_id: [u8; 16],
     /// Whether the message is a request or a response.
     pub msg_type: RpcMsgType,
     /// Plugin specific status code.
     pub status_code: StatusCode,
+    /// Flags, see [`MessageMeta::PUSHBACK`], [`MessageMeta::RAW`] and [`MessageMeta::COMPAT`].
+    pub flags: u32,
+}
+
+impl MessageMeta {
+    /// Set on a reply by a congested server, to ask the client to slow down. The client is free
+    /// to ignore it.
+    pub const PUSHBACK: u32 = 1 << 0;
+
+    /// Set on the requests and replies of raw calls, whose payload is a byte string sent as is
+    /// rather than marshaled by the dispatch library of a proto.
+    pub const RAW: u32 = 1 << 1;
+
+    /// Set along with [`MessageMeta::RAW`] on the requests and replies encoded in the protobuf
+    /// wire format, for peers built from different versions of a proto. They are sent as raw
+    /// messages, but routed to the services generated from the proto.
+    pub const COMPAT: u32 = 1 << 2;
+
+    /// Returns whether the server asks the client to slow down.
+    #[inline]
+    pub fn pushback(&self) -> bool {
+        self.flags & Self::PUSHBACK != 0
+    }
+
+    /// Returns whether the message is of a raw call.
+    #[inline]
+    pub fn raw(&self) -> bool {
+        self.flags & Self::RAW != 0
+    }
+
+    /// Returns whether the message is encoded in the protobuf wire format.
+    #[inline]
+    pub fn compat(&self) -> bool {
+        self.flags & Self::COMPAT != 0
+    }
+
+    /// Returns whether the message is of an exactly-once call, i.e., it carries a request ID.
+    #[inline]
+    pub fn exactly_once(&self) -> bool {
+        self.request_id != [0; 16]
+    }
 }
 
 /// An RPC descriptor.
@@ -142,9 +389,10 @@ mod sa {
     use std::mem::size_of;
 
     const_assert_eq!(size_of::<StatusCode>(), 4);
+    const_assert_eq!(size_of::<ConnectionState>(), 4);
     const_assert_eq!(size_of::<Token>(), size_of::<usize>());
     const_assert_eq!(size_of::<TransportStatus>(), 4);
     const_assert_eq!(size_of::<RpcId>(), 16);
-    const_assert_eq!(size_of::<MessageMeta>(), 40);
-    const_assert_eq!(size_of::<MessageErased>(), 56);
+    const_assert_eq!(size_of::<MessageMeta>(), 72);
+    const_assert_eq!(size_of::<MessageErased>(), 88);
 }
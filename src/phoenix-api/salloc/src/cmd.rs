@@ -9,6 +9,14 @@ pub enum Command {
     AllocShm(usize, usize),
     // addr: usize
     DeallocShm(usize),
+    // Layout: (size, align, device)
+    AllocDeviceShm(usize, usize, i32),
+    // addr: usize
+    DeallocDeviceShm(usize),
+    // (len, file_off), the fd of the memory is sent before the command
+    RegisterUserMemory(usize, i64),
+    // addr: usize
+    UnregisterUserMemory(usize),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,6 +24,12 @@ pub enum CompletionKind {
     // remote_addr, file_off
     AllocShm(usize, i64),
     DeallocShm,
+    // remote_addr, cudaIpcMemHandle_t
+    AllocDeviceShm(usize, Vec<u8>),
+    DeallocDeviceShm,
+    // remote_addr
+    RegisterUserMemory(usize),
+    UnregisterUserMemory,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,16 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    // The layouts of the trait objects and types shared with the plugins depend on the compiler,
+    // which is recorded in the ABI descriptor, see `src/abi.rs`.
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=PHOENIX_RUSTC_VERSION={}", version.trim());
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
@@ -0,0 +1,208 @@
+//! The ABI descriptor of the plugins.
+//!
+//! phoenix and its plugins exchange trait objects, e.g., [`PhoenixModule`], [`PhoenixAddon`], and
+//! [`Engine`], and the types in them, which only agree if both sides are built from the same
+//! version of this crate by the same compiler. A plugin built otherwise would crash phoenix long
+//! after it is loaded, so every plugin exports its [`AbiDescriptor`] with [`export_abi!`], which
+//! phoenix checks against its own before initializing the plugin, and rejects the plugin with the
+//! fields that differ.
+//!
+//! ```ignore
+//! // in the lib.rs of a plugin
+//! phoenix_common::export_abi!();
+//! ```
+//!
+//! [`PhoenixModule`]: crate::PhoenixModule
+//! [`PhoenixAddon`]: crate::PhoenixAddon
+//! [`Engine`]: crate::engine::Engine
+use std::fmt;
+use std::mem::{align_of, size_of};
+
+use phoenix_api::rpc::MessageMeta;
+
+use crate::engine::datapath::message::{EngineRxMessage, EngineTxMessage, RpcMessageTx};
+use crate::engine::datapath::DataPathNode;
+use crate::engine::{EngineType, EventRecorder, Indicator, Profiler, TimerWheel};
+use crate::module::Version;
+use crate::storage::ResourceCollection;
+
+/// The symbol of the descriptor in a plugin.
+pub const ABI_SYMBOL: &str = "PHOENIX_ABI";
+
+/// The first field of a descriptor, to tell it from anything else under the symbol.
+pub const ABI_MAGIC: u64 = u64::from_le_bytes(*b"PHXABI\0\0");
+
+/// The version of [`AbiDescriptor`] itself, bumped when its fields change.
+pub const DESCRIPTOR_VERSION: u32 = 1;
+
+/// The revision of the plugin interface, bumped when a trait shared with the plugins, e.g.,
+/// `Engine`, `PhoenixModule`, or `PhoenixAddon`, changes its methods in a way the layout hash
+/// cannot see.
+pub const ABI_REVISION: u32 = 1;
+
+const VERSION_LEN: usize = 32;
+const RUSTC_LEN: usize = 96;
+
+/// The ABI a plugin, or phoenix, is built with.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AbiDescriptor {
+    pub magic: u64,
+    pub descriptor_version: u32,
+    pub abi_revision: u32,
+    /// A hash of the sizes and alignments of the types shared with the plugins
+    pub layout_hash: u64,
+    /// The version of this crate, padded with NULs
+    pub common_version: [u8; VERSION_LEN],
+    /// The output of `rustc --version` of the compiler, padded with NULs
+    pub rustc_version: [u8; RUSTC_LEN],
+}
+
+impl AbiDescriptor {
+    /// The descriptor of the ABI this crate is built with.
+    pub const fn current() -> Self {
+        AbiDescriptor {
+            magic: ABI_MAGIC,
+            descriptor_version: DESCRIPTOR_VERSION,
+            abi_revision: ABI_REVISION,
+            layout_hash: layout_hash(),
+            common_version: padded(env!("CARGO_PKG_VERSION")),
+            rustc_version: padded(env!("PHOENIX_RUSTC_VERSION")),
+        }
+    }
+
+    /// Reads the descriptor at `addr`, the address of the symbol [`ABI_SYMBOL`] of a plugin.
+    /// Returns `None` if it is not a descriptor of the same version of the struct.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must point to at least the magic and the version of a descriptor, and to a whole
+    /// descriptor if they match.
+    pub unsafe fn read(addr: usize) -> Option<Self> {
+        let magic = (addr as *const u64).read_unaligned();
+        let version = ((addr + size_of::<u64>()) as *const u32).read_unaligned();
+        if magic != ABI_MAGIC || version != DESCRIPTOR_VERSION {
+            return None;
+        }
+        Some((addr as *const AbiDescriptor).read_unaligned())
+    }
+
+    /// Returns the fields that differ from `other`, as `field: self vs. other`.
+    pub fn mismatches(&self, other: &AbiDescriptor) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if self.abi_revision != other.abi_revision {
+            mismatches.push(format!(
+                "ABI revision: {} vs. {}",
+                self.abi_revision, other.abi_revision
+            ));
+        }
+        if self.common_version != other.common_version {
+            mismatches.push(format!(
+                "phoenix_common version: {} vs. {}",
+                unpadded(&self.common_version),
+                unpadded(&other.common_version)
+            ));
+        }
+        if self.rustc_version != other.rustc_version {
+            mismatches.push(format!(
+                "compiler: {} vs. {}",
+                unpadded(&self.rustc_version),
+                unpadded(&other.rustc_version)
+            ));
+        }
+        if self.layout_hash != other.layout_hash {
+            mismatches.push(format!(
+                "layout hash: {:#018x} vs. {:#018x}",
+                self.layout_hash, other.layout_hash
+            ));
+        }
+        mismatches
+    }
+}
+
+impl fmt::Debug for AbiDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AbiDescriptor")
+            .field("descriptor_version", &self.descriptor_version)
+            .field("abi_revision", &self.abi_revision)
+            .field("layout_hash", &format_args!("{:#018x}", self.layout_hash))
+            .field("common_version", &unpadded(&self.common_version))
+            .field("rustc_version", &unpadded(&self.rustc_version))
+            .finish()
+    }
+}
+
+/// Exports the [`AbiDescriptor`] of a plugin, which phoenix checks before loading it. Every
+/// plugin must invoke it once, in its crate root.
+#[macro_export]
+macro_rules! export_abi {
+    () => {
+        #[no_mangle]
+        #[used]
+        #[link_section = ".phoenix_abi"]
+        pub static PHOENIX_ABI: $crate::abi::AbiDescriptor = $crate::abi::AbiDescriptor::current();
+    };
+}
+
+/// The sizes and alignments of types.
+macro_rules! layouts {
+    ($($ty:ty),* $(,)?) => {
+        [$((size_of::<$ty>(), align_of::<$ty>())),*]
+    };
+}
+
+/// Hashes the sizes and alignments of the types that cross between phoenix and the plugins, with
+/// FNV-1a.
+const fn layout_hash() -> u64 {
+    let layouts = layouts![
+        EngineType,
+        Indicator,
+        Profiler,
+        EventRecorder,
+        TimerWheel,
+        DataPathNode,
+        MessageMeta,
+        RpcMessageTx,
+        EngineTxMessage,
+        EngineRxMessage,
+        ResourceCollection,
+        Version,
+    ];
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < layouts.len() {
+        let (size, align) = layouts[i];
+        hash = fnv1a(hash, size as u64);
+        hash = fnv1a(hash, align as u64);
+        i += 1;
+    }
+    hash
+}
+
+const fn fnv1a(mut hash: u64, value: u64) -> u64 {
+    let bytes = value.to_le_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Copies `s` into a NUL-padded array, truncated if it does not fit.
+const fn padded<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut out = [0; N];
+    let mut i = 0;
+    while i < bytes.len() && i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+fn unpadded(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
@@ -40,6 +40,13 @@ pub trait PhoenixAddon: TypeTagged + Send + Sync + 'static {
     /// Live update addon's (RPC policy's) configuration.
     fn update_config(&mut self, config: &str) -> Result<()>;
 
+    /// An engine request applying the current configuration to the running engines of the
+    /// addon, or `None` if they only take the configuration when created.
+    #[inline]
+    fn config_request(&self) -> Option<Vec<u8>> {
+        None
+    }
+
     /// Create a new addon engine
     fn create_engine(
         &mut self,
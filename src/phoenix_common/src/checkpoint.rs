@@ -0,0 +1,60 @@
+//! Checkpoints of the daemon, to serve the running clients again after the daemon restarts.
+//!
+//! A checkpoint records what a restarted daemon cannot learn from its clients: the shared memory
+//! regions it allocated for each client process (see [`PhoenixModule::checkpoint`]), and the
+//! states and connections of the engines (see [`Engine::checkpoint`]). The memory of the regions
+//! is not in the checkpoint. The clients keep mapping it, so the restarted daemon opens the
+//! memfds again from the mappings of the clients, found by their names and addresses, and hands
+//! them to the modules to map (see [`PhoenixModule::restore`]).
+//!
+//! The connections of the engines do not survive the daemon, as the sockets and the RDMA verbs
+//! objects are owned by the daemon process. They are recorded so that the restarted daemon can
+//! tell which connections the clients have lost.
+//!
+//! [`PhoenixModule::checkpoint`]: crate::module::PhoenixModule::checkpoint
+//! [`PhoenixModule::restore`]: crate::module::PhoenixModule::restore
+//! [`Engine::checkpoint`]: crate::engine::Engine::checkpoint
+use std::fs::File;
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// A shared memory region allocated for a client, which maps it at the same address as the
+/// daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShmRegionDescriptor {
+    /// The name of the memfd backing the region, without the `memfd:` prefix shown in
+    /// `/proc/<pid>/maps`.
+    pub name: String,
+    pub addr: usize,
+    pub len: usize,
+    pub align: usize,
+}
+
+/// A connection of an engine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionDescriptor {
+    /// The handle of the connection given to the client.
+    pub handle: u64,
+    pub local: Option<SocketAddr>,
+    pub peer: Option<SocketAddr>,
+    /// The handle of the listener the connection is accepted on, if it is.
+    pub listener: Option<u64>,
+}
+
+/// What an engine saves in a checkpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineCheckpoint {
+    /// The state to resume the engine from, in an encoding of the engine's choice. Empty if
+    /// the engine cannot be resumed.
+    pub state: Vec<u8>,
+    pub connections: Vec<ConnectionDescriptor>,
+}
+
+/// A shared memory region of a checkpoint, with the memfd opened again from the mapping of the
+/// client.
+#[derive(Debug)]
+pub struct RestoredRegion {
+    pub descriptor: ShmRegionDescriptor,
+    pub memfd: File,
+}
@@ -0,0 +1,64 @@
+//! Typed configurations of the plugins.
+//!
+//! A plugin declares its configuration as a serde struct with `#[serde(default)]`, so the fields
+//! left out take the values of its [`Default`] implementation, and checks the values the types
+//! cannot rule out in [`PluginConfig::validate`]. The same parsing runs when the plugin is loaded
+//! and when its configuration is updated live, so a bad update leaves the running configuration
+//! untouched.
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// The TOML is malformed or does not match the schema. The message tells the line and column.
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid `{field}`: {reason}")]
+    Invalid { field: String, reason: String },
+}
+
+impl ConfigError {
+    /// Reports an invalid value of `field`, the dotted path of the key, e.g., `srq.buffer_size`.
+    pub fn invalid(field: impl Into<String>, reason: impl fmt::Display) -> Self {
+        ConfigError::Invalid {
+            field: field.into(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+pub trait PluginConfig: DeserializeOwned + Default {
+    /// Checks the values after they are parsed.
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    /// Parses and validates a configuration. The default configuration is validated too if
+    /// `config` is `None`.
+    fn parse(config: Option<&str>) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(config.unwrap_or(""))?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Checks that `value` of `field` is within `range`.
+pub fn check_range<T>(
+    field: &str,
+    value: T,
+    range: impl std::ops::RangeBounds<T> + fmt::Debug,
+) -> Result<(), ConfigError>
+where
+    T: PartialOrd + fmt::Display,
+{
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        Err(ConfigError::invalid(
+            field,
+            format!("{} is not within {:?}", value, range),
+        ))
+    }
+}
@@ -1,6 +1,8 @@
 use std::ptr::Unique;
 
-use phoenix_api::rpc::{CallId, MessageMeta, RpcId, TransportStatus};
+use phoenix_api::rpc::{
+    CallId, ConnectionState, MessageMeta, RpcId, TransportStatus, TransportTiming,
+};
 use phoenix_api::Handle;
 use phoenix_api_mrpc::dp::RECV_RECLAIM_BS;
 
@@ -14,6 +16,15 @@ pub struct RpcMessageTx {
     // Each RPC message is assigned a buffer for meta and optionally for its data
     pub meta_buf_ptr: MetaBufferPtr,
     pub addr_backend: usize,
+    // When the message is given up if it has not been sent, in nanoseconds of `monotonic_ns`.
+    // 0 for never.
+    pub deadline: u64,
+    // Whether the payload is read by the peer with RDMA READs instead of being sent, if the
+    // transport supports it.
+    pub bulk: bool,
+    // The version of the dispatch library to marshal the message with, the one loaded when the
+    // message is sent. 0 for the latest.
+    pub dispatch_version: u32,
 }
 
 #[derive(Debug)]
@@ -35,4 +46,8 @@ pub enum EngineRxMessage {
     Ack(RpcId, TransportStatus),
     // (conn_id, status), we cannot know which rpc_id the receive corresponds
     RecvError(Handle, TransportStatus),
+    // (conn_id, state), the connection health changes, e.g., detected by keep-alive
+    ConnectionState(Handle, ConnectionState),
+    // the timestamps of a call that has timing enabled, sent before its reply
+    CallTiming(RpcId, TransportTiming),
 }
@@ -67,7 +67,7 @@ impl MetaBuffer {
     /// Returns the number of bytes the `MetaBuffer` can hold.
     #[inline]
     pub const fn capacity() -> usize {
-        META_BUFFER_SIZE - 40
+        META_BUFFER_SIZE - (mem::size_of::<MessageMeta>() + 8)
     }
 
     /// Returns the offset in bytes of the message to the beginning of `length_delimited`.
@@ -178,4 +178,10 @@ impl MetaBufferPool {
         self.free.push(buf);
         Ok(())
     }
+
+    /// Returns the [`RpcId`]s that hold a [`MetaBuffer`], i.e., the messages in flight.
+    #[inline]
+    pub fn in_use(&self) -> impl Iterator<Item = RpcId> + '_ {
+        self.used.keys().copied()
+    }
 }
@@ -0,0 +1,158 @@
+//! Event records of the RPCs passing through an engine, rolled up by the daemon.
+//!
+//! An engine that reports events keeps an [`EventRecorder`] and returns it from
+//! [`Engine::events`](super::Engine::events). Recording pushes a small [`Event`] into a lock-free
+//! ring shared with the daemon and never blocks; an event that finds the ring full is dropped and
+//! counted. The daemon drains the rings of all the engines in its control loop when
+//! `profiling.engine_events` is set, and computes the rates and the percentiles over a rolling
+//! window for the stats, so the engines keep no counters or histograms of their own.
+//!
+//! ```ignore
+//! let sent_at = self.events.timestamp();
+//! self.events.record(EventKind::Sent, len as u64);
+//! // ... when the reply arrives
+//! self.events.record_since(EventKind::Completed, sent_at);
+//! ```
+//!
+//! A disabled recorder costs an atomic load per event.
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crossbeam::queue::ArrayQueue;
+
+use phoenix_api::rpc::monotonic_ns;
+
+/// The number of events a ring holds by default.
+pub const DEFAULT_RING_CAPACITY: usize = 16384;
+
+/// What happened to an RPC in an engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// A message was sent, the value is its size in bytes.
+    Sent,
+    /// A message was received, the value is its size in bytes.
+    Received,
+    /// A call completed, the value is its latency in nanoseconds.
+    Completed,
+    /// A message failed to be sent or received.
+    Failed,
+    /// A message was dropped, e.g., it expired.
+    Dropped,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 5] = [
+        EventKind::Sent,
+        EventKind::Received,
+        EventKind::Completed,
+        EventKind::Failed,
+        EventKind::Dropped,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Sent => "sent",
+            EventKind::Received => "received",
+            EventKind::Completed => "completed",
+            EventKind::Failed => "failed",
+            EventKind::Dropped => "dropped",
+        }
+    }
+}
+
+/// An event recorded by an engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    /// The size or the latency, depending on the kind
+    pub value: u64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    enabled: AtomicBool,
+    ring: ArrayQueue<Event>,
+    // the events that found the ring full
+    overflows: AtomicU64,
+}
+
+/// The ring of the events of an engine. Clones share the same ring, so the daemon can drain it
+/// while the engine is running.
+#[derive(Debug, Clone)]
+pub struct EventRecorder {
+    inner: Arc<Inner>,
+}
+
+impl Default for EventRecorder {
+    fn default() -> Self {
+        Self::new(DEFAULT_RING_CAPACITY)
+    }
+}
+
+impl EventRecorder {
+    pub fn new(capacity: usize) -> Self {
+        EventRecorder {
+            inner: Arc::new(Inner {
+                enabled: AtomicBool::new(false),
+                ring: ArrayQueue::new(capacity.max(1)),
+                overflows: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Records an event of `kind` with `value` if the recorder is enabled.
+    #[inline]
+    pub fn record(&self, kind: EventKind, value: u64) {
+        if self.is_enabled() && self.inner.ring.push(Event { kind, value }).is_err() {
+            self.inner.overflows.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the time to measure a latency from, or 0 if the recorder is disabled.
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        if self.is_enabled() {
+            monotonic_ns()
+        } else {
+            0
+        }
+    }
+
+    /// Records an event of `kind` with the time elapsed since `since`, taken by
+    /// [`timestamp`](Self::timestamp). Nothing is recorded if `since` is 0.
+    #[inline]
+    pub fn record_since(&self, kind: EventKind, since: u64) {
+        if since != 0 {
+            self.record(kind, monotonic_ns().saturating_sub(since));
+        }
+    }
+
+    /// Enables or disables the recorder. The ring is cleared when it is disabled.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.inner.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            while self.inner.ring.pop().is_some() {}
+        }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Pops the events in the ring, at most as many as it holds, so a busy engine cannot keep
+    /// the caller draining.
+    pub fn drain(&self, mut f: impl FnMut(Event)) {
+        for _ in 0..self.inner.ring.capacity() {
+            match self.inner.ring.pop() {
+                Some(event) => f(event),
+                None => break,
+            }
+        }
+    }
+
+    /// Returns the number of events dropped since the last call, since the ring was full.
+    pub fn take_overflows(&self) -> u64 {
+        self.inner.overflows.swap(0, Ordering::Relaxed)
+    }
+}
@@ -5,6 +5,8 @@ use futures::future::BoxFuture;
 
 pub use crate::PhoenixResult;
 
+use crate::checkpoint::EngineCheckpoint;
+
 pub mod future;
 
 pub mod datapath;
@@ -13,6 +15,15 @@ pub use datapath::node::Vertex;
 pub mod decompose;
 pub use decompose::{Decompose, DecomposeResult};
 
+pub mod profile;
+pub use profile::Profiler;
+
+pub mod events;
+pub use events::{EventKind, EventRecorder};
+
+pub mod timer;
+pub use timer::{TimerId, TimerWheel};
+
 pub type EngineResult = Result<(), Box<dyn std::error::Error>>;
 
 #[repr(transparent)]
@@ -21,6 +32,18 @@ pub struct EngineType(pub &'static str);
 
 pub type EnginePair = (EngineType, EngineType);
 
+/// How often an engine is polled by its runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SchedulingClass {
+    /// Polled in every iteration of the runtime.
+    #[default]
+    LatencyCritical,
+    /// Polled once every `batch_poll_interval` iterations of the runtime. Only for engines that
+    /// are not on the datapath, e.g., statistics aggregators. The engines on the datapath, even
+    /// the loggers, delay the messages that pass them if they are polled less often.
+    Batch,
+}
+
 pub trait Engine: Decompose + Send + Vertex + Unpin + 'static {
     /// Turn the Engine into an executable `Future`
     fn activate<'a>(self: Pin<&'a mut Self>) -> BoxFuture<'a, EngineResult>;
@@ -28,9 +51,50 @@ pub trait Engine: Decompose + Send + Vertex + Unpin + 'static {
     /// Returns a text description of the engine.
     fn description(self: Pin<&Self>) -> String;
 
+    /// Returns a human-readable snapshot of the state of the engine to attach to bug reports,
+    /// e.g., the counts of the resources, the connection handles, and the recent errors. It must
+    /// not contain the payloads of the messages.
+    #[inline]
+    fn dump(self: Pin<&Self>) -> Option<String> {
+        None
+    }
+
+    /// Returns what the engine saves in a checkpoint of the daemon, i.e., the state to resume it
+    /// from after the daemon restarts and the connections it holds. `None` if there is nothing to
+    /// save, and the engine is created afresh for the client.
+    #[inline]
+    fn checkpoint(self: Pin<&Self>) -> Option<EngineCheckpoint> {
+        None
+    }
+
     /// Returns the progress tracker, which implies the future work.
     fn tracker(self: Pin<&mut Self>) -> &mut Indicator;
 
+    /// Returns the scheduling class of the engine, which must not change over its lifetime.
+    #[inline]
+    fn scheduling_class(&self) -> SchedulingClass {
+        SchedulingClass::LatencyCritical
+    }
+
+    /// Returns the profiler of the phases of the engine's mainloop, if the engine has one.
+    #[inline]
+    fn profiler(&self) -> Option<&Profiler> {
+        None
+    }
+
+    /// Returns the recorder of the events of the RPCs through the engine, if the engine has one.
+    #[inline]
+    fn events(&self) -> Option<&EventRecorder> {
+        None
+    }
+
+    /// Returns the timers of the engine, if the engine has any. The runtime fires the expired
+    /// ones right before it polls the engine.
+    #[inline]
+    fn timers(self: Pin<&mut Self>) -> Option<&mut TimerWheel> {
+        None
+    }
+
     /// Asks the engine to updates its local storage pointer.
     ///
     /// # Warning
@@ -60,11 +124,26 @@ pub trait Engine: Decompose + Send + Vertex + Unpin + 'static {
         // empty default impl
         Ok(())
     }
+
+    /// Called before the engine is shut down because an engine in the same service
+    /// subscription has failed. Service engines should notify the client with an error.
+    #[inline]
+    fn on_fault(&mut self, _reason: &str) {
+        // empty default impl
+    }
 }
 
 /// This indicates the runtime of an engine's status.
+///
+/// It also carries the work budget of the engine for the next poll, set by the runtime to share
+/// its core fairly among the engines of the scheduling groups on it. An engine should stop
+/// taking new work in a poll once the work it has done reaches the budget, and leave the rest to
+/// the next poll.
 #[derive(Debug)]
-pub struct Indicator(pub(crate) usize);
+pub struct Indicator {
+    nwork: usize,
+    budget: usize,
+}
 
 impl Default for Indicator {
     fn default() -> Self {
@@ -78,31 +157,45 @@ impl Indicator {
 
     #[inline]
     pub(crate) fn new(x: usize) -> Self {
-        Indicator(x)
+        Indicator {
+            nwork: x,
+            budget: usize::MAX,
+        }
     }
 
     #[inline]
     pub fn set_busy(&mut self) {
-        self.0 = Self::BUSY;
+        self.nwork = Self::BUSY;
     }
 
     #[inline]
     pub fn nwork(&self) -> usize {
-        self.0
+        self.nwork
     }
 
     #[inline]
     pub fn set_nwork(&mut self, nwork: usize) {
-        self.0 = nwork;
+        self.nwork = nwork;
+    }
+
+    /// Returns the work budget of the current poll, unlimited if the runtime does not set one.
+    #[inline]
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    #[inline]
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
     }
 
     #[inline]
     pub(crate) fn is_busy(&self) -> bool {
-        self.0 == Self::BUSY
+        self.nwork == Self::BUSY
     }
 
     #[inline]
     pub(crate) fn is_spinning(&self) -> bool {
-        self.0 == 0
+        self.nwork == 0
     }
 }
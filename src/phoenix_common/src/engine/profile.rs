@@ -0,0 +1,167 @@
+//! Fine-grained profiling of the phases of an engine's mainloop.
+//!
+//! An engine that supports profiling keeps a [`Profiler`] and returns it from
+//! [`Engine::profiler`](super::Engine::profiler). Each iteration of its mainloop is split into
+//! phases (see [`Phase`]), and the time spent in each phase is recorded in a histogram when the
+//! profiler is enabled. The daemon enables the profilers with `profiling.engine_phases` and
+//! reports the histograms in its stats.
+//!
+//! ```ignore
+//! let start = self.profiler.start();
+//! work += self.check_input_queue()?;
+//! self.profiler.record(Phase::InputQueue, start);
+//! ```
+//!
+//! A disabled profiler costs an atomic load per phase.
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use minstant::Instant;
+
+use ipc::control::PhaseStats;
+
+/// The number of buckets of a histogram. Bucket `i` counts the durations in `[2^i, 2^(i+1))`
+/// nanoseconds, the last bucket counts everything longer.
+pub const NUM_BUCKETS: usize = 32;
+
+/// A phase of an engine's mainloop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Draining the queues from the neighbor engines.
+    InputQueue,
+    /// Polling the transport, e.g., the completion queues.
+    Transport,
+    /// Polling the shared memory queues of the application.
+    Customer,
+    /// Handling the commands from the application or the control plane.
+    Command,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [
+        Phase::InputQueue,
+        Phase::Transport,
+        Phase::Customer,
+        Phase::Command,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::InputQueue => "input_queue",
+            Phase::Transport => "transport",
+            Phase::Customer => "customer",
+            Phase::Command => "command",
+        }
+    }
+}
+
+/// A log2 histogram of durations.
+#[derive(Debug)]
+struct Histogram {
+    count: AtomicU64,
+    total_ns: AtomicU64,
+    buckets: [AtomicU64; NUM_BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            count: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Histogram {
+    #[inline]
+    fn record(&self, ns: u64) {
+        let bucket = (63 - ns.max(1).leading_zeros() as usize).min(NUM_BUCKETS - 1);
+        // Only the engine writes, so relaxed loads and stores are enough.
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.total_ns.store(0, Ordering::Relaxed);
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    enabled: AtomicBool,
+    phases: [Histogram; Phase::ALL.len()],
+}
+
+/// The histograms of the phases of an engine. Clones share the same histograms, so the daemon
+/// can read them while the engine is running.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    inner: Arc<Inner>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the start of a phase, or `None` if the profiler is disabled.
+    #[inline]
+    pub fn start(&self) -> Option<Instant> {
+        if self.inner.enabled.load(Ordering::Relaxed) {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Records the time elapsed since `start` in the histogram of `phase`.
+    #[inline]
+    pub fn record(&self, phase: Phase, start: Option<Instant>) {
+        if let Some(start) = start {
+            let ns = start.elapsed().as_nanos() as u64;
+            self.inner.phases[phase as usize].record(ns);
+        }
+    }
+
+    /// Enables or disables the profiler. The histograms are cleared when it is enabled.
+    pub fn set_enabled(&self, enabled: bool) {
+        if enabled && !self.is_enabled() {
+            for histogram in self.inner.phases.iter() {
+                histogram.reset();
+            }
+        }
+        self.inner.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the histograms of the phases that have been recorded.
+    pub fn snapshot(&self) -> Vec<PhaseStats> {
+        Phase::ALL
+            .iter()
+            .filter_map(|phase| {
+                let histogram = &self.inner.phases[*phase as usize];
+                let count = histogram.count.load(Ordering::Relaxed);
+                (count > 0).then(|| PhaseStats {
+                    phase: phase.name().to_owned(),
+                    count,
+                    total_ns: histogram.total_ns.load(Ordering::Relaxed),
+                    buckets: histogram
+                        .buckets
+                        .iter()
+                        .map(|b| b.load(Ordering::Relaxed))
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,418 @@
+//! Timers of an engine, fired by its runtime.
+//!
+//! An engine that needs timers, e.g., for deadlines, keep-alives, retries, or reaping idle
+//! resources, keeps a [`TimerWheel`] and returns it from
+//! [`Engine::timers`](super::Engine::timers). The runtime ticks the wheel right before it polls
+//! the engine, which runs the callbacks of the timers that have expired. A callback cannot borrow
+//! the engine, so it usually sets a flag or sends a message that the engine picks up in the poll.
+//!
+//! ```ignore
+//! let expired = Arc::clone(&self.expired);
+//! let id = self.timers.insert_after(timeout, move || expired.store(true, Ordering::Release));
+//! // ... when the reply arrives in time
+//! self.timers.cancel(id);
+//! ```
+//!
+//! The wheel is hierarchical: [`LEVELS`] levels of [`SLOTS`] slots each, a slot of a level
+//! spanning all the slots of the level below. Inserting and cancelling a timer are O(1), and a
+//! timer is moved down at most once per level before it fires. Timers never fire early, but may
+//! fire up to a tick late, plus however long the runtime takes to get to the engine.
+//!
+//! The timers are not carried over the upgrades of an engine, as the callbacks are code of the
+//! version being replaced. An engine arms its timers again on restore.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The number of levels of the wheel.
+pub const LEVELS: usize = 4;
+/// The number of slots of each level.
+pub const SLOTS: usize = 64;
+
+const SLOT_BITS: u32 = SLOTS.trailing_zeros();
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+// the furthest a timer is placed from the current tick, in a slot of the top level other than
+// the current one. Farther timers are placed there, and placed again when the wheel gets to it.
+const MAX_TICKS: u64 = (SLOTS as u64 - 1) << (SLOT_BITS * (LEVELS as u32 - 1));
+
+/// The default length of a tick.
+pub const DEFAULT_RESOLUTION: Duration = Duration::from_millis(1);
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// The handle of a timer, to cancel it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId {
+    index: u32,
+    generation: u32,
+}
+
+struct Entry {
+    // bumped each time the entry is freed, so that stale ids do not cancel a new timer
+    generation: u32,
+    // the tick the timer expires at
+    deadline: u64,
+    // the slot the timer is in, as level * SLOTS + slot
+    slot: usize,
+    prev: Option<u32>,
+    next: Option<u32>,
+    // `None` if the entry is free
+    callback: Option<Callback>,
+}
+
+/// A hierarchical timer wheel.
+pub struct TimerWheel {
+    resolution: Duration,
+    start: Instant,
+    // the ticks since `start` the wheel has been advanced to
+    elapsed: u64,
+    // the first timer of each slot
+    heads: Vec<Option<u32>>,
+    entries: Vec<Entry>,
+    // the free entries, linked through `next`
+    free: Option<u32>,
+    len: usize,
+}
+
+impl fmt::Debug for TimerWheel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimerWheel")
+            .field("resolution", &self.resolution)
+            .field("elapsed", &self.elapsed)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new(DEFAULT_RESOLUTION)
+    }
+}
+
+impl TimerWheel {
+    /// Creates a wheel that ticks every `resolution`, starting from now.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `resolution` is zero.
+    pub fn new(resolution: Duration) -> Self {
+        assert!(!resolution.is_zero(), "the resolution must not be zero");
+        TimerWheel {
+            resolution,
+            start: Instant::now(),
+            elapsed: 0,
+            heads: vec![None; LEVELS * SLOTS],
+            entries: Vec::new(),
+            free: None,
+            len: 0,
+        }
+    }
+
+    /// The number of timers armed.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Arms a timer that calls `callback` at `deadline`, or at the next tick if it has passed.
+    pub fn insert<F>(&mut self, deadline: Instant, callback: F) -> TimerId
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.len == 0 {
+            // the wheel may not have been ticked for long
+            self.tick(Instant::now());
+        }
+        // rounded up, so that the timer never fires early
+        let since_start = deadline.saturating_duration_since(self.start);
+        let resolution = self.resolution.as_nanos();
+        let ticks = (since_start.as_nanos() + resolution - 1) / resolution;
+        let deadline = (ticks.min(u64::MAX as u128) as u64).max(self.elapsed + 1);
+
+        let index = match self.free {
+            Some(index) => {
+                let entry = &mut self.entries[index as usize];
+                self.free = entry.next;
+                entry.deadline = deadline;
+                entry.callback = Some(Box::new(callback));
+                index
+            }
+            None => {
+                self.entries.push(Entry {
+                    generation: 0,
+                    deadline,
+                    slot: 0,
+                    prev: None,
+                    next: None,
+                    callback: Some(Box::new(callback)),
+                });
+                (self.entries.len() - 1) as u32
+            }
+        };
+        self.link(index);
+        self.len += 1;
+        TimerId {
+            index,
+            generation: self.entries[index as usize].generation,
+        }
+    }
+
+    /// Arms a timer that calls `callback` after `delay`.
+    #[inline]
+    pub fn insert_after<F>(&mut self, delay: Duration, callback: F) -> TimerId
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.insert(Instant::now() + delay, callback)
+    }
+
+    /// Disarms a timer. Returns `false` if it has fired or been cancelled already.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        match self.entries.get(id.index as usize) {
+            Some(entry) if entry.generation == id.generation && entry.callback.is_some() => {}
+            _ => return false,
+        }
+        self.unlink(id.index);
+        self.release(id.index);
+        true
+    }
+
+    /// Advances the wheel to `now`, and runs the callbacks of the timers that have expired.
+    /// Returns the number of callbacks run.
+    pub fn tick(&mut self, now: Instant) -> usize {
+        let since_start = now.saturating_duration_since(self.start);
+        let target = (since_start.as_nanos() / self.resolution.as_nanos()) as u64;
+        let mut fired = 0;
+        while self.elapsed < target {
+            if self.len == 0 {
+                self.elapsed = target;
+                break;
+            }
+            fired += self.step();
+        }
+        fired
+    }
+
+    /// Advances the wheel by a tick.
+    fn step(&mut self) -> usize {
+        self.elapsed += 1;
+        // move the timers down from the slots of the upper levels the tick has reached, from the
+        // top, as the timers of a level may go to the slot reached in the level below
+        let reached = (1..LEVELS)
+            .take_while(|&level| self.elapsed & ((1 << (SLOT_BITS * level as u32)) - 1) == 0)
+            .count();
+        for level in (1..=reached).rev() {
+            let shift = SLOT_BITS * level as u32;
+            let slot = level * SLOTS + ((self.elapsed >> shift) & SLOT_MASK) as usize;
+            let mut cursor = self.heads[slot].take();
+            while let Some(index) = cursor {
+                cursor = self.entries[index as usize].next;
+                self.link(index);
+            }
+        }
+
+        let slot = (self.elapsed & SLOT_MASK) as usize;
+        let mut cursor = self.heads[slot].take();
+        let mut fired = 0;
+        while let Some(index) = cursor {
+            let entry = &mut self.entries[index as usize];
+            cursor = entry.next;
+            let callback = entry.callback.take().unwrap();
+            self.release(index);
+            callback();
+            fired += 1;
+        }
+        fired
+    }
+
+    /// Puts a timer in the slot of its deadline.
+    fn link(&mut self, index: u32) {
+        let deadline = self.entries[index as usize]
+            .deadline
+            .min(self.elapsed + MAX_TICKS);
+        // the level is the one of the highest slot bits the deadline differs from now in
+        let masked = (deadline ^ self.elapsed) | SLOT_MASK;
+        let level = ((63 - masked.leading_zeros()) / SLOT_BITS) as usize;
+        let level = level.min(LEVELS - 1);
+        let shift = SLOT_BITS * level as u32;
+        let slot = level * SLOTS + ((deadline >> shift) & SLOT_MASK) as usize;
+
+        let head = self.heads[slot].replace(index);
+        if let Some(head) = head {
+            self.entries[head as usize].prev = Some(index);
+        }
+        let entry = &mut self.entries[index as usize];
+        entry.slot = slot;
+        entry.prev = None;
+        entry.next = head;
+    }
+
+    /// Takes a timer out of its slot.
+    fn unlink(&mut self, index: u32) {
+        let entry = &self.entries[index as usize];
+        let (slot, prev, next) = (entry.slot, entry.prev, entry.next);
+        match prev {
+            Some(prev) => self.entries[prev as usize].next = next,
+            None => self.heads[slot] = next,
+        }
+        if let Some(next) = next {
+            self.entries[next as usize].prev = prev;
+        }
+    }
+
+    /// Frees the entry of a timer that has been taken out of its slot.
+    fn release(&mut self, index: u32) {
+        let entry = &mut self.entries[index as usize];
+        entry.generation = entry.generation.wrapping_add(1);
+        entry.callback = None;
+        entry.prev = None;
+        entry.next = self.free;
+        self.free = Some(index);
+        self.len -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // long enough that the wheel is not advanced by the time the test takes
+    const RESOLUTION: Duration = Duration::from_secs(60);
+
+    fn at(wheel: &TimerWheel, ticks: u64) -> Instant {
+        wheel.start + RESOLUTION * ticks as u32
+    }
+
+    fn counter() -> (Arc<AtomicUsize>, impl FnOnce() + Send + 'static) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let fired = Arc::clone(&count);
+        (count, move || {
+            fired.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    // Arms a timer at `deadline` on a new wheel, and checks that it fires at that tick.
+    fn fires_at(deadline: u64) {
+        let mut wheel = TimerWheel::new(RESOLUTION);
+        let (count, callback) = counter();
+        wheel.insert(at(&wheel, deadline), callback);
+        assert_eq!(wheel.tick(at(&wheel, deadline - 1)), 0, "{deadline}");
+        assert_eq!(wheel.tick(at(&wheel, deadline)), 1, "{deadline}");
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn level_zero() {
+        fires_at(1);
+        fires_at(5);
+        fires_at(SLOTS as u64 - 1);
+    }
+
+    #[test]
+    fn cascade_at_boundaries() {
+        let slots = SLOTS as u64;
+        for deadline in [
+            // the first tick of each upper level, where its slots are moved down
+            slots,
+            slots * slots,
+            slots * slots * slots,
+            // right after them
+            slots + 1,
+            slots * slots + 1,
+            slots * slots * slots + 1,
+            // in a slot of each level other than the first
+            3 * slots + 7,
+            5 * slots * slots + 3 * slots,
+            2 * slots * slots * slots + slots * slots + slots + 1,
+        ] {
+            fires_at(deadline);
+        }
+    }
+
+    #[test]
+    fn cascade_with_the_wheel_advanced() {
+        let mut wheel = TimerWheel::new(RESOLUTION);
+        let (_, keep) = counter();
+        wheel.insert(at(&wheel, 1 << 20), keep);
+        wheel.tick(at(&wheel, SLOTS as u64 - 3));
+
+        // crosses the boundary of level 1 from the middle of the level 0
+        let (count, callback) = counter();
+        let deadline = SLOTS as u64 + 2;
+        wheel.insert(at(&wheel, deadline), callback);
+        assert_eq!(wheel.tick(at(&wheel, deadline - 1)), 0);
+        assert_eq!(wheel.tick(at(&wheel, deadline)), 1);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        assert_eq!(wheel.len(), 1);
+    }
+
+    #[test]
+    fn cancel_before_firing() {
+        let mut wheel = TimerWheel::new(RESOLUTION);
+        let (count, callback) = counter();
+        let id = wheel.insert(at(&wheel, 100), callback);
+        let (other_count, other) = counter();
+        wheel.insert(at(&wheel, 100), other);
+
+        assert!(wheel.cancel(id));
+        assert_eq!(wheel.len(), 1);
+        assert!(!wheel.cancel(id));
+        assert_eq!(wheel.tick(at(&wheel, 200)), 1);
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+        assert_eq!(other_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn stale_id_after_reuse() {
+        let mut wheel = TimerWheel::new(RESOLUTION);
+        let (_, callback) = counter();
+        let stale = wheel.insert(at(&wheel, 10), callback);
+        assert!(wheel.cancel(stale));
+
+        // the entry is reused for the new timer, with another generation
+        let (count, callback) = counter();
+        let id = wheel.insert(at(&wheel, 10), callback);
+        assert_eq!(id.index, stale.index);
+        assert_ne!(id.generation, stale.generation);
+
+        assert!(!wheel.cancel(stale));
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(wheel.tick(at(&wheel, 10)), 1);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        // nor does the id of a fired timer cancel anything
+        assert!(!wheel.cancel(id));
+    }
+
+    #[test]
+    fn beyond_the_range() {
+        // placed in the furthest slot, and placed again when the wheel gets there
+        fires_at(MAX_TICKS + SLOTS as u64 + 5);
+    }
+
+    #[test]
+    fn zero_or_past_deadline() {
+        let mut wheel = TimerWheel::new(RESOLUTION);
+        let (count, callback) = counter();
+        wheel.insert(wheel.start, callback);
+        assert_eq!(wheel.tick(at(&wheel, 1)), 1);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        // a deadline the wheel has passed fires at the next tick
+        let (_, keep) = counter();
+        wheel.insert(at(&wheel, 1000), keep);
+        wheel.tick(at(&wheel, 10));
+        let (count, callback) = counter();
+        wheel.insert(at(&wheel, 3), callback);
+        assert_eq!(wheel.tick(at(&wheel, 10)), 0);
+        assert_eq!(wheel.tick(at(&wheel, 11)), 1);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+}
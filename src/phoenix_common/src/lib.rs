@@ -5,8 +5,11 @@ pub extern crate tracing;
 // alias
 pub extern crate tracing as log;
 
+pub mod abi;
 #[allow(clippy::missing_safety_doc)]
 pub mod addon;
+pub mod checkpoint;
+pub mod config;
 #[allow(clippy::missing_safety_doc)]
 pub mod module;
 
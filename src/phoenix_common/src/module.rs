@@ -1,15 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::{os::unix::ucred::UCred, path::Path};
 
 use dashmap::DashMap;
 use ipc::unix::DomainSocket;
+use ipc::version::ProtocolVersion;
 use nix::unistd::Pid;
 use phoenix_api::engine::SchedulingMode;
 pub use semver::Version;
 
+use crate::checkpoint::{EngineCheckpoint, RestoredRegion, ShmRegionDescriptor};
 use crate::engine::datapath::node::{ChannelDescriptor, DataPathNode};
 use crate::engine::{Engine, EnginePair, EngineType};
 use crate::envelop::TypeTagged;
+use crate::state_mgr::ResourceUsage;
 use crate::storage::{ResourceCollection, SharedStorage};
 use crate::PhoenixResult;
 
@@ -41,6 +44,8 @@ pub enum NewEngineRequest<'a> {
         mode: SchedulingMode,
         cred: &'a UCred,
         config_string: Option<String>,
+        /// The control path protocol version agreed on with the client
+        protocol: ProtocolVersion,
     },
     Auxiliary {
         pid: Pid,
@@ -99,6 +104,38 @@ pub trait PhoenixModule: TypeTagged + Send + Sync + 'static {
     /// depending on prev version
     fn migrate(&mut self, prev_module: Box<dyn PhoenixModule>);
 
+    /// The resources held by the module for each client process
+    fn resource_usage(&self) -> Vec<(Pid, ResourceUsage)> {
+        Vec::new()
+    }
+
+    /// The limits and features of the module the user libraries may adapt to, e.g., the largest
+    /// message accepted, by name
+    fn capabilities(&self) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+
+    /// Release the per-process states and resources of a client process that has exited.
+    /// Returns the resources that were still registered, i.e., leaked.
+    /// This is called after all the engines of the process have been shut down.
+    fn reclaim(&mut self, _pid: Pid) -> ResourceUsage {
+        ResourceUsage::new()
+    }
+
+    /// The shared memory regions the module allocated for a client process, to be saved in a
+    /// checkpoint of the daemon.
+    fn checkpoint(&self, _pid: Pid) -> Vec<ShmRegionDescriptor> {
+        Vec::new()
+    }
+
+    /// Take back the shared memory regions of a client process from a checkpoint, after the
+    /// daemon restarts. The memfds are opened again from the mappings of the client, the module
+    /// must map them at the addresses the client maps them.
+    /// This is called before any engine is created for the process.
+    fn restore(&mut self, _pid: Pid, _regions: Vec<RestoredRegion>) -> PhoenixResult<()> {
+        Ok(())
+    }
+
     /// Create a new engine
     /// Upon success, returns an Option
     /// Some indicates a newly created engine
@@ -150,6 +187,24 @@ pub trait PhoenixModule: TypeTagged + Send + Sync + 'static {
         plugged: &ModuleCollection,
         prev_version: Version,
     ) -> PhoenixResult<Box<dyn Engine>>;
+
+    /// Create an engine for a client that was served before the daemon restarted, from what the
+    /// previous engine saved in a checkpoint of the daemon.
+    /// By default, the engine is created afresh, for the engines whose states are rebuilt by the
+    /// client, e.g., when it registers its service again.
+    #[allow(clippy::too_many_arguments)]
+    fn resume_engine(
+        &mut self,
+        ty: EngineType,
+        request: NewEngineRequest,
+        _checkpoint: &EngineCheckpoint,
+        shared: &mut SharedStorage,
+        global: &mut ResourceCollection,
+        node: DataPathNode,
+        plugged: &ModuleCollection,
+    ) -> PhoenixResult<Option<Box<dyn Engine>>> {
+        self.create_engine(ty, request, shared, global, node, plugged)
+    }
 }
 
 #[allow(clippy::missing_safety_doc)]
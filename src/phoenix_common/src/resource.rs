@@ -75,6 +75,22 @@ impl<K: Eq + std::hash::Hash, R> ResourceTableGeneric<K, R> {
         &self.table
     }
 
+    /// Returns the number of resources in the table.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Removes all resources from the table regardless of their reference counts.
+    pub fn clear(&self) {
+        self.table.clear();
+    }
+
     pub fn insert(&self, h: K, r: R) -> Result<(), Error> {
         match self.table.insert(h, Entry::new(r, 1)) {
             Some(_) => Err(Error::Exists),
@@ -140,10 +156,22 @@ impl<K: Eq + std::hash::Hash, R> ResourceTableGeneric<K, R> {
 use crate::page_padded::PagePadded;
 use sharded_slab::Slab;
 
+/// The configuration of the slab of a [`ResourceSlab`].
+///
+/// The keys of a slab carry the generation of their slots, so the key of a removed resource is
+/// not found after its slot is taken by another resource. The bits above [`Handle::ID_BITS`] are
+/// reserved, so the generation wraps around within the id of a handle rather than overflows it.
+#[derive(Debug)]
+pub struct HandleConfig;
+
+impl sharded_slab::Config for HandleConfig {
+    const RESERVED_BITS: usize = (u64::BITS - Handle::ID_BITS) as usize;
+}
+
 #[derive(Debug)]
 pub struct ResourceSlab<R> {
     // fast path slab
-    slab: Arc<Slab<PagePadded<R>>>,
+    slab: Arc<Slab<PagePadded<R>, HandleConfig>>,
     // slow path table, Handle -> key in the slab
     table: ResourceTable<usize>,
     // key -> Handle, this is like a weak reference
@@ -153,7 +181,7 @@ pub struct ResourceSlab<R> {
 impl<R> Default for ResourceSlab<R> {
     fn default() -> Self {
         ResourceSlab {
-            slab: Arc::new(Slab::new()),
+            slab: Arc::new(Slab::new_with_config::<HandleConfig>()),
             table: ResourceTable::default(),
             inverse_table: DashMap::default(),
         }
@@ -171,6 +199,27 @@ impl<R> Drop for ResourceSlab<R> {
 }
 
 impl<R> ResourceSlab<R> {
+    /// Returns the number of resources in the slab.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inverse_table.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inverse_table.is_empty()
+    }
+
+    /// Removes all resources from the slab regardless of their reference counts.
+    pub fn clear(&self) {
+        let keys: Vec<usize> = self.inverse_table.iter().map(|e| *e.key()).collect();
+        for key in keys {
+            let _ = self.slab.remove(key);
+            self.inverse_table.remove(&key);
+        }
+        self.table.clear();
+    }
+
     pub fn insert(&self, r: R) -> Result<usize, Error> {
         unsafe { libnuma_sys::numa_set_localalloc() };
         match self.slab.insert(PagePadded::new(r)) {
@@ -184,7 +233,10 @@ impl<R> ResourceSlab<R> {
     }
 
     #[inline]
-    pub fn get(&self, key: usize) -> Result<sharded_slab::OwnedEntry<PagePadded<R>>, Error> {
+    pub fn get(
+        &self,
+        key: usize,
+    ) -> Result<sharded_slab::OwnedEntry<PagePadded<R>, HandleConfig>, Error> {
         match Slab::get_owned(Arc::clone(&self.slab), key) {
             Some(r) => Ok(r),
             None => Err(Error::NotFound),
@@ -192,7 +244,10 @@ impl<R> ResourceSlab<R> {
     }
 
     #[inline]
-    pub fn get_dp(&self, key: usize) -> Result<sharded_slab::Entry<'_, PagePadded<R>>, Error> {
+    pub fn get_dp(
+        &self,
+        key: usize,
+    ) -> Result<sharded_slab::Entry<'_, PagePadded<R>, HandleConfig>, Error> {
         match self.slab.get(key) {
             Some(r) => Ok(r),
             None => Err(Error::NotFound),
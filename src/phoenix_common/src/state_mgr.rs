@@ -1,13 +1,34 @@
 use std::collections::hash_map;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Weak};
 
 use fnv::FnvHashMap as HashMap;
+use nix::errno::Errno;
+use nix::sys::signal;
 pub use nix::unistd::Pid;
 
+/// The number of resources of each kind, e.g., `"cmid" => 2`.
+pub type ResourceUsage = BTreeMap<&'static str, usize>;
+
 pub trait ProcessShared: Sized {
     type Err;
 
     fn new(pid: Pid) -> Result<Self, Self::Err>;
+
+    /// The resources registered in this state.
+    fn resource_usage(&self) -> ResourceUsage {
+        ResourceUsage::new()
+    }
+
+    /// Releases the resources registered in this state. This is called when the process has
+    /// exited but the state is still referenced.
+    fn reclaim(&self) {}
+}
+
+/// Returns false if the process does not exist anymore.
+pub fn is_process_alive(pid: Pid) -> bool {
+    // Sending no signal only performs the existence and permission checks.
+    !matches!(signal::kill(pid, None), Err(Errno::ESRCH))
 }
 
 /// Per-user-application-process shared state
@@ -74,6 +95,12 @@ impl<S: ProcessShared> SharedStateManager<S> {
         }
     }
 
+    /// Returns the state of `pid` if it is alive.
+    #[inline]
+    pub fn get(&self, pid: Pid) -> Option<Arc<S>> {
+        self.states.get(&pid).and_then(Weak::upgrade)
+    }
+
     #[inline]
     pub fn contains(&self, pid: Pid) -> bool {
         if let Some(state) = self.states.get(&pid) {
@@ -82,4 +109,37 @@ impl<S: ProcessShared> SharedStateManager<S> {
             false
         }
     }
+
+    /// Returns the processes whose states are alive.
+    pub fn pids(&self) -> Vec<Pid> {
+        self.states
+            .iter()
+            .filter(|(_, state)| state.strong_count() > 0)
+            .map(|(pid, _)| *pid)
+            .collect()
+    }
+
+    /// Returns the resource usage of each alive state.
+    pub fn resource_usage(&self) -> Vec<(Pid, ResourceUsage)> {
+        self.states
+            .iter()
+            .filter_map(|(pid, state)| state.upgrade().map(|s| (*pid, s.resource_usage())))
+            .collect()
+    }
+
+    /// Removes the state of `pid` and releases the resources that are still registered in it.
+    /// Returns the resources that are released. Entries of states that have been dropped are
+    /// removed as well.
+    pub fn reclaim(&mut self, pid: Pid) -> ResourceUsage {
+        let usage = match self.states.remove(&pid).and_then(|state| state.upgrade()) {
+            Some(state) => {
+                let usage = state.resource_usage();
+                state.reclaim();
+                usage
+            }
+            None => ResourceUsage::new(),
+        };
+        self.states.retain(|_, state| state.strong_count() > 0);
+        usage
+    }
 }
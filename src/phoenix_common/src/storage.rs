@@ -35,6 +35,17 @@ pub fn get_default_prefix(global: &ResourceCollection) -> anyhow::Result<&PathBu
         .ok_or_else(|| anyhow::anyhow!("{PHOENIX_PREFIX_KEY} not found in ResourceCollection"))
 }
 
+pub const PHOENIX_USER_DIR_KEY: &str = "PHOENIX_USER_DIR";
+
+/// Returns the directory only the user of the client can access, where the engines bind their
+/// sockets in place of the prefix. Returns `None` if the daemon does not isolate the users.
+pub fn get_user_dir(global: &ResourceCollection) -> Option<&PathBuf> {
+    global.get(PHOENIX_USER_DIR_KEY).map(|x| {
+        x.downcast_ref::<PathBuf>()
+            .expect("Expect a PathBuf for PHOENIX_USER_DIR")
+    })
+}
+
 pub struct CommandPathBroker {
     senders: HashMap<EngineType, AnyCommandSender>,
     receivers: HashMap<EngineType, AnyCommandReceiver>,
@@ -0,0 +1,74 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use uuid::Uuid;
+
+use ipc::control::{pid_t, AffinityRequest, Request};
+use ipc::unix::DomainSocket;
+use phoenix_api::engine::CpuSet;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix engine placement control")]
+struct Opts {
+    /// The client process whose engines to move
+    #[arg(long)]
+    pid: pid_t,
+    /// Only move the engines of this service subscription
+    #[arg(long)]
+    sid: Option<u64>,
+    /// The cores to run the engines on, e.g., "0-3,8"
+    #[arg(short, long, conflicts_with = "numa_node")]
+    cpus: Option<CpuSet>,
+    /// The NUMA node to run the engines on. The engines may run on any core if neither this nor
+    /// --cpus is given.
+    #[arg(short, long)]
+    numa_node: Option<u8>,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::SetAffinity(AffinityRequest {
+        pid: opts.pid,
+        sid: opts.sid,
+        cpus: opts.cpus,
+        numa_node: opts.numa_node,
+    });
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+}
@@ -0,0 +1,164 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use uuid::Uuid;
+
+use ipc::control::{
+    pid_t, ChannelDirection, GraphRequest, Request, Response, ResponseKind, SubscriptionGraph,
+};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Dot,
+    Json,
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix datapath graph")]
+struct Opts {
+    /// Only show the service subscriptions of this client process
+    #[arg(long)]
+    pid: Option<pid_t>,
+    /// Only show this service subscription
+    #[arg(long, requires = "pid")]
+    sid: Option<u64>,
+    /// The output format
+    #[arg(short, long, value_enum, default_value_t = Format::Dot)]
+    format: Format,
+    /// Write the graph to a file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Renders the graphs in the DOT language, one cluster for each service subscription. Channels
+/// with queued messages are highlighted.
+fn to_dot(graphs: &[SubscriptionGraph]) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph phoenix {{").unwrap();
+    writeln!(dot, "    node [shape=box];").unwrap();
+    for g in graphs {
+        let node = |engine_type: &str| format!("\"{}_{}_{}\"", g.pid, g.sid, engine_type);
+        writeln!(dot, "    subgraph \"cluster_{}_{}\" {{", g.pid, g.sid).unwrap();
+        writeln!(
+            dot,
+            "        label=\"pid={} sid={} service={}\";",
+            g.pid, g.sid, g.service
+        )
+        .unwrap();
+        for e in &g.engines {
+            let description = e.description.as_deref().unwrap_or("(not responding)");
+            writeln!(
+                dot,
+                "        {} [label=\"{}\\neid={} runtime={}\\n{}\"];",
+                node(&e.engine_type),
+                e.engine_type,
+                e.eid,
+                e.runtime,
+                description.escape_default(),
+            )
+            .unwrap();
+        }
+        for c in &g.channels {
+            let queued = c.queued.map_or_else(|| "?".to_owned(), |n| n.to_string());
+            let (direction, style) = match c.direction {
+                ChannelDirection::Tx => ("tx", "solid"),
+                ChannelDirection::Rx => ("rx", "dashed"),
+            };
+            let color = if c.queued.unwrap_or(0) > 0 {
+                "red"
+            } else {
+                "black"
+            };
+            writeln!(
+                dot,
+                "        {} -> {} [label=\"{}[{}->{}] {}\", style={}, color={}];",
+                node(&c.sender),
+                node(&c.receiver),
+                direction,
+                c.sender_index,
+                c.receiver_index,
+                queued,
+                style,
+                color,
+            )
+            .unwrap();
+        }
+        writeln!(dot, "    }}").unwrap();
+    }
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::DataPathGraph(GraphRequest {
+        pid: opts.pid,
+        sid: opts.sid,
+    });
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    let graphs = match res.0.unwrap() {
+        ResponseKind::DataPathGraph(graphs) => graphs,
+        _ => panic!("invalid response"),
+    };
+
+    let mut writer: Box<dyn Write> = match opts.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).expect("unable to create file"),
+        )),
+        None => Box::new(io::stdout()),
+    };
+    match opts.format {
+        Format::Dot => writer.write_all(to_dot(&graphs).as_bytes()).unwrap(),
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut writer, &graphs).unwrap();
+            writeln!(writer).unwrap();
+        }
+    }
+}
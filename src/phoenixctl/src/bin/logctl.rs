@@ -0,0 +1,70 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix log filter control")]
+struct Opts {
+    /// The new log filter, in the same syntax as PHOENIX_LOG. Print the current filter if not
+    /// given. For example, to turn on debug logs for the RpcAdapterEngine of process 1234:
+    /// "info,[engine{ty=RpcAdapterEngine,pid=1234}]=debug"
+    filter: Option<String>,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::LogFilter(opts.filter);
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; 4096];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    match res.0 {
+        Ok(ResponseKind::LogFilter(filter)) => println!("{}", filter),
+        Ok(kind) => panic!("unexpected response: {:?}", kind),
+        Err(e) => eprintln!("Failed to set log filter: {}", e),
+    }
+}
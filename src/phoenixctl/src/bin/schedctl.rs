@@ -0,0 +1,72 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix engine scheduling control")]
+struct Opts {
+    /// Poll the batch engines, those not on the datapath, once every this many iterations of
+    /// their runtime. Print the current interval if not given.
+    #[arg(long)]
+    batch_poll_interval: Option<u32>,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::BatchPollInterval(opts.batch_poll_interval);
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; 4096];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    match res.0 {
+        Ok(ResponseKind::BatchPollInterval(interval)) => {
+            println!("batch_poll_interval: {}", interval)
+        }
+        Ok(kind) => panic!("unexpected response: {:?}", kind),
+        Err(e) => eprintln!("Failed to set batch_poll_interval: {}", e),
+    }
+}
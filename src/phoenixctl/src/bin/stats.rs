@@ -0,0 +1,185 @@
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+#[macro_use]
+extern crate prettytable;
+use clap::Parser;
+use prettytable::Table;
+use uuid::Uuid;
+
+use ipc::control::{Request, Response, ResponseKind};
+use ipc::unix::DomainSocket;
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+/// Returns the upper bound in ns of the bucket where the quantile `q` of the runs falls.
+fn quantile(buckets: &[u64], count: u64, q: f64) -> u64 {
+    let target = ((count as f64) * q).ceil() as u64;
+    let mut seen = 0;
+    for (i, n) in buckets.iter().enumerate() {
+        seen += n;
+        if seen >= target {
+            return 1u64 << (i + 1);
+        }
+    }
+    1u64 << buckets.len()
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix resource statistics")]
+struct Opts {
+    /// Dump the statistics to a JSON file instead of printing them
+    #[arg(short, long)]
+    dump: Option<PathBuf>,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(sock_path).unwrap();
+
+    let req = Request::Stats;
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    let (_, sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+    assert_eq!(sender.as_pathname(), Some(service_path.as_ref()));
+
+    let res: Response = bincode::deserialize(&buf).unwrap();
+    let stats = match res.0.unwrap() {
+        ResponseKind::Stats(stats) => stats,
+        _ => panic!("invalid response"),
+    };
+
+    if let Some(path) = opts.dump {
+        let f = File::create(path).expect("unable to create file");
+        let writer = BufWriter::new(f);
+        serde_json::to_writer_pretty(writer, &stats).unwrap();
+        return;
+    }
+
+    println!(
+        "clients: {}, subscriptions: {}, engines: {}",
+        stats.clients, stats.subscriptions, stats.engines
+    );
+
+    let mut table = Table::new();
+    table.add_row(row![bFm => "PID", "Module", "Resource", "Count"]);
+    for entry in stats.resources {
+        for (kind, count) in entry.resources {
+            table.add_row(row![entry.pid, entry.module, kind, count]);
+        }
+    }
+    table.printstd();
+
+    let mut table = Table::new();
+    table.add_row(row![bFm => "PID", "SID", "Group", "Runtime", "Cores", "Engines"]);
+    for group in stats.placements {
+        table.add_row(row![
+            group.pid,
+            group.sid,
+            group.gid,
+            group.runtime,
+            group.cpus,
+            group.engines.join(", ")
+        ]);
+    }
+    table.printstd();
+
+    println!(
+        "leaks reclaimed: {} processes, {} engines",
+        stats.leaks.processes, stats.leaks.engines
+    );
+    if !stats.leaks.resources.is_empty() {
+        let mut table = Table::new();
+        table.add_row(row![bFr => "Resource", "Count"]);
+        for (kind, count) in stats.leaks.resources {
+            table.add_row(row![kind, Fr->count]);
+        }
+        table.printstd();
+    }
+
+    if !stats.profiles.is_empty() {
+        let mut table = Table::new();
+        table.add_row(row![
+            bFm => "EID", "PID", "Engine", "Phase", "Count", "Mean (ns)", "P50 (ns)", "P99 (ns)"
+        ]);
+        for profile in stats.profiles {
+            for phase in profile.phases {
+                table.add_row(row![
+                    profile.eid,
+                    profile.pid,
+                    profile.engine_type,
+                    phase.phase,
+                    Fr->phase.count,
+                    Fr->phase.total_ns / phase.count,
+                    Fr->format!("<{}", quantile(&phase.buckets, phase.count, 0.5)),
+                    Fr->format!("<{}", quantile(&phase.buckets, phase.count, 0.99))
+                ]);
+            }
+        }
+        table.printstd();
+    }
+
+    if !stats.events.is_empty() {
+        let mut table = Table::new();
+        table.add_row(row![
+            bFm => "EID", "PID", "Engine", "Event", "Total", "Rate (/s)", "P50", "P99", "Max"
+        ]);
+        for events in stats.events {
+            for kind in events.kinds {
+                table.add_row(row![
+                    events.eid,
+                    events.pid,
+                    events.engine_type,
+                    kind.kind,
+                    Fr->kind.total,
+                    Fr->format!("{:.1}", kind.rate),
+                    Fr->kind.p50,
+                    Fr->kind.p99,
+                    Fr->kind.max
+                ]);
+            }
+            if events.overflows > 0 {
+                println!(
+                    "engine {} lost {} events to a full ring",
+                    events.eid, events.overflows
+                );
+            }
+        }
+        table.printstd();
+    }
+}
@@ -0,0 +1,87 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use uuid::Uuid;
+
+use clap::Parser;
+use ipc::control::Request;
+use ipc::unix::DomainSocket;
+use phoenix_api::Handle;
+use phoenix_api_rpc_adapter::control_plane::{ConnEvent, Request as RpcAdapterRequest};
+
+const MAX_MSG_LEN: usize = 65536;
+
+const DEFAULT_PHOENIX_PREFIX: &str = "/tmp/phoenix";
+const DEFAULT_PHOENIX_CONTROL: &str = "control.sock";
+
+lazy_static::lazy_static! {
+    static ref PHOENIX_PREFIX: PathBuf = {
+        env::var("PHOENIX_PREFIX").map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_PREFIX), |p| {
+            let path = PathBuf::from(p);
+            assert!(path.is_dir(), "{path:?} is not a directly");
+            path
+        })
+    };
+
+    static ref PHOENIX_CONTROL_SOCK: PathBuf = {
+        env::var("PHOENIX_CONTROL")
+            .map_or_else(|_| PathBuf::from(DEFAULT_PHOENIX_CONTROL), PathBuf::from)
+    };
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "Phoenix RpcAdapter connection event tail")]
+struct Opts {
+    /// The engine id of the RpcAdapterEngine.
+    #[arg(short, long)]
+    eid: u64,
+    /// The handle of the connection to tail, all the connections of the engine if not given.
+    #[arg(short, long)]
+    conn: Option<u64>,
+}
+
+fn main() {
+    let opts = Opts::parse();
+
+    let uuid = Uuid::new_v4();
+    let arg0 = env::args().next().unwrap();
+    let appname = Path::new(&arg0).file_name().unwrap().to_string_lossy();
+
+    let sock_path = PHOENIX_PREFIX.join(format!("phoenix-client-{}_{}.sock", appname, uuid));
+
+    if sock_path.exists() {
+        std::fs::remove_file(&sock_path).expect("remove_file");
+    }
+    let sock = DomainSocket::bind(&sock_path).unwrap();
+
+    // the engine sends the events to the socket until it is gone
+    let request = RpcAdapterRequest::TailConnLog(opts.conn.map(Handle), sock_path);
+    let request_encoded = bincode::serialize(&request).unwrap();
+    let req = Request::EngineRequest(opts.eid, request_encoded);
+    let buf = bincode::serialize(&req).unwrap();
+    assert!(buf.len() < MAX_MSG_LEN);
+
+    let service_path = PHOENIX_PREFIX.join(PHOENIX_CONTROL_SOCK.as_path());
+    sock.send_to(&buf, &service_path).unwrap();
+
+    let mut buf = vec![0u8; MAX_MSG_LEN];
+    loop {
+        let (len, _sender) = sock.recv_from(buf.as_mut_slice()).unwrap();
+        let event: ConnEvent = match bincode::deserialize(&buf[..len]) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Failed to decode an event: {}", e);
+                continue;
+            }
+        };
+        let at = event.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        println!(
+            "{}.{:06} {:?} {:?}",
+            at.as_secs(),
+            at.subsec_micros(),
+            event.conn_id,
+            event.kind
+        );
+    }
+}
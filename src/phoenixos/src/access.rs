@@ -0,0 +1,300 @@
+//! Admission of the client processes, and the private directories of the users.
+//!
+//! Every request to the control socket carries the credentials of the sender, filled in by the
+//! kernel (`SO_PEERCRED`), so a client cannot pass itself off as another user. The daemon refuses
+//! the requests from the users and groups that [`AccessConfig`] does not allow, and optionally
+//! from the processes in a user namespace other than its own, e.g., in a rootless container,
+//! where the user IDs do not mean what they mean to the daemon.
+//!
+//! The engines of a client bind the domain sockets the shared memory of the client is passed
+//! over in the prefix by default, where any user can connect to them. With `per_user_dirs`, they
+//! are bound in `<prefix>/users/<uid>` instead, a directory owned by the user and closed to the
+//! others. The mode of the prefix is left alone: it also holds the dispatch libraries the daemon
+//! loads, so it must not be writable by the users. `<prefix>/users` is owned by the daemon and
+//! only lets the users reach their own directories.
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::UCred;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::config::AccessConfig;
+
+/// The directory under the prefix that holds the directories of the users.
+const USERS_DIR: &str = "users";
+
+#[derive(Debug, Error)]
+pub(crate) enum AccessError {
+    #[error("user {uid} (gid {gid}) is not allowed")]
+    NotAllowed { uid: u32, gid: u32 },
+    #[error("process {0} is in another user namespace")]
+    UserNamespace(i32),
+    #[error("the credentials carry no pid")]
+    NoPid,
+    #[error("failed to inspect process {pid}: {source}")]
+    Proc { pid: i32, source: io::Error },
+    #[error("failed to prepare {path:?}: {source}")]
+    UserDir { path: PathBuf, source: io::Error },
+}
+
+pub(crate) struct AccessControl {
+    config: AccessConfig,
+    users_dir: PathBuf,
+    // the device and inode of the user namespace of the daemon
+    userns: Option<(u64, u64)>,
+}
+
+impl AccessControl {
+    pub(crate) fn new(config: &AccessConfig, prefix: &Path) -> io::Result<Self> {
+        let userns = if config.require_same_userns {
+            Some(user_namespace("self")?)
+        } else {
+            None
+        };
+        let users_dir = prefix.join(USERS_DIR);
+        if config.per_user_dirs {
+            prepare_users_dir(&users_dir)?;
+        }
+        Ok(AccessControl {
+            config: config.clone(),
+            users_dir,
+            userns,
+        })
+    }
+
+    /// Checks the credentials of a request to the control socket.
+    pub(crate) fn check(&self, cred: &UCred) -> Result<(), AccessError> {
+        let pid = cred.pid.ok_or(AccessError::NoPid)?;
+        if let Some(userns) = self.userns {
+            let theirs = user_namespace(&pid.to_string())
+                .map_err(|source| AccessError::Proc { pid, source })?;
+            if theirs != userns {
+                return Err(AccessError::UserNamespace(pid));
+            }
+        }
+
+        let (uids, gids) = (&self.config.allowed_uids, &self.config.allowed_gids);
+        if (uids.is_empty() && gids.is_empty()) || uids.contains(&cred.uid) {
+            return Ok(());
+        }
+        if !gids.is_empty() {
+            if gids.contains(&cred.gid) {
+                return Ok(());
+            }
+            let groups =
+                supplementary_groups(pid).map_err(|source| AccessError::Proc { pid, source })?;
+            if groups.iter().any(|gid| gids.contains(gid)) {
+                return Ok(());
+            }
+        }
+        Err(AccessError::NotAllowed {
+            uid: cred.uid,
+            gid: cred.gid,
+        })
+    }
+
+    /// Returns the directory the engines of a client bind their sockets in, or `None` if they are
+    /// bound in the prefix. The directory is created and handed to the user if needed.
+    pub(crate) fn user_dir(&self, cred: &UCred) -> Result<Option<PathBuf>, AccessError> {
+        if !self.config.per_user_dirs {
+            return Ok(None);
+        }
+        let path = self.users_dir.join(cred.uid.to_string());
+        match prepare_user_dir(&path, cred.uid, cred.gid) {
+            Ok(()) => Ok(Some(path)),
+            Err(source) => Err(AccessError::UserDir { path, source }),
+        }
+    }
+}
+
+/// Makes `path` a directory owned by the daemon, which the users can only pass through.
+fn prepare_users_dir(path: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o711).create(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    // a directory planted by another user would let them swap the directories of the users
+    let metadata = fs::symlink_metadata(path)?;
+    // SAFETY: geteuid is always successful
+    let euid = unsafe { libc::geteuid() };
+    if !metadata.is_dir() || metadata.uid() != euid {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{:?} is not a directory owned by the daemon", path),
+        ));
+    }
+    // the umask may have taken some bits
+    if metadata.mode() & 0o7777 != 0o711 {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o711))?;
+    }
+    Ok(())
+}
+
+/// Makes `path` a directory that only `uid` can access.
+fn prepare_user_dir(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::Other, "not a directory"));
+    }
+    if metadata.uid() != uid || metadata.gid() != gid {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: the path is a valid C string
+        if unsafe { libc::chown(c_path.as_ptr(), uid, gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if metadata.mode() & 0o7777 != 0o700 {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Returns the device and inode of the user namespace of a process, `self` for the daemon.
+fn user_namespace(pid: &str) -> io::Result<(u64, u64)> {
+    let metadata = fs::metadata(format!("/proc/{}/ns/user", pid))?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// Returns the supplementary groups of a process.
+fn supplementary_groups(pid: i32) -> io::Result<Vec<u32>> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+    Ok(status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| {
+            groups
+                .split_whitespace()
+                .filter_map(|gid| gid.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(allowed_uids: Vec<u32>, allowed_gids: Vec<u32>) -> AccessControl {
+        AccessControl {
+            config: AccessConfig {
+                allowed_uids,
+                allowed_gids,
+                ..Default::default()
+            },
+            users_dir: PathBuf::new(),
+            userns: None,
+        }
+    }
+
+    // the credentials of the test process, whose /proc entries are inspected
+    fn cred(uid: u32, gid: u32) -> UCred {
+        UCred {
+            uid,
+            gid,
+            pid: Some(std::process::id() as i32),
+        }
+    }
+
+    fn is_allowed(access: &AccessControl, cred: &UCred) -> bool {
+        match access.check(cred) {
+            Ok(()) => true,
+            Err(AccessError::NotAllowed { .. }) => false,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn allow_lists() {
+        // a gid no process of the test is in
+        const OTHER_GID: u32 = u32::MAX - 1;
+        let cases: &[(&[u32], &[u32], u32, u32, bool)] = &[
+            // allowed uids, allowed gids, uid, gid, allowed
+            (&[], &[], 1000, 1000, true),
+            (&[1000], &[], 1000, 1000, true),
+            (&[1000], &[], 1001, 1000, false),
+            (&[], &[1000], 1001, 1000, true),
+            (&[], &[OTHER_GID], 1001, 1000, false),
+            (&[1000], &[OTHER_GID], 1000, 1001, true),
+            (&[1000], &[OTHER_GID], 1001, OTHER_GID, true),
+            (&[1000], &[OTHER_GID], 1001, 1001, false),
+        ];
+        for &(uids, gids, uid, gid, allowed) in cases {
+            let access = access(uids.to_vec(), gids.to_vec());
+            assert_eq!(
+                is_allowed(&access, &cred(uid, gid)),
+                allowed,
+                "uids {:?}, gids {:?}, uid {}, gid {}",
+                uids,
+                gids,
+                uid,
+                gid
+            );
+        }
+    }
+
+    #[test]
+    fn supplementary_groups_are_allowed() {
+        let groups = supplementary_groups(std::process::id() as i32).unwrap();
+        let Some(&group) = groups.first() else {
+            return;
+        };
+        let access = access(vec![], vec![group]);
+        assert!(is_allowed(&access, &cred(u32::MAX - 1, u32::MAX - 1)));
+    }
+
+    #[test]
+    fn user_namespace_is_checked() {
+        let mut access = access(vec![], vec![]);
+        access.userns = Some(user_namespace("self").unwrap());
+        assert!(access.check(&cred(1000, 1000)).is_ok());
+
+        // pretend the daemon is in another user namespace
+        access.userns = Some((0, 0));
+        assert!(matches!(
+            access.check(&cred(1000, 1000)),
+            Err(AccessError::UserNamespace(_))
+        ));
+    }
+
+    #[test]
+    fn no_pid_is_rejected() {
+        let cred = UCred {
+            uid: 0,
+            gid: 0,
+            pid: None,
+        };
+        assert!(matches!(
+            access(vec![], vec![]).check(&cred),
+            Err(AccessError::NoPid)
+        ));
+    }
+
+    #[test]
+    fn users_dir_is_closed() {
+        let prefix = std::env::temp_dir().join(format!("phoenix-access-{}", std::process::id()));
+        fs::create_dir_all(&prefix).unwrap();
+        let prefix_mode = fs::metadata(&prefix).unwrap().mode() & 0o7777;
+        let config = AccessConfig {
+            per_user_dirs: true,
+            ..Default::default()
+        };
+        let access = AccessControl::new(&config, &prefix).unwrap();
+        let users_mode = fs::metadata(&access.users_dir).unwrap().mode() & 0o7777;
+        let after = fs::metadata(&prefix).unwrap().mode() & 0o7777;
+        fs::remove_dir_all(&prefix).unwrap();
+
+        assert_eq!(users_mode, 0o711);
+        // the prefix keeps its mode
+        assert_eq!(after, prefix_mode);
+    }
+}
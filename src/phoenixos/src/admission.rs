@@ -0,0 +1,131 @@
+//! Admission of new client processes against the limits of the host.
+//!
+//! A client registering a service is refused when the daemon already serves `max_clients`
+//! processes, when the engines of all clients reach `max_engines_per_core` times the cores
+//! available to the daemon, or when the cgroup of the client has `max_clients_per_cgroup`
+//! processes served, or it or an ancestor in `cgroup_memory_caps` uses more memory than its cap.
+//! The other threads of a client process already served only count against the engine limit.
+//!
+//! The cgroups are those of the unified (v2) hierarchy. The limits of the cgroups do not apply to
+//! the clients outside of it.
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use nix::unistd::Pid;
+use phoenix_api::error::Rejection;
+
+use crate::config::AdmissionConfig;
+use crate::log;
+use crate::runtime::RuntimeManager;
+
+/// Where the unified cgroup hierarchy is mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+pub(crate) struct Admission {
+    config: AdmissionConfig,
+    cores: usize,
+}
+
+impl Admission {
+    pub(crate) fn new(config: &AdmissionConfig) -> Self {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        Admission {
+            config: config.clone(),
+            cores,
+        }
+    }
+
+    /// Checks whether the client `pid` may create the engines of another service.
+    pub(crate) fn check(&self, pid: Pid, runtime: &RuntimeManager) -> Result<(), Rejection> {
+        if let Some(max_per_core) = self.config.max_engines_per_core {
+            let engines = runtime.engine_subscriptions.len();
+            if engines >= max_per_core * self.cores {
+                return Err(Rejection::MaxEnginesPerCore {
+                    engines,
+                    cores: self.cores,
+                    max_per_core,
+                });
+            }
+        }
+
+        let clients: HashSet<Pid> = runtime
+            .service_subscriptions
+            .iter()
+            .map(|entry| entry.key().0)
+            .collect();
+        if clients.contains(&pid) {
+            return Ok(());
+        }
+        if let Some(max) = self.config.max_clients {
+            if clients.len() >= max {
+                return Err(Rejection::MaxClients { max });
+            }
+        }
+
+        if self.config.max_clients_per_cgroup.is_none() && self.config.cgroup_memory_caps.is_empty()
+        {
+            return Ok(());
+        }
+        let cgroup = match cgroup_of(pid) {
+            Ok(Some(cgroup)) => cgroup,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                log::warn!("Cannot find the cgroup of client {}: {}", pid, e);
+                return Ok(());
+            }
+        };
+        if let Some(max) = self.config.max_clients_per_cgroup {
+            // the processes that have exited are not found
+            let peers = clients
+                .iter()
+                .filter(|&&client| matches!(cgroup_of(client), Ok(Some(c)) if c == cgroup))
+                .count();
+            if peers >= max {
+                return Err(Rejection::CgroupClients {
+                    cgroup: cgroup.display().to_string(),
+                    max,
+                });
+            }
+        }
+        for (capped, &cap) in &self.config.cgroup_memory_caps {
+            if !cgroup.starts_with(capped) {
+                continue;
+            }
+            match memory_usage(Path::new(capped)) {
+                Ok(usage) if usage > cap => {
+                    return Err(Rejection::CgroupMemory {
+                        cgroup: capped.clone(),
+                        usage,
+                        cap,
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Cannot read the memory usage of cgroup {}: {}", capped, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the path of the cgroup of a process in the unified hierarchy, if it is in one.
+fn cgroup_of(pid: Pid) -> io::Result<Option<PathBuf>> {
+    let cgroups = fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+    Ok(cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from))
+}
+
+/// Returns the memory in bytes used by the processes of `cgroup` and its descendants.
+fn memory_usage(cgroup: &Path) -> io::Result<u64> {
+    let path = Path::new(CGROUP_ROOT)
+        .join(cgroup.strip_prefix("/").unwrap_or(cgroup))
+        .join("memory.current");
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,460 @@
+//! Checkpoints of the daemon, which let a restarted daemon serve the running clients again
+//! instead of forcing them to restart.
+//!
+//! With `checkpoint.enable`, the control loop saves a checkpoint to
+//! `<control.prefix>/<checkpoint.path>` every `checkpoint.interval_secs` and when the daemon
+//! exits. It holds the shared memory regions the modules have allocated for each client process
+//! (see [`PhoenixModule::checkpoint`]), and what the engines of its service subscriptions save
+//! (see [`Engine::checkpoint`]). A daemon that crashes only loses what changed since the last
+//! checkpoint.
+//!
+//! When the daemon starts, it takes back the regions of the clients in the checkpoint that are
+//! still running. The memfds of the regions are opened again from the file descriptors the
+//! clients keep, found by the names of the memfds in `/proc/<pid>/fd`, which the daemon may only
+//! open if it is allowed to trace the clients, e.g., as the same user. The modules map the memfds
+//! at the addresses the clients map them (see [`PhoenixModule::restore`]). When a client registers a service again,
+//! e.g., by `mrpc::reattach`, its engines are resumed from their checkpoints (see
+//! [`PhoenixModule::resume_engine`]). The connections do not survive the daemon, they are logged
+//! as lost.
+//!
+//! The engines of mRPC save no state to resume from, so they are created afresh. What they hold
+//! is either tied to the connections, e.g., the connection tables and the receive buffers of the
+//! RPC adapter, or rebuilt by the client when it registers again, e.g., the dispatch library of
+//! the mRPC engine, which `mrpc::reattach` loads again with the protos of the client.
+//!
+//! [`PhoenixModule::checkpoint`]: phoenix_common::module::PhoenixModule::checkpoint
+//! [`PhoenixModule::restore`]: phoenix_common::module::PhoenixModule::restore
+//! [`PhoenixModule::resume_engine`]: phoenix_common::module::PhoenixModule::resume_engine
+//! [`Engine::checkpoint`]: phoenix_common::engine::Engine::checkpoint
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::anyhow;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use phoenix_common::checkpoint::{EngineCheckpoint, RestoredRegion, ShmRegionDescriptor};
+use phoenix_common::state_mgr::is_process_alive;
+
+use crate::config::CheckpointConfig;
+use crate::log;
+use crate::plugin_mgr::PluginManager;
+use crate::runtime::manager::EngineCheckpoints;
+use crate::runtime::RuntimeManager;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DaemonCheckpoint {
+    /// The version of phoenixos that takes the checkpoint
+    version: String,
+    /// Unix time in seconds
+    taken_at: u64,
+    processes: Vec<ProcessCheckpoint>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ProcessCheckpoint {
+    pid: i32,
+    /// The shared memory regions of the process, by the name of the module that allocates them
+    regions: BTreeMap<String, Vec<ShmRegionDescriptor>>,
+    subscriptions: Vec<SubscriptionCheckpoint>,
+}
+
+/// A service subscription of a client in a checkpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SubscriptionCheckpoint {
+    pub(crate) service: String,
+    /// The checkpoints of the engines by their types, for the engines that save one
+    pub(crate) engines: BTreeMap<String, EngineCheckpoint>,
+}
+
+pub(crate) struct Checkpointer {
+    interval: Option<Duration>,
+    last_checkpoint: Instant,
+    path: PathBuf,
+    /// The subscriptions restored from the checkpoint whose clients have not registered again,
+    /// by the client and the name of the service
+    pending: HashMap<(Pid, String), Vec<SubscriptionCheckpoint>>,
+    /// The checkpoints asked of the engines, which are saved once the runtimes respond
+    taking: Option<EngineCheckpoints>,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(config: &CheckpointConfig, prefix: &Path) -> Self {
+        Checkpointer {
+            interval: config
+                .enable
+                .then(|| Duration::from_secs_f64(config.interval_secs)),
+            last_checkpoint: Instant::now(),
+            path: prefix.join(&config.path),
+            pending: HashMap::new(),
+            taking: None,
+        }
+    }
+
+    /// Takes back the shared memory regions of the running clients from the checkpoint left by
+    /// the previous daemon, if any. This must be called before any client registers.
+    pub(crate) fn restore(&mut self, plugins: &PluginManager) {
+        if self.interval.is_none() {
+            return;
+        }
+        let checkpoint: DaemonCheckpoint = match fs::read(&self.path) {
+            Ok(content) => match serde_json::from_slice(&content) {
+                Ok(checkpoint) => checkpoint,
+                Err(e) => {
+                    log::warn!("ignored the malformed checkpoint {:?}: {}", self.path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                log::warn!("failed to read the checkpoint {:?}: {}", self.path, e);
+                return;
+            }
+        };
+        log::info!(
+            "restoring from the checkpoint of phoenix {} taken at unix time {}",
+            checkpoint.version,
+            checkpoint.taken_at
+        );
+
+        for process in checkpoint.processes {
+            let pid = Pid::from_raw(process.pid);
+            if !is_process_alive(pid) {
+                continue;
+            }
+            if let Err(e) = restore_regions(pid, process.regions, plugins) {
+                log::warn!("failed to restore client pid={}: {}", pid, e);
+                // drop the regions taken back by the other modules
+                let names: Vec<String> = plugins.modules.iter().map(|m| m.key().clone()).collect();
+                for name in names {
+                    if let Some(mut module) = plugins.modules.get_mut(&name) {
+                        module.reclaim(pid);
+                    }
+                }
+                continue;
+            }
+            self.keep(pid, process.subscriptions);
+            log::info!("restored client pid={}", pid);
+        }
+    }
+
+    /// Keeps the subscriptions of the client `pid` until it registers again.
+    fn keep(&mut self, pid: Pid, subscriptions: Vec<SubscriptionCheckpoint>) {
+        for subscription in subscriptions {
+            for conn in subscription
+                .engines
+                .values()
+                .flat_map(|engine| &engine.connections)
+            {
+                log::warn!(
+                    "connection {} of service {} of client pid={} is lost, local {:?}, peer {:?}",
+                    conn.handle,
+                    subscription.service,
+                    pid,
+                    conn.local,
+                    conn.peer
+                );
+            }
+            self.pending
+                .entry((pid, subscription.service.clone()))
+                .or_insert_with(Vec::new)
+                .push(subscription);
+        }
+    }
+
+    /// Returns the checkpoint of the subscription to `service` of a client that registers again.
+    pub(crate) fn resume(&mut self, pid: Pid, service: &str) -> Option<SubscriptionCheckpoint> {
+        let key = (pid, service.to_owned());
+        let subscriptions = self.pending.get_mut(&key)?;
+        let subscription = subscriptions.remove(0);
+        if subscriptions.is_empty() {
+            self.pending.remove(&key);
+        }
+        Some(subscription)
+    }
+
+    /// Takes a checkpoint if the interval has elapsed since the last one. The engines are asked
+    /// for their checkpoints first, and the checkpoint is saved by a later poll once the runtimes
+    /// respond, so the control loop does not wait for them.
+    pub(crate) fn poll(&mut self, runtime_manager: &RuntimeManager, plugins: &PluginManager) {
+        if let Some(engines) = self.taking.as_mut() {
+            if engines.poll() {
+                let engines = self.taking.take().unwrap();
+                self.save(runtime_manager, plugins, engines);
+                self.last_checkpoint = Instant::now();
+            }
+            return;
+        }
+        match self.interval {
+            Some(interval) if self.last_checkpoint.elapsed() >= interval => {
+                self.taking = Some(runtime_manager.request_checkpoints());
+            }
+            _ => {}
+        }
+    }
+
+    /// Takes a checkpoint if checkpoints are enabled, waiting for the runtimes to respond.
+    pub(crate) fn checkpoint(&mut self, runtime_manager: &RuntimeManager, plugins: &PluginManager) {
+        if self.interval.is_none() {
+            return;
+        }
+        let mut engines = self
+            .taking
+            .take()
+            .unwrap_or_else(|| runtime_manager.request_checkpoints());
+        engines.wait();
+        self.save(runtime_manager, plugins, engines);
+    }
+
+    fn save(
+        &mut self,
+        runtime_manager: &RuntimeManager,
+        plugins: &PluginManager,
+        engines: EngineCheckpoints,
+    ) {
+        self.pending.retain(|(pid, _), _| is_process_alive(*pid));
+        let checkpoint = self.take(runtime_manager, plugins, engines);
+        match write_checkpoint(&self.path, &checkpoint) {
+            Ok(()) => log::debug!(
+                "checkpoint of {} client processes saved to {:?}",
+                checkpoint.processes.len(),
+                self.path
+            ),
+            Err(e) => log::warn!("failed to save the checkpoint to {:?}: {}", self.path, e),
+        }
+    }
+
+    fn take(
+        &self,
+        runtime_manager: &RuntimeManager,
+        plugins: &PluginManager,
+        engines: EngineCheckpoints,
+    ) -> DaemonCheckpoint {
+        let mut processes: BTreeMap<i32, ProcessCheckpoint> = BTreeMap::new();
+        for module in plugins.modules.iter() {
+            for (pid, _) in module.resource_usage() {
+                let regions = module.checkpoint(pid);
+                if !regions.is_empty() {
+                    process(&mut processes, pid)
+                        .regions
+                        .insert(module.key().clone(), regions);
+                }
+            }
+        }
+        for (pid, service, engines) in runtime_manager.checkpoint_engines(engines) {
+            let engines = engines
+                .into_iter()
+                .map(|(engine_type, checkpoint)| (engine_type.0.to_owned(), checkpoint))
+                .collect();
+            process(&mut processes, pid)
+                .subscriptions
+                .push(SubscriptionCheckpoint {
+                    service: service.0.to_owned(),
+                    engines,
+                });
+        }
+        // the clients that have not registered again since the checkpoint was restored
+        for ((pid, _), subscriptions) in &self.pending {
+            process(&mut processes, *pid)
+                .subscriptions
+                .extend(subscriptions.iter().cloned());
+        }
+
+        DaemonCheckpoint {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            taken_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            processes: processes.into_values().collect(),
+        }
+    }
+}
+
+fn process(processes: &mut BTreeMap<i32, ProcessCheckpoint>, pid: Pid) -> &mut ProcessCheckpoint {
+    processes
+        .entry(pid.as_raw())
+        .or_insert_with(|| ProcessCheckpoint {
+            pid: pid.as_raw(),
+            ..Default::default()
+        })
+}
+
+/// Writes the checkpoint to a temporary file first, so a crash in between leaves the previous
+/// checkpoint intact.
+fn write_checkpoint(path: &Path, checkpoint: &DaemonCheckpoint) -> io::Result<()> {
+    let content = serde_json::to_vec(checkpoint)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
+
+/// Hands the regions of the client `pid` to the modules that allocated them.
+fn restore_regions(
+    pid: Pid,
+    regions: BTreeMap<String, Vec<ShmRegionDescriptor>>,
+    plugins: &PluginManager,
+) -> anyhow::Result<()> {
+    let mut memfds = open_memfds(pid)?;
+    for (module_name, descriptors) in regions {
+        let mut module = plugins
+            .modules
+            .get_mut(&module_name)
+            .ok_or_else(|| anyhow!("module {} is not loaded", module_name))?;
+        let mut restored = Vec::with_capacity(descriptors.len());
+        for descriptor in descriptors {
+            match memfds.remove(&descriptor.name) {
+                Some(memfd) => restored.push(RestoredRegion { descriptor, memfd }),
+                // freed by the client after the checkpoint
+                None => log::debug!("region {} of client pid={} is gone", descriptor.name, pid),
+            }
+        }
+        module.restore(pid, restored)?;
+    }
+    Ok(())
+}
+
+/// Opens the memfds the process `pid` holds, by their names.
+fn open_memfds(pid: Pid) -> io::Result<HashMap<String, File>> {
+    let mut memfds = HashMap::new();
+    for entry in fs::read_dir(format!("/proc/{}/fd", pid))? {
+        let path = entry?.path();
+        // the descriptor may have been closed since it was listed
+        let Ok(target) = fs::read_link(&path) else {
+            continue;
+        };
+        let Some(name) = memfd_name(&target) else {
+            continue;
+        };
+        if memfds.contains_key(name) {
+            continue;
+        }
+        let memfd = OpenOptions::new().read(true).write(true).open(&path)?;
+        memfds.insert(name.to_owned(), memfd);
+    }
+    Ok(memfds)
+}
+
+/// Returns the name of the memfd a file descriptor links to, e.g., `shared-mr-600000000000` of
+/// `/memfd:shared-mr-600000000000 (deleted)`.
+fn memfd_name(target: &Path) -> Option<&str> {
+    let target = target.to_str()?.strip_prefix("/memfd:")?;
+    Some(target.strip_suffix(" (deleted)").unwrap_or(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phoenix_common::checkpoint::ConnectionDescriptor;
+
+    #[test]
+    fn memfd_names() {
+        assert_eq!(
+            memfd_name(Path::new("/memfd:shared-mr-600000000000 (deleted)")),
+            Some("shared-mr-600000000000")
+        );
+        assert_eq!(memfd_name(Path::new("/memfd:heap")), Some("heap"));
+        assert_eq!(memfd_name(Path::new("/tmp/phoenix/file")), None);
+        assert_eq!(memfd_name(Path::new("socket:[1234]")), None);
+    }
+
+    fn subscription() -> SubscriptionCheckpoint {
+        let engine = EngineCheckpoint {
+            state: vec![0, 1, 0xfe, 0xff],
+            connections: vec![ConnectionDescriptor {
+                handle: 7,
+                local: Some("10.0.0.1:5000".parse().unwrap()),
+                peer: Some("10.0.0.2:41000".parse().unwrap()),
+                listener: Some(3),
+            }],
+        };
+        SubscriptionCheckpoint {
+            service: "Mrpc".to_owned(),
+            engines: BTreeMap::from([
+                ("MrpcEngine".to_owned(), Default::default()),
+                ("RpcAdapterEngine".to_owned(), engine),
+            ]),
+        }
+    }
+
+    // a checkpoint written to and read back from a file under the temporary directory
+    fn round_trip(checkpoint: &DaemonCheckpoint, name: &str) -> DaemonCheckpoint {
+        let dir = std::env::temp_dir().join(format!(
+            "phoenix-checkpoint-{}-{}",
+            std::process::id(),
+            name
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint.json");
+        write_checkpoint(&path, checkpoint).unwrap();
+        let loaded = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let checkpoint = DaemonCheckpoint {
+            version: "0.1.0".to_owned(),
+            taken_at: 1,
+            processes: vec![ProcessCheckpoint {
+                pid: 42,
+                regions: BTreeMap::from([(
+                    "Salloc".to_owned(),
+                    vec![ShmRegionDescriptor {
+                        name: "shared-mr-600000000000".to_owned(),
+                        addr: 0x600000000000,
+                        len: 4096,
+                        align: 4096,
+                    }],
+                )]),
+                subscriptions: vec![subscription()],
+            }],
+        };
+        let loaded = round_trip(&checkpoint, "file");
+
+        assert_eq!(loaded.processes.len(), 1);
+        let process = &loaded.processes[0];
+        assert_eq!(process.pid, 42);
+        assert_eq!(process.regions, checkpoint.processes[0].regions);
+        assert_eq!(process.subscriptions, vec![subscription()]);
+    }
+
+    #[test]
+    fn engine_state_resumed() {
+        let checkpoint = DaemonCheckpoint {
+            version: "0.1.0".to_owned(),
+            taken_at: 1,
+            processes: vec![ProcessCheckpoint {
+                pid: 42,
+                subscriptions: vec![subscription(), subscription()],
+                ..Default::default()
+            }],
+        };
+        let loaded = round_trip(&checkpoint, "resume");
+        let config = CheckpointConfig {
+            enable: true,
+            ..Default::default()
+        };
+        let mut checkpointer = Checkpointer::new(&config, &std::env::temp_dir());
+        let process = loaded.processes.into_iter().next().unwrap();
+        let pid = Pid::from_raw(process.pid);
+        checkpointer.keep(pid, process.subscriptions);
+
+        // each subscription of the client to the service is resumed once
+        for _ in 0..2 {
+            let resumed = checkpointer.resume(pid, "Mrpc").unwrap();
+            assert_eq!(
+                resumed.engines["RpcAdapterEngine"].state,
+                [0, 1, 0xfe, 0xff]
+            );
+            assert_eq!(resumed, subscription());
+        }
+        assert!(checkpointer.resume(pid, "Mrpc").is_none());
+        assert!(checkpointer.pending.is_empty());
+    }
+}
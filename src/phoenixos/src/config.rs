@@ -1,11 +1,83 @@
+//! Daemon configuration.
+//!
+//! The configuration is a single TOML file. Besides the fields of [`Config`], the file may
+//! contain
+//! - `include = ["a.toml", ...]`, files (relative to the including file) that are loaded
+//!   before the including file. Tables are merged with the later ones taking precedence, and
+//!   `modules`, `addons`, and `scheduling` are concatenated.
+//! - `[module_config.<Name>]` sections, each becomes the `config_string` of the module or
+//!   addon named `<Name>`.
+//! - `[profiles.<name>]` sections, each is a chain of addon engines a client can select when it
+//!   subscribes to a service, see [`Profile`].
+//!
+//! The relative `config_path` and `dep_path` of the modules and addons are relative to the file
+//! that declares them.
+//!
+//! `${VAR}` and `${VAR:-default}` are substituted with environment variables before parsing,
+//! except in comment lines. Use `$${` to write a literal `${`.
+use std::collections::{BTreeMap, HashSet};
+use std::env;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 use phoenix_api::engine::CustomSchedulingSpec;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 use ipc::control::PluginDescriptor;
 
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to parse {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("{path:?}: environment variable {var} is not set and has no default")]
+    UndefinedEnv { path: PathBuf, var: String },
+    #[error("{path:?}: unterminated `${{` in line {line}")]
+    UnterminatedEnv { path: PathBuf, line: usize },
+    #[error("{path:?} is included recursively")]
+    IncludeCycle { path: PathBuf },
+    #[error("{path:?}: `{key}` must be {expected}")]
+    Type {
+        path: PathBuf,
+        key: String,
+        expected: &'static str,
+    },
+    #[error("invalid config: {0}")]
+    Invalid(toml::de::Error),
+    #[error("plugin {0} is declared more than once")]
+    DuplicatePlugin(String),
+    #[error("[module_config.{0}] does not match any module or addon")]
+    UnknownModuleSection(String),
+    #[error("module_config.{0} must be a table")]
+    ModuleSectionType(String),
+    #[error(
+        "plugin {0} has more than one of `config_path`, `config_string`, and [module_config.{0}]"
+    )]
+    ConflictingConfig(String),
+    #[error("config_path {path:?} of plugin {name} does not exist")]
+    MissingConfigPath { name: String, path: PathBuf },
+    #[error("sweeper.interval_secs must be a positive number, got {0}")]
+    SweepInterval(f64),
+    #[error("checkpoint.interval_secs must be a positive number, got {0}")]
+    CheckpointInterval(f64),
+    #[error("runtime.batch_poll_interval must be positive")]
+    BatchPollInterval,
+    #[error("runtime.autoscale: {0}")]
+    Autoscale(&'static str),
+    #[error("registry.announce_interval_ms must be positive and less than registry.ttl_ms")]
+    RegistryInterval,
+    #[error("policy_watch.interval_ms must be positive")]
+    PolicyWatchInterval,
+    #[error("engine {engine} appears more than once in profile {profile}")]
+    DuplicateProfileEngine { profile: String, engine: String },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Control {
@@ -41,6 +113,221 @@ pub struct TracingConfig {
 pub struct ProfilingConfig {
     pub enable_on_new_client: bool,
     pub duration_ms: u64,
+    /// Record the time the engines spend in each phase of their mainloops, reported in the
+    /// stats
+    #[serde(default)]
+    pub engine_phases: bool,
+    /// Roll up the events the engines record on the RPCs into rates and percentiles, reported
+    /// in the stats
+    #[serde(default)]
+    pub engine_events: bool,
+}
+
+/// Settings of the sweeper that reclaims the resources of exited client processes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SweeperConfig {
+    pub enable: bool,
+    /// Seconds between two sweeps
+    pub interval_secs: f64,
+}
+
+impl Default for SweeperConfig {
+    fn default() -> Self {
+        SweeperConfig {
+            enable: true,
+            interval_secs: 5.0,
+        }
+    }
+}
+
+/// Settings of the checkpoints of the daemon, which let a restarted daemon serve the running
+/// clients again, see `checkpoint`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckpointConfig {
+    pub enable: bool,
+    /// Seconds between two checkpoints. A checkpoint is also taken when the daemon exits.
+    pub interval_secs: f64,
+    /// The checkpoint file, relative to `control.prefix`
+    pub path: PathBuf,
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        CheckpointConfig {
+            enable: false,
+            interval_secs: 10.0,
+            path: PathBuf::from("checkpoint.json"),
+        }
+    }
+}
+
+/// Settings of the Prometheus metrics endpoint. It is only available when phoenix is built with
+/// the `metrics` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enable: bool,
+    /// The address to serve `/metrics` on
+    pub listen: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enable: false,
+            listen: "127.0.0.1:9464".to_owned(),
+        }
+    }
+}
+
+/// Settings of the name service, where servers register their endpoints and clients look them
+/// up by the service name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistryConfig {
+    /// The UDP address to exchange endpoints with the peer daemons on. The endpoints are only
+    /// known on this host if not given.
+    pub listen: Option<String>,
+    /// The `listen` addresses of the peer daemons, the only sources the announcements are
+    /// accepted from
+    pub peers: Vec<String>,
+    /// The host registered in place of an unspecified bind address, the host name by default
+    pub advertise_host: Option<String>,
+    /// Milliseconds between two announcements of the local endpoints to the peers
+    pub announce_interval_ms: u64,
+    /// The endpoints of a peer are forgotten if it does not announce them again within this
+    /// many milliseconds
+    pub ttl_ms: u64,
+}
+
+impl Default for RegistryConfig {
+    fn default() -> Self {
+        RegistryConfig {
+            listen: None,
+            peers: Vec::new(),
+            advertise_host: None,
+            announce_interval_ms: 1000,
+            ttl_ms: 5000,
+        }
+    }
+}
+
+/// Settings of the admission of the client processes, checked against the credentials of the
+/// sender of each request to the control socket.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    /// The users allowed to connect. Everyone is allowed if both this and `allowed_gids` are
+    /// empty.
+    pub allowed_uids: Vec<u32>,
+    /// The groups allowed to connect, matched against the primary and supplementary groups of
+    /// the client
+    pub allowed_gids: Vec<u32>,
+    /// Refuse the clients in a user namespace other than the daemon's, e.g., in a rootless
+    /// container, whose user IDs mean other users to the daemon
+    pub require_same_userns: bool,
+    /// Bind the sockets of the engines of each user in `<prefix>/users/<uid>`, which only the
+    /// user can access. The daemon must run as root to hand the directories to the users.
+    pub per_user_dirs: bool,
+}
+
+/// Limits on the client processes the daemon admits, so that the clients of one team cannot
+/// exhaust the daemon. The limits are checked when a client registers a service.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionConfig {
+    /// The most client processes served at a time
+    pub max_clients: Option<usize>,
+    /// The most engines of all clients per core available to the daemon
+    pub max_engines_per_core: Option<usize>,
+    /// The most client processes of each cgroup (v2) served at a time
+    pub max_clients_per_cgroup: Option<usize>,
+    /// The memory caps of cgroups in bytes, keyed by their paths in the cgroup (v2) hierarchy,
+    /// e.g., `/team-a.slice`. The clients in a cgroup or its descendants are refused while the
+    /// cgroup uses more memory than its cap.
+    pub cgroup_memory_caps: BTreeMap<String, u64>,
+}
+
+/// Settings of the watcher applying the changes to the `config_path` files of the addons, e.g.,
+/// the rate limits of a policy, as they are written.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyWatchConfig {
+    pub enable: bool,
+    /// Milliseconds between two checks of the files
+    pub interval_ms: u64,
+}
+
+impl Default for PolicyWatchConfig {
+    fn default() -> Self {
+        PolicyWatchConfig {
+            enable: false,
+            interval_ms: 1000,
+        }
+    }
+}
+
+/// Settings of the runtimes that drive the engines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// The batch engines are polled once every this many iterations of their runtime. It can be
+    /// changed while the daemon is running with `phoenixctl schedctl`.
+    pub batch_poll_interval: u32,
+    /// The work an engine is granted in each iteration of its runtime, e.g., the messages it
+    /// takes from its queues. The work an engine does not use while it has work is carried to
+    /// the next iteration, so that a busy engine cannot starve the others sharing its core. 0
+    /// lets the engines do as much work as they have in each iteration.
+    pub engine_quantum: usize,
+    pub autoscale: AutoscaleConfig,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            batch_poll_interval: 16,
+            engine_quantum: 256,
+            autoscale: AutoscaleConfig::default(),
+        }
+    }
+}
+
+/// Settings of the autoscaler, which spreads the scheduling groups of a busy compact runtime
+/// onto a new runtime, and gathers the groups of an idle one back onto another.
+///
+/// The load of a runtime is the fraction of its iterations in which some engine did work. A
+/// runtime must stay above `scale_out_load`, or below `scale_in_load`, for `sustain` samples in a
+/// row before its groups are moved, and every move starts the count over.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoscaleConfig {
+    pub enable: bool,
+    /// Milliseconds between two samples of the load of the runtimes
+    pub interval_ms: u64,
+    /// Half of the groups of a runtime above this load are moved to a new runtime
+    pub scale_out_load: f64,
+    /// The groups of a runtime below this load are moved to another runtime, if the load of the
+    /// two stays below `scale_out_load`
+    pub scale_in_load: f64,
+    /// The number of samples in a row a runtime must stay above or below the loads
+    pub sustain: u32,
+    /// The most compact runtimes that run scheduling groups at a time
+    pub max_runtimes: usize,
+}
+
+impl Default for AutoscaleConfig {
+    fn default() -> Self {
+        AutoscaleConfig {
+            enable: false,
+            interval_ms: 1000,
+            scale_out_load: 0.8,
+            scale_in_load: 0.2,
+            sustain: 5,
+            max_runtimes: num_cpus::get(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -55,6 +342,30 @@ pub struct SchedulingPolicy {
     pub mode: CustomSchedulingSpec,
 }
 
+/// An addon engine of a [`Profile`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileEngine {
+    /// The engine type, e.g., `RateLimitEngine`. The addon that provides it must be loaded.
+    pub engine: String,
+    /// Whether the engine also handles the messages received by the application. Otherwise it
+    /// is only on the path of the messages sent.
+    #[serde(default)]
+    pub rx: bool,
+}
+
+/// A named chain of addon engines, e.g., `secure` with a rate limiter, an ACL and encryption,
+/// or `fast` with none. A client selects a profile by the `profile` field of its service
+/// setting, and the engines are placed between the service engine and the engine next to it
+/// when the service is created.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// The engines in the order the messages sent by the application go through them. The
+    /// received messages go through the `rx` engines in the reverse order.
+    pub engines: Vec<ProfileEngine>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -65,17 +376,502 @@ pub struct Config {
     pub control: Control,
     pub linker: LinkerConfig,
     #[serde(default)]
+    pub sweeper: SweeperConfig,
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+    #[serde(default)]
+    pub registry: RegistryConfig,
+    #[serde(default)]
+    pub access: AccessConfig,
+    #[serde(default)]
+    pub admission: AdmissionConfig,
+    #[serde(default)]
+    pub policy_watch: PolicyWatchConfig,
+    #[serde(default)]
     pub modules: Vec<PluginDescriptor>,
     #[serde(default)]
     pub addons: Vec<PluginDescriptor>,
     #[serde(default)]
     pub scheduling: Vec<SchedulingPolicy>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
 }
 
 impl Config {
-    pub fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let config = toml::from_str(&content)?;
+    /// Loads the config file at `path` with its includes, and validates it.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let mut stack = Vec::new();
+        let mut value = load_value(path.as_ref(), &mut stack)?;
+        apply_module_config(&mut value)?;
+        let config: Config = value.try_into().map_err(ConfigError::Invalid)?;
+        config.validate()?;
         Ok(config)
     }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let interval = self.sweeper.interval_secs;
+        if !interval.is_finite() || interval <= 0.0 {
+            return Err(ConfigError::SweepInterval(interval));
+        }
+        let interval = self.checkpoint.interval_secs;
+        if !interval.is_finite() || interval <= 0.0 {
+            return Err(ConfigError::CheckpointInterval(interval));
+        }
+        if self.runtime.batch_poll_interval == 0 {
+            return Err(ConfigError::BatchPollInterval);
+        }
+        let autoscale = &self.runtime.autoscale;
+        if autoscale.interval_ms == 0 || autoscale.sustain == 0 || autoscale.max_runtimes == 0 {
+            return Err(ConfigError::Autoscale(
+                "interval_ms, sustain and max_runtimes must be positive",
+            ));
+        }
+        if !(0.0..=1.0).contains(&autoscale.scale_in_load)
+            || !(0.0..=1.0).contains(&autoscale.scale_out_load)
+            || autoscale.scale_in_load >= autoscale.scale_out_load
+        {
+            return Err(ConfigError::Autoscale(
+                "expect 0 <= scale_in_load < scale_out_load <= 1",
+            ));
+        }
+        let registry = &self.registry;
+        if registry.announce_interval_ms == 0 || registry.announce_interval_ms >= registry.ttl_ms {
+            return Err(ConfigError::RegistryInterval);
+        }
+        if self.policy_watch.interval_ms == 0 {
+            return Err(ConfigError::PolicyWatchInterval);
+        }
+        let mut names = HashSet::new();
+        for plugin in self.modules.iter().chain(&self.addons) {
+            if !names.insert(plugin.name.as_str()) {
+                return Err(ConfigError::DuplicatePlugin(plugin.name.clone()));
+            }
+            if plugin.config_path.is_some() && plugin.config_string.is_some() {
+                return Err(ConfigError::ConflictingConfig(plugin.name.clone()));
+            }
+            if let Some(path) = plugin.config_path.as_ref() {
+                if !path.exists() {
+                    return Err(ConfigError::MissingConfigPath {
+                        name: plugin.name.clone(),
+                        path: path.clone(),
+                    });
+                }
+            }
+        }
+        for (name, profile) in &self.profiles {
+            let mut engines = HashSet::new();
+            for entry in &profile.engines {
+                if !engines.insert(entry.engine.as_str()) {
+                    return Err(ConfigError::DuplicateProfileEngine {
+                        profile: name.clone(),
+                        engine: entry.engine.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the descriptor of the module or addon named `name`.
+    pub fn find_plugin(&self, name: &str) -> Option<&PluginDescriptor> {
+        self.modules
+            .iter()
+            .chain(&self.addons)
+            .rev()
+            .find(|x| x.name == name)
+    }
+}
+
+/// Keys whose array values are concatenated rather than replaced when merging.
+const CONCAT_KEYS: [&str; 3] = ["modules", "addons", "scheduling"];
+
+/// Keys of the plugin descriptors that are paths to files, resolved against the declaring file.
+const PLUGIN_PATH_KEYS: [&str; 2] = ["config_path", "dep_path"];
+
+fn load_value(path: &Path, stack: &mut Vec<PathBuf>) -> Result<toml::Value, ConfigError> {
+    let canonical = path.canonicalize().map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    if stack.contains(&canonical) {
+        return Err(ConfigError::IncludeCycle {
+            path: path.to_path_buf(),
+        });
+    }
+
+    let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let content = substitute_env(&content, path)?;
+    let mut value: toml::Value = toml::from_str(&content).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    if matches!(value.get("module_config"), Some(v) if !v.is_table()) {
+        return Err(ConfigError::Type {
+            path: path.to_path_buf(),
+            key: "module_config".to_owned(),
+            expected: "a table",
+        });
+    }
+
+    let includes = match value.as_table_mut().and_then(|t| t.remove("include")) {
+        Some(toml::Value::Array(includes)) => includes,
+        Some(_) => {
+            return Err(ConfigError::Type {
+                path: path.to_path_buf(),
+                key: "include".to_owned(),
+                expected: "an array of paths",
+            })
+        }
+        None => Vec::new(),
+    };
+
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    resolve_plugin_paths(&mut value, base);
+
+    stack.push(canonical);
+    let mut merged = toml::Value::Table(toml::value::Table::new());
+    for include in includes {
+        let include = match include {
+            toml::Value::String(include) => base.join(include),
+            _ => {
+                return Err(ConfigError::Type {
+                    path: path.to_path_buf(),
+                    key: "include".to_owned(),
+                    expected: "an array of paths",
+                })
+            }
+        };
+        let included = load_value(&include, stack)?;
+        merge_value(&mut merged, included, true);
+    }
+    stack.pop();
+
+    merge_value(&mut merged, value, true);
+    Ok(merged)
+}
+
+/// Resolves the relative file paths of the modules and addons in `value` against `base`.
+fn resolve_plugin_paths(value: &mut toml::Value, base: &Path) {
+    for key in ["modules", "addons"] {
+        let plugins = match value.get_mut(key).and_then(|v| v.as_array_mut()) {
+            Some(plugins) => plugins,
+            None => continue,
+        };
+        for plugin in plugins.iter_mut().filter_map(|p| p.as_table_mut()) {
+            for path_key in PLUGIN_PATH_KEYS {
+                if let Some(toml::Value::String(path)) = plugin.get_mut(path_key) {
+                    if Path::new(path.as_str()).is_relative() {
+                        *path = base.join(path.as_str()).to_string_lossy().into_owned();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn merge_value(dst: &mut toml::Value, src: toml::Value, top_level: bool) {
+    match (dst, src) {
+        (toml::Value::Table(dst), toml::Value::Table(src)) => {
+            for (key, value) in src {
+                let concat = top_level && CONCAT_KEYS.contains(&key.as_str());
+                match dst.get_mut(&key) {
+                    Some(toml::Value::Array(existing)) if concat => {
+                        if let toml::Value::Array(mut value) = value {
+                            existing.append(&mut value);
+                        } else {
+                            *existing = vec![value];
+                        }
+                    }
+                    Some(existing) => merge_value(existing, value, false),
+                    None => {
+                        dst.insert(key, value);
+                    }
+                }
+            }
+        }
+        (dst, src) => *dst = src,
+    }
+}
+
+/// Moves each `[module_config.<Name>]` section into the `config_string` of the plugin.
+fn apply_module_config(value: &mut toml::Value) -> Result<(), ConfigError> {
+    let table = match value.as_table_mut() {
+        Some(table) => table,
+        None => return Ok(()),
+    };
+    let sections = match table.remove("module_config") {
+        Some(toml::Value::Table(sections)) => sections,
+        _ => return Ok(()),
+    };
+
+    for (name, section) in sections {
+        if !section.is_table() {
+            return Err(ConfigError::ModuleSectionType(name));
+        }
+        let position = ["modules", "addons"].into_iter().find_map(|key| {
+            let index = table
+                .get(key)?
+                .as_array()?
+                .iter()
+                .position(|x| x.get("name").and_then(|n| n.as_str()) == Some(name.as_str()))?;
+            Some((key, index))
+        });
+        let plugin = match position {
+            Some((key, index)) => table[key][index].as_table_mut(),
+            None => None,
+        };
+        let plugin = match plugin {
+            Some(plugin) => plugin,
+            None => return Err(ConfigError::UnknownModuleSection(name)),
+        };
+        if plugin.contains_key("config_path") || plugin.contains_key("config_string") {
+            return Err(ConfigError::ConflictingConfig(name));
+        }
+        let config_string =
+            toml::to_string(&section).expect("a toml value can always be serialized");
+        plugin.insert(
+            "config_string".to_owned(),
+            toml::Value::String(config_string),
+        );
+    }
+    Ok(())
+}
+
+/// Substitutes `${VAR}` and `${VAR:-default}` with the environment variables. Comment lines
+/// are left as is.
+fn substitute_env(content: &str, path: &Path) -> Result<String, ConfigError> {
+    let mut output = String::with_capacity(content.len());
+    for (lineno, line) in content.split_inclusive('\n').enumerate() {
+        if line.trim_start().starts_with('#') {
+            output.push_str(line);
+            continue;
+        }
+        let mut rest = line;
+        while let Some(pos) = rest.find("${") {
+            if rest[..pos].ends_with('$') {
+                // `$${` is an escaped `${`
+                output.push_str(&rest[..pos - 1]);
+                output.push_str("${");
+                rest = &rest[pos + 2..];
+                continue;
+            }
+            output.push_str(&rest[..pos]);
+            let end = match rest[pos..].find('}') {
+                Some(end) => pos + end,
+                None => {
+                    return Err(ConfigError::UnterminatedEnv {
+                        path: path.to_path_buf(),
+                        line: lineno + 1,
+                    })
+                }
+            };
+            let expr = &rest[pos + 2..end];
+            let (var, default) = match expr.split_once(":-") {
+                Some((var, default)) => (var, Some(default)),
+                None => (expr, None),
+            };
+            match (env::var(var), default) {
+                (Ok(value), _) => output.push_str(&value),
+                (Err(_), Some(default)) => output.push_str(default),
+                (Err(_), None) => {
+                    return Err(ConfigError::UndefinedEnv {
+                        path: path.to_path_buf(),
+                        var: var.to_owned(),
+                    })
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        output.push_str(rest);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of config files, removed when dropped.
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                env::temp_dir().join(format!("phoenix-config-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            ConfigDir(dir)
+        }
+
+        fn write(&self, file: &str, content: &str) -> PathBuf {
+            let path = self.0.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn plugin_names(value: &toml::Value) -> Vec<&str> {
+        value["modules"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn include_merges_tables_and_concats_plugins() {
+        let dir = ConfigDir::new("merge");
+        dir.write(
+            "common/base.toml",
+            r#"
+            log_level = "info"
+            [runtime]
+            batch_poll_interval = 8
+            engine_quantum = 1
+            [[modules]]
+            name = "A"
+            lib_path = "a.rlib"
+            "#,
+        );
+        let main = dir.write(
+            "phoenix.toml",
+            r#"
+            include = ["common/base.toml"]
+            [runtime]
+            batch_poll_interval = 4
+            [[modules]]
+            name = "B"
+            lib_path = "b.rlib"
+            "#,
+        );
+
+        let value = load_value(&main, &mut Vec::new()).unwrap();
+        assert_eq!(value["log_level"].as_str(), Some("info"));
+        assert_eq!(
+            value["runtime"]["batch_poll_interval"].as_integer(),
+            Some(4)
+        );
+        assert_eq!(value["runtime"]["engine_quantum"].as_integer(), Some(1));
+        assert_eq!(plugin_names(&value), ["A", "B"]);
+        assert!(value.get("include").is_none());
+    }
+
+    #[test]
+    fn include_resolves_paths_against_declaring_file() {
+        let dir = ConfigDir::new("paths");
+        dir.write(
+            "common/base.toml",
+            r#"
+            [[modules]]
+            name = "A"
+            lib_path = "a.rlib"
+            config_path = "a.toml"
+            [[addons]]
+            name = "B"
+            lib_path = "b.rlib"
+            config_path = "/etc/phoenix/b.toml"
+            dep_path = "deps/b.d"
+            "#,
+        );
+        let main = dir.write(
+            "phoenix.toml",
+            r#"
+            include = ["common/base.toml"]
+            [[modules]]
+            name = "C"
+            lib_path = "c.rlib"
+            config_path = "c.toml"
+            "#,
+        );
+
+        let value = load_value(&main, &mut Vec::new()).unwrap();
+        let modules = value["modules"].as_array().unwrap();
+        let addons = value["addons"].as_array().unwrap();
+        let path = |v: &toml::Value| PathBuf::from(v.as_str().unwrap());
+        assert_eq!(
+            path(&modules[0]["config_path"]),
+            dir.0.join("common/a.toml")
+        );
+        assert_eq!(path(&modules[1]["config_path"]), dir.0.join("c.toml"));
+        // lib_path is relative to the prefix of the plugins
+        assert_eq!(path(&modules[0]["lib_path"]), PathBuf::from("a.rlib"));
+        assert_eq!(
+            path(&addons[0]["config_path"]),
+            PathBuf::from("/etc/phoenix/b.toml")
+        );
+        assert_eq!(path(&addons[0]["dep_path"]), dir.0.join("common/deps/b.d"));
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = ConfigDir::new("cycle");
+        let a = dir.write("a.toml", "include = [\"sub/b.toml\"]\n");
+        dir.write("sub/b.toml", "include = [\"../a.toml\"]\n");
+        assert!(matches!(
+            load_value(&a, &mut Vec::new()),
+            Err(ConfigError::IncludeCycle { .. })
+        ));
+
+        // the same file included twice is not a cycle
+        let c = dir.write("c.toml", "include = [\"d.toml\", \"d.toml\"]\n");
+        dir.write(
+            "d.toml",
+            "[[modules]]\nname = \"D\"\nlib_path = \"d.rlib\"\n",
+        );
+        let value = load_value(&c, &mut Vec::new()).unwrap();
+        assert_eq!(plugin_names(&value), ["D", "D"]);
+    }
+
+    #[test]
+    fn include_type_error() {
+        let dir = ConfigDir::new("type");
+        let main = dir.write("phoenix.toml", "include = \"a.toml\"\n");
+        assert!(matches!(
+            load_value(&main, &mut Vec::new()),
+            Err(ConfigError::Type { key, .. }) if key == "include"
+        ));
+    }
+
+    #[test]
+    fn env_substitution() {
+        let path = Path::new("phoenix.toml");
+        env::set_var("PHOENIX_CONFIG_TEST_PREFIX", "/tmp/test");
+        env::remove_var("PHOENIX_CONFIG_TEST_UNSET");
+
+        let content = "prefix = \"${PHOENIX_CONFIG_TEST_PREFIX}/x\"\n\
+                       level = \"${PHOENIX_CONFIG_TEST_UNSET:-info}\"\n\
+                       # ${PHOENIX_CONFIG_TEST_UNSET}\n\
+                       literal = \"$${PHOENIX_CONFIG_TEST_PREFIX}\"\n";
+        assert_eq!(
+            substitute_env(content, path).unwrap(),
+            "prefix = \"/tmp/test/x\"\n\
+             level = \"info\"\n\
+             # ${PHOENIX_CONFIG_TEST_UNSET}\n\
+             literal = \"${PHOENIX_CONFIG_TEST_PREFIX}\"\n"
+        );
+
+        assert!(matches!(
+            substitute_env("a = \"${PHOENIX_CONFIG_TEST_UNSET}\"\n", path),
+            Err(ConfigError::UndefinedEnv { var, .. }) if var == "PHOENIX_CONFIG_TEST_UNSET"
+        ));
+        assert!(matches!(
+            substitute_env("a = 1\nb = \"${PHOENIX_CONFIG_TEST_PREFIX\"\n", path),
+            Err(ConfigError::UnterminatedEnv { line: 2, .. })
+        ));
+    }
 }
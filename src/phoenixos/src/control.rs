@@ -4,32 +4,49 @@ use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
 use std::os::unix::net::{SocketAddr, UCred};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{anyhow, bail};
 use ipc::control::ResponseKind;
-use ipc::control::{PluginType, Response};
+use ipc::control::{PluginDescriptor, PluginType, ReloadRequest, Response};
 use itertools::Itertools;
 use nix::unistd::Pid;
 
-use ipc::control::ServiceSubscriptionInfo;
+use ipc::control::{Capabilities, ServiceSubscriptionInfo};
 use ipc::unix::DomainSocket;
+use ipc::version::{ProtocolVersion, PROTOCOL_V1, PROTOCOL_VERSION};
 use phoenix_api::engine::{SchedulingHint, SchedulingMode};
 
 use phoenix_common::engine::datapath::{ChannelDescriptor, DataPathNode};
 use phoenix_common::engine::EngineType;
 use phoenix_common::module::{NewEngineRequest, Service};
-use phoenix_common::storage::{ResourceCollection, SharedStorage, PHOENIX_PREFIX_KEY};
+use phoenix_common::storage::{
+    ResourceCollection, SharedStorage, PHOENIX_PREFIX_KEY, PHOENIX_USER_DIR_KEY,
+};
 
-use crate::config::Config;
+use crate::access::AccessControl;
+use crate::admission::Admission;
+use crate::checkpoint::Checkpointer;
+use crate::config::{Config, Profile};
+use crate::dump;
+use crate::events;
+use crate::logging::LogFilterHandle;
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsServer;
 use crate::plugin::{Plugin, PluginName};
 use crate::plugin_mgr::PluginManager;
+use crate::policy_watch::PolicyWatcher;
+use crate::profiler;
+use crate::registry::Registry;
+use crate::runtime::affinity::CoreMask;
 use crate::runtime::graph::create_datapath_channels;
 use crate::runtime::manager::{EngineId, ServiceSubscription, SubscriptionId};
+use crate::runtime::scaler::Autoscaler;
 use crate::runtime::{EngineContainer, EngineUpgrader, RuntimeManager};
+use crate::sweeper::Sweeper;
 use crate::{log, tracing};
 
 pub struct Control {
@@ -39,6 +56,17 @@ pub struct Control {
     upgrader: EngineUpgrader,
     scheduling_override: HashMap<String, SchedulingMode>,
     config: Config,
+    config_path: PathBuf,
+    log_filter: LogFilterHandle,
+    sweeper: Sweeper,
+    checkpointer: Checkpointer,
+    policy_watcher: PolicyWatcher,
+    autoscaler: Autoscaler,
+    registry: Registry,
+    access: AccessControl,
+    admission: Admission,
+    #[cfg(feature = "metrics")]
+    metrics: Option<MetricsServer>,
 }
 
 impl Control {
@@ -64,6 +92,12 @@ impl Control {
                                 mrpc_module.config_string = Some(c.replace("Rdma", "Tcp"));
                             }
                         }
+                        TransportType::Auto => {
+                            if let Some(c) = mrpc_module.config_string.as_ref() {
+                                let c = c.replace("Rdma", "Auto").replace("Tcp", "Auto");
+                                mrpc_module.config_string = Some(c);
+                            }
+                        }
                     }
                     self.plugins
                         .load_or_upgrade_modules(&[mrpc_module.clone()])
@@ -76,6 +110,7 @@ impl Control {
 }
 
 impl Control {
+    #[allow(clippy::too_many_arguments)]
     fn create_service(
         &mut self,
         service: Service,
@@ -84,6 +119,7 @@ impl Control {
         scheduling_hint: SchedulingHint,
         cred: &UCred,
         config_string: Option<String>,
+        protocol: ProtocolVersion,
     ) -> anyhow::Result<()> {
         let pid = Pid::from_raw(cred.pid.unwrap());
         if self.upgrader.is_upgrading(pid) {
@@ -93,14 +129,34 @@ impl Control {
         // NOTE(cjr): Specially handle here. A complete solution needs a large refactoring.
         // TODO(cjr): Need a complete refactoring.
         self.choose_transport(&service, config_string.as_ref())?;
+        let profile = self.requested_profile(config_string.as_ref())?;
+        // the client was served before the daemon restarted
+        let resumed = self.checkpointer.resume(pid, service.0);
+        if resumed.is_some() {
+            tracing::info!(
+                "Resuming service {:?} of client pid={:?} from the checkpoint",
+                service,
+                pid
+            );
+        }
 
         let service_registry = self
             .plugins
             .service_registry
             .get(&service)
             .ok_or_else(|| anyhow!("service {:?} not found in the registry", service))?;
-        let tx_channels = service_registry.tx_channels.iter().copied();
-        let rx_channels = service_registry.rx_channels.iter().copied();
+        let service_engine_type = service_registry.engines.last().unwrap();
+        let mut tx_channels = service_registry.tx_channels.clone();
+        let mut rx_channels = service_registry.rx_channels.clone();
+        let addons = match profile {
+            Some(profile) => self.splice_profile(
+                &profile,
+                *service_engine_type,
+                &mut tx_channels,
+                &mut rx_channels,
+            )?,
+            None => Vec::new(),
+        };
         let (mut nodes, graph) = create_datapath_channels(
             tx_channels,
             rx_channels,
@@ -109,10 +165,13 @@ impl Control {
 
         let subscription = ServiceSubscription {
             service,
-            addons: Vec::new(),
+            addons: addons.clone(),
             graph,
         };
 
+        // the sockets of the engines go to the directory of the user if the users are isolated
+        let user_dir = self.access.user_dir(cred)?;
+
         let mut shared = SharedStorage::new();
         let mut global = self
             .runtime_manager
@@ -126,6 +185,11 @@ impl Control {
             PHOENIX_PREFIX_KEY.to_owned(),
             Box::new(self.config.control.prefix.clone()), // Box<PathBuf>
         );
+        if let Some(user_dir) = user_dir {
+            global
+                .value_mut()
+                .insert(PHOENIX_USER_DIR_KEY.to_owned(), Box::new(user_dir));
+        }
 
         let mut singleton_id = service_registry.scheduling_groups.size();
         let mut containers_to_submit = HashMap::new();
@@ -156,14 +220,28 @@ impl Control {
                 config_string: config_string.clone(),
             };
 
-            let engine = module.create_engine(
-                *aux_engine_type,
-                request,
-                &mut shared,
-                global.value_mut(),
-                node,
-                &self.plugins.modules,
-            )?;
+            let engine = match resumed
+                .as_ref()
+                .and_then(|r| r.engines.get(aux_engine_type.0))
+            {
+                Some(checkpoint) => module.resume_engine(
+                    *aux_engine_type,
+                    request,
+                    checkpoint,
+                    &mut shared,
+                    global.value_mut(),
+                    node,
+                    &self.plugins.modules,
+                )?,
+                None => module.create_engine(
+                    *aux_engine_type,
+                    request,
+                    &mut shared,
+                    global.value_mut(),
+                    node,
+                    &self.plugins.modules,
+                )?,
+            };
 
             // submit auxiliary to runtime manager
             if let Some(engine) = engine {
@@ -182,8 +260,41 @@ impl Control {
             }
         }
 
+        // the addon engines of the profile run with the service engine
+        let service_group = service_registry
+            .scheduling_groups
+            .find_representative(*service_engine_type)
+            .unwrap_or_else(|| {
+                singleton_id += 1;
+                singleton_id - 1
+            });
+        for addon_engine_type in &addons {
+            let plugin = self.plugins.engine_registry.get(addon_engine_type).unwrap();
+            let (addon_name, specified_mode) = match plugin.value() {
+                (PluginName::Addon(addon), mode) => (addon, *mode),
+                (PluginName::Module(_), _) => {
+                    panic!("profile engine {:?} is not an addon", addon_engine_type)
+                }
+            };
+            let mut addon = self.plugins.addons.get_mut(addon_name).unwrap();
+            let node = nodes
+                .remove(addon_engine_type)
+                .unwrap_or_else(DataPathNode::new);
+            let engine = addon.create_engine(*addon_engine_type, pid, node)?;
+            tracing::info!(
+                "Created addon engine {:?} of service {:?} for client pid={:?}",
+                addon_engine_type,
+                service,
+                pid
+            );
+            let container = EngineContainer::new(engine, *addon_engine_type, addon.version());
+            containers_to_submit
+                .entry(service_group)
+                .or_insert_with(Vec::new)
+                .push((container, specified_mode.unwrap_or(service_mode)));
+        }
+
         // finally, create service engine
-        let service_engine_type = service_registry.engines.last().unwrap();
         let plugin = self
             .plugins
             .engine_registry
@@ -203,20 +314,36 @@ impl Control {
             mode: specified_mode,
             cred,
             config_string,
+            protocol,
         };
 
         let node = nodes
             .remove(service_engine_type)
             .unwrap_or_else(DataPathNode::new);
 
-        let engine = match module.create_engine(
-            *service_engine_type,
-            request,
-            &mut shared,
-            global.value_mut(),
-            node,
-            &self.plugins.modules,
-        ) {
+        let created = match resumed
+            .as_ref()
+            .and_then(|r| r.engines.get(service_engine_type.0))
+        {
+            Some(checkpoint) => module.resume_engine(
+                *service_engine_type,
+                request,
+                checkpoint,
+                &mut shared,
+                global.value_mut(),
+                node,
+                &self.plugins.modules,
+            ),
+            None => module.create_engine(
+                *service_engine_type,
+                request,
+                &mut shared,
+                global.value_mut(),
+                node,
+                &self.plugins.modules,
+            ),
+        };
+        let engine = match created {
             Ok(Some(ret)) => ret,
             Ok(None) => bail!(
                 "service engine must always be created, engine_type={:?}",
@@ -240,15 +367,8 @@ impl Control {
         );
         // Submit service engine to runtime manager
         let container = EngineContainer::new(engine, *service_engine_type, module.version());
-        let representative = service_registry
-            .scheduling_groups
-            .find_representative(*service_engine_type)
-            .unwrap_or_else(|| {
-                singleton_id += 1;
-                singleton_id - 1
-            });
         let entry = containers_to_submit
-            .entry(representative)
+            .entry(service_group)
             .or_insert_with(Vec::new);
         entry.push((container, specified_mode));
 
@@ -279,8 +399,78 @@ impl Control {
         Ok(())
     }
 
-    /// Create a `Control` instance.
-    pub fn new(runtime_manager: Arc<RuntimeManager>, config: Config) -> Self {
+    /// Returns the profile named by the `profile` field of the config string of a new client,
+    /// if it has one.
+    fn requested_profile(&self, config_string: Option<&String>) -> anyhow::Result<Option<Profile>> {
+        let setting = match config_string.map(|s| serde_json::from_str::<serde_json::Value>(s)) {
+            Some(Ok(setting)) => setting,
+            _ => return Ok(None),
+        };
+        let name = match setting.get("profile").and_then(|p| p.as_str()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match self.config.profiles.get(name) {
+            Some(profile) => Ok(Some(profile.clone())),
+            None => bail!("profile {} is not defined in the config", name),
+        }
+    }
+
+    /// Routes the channels between the service engine and the engine next to it through the
+    /// engines of `profile`. Returns the engines of the profile.
+    fn splice_profile(
+        &self,
+        profile: &Profile,
+        service_engine: EngineType,
+        tx_channels: &mut Vec<ChannelDescriptor>,
+        rx_channels: &mut Vec<ChannelDescriptor>,
+    ) -> anyhow::Result<Vec<EngineType>> {
+        let mut tx_engines = Vec::with_capacity(profile.engines.len());
+        let mut rx_engines = Vec::new();
+        for entry in &profile.engines {
+            let engine = unsafe { transmute_engine_type_from_str(entry.engine.as_str()) };
+            let plugin = self
+                .plugins
+                .engine_registry
+                .get(&engine)
+                .ok_or_else(|| anyhow!("Addon engine type {:?} not found", entry.engine))?;
+            if !matches!(plugin.value().0, PluginName::Addon(_)) {
+                bail!("engine {:?} of a profile is not an addon", entry.engine);
+            }
+            tx_engines.push(*plugin.key());
+            if entry.rx {
+                rx_engines.push(*plugin.key());
+            }
+        }
+        rx_engines.reverse();
+
+        if !tx_engines.is_empty() {
+            let index = tx_channels
+                .iter()
+                .position(|c| c.0 == service_engine)
+                .ok_or_else(|| anyhow!("service engine {:?} has no tx channel", service_engine))?;
+            let channel = tx_channels.remove(index);
+            tx_channels.extend(route_through(channel, &tx_engines));
+        }
+        if !rx_engines.is_empty() {
+            let index = rx_channels
+                .iter()
+                .position(|c| c.1 == service_engine)
+                .ok_or_else(|| anyhow!("service engine {:?} has no rx channel", service_engine))?;
+            let channel = rx_channels.remove(index);
+            rx_channels.extend(route_through(channel, &rx_engines));
+        }
+        Ok(tx_engines)
+    }
+
+    /// Create a `Control` instance. `config_path` is where `config` was loaded from, it is
+    /// read again on `ReloadConfig` requests.
+    pub fn new(
+        runtime_manager: Arc<RuntimeManager>,
+        config: Config,
+        config_path: PathBuf,
+        log_filter: LogFilterHandle,
+    ) -> Self {
         let config_clone = config.clone();
 
         // Create phoenix working directory if not existing
@@ -318,7 +508,34 @@ impl Control {
                 .expect("failed to load preset addons");
         }
 
+        set_restart_policies(&plugins, &runtime_manager, &config.modules);
+        set_restart_policies(&plugins, &runtime_manager, &config.addons);
+
         let upgrader = EngineUpgrader::new(Arc::clone(&runtime_manager), Arc::clone(&plugins));
+        let sweeper = Sweeper::new(&config.sweeper);
+        // take back the shared heaps of the clients before any of them registers again
+        let mut checkpointer = Checkpointer::new(&config.checkpoint, phoenix_prefix);
+        checkpointer.restore(&plugins);
+        let policy_watcher = PolicyWatcher::new(&config.policy_watch);
+        let autoscaler = Autoscaler::new(&config.runtime.autoscale);
+        let registry = Registry::new(&config.registry)
+            .unwrap_or_else(|e| panic!("Cannot start the registry: {}", e));
+        let access = AccessControl::new(&config.access, phoenix_prefix)
+            .unwrap_or_else(|e| panic!("Cannot set up the access control: {}", e));
+        let admission = Admission::new(&config.admission);
+
+        #[cfg(feature = "metrics")]
+        let metrics = config.metrics.enable.then(|| {
+            MetricsServer::bind(&config.metrics.listen).unwrap_or_else(|e| {
+                panic!("Cannot serve metrics at {:?}: {}", config.metrics.listen, e)
+            })
+        });
+        #[cfg(not(feature = "metrics"))]
+        if config.metrics.enable {
+            log::warn!("metrics.enable is ignored, phoenix is built without the metrics feature");
+        }
+        profiler::set_enabled(config.profiling.engine_phases);
+        events::set_enabled(config.profiling.engine_events);
         tracing::info!("Control plane initialized");
 
         let scheduling_override = config
@@ -334,7 +551,141 @@ impl Control {
             upgrader,
             scheduling_override,
             config: config_clone,
+            config_path,
+            log_filter,
+            sweeper,
+            checkpointer,
+            policy_watcher,
+            autoscaler,
+            registry,
+            access,
+            admission,
+            #[cfg(feature = "metrics")]
+            metrics,
+        }
+    }
+
+    /// Reload the config file, then upgrade the modules and addons whose descriptor or
+    /// config has changed, and apply the new `runtime.batch_poll_interval`. Other fields of the
+    /// config only take effect after a restart.
+    fn reload_config(&mut self, request: ReloadRequest) -> anyhow::Result<()> {
+        let new_config = Config::from_path(&self.config_path)?;
+
+        let mut modules = Vec::new();
+        for module in new_config.modules.iter() {
+            if self.plugin_changed(module)? {
+                modules.push(module.clone());
+            }
+        }
+        let mut addons = Vec::new();
+        for addon in new_config.addons.iter() {
+            if self.plugin_changed(addon)? {
+                addons.push(addon.clone());
+            }
+        }
+
+        if !modules.is_empty() {
+            log::info!(
+                "Reloading modules: {:?}",
+                modules.iter().map(|x| &x.name).collect::<Vec<_>>()
+            );
+            let engines_to_upgrade = self.plugins.load_or_upgrade_modules(&modules)?;
+            self.upgrader.upgrade(
+                engines_to_upgrade,
+                request.flush,
+                request.detach_subscription,
+            )?;
         }
+        for addon in &addons {
+            log::info!("Reloading addon: {}", addon.name);
+            self.plugins.load_or_upgrade_addon(addon)?;
+        }
+        if new_config.runtime.batch_poll_interval != self.config.runtime.batch_poll_interval {
+            log::info!(
+                "Set batch_poll_interval to {}",
+                new_config.runtime.batch_poll_interval
+            );
+            self.runtime_manager
+                .set_batch_poll_interval(new_config.runtime.batch_poll_interval);
+            self.config.runtime.batch_poll_interval = new_config.runtime.batch_poll_interval;
+        }
+        if new_config.profiling.engine_phases != self.config.profiling.engine_phases {
+            log::info!(
+                "Set profiling.engine_phases to {}",
+                new_config.profiling.engine_phases
+            );
+            profiler::set_enabled(new_config.profiling.engine_phases);
+            self.config.profiling.engine_phases = new_config.profiling.engine_phases;
+        }
+        if new_config.profiling.engine_events != self.config.profiling.engine_events {
+            log::info!(
+                "Set profiling.engine_events to {}",
+                new_config.profiling.engine_events
+            );
+            events::set_enabled(new_config.profiling.engine_events);
+            self.config.profiling.engine_events = new_config.profiling.engine_events;
+        }
+        // restart policies are applied without upgrading the plugins
+        set_restart_policies(&self.plugins, &self.runtime_manager, &new_config.modules);
+        set_restart_policies(&self.plugins, &self.runtime_manager, &new_config.addons);
+        for desc in self
+            .config
+            .modules
+            .iter_mut()
+            .chain(&mut self.config.addons)
+        {
+            if let Some(new) = new_config.find_plugin(&desc.name) {
+                desc.restart = new.restart;
+            }
+        }
+
+        for desc in modules.into_iter() {
+            match self
+                .config
+                .modules
+                .iter_mut()
+                .rev()
+                .find(|x| x.name == desc.name)
+            {
+                Some(old) => *old = desc,
+                None => self.config.modules.push(desc),
+            }
+        }
+        for desc in addons.into_iter() {
+            match self
+                .config
+                .addons
+                .iter_mut()
+                .rev()
+                .find(|x| x.name == desc.name)
+            {
+                Some(old) => *old = desc,
+                None => self.config.addons.push(desc),
+            }
+        }
+        tracing::info!("Config reloaded from {:?}", self.config_path);
+        Ok(())
+    }
+
+    /// Whether the library or the config of a plugin differs from the loaded one.
+    fn plugin_changed(&self, new: &PluginDescriptor) -> anyhow::Result<bool> {
+        let old = match self.config.find_plugin(&new.name) {
+            Some(old) => old,
+            None => {
+                log::warn!(
+                    "plugin {} is not loaded, use upgrade to load new plugins",
+                    new.name
+                );
+                return Ok(false);
+            }
+        };
+        let new_string = Plugin::load_config(new.config_path.as_ref(), new.config_string.as_ref())?;
+        let old_string = Plugin::load_config(old.config_path.as_ref(), old.config_string.as_ref())?;
+        Ok(
+            new.lib_path != old.lib_path
+                || new.dep_path != old.dep_path
+                || new_string != old_string,
+        )
     }
 
     pub fn mainloop(&mut self, exit_flag: &AtomicBool) -> anyhow::Result<()> {
@@ -364,11 +715,88 @@ impl Control {
                     log::warn!("recv failed: {:?}", e)
                 }
             }
+            self.sweeper.poll(&self.runtime_manager, &self.plugins);
+            self.checkpointer.poll(&self.runtime_manager, &self.plugins);
+            self.policy_watcher
+                .poll(&self.config.addons, &self.runtime_manager, &self.plugins);
+            self.autoscaler
+                .poll(&self.runtime_manager, &mut self.upgrader);
+            self.registry.poll();
+            events::poll();
+            dump::poll(&self.runtime_manager, &self.config.control.prefix);
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                metrics.poll(|| self.sweeper.stats(&self.runtime_manager, &self.plugins));
+            }
         }
+        self.checkpointer
+            .checkpoint(&self.runtime_manager, &self.plugins);
         log::info!("exiting...");
         Ok(())
     }
 
+    fn new_client(
+        &mut self,
+        sender: &SocketAddr,
+        cred: &UCred,
+        hint: SchedulingHint,
+        service_name: String,
+        config_str: Option<String>,
+        protocol: ProtocolVersion,
+    ) -> anyhow::Result<()> {
+        let client_path = sender
+            .as_pathname()
+            .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+        let service = unsafe { transmute_service_from_str(service_name.as_str()) };
+        let service = *self
+            .plugins
+            .service_registry
+            .get(&service)
+            .ok_or_else(|| anyhow!("Service {:?} not found, requested by {:?}", service, sender))?
+            .key();
+        let pid = Pid::from_raw(cred.pid.unwrap());
+        if let Err(rejection) = self.admission.check(pid, &self.runtime_manager) {
+            tracing::warn!(
+                target: "audit",
+                pid = pid.as_raw(),
+                uid = cred.uid,
+                service = %service_name,
+                reason = %rejection,
+                "client rejected"
+            );
+            // tell the client instead of leaving it waiting for the engine
+            let response = Response(Err(phoenix_api::Error::Rejected(rejection.clone())));
+            self.sock
+                .send_to(&bincode::serialize(&response)?, client_path)?;
+            return Err(rejection.into());
+        }
+        let desired_mode = hint.mode;
+        let mode_override = self
+            .scheduling_override
+            .get(&service_name)
+            .copied()
+            .unwrap_or(desired_mode);
+        self.create_service(
+            service,
+            client_path,
+            mode_override,
+            hint,
+            cred,
+            config_str,
+            protocol,
+        )
+    }
+
+    /// Tells a client the request fails, instead of leaving it waiting for the response.
+    fn reply_error(&self, sender: &SocketAddr, e: &dyn std::fmt::Display) -> anyhow::Result<()> {
+        if let Some(client_path) = sender.as_pathname() {
+            let response = Response(Err(phoenix_api::Error::Generic(e.to_string())));
+            self.sock
+                .send_to(&bincode::serialize(&response)?, client_path)?;
+        }
+        Ok(())
+    }
+
     fn dispatch(
         &mut self,
         buf: &mut [u8],
@@ -376,29 +804,36 @@ impl Control {
         cred: &UCred,
     ) -> anyhow::Result<()> {
         use ipc::control;
-        let msg: control::Request = bincode::deserialize(buf).unwrap();
+        if let Err(e) = self.access.check(cred) {
+            self.reply_error(sender, &e)?;
+            return Err(e.into());
+        }
+        // a request from a client of another build may not decode, do not crash the daemon for it
+        let msg: control::Request = match bincode::deserialize(buf) {
+            Ok(msg) => msg,
+            Err(e) => {
+                let e = anyhow!("undecodable request, the client may speak another protocol: {e}");
+                self.reply_error(sender, &e)?;
+                return Err(e);
+            }
+        };
         match msg {
-            control::Request::NewClient(hint, service_name, config_str) => {
-                let client_path = sender
-                    .as_pathname()
-                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
-                let service = unsafe { transmute_service_from_str(service_name.as_str()) };
-                let service = *self
-                    .plugins
-                    .service_registry
-                    .get(&service)
-                    .ok_or_else(|| {
-                        anyhow!("Service {:?} not found, requested by {:?}", service, sender)
-                    })?
-                    .key();
-                let desired_mode = hint.mode;
-                let mode_override = self
-                    .scheduling_override
-                    .get(&service_name)
-                    .copied()
-                    .unwrap_or(desired_mode);
-                self.create_service(service, client_path, mode_override, hint, cred, config_str)?;
-                Ok(())
+            control::Request::NewClient(..) => {
+                // the legacy request of the untagged protocol
+                let e = ipc::Error::ProtocolVersion(PROTOCOL_V1, PROTOCOL_VERSION);
+                self.reply_error(sender, &e)?;
+                Err(e.into())
+            }
+            control::Request::NewVersionedClient(version, hint, service_name, config_str) => {
+                match ipc::version::negotiate(version) {
+                    Ok(protocol) => {
+                        self.new_client(sender, cred, hint, service_name, config_str, protocol)
+                    }
+                    Err(e) => {
+                        self.reply_error(sender, &e)?;
+                        Err(e.into())
+                    }
+                }
             }
             control::Request::EngineRequest(eid, request) => {
                 log::info!("Receive engine request");
@@ -426,6 +861,11 @@ impl Control {
                             request.flush,
                             request.detach_subscription,
                         )?;
+                        set_restart_policies(
+                            &self.plugins,
+                            &self.runtime_manager,
+                            &request.plugins,
+                        );
 
                         self.config.modules.append(&mut request.plugins);
                     }
@@ -433,10 +873,114 @@ impl Control {
                         for addon in &request.plugins {
                             self.plugins.load_or_upgrade_addon(addon)?;
                         }
+                        set_restart_policies(
+                            &self.plugins,
+                            &self.runtime_manager,
+                            &request.plugins,
+                        );
                     }
                 }
                 Ok(())
             }
+            control::Request::ReloadConfig(request) => {
+                log::info!("Receive reload config request: {:?}", request);
+                self.reload_config(request)
+            }
+            control::Request::LogFilter(filter) => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+
+                let result = match filter {
+                    Some(filter) => {
+                        log::info!("Set log filter to {:?}", filter);
+                        self.log_filter.set(&filter)
+                    }
+                    None => Ok(()),
+                };
+                let response = match result {
+                    Ok(()) => Response(Ok(ResponseKind::LogFilter(self.log_filter.current()))),
+                    Err(e) => Response(Err(phoenix_api::Error::Generic(e.to_string()))),
+                };
+                let mut buf = bincode::serialize(&response)?;
+                let nbytes = self.sock.send_to(buf.as_mut_slice(), client_path)?;
+                assert_eq!(
+                    nbytes,
+                    buf.len(),
+                    "expect to send {} bytes, but only {} was sent",
+                    buf.len(),
+                    nbytes
+                );
+                Ok(())
+            }
+            control::Request::BatchPollInterval(interval) => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+
+                let response = match interval {
+                    Some(0) => Response(Err(phoenix_api::Error::Generic(
+                        "batch_poll_interval must be positive".to_owned(),
+                    ))),
+                    Some(interval) => {
+                        log::info!("Set batch_poll_interval to {}", interval);
+                        self.runtime_manager.set_batch_poll_interval(interval);
+                        self.config.runtime.batch_poll_interval = interval;
+                        Response(Ok(ResponseKind::BatchPollInterval(interval)))
+                    }
+                    None => Response(Ok(ResponseKind::BatchPollInterval(
+                        self.runtime_manager.batch_poll_interval(),
+                    ))),
+                };
+                let mut buf = bincode::serialize(&response)?;
+                let nbytes = self.sock.send_to(buf.as_mut_slice(), client_path)?;
+                assert_eq!(
+                    nbytes,
+                    buf.len(),
+                    "expect to send {} bytes, but only {} was sent",
+                    buf.len(),
+                    nbytes
+                );
+                Ok(())
+            }
+            control::Request::Stats => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+
+                let stats = self.sweeper.stats(&self.runtime_manager, &self.plugins);
+                let response = Response(Ok(ResponseKind::Stats(stats)));
+                let mut buf = bincode::serialize(&response)?;
+                let nbytes = self.sock.send_to(buf.as_mut_slice(), client_path)?;
+                assert_eq!(
+                    nbytes,
+                    buf.len(),
+                    "expect to send {} bytes, but only {} was sent",
+                    buf.len(),
+                    nbytes
+                );
+                Ok(())
+            }
+            control::Request::DataPathGraph(request) => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+
+                let pid = request.pid.map(Pid::from_raw);
+                let sid = request.sid.map(SubscriptionId);
+                let graphs = self.runtime_manager.datapath_graphs(pid, sid);
+                let response = Response(Ok(ResponseKind::DataPathGraph(graphs)));
+                let mut buf = bincode::serialize(&response)?;
+                let nbytes = self.sock.send_to(buf.as_mut_slice(), client_path)?;
+                assert_eq!(
+                    nbytes,
+                    buf.len(),
+                    "expect to send {} bytes, but only {} was sent",
+                    buf.len(),
+                    nbytes
+                );
+                Ok(())
+            }
             control::Request::ListSubscription => {
                 let client_path = sender
                     .as_pathname()
@@ -559,6 +1103,111 @@ impl Control {
                 )?;
                 Ok(())
             }
+            control::Request::SetAffinity(request) => {
+                log::info!("Receive set affinity request: {:?}", request);
+                let pid = Pid::from_raw(request.pid);
+                let sid = request.sid.map(SubscriptionId);
+                if !self
+                    .runtime_manager
+                    .engine_subscriptions
+                    .iter()
+                    .any(|e| e.pid == pid && sid.map_or(true, |sid| e.sid == sid))
+                {
+                    bail!(
+                        "no engines found for client (pid={:?}, sid={:?})",
+                        pid,
+                        request.sid
+                    );
+                }
+                let hint = SchedulingHint {
+                    mode: SchedulingMode::default(),
+                    numa_node_affinity: request.numa_node,
+                    cpu_affinity: request.cpus,
+                };
+                let cores = CoreMask::from_hint(&hint);
+                if cores.to_cpu_set().is_empty() {
+                    bail!("none of the cores {:?} is available", request.cpus);
+                }
+                self.upgrader.migrate(pid, sid, cores)?;
+                Ok(())
+            }
+            control::Request::RegisterEndpoint(endpoint) => {
+                let pid = Pid::from_raw(cred.pid.unwrap());
+                self.registry.register(endpoint, pid);
+                Ok(())
+            }
+            control::Request::UnregisterEndpoint(endpoint) => {
+                let pid = Pid::from_raw(cred.pid.unwrap());
+                self.registry.unregister(endpoint, pid);
+                Ok(())
+            }
+            control::Request::LookupEndpoint(service) => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+
+                let endpoints = self.registry.lookup(&service);
+                let response = Response(Ok(ResponseKind::LookupEndpoint(endpoints)));
+                let mut buf = bincode::serialize(&response)?;
+                let nbytes = self.sock.send_to(buf.as_mut_slice(), client_path)?;
+                assert_eq!(
+                    nbytes,
+                    buf.len(),
+                    "expect to send {} bytes, but only {} was sent",
+                    buf.len(),
+                    nbytes
+                );
+                Ok(())
+            }
+            control::Request::Capabilities => {
+                let client_path = sender
+                    .as_pathname()
+                    .ok_or_else(|| anyhow!("peer is unnamed, something is wrong"))?;
+
+                let response = Response(Ok(ResponseKind::Capabilities(self.capabilities())));
+                let mut buf = bincode::serialize(&response)?;
+                let nbytes = self.sock.send_to(buf.as_mut_slice(), client_path)?;
+                assert_eq!(
+                    nbytes,
+                    buf.len(),
+                    "expect to send {} bytes, but only {} was sent",
+                    buf.len(),
+                    nbytes
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the services, modules, addons and profiles served.
+    fn capabilities(&self) -> Capabilities {
+        let mut services: Vec<_> = self
+            .plugins
+            .service_registry
+            .iter()
+            .map(|s| s.key().0.to_owned())
+            .collect();
+        services.sort();
+        let modules = self
+            .plugins
+            .modules
+            .iter()
+            .map(|m| (m.key().clone(), m.value().capabilities()))
+            .collect();
+        let mut addon_engines: Vec<_> = self
+            .plugins
+            .engine_registry
+            .iter()
+            .filter(|e| matches!(e.value().0, PluginName::Addon(_)))
+            .map(|e| e.key().0.to_owned())
+            .collect();
+        addon_engines.sort();
+        Capabilities {
+            protocol_version: PROTOCOL_VERSION,
+            services,
+            modules,
+            addon_engines,
+            profiles: self.config.profiles.keys().cloned().collect(),
         }
     }
 
@@ -593,6 +1242,40 @@ impl Control {
     }
 }
 
+/// Returns the channels that take the messages of `channel` through `engines` in order. The
+/// engines receive and send the messages on their channel 0.
+fn route_through(channel: ChannelDescriptor, engines: &[EngineType]) -> Vec<ChannelDescriptor> {
+    let ChannelDescriptor(sender, receiver, sender_idx, receiver_idx) = channel;
+    let mut hops = Vec::with_capacity(engines.len() + 1);
+    let (mut prev, mut prev_idx) = (sender, sender_idx);
+    for engine in engines.iter().copied() {
+        hops.push(ChannelDescriptor(prev, engine, prev_idx, 0));
+        (prev, prev_idx) = (engine, 0);
+    }
+    hops.push(ChannelDescriptor(prev, receiver, prev_idx, receiver_idx));
+    hops
+}
+
+/// Registers the restart policies of the engines of the plugins.
+fn set_restart_policies(
+    plugins: &PluginManager,
+    runtime_manager: &RuntimeManager,
+    descriptors: &[PluginDescriptor],
+) {
+    for desc in descriptors {
+        if let Some(module) = plugins.modules.get(&desc.name) {
+            for engine in module.engines() {
+                runtime_manager.set_restart_policy(*engine, desc.restart);
+            }
+        }
+        if let Some(addon) = plugins.addons.get(&desc.name) {
+            for engine in addon.engines() {
+                runtime_manager.set_restart_policy(*engine, desc.restart);
+            }
+        }
+    }
+}
+
 unsafe fn transmute_engine_type_from_str(engine: &str) -> EngineType {
     let bytes = engine.as_bytes();
     let (ptr, len) = (bytes.as_ptr(), bytes.len());
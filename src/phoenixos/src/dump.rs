@@ -0,0 +1,54 @@
+//! The dumps of the engines to attach to bug reports.
+//!
+//! `phoenixos` asks for a dump on `SIGUSR1` (see [`request_dump`]). The control loop then has
+//! each runtime dump its engines (see [`Engine::dump`]), and writes the dumps to
+//! `<control.prefix>/dump-<pid>-<unix time>.txt`. The engines leave out the payloads of the
+//! messages, so the file can be shared.
+//!
+//! [`Engine::dump`]: phoenix_common::engine::Engine::dump
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use crate::log;
+use crate::runtime::manager::RuntimeManager;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks the daemon to dump its engines. It only sets a flag, so it can be called from a signal
+/// handler.
+pub fn request_dump() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Writes the dumps of the engines to a file in `dir`, if asked for.
+pub(crate) fn poll(runtime_manager: &RuntimeManager, dir: &Path) {
+    if !REQUESTED.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    match write_dump(runtime_manager, dir) {
+        Ok(path) => log::info!("engines dumped to {:?}", path),
+        Err(e) => log::warn!("failed to dump the engines: {}", e),
+    }
+}
+
+fn write_dump(runtime_manager: &RuntimeManager, dir: &Path) -> io::Result<PathBuf> {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let pid = process::id();
+    let path = dir.join(format!("dump-{}-{}.txt", pid, now));
+    let mut content = format!(
+        "phoenix {}, pid {}, unix time {}\n",
+        env!("CARGO_PKG_VERSION"),
+        pid,
+        now
+    );
+    content.push_str(&runtime_manager.dump_engines());
+    fs::write(&path, content)?;
+    Ok(path)
+}
@@ -0,0 +1,221 @@
+//! The rollups of the events recorded by the running engines.
+//!
+//! The event recorder of an engine (see [`Engine::events`]) is registered when the engine is
+//! submitted to a runtime, and enabled if `profiling.engine_events` is set. The control loop
+//! drains the rings of the recorders in every iteration, and keeps for each engine and event kind
+//! the count and a log2 histogram of the values in one-second slots, from which the rates and the
+//! percentiles over the last `WINDOW_SLOTS` seconds are computed for the stats. All of it happens
+//! on the control plane, the engines only push the events.
+//!
+//! [`Engine::events`]: phoenix_common::engine::Engine::events
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+
+use ipc::control::{EngineEvents, EventStats};
+use phoenix_common::engine::{EventKind, EventRecorder};
+
+use crate::runtime::manager::{EngineId, EngineInfo};
+
+/// The length of a slot of the window.
+const SLOT: Duration = Duration::from_secs(1);
+/// The number of slots of the window, including the current one.
+const WINDOW_SLOTS: u64 = 10;
+/// Bucket `i` counts the values in `[2^i, 2^(i+1))`.
+const NUM_BUCKETS: usize = 64;
+
+#[derive(Clone)]
+struct Slot {
+    // the number of the slot since `START`
+    id: u64,
+    count: u64,
+    max: u64,
+    buckets: [u64; NUM_BUCKETS],
+}
+
+impl Slot {
+    fn new(id: u64) -> Self {
+        Slot {
+            id,
+            count: 0,
+            max: 0,
+            buckets: [0; NUM_BUCKETS],
+        }
+    }
+}
+
+/// The events of a kind, the slots of the window are reused in turn.
+struct Rollup {
+    total: u64,
+    slots: Vec<Slot>,
+}
+
+impl Default for Rollup {
+    fn default() -> Self {
+        Rollup {
+            total: 0,
+            slots: vec![Slot::new(u64::MAX); WINDOW_SLOTS as usize],
+        }
+    }
+}
+
+impl Rollup {
+    fn record(&mut self, slot_id: u64, value: u64) {
+        let slot = &mut self.slots[(slot_id % WINDOW_SLOTS) as usize];
+        if slot.id != slot_id {
+            *slot = Slot::new(slot_id);
+        }
+        let bucket = 63 - value.max(1).leading_zeros() as usize;
+        slot.count += 1;
+        slot.max = slot.max.max(value);
+        slot.buckets[bucket] += 1;
+        self.total += 1;
+    }
+
+    fn snapshot(&self, kind: EventKind, slot_id: u64, window_secs: f64) -> EventStats {
+        let mut count = 0;
+        let mut max = 0;
+        let mut buckets = [0u64; NUM_BUCKETS];
+        for slot in self
+            .slots
+            .iter()
+            .filter(|slot| slot.id <= slot_id && slot_id - slot.id < WINDOW_SLOTS)
+        {
+            count += slot.count;
+            max = max.max(slot.max);
+            for (sum, n) in buckets.iter_mut().zip(slot.buckets.iter()) {
+                *sum += n;
+            }
+        }
+        EventStats {
+            kind: kind.name().to_owned(),
+            total: self.total,
+            window_count: count,
+            rate: count as f64 / window_secs,
+            p50: quantile(&buckets, count, 0.5).min(max),
+            p99: quantile(&buckets, count, 0.99).min(max),
+            max,
+        }
+    }
+}
+
+/// Returns the upper bound of the bucket the `q` quantile falls in.
+fn quantile(buckets: &[u64], count: u64, q: f64) -> u64 {
+    let target = ((count as f64) * q).ceil() as u64;
+    let mut seen = 0;
+    for (i, n) in buckets.iter().enumerate() {
+        seen += n;
+        if seen >= target {
+            return 1u64.checked_shl(i as u32 + 1).unwrap_or(u64::MAX);
+        }
+    }
+    u64::MAX
+}
+
+struct Entry {
+    info: EngineInfo,
+    recorder: EventRecorder,
+    // when the events of the engine started to be rolled up
+    since: Instant,
+    kinds: [Rollup; EventKind::ALL.len()],
+    overflows: u64,
+}
+
+impl Entry {
+    fn new(info: EngineInfo, recorder: EventRecorder) -> Self {
+        Entry {
+            info,
+            recorder,
+            since: Instant::now(),
+            kinds: Default::default(),
+            overflows: 0,
+        }
+    }
+}
+
+lazy_static! {
+    static ref RECORDERS: DashMap<EngineId, Entry> = DashMap::new();
+    static ref START: Instant = Instant::now();
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the current slot, and how long it has lasted.
+fn current_slot() -> (u64, Duration) {
+    let elapsed = START.elapsed();
+    let id = (elapsed.as_nanos() / SLOT.as_nanos()) as u64;
+    (id, elapsed - SLOT * id as u32)
+}
+
+/// Enables or disables the recorders of all the engines, including those started later. The
+/// rollups start over when they are enabled.
+pub(crate) fn set_enabled(enabled: bool) {
+    let was_enabled = ENABLED.swap(enabled, Ordering::Relaxed);
+    for mut entry in RECORDERS.iter_mut() {
+        entry.recorder.set_enabled(enabled);
+        if enabled && !was_enabled {
+            let (info, recorder) = (entry.info, entry.recorder.clone());
+            *entry = Entry::new(info, recorder);
+        }
+    }
+}
+
+pub(crate) fn register_engine(eid: EngineId, info: EngineInfo, recorder: Option<&EventRecorder>) {
+    if let Some(recorder) = recorder {
+        recorder.set_enabled(ENABLED.load(Ordering::Relaxed));
+        RECORDERS.insert(eid, Entry::new(info, recorder.clone()));
+    }
+}
+
+pub(crate) fn unregister_engine(eid: EngineId) {
+    RECORDERS.remove(&eid);
+}
+
+/// Drains the rings of the recorders into the rollups.
+pub(crate) fn poll() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let (slot_id, _) = current_slot();
+    for mut entry in RECORDERS.iter_mut() {
+        let entry = entry.value_mut();
+        let kinds = &mut entry.kinds;
+        entry
+            .recorder
+            .drain(|event| kinds[event.kind as usize].record(slot_id, event.value));
+        entry.overflows += entry.recorder.take_overflows();
+    }
+}
+
+/// Returns the rollups of the engines that have recorded any event, ordered by engine ID.
+pub(crate) fn stats() -> Vec<EngineEvents> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+    let (slot_id, into_slot) = current_slot();
+    let window = SLOT * (WINDOW_SLOTS as u32 - 1) + into_slot;
+    let mut stats: Vec<_> = RECORDERS
+        .iter()
+        .map(|entry| {
+            let window_secs = window.min(entry.since.elapsed()).as_secs_f64().max(1e-3);
+            EngineEvents {
+                eid: entry.key().0,
+                pid: entry.info.pid.as_raw(),
+                engine_type: entry.info.engine_type.0.to_owned(),
+                window_secs,
+                kinds: EventKind::ALL
+                    .iter()
+                    .map(|kind| (kind, &entry.kinds[*kind as usize]))
+                    .filter(|(_, rollup)| rollup.total > 0)
+                    .map(|(kind, rollup)| rollup.snapshot(*kind, slot_id, window_secs))
+                    .collect(),
+                overflows: entry.overflows,
+            }
+        })
+        .filter(|events| !events.kinds.is_empty() || events.overflows > 0)
+        .collect();
+    stats.sort_by_key(|events| events.eid);
+    stats
+}
@@ -0,0 +1,58 @@
+//! The Phoenix daemon. The `phoenixos` binary is a thin wrapper around [`run`], which also lets
+//! other processes host a daemon, e.g., the integration tests of the services.
+#![feature(peer_credentials_unix_socket)]
+#![feature(drain_filter)]
+#![feature(strict_provenance)]
+#![feature(int_roundings)]
+#![feature(local_key_cell_methods)]
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+pub use phoenix_common::tracing;
+pub use phoenix_common::tracing as log;
+
+pub(crate) mod access;
+pub(crate) mod admission;
+pub(crate) mod checkpoint;
+pub(crate) mod config;
+pub(crate) mod control;
+pub(crate) mod dump;
+pub(crate) mod events;
+pub(crate) mod linker;
+pub(crate) mod logging;
+#[cfg(feature = "metrics")]
+pub(crate) mod metrics;
+pub(crate) mod plugin;
+pub(crate) mod plugin_mgr;
+pub(crate) mod policy_watch;
+pub(crate) mod profiler;
+pub(crate) mod registry;
+pub(crate) mod runtime;
+pub(crate) mod sweeper;
+
+pub(crate) mod dependency;
+
+pub use config::{Config, ConfigError};
+pub use dump::request_dump;
+pub use logging::{init_log, init_test_log, LogFilterHandle};
+
+use control::Control;
+use runtime::manager::RuntimeManager;
+
+/// Runs the daemon until `exit_flag` is set. `config_path` is where `config` was loaded from,
+/// it is read again when the config is reloaded.
+pub fn run(
+    config: Config,
+    config_path: PathBuf,
+    log_filter: LogFilterHandle,
+    exit_flag: &AtomicBool,
+) -> anyhow::Result<()> {
+    // create runtime manager
+    let runtime_manager = Arc::new(RuntimeManager::new(&config));
+
+    // the Control now takes over
+    let mut control = Control::new(runtime_manager, config, config_path, log_filter);
+    control.mainloop(exit_flag)
+}
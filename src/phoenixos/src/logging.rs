@@ -3,8 +3,9 @@ use std::fmt;
 use ansi_term::Colour;
 use tracing_subscriber::filter::EnvFilter;
 use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields};
-use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::{FmtContext, FormattedFields};
 use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, Registry};
 
 use phoenix_common::tracing::{self, Event, Level, Subscriber};
 
@@ -78,18 +79,57 @@ where
             metadata.line().unwrap_or(0),
         )?;
 
+        // print the spans, e.g., the engine that emits the event
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                write!(writer, "{}", span.name())?;
+                let ext = span.extensions();
+                if let Some(fields) = ext.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, "{{{}}}", fields)?;
+                    }
+                }
+                write!(writer, ": ")?;
+            }
+        }
+
         ctx.field_format().format_fields(writer.by_ref(), event)?;
         writeln!(writer)
     }
 }
 
+/// A handle to change the filter of the log output at runtime.
+pub struct LogFilterHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilterHandle {
+    /// Replaces the log filter with `directives`, in the same syntax as `PHOENIX_LOG`, e.g.,
+    /// `info,[engine{ty=RpcAdapterEngine,pid=1234}]=debug`.
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let filter = EnvFilter::builder().parse(directives)?;
+        self.handle.reload(filter)?;
+        Ok(())
+    }
+
+    /// Returns the current log filter.
+    pub fn current(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+}
+
 pub fn init_log(
     config: &Config,
     ansi: bool,
-) -> Option<(
-    tracing_appender::non_blocking::WorkerGuard,
-    tracing_chrome::FlushGuard,
-)> {
+) -> (
+    LogFilterHandle,
+    Option<(
+        tracing_appender::non_blocking::WorkerGuard,
+        tracing_chrome::FlushGuard,
+    )>,
+) {
     use tracing_subscriber::prelude::*;
 
     const LOG_ENV: &str = "PHOENIX_LOG";
@@ -103,6 +143,7 @@ pub fn init_log(
         )
         .with_env_var(LOG_ENV)
         .from_env_lossy();
+    let (log_env_filter, handle) = reload::Layer::new(log_env_filter);
 
     let log_fmt_layer = tracing_subscriber::fmt::layer()
         .event_format(PhoenixFormatter { ansi })
@@ -110,7 +151,7 @@ pub fn init_log(
 
     let registry = tracing_subscriber::registry().with(log_fmt_layer);
 
-    if config.tracing.enable {
+    let guards = if config.tracing.enable {
         Some(init_tracing(
             &config.tracing.min_event_level,
             &config.tracing.max_event_level,
@@ -122,7 +163,34 @@ pub fn init_log(
         registry.init();
         tracing::info!("tracing-log initialized");
         None
-    }
+    };
+
+    (LogFilterHandle { handle }, guards)
+}
+
+/// Initializes the log for a daemon running in a test process. The messages are written with
+/// `print!`, so `cargo test` captures them. Unlike [`init_log`], it can be called more than once,
+/// the handles returned by the later calls have no effect.
+pub fn init_test_log(config: &Config) -> LogFilterHandle {
+    use tracing_subscriber::prelude::*;
+
+    let log_env_filter = EnvFilter::builder()
+        .with_default_directive(config.log_level.parse().expect("invalid default log level"))
+        .with_env_var("PHOENIX_LOG")
+        .from_env_lossy();
+    let (log_env_filter, handle) = reload::Layer::new(log_env_filter);
+
+    let log_fmt_layer = tracing_subscriber::fmt::layer()
+        .event_format(PhoenixFormatter { ansi: false })
+        .with_test_writer()
+        .with_filter(log_env_filter);
+
+    // another daemon of the process has initialized the log
+    let _ = tracing_subscriber::registry()
+        .with(log_fmt_layer)
+        .try_init();
+
+    LogFilterHandle { handle }
 }
 
 fn init_tracing<L>(
@@ -0,0 +1,307 @@
+//! Prometheus metrics of the daemon.
+//!
+//! The engines count their polls, work, faults, and restarts in a global registry. The
+//! control plane serves these counters, together with the daemon stats (see
+//! [`Sweeper::stats`]), in the Prometheus text format at `http://<metrics.listen>/metrics`.
+//! Scrapes are served by the control thread between control requests, so they never touch
+//! the runtimes.
+//!
+//! [`Sweeper::stats`]: crate::sweeper::Sweeper::stats
+use std::fmt::{self, Write as _};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+
+use ipc::control::DaemonStats;
+
+use crate::runtime::manager::{EngineId, EngineInfo};
+use crate::{log, tracing};
+
+/// The largest request header that is accepted.
+const MAX_REQUEST_SIZE: usize = 8192;
+const IO_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Counters of an engine, updated by the runtime that drives the engine.
+#[derive(Debug, Default)]
+pub(crate) struct EngineCounters {
+    pub(crate) polls: AtomicU64,
+    pub(crate) work: AtomicU64,
+    pub(crate) faults: AtomicU64,
+    pub(crate) restarts: AtomicU64,
+}
+
+impl EngineCounters {
+    /// Returns polls, work, faults, and restarts.
+    fn load(&self) -> [u64; 4] {
+        [
+            self.polls.load(Ordering::Relaxed),
+            self.work.load(Ordering::Relaxed),
+            self.faults.load(Ordering::Relaxed),
+            self.restarts.load(Ordering::Relaxed),
+        ]
+    }
+}
+
+struct EngineEntry {
+    info: EngineInfo,
+    counters: Arc<EngineCounters>,
+}
+
+#[derive(Default)]
+struct Registry {
+    engines: DashMap<EngineId, EngineEntry>,
+    started: AtomicU64,
+    stopped: AtomicU64,
+}
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::default();
+}
+
+/// Starts counting for the engine `eid`.
+pub(crate) fn register_engine(eid: EngineId, info: EngineInfo) {
+    let entry = EngineEntry {
+        info,
+        counters: Arc::new(EngineCounters::default()),
+    };
+    REGISTRY.engines.insert(eid, entry);
+    REGISTRY.started.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn unregister_engine(eid: EngineId) {
+    if REGISTRY.engines.remove(&eid).is_some() {
+        REGISTRY.stopped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the counters of the engine `eid`. The counters of an engine that is not registered
+/// are not exported.
+pub(crate) fn engine_counters(eid: EngineId) -> Arc<EngineCounters> {
+    REGISTRY
+        .engines
+        .get(&eid)
+        .map(|e| Arc::clone(&e.counters))
+        .unwrap_or_default()
+}
+
+/// Serves the `/metrics` endpoint.
+pub(crate) struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    pub(crate) fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        tracing::info!(
+            "Serving metrics at http://{}/metrics",
+            listener.local_addr()?
+        );
+        Ok(MetricsServer { listener })
+    }
+
+    /// Serves the pending scrapes, calling `stats` once for each of them.
+    pub(crate) fn poll<F: Fn() -> DaemonStats>(&self, stats: F) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    if let Err(e) = serve(stream, &stats) {
+                        log::debug!("Failed to serve metrics to {}: {}", peer, e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Metrics accept failed: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn serve<F: Fn() -> DaemonStats>(mut stream: TcpStream, stats: &F) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let mut request = Vec::with_capacity(1024);
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 || request.len() + n > MAX_REQUEST_SIZE {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(&stats())),
+        (Some("GET"), Some(_)) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Renders the metrics in the Prometheus text format.
+pub(crate) fn render(stats: &DaemonStats) -> String {
+    let mut out = String::new();
+    let mut w = Writer(&mut out);
+
+    w.header(
+        "phoenix_clients",
+        "gauge",
+        "Client processes known to the daemon.",
+    );
+    w.sample("phoenix_clients", &[], stats.clients);
+    w.header(
+        "phoenix_subscriptions",
+        "gauge",
+        "Active service subscriptions.",
+    );
+    w.sample("phoenix_subscriptions", &[], stats.subscriptions);
+    w.header("phoenix_engines", "gauge", "Running engines.");
+    w.sample("phoenix_engines", &[], stats.engines);
+    w.header(
+        "phoenix_engines_started_total",
+        "counter",
+        "Engines started.",
+    );
+    w.sample(
+        "phoenix_engines_started_total",
+        &[],
+        REGISTRY.started.load(Ordering::Relaxed),
+    );
+    w.header(
+        "phoenix_engines_stopped_total",
+        "counter",
+        "Engines shut down.",
+    );
+    w.sample(
+        "phoenix_engines_stopped_total",
+        &[],
+        REGISTRY.stopped.load(Ordering::Relaxed),
+    );
+
+    let mut engines: Vec<_> = REGISTRY
+        .engines
+        .iter()
+        .map(|e| (*e.key(), e.info, e.counters.load()))
+        .collect();
+    engines.sort_by_key(|(eid, ..)| eid.0);
+    let per_engine = [
+        ("phoenix_engine_polls_total", "Times the engine is polled."),
+        (
+            "phoenix_engine_work_total",
+            "Units of work done by the engine.",
+        ),
+        (
+            "phoenix_engine_faults_total",
+            "Errors and panics of the engine.",
+        ),
+        (
+            "phoenix_engine_restarts_total",
+            "Restarts of the engine after faults.",
+        ),
+    ];
+    for (i, (name, help)) in per_engine.into_iter().enumerate() {
+        w.header(name, "counter", help);
+        for (eid, info, counters) in engines.iter() {
+            w.sample(
+                name,
+                &[
+                    ("engine_id", &eid.0.to_string()),
+                    ("engine_type", info.engine_type.0),
+                    ("pid", &info.pid.to_string()),
+                    ("runtime", &info.rid.0.to_string()),
+                ],
+                counters[i],
+            );
+        }
+    }
+
+    w.header(
+        "phoenix_resources",
+        "gauge",
+        "Resources held by the modules for each client process.",
+    );
+    for resource in stats.resources.iter() {
+        for (kind, count) in resource.resources.iter() {
+            w.sample(
+                "phoenix_resources",
+                &[
+                    ("pid", &resource.pid.to_string()),
+                    ("module", &resource.module),
+                    ("kind", kind),
+                ],
+                count,
+            );
+        }
+    }
+
+    w.header(
+        "phoenix_leaked_processes_total",
+        "counter",
+        "Exited client processes that left resources behind.",
+    );
+    w.sample("phoenix_leaked_processes_total", &[], stats.leaks.processes);
+    w.header(
+        "phoenix_leaked_engines_total",
+        "counter",
+        "Engines shut down because their client process exited.",
+    );
+    w.sample("phoenix_leaked_engines_total", &[], stats.leaks.engines);
+    w.header(
+        "phoenix_leaked_resources_total",
+        "counter",
+        "Resources reclaimed from exited client processes.",
+    );
+    for (kind, count) in stats.leaks.resources.iter() {
+        w.sample("phoenix_leaked_resources_total", &[("kind", kind)], count);
+    }
+
+    out
+}
+
+struct Writer<'a>(&'a mut String);
+
+impl<'a> Writer<'a> {
+    fn header(&mut self, name: &str, ty: &str, help: &str) {
+        // Writing to a String never fails.
+        let _ = writeln!(self.0, "# HELP {} {}\n# TYPE {} {}", name, help, name, ty);
+    }
+
+    fn sample<V: fmt::Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        let _ = write!(self.0, "{}", name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(self.0, "{{{}}}", labels);
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,7 +1,8 @@
 use std::path::Path;
 
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, Context};
 
+use phoenix_common::abi::{AbiDescriptor, ABI_SYMBOL};
 use phoenix_common::{InitAddonFn, InitFnResult, InitModuleFn, PhoenixAddon, PhoenixModule};
 
 use crate::linker::LinkedModule;
@@ -24,6 +25,33 @@ impl Plugin {
         Self { linked, _old: None }
     }
 
+    /// Checks that a plugin just linked is built with the same ABI as phoenix, before anything of
+    /// it is called.
+    pub(crate) fn check_abi(linked: &LinkedModule) -> anyhow::Result<()> {
+        let path = linked.path().display();
+        let addr = linked.lookup_symbol_addr(ABI_SYMBOL).ok_or_else(|| {
+            anyhow!(
+                "{} does not export its ABI descriptor, rebuild it with \
+                 `phoenix_common::export_abi!()`",
+                path
+            )
+        })?;
+        // SAFETY: the symbol is exported by `export_abi!`, a descriptor of an older version
+        // has at least the magic and the version
+        let descriptor = unsafe { AbiDescriptor::read(addr) }
+            .ok_or_else(|| anyhow!("{} exports an unknown ABI descriptor", path))?;
+        let mismatches = AbiDescriptor::current().mismatches(&descriptor);
+        if !mismatches.is_empty() {
+            bail!(
+                "{} is built with a different ABI from phoenix, rebuild it with the same \
+                 phoenix_common and compiler (phoenix vs. plugin): {}",
+                path,
+                mismatches.join(", ")
+            );
+        }
+        Ok(())
+    }
+
     pub(crate) fn init_module(
         &self,
         config_string: Option<&str>,
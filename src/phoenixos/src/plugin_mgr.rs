@@ -100,6 +100,7 @@ impl PluginManager {
             let mut linker = self.rt_linker.lock().unwrap();
             linker.load_archive(lib_path, dep_path)?
         };
+        Plugin::check_abi(&linked)?;
 
         // Replace the old plugin or create the new plugin
         let new_plug = match self.plugins.remove(&addon) {
@@ -177,6 +178,7 @@ impl PluginManager {
                 let mut linker = self.rt_linker.lock().unwrap();
                 linker.load_archive(lib_path, dep_path)?
             };
+            Plugin::check_abi(&linked)?;
 
             // Replace the old plugin or create the new plugin
             let new_plug = match self.plugins.remove(&descriptor) {
@@ -0,0 +1,183 @@
+//! The policy watcher applies the changes to the config files of the addons as they are written,
+//! so that policies such as rate limits and ACL rules can be managed by editing or checking out
+//! their files.
+//!
+//! The `config_path` of each addon is checked periodically. A changed file is validated by the
+//! addon, which takes it for the engines created afterwards, and sent to the running engines of
+//! the addon if the addon can encode it as an engine request. A file failing validation is
+//! rejected as a whole, the addon and its engines keep the last good config. Each change, applied
+//! or rejected, is logged to the `audit` target.
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::ucred::UCred;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use nix::unistd::{Gid, Pid, Uid};
+
+use ipc::control::PluginDescriptor;
+
+use crate::config::PolicyWatchConfig;
+use crate::plugin::PluginName;
+use crate::plugin_mgr::PluginManager;
+use crate::runtime::RuntimeManager;
+use crate::{log, tracing};
+
+/// The config file of an addon, as last seen.
+struct Watched {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// The contents last applied, or found when the file is first seen
+    contents: String,
+}
+
+pub(crate) struct PolicyWatcher {
+    interval: Option<Duration>,
+    last_check: Instant,
+    /// Keyed by the name of the addon
+    files: HashMap<String, Watched>,
+}
+
+impl PolicyWatcher {
+    pub(crate) fn new(config: &PolicyWatchConfig) -> Self {
+        PolicyWatcher {
+            interval: config
+                .enable
+                .then(|| Duration::from_millis(config.interval_ms)),
+            last_check: Instant::now(),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Checks the config files of `addons` if the interval has elapsed since the last check.
+    pub(crate) fn poll(
+        &mut self,
+        addons: &[PluginDescriptor],
+        runtime_manager: &RuntimeManager,
+        plugins: &PluginManager,
+    ) {
+        match self.interval {
+            Some(interval) if self.last_check.elapsed() >= interval => {
+                self.check(addons, runtime_manager, plugins);
+                self.last_check = Instant::now();
+            }
+            _ => {}
+        }
+    }
+
+    fn check(
+        &mut self,
+        addons: &[PluginDescriptor],
+        runtime_manager: &RuntimeManager,
+        plugins: &PluginManager,
+    ) {
+        // forget the addons unloaded or no longer configured by a file
+        self.files.retain(|name, watched| {
+            addons.iter().any(|addon| {
+                &addon.name == name && addon.config_path.as_ref() == Some(&watched.path)
+            })
+        });
+
+        for addon in addons {
+            let Some(path) = addon.config_path.as_ref() else {
+                continue;
+            };
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+            let watched = match self.files.get_mut(&addon.name) {
+                Some(watched) if watched.modified == modified => continue,
+                Some(watched) => watched,
+                None => {
+                    // the addon has been loaded with the file as it is now
+                    let contents = fs::read_to_string(path).unwrap_or_default();
+                    self.files.insert(
+                        addon.name.clone(),
+                        Watched {
+                            path: path.clone(),
+                            modified,
+                            contents,
+                        },
+                    );
+                    continue;
+                }
+            };
+            watched.modified = modified;
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) if contents != watched.contents => contents,
+                Ok(_) => continue,
+                Err(e) => {
+                    // e.g., the file is being replaced
+                    log::debug!("Cannot read {:?} of addon {}: {}", path, addon.name, e);
+                    watched.modified = None;
+                    continue;
+                }
+            };
+            match apply(&addon.name, &contents, runtime_manager, plugins) {
+                Ok(engines) => {
+                    tracing::info!(
+                        target: "audit",
+                        addon = %addon.name,
+                        path = ?path,
+                        engines,
+                        config = %contents.trim(),
+                        "policy config applied"
+                    );
+                    watched.contents = contents;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        target: "audit",
+                        addon = %addon.name,
+                        path = ?path,
+                        error = %e,
+                        "policy config rejected"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Applies `config` to the addon `name` and its running engines, and returns the number of
+/// engines it is sent to.
+fn apply(
+    name: &str,
+    config: &str,
+    runtime_manager: &RuntimeManager,
+    plugins: &PluginManager,
+) -> anyhow::Result<usize> {
+    let request = {
+        let mut addon = plugins
+            .addons
+            .get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("addon {} is not loaded", name))?;
+        addon.update_config(config)?;
+        addon.config_request()
+    };
+    let Some(request) = request else {
+        return Ok(0);
+    };
+
+    let engines: Vec<_> = runtime_manager
+        .engine_subscriptions
+        .iter()
+        .filter(|info| {
+            plugins.engine_registry.get(&info.engine_type).map_or(
+                false,
+                |entry| matches!(&entry.0, PluginName::Addon(n) if n == name),
+            )
+        })
+        .map(|info| (*info.key(), info.rid))
+        .collect();
+    let cred = UCred {
+        pid: Some(Pid::this().as_raw()),
+        uid: Uid::current().as_raw(),
+        gid: Gid::current().as_raw(),
+    };
+    let guard = runtime_manager.inner.lock().unwrap();
+    for &(eid, rid) in &engines {
+        if let Some(runtime) = guard.runtimes.get(&rid) {
+            runtime.submit_engine_request(eid, request.clone(), cred);
+        }
+    }
+    Ok(engines.len())
+}
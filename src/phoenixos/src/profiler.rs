@@ -0,0 +1,69 @@
+//! The phase profilers of the running engines.
+//!
+//! The profiler of an engine (see [`Engine::profiler`]) is registered when the engine is
+//! submitted to a runtime, and enabled if `profiling.engine_phases` is set. The control plane
+//! reads the histograms from here for the stats, so profiling never touches the runtimes.
+//!
+//! [`Engine::profiler`]: phoenix_common::engine::Engine::profiler
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+
+use ipc::control::EngineProfile;
+use phoenix_common::engine::Profiler;
+
+use crate::runtime::manager::{EngineId, EngineInfo};
+
+struct Entry {
+    info: EngineInfo,
+    profiler: Profiler,
+}
+
+lazy_static! {
+    static ref PROFILERS: DashMap<EngineId, Entry> = DashMap::new();
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables the profilers of all the engines, including those started later.
+pub(crate) fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    for entry in PROFILERS.iter() {
+        entry.profiler.set_enabled(enabled);
+    }
+}
+
+pub(crate) fn register_engine(eid: EngineId, info: EngineInfo, profiler: Option<&Profiler>) {
+    if let Some(profiler) = profiler {
+        profiler.set_enabled(ENABLED.load(Ordering::Relaxed));
+        let entry = Entry {
+            info,
+            profiler: profiler.clone(),
+        };
+        PROFILERS.insert(eid, entry);
+    }
+}
+
+pub(crate) fn unregister_engine(eid: EngineId) {
+    PROFILERS.remove(&eid);
+}
+
+/// Returns the profiles of the engines that have recorded any phase, ordered by engine ID.
+pub(crate) fn profiles() -> Vec<EngineProfile> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+    let mut profiles: Vec<_> = PROFILERS
+        .iter()
+        .map(|entry| EngineProfile {
+            eid: entry.key().0,
+            pid: entry.info.pid.as_raw(),
+            engine_type: entry.info.engine_type.0.to_owned(),
+            phases: entry.profiler.snapshot(),
+        })
+        .filter(|profile| !profile.phases.is_empty())
+        .collect();
+    profiles.sort_by_key(|profile| profile.eid);
+    profiles
+}
@@ -0,0 +1,342 @@
+//! The name service of the daemon.
+//!
+//! Servers register the endpoints of their services when they add them, and clients look the
+//! endpoints up by the service name. A local endpoint is kept as long as the process that
+//! registered it is alive.
+//!
+//! With `registry.listen` set, the daemon announces its local endpoints to `registry.peers` over
+//! UDP every `announce_interval_ms`, and learns theirs in turn. An announcement carries all the
+//! local endpoints of the sender and replaces what was known from it, so an endpoint removed at
+//! a peer is gone after its next announcement, and the endpoints of a peer that stops announcing
+//! expire after `ttl_ms`. Endpoints are not relayed, so each daemon should list all the others
+//! as its peers. The announcements are only accepted from the addresses of `registry.peers`,
+//! which must be the `listen` addresses of the peers, and those from any other source are
+//! dropped.
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use ipc::control::ServiceEndpoint;
+use phoenix_common::state_mgr::is_process_alive;
+
+use crate::config::RegistryConfig;
+use crate::log;
+
+/// The largest payload of a UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Announcement {
+    endpoints: Vec<ServiceEndpoint>,
+}
+
+struct Federation {
+    sock: UdpSocket,
+    peers: Vec<SocketAddr>,
+    buf: Vec<u8>,
+}
+
+impl Federation {
+    fn announce<'a>(&self, endpoints: impl Iterator<Item = &'a ServiceEndpoint>) {
+        let announcement = Announcement {
+            endpoints: endpoints.cloned().collect(),
+        };
+        let buf = bincode::serialize(&announcement).expect("serialize announcement");
+        if buf.len() > MAX_DATAGRAM_SIZE {
+            log::warn!(
+                "{} endpoints do not fit in an announcement, not announced",
+                announcement.endpoints.len()
+            );
+            return;
+        }
+        for peer in &self.peers {
+            if let Err(e) = self.sock.send_to(&buf, peer) {
+                log::debug!("Failed to announce endpoints to {}: {}", peer, e);
+            }
+        }
+    }
+}
+
+pub(crate) struct Registry {
+    advertise_host: String,
+    interval: Duration,
+    ttl: Duration,
+    last_announce: Instant,
+    /// Endpoints registered by the processes on this host
+    local: HashMap<ServiceEndpoint, Pid>,
+    /// Endpoints announced by each peer, and when they were announced
+    remote: HashMap<SocketAddr, (Vec<ServiceEndpoint>, Instant)>,
+    federation: Option<Federation>,
+}
+
+impl Registry {
+    pub(crate) fn new(config: &RegistryConfig) -> io::Result<Self> {
+        let advertise_host = match &config.advertise_host {
+            Some(host) => host.clone(),
+            None => hostname()?,
+        };
+
+        let federation = match &config.listen {
+            Some(listen) => {
+                let sock = UdpSocket::bind(listen)?;
+                sock.set_nonblocking(true)?;
+                let mut peers = Vec::with_capacity(config.peers.len());
+                for peer in &config.peers {
+                    let addr = peer.to_socket_addrs()?.next().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, format!("{peer} not resolved"))
+                    })?;
+                    peers.push(addr);
+                }
+                log::info!("Registry listening on {}, peers: {:?}", listen, peers);
+                Some(Federation {
+                    sock,
+                    peers,
+                    buf: vec![0u8; MAX_DATAGRAM_SIZE],
+                })
+            }
+            None => {
+                if !config.peers.is_empty() {
+                    log::warn!("registry.peers is ignored without registry.listen");
+                }
+                None
+            }
+        };
+
+        Ok(Registry {
+            advertise_host,
+            interval: Duration::from_millis(config.announce_interval_ms),
+            ttl: Duration::from_millis(config.ttl_ms),
+            last_announce: Instant::now(),
+            local: HashMap::new(),
+            remote: HashMap::new(),
+            federation,
+        })
+    }
+
+    /// Replaces an unspecified host with the advertised host.
+    fn resolve_host(&self, endpoint: &mut ServiceEndpoint) {
+        if matches!(endpoint.host.parse::<IpAddr>(), Ok(ip) if ip.is_unspecified()) {
+            endpoint.host = self.advertise_host.clone();
+        }
+    }
+
+    pub(crate) fn register(&mut self, mut endpoint: ServiceEndpoint, pid: Pid) {
+        self.resolve_host(&mut endpoint);
+        log::info!("Register {:?} of pid {}", endpoint, pid);
+        if self.local.insert(endpoint, pid).is_none() {
+            // let the peers know without waiting for the next announcement
+            if let Some(federation) = &self.federation {
+                federation.announce(self.local.keys());
+            }
+        }
+    }
+
+    /// Removes the endpoint if it is registered by `pid`.
+    pub(crate) fn unregister(&mut self, mut endpoint: ServiceEndpoint, pid: Pid) {
+        self.resolve_host(&mut endpoint);
+        if self.local.get(&endpoint) == Some(&pid) {
+            log::info!("Unregister {:?} of pid {}", endpoint, pid);
+            self.local.remove(&endpoint);
+            if let Some(federation) = &self.federation {
+                federation.announce(self.local.keys());
+            }
+        }
+    }
+
+    /// Returns the endpoints of `service`, the local ones first.
+    pub(crate) fn lookup(&self, service: &str) -> Vec<ServiceEndpoint> {
+        let mut local: Vec<_> = self
+            .local
+            .keys()
+            .filter(|e| e.service == service)
+            .cloned()
+            .collect();
+        local.sort_by(|a, b| (&a.host, a.port).cmp(&(&b.host, b.port)));
+
+        let remote = self
+            .remote
+            .values()
+            .filter(|(_, announced)| announced.elapsed() < self.ttl)
+            .flat_map(|(endpoints, _)| endpoints)
+            .filter(|e| e.service == service && !self.local.contains_key(e));
+        local.extend(remote.cloned());
+        local
+    }
+
+    /// Receives the announcements of the peers, and drops the endpoints of exited processes
+    /// and announces the rest if the interval has elapsed.
+    pub(crate) fn poll(&mut self) {
+        if let Some(federation) = &mut self.federation {
+            loop {
+                match federation.sock.recv_from(&mut federation.buf) {
+                    Ok((size, peer)) => {
+                        if !federation.peers.contains(&peer) {
+                            log::warn!("Dropped an announcement from {}, not a peer", peer);
+                            continue;
+                        }
+                        match bincode::deserialize::<Announcement>(&federation.buf[..size]) {
+                            Ok(announcement) => {
+                                self.remote
+                                    .insert(peer, (announcement.endpoints, Instant::now()));
+                            }
+                            Err(e) => log::warn!("Invalid announcement from {}: {}", peer, e),
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        log::warn!("Registry recv failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        if self.last_announce.elapsed() < self.interval {
+            return;
+        }
+        self.last_announce = Instant::now();
+        self.local.retain(|endpoint, pid| {
+            let alive = is_process_alive(*pid);
+            if !alive {
+                log::info!("Unregister {:?}, pid {} exited", endpoint, pid);
+            }
+            alive
+        });
+        let ttl = self.ttl;
+        self.remote
+            .retain(|_, (_, announced)| announced.elapsed() < ttl);
+        if let Some(federation) = &self.federation {
+            federation.announce(self.local.keys());
+        }
+    }
+}
+
+fn hostname() -> io::Result<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the length passed is the length of the buffer
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(service: &str, host: &str, port: u16) -> ServiceEndpoint {
+        ServiceEndpoint {
+            service: service.to_owned(),
+            host: host.to_owned(),
+            port,
+            proto_hash: "hash".to_owned(),
+        }
+    }
+
+    fn local_config() -> RegistryConfig {
+        RegistryConfig {
+            advertise_host: Some("host0".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn announce(from: &UdpSocket, to: SocketAddr, endpoints: Vec<ServiceEndpoint>) {
+        let buf = bincode::serialize(&Announcement { endpoints }).unwrap();
+        from.send_to(&buf, to).unwrap();
+    }
+
+    /// Polls until the announcements sent over loopback are received.
+    fn poll_received(registry: &mut Registry) {
+        for _ in 0..100 {
+            registry.poll();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn register_lookup() {
+        let mut registry = Registry::new(&local_config()).unwrap();
+        let me = Pid::this();
+        registry.register(endpoint("svc", "0.0.0.0", 5000), me);
+        registry.register(endpoint("svc", "10.0.0.1", 4000), me);
+        registry.register(endpoint("other", "10.0.0.1", 4001), me);
+        assert_eq!(
+            registry.lookup("svc"),
+            vec![
+                endpoint("svc", "10.0.0.1", 4000),
+                endpoint("svc", "host0", 5000)
+            ]
+        );
+
+        // only the process that registered an endpoint unregisters it
+        registry.unregister(endpoint("svc", "10.0.0.1", 4000), Pid::from_raw(1));
+        assert_eq!(registry.lookup("svc").len(), 2);
+        registry.unregister(endpoint("svc", "10.0.0.1", 4000), me);
+        assert_eq!(registry.lookup("svc"), vec![endpoint("svc", "host0", 5000)]);
+        assert!(registry.lookup("none").is_empty());
+    }
+
+    #[test]
+    fn announcements_from_peers_only() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = RegistryConfig {
+            listen: Some("127.0.0.1:0".to_owned()),
+            peers: vec![peer.local_addr().unwrap().to_string()],
+            ..local_config()
+        };
+        let mut registry = Registry::new(&config).unwrap();
+        let addr = registry
+            .federation
+            .as_ref()
+            .unwrap()
+            .sock
+            .local_addr()
+            .unwrap();
+
+        announce(&stranger, addr, vec![endpoint("svc", "10.0.0.9", 9000)]);
+        announce(&peer, addr, vec![endpoint("svc", "10.0.0.2", 4000)]);
+        poll_received(&mut registry);
+        assert_eq!(
+            registry.lookup("svc"),
+            vec![endpoint("svc", "10.0.0.2", 4000)]
+        );
+
+        // an announcement replaces what was known from the peer
+        announce(&peer, addr, vec![endpoint("svc", "10.0.0.2", 4001)]);
+        poll_received(&mut registry);
+        assert_eq!(
+            registry.lookup("svc"),
+            vec![endpoint("svc", "10.0.0.2", 4001)]
+        );
+    }
+
+    #[test]
+    fn remote_endpoints_expire() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = RegistryConfig {
+            listen: Some("127.0.0.1:0".to_owned()),
+            peers: vec![peer.local_addr().unwrap().to_string()],
+            ttl_ms: 0,
+            ..local_config()
+        };
+        let mut registry = Registry::new(&config).unwrap();
+        let addr = registry
+            .federation
+            .as_ref()
+            .unwrap()
+            .sock
+            .local_addr()
+            .unwrap();
+
+        announce(&peer, addr, vec![endpoint("svc", "10.0.0.2", 4000)]);
+        poll_received(&mut registry);
+        assert!(registry.lookup("svc").is_empty());
+    }
+}
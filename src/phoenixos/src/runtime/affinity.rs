@@ -2,6 +2,8 @@ use std::fmt;
 
 pub(crate) use libnuma::masks::CpuMask;
 
+use phoenix_api::engine::{CpuSet, SchedulingHint};
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct CoreMask(CpuMask);
 
@@ -29,6 +31,40 @@ impl CoreMask {
         }
     }
 
+    /// Returns the cores that `hint` asks for. Cores that the daemon is not permitted to run on
+    /// are ignored.
+    pub(crate) fn from_hint(hint: &SchedulingHint) -> Self {
+        match hint.cpu_affinity {
+            Some(cpus) => Self::from_cpus(&cpus),
+            None => Self::from_numa_node(hint.numa_node_affinity),
+        }
+    }
+
+    pub(crate) fn from_cpus(cpus: &CpuSet) -> Self {
+        use libnuma::masks::indices::CpuIndex;
+        use libnuma::masks::Mask;
+        let all_cpus = CpuIndex::number_of_permitted_cpus();
+        let cpu_mask = CpuMask::allocate();
+        for i in cpus.iter().take_while(|i| *i < all_cpus) {
+            cpu_mask.set(CpuIndex::new(i as _));
+        }
+        CoreMask(cpu_mask)
+    }
+
+    /// Returns the cores in the mask.
+    pub(crate) fn to_cpu_set(&self) -> CpuSet {
+        use libnuma::masks::indices::CpuIndex;
+        use libnuma::masks::Mask;
+        let all_cpus = CpuIndex::number_of_permitted_cpus();
+        let mut cpus = CpuSet::new();
+        for i in 0..all_cpus {
+            if self.0.is_set(CpuIndex::new(i as _)) {
+                cpus.insert(i);
+            }
+        }
+        cpus
+    }
+
     pub(crate) fn sched_set_affinity_for_current_thread(&self) -> bool {
         self.0.sched_set_affinity_for_current_thread()
     }
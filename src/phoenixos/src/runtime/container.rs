@@ -1,11 +1,21 @@
 use std::future::Future;
 use std::os::unix::ucred::UCred;
 use std::pin::Pin;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures::future::BoxFuture;
 use semver::Version;
 
-use phoenix_common::engine::{Engine, EngineResult, EngineType};
+use ipc::control::RestartPolicy;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, EngineCounters};
+#[cfg(feature = "metrics")]
+use crate::runtime::manager::EngineId;
+use crate::tracing::{self, Instrument};
+use phoenix_common::engine::{Engine, EngineResult, EngineType, SchedulingClass};
 
 /// A container that bundles a `Box<dyn Engine>` and its `Future` object so that the caller of this
 /// type can use both the methods provided by the `Engine` trait and poll the future.
@@ -35,8 +45,24 @@ pub(crate) struct EngineContainer {
     /// The type of the engine.
     ty: EngineType,
 
+    /// The scheduling class of the engine.
+    class: SchedulingClass,
+
     /// The verion of the phoenix module that the engine belongs to.
     version: Version,
+
+    /// When the engine has been restarted after failures.
+    restarts: Vec<Instant>,
+
+    /// The engine has failed and is waiting to be shut down.
+    faulted: bool,
+
+    /// The work the engine may still do in its turns, accumulated while it has work.
+    deficit: usize,
+
+    /// The counters of the engine, fetched on the first poll.
+    #[cfg(feature = "metrics")]
+    counters: Option<Arc<EngineCounters>>,
 }
 
 /// Extending the future's lifetime from 'a to 'static.
@@ -52,6 +78,7 @@ unsafe fn extend_lifetime<'a>(
 
 impl EngineContainer {
     pub(crate) fn new(engine: Box<dyn Engine>, ty: EngineType, version: Version) -> Self {
+        let class = engine.scheduling_class();
         let mut pinned = Pin::new(engine);
         let future = {
             let fut = pinned.as_mut().activate();
@@ -68,9 +95,33 @@ impl EngineContainer {
             engine: pinned,
             version,
             ty,
+            class,
+            restarts: Vec::new(),
+            faulted: false,
+            deficit: 0,
+            #[cfg(feature = "metrics")]
+            counters: None,
         }
     }
 
+    /// Sets the span of the engine, which is entered whenever the future of the engine is polled.
+    /// The span identifies the engine in the log, and makes it possible to filter logs by engine,
+    /// e.g., `[engine{ty=RpcAdapterEngine,pid=1234}]=debug`. It is an info span so that it is
+    /// enabled, and can match such a filter, under the default level.
+    pub(crate) fn set_span(&mut self, eid: u64, pid: i32) {
+        let span = tracing::info_span!("engine", eid, pid, ty = self.ty.0);
+        let future = std::mem::replace(&mut self.future, Box::pin(futures::future::pending()));
+        self.future = Box::pin(future.instrument(span));
+    }
+
+    /// Returns the counters of the engine `eid`.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub(crate) fn counters(&mut self, eid: EngineId) -> &EngineCounters {
+        self.counters
+            .get_or_insert_with(|| metrics::engine_counters(eid))
+    }
+
     #[inline]
     pub(crate) fn future(&mut self) -> Pin<&mut dyn Future<Output = EngineResult>> {
         self.future.as_mut()
@@ -91,6 +142,11 @@ impl EngineContainer {
         self.ty
     }
 
+    #[inline]
+    pub(crate) fn scheduling_class(&self) -> SchedulingClass {
+        self.class
+    }
+
     #[inline]
     pub(crate) fn version(&self) -> Version {
         self.version.clone()
@@ -113,4 +169,68 @@ impl EngineContainer {
     pub(crate) fn flush(&mut self) -> anyhow::Result<usize> {
         self.engine.flush()
     }
+
+    /// Records a restart if `policy` allows restarting the engine after a failure.
+    pub(crate) fn try_restart(&mut self, policy: &RestartPolicy) -> bool {
+        if !policy.enable {
+            return false;
+        }
+        let now = Instant::now();
+        let window = Duration::from_secs(policy.window_secs);
+        self.restarts.retain(|t| now.duration_since(*t) < window);
+        if self.restarts.len() >= policy.max_restarts {
+            return false;
+        }
+        self.restarts.push(now);
+        true
+    }
+
+    /// Drops the future of the engine and creates a new one by activating the engine again.
+    ///
+    /// The states of the engine are kept as is, which may be inconsistent if the engine
+    /// panicked in the middle of an operation.
+    pub(crate) fn restart(&mut self) {
+        // Drop the old future before creating a new one from the same engine.
+        self.future = Box::pin(async { Ok(()) });
+        let fut = self.engine.as_mut().activate();
+        // SAFETY: see `EngineContainer::new`.
+        self.future = unsafe { extend_lifetime(fut) };
+    }
+
+    #[inline]
+    pub(crate) fn set_faulted(&mut self) {
+        self.faulted = true;
+    }
+
+    #[inline]
+    pub(crate) fn is_faulted(&self) -> bool {
+        self.faulted
+    }
+
+    /// Grants the engine a `quantum` of work for its turn, and returns its budget for the turn.
+    /// The work left over from the previous turns is carried, up to another quantum.
+    #[inline]
+    pub(crate) fn replenish(&mut self, quantum: usize) -> usize {
+        self.deficit = self
+            .deficit
+            .saturating_add(quantum)
+            .min(quantum.saturating_mul(2));
+        self.deficit
+    }
+
+    /// Charges the engine for the `nwork` it reports after its turn. An engine without work
+    /// carries nothing to its next turn.
+    #[inline]
+    pub(crate) fn charge(&mut self, nwork: usize) {
+        self.deficit = if nwork == 0 {
+            0
+        } else {
+            self.deficit.saturating_sub(nwork)
+        };
+    }
+
+    /// Tells the engine that it is shut down because of a failure in its service subscription.
+    pub(crate) fn on_fault(&mut self, reason: &str) {
+        self.engine.on_fault(reason)
+    }
 }
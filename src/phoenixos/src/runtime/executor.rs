@@ -1,9 +1,12 @@
+use std::any::Any;
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::os::unix::ucred::UCred;
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
-use std::sync::Weak;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Weak};
 use std::task::{Context, Poll};
 use std::thread;
 use std::time::Duration;
@@ -13,7 +16,8 @@ use minstant::Instant;
 use spin::Mutex;
 use thiserror::Error;
 
-use phoenix_common::engine::EngineResult;
+use phoenix_common::checkpoint::EngineCheckpoint;
+use phoenix_common::engine::{EngineResult, SchedulingClass, Vertex};
 
 use super::affinity::CoreMask;
 use super::group::GroupId;
@@ -49,6 +53,38 @@ pub(crate) enum SuspendResult {
     NotFound,
 }
 
+/// Why the runtime shuts down an engine out of band.
+#[derive(Debug, Clone)]
+pub(crate) enum ShutdownReason {
+    /// The service subscription has failed. The engine is notified with the reason.
+    Fault(String),
+    /// The client process has exited, so there is nobody to notify.
+    ClientExited,
+}
+
+impl std::fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShutdownReason::Fault(reason) => f.write_str(reason),
+            ShutdownReason::ClientExited => f.write_str("client process exited"),
+        }
+    }
+}
+
+/// The description and input queues of an engine, taken by the runtime that drives it.
+#[derive(Debug, Clone)]
+pub(crate) struct EngineSnapshot {
+    pub(crate) eid: EngineId,
+    pub(crate) description: String,
+    pub(crate) tx_inputs: Vec<usize>,
+    pub(crate) rx_inputs: Vec<usize>,
+    /// The dump of the state of the engine, if asked for, see `Engine::dump`.
+    pub(crate) dump: Option<String>,
+    /// What the engine saves in a checkpoint of the daemon, if asked for, see
+    /// `Engine::checkpoint`.
+    pub(crate) checkpoint: Option<EngineCheckpoint>,
+}
+
 enum RuntimeSubmission {
     NewGroup(SchedulingGroup),
     AttachToGroup(GroupId, Vec<(EngineId, EngineContainer)>),
@@ -73,6 +109,13 @@ pub(crate) struct Runtime {
     pub(crate) running: RefCell<Vec<RefCell<SchedulingGroup>>>,
     // number of active scheduling groups
     active_cnt: AtomicUsize,
+    // batch engines are polled once every `batch_poll_interval` iterations, shared by all runtimes
+    batch_poll_interval: Arc<AtomicU32>,
+    // the work granted to each engine in an iteration, 0 for unlimited
+    engine_quantum: usize,
+    // the number of iterations of the mainloop, and of those in which some engine did work
+    rounds: AtomicU64,
+    busy_rounds: AtomicU64,
 
     // Whether the engine is dedicated or shared.
     mode: AtomicU8,
@@ -93,16 +136,35 @@ pub(crate) struct Runtime {
     pub(crate) new_ctrl_request: AtomicBool,
     pub(crate) control_requests: Mutex<Vec<(EngineId, Vec<u8>, UCred)>>,
 
+    /// Engines to shut down because their service subscriptions have failed, and the reasons.
+    new_shutdown: AtomicBool,
+    shutdown_requests: Mutex<Vec<(EngineId, ShutdownReason)>>,
+
+    /// Senders waiting for the snapshots of the engines on this runtime.
+    new_inspect: AtomicBool,
+    // and whether to dump the engines and to checkpoint them
+    inspect_requests: Mutex<Vec<(mpsc::Sender<Vec<EngineSnapshot>>, bool, bool)>>,
+
     pub(crate) runtime_manager: Weak<RuntimeManager>,
 }
 
 impl Runtime {
-    pub(crate) fn new(id: RuntimeId, cores: CoreMask, rm: Weak<RuntimeManager>) -> Self {
+    pub(crate) fn new(
+        id: RuntimeId,
+        cores: CoreMask,
+        batch_poll_interval: Arc<AtomicU32>,
+        engine_quantum: usize,
+        rm: Weak<RuntimeManager>,
+    ) -> Self {
         Runtime {
             id,
             cores,
             running: RefCell::new(Vec::new()),
             active_cnt: AtomicUsize::new(0),
+            batch_poll_interval,
+            engine_quantum,
+            rounds: AtomicU64::new(0),
+            busy_rounds: AtomicU64::new(0),
 
             mode: AtomicU8::new(0),
             group_signature: AtomicU32::new(0),
@@ -117,10 +179,43 @@ impl Runtime {
             new_ctrl_request: AtomicBool::new(false),
             control_requests: Mutex::new(Vec::new()),
 
+            new_shutdown: AtomicBool::new(false),
+            shutdown_requests: Mutex::new(Vec::new()),
+
+            new_inspect: AtomicBool::new(false),
+            inspect_requests: Mutex::new(Vec::new()),
+
             runtime_manager: rm,
         }
     }
 
+    /// The cores that the runtime runs on.
+    #[inline]
+    pub(crate) fn cores(&self) -> &CoreMask {
+        &self.cores
+    }
+
+    /// The mode of the groups the runtime runs, `None` if it has never run any.
+    #[inline]
+    pub(crate) fn mode(&self) -> Option<RuntimeMode> {
+        match self.mode.load(Ordering::Relaxed) {
+            1 => Some(RuntimeMode::Compact),
+            2 => Some(RuntimeMode::Dedicated),
+            3 => Some(RuntimeMode::GroupShared),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of iterations of the runtime so far, and of those in which some engine
+    /// did work.
+    #[inline]
+    pub(crate) fn rounds(&self) -> (u64, u64) {
+        (
+            self.rounds.load(Ordering::Relaxed),
+            self.busy_rounds.load(Ordering::Relaxed),
+        )
+    }
+
     /// Returns true if there is no runnable engine or pending engine.
     #[inline]
     pub(crate) fn is_empty(&self) -> bool {
@@ -229,6 +324,57 @@ impl Runtime {
         self.new_suspend.store(true, Ordering::Release);
     }
 
+    /// Shut down an engine because its service subscription has failed or its client has exited.
+    pub(crate) fn request_shutdown(&self, eid: EngineId, reason: ShutdownReason) {
+        self.shutdown_requests.lock().push((eid, reason));
+        self.new_shutdown.store(true, Ordering::Release);
+    }
+
+    /// Asks for the snapshots of the engines on this runtime, which are sent to `tx` the next
+    /// time the runtime checks its requests. The engines are dumped as well if `dump` is set, and
+    /// checkpointed if `checkpoint` is set.
+    pub(crate) fn request_inspect(
+        &self,
+        tx: mpsc::Sender<Vec<EngineSnapshot>>,
+        dump: bool,
+        checkpoint: bool,
+    ) {
+        self.inspect_requests.lock().push((tx, dump, checkpoint));
+        self.new_inspect.store(true, Ordering::Release);
+    }
+
+    fn inspect_engines(&self) {
+        let requests: Vec<_> = self.inspect_requests.lock().drain(..).collect();
+        let dump = requests.iter().any(|(_, dump, _)| *dump);
+        let checkpoint = requests.iter().any(|(_, _, checkpoint)| *checkpoint);
+        let mut snapshots = Vec::new();
+        for group in self.running.borrow().iter() {
+            let mut group = group.borrow_mut();
+            for (eid, engine) in group.engines.iter_mut() {
+                let description = engine.engine().description();
+                let dumped = if dump { engine.engine().dump() } else { None };
+                let checkpointed = if checkpoint {
+                    engine.engine().checkpoint()
+                } else {
+                    None
+                };
+                let vertex = engine.engine_mut().get_mut();
+                snapshots.push(EngineSnapshot {
+                    eid: *eid,
+                    description,
+                    tx_inputs: vertex.tx_inputs().iter().map(|q| q.len()).collect(),
+                    rx_inputs: vertex.rx_inputs().iter().map(|q| q.len()).collect(),
+                    dump: dumped,
+                    checkpoint: checkpointed,
+                });
+            }
+        }
+        for (tx, _, _) in requests {
+            // the requester may have given up waiting
+            let _ = tx.send(snapshots.clone());
+        }
+    }
+
     #[inline]
     fn save_energy_or_shutdown(&self, last_event_ts: Instant) {
         // THRES:DURA = 20:1 will lose around 10% bandwidth which is unacceptable,
@@ -261,6 +407,66 @@ impl Runtime {
         }
     }
 
+    /// Restarts a failed engine if its restart policy allows, otherwise shuts down its
+    /// service subscription.
+    fn handle_fault(&self, eid: EngineId, engine: &mut EngineContainer, reason: String) {
+        let desc = engine.engine().description();
+        log::error!("Engine [{}] error: {}", desc, reason);
+
+        #[cfg(feature = "metrics")]
+        engine.counters(eid).faults.fetch_add(1, Ordering::Relaxed);
+
+        let rm = self.runtime_manager.upgrade().unwrap();
+        let policy = rm.restart_policy(engine.engine_type());
+        if engine.try_restart(&policy) {
+            log::warn!("Restarting engine [{}]", desc);
+            #[cfg(feature = "metrics")]
+            engine
+                .counters(eid)
+                .restarts
+                .fetch_add(1, Ordering::Relaxed);
+            engine.restart();
+        } else {
+            engine.set_faulted();
+            rm.shutdown_subscription(eid, ShutdownReason::Fault(reason));
+        }
+    }
+
+    /// Shuts down the engines in `shutdown_requests`, after notifying them of the failure.
+    fn shutdown_requested_engines(&self) {
+        let requests: HashMap<_, _> = self.shutdown_requests.lock().drain(..).collect();
+
+        let mut running = self.running.borrow_mut();
+        let mut engines = Vec::with_capacity(requests.len());
+        let mut emptied = Vec::new();
+        for (group_index, group) in running.iter_mut().enumerate() {
+            let group = group.get_mut();
+            let before = group.engines.len();
+            engines.extend(group.engines.drain_filter(|e| requests.contains_key(&e.0)));
+            if before > 0 && group.engines.is_empty() {
+                emptied.push(group_index);
+            }
+        }
+        for group_index in emptied.into_iter().rev() {
+            // All engines in the scheduling group has shutdown
+            self.active_cnt.fetch_sub(1, Ordering::Relaxed);
+            running.swap_remove(group_index);
+        }
+        drop(running);
+
+        let rm = self.runtime_manager.upgrade().unwrap();
+        for (eid, mut engine) in engines {
+            let desc = engine.engine().description().to_owned();
+            engine.engine_mut().set_els();
+            if let ShutdownReason::Fault(reason) = &requests[&eid] {
+                engine.on_fault(reason);
+            }
+            drop(engine);
+            log::info!("Engine [{}] shutdown, reason: {}", desc, requests[&eid]);
+            rm.register_engine_shutdown(eid);
+        }
+    }
+
     /// A spinning future executor.
     pub(crate) fn mainloop(&self) -> Result<(), Error> {
         let waker = futures::task::noop_waker();
@@ -270,30 +476,76 @@ impl Runtime {
 
         let mut last_event_ts = Instant::now();
 
+        let mut round: u64 = 0;
+
         loop {
             // TODO(cjr): if there's no active engine on this runtime, call `mwait` to put the CPU
             // into an optimized state. (the wakeup latency and whether it can be used in user mode
             // are two concerns)
             self.save_energy_or_shutdown(last_event_ts);
 
+            let interval = self.batch_poll_interval.load(Ordering::Relaxed).max(1);
+            let poll_batch = round % interval as u64 == 0;
+            round = round.wrapping_add(1);
+            let mut busy = false;
+
             // drive each engine
             for (group_index, group) in self.running.borrow().iter().enumerate() {
                 let mut group = group.borrow_mut();
 
-                for (engine_index, (_eid, engine)) in group.engines.iter_mut().enumerate() {
+                for (engine_index, (eid, engine)) in group.engines.iter_mut().enumerate() {
+                    if engine.is_faulted() {
+                        continue;
+                    }
+                    if !poll_batch && engine.scheduling_class() == SchedulingClass::Batch {
+                        continue;
+                    }
+
                     // Set engine's local storage here before poll
                     engine.engine_mut().set_els();
 
+                    // the engines take turns, each doing at most its budget of work
+                    if self.engine_quantum > 0 {
+                        let budget = engine.replenish(self.engine_quantum);
+                        engine.engine_mut().tracker().set_budget(budget);
+                    }
+
                     // bind to a variable first (otherwise engine is borrowed in the match expression)
-                    let ret = engine.future().poll(&mut cx);
+                    // a panic, including one in a timer callback, is caught so that it only
+                    // affects the service subscription of the engine rather than the whole runtime
+                    let ret = panic::catch_unwind(AssertUnwindSafe(|| {
+                        if let Some(timers) = engine.engine_mut().timers() {
+                            timers.tick(std::time::Instant::now());
+                        }
+                        engine.future().poll(&mut cx)
+                    }));
+                    let ret = match ret {
+                        Ok(ret) => ret,
+                        Err(payload) => {
+                            let reason = format!("panicked: {}", panic_message(&*payload));
+                            self.handle_fault(*eid, engine, reason);
+                            continue;
+                        }
+                    };
                     match ret {
                         Poll::Pending => {
                             let tracker = engine.engine_mut().tracker();
+                            let nwork = tracker.nwork();
                             // has_work += tracker.nwork();
-                            if tracker.nwork() > 0 {
+                            if nwork > 0 {
                                 last_event_ts = Instant::now();
+                                busy = true;
                             }
                             tracker.set_nwork(0);
+                            if self.engine_quantum > 0 {
+                                engine.charge(nwork);
+                            }
+                            #[cfg(feature = "metrics")]
+                            {
+                                let counters = engine.counters(*eid);
+                                counters.polls.fetch_add(1, Ordering::Relaxed);
+                                counters.work.fetch_add(nwork as u64, Ordering::Relaxed);
+                            }
                         }
                         Poll::Ready(EngineResult::Ok(())) => {
                             log::info!(
@@ -303,13 +555,17 @@ impl Runtime {
                             shutdown.push((group_index, engine_index));
                         }
                         Poll::Ready(EngineResult::Err(e)) => {
-                            log::error!("Engine [{}] error: {}", engine.engine().description(), e);
-                            shutdown.push((group_index, engine_index));
+                            self.handle_fault(*eid, engine, e.to_string());
                         }
                     }
                 }
             }
 
+            self.rounds.fetch_add(1, Ordering::Relaxed);
+            if busy {
+                self.busy_rounds.fetch_add(1, Ordering::Relaxed);
+            }
+
             // garbage collect every several rounds, maybe move to another thread.
             for (group_index, engine_index) in shutdown.drain(..).rev() {
                 let mut running = self.running.borrow_mut();
@@ -368,6 +624,17 @@ impl Runtime {
                 }
             }
 
+            if Ok(true)
+                == self.new_shutdown.compare_exchange(
+                    true,
+                    false,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+            {
+                self.shutdown_requested_engines();
+            }
+
             if Ok(true)
                 == self.new_suspend.compare_exchange(
                     true,
@@ -443,7 +710,28 @@ impl Runtime {
                 }
             }
 
+            if Ok(true)
+                == self.new_inspect.compare_exchange(
+                    true,
+                    false,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+            {
+                self.inspect_engines();
+            }
+
             // loop scope ends here
         }
     }
 }
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown panic payload"
+    }
+}
@@ -1,26 +1,32 @@
 //! Runtime manager is the control plane of runtimes. It is responsible for
 //! creating/destructing runtimes, map runtimes to cores, balance the work
 //! among different runtimes, and even dynamically scale out/down the runtimes.
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::Hash;
-use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU32, AtomicU64};
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crc32fast::Hasher as Crc32Hasher;
 use dashmap::DashMap;
 use nix::unistd::Pid;
 
+use ipc::control::{
+    ChannelDirection, GraphChannel, GraphEngine, GroupPlacement, RestartPolicy, SubscriptionGraph,
+};
 use phoenix_api::engine::{SchedulingHint, SchedulingMode};
+use phoenix_common::checkpoint::EngineCheckpoint;
 use phoenix_common::engine::EngineType;
 use phoenix_common::module::Service;
 use phoenix_common::storage::ResourceCollection;
 
 use super::affinity::CoreMask;
 use super::container::EngineContainer;
-use super::executor::{self, Runtime, RuntimeMode};
+use super::executor::{self, EngineSnapshot, Runtime, RuntimeMode, ShutdownReason};
 use super::graph::DataPathGraph;
 use super::group::GroupId;
 use super::SchedulingGroup;
@@ -55,6 +61,74 @@ pub(crate) struct EngineInfo {
     pub(crate) scheduling_mode: SchedulingMode,
 }
 
+/// How long to wait for the runtimes to take the snapshots of their engines.
+const INSPECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The snapshots of the engines the runtimes have sent so far, see
+/// [`RuntimeManager::request_inspect`].
+struct Inspection {
+    rx: mpsc::Receiver<Vec<EngineSnapshot>>,
+    // the number of runtimes that have not responded
+    waiting: usize,
+    deadline: Instant,
+    snapshots: HashMap<EngineId, EngineSnapshot>,
+}
+
+impl Inspection {
+    fn receive(&mut self, runtime_snapshots: Vec<EngineSnapshot>) {
+        self.waiting -= 1;
+        self.snapshots
+            .extend(runtime_snapshots.into_iter().map(|s| (s.eid, s)));
+    }
+
+    /// Takes the snapshots that have arrived. Returns true if all the runtimes have responded or
+    /// the time is up.
+    fn poll(&mut self) -> bool {
+        while self.waiting > 0 {
+            match self.rx.try_recv() {
+                Ok(runtime_snapshots) => self.receive(runtime_snapshots),
+                Err(mpsc::TryRecvError::Empty) => return Instant::now() >= self.deadline,
+                // a runtime has exited
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        }
+        true
+    }
+
+    /// Waits for the runtimes that have not responded until the time is up.
+    fn wait(&mut self) {
+        while self.waiting > 0 {
+            match self
+                .rx
+                .recv_timeout(self.deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(runtime_snapshots) => self.receive(runtime_snapshots),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// The checkpoints of the engines being taken, see [`RuntimeManager::request_checkpoints`].
+pub(crate) struct EngineCheckpoints {
+    engines: Vec<(EngineId, EngineInfo)>,
+    inspection: Inspection,
+}
+
+impl EngineCheckpoints {
+    /// Returns true if all the runtimes have responded or the time is up, without waiting.
+    #[inline]
+    pub(crate) fn poll(&mut self) -> bool {
+        self.inspection.poll()
+    }
+
+    /// Waits until all the runtimes have responded or the time is up.
+    #[inline]
+    pub(crate) fn wait(&mut self) {
+        self.inspection.wait()
+    }
+}
+
 pub(crate) struct ServiceSubscription {
     pub(crate) service: Service,
     pub(crate) addons: Vec<EngineType>,
@@ -85,6 +159,13 @@ impl GlobalResourceManager {
             self.resource.remove(&pid);
         }
     }
+
+    /// Removes the global resources of `pid` regardless of its active engine groups.
+    /// Returns the number of resources removed.
+    pub(crate) fn reclaim(&self, pid: Pid) -> usize {
+        self.active_cnt.remove(&pid);
+        self.resource.remove(&pid).map_or(0, |(_, r)| r.len())
+    }
 }
 
 pub struct RuntimeManager {
@@ -103,6 +184,23 @@ pub struct RuntimeManager {
     /// and the number of active engines in that group
     pub(crate) service_subscriptions: DashMap<(Pid, SubscriptionId), (ServiceSubscription, usize)>,
     pub(crate) global_resource_mgr: GlobalResourceManager,
+    /// Restart policies of engines, keyed by the name of the engine type
+    restart_policies: DashMap<String, RestartPolicy>,
+    /// How often the batch engines are polled, in iterations of their runtimes
+    batch_poll_interval: Arc<AtomicU32>,
+    /// The work granted to each engine in an iteration of its runtime, 0 for unlimited
+    engine_quantum: usize,
+}
+
+/// Where to schedule a scheduling group.
+#[derive(Debug, Clone, Copy)]
+enum Placement {
+    /// Any runtime that accepts the group
+    Any,
+    /// The given runtime
+    On(RuntimeId),
+    /// A new runtime
+    New,
 }
 
 pub struct Inner {
@@ -120,8 +218,24 @@ impl Inner {
         group: SchedulingGroup,
         rm: &Arc<RuntimeManager>,
         mode: SchedulingMode,
-        hint: SchedulingHint,
-    ) {
+        cores: CoreMask,
+    ) -> RuntimeId {
+        self.schedule_on(pid, sid, group, rm, mode, cores, Placement::Any)
+    }
+
+    /// Schedules the group on a runtime on `cores` as `target` asks, starting a new runtime if no
+    /// runtime asked for accepts the group.
+    #[allow(clippy::too_many_arguments)]
+    fn schedule_on(
+        &mut self,
+        pid: Pid,
+        sid: SubscriptionId,
+        group: SchedulingGroup,
+        rm: &Arc<RuntimeManager>,
+        mode: SchedulingMode,
+        cores: CoreMask,
+        target: Placement,
+    ) -> RuntimeId {
         let (runtime_mode, quota) = match mode {
             SchedulingMode::Dedicate => (RuntimeMode::Dedicated, None),
             SchedulingMode::Compact => (RuntimeMode::Compact, None),
@@ -129,15 +243,6 @@ impl Inner {
             SchedulingMode::Spread => unimplemented!(),
         };
 
-        // choose cores to schedule
-        let cores = CoreMask::from_numa_node(hint.numa_node_affinity);
-        log::debug!(
-            "group: {:?}, scheduling hint: {:?}, cores: {}",
-            group,
-            hint,
-            cores
-        );
-
         // calculate group signature
         let mut hasher = Crc32Hasher::new();
         let group_engines = group.engines.iter().map(|x| x.1.engine_type());
@@ -147,10 +252,21 @@ impl Inner {
         let group_signature = hasher.finalize();
 
         // find an available runtime
-        let rid = match self.runtimes.iter().find(|(_i, r)| {
-            r.try_acquire(runtime_mode, Some(group_signature), cores.clone(), quota)
-        }) {
-            Some((rid, _runtime)) => *rid,
+        let acquire =
+            |r: &Runtime| r.try_acquire(runtime_mode, Some(group_signature), cores.clone(), quota);
+        let found = match target {
+            Placement::Any => self
+                .runtimes
+                .iter()
+                .find(|(_i, r)| acquire(r))
+                .map(|(rid, _runtime)| *rid),
+            Placement::On(rid) => {
+                Some(rid).filter(|rid| self.runtimes.get(rid).map_or(false, |r| acquire(r)))
+            }
+            Placement::New => None,
+        };
+        let rid = match found {
+            Some(rid) => rid,
             None => self.start_runtime(cores, runtime_mode, Some(group_signature), Arc::clone(rm)),
         };
 
@@ -174,17 +290,22 @@ impl Inner {
             );
             let prev = rm.engine_subscriptions.insert(*eid, engine_info);
             assert!(prev.is_none(), "eid={:?} is already used", eid);
+            #[cfg(feature = "metrics")]
+            crate::metrics::register_engine(*eid, engine_info);
+            crate::profiler::register_engine(*eid, engine_info, engine.engine().profiler());
+            crate::events::register_engine(*eid, engine_info, engine.engine().events());
         }
 
         self.runtimes[&rid].add_group(group);
         // a runtime will not be parked when having pending engines, so in theory, we can check
         // whether the runtime and only unpark it when it's in parked state.
         self.handles[&rid].thread().unpark();
+        rid
     }
 }
 
 impl RuntimeManager {
-    pub fn new(_config: &Config) -> Self {
+    pub fn new(config: &Config) -> Self {
         let inner = Inner {
             runtime_counter: 0,
             runtimes: HashMap::with_capacity(1),
@@ -198,6 +319,9 @@ impl RuntimeManager {
             engine_subscriptions: DashMap::new(),
             service_subscriptions: DashMap::new(),
             global_resource_mgr: GlobalResourceManager::new(),
+            restart_policies: DashMap::new(),
+            batch_poll_interval: Arc::new(AtomicU32::new(config.runtime.batch_poll_interval)),
+            engine_quantum: config.runtime.engine_quantum,
         }
     }
 
@@ -212,8 +336,9 @@ impl RuntimeManager {
     ) {
         let inner = self.inner.lock().unwrap();
         let mut submission = Vec::with_capacity(engines.len());
-        for engine in engines {
+        for mut engine in engines {
             let eid = EngineId(self.engine_counter.fetch_add(1, Ordering::Relaxed));
+            engine.set_span(eid.0, pid.as_raw());
             let engine_type = engine.engine_type();
             let engine_info = EngineInfo {
                 pid,
@@ -233,6 +358,10 @@ impl RuntimeManager {
             );
             let prev = self.engine_subscriptions.insert(eid, engine_info);
             assert!(prev.is_none(), "eid={:?} is already used", eid);
+            #[cfg(feature = "metrics")]
+            crate::metrics::register_engine(eid, engine_info);
+            crate::profiler::register_engine(eid, engine_info, engine.engine().profiler());
+            crate::events::register_engine(eid, engine_info, engine.engine().events());
             submission.push((eid, engine));
         }
         inner.runtimes[&rid].attach_engines_to_group(gid, submission);
@@ -248,8 +377,9 @@ impl RuntimeManager {
     ) {
         let mut inner = self.inner.lock().unwrap();
         let mut submission = Vec::with_capacity(engines.len());
-        for engine in engines {
+        for mut engine in engines {
             let eid = EngineId(self.engine_counter.fetch_add(1, Ordering::Relaxed));
+            engine.set_span(eid.0, pid.as_raw());
             submission.push((eid, engine));
         }
         let gid = GroupId(
@@ -258,7 +388,53 @@ impl RuntimeManager {
         );
         let group = SchedulingGroup::new(gid, submission);
 
-        inner.schedule(pid, sid, group, self, mode, hint);
+        // choose cores to schedule
+        let cores = CoreMask::from_hint(&hint);
+        log::debug!(
+            "group: {:?}, scheduling hint: {:?}, cores: {}",
+            group,
+            hint,
+            cores
+        );
+        inner.schedule(pid, sid, group, self, mode, cores);
+    }
+
+    /// Schedules the suspended engines of a group again, on a runtime that runs on `cores`.
+    /// The engines keep their IDs.
+    pub(crate) fn resubmit_group(
+        self: &Arc<Self>,
+        pid: Pid,
+        sid: SubscriptionId,
+        gid: GroupId,
+        engines: Vec<(EngineId, EngineContainer)>,
+        mode: SchedulingMode,
+        cores: CoreMask,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        let group = SchedulingGroup::new(gid, engines);
+        log::debug!("group: {:?}, moving to cores: {}", group, cores);
+        inner.schedule(pid, sid, group, self, mode, cores);
+    }
+
+    /// Schedules the suspended engines of a group again, on the runtime `rid`, or on a new
+    /// runtime on `cores` if `rid` is not given or does not accept the group. Returns the runtime
+    /// the group is scheduled on. The engines keep their IDs.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn place_group(
+        self: &Arc<Self>,
+        pid: Pid,
+        sid: SubscriptionId,
+        gid: GroupId,
+        engines: Vec<(EngineId, EngineContainer)>,
+        mode: SchedulingMode,
+        cores: CoreMask,
+        rid: Option<RuntimeId>,
+    ) -> RuntimeId {
+        let mut inner = self.inner.lock().unwrap();
+        let group = SchedulingGroup::new(gid, engines);
+        log::debug!("group: {:?}, moving to runtime: {:?}", group, rid);
+        let target = rid.map_or(Placement::New, Placement::On);
+        inner.schedule_on(pid, sid, group, self, mode, cores, target)
     }
 
     /// Create a new engine group for service subscription
@@ -276,8 +452,337 @@ impl RuntimeManager {
         sid
     }
 
+    pub(crate) fn set_restart_policy(&self, engine_type: EngineType, policy: RestartPolicy) {
+        self.restart_policies
+            .insert(engine_type.0.to_owned(), policy);
+    }
+
+    /// Sets how often the batch engines are polled, in iterations of their runtimes. Takes effect
+    /// on all runtimes from their next iteration.
+    pub(crate) fn set_batch_poll_interval(&self, interval: u32) {
+        assert!(interval > 0, "batch_poll_interval must be positive");
+        self.batch_poll_interval.store(interval, Ordering::Relaxed);
+    }
+
+    pub(crate) fn batch_poll_interval(&self) -> u32 {
+        self.batch_poll_interval.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn restart_policy(&self, engine_type: EngineType) -> RestartPolicy {
+        self.restart_policies
+            .get(engine_type.0)
+            .map(|x| *x.value())
+            .unwrap_or_default()
+    }
+
+    /// Shut down all engines of the service subscription that `engine_id` belongs to, because
+    /// one of them has failed.
+    pub(crate) fn shutdown_subscription(&self, engine_id: EngineId, reason: ShutdownReason) {
+        let (pid, sid) = match self.engine_subscriptions.get(&engine_id) {
+            Some(info) => (info.pid, info.sid),
+            None => return,
+        };
+        let engines = self
+            .engine_subscriptions
+            .iter()
+            .filter(|e| e.pid == pid && e.sid == sid)
+            .map(|e| (*e.key(), e.rid))
+            .collect::<Vec<_>>();
+        tracing::warn!(
+            "Shutting down service subscription (pid={:?}, sid={:?}), reason: {}",
+            pid,
+            sid,
+            reason
+        );
+        self.request_shutdown(engines, reason);
+    }
+
+    /// Shuts down all engines serving `pid`. Returns the number of engines.
+    pub(crate) fn shutdown_process(&self, pid: Pid, reason: ShutdownReason) -> usize {
+        let engines = self
+            .engine_subscriptions
+            .iter()
+            .filter(|e| e.pid == pid)
+            .map(|e| (*e.key(), e.rid))
+            .collect::<Vec<_>>();
+        let num_engines = engines.len();
+        self.request_shutdown(engines, reason);
+        num_engines
+    }
+
+    fn request_shutdown(&self, engines: Vec<(EngineId, RuntimeId)>, reason: ShutdownReason) {
+        let inner = self.inner.lock().unwrap();
+        for (eid, rid) in engines {
+            inner.runtimes[&rid].request_shutdown(eid, reason.clone());
+            inner.handles[&rid].thread().unpark();
+        }
+    }
+
+    /// Returns where the scheduling groups run, ordered by client.
+    pub(crate) fn placements(&self) -> Vec<GroupPlacement> {
+        let mut groups = BTreeMap::new();
+        for engine in self.engine_subscriptions.iter() {
+            let key = (engine.pid.as_raw(), engine.sid.0, engine.gid.0);
+            groups
+                .entry(key)
+                .or_insert_with(|| (engine.rid, Vec::new()))
+                .1
+                .push(engine.engine_type.0.to_owned());
+        }
+
+        let inner = self.inner.lock().unwrap();
+        groups
+            .into_iter()
+            .map(|((pid, sid, gid), (rid, engines))| GroupPlacement {
+                pid,
+                sid,
+                gid,
+                runtime: rid.0,
+                engines,
+                cpus: inner
+                    .runtimes
+                    .get(&rid)
+                    .map(|r| r.cores().to_cpu_set().to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Asks the runtimes `rids` for the snapshots of their engines, and to dump the engines if
+    /// `dump` is set and checkpoint them if `checkpoint` is set.
+    fn request_inspect(
+        &self,
+        rids: &HashSet<RuntimeId>,
+        dump: bool,
+        checkpoint: bool,
+    ) -> Inspection {
+        let (tx, rx) = mpsc::channel();
+        let inner = self.inner.lock().unwrap();
+        for rid in rids {
+            inner.runtimes[rid].request_inspect(tx.clone(), dump, checkpoint);
+            inner.handles[rid].thread().unpark();
+        }
+        Inspection {
+            rx,
+            waiting: rids.len(),
+            deadline: Instant::now() + INSPECT_TIMEOUT,
+            snapshots: HashMap::new(),
+        }
+    }
+
+    /// Takes the snapshots of the engines on the runtimes `rids`, and dumps the engines if `dump`
+    /// is set and checkpoints them if `checkpoint` is set. The engines of a runtime that does not
+    /// respond in time are left out.
+    fn inspect_runtimes(
+        &self,
+        rids: &HashSet<RuntimeId>,
+        dump: bool,
+        checkpoint: bool,
+    ) -> HashMap<EngineId, EngineSnapshot> {
+        let mut inspection = self.request_inspect(rids, dump, checkpoint);
+        inspection.wait();
+        inspection.snapshots
+    }
+
+    /// Returns the dumps of all the engines, as text to attach to bug reports, see
+    /// `Engine::dump`.
+    pub(crate) fn dump_engines(&self) -> String {
+        use std::fmt::Write;
+
+        let mut engines: Vec<(EngineId, EngineInfo)> = self
+            .engine_subscriptions
+            .iter()
+            .map(|e| (*e.key(), *e.value()))
+            .collect();
+        engines.sort_by_key(|(eid, _)| eid.0);
+        let rids: HashSet<RuntimeId> = engines.iter().map(|(_, info)| info.rid).collect();
+        let mut snapshots = self.inspect_runtimes(&rids, true, false);
+
+        let mut out = String::new();
+        for (eid, info) in engines {
+            let _ = writeln!(
+                out,
+                "engine {} {}, pid {}, subscription {}, runtime {}",
+                eid.0, info.engine_type.0, info.pid, info.sid.0, info.rid.0
+            );
+            match snapshots.remove(&eid) {
+                Some(snapshot) => {
+                    let _ = writeln!(out, "  description: {}", snapshot.description);
+                    let _ = writeln!(
+                        out,
+                        "  queued: tx inputs {:?}, rx inputs {:?}",
+                        snapshot.tx_inputs, snapshot.rx_inputs
+                    );
+                    for line in snapshot.dump.iter().flat_map(|dump| dump.lines()) {
+                        let _ = writeln!(out, "  {}", line);
+                    }
+                }
+                // suspended, being upgraded, or its runtime is busy
+                None => {
+                    let _ = writeln!(out, "  not responding");
+                }
+            }
+        }
+        out
+    }
+
+    /// Asks the runtimes for what their engines save in a checkpoint of the daemon, see
+    /// `Engine::checkpoint`. The checkpoints are collected by [`RuntimeManager::checkpoint_engines`]
+    /// once [`EngineCheckpoints::poll`] returns true, so the caller does not wait for the runtimes.
+    pub(crate) fn request_checkpoints(&self) -> EngineCheckpoints {
+        let engines: Vec<(EngineId, EngineInfo)> = self
+            .engine_subscriptions
+            .iter()
+            .map(|e| (*e.key(), *e.value()))
+            .collect();
+        let rids: HashSet<RuntimeId> = engines.iter().map(|(_, info)| info.rid).collect();
+        let inspection = self.request_inspect(&rids, false, true);
+        EngineCheckpoints {
+            engines,
+            inspection,
+        }
+    }
+
+    /// Returns what the engines of each service subscription save in a checkpoint of the daemon,
+    /// as requested by [`RuntimeManager::request_checkpoints`]. The engines that save nothing or
+    /// have not responded are left out.
+    pub(crate) fn checkpoint_engines(
+        &self,
+        checkpoints: EngineCheckpoints,
+    ) -> Vec<(Pid, Service, Vec<(EngineType, EngineCheckpoint)>)> {
+        let EngineCheckpoints {
+            engines,
+            inspection,
+        } = checkpoints;
+        let mut snapshots = inspection.snapshots;
+
+        let mut subscriptions: HashMap<(Pid, SubscriptionId), Vec<(EngineType, EngineCheckpoint)>> =
+            HashMap::new();
+        for (eid, info) in engines {
+            let checkpoint = snapshots.remove(&eid).and_then(|s| s.checkpoint);
+            let entry = subscriptions
+                .entry((info.pid, info.sid))
+                .or_insert_with(Vec::new);
+            entry.extend(checkpoint.map(|c| (info.engine_type, c)));
+        }
+        subscriptions
+            .into_iter()
+            .filter_map(|((pid, sid), engines)| {
+                let service = self.service_subscriptions.get(&(pid, sid))?.0.service;
+                Some((pid, service, engines))
+            })
+            .collect()
+    }
+
+    /// Returns the engines and channels on the datapath of the matching service subscriptions,
+    /// with the number of messages queued in the channels.
+    pub(crate) fn datapath_graphs(
+        &self,
+        pid: Option<Pid>,
+        sid: Option<SubscriptionId>,
+    ) -> Vec<SubscriptionGraph> {
+        let matches = |p: Pid, s: SubscriptionId| {
+            pid.map_or(true, |pid| pid == p) && sid.map_or(true, |sid| sid == s)
+        };
+        let engines: Vec<(EngineId, EngineInfo)> = self
+            .engine_subscriptions
+            .iter()
+            .filter(|e| matches(e.pid, e.sid))
+            .map(|e| (*e.key(), *e.value()))
+            .collect();
+
+        let rids: HashSet<RuntimeId> = engines.iter().map(|(_, info)| info.rid).collect();
+        let mut snapshots = self.inspect_runtimes(&rids, false, false);
+
+        let mut graphs = Vec::new();
+        for subscription in self.service_subscriptions.iter() {
+            let (p, s) = *subscription.key();
+            if !matches(p, s) {
+                continue;
+            }
+
+            let mut graph_engines: Vec<GraphEngine> = engines
+                .iter()
+                .filter(|(_, info)| info.pid == p && info.sid == s)
+                .map(|(eid, info)| {
+                    let snapshot = snapshots.remove(eid);
+                    GraphEngine {
+                        eid: eid.0,
+                        engine_type: info.engine_type.0.to_owned(),
+                        runtime: info.rid.0,
+                        description: snapshot.as_ref().map(|s| s.description.clone()),
+                        tx_inputs: snapshot
+                            .as_ref()
+                            .map_or_else(Vec::new, |s| s.tx_inputs.clone()),
+                        rx_inputs: snapshot.map_or_else(Vec::new, |s| s.rx_inputs),
+                    }
+                })
+                .collect();
+            graph_engines.sort_by_key(|e| e.eid);
+
+            // the number of messages queued at an input of the receiving engine
+            let queued = |receiver: EngineType, index: usize, direction: ChannelDirection| {
+                let engine = graph_engines
+                    .iter()
+                    .find(|e| e.engine_type == receiver.0 && e.description.is_some())?;
+                let inputs = match direction {
+                    ChannelDirection::Tx => &engine.tx_inputs,
+                    ChannelDirection::Rx => &engine.rx_inputs,
+                };
+                inputs.get(index).copied()
+            };
+
+            let graph = &subscription.value().0.graph;
+            let mut channels = Vec::new();
+            for (direction, outputs) in [
+                (ChannelDirection::Tx, &graph.tx_outputs),
+                (ChannelDirection::Rx, &graph.rx_outputs),
+            ] {
+                for (sender, endpoints) in outputs.iter() {
+                    for (sender_index, &(receiver, receiver_index)) in endpoints.iter().enumerate()
+                    {
+                        channels.push(GraphChannel {
+                            direction,
+                            sender: sender.0.to_owned(),
+                            receiver: receiver.0.to_owned(),
+                            sender_index,
+                            receiver_index,
+                            queued: queued(receiver, receiver_index, direction),
+                        });
+                    }
+                }
+            }
+            channels.sort_by(|a, b| {
+                (
+                    a.direction == ChannelDirection::Rx,
+                    &a.sender,
+                    a.sender_index,
+                )
+                    .cmp(&(
+                        b.direction == ChannelDirection::Rx,
+                        &b.sender,
+                        b.sender_index,
+                    ))
+            });
+
+            graphs.push(SubscriptionGraph {
+                pid: p.as_raw(),
+                sid: s.0,
+                service: subscription.value().0.service.0.to_owned(),
+                engines: graph_engines,
+                channels,
+            });
+        }
+        graphs.sort_by_key(|g| (g.pid, g.sid));
+        graphs
+    }
+
     pub(crate) fn register_engine_shutdown(&self, engine_id: EngineId) {
         let info = self.engine_subscriptions.remove(&engine_id).unwrap().1;
+        #[cfg(feature = "metrics")]
+        crate::metrics::unregister_engine(engine_id);
+        crate::profiler::unregister_engine(engine_id);
+        crate::events::unregister_engine(engine_id);
         let removed =
             self.service_subscriptions
                 .remove_if_mut(&(info.pid, info.sid), |_, (_, cnt)| {
@@ -302,7 +807,13 @@ impl Inner {
         let runtime_id = RuntimeId(self.runtime_counter);
         self.runtime_counter = self.runtime_counter.checked_add(1).unwrap();
 
-        let runtime = Arc::new(Runtime::new(runtime_id, cores.clone(), Arc::downgrade(&rm)));
+        let runtime = Arc::new(Runtime::new(
+            runtime_id,
+            cores.clone(),
+            Arc::clone(&rm.batch_poll_interval),
+            rm.engine_quantum,
+            Arc::downgrade(&rm),
+        ));
         let flag = runtime.try_acquire(mode, group_signature, cores.clone(), None);
         assert!(flag);
 
@@ -17,3 +17,5 @@ pub(crate) use upgrade::EngineUpgrader;
 pub(crate) mod affinity;
 
 pub(crate) mod lb;
+
+pub(crate) mod scaler;
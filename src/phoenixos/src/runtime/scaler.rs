@@ -0,0 +1,378 @@
+//! The autoscaler spreads the scheduling groups of the compact runtimes over more runtimes when
+//! they are busy, and gathers them back onto fewer runtimes when they are idle.
+//!
+//! Every `interval_ms`, it samples the load of each compact runtime, the fraction of its
+//! iterations since the last sample in which some engine did work. When a runtime with more than
+//! one group stays above `scale_out_load` for `sustain` samples in a row, half of its groups are
+//! moved to a new runtime on the same cores, unless `max_runtimes` runtimes already run groups.
+//! When a runtime stays below `scale_in_load` for as long, its groups are moved to the least
+//! loaded other runtime on the same cores, if the load of the two stays below `scale_out_load`.
+//! The runtime left without groups parks, and is reused by the next groups scheduled.
+//!
+//! The groups are moved by the engine upgrader, so a client is never moved while it is being
+//! upgraded. The counts of all runtimes start over after a move, so that the runtimes settle
+//! before the next one.
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use nix::unistd::Pid;
+
+use super::affinity::CoreMask;
+use super::executor::RuntimeMode;
+use super::group::GroupId;
+use super::manager::{RuntimeId, RuntimeManager};
+use super::EngineUpgrader;
+use crate::config::AutoscaleConfig;
+use crate::log;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    /// The counters of the runtime at the last sample
+    rounds: u64,
+    busy_rounds: u64,
+    /// The number of samples in a row above `scale_out_load`
+    hot: u32,
+    /// The number of samples in a row below `scale_in_load`
+    cold: u32,
+}
+
+impl Sample {
+    /// Takes the counters of the runtime, and returns its load since the last sample.
+    fn update(&mut self, rounds: u64, busy_rounds: u64, config: &AutoscaleConfig) -> f64 {
+        let load = if rounds > self.rounds {
+            (busy_rounds - self.busy_rounds) as f64 / (rounds - self.rounds) as f64
+        } else {
+            0.0
+        };
+        self.rounds = rounds;
+        self.busy_rounds = busy_rounds;
+        self.hot = if load >= config.scale_out_load {
+            self.hot + 1
+        } else {
+            0
+        };
+        self.cold = if load <= config.scale_in_load {
+            self.cold + 1
+        } else {
+            0
+        };
+        load
+    }
+}
+
+/// A compact runtime as seen at a sample.
+struct RuntimeLoad {
+    rid: RuntimeId,
+    cores: CoreMask,
+    load: f64,
+    sample: Sample,
+    groups: Vec<(Pid, GroupId)>,
+}
+
+pub(crate) struct Autoscaler {
+    config: AutoscaleConfig,
+    last_sample: Instant,
+    samples: HashMap<RuntimeId, Sample>,
+}
+
+impl Autoscaler {
+    pub(crate) fn new(config: &AutoscaleConfig) -> Self {
+        Autoscaler {
+            config: config.clone(),
+            last_sample: Instant::now(),
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Samples the runtimes and moves groups if the interval has elapsed since the last sample.
+    pub(crate) fn poll(&mut self, runtime_manager: &RuntimeManager, upgrader: &mut EngineUpgrader) {
+        if !self.config.enable
+            || self.last_sample.elapsed() < Duration::from_millis(self.config.interval_ms)
+        {
+            return;
+        }
+        self.last_sample = Instant::now();
+
+        let runtimes = self.sample(runtime_manager);
+        if let Some((groups, target, cores)) = self.decide(&runtimes) {
+            match upgrader.rebalance(groups, target, cores) {
+                Ok(()) => self.cool_down(),
+                Err(e) => log::debug!("Autoscaler: {}", e),
+            }
+        }
+    }
+
+    /// Starts the counts of all runtimes over after a move.
+    fn cool_down(&mut self) {
+        for sample in self.samples.values_mut() {
+            sample.hot = 0;
+            sample.cold = 0;
+        }
+    }
+
+    /// Updates the samples of the compact runtimes and returns those running groups.
+    fn sample(&mut self, runtime_manager: &RuntimeManager) -> Vec<RuntimeLoad> {
+        let mut groups: HashMap<RuntimeId, BTreeMap<GroupId, Pid>> = HashMap::new();
+        for engine in runtime_manager.engine_subscriptions.iter() {
+            groups
+                .entry(engine.rid)
+                .or_default()
+                .insert(engine.gid, engine.pid);
+        }
+
+        let inner = runtime_manager.inner.lock().unwrap();
+        self.samples
+            .retain(|rid, _| inner.runtimes.contains_key(rid));
+        let mut runtimes = Vec::new();
+        for (rid, runtime) in inner.runtimes.iter() {
+            if runtime.mode() != Some(RuntimeMode::Compact) {
+                continue;
+            }
+            let (rounds, busy_rounds) = runtime.rounds();
+            let sample = self.samples.entry(*rid).or_default();
+            let load = sample.update(rounds, busy_rounds, &self.config);
+
+            let groups: Vec<_> = groups
+                .remove(rid)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(gid, pid)| (pid, gid))
+                .collect();
+            if !groups.is_empty() {
+                runtimes.push(RuntimeLoad {
+                    rid: *rid,
+                    cores: runtime.cores().clone(),
+                    load,
+                    sample: *sample,
+                    groups,
+                });
+            }
+        }
+        runtimes
+    }
+
+    /// Returns the groups to move, the runtime to move them to, or `None` for a new runtime, and
+    /// the cores of the runtimes.
+    fn decide(
+        &self,
+        runtimes: &[RuntimeLoad],
+    ) -> Option<(Vec<(Pid, GroupId)>, Option<RuntimeId>, CoreMask)> {
+        let sustain = self.config.sustain;
+
+        // scale out the busiest runtime
+        if runtimes.len() < self.config.max_runtimes {
+            let busiest = runtimes
+                .iter()
+                .filter(|r| r.sample.hot >= sustain && r.groups.len() > 1)
+                .max_by(|a, b| a.load.total_cmp(&b.load));
+            if let Some(from) = busiest {
+                let moved = from.groups[from.groups.len() / 2..].to_vec();
+                log::info!(
+                    "Autoscaler: runtime {:?} is busy (load={:.2}), moving {} of its {} groups \
+                     to a new runtime",
+                    from.rid,
+                    from.load,
+                    moved.len(),
+                    from.groups.len(),
+                );
+                return Some((moved, None, from.cores.clone()));
+            }
+        }
+
+        // scale in the idle runtime with the fewest groups
+        let idlest = runtimes
+            .iter()
+            .filter(|r| r.sample.cold >= sustain)
+            .min_by_key(|r| r.groups.len())?;
+        let to = runtimes
+            .iter()
+            .filter(|r| r.rid != idlest.rid && r.cores == idlest.cores && r.sample.hot == 0)
+            .filter(|r| r.load + idlest.load < self.config.scale_out_load)
+            .min_by(|a, b| a.load.total_cmp(&b.load))?;
+        log::info!(
+            "Autoscaler: runtime {:?} is idle (load={:.2}), moving its {} groups to runtime {:?}",
+            idlest.rid,
+            idlest.load,
+            idlest.groups.len(),
+            to.rid,
+        );
+        Some((idlest.groups.clone(), Some(to.rid), idlest.cores.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AutoscaleConfig {
+        AutoscaleConfig {
+            enable: true,
+            interval_ms: 1000,
+            scale_out_load: 0.8,
+            scale_in_load: 0.2,
+            sustain: 3,
+            max_runtimes: 4,
+        }
+    }
+
+    #[test]
+    fn hysteresis() {
+        // the busy rounds of each 100 rounds sampled, and the counts after each sample
+        let cases: &[(&str, &[u64], &[(u32, u32)])] = &[
+            (
+                "hot at the threshold",
+                &[80, 100, 80],
+                &[(1, 0), (2, 0), (3, 0)],
+            ),
+            (
+                "a dip starts over",
+                &[90, 90, 79, 90],
+                &[(1, 0), (2, 0), (0, 0), (1, 0)],
+            ),
+            (
+                "cold at the threshold",
+                &[20, 0, 20],
+                &[(0, 1), (0, 2), (0, 3)],
+            ),
+            (
+                "a spike starts over",
+                &[10, 21, 10],
+                &[(0, 1), (0, 0), (0, 1)],
+            ),
+            ("in between", &[50, 30, 70], &[(0, 0), (0, 0), (0, 0)]),
+            ("hot then cold", &[90, 90, 10], &[(1, 0), (2, 0), (0, 1)]),
+        ];
+        let config = config();
+        for &(name, busy, counts) in cases {
+            let mut sample = Sample::default();
+            for (i, (&busy, &count)) in busy.iter().zip(counts).enumerate() {
+                let rounds = 100 * (i as u64 + 1);
+                let load = sample.update(rounds, sample.busy_rounds + busy, &config);
+                assert_eq!(load, busy as f64 / 100.0, "{name}");
+                assert_eq!((sample.hot, sample.cold), count, "{name}, sample {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn idle_runtime_is_cold() {
+        // a runtime that has not run an iteration since the last sample
+        let mut sample = Sample::default();
+        sample.update(100, 100, &config());
+        assert_eq!(sample.update(100, 100, &config()), 0.0);
+        assert_eq!((sample.hot, sample.cold), (0, 1));
+    }
+
+    // (rid, load, hot, cold, the number of groups)
+    type Runtime = (u64, f64, u32, u32, u64);
+
+    fn runtimes(runtimes: &[Runtime], cores: &CoreMask) -> Vec<RuntimeLoad> {
+        runtimes
+            .iter()
+            .map(|&(rid, load, hot, cold, groups)| RuntimeLoad {
+                rid: RuntimeId(rid),
+                cores: cores.clone(),
+                load,
+                sample: Sample {
+                    hot,
+                    cold,
+                    ..Sample::default()
+                },
+                groups: (0..groups)
+                    .map(|gid| (Pid::from_raw(1), GroupId(rid * 100 + gid)))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decide() {
+        // the runtimes, and the groups moved by id and the runtime they go to, 0 for a new one
+        let cases: &[(&str, &[Runtime], Option<(&[u64], u64)>)] = &[
+            (
+                "scale out half of the groups",
+                &[(1, 0.9, 3, 0, 4)],
+                Some((&[102, 103], 0)),
+            ),
+            ("not busy for long enough", &[(1, 0.9, 2, 0, 4)], None),
+            ("a single group is not split", &[(1, 0.9, 3, 0, 1)], None),
+            (
+                "the busiest one first",
+                &[(1, 0.85, 3, 0, 2), (2, 0.95, 3, 0, 3)],
+                Some((&[201, 202], 0)),
+            ),
+            (
+                "scale in to the least loaded",
+                &[(1, 0.1, 0, 3, 2), (2, 0.5, 0, 0, 2), (3, 0.3, 0, 0, 2)],
+                Some((&[100, 101], 3)),
+            ),
+            (
+                "not idle for long enough",
+                &[(1, 0.1, 0, 2, 2), (2, 0.3, 0, 0, 2)],
+                None,
+            ),
+            (
+                "the two would be busy",
+                &[(1, 0.15, 0, 3, 2), (2, 0.7, 0, 0, 2)],
+                None,
+            ),
+            (
+                "not onto a hot runtime",
+                &[(1, 0.1, 0, 3, 2), (2, 0.6, 1, 0, 2)],
+                None,
+            ),
+            (
+                "the idle one with the fewest groups",
+                &[(1, 0.1, 0, 3, 3), (2, 0.1, 0, 3, 1), (3, 0.4, 0, 0, 2)],
+                Some((&[200], 1)),
+            ),
+        ];
+        let cores = CoreMask::from_numa_node(None);
+        let autoscaler = Autoscaler::new(&config());
+        for &(name, loads, expected) in cases {
+            let decided = autoscaler
+                .decide(&runtimes(loads, &cores))
+                .map(|(groups, to, _)| {
+                    let groups: Vec<_> = groups.into_iter().map(|(_, gid)| gid.0).collect();
+                    (groups, to.map_or(0, |rid| rid.0))
+                });
+            let expected = expected.map(|(groups, to)| (groups.to_vec(), to));
+            assert_eq!(decided, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn max_runtimes() {
+        let cores = CoreMask::from_numa_node(None);
+        let autoscaler = Autoscaler::new(&AutoscaleConfig {
+            max_runtimes: 2,
+            ..config()
+        });
+        let busy = [(1, 0.9, 3, 0, 4), (2, 0.9, 3, 0, 4)];
+        assert!(autoscaler.decide(&runtimes(&busy, &cores)).is_none());
+        assert!(autoscaler.decide(&runtimes(&busy[..1], &cores)).is_some());
+    }
+
+    #[test]
+    fn cool_down_after_a_move() {
+        let config = config();
+        let mut autoscaler = Autoscaler::new(&config);
+        let cores = CoreMask::from_numa_node(None);
+        let rid = RuntimeId(1);
+        // samples the runtime at full load, and decides
+        let sample_busy = |autoscaler: &mut Autoscaler| {
+            let sample = autoscaler.samples.entry(rid).or_default();
+            let load = sample.update(sample.rounds + 100, sample.busy_rounds + 100, &config);
+            let sample = *sample;
+            let runtimes = runtimes(&[(rid.0, load, sample.hot, sample.cold, 4)], &cores);
+            autoscaler.decide(&runtimes).is_some()
+        };
+
+        let decided: Vec<_> = (0..3).map(|_| sample_busy(&mut autoscaler)).collect();
+        assert_eq!(decided, [false, false, true]);
+        autoscaler.cool_down();
+        // the runtime must stay busy for as long again
+        let decided: Vec<_> = (0..3).map(|_| sample_busy(&mut autoscaler)).collect();
+        assert_eq!(decided, [false, false, true]);
+    }
+}
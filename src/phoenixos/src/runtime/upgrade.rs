@@ -1,3 +1,12 @@
+//! Live upgrade of engines.
+//!
+//! An engine is upgraded by suspending it, decomposing it into its states, and restoring a new
+//! engine from the states with the newly loaded module, all within the running daemon. The states
+//! are handed over as Rust objects in memory, they are never serialized.
+//!
+//! A restart of the daemon is survived by checkpoints instead, see `checkpoint`. They save the
+//! shared memory regions of the clients and what the engines choose to serialize, not the
+//! engines themselves.
 use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
@@ -17,6 +26,7 @@ use phoenix_common::engine::datapath::{
 use phoenix_common::engine::{Engine, EngineType};
 use phoenix_common::storage::{ResourceCollection, SharedStorage};
 
+use super::affinity::CoreMask;
 use super::executor::SuspendResult;
 use super::graph::DataPathGraph;
 use super::graph::{EndpointCollection, EndpointType, Error};
@@ -308,6 +318,7 @@ async fn attach_addon<I>(
                 SchedulingHint {
                     mode,
                     numa_node_affinity: None,
+                    cpu_affinity: None,
                 },
             );
         }
@@ -476,6 +487,127 @@ async fn detach_addon<I>(
     indicator.remove(&pid);
 }
 
+/// Move the engines of a client, or of one of its service subscriptions, to runtimes on `cores`.
+/// The engines of a scheduling group stay together.
+async fn migrate_engines(
+    rm: Arc<RuntimeManager>,
+    pid: Pid,
+    sid: Option<SubscriptionId>,
+    cores: CoreMask,
+    indicator: Arc<DashSet<Pid>>,
+) {
+    let mut engines = rm
+        .engine_subscriptions
+        .iter()
+        .filter(|e| e.pid == pid && sid.map_or(true, |sid| e.sid == sid))
+        .map(|e| (*e.key(), *e.value()))
+        .collect::<Vec<_>>();
+
+    let guard = rm.inner.lock().unwrap();
+    for (engine_id, info) in engines.iter() {
+        let runtime = guard.runtimes.get(&info.rid).unwrap();
+        runtime.request_suspend(*engine_id);
+    }
+    drop(guard);
+
+    let mut groups = HashMap::new();
+    while !engines.is_empty() {
+        let guard = rm.inner.lock().unwrap();
+        engines.retain(|(eid, info)| {
+            let runtime = guard.runtimes.get(&info.rid).unwrap();
+            if let Some((_, result)) = runtime.suspended.remove(eid) {
+                if let SuspendResult::Engine(container) = result {
+                    groups
+                        .entry(info.gid)
+                        .or_insert_with(|| (info.sid, info.scheduling_mode, Vec::new()))
+                        .2
+                        .push((*eid, container));
+                    rm.engine_subscriptions.remove(eid);
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    for (gid, (sid, mode, containers)) in groups {
+        log::info!(
+            "Moving scheduling group {:?} of client (pid={:?}, sid={:?}) to cores {}",
+            gid,
+            pid,
+            sid,
+            cores,
+        );
+        rm.resubmit_group(pid, sid, gid, containers, mode, cores.clone());
+    }
+    indicator.remove(&pid);
+}
+
+/// Move the scheduling groups `groups`, given with their clients, together to the runtime
+/// `target`, or to a new runtime on `cores` if no target is given.
+async fn rebalance_groups(
+    rm: Arc<RuntimeManager>,
+    groups: Vec<(Pid, GroupId)>,
+    target: Option<RuntimeId>,
+    cores: CoreMask,
+    indicator: Arc<DashSet<Pid>>,
+) {
+    let gids = groups.iter().map(|(_, gid)| *gid).collect::<HashSet<_>>();
+    let mut engines = rm
+        .engine_subscriptions
+        .iter()
+        .filter(|e| gids.contains(&e.gid))
+        .map(|e| (*e.key(), *e.value()))
+        .collect::<Vec<_>>();
+
+    let guard = rm.inner.lock().unwrap();
+    for (engine_id, info) in engines.iter() {
+        let runtime = guard.runtimes.get(&info.rid).unwrap();
+        runtime.request_suspend(*engine_id);
+    }
+    drop(guard);
+
+    let mut suspended = HashMap::new();
+    while !engines.is_empty() {
+        let guard = rm.inner.lock().unwrap();
+        engines.retain(|(eid, info)| {
+            let runtime = guard.runtimes.get(&info.rid).unwrap();
+            if let Some((_, result)) = runtime.suspended.remove(eid) {
+                if let SuspendResult::Engine(container) = result {
+                    suspended
+                        .entry(info.gid)
+                        .or_insert_with(|| (info.sid, info.scheduling_mode, Vec::new()))
+                        .2
+                        .push((*eid, container));
+                    rm.engine_subscriptions.remove(eid);
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    let mut target = target;
+    for (pid, gid) in groups.iter() {
+        if let Some((sid, mode, containers)) = suspended.remove(gid) {
+            let rid = rm.place_group(*pid, sid, *gid, containers, mode, cores.clone(), target);
+            log::info!(
+                "Moved scheduling group {:?} of client (pid={:?}, sid={:?}) to runtime {:?}",
+                gid,
+                pid,
+                sid,
+                rid,
+            );
+            target = Some(rid);
+        }
+    }
+    for (pid, _) in groups {
+        indicator.remove(&pid);
+    }
+}
+
 /// Upgrade the engines of a client process
 /// Arguments:
 /// * to_upgrade: eninges to be upgraded
@@ -908,6 +1040,64 @@ impl EngineUpgrader {
         self.executor.spawn_ok(fut);
         Ok(())
     }
+
+    /// Move the engines of a client to `cores`. All service subscriptions of the client are
+    /// moved if `sid` is not given.
+    pub(crate) fn migrate(
+        &mut self,
+        pid: Pid,
+        sid: Option<SubscriptionId>,
+        cores: CoreMask,
+    ) -> anyhow::Result<()> {
+        if self.upgrade_indicator.contains(&pid) {
+            bail!(
+                "there is already an ongoing upgrade for client pid={:?}",
+                pid
+            )
+        }
+        self.upgrade_indicator.insert(pid);
+        let fut = migrate_engines(
+            Arc::clone(&self.runtime_manager),
+            pid,
+            sid,
+            cores,
+            Arc::clone(&self.upgrade_indicator),
+        );
+        self.executor.spawn_ok(fut);
+        Ok(())
+    }
+
+    /// Move scheduling groups, given with their clients, together to the runtime `target`, or to
+    /// a new runtime on `cores`.
+    pub(crate) fn rebalance(
+        &mut self,
+        groups: Vec<(Pid, GroupId)>,
+        target: Option<RuntimeId>,
+        cores: CoreMask,
+    ) -> anyhow::Result<()> {
+        if let Some((pid, _)) = groups
+            .iter()
+            .find(|(pid, _)| self.upgrade_indicator.contains(pid))
+        {
+            bail!(
+                "there is already an ongoing upgrade for client pid={:?}",
+                pid
+            )
+        }
+        for (pid, _) in groups.iter() {
+            self.upgrade_indicator.insert(*pid);
+        }
+        let fut = rebalance_groups(
+            Arc::clone(&self.runtime_manager),
+            groups,
+            target,
+            cores,
+            Arc::clone(&self.upgrade_indicator),
+        );
+        self.executor.spawn_ok(fut);
+        Ok(())
+    }
+
     /// Live upgrade existing clients
     /// Arguments:
     /// * engine_types: engines that need to be upgraded
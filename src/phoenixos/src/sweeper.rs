@@ -0,0 +1,172 @@
+//! The sweeper reclaims the resources left in the daemon by client processes that have exited.
+//!
+//! A process is considered exited if it no longer exists. The sweeper first shuts down the
+//! engines still serving an exited process. Once they are gone, it removes the per-process
+//! states of the modules and the global resources of the process, and reports whatever was
+//! still registered as leaked.
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+use nix::unistd::Pid;
+
+use ipc::control::{DaemonStats, LeakStats, ResourceStats};
+use phoenix_common::state_mgr::{is_process_alive, ResourceUsage};
+
+use crate::config::SweeperConfig;
+use crate::plugin_mgr::PluginManager;
+use crate::runtime::executor::ShutdownReason;
+use crate::runtime::RuntimeManager;
+use crate::tracing;
+
+pub(crate) struct Sweeper {
+    interval: Option<Duration>,
+    last_sweep: Instant,
+    /// Exited processes whose engines are being shut down
+    pending: HashSet<Pid>,
+    leaks: LeakStats,
+}
+
+impl Sweeper {
+    pub(crate) fn new(config: &SweeperConfig) -> Self {
+        Sweeper {
+            interval: config
+                .enable
+                .then(|| Duration::from_secs_f64(config.interval_secs)),
+            last_sweep: Instant::now(),
+            pending: HashSet::new(),
+            leaks: LeakStats::default(),
+        }
+    }
+
+    /// Sweeps if the interval has elapsed since the last sweep.
+    pub(crate) fn poll(&mut self, runtime_manager: &RuntimeManager, plugins: &PluginManager) {
+        match self.interval {
+            Some(interval) if self.last_sweep.elapsed() >= interval => {
+                self.sweep(runtime_manager, plugins);
+                self.last_sweep = Instant::now();
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn sweep(&mut self, runtime_manager: &RuntimeManager, plugins: &PluginManager) {
+        let exited: Vec<Pid> = tracked_pids(runtime_manager, plugins)
+            .into_iter()
+            .filter(|pid| !is_process_alive(*pid))
+            .collect();
+
+        for pid in exited {
+            let has_engines = runtime_manager
+                .engine_subscriptions
+                .iter()
+                .any(|e| e.pid == pid);
+            if has_engines {
+                // Reclaim the rest after the engines are shut down.
+                if self.pending.insert(pid) {
+                    let num_engines =
+                        runtime_manager.shutdown_process(pid, ShutdownReason::ClientExited);
+                    self.leaks.engines += num_engines;
+                    tracing::warn!(
+                        pid = pid.as_raw(),
+                        engines = num_engines,
+                        "client process exited, shutting down its engines"
+                    );
+                }
+                continue;
+            }
+            self.pending.remove(&pid);
+            self.reclaim(pid, runtime_manager, plugins);
+        }
+    }
+
+    fn reclaim(&mut self, pid: Pid, runtime_manager: &RuntimeManager, plugins: &PluginManager) {
+        let mut leaked = BTreeMap::new();
+        for mut module in plugins.modules.iter_mut() {
+            let usage = module.value_mut().reclaim(pid);
+            for (kind, count) in usage.into_iter().filter(|(_, count)| *count > 0) {
+                leaked.insert(format!("{}.{}", module.key(), kind), count);
+            }
+        }
+        let global = runtime_manager.global_resource_mgr.reclaim(pid);
+        if global > 0 {
+            leaked.insert("global".to_owned(), global);
+        }
+
+        if leaked.is_empty() {
+            return;
+        }
+        self.leaks.processes += 1;
+        for (kind, count) in leaked.iter() {
+            *self.leaks.resources.entry(kind.clone()).or_default() += count;
+        }
+        tracing::warn!(
+            pid = pid.as_raw(),
+            total = leaked.values().sum::<usize>(),
+            resources = ?leaked,
+            "reclaimed resources leaked by exited client process"
+        );
+    }
+
+    /// Collects the engines and resources currently held by the daemon.
+    pub(crate) fn stats(
+        &self,
+        runtime_manager: &RuntimeManager,
+        plugins: &PluginManager,
+    ) -> DaemonStats {
+        let mut resources = Vec::new();
+        for module in plugins.modules.iter() {
+            for (pid, usage) in module.value().resource_usage() {
+                resources.push(ResourceStats {
+                    pid: pid.as_raw(),
+                    module: module.key().clone(),
+                    resources: to_owned_usage(usage),
+                });
+            }
+        }
+        resources.sort_by(|a, b| (a.pid, &a.module).cmp(&(b.pid, &b.module)));
+
+        DaemonStats {
+            clients: tracked_pids(runtime_manager, plugins).len(),
+            subscriptions: runtime_manager.service_subscriptions.len(),
+            engines: runtime_manager.engine_subscriptions.len(),
+            resources,
+            leaks: self.leaks.clone(),
+            placements: runtime_manager.placements(),
+            profiles: crate::profiler::profiles(),
+            events: crate::events::stats(),
+        }
+    }
+}
+
+/// The processes that have engines, global resources, or module states in the daemon.
+fn tracked_pids(runtime_manager: &RuntimeManager, plugins: &PluginManager) -> HashSet<Pid> {
+    let mut pids: HashSet<Pid> = runtime_manager
+        .engine_subscriptions
+        .iter()
+        .map(|e| e.pid)
+        .collect();
+    pids.extend(
+        runtime_manager
+            .global_resource_mgr
+            .resource
+            .iter()
+            .map(|r| *r.key()),
+    );
+    for module in plugins.modules.iter() {
+        pids.extend(
+            module
+                .value()
+                .resource_usage()
+                .into_iter()
+                .map(|(pid, _)| pid),
+        );
+    }
+    pids
+}
+
+fn to_owned_usage(usage: ResourceUsage) -> BTreeMap<String, usize> {
+    usage
+        .into_iter()
+        .map(|(kind, count)| (kind.to_owned(), count))
+        .collect()
+}
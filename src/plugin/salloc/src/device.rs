@@ -0,0 +1,137 @@
+//! Device memory region.
+//!
+//! The backend allocates GPU memory on behalf of the application and exports it through a CUDA
+//! IPC handle. The application opens the handle to get its own mapping of the same buffer. The
+//! RDMA transports register the backend mapping with the NIC (GPUDirect RDMA), so payloads on the
+//! device can be sent without being staged in host memory.
+//!
+//! Device memory is only available when the plugin is built with the `cuda` feature.
+use std::alloc::Layout;
+
+use thiserror::Error;
+
+/// The size of `cudaIpcMemHandle_t`.
+pub const CUDA_IPC_HANDLE_SIZE: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("CUDA error: {0}")]
+    Cuda(i32),
+    #[error("Unsupported alignment for device memory: {0}")]
+    Alignment(usize),
+    #[error("Device memory is not supported, rebuild salloc with feature \"cuda\"")]
+    Unsupported,
+}
+
+#[cfg(feature = "cuda")]
+mod ffi {
+    use std::os::raw::{c_int, c_void};
+
+    #[repr(C)]
+    pub(super) struct CudaIpcMemHandle {
+        pub(super) reserved: [u8; super::CUDA_IPC_HANDLE_SIZE],
+    }
+
+    #[link(name = "cudart")]
+    extern "C" {
+        pub(super) fn cudaSetDevice(device: c_int) -> c_int;
+        pub(super) fn cudaMalloc(dev_ptr: *mut *mut c_void, size: usize) -> c_int;
+        pub(super) fn cudaFree(dev_ptr: *mut c_void) -> c_int;
+        pub(super) fn cudaIpcGetMemHandle(
+            handle: *mut CudaIpcMemHandle,
+            dev_ptr: *mut c_void,
+        ) -> c_int;
+    }
+}
+
+#[derive(Debug)]
+pub struct DeviceRegion {
+    ptr: usize,
+    len: usize,
+    device: i32,
+    ipc_handle: Vec<u8>,
+}
+
+impl DeviceRegion {
+    #[cfg(feature = "cuda")]
+    pub fn new(layout: Layout, device: i32) -> Result<Self, Error> {
+        use std::mem::MaybeUninit;
+        use std::ptr;
+
+        macro_rules! cuda_call {
+            ($call:expr) => {
+                match unsafe { $call } {
+                    0 => {}
+                    e => return Err(Error::Cuda(e)),
+                }
+            };
+        }
+
+        // cudaMalloc always returns 256-byte aligned memory, larger alignment is not supported.
+        if layout.align() > 256 {
+            return Err(Error::Alignment(layout.align()));
+        }
+        let mut dev_ptr = ptr::null_mut();
+        cuda_call!(ffi::cudaSetDevice(device));
+        cuda_call!(ffi::cudaMalloc(&mut dev_ptr, layout.size()));
+        let mut handle = MaybeUninit::<ffi::CudaIpcMemHandle>::uninit();
+        let rc = unsafe { ffi::cudaIpcGetMemHandle(handle.as_mut_ptr(), dev_ptr) };
+        if rc != 0 {
+            unsafe { ffi::cudaFree(dev_ptr) };
+            return Err(Error::Cuda(rc));
+        }
+        let handle = unsafe { handle.assume_init() };
+        Ok(Self {
+            ptr: dev_ptr.addr(),
+            len: layout.size(),
+            device,
+            ipc_handle: handle.reserved.to_vec(),
+        })
+    }
+
+    #[cfg(not(feature = "cuda"))]
+    pub fn new(_layout: Layout, _device: i32) -> Result<Self, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// The address of the buffer in the backend's CUDA context.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.ptr
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn device(&self) -> i32 {
+        self.device
+    }
+
+    #[inline]
+    pub fn ipc_handle(&self) -> &[u8] {
+        &self.ipc_handle
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: usize) -> bool {
+        self.ptr <= addr && addr < self.ptr + self.len
+    }
+}
+
+impl Drop for DeviceRegion {
+    fn drop(&mut self) {
+        #[cfg(feature = "cuda")]
+        unsafe {
+            ffi::cudaSetDevice(self.device);
+            ffi::cudaFree(std::ptr::from_exposed_addr_mut(self.ptr));
+        }
+    }
+}
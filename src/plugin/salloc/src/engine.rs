@@ -1,15 +1,20 @@
 use std::alloc::Layout;
-use std::os::unix::io::AsRawFd;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use futures::future::BoxFuture;
 
 use phoenix_api::salloc::cmd;
 
+use super::device::DeviceRegion;
 use super::module::CustomerType;
 use super::region::SharedRegion;
 use super::state::State as SallocState;
+use super::user::{Error as UserRegionError, UserRegion};
 use super::{ControlPathError, ResourceError};
 
 use phoenix_common::engine::datapath::DataPathNode;
@@ -188,6 +193,70 @@ impl SallocEngine {
                     .map_or_else(|| Err(ResourceError::NotFound), |_| Ok(()))?;
                 Ok(cmd::CompletionKind::DeallocShm)
             }
+            Command::AllocDeviceShm(size, align, device) => {
+                tracing::trace!("AllocDeviceShm, size: {}, device: {}", size, device);
+                let layout = Layout::from_size_align(size, align)?;
+                let region = DeviceRegion::new(layout, device)?;
+                let local_addr = region.addr();
+                let ipc_handle = region.ipc_handle().to_vec();
+
+                self.state
+                    .resource()
+                    .device_table
+                    .lock()
+                    .insert(local_addr, Arc::new(region))
+                    .map_or_else(|| Ok(()), |_| Err(ResourceError::Exists))?;
+                Ok(cmd::CompletionKind::AllocDeviceShm(local_addr, ipc_handle))
+            }
+            Command::DeallocDeviceShm(addr) => {
+                // NOTE: the memory is freed once the RDMA transports have dropped the MRs they
+                // have registered on it, which they do the next time they send. The app must not
+                // free a buffer that is still being sent.
+                let resource = self.state.resource();
+                resource
+                    .device_table
+                    .lock()
+                    .remove(&addr)
+                    .map_or_else(|| Err(ResourceError::NotFound), |_| Ok(()))?;
+                resource.device_frees.fetch_add(1, Ordering::Release);
+                Ok(cmd::CompletionKind::DeallocDeviceShm)
+            }
+            Command::RegisterUserMemory(len, file_off) => {
+                tracing::trace!("RegisterUserMemory, len: {}, file_off: {}", len, file_off);
+                // the app sends the fd before the command, a misbehaving one may send none or more
+                let fds = self.customer.try_recv_fd()?.unwrap_or_default();
+                // SAFETY: the fds are received from the app and owned by us
+                let mut files: Vec<_> = fds
+                    .into_iter()
+                    .map(|fd| unsafe { File::from_raw_fd(fd) })
+                    .collect();
+                if files.len() != 1 {
+                    // the files are closed on drop
+                    return Err(UserRegionError::Fds(files.len()).into());
+                }
+                let file = files.pop().unwrap();
+                let region = UserRegion::new(file, len, file_off, &self.state.addr_mediator)?;
+                let local_addr = region.addr();
+
+                self.state
+                    .resource()
+                    .user_table
+                    .lock()
+                    .insert(local_addr, Arc::new(region))
+                    .map_or_else(|| Ok(()), |_| Err(ResourceError::Exists))?;
+                Ok(cmd::CompletionKind::RegisterUserMemory(local_addr))
+            }
+            Command::UnregisterUserMemory(addr) => {
+                // NOTE: the RDMA transports hold the region until the sends from it complete,
+                // so it is only unmapped after the last send.
+                self.state
+                    .resource()
+                    .user_table
+                    .lock()
+                    .remove(&addr)
+                    .map_or_else(|| Err(ResourceError::NotFound), |_| Ok(()))?;
+                Ok(cmd::CompletionKind::UnregisterUserMemory)
+            }
         }
     }
 }
@@ -11,10 +11,12 @@ use phoenix_common::resource::Error as ResourceError;
 use phoenix_common::{InitFnResult, PhoenixModule};
 
 pub mod config;
+pub mod device;
 pub(crate) mod engine;
 pub mod module;
 pub mod region;
 pub mod state;
+pub mod user;
 
 #[derive(Error, Debug)]
 pub enum ControlPathError {
@@ -25,6 +27,10 @@ pub enum ControlPathError {
     Layout(#[from] LayoutError),
     #[error("SharedRegion allocate error: {0}")]
     SharedRegion(#[from] region::Error),
+    #[error("DeviceRegion allocate error: {0}")]
+    DeviceRegion(#[from] device::Error),
+    #[error("UserRegion register error: {0}")]
+    UserRegion(#[from] user::Error),
     // Below are errors that does not return to the user.
     #[error("Ipc-channel TryRecvError")]
     IpcTryRecv,
@@ -50,6 +56,8 @@ impl<T> From<SendError<T>> for ControlPathError {
 use crate::config::SallocConfig;
 use crate::module::SallocModule;
 
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let config = SallocConfig::new(config_string)?;
@@ -9,19 +9,22 @@ use ipc::customer::ShmCustomer;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::salloc::{cmd, dp};
 
+use phoenix_common::checkpoint::{RestoredRegion, ShmRegionDescriptor};
 use phoenix_common::engine::datapath::node::DataPathNode;
 use phoenix_common::engine::{Engine, EnginePair, EngineType};
 use phoenix_common::module::{
     ModuleCollection, ModuleDowncast, NewEngineRequest, PhoenixModule, Service, ServiceInfo,
     Version,
 };
-use phoenix_common::state_mgr::SharedStateManager;
-use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
+use phoenix_common::state_mgr::{ResourceUsage, SharedStateManager};
+use phoenix_common::storage::{
+    get_default_prefix, get_user_dir, ResourceCollection, SharedStorage,
+};
 
 use super::engine::SallocEngine;
 use super::state::{Shared, State};
 use crate::config::SallocConfig;
-use crate::region::AddressMediator;
+use crate::region::{AddressMediator, SharedRegion};
 
 pub(crate) type CustomerType =
     ShmCustomer<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>;
@@ -71,6 +74,8 @@ pub struct SallocModule {
     config: SallocConfig,
     pub state_mgr: SharedStateManager<Shared>,
     addr_mediator: Arc<AddressMediator>,
+    /// The states restored from a checkpoint of the daemon, kept until the clients reattach
+    restored: HashMap<Pid, Arc<Shared>>,
 }
 
 impl SallocModule {
@@ -86,6 +91,7 @@ impl SallocModule {
             config,
             state_mgr: SharedStateManager::new(),
             addr_mediator: Arc::new(AddressMediator::new()),
+            restored: HashMap::new(),
         }
     }
 }
@@ -127,6 +133,7 @@ impl PhoenixModule for SallocModule {
         collections.insert("state_mgr".to_string(), Box::new(module.state_mgr));
         collections.insert("config".to_string(), Box::new(module.config));
         collections.insert("addr_mediator".to_string(), Box::new(module.addr_mediator));
+        collections.insert("restored".to_string(), Box::new(module.restored));
         collections
     }
 
@@ -139,6 +146,39 @@ impl PhoenixModule for SallocModule {
         let prev_concrete = unsafe { *prev_module.downcast_unchecked::<Self>() };
         self.state_mgr = prev_concrete.state_mgr;
         self.addr_mediator = prev_concrete.addr_mediator;
+        self.restored = prev_concrete.restored;
+    }
+
+    fn resource_usage(&self) -> Vec<(Pid, ResourceUsage)> {
+        self.state_mgr.resource_usage()
+    }
+
+    fn reclaim(&mut self, pid: Pid) -> ResourceUsage {
+        let usage = self.state_mgr.reclaim(pid);
+        self.restored.remove(&pid);
+        usage
+    }
+
+    fn checkpoint(&self, pid: Pid) -> Vec<ShmRegionDescriptor> {
+        self.state_mgr.get(pid).map_or_else(Vec::new, |shared| {
+            let mr_table = shared.resource.mr_table.lock();
+            mr_table.values().map(SharedRegion::descriptor).collect()
+        })
+    }
+
+    fn restore(&mut self, pid: Pid, regions: Vec<RestoredRegion>) -> Result<()> {
+        let shared = self.state_mgr.get_or_create(pid)?;
+        {
+            let mut mr_table = shared.resource.mr_table.lock();
+            for region in regions {
+                let restored =
+                    SharedRegion::restore(region.memfd, &region.descriptor, &self.addr_mediator)?;
+                mr_table.insert(region.descriptor.addr, restored);
+            }
+        }
+        // no engine holds the state until the client reattaches
+        self.restored.insert(pid, shared);
+        Ok(())
     }
 
     fn create_engine(
@@ -159,6 +199,7 @@ impl PhoenixModule for SallocModule {
             mode,
             cred,
             config_string: _config_string,
+            protocol,
         } = request
         {
             // 1. generate a path and bind a unix domain socket to it
@@ -170,16 +211,19 @@ impl PhoenixModule for SallocModule {
             // use the phoenix_prefix if not otherwise specified
             let phoenix_prefix = get_default_prefix(global)?;
             let engine_prefix = self.config.prefix.as_ref().unwrap_or(phoenix_prefix);
-            let engine_path = engine_prefix.join(instance_name);
+            // the user has a directory of its own if the daemon isolates the users
+            let socket_dir = get_user_dir(global).unwrap_or(engine_prefix);
+            let engine_path = socket_dir.join(instance_name);
 
             // 2. create customer stub
-            let customer = ShmCustomer::accept(sock, client_path, mode, engine_path)?;
+            let customer = ShmCustomer::accept(sock, client_path, mode, engine_path, protocol)?;
 
             // 3. the following part are expected to be done in the Engine's constructor.
             // the transport module is responsible for initializing and starting the transport engines
             let client_pid = Pid::from_raw(cred.pid.unwrap());
 
             let shared = self.state_mgr.get_or_create(client_pid)?;
+            self.restored.remove(&client_pid);
             let builder = SallocEngineBuilder::new(
                 customer,
                 client_pid,
@@ -1,6 +1,7 @@
 //! Shared memory region.
 
 use std::alloc::Layout;
+use std::fs::File;
 use std::io;
 use std::ops::{Deref, DerefMut};
 use std::os::unix::prelude::AsRawFd;
@@ -10,6 +11,7 @@ use mmap::MmapFixed;
 use thiserror::Error;
 
 use phoenix_api::{AsHandle, Handle};
+use phoenix_common::checkpoint::ShmRegionDescriptor;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -17,6 +19,10 @@ pub enum Error {
     Memfd(#[from] memfd::Error),
     #[error("IO: {0}.")]
     Io(#[from] io::Error),
+    #[error("{0} is not a memfd.")]
+    NotMemfd(String),
+    #[error("Region {0} is smaller than its descriptor.")]
+    Truncated(String),
 }
 
 #[derive(Debug)]
@@ -57,15 +63,46 @@ impl SharedRegion {
             .close_on_exec(false)
             .hugetlb(hugetlb_size);
 
-        let name = format!("shared-mr-{}", nbytes);
-        let memfd = opts.create(name)?;
+        let target_addr = addr_mediator.allocate(layout);
+        let memfd = opts.create(memfd_name(target_addr))?;
         memfd.as_file().set_len(nbytes as u64)?;
 
-        let target_addr = addr_mediator.allocate(layout);
         let mmap = MmapFixed::new(target_addr, nbytes, 0, memfd.as_file())?;
         Ok(Self { mmap, memfd, align })
     }
 
+    /// Maps a region of a checkpoint of the daemon at the address the client maps it, with the
+    /// memfd opened again from the client.
+    pub fn restore(
+        memfd: File,
+        descriptor: &ShmRegionDescriptor,
+        addr_mediator: &AddressMediator,
+    ) -> Result<Self, Error> {
+        let memfd =
+            Memfd::try_from_fd(memfd).map_err(|_| Error::NotMemfd(descriptor.name.clone()))?;
+        if memfd.as_file().metadata()?.len() < descriptor.len as u64 {
+            return Err(Error::Truncated(descriptor.name.clone()));
+        }
+        addr_mediator.reserve(descriptor.addr, descriptor.len);
+        let mmap = MmapFixed::new(descriptor.addr, descriptor.len, 0, memfd.as_file())?;
+        Ok(Self {
+            mmap,
+            memfd,
+            align: descriptor.align,
+        })
+    }
+
+    /// Returns the descriptor of the region to save in a checkpoint of the daemon.
+    pub fn descriptor(&self) -> ShmRegionDescriptor {
+        let addr = self.mmap.as_ptr().addr();
+        ShmRegionDescriptor {
+            name: memfd_name(addr),
+            addr,
+            len: self.mmap.len(),
+            align: self.align,
+        }
+    }
+
     #[inline]
     pub fn memfd(&self) -> &Memfd {
         &self.memfd
@@ -75,6 +112,30 @@ impl SharedRegion {
     pub fn align(&self) -> usize {
         self.align
     }
+
+    /// Returns the memory of `[offset, offset + len)` to the system. The range reads as zeros
+    /// afterwards, in every process that maps the region.
+    pub fn discard(&self, offset: usize, len: usize) -> Result<(), Error> {
+        assert!(offset + len <= self.mmap.len());
+        let ret = unsafe {
+            libc::fallocate(
+                self.memfd.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
+
+/// The name of the memfd of the region at `addr`. The address tells the regions of a client apart,
+/// so a restarted daemon finds the memfd of a region in the client by its name.
+fn memfd_name(addr: usize) -> String {
+    format!("shared-mr-{:x}", addr)
 }
 
 impl AsRef<SharedRegion> for SharedRegion {
@@ -111,9 +172,16 @@ impl AddressMediator {
         *current = next + layout.size();
         next
     }
+
+    /// Takes `[addr, addr + len)` out of the addresses to allocate, for a region restored from a
+    /// checkpoint of the daemon.
+    pub(crate) fn reserve(&self, addr: usize, len: usize) {
+        let mut current = self.current.lock();
+        *current = (*current).max(addr + len);
+    }
 }
 
-fn page_size() -> usize {
+pub(crate) fn page_size() -> usize {
     use std::sync::atomic::{AtomicUsize, Ordering};
     static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
 
@@ -1,13 +1,16 @@
 use std::collections::BTreeMap;
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use nix::unistd::Pid;
 
+use crate::device::DeviceRegion;
 use crate::region::AddressMediator;
+use crate::user::UserRegion;
 
 use super::region::SharedRegion;
-use phoenix_common::state_mgr::ProcessShared;
+use phoenix_common::state_mgr::{ProcessShared, ResourceUsage};
 
 pub struct State {
     pub(crate) shared: Arc<Shared>,
@@ -45,17 +48,120 @@ impl ProcessShared for Shared {
         };
         Ok(shared)
     }
+
+    fn resource_usage(&self) -> ResourceUsage {
+        ResourceUsage::from([
+            ("shm_region", self.resource.mr_table.lock().len()),
+            ("device_region", self.resource.device_table.lock().len()),
+            ("user_region", self.resource.user_table.lock().len()),
+        ])
+    }
+
+    fn reclaim(&self) {
+        self.resource.mr_table.lock().clear();
+        self.resource.device_table.lock().clear();
+        self.resource.user_table.lock().clear();
+    }
 }
 
 pub struct Resource {
     // TODO(wyj): apply the alignment trick and replace the BTreeMap here.
     pub(crate) mr_table: spin::Mutex<BTreeMap<usize, SharedRegion>>,
+    // device memory regions, indexed by the backend address
+    pub(crate) device_table: spin::Mutex<BTreeMap<usize, Arc<DeviceRegion>>>,
+    // the number of the device regions freed by the app, to tell the MRs over them are stale
+    pub(crate) device_frees: AtomicUsize,
+    // user memory regions registered by the app, indexed by the backend address
+    pub(crate) user_table: spin::Mutex<BTreeMap<usize, Arc<UserRegion>>>,
 }
 
 impl Resource {
     fn new() -> Self {
         Self {
             mr_table: spin::Mutex::new(BTreeMap::default()),
+            device_table: spin::Mutex::new(BTreeMap::default()),
+            device_frees: AtomicUsize::new(0),
+            user_table: spin::Mutex::new(BTreeMap::default()),
+        }
+    }
+
+    /// Finds the device region that contains `addr`. The returned reference keeps the region
+    /// allocated after the app frees it.
+    pub fn lookup_device_region(&self, addr: usize) -> Option<Arc<DeviceRegion>> {
+        let device_table = self.device_table.lock();
+        device_table
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, region)| region.contains(addr))
+            .map(|(_, region)| Arc::clone(region))
+    }
+
+    /// Returns whether `region` has not been freed by the app.
+    pub fn is_device_region_allocated(&self, region: &Arc<DeviceRegion>) -> bool {
+        self.device_table
+            .lock()
+            .get(&region.addr())
+            .map_or(false, |current| Arc::ptr_eq(current, region))
+    }
+
+    /// Returns the number of the device regions freed by the app so far. The MRs over the
+    /// device regions only need to be checked when it changes.
+    #[inline]
+    pub fn device_frees(&self) -> usize {
+        self.device_frees.load(Ordering::Acquire)
+    }
+
+    /// Returns whether `[addr, addr + len)` lies in a region of the shared heap, which the app
+    /// maps at the same addresses.
+    pub fn in_shared_heap(&self, addr: usize, len: usize) -> bool {
+        let Some(end) = addr.checked_add(len) else {
+            return false;
+        };
+        self.mr_table
+            .lock()
+            .range(..=addr)
+            .next_back()
+            .map_or(false, |(&start, region)| end <= start + region.len())
+    }
+
+    /// Finds the user memory region that contains `addr`. The returned reference keeps the
+    /// region mapped after the app unregisters it.
+    pub fn lookup_user_region(&self, addr: usize) -> Option<Arc<UserRegion>> {
+        let user_table = self.user_table.lock();
+        user_table
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, region)| region.contains(addr))
+            .map(|(_, region)| Arc::clone(region))
+    }
+
+    /// Returns whether `[addr, addr + len)` lies in a region of the app, on the shared heap, on a
+    /// device or in user memory.
+    pub fn contains(&self, addr: usize, len: usize) -> bool {
+        let Some(end) = addr.checked_add(len) else {
+            return false;
+        };
+        let within = |start: usize, region_len: usize| addr >= start && end <= start + region_len;
+        if let Some((&start, region)) = self.mr_table.lock().range(..=addr).next_back() {
+            if within(start, region.len()) {
+                return true;
+            }
+        }
+        if let Some((&start, region)) = self.device_table.lock().range(..=addr).next_back() {
+            if within(start, region.len()) {
+                return true;
+            }
         }
+        if let Some((&start, region)) = self.user_table.lock().range(..=addr).next_back() {
+            if within(start, region.len()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns whether the user memory region starting at `addr` is still registered.
+    pub fn is_user_region_registered(&self, addr: usize) -> bool {
+        self.user_table.lock().contains_key(&addr)
     }
 }
@@ -0,0 +1,82 @@
+//! User memory region.
+//!
+//! An application registers memory that it allocated itself, e.g., a large dataset mapped from a
+//! file, so that the memory can be sent as message payload without being copied to the shared
+//! heap. The memory must be backed by a file or a memfd. The application sends the file
+//! descriptor, and the backend maps the same pages read-only at an address taken from the
+//! `AddressMediator`. The RDMA transports register MRs over the backend mapping.
+//!
+//! The regions are reference counted. Unregistering a region only removes it from the table, the
+//! transports keep their references until the sends from the region complete, so the mapping
+//! outlives the last send.
+use std::alloc::Layout;
+use std::fs::File;
+use std::io;
+
+use mmap::MmapFixed;
+use thiserror::Error;
+
+use crate::region::{page_size, AddressMediator};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO: {0}.")]
+    Io(#[from] io::Error),
+    #[error("File offset {0} is not page aligned.")]
+    Alignment(i64),
+    #[error("Range [{0}, {0} + {1}) is empty or beyond the end of the file ({2} bytes).")]
+    OutOfRange(i64, usize, u64),
+    #[error("Expect the fd of the file to map, received {0} fds.")]
+    Fds(usize),
+}
+
+#[derive(Debug)]
+pub struct UserRegion {
+    mmap: MmapFixed,
+    // keeps the file open as long as it is mapped
+    _file: File,
+}
+
+impl UserRegion {
+    /// Maps `[file_off, file_off + len)` of `file` into the backend.
+    pub fn new(
+        file: File,
+        len: usize,
+        file_off: i64,
+        addr_mediator: &AddressMediator,
+    ) -> Result<Self, Error> {
+        if file_off < 0 || file_off as usize % page_size() != 0 {
+            return Err(Error::Alignment(file_off));
+        }
+        let file_len = file.metadata()?.len();
+        if len == 0 || file_off as u64 + len as u64 > file_len {
+            return Err(Error::OutOfRange(file_off, len, file_len));
+        }
+
+        let layout = Layout::from_size_align(len, page_size()).unwrap();
+        let target_addr = addr_mediator.allocate(layout);
+        let mmap = MmapFixed::new_readonly(target_addr, len, file_off, &file)?;
+        Ok(Self { mmap, _file: file })
+    }
+
+    /// The address of the region in the backend.
+    #[inline]
+    pub fn addr(&self) -> usize {
+        self.mmap.as_ptr().addr()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.mmap.len() == 0
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: usize) -> bool {
+        self.addr() <= addr && addr < self.addr() + self.len()
+    }
+}
@@ -10,6 +10,8 @@ pub struct RdmaTransportConfig {
     pub datapath_wq_depth: usize,
     pub datapath_cq_depth: usize,
     pub command_max_interval_ms: u32,
+    /// Reuse of the registered memory regions
+    pub mr_cache: MrCacheConfig,
 }
 
 impl Default for RdmaTransportConfig {
@@ -20,6 +22,7 @@ impl Default for RdmaTransportConfig {
             datapath_wq_depth: 32,
             datapath_cq_depth: 32,
             command_max_interval_ms: 1000,
+            mr_cache: MrCacheConfig::default(),
         }
     }
 }
@@ -30,3 +33,29 @@ impl RdmaTransportConfig {
         Ok(config)
     }
 }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MrCacheConfig {
+    /// Whether to keep the deregistered MRs registered for reuse. A parked MR keeps its rkey,
+    /// so a peer that still holds the rkey can access the memory after it is handed to another
+    /// user. Only enable it if the peers are trusted.
+    pub enable: bool,
+    /// The total bytes of the idle MRs kept registered
+    pub capacity_bytes: usize,
+    /// Idle MRs are deregistered after this long
+    pub idle_timeout_ms: u64,
+    /// Register MRs with on-demand paging if the NIC supports it
+    pub odp: bool,
+}
+
+impl Default for MrCacheConfig {
+    fn default() -> Self {
+        MrCacheConfig {
+            enable: false,
+            capacity_bytes: 256 << 20,
+            idle_timeout_ms: 10000,
+            odp: false,
+        }
+    }
+}
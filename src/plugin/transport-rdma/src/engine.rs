@@ -12,7 +12,7 @@ use phoenix_api::engine::SchedulingMode;
 use phoenix_api::net;
 use phoenix_api::net::returned;
 use phoenix_api::transport::rdma::{cmd, dp};
-use phoenix_api::{AsHandle, Handle};
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
 
 // use rdma::ibv;
 use rdma::rdmacm;
@@ -305,7 +305,7 @@ impl TransportEngine {
                     .ops
                     .resource()
                     .cmid_table
-                    .get_dp(cmid_handle.0 as usize)
+                    .get_dp(cmid_handle.id() as usize)
                 {
                     if let Some(qp) = cmid.qp() {
                         (net::CompletionQueue(qp.send_cq().as_handle()), *wr_id)
@@ -321,7 +321,7 @@ impl TransportEngine {
                     .ops
                     .resource()
                     .cmid_table
-                    .get_dp(cmid_handle.0 as usize)
+                    .get_dp(cmid_handle.id() as usize)
                 {
                     if let Some(qp) = cmid.qp() {
                         (net::CompletionQueue(qp.recv_cq().as_handle()), *wr_id)
@@ -338,7 +338,7 @@ impl TransportEngine {
                     .ops
                     .resource()
                     .cmid_table
-                    .get_dp(cmid_handle.0 as usize)
+                    .get_dp(cmid_handle.id() as usize)
                 {
                     if let Some(qp) = cmid.qp() {
                         (net::CompletionQueue(qp.send_cq().as_handle()), *wr_id)
@@ -354,7 +354,7 @@ impl TransportEngine {
                     .ops
                     .resource()
                     .cmid_table
-                    .get_dp(cmid_handle.0 as usize)
+                    .get_dp(cmid_handle.id() as usize)
                 {
                     if let Some(qp) = cmid.qp() {
                         (net::CompletionQueue(qp.send_cq().as_handle()), *wr_id)
@@ -480,7 +480,11 @@ impl TransportEngine {
         use dp::WorkRequest;
         match req {
             WorkRequest::PostRecv(cmid_handle, wr_id, range, mr_handle) => {
-                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let mr = self
+                    .ops
+                    .resource()
+                    .mr_table
+                    .get_dp(mr_handle.id() as usize)?;
                 let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
                 unsafe {
                     self.ops.post_recv(*cmid_handle, &rdma_mr, *range, *wr_id)?;
@@ -488,7 +492,11 @@ impl TransportEngine {
                 Ok(())
             }
             WorkRequest::PostSend(cmid_handle, wr_id, range, mr_handle, send_flags) => {
-                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let mr = self
+                    .ops
+                    .resource()
+                    .mr_table
+                    .get_dp(mr_handle.id() as usize)?;
                 let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
                 unsafe {
                     self.ops
@@ -497,7 +505,11 @@ impl TransportEngine {
                 Ok(())
             }
             WorkRequest::PostSendWithImm(cmid_handle, wr_id, range, mr_handle, send_flags, imm) => {
-                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let mr = self
+                    .ops
+                    .resource()
+                    .mr_table
+                    .get_dp(mr_handle.id() as usize)?;
                 let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
                 unsafe {
                     self.ops.post_send_with_imm(
@@ -520,7 +532,11 @@ impl TransportEngine {
                 rkey,
                 send_flags,
             ) => {
-                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let mr = self
+                    .ops
+                    .resource()
+                    .mr_table
+                    .get_dp(mr_handle.id() as usize)?;
                 let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
                 unsafe {
                     self.ops.post_write(
@@ -544,7 +560,11 @@ impl TransportEngine {
                 rkey,
                 send_flags,
             ) => {
-                let mr = self.ops.resource().mr_table.get_dp(mr_handle.0 as usize)?;
+                let mr = self
+                    .ops
+                    .resource()
+                    .mr_table
+                    .get_dp(mr_handle.id() as usize)?;
                 let rdma_mr = rdmacm::MemoryRegion::from(mr.as_ref());
                 unsafe {
                     self.ops.post_read(
@@ -715,7 +735,7 @@ impl TransportEngine {
                     .mr_table
                     .occupy_or_create_resource(raw_mr_handle, mr)
                     .map_err(ApiError::from)?;
-                let new_mr_handle = Handle(key as u64);
+                let new_mr_handle = Handle::new(HandleNamespace::Mr, key as u64);
 
                 let ret_mr = returned::MemoryRegion {
                     handle: net::MemoryRegion(new_mr_handle),
@@ -730,11 +750,16 @@ impl TransportEngine {
             }
             Command::DeregMr(mr) => {
                 tracing::trace!("DeregMr, mr: {:?}", mr);
-                self.ops
+                let closed = self
+                    .ops
                     .resource()
                     .mr_table
-                    .close_resource_by_key(mr.0 .0 as usize)
+                    .close_resource_by_key(mr.0.id() as usize)
                     .map_err(ApiError::from)?;
+                // the last reference is closed, keep it registered for later RegMr
+                if let Some(closed) = closed {
+                    self.ops.resource().mr_cache.put(closed.into_inner());
+                }
                 Ok(CompletionKind::DeregMr)
             }
             Command::DeallocPd(pd) => {
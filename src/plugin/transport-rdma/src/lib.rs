@@ -12,6 +12,7 @@ pub(crate) mod cm;
 pub mod config;
 pub(crate) mod engine;
 pub mod module;
+pub mod mr_cache;
 
 #[allow(clippy::too_many_arguments)]
 pub mod ops;
@@ -40,6 +41,8 @@ pub enum ApiError {
     Mio(io::Error),
     #[error("No CM event")]
     NoCmEvent,
+    #[error("No QP is created on the CmId")]
+    NoQp,
     #[error("Transport specific error: {0}")]
     Transport(i32),
 }
@@ -125,6 +128,8 @@ impl DatapathError {
 
 use crate::config::RdmaTransportConfig;
 use crate::module::RdmaTransportModule;
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let config = RdmaTransportConfig::new(config_string)?;
@@ -9,6 +9,7 @@ use uuid::Uuid;
 
 use ipc::customer::ShmCustomer;
 use ipc::unix::DomainSocket;
+use ipc::version::ProtocolVersion;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::transport::rdma::{cmd, dp};
 
@@ -18,8 +19,10 @@ use phoenix_common::module::{
     ModuleCollection, ModuleDowncast, NewEngineRequest, PhoenixModule, Service, ServiceInfo,
     Version,
 };
-use phoenix_common::state_mgr::SharedStateManager;
-use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
+use phoenix_common::state_mgr::{ResourceUsage, SharedStateManager};
+use phoenix_common::storage::{
+    get_default_prefix, get_user_dir, ResourceCollection, SharedStorage,
+};
 
 use crate::cm::engine::CmEngine;
 use crate::config::RdmaTransportConfig;
@@ -158,6 +161,14 @@ impl PhoenixModule for RdmaTransportModule {
         self.state_mgr = prev_concrete.state_mgr;
     }
 
+    fn resource_usage(&self) -> Vec<(Pid, ResourceUsage)> {
+        self.state_mgr.resource_usage()
+    }
+
+    fn reclaim(&mut self, pid: Pid) -> ResourceUsage {
+        self.state_mgr.reclaim(pid)
+    }
+
     fn create_engine(
         &mut self,
         ty: EngineType,
@@ -189,6 +200,7 @@ impl PhoenixModule for RdmaTransportModule {
                     mode,
                     cred,
                     config_string,
+                    protocol,
                 } = request
                 {
                     let phoenix_prefix = get_default_prefix(global)?;
@@ -199,7 +211,9 @@ impl PhoenixModule for RdmaTransportModule {
                         node,
                         cred,
                         phoenix_prefix,
+                        get_user_dir(global),
                         config_string,
+                        protocol,
                     )?;
                     Ok(Some(Box::new(engine)))
                 } else {
@@ -245,16 +259,19 @@ impl RdmaTransportModule {
         node: DataPathNode,
         cred: &UCred,
         phoenix_prefix: &PathBuf,
+        user_dir: Option<&PathBuf>,
         _config_string: Option<String>,
+        protocol: ProtocolVersion,
     ) -> Result<TransportEngine> {
         let uuid = Uuid::new_v4();
         let instance_name = format!("{}-{}.sock", self.config.engine_basename, uuid);
 
         // use the phoenix_prefix if not otherwise specified
         let engine_prefix = self.config.prefix.as_ref().unwrap_or(phoenix_prefix);
-        let engine_path = engine_prefix.join(instance_name);
+        // the user has a directory of its own if the daemon isolates the users
+        let engine_path = user_dir.unwrap_or(engine_prefix).join(instance_name);
 
-        let customer = ShmCustomer::accept(sock, client_path, mode, engine_path)?;
+        let customer = ShmCustomer::accept(sock, client_path, mode, engine_path, protocol)?;
 
         // 3. the following part are expected to be done in the Engine's constructor.
         // the transport module is responsible for initializing and starting the transport engines
@@ -298,6 +315,7 @@ impl RdmaTransportModule {
         }
 
         let shared = self.state_mgr.get_or_create(client_pid)?;
+        shared.resource.mr_cache.configure(self.config.mr_cache);
         let state = State::new(shared);
 
         Ok(Ops::new(state))
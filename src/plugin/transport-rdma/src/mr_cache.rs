@@ -0,0 +1,168 @@
+//! A cache of registered memory regions.
+//!
+//! Registering an MR allocates and maps its memory and pins the pages on the NIC, which is the bulk
+//! of the cost of `RegMr`. When the last reference to an MR is closed, the MR is parked here
+//! instead of being deregistered, and a later `RegMr` of the same size, protection domain and
+//! access flags takes it back. Parked MRs are deregistered lazily, once they have been idle for
+//! too long or when the cache grows beyond its capacity.
+//!
+//! A parked MR is never deregistered, so its rkey stays valid across reuses: a remote peer that
+//! kept the rkey from an earlier user of the MR can read and write the memory of the later one.
+//! The cache is therefore disabled unless it is enabled in the config.
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use phoenix_api::net::AccessFlags;
+use phoenix_api::{AsHandle, Handle};
+use rdma::mr::MemoryRegion;
+
+use super::config::MrCacheConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    pd: Handle,
+    nbytes: usize,
+    access: AccessFlags,
+}
+
+impl Key {
+    fn of(mr: &MemoryRegion) -> Self {
+        Key {
+            pd: mr.pd().as_handle(),
+            nbytes: mr.len(),
+            access: mr.access(),
+        }
+    }
+}
+
+struct IdleMr {
+    key: Key,
+    mr: MemoryRegion,
+    since: Instant,
+}
+
+struct Inner {
+    config: MrCacheConfig,
+    // ordered by the time the MRs are parked, the oldest first
+    idle: VecDeque<IdleMr>,
+    cached_bytes: usize,
+}
+
+impl Inner {
+    /// Removes the MRs that have expired or do not fit in the capacity.
+    fn evict(&mut self, now: Instant) -> Vec<MemoryRegion> {
+        let timeout = Duration::from_millis(self.config.idle_timeout_ms);
+        let mut evicted = Vec::new();
+        while let Some(front) = self.idle.front() {
+            if self.cached_bytes <= self.config.capacity_bytes
+                && now.saturating_duration_since(front.since) < timeout
+            {
+                break;
+            }
+            let front = self.idle.pop_front().unwrap();
+            self.cached_bytes -= front.key.nbytes;
+            evicted.push(front.mr);
+        }
+        evicted
+    }
+}
+
+pub struct MrCache {
+    inner: spin::Mutex<Inner>,
+}
+
+impl Default for MrCache {
+    fn default() -> Self {
+        MrCache {
+            inner: spin::Mutex::new(Inner {
+                config: MrCacheConfig::default(),
+                idle: VecDeque::new(),
+                cached_bytes: 0,
+            }),
+        }
+    }
+}
+
+impl MrCache {
+    pub(crate) fn configure(&self, config: MrCacheConfig) {
+        self.inner.lock().config = config;
+    }
+
+    pub(crate) fn config(&self) -> MrCacheConfig {
+        self.inner.lock().config
+    }
+
+    /// Returns the number of the parked MRs.
+    pub fn len(&self) -> usize {
+        self.inner.lock().idle.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes a parked MR that matches the request. The memory is zeroed as if the MR were just
+    /// registered.
+    pub(crate) fn take(
+        &self,
+        pd: Handle,
+        nbytes: usize,
+        access: AccessFlags,
+    ) -> Option<MemoryRegion> {
+        let key = Key { pd, nbytes, access };
+        let (mr, evicted) = {
+            let mut inner = self.inner.lock();
+            let evicted = inner.evict(Instant::now());
+            let mr = inner
+                .idle
+                .iter()
+                .rposition(|e| e.key == key)
+                .and_then(|pos| inner.idle.remove(pos))
+                .map(|e| e.mr);
+            if mr.is_some() {
+                inner.cached_bytes -= nbytes;
+            }
+            (mr, evicted)
+        };
+        // deregister without holding the lock
+        mem::drop(evicted);
+
+        mr.map(|mut mr| {
+            mr.fill(0);
+            mr
+        })
+    }
+
+    /// Parks an MR that is no longer referenced, or deregisters it if the cache is disabled.
+    pub(crate) fn put(&self, mr: MemoryRegion) {
+        let key = Key::of(&mr);
+        let evicted = {
+            let mut inner = self.inner.lock();
+            if !inner.config.enable || key.nbytes > inner.config.capacity_bytes {
+                mem::drop(inner);
+                mem::drop(mr);
+                return;
+            }
+            let now = Instant::now();
+            inner.idle.push_back(IdleMr {
+                key,
+                mr,
+                since: now,
+            });
+            inner.cached_bytes += key.nbytes;
+            inner.evict(now)
+        };
+        mem::drop(evicted);
+    }
+
+    /// Deregisters all the parked MRs.
+    pub(crate) fn clear(&self) {
+        let idle = {
+            let mut inner = self.inner.lock();
+            inner.cached_bytes = 0;
+            mem::take(&mut inner.idle)
+        };
+        mem::drop(idle);
+    }
+}
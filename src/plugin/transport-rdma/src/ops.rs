@@ -1,7 +1,8 @@
 //! Providing the API implemention for both TransportEngine and RpcAdapter.
 //! The API design requires a bit finesse.
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::ptr;
 use std::slice;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -17,7 +18,7 @@ use rdma::rdmacm::CmId;
 use phoenix_common::engine::future;
 use phoenix_common::log;
 
-use super::state::{EventChannel, Resource, State};
+use super::state::{supports_odp, EventChannel, Resource, State};
 use super::{ApiError, DatapathError};
 
 pub type Result<T> = std::result::Result<T, ApiError>;
@@ -67,7 +68,10 @@ impl Ops {
         //     user_buf,
         //     mr_handle
         // );
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
 
         // since post_recv itself is already unsafe, it is the user's responsibility to
         // make sure the received data is valid. The user must avoid post_recv a same
@@ -103,7 +107,10 @@ impl Ops {
         //     mr_handle,
         //     send_flags,
         // );
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
 
         // let rdma_mr = rdmacm::MemoryRegion::from(mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
@@ -129,7 +136,10 @@ impl Ops {
         send_flags: net::SendFlags,
         imm: u32,
     ) -> std::result::Result<(), DatapathError> {
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
 
         // let rdma_mr = rdmacm::MemoryRegion::from(&mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
@@ -140,6 +150,42 @@ impl Ops {
         Ok(())
     }
 
+    /// Sends a datagram through the unreliable datagram QP of `cmid_handle` to the QP `remote_qpn`
+    /// at the port addressed by `ah`.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `Ops::poll_cq` returns a completion for this receive).
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_ud_send_with_imm(
+        &self,
+        cmid_handle: Handle,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+        send_flags: net::SendFlags,
+        imm: u32,
+        ah: &net::AddressHandle,
+        remote_qpn: u32,
+        remote_qkey: u32,
+    ) -> std::result::Result<(), DatapathError> {
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
+        let ah = self.resource().ah_table.get_dp(&ah.0)?;
+
+        let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
+
+        let flags: ibv::SendFlags = send_flags.into();
+        cmid.post_ud_send_with_imm(wr_id, buf, mr, flags.0, imm, &ah, remote_qpn, remote_qkey)
+            .map_err(DatapathError::RdmaCm)?;
+        Ok(())
+    }
+
     /// # Safety
     ///
     /// The memory region can only be safely reused or dropped after the request is fully executed
@@ -156,7 +202,10 @@ impl Ops {
         remote_offset: u64,
         send_flags: net::SendFlags,
     ) -> std::result::Result<(), DatapathError> {
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
 
         // let rdma_mr = rdmacm::MemoryRegion::from(mr);
         let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
@@ -185,7 +234,10 @@ impl Ops {
         remote_offset: u64,
         send_flags: net::SendFlags,
     ) -> std::result::Result<(), DatapathError> {
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
 
         let remote_addr = rkey.addr + remote_offset;
         let flags: ibv::SendFlags = send_flags.into();
@@ -198,6 +250,31 @@ impl Ops {
         Ok(())
     }
 
+    /// Posts a receive request to the shared receive queue `srq`. The completion comes from the
+    /// CQ of the QP that the message arrives at.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `Ops::poll_cq` returns a completion for this receive).
+    #[inline]
+    pub unsafe fn post_srq_recv(
+        &self,
+        srq: &net::SharedReceiveQueue,
+        mr: &rdmacm::MemoryRegion,
+        range: phoenix_api::buf::Range,
+        wr_id: u64,
+    ) -> std::result::Result<(), DatapathError> {
+        let srq = self.resource().srq_table.get_dp(&srq.0)?;
+
+        let buf = &mr[range.offset as usize..(range.offset + range.len) as usize];
+        let buf_mut = slice::from_raw_parts_mut(buf.as_ptr() as _, buf.len());
+        srq.post_recv(wr_id, buf_mut, mr)
+            .map_err(DatapathError::Ibv)?;
+        Ok(())
+    }
+
     #[inline]
     pub fn poll_cq(
         &self,
@@ -229,10 +306,78 @@ impl Ops {
     pub fn get_sgid(&self, cmid_handle: Handle) -> Result<ibv::Gid> {
         log::debug!("GetSgid, cmid_handle: {:?}", cmid_handle,);
 
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         Ok(cmid.sgid())
     }
 
+    /// Returns the number of the QP of `cmid_handle`.
+    pub fn get_qp_num(&self, cmid_handle: Handle) -> Result<u32> {
+        log::debug!("GetQpNum, cmid_handle: {:?}", cmid_handle);
+
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
+        let qp = cmid.qp().ok_or(ApiError::NoQp)?;
+        Ok(qp.qp_num())
+    }
+
+    /// Returns the most bytes a send on the QP of `cmid_handle` can carry inline.
+    pub fn get_max_inline_data(&self, cmid_handle: Handle) -> Result<u32> {
+        log::debug!("GetMaxInlineData, cmid_handle: {:?}", cmid_handle);
+
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
+        let qp = cmid.qp().ok_or(ApiError::NoQp)?;
+        qp.max_inline_data().map_err(ApiError::Ibv)
+    }
+
+    /// Creates an address handle to send datagrams from the unreliable datagram QP of
+    /// `cmid_handle` to the port with `dgid`.
+    pub fn create_ah(&self, cmid_handle: Handle, dgid: &ibv::Gid) -> Result<net::AddressHandle> {
+        log::debug!("CreateAh, cmid_handle: {:?}, dgid: {:?}", cmid_handle, dgid);
+
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
+        let pd = cmid.qp().ok_or(ApiError::NoQp)?.pd();
+        let sgid_index = pd
+            .context()
+            .gid_index(&cmid.sgid())
+            .map_err(ApiError::Ibv)?;
+        let ah = pd.create_ah(*dgid, sgid_index).map_err(ApiError::Ibv)?;
+        let handle = ah.as_handle();
+        self.resource().ah_table.insert(handle, ah)?;
+        Ok(net::AddressHandle(handle))
+    }
+
+    pub fn destroy_ah(&self, ah: &net::AddressHandle) -> Result<()> {
+        log::debug!("DestroyAh, ah: {:?}", ah);
+        self.resource().ah_table.close_resource(&ah.0)?;
+        Ok(())
+    }
+
+    /// Creates a shared receive queue in `pd` for the QPs that are created with it afterwards.
+    pub fn create_srq(
+        &self,
+        pd: &net::ProtectionDomain,
+        max_wr: u32,
+        max_sge: u32,
+    ) -> Result<net::SharedReceiveQueue> {
+        log::debug!(
+            "CreateSrq, pd: {:?}, max_wr: {}, max_sge: {}",
+            pd,
+            max_wr,
+            max_sge
+        );
+
+        let pd = self.resource().pd_table.get(&pd.0)?;
+        let srq = pd.create_srq(max_wr, max_sge).map_err(ApiError::Ibv)?;
+        let handle = srq.as_handle();
+        self.resource().srq_table.insert(handle, srq)?;
+        Ok(net::SharedReceiveQueue(handle))
+    }
+
+    pub fn destroy_srq(&self, srq: &net::SharedReceiveQueue) -> Result<()> {
+        log::debug!("DestroySrq, srq: {:?}", srq);
+        self.resource().srq_table.close_resource(&srq.0)?;
+        Ok(())
+    }
+
     pub fn find_verbs_by_sgid(&self, sgid: &ibv::Gid) -> Result<Option<returned::VerbsContext>> {
         log::debug!("FindVerbsBySgid, sgid: {:?}", sgid,);
 
@@ -323,7 +468,7 @@ impl Ops {
         let cmid = self
             .resource()
             .cmid_table
-            .get(returned_cmid.handle.0 .0 as usize)?;
+            .get(returned_cmid.handle.0.id() as usize)?;
         let ec_handle = cmid.event_channel().as_handle();
         Ok((
             returned_cmid,
@@ -390,7 +535,7 @@ impl Ops {
             backlog
         );
 
-        let listener = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let listener = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         listener.listen(backlog).map_err(ApiError::RdmaCm)?;
         Ok(())
     }
@@ -436,7 +581,10 @@ impl Ops {
         log::debug!("GetRequest, listener_handle: {:?}", listener_handle);
 
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_CONNECT_REQUEST;
-        let listener_cmid = self.resource().cmid_table.get(listener_handle.0 as usize)?;
+        let listener_cmid = self
+            .resource()
+            .cmid_table
+            .get(listener_handle.id() as usize)?;
         let ec_handle = listener_cmid.event_channel().as_handle();
         let event = self.wait_cm_event(&ec_handle, event_type).await?;
 
@@ -448,7 +596,10 @@ impl Ops {
         // log::trace!("TryGetRequest, listener_handle: {:?}", listener_handle);
 
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_CONNECT_REQUEST;
-        let listener_cmid = self.resource().cmid_table.get(listener_handle.0 as usize)?;
+        let listener_cmid = self
+            .resource()
+            .cmid_table
+            .get(listener_handle.id() as usize)?;
         let ec_handle = listener_cmid.event_channel().as_handle();
         let res = self.try_get_cm_event(&ec_handle, event_type);
         if res.is_none() {
@@ -489,7 +640,7 @@ impl Ops {
             conn_param
         );
 
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         cmid.accept(self.get_conn_param(conn_param).as_ref())
             .map_err(ApiError::RdmaCm)?;
 
@@ -512,7 +663,7 @@ impl Ops {
             conn_param
         );
 
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         cmid.connect(self.get_conn_param(conn_param).as_ref())
             .map_err(ApiError::RdmaCm)?;
 
@@ -531,7 +682,7 @@ impl Ops {
             sockaddr
         );
 
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         cmid.bind_addr(sockaddr).map_err(ApiError::RdmaCm)?;
         Ok(())
     }
@@ -543,7 +694,7 @@ impl Ops {
             sockaddr
         );
 
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         cmid.resolve_addr(sockaddr).map_err(ApiError::RdmaCm)?;
 
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_ADDR_RESOLVED;
@@ -560,7 +711,7 @@ impl Ops {
             timeout_ms
         );
 
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         cmid.resolve_route(timeout_ms).map_err(ApiError::RdmaCm)?;
 
         let event_type = rdma::ffi::rdma_cm_event_type::RDMA_CM_EVENT_ROUTE_RESOLVED;
@@ -583,7 +734,7 @@ impl Ops {
             qp_init_attr
         );
 
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
 
         let pd = pd.cloned().or_else(|| {
             // use the default pd of the corresponding device
@@ -605,15 +756,22 @@ impl Ops {
 
     /// Must be set before resolve_addr
     pub fn set_tos(&self, cmid_handle: Handle, tos: u8) -> Result<()> {
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         // assert!(cmid.qp().is_some(), "this must be called after QP is created");
         cmid.set_tos(tos).map_err(ApiError::RdmaCm)?;
         Ok(())
     }
 
+    /// Must be set before bind_addr
+    pub fn set_afonly(&self, cmid_handle: Handle, afonly: bool) -> Result<()> {
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
+        cmid.set_afonly(afonly).map_err(ApiError::RdmaCm)?;
+        Ok(())
+    }
+
     /// Must be after connect/accept
     pub fn set_rnr_timeout(&self, cmid_handle: Handle, min_rnr_timer: u8) -> Result<()> {
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         // assert!(cmid.qp().is_some(), "this must be called after QP is created");
         cmid.set_rnr_timeout(min_rnr_timer)
             .map_err(ApiError::RdmaCm)?;
@@ -621,6 +779,7 @@ impl Ops {
     }
 
     // NOTE(cjr): reg_mr does not insert the MR to its table.
+    /// Takes an MR from the MR cache if there is a match, registers a new one otherwise.
     pub fn reg_mr(
         &self,
         pd: &net::ProtectionDomain,
@@ -634,7 +793,17 @@ impl Ops {
             access
         );
 
-        let pd = self.resource().pd_table.get(&pd.0)?;
+        let pd_handle = pd.0;
+        let pd = self.resource().pd_table.get(&pd_handle)?;
+        let mr_cache = &self.resource().mr_cache;
+        let access = if mr_cache.config().odp && supports_odp(pd.context()) {
+            access | net::AccessFlags::ON_DEMAND
+        } else {
+            access
+        };
+        if let Some(mr) = mr_cache.take(pd_handle, nbytes, access) {
+            return Ok(mr);
+        }
         let mr = MemoryRegion::new(&pd, nbytes, access)
             .map_err(ApiError::MemoryRegion)
             .expect("something is wrong; remove this expect() later");
@@ -709,7 +878,7 @@ impl Ops {
         log::debug!("Disconnect, cmid: {:?}", cmid);
 
         let cmid_handle = cmid.0;
-        let cmid = self.resource().cmid_table.get(cmid_handle.0 as usize)?;
+        let cmid = self.resource().cmid_table.get(cmid_handle.id() as usize)?;
         // use the context to distinguish if the connection is disconnected
         if let Ok(0) =
             unsafe { &*cmid.context() }.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
@@ -735,7 +904,7 @@ impl Ops {
         let maybe_id = self
             .resource()
             .cmid_table
-            .close_resource_by_key(cmid.0 .0 as usize)?;
+            .close_resource_by_key(cmid.0.id() as usize)?;
 
         // NOTE(cjr): This code following is very ugly. Ultimately the goal is to drop CmEvents
         // before destroying CmId before dropping EventChannel
@@ -800,6 +969,31 @@ impl Ops {
         Ok(pds)
     }
 
+    /// Returns the IP address of the device `nic_index`, in the order of [`Ops::get_default_pds`],
+    /// as found in its GID table. An IPv4 address is preferred to an IPv6 one.
+    pub fn get_device_addr(&self, nic_index: usize) -> Result<IpAddr> {
+        log::debug!("GetDeviceAddr, nic_index: {}", nic_index);
+        let (_pd, gids) = self
+            .resource()
+            .default_pds
+            .get(nic_index)
+            .ok_or(ApiError::NotFound)?;
+        let addrs: Vec<Ipv6Addr> = gids
+            .iter()
+            .map(|gid| Ipv6Addr::from(gid.raw()))
+            .filter(|addr| !addr.is_unspecified())
+            .collect();
+        if let Some(addr) = addrs.iter().find_map(|addr| addr.to_ipv4_mapped()) {
+            return Ok(IpAddr::V4(addr));
+        }
+        // skip the link-local addresses, fe80::/10
+        addrs
+            .into_iter()
+            .find(|addr| addr.segments()[0] & 0xffc0 != 0xfe80)
+            .map(IpAddr::V6)
+            .ok_or(ApiError::NotFound)
+    }
+
     pub fn get_default_contexts(&self) -> Result<Vec<returned::VerbsContext>> {
         log::debug!("GetDefaultContexts");
         use super::state::DEFAULT_CTXS;
@@ -821,6 +1015,70 @@ impl Ops {
         rdmacm::MemoryRegion::new_on_demand_paging(pd.pd()).map_err(ApiError::Ibv)
     }
 
+    /// Registers a buffer that is allocated elsewhere, e.g., a GPU buffer.
+    pub fn create_mr_with_addr(
+        &self,
+        pd_handle: &net::ProtectionDomain,
+        addr: usize,
+        len: usize,
+    ) -> Result<rdmacm::MemoryRegion<'static>> {
+        log::debug!(
+            "CreateMrWithAddr: pd_handle: {:?}, addr: {:#x}, len: {}",
+            pd_handle,
+            addr,
+            len
+        );
+        let pd = self.resource().pd_table.get(&pd_handle.0)?;
+        rdmacm::MemoryRegion::new_with_addr(pd.pd(), ptr::from_exposed_addr_mut(addr), len)
+            .map_err(ApiError::Ibv)
+    }
+
+    /// Registers a buffer that is allocated elsewhere and only sent from, e.g., a read-only
+    /// mapping of a file.
+    pub fn create_read_only_mr_with_addr(
+        &self,
+        pd_handle: &net::ProtectionDomain,
+        addr: usize,
+        len: usize,
+    ) -> Result<rdmacm::MemoryRegion<'static>> {
+        log::debug!(
+            "CreateReadOnlyMrWithAddr: pd_handle: {:?}, addr: {:#x}, len: {}",
+            pd_handle,
+            addr,
+            len
+        );
+        let pd = self.resource().pd_table.get(&pd_handle.0)?;
+        rdmacm::MemoryRegion::new_read_only_with_addr(
+            pd.pd(),
+            ptr::from_exposed_addr_mut(addr),
+            len,
+        )
+        .map_err(ApiError::Ibv)
+    }
+
+    /// Registers a buffer that the peer may only read with RDMA READs, e.g., the payload of a
+    /// message it pulls.
+    pub fn create_remote_read_mr_with_addr(
+        &self,
+        pd_handle: &net::ProtectionDomain,
+        addr: usize,
+        len: usize,
+    ) -> Result<rdmacm::MemoryRegion<'static>> {
+        log::trace!(
+            "CreateRemoteReadMrWithAddr: pd_handle: {:?}, addr: {:#x}, len: {}",
+            pd_handle,
+            addr,
+            len
+        );
+        let pd = self.resource().pd_table.get(&pd_handle.0)?;
+        rdmacm::MemoryRegion::new_remote_read_with_addr(
+            pd.pd(),
+            ptr::from_exposed_addr_mut(addr),
+            len,
+        )
+        .map_err(ApiError::Ibv)
+    }
+
     fn get_qp_params(
         &self,
         pd_handle: Option<&net::ProtectionDomain>,
@@ -845,11 +1103,17 @@ impl Ops {
             } else {
                 None
             };
+            let srq = if let Some(ref h) = a.srq {
+                Some(self.resource().srq_table.get(&h.0)?)
+            } else {
+                None
+            };
             use std::ops::Deref;
             let attr = ibv::QpInitAttr {
                 qp_context: 0,
                 send_cq: send_cq.as_ref().map(|x| x.deref().deref()),
                 recv_cq: recv_cq.as_ref().map(|x| x.deref().deref()),
+                srq: srq.as_ref().map(|x| x.deref()),
                 cap: a.cap.into(),
                 qp_type: a.qp_type.into(),
                 sq_sig_all: a.sq_sig_all,
@@ -979,28 +1243,40 @@ impl Ops {
     #[inline]
     pub fn get_local_addr(&self, cmid: &net::CmId) -> Result<SocketAddr> {
         let cmid_handle = cmid.0;
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
         Ok(cmid.get_local_addr())
     }
 
     #[inline]
     pub fn get_peer_addr(&self, cmid: &net::CmId) -> Result<SocketAddr> {
         let cmid_handle = cmid.0;
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
         Ok(cmid.get_peer_addr())
     }
 
     #[inline]
     pub fn get_src_port(&self, cmid: &net::CmId) -> Result<u16> {
         let cmid_handle = cmid.0;
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
         Ok(cmid.get_src_port())
     }
 
     #[inline]
     pub fn get_dst_port(&self, cmid: &net::CmId) -> Result<u16> {
         let cmid_handle = cmid.0;
-        let cmid = self.resource().cmid_table.get_dp(cmid_handle.0 as usize)?;
+        let cmid = self
+            .resource()
+            .cmid_table
+            .get_dp(cmid_handle.id() as usize)?;
         Ok(cmid.get_dst_port())
     }
 }
@@ -10,16 +10,17 @@ use lazy_static::lazy_static;
 use nix::unistd::Pid;
 
 use phoenix_api::net;
-use phoenix_api::{AsHandle, Handle};
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
 use rdma::ibv;
 use rdma::rdmacm;
 use rdma::rdmacm::CmId;
 
 use phoenix_common::resource::{ResourceSlab, ResourceTable};
-use phoenix_common::state_mgr::ProcessShared;
+use phoenix_common::state_mgr::{ProcessShared, ResourceUsage};
 use phoenix_common::tracing;
 
 use super::cm::CmEventManager;
+use super::mr_cache::MrCache;
 use super::ApiError;
 
 // TODO(cjr): Make this global lock more fine-grained.
@@ -65,6 +66,31 @@ impl ProcessShared for Shared {
         };
         Ok(shared)
     }
+
+    fn resource_usage(&self) -> ResourceUsage {
+        let res = &self.resource;
+        ResourceUsage::from([
+            ("cmid", res.cmid_table.len()),
+            ("event_channel", res.event_channel_table.len()),
+            ("qp", res.qp_table.len()),
+            ("mr", res.mr_table.len()),
+            ("mr_cache", res.mr_cache.len()),
+            ("cq", res.cq_table.len()),
+            ("srq", res.srq_table.len()),
+        ])
+    }
+
+    fn reclaim(&self) {
+        // The default PDs are released together with the state.
+        let res = &self.resource;
+        res.cmid_table.clear();
+        res.event_channel_table.clear();
+        res.qp_table.clear();
+        res.mr_table.clear();
+        res.mr_cache.clear();
+        res.cq_table.clear();
+        res.srq_table.clear();
+    }
 }
 
 // TODO(cjr): move this to per-process state for better isolation
@@ -92,6 +118,15 @@ impl PinnedContext {
 pub(crate) struct DefaultContext {
     pub(crate) pinned_ctx: Pin<Box<PinnedContext>>,
     gid_table: Vec<ibv::Gid>,
+    /// Whether the device supports on-demand paging, queried once when it is opened
+    odp: bool,
+}
+
+/// Returns whether the device of `ctx` supports on-demand paging of memory regions.
+pub(crate) fn supports_odp(ctx: &ibv::Context) -> bool {
+    DEFAULT_CTXS
+        .iter()
+        .any(|c| c.pinned_ctx.verbs.as_handle() == ctx.as_handle() && c.odp)
 }
 
 /// Open default verbs contexts
@@ -106,9 +141,11 @@ fn open_default_verbs() -> io::Result<Vec<DefaultContext>> {
         })();
         match result {
             Ok((ctx, gid_table)) => {
+                let odp = ctx.supports_odp();
                 default_ctxs.push(DefaultContext {
                     pinned_ctx: Box::pin(PinnedContext::new(ctx)),
                     gid_table,
+                    odp,
                 });
             }
             Err(e) => {
@@ -188,7 +225,13 @@ pub struct Resource {
     pub event_channel_table: ResourceTable<EventChannel>,
     pub qp_table: ResourceTable<ibv::QueuePair<'static>>,
     pub mr_table: ResourceSlab<rdma::mr::MemoryRegion>,
+    // MRs closed by the user, kept registered for reuse
+    pub mr_cache: MrCache,
     pub cq_table: ResourceSlab<ibv::CompletionQueue<'static>>,
+    // shared receive queues, must be dropped after the QPs using them and before their PDs
+    pub srq_table: ResourceTable<ibv::SharedReceiveQueue<'static>>,
+    // address handles of the unreliable datagram QPs, must be dropped before their PDs
+    pub ah_table: ResourceTable<ibv::AddressHandle<'static>>,
     pub pd_table: ResourceTable<ibv::ProtectionDomain<'static>>,
 }
 
@@ -199,6 +242,7 @@ impl Resource {
         for DefaultContext {
             pinned_ctx: ctx,
             gid_table,
+            ..
         } in DEFAULT_CTXS.iter()
         {
             let pd = match ctx.verbs.alloc_pd() {
@@ -218,7 +262,10 @@ impl Resource {
             event_channel_table: ResourceTable::default(),
             qp_table: ResourceTable::default(),
             mr_table: ResourceSlab::default(),
+            mr_cache: MrCache::default(),
             cq_table: ResourceSlab::default(),
+            srq_table: ResourceTable::default(),
+            ah_table: ResourceTable::default(),
             pd_table,
         })
     }
@@ -271,6 +318,6 @@ impl Resource {
 
     pub fn insert_cmid(&self, cmid: CmId<'static>) -> Result<Handle, ApiError> {
         let key = self.cmid_table.insert(cmid)?;
-        Ok(Handle(key as u64))
+        Ok(Handle::new(HandleNamespace::CmId, key as u64))
     }
 }
@@ -104,6 +104,8 @@ impl TransportError {
 
 use crate::config::TcpTransportConfig;
 use crate::module::TcpTransportModule;
+phoenix_common::export_abi!();
+
 #[no_mangle]
 pub fn init_module(config_string: Option<&str>) -> InitFnResult<Box<dyn PhoenixModule>> {
     let config = TcpTransportConfig::new(config_string)?;
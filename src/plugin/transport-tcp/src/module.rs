@@ -8,6 +8,7 @@ use uuid::Uuid;
 
 use ipc::customer::ShmCustomer;
 use ipc::unix::DomainSocket;
+use ipc::version::ProtocolVersion;
 use phoenix_api::engine::SchedulingMode;
 use phoenix_api::transport::tcp::{cmd, dp};
 
@@ -18,7 +19,9 @@ use phoenix_common::module::{
     Version,
 };
 use phoenix_common::state_mgr::SharedStateManager;
-use phoenix_common::storage::{get_default_prefix, ResourceCollection, SharedStorage};
+use phoenix_common::storage::{
+    get_default_prefix, get_user_dir, ResourceCollection, SharedStorage,
+};
 
 use super::engine::TransportEngine;
 use super::ops::Ops;
@@ -134,6 +137,7 @@ impl PhoenixModule for TcpTransportModule {
                     mode,
                     cred,
                     config_string: _config_string,
+                    protocol,
                 } = request
                 {
                     let phoenix_prefix = get_default_prefix(global)?;
@@ -143,7 +147,9 @@ impl PhoenixModule for TcpTransportModule {
                         mode,
                         node,
                         phoenix_prefix,
+                        get_user_dir(global),
                         cred,
+                        protocol,
                     )?;
                     Ok(Some(Box::new(engine)))
                 } else {
@@ -176,6 +182,7 @@ impl PhoenixModule for TcpTransportModule {
 }
 
 impl TcpTransportModule {
+    #[allow(clippy::too_many_arguments)]
     fn create_transport_engine(
         &mut self,
         sock: &DomainSocket,
@@ -183,16 +190,19 @@ impl TcpTransportModule {
         mode: SchedulingMode,
         node: DataPathNode,
         phoenix_prefix: &PathBuf,
+        user_dir: Option<&PathBuf>,
         cred: &UCred,
+        protocol: ProtocolVersion,
     ) -> Result<TransportEngine> {
         let uuid = Uuid::new_v4();
         let instance_name = format!("{}-{}.sock", self.config.engine_basename, uuid);
 
         // use the phoenix_prefix if not otherwise specified
         let engine_prefix = self.config.prefix.as_ref().unwrap_or(phoenix_prefix);
-        let engine_path = engine_prefix.join(instance_name);
+        // the user has a directory of its own if the daemon isolates the users
+        let engine_path = user_dir.unwrap_or(engine_prefix).join(instance_name);
 
-        let customer = ShmCustomer::accept(sock, client_path, mode, engine_path)?;
+        let customer = ShmCustomer::accept(sock, client_path, mode, engine_path, protocol)?;
 
         let client_pid = Pid::from_raw(cred.pid.unwrap());
         let ops = self.create_ops(client_pid)?;
@@ -1,7 +1,7 @@
 use std::cell::{Ref, RefMut};
 use std::collections::VecDeque;
-use std::io::{IoSlice, Read, Write};
-use std::net::SocketAddr;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::num::NonZeroU32;
 use std::os::unix::io::AsRawFd;
 use std::time::Duration;
@@ -11,11 +11,15 @@ use mio::{Events, Interest, Poll, Token};
 use phoenix_api::buf::Range;
 use phoenix_api::net::{MappedAddrStatus, WcOpcode, WcStatus};
 use phoenix_api::transport::tcp::dp;
-use phoenix_api::{AsHandle, Handle};
+use phoenix_api::{AsHandle, Handle, HandleNamespace};
+use socket2::{Domain, Socket, Type};
 
 use super::state::State;
 use super::{ApiError, TransportError};
 
+/// The same backlog as `TcpListener::bind`.
+const LISTEN_BACKLOG: i32 = 1024;
+
 pub struct Ops {
     pub state: State,
 }
@@ -46,8 +50,24 @@ impl Ops {
 // Control path APIs
 impl Ops {
     pub fn bind(&self, addr: &SocketAddr) -> Result<Handle, ApiError> {
-        let mut listener = TcpListener::bind(*addr)?;
-        let handle = listener.as_raw_fd().as_handle();
+        let listener = TcpListener::bind(*addr)?;
+        self.register_listener(listener)
+    }
+
+    /// Like [`Ops::bind`], but sets `SO_REUSEPORT` on the listener so that multiple listeners
+    /// can bind to the same address. The kernel distributes incoming connections among them.
+    pub fn bind_reuse_port(&self, addr: &SocketAddr) -> Result<Handle, ApiError> {
+        let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&(*addr).into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        self.register_listener(TcpListener::from_std(socket.into()))
+    }
+
+    fn register_listener(&self, mut listener: TcpListener) -> Result<Handle, ApiError> {
+        let handle = Handle::new(HandleNamespace::Listener, listener.as_raw_fd() as u64);
 
         self.poll().registry().register(
             &mut listener,
@@ -63,7 +83,58 @@ impl Ops {
     }
 
     pub fn connect(&self, addr: &SocketAddr) -> Result<Handle, ApiError> {
-        let mut sock = TcpStream::connect(*addr)?;
+        let sock = TcpStream::connect(*addr)?;
+        self.register_stream(sock)
+    }
+
+    /// Like [`Ops::connect`], but binds the local side of the socket to `local_ip` and to the
+    /// first free port of `port_range` first, if they are set, and sets the type of service of
+    /// the packets of an IPv4 connection to `tos`.
+    pub fn connect_from(
+        &self,
+        addr: &SocketAddr,
+        local_ip: Option<IpAddr>,
+        port_range: Option<(u16, u16)>,
+        tos: Option<u8>,
+    ) -> Result<Handle, ApiError> {
+        let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+        if let (Some(tos), SocketAddr::V4(_)) = (tos, addr) {
+            socket.set_tos(tos as u32)?;
+        }
+        let local_ip = local_ip.unwrap_or(match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        });
+        match port_range {
+            Some((first, last)) => {
+                let mut bound = false;
+                for port in first..=last {
+                    match socket.bind(&SocketAddr::new(local_ip, port).into()) {
+                        Ok(()) => {
+                            bound = true;
+                            break;
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                if !bound {
+                    return Err(io::Error::from(io::ErrorKind::AddrInUse).into());
+                }
+            }
+            None => socket.bind(&SocketAddr::new(local_ip, 0).into())?,
+        }
+        // connect in the background, as `TcpStream::connect` does
+        socket.set_nonblocking(true)?;
+        match socket.connect(&(*addr).into()) {
+            Ok(()) => {}
+            Err(e) if e.raw_os_error() == Some(nix::errno::Errno::EINPROGRESS as i32) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.register_stream(TcpStream::from_std(socket.into()))
+    }
+
+    fn register_stream(&self, mut sock: TcpStream) -> Result<Handle, ApiError> {
         sock.set_nodelay(true)?;
         let handle = sock.as_raw_fd().as_handle();
 
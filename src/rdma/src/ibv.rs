@@ -303,6 +303,20 @@ impl Context {
         Ok(gid)
     }
 
+    /// Returns the index of `gid` in the GID table of the port.
+    pub fn gid_index(&self, gid: &Gid) -> io::Result<u8> {
+        let port_attr = self.port_attr()?;
+        for index in 0..port_attr.gid_tbl_len.min(u8::MAX as i32 + 1) as usize {
+            if self.gid(index)? == *gid {
+                return Ok(index as u8);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "gid not found in the gid table".to_string(),
+        ))
+    }
+
     /// Create a completion queue (CQ).
     ///
     /// When an outstanding Work Request, within a Send or Receive Queue, is completed, a Work
@@ -343,6 +357,22 @@ impl Context {
         }
     }
 
+    /// Returns whether the device supports on-demand paging (ODP) of memory regions for sends
+    /// and receives on RC QPs.
+    pub fn supports_odp(&self) -> bool {
+        let mut attr = ffi::ibv_device_attr_ex::default();
+        let errno =
+            unsafe { ffi::ibv_query_device_ex_real(self.ctx, ptr::null(), &mut attr as *mut _) };
+        if errno != 0 {
+            return false;
+        }
+        let odp_caps = &attr.odp_caps;
+        let rc_caps = ffi::ibv_odp_transport_cap_bits_IBV_ODP_SUPPORT_SEND
+            | ffi::ibv_odp_transport_cap_bits_IBV_ODP_SUPPORT_RECV;
+        odp_caps.general_caps & ffi::ibv_odp_general_caps_IBV_ODP_SUPPORT as u64 != 0
+            && odp_caps.per_transport_caps.rc_odp_caps & rc_caps == rc_caps
+    }
+
     /// Allocate a protection domain (PDs) for the device's context.
     ///
     /// The created PD will be used primarily to create `QueuePair`s and `MemoryRegion`s.
@@ -484,13 +514,15 @@ pub struct QpCapability(pub ffi::ibv_qp_cap);
 pub struct QpType(pub ibv_qp_type::Type);
 
 /// The attributes to initialize a QP.
-pub struct QpInitAttr<'ctx, 'scq, 'rcq> {
+pub struct QpInitAttr<'ctx, 'scq, 'rcq, 'srq> {
     /// Associated context of the QP.
     pub qp_context: usize,
     /// CQ to be associated with the Send Queue (SQ).
     pub send_cq: Option<&'scq CompletionQueue<'ctx>>,
     /// CQ to be associated with the Receive Queue (RQ).
     pub recv_cq: Option<&'rcq CompletionQueue<'ctx>>,
+    /// SRQ to take the receive requests from instead of the QP's own Receive Queue.
+    pub srq: Option<&'srq SharedReceiveQueue<'ctx>>,
     /// QP capabilities.
     pub cap: QpCapability,
     /// QP Transport Service Type: IBV_QPT_RC, IBV_QPT_UC, IBV_QPT_UD, IBV_QPT_RAW_PACKET or
@@ -500,10 +532,11 @@ pub struct QpInitAttr<'ctx, 'scq, 'rcq> {
     pub sq_sig_all: bool,
 }
 
-impl<'ctx, 'scq, 'rcq> QpInitAttr<'ctx, 'scq, 'rcq>
+impl<'ctx, 'scq, 'rcq, 'srq> QpInitAttr<'ctx, 'scq, 'rcq, 'srq>
 where
     'ctx: 'scq,
     'ctx: 'rcq,
+    'ctx: 'srq,
 {
     /// Returns the C type of `ibv_qp_init_attr`.
     ///
@@ -514,7 +547,7 @@ where
             qp_context: self.qp_context as *mut _,
             send_cq: self.send_cq.map_or(ptr::null_mut(), |cq| cq.cq),
             recv_cq: self.recv_cq.map_or(ptr::null_mut(), |cq| cq.cq),
-            srq: ptr::null_mut(),
+            srq: self.srq.map_or(ptr::null_mut(), |srq| srq.srq),
             cap: self.cap.0,
             qp_type: self.qp_type.0,
             sq_sig_all: self.sq_sig_all as i32,
@@ -869,6 +902,18 @@ pub struct Gid {
 }
 
 impl Gid {
+    /// Creates a `Gid` from its raw bytes, in network order.
+    #[inline]
+    pub fn from_raw(raw: [u8; 16]) -> Self {
+        Self { raw }
+    }
+
+    /// Returns the raw bytes of the `Gid`, in network order.
+    #[inline]
+    pub fn raw(&self) -> [u8; 16] {
+        self.raw
+    }
+
     /// Expose the subnet_prefix component of the `Gid` as a u64. This is
     /// equivalent to accessing the `global.subnet_prefix` component of the
     /// `ffi::ibv_gid` union.
@@ -1167,6 +1212,67 @@ impl<'ctx> ProtectionDomain<'ctx> {
         QueuePairBuilder::new(self, send, 1, recv, 1, qp_type)
     }
 
+    /// Creates an address handle to send datagrams to the port with `dgid` through an unreliable
+    /// datagram `QueuePair`.
+    ///
+    /// `sgid_index` is the index of the local GID to send from, see [`Context::gid_index`].
+    ///
+    /// # Errors
+    ///
+    ///  - `EINVAL`: Invalid `pd` or attributes.
+    ///  - `ENOMEM`: Not enough resources to complete this operation.
+    pub fn create_ah(&self, dgid: Gid, sgid_index: u8) -> io::Result<AddressHandle<'ctx>> {
+        let mut attr = ffi::ibv_ah_attr {
+            is_global: 1,
+            port_num: PORT_NUM,
+            ..Default::default()
+        };
+        attr.grh.dgid = dgid.into();
+        attr.grh.sgid_index = sgid_index;
+        attr.grh.hop_limit = 0xff;
+        let ah = unsafe { ffi::ibv_create_ah(self.pd, &mut attr as *mut _) };
+        if ah.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(AddressHandle {
+                _phantom: PhantomData,
+                ah,
+            })
+        }
+    }
+
+    /// Creates a shared receive queue (SRQ) that can hold up to `max_wr` outstanding receive
+    /// requests, each of up to `max_sge` scatter/gather elements.
+    ///
+    /// The `QueuePair`s associated with the SRQ take their receive requests from it rather than
+    /// from their own Receive Queues, so the receive buffers can be shared among them. Only
+    /// `QueuePair`s of the same `ProtectionDomain` can be associated with it.
+    ///
+    /// # Errors
+    ///
+    ///  - `EINVAL`: Invalid `pd` or the values of `max_wr` or `max_sge` exceed the limits of the
+    ///    device.
+    ///  - `ENOMEM`: Not enough resources to complete this operation.
+    pub fn create_srq(&self, max_wr: u32, max_sge: u32) -> io::Result<SharedReceiveQueue<'ctx>> {
+        let mut attr = ffi::ibv_srq_init_attr {
+            srq_context: ptr::null_mut(),
+            attr: ffi::ibv_srq_attr {
+                max_wr,
+                max_sge,
+                srq_limit: 0,
+            },
+        };
+        let srq = unsafe { ffi::ibv_create_srq(self.pd, &mut attr as *mut _) };
+        if srq.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(SharedReceiveQueue {
+                _phantom: PhantomData,
+                srq,
+            })
+        }
+    }
+
     /// Allocates and registers a Memory Region (MR) associated with this `ProtectionDomain`.
     ///
     /// This process allows the RDMA device to read and write data to the allocated memory. Only
@@ -1321,6 +1427,27 @@ impl<'res> QueuePair<'res> {
         (pd_ret, send_cq_ret, recv_cq_ret)
     }
 
+    /// Returns the number of this QP, which addresses it within its device.
+    #[inline]
+    pub fn qp_num(&self) -> u32 {
+        assert!(!self.qp.is_null());
+        unsafe { &*self.qp }.qp_num
+    }
+
+    /// Returns the most bytes a send on this QP can carry inline, which may be more than asked
+    /// for when the QP was created.
+    pub fn max_inline_data(&self) -> io::Result<u32> {
+        assert!(!self.qp.is_null());
+        let mut attr = ffi::ibv_qp_attr::default();
+        let mut init_attr: ffi::ibv_qp_init_attr = unsafe { mem::zeroed() };
+        let mask = ffi::ibv_qp_attr_mask::IBV_QP_CAP;
+        let errno = unsafe { ffi::ibv_query_qp(self.qp, &mut attr, mask.0 as i32, &mut init_attr) };
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        Ok(attr.cap.max_inline_data)
+    }
+
     /// Returns the protection domain of this QP.
     #[inline]
     pub fn pd(&self) -> &ProtectionDomain<'res> {
@@ -1520,6 +1647,120 @@ impl<'a> Drop for QueuePair<'a> {
     }
 }
 
+/// The path to a remote port, used to address the datagrams sent through an unreliable datagram
+/// `QueuePair`. It must be dropped before its `ProtectionDomain`.
+pub struct AddressHandle<'pd> {
+    pub(crate) _phantom: PhantomData<&'pd ()>,
+    pub(crate) ah: *mut ffi::ibv_ah,
+}
+
+unsafe impl<'a> Send for AddressHandle<'a> {}
+unsafe impl<'a> Sync for AddressHandle<'a> {}
+
+impl<'pd> AddressHandle<'pd> {
+    /// Exposes the inner ah structure.
+    #[inline]
+    pub fn ah(&self) -> *mut ffi::ibv_ah {
+        self.ah
+    }
+}
+
+#[cfg(feature = "phoenix")]
+impl<'pd> AsHandle for AddressHandle<'pd> {
+    /// Returns the inner handle of this address handle.
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        assert!(!self.ah.is_null());
+        let ah = unsafe { &*self.ah };
+        let ctx_handle = (&ah.context).as_ref().as_handle();
+        let ah_handle = ah.handle;
+        Handle(ctx_handle.0 << 32 | ah_handle as u64)
+    }
+}
+
+impl<'a> Drop for AddressHandle<'a> {
+    fn drop(&mut self) {
+        let errno = unsafe { ffi::ibv_destroy_ah(self.ah) };
+        if errno != 0 {
+            let e = io::Error::from_raw_os_error(errno);
+            panic!("{}", e);
+        }
+    }
+}
+
+/// A receive queue shared by the `QueuePair`s associated with it. It must be dropped after these
+/// `QueuePair`s and before its `ProtectionDomain`.
+pub struct SharedReceiveQueue<'pd> {
+    pub(crate) _phantom: PhantomData<&'pd ()>,
+    pub(crate) srq: *mut ffi::ibv_srq,
+}
+
+unsafe impl<'a> Send for SharedReceiveQueue<'a> {}
+unsafe impl<'a> Sync for SharedReceiveQueue<'a> {}
+
+impl<'pd> SharedReceiveQueue<'pd> {
+    /// Exposes the inner srq structure.
+    #[inline]
+    pub fn srq(&self) -> *mut ffi::ibv_srq {
+        self.srq
+    }
+
+    /// Posts a receive request of `buf` to the SRQ. The completion goes to the receive CQ of the
+    /// `QueuePair` that the message arrives at, with `wr_id` as its identifier.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `CompletionQueue::poll` returns a completion for this receive).
+    #[inline]
+    pub unsafe fn post_recv<'a>(
+        &self,
+        wr_id: u64,
+        buf: &mut [u8],
+        mr: &crate::rdmacm::MemoryRegion<'a>,
+    ) -> io::Result<()> {
+        let addr = buf.as_ptr();
+        let length = buf.len();
+
+        let mr = mr.0;
+        assert!(!mr.is_null());
+        assert!(
+            (&*mr).addr as *const _ <= addr
+                && addr.add(length) <= (&*mr).addr.add((&*mr).length as usize) as *const _
+        );
+        let rc =
+            ffi::ibv_post_srq_recv_real(self.srq, wr_id as _, addr as *mut _, length as u64, mr);
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "phoenix")]
+impl<'pd> AsHandle for SharedReceiveQueue<'pd> {
+    /// Returns the inner handle of this shared receive queue.
+    #[inline]
+    fn as_handle(&self) -> Handle {
+        assert!(!self.srq.is_null());
+        let srq = unsafe { &*self.srq };
+        let ctx_handle = (&srq.context).as_ref().as_handle();
+        let srq_handle = srq.handle;
+        Handle(ctx_handle.0 << 32 | srq_handle as u64)
+    }
+}
+
+impl<'a> Drop for SharedReceiveQueue<'a> {
+    fn drop(&mut self) {
+        let errno = unsafe { ffi::ibv_destroy_srq(self.srq) };
+        if errno != 0 {
+            let e = io::Error::from_raw_os_error(errno);
+            panic!("{}", e);
+        }
+    }
+}
+
 #[cfg(all(test, feature = "serde"))]
 mod test_serde {
     use super::*;
@@ -29,6 +29,7 @@ pub struct MemoryRegion {
     mmap: MmapFixed,
     memfd: Memfd,
     file_off: usize,
+    access: AccessFlags,
 }
 
 unsafe impl Send for MemoryRegion {}
@@ -115,6 +116,7 @@ impl MemoryRegion {
                 mmap,
                 memfd,
                 file_off,
+                access,
             })
         }
     }
@@ -149,6 +151,12 @@ impl MemoryRegion {
     pub fn file_off(&self) -> usize {
         self.file_off
     }
+
+    /// Returns the access flags the memory region is registered with.
+    #[inline]
+    pub fn access(&self) -> AccessFlags {
+        self.access
+    }
 }
 
 impl AsRef<MemoryRegion> for MemoryRegion {
@@ -66,10 +66,52 @@ int rdma_post_ud_send_real(struct rdma_cm_id *id, void *context, void *addr, siz
         return rdma_post_ud_send(id, context, addr, length, mr, flags, ah, remote_qpn);
 }
 
+int rdma_post_ud_send_with_imm_real(struct rdma_cm_id *id, void *context, void *addr, size_t length, struct ibv_mr *mr, int flags, struct ibv_ah *ah, uint32_t remote_qpn, uint32_t remote_qkey, uint32_t imm_data) {
+        struct ibv_sge sge;
+        struct ibv_send_wr wr, *bad;
+
+        sge.addr = (uint64_t) (uintptr_t) addr;
+        sge.length = (uint32_t) length;
+        sge.lkey = mr ? mr->lkey : 0;
+
+        wr.wr_id = (uintptr_t) context;
+        wr.next = NULL;
+        wr.sg_list = &sge;
+        wr.num_sge = 1;
+        wr.opcode = IBV_WR_SEND_WITH_IMM;
+        wr.send_flags = flags;
+        wr.imm_data = imm_data;
+        wr.wr.ud.ah = ah;
+        wr.wr.ud.remote_qpn = remote_qpn;
+        wr.wr.ud.remote_qkey = remote_qkey;
+
+        return rdma_seterrno(ibv_post_send(id->qp, &wr, &bad));
+}
+
+int ibv_post_srq_recv_real(struct ibv_srq *srq, void *context, void *addr, size_t length, struct ibv_mr *mr) {
+        struct ibv_sge sge;
+        struct ibv_recv_wr wr, *bad;
+
+        sge.addr = (uint64_t) (uintptr_t) addr;
+        sge.length = (uint32_t) length;
+        sge.lkey = mr ? mr->lkey : 0;
+
+        wr.wr_id = (uintptr_t) context;
+        wr.next = NULL;
+        wr.sg_list = &sge;
+        wr.num_sge = 1;
+
+        return rdma_seterrno(ibv_post_srq_recv(srq, &wr, &bad));
+}
+
 int rdma_get_send_comp_real(struct rdma_cm_id *id, struct ibv_wc *wc) {
         return rdma_get_send_comp(id, wc);
 }
 
 int rdma_get_recv_comp_real(struct rdma_cm_id *id, struct ibv_wc *wc) {
         return rdma_get_recv_comp(id, wc);
-}
\ No newline at end of file
+}
+
+int ibv_query_device_ex_real(struct ibv_context *context, const struct ibv_query_device_ex_input *input, struct ibv_device_attr_ex *attr) {
+        return ibv_query_device_ex(context, input, attr);
+}
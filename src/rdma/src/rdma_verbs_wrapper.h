@@ -55,10 +55,23 @@ int rdma_post_ud_send_real(struct rdma_cm_id* id, void* context, void* addr,
                            size_t length, struct ibv_mr* mr, int flags,
                            struct ibv_ah* ah, uint32_t remote_qpn);
 
+int rdma_post_ud_send_with_imm_real(struct rdma_cm_id* id, void* context,
+                                    void* addr, size_t length,
+                                    struct ibv_mr* mr, int flags,
+                                    struct ibv_ah* ah, uint32_t remote_qpn,
+                                    uint32_t remote_qkey, uint32_t imm_data);
+
+int ibv_post_srq_recv_real(struct ibv_srq* srq, void* context, void* addr,
+                            size_t length, struct ibv_mr* mr);
+
 int rdma_get_send_comp_real(struct rdma_cm_id* id, struct ibv_wc* wc);
 
 int rdma_get_recv_comp_real(struct rdma_cm_id* id, struct ibv_wc* wc);
 
+int ibv_query_device_ex_real(struct ibv_context* context,
+                             const struct ibv_query_device_ex_input* input,
+                             struct ibv_device_attr_ex* attr);
+
 #ifdef __cplusplus
 }
 #endif
@@ -5,7 +5,7 @@ use std::marker::PhantomData;
 use std::mem;
 use std::mem::{ManuallyDrop, MaybeUninit};
 use std::net::SocketAddr;
-use std::os::raw::{c_char, c_void};
+use std::os::raw::{c_char, c_int, c_void};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::ptr;
 use std::slice;
@@ -510,6 +510,56 @@ impl<'a> MemoryRegion<'a> {
             Ok(Self(mr, PhantomData))
         }
     }
+
+    /// Registers an existing buffer. `addr` can point to device memory if the peer memory
+    /// client of the device (e.g., nvidia-peermem) is loaded.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn new_with_addr(pd: *mut ffi::ibv_pd, addr: *mut u8, len: usize) -> io::Result<Self> {
+        let access = ffi::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
+            | ffi::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE
+            | ffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ;
+        let mr = unsafe { ffi::ibv_reg_mr(pd, addr.cast(), len as _, access.0 as i32) };
+        if mr.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Self(mr, PhantomData))
+        }
+    }
+
+    /// Registers an existing buffer that is only read by the NIC, i.e., sent from. Unlike
+    /// `new_with_addr`, this works on read-only mappings.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn new_read_only_with_addr(
+        pd: *mut ffi::ibv_pd,
+        addr: *mut u8,
+        len: usize,
+    ) -> io::Result<Self> {
+        // local read access is always granted
+        let access = ffi::ibv_access_flags(0);
+        let mr = unsafe { ffi::ibv_reg_mr(pd, addr.cast(), len as _, access.0 as i32) };
+        if mr.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Self(mr, PhantomData))
+        }
+    }
+
+    /// Registers an existing buffer that the peer may only read with RDMA READs. The peer cannot
+    /// write to it, nor access the memory around it.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn new_remote_read_with_addr(
+        pd: *mut ffi::ibv_pd,
+        addr: *mut u8,
+        len: usize,
+    ) -> io::Result<Self> {
+        let access = ffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ;
+        let mr = unsafe { ffi::ibv_reg_mr(pd, addr.cast(), len as _, access.0 as i32) };
+        if mr.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Self(mr, PhantomData))
+        }
+    }
 }
 
 #[cfg(feature = "phoenix")]
@@ -788,6 +838,28 @@ impl<'res> CmId<'res> {
         Ok(())
     }
 
+    /// Sets whether a CmId bound to an IPv6 address only takes IPv6 connections. Otherwise, one
+    /// bound to the unspecified IPv6 address takes IPv4 connections too. Must be set before
+    /// bind_addr, and follows `net.ipv6.bindv6only` if not set.
+    pub fn set_afonly(&self, afonly: bool) -> io::Result<()> {
+        let id = self.0;
+        assert!(self.qp().is_none());
+        let mut val: c_int = afonly as c_int;
+        let rc = unsafe {
+            ffi::rdma_set_option(
+                id,
+                ffi::RDMA_OPTION_ID as _,
+                ffi::RDMA_OPTION_ID_AFONLY as _,
+                &mut val as *mut c_int as *mut c_void,
+                mem::size_of_val(&val) as _,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
     pub fn set_rnr_timeout(&self, min_rnr_timer: u8) -> io::Result<()> {
         assert!(self.qp().is_some());
         let qp = self.qp().unwrap().qp;
@@ -924,6 +996,56 @@ impl<'res> CmId<'res> {
         Ok(())
     }
 
+    /// Sends a datagram with an immediate value through the unreliable datagram QP of this id, to
+    /// the QP `remote_qpn` at the port addressed by `ah`.
+    ///
+    /// # Safety
+    ///
+    /// The memory region can only be safely reused or dropped after the request is fully executed
+    /// and a work completion has been retrieved from the corresponding completion queue (i.e.,
+    /// until `CompletionQueue::poll` returns a completion for this send).
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn post_ud_send_with_imm<'a>(
+        &self,
+        wr_id: u64,
+        buf: &[u8],
+        mr: &MemoryRegion<'a>,
+        flags: ffi::ibv_send_flags,
+        imm: u32,
+        ah: &ibv::AddressHandle<'_>,
+        remote_qpn: u32,
+        remote_qkey: u32,
+    ) -> io::Result<()> {
+        let id = self.0;
+        let context = wr_id as _;
+        let addr = buf.as_ptr();
+        let length = buf.len();
+
+        let mr = mr.0;
+        assert!(!mr.is_null());
+        assert!(
+            (&*mr).addr as *const _ <= addr
+                && addr.add(length) <= (&*mr).addr.add((&*mr).length as usize) as *const _
+        );
+        let rc = ffi::rdma_post_ud_send_with_imm_real(
+            id,
+            context,
+            addr as *mut _,
+            length as u64,
+            mr,
+            flags.0 as _,
+            ah.ah(),
+            remote_qpn,
+            remote_qkey,
+            imm,
+        );
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
     /// # Safety
     ///
     /// The memory region can only be safely reused or dropped after the request is fully executed
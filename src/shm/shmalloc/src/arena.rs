@@ -0,0 +1,234 @@
+//! Arenas that serve the allocations of a message from one block of the shared heap.
+//!
+//! Building a large message, e.g., a reply of thousands of strings, takes one allocation for each
+//! field and each element. Within [`Arena::scope`], the allocations of the calling thread are
+//! carved out of a single block instead, and the latest allocation in the block grows in place.
+//! Objects in an arena are never freed one by one. The block is returned to the shared heap after
+//! the [`Arena`] is dropped and all objects in the block are deallocated.
+use std::alloc::{AllocError, Layout};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use slabmalloc::ZoneAllocator;
+
+use shm::alloc::ShmAllocator;
+use shm::ptr::ShmNonNull;
+
+use super::SharedHeapAllocator;
+
+/// The alignment of the block of an arena.
+const BLOCK_ALIGN: usize = 64;
+
+lazy_static! {
+    // start address in the app -> block, of all blocks with live objects
+    static ref ARENAS: spin::Mutex<BTreeMap<usize, Arc<Block>>> =
+        spin::Mutex::new(BTreeMap::new());
+}
+
+// The number of entries in ARENAS, to skip the lookup when there is no arena.
+static NUM_ARENAS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // the block that serves the allocations of this thread
+    static ACTIVE: RefCell<Option<Arc<Block>>> = RefCell::new(None);
+}
+
+#[derive(Debug)]
+struct Block {
+    addr_app: usize,
+    addr_backend: usize,
+    len: usize,
+    // The next free offset. Only the thread in the scope of the arena allocates from the block.
+    offset: AtomicUsize,
+    // The offset of the latest allocation.
+    last: AtomicUsize,
+    // The number of objects not deallocated yet, plus one for the `Arena`.
+    live: AtomicUsize,
+}
+
+impl Block {
+    #[inline]
+    fn contains(&self, addr: usize) -> bool {
+        self.addr_app <= addr && addr < self.addr_app + self.len
+    }
+
+    fn slice_at(&self, offset: usize, len: usize) -> ShmNonNull<[u8]> {
+        let ptr_app = std::ptr::from_exposed_addr_mut(self.addr_app + offset);
+        let ptr_backend = std::ptr::from_exposed_addr_mut(self.addr_backend + offset);
+        // SAFETY: the addresses are within the block, which is not at address 0.
+        unsafe {
+            ShmNonNull::slice_from_raw_parts(
+                NonNull::new_unchecked(ptr_app),
+                NonNull::new_unchecked(ptr_backend),
+                len,
+            )
+        }
+    }
+
+    fn allocate(&self, layout: Layout) -> Option<ShmNonNull<[u8]>> {
+        let offset = self.offset.load(Ordering::Relaxed);
+        let align_mask = layout.align() - 1;
+        let start = ((self.addr_app + offset + align_mask) & !align_mask) - self.addr_app;
+        let end = start.checked_add(layout.size())?;
+        if end > self.len {
+            return None;
+        }
+        self.offset.store(end, Ordering::Relaxed);
+        self.last.store(start, Ordering::Relaxed);
+        self.live.fetch_add(1, Ordering::Relaxed);
+        Some(self.slice_at(start, layout.size()))
+    }
+
+    /// Returns `true` if the object at `addr` can be resized to `new_size` without moving it.
+    fn resize_in_place(&self, addr: usize, old_size: usize, new_size: usize) -> bool {
+        if new_size <= old_size {
+            return true;
+        }
+        let start = addr - self.addr_app;
+        if start != self.last.load(Ordering::Relaxed) {
+            return false;
+        }
+        match start.checked_add(new_size) {
+            Some(end) if end <= self.len => {
+                self.offset.store(end, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn release(&self) {
+        if self.live.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        if ARENAS.lock().remove(&self.addr_app).is_some() {
+            NUM_ARENAS.fetch_sub(1, Ordering::Relaxed);
+        }
+        let ptr = self.slice_at(0, self.len).cast::<u8>();
+        let layout = Layout::from_size_align(self.len, BLOCK_ALIGN).unwrap();
+        SharedHeapAllocator.deallocate(ptr, layout);
+    }
+}
+
+fn find(addr: usize) -> Option<Arc<Block>> {
+    if NUM_ARENAS.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    ARENAS
+        .lock()
+        .range(..=addr)
+        .next_back()
+        .filter(|(_, block)| block.contains(addr))
+        .map(|(_, block)| Arc::clone(block))
+}
+
+/// Forgets the arenas inherited from the parent process after fork.
+pub(crate) fn forget_inherited() {
+    ACTIVE.with(|active| active.take());
+    ARENAS.lock().clear();
+    NUM_ARENAS.store(0, Ordering::Relaxed);
+}
+
+/// Allocates from the arena in scope on this thread, if any.
+#[inline]
+pub(crate) fn allocate(layout: Layout) -> Option<ShmNonNull<[u8]>> {
+    ACTIVE
+        .try_with(|active| active.borrow().as_ref()?.allocate(layout))
+        .ok()
+        .flatten()
+}
+
+/// Returns `true` if `addr` is in an arena, in which case the object is released with the
+/// arena.
+#[inline]
+pub(crate) fn deallocate(addr: usize) -> bool {
+    match find(addr) {
+        Some(block) => {
+            block.release();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns `None` if `addr` is not in an arena, or whether the object at `addr` can be resized
+/// to `new_size` without moving it.
+#[inline]
+pub(crate) fn resize_in_place(addr: usize, old_size: usize, new_size: usize) -> Option<bool> {
+    find(addr).map(|block| block.resize_in_place(addr, old_size, new_size))
+}
+
+/// A block of the shared heap that serves the allocations of the calling thread within
+/// [`scope`](Arena::scope).
+#[derive(Debug)]
+pub struct Arena {
+    block: Arc<Block>,
+}
+
+impl Arena {
+    /// Allocates an arena of `capacity` bytes, up to [`ZoneAllocator::MAX_ALLOC_SIZE`].
+    /// Allocations that do not fit in the remaining capacity are served by the shared heap as
+    /// usual.
+    pub fn with_capacity(capacity: usize) -> Result<Self, AllocError> {
+        let capacity = capacity.clamp(BLOCK_ALIGN, ZoneAllocator::MAX_ALLOC_SIZE);
+        let layout = Layout::from_size_align(capacity, BLOCK_ALIGN).map_err(|_| AllocError)?;
+        // The block of a nested arena is not carved out of the outer one.
+        let ptr = {
+            let _restore = Restore::enter(None);
+            SharedHeapAllocator.allocate(layout)?
+        };
+        let block = Arc::new(Block {
+            addr_app: ptr.as_mut_ptr_app().expose_addr(),
+            addr_backend: ptr.as_mut_ptr_backend().expose_addr(),
+            len: layout.size(),
+            offset: AtomicUsize::new(0),
+            last: AtomicUsize::new(usize::MAX),
+            live: AtomicUsize::new(1),
+        });
+        ARENAS.lock().insert(block.addr_app, Arc::clone(&block));
+        NUM_ARENAS.fetch_add(1, Ordering::Relaxed);
+        Ok(Arena { block })
+    }
+
+    /// Runs `f` with the allocations of the shared heap on this thread served by the arena.
+    /// Scopes can be nested, the innermost arena serves the allocations.
+    pub fn scope<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let _restore = Restore::enter(Some(Arc::clone(&self.block)));
+        f()
+    }
+
+    /// Returns the number of bytes allocated from the arena.
+    pub fn used(&self) -> usize {
+        self.block.offset.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size of the arena in bytes.
+    pub fn capacity(&self) -> usize {
+        self.block.len
+    }
+}
+
+/// Sets the block in scope on this thread, and restores the previous one on drop.
+struct Restore(Option<Arc<Block>>);
+
+impl Restore {
+    fn enter(block: Option<Arc<Block>>) -> Self {
+        Restore(ACTIVE.with(|active| active.replace(block)))
+    }
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| *active.borrow_mut() = self.0.take());
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.block.release();
+    }
+}
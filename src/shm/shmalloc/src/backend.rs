@@ -1,4 +1,6 @@
+use std::cell::{Ref, RefCell};
 use std::io;
+use std::mem;
 
 use thiserror::Error;
 
@@ -14,13 +16,21 @@ thread_local! {
     pub static SA_CTX: SAContext = SAContext::register().expect("phoenix salloc register failed");
 }
 
+type Service = ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>;
+
 pub struct SAContext {
-    pub(crate) service:
-        ShmService<cmd::Command, cmd::Completion, dp::WorkRequestSlot, dp::CompletionSlot>,
+    // replaced by `reinit_after_fork`
+    service: RefCell<Service>,
 }
 
 impl SAContext {
     fn register() -> Result<SAContext, Error> {
+        Ok(Self {
+            service: RefCell::new(Self::register_service()?),
+        })
+    }
+
+    fn register_service() -> Result<Service, Error> {
         let service = ShmService::register(
             &*PHOENIX_PREFIX,
             &*PHOENIX_CONTROL_SOCK,
@@ -28,10 +38,45 @@ impl SAContext {
             SchedulingHint::default(),
             None,
         )?;
-        Ok(Self { service })
+        Ok(service)
+    }
+
+    #[inline]
+    pub(crate) fn service(&self) -> Ref<'_, Service> {
+        self.service.borrow()
     }
 }
 
+/// Registers the calling thread with the salloc backend again in a child process after fork,
+/// and forgets the shared heap inherited from the parent.
+///
+/// The heap of the parent is mapped from memory that the backend allocated for the parent, so
+/// the child must not allocate from it or release it. Objects allocated before the fork must
+/// not be used or dropped in the child.
+pub fn reinit_after_fork() -> Result<(), Error> {
+    let service = SAContext::register_service()?;
+    SA_CTX.with(|ctx| {
+        // Dropping the inherited service would remove the socket file of the parent.
+        mem::forget(ctx.service.replace(service));
+    });
+    crate::wheap::forget_inherited_heap();
+    crate::device::forget_inherited_regions();
+    crate::user::forget_inherited_regions();
+    Ok(())
+}
+
+/// Registers the calling thread with the salloc backend again after the backend restarts,
+/// keeping the shared heap.
+///
+/// The backend must have been restarted from a checkpoint (see `[checkpoint]` in `phoenix.toml`),
+/// which takes back the regions of the heap. Otherwise the new backend does not know the regions
+/// allocated before, and the objects on them cannot be sent.
+pub fn reattach() -> Result<(), Error> {
+    let service = SAContext::register_service()?;
+    SA_CTX.with(|ctx| drop(ctx.service.replace(service)));
+    Ok(())
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Service error: {0}")]
@@ -40,4 +85,8 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("Interface error {0}: {1}")]
     Interface(&'static str, phoenix_api::Error),
+    #[error("CUDA error: {0}")]
+    Cuda(i32),
+    #[error("Device memory is not supported, rebuild shmalloc with feature \"cuda\"")]
+    DeviceUnsupported,
 }
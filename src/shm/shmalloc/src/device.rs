@@ -0,0 +1,200 @@
+//! Device memory buffers for GPUDirect payloads.
+//!
+//! The memory is allocated by the backend and mapped into this process through a CUDA IPC
+//! handle. The buffer has a different address in the app and in the backend, just like a
+//! `ShmNonNull`. Its contents can only be accessed by device code or CUDA memcpy.
+use std::collections::BTreeMap;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lazy_static::lazy_static;
+
+use phoenix_api::salloc::cmd::{Command, CompletionKind};
+use phoenix_syscalls::_rx_recv_impl as rx_recv_impl;
+use shm::ptr::ShmNonNull;
+
+use super::backend::{Error, SA_CTX};
+
+lazy_static! {
+    // app address -> (length, backend address) of all live device buffers
+    static ref DEVICE_REGIONS: spin::Mutex<BTreeMap<usize, (usize, usize)>> =
+        spin::Mutex::new(BTreeMap::new());
+}
+
+// The number of entries in DEVICE_REGIONS, to skip the lookup when there is no device buffer,
+// e.g., on every free of the shared heap.
+static NUM_REGIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Forgets the device buffers inherited from the parent process after fork. CUDA IPC mappings
+/// are not inherited by the child.
+pub(crate) fn forget_inherited_regions() {
+    DEVICE_REGIONS.lock().clear();
+    NUM_REGIONS.store(0, Ordering::Relaxed);
+}
+
+/// Returns `true` if `addr` points into a [`DeviceBuffer`] of this process.
+pub fn is_device_addr(addr: usize) -> bool {
+    query_backend_addr(addr, 1).is_some()
+}
+
+/// Translates a device address in the app to the backend. Returns `None` if
+/// `[addr, addr + len)` is not within a [`DeviceBuffer`].
+pub fn query_backend_addr(addr: usize, len: usize) -> Option<usize> {
+    // a DeviceBuffer is registered before any address in it can be used
+    if NUM_REGIONS.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    DEVICE_REGIONS
+        .lock()
+        .range(..=addr)
+        .next_back()
+        .filter(|(&start, &(region_len, _))| addr + len <= start + region_len)
+        .map(|(&start, &(_, backend))| backend + (addr - start))
+}
+
+#[cfg(feature = "cuda")]
+mod ffi {
+    use std::os::raw::{c_int, c_uint, c_void};
+
+    pub(super) const CUDA_IPC_MEM_LAZY_ENABLE_PEER_ACCESS: c_uint = 1;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub(super) struct CudaIpcMemHandle {
+        pub(super) reserved: [u8; 64],
+    }
+
+    #[link(name = "cudart")]
+    extern "C" {
+        pub(super) fn cudaSetDevice(device: c_int) -> c_int;
+        pub(super) fn cudaIpcOpenMemHandle(
+            dev_ptr: *mut *mut c_void,
+            handle: CudaIpcMemHandle,
+            flags: c_uint,
+        ) -> c_int;
+        pub(super) fn cudaIpcCloseMemHandle(dev_ptr: *mut c_void) -> c_int;
+    }
+}
+
+/// A buffer in GPU memory that can be sent as an RPC payload without being copied to host
+/// memory.
+#[derive(Debug)]
+pub struct DeviceBuffer {
+    ptr: ShmNonNull<u8>,
+    len: usize,
+    device: i32,
+}
+
+unsafe impl Send for DeviceBuffer {}
+unsafe impl Sync for DeviceBuffer {}
+
+impl DeviceBuffer {
+    /// Allocates `len` bytes on GPU `device`.
+    pub fn new(len: usize, device: i32) -> Result<Self, Error> {
+        assert!(len > 0);
+        SA_CTX.with(|ctx| {
+            // cudaMalloc aligns to 256 bytes
+            let req = Command::AllocDeviceShm(len, 256, device);
+            ctx.service().send_cmd(req)?;
+            match ctx.service().recv_comp()?.0 {
+                Ok(CompletionKind::AllocDeviceShm(remote_addr, ipc_handle)) => {
+                    let addr = match Self::open_ipc_handle(&ipc_handle, device) {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            ctx.service()
+                                .send_cmd(Command::DeallocDeviceShm(remote_addr))?;
+                            rx_recv_impl!(ctx.service(), CompletionKind::DeallocDeviceShm)?;
+                            return Err(e);
+                        }
+                    };
+                    DEVICE_REGIONS.lock().insert(addr, (len, remote_addr));
+                    NUM_REGIONS.fetch_add(1, Ordering::Relaxed);
+                    let ptr = ShmNonNull::new(
+                        std::ptr::from_exposed_addr_mut(addr),
+                        std::ptr::from_exposed_addr_mut(remote_addr),
+                    )
+                    .unwrap();
+                    Ok(DeviceBuffer { ptr, len, device })
+                }
+                Err(e) => Err(Error::Interface("AllocDeviceShm", e)),
+                otherwise => panic!("Expect AllocDeviceShm, found {:?}", otherwise),
+            }
+        })
+    }
+
+    #[cfg(feature = "cuda")]
+    fn open_ipc_handle(ipc_handle: &[u8], device: i32) -> Result<usize, Error> {
+        let mut handle = ffi::CudaIpcMemHandle { reserved: [0; 64] };
+        handle.reserved.copy_from_slice(ipc_handle);
+        let mut dev_ptr = std::ptr::null_mut();
+        let rc = unsafe { ffi::cudaSetDevice(device) };
+        if rc != 0 {
+            return Err(Error::Cuda(rc));
+        }
+        let rc = unsafe {
+            ffi::cudaIpcOpenMemHandle(
+                &mut dev_ptr,
+                handle,
+                ffi::CUDA_IPC_MEM_LAZY_ENABLE_PEER_ACCESS,
+            )
+        };
+        if rc != 0 {
+            return Err(Error::Cuda(rc));
+        }
+        Ok(dev_ptr.expose_addr())
+    }
+
+    #[cfg(not(feature = "cuda"))]
+    fn open_ipc_handle(_ipc_handle: &[u8], _device: i32) -> Result<usize, Error> {
+        Err(Error::DeviceUnsupported)
+    }
+
+    /// Returns the device pointer of the buffer in this process.
+    #[inline]
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.ptr.to_raw_parts().0
+    }
+
+    /// Returns the device pointer in the app and in the backend.
+    #[inline]
+    pub fn as_shm_non_null(&self) -> ShmNonNull<u8> {
+        self.ptr
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn device(&self) -> i32 {
+        self.device
+    }
+}
+
+impl Drop for DeviceBuffer {
+    fn drop(&mut self) {
+        let addr = self.ptr.as_ptr_app().expose_addr();
+        if DEVICE_REGIONS.lock().remove(&addr).is_some() {
+            NUM_REGIONS.fetch_sub(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "cuda")]
+        unsafe {
+            ffi::cudaSetDevice(self.device);
+            ffi::cudaIpcCloseMemHandle(self.ptr.as_ptr_app().cast());
+        }
+        (|| {
+            SA_CTX.with(|ctx| {
+                let req = Command::DeallocDeviceShm(self.ptr.as_ptr_backend().expose_addr());
+                ctx.service().send_cmd(req)?;
+                rx_recv_impl!(ctx.service(), CompletionKind::DeallocDeviceShm)
+            })
+        })()
+        .unwrap_or_else(|e| eprintln!("Dropping DeviceBuffer: {}", e));
+    }
+}
@@ -1,12 +1,107 @@
+//! Reclamation of the shared heap.
+//!
+//! Pages that no longer hold live objects are collected into the global page pool. A background
+//! reclaimer periodically returns the surplus of empty pages to the backend, which releases the
+//! memory of the corresponding shared regions. Applications can call [`trim`] to return all empty
+//! pages during idle periods.
+//!
+//! Live objects are never moved behind the application's back, since the shared heap has no way
+//! to find the pointers to them. Instead, pages relinquished by exited threads are only reused
+//! once they become empty, and [`should_relocate`] tells the application whether an object sits
+//! in such a sparsely occupied page. Copying the object into a fresh allocation and dropping the
+//! original lets the page drain and be returned.
+use std::collections::VecDeque;
+use std::mem;
+use std::ptr::Unique;
+
 use lazy_static::lazy_static;
 
+pub use slabmalloc::PageBufferStats;
 use slabmalloc::GLOBAL_PAGE_POOL;
 
+use super::wheap::SHARED_HEAP_REGIONS;
+
 lazy_static! {
     pub(crate) static ref PAGE_RECLAIMER_CTX: PageReclaimerContext =
         PageReclaimerContext::initialize();
 }
 
+/// Objects in a relinquished page whose occupancy is below this ratio should be relocated.
+const RELOCATE_OCCUPANCY_RATIO: f64 = 0.25;
+
+/// A snapshot of the shared heap of this process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    /// Number of shared regions mapped from the backend.
+    pub regions: usize,
+    /// Total bytes of the mapped shared regions.
+    pub mapped_bytes: usize,
+    /// Pages of 4KB kept in the global page pool.
+    pub small_pages: PageBufferStats,
+    /// Pages of 2MB kept in the global page pool.
+    pub large_pages: PageBufferStats,
+    /// Pages of 1GB kept in the global page pool.
+    pub huge_pages: PageBufferStats,
+}
+
+/// Returns a snapshot of the shared heap.
+///
+/// Pages currently owned by the allocator of a live thread are only accounted in `regions` and
+/// `mapped_bytes`.
+pub fn heap_stats() -> HeapStats {
+    let (regions, mapped_bytes) = {
+        let guard = SHARED_HEAP_REGIONS.lock();
+        (guard.len(), guard.values().map(|r| r.len()).sum())
+    };
+    HeapStats {
+        regions,
+        mapped_bytes,
+        small_pages: GLOBAL_PAGE_POOL.small_page_stats(),
+        large_pages: GLOBAL_PAGE_POOL.large_page_stats(),
+        huge_pages: GLOBAL_PAGE_POOL.huge_page_stats(),
+    }
+}
+
+/// Returns all empty pages in the global page pool to the backend. Returns the number of bytes
+/// released.
+pub fn trim() -> usize {
+    release_regions(GLOBAL_PAGE_POOL.release_small_pages(0, 0))
+        + release_regions(GLOBAL_PAGE_POOL.release_large_pages(0, 0))
+        + release_regions(GLOBAL_PAGE_POOL.release_huge_pages(0, 0))
+}
+
+/// Returns whether the object at `ptr` should be moved to a fresh allocation so that the page
+/// holding it can be released.
+///
+/// This is only the case for objects in a sparsely occupied page relinquished by an exited
+/// thread. The lookup walks the pages in the global pool, so call it during idle periods.
+pub fn should_relocate(ptr: *const u8) -> bool {
+    match GLOBAL_PAGE_POOL.occupancy(ptr.addr()) {
+        Some((live, slots)) => (live as f64) < (slots as f64) * RELOCATE_OCCUPANCY_RATIO,
+        None => false,
+    }
+}
+
+/// Drops the shared regions backing the released pages. Each page is backed by its own region,
+/// and dropping the region notifies the backend to release it.
+fn release_regions<P>(pages: Option<VecDeque<Unique<P>>>) -> usize {
+    let pages = match pages {
+        Some(pages) => pages,
+        None => return 0,
+    };
+    let regions: Vec<_> = {
+        let mut guard = SHARED_HEAP_REGIONS.lock();
+        pages
+            .into_iter()
+            .filter_map(|page| guard.remove(&page.as_ptr().addr()))
+            .collect()
+    };
+    let released = regions.iter().map(|r| r.len()).sum();
+    // Talk to the backend without holding the lock.
+    mem::drop(regions);
+    released
+}
+
 pub(crate) struct PageReclaimerContext;
 
 impl PageReclaimerContext {
@@ -20,26 +115,30 @@ impl PageReclaimerContext {
 
     async fn reclaim_task() {
         loop {
-            GLOBAL_PAGE_POOL.release_small_pages(
+            release_regions(GLOBAL_PAGE_POOL.release_small_pages(
                 Self::SMALL_PAGE_RELEASE_THRESHOLD,
                 Self::SMALL_PAGE_RELEASE_RESERVE,
-            );
-            GLOBAL_PAGE_POOL.release_large_pages(
+            ));
+            release_regions(GLOBAL_PAGE_POOL.release_large_pages(
                 Self::LARGE_PAGE_RELEASE_THRESHOLD,
                 Self::LARGE_PAGE_RELEASE_RESERVE,
-            );
-            GLOBAL_PAGE_POOL.release_huge_pages(
+            ));
+            release_regions(GLOBAL_PAGE_POOL.release_huge_pages(
                 Self::HUGE_PAGE_RELEASE_THRESHOLD,
                 Self::HUGE_PAGE_RELEASE_RESERVE,
-            );
+            ));
             smol::Timer::after(std::time::Duration::from_millis(Self::RELEASE_INTERVAL_MS)).await;
         }
     }
 
     fn initialize() -> PageReclaimerContext {
         lazy_static::initialize(&GLOBAL_PAGE_POOL);
+        Self::spawn_reclaimer();
+        PageReclaimerContext
+    }
+
+    pub(crate) fn spawn_reclaimer() {
         let task = Self::reclaim_task();
         std::thread::spawn(move || smol::future::block_on(task));
-        PageReclaimerContext
     }
 }
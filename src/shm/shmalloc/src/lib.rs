@@ -1,8 +1,17 @@
 #![feature(allocator_api)]
 #![feature(strict_provenance)]
+#![feature(ptr_internals)]
 
 pub mod wheap;
 pub use wheap::SharedHeapAllocator;
 
+pub mod arena;
+pub use arena::Arena;
+
 pub mod backend;
-pub(crate) mod gc;
+pub mod device;
+pub use device::DeviceBuffer;
+pub mod gc;
+pub use gc::{heap_stats, should_relocate, trim, HeapStats};
+pub mod user;
+pub use user::UserMemory;
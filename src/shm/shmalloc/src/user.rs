@@ -0,0 +1,162 @@
+//! Memory allocated by the application that is sent as RPC payload in place.
+//!
+//! The application registers memory that it allocated itself, e.g., a large dataset mapped from a
+//! file, with the backend. The backend maps the same file range at its own address, so the memory
+//! has a different address in the app and in the backend, just like a `ShmNonNull`. The memory
+//! can then be sent without being copied to the shared heap.
+use std::collections::BTreeMap;
+use std::os::unix::io::RawFd;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lazy_static::lazy_static;
+
+use phoenix_api::salloc::cmd::{Command, CompletionKind};
+use phoenix_syscalls::_rx_recv_impl as rx_recv_impl;
+use shm::ptr::ShmNonNull;
+
+use super::backend::{Error, SA_CTX};
+
+lazy_static! {
+    // app address -> (length, backend address) of all registered user memory
+    static ref USER_REGIONS: spin::Mutex<BTreeMap<usize, (usize, usize)>> =
+        spin::Mutex::new(BTreeMap::new());
+}
+
+// The lowest start and the highest end of the registered user memory, updated with USER_REGIONS.
+// They tell most addresses apart from user memory without taking the lock, e.g., on every free
+// of the shared heap.
+static LOWEST: AtomicUsize = AtomicUsize::new(usize::MAX);
+static HIGHEST: AtomicUsize = AtomicUsize::new(0);
+
+fn update_bounds(regions: &BTreeMap<usize, (usize, usize)>) {
+    let lowest = regions.keys().next().copied().unwrap_or(usize::MAX);
+    let highest = regions
+        .iter()
+        .map(|(&start, &(len, _))| start + len)
+        .max()
+        .unwrap_or(0);
+    LOWEST.store(lowest, Ordering::Release);
+    HIGHEST.store(highest, Ordering::Release);
+}
+
+/// Forgets the user memory registered by the parent process after fork. The registrations
+/// belong to the parent.
+pub(crate) fn forget_inherited_regions() {
+    let mut regions = USER_REGIONS.lock();
+    regions.clear();
+    update_bounds(&regions);
+}
+
+/// Returns `true` if `addr` points into a [`UserMemory`] of this process.
+pub fn is_user_addr(addr: usize) -> bool {
+    // a UserMemory is registered before any address in it can be freed, so the bounds are not
+    // stale for such an address
+    if addr < LOWEST.load(Ordering::Acquire) || addr >= HIGHEST.load(Ordering::Acquire) {
+        return false;
+    }
+    query_backend_addr(addr, 1).is_some()
+}
+
+/// Translates an address of user memory in the app to the backend. Returns `None` if
+/// `[addr, addr + len)` is not within a [`UserMemory`].
+pub fn query_backend_addr(addr: usize, len: usize) -> Option<usize> {
+    USER_REGIONS
+        .lock()
+        .range(..=addr)
+        .next_back()
+        .filter(|(&start, &(region_len, _))| addr + len <= start + region_len)
+        .map(|(&start, &(_, backend))| backend + (addr - start))
+}
+
+/// Memory allocated by the application and registered with the backend, which can be sent as an
+/// RPC payload without being copied to the shared heap.
+///
+/// The backend only reads the memory. Dropping the `UserMemory` unregisters it, and the backend
+/// keeps its mapping until the sends from the memory that are still in flight complete.
+#[derive(Debug)]
+pub struct UserMemory {
+    ptr: ShmNonNull<u8>,
+    len: usize,
+}
+
+unsafe impl Send for UserMemory {}
+unsafe impl Sync for UserMemory {}
+
+impl UserMemory {
+    /// Registers `[ptr, ptr + len)`, which maps `[file_off, file_off + len)` of `fd`. The
+    /// offset must be page aligned. `fd` is still owned by the caller and can be closed after
+    /// this returns.
+    ///
+    /// # Safety
+    ///
+    /// * The memory must be a shared mapping (`MAP_SHARED`) of the file range, so that the
+    ///   backend sees the same contents.
+    /// * The memory must stay mapped as long as the returned `UserMemory` lives.
+    pub unsafe fn register(
+        ptr: NonNull<u8>,
+        len: usize,
+        fd: RawFd,
+        file_off: i64,
+    ) -> Result<Self, Error> {
+        assert!(len > 0);
+        SA_CTX.with(|ctx| {
+            ctx.service().send_fd(&[fd])?;
+            ctx.service()
+                .send_cmd(Command::RegisterUserMemory(len, file_off))?;
+            match ctx.service().recv_comp()?.0 {
+                Ok(CompletionKind::RegisterUserMemory(remote_addr)) => {
+                    let mut regions = USER_REGIONS.lock();
+                    regions.insert(ptr.addr().get(), (len, remote_addr));
+                    update_bounds(&regions);
+                    drop(regions);
+                    let ptr =
+                        ShmNonNull::new(ptr.as_ptr(), ptr.as_ptr().with_addr(remote_addr)).unwrap();
+                    Ok(UserMemory { ptr, len })
+                }
+                Err(e) => Err(Error::Interface("RegisterUserMemory", e)),
+                otherwise => panic!("Expect RegisterUserMemory, found {:?}", otherwise),
+            }
+        })
+    }
+
+    /// Returns the pointer to the memory in this process.
+    #[inline]
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.ptr.to_raw_parts().0
+    }
+
+    /// Returns the pointer to the memory in the app and in the backend.
+    #[inline]
+    pub fn as_shm_non_null(&self) -> ShmNonNull<u8> {
+        self.ptr
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for UserMemory {
+    fn drop(&mut self) {
+        let addr = self.ptr.as_ptr_app().expose_addr();
+        let mut regions = USER_REGIONS.lock();
+        regions.remove(&addr);
+        update_bounds(&regions);
+        drop(regions);
+        (|| {
+            SA_CTX.with(|ctx| {
+                let req = Command::UnregisterUserMemory(self.ptr.as_ptr_backend().expose_addr());
+                ctx.service().send_cmd(req)?;
+                rx_recv_impl!(ctx.service(), CompletionKind::UnregisterUserMemory)
+            })
+        })()
+        .unwrap_or_else(|e| eprintln!("Dropping UserMemory: {}", e));
+    }
+}
@@ -4,7 +4,7 @@ use std::collections::BTreeMap;
 use std::io;
 use std::mem;
 use std::num::NonZeroUsize;
-use std::ptr::NonNull;
+use std::ptr::{self, NonNull};
 
 // use fnv::FnvHashMap as HashMap;
 use lazy_static::lazy_static;
@@ -43,28 +43,8 @@ impl WriteHeap {
     }
 
     fn allocate_shm(&self, len: usize) -> Result<WriteRegion, Error> {
-        assert!(len > 0);
-        SA_CTX.with(|ctx| {
-            // TODO(cjr): use a correct align
-            let align = len;
-            let req = cmd::Command::AllocShm(len, align);
-            ctx.service.send_cmd(req)?;
-            let fds = ctx.service.recv_fd()?;
-
-            assert_eq!(fds.len(), 1);
-
-            let memfd = Memfd::try_from_fd(fds[0]).map_err(|_| io::Error::last_os_error())?;
-            let file_len = memfd.as_file().metadata()?.len() as usize;
-            assert!(file_len >= len);
-
-            match ctx.service.recv_comp().unwrap().0 {
-                Ok(cmd::CompletionKind::AllocShm(remote_addr, file_off)) => {
-                    Ok(WriteRegion::new(remote_addr, len, align, file_off, memfd).unwrap())
-                }
-                Err(e) => Err(Error::Interface("AllocShm", e)),
-                otherwise => panic!("Expect AllocShm, found {:?}", otherwise),
-            }
-        })
+        // TODO(cjr): use a correct align
+        allocate_region(len, len)
     }
 
     #[inline]
@@ -147,6 +127,44 @@ impl WriteHeap {
     }
 }
 
+/// Asks the backend for a shared region of `len` bytes aligned to `align`, and maps it at the
+/// same address as the backend.
+fn allocate_region(len: usize, align: usize) -> Result<WriteRegion, Error> {
+    assert!(len > 0);
+    SA_CTX.with(|ctx| {
+        let req = cmd::Command::AllocShm(len, align);
+        ctx.service().send_cmd(req)?;
+        let fds = ctx.service().recv_fd()?;
+
+        assert_eq!(fds.len(), 1);
+
+        let memfd = Memfd::try_from_fd(fds[0]).map_err(|_| io::Error::last_os_error())?;
+        let file_len = memfd.as_file().metadata()?.len() as usize;
+        assert!(file_len >= len);
+
+        match ctx.service().recv_comp().unwrap().0 {
+            Ok(cmd::CompletionKind::AllocShm(remote_addr, file_off)) => {
+                Ok(WriteRegion::new(remote_addr, len, align, file_off, memfd).unwrap())
+            }
+            Err(e) => Err(Error::Interface("AllocShm", e)),
+            otherwise => panic!("Expect AllocShm, found {:?}", otherwise),
+        }
+    })
+}
+
+/// Forgets the heap inherited from the parent process after fork, see
+/// [`reinit_after_fork`](crate::backend::reinit_after_fork).
+pub(crate) fn forget_inherited_heap() {
+    TL_SHARED_HEAP.with(|heap| mem::forget(heap.replace(WriteHeap::new())));
+    mem::forget(mem::take(&mut *SHARED_HEAP_REGIONS.lock()));
+    GLOBAL_PAGE_POOL.forget_all();
+    super::arena::forget_inherited();
+    #[cfg(feature = "debug-guard")]
+    guard::forget_inherited();
+    // The reclaimer thread does not survive fork.
+    super::gc::PageReclaimerContext::spawn_reclaimer();
+}
+
 impl Default for WriteHeap {
     fn default() -> Self {
         WriteHeap::new()
@@ -188,6 +206,14 @@ impl SharedHeapAllocator {
 unsafe impl ShmAllocator for SharedHeapAllocator {
     fn allocate(&self, layout: Layout) -> Result<ShmNonNull<[u8]>, AllocError> {
         use slabmalloc::{AllocationError, Allocator};
+        if let Some(ptr) = super::arena::allocate(layout) {
+            return Ok(ptr);
+        }
+        // the objects the backend fails to give a guarded region come from the heap
+        #[cfg(feature = "debug-guard")]
+        if let Some(ptr) = guard::allocate(layout) {
+            return Ok(ptr);
+        }
         match layout.size() {
             0..=ZoneAllocator::MAX_ALLOC_SIZE => {
                 TL_SHARED_HEAP.with(|shared_heap| {
@@ -292,7 +318,6 @@ unsafe impl ShmAllocator for SharedHeapAllocator {
                 })
             }
             _ => {
-                // WARNING(wyj): dealloc will not be properly handled in this case
                 eprintln!(
                     "Requested: {} bytes. Please handle object size larger than {}",
                     layout.size(),
@@ -333,6 +358,22 @@ unsafe impl ShmAllocator for SharedHeapAllocator {
     fn deallocate(&self, ptr: ShmNonNull<u8>, layout: Layout) {
         // backend deallocation is handled by SharedRegion::drop together with global GarbageCollector
         use slabmalloc::Allocator;
+        // device memory is owned by a DeviceBuffer
+        if super::device::is_device_addr(ptr.as_ptr_app().addr()) {
+            return;
+        }
+        // user memory is owned by the app and registered by a UserMemory
+        if super::user::is_user_addr(ptr.as_ptr_app().addr()) {
+            return;
+        }
+        // objects in an arena are released together with the arena
+        if super::arena::deallocate(ptr.as_ptr_app().addr()) {
+            return;
+        }
+        #[cfg(feature = "debug-guard")]
+        if guard::deallocate(ptr.as_ptr_app().addr(), layout.size()) {
+            return;
+        }
         match layout.size() {
             0..=ZoneAllocator::MAX_ALLOC_SIZE => {
                 TL_SHARED_HEAP.with(|shared_heap| {
@@ -343,9 +384,102 @@ unsafe impl ShmAllocator for SharedHeapAllocator {
                         .expect("Cannot deallocate");
                 });
             }
-            _ => todo!("Handle object size larger than 1GB"),
+            _ => {
+                // objects larger than MAX_ALLOC_SIZE own a dedicated region
+                let region = SHARED_HEAP_REGIONS.lock().remove(&ptr.as_ptr_app().addr());
+                assert!(region.is_some(), "Cannot deallocate");
+                // the backend is notified outside of the lock
+                mem::drop(region);
+            }
         }
     }
+
+    unsafe fn grow(
+        &self,
+        ptr: ShmNonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<ShmNonNull<[u8]>, AllocError> {
+        debug_assert!(
+            new_layout.size() >= old_layout.size(),
+            "`new_layout.size()` must be greater than or equal to `old_layout.size()`"
+        );
+        if let Some(ptr) = Self::resize_in_place(ptr, old_layout, new_layout) {
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        // SAFETY: the old and new blocks are valid for `old_layout.size()` bytes, and they do
+        // not overlap because the old block is not deallocated yet.
+        ptr::copy_nonoverlapping(
+            ptr.as_ptr_app(),
+            new_ptr.as_mut_ptr_app(),
+            old_layout.size(),
+        );
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: ShmNonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<ShmNonNull<[u8]>, AllocError> {
+        debug_assert!(
+            new_layout.size() <= old_layout.size(),
+            "`new_layout.size()` must be smaller than or equal to `old_layout.size()`"
+        );
+        if let Some(ptr) = Self::resize_in_place(ptr, old_layout, new_layout) {
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        // SAFETY: the old and new blocks are valid for `new_layout.size()` bytes, and they do
+        // not overlap because the old block is not deallocated yet.
+        ptr::copy_nonoverlapping(
+            ptr.as_ptr_app(),
+            new_ptr.as_mut_ptr_app(),
+            new_layout.size(),
+        );
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
+    }
+}
+
+impl SharedHeapAllocator {
+    /// Returns the block at `ptr` resized to `new_layout` if it does not need to move. An
+    /// object in an arena can grow if it is the latest allocation of the arena, and an object
+    /// in a slab can grow up to its size class.
+    fn resize_in_place(
+        ptr: ShmNonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<ShmNonNull<[u8]>> {
+        let addr = ptr.as_ptr_app().addr();
+        // a guarded object ends right at its guard page
+        if cfg!(feature = "debug-guard") || addr % new_layout.align() != 0 {
+            return None;
+        }
+        let (old_size, new_size) = (old_layout.size(), new_layout.size());
+        let in_place = match super::arena::resize_in_place(addr, old_size, new_size) {
+            Some(in_place) => in_place,
+            None => {
+                old_size.max(new_size) <= ZoneAllocator::MAX_ALLOC_SIZE
+                    && ZoneAllocator::get_max_size(old_size)
+                        == ZoneAllocator::get_max_size(new_size)
+            }
+        };
+        if !in_place {
+            return None;
+        }
+        let (ptr_app, ptr_backend) = ptr.to_raw_parts();
+        Some(ShmNonNull::slice_from_raw_parts(
+            ptr_app,
+            ptr_backend,
+            new_size,
+        ))
+    }
 }
 
 mod region {
@@ -387,9 +521,9 @@ mod region {
             (|| {
                 SA_CTX.with(|ctx| {
                     let req = Command::DeallocShm(self.remote_addr);
-                    ctx.service.send_cmd(req)?;
+                    ctx.service().send_cmd(req)?;
                     // TODO(wyj): do we really need to wait for completion here?
-                    rx_recv_impl!(ctx.service, CompletionKind::DeallocShm)
+                    rx_recv_impl!(ctx.service(), CompletionKind::DeallocShm)
                 })
             })()
             .unwrap_or_else(|e| eprintln!("Dropping WriteRegion: {}", e));
@@ -438,5 +572,115 @@ mod region {
         pub(crate) fn align(&self) -> usize {
             self.align
         }
+
+        /// Makes `[offset, offset + len)` of the region inaccessible in the app.
+        #[cfg(feature = "debug-guard")]
+        pub(crate) fn protect_none(&self, offset: usize, len: usize) -> std::io::Result<()> {
+            self.mmap.protect_none(offset, len)
+        }
+    }
+}
+
+/// The guarded heap of the `debug-guard` feature, to catch the memory bugs of the app and of the
+/// backend that would corrupt the shared heap silently.
+///
+/// Each object gets a region of its own and ends right before a guard page, so that an overflow
+/// faults where it happens instead of overwriting the next object. A freed object is filled with
+/// [`POISON`] and its region made inaccessible in the app. The region is kept in quarantine for
+/// the next [`QUARANTINE`] frees before it goes back to the backend, so that a use after free
+/// faults in the app, and reads the poison in the backend.
+///
+/// It takes two pages and a round trip to the backend per object, for debugging only. The objects
+/// in arenas are not guarded.
+#[cfg(feature = "debug-guard")]
+mod guard {
+    use std::alloc::Layout;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::mem;
+    use std::ptr::{self, NonNull};
+
+    use lazy_static::lazy_static;
+
+    use shm::ptr::ShmNonNull;
+
+    use super::allocate_region;
+    use super::region::WriteRegion;
+
+    /// The byte the freed objects are filled with.
+    pub(crate) const POISON: u8 = 0xdb;
+    /// The number of freed regions kept inaccessible.
+    pub(crate) const QUARANTINE: usize = 64;
+
+    const PAGE_SIZE: usize = 4096;
+
+    #[derive(Default)]
+    struct Guarded {
+        // the regions of the objects, by the addresses of the objects
+        live: BTreeMap<usize, WriteRegion>,
+        quarantine: VecDeque<WriteRegion>,
+    }
+
+    lazy_static! {
+        static ref GUARDED: spin::Mutex<Guarded> = spin::Mutex::new(Guarded::default());
+    }
+
+    /// Allocates an object right before a guard page.
+    pub(crate) fn allocate(layout: Layout) -> Option<ShmNonNull<[u8]>> {
+        let align = layout.align().max(PAGE_SIZE);
+        let size = round_up(layout.size().max(1), layout.align());
+        let data_len = round_up(size, align);
+        let region = match allocate_region(data_len + PAGE_SIZE, align) {
+            Ok(region) => region,
+            Err(e) => {
+                log::warn!("allocate guarded region: {}", e);
+                return None;
+            }
+        };
+        if let Err(e) = region.protect_none(data_len, PAGE_SIZE) {
+            log::warn!("protect guard page: {}", e);
+            return None;
+        }
+        // the backend maps the region at the same address
+        let addr = region.as_ptr().addr() + data_len - size;
+        let ptr_app = NonNull::new(addr as *mut u8).unwrap();
+        GUARDED.lock().live.insert(addr, region);
+        Some(ShmNonNull::slice_from_raw_parts(
+            ptr_app,
+            ptr_app,
+            layout.size(),
+        ))
+    }
+
+    /// Poisons the object at `addr` of `size` bytes and quarantines its region. Returns false if
+    /// the object is not guarded.
+    pub(crate) fn deallocate(addr: usize, size: usize) -> bool {
+        let mut guarded = GUARDED.lock();
+        let Some(region) = guarded.live.remove(&addr) else {
+            return false;
+        };
+        // SAFETY: the object is in the region, which is still accessible
+        unsafe { ptr::write_bytes(addr as *mut u8, POISON, size) };
+        if let Err(e) = region.protect_none(0, region.len()) {
+            log::warn!("protect freed region: {}", e);
+        }
+        guarded.quarantine.push_back(region);
+        let released = if guarded.quarantine.len() > QUARANTINE {
+            guarded.quarantine.pop_front()
+        } else {
+            None
+        };
+        mem::drop(guarded);
+        // the backend is notified outside of the lock
+        mem::drop(released);
+        true
+    }
+
+    pub(crate) fn forget_inherited() {
+        mem::forget(mem::take(&mut *GUARDED.lock()));
+    }
+
+    #[inline]
+    fn round_up(n: usize, align: usize) -> usize {
+        (n + align - 1) & !(align - 1)
     }
 }
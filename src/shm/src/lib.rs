@@ -69,3 +69,6 @@ pub mod collections;
 /// Shared-memory version of [`std::string::String`].
 #[allow(clippy::partialeq_ne_impl)]
 pub mod string;
+
+#[cfg(feature = "serde")]
+mod serde_impls;
@@ -0,0 +1,99 @@
+//! [`serde`] support for the shared-memory collections, with the `serde` feature.
+//!
+//! They serialize as their counterparts in [`std`] do: a [`Vec`] as a sequence, a [`String`] as
+//! a string, and a [`Box`] as what it points to. Deserializing allocates in the default instance
+//! of the allocator, e.g., on the shared heap of the current thread for the allocator of mRPC.
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::alloc::ShmAllocator;
+use crate::boxed::Box;
+use crate::string::String;
+use crate::vec::Vec;
+
+// the most elements reserved up front from the size hint of an untrusted input
+const MAX_PREALLOC: usize = 4096;
+
+impl<T: Serialize, A: ShmAllocator> Serialize for Vec<T, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T, A> Deserialize<'de> for Vec<T, A>
+where
+    T: Deserialize<'de>,
+    A: ShmAllocator + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VecVisitor<T, A>(PhantomData<(T, A)>);
+
+        impl<'de, T, A> Visitor<'de> for VecVisitor<T, A>
+        where
+            T: Deserialize<'de>,
+            A: ShmAllocator + Default,
+        {
+            type Value = Vec<T, A>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a sequence")
+            }
+
+            fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+                let capacity = seq.size_hint().unwrap_or(0).min(MAX_PREALLOC);
+                let mut vec = Vec::with_capacity(capacity);
+                while let Some(value) = seq.next_element()? {
+                    vec.push(value);
+                }
+                Ok(vec)
+            }
+        }
+
+        deserializer.deserialize_seq(VecVisitor(PhantomData))
+    }
+}
+
+impl<A: ShmAllocator> Serialize for String<A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de, A: ShmAllocator + Default> Deserialize<'de> for String<A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StringVisitor<A>(PhantomData<A>);
+
+        impl<'de, A: ShmAllocator + Default> Visitor<'de> for StringVisitor<A> {
+            type Value = String<A>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(String::from(v))
+            }
+        }
+
+        deserializer.deserialize_str(StringVisitor(PhantomData))
+    }
+}
+
+impl<T: Serialize, A: ShmAllocator> Serialize for Box<T, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+impl<'de, T, A> Deserialize<'de> for Box<T, A>
+where
+    T: Deserialize<'de>,
+    A: ShmAllocator + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Box::new)
+    }
+}
@@ -32,6 +32,7 @@ mod sc;
 mod zone;
 
 pub use pool::GlobalPagePool;
+pub use pool::PageBufferStats;
 pub use pool::GLOBAL_PAGE_POOL;
 
 pub use pages::*;
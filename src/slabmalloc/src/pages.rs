@@ -15,6 +15,7 @@ pub(crate) trait Bitfield {
     fn clear_bit(&self, idx: usize);
     fn is_full(&self) -> bool;
     fn all_free(&self, relevant_bits: usize) -> bool;
+    fn allocated(&self, relevant_bits: usize) -> usize;
 }
 
 /// Implementation of bit operations on u64 slices.
@@ -146,6 +147,26 @@ impl Bitfield for [AtomicU64] {
 
         true
     }
+
+    /// Counts the allocated slots among the first `relevant_bits` bits.
+    #[inline(always)]
+    fn allocated(&self, relevant_bits: usize) -> usize {
+        let mut count = 0;
+        for (idx, bitmap) in self.iter().enumerate() {
+            let start = idx * 64;
+            if start >= relevant_bits {
+                break;
+            }
+            let bits = bitmap.load(Ordering::Relaxed);
+            let bits = if relevant_bits - start < 64 {
+                bits & ((1 << (relevant_bits - start)) - 1)
+            } else {
+                bits
+            };
+            count += bits.count_ones() as usize;
+        }
+        count
+    }
 }
 
 /// This trait is used to define a page from which objects are allocated
@@ -200,6 +221,11 @@ pub trait AllocablePage {
         self.bitfield().all_free(relevant_bits)
     }
 
+    /// Returns the number of live objects in the page.
+    fn allocated_objects(&self, relevant_bits: usize) -> usize {
+        self.bitfield().allocated(relevant_bits)
+    }
+
     /// Deallocates a memory object within this page.
     fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
         trace!(
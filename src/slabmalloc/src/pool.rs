@@ -24,6 +24,52 @@ impl<P> PageBuffer<P> {
     }
 }
 
+/// Occupancy of the pages kept in the global pool for one page size.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageBufferStats {
+    /// Pages without live objects.
+    pub empty_pages: usize,
+    /// Pages relinquished by exited threads that still hold live objects.
+    pub used_pages: usize,
+    /// Live objects in the used pages.
+    pub live_objects: usize,
+    /// Object slots in the used pages.
+    pub object_slots: usize,
+}
+
+impl<P: AllocablePage> PageBuffer<P> {
+    fn stats(&self) -> PageBufferStats {
+        let mut stats = PageBufferStats {
+            empty_pages: self.empty.len(),
+            ..Default::default()
+        };
+        for (page, obj_per_page) in self.used.iter() {
+            let live = unsafe { page.as_ref().allocated_objects(*obj_per_page) };
+            if live == 0 {
+                stats.empty_pages += 1;
+            } else {
+                stats.used_pages += 1;
+                stats.live_objects += live;
+                stats.object_slots += obj_per_page;
+            }
+        }
+        stats
+    }
+
+    fn occupancy(&self, addr: usize) -> Option<(usize, usize)> {
+        let page_addr = addr & !(P::SIZE - 1);
+        self.used
+            .iter()
+            .find(|(page, _)| page.as_ptr() as usize == page_addr)
+            .map(|(page, obj_per_page)| unsafe {
+                (
+                    page.as_ref().allocated_objects(*obj_per_page),
+                    *obj_per_page,
+                )
+            })
+    }
+}
+
 pub struct GlobalPagePool<'a> {
     small_pages: spin::Mutex<PageBuffer<ObjectPage<'a>>>,
     large_pages: spin::Mutex<PageBuffer<LargeObjectPage<'a>>>,
@@ -212,4 +258,38 @@ impl<'a> GlobalPagePool<'a> {
             None
         }
     }
+
+    pub fn small_page_stats(&self) -> PageBufferStats {
+        self.small_pages.lock().stats()
+    }
+
+    pub fn large_page_stats(&self) -> PageBufferStats {
+        self.large_pages.lock().stats()
+    }
+
+    pub fn huge_page_stats(&self) -> PageBufferStats {
+        self.huge_pages.lock().stats()
+    }
+
+    /// Returns the number of live objects and object slots of the page that contains `addr`,
+    /// if that page was relinquished to the pool while it still held live objects.
+    ///
+    /// Such pages are not handed out again until all their objects are freed, so the objects
+    /// in a sparsely occupied one are worth moving elsewhere. This walks the used lists and is
+    /// meant for idle periods.
+    pub fn occupancy(&self, addr: usize) -> Option<(usize, usize)> {
+        self.small_pages
+            .lock()
+            .occupancy(addr)
+            .or_else(|| self.large_pages.lock().occupancy(addr))
+            .or_else(|| self.huge_pages.lock().occupancy(addr))
+    }
+
+    /// Forgets all pages in the pool without touching them. This is meant for a child process
+    /// after fork, where the pages in the pool are backed by memory of the parent.
+    pub fn forget_all(&self) {
+        *self.small_pages.lock() = PageBuffer::new();
+        *self.large_pages.lock() = PageBuffer::new();
+        *self.huge_pages.lock() = PageBuffer::new();
+    }
 }
@@ -681,3 +681,24 @@ pub fn check_is_full_512() {
     }
     assert!(page.is_full());
 }
+
+#[test]
+pub fn check_allocated_objects() {
+    let _r = env_logger::try_init();
+    let mut page: ObjectPage = Default::default();
+    page.bitfield.initialize(64, BASE_PAGE_SIZE - 80);
+    let layout = Layout::from_size_align(64, 1).unwrap();
+    let obj_per_page = core::cmp::min((BASE_PAGE_SIZE - 80) / 64, 8 * 64);
+    assert_eq!(page.allocated_objects(obj_per_page), 0);
+
+    let mut ptrs = Vec::new();
+    for _ in 0..obj_per_page {
+        ptrs.push(NonNull::new(page.allocate(layout)).unwrap());
+    }
+    assert_eq!(page.allocated_objects(obj_per_page), obj_per_page);
+
+    for ptr in ptrs.iter().step_by(2) {
+        page.deallocate(*ptr, layout).unwrap();
+    }
+    assert_eq!(page.allocated_objects(obj_per_page), obj_per_page / 2);
+}